@@ -0,0 +1,558 @@
+//! Query audit log.
+//!
+//! Every statement a frontend executes produces one [`AuditRecord`]: who ran it, when,
+//! through which frontend, which tables it read, how many rows it returned, how long it
+//! took and whether it succeeded. Records go to an append-only [`AuditSink`]: a JSON
+//! lines file ([`FileAuditSink`]) or a table queryable from SQL ([`TableAuditSink`]).
+//!
+//! SQL text is recorded by default; [`Auditor::with_sql_text`] turns that off where
+//! statements may carry sensitive literals.
+//!
+//! An [`Auditor`] can also keep a [`SlowQueryLog`] of the statements running longer
+//! than a threshold and a [`QueryHistory`] of all of them, with or without writing
+//! audit records.
+//!
+//! Audited or not, every statement runs in a `query` [`tracing`] span carrying a fresh
+//! `query_id`, the client's `session_id` and the frontend as `source` (see
+//! [`igloo_common::logging`]); its [`AuditEntry`] logs when it starts and finishes.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, ListBuilder, StringArray, StringBuilder, TimestampMillisecondArray, UInt64Array,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{MemTable, Session, TableProvider};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::{RecordBatchStream, SendableRecordBatchStream};
+use datafusion::logical_expr::{Expr, TableType};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::Stream;
+use igloo_common::redact::redact;
+use igloo_engine::diagnostics::{explain_analyze, scanned_bytes, source_timings};
+use igloo_engine::running::QueryStart;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, info_span, warn, Span};
+use uuid::Uuid;
+
+use crate::query_history::{QueryHistory, QueryRecord};
+use crate::slow_log::{SlowQueryLog, SlowQueryRecord};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    Error,
+    /// The client went away before the result was fully read.
+    Cancelled,
+}
+
+impl Outcome {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::Error => "error",
+            Outcome::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    /// Start of execution, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// Subject of the authenticated principal, if any.
+    pub principal: Option<String>,
+    /// `http`, `websocket`, `pgwire`, `flight` or `flight_sql`.
+    pub frontend: &'static str,
+    pub sql: Option<String>,
+    pub tables: Vec<String>,
+    /// Rows returned (or affected, for DML), when known.
+    pub rows: Option<u64>,
+    pub duration_ms: u64,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+}
+
+/// Where audit records are written. Sinks only ever append.
+pub trait AuditSink: Send + Sync {
+    fn write(&self, record: &AuditRecord) -> io::Result<()>;
+}
+
+/// Appends records to a file, one JSON object per line.
+pub struct FileAuditSink {
+    file: Mutex<File>,
+}
+
+impl FileAuditSink {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn write(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.lock().expect("audit file lock poisoned").write_all(&line)
+    }
+}
+
+/// Keeps records in memory and exposes them as a read-only table, e.g.
+/// `engine.register_table("audit_log", Arc::new(sink.clone()))`.
+#[derive(Debug, Clone, Default)]
+pub struct TableAuditSink {
+    records: Arc<Mutex<Vec<AuditRecord>>>,
+}
+
+impl TableAuditSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> Vec<AuditRecord> {
+        self.records.lock().expect("audit table lock poisoned").clone()
+    }
+
+    fn to_batch(&self) -> DataFusionResult<RecordBatch> {
+        let records = self.records();
+        let mut tables = ListBuilder::new(StringBuilder::new());
+        for record in &records {
+            tables.append_value(record.tables.iter().map(Some));
+        }
+        let strings = |f: fn(&AuditRecord) -> Option<&str>| -> ArrayRef {
+            Arc::new(records.iter().map(f).collect::<StringArray>())
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(
+                records
+                    .iter()
+                    .map(|r| Some(r.timestamp_ms as i64))
+                    .collect::<TimestampMillisecondArray>()
+                    .with_timezone("+00:00"),
+            ),
+            strings(|r| r.principal.as_deref()),
+            strings(|r| Some(r.frontend)),
+            strings(|r| r.sql.as_deref()),
+            Arc::new(tables.finish()),
+            Arc::new(records.iter().map(|r| r.rows).collect::<UInt64Array>()),
+            Arc::new(records.iter().map(|r| Some(r.duration_ms)).collect::<UInt64Array>()),
+            strings(|r| Some(r.outcome.as_str())),
+            strings(|r| r.error.as_deref()),
+        ];
+        Ok(RecordBatch::try_new(self.schema(), columns)?)
+    }
+}
+
+impl AuditSink for TableAuditSink {
+    fn write(&self, record: &AuditRecord) -> io::Result<()> {
+        self.records.lock().expect("audit table lock poisoned").push(record.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TableProvider for TableAuditSink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let item = Field::new("item", DataType::Utf8, true);
+        Arc::new(Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into())),
+                false,
+            ),
+            Field::new("principal", DataType::Utf8, true),
+            Field::new("frontend", DataType::Utf8, false),
+            Field::new("sql", DataType::Utf8, true),
+            Field::new("tables", DataType::List(Arc::new(item)), false),
+            Field::new("rows", DataType::UInt64, true),
+            Field::new("duration_ms", DataType::UInt64, false),
+            Field::new("outcome", DataType::Utf8, false),
+            Field::new("error", DataType::Utf8, true),
+        ]))
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let snapshot = MemTable::try_new(self.schema(), vec![vec![self.to_batch()?]])?;
+        snapshot.scan(state, projection, filters, limit).await
+    }
+}
+
+/// Hands out an [`AuditEntry`] per statement and writes finished entries to its sink.
+/// The default auditor has no sink, for keeping only a slow query log or query history.
+pub struct Auditor {
+    sink: Option<Arc<dyn AuditSink>>,
+    include_sql: bool,
+    slow_log: Option<Arc<SlowQueryLog>>,
+    history: Option<Arc<QueryHistory>>,
+}
+
+impl Default for Auditor {
+    fn default() -> Self {
+        Self { sink: None, include_sql: true, slow_log: None, history: None }
+    }
+}
+
+impl Auditor {
+    pub fn new(sink: impl AuditSink + 'static) -> Self {
+        Self { sink: Some(Arc::new(sink)), ..Self::default() }
+    }
+
+    /// Whether records carry the full SQL text (the default), slow query and query
+    /// history records too.
+    pub fn with_sql_text(mut self, include: bool) -> Self {
+        self.include_sql = include;
+        self
+    }
+
+    /// Also record slow statements in `log`.
+    pub fn with_slow_query_log(mut self, log: SlowQueryLog) -> Self {
+        self.slow_log = Some(Arc::new(log));
+        self
+    }
+
+    /// Also record every statement in `history`, which can be registered as a table
+    /// at the same time.
+    pub fn with_query_history(mut self, history: Arc<QueryHistory>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    /// Start auditing a statement of the session `session` (the client's session id,
+    /// or connection where sessions are per connection).
+    pub fn start(
+        &self,
+        frontend: &'static str,
+        principal: Option<&str>,
+        session: Option<&str>,
+        sql: &str,
+    ) -> AuditEntry {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let mut entry = AuditEntry::new(frontend, principal, session, sql);
+        entry.pending = self.sink.as_ref().map(|sink| Pending {
+            sink: Arc::clone(sink),
+            record: AuditRecord {
+                timestamp_ms,
+                principal: principal.map(str::to_string),
+                frontend,
+                sql: self.include_sql.then(|| sql.to_string()),
+                tables: Vec::new(),
+                rows: None,
+                duration_ms: 0,
+                outcome: Outcome::Ok,
+                error: None,
+            },
+        });
+        entry.slow = self.slow_log.as_ref().map(|log| Slow {
+            log: Arc::clone(log),
+            record: SlowQueryRecord {
+                timestamp_ms,
+                query_id: entry.query_id().to_string(),
+                principal: principal.map(str::to_string),
+                frontend,
+                sql: self.include_sql.then(|| sql.to_string()),
+                duration_ms: 0,
+                plan: None,
+                sources: Vec::new(),
+                error: None,
+            },
+        });
+        entry.history = self.history.as_ref().map(|history| History {
+            history: Arc::clone(history),
+            record: QueryRecord {
+                query_id: entry.query_id().to_string(),
+                started_ms: timestamp_ms,
+                finished_ms: timestamp_ms,
+                principal: principal.map(str::to_string),
+                frontend: frontend.to_string(),
+                sql: self.include_sql.then(|| sql.to_string()),
+                rows: None,
+                scanned_bytes: None,
+                cache_hit: false,
+                outcome: Outcome::Ok,
+                error: None,
+            },
+        });
+        entry
+    }
+}
+
+/// Start auditing a statement if auditing is enabled, and logging it either way.
+pub fn start(
+    auditor: Option<&Auditor>,
+    frontend: &'static str,
+    principal: Option<&str>,
+    session: Option<&str>,
+    sql: &str,
+) -> AuditEntry {
+    match auditor {
+        Some(auditor) => auditor.start(frontend, principal, session, sql),
+        None => AuditEntry::new(frontend, principal, session, sql),
+    }
+}
+
+struct Pending {
+    sink: Arc<dyn AuditSink>,
+    record: AuditRecord,
+}
+
+/// The slow query record of a statement, written if it turns out to be slow.
+struct Slow {
+    log: Arc<SlowQueryLog>,
+    record: SlowQueryRecord,
+}
+
+/// The query history record of a statement.
+struct History {
+    history: Arc<QueryHistory>,
+    record: QueryRecord,
+}
+
+/// A statement being audited. Its record is written once it succeeds or fails; an
+/// entry dropped before either is recorded as [`Outcome::Cancelled`].
+pub struct AuditEntry {
+    pending: Option<Pending>,
+    slow: Option<Slow>,
+    history: Option<History>,
+    plan: Option<Arc<dyn ExecutionPlan>>,
+    statement: QueryStart,
+    span: Span,
+    started: Instant,
+    finished: bool,
+}
+
+impl AuditEntry {
+    fn new(
+        frontend: &'static str,
+        principal: Option<&str>,
+        session: Option<&str>,
+        sql: &str,
+    ) -> Self {
+        let query_id = Uuid::new_v4().to_string();
+        let span = info_span!("query", query_id, session_id = session, source = frontend);
+        span.in_scope(|| info!(principal, "statement started"));
+        AuditEntry {
+            pending: None,
+            slow: None,
+            history: None,
+            plan: None,
+            statement: QueryStart {
+                query_id,
+                principal: principal.map(str::to_string),
+                frontend: frontend.to_string(),
+                sql: redact(sql),
+            },
+            span,
+            started: Instant::now(),
+            finished: false,
+        }
+    }
+
+    /// The `query_id` of the statement's log lines and records.
+    pub fn query_id(&self) -> &str {
+        &self.statement.query_id
+    }
+
+    /// The statement as it is listed among the engine's running queries, see
+    /// [`QueryEngine::with_running_query`](igloo_engine::QueryEngine::with_running_query).
+    pub fn running_query(&self) -> QueryStart {
+        self.statement.clone()
+    }
+
+    /// The statement's `query` span, to run its work in.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Record the tables the statement reads, see
+    /// [`source_tables`](igloo_engine::diagnostics::source_tables).
+    pub fn set_tables(&mut self, tables: Vec<String>) {
+        if let Some(pending) = &mut self.pending {
+            pending.record.tables = tables;
+        }
+    }
+
+    /// Record the physical plan the statement executes, for the slow query log and
+    /// the bytes it scanned in the query history.
+    pub fn set_plan(&mut self, plan: &Arc<dyn ExecutionPlan>) {
+        if self.slow.is_some() || self.history.is_some() {
+            self.plan = Some(Arc::clone(plan));
+        }
+    }
+
+    /// Record that the statement's result was served from a cache.
+    pub fn set_cache_hit(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.record.cache_hit = true;
+        }
+    }
+
+    /// Pass `result` through, recording the statement as failed if it is an error.
+    pub fn check<T, E: Display>(&mut self, result: Result<T, E>) -> Result<T, E> {
+        if let Err(e) = &result {
+            self.finish(Outcome::Error, None, Some(e.to_string()));
+        }
+        result
+    }
+
+    pub fn succeeded(mut self, rows: Option<u64>) {
+        self.finish(Outcome::Ok, rows, None);
+    }
+
+    /// Record the statement once `stream` is exhausted, counting the rows it yields.
+    pub fn wrap(self, stream: SendableRecordBatchStream) -> SendableRecordBatchStream {
+        Box::pin(AuditedStream { inner: stream, entry: self, rows: 0 })
+    }
+
+    fn finish(&mut self, outcome: Outcome, rows: Option<u64>, error: Option<String>) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let duration = self.started.elapsed();
+        let duration_ms = duration.as_millis() as u64;
+        // Driver errors can echo connection strings.
+        let error = error.map(|e| redact(&e));
+        let _span = self.span.enter();
+        match outcome {
+            Outcome::Ok => info!(rows, duration_ms, "statement finished"),
+            Outcome::Error => warn!(rows, duration_ms, error, "statement failed"),
+            Outcome::Cancelled => info!(duration_ms, "statement cancelled"),
+        }
+        let plan = self.plan.take();
+        if let Some(Slow { log, mut record }) = self.slow.take() {
+            if log.is_slow(duration) {
+                warn!(
+                    duration_ms,
+                    threshold_ms = log.threshold().as_millis() as u64,
+                    "slow statement"
+                );
+                record.duration_ms = duration_ms;
+                record.plan = plan.as_ref().map(explain_analyze);
+                record.sources = plan.as_ref().map(source_timings).unwrap_or_default();
+                record.error = error.clone();
+                if let Err(e) = log.write(&record) {
+                    warn!(error = %e, "failed to write slow query record");
+                }
+            }
+        }
+        if let Some(History { history, mut record }) = self.history.take() {
+            record.finished_ms = record.started_ms + duration_ms;
+            record.rows = rows;
+            record.scanned_bytes = plan.as_ref().map(scanned_bytes);
+            record.outcome = outcome;
+            record.error = error.clone();
+            if let Err(e) = history.record(record) {
+                warn!(error = %e, "failed to write query history record");
+            }
+        }
+        let Some(Pending { sink, mut record }) = self.pending.take() else {
+            return;
+        };
+        record.duration_ms = duration_ms;
+        record.outcome = outcome;
+        record.rows = rows;
+        record.error = error;
+        if let Err(e) = sink.write(&record) {
+            warn!(error = %e, "failed to write audit record");
+        }
+    }
+}
+
+impl Drop for AuditEntry {
+    fn drop(&mut self) {
+        self.finish(Outcome::Cancelled, None, None);
+    }
+}
+
+struct AuditedStream {
+    inner: SendableRecordBatchStream,
+    entry: AuditEntry,
+    rows: u64,
+}
+
+impl Stream for AuditedStream {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let next = this.inner.as_mut().poll_next(cx);
+        match &next {
+            Poll::Ready(Some(Ok(batch))) => this.rows += batch.num_rows() as u64,
+            Poll::Ready(Some(Err(e))) => {
+                this.entry.finish(Outcome::Error, Some(this.rows), Some(e.to_string()))
+            }
+            Poll::Ready(None) => this.entry.finish(Outcome::Ok, Some(this.rows), None),
+            Poll::Pending => {}
+        }
+        next
+    }
+}
+
+impl RecordBatchStream for AuditedStream {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_sink_appends_json_lines() {
+        let path = std::env::temp_dir().join(format!("igloo-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let auditor = Auditor::new(FileAuditSink::open(&path).unwrap()).with_sql_text(false);
+
+        let mut entry = auditor.start("pgwire", Some("alice"), None, "SELECT * FROM secrets");
+        entry.set_tables(vec!["secrets".to_string()]);
+        entry.succeeded(Some(4));
+        drop(auditor.start("http", None, Some("s1"), "SELECT 1"));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> =
+            contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["principal"], "alice");
+        assert_eq!(lines[0]["sql"], serde_json::Value::Null);
+        assert_eq!(lines[0]["tables"], serde_json::json!(["secrets"]));
+        assert_eq!(lines[0]["rows"], 4);
+        assert_eq!(lines[0]["outcome"], "ok");
+        assert_eq!(lines[1]["outcome"], "cancelled");
+    }
+
+    #[test]
+    fn test_disabled_auditing_records_nothing() {
+        let mut entry = start(None, "http", None, None, "SELECT 1");
+        assert!(entry.check(Err::<(), _>("boom")).is_err());
+        assert!(entry.pending.is_none());
+    }
+}
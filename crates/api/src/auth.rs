@@ -0,0 +1,329 @@
+//! Authentication for the network frontends.
+//!
+//! Clients present a single credential, either a static API key or a JWT:
+//!
+//! - HTTP: `Authorization: Bearer <credential>` or `X-API-Key: <credential>`;
+//! - Flight / Flight SQL: `authorization: Bearer <credential>` metadata;
+//! - pgwire: the connection password (the user name is ignored).
+//!
+//! A successful check yields a [`Principal`], which is attached to the request (HTTP
+//! and Flight request extensions) or the session (pgwire metadata under
+//! [`PRINCIPAL_METADATA_KEY`]) for authorization and auditing.
+//!
+//! A principal may belong to a tenant (see [`igloo_engine::tenant`]): an API key's
+//! principal is given one with [`Principal::with_tenant`], a JWT's comes from the claim
+//! named by [`JwtConfig::with_tenant_claim`]. [`engine_for`] confines its queries to
+//! that tenant's catalog and resources.
+
+use igloo_common::error::ApiError;
+use igloo_engine::QueryEngine;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// pgwire session metadata key holding the authenticated principal's subject.
+pub const PRINCIPAL_METADATA_KEY: &str = "igloo.principal";
+
+/// pgwire session metadata key holding the authenticated principal's tenant, if any.
+pub const TENANT_METADATA_KEY: &str = "igloo.tenant";
+
+/// The authenticated identity behind a request or session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Principal {
+    pub subject: String,
+    pub roles: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Principal {
+    pub fn new(subject: impl Into<String>) -> Self {
+        Self { subject: subject.into(), roles: Vec::new(), tenant: None }
+    }
+
+    pub fn with_roles(mut self, roles: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.roles = roles.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+}
+
+/// The engine `principal`'s queries run on: its tenant's, subject to the policies for
+/// its roles and within the budget of its resource class. Without a principal, or
+/// for one without a tenant, that is `engine`'s.
+pub fn engine_for(
+    engine: &QueryEngine,
+    principal: Option<&Principal>,
+) -> Result<QueryEngine, AuthError> {
+    let Some(principal) = principal else {
+        return Ok(engine.with_resources(None));
+    };
+    let tenant = match &principal.tenant {
+        Some(tenant) => {
+            engine.tenant(tenant).ok_or_else(|| AuthError::UnknownTenant(tenant.clone()))?
+        }
+        None => engine.clone(),
+    };
+    Ok(tenant.for_roles(&principal.roles).with_resources(Some(&principal.subject)))
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("missing credentials")]
+    MissingCredentials,
+    #[error("invalid API key")]
+    InvalidApiKey,
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+    #[error("unknown tenant '{0}'")]
+    UnknownTenant(String),
+}
+
+impl AuthError {
+    pub fn to_api_error(&self) -> ApiError {
+        let code = match self {
+            AuthError::UnknownTenant(_) => "permission_denied",
+            _ => "unauthenticated",
+        };
+        ApiError { code, message: self.to_string(), detail: None, hint: None, retryable: false }
+    }
+}
+
+/// How JWTs are validated.
+pub struct JwtConfig {
+    key: DecodingKey,
+    validation: Validation,
+    required_claims: Vec<(String, serde_json::Value)>,
+    roles_claim: String,
+    tenant_claim: Option<String>,
+}
+
+impl JwtConfig {
+    /// Tokens signed with HMAC-SHA256 using a shared secret.
+    pub fn hs256(secret: &[u8]) -> Self {
+        Self::new(DecodingKey::from_secret(secret), Algorithm::HS256)
+    }
+
+    /// Tokens signed with RS256 by the holder of the private key for `pem`.
+    pub fn rs256_pem(pem: &[u8]) -> Result<Self, AuthError> {
+        let key =
+            DecodingKey::from_rsa_pem(pem).map_err(|e| AuthError::InvalidToken(e.to_string()))?;
+        Ok(Self::new(key, Algorithm::RS256))
+    }
+
+    fn new(key: DecodingKey, algorithm: Algorithm) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.validate_aud = false;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        Self {
+            key,
+            validation,
+            required_claims: Vec::new(),
+            roles_claim: "roles".to_string(),
+            tenant_claim: None,
+        }
+    }
+
+    pub fn with_issuer(mut self, issuer: &str) -> Self {
+        self.validation.set_issuer(&[issuer]);
+        self
+    }
+
+    pub fn with_audience(mut self, audience: &str) -> Self {
+        self.validation.set_audience(&[audience]);
+        self.validation.validate_aud = true;
+        self
+    }
+
+    /// Only accept tokens whose `name` claim equals `value`.
+    pub fn with_required_claim(mut self, name: &str, value: serde_json::Value) -> Self {
+        self.required_claims.push((name.to_string(), value));
+        self
+    }
+
+    /// The claim holding the principal's roles, as an array of strings. Defaults to
+    /// `roles`.
+    pub fn with_roles_claim(mut self, name: &str) -> Self {
+        self.roles_claim = name.to_string();
+        self
+    }
+
+    /// The claim naming the principal's tenant, as a string. Tokens are not tied to a
+    /// tenant unless this is set.
+    pub fn with_tenant_claim(mut self, name: &str) -> Self {
+        self.tenant_claim = Some(name.to_string());
+        self
+    }
+
+    fn authenticate(&self, token: &str) -> Result<Principal, AuthError> {
+        let claims = jsonwebtoken::decode::<HashMap<String, serde_json::Value>>(
+            token,
+            &self.key,
+            &self.validation,
+        )
+        .map_err(|e| AuthError::InvalidToken(e.to_string()))?
+        .claims;
+        for (name, expected) in &self.required_claims {
+            if claims.get(name) != Some(expected) {
+                return Err(AuthError::InvalidToken(format!("claim '{name}' does not match")));
+            }
+        }
+        let subject = claims.get("sub").and_then(|sub| sub.as_str()).unwrap_or_default();
+        let roles = match claims.get(&self.roles_claim) {
+            Some(serde_json::Value::Array(roles)) => {
+                roles.iter().filter_map(|role| role.as_str()).map(str::to_string).collect()
+            }
+            _ => Vec::new(),
+        };
+        let tenant = match &self.tenant_claim {
+            Some(name) => match claims.get(name).and_then(|tenant| tenant.as_str()) {
+                Some(tenant) => Some(tenant.to_string()),
+                None => return Err(AuthError::InvalidToken(format!("missing claim '{name}'"))),
+            },
+            None => None,
+        };
+        Ok(Principal { subject: subject.to_string(), roles, tenant })
+    }
+}
+
+/// Validates credentials against the configured API keys and JWT settings.
+#[derive(Default)]
+pub struct Authenticator {
+    api_keys: HashMap<String, Principal>,
+    jwt: Option<JwtConfig>,
+}
+
+impl Authenticator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_api_key(mut self, key: impl Into<String>, principal: Principal) -> Self {
+        self.api_keys.insert(key.into(), principal);
+        self
+    }
+
+    pub fn with_jwt(mut self, jwt: JwtConfig) -> Self {
+        self.jwt = Some(jwt);
+        self
+    }
+
+    /// Resolve a credential to the principal it belongs to. Anything shaped like a JWT
+    /// is validated as one when JWTs are configured; everything else is an API key.
+    pub fn authenticate(&self, credential: &str) -> Result<Principal, AuthError> {
+        if credential.is_empty() {
+            return Err(AuthError::MissingCredentials);
+        }
+        match &self.jwt {
+            Some(jwt) if credential.split('.').count() == 3 => jwt.authenticate(credential),
+            _ => self.api_keys.get(credential).cloned().ok_or(AuthError::InvalidApiKey),
+        }
+    }
+
+    /// Authenticate an `Authorization` header value of the form `Bearer <credential>`.
+    pub fn authenticate_bearer(&self, header: Option<&str>) -> Result<Principal, AuthError> {
+        let credential = header
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or(AuthError::MissingCredentials)?;
+        self.authenticate(credential.trim())
+    }
+}
+
+/// A tonic interceptor that rejects Flight requests without valid credentials and
+/// attaches the [`Principal`] to those with them.
+#[allow(clippy::result_large_err)] // tonic interceptors return `Status` directly.
+pub fn flight_interceptor(
+    auth: Arc<Authenticator>,
+) -> impl FnMut(tonic::Request<()>) -> Result<tonic::Request<()>, tonic::Status> + Clone {
+    move |mut request: tonic::Request<()>| {
+        let header = request.metadata().get("authorization").and_then(|v| v.to_str().ok());
+        let principal = auth
+            .authenticate_bearer(header)
+            .map_err(|e| tonic::Status::unauthenticated(e.to_string()))?;
+        request.extensions_mut().insert(principal);
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn token(claims: serde_json::Value) -> String {
+        encode(&Header::default(), &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    fn authenticator() -> Authenticator {
+        let jwt = JwtConfig::hs256(SECRET)
+            .with_issuer("https://idp.example.com")
+            .with_audience("igloo")
+            .with_required_claim("tenant", serde_json::json!("acme"));
+        Authenticator::new().with_api_key("key-1", Principal::new("etl")).with_jwt(jwt)
+    }
+
+    #[test]
+    fn test_api_keys() {
+        let auth = authenticator();
+        assert_eq!(auth.authenticate("key-1").unwrap().subject, "etl");
+        assert!(matches!(auth.authenticate("key-2"), Err(AuthError::InvalidApiKey)));
+        assert!(matches!(auth.authenticate(""), Err(AuthError::MissingCredentials)));
+        assert!(matches!(
+            auth.authenticate_bearer(Some("Basic a2V5LTE=")),
+            Err(AuthError::MissingCredentials)
+        ));
+        assert_eq!(auth.authenticate_bearer(Some("Bearer key-1")).unwrap().subject, "etl");
+    }
+
+    #[test]
+    fn test_jwt_validation() {
+        let auth = authenticator();
+        let exp = jsonwebtoken::get_current_timestamp() + 600;
+        let claims = serde_json::json!({
+            "sub": "alice", "iss": "https://idp.example.com", "aud": "igloo",
+            "exp": exp, "tenant": "acme", "roles": ["analyst"],
+        });
+        let principal = auth.authenticate(&token(claims.clone())).unwrap();
+        assert_eq!(principal, Principal::new("alice").with_roles(["analyst"]));
+
+        let mut wrong_audience = claims.clone();
+        wrong_audience["aud"] = "other".into();
+        let mut wrong_tenant = claims.clone();
+        wrong_tenant["tenant"] = "globex".into();
+        let mut expired = claims;
+        expired["exp"] = (exp - 3600).into();
+        for claims in [wrong_audience, wrong_tenant, expired] {
+            let result = auth.authenticate(&token(claims));
+            assert!(matches!(result, Err(AuthError::InvalidToken(_))), "{result:?}");
+        }
+    }
+
+    #[test]
+    fn test_tenants() {
+        let jwt = JwtConfig::hs256(SECRET).with_tenant_claim("org");
+        let auth = Authenticator::new()
+            .with_api_key("key-1", Principal::new("etl").with_tenant("acme"))
+            .with_jwt(jwt);
+        let exp = jsonwebtoken::get_current_timestamp() + 600;
+        let claims = serde_json::json!({"sub": "alice", "exp": exp, "org": "globex"});
+        let alice = auth.authenticate(&token(claims)).unwrap();
+        assert_eq!(alice.tenant.as_deref(), Some("globex"));
+        let claims = serde_json::json!({"sub": "bob", "exp": exp});
+        assert!(matches!(auth.authenticate(&token(claims)), Err(AuthError::InvalidToken(_))));
+
+        let engine = QueryEngine::new();
+        engine.add_tenant(igloo_engine::tenant::Tenant::new("acme")).unwrap();
+        let etl = auth.authenticate("key-1").unwrap();
+        assert!(engine_for(&engine, Some(&etl)).is_ok());
+        let err = engine_for(&engine, Some(&alice)).err().unwrap();
+        assert_eq!(err.to_api_error().code, "permission_denied");
+    }
+}
@@ -0,0 +1,354 @@
+//! Running queries on an Apache DataFusion Ballista cluster.
+//!
+//! An engine built with [`QueryEngine::with_query_planner`] and a [`BallistaPlanner`]
+//! keeps planning queries itself, against igloo's catalog and with its policies, but
+//! hands their execution to a Ballista scheduler:
+//!
+//! - the optimized logical plan is serialized with `datafusion-proto` and submitted as
+//!   a job (`ExecuteQuery`), together with the session's `datafusion.*` settings;
+//! - the job is followed (`GetJobStatus`) until it has succeeded or failed;
+//! - its output partitions are fetched in order from the executors holding them, with
+//!   a Flight `DoGet` each.
+//!
+//! The job only starts once the plan's [`BallistaExec`] is executed, and is cancelled
+//! (`CancelJob`) if its results are dropped before it has finished.
+//!
+//! Plans `datafusion-proto` cannot serialize (in-memory tables, custom table
+//! providers), writes and plans that read no table run locally as before. Ballista's
+//! executors read the same paths as igloo, so file and object store tables must be
+//! reachable from each of them.
+//!
+//! [`QueryEngine::with_query_planner`]: igloo_engine::QueryEngine::with_query_planner
+
+use crate::ballista::protobuf::action::ActionType;
+use crate::ballista::protobuf::execute_query_failure_result::Failure;
+use crate::ballista::protobuf::execute_query_params::{OptionalJobName, Query};
+use crate::ballista::protobuf::execute_query_result::Result as ExecuteResult;
+use crate::ballista::protobuf::job_status::Status as JobState;
+use crate::ballista::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+use crate::ballista::protobuf::{
+    Action, CancelJobParams, ExecuteQueryParams, FetchPartition, GetJobStatusParams, KeyValuePair,
+    PartitionLocation,
+};
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::flight_service_client::FlightServiceClient;
+use arrow_flight::Ticket;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::QueryPlanner;
+use datafusion::execution::session_state::SessionState;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+};
+use datafusion::physical_planner::{DefaultPhysicalPlanner, PhysicalPlanner};
+use datafusion_proto::bytes::logical_plan_to_bytes;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use prost::Message;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tonic::transport::{Channel, Endpoint};
+
+/// Generated messages and client of Ballista's scheduler protocol.
+pub mod protobuf {
+    tonic::include_proto!("ballista.protobuf");
+}
+
+/// How often a running job's status is polled unless configured otherwise.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Plans queries locally and executes them on a Ballista cluster.
+#[derive(Debug, Clone)]
+pub struct BallistaPlanner {
+    scheduler: String,
+    poll_interval: Duration,
+}
+
+impl BallistaPlanner {
+    /// Run queries through the scheduler at `scheduler`, e.g. `http://host:50050`.
+    pub fn new(scheduler: impl Into<String>) -> Self {
+        Self { scheduler: scheduler.into(), poll_interval: DEFAULT_POLL_INTERVAL }
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// The serialized plan if Ballista should run it.
+    fn delegate(plan: &LogicalPlan) -> Option<Vec<u8>> {
+        let query = !matches!(
+            plan,
+            LogicalPlan::Dml(_)
+                | LogicalPlan::Ddl(_)
+                | LogicalPlan::Copy(_)
+                | LogicalPlan::Explain(_)
+                | LogicalPlan::Analyze(_)
+                | LogicalPlan::Statement(_)
+                | LogicalPlan::DescribeTable(_)
+        );
+        let mut scans = false;
+        let _ = plan.apply(|node| {
+            scans |= matches!(node, LogicalPlan::TableScan(_));
+            Ok(if scans { TreeNodeRecursion::Stop } else { TreeNodeRecursion::Continue })
+        });
+        (query && scans).then(|| logical_plan_to_bytes(plan).ok()).flatten().map(Vec::from)
+    }
+}
+
+#[async_trait]
+impl QueryPlanner for BallistaPlanner {
+    async fn create_physical_plan(
+        &self,
+        logical_plan: &LogicalPlan,
+        session_state: &SessionState,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let Some(plan) = Self::delegate(logical_plan) else {
+            let planner = DefaultPhysicalPlanner::default();
+            return planner.create_physical_plan(logical_plan, session_state).await;
+        };
+        let settings = session_state
+            .config_options()
+            .entries()
+            .into_iter()
+            .filter_map(|entry| Some(KeyValuePair { key: entry.key, value: entry.value? }))
+            .collect();
+        let job = BallistaJob {
+            scheduler: self.scheduler.clone(),
+            poll_interval: self.poll_interval,
+            plan,
+            settings,
+            name: session_state.session_id().to_string(),
+        };
+        let mut exec: Arc<dyn ExecutionPlan> =
+            Arc::new(BallistaExec::new(Arc::new(logical_plan.schema().as_arrow().clone()), job));
+        // Run the rules that wrap a plan's output (e.g. resource budgets).
+        for rule in session_state.physical_optimizers() {
+            exec = rule.optimize(exec, session_state.config_options())?;
+        }
+        Ok(exec)
+    }
+}
+
+/// A query for a Ballista scheduler.
+#[derive(Debug, Clone)]
+struct BallistaJob {
+    scheduler: String,
+    poll_interval: Duration,
+    plan: Vec<u8>,
+    settings: Vec<KeyValuePair>,
+    name: String,
+}
+
+impl BallistaJob {
+    async fn connect(&self) -> DataFusionResult<SchedulerGrpcClient<Channel>> {
+        let channel = Endpoint::from_shared(self.scheduler.clone())
+            .map_err(|e| DataFusionError::External(Box::new(e)))?
+            .connect()
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        Ok(SchedulerGrpcClient::new(channel))
+    }
+
+    /// Submit the job and wait for it to finish, returning where its output is.
+    async fn run(
+        &self,
+        scheduler: &mut SchedulerGrpcClient<Channel>,
+        submitted: &mut Option<String>,
+    ) -> DataFusionResult<Vec<PartitionLocation>> {
+        let params = ExecuteQueryParams {
+            query: Some(Query::LogicalPlan(self.plan.clone())),
+            optional_session_id: None,
+            settings: self.settings.clone(),
+            optional_job_name: Some(OptionalJobName::JobName(self.name.clone())),
+        };
+        let response = scheduler.execute_query(params).await.map_err(status_error)?;
+        let job_id = match response.into_inner().result {
+            Some(ExecuteResult::Success(success)) => success.job_id,
+            Some(ExecuteResult::Failure(failure)) => {
+                let reason = match failure.failure {
+                    Some(Failure::SessionNotFound(e))
+                    | Some(Failure::PlanParsingFailure(e))
+                    | Some(Failure::SqlParsingFailure(e)) => e,
+                    None => "unknown error".to_string(),
+                };
+                return Err(DataFusionError::Execution(format!(
+                    "Ballista refused the query: {reason}"
+                )));
+            }
+            None => return Err(DataFusionError::Execution("empty Ballista response".into())),
+        };
+        submitted.replace(job_id.clone());
+        loop {
+            let params = GetJobStatusParams { job_id: job_id.clone() };
+            let status = scheduler.get_job_status(params).await.map_err(status_error)?;
+            match status.into_inner().status.and_then(|status| status.status) {
+                Some(JobState::Successful(job)) => {
+                    let mut locations = job.partition_location;
+                    locations.sort_by_key(|location| {
+                        location.partition_id.as_ref().map(|id| id.partition_id)
+                    });
+                    return Ok(locations);
+                }
+                Some(JobState::Failed(job)) => {
+                    return Err(DataFusionError::Execution(format!(
+                        "Ballista job {job_id} failed: {}",
+                        job.error
+                    )));
+                }
+                Some(JobState::Queued(_)) | Some(JobState::Running(_)) | None => {
+                    tokio::time::sleep(self.poll_interval).await;
+                }
+            }
+        }
+    }
+}
+
+/// Cancels a submitted job unless it finished.
+struct CancelOnDrop {
+    job: BallistaJob,
+    job_id: Option<String>,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let (Some(job_id), Ok(runtime)) =
+            (self.job_id.take(), tokio::runtime::Handle::try_current())
+        else {
+            return;
+        };
+        let job = self.job.clone();
+        runtime.spawn(async move {
+            if let Ok(mut scheduler) = job.connect().await {
+                let _ = scheduler.cancel_job(CancelJobParams { job_id }).await;
+            }
+        });
+    }
+}
+
+/// Output of one partition of a finished job, read from its executor.
+async fn fetch(
+    location: PartitionLocation,
+) -> DataFusionResult<BoxStream<'static, DataFusionResult<RecordBatch>>> {
+    let (Some(partition), Some(executor)) = (location.partition_id, location.executor_meta) else {
+        return Err(DataFusionError::Execution("incomplete Ballista partition location".into()));
+    };
+    let action = Action {
+        action_type: Some(ActionType::FetchPartition(FetchPartition {
+            job_id: partition.job_id,
+            stage_id: partition.stage_id,
+            partition_id: partition.partition_id,
+            path: location.path,
+            host: executor.host.clone(),
+            port: executor.port,
+        })),
+        settings: Vec::new(),
+    };
+    let address = format!("http://{}:{}", executor.host, executor.port);
+    let channel = Endpoint::from_shared(address)
+        .map_err(|e| DataFusionError::External(Box::new(e)))?
+        .connect()
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    let ticket = Ticket { ticket: action.encode_to_vec().into() };
+    let response = FlightServiceClient::new(channel).do_get(ticket).await.map_err(status_error)?;
+    let batches = FlightRecordBatchStream::new_from_flight_data(
+        response.into_inner().map_err(arrow_flight::error::FlightError::from),
+    );
+    Ok(batches.map_err(|e| DataFusionError::External(Box::new(e))).boxed())
+}
+
+fn status_error(status: tonic::Status) -> DataFusionError {
+    DataFusionError::External(Box::new(status))
+}
+
+/// Runs a query on a Ballista cluster and streams its output as one partition.
+#[derive(Debug)]
+pub struct BallistaExec {
+    schema: SchemaRef,
+    job: BallistaJob,
+    properties: PlanProperties,
+}
+
+impl BallistaExec {
+    fn new(schema: SchemaRef, job: BallistaJob) -> Self {
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(Arc::clone(&schema)),
+            Partitioning::UnknownPartitioning(1),
+            EmissionType::Incremental,
+            Boundedness::Bounded,
+        );
+        Self { schema, job, properties }
+    }
+
+    /// The scheduler the query runs on.
+    pub fn scheduler(&self) -> &str {
+        &self.job.scheduler
+    }
+}
+
+impl DisplayAs for BallistaExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BallistaExec: scheduler={}", self.job.scheduler)
+    }
+}
+
+impl ExecutionPlan for BallistaExec {
+    fn name(&self) -> &str {
+        "BallistaExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        if partition != 0 {
+            return Err(DataFusionError::Internal(format!(
+                "BallistaExec has one partition, not {partition}"
+            )));
+        }
+        let job = self.job.clone();
+        let locations = async move {
+            let mut scheduler = job.connect().await?;
+            let mut guard = CancelOnDrop { job, job_id: None };
+            let locations = guard.job.run(&mut scheduler, &mut guard.job_id).await;
+            // Finished (or failed) jobs are not cancelled.
+            guard.job_id = None;
+            locations
+        };
+        let batches = futures::stream::once(locations)
+            .map_ok(|locations| futures::stream::iter(locations).then(fetch).try_flatten())
+            .try_flatten();
+        Ok(Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches)))
+    }
+}
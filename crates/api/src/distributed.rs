@@ -0,0 +1,961 @@
+//! Distributed query execution across worker nodes.
+//!
+//! A coordinator adds a [`DistributedPlanner`] to its engine's physical optimizer
+//! rules. While its [`Membership`] has workers available, it cuts each plan into stages at every `RepartitionExec` and below
+//! every `CoalescePartitionsExec` / `SortPreservingMergeExec`, putting a
+//! [`ShuffleExchangeExec`] at each cut:
+//!
+//! - when first executed, an exchange serializes its stage with `datafusion-proto`
+//!   and sends the workers one `ExecuteTask` per input partition, each to the worker
+//!   with the most spare capacity;
+//! - a worker ([`WorkerExecutor`]) runs its task and keeps the output in memory, split
+//!   into the stage's output partitions: by hash or round robin when the stage ends in
+//!   a repartition, one to one otherwise;
+//! - the exchange's partitions are then streamed back (`GetDataForTask`) from every
+//!   worker that ran one of its tasks, either to the coordinator or to the workers
+//!   running a later stage, where the exchange has become a [`ShuffleReaderExec`].
+//!
+//! Stages run bottom-up, each once the stages it reads from have finished, so the
+//! coordinator itself runs only the top of the plan, typically the final merge. Once a
+//! query's plan is dropped its shuffle data is removed from the workers (`RemoveJob`).
+//!
+//! A query survives losing workers. Each task's output is kept and read apart from
+//! the others, so a task can be run again without its output being read twice. When
+//! a task cannot reach its worker it is run again on another one; when it cannot read
+//! its input from a worker (which then tells the coordinator which one), the tasks
+//! whose output that worker held are run again elsewhere, recursively, and the task
+//! follows. Output still held by the surviving workers is reused. The coordinator
+//! recovers output it cannot read itself the same way. A stage's tasks are run at
+//! most [`DistributedPlanner::with_max_attempts`] times before the query fails.
+//!
+//! Plans that `datafusion-proto` cannot serialize (in-memory tables, custom table
+//! providers, writes) run on the coordinator as before. Workers read the same paths as
+//! the coordinator, so file and object store tables must be reachable from each of them.
+//!
+//! Other crates' plan nodes, such as the scans of federated sources, are sent to the
+//! workers by a codec registered under the same name with the planner
+//! ([`DistributedPlanner::with_extension_codec`]) and each worker
+//! ([`WorkerExecutor::with_extension_codec`]). Such codecs encode a reference to the
+//! credentials a scan needs (e.g. the name of a secret), never the credentials
+//! themselves, and resolve it on the worker when decoding.
+
+use crate::flight_sql::datafusion_error_to_status;
+use crate::igloo::extension_node::Node as ExtensionNodeKind;
+use crate::igloo::worker_service_client::WorkerServiceClient;
+use crate::igloo::worker_service_server::WorkerService;
+use crate::igloo::{
+    DataForTaskRequest, DataForTaskResponse, ExtensionNode, PrefetchNode, RegisteredNode,
+    RemoveJobRequest, ShuffleReaderNode, TaskDefinition, TaskStatus,
+};
+use crate::membership::Membership;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{FunctionRegistry, SendableRecordBatchStream, TaskContext};
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::metrics::Time;
+use datafusion::physical_plan::repartition::{BatchPartitioner, RepartitionExec};
+use datafusion::physical_plan::sorts::sort_preserving_merge::SortPreservingMergeExec;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, ExecutionPlanProperties, Partitioning,
+    PlanProperties,
+};
+use datafusion_proto::physical_plan::from_proto::parse_protobuf_partitioning;
+use datafusion_proto::physical_plan::to_proto::serialize_partitioning;
+use datafusion_proto::physical_plan::{AsExecutionPlan, PhysicalExtensionCodec};
+use datafusion_proto::protobuf::{Partitioning as PartitioningNode, PhysicalPlanNode};
+use futures::future::{join_all, try_join_all, BoxFuture};
+use futures::stream::BoxStream;
+use futures::{FutureExt, Stream, StreamExt, TryStreamExt};
+use igloo_engine::prefetch::PrefetchExec;
+use igloo_engine::QueryEngine;
+use prost::Message;
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Code, Request, Response, Status};
+
+/// Times a stage's tasks are run, counting the first, before a query fails because
+/// workers were lost.
+pub const DEFAULT_MAX_ATTEMPTS: usize = 3;
+
+/// gRPC metadata key on a failed task naming the worker whose shuffle output the task
+/// could not read.
+const LOST_WORKER_METADATA: &str = "x-igloo-lost-worker";
+
+/// Physical optimizer rule that splits plans into stages run by the workers of
+/// `membership`.
+#[derive(Debug)]
+pub struct DistributedPlanner {
+    membership: Arc<Membership>,
+    clients: Arc<WorkerClients>,
+    extensions: Arc<ExtensionCodecs>,
+    max_attempts: usize,
+}
+
+impl DistributedPlanner {
+    pub fn new(membership: Arc<Membership>) -> Self {
+        Self {
+            membership,
+            clients: Arc::default(),
+            extensions: Arc::default(),
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+        }
+    }
+
+    /// Send the plan nodes `codec` encodes to the workers, which decode them with the
+    /// codec registered there under the same `name`.
+    pub fn with_extension_codec(
+        mut self,
+        name: impl Into<String>,
+        codec: Arc<dyn PhysicalExtensionCodec>,
+    ) -> Self {
+        Arc::make_mut(&mut self.extensions).insert(name.into(), codec);
+        self
+    }
+
+    /// Run a stage's tasks at most `attempts` times (at least once) when workers are
+    /// lost.
+    pub fn with_max_attempts(mut self, attempts: usize) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+}
+
+impl PhysicalOptimizerRule for DistributedPlanner {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if !self.membership.has_available() {
+            return Ok(plan);
+        }
+        // Only plans the workers can run in full are distributed.
+        let codec = ShuffleCodec::new(Arc::clone(&self.clients), Arc::clone(&self.extensions));
+        if PhysicalPlanNode::try_from_physical_plan(Arc::clone(&plan), &codec).is_err() {
+            return Ok(plan);
+        }
+        let job = Arc::new(Job::new(
+            Arc::clone(&self.membership),
+            Arc::clone(&self.clients),
+            Arc::clone(&self.extensions),
+            self.max_attempts,
+        ));
+        let exchange = |stage| Arc::new(ShuffleExchangeExec::new(stage, Arc::clone(&job)));
+        let plan = plan.transform_up(|node| {
+            if let Some(repartition) = node.as_any().downcast_ref::<RepartitionExec>() {
+                // Order-preserving repartitions stay within their stage.
+                if !repartition.preserve_order() {
+                    return Ok(Transformed::yes(exchange(node) as Arc<dyn ExecutionPlan>));
+                }
+            }
+            let any = node.as_any();
+            if any.is::<CoalescePartitionsExec>() || any.is::<SortPreservingMergeExec>() {
+                let input = Arc::clone(node.children()[0]);
+                if !input.as_any().is::<ShuffleExchangeExec>() {
+                    let node = node.with_new_children(vec![exchange(input)])?;
+                    return Ok(Transformed::yes(node));
+                }
+            }
+            Ok(Transformed::no(node))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        "distributed_planner"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// gRPC channels to workers by address, connected on first use.
+#[derive(Debug, Default)]
+struct WorkerClients {
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+impl WorkerClients {
+    fn client(&self, address: &str) -> DataFusionResult<WorkerServiceClient<Channel>> {
+        let mut channels = self.channels.lock().expect("worker client lock poisoned");
+        let channel = match channels.get(address) {
+            Some(channel) => channel.clone(),
+            None => {
+                let url = match address.contains("://") {
+                    true => address.to_string(),
+                    false => format!("http://{address}"),
+                };
+                let channel = Endpoint::from_shared(url)
+                    .map_err(|e| DataFusionError::External(Box::new(e)))?
+                    .connect_lazy();
+                channels.insert(address.to_string(), channel.clone());
+                channel
+            }
+        };
+        Ok(WorkerServiceClient::new(channel).max_decoding_message_size(usize::MAX))
+    }
+}
+
+/// Codecs for other crates' plan nodes, by the name they are registered under.
+type ExtensionCodecs = BTreeMap<String, Arc<dyn PhysicalExtensionCodec>>;
+
+/// Why a task did not finish.
+enum TaskError {
+    /// A worker was lost: the one running the task, or one holding its input.
+    Lost(String),
+    Failed(DataFusionError),
+}
+
+/// The stages of one query. Dropping the last plan node referring to it removes the
+/// query's shuffle data from the workers.
+#[derive(Debug)]
+struct Job {
+    id: String,
+    membership: Arc<Membership>,
+    clients: Arc<WorkerClients>,
+    extensions: Arc<ExtensionCodecs>,
+    max_attempts: usize,
+    next_stage: AtomicU32,
+    /// Workers given tasks, which may hold shuffle data.
+    used: Mutex<BTreeSet<String>>,
+    /// Workers lost during the query, which get none of its tasks.
+    lost: Mutex<BTreeSet<String>>,
+}
+
+impl Job {
+    fn new(
+        membership: Arc<Membership>,
+        clients: Arc<WorkerClients>,
+        extensions: Arc<ExtensionCodecs>,
+        max_attempts: usize,
+    ) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            membership,
+            clients,
+            extensions,
+            max_attempts,
+            next_stage: AtomicU32::new(1),
+            used: Mutex::default(),
+            lost: Mutex::default(),
+        }
+    }
+
+    fn lose(&self, worker: &str) {
+        self.lost.lock().expect("job lock poisoned").insert(worker.to_string());
+    }
+
+    /// Run the tasks of stage `stage_id` for the given input partitions, and return
+    /// for each the worker now holding its output or why it has none.
+    async fn run_tasks(
+        &self,
+        stage_id: u32,
+        payload: &[u8],
+        partitions: &[usize],
+    ) -> Vec<(usize, Result<String, TaskError>)> {
+        let lost = self.lost.lock().expect("job lock poisoned").clone();
+        let mut running = Vec::with_capacity(partitions.len());
+        for &partition in partitions {
+            let placed = self
+                .membership
+                .place(&lost)
+                .ok_or_else(|| {
+                    DataFusionError::Execution(format!("no worker available for stage {stage_id}"))
+                })
+                .and_then(|slot| Ok((self.clients.client(&slot.address)?, slot)));
+            if let Ok((_, slot)) = &placed {
+                self.used.lock().expect("job lock poisoned").insert(slot.address.clone());
+            }
+            let task = TaskDefinition {
+                task_id: format!("{}/{stage_id}/{partition}", self.id),
+                payload: payload.to_vec(),
+                job_id: self.id.clone(),
+                stage_id,
+                partition: partition as u32,
+            };
+            running.push(async move {
+                let (mut client, slot) = match placed {
+                    Ok(placed) => placed,
+                    Err(e) => return (partition, Err(TaskError::Failed(e))),
+                };
+                let worker = slot.address.clone();
+                let outcome = match client.execute_task(task).await {
+                    Ok(_) => {
+                        slot.reached(true);
+                        Ok(worker)
+                    }
+                    Err(status) if status.code() == Code::Unavailable => {
+                        slot.reached(false);
+                        Err(TaskError::Lost(worker))
+                    }
+                    // Errors from the task itself say nothing about the worker's health.
+                    Err(status) => {
+                        slot.reached(true);
+                        let lost = status.metadata().get(LOST_WORKER_METADATA);
+                        match lost.and_then(|value| value.to_str().ok()) {
+                            Some(lost) => Err(TaskError::Lost(lost.to_string())),
+                            None => Err(TaskError::Failed(DataFusionError::Execution(format!(
+                                "stage {stage_id} failed on worker {worker}: {}",
+                                status.message()
+                            )))),
+                        }
+                    }
+                };
+                (partition, outcome)
+            });
+        }
+        join_all(running).await
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let used = self.used.get_mut().expect("job lock poisoned");
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        for worker in used.iter() {
+            let Ok(mut client) = self.clients.client(worker) else {
+                continue;
+            };
+            let request = RemoveJobRequest { job_id: self.id.clone() };
+            runtime.spawn(async move { client.remove_job(request).await });
+        }
+    }
+}
+
+/// A stage and, once it has run, the worker holding each of its tasks' output.
+#[derive(Debug)]
+struct Stage {
+    id: u32,
+    plan: Arc<dyn ExecutionPlan>,
+    properties: PlanProperties,
+    job: Arc<Job>,
+    locations: tokio::sync::Mutex<Option<Vec<String>>>,
+}
+
+impl Stage {
+    /// Run the stage on the workers, after the stages it reads from, unless it has
+    /// already run, and return a reader of its output.
+    async fn resolve(
+        self: &Arc<Self>,
+        context: &Arc<TaskContext>,
+    ) -> DataFusionResult<Arc<ShuffleReaderExec>> {
+        let mut locations = self.locations.lock().await;
+        let locations = match &mut *locations {
+            Some(locations) => locations.clone(),
+            None => {
+                let mut tasks = vec![None; self.task_count()];
+                self.run(&mut tasks, context).await?;
+                locations.insert(tasks.into_iter().flatten().collect()).clone()
+            }
+        };
+        Ok(Arc::new(ShuffleReaderExec {
+            job_id: self.job.id.clone(),
+            stage_id: self.id,
+            locations,
+            properties: self.properties.clone(),
+            clients: Arc::clone(&self.job.clients),
+            stage: Some(Arc::clone(self)),
+        }))
+    }
+
+    /// Run again, on other workers, the tasks whose output was held by the `lost`
+    /// worker, and return where the stage's output is now.
+    fn recover<'a>(
+        &'a self,
+        lost: &'a str,
+        context: &'a Arc<TaskContext>,
+    ) -> BoxFuture<'a, DataFusionResult<Vec<String>>> {
+        async move {
+            self.job.lose(lost);
+            let mut locations = self.locations.lock().await;
+            let Some(current) = &mut *locations else {
+                return Ok(Vec::new());
+            };
+            let mut tasks: Vec<_> =
+                current.iter().map(|worker| (worker != lost).then(|| worker.clone())).collect();
+            self.run(&mut tasks, context).await?;
+            *current = tasks.into_iter().flatten().collect();
+            Ok(current.clone())
+        }
+        .boxed()
+    }
+
+    /// Run the tasks without a location yet. A task that loses a worker is run again,
+    /// as are the tasks of the stages it reads from that lost their output with it.
+    async fn run(
+        &self,
+        tasks: &mut [Option<String>],
+        context: &Arc<TaskContext>,
+    ) -> DataFusionResult<()> {
+        for attempt in 1.. {
+            let pending: Vec<_> = (0..tasks.len()).filter(|&p| tasks[p].is_none()).collect();
+            if pending.is_empty() {
+                break;
+            }
+            let plan = resolve_exchanges(Arc::clone(&self.plan), context).await?;
+            let codec =
+                ShuffleCodec::new(Arc::clone(&self.job.clients), Arc::clone(&self.job.extensions));
+            let mut payload = Vec::new();
+            PhysicalPlanNode::try_from_physical_plan(plan, &codec)?.try_encode(&mut payload)?;
+            let mut lost = BTreeSet::new();
+            for (partition, outcome) in self.job.run_tasks(self.id, &payload, &pending).await {
+                match outcome {
+                    Ok(worker) => tasks[partition] = Some(worker),
+                    Err(TaskError::Lost(worker)) => {
+                        lost.insert(worker);
+                    }
+                    Err(TaskError::Failed(e)) => return Err(e),
+                }
+            }
+            if lost.is_empty() {
+                continue;
+            }
+            if attempt >= self.job.max_attempts {
+                let lost: Vec<_> = lost.into_iter().collect();
+                return Err(DataFusionError::Execution(format!(
+                    "stage {} failed after {attempt} attempts, lost workers: {}",
+                    self.id,
+                    lost.join(", ")
+                )));
+            }
+            for worker in &lost {
+                self.job.lose(worker);
+                // Output of this stage's finished tasks went with the worker too.
+                for task in tasks.iter_mut() {
+                    if task.as_ref() == Some(worker) {
+                        *task = None;
+                    }
+                }
+                for input in self.inputs() {
+                    input.recover(worker, context).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// One task per input partition of the final repartition, or per output
+    /// partition of a stage without one.
+    fn task_count(&self) -> usize {
+        match self.plan.as_any().downcast_ref::<RepartitionExec>() {
+            Some(repartition) => repartition.input().output_partitioning().partition_count(),
+            None => self.plan.output_partitioning().partition_count(),
+        }
+    }
+
+    /// The stages this stage reads from.
+    fn inputs(&self) -> Vec<Arc<Stage>> {
+        let mut inputs = Vec::new();
+        let mut pending = vec![Arc::clone(&self.plan)];
+        while let Some(plan) = pending.pop() {
+            match plan.as_any().downcast_ref::<ShuffleExchangeExec>() {
+                Some(exchange) => inputs.push(Arc::clone(&exchange.stage)),
+                None => pending.extend(plan.children().into_iter().cloned()),
+            }
+        }
+        inputs
+    }
+}
+
+/// `plan` with every exchange in it replaced by a reader of its stage's output.
+fn resolve_exchanges(
+    plan: Arc<dyn ExecutionPlan>,
+    context: &Arc<TaskContext>,
+) -> BoxFuture<'_, DataFusionResult<Arc<dyn ExecutionPlan>>> {
+    async move {
+        if let Some(exchange) = plan.as_any().downcast_ref::<ShuffleExchangeExec>() {
+            let reader: Arc<dyn ExecutionPlan> = exchange.stage.resolve(context).await?;
+            return Ok(reader);
+        }
+        let children: Vec<_> = plan.children().into_iter().cloned().collect();
+        if children.is_empty() {
+            return Ok(plan);
+        }
+        let children =
+            try_join_all(children.into_iter().map(|child| resolve_exchanges(child, context)))
+                .await?;
+        plan.with_new_children(children)
+    }
+    .boxed()
+}
+
+/// Boundary between two stages: its input is run on the workers and its partitions
+/// are read back from them.
+#[derive(Debug)]
+pub struct ShuffleExchangeExec {
+    stage: Arc<Stage>,
+}
+
+impl ShuffleExchangeExec {
+    fn new(plan: Arc<dyn ExecutionPlan>, job: Arc<Job>) -> Self {
+        let id = job.next_stage.fetch_add(1, Ordering::Relaxed);
+        Self::with_id(id, plan, job)
+    }
+
+    fn with_id(id: u32, plan: Arc<dyn ExecutionPlan>, job: Arc<Job>) -> Self {
+        let properties = plan.properties().clone();
+        let locations = tokio::sync::Mutex::new(None);
+        Self { stage: Arc::new(Stage { id, plan, properties, job, locations }) }
+    }
+
+    pub fn stage_id(&self) -> u32 {
+        self.stage.id
+    }
+}
+
+impl DisplayAs for ShuffleExchangeExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ShuffleExchangeExec: stage={}", self.stage.id)
+    }
+}
+
+impl ExecutionPlan for ShuffleExchangeExec {
+    fn name(&self) -> &str {
+        "ShuffleExchangeExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.stage.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.stage.plan]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan = children.swap_remove(0);
+        Ok(Arc::new(Self::with_id(self.stage.id, plan, Arc::clone(&self.stage.job))))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let stage = Arc::clone(&self.stage);
+        let stream = futures::stream::once(async move {
+            let reader = stage.resolve(&context).await?;
+            reader.execute(partition, context)
+        })
+        .try_flatten();
+        Ok(Box::pin(RecordBatchStreamAdapter::new(self.schema(), stream)))
+    }
+}
+
+/// Shuffle output that could not be read because the worker holding it is gone or no
+/// longer has it.
+#[derive(Debug, Error)]
+#[error("failed to read shuffle data from worker {worker}: {message}")]
+struct LostOutput {
+    worker: String,
+    message: String,
+}
+
+/// The worker named by a [`LostOutput`] anywhere in `error`'s chain of sources.
+fn lost_worker(error: &DataFusionError) -> Option<&str> {
+    let mut error: Option<&(dyn std::error::Error + 'static)> = Some(error);
+    while let Some(e) = error {
+        if let Some(lost) = e.downcast_ref::<LostOutput>() {
+            return Some(&lost.worker);
+        }
+        error = e.source();
+    }
+    None
+}
+
+/// Reads the output partitions of a stage that has run on the workers.
+#[derive(Debug)]
+pub struct ShuffleReaderExec {
+    job_id: String,
+    stage_id: u32,
+    /// Worker holding each task's output, by input partition.
+    locations: Vec<String>,
+    properties: PlanProperties,
+    clients: Arc<WorkerClients>,
+    /// On the coordinator, the stage read, which keeps the job's shuffle data on the
+    /// workers while this reader's streams run and recovers output that was lost.
+    stage: Option<Arc<Stage>>,
+}
+
+impl ShuffleReaderExec {
+    /// Stream the output partition `request` asks for from `location`.
+    async fn fetch(
+        clients: &WorkerClients,
+        location: &str,
+        request: DataForTaskRequest,
+    ) -> DataFusionResult<BoxStream<'static, DataFusionResult<RecordBatch>>> {
+        let mut client = clients.client(location)?;
+        let response = match client.get_data_for_task(request).await {
+            Ok(response) => response,
+            Err(status) if matches!(status.code(), Code::Unavailable | Code::NotFound) => {
+                let lost =
+                    LostOutput { worker: location.to_string(), message: status.message().into() };
+                return Err(DataFusionError::External(Box::new(lost)));
+            }
+            Err(status) => {
+                return Err(DataFusionError::Execution(format!(
+                    "failed to read shuffle data from worker {location}: {}",
+                    status.message()
+                )))
+            }
+        };
+        let batches = response.into_inner().map(|response| {
+            let response =
+                response.map_err(|status| DataFusionError::External(Box::new(status)))?;
+            let (_, batches) = decode_ipc(&response.data)?;
+            Ok::<_, DataFusionError>(futures::stream::iter(batches.into_iter().map(Ok)))
+        });
+        Ok(batches.try_flatten().boxed())
+    }
+}
+
+/// Tasks by the worker holding their output.
+fn by_location<'a>(
+    tasks: impl IntoIterator<Item = (u32, &'a String)>,
+) -> BTreeMap<String, Vec<u32>> {
+    let mut locations: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for (task, location) in tasks {
+        locations.entry(location.clone()).or_default().push(task);
+    }
+    locations
+}
+
+impl DisplayAs for ShuffleReaderExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ShuffleReaderExec: stage={}, locations={:?}", self.stage_id, self.locations)
+    }
+}
+
+impl ExecutionPlan for ShuffleReaderExec {
+    fn name(&self) -> &str {
+        "ShuffleReaderExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let (clients, stage) = (Arc::clone(&self.clients), self.stage.clone());
+        let request = DataForTaskRequest {
+            task_id: String::new(),
+            job_id: self.job_id.clone(),
+            stage_id: self.stage_id,
+            partition: partition as u32,
+            map_partitions: Vec::new(),
+        };
+        let locations = by_location((0..).zip(&self.locations));
+        let stream = futures::stream::iter(locations)
+            .then(move |(location, tasks)| {
+                // Plans are dropped once their streams are created, so the streams
+                // hold on to the stage and with it the job.
+                let (clients, stage, context) =
+                    (Arc::clone(&clients), stage.clone(), Arc::clone(&context));
+                let request =
+                    DataForTaskRequest { map_partitions: tasks.clone(), ..request.clone() };
+                async move {
+                    let error = match Self::fetch(&clients, &location, request.clone()).await {
+                        Ok(batches) => return Ok(batches),
+                        Err(e) => e,
+                    };
+                    // Output lost before the coordinator read it is computed again.
+                    let Some(stage) = stage.filter(|_| lost_worker(&error).is_some()) else {
+                        return Err(error);
+                    };
+                    let locations = stage.recover(&location, &context).await?;
+                    let moved = tasks.iter().map(|&task| (task, &locations[task as usize]));
+                    let mut recovered = Vec::new();
+                    for (location, tasks) in by_location(moved) {
+                        let request =
+                            DataForTaskRequest { map_partitions: tasks, ..request.clone() };
+                        recovered.push(Self::fetch(&clients, &location, request).await?);
+                    }
+                    Ok(futures::stream::iter(recovered).flatten().boxed())
+                }
+            })
+            .try_flatten();
+        Ok(Box::pin(RecordBatchStreamAdapter::new(self.schema(), stream)))
+    }
+}
+
+/// Encodes [`ShuffleReaderExec`]s, which are all a shipped stage contains besides
+/// DataFusion's own plan nodes.
+#[derive(Debug)]
+struct ShuffleCodec {
+    clients: Arc<WorkerClients>,
+    extensions: Arc<ExtensionCodecs>,
+}
+
+impl ShuffleCodec {
+    fn new(clients: Arc<WorkerClients>, extensions: Arc<ExtensionCodecs>) -> Self {
+        Self { clients, extensions }
+    }
+}
+
+impl PhysicalExtensionCodec for ShuffleCodec {
+    fn try_decode(
+        &self,
+        buf: &[u8],
+        inputs: &[Arc<dyn ExecutionPlan>],
+        registry: &dyn FunctionRegistry,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let node =
+            ExtensionNode::decode(buf).map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let node = match node.node {
+            Some(ExtensionNodeKind::ShuffleReader(node)) => node,
+            Some(ExtensionNodeKind::Prefetch(node)) => {
+                let input = Arc::clone(&inputs[0]);
+                return Ok(Arc::new(PrefetchExec::new(input, node.batches as usize)));
+            }
+            Some(ExtensionNodeKind::Registered(node)) => {
+                let Some(codec) = self.extensions.get(&node.codec) else {
+                    return Err(DataFusionError::Plan(format!(
+                        "no codec '{}' registered for this plan node",
+                        node.codec
+                    )));
+                };
+                return codec.try_decode(&node.payload, inputs, registry);
+            }
+            None => return Err(DataFusionError::Plan("empty extension node".to_string())),
+        };
+        let (schema, _) = decode_ipc(&node.schema)?;
+        let partitioning = PartitioningNode::decode(node.partitioning.as_slice())
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let partitioning =
+            parse_protobuf_partitioning(Some(&partitioning), registry, &schema, self)?
+                .unwrap_or(Partitioning::UnknownPartitioning(1));
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(schema),
+            partitioning,
+            EmissionType::Incremental,
+            Boundedness::Bounded,
+        );
+        Ok(Arc::new(ShuffleReaderExec {
+            job_id: node.job_id,
+            stage_id: node.stage_id,
+            locations: node.locations,
+            properties,
+            clients: Arc::clone(&self.clients),
+            stage: None,
+        }))
+    }
+
+    fn try_encode(&self, node: Arc<dyn ExecutionPlan>, buf: &mut Vec<u8>) -> DataFusionResult<()> {
+        if let Some(prefetch) = node.as_any().downcast_ref::<PrefetchExec>() {
+            let node = PrefetchNode { batches: prefetch.batches() as u32 };
+            let node = ExtensionNode { node: Some(ExtensionNodeKind::Prefetch(node)) };
+            return node.encode(buf).map_err(|e| DataFusionError::External(Box::new(e)));
+        }
+        let Some(reader) = node.as_any().downcast_ref::<ShuffleReaderExec>() else {
+            for (name, codec) in self.extensions.iter() {
+                let mut payload = Vec::new();
+                if codec.try_encode(Arc::clone(&node), &mut payload).is_ok() {
+                    let node = RegisteredNode { codec: name.clone(), payload };
+                    let node = ExtensionNode { node: Some(ExtensionNodeKind::Registered(node)) };
+                    return node.encode(buf).map_err(|e| DataFusionError::External(Box::new(e)));
+                }
+            }
+            return Err(DataFusionError::NotImplemented(format!(
+                "{} cannot be sent to workers",
+                node.name()
+            )));
+        };
+        let node = ShuffleReaderNode {
+            job_id: reader.job_id.clone(),
+            stage_id: reader.stage_id,
+            locations: reader.locations.clone(),
+            schema: encode_ipc(&reader.schema(), None)?,
+            partitioning: serialize_partitioning(&reader.properties.partitioning, self)?
+                .encode_to_vec(),
+        };
+        let node = ExtensionNode { node: Some(ExtensionNodeKind::ShuffleReader(node)) };
+        node.encode(buf).map_err(|e| DataFusionError::External(Box::new(e)))
+    }
+}
+
+/// An Arrow IPC stream of `schema` and at most one batch.
+fn encode_ipc(schema: &SchemaRef, batch: Option<&RecordBatch>) -> DataFusionResult<Vec<u8>> {
+    let mut writer = StreamWriter::try_new(Vec::new(), schema)?;
+    if let Some(batch) = batch {
+        writer.write(batch)?;
+    }
+    writer.finish()?;
+    Ok(writer.into_inner()?)
+}
+
+fn decode_ipc(data: &[u8]) -> DataFusionResult<(SchemaRef, Vec<RecordBatch>)> {
+    let reader = StreamReader::try_new(data, None)?;
+    let schema = reader.schema();
+    let batches = reader.collect::<Result<Vec<_>, _>>()?;
+    Ok((schema, batches))
+}
+
+/// A task's output held by a worker, by job, stage and input partition.
+type TaskKey = (String, u32, u32);
+
+/// The `WorkerService` of a worker node: runs stage tasks with `engine`'s functions
+/// and configuration and serves their output until the job is removed.
+pub struct WorkerExecutor {
+    engine: Arc<QueryEngine>,
+    clients: Arc<WorkerClients>,
+    extensions: Arc<ExtensionCodecs>,
+    /// Each task's output batches by output partition.
+    shuffles: Mutex<HashMap<TaskKey, HashMap<u32, Vec<RecordBatch>>>>,
+}
+
+impl WorkerExecutor {
+    pub fn new(engine: Arc<QueryEngine>) -> Self {
+        Self {
+            engine,
+            clients: Arc::default(),
+            extensions: Arc::default(),
+            shuffles: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Decode the plan nodes the coordinator encoded with its codec named `name`.
+    pub fn with_extension_codec(
+        mut self,
+        name: impl Into<String>,
+        codec: Arc<dyn PhysicalExtensionCodec>,
+    ) -> Self {
+        Arc::make_mut(&mut self.extensions).insert(name.into(), codec);
+        self
+    }
+
+    async fn run(&self, task: &TaskDefinition) -> DataFusionResult<()> {
+        let ctx = self.engine.session_context();
+        let codec = ShuffleCodec::new(Arc::clone(&self.clients), Arc::clone(&self.extensions));
+        let plan = PhysicalPlanNode::try_decode(&task.payload)?.try_into_physical_plan(
+            ctx,
+            ctx.runtime_env().as_ref(),
+            &codec,
+        )?;
+        let partition = task.partition as usize;
+        // Buffered so that a failed task leaves nothing behind.
+        let mut output: HashMap<u32, Vec<RecordBatch>> = HashMap::new();
+        match plan.as_any().downcast_ref::<RepartitionExec>() {
+            Some(repartition) => {
+                let mut partitioner =
+                    BatchPartitioner::try_new(repartition.partitioning().clone(), Time::new())?;
+                let mut stream = repartition.input().execute(partition, ctx.task_ctx())?;
+                while let Some(batch) = stream.next().await {
+                    partitioner.partition(batch?, |bucket, batch| {
+                        output.entry(bucket as u32).or_default().push(batch);
+                        Ok(())
+                    })?;
+                }
+            }
+            None => {
+                let stream = plan.execute(partition, ctx.task_ctx())?;
+                output.insert(task.partition, stream.try_collect().await?);
+            }
+        }
+        // A task run again replaces its earlier output.
+        let key = (task.job_id.clone(), task.stage_id, task.partition);
+        self.shuffles.lock().expect("shuffle lock poisoned").insert(key, output);
+        Ok(())
+    }
+}
+
+type DataForTaskStream = Pin<Box<dyn Stream<Item = Result<DataForTaskResponse, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl WorkerService for WorkerExecutor {
+    type GetDataForTaskStream = DataForTaskStream;
+
+    async fn execute_task(
+        &self,
+        request: Request<TaskDefinition>,
+    ) -> Result<Response<TaskStatus>, Status> {
+        if let Err(e) = self.run(request.get_ref()).await {
+            // Tell the coordinator whose output to compute again.
+            if let Some(worker) = lost_worker(&e).and_then(|worker| worker.parse().ok()) {
+                let mut status = Status::aborted(e.to_string());
+                status.metadata_mut().insert(LOST_WORKER_METADATA, worker);
+                return Err(status);
+            }
+            return Err(datafusion_error_to_status(e));
+        }
+        Ok(Response::new(TaskStatus { status: "SUCCEEDED".to_string() }))
+    }
+
+    #[allow(clippy::result_large_err)] // Streamed responses are `Result<_, Status>`.
+    async fn get_data_for_task(
+        &self,
+        request: Request<DataForTaskRequest>,
+    ) -> Result<Response<Self::GetDataForTaskStream>, Status> {
+        let request = request.into_inner();
+        let mut batches = Vec::new();
+        {
+            let shuffles = self.shuffles.lock().expect("shuffle lock poisoned");
+            for task in request.map_partitions {
+                let key = (request.job_id.clone(), request.stage_id, task);
+                let Some(output) = shuffles.get(&key) else {
+                    return Err(Status::not_found(format!(
+                        "no output of task {task} of stage {} of job {}",
+                        request.stage_id, request.job_id
+                    )));
+                };
+                batches.extend(output.get(&request.partition).into_iter().flatten().cloned());
+            }
+        }
+        let stream = futures::stream::iter(batches).map(|batch| {
+            let data =
+                encode_ipc(&batch.schema(), Some(&batch)).map_err(datafusion_error_to_status)?;
+            Ok(DataForTaskResponse { data })
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn remove_job(&self, request: Request<RemoveJobRequest>) -> Result<Response<()>, Status> {
+        let job_id = &request.get_ref().job_id;
+        let mut shuffles = self.shuffles.lock().expect("shuffle lock poisoned");
+        shuffles.retain(|(job, _, _), _| job != job_id);
+        Ok(Response::new(()))
+    }
+}
@@ -0,0 +1,568 @@
+//! Arrow Flight SQL frontend.
+//!
+//! Implements the Flight SQL protocol over [`QueryEngine`], so JDBC/ADBC Flight SQL
+//! drivers and BI tools can connect to Igloo directly. Results are streamed to the
+//! client batch by batch as DataFusion produces them.
+//!
+//! `SET` statements, run with `DoGet` or `DoPutStatementUpdate`, change the session
+//! named by the call's [`SESSION_HEADER`] metadata (see [`crate::session`]); later
+//! calls with the same metadata are planned with its variables.
+
+// Helpers here return `tonic::Status` directly, like the trait methods they serve.
+#![allow(clippy::result_large_err)]
+
+use crate::audit::{self, AuditEntry, Auditor};
+use crate::auth::{self, Principal};
+use crate::quota::{self, QuotaLimiter, QuotaPermit};
+use crate::session::{SessionStore, SESSION_HEADER};
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::FlightService;
+use arrow_flight::sql::metadata::{SqlInfoData, SqlInfoDataBuilder};
+use arrow_flight::sql::server::{FlightSqlService, PeekableFlightDataStream};
+use arrow_flight::sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult, Any, CommandGetCatalogs, CommandGetDbSchemas,
+    CommandGetSqlInfo, CommandGetTableTypes, CommandGetTables, CommandPreparedStatementQuery,
+    CommandStatementQuery, CommandStatementUpdate, DoPutPreparedStatementResult, ProstMessageExt,
+    SqlInfo, TicketStatementQuery,
+};
+use arrow_flight::{
+    Action, FlightDescriptor, FlightEndpoint, FlightInfo, IpcMessage, SchemaAsIpc, Ticket,
+};
+use datafusion::arrow::datatypes::{Field, Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::IpcWriteOptions;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::{ParamValues, ScalarValue};
+use datafusion::dataframe::DataFrame;
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::execute_stream;
+use futures::TryStreamExt;
+use igloo_common::error::ApiError;
+use igloo_common::redact::redact;
+use igloo_engine::diagnostics::source_tables;
+use igloo_engine::session::{parse_set_sql, with_timeout};
+use igloo_engine::QueryEngine;
+use prost::bytes::Bytes;
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tonic::{Code, Request, Response, Status};
+
+type DoGetStream = <IglooFlightSqlService as FlightService>::DoGetStream;
+
+/// A statement created with `CreatePreparedStatement`, plus any parameters bound to it.
+#[derive(Debug, Clone)]
+struct PreparedStatement {
+    sql: String,
+    params: Option<ParamValues>,
+}
+
+pub struct IglooFlightSqlService {
+    engine: Arc<QueryEngine>,
+    statements: Mutex<HashMap<String, PreparedStatement>>,
+    next_handle: AtomicU64,
+    sql_info: SqlInfoData,
+    audit: Option<Arc<Auditor>>,
+    quotas: Option<Arc<QuotaLimiter>>,
+    sessions: SessionStore,
+}
+
+impl IglooFlightSqlService {
+    pub fn new(engine: Arc<QueryEngine>) -> Self {
+        let mut builder = SqlInfoDataBuilder::new();
+        builder.append(SqlInfo::FlightSqlServerName, "Igloo");
+        builder.append(SqlInfo::FlightSqlServerVersion, env!("CARGO_PKG_VERSION"));
+        builder.append(SqlInfo::FlightSqlServerArrowVersion, "55");
+        builder.append(SqlInfo::FlightSqlServerReadOnly, true);
+        let sql_info = builder.build().expect("static SqlInfo is valid");
+        Self {
+            engine,
+            statements: Mutex::new(HashMap::new()),
+            next_handle: AtomicU64::new(1),
+            sql_info,
+            audit: None,
+            quotas: None,
+            sessions: SessionStore::new(),
+        }
+    }
+
+    /// Record every query in the audit log.
+    pub fn with_audit(mut self, auditor: Arc<Auditor>) -> Self {
+        self.audit = Some(auditor);
+        self
+    }
+
+    /// Enforce per-principal rate limits and quotas on queries.
+    pub fn with_quotas(mut self, quotas: Arc<QuotaLimiter>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// The engine as the caller's session sees it.
+    fn session<T>(&self, request: &Request<T>) -> Result<QueryEngine, Status> {
+        let (principal, id) = session_key(request);
+        Ok(engine_for(&self.engine, request)?.with_session(&self.sessions.get(principal, id)))
+    }
+
+    async fn plan(
+        engine: &QueryEngine,
+        sql: &str,
+        params: Option<ParamValues>,
+    ) -> Result<DataFrame, Status> {
+        let df = engine.sql(sql).await.map_err(datafusion_error_to_status)?;
+        match params {
+            Some(params) => df.with_param_values(params).map_err(datafusion_error_to_status),
+            None => Ok(df),
+        }
+    }
+
+    /// Apply `sql` to the caller's session if it is a `SET`, returning whether it was.
+    fn try_set<T>(
+        &self,
+        request: &Request<T>,
+        sql: &str,
+        audit: &mut AuditEntry,
+    ) -> Result<bool, Status> {
+        let Some((name, value)) =
+            audit.check(parse_set_sql(sql)).map_err(datafusion_error_to_status)?
+        else {
+            return Ok(false);
+        };
+        let (principal, id) = session_key(request);
+        audit
+            .check(self.sessions.set(principal, id, &name, &value))
+            .map_err(datafusion_error_to_status)?;
+        Ok(true)
+    }
+
+    /// Stream a single, already materialized metadata batch.
+    fn stream_batch(batch: RecordBatch) -> Result<Response<DoGetStream>, Status> {
+        let schema = batch.schema();
+        let stream = FlightDataEncoderBuilder::new()
+            .with_schema(schema)
+            .build(futures::stream::once(async { Ok(batch) }))
+            .map_err(Status::from);
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    fn prepared(&self, handle: &[u8]) -> Result<PreparedStatement, Status> {
+        let handle = std::str::from_utf8(handle)
+            .map_err(|_| Status::invalid_argument("Prepared statement handle is not UTF-8"))?;
+        self.statements
+            .lock()
+            .unwrap()
+            .get(handle)
+            .cloned()
+            .ok_or_else(|| Status::not_found(format!("Unknown prepared statement: {handle}")))
+    }
+}
+
+/// Start auditing a query on behalf of the principal attached to `request`, if any,
+/// and admit it under that principal's quotas.
+pub(crate) fn admit<T>(
+    auditor: Option<&Auditor>,
+    quotas: Option<&QuotaLimiter>,
+    frontend: &'static str,
+    request: &Request<T>,
+    sql: &str,
+) -> Result<(AuditEntry, QuotaPermit), Status> {
+    let (principal, session) = session_key(request);
+    let mut audit = audit::start(auditor, frontend, principal, session, sql);
+    let permit = audit
+        .check(quota::acquire(quotas, principal))
+        .map_err(|e| Status::resource_exhausted(redact(&e.to_string())))?;
+    Ok((audit, permit))
+}
+
+/// The engine the principal attached to `request`, if any, may query: its tenant's,
+/// subject to the policies for its roles.
+pub(crate) fn engine_for<T>(
+    engine: &QueryEngine,
+    request: &Request<T>,
+) -> Result<QueryEngine, Status> {
+    auth::engine_for(engine, request.extensions().get::<Principal>())
+        .map_err(|e| Status::permission_denied(redact(&e.to_string())))
+}
+
+/// The principal and session id a request was made with, for the [`SessionStore`].
+pub(crate) fn session_key<T>(request: &Request<T>) -> (Option<&str>, Option<&str>) {
+    let principal = request.extensions().get::<Principal>().map(|p| p.subject.as_str());
+    let id = request.metadata().get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    (principal, id)
+}
+
+/// Execute a query planned on `engine` once admitted and stream its batches as Flight
+/// data, failing the stream once the engine's statement timeout has passed.
+pub(crate) async fn stream_dataframe(
+    df: DataFrame,
+    engine: &QueryEngine,
+    mut audit: AuditEntry,
+    mut permit: QuotaPermit,
+) -> Result<Response<DoGetStream>, Status> {
+    permit.admit(engine.priority().unwrap_or_default()).await;
+    let schema: SchemaRef = Arc::new(df.schema().as_arrow().clone());
+    audit.set_tables(source_tables(df.logical_plan()));
+    let task_ctx = Arc::new(df.task_ctx());
+    let plan = audit.check(df.create_physical_plan().await).map_err(datafusion_error_to_status)?;
+    permit.track(plan.clone());
+    audit.set_plan(&plan);
+    let batches =
+        audit.check(execute_stream(plan, task_ctx)).map_err(datafusion_error_to_status)?;
+    let batches = with_timeout(batches, engine.statement_timeout());
+    let batches = permit.wrap(audit.wrap(batches)).map_err(datafusion_error_to_flight);
+    let stream =
+        FlightDataEncoderBuilder::new().with_schema(schema).build(batches).map_err(Status::from);
+    Ok(Response::new(Box::pin(stream)))
+}
+
+/// Build a `FlightInfo` with a single endpoint whose ticket is `ticket`.
+fn flight_info(
+    schema: &Schema,
+    ticket: Any,
+    descriptor: FlightDescriptor,
+) -> Result<Response<FlightInfo>, Status> {
+    let endpoint =
+        FlightEndpoint::new().with_ticket(Ticket { ticket: ticket.encode_to_vec().into() });
+    let info = FlightInfo::new()
+        .try_with_schema(schema)
+        .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))?
+        .with_endpoint(endpoint)
+        .with_descriptor(descriptor);
+    Ok(Response::new(info))
+}
+
+fn schema_to_ipc(schema: &Schema) -> Result<Bytes, Status> {
+    let message: IpcMessage = SchemaAsIpc::new(schema, &IpcWriteOptions::default())
+        .try_into()
+        .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))?;
+    Ok(message.0)
+}
+
+/// Errors caused by the statement itself are the client's to fix; the rest are ours.
+/// The status carries the redacted message, and its details the [`ApiError`] as JSON.
+pub(crate) fn datafusion_error_to_status(e: DataFusionError) -> Status {
+    let error = ApiError::from(&e);
+    let code = match error.code {
+        "sql_parse_error" | "plan_error" => Code::InvalidArgument,
+        "not_implemented" => Code::Unimplemented,
+        "resources_exhausted" => Code::Unavailable,
+        _ => Code::Internal,
+    };
+    Status::with_details(code, error.message.clone(), error.to_json().into())
+}
+
+/// An error of a result stream, as [`datafusion_error_to_status`] reports it.
+pub(crate) fn datafusion_error_to_flight(e: DataFusionError) -> FlightError {
+    FlightError::Tonic(Box::new(datafusion_error_to_status(e)))
+}
+
+#[tonic::async_trait]
+impl FlightSqlService for IglooFlightSqlService {
+    type FlightService = IglooFlightSqlService;
+
+    async fn get_flight_info_statement(
+        &self,
+        query: CommandStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        // A SET has no result; it is applied when the ticket is fetched.
+        let schema = match parse_set_sql(&query.query).map_err(datafusion_error_to_status)? {
+            Some(_) => Schema::empty(),
+            None => {
+                let df = Self::plan(&self.session(&request)?, &query.query, None).await?;
+                df.schema().as_arrow().clone()
+            }
+        };
+        let ticket = TicketStatementQuery { statement_handle: query.query.into() };
+        flight_info(&schema, ticket.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_prepared_statement(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let statement = self.prepared(&query.prepared_statement_handle)?;
+        let df = Self::plan(&self.session(&request)?, &statement.sql, statement.params).await?;
+        flight_info(df.schema().as_arrow(), query.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let schema = query.into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let schema = query.clone().into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_tables(
+        &self,
+        query: CommandGetTables,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let schema = query.clone().into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_table_types(
+        &self,
+        query: CommandGetTableTypes,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let schema = query.into_builder().schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn get_flight_info_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let schema = query.clone().into_builder(&self.sql_info).schema();
+        flight_info(&schema, query.as_any(), request.into_inner())
+    }
+
+    async fn do_get_statement(
+        &self,
+        ticket: TicketStatementQuery,
+        request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let sql = String::from_utf8(ticket.statement_handle.to_vec())
+            .map_err(|_| Status::invalid_argument("Statement handle is not valid UTF-8"))?;
+        let (mut audit, permit) =
+            admit(self.audit.as_deref(), self.quotas.as_deref(), "flight_sql", &request, &sql)?;
+        if self.try_set(&request, &sql, &mut audit)? {
+            audit.succeeded(None);
+            return Self::stream_batch(RecordBatch::new_empty(Arc::new(Schema::empty())));
+        }
+        let engine = audit.check(self.session(&request))?.with_running_query(audit.running_query());
+        let df = audit.check(Self::plan(&engine, &sql, None).await)?;
+        stream_dataframe(df, &engine, audit, permit).await
+    }
+
+    async fn do_get_prepared_statement(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let statement = self.prepared(&query.prepared_statement_handle)?;
+        let (mut audit, permit) = admit(
+            self.audit.as_deref(),
+            self.quotas.as_deref(),
+            "flight_sql",
+            &request,
+            &statement.sql,
+        )?;
+        let engine = audit.check(self.session(&request))?.with_running_query(audit.running_query());
+        let df = audit.check(Self::plan(&engine, &statement.sql, statement.params).await)?;
+        stream_dataframe(df, &engine, audit, permit).await
+    }
+
+    async fn do_get_catalogs(
+        &self,
+        query: CommandGetCatalogs,
+        request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let mut builder = query.into_builder();
+        for catalog in engine_for(&self.engine, &request)?.session_context().catalog_names() {
+            builder.append(catalog);
+        }
+        Self::stream_batch(builder.build().map_err(Status::from)?)
+    }
+
+    async fn do_get_schemas(
+        &self,
+        query: CommandGetDbSchemas,
+        request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let engine = engine_for(&self.engine, &request)?;
+        let ctx = engine.session_context();
+        let mut builder = query.into_builder();
+        for catalog_name in ctx.catalog_names() {
+            if let Some(catalog) = ctx.catalog(&catalog_name) {
+                for schema_name in catalog.schema_names() {
+                    builder.append(&catalog_name, schema_name);
+                }
+            }
+        }
+        Self::stream_batch(builder.build().map_err(Status::from)?)
+    }
+
+    async fn do_get_tables(
+        &self,
+        query: CommandGetTables,
+        request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let engine = engine_for(&self.engine, &request)?;
+        let ctx = engine.session_context();
+        let mut builder = query.into_builder();
+        for catalog_name in ctx.catalog_names() {
+            let Some(catalog) = ctx.catalog(&catalog_name) else { continue };
+            for schema_name in catalog.schema_names() {
+                let Some(schema) = catalog.schema(&schema_name) else { continue };
+                for table_name in schema.table_names() {
+                    let Some(table) =
+                        schema.table(&table_name).await.map_err(datafusion_error_to_status)?
+                    else {
+                        continue;
+                    };
+                    let table_type = table.table_type().to_string().to_uppercase();
+                    builder
+                        .append(
+                            &catalog_name,
+                            &schema_name,
+                            &table_name,
+                            table_type,
+                            &table.schema(),
+                        )
+                        .map_err(Status::from)?;
+                }
+            }
+        }
+        Self::stream_batch(builder.build().map_err(Status::from)?)
+    }
+
+    async fn do_get_table_types(
+        &self,
+        query: CommandGetTableTypes,
+        _request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        let mut builder = query.into_builder();
+        for table_type in ["BASE TABLE", "VIEW", "TEMPORARY"] {
+            builder.append(table_type);
+        }
+        Self::stream_batch(builder.build().map_err(Status::from)?)
+    }
+
+    async fn do_get_sql_info(
+        &self,
+        query: CommandGetSqlInfo,
+        _request: Request<Ticket>,
+    ) -> Result<Response<DoGetStream>, Status> {
+        Self::stream_batch(query.into_builder(&self.sql_info).build().map_err(Status::from)?)
+    }
+
+    async fn do_put_prepared_statement_query(
+        &self,
+        query: CommandPreparedStatementQuery,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<DoPutPreparedStatementResult, Status> {
+        let batches: Vec<_> = FlightRecordBatchStream::new_from_flight_data(
+            request.into_inner().map_err(FlightError::from),
+        )
+        .try_collect()
+        .await
+        .map_err(Status::from)?;
+
+        // Only the first row of parameters is bound; batch execution is not supported.
+        let params = match batches.iter().find(|b| b.num_rows() > 0) {
+            Some(batch) => {
+                let values = batch
+                    .columns()
+                    .iter()
+                    .map(|column| ScalarValue::try_from_array(column, 0))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(datafusion_error_to_status)?;
+                Some(ParamValues::List(values))
+            }
+            None => None,
+        };
+
+        let handle = String::from_utf8(query.prepared_statement_handle.to_vec())
+            .map_err(|_| Status::invalid_argument("Prepared statement handle is not UTF-8"))?;
+        let mut statements = self.statements.lock().unwrap();
+        let statement = statements
+            .get_mut(&handle)
+            .ok_or_else(|| Status::not_found(format!("Unknown prepared statement: {handle}")))?;
+        statement.params = params;
+        Ok(DoPutPreparedStatementResult {
+            prepared_statement_handle: Some(query.prepared_statement_handle),
+        })
+    }
+
+    /// Only session `SET` statements are accepted; the server is otherwise read-only.
+    async fn do_put_statement_update(
+        &self,
+        command: CommandStatementUpdate,
+        request: Request<PeekableFlightDataStream>,
+    ) -> Result<i64, Status> {
+        let (mut audit, _permit) = admit(
+            self.audit.as_deref(),
+            self.quotas.as_deref(),
+            "flight_sql",
+            &request,
+            &command.query,
+        )?;
+        if !self.try_set(&request, &command.query, &mut audit)? {
+            return audit
+                .check(Err(Status::unimplemented("Only SET statements can be run as updates")));
+        }
+        audit.succeeded(None);
+        Ok(0)
+    }
+
+    async fn do_action_create_prepared_statement(
+        &self,
+        query: ActionCreatePreparedStatementRequest,
+        request: Request<Action>,
+    ) -> Result<ActionCreatePreparedStatementResult, Status> {
+        let df = Self::plan(&self.session(&request)?, &query.query, None).await?;
+        let dataset_schema = schema_to_ipc(df.schema().as_arrow())?;
+
+        // Placeholders ($1, $2, ...) become the fields of the parameter schema, in order.
+        let mut parameters: Vec<_> = df
+            .logical_plan()
+            .get_parameter_types()
+            .map_err(datafusion_error_to_status)?
+            .into_iter()
+            .collect();
+        parameters.sort_by_key(|(name, _)| name.trim_start_matches('$').parse::<usize>().ok());
+        let parameter_fields: Vec<Field> = parameters
+            .into_iter()
+            .map(|(name, data_type)| {
+                Field::new(
+                    name,
+                    data_type.unwrap_or(datafusion::arrow::datatypes::DataType::Null),
+                    true,
+                )
+            })
+            .collect();
+        let parameter_schema = schema_to_ipc(&Schema::new(parameter_fields))?;
+
+        let handle = self.next_handle.fetch_add(1, Ordering::Relaxed).to_string();
+        self.statements
+            .lock()
+            .unwrap()
+            .insert(handle.clone(), PreparedStatement { sql: query.query, params: None });
+        Ok(ActionCreatePreparedStatementResult {
+            prepared_statement_handle: handle.into(),
+            dataset_schema,
+            parameter_schema,
+        })
+    }
+
+    async fn do_action_close_prepared_statement(
+        &self,
+        query: ActionClosePreparedStatementRequest,
+        _request: Request<Action>,
+    ) -> Result<(), Status> {
+        let handle = String::from_utf8_lossy(&query.prepared_statement_handle).into_owned();
+        self.statements.lock().unwrap().remove(&handle);
+        Ok(())
+    }
+
+    async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
+}
@@ -0,0 +1,536 @@
+//! HTTP/JSON frontend.
+//!
+//! Exposes Igloo to web applications that have no database driver:
+//!
+//! - `POST /query` runs `{"sql": "..."}` and returns the result in any
+//!   [`OutputFormat`], chosen from the `Accept` header (JSON when absent).
+//! - `POST /validate` checks `{"sql": "..."}` without running it, returning the
+//!   [`Validation`] of what running it would do (see [`igloo_engine::validate`]).
+//! - `GET /query/ws` streams results over a WebSocket as they are produced; see [`ws`].
+//! - `GET /tables` lists the tables registered with the engine.
+//! - `GET /lineage` lists the recorded lineage edges (see [`igloo_engine::lineage`]);
+//!   with `?target=`, only those the target derives from, directly or not, narrowed
+//!   to one of its columns with `&column=`. Targets are tables unless `&kind=query`,
+//!   which names a query by its SQL.
+//! - `POST /catalogs/:name/sync` re-reads the metadata of an external catalog and
+//!   registers it, returning the [`SyncReport`] of what changed (see
+//!   [`igloo_engine::external_catalog`]); with `?dry_run=true` it only reports.
+//! - `GET /admin/memory` and `GET /metrics` report where memory is held, by query,
+//!   memory pool and cache, as JSON and for Prometheus; see [`admin`].
+//! - `GET /admin/queries` lists the running queries and `DELETE /admin/queries/:id`
+//!   kills one; see [`admin`].
+//! - `GET /admin/profiles/:id` returns the timeline of a profiled query; see [`admin`].
+//! - `GET /admin/state` exports the tables, views and policies set up at runtime, and
+//!   `PUT /admin/state` imports them into another engine; see [`admin`].
+//! - `GET /admin/quality` returns the latest results of the data quality checks when
+//!   [`HttpOptions::with_quality`] is set, which `/metrics` then reports too; see
+//!   [`admin`].
+//! - `GET /admin/status` returns the state of each component of the process when
+//!   [`HttpOptions::with_supervisor`] is set; see [`admin`].
+//! - `GET /admin/errors` returns the recent failed queries with the cause chain and
+//!   backtrace of their errors; see [`admin`].
+//! - `/jobs` runs queries asynchronously when [`HttpOptions::with_jobs`] is set; see
+//!   [`jobs`].
+//! - `GET /healthz` (alias `/health`) reports liveness and `GET /readyz` readiness;
+//!   see [`health`].
+//!
+//! With [`HttpOptions::with_auth`], every route except the health checks requires
+//! credentials (see [`crate::auth`]) and gets the caller's [`Principal`] as a request
+//! extension. Queries are then subject to the data policies for the principal's roles
+//! (see [`igloo_engine::policy`]), and a principal with a tenant queries and lists only
+//! that tenant's tables (`403 Forbidden` if the engine has no such tenant).
+//! [`HttpOptions::with_tls`] serves HTTPS instead.
+//! [`HttpOptions::with_quotas`] rate limits queries per principal; refused ones get
+//! `429 Too Many Requests` with a `quota_exceeded` error.
+//!
+//! `SET` statements change the session named by the request's [`SESSION_HEADER`] (see
+//! [`crate::session`]), and later requests with the same header run with its
+//! variables; a session's `output_format` applies when there is no `Accept` header.
+//!
+//! Results the engine spilled to disk (see [`igloo_engine::spool`]) are encoded as the
+//! response body is sent, instead of in memory.
+//!
+//! Errors are returned as [`ApiError`] JSON bodies. Query diagnostics are returned in
+//! [`DIAGNOSTIC_HEADER`] response headers, one per diagnostic, and the id of a
+//! profiled query in a [`PROFILE_HEADER`] response header.
+
+use crate::audit::{self, Auditor};
+use crate::auth::{self, AuthError, Authenticator, Principal};
+use crate::jobs::JobManager;
+use crate::quota::{self, QuotaError, QuotaLimiter};
+use crate::session::{SessionStore, SESSION_HEADER};
+use crate::supervisor::Supervisor;
+use crate::tls::TlsConfig;
+use crate::DIAGNOSTIC_HEADER;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Extension, Json, Router};
+use datafusion::arrow::datatypes::Schema;
+use datafusion::error::DataFusionError;
+use health::Readiness;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use igloo_common::error::ApiError;
+use igloo_common::redact::redact;
+use igloo_engine::external_catalog::SyncReport;
+use igloo_engine::formats::OutputFormat;
+use igloo_engine::lineage::{LineageEdge, TargetKind};
+use igloo_engine::quality::QualityChecks;
+use igloo_engine::session::parse_set_sql;
+use igloo_engine::session::SessionVars;
+use igloo_engine::spool::SpooledResult;
+use igloo_engine::validate::Validation;
+use igloo_engine::QueryEngine;
+use serde::{Deserialize, Serialize};
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_stream::wrappers::ReceiverStream;
+
+pub mod admin;
+pub mod health;
+pub mod jobs;
+pub mod ws;
+
+/// Response header carrying the query id of a profiled query, under which
+/// `GET /admin/profiles/:id` returns its profile.
+pub const PROFILE_HEADER: &str = "x-igloo-profile";
+
+#[derive(Debug, Deserialize)]
+pub struct QueryRequest {
+    pub sql: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LineageRequest {
+    pub target: Option<String>,
+    pub kind: Option<TargetKind>,
+    pub column: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SyncRequest {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableEntry {
+    pub catalog: String,
+    pub schema: String,
+    pub name: String,
+}
+
+/// An [`ApiError`] paired with the HTTP status it is reported with.
+pub struct HttpError {
+    status: StatusCode,
+    error: ApiError,
+}
+
+impl HttpError {
+    fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        let error = ApiError {
+            code,
+            message: redact(&message.into()),
+            detail: None,
+            hint: None,
+            retryable: false,
+        };
+        Self { status, error }
+    }
+}
+
+impl From<DataFusionError> for HttpError {
+    fn from(e: DataFusionError) -> Self {
+        let error = ApiError::from(&e);
+        let status = match error.code {
+            "sql_parse_error" | "plan_error" => StatusCode::BAD_REQUEST,
+            "not_implemented" => StatusCode::NOT_IMPLEMENTED,
+            // Out of memory or shutting down: worth retrying, elsewhere or later.
+            "resources_exhausted" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        HttpError { status, error }
+    }
+}
+
+impl From<AuthError> for HttpError {
+    fn from(e: AuthError) -> Self {
+        let status = match e {
+            AuthError::UnknownTenant(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::UNAUTHORIZED,
+        };
+        HttpError { status, error: e.to_api_error() }
+    }
+}
+
+impl From<QuotaError> for HttpError {
+    fn from(e: QuotaError) -> Self {
+        HttpError { status: StatusCode::TOO_MANY_REQUESTS, error: e.to_api_error() }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.error)).into_response()
+    }
+}
+
+/// Optional behaviour of the HTTP API.
+#[derive(Clone, Default)]
+pub struct HttpOptions {
+    readiness: Readiness,
+    auth: Option<Arc<Authenticator>>,
+    tls: Option<TlsConfig>,
+    audit: Option<Arc<Auditor>>,
+    quotas: Option<Arc<QuotaLimiter>>,
+    jobs: Option<Arc<JobManager>>,
+    quality: Option<Arc<QualityChecks>>,
+    supervisor: Option<Arc<Supervisor>>,
+    sessions: Arc<SessionStore>,
+    errors: Arc<admin::ErrorLog>,
+}
+
+impl HttpOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Probes run by `/readyz`. Without any, it reports ready whenever the server is up.
+    pub fn with_readiness(mut self, readiness: Readiness) -> Self {
+        self.readiness = readiness;
+        self
+    }
+
+    /// Require credentials on every route except the health checks.
+    pub fn with_auth(mut self, auth: Arc<Authenticator>) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// Record every query in the audit log.
+    pub fn with_audit(mut self, auditor: Arc<Auditor>) -> Self {
+        self.audit = Some(auditor);
+        self
+    }
+
+    /// Enforce per-principal rate limits and quotas on queries.
+    pub fn with_quotas(mut self, quotas: Arc<QuotaLimiter>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// Serve the asynchronous job routes under `/jobs`, running jobs on `jobs`.
+    pub fn with_jobs(mut self, jobs: Arc<JobManager>) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Serve the results of the data quality checks `quality` runs at
+    /// `/admin/quality`, and report them in `/metrics`.
+    pub fn with_quality(mut self, quality: Arc<QualityChecks>) -> Self {
+        self.quality = Some(quality);
+        self
+    }
+
+    /// Serve the state of the components `supervisor` runs at `/admin/status`.
+    pub fn with_supervisor(mut self, supervisor: Arc<Supervisor>) -> Self {
+        self.supervisor = Some(supervisor);
+        self
+    }
+
+    /// Serve HTTPS instead of plain HTTP.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+}
+
+/// Routes for the HTTP API, ready to be served or nested into a larger router.
+pub fn router(engine: Arc<QueryEngine>) -> Router {
+    router_with_options(engine, HttpOptions::default())
+}
+
+pub fn router_with_options(engine: Arc<QueryEngine>, options: HttpOptions) -> Router {
+    let mut routes = Router::new()
+        .route("/query", post(query))
+        .route("/query/ws", get(ws::handler))
+        .route("/validate", post(validate))
+        .route("/tables", get(tables))
+        .route("/lineage", get(lineage))
+        .route("/catalogs/:name/sync", post(sync_catalog))
+        .route("/admin/memory", get(admin::memory))
+        .route("/admin/queries", get(admin::queries))
+        .route("/admin/queries/:id", delete(admin::kill))
+        .route("/admin/profiles/:id", get(admin::profile))
+        .route("/admin/state", get(admin::export_state).put(admin::import_state))
+        .route("/admin/errors", get(admin::errors))
+        .route("/metrics", get(admin::metrics));
+    if let Some(quality) = options.quality {
+        routes = routes.route("/admin/quality", get(admin::quality)).layer(Extension(quality));
+    }
+    if let Some(supervisor) = options.supervisor {
+        routes = routes.route("/admin/status", get(admin::status)).layer(Extension(supervisor));
+    }
+    if let Some(jobs) = options.jobs {
+        routes = routes.merge(jobs::routes().layer(Extension(jobs)));
+    }
+    let mut api = routes
+        .with_state(engine)
+        .layer(Extension(options.sessions))
+        .layer(Extension(options.errors));
+    if let Some(auditor) = options.audit {
+        api = api.layer(Extension(auditor));
+    }
+    if let Some(quotas) = options.quotas {
+        api = api.layer(Extension(quotas));
+    }
+    if let Some(auth) = options.auth {
+        api = api.layer(middleware::from_fn_with_state(auth, require_auth));
+    }
+    api.merge(health::routes(options.readiness))
+}
+
+/// Serve the HTTP API on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, engine: Arc<QueryEngine>) -> std::io::Result<()> {
+    serve_with_options(listener, engine, HttpOptions::default()).await
+}
+
+pub async fn serve_with_options(
+    listener: TcpListener,
+    engine: Arc<QueryEngine>,
+    mut options: HttpOptions,
+) -> std::io::Result<()> {
+    let Some(tls) = options.tls.take() else {
+        return axum::serve(listener, router_with_options(engine, options)).await;
+    };
+    let mut config = tls.server_config()?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let service = TowerToHyperService::new(router_with_options(engine, options));
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let (acceptor, service) = (acceptor.clone(), service.clone());
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(socket).await {
+                Ok(stream) => stream,
+                Err(e) => return tracing::warn!(error = %e, "TLS handshake failed"),
+            };
+            let connection = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+            if let Err(e) = connection {
+                tracing::warn!(error = %e, "HTTP connection error");
+            }
+        });
+    }
+}
+
+async fn require_auth(
+    State(auth): State<Arc<Authenticator>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, HttpError> {
+    let headers = request.headers();
+    let header = |name| headers.get(name).and_then(|v: &HeaderValue| v.to_str().ok());
+    let principal = match header(header::AUTHORIZATION.as_str()) {
+        Some(authorization) => auth.authenticate_bearer(Some(authorization)),
+        None => auth.authenticate(header("x-api-key").unwrap_or_default()),
+    }?;
+    request.extensions_mut().insert::<Principal>(principal);
+    Ok(next.run(request).await)
+}
+
+#[allow(clippy::too_many_arguments)] // One extractor per layer.
+async fn query(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    auditor: Option<Extension<Arc<Auditor>>>,
+    quotas: Option<Extension<Arc<QuotaLimiter>>>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+    Extension(errors): Extension<Arc<admin::ErrorLog>>,
+    headers: HeaderMap,
+    Json(request): Json<QueryRequest>,
+) -> Result<Response, HttpError> {
+    let subject = principal.as_ref().map(|p| p.subject.as_str());
+    let session_id = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let session = sessions.get(subject, session_id);
+    let format = negotiate(&headers, &session)?;
+    let mut audit = audit::start(
+        auditor.as_deref().map(Arc::as_ref),
+        "http",
+        subject,
+        session_id,
+        &request.sql,
+    );
+    let mut permit = audit.check(quota::acquire(quotas.as_deref().map(Arc::as_ref), subject))?;
+    if let Some((name, value)) = audit.check(parse_set_sql(&request.sql))? {
+        audit.check(sessions.set(subject, session_id, &name, &value))?;
+        audit.succeeded(None);
+        let body = format.to_bytes(&Arc::new(Schema::empty()), &[])?;
+        return Ok(([(header::CONTENT_TYPE, format.content_type())], body).into_response());
+    }
+    let engine = scoped(&engine, principal.as_deref())?
+        .with_session(&session)
+        .with_running_query(audit.running_query());
+    permit.admit(engine.priority().unwrap_or_default()).await;
+    let result = engine.query(&request.sql).await;
+    if let Err(e) = &result {
+        errors.record(&request.sql, e);
+    }
+    let result = audit.check(result)?;
+    permit.charge(result.scanned_bytes);
+    audit.set_plan(&result.plan);
+    if result.cache_hit {
+        audit.set_cache_hit();
+    }
+    audit.set_tables(result.tables.clone());
+    let (body, rows) = match &result.spilled {
+        Some(spilled) => (spilled_body(format, spilled.clone()), spilled.num_rows() as u64),
+        None => {
+            let body = audit.check(format.to_bytes(&result.schema, &result.batches))?;
+            (Body::from(body), result.batches.iter().map(|b| b.num_rows() as u64).sum())
+        }
+    };
+    audit.succeeded(Some(rows));
+
+    let mut response = ([(header::CONTENT_TYPE, format.content_type())], body).into_response();
+    for diagnostic in &result.diagnostics {
+        if let Ok(value) = HeaderValue::try_from(diagnostic.to_string()) {
+            response.headers_mut().append(DIAGNOSTIC_HEADER, value);
+        }
+    }
+    if let Some(profile) = engine.profiling() {
+        if let Ok(value) = HeaderValue::try_from(profile.query_id()) {
+            response.headers_mut().insert(PROFILE_HEADER, value);
+        }
+    }
+    Ok(response)
+}
+
+async fn validate(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+    headers: HeaderMap,
+    Json(request): Json<QueryRequest>,
+) -> Result<Json<Validation>, HttpError> {
+    let subject = principal.as_ref().map(|p| p.subject.as_str());
+    let session_id = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let engine =
+        scoped(&engine, principal.as_deref())?.with_session(&sessions.get(subject, session_id));
+    Ok(Json(engine.validate(&request.sql).await?))
+}
+
+/// A body encoding `result` in `format` as it is sent. An error reading the result
+/// back aborts the response.
+fn spilled_body(format: OutputFormat, result: SpooledResult) -> Body {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::task::spawn_blocking(move || {
+        let mut out = BufWriter::with_capacity(64 * 1024, ChannelWriter(tx.clone()));
+        let written = result
+            .batches()
+            .and_then(|batches| format.write_batches(result.schema(), batches, &mut out))
+            .and_then(|()| Ok(out.flush()?));
+        if let Err(e) = written {
+            let _ = tx.blocking_send(Err(e));
+        }
+        // Remove the file before the body ends.
+        drop((out, result));
+    });
+    Body::from_stream(ReceiverStream::new(rx))
+}
+
+/// Sends what is written to a response body, failing once the client is gone.
+struct ChannelWriter(mpsc::Sender<Result<Bytes, DataFusionError>>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let sent = self.0.blocking_send(Ok(Bytes::copy_from_slice(buf)));
+        sent.map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client went away"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The result format for the request's `Accept` header, or the session's
+/// `output_format` without one.
+fn negotiate(headers: &HeaderMap, session: &SessionVars) -> Result<OutputFormat, HttpError> {
+    match headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()) {
+        Some(accept) => OutputFormat::negotiate(accept).ok_or_else(|| {
+            let supported = OutputFormat::ALL.map(OutputFormat::content_type).join(", ");
+            HttpError::new(
+                StatusCode::NOT_ACCEPTABLE,
+                "not_acceptable",
+                format!("supported formats: {supported}"),
+            )
+        }),
+        None => Ok(session.output_format.unwrap_or(OutputFormat::Json)),
+    }
+}
+
+/// The engine as the caller sees it, subject to the policies for its roles.
+fn scoped(engine: &QueryEngine, principal: Option<&Principal>) -> Result<QueryEngine, HttpError> {
+    Ok(auth::engine_for(engine, principal)?)
+}
+
+async fn tables(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<Vec<TableEntry>>, HttpError> {
+    let engine = scoped(&engine, principal.as_deref())?;
+    let ctx = engine.session_context();
+    let mut entries = Vec::new();
+    for catalog_name in ctx.catalog_names() {
+        let Some(catalog) = ctx.catalog(&catalog_name) else { continue };
+        for schema_name in catalog.schema_names() {
+            let Some(schema) = catalog.schema(&schema_name) else { continue };
+            for name in schema.table_names() {
+                entries.push(TableEntry {
+                    catalog: catalog_name.clone(),
+                    schema: schema_name.clone(),
+                    name,
+                });
+            }
+        }
+    }
+    entries.sort_by(|a, b| (&a.catalog, &a.schema, &a.name).cmp(&(&b.catalog, &b.schema, &b.name)));
+    Ok(Json(entries))
+}
+
+async fn lineage(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Query(request): Query<LineageRequest>,
+) -> Result<Json<Vec<LineageEdge>>, HttpError> {
+    let engine = scoped(&engine, principal.as_deref())?;
+    let Some(target) = &request.target else {
+        return Ok(Json(engine.lineage().await?));
+    };
+    let kind = request.kind.unwrap_or(TargetKind::Table);
+    Ok(Json(engine.trace_lineage(kind, target, request.column.as_deref()).await?))
+}
+
+async fn sync_catalog(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Path(name): Path<String>,
+    Query(request): Query<SyncRequest>,
+) -> Result<Json<SyncReport>, HttpError> {
+    let engine = scoped(&engine, principal.as_deref())?;
+    let report = match request.dry_run {
+        true => engine.diff_catalog(&name).await?,
+        false => engine.sync_catalog(&name).await?,
+    };
+    Ok(Json(report))
+}
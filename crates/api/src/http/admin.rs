@@ -0,0 +1,352 @@
+//! Memory statistics and running queries for operators.
+//!
+//! - `GET /admin/memory` returns the engine's [`MemoryReport`] as JSON: the process's
+//!   resident set and heap, the memory pools, the running queries by the memory they
+//!   hold and the caches (see [`igloo_engine::memory`]).
+//! - `GET /metrics` returns the same figures in the Prometheus text format, as gauges
+//!   named `igloo_*_bytes`, with the query id, pool or cache as a label.
+//! - `GET /admin/queries` returns the running queries as JSON, longest running first,
+//!   as `SHOW QUERIES` does (see [`igloo_engine::running`]).
+//! - `DELETE /admin/queries/:id` kills a running query, as `KILL` does; 404 if there is
+//!   no such query.
+//! - `GET /admin/profiles/:id` returns the profile of a recent query run with the
+//!   session variable `profile` on (see [`igloo_engine::profile`]), as a JSON timeline,
+//!   or with `?format=chrome` in the Chrome trace event format.
+//! - `GET /admin/quality` returns the latest [`CheckResult`] of each data quality check
+//!   (see [`igloo_engine::quality`]). `/metrics` then also reports, per check,
+//!   `igloo_quality_check_passed`: 1 if its latest run passed, 0 if it failed or could
+//!   not run. Checks not run yet are left out.
+//! - `GET /admin/status` returns the state of each component of the process, as its
+//!   [`Supervisor`] reports it (see [`crate::supervisor`]): `ok` if all are running or
+//!   ended as expected, `degraded` if one is restarting or failed, `draining` once the
+//!   engine no longer admits queries before shutting down.
+//! - `GET /admin/state` exports the engine's state as a JSON [`StateBundle`], which
+//!   `PUT /admin/state` imports into another engine, returning its [`ImportReport`]
+//!   (see [`igloo_engine::bundle`]).
+//! - `GET /admin/errors` returns the most recent queries of `POST /query` that failed,
+//!   newest first, each with the report of its error: the message, the chain of its
+//!   causes and, when `RUST_BACKTRACE` is set, the backtrace of the Igloo error among
+//!   them (see [`igloo_common::error::report_error`]). Reports and SQL are redacted.
+//!
+//! These cover every tenant, so principals of a tenant are refused.
+
+use super::HttpError;
+use crate::auth::Principal;
+use crate::supervisor::{ComponentState, ComponentStatus, Supervisor};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use igloo_common::error::report_error;
+use igloo_common::redact::redact;
+use igloo_engine::bundle::{ImportReport, StateBundle};
+use igloo_engine::memory::MemoryReport;
+use igloo_engine::quality::{CheckResult, CheckStatus, QualityChecks};
+use igloo_engine::running::RunningQuery;
+use igloo_engine::QueryEngine;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many failed queries `GET /admin/errors` returns at most.
+const MAX_ERRORS: usize = 100;
+
+/// A query that failed, as `GET /admin/errors` returns it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    pub sql: String,
+    /// The error, its causes and backtrace, see [`report_error`].
+    pub report: String,
+    /// When it failed, in milliseconds since the Unix epoch.
+    pub failed_at_ms: u64,
+}
+
+/// The most recent failed queries, oldest first.
+#[derive(Debug, Default)]
+pub(super) struct ErrorLog {
+    errors: Mutex<VecDeque<ErrorReport>>,
+}
+
+impl ErrorLog {
+    pub(super) fn record(&self, sql: &str, error: &(dyn std::error::Error + 'static)) {
+        let report = ErrorReport {
+            sql: redact(sql),
+            report: redact(&report_error(error)),
+            failed_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        };
+        let mut errors = self.errors.lock().expect("error log lock poisoned");
+        if errors.len() == MAX_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(report);
+    }
+}
+
+pub(super) async fn memory(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<MemoryReport>, HttpError> {
+    authorize(principal.as_deref())?;
+    Ok(Json(engine.memory_report()))
+}
+
+pub(super) async fn metrics(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    quality: Option<Extension<Arc<QualityChecks>>>,
+) -> Result<impl IntoResponse, HttpError> {
+    authorize(principal.as_deref())?;
+    let mut body = prometheus(&engine.memory_report());
+    if let Some(Extension(quality)) = quality {
+        body.push_str(&quality_gauges(&quality.results()));
+    }
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
+
+pub(super) async fn queries(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<Vec<RunningQuery>>, HttpError> {
+    authorize(principal.as_deref())?;
+    Ok(Json(engine.running_queries()))
+}
+
+pub(super) async fn kill(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, HttpError> {
+    authorize(principal.as_deref())?;
+    if !engine.kill_query(&id) {
+        let message = format!("no running query {id}");
+        return Err(HttpError::new(StatusCode::NOT_FOUND, "not_found", message));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub(super) async fn quality(
+    principal: Option<Extension<Principal>>,
+    Extension(quality): Extension<Arc<QualityChecks>>,
+) -> Result<Json<Vec<CheckResult>>, HttpError> {
+    authorize(principal.as_deref())?;
+    Ok(Json(quality.results()))
+}
+
+pub(super) async fn errors(
+    principal: Option<Extension<Principal>>,
+    Extension(errors): Extension<Arc<ErrorLog>>,
+) -> Result<Json<Vec<ErrorReport>>, HttpError> {
+    authorize(principal.as_deref())?;
+    let errors = errors.errors.lock().expect("error log lock poisoned");
+    Ok(Json(errors.iter().rev().cloned().collect()))
+}
+
+#[derive(Debug, Serialize)]
+pub(super) struct Status {
+    status: &'static str,
+    components: Vec<ComponentStatus>,
+}
+
+pub(super) async fn status(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Extension(supervisor): Extension<Arc<Supervisor>>,
+) -> Result<Json<Status>, HttpError> {
+    authorize(principal.as_deref())?;
+    let components = supervisor.statuses();
+    let degraded = components.iter().any(|component| {
+        matches!(component.state, ComponentState::Restarting | ComponentState::Failed)
+    });
+    let status = if engine.is_draining() {
+        "draining"
+    } else if degraded {
+        "degraded"
+    } else {
+        "ok"
+    };
+    Ok(Json(Status { status, components }))
+}
+
+pub(super) async fn export_state(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+) -> Result<Json<StateBundle>, HttpError> {
+    authorize(principal.as_deref())?;
+    Ok(Json(engine.export_state().await?))
+}
+
+pub(super) async fn import_state(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Json(bundle): Json<StateBundle>,
+) -> Result<Json<ImportReport>, HttpError> {
+    authorize(principal.as_deref())?;
+    Ok(Json(engine.import_state(&bundle).await?))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ProfileRequest {
+    /// `json` (the default) or `chrome`.
+    pub format: Option<String>,
+}
+
+pub(super) async fn profile(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+    Query(request): Query<ProfileRequest>,
+) -> Result<Json<serde_json::Value>, HttpError> {
+    authorize(principal.as_deref())?;
+    let Some(profile) = engine.profile(&id) else {
+        let message = format!("no profile of query {id}");
+        return Err(HttpError::new(StatusCode::NOT_FOUND, "not_found", message));
+    };
+    match request.format.as_deref() {
+        None | Some("json") => Ok(Json(serde_json::json!(profile.timeline()))),
+        Some("chrome") => Ok(Json(profile.chrome_trace())),
+        Some(other) => Err(HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_request",
+            format!("unknown profile format {other}, expected json or chrome"),
+        )),
+    }
+}
+
+fn authorize(principal: Option<&Principal>) -> Result<(), HttpError> {
+    match principal.and_then(|p| p.tenant.as_deref()) {
+        Some(tenant) => Err(HttpError::new(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            format!("principals of tenant {tenant} cannot read process statistics"),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// `report` in the Prometheus text exposition format.
+fn prometheus(report: &MemoryReport) -> String {
+    let process = &report.process;
+    let allocator = process.allocator.as_ref();
+    let mut out = Gauges::default();
+    out.gauge("igloo_process_resident_bytes", "Resident set size.", process.resident_bytes);
+    let allocated = allocator.map(|a| a.allocated_bytes as u64);
+    out.gauge("igloo_heap_allocated_bytes", "Bytes allocated on the heap.", allocated);
+    let peak = allocator.map(|a| a.peak_bytes as u64);
+    out.gauge("igloo_heap_peak_bytes", "Peak of the bytes allocated on the heap.", peak);
+    out.labelled(
+        "igloo_pool_reserved_bytes",
+        "Bytes reserved from a memory pool.",
+        "pool",
+        report.pools.iter().map(|p| (p.name.as_str(), p.reserved_bytes)),
+    );
+    out.labelled(
+        "igloo_pool_limit_bytes",
+        "Limit of a memory pool.",
+        "pool",
+        report.pools.iter().filter_map(|p| Some((p.name.as_str(), p.limit_bytes?))),
+    );
+    out.labelled(
+        "igloo_query_reserved_bytes",
+        "Bytes reserved by a running query.",
+        "query_id",
+        report.queries.iter().map(|q| (q.query_id.as_str(), q.reserved_bytes)),
+    );
+    out.labelled(
+        "igloo_query_peak_bytes",
+        "Peak of the bytes reserved by a running query.",
+        "query_id",
+        report.queries.iter().map(|q| (q.query_id.as_str(), q.peak_bytes)),
+    );
+    out.labelled(
+        "igloo_cache_bytes",
+        "Approximate bytes held by a cache.",
+        "cache",
+        report.caches.iter().map(|c| (c.name.as_str(), c.bytes)),
+    );
+    out.0
+}
+
+/// The results of the data quality checks that have run, in the Prometheus text
+/// exposition format.
+fn quality_gauges(results: &[CheckResult]) -> String {
+    let mut out = Gauges::default();
+    out.labelled(
+        "igloo_quality_check_passed",
+        "Whether the latest run of a data quality check passed.",
+        "check",
+        results
+            .iter()
+            .filter(|r| r.status != CheckStatus::Pending)
+            .map(|r| (r.name.as_str(), usize::from(r.status == CheckStatus::Passed))),
+    );
+    out.0
+}
+
+#[derive(Default)]
+struct Gauges(String);
+
+impl Gauges {
+    /// The gauge `name`, if there is a `value`.
+    fn gauge(&mut self, name: &str, help: &str, value: Option<u64>) {
+        if let Some(value) = value {
+            self.header(name, help);
+            let _ = writeln!(self.0, "{name} {value}");
+        }
+    }
+
+    /// The gauge `name`, one sample per value of `label`.
+    fn labelled<'a>(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        samples: impl Iterator<Item = (&'a str, usize)>,
+    ) {
+        self.header(name, help);
+        for (value, sample) in samples {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            let _ = writeln!(self.0, "{name}{{{label}=\"{value}\"}} {sample}");
+        }
+    }
+
+    fn header(&mut self, name: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}\n# TYPE {name} gauge");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo_common::memory::ProcessMemory;
+    use igloo_engine::memory::{CacheMemory, PoolMemory, QueryMemory};
+
+    #[test]
+    fn test_prometheus_text() {
+        let report = MemoryReport {
+            process: ProcessMemory { resident_bytes: Some(4096), allocator: None },
+            pools: vec![PoolMemory {
+                name: "engine".to_string(),
+                reserved_bytes: 10,
+                limit_bytes: None,
+            }],
+            queries: vec![QueryMemory {
+                query_id: "q\"1".to_string(),
+                reserved_bytes: 7,
+                peak_bytes: 9,
+                elapsed_ms: 1,
+            }],
+            caches: vec![CacheMemory { name: "ingest_dedup".to_string(), bytes: 3 }],
+        };
+        let text = prometheus(&report);
+        assert!(text.contains("# TYPE igloo_process_resident_bytes gauge\n"));
+        assert!(text.contains("igloo_process_resident_bytes 4096\n"));
+        assert!(!text.contains("igloo_heap_allocated_bytes"));
+        assert!(text.contains("igloo_pool_reserved_bytes{pool=\"engine\"} 10\n"));
+        assert!(text.contains("igloo_query_peak_bytes{query_id=\"q\\\"1\"} 9\n"));
+        assert!(text.contains("igloo_cache_bytes{cache=\"ingest_dedup\"} 3\n"));
+    }
+}
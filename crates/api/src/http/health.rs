@@ -0,0 +1,235 @@
+//! Liveness and readiness endpoints.
+//!
+//! `GET /healthz` answers as long as the process is serving requests. `GET /readyz`
+//! runs every registered [`Probe`] concurrently and returns `200` only if all of them
+//! pass, `503` otherwise, with one entry per dependency either way:
+//!
+//! ```json
+//! {"status": "not_ready", "checks": [
+//!   {"name": "postgres", "status": "failed", "detail": "connection refused", "elapsed_ms": 3}
+//! ]}
+//! ```
+
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use datafusion::execution::object_store::ObjectStoreUrl;
+use futures::future::join_all;
+use igloo_common::redact::redact;
+use igloo_engine::QueryEngine;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// How long a probe may take before it counts as failed.
+pub const DEFAULT_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A dependency that must be reachable for Igloo to serve queries.
+#[async_trait]
+pub trait Probe: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Check the dependency, describing what is wrong on failure.
+    async fn check(&self) -> Result<(), String>;
+}
+
+/// Checks that an object store registered with the engine can be listed.
+pub struct ObjectStoreProbe {
+    name: String,
+    engine: Arc<QueryEngine>,
+    url: ObjectStoreUrl,
+}
+
+impl ObjectStoreProbe {
+    pub fn new(name: impl Into<String>, engine: Arc<QueryEngine>, url: ObjectStoreUrl) -> Self {
+        Self { name: name.into(), engine, url }
+    }
+}
+
+#[async_trait]
+impl Probe for ObjectStoreProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        let runtime = self.engine.session_context().runtime_env();
+        let store = runtime.object_store(&self.url).map_err(|e| e.to_string())?;
+        store.list_with_delimiter(None).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+/// Checks that a network service (e.g. a Postgres server) accepts TCP connections.
+pub struct TcpProbe {
+    name: String,
+    addr: String,
+}
+
+impl TcpProbe {
+    pub fn new(name: impl Into<String>, addr: impl Into<String>) -> Self {
+        Self { name: name.into(), addr: addr.into() }
+    }
+}
+
+#[async_trait]
+impl Probe for TcpProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        TcpStream::connect(&self.addr).await.map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+
+/// Fails when a replication lag (e.g. of a CDC stream) exceeds a threshold.
+pub struct LagProbe {
+    name: String,
+    max_lag: Duration,
+    lag: Box<dyn Fn() -> Option<Duration> + Send + Sync>,
+}
+
+impl LagProbe {
+    /// `lag` reports the current lag, or `None` if it is unknown (which fails the probe).
+    pub fn new(
+        name: impl Into<String>,
+        max_lag: Duration,
+        lag: impl Fn() -> Option<Duration> + Send + Sync + 'static,
+    ) -> Self {
+        Self { name: name.into(), max_lag, lag: Box::new(lag) }
+    }
+}
+
+#[async_trait]
+impl Probe for LagProbe {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        match (self.lag)() {
+            Some(lag) if lag <= self.max_lag => Ok(()),
+            Some(lag) => Err(format!(
+                "lag of {} ms exceeds threshold of {} ms",
+                lag.as_millis(),
+                self.max_lag.as_millis()
+            )),
+            None => Err("lag is unknown".to_string()),
+        }
+    }
+}
+
+/// Fails once the engine drains before shutting down (see [`QueryEngine::drain`]), so
+/// that load balancers stop sending it queries.
+pub struct DrainProbe {
+    engine: Arc<QueryEngine>,
+}
+
+impl DrainProbe {
+    pub fn new(engine: Arc<QueryEngine>) -> Self {
+        Self { engine }
+    }
+}
+
+#[async_trait]
+impl Probe for DrainProbe {
+    fn name(&self) -> &str {
+        "draining"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        if self.engine.is_draining() {
+            return Err("shutting down".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The probes `/readyz` runs.
+#[derive(Clone)]
+pub struct Readiness {
+    probes: Vec<Arc<dyn Probe>>,
+    timeout: Duration,
+}
+
+impl Default for Readiness {
+    fn default() -> Self {
+        Self { probes: Vec::new(), timeout: DEFAULT_PROBE_TIMEOUT }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProbeResult {
+    pub name: String,
+    /// `ok` or `failed`.
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub elapsed_ms: u128,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadinessReport {
+    /// `ready` or `not_ready`.
+    pub status: &'static str,
+    pub checks: Vec<ProbeResult>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_probe(mut self, probe: impl Probe + 'static) -> Self {
+        self.probes.push(Arc::new(probe));
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run all probes concurrently.
+    pub async fn check(&self) -> ReadinessReport {
+        let checks = join_all(self.probes.iter().map(|probe| async move {
+            let started = Instant::now();
+            let outcome = match tokio::time::timeout(self.timeout, probe.check()).await {
+                Ok(outcome) => outcome,
+                Err(_) => Err(format!("timed out after {} ms", self.timeout.as_millis())),
+            };
+            ProbeResult {
+                name: probe.name().to_string(),
+                status: if outcome.is_ok() { "ok" } else { "failed" },
+                // Driver errors can echo connection strings.
+                detail: outcome.err().map(|detail| redact(&detail)),
+                elapsed_ms: started.elapsed().as_millis(),
+            }
+        }))
+        .await;
+        let ready = checks.iter().all(|check| check.status == "ok");
+        ReadinessReport { status: if ready { "ready" } else { "not_ready" }, checks }
+    }
+}
+
+pub(super) fn routes(readiness: Readiness) -> Router {
+    Router::new()
+        .route("/health", get(healthz))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(Arc::new(readiness))
+}
+
+async fn healthz() -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "status": "ok" }))
+}
+
+async fn readyz(State(readiness): State<Arc<Readiness>>) -> (StatusCode, Json<ReadinessReport>) {
+    let report = readiness.check().await;
+    let status =
+        if report.status == "ready" { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(report))
+}
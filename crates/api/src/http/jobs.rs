@@ -0,0 +1,155 @@
+//! Asynchronous query jobs over HTTP, see [`crate::jobs`].
+//!
+//! - `POST /jobs` queues `{"sql": "..."}` and answers `202 Accepted` with the job's
+//!   [`JobInfo`], whose `id` addresses it from then on.
+//! - `GET /jobs/{id}` reports the job's state and how many result pages are written.
+//! - `GET /jobs/{id}/results?page=N` returns page `N` (from 0) of the result in any
+//!   [`OutputFormat`], chosen like for `/query`. [`LAST_PAGE_HEADER`] is `true` on the
+//!   last page of a finished job. A page that is not written yet is `409 Conflict`.
+//! - `DELETE /jobs/{id}` cancels the job and deletes its result.
+//!
+//! Jobs run with the session variables of the request that submitted them and are
+//! audited and rate limited like `/query`, but admitted as `batch` queries unless the
+//! session sets another `priority`. Only the principal that submitted a job
+//! sees it.
+
+use super::{negotiate, HttpError, QueryRequest};
+use crate::audit::{self, Auditor};
+use crate::auth::Principal;
+use crate::jobs::{JobError, JobInfo, JobManager};
+use crate::quota::{self, QuotaLimiter};
+use crate::session::{SessionStore, SESSION_HEADER};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Extension, Json, Router};
+use datafusion::physical_plan::execute_stream;
+use igloo_engine::admission::Priority;
+use igloo_engine::diagnostics::source_tables;
+use igloo_engine::session::{parse_set_sql, with_timeout};
+use igloo_engine::QueryEngine;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Response header telling whether a result page is the last one.
+pub const LAST_PAGE_HEADER: &str = "x-igloo-last-page";
+
+#[derive(Debug, Deserialize)]
+pub(super) struct PageQuery {
+    #[serde(default)]
+    page: usize,
+}
+
+impl From<JobError> for HttpError {
+    fn from(e: JobError) -> Self {
+        let status = match e {
+            JobError::UnknownJob(_) | JobError::NoSuchPage { .. } => StatusCode::NOT_FOUND,
+            JobError::PageNotReady { .. } | JobError::Failed { .. } => StatusCode::CONFLICT,
+            JobError::DataFusion(e) => return e.into(),
+        };
+        HttpError { status, error: e.to_api_error() }
+    }
+}
+
+pub(super) fn routes() -> Router<Arc<QueryEngine>> {
+    Router::new()
+        .route("/jobs", post(submit))
+        .route("/jobs/:id", get(status).delete(cancel))
+        .route("/jobs/:id/results", get(results))
+}
+
+#[allow(clippy::too_many_arguments)] // One extractor per optional layer, as for `/query`.
+async fn submit(
+    State(engine): State<Arc<QueryEngine>>,
+    Extension(jobs): Extension<Arc<JobManager>>,
+    principal: Option<Extension<Principal>>,
+    auditor: Option<Extension<Arc<Auditor>>>,
+    quotas: Option<Extension<Arc<QuotaLimiter>>>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+    headers: HeaderMap,
+    Json(request): Json<QueryRequest>,
+) -> Result<Response, HttpError> {
+    let subject = principal.as_ref().map(|p| p.subject.as_str());
+    if parse_set_sql(&request.sql)?.is_some() {
+        let message = "SET cannot run as a job; send it to /query";
+        return Err(HttpError::new(StatusCode::BAD_REQUEST, "invalid_request", message));
+    }
+    let session_id = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let session = sessions.get(subject, session_id);
+    let engine = super::scoped(&engine, principal.as_deref())?.with_session(&session);
+    let mut audit = audit::start(
+        auditor.as_deref().map(Arc::as_ref),
+        "http",
+        subject,
+        session_id,
+        &request.sql,
+    );
+    let mut permit = audit.check(quota::acquire(quotas.as_deref().map(Arc::as_ref), subject))?;
+    let engine = engine.with_running_query(audit.running_query());
+    let sql = request.sql;
+    let id = jobs.submit(subject, async move {
+        permit.admit(engine.priority().unwrap_or(Priority::Batch)).await;
+        let started = async {
+            let df = engine.sql(&sql).await?;
+            audit.set_tables(source_tables(df.logical_plan()));
+            let task_ctx = Arc::new(df.task_ctx());
+            let plan = df.create_physical_plan().await?;
+            permit.track(plan.clone());
+            audit.set_plan(&plan);
+            execute_stream(plan, task_ctx)
+        }
+        .await;
+        let stream = audit.check(started)?;
+        let stream = with_timeout(stream, engine.statement_timeout());
+        Ok(permit.wrap(audit.wrap(stream)))
+    })?;
+    let info = jobs.status(&id).ok_or_else(|| JobError::UnknownJob(id))?;
+    Ok((StatusCode::ACCEPTED, Json(info)).into_response())
+}
+
+async fn status(
+    Extension(jobs): Extension<Arc<JobManager>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<Json<JobInfo>, HttpError> {
+    Ok(Json(owned(&jobs, &id, principal.as_deref())?))
+}
+
+async fn results(
+    Extension(jobs): Extension<Arc<JobManager>>,
+    principal: Option<Extension<Principal>>,
+    Extension(sessions): Extension<Arc<SessionStore>>,
+    Path(id): Path<String>,
+    Query(query): Query<PageQuery>,
+    headers: HeaderMap,
+) -> Result<Response, HttpError> {
+    owned(&jobs, &id, principal.as_deref())?;
+    let subject = principal.as_ref().map(|p| p.subject.as_str());
+    let session_id = headers.get(SESSION_HEADER).and_then(|v| v.to_str().ok());
+    let format = negotiate(&headers, &sessions.get(subject, session_id))?;
+    let page = jobs.fetch_results(&id, query.page).await?;
+    let body = format.to_bytes(&page.schema, &page.batches)?;
+    let last = if page.last { "true" } else { "false" };
+    let last_page = HeaderName::from_static(LAST_PAGE_HEADER);
+    let headers = [(header::CONTENT_TYPE, format.content_type()), (last_page, last)];
+    Ok((headers, body).into_response())
+}
+
+async fn cancel(
+    Extension(jobs): Extension<Arc<JobManager>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, HttpError> {
+    owned(&jobs, &id, principal.as_deref())?;
+    jobs.cancel(&id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Job `id`, unless it was submitted by another principal.
+fn owned(jobs: &JobManager, id: &str, principal: Option<&Principal>) -> Result<JobInfo, JobError> {
+    match jobs.status(id) {
+        Some(info) if info.owner.as_deref() == principal.map(|p| p.subject.as_str()) => Ok(info),
+        _ => Err(JobError::UnknownJob(id.to_string())),
+    }
+}
@@ -0,0 +1,273 @@
+//! WebSocket streaming of query results.
+//!
+//! `GET /query/ws` upgrades to a WebSocket. The client sends `{"sql": "..."}` text
+//! messages; for each, the server replies with a `schema` message, then a `batch`
+//! message per record batch as it is produced, and finally `complete` (or `error`).
+//! While a query runs, `progress` messages report how far it has got, at least once
+//! per [`PROGRESS_INTERVAL`], so dashboards can render incrementally and show that a
+//! long federated query is still alive. Queries on one socket run one at a time.
+//!
+//! A socket is a session: `SET` statements (answered with `complete`) apply to the
+//! queries sent after them on the same socket.
+
+use super::QueryRequest;
+use crate::audit::{self, AuditEntry, Auditor};
+use crate::auth::Principal;
+use crate::quota::{self, QuotaLimiter, QuotaPermit};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use axum::Extension;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::execute_stream;
+use futures::StreamExt;
+use igloo_common::error::ApiError;
+use igloo_engine::diagnostics::{inspect_plan, source_tables};
+use igloo_engine::formats::OutputFormat;
+use igloo_engine::session::{parse_set_sql, with_timeout, SessionVars};
+use igloo_engine::QueryEngine;
+use serde::Serialize;
+use serde_json::value::RawValue;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Maximum time between two messages while a query is running.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Serialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+/// Messages sent by the server, tagged by `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    Schema {
+        columns: Vec<ColumnInfo>,
+    },
+    Diagnostic {
+        severity: String,
+        code: &'static str,
+        message: String,
+    },
+    /// Rows of one record batch as a JSON array of objects.
+    Batch {
+        rows: Box<RawValue>,
+    },
+    Progress {
+        batches: usize,
+        rows: usize,
+        elapsed_ms: u128,
+    },
+    Complete {
+        batches: usize,
+        rows: usize,
+        elapsed_ms: u128,
+    },
+    Error(ApiError),
+}
+
+pub(super) async fn handler(
+    State(engine): State<Arc<QueryEngine>>,
+    principal: Option<Extension<Principal>>,
+    auditor: Option<Extension<Arc<Auditor>>>,
+    quotas: Option<Extension<Arc<QuotaLimiter>>>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, super::HttpError> {
+    let subject = principal.as_ref().map(|p| p.subject.clone());
+    let engine = super::scoped(&engine, principal.as_deref())?;
+    let auditor = auditor.map(|Extension(auditor)| auditor);
+    let quotas = quotas.map(|Extension(quotas)| quotas);
+    Ok(upgrade.on_upgrade(move |socket| session(socket, engine, auditor, quotas, subject)))
+}
+
+async fn session(
+    mut socket: WebSocket,
+    engine: QueryEngine,
+    auditor: Option<Arc<Auditor>>,
+    quotas: Option<Arc<QuotaLimiter>>,
+    subject: Option<String>,
+) {
+    let mut session = SessionVars::new();
+    // Sessions are per connection.
+    let session_id = Uuid::new_v4().to_string();
+    while let Some(Ok(message)) = socket.recv().await {
+        let text = match message {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+        let result = match serde_json::from_str::<QueryRequest>(&text) {
+            Ok(request) => {
+                let mut audit = audit::start(
+                    auditor.as_deref(),
+                    "websocket",
+                    subject.as_deref(),
+                    Some(&session_id),
+                    &request.sql,
+                );
+                match audit.check(quota::acquire(quotas.as_deref(), subject.as_deref())) {
+                    Ok(permit) => {
+                        let sql = &request.sql;
+                        statement(&mut socket, &engine, &mut session, sql, audit, permit).await
+                    }
+                    Err(e) => send(&mut socket, &ServerMessage::Error(e.to_api_error())).await,
+                }
+            }
+            Err(e) => {
+                let error = super::HttpError::new(
+                    axum::http::StatusCode::BAD_REQUEST,
+                    "invalid_request",
+                    e.to_string(),
+                );
+                send(&mut socket, &ServerMessage::Error(error.error)).await
+            }
+        };
+        // The client went away; nothing left to stream to.
+        if result.is_err() {
+            break;
+        }
+    }
+}
+
+/// Run one statement: `SET` changes the socket's session, anything else is a query.
+async fn statement(
+    socket: &mut WebSocket,
+    engine: &QueryEngine,
+    session: &mut SessionVars,
+    sql: &str,
+    mut audit: AuditEntry,
+    permit: QuotaPermit,
+) -> Result<(), axum::Error> {
+    let set = parse_set_sql(sql)
+        .and_then(|set| set.map(|(name, value)| session.set(&name, &value)).transpose());
+    match audit.check(set) {
+        Ok(Some(())) => {
+            audit.succeeded(None);
+            send(socket, &ServerMessage::Complete { batches: 0, rows: 0, elapsed_ms: 0 }).await
+        }
+        Ok(None) => {
+            let engine = engine.with_session(session).with_running_query(audit.running_query());
+            run_query(socket, &engine, sql, audit, permit).await
+        }
+        Err(e) => send_error(socket, e).await,
+    }
+}
+
+/// Stream one query's results. Query failures are reported to the client; the
+/// returned error only signals that the socket itself is gone.
+async fn run_query(
+    socket: &mut WebSocket,
+    engine: &QueryEngine,
+    sql: &str,
+    mut audit: AuditEntry,
+    mut permit: QuotaPermit,
+) -> Result<(), axum::Error> {
+    let started = Instant::now();
+    permit.admit(engine.priority().unwrap_or_default()).await;
+    let prepared = prepare(socket, engine, sql, &mut audit, &mut permit).await;
+    let mut stream = match audit.check(prepared) {
+        Ok(Some(stream)) => permit.wrap(audit.wrap(stream)),
+        Ok(None) => return Ok(()),
+        Err(e) => return send_error(socket, e).await,
+    };
+
+    let (mut batches, mut rows) = (0, 0);
+    let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            next = stream.next() => match next {
+                Some(Ok(batch)) => {
+                    batches += 1;
+                    rows += batch.num_rows();
+                    match batch_message(&batch) {
+                        Ok(message) => send(socket, &message).await?,
+                        Err(e) => return send_error(socket, e).await,
+                    }
+                    let elapsed_ms = started.elapsed().as_millis();
+                    send(socket, &ServerMessage::Progress { batches, rows, elapsed_ms }).await?;
+                    ticker.reset();
+                }
+                Some(Err(e)) => return send_error(socket, e).await,
+                None => break,
+            },
+            _ = ticker.tick() => {
+                let elapsed_ms = started.elapsed().as_millis();
+                send(socket, &ServerMessage::Progress { batches, rows, elapsed_ms }).await?;
+            }
+        }
+    }
+    let elapsed_ms = started.elapsed().as_millis();
+    send(socket, &ServerMessage::Complete { batches, rows, elapsed_ms }).await
+}
+
+/// Plan `sql`, send its schema and diagnostics, and start execution. Returns `None` if
+/// the socket failed while sending.
+async fn prepare(
+    socket: &mut WebSocket,
+    engine: &QueryEngine,
+    sql: &str,
+    audit: &mut AuditEntry,
+    permit: &mut QuotaPermit,
+) -> Result<Option<datafusion::execution::SendableRecordBatchStream>, DataFusionError> {
+    let df = engine.sql(sql).await?;
+    audit.set_tables(source_tables(df.logical_plan()));
+    let diagnostics = inspect_plan(&df.clone().into_optimized_plan()?)?;
+
+    let columns = schema_columns(df.schema().as_arrow());
+    if send(socket, &ServerMessage::Schema { columns }).await.is_err() {
+        return Ok(None);
+    }
+    for diagnostic in diagnostics {
+        let message = ServerMessage::Diagnostic {
+            severity: diagnostic.severity.to_string(),
+            code: diagnostic.code,
+            message: diagnostic.message,
+        };
+        if send(socket, &message).await.is_err() {
+            return Ok(None);
+        }
+    }
+    let task_ctx = Arc::new(df.task_ctx());
+    let plan = df.create_physical_plan().await?;
+    permit.track(plan.clone());
+    audit.set_plan(&plan);
+    let stream = execute_stream(plan, task_ctx)?;
+    Ok(Some(with_timeout(stream, engine.statement_timeout())))
+}
+
+fn schema_columns(schema: &Schema) -> Vec<ColumnInfo> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| ColumnInfo {
+            name: field.name().clone(),
+            data_type: field.data_type().to_string(),
+            nullable: field.is_nullable(),
+        })
+        .collect()
+}
+
+fn batch_message(batch: &RecordBatch) -> Result<ServerMessage, DataFusionError> {
+    let buf = OutputFormat::Json.to_bytes(&batch.schema(), std::slice::from_ref(batch))?;
+    let json = String::from_utf8(buf).map_err(|e| DataFusionError::External(Box::new(e)))?;
+    let rows = RawValue::from_string(json).map_err(|e| DataFusionError::External(Box::new(e)))?;
+    Ok(ServerMessage::Batch { rows })
+}
+
+async fn send_error(socket: &mut WebSocket, e: DataFusionError) -> Result<(), axum::Error> {
+    let error = super::HttpError::from(e);
+    send(socket, &ServerMessage::Error(error.error)).await
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).expect("server messages are always serializable");
+    socket.send(Message::Text(text)).await
+}
@@ -0,0 +1,421 @@
+//! Asynchronous queries.
+//!
+//! A [`JobManager`] runs queries in the background instead of for the lifetime of
+//! one request: [`JobManager::submit_query`] returns a job id straight away, the
+//! query runs on a [`Scheduler`], and its result is spooled to an object store (a
+//! local directory with [`JobManager::local`]) in pages of up to
+//! [`JobManager::with_page_rows`] rows as it is produced. Clients poll
+//! [`JobManager::status`], read pages with [`JobManager::fetch_results`] (each page as
+//! soon as it is written, before the query has finished) and drop a job and its
+//! result with [`JobManager::cancel`]. Finished jobs are dropped on their own after
+//! the retention period.
+//!
+//! The HTTP API serves jobs under `/jobs` (see [`crate::http`]).
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::SendableRecordBatchStream;
+use futures::{StreamExt, TryStreamExt};
+use igloo_common::error::ApiError;
+use igloo_common::redact::redact;
+use igloo_engine::scheduler::{Scheduler, TaskId, TaskStatus};
+use igloo_engine::session::with_timeout;
+use igloo_engine::QueryEngine;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::{ObjectStore, PutPayload};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Rows per result page unless configured otherwise.
+pub const DEFAULT_PAGE_ROWS: usize = 10_000;
+
+/// How long a finished job's result is kept unless configured otherwise.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// A job as reported to its client.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    /// Subject of the principal that submitted the job, if any.
+    #[serde(skip)]
+    pub owner: Option<String>,
+    pub state: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Result pages written so far.
+    pub pages: usize,
+    /// Result rows written so far.
+    pub rows: u64,
+}
+
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error("no job {0}")]
+    UnknownJob(String),
+    #[error("job {id} has no result page {page}")]
+    NoSuchPage { id: String, page: usize },
+    #[error("result page {page} of job {id} is not ready yet")]
+    PageNotReady { id: String, page: usize },
+    #[error("job {id} failed: {message}")]
+    Failed { id: String, message: String },
+    #[error(transparent)]
+    DataFusion(#[from] DataFusionError),
+}
+
+impl JobError {
+    pub fn to_api_error(&self) -> ApiError {
+        let (code, hint, retryable) = match self {
+            JobError::UnknownJob(_) | JobError::NoSuchPage { .. } => ("not_found", None, false),
+            JobError::PageNotReady { .. } => {
+                ("not_ready", Some("poll the job's status until the page is written"), true)
+            }
+            JobError::Failed { .. } => ("job_failed", None, false),
+            JobError::DataFusion(e) => return ApiError::from(e),
+        };
+        let hint = hint.map(str::to_string);
+        ApiError { code, message: redact(&self.to_string()), detail: None, hint, retryable }
+    }
+}
+
+/// One page of a job's result.
+#[derive(Debug, Clone)]
+pub struct ResultPage {
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    /// Whether this is the last page of a finished job.
+    pub last: bool,
+}
+
+/// Runs queries in the background and keeps their results for clients to fetch.
+pub struct JobManager {
+    scheduler: Scheduler,
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    page_rows: usize,
+    retention: Duration,
+    jobs: Mutex<HashMap<String, Job>>,
+}
+
+struct Job {
+    task: TaskId,
+    owner: Option<String>,
+    progress: Arc<Mutex<Progress>>,
+}
+
+#[derive(Default)]
+struct Progress {
+    pages: usize,
+    rows: u64,
+    /// How the query ended, once it has. Cancelled jobs never get here.
+    outcome: Option<Result<(), String>>,
+    finished: Option<Instant>,
+}
+
+impl JobManager {
+    /// Run jobs on `scheduler`, spooling their results to `store`.
+    pub fn new(scheduler: Scheduler, store: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            scheduler,
+            store,
+            prefix: Path::from("jobs"),
+            page_rows: DEFAULT_PAGE_ROWS,
+            retention: DEFAULT_RETENTION,
+            jobs: Mutex::default(),
+        }
+    }
+
+    /// Run jobs on `scheduler`, spooling their results under the local directory `dir`,
+    /// which is created if needed.
+    pub fn local(scheduler: Scheduler, dir: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        let store = LocalFileSystem::new_with_prefix(dir.as_ref())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        Ok(Self::new(scheduler, Arc::new(store)))
+    }
+
+    /// Spool results under `prefix` in the store instead of `jobs`.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Path::from(prefix);
+        self
+    }
+
+    pub fn with_page_rows(mut self, rows: usize) -> Self {
+        self.page_rows = rows.max(1);
+        self
+    }
+
+    /// Keep finished jobs and their results for `retention`.
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Queue `sql` to run on `engine`, as submitted by `owner`, and return the job id.
+    pub fn submit_query(
+        &self,
+        engine: QueryEngine,
+        sql: &str,
+        owner: Option<&str>,
+    ) -> Result<String, JobError> {
+        let sql = sql.to_string();
+        self.submit(owner, async move {
+            let stream = engine.sql(&sql).await?.execute_stream().await?;
+            Ok(with_timeout(stream, engine.statement_timeout()))
+        })
+    }
+
+    /// Queue a job spooling the stream `start` resolves to, for callers that audit or
+    /// meter the query themselves.
+    pub fn submit<F>(&self, owner: Option<&str>, start: F) -> Result<String, JobError>
+    where
+        F: Future<Output = DataFusionResult<SendableRecordBatchStream>> + Send + 'static,
+    {
+        self.expire();
+        let id = uuid::Uuid::new_v4().to_string();
+        let progress = Arc::new(Mutex::new(Progress::default()));
+        let mut spool = Spool {
+            store: Arc::clone(&self.store),
+            dir: self.prefix.child(id.as_str()),
+            page_rows: self.page_rows,
+            progress: Arc::clone(&progress),
+            pending: Vec::new(),
+            pending_rows: 0,
+        };
+        let task = self.scheduler.submit(format!("job {id}"), async move {
+            let result = match start.await {
+                Ok(stream) => spool.run(stream).await,
+                Err(e) => Err(e),
+            };
+            let mut progress = spool.progress.lock().expect("job lock poisoned");
+            progress.outcome = Some(result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+            progress.finished = Some(Instant::now());
+            result
+        })?;
+        let job = Job { task, owner: owner.map(str::to_string), progress };
+        self.lock().insert(id.clone(), job);
+        Ok(id)
+    }
+
+    pub fn status(&self, id: &str) -> Option<JobInfo> {
+        let jobs = self.lock();
+        let job = jobs.get(id)?;
+        let progress = job.progress.lock().expect("job lock poisoned");
+        let (state, error) = match &progress.outcome {
+            Some(Ok(())) => (JobState::Succeeded, None),
+            Some(Err(e)) => (JobState::Failed, Some(e.clone())),
+            None => match self.scheduler.status(job.task) {
+                Some(TaskStatus::Queued) => (JobState::Queued, None),
+                Some(TaskStatus::Running) => (JobState::Running, None),
+                // The spooling task failed before recording its outcome.
+                Some(TaskStatus::Failed(e)) => (JobState::Failed, Some(e)),
+                _ => (JobState::Cancelled, None),
+            },
+        };
+        Some(JobInfo {
+            id: id.to_string(),
+            owner: job.owner.clone(),
+            state,
+            error,
+            pages: progress.pages,
+            rows: progress.rows,
+        })
+    }
+
+    /// Page `page` (from 0) of a job's result. Pages are available as soon as they
+    /// are written, while the query is still running.
+    pub async fn fetch_results(&self, id: &str, page: usize) -> Result<ResultPage, JobError> {
+        let info = self.status(id).ok_or_else(|| JobError::UnknownJob(id.to_string()))?;
+        if page >= info.pages {
+            return Err(match info.state {
+                JobState::Queued | JobState::Running => {
+                    JobError::PageNotReady { id: id.to_string(), page }
+                }
+                JobState::Failed => {
+                    JobError::Failed { id: id.to_string(), message: info.error.unwrap_or_default() }
+                }
+                JobState::Succeeded | JobState::Cancelled => {
+                    JobError::NoSuchPage { id: id.to_string(), page }
+                }
+            });
+        }
+        let path = page_path(&self.prefix.child(id), page);
+        let data = self.store.get(&path).await.map_err(DataFusionError::from)?;
+        let data = data.bytes().await.map_err(DataFusionError::from)?;
+        let reader = StreamReader::try_new(data.as_ref(), None).map_err(DataFusionError::from)?;
+        let schema = reader.schema();
+        let batches = reader.collect::<Result<Vec<_>, _>>().map_err(DataFusionError::from)?;
+        let last = info.state == JobState::Succeeded && page + 1 == info.pages;
+        Ok(ResultPage { schema, batches, last })
+    }
+
+    /// Stop a job if it is still running and delete its result. Returns whether the
+    /// job existed.
+    pub async fn cancel(&self, id: &str) -> Result<bool, JobError> {
+        let Some(job) = self.lock().remove(id) else {
+            return Ok(false);
+        };
+        self.scheduler.cancel(job.task);
+        // A running task may still write a page before it notices the cancellation.
+        self.scheduler.wait(job.task).await;
+        self.delete(id).await?;
+        Ok(true)
+    }
+
+    /// Drop the finished jobs older than the retention period.
+    fn expire(&self) {
+        let expired: Vec<String> = self
+            .lock()
+            .iter()
+            .filter(|(_, job)| {
+                let progress = job.progress.lock().expect("job lock poisoned");
+                progress.finished.is_some_and(|at| at.elapsed() >= self.retention)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        for id in expired {
+            self.lock().remove(&id);
+            let (store, dir) = (Arc::clone(&self.store), self.prefix.child(id.as_str()));
+            runtime.spawn(async move { delete_dir(store.as_ref(), &dir).await });
+        }
+    }
+
+    async fn delete(&self, id: &str) -> DataFusionResult<()> {
+        delete_dir(self.store.as_ref(), &self.prefix.child(id)).await
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Job>> {
+        self.jobs.lock().expect("job lock poisoned")
+    }
+}
+
+async fn delete_dir(store: &dyn ObjectStore, dir: &Path) -> DataFusionResult<()> {
+    let objects: Vec<_> = store.list(Some(dir)).try_collect().await?;
+    for object in objects {
+        store.delete(&object.location).await?;
+    }
+    Ok(())
+}
+
+fn page_path(dir: &Path, page: usize) -> Path {
+    dir.child(format!("page-{page:08}.arrow"))
+}
+
+/// Writes a result to the store in pages of `page_rows` rows, each an Arrow IPC
+/// stream.
+struct Spool {
+    store: Arc<dyn ObjectStore>,
+    dir: Path,
+    page_rows: usize,
+    progress: Arc<Mutex<Progress>>,
+    pending: Vec<RecordBatch>,
+    pending_rows: usize,
+}
+
+impl Spool {
+    async fn run(&mut self, mut stream: SendableRecordBatchStream) -> DataFusionResult<()> {
+        let schema = stream.schema();
+        while let Some(batch) = stream.next().await {
+            let mut batch = batch?;
+            while self.pending_rows + batch.num_rows() >= self.page_rows {
+                let take = self.page_rows - self.pending_rows;
+                self.pending.push(batch.slice(0, take));
+                self.pending_rows += take;
+                self.write_page(&schema).await?;
+                batch = batch.slice(take, batch.num_rows() - take);
+            }
+            if batch.num_rows() > 0 {
+                self.pending_rows += batch.num_rows();
+                self.pending.push(batch);
+            }
+        }
+        // An empty result still has a page, carrying the schema.
+        let pages = self.progress.lock().expect("job lock poisoned").pages;
+        if self.pending_rows > 0 || pages == 0 {
+            self.write_page(&schema).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_page(&mut self, schema: &SchemaRef) -> DataFusionResult<()> {
+        let mut writer = StreamWriter::try_new(Vec::new(), schema)?;
+        for batch in self.pending.drain(..) {
+            writer.write(&batch)?;
+        }
+        writer.finish()?;
+        let page = self.progress.lock().expect("job lock poisoned").pages;
+        let payload = PutPayload::from(writer.into_inner()?);
+        self.store.put(&page_path(&self.dir, page), payload).await?;
+        let mut progress = self.progress.lock().expect("job lock poisoned");
+        progress.pages += 1;
+        progress.rows += std::mem::take(&mut self.pending_rows) as u64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn test_results_are_paged_and_cancel_removes_them() {
+        let store = Arc::new(InMemory::new());
+        let jobs = JobManager::new(Scheduler::new(1, 4), store.clone()).with_page_rows(2);
+        let id = jobs.submit_query(QueryEngine::new(), "SELECT * FROM range(5)", None).unwrap();
+        let err = jobs.fetch_results(&id, 3).await;
+        assert!(matches!(
+            err,
+            Err(JobError::PageNotReady { .. }) | Err(JobError::NoSuchPage { .. })
+        ));
+
+        while jobs.status(&id).unwrap().state != JobState::Succeeded {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let info = jobs.status(&id).unwrap();
+        assert_eq!((info.pages, info.rows), (3, 5));
+        let rows = |page: &ResultPage| page.batches.iter().map(|b| b.num_rows()).sum::<usize>();
+        let first = jobs.fetch_results(&id, 0).await.unwrap();
+        assert_eq!((rows(&first), first.last), (2, false));
+        let last = jobs.fetch_results(&id, 2).await.unwrap();
+        assert_eq!((rows(&last), last.last), (1, true));
+        assert!(matches!(jobs.fetch_results(&id, 3).await, Err(JobError::NoSuchPage { .. })));
+
+        assert!(jobs.cancel(&id).await.unwrap());
+        assert!(jobs.status(&id).is_none());
+        assert_eq!(store.list(None).try_collect::<Vec<_>>().await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_jobs_report_their_error() {
+        let jobs = JobManager::new(Scheduler::new(1, 4), Arc::new(InMemory::new()));
+        let id = jobs.submit_query(QueryEngine::new(), "SELECT * FROM missing", None).unwrap();
+        while matches!(jobs.status(&id).unwrap().state, JobState::Queued | JobState::Running) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let info = jobs.status(&id).unwrap();
+        assert_eq!(info.state, JobState::Failed);
+        assert!(info.error.unwrap().contains("missing"));
+        assert!(matches!(jobs.fetch_results(&id, 0).await, Err(JobError::Failed { .. })));
+    }
+}
@@ -14,35 +14,156 @@ pub mod arrow {
     }
 }
 
+pub mod audit;
+pub mod auth;
+pub mod ballista;
+pub mod distributed;
+pub mod flight_sql;
+pub mod http;
+pub mod jobs;
+pub mod membership;
+pub mod pgwire;
+pub mod query_history;
+pub mod quota;
+pub mod session;
+pub mod slow_log;
+pub mod supervisor;
+pub mod tls;
+
+use crate::audit::Auditor;
+use crate::quota::QuotaLimiter;
+use crate::session::SessionStore;
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_descriptor::DescriptorType;
 use arrow_flight::{
     flight_service_server::FlightService, /*Action, ActionType, Criteria, Empty,*/
 };
 use arrow_flight::{
-    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo,
     HandshakeRequest, HandshakeResponse, PutResult, SchemaAsIpc, SchemaResult, Ticket,
 };
 use datafusion::arrow::ipc::writer::IpcWriteOptions;
-use futures::Stream;
+use datafusion::common::TableReference;
+use datafusion::error::DataFusionError;
+use futures::{Stream, StreamExt, TryStreamExt};
 use igloo_common::catalog::MemoryCatalog;
+use igloo_common::redact::redact;
+use igloo_engine::ingest::IngestOptions;
+use igloo_engine::session::parse_set_sql;
 use igloo_engine::QueryEngine;
 use std::pin::Pin;
 use std::sync::Arc;
+use tonic::metadata::MetadataValue;
 use tonic::{Request, Response, Status, Streaming};
 
-pub struct IglooFlightSqlService {
+/// gRPC metadata key under which query diagnostics are returned, one entry each.
+pub const DIAGNOSTIC_HEADER: &str = "x-igloo-diagnostic";
+
+/// gRPC metadata key of the columns, comma-separated, identifying the rows of a
+/// `DoPut`: rows whose keys were appended before are left out as duplicates (see
+/// [`igloo_engine::ingest`]).
+pub const IDEMPOTENCY_KEYS_HEADER: &str = "x-igloo-idempotency-keys";
+
+/// Prefix of tickets that read a whole registered table rather than run SQL. The
+/// rest of the ticket is the table reference, quoted as needed.
+pub const TABLE_TICKET_PREFIX: &str = "igloo.table:";
+
+/// Plain Arrow Flight service: the SQL text itself is the command/ticket.
+/// See [`flight_sql::IglooFlightSqlService`] for the Flight SQL protocol.
+///
+/// Registered tables and views are also exposed directly as flights: `ListFlights`
+/// returns one per table, with a `[catalog, schema, table]` path descriptor and a
+/// [`TABLE_TICKET_PREFIX`] ticket, so Arrow-native consumers can bulk-pull data
+/// without composing SQL.
+///
+/// `SET` tickets change the session named by the call's
+/// [`SESSION_HEADER`](session::SESSION_HEADER) metadata, as in Flight SQL.
+///
+/// `DoPut` with a path descriptor appends the uploaded batches to that table, in
+/// commits as [`QueryEngine::ingest`] makes them, deduplicated by the columns of its
+/// [`IDEMPOTENCY_KEYS_HEADER`] metadata if any.
+pub struct IglooFlightService {
     engine: Arc<QueryEngine>,
     #[allow(dead_code)]
     catalog: Arc<MemoryCatalog>,
+    audit: Option<Arc<Auditor>>,
+    quotas: Option<Arc<QuotaLimiter>>,
+    sessions: SessionStore,
 }
 
-impl IglooFlightSqlService {
+impl IglooFlightService {
     pub fn new(engine: Arc<QueryEngine>, catalog: Arc<MemoryCatalog>) -> Self {
-        Self { engine, catalog }
+        Self { engine, catalog, audit: None, quotas: None, sessions: SessionStore::new() }
+    }
+
+    /// Record every `DoGet` in the audit log.
+    pub fn with_audit(mut self, auditor: Arc<Auditor>) -> Self {
+        self.audit = Some(auditor);
+        self
+    }
+
+    /// Enforce per-principal rate limits and quotas on `DoGet`.
+    pub fn with_quotas(mut self, quotas: Arc<QuotaLimiter>) -> Self {
+        self.quotas = Some(quotas);
+        self
+    }
+
+    /// The engine as the caller's session sees it.
+    #[allow(clippy::result_large_err)] // Returns `Status` directly, like the trait methods.
+    fn session<T>(&self, request: &Request<T>) -> Result<QueryEngine, Status> {
+        let (principal, id) = flight_sql::session_key(request);
+        let engine = flight_sql::engine_for(&self.engine, request)?;
+        Ok(engine.with_session(&self.sessions.get(principal, id)))
+    }
+
+    /// Describe one table as a flight that can be fetched with a single `DoGet`.
+    async fn table_flight_info(
+        engine: &QueryEngine,
+        table: TableReference,
+    ) -> Result<FlightInfo, Status> {
+        let df = engine.session_context().table(table.clone()).await.map_err(table_error)?;
+        let ticket = format!("{TABLE_TICKET_PREFIX}{}", table.to_quoted_string());
+        let path = table.to_vec();
+        FlightInfo::new()
+            .try_with_schema(df.schema().as_arrow())
+            .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))
+            .map(|info| {
+                info.with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(ticket)))
+                    .with_descriptor(FlightDescriptor::new_path(path))
+            })
+    }
+}
+
+/// Resolve a path descriptor (`[table]`, `[schema, table]` or `[catalog, schema, table]`).
+#[allow(clippy::result_large_err)] // Returns `Status` directly, like the trait methods.
+fn path_to_table(path: &[String]) -> Result<TableReference, Status> {
+    match path {
+        [table] => Ok(TableReference::bare(table.as_str())),
+        [schema, table] => Ok(TableReference::partial(schema.as_str(), table.as_str())),
+        [catalog, schema, table] => {
+            Ok(TableReference::full(catalog.as_str(), schema.as_str(), table.as_str()))
+        }
+        _ => Err(Status::invalid_argument(
+            "Path descriptor must be [table], [schema, table] or [catalog, schema, table]",
+        )),
+    }
+}
+
+fn table_error(e: DataFusionError) -> Status {
+    match e.find_root() {
+        DataFusionError::Plan(message)
+            if message.starts_with("No table named") || message.contains("not found") =>
+        {
+            Status::not_found(redact(&e.to_string()))
+        }
+        _ => flight_sql::datafusion_error_to_status(e),
     }
 }
 
 #[tonic::async_trait]
-impl FlightService for IglooFlightSqlService {
+impl FlightService for IglooFlightService {
     type HandshakeStream =
         Pin<Box<dyn Stream<Item = Result<HandshakeResponse, Status>> + Send + 'static>>;
     type ListFlightsStream =
@@ -63,88 +184,180 @@ impl FlightService for IglooFlightSqlService {
         Err(Status::unimplemented("handshake is not yet implemented"))
     }
 
+    /// Lists every registered table and view. Criteria are ignored.
     async fn list_flights(
         &self,
-        _request: Request<Criteria>,
+        request: Request<Criteria>,
     ) -> Result<Response<Self::ListFlightsStream>, Status> {
-        Err(Status::unimplemented("list_flights is not yet implemented"))
+        let engine = flight_sql::engine_for(&self.engine, &request)?;
+        let ctx = engine.session_context();
+        let mut flights = Vec::new();
+        for catalog_name in ctx.catalog_names() {
+            let Some(catalog) = ctx.catalog(&catalog_name) else { continue };
+            for schema_name in catalog.schema_names() {
+                let Some(schema) = catalog.schema(&schema_name) else { continue };
+                let mut table_names = schema.table_names();
+                table_names.sort();
+                for table_name in table_names {
+                    let table = TableReference::full(
+                        catalog_name.as_str(),
+                        schema_name.as_str(),
+                        table_name,
+                    );
+                    flights.push(Self::table_flight_info(&engine, table).await);
+                }
+            }
+        }
+        Ok(Response::new(Box::pin(futures::stream::iter(flights))))
     }
 
     async fn get_flight_info(
         &self,
         request: Request<FlightDescriptor>,
     ) -> Result<Response<FlightInfo>, Status> {
+        let engine = self.session(&request)?;
         let descriptor = request.into_inner();
+        if descriptor.r#type() == DescriptorType::Path {
+            let table = path_to_table(&descriptor.path)?;
+            return Self::table_flight_info(&engine, table).await.map(Response::new);
+        }
         let cmd_bytes = descriptor.cmd;
         if cmd_bytes.is_empty() {
             return Err(Status::invalid_argument("No SQL command in FlightDescriptor"));
         }
         let sql = String::from_utf8(cmd_bytes.to_vec()).unwrap_or_default();
-        let batches = self.engine.execute(&sql).await;
-        let schema = batches.first().map(|b| b.schema()).ok_or(Status::not_found("No results"))?;
-        let options = IpcWriteOptions::default();
-        let schema_ipc = SchemaAsIpc::new(schema.as_ref(), &options);
-        let flight_data = FlightData::from(schema_ipc);
-        let schema_bytes = flight_data.data_header;
-        let flight_info = FlightInfo { schema: schema_bytes, ..Default::default() };
-        Ok(Response::new(flight_info))
+        // A SET has no result; it is applied by `DoGet`.
+        if parse_set_sql(&sql).map_err(flight_sql::datafusion_error_to_status)?.is_some() {
+            let info = FlightInfo::new()
+                .try_with_schema(&datafusion::arrow::datatypes::Schema::empty())
+                .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))?;
+            return Ok(Response::new(info));
+        }
+        // Planned for its schema only; `DoGet` executes it.
+        let df = engine.sql(&sql).await.map_err(flight_sql::datafusion_error_to_status)?;
+        let info = FlightInfo::new()
+            .try_with_schema(df.schema().as_arrow())
+            .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))?;
+        Ok(Response::new(info))
     }
 
+    /// Only path descriptors (tables) are supported.
     async fn get_schema(
         &self,
-        _request: Request<FlightDescriptor>,
+        request: Request<FlightDescriptor>,
     ) -> Result<Response<SchemaResult>, Status> {
-        Err(Status::unimplemented("get_schema is not yet implemented"))
+        let engine = flight_sql::engine_for(&self.engine, &request)?;
+        let descriptor = request.into_inner();
+        if descriptor.r#type() != DescriptorType::Path {
+            return Err(Status::unimplemented("get_schema is only supported for path descriptors"));
+        }
+        let table = path_to_table(&descriptor.path)?;
+        let df = engine.session_context().table(table).await.map_err(table_error)?;
+        let options = IpcWriteOptions::default();
+        SchemaAsIpc::new(df.schema().as_arrow(), &options)
+            .try_into()
+            .map(Response::new)
+            .map_err(|e| Status::internal(format!("Unable to encode schema: {e}")))
     }
 
     async fn do_get(
         &self,
         request: Request<Ticket>,
     ) -> Result<Response<Self::DoGetStream>, Status> {
-        use tokio::sync::mpsc;
-        use tokio_stream::wrappers::ReceiverStream;
-
-        let ticket = request.into_inner();
-        let sql = match String::from_utf8(ticket.ticket.to_vec()) {
+        let sql = match String::from_utf8(request.get_ref().ticket.to_vec()) {
             Ok(s) => s,
             Err(_) => return Err(Status::invalid_argument("Ticket is not valid UTF-8")),
         };
+        let (mut audit, mut permit) = flight_sql::admit(
+            self.audit.as_deref(),
+            self.quotas.as_deref(),
+            "flight",
+            &request,
+            &sql,
+        )?;
+        let engine = audit.check(self.session(&request))?.with_running_query(audit.running_query());
+        if let Some(table) = sql.strip_prefix(TABLE_TICKET_PREFIX) {
+            let df =
+                audit.check(engine.session_context().table(table).await).map_err(table_error)?;
+            return flight_sql::stream_dataframe(df, &engine, audit, permit).await;
+        }
+        if let Some((name, value)) =
+            audit.check(parse_set_sql(&sql)).map_err(flight_sql::datafusion_error_to_status)?
+        {
+            let (principal, id) = flight_sql::session_key(&request);
+            audit
+                .check(self.sessions.set(principal, id, &name, &value))
+                .map_err(flight_sql::datafusion_error_to_status)?;
+            audit.succeeded(None);
+            return Ok(Response::new(Box::pin(futures::stream::empty())));
+        }
 
-        let batches = self.engine.execute(&sql).await;
-        let (tx, rx) = mpsc::channel(2);
+        // Batches go out as they are produced, so results of any size pass through
+        // bounded memory.
+        permit.admit(engine.priority().unwrap_or_default()).await;
+        let result = audit
+            .check(engine.query_stream(&sql).await)
+            .map_err(flight_sql::datafusion_error_to_status)?;
+        permit.track(result.plan.clone());
+        audit.set_plan(&result.plan);
+        if result.cache_hit {
+            audit.set_cache_hit();
+        }
+        audit.set_tables(result.tables);
+        let batches =
+            permit.wrap(audit.wrap(result.batches)).map_err(flight_sql::datafusion_error_to_flight);
+        let stream = FlightDataEncoderBuilder::new()
+            .with_schema(result.schema)
+            .build(batches)
+            .map_err(Status::from);
 
-        tokio::spawn(async move {
-            if batches.is_empty() {
-                let _ = tx.send(Err(Status::not_found("No results for query"))).await;
-                return;
+        let mut response = Response::new(Box::pin(stream) as Self::DoGetStream);
+        // Diagnostics travel as response headers so clients can show them before the data.
+        for diagnostic in &result.diagnostics {
+            if let Ok(value) = MetadataValue::try_from(diagnostic.to_string()) {
+                response.metadata_mut().append(DIAGNOSTIC_HEADER, value);
             }
-            let schema = batches[0].schema();
-            match arrow_flight::utils::batches_to_flight_data(schema.as_ref(), batches) {
-                Ok(flight_data_vec) => {
-                    for flight_data in flight_data_vec {
-                        if tx.send(Ok(flight_data)).await.is_err() {
-                            break;
-                        }
-                    }
-                }
-                Err(e) => {
-                    let _ = tx
-                        .send(Err(Status::internal(format!(
-                            "Failed to convert RecordBatches: {e}"
-                        ))))
-                        .await;
-                }
-            }
-        });
-
-        Ok(Response::new(Box::pin(ReceiverStream::new(rx)) as Self::DoGetStream))
+        }
+        Ok(response)
     }
 
+    /// Appends to the table named by the first message's path descriptor. The one
+    /// result's app metadata is `{"rows": ..., "commits": ..., "duplicates": ...}`,
+    /// what was appended and what was left out.
     async fn do_put(
         &self,
-        _request: Request<Streaming<FlightData>>,
+        request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        Err(Status::unimplemented("do_put is not yet implemented"))
+        let engine = self.session(&request)?;
+        let mut options = IngestOptions::default();
+        if let Some(keys) = request.metadata().get(IDEMPOTENCY_KEYS_HEADER) {
+            let keys = keys.to_str().map_err(|_| {
+                Status::invalid_argument(format!("{IDEMPOTENCY_KEYS_HEADER} is not ASCII"))
+            })?;
+            options = options.with_keys(keys.split(',').map(str::trim));
+        }
+        let mut data = request.into_inner();
+        let Some(first) = data.message().await? else {
+            return Err(Status::invalid_argument("DoPut sent no data"));
+        };
+        let table = match &first.flight_descriptor {
+            Some(descriptor) if descriptor.r#type() == DescriptorType::Path => {
+                path_to_table(&descriptor.path)?
+            }
+            _ => return Err(Status::invalid_argument("DoPut needs a path descriptor")),
+        };
+        let data =
+            futures::stream::once(async { Ok(first) }).chain(data).map_err(FlightError::from);
+        let batches = FlightRecordBatchStream::new_from_flight_data(data)
+            .map_err(|e| DataFusionError::External(Box::new(e)));
+        let report = engine.ingest(table, batches, options).await.map_err(table_error)?;
+        let metadata = serde_json::json!({
+            "rows": report.rows,
+            "commits": report.commits,
+            "duplicates": report.duplicates,
+        });
+        let result = PutResult { app_metadata: metadata.to_string().into() };
+        Ok(Response::new(Box::pin(futures::stream::iter([Ok(result)]))))
     }
 
     async fn do_exchange(
@@ -0,0 +1,368 @@
+//! Worker membership for distributed execution.
+//!
+//! A coordinator's [`Membership`] knows the workers it may place stages on: those
+//! configured up front with [`Membership::with_worker`] and those that register over
+//! the coordinator's `CoordinatorService` ([`MembershipService`]) and keep sending
+//! heartbeats. Each worker has a capacity, the tasks it is meant to run at once, and
+//! every task goes to the available worker with the most spare capacity.
+//!
+//! A worker gets no new tasks while it is
+//!
+//! - draining: taken out of service with [`Membership::drain`], e.g. ahead of
+//!   maintenance, until [`Membership::resume`]; tasks already placed on it finish;
+//! - unresponsive: registered, but without a heartbeat for the heartbeat timeout;
+//! - failing: it could not be reached for `max_failures` tasks in a row, and is left
+//!   out for the failure cooldown before being tried again.
+
+use crate::igloo::coordinator_service_server::CoordinatorService;
+use crate::igloo::{HeartbeatInfo, HeartbeatResponse, RegistrationAck, WorkerInfo};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tonic::{Request, Response, Status};
+
+/// Capacity of workers that do not state their own.
+pub const DEFAULT_CAPACITY: u32 = 4;
+
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
+
+pub const DEFAULT_MAX_FAILURES: u32 = 3;
+
+pub const DEFAULT_FAILURE_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorkerState {
+    Active,
+    Draining,
+    Unresponsive,
+    Failing,
+}
+
+/// A worker as the coordinator currently sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerStatus {
+    pub id: String,
+    pub address: String,
+    pub capacity: u32,
+    /// Tasks placed on the worker that have not finished.
+    pub running: u32,
+    pub state: WorkerState,
+    /// When the worker last registered or sent a heartbeat; `None` for configured
+    /// workers, which are not expected to.
+    pub last_heartbeat: Option<SystemTime>,
+}
+
+/// The workers of a coordinator and their health.
+#[derive(Debug)]
+pub struct Membership {
+    workers: Mutex<HashMap<String, Worker>>,
+    heartbeat_timeout: Duration,
+    max_failures: u32,
+    failure_cooldown: Duration,
+}
+
+#[derive(Debug)]
+struct Worker {
+    address: String,
+    capacity: u32,
+    running: u32,
+    draining: bool,
+    /// Configured workers are not expected to send heartbeats.
+    heartbeat: Option<(Instant, SystemTime)>,
+    failures: u32,
+    failing_until: Option<Instant>,
+}
+
+impl Default for Membership {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Membership {
+    pub fn new() -> Self {
+        Self {
+            workers: Mutex::new(HashMap::new()),
+            heartbeat_timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+            max_failures: DEFAULT_MAX_FAILURES,
+            failure_cooldown: DEFAULT_FAILURE_COOLDOWN,
+        }
+    }
+
+    /// Add a worker at `address` (`host:port`) with [`DEFAULT_CAPACITY`], identified by
+    /// its address. Configured workers are considered up without heartbeats.
+    pub fn with_worker(self, address: impl Into<String>) -> Self {
+        let address = address.into();
+        self.lock().insert(address.clone(), Worker::new(address, DEFAULT_CAPACITY, None));
+        self
+    }
+
+    /// How long a registered worker may go without a heartbeat before it is left out.
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Leave a worker out for `cooldown` after it could not be reached for
+    /// `max_failures` tasks in a row.
+    pub fn with_max_failures(mut self, max_failures: u32, cooldown: Duration) -> Self {
+        self.max_failures = max_failures.max(1);
+        self.failure_cooldown = cooldown;
+        self
+    }
+
+    /// Add or replace worker `id`. A worker registering again after a restart replaces
+    /// any other entry for its address.
+    pub fn register(&self, id: &str, address: &str, capacity: u32) {
+        let mut workers = self.lock();
+        let draining = workers.get(id).is_some_and(|worker| worker.draining);
+        workers.retain(|other, worker| other == id || worker.address != address);
+        let now = (Instant::now(), SystemTime::now());
+        let mut worker = Worker::new(address.to_string(), capacity, Some(now));
+        worker.draining = draining;
+        workers.insert(id.to_string(), worker);
+    }
+
+    /// Record a heartbeat from worker `id`. `false` if the worker is unknown and
+    /// should register (again).
+    pub fn heartbeat(&self, id: &str) -> bool {
+        match self.lock().get_mut(id) {
+            Some(worker) => {
+                worker.heartbeat = Some((Instant::now(), SystemTime::now()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop placing tasks on the worker with this id or address. `false` if unknown.
+    pub fn drain(&self, worker: &str) -> bool {
+        self.set_draining(worker, true)
+    }
+
+    /// Undo [`Membership::drain`]. `false` if the worker is unknown.
+    pub fn resume(&self, worker: &str) -> bool {
+        self.set_draining(worker, false)
+    }
+
+    fn set_draining(&self, name: &str, draining: bool) -> bool {
+        let mut workers = self.lock();
+        let mut found = false;
+        for (id, worker) in workers.iter_mut() {
+            if id == name || worker.address == name {
+                worker.draining = draining;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Every known worker, by id.
+    pub fn workers(&self) -> Vec<WorkerStatus> {
+        let now = Instant::now();
+        let workers = self.lock();
+        let mut statuses: Vec<_> = workers
+            .iter()
+            .map(|(id, worker)| WorkerStatus {
+                id: id.clone(),
+                address: worker.address.clone(),
+                capacity: worker.capacity,
+                running: worker.running,
+                state: self.state(worker, now),
+                last_heartbeat: worker.heartbeat.map(|(_, at)| at),
+            })
+            .collect();
+        statuses.sort_by(|a, b| a.id.cmp(&b.id));
+        statuses
+    }
+
+    /// Whether any worker can take tasks.
+    pub fn has_available(&self) -> bool {
+        let now = Instant::now();
+        self.lock().values().any(|worker| self.state(worker, now) == WorkerState::Active)
+    }
+
+    /// Reserve a task slot on the available worker with the most spare capacity,
+    /// other than those at the `excluded` addresses.
+    pub(crate) fn place(self: &Arc<Self>, excluded: &BTreeSet<String>) -> Option<TaskSlot> {
+        let now = Instant::now();
+        let mut workers = self.lock();
+        let (id, worker) = workers
+            .iter_mut()
+            .filter(|(_, worker)| self.state(worker, now) == WorkerState::Active)
+            .filter(|(_, worker)| !excluded.contains(&worker.address))
+            // Least loaded relative to capacity, ties broken by id for stable placement.
+            .min_by(|(a_id, a), (b_id, b)| {
+                let load = |w: &Worker| (w.running + 1) as f64 / w.capacity.max(1) as f64;
+                load(a).total_cmp(&load(b)).then_with(|| a_id.cmp(b_id))
+            })?;
+        worker.running += 1;
+        Some(TaskSlot {
+            membership: Arc::clone(self),
+            id: id.clone(),
+            address: worker.address.clone(),
+        })
+    }
+
+    fn state(&self, worker: &Worker, now: Instant) -> WorkerState {
+        if worker.draining {
+            WorkerState::Draining
+        } else if worker.failing_until.is_some_and(|until| now < until) {
+            WorkerState::Failing
+        } else if worker
+            .heartbeat
+            .is_some_and(|(at, _)| now.duration_since(at) > self.heartbeat_timeout)
+        {
+            WorkerState::Unresponsive
+        } else {
+            WorkerState::Active
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Worker>> {
+        self.workers.lock().expect("membership lock poisoned")
+    }
+}
+
+impl Worker {
+    fn new(address: String, capacity: u32, heartbeat: Option<(Instant, SystemTime)>) -> Self {
+        Self {
+            address,
+            capacity: capacity.max(1),
+            running: 0,
+            draining: false,
+            heartbeat,
+            failures: 0,
+            failing_until: None,
+        }
+    }
+}
+
+/// A task placed on a worker. Dropping it frees the slot.
+#[derive(Debug)]
+pub(crate) struct TaskSlot {
+    membership: Arc<Membership>,
+    id: String,
+    pub(crate) address: String,
+}
+
+impl TaskSlot {
+    /// Record whether the worker could be reached to run the task.
+    pub(crate) fn reached(&self, reached: bool) {
+        let membership = &self.membership;
+        let mut workers = membership.lock();
+        let Some(worker) = workers.get_mut(&self.id) else {
+            return;
+        };
+        if reached {
+            worker.failures = 0;
+            return;
+        }
+        worker.failures += 1;
+        if worker.failures >= membership.max_failures {
+            worker.failures = 0;
+            worker.failing_until = Some(Instant::now() + membership.failure_cooldown);
+        }
+    }
+}
+
+impl Drop for TaskSlot {
+    fn drop(&mut self) {
+        if let Some(worker) = self.membership.lock().get_mut(&self.id) {
+            worker.running = worker.running.saturating_sub(1);
+        }
+    }
+}
+
+/// The coordinator's `CoordinatorService`: worker registration and heartbeats.
+pub struct MembershipService {
+    membership: Arc<Membership>,
+}
+
+impl MembershipService {
+    pub fn new(membership: Arc<Membership>) -> Self {
+        Self { membership }
+    }
+}
+
+#[tonic::async_trait]
+impl CoordinatorService for MembershipService {
+    async fn register_worker(
+        &self,
+        request: Request<WorkerInfo>,
+    ) -> Result<Response<RegistrationAck>, Status> {
+        let info = request.into_inner();
+        if info.id.is_empty() || info.address.is_empty() {
+            return Err(Status::invalid_argument("worker id and address are required"));
+        }
+        let capacity = match info.capacity {
+            0 => DEFAULT_CAPACITY,
+            capacity => capacity,
+        };
+        self.membership.register(&info.id, &info.address, capacity);
+        tracing::info!(worker = info.id, address = info.address, capacity, "registered worker");
+        Ok(Response::new(RegistrationAck { message: "Registered".to_string() }))
+    }
+
+    async fn send_heartbeat(
+        &self,
+        request: Request<HeartbeatInfo>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let ok = self.membership.heartbeat(&request.get_ref().worker_id);
+        Ok(Response::new(HeartbeatResponse { ok }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(slots: &[TaskSlot]) -> Vec<&str> {
+        slots.iter().map(|slot| slot.address.as_str()).collect()
+    }
+
+    #[test]
+    fn test_placement_follows_capacity() {
+        let membership = Arc::new(Membership::new().with_worker("a:1"));
+        membership.register("b", "b:1", 2 * DEFAULT_CAPACITY);
+        let slots: Vec<_> = (0..6).map(|_| membership.place(&BTreeSet::new()).unwrap()).collect();
+        let on_b = addresses(&slots).iter().filter(|address| **address == "b:1").count();
+        assert_eq!(on_b, 4);
+        drop(slots);
+        assert!(membership.workers().iter().all(|worker| worker.running == 0));
+
+        assert!(membership.drain("b"));
+        let slots: Vec<_> = (0..3).map(|_| membership.place(&BTreeSet::new()).unwrap()).collect();
+        assert_eq!(addresses(&slots), ["a:1", "a:1", "a:1"]);
+        assert!(membership.resume("b:1"));
+        assert_eq!(membership.place(&BTreeSet::new()).unwrap().address, "b:1");
+    }
+
+    #[test]
+    fn test_unhealthy_workers_are_left_out() {
+        let membership = Arc::new(
+            Membership::new()
+                .with_worker("a:1")
+                .with_heartbeat_timeout(Duration::from_millis(50))
+                .with_max_failures(2, Duration::from_secs(60)),
+        );
+        membership.register("b", "b:1", 1);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!membership.heartbeat("unknown"));
+        let state = |id: &str| membership.workers().into_iter().find(|w| w.id == id).unwrap().state;
+        assert_eq!(state("b"), WorkerState::Unresponsive);
+
+        for _ in 0..2 {
+            membership.place(&BTreeSet::new()).unwrap().reached(false);
+        }
+        assert_eq!(state("a:1"), WorkerState::Failing);
+        assert!(!membership.has_available());
+        assert!(membership.place(&BTreeSet::new()).is_none());
+
+        // A restarted worker registers under a new id, replacing its old entry.
+        membership.register("b2", "b:1", 1);
+        let ids: Vec<_> = membership.workers().into_iter().map(|w| w.id).collect();
+        assert_eq!(ids, ["a:1", "b2"]);
+        assert_eq!(membership.place(&BTreeSet::new()).unwrap().address, "b:1");
+    }
+}
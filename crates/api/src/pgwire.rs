@@ -0,0 +1,820 @@
+//! PostgreSQL wire protocol frontend.
+//!
+//! Lets `psql`, PostgreSQL drivers and BI tools talk to Igloo as if it were a Postgres
+//! server. Both the simple and the extended (prepared statement) protocols are
+//! translated into [`QueryEngine`] plans; results are streamed back batch by batch as
+//! pg rows. Query diagnostics are sent as `NOTICE` messages before the rows.
+//!
+//! With [`IglooPgServer::with_auth`], clients must send a credential (API key or JWT,
+//! see [`crate::auth`]) as their password; the principal's subject is then kept in
+//! the session metadata under [`PRINCIPAL_METADATA_KEY`], and its tenant, if any, under
+//! [`TENANT_METADATA_KEY`]; the connection sees only that tenant's tables (see
+//! [`igloo_engine::tenant`]). [`IglooPgServer::with_tls`]
+//! lets clients encrypt their connection. [`IglooPgServer::with_quotas`] rate limits
+//! statements per principal, refusing them with SQLSTATE `53400`.
+//!
+//! `SET` statements change the connection's session variables (see
+//! [`igloo_engine::session`]), kept in its metadata under [`SESSION_METADATA_PREFIX`].
+
+use crate::audit::{self, AuditEntry, Auditor};
+use crate::auth::{AuthError, Authenticator, PRINCIPAL_METADATA_KEY, TENANT_METADATA_KEY};
+use crate::quota::{self, QuotaLimiter, QuotaPermit};
+use crate::tls::TlsConfig;
+use async_trait::async_trait;
+use datafusion::arrow::array::{Array, AsArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{
+    DataType, Date32Type, Date64Type, Decimal128Type, Float16Type, Float32Type, Float64Type,
+    Int16Type, Int32Type, Int64Type, Int8Type, Schema, TimeUnit, TimestampMicrosecondType,
+    TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::{ArrayFormatter, FormatOptions};
+use datafusion::common::{ParamValues, ScalarValue};
+use datafusion::dataframe::DataFrame;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan, Statement as PlanStatement, WriteOp};
+use datafusion::physical_plan::common::collect;
+use datafusion::physical_plan::execute_stream;
+use datafusion::sql::parser::{DFParser, Statement};
+use datafusion::sql::sqlparser::dialect::PostgreSqlDialect;
+use futures::{stream, Sink, SinkExt, StreamExt};
+use igloo_common::error::ApiError;
+use igloo_common::redact::redact;
+use igloo_engine::diagnostics::{inspect_plan, source_tables, Severity};
+use igloo_engine::session::{parse_set, with_timeout, SessionVars};
+use igloo_engine::QueryEngine;
+use pgwire::api::auth::{
+    finish_authentication, save_startup_parameters_to_metadata, DefaultServerParameterProvider,
+    StartupHandler,
+};
+use pgwire::api::copy::NoopCopyHandler;
+use pgwire::api::portal::{Format, Portal};
+use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
+use pgwire::api::results::{
+    DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldInfo, QueryResponse,
+    Response, Tag,
+};
+use pgwire::api::stmt::{QueryParser, StoredStatement};
+use pgwire::api::{
+    ClientInfo, NoopErrorHandler, PgWireConnectionState, PgWireServerHandlers, Type,
+};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::data::DataRow;
+use pgwire::messages::response::NoticeResponse;
+use pgwire::messages::startup::Authentication;
+use pgwire::messages::{PgWireBackendMessage, PgWireFrontendMessage};
+use pgwire::tokio::TlsAcceptor;
+use rust_decimal::Decimal;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+/// Parses incoming SQL once, at `Parse` time, so syntax errors surface immediately.
+#[derive(Debug, Default)]
+pub struct IglooQueryParser;
+
+#[async_trait]
+impl QueryParser for IglooQueryParser {
+    type Statement = Statement;
+
+    async fn parse_sql<C>(&self, _client: &C, sql: &str, _types: &[Type]) -> PgWireResult<Statement>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let mut statements = parse(sql)?;
+        match (statements.pop(), statements.is_empty()) {
+            (Some(statement), true) => Ok(statement),
+            (None, _) => Err(user_error("42601", "empty statement")),
+            (Some(_), false) => Err(user_error(
+                "42601",
+                "cannot insert multiple commands into a prepared statement",
+            )),
+        }
+    }
+}
+
+/// Prefix of the session metadata keys holding the connection's session variables.
+pub const SESSION_METADATA_PREFIX: &str = "igloo.session.";
+
+/// Query handler shared by every connection.
+#[derive(Clone)]
+pub struct IglooPgBackend {
+    engine: Arc<QueryEngine>,
+    query_parser: Arc<IglooQueryParser>,
+    auth: Option<Arc<Authenticator>>,
+    audit: Option<Arc<Auditor>>,
+    quotas: Option<Arc<QuotaLimiter>>,
+}
+
+impl IglooPgBackend {
+    pub fn new(engine: Arc<QueryEngine>) -> Self {
+        Self {
+            engine,
+            query_parser: Arc::new(IglooQueryParser),
+            auth: None,
+            audit: None,
+            quotas: None,
+        }
+    }
+
+    /// Start auditing `statement` and admit it under the client's quotas.
+    fn admit<C: ClientInfo>(
+        &self,
+        client: &C,
+        statement: &Statement,
+    ) -> PgWireResult<(AuditEntry, QuotaPermit)> {
+        let principal = client.metadata().get(PRINCIPAL_METADATA_KEY).map(String::as_str);
+        let sql = statement.to_string();
+        // Sessions are per connection.
+        let session = client.socket_addr().to_string();
+        let mut audit =
+            audit::start(self.audit.as_deref(), "pgwire", principal, Some(&session), &sql);
+        let permit = audit
+            .check(quota::acquire(self.quotas.as_deref(), principal))
+            .map_err(|e| user_error("53400", e.to_string()))?;
+        Ok((audit, permit))
+    }
+
+    /// The engine as the client sees it, with its session variables applied and the
+    /// budget of its principal's resource class.
+    fn session<C: ClientInfo>(&self, client: &C) -> PgWireResult<QueryEngine> {
+        let principal = client.metadata().get(PRINCIPAL_METADATA_KEY).map(String::as_str);
+        let engine = match client.metadata().get(TENANT_METADATA_KEY) {
+            Some(tenant) => self.engine.tenant(tenant).ok_or_else(|| {
+                user_error("42501", AuthError::UnknownTenant(tenant.clone()).to_string())
+            })?,
+            None => QueryEngine::clone(&self.engine),
+        };
+        Ok(engine.with_resources(principal).with_session(&session_vars(client)))
+    }
+
+    /// Run `statement` as a session `SET` if it is one, returning its response.
+    fn try_set<'a, C: ClientInfo>(
+        client: &mut C,
+        statement: &Statement,
+        audit: &mut AuditEntry,
+    ) -> PgWireResult<Option<Response<'a>>> {
+        let Some((name, value)) =
+            audit.check(parse_set(statement)).map_err(datafusion_error_to_pg)?
+        else {
+            return Ok(None);
+        };
+        let mut vars = session_vars(client);
+        audit.check(vars.set(&name, &value)).map_err(datafusion_error_to_pg)?;
+        let metadata = client.metadata_mut();
+        metadata.retain(|key, _| !key.starts_with(SESSION_METADATA_PREFIX));
+        for (name, value) in vars.settings() {
+            metadata.insert(format!("{SESSION_METADATA_PREFIX}{name}"), value);
+        }
+        Ok(Some(Response::Execution(Tag::new("SET"))))
+    }
+
+    async fn plan(engine: &QueryEngine, statement: Statement) -> PgWireResult<LogicalPlan> {
+        let state = engine.session_context().state();
+        state.statement_to_plan(statement).await.map_err(datafusion_error_to_pg)
+    }
+
+    /// Plan a portal's statement with its bound parameter values.
+    async fn bind(engine: &QueryEngine, portal: &Portal<Statement>) -> PgWireResult<LogicalPlan> {
+        let plan = Self::plan(engine, portal.statement.statement.clone()).await?;
+        let types = parameter_types(&plan, &portal.statement.parameter_types)?;
+        if types.is_empty() {
+            return Ok(plan);
+        }
+        let params = parameter_values(portal, &types)?;
+        plan.with_param_values(params).map_err(datafusion_error_to_pg)
+    }
+
+    /// Execute a planned statement, sending its diagnostics to `client` first.
+    async fn execute<'a, C>(
+        engine: &QueryEngine,
+        client: &mut C,
+        plan: LogicalPlan,
+        format: &Format,
+        mut audit: AuditEntry,
+        mut permit: QuotaPermit,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: Sink<PgWireBackendMessage> + Unpin + Send,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        permit.admit(engine.priority().unwrap_or_default()).await;
+        let command = command_tag(&plan);
+        audit.set_tables(source_tables(&plan));
+        let df =
+            audit.check(engine.execute_logical_plan(plan).await).map_err(datafusion_error_to_pg)?;
+        send_diagnostics(client, &df).await?;
+        let fields = Arc::new(schema_to_fields(df.schema().as_arrow(), format));
+        let task_ctx = Arc::new(df.task_ctx());
+        let physical =
+            audit.check(df.create_physical_plan().await).map_err(datafusion_error_to_pg)?;
+        permit.track(physical.clone());
+        audit.set_plan(&physical);
+        let batches =
+            audit.check(execute_stream(physical, task_ctx)).map_err(datafusion_error_to_pg)?;
+        let batches = with_timeout(batches, engine.statement_timeout());
+
+        match command {
+            // DML reports the affected row count in the tag, e.g. `INSERT 0 3`.
+            Some(tag @ ("INSERT" | "UPDATE" | "DELETE")) => {
+                let batches =
+                    audit.check(collect(batches).await).map_err(datafusion_error_to_pg)?;
+                let rows = batches
+                    .first()
+                    .filter(|b| b.num_rows() > 0)
+                    .and_then(|b| b.column(0).as_primitive_opt::<UInt64Type>())
+                    .map(|count| count.value(0) as usize)
+                    .unwrap_or(0);
+                let tag = match tag {
+                    // Postgres still reports a (always zero) OID for inserts.
+                    "INSERT" => Tag::new(tag).with_oid(0),
+                    _ => Tag::new(tag),
+                };
+                audit.succeeded(Some(rows as u64));
+                Ok(Response::Execution(tag.with_rows(rows)))
+            }
+            Some(tag) => {
+                audit.check(collect(batches).await).map_err(datafusion_error_to_pg)?;
+                audit.succeeded(None);
+                Ok(Response::Execution(Tag::new(tag)))
+            }
+            None => {
+                let batches = permit.wrap(audit.wrap(batches));
+                let header = fields.clone();
+                let rows = batches
+                    .map(move |batch| {
+                        let rows = batch
+                            .map_err(datafusion_error_to_pg)
+                            .and_then(|batch| encode_batch(&batch, &fields));
+                        stream::iter(match rows {
+                            Ok(rows) => rows.into_iter().map(Ok).collect(),
+                            Err(e) => vec![Err(e)],
+                        })
+                    })
+                    .flatten();
+                Ok(Response::Query(QueryResponse::new(header, rows)))
+            }
+        }
+    }
+}
+
+/// Trusts every connection unless an [`Authenticator`] is configured, in which case
+/// the password is checked as a credential.
+#[async_trait]
+impl StartupHandler for IglooPgBackend {
+    async fn on_startup<C>(
+        &self,
+        client: &mut C,
+        message: PgWireFrontendMessage,
+    ) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let parameters = DefaultServerParameterProvider::default();
+        match (message, &self.auth) {
+            (PgWireFrontendMessage::Startup(startup), None) => {
+                save_startup_parameters_to_metadata(client, &startup);
+                finish_authentication(client, &parameters).await?;
+            }
+            (PgWireFrontendMessage::Startup(startup), Some(_)) => {
+                save_startup_parameters_to_metadata(client, &startup);
+                client.set_state(PgWireConnectionState::AuthenticationInProgress);
+                let request = Authentication::CleartextPassword;
+                client.send(PgWireBackendMessage::Authentication(request)).await?;
+            }
+            (PgWireFrontendMessage::PasswordMessageFamily(password), Some(auth)) => {
+                let password = password.into_password()?;
+                let principal = auth.authenticate(&password.password).map_err(|e| {
+                    PgWireError::UserError(Box::new(ErrorInfo::new(
+                        "FATAL".to_string(),
+                        "28P01".to_string(),
+                        e.to_string(),
+                    )))
+                })?;
+                let metadata = client.metadata_mut();
+                metadata.insert(PRINCIPAL_METADATA_KEY.to_string(), principal.subject);
+                if let Some(tenant) = principal.tenant {
+                    metadata.insert(TENANT_METADATA_KEY.to_string(), tenant);
+                }
+                finish_authentication(client, &parameters).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SimpleQueryHandler for IglooPgBackend {
+    async fn do_query<'a, C>(&self, client: &mut C, query: &str) -> PgWireResult<Vec<Response<'a>>>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let statements = parse(query)?;
+        if statements.is_empty() {
+            return Ok(vec![Response::EmptyQuery]);
+        }
+        let mut responses = Vec::with_capacity(statements.len());
+        for statement in statements {
+            let (mut audit, permit) = self.admit(client, &statement)?;
+            if let Some(response) = Self::try_set(client, &statement, &mut audit)? {
+                audit.succeeded(None);
+                responses.push(response);
+                continue;
+            }
+            let engine =
+                audit.check(self.session(client))?.with_running_query(audit.running_query());
+            let plan = audit.check(Self::plan(&engine, statement).await)?;
+            let format = Format::UnifiedText;
+            responses.push(Self::execute(&engine, client, plan, &format, audit, permit).await?);
+        }
+        Ok(responses)
+    }
+}
+
+#[async_trait]
+impl ExtendedQueryHandler for IglooPgBackend {
+    type Statement = Statement;
+    type QueryParser = IglooQueryParser;
+
+    fn query_parser(&self) -> Arc<Self::QueryParser> {
+        self.query_parser.clone()
+    }
+
+    async fn do_query<'a, C>(
+        &self,
+        client: &mut C,
+        portal: &Portal<Self::Statement>,
+        _max_rows: usize,
+    ) -> PgWireResult<Response<'a>>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let (mut audit, permit) = self.admit(client, &portal.statement.statement)?;
+        if let Some(response) = Self::try_set(client, &portal.statement.statement, &mut audit)? {
+            audit.succeeded(None);
+            return Ok(response);
+        }
+        let engine = audit.check(self.session(client))?.with_running_query(audit.running_query());
+        let plan = audit.check(Self::bind(&engine, portal).await)?;
+        Self::execute(&engine, client, plan, &portal.result_column_format, audit, permit).await
+    }
+
+    async fn do_describe_statement<C>(
+        &self,
+        client: &mut C,
+        target: &StoredStatement<Self::Statement>,
+    ) -> PgWireResult<DescribeStatementResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let plan = Self::plan(&self.session(client)?, target.statement.clone()).await?;
+        let param_types = parameter_types(&plan, &target.parameter_types)?
+            .into_iter()
+            .map(|(pg, _)| pg)
+            .collect();
+        let fields = result_fields(&plan, &Format::UnifiedText);
+        Ok(DescribeStatementResponse::new(param_types, fields))
+    }
+
+    async fn do_describe_portal<C>(
+        &self,
+        client: &mut C,
+        portal: &Portal<Self::Statement>,
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let plan = Self::plan(&self.session(client)?, portal.statement.statement.clone()).await?;
+        Ok(DescribePortalResponse::new(result_fields(&plan, &portal.result_column_format)))
+    }
+}
+
+/// Hands the same [`IglooPgBackend`] to every connection.
+#[derive(Clone)]
+pub struct IglooPgServer {
+    backend: Arc<IglooPgBackend>,
+    tls: Option<TlsAcceptor>,
+}
+
+impl IglooPgServer {
+    pub fn new(engine: Arc<QueryEngine>) -> Self {
+        Self { backend: Arc::new(IglooPgBackend::new(engine)), tls: None }
+    }
+
+    /// Accept `SSLRequest`s, encrypting connections with `tls`. Clients that do not ask
+    /// for TLS can still connect in plain text; require `sslmode=require` (or mutual TLS,
+    /// see [`crate::tls`]) on the client side to rule that out.
+    pub fn with_tls(mut self, tls: &TlsConfig) -> std::io::Result<Self> {
+        self.tls = Some(tls.acceptor()?);
+        Ok(self)
+    }
+
+    /// Require a valid credential as the password of every connection.
+    pub fn with_auth(self, auth: Arc<Authenticator>) -> Self {
+        let backend = IglooPgBackend { auth: Some(auth), ..(*self.backend).clone() };
+        Self { backend: Arc::new(backend), ..self }
+    }
+
+    /// Record every statement in the audit log.
+    pub fn with_audit(self, auditor: Arc<Auditor>) -> Self {
+        let backend = IglooPgBackend { audit: Some(auditor), ..(*self.backend).clone() };
+        Self { backend: Arc::new(backend), ..self }
+    }
+
+    /// Enforce per-principal rate limits and quotas on statements.
+    pub fn with_quotas(self, quotas: Arc<QuotaLimiter>) -> Self {
+        let backend = IglooPgBackend { quotas: Some(quotas), ..(*self.backend).clone() };
+        Self { backend: Arc::new(backend), ..self }
+    }
+}
+
+impl PgWireServerHandlers for IglooPgServer {
+    type StartupHandler = IglooPgBackend;
+    type SimpleQueryHandler = IglooPgBackend;
+    type ExtendedQueryHandler = IglooPgBackend;
+    type CopyHandler = NoopCopyHandler;
+    type ErrorHandler = NoopErrorHandler;
+
+    fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
+        self.backend.clone()
+    }
+
+    fn extended_query_handler(&self) -> Arc<Self::ExtendedQueryHandler> {
+        self.backend.clone()
+    }
+
+    fn startup_handler(&self) -> Arc<Self::StartupHandler> {
+        self.backend.clone()
+    }
+
+    fn copy_handler(&self) -> Arc<Self::CopyHandler> {
+        Arc::new(NoopCopyHandler)
+    }
+
+    fn error_handler(&self) -> Arc<Self::ErrorHandler> {
+        Arc::new(NoopErrorHandler)
+    }
+}
+
+/// Accept PostgreSQL connections on `listener` until the task is dropped.
+pub async fn serve(listener: TcpListener, engine: Arc<QueryEngine>) -> std::io::Result<()> {
+    serve_with(listener, IglooPgServer::new(engine)).await
+}
+
+/// Like [`serve`], for a server configured with e.g. [`IglooPgServer::with_auth`].
+pub async fn serve_with(listener: TcpListener, server: IglooPgServer) -> std::io::Result<()> {
+    let tls = server.tls.clone();
+    let server = Arc::new(server);
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let (server, tls) = (server.clone(), tls.clone());
+        tokio::spawn(async move {
+            if let Err(e) = pgwire::tokio::process_socket(socket, tls, server).await {
+                tracing::warn!(error = %e, "pgwire connection error");
+            }
+        });
+    }
+}
+
+fn parse(sql: &str) -> PgWireResult<Vec<Statement>> {
+    DFParser::parse_sql_with_dialect(sql, &PostgreSqlDialect {})
+        .map(Vec::from)
+        .map_err(datafusion_error_to_pg)
+}
+
+/// Command tag for statements that don't return rows; `None` for queries.
+fn command_tag(plan: &LogicalPlan) -> Option<&'static str> {
+    match plan {
+        LogicalPlan::Dml(dml) => Some(match dml.op {
+            WriteOp::Insert(_) => "INSERT",
+            WriteOp::Update => "UPDATE",
+            WriteOp::Delete => "DELETE",
+            WriteOp::Ctas => "SELECT",
+        }),
+        LogicalPlan::Ddl(ddl) => Some(match ddl {
+            DdlStatement::CreateExternalTable(_) | DdlStatement::CreateMemoryTable(_) => {
+                "CREATE TABLE"
+            }
+            DdlStatement::CreateView(_) => "CREATE VIEW",
+            DdlStatement::CreateCatalogSchema(_) => "CREATE SCHEMA",
+            DdlStatement::CreateCatalog(_) => "CREATE DATABASE",
+            DdlStatement::CreateIndex(_) => "CREATE INDEX",
+            DdlStatement::DropTable(_) => "DROP TABLE",
+            DdlStatement::DropView(_) => "DROP VIEW",
+            DdlStatement::DropCatalogSchema(_) => "DROP SCHEMA",
+            DdlStatement::CreateFunction(_) => "CREATE FUNCTION",
+            DdlStatement::DropFunction(_) => "DROP FUNCTION",
+        }),
+        LogicalPlan::Statement(statement) => match statement {
+            PlanStatement::TransactionStart(_) => Some("BEGIN"),
+            PlanStatement::TransactionEnd(_) => Some("COMMIT"),
+            PlanStatement::SetVariable(_) => Some("SET"),
+            PlanStatement::Prepare(_) => Some("PREPARE"),
+            PlanStatement::Deallocate(_) => Some("DEALLOCATE"),
+            PlanStatement::Execute(_) => None,
+        },
+        LogicalPlan::Copy(_) => Some("COPY"),
+        _ => None,
+    }
+}
+
+async fn send_diagnostics<C>(client: &mut C, df: &DataFrame) -> PgWireResult<()>
+where
+    C: Sink<PgWireBackendMessage> + Unpin + Send,
+    PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+{
+    let plan = df.clone().into_optimized_plan().map_err(datafusion_error_to_pg)?;
+    for diagnostic in inspect_plan(&plan).map_err(datafusion_error_to_pg)? {
+        let severity = match diagnostic.severity {
+            Severity::Notice => "NOTICE",
+            Severity::Warning => "WARNING",
+        };
+        let info = ErrorInfo::new(severity.to_string(), "01000".to_string(), diagnostic.message);
+        client.send(PgWireBackendMessage::NoticeResponse(NoticeResponse::from(info))).await?;
+    }
+    Ok(())
+}
+
+/// The pg type used to send values of an Arrow type. Types without a native mapping
+/// are sent as `TEXT` using Arrow's display format.
+pub fn pg_type(data_type: &DataType) -> Type {
+    match data_type {
+        DataType::Null => Type::UNKNOWN,
+        DataType::Boolean => Type::BOOL,
+        DataType::Int8 | DataType::UInt8 | DataType::Int16 => Type::INT2,
+        DataType::UInt16 | DataType::Int32 => Type::INT4,
+        DataType::UInt32 | DataType::Int64 | DataType::UInt64 => Type::INT8,
+        DataType::Float16 | DataType::Float32 => Type::FLOAT4,
+        DataType::Float64 => Type::FLOAT8,
+        DataType::Decimal128(_, _) => Type::NUMERIC,
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => Type::VARCHAR,
+        DataType::Binary | DataType::LargeBinary | DataType::BinaryView => Type::BYTEA,
+        DataType::Date32 | DataType::Date64 => Type::DATE,
+        DataType::Timestamp(_, None) => Type::TIMESTAMP,
+        DataType::Timestamp(_, Some(_)) => Type::TIMESTAMPTZ,
+        DataType::Dictionary(_, value) => pg_type(value),
+        _ => Type::TEXT,
+    }
+}
+
+fn schema_to_fields(schema: &Schema, format: &Format) -> Vec<FieldInfo> {
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            FieldInfo::new(
+                field.name().clone(),
+                None,
+                None,
+                pg_type(field.data_type()),
+                format.format_for(idx),
+            )
+        })
+        .collect()
+}
+
+fn result_fields(plan: &LogicalPlan, format: &Format) -> Vec<FieldInfo> {
+    match command_tag(plan) {
+        Some(_) => vec![],
+        None => schema_to_fields(plan.schema().as_arrow(), format),
+    }
+}
+
+fn encode_batch(batch: &RecordBatch, fields: &Arc<Vec<FieldInfo>>) -> PgWireResult<Vec<DataRow>> {
+    // Dictionary-encoded columns are sent as their values.
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| match column.data_type() {
+            DataType::Dictionary(_, value) => cast(column, value),
+            _ => Ok(Arc::clone(column)),
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| datafusion_error_to_pg(e.into()))?;
+    let options = FormatOptions::default();
+    let formatters = columns
+        .iter()
+        .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| datafusion_error_to_pg(e.into()))?;
+
+    let mut rows = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows() {
+        let mut encoder = DataRowEncoder::new(fields.clone());
+        for (column, formatter) in columns.iter().zip(&formatters) {
+            encode_value(&mut encoder, column.as_ref(), formatter, row)?;
+        }
+        rows.push(encoder.finish()?);
+    }
+    Ok(rows)
+}
+
+/// Encode `array[row]` as the pg type chosen by [`pg_type`].
+fn encode_value(
+    encoder: &mut DataRowEncoder,
+    array: &dyn Array,
+    formatter: &ArrayFormatter,
+    row: usize,
+) -> PgWireResult<()> {
+    if array.is_null(row) {
+        return encoder.encode_field(&None::<i8>);
+    }
+    match array.data_type() {
+        DataType::Boolean => encoder.encode_field(&array.as_boolean().value(row)),
+        DataType::Int8 => {
+            encoder.encode_field(&(array.as_primitive::<Int8Type>().value(row) as i16))
+        }
+        DataType::UInt8 => {
+            encoder.encode_field(&(array.as_primitive::<UInt8Type>().value(row) as i16))
+        }
+        DataType::Int16 => encoder.encode_field(&array.as_primitive::<Int16Type>().value(row)),
+        DataType::UInt16 => {
+            encoder.encode_field(&(array.as_primitive::<UInt16Type>().value(row) as i32))
+        }
+        DataType::Int32 => encoder.encode_field(&array.as_primitive::<Int32Type>().value(row)),
+        DataType::UInt32 => {
+            encoder.encode_field(&(array.as_primitive::<UInt32Type>().value(row) as i64))
+        }
+        DataType::Int64 => encoder.encode_field(&array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt64 => {
+            let value = array.as_primitive::<UInt64Type>().value(row);
+            let value = i64::try_from(value).map_err(|_| {
+                user_error("22003", format!("value {} is out of range for type bigint", value))
+            })?;
+            encoder.encode_field(&value)
+        }
+        DataType::Float16 => {
+            encoder.encode_field(&array.as_primitive::<Float16Type>().value(row).to_f32())
+        }
+        DataType::Float32 => encoder.encode_field(&array.as_primitive::<Float32Type>().value(row)),
+        DataType::Float64 => encoder.encode_field(&array.as_primitive::<Float64Type>().value(row)),
+        DataType::Decimal128(_, scale) => {
+            let value = array.as_primitive::<Decimal128Type>().value(row);
+            let decimal = Decimal::try_from_i128_with_scale(value, *scale as u32)
+                .map_err(|e| user_error("22003", e.to_string()))?;
+            encoder.encode_field(&decimal)
+        }
+        DataType::Utf8 => encoder.encode_field(&array.as_string::<i32>().value(row)),
+        DataType::LargeUtf8 => encoder.encode_field(&array.as_string::<i64>().value(row)),
+        DataType::Utf8View => encoder.encode_field(&array.as_string_view().value(row)),
+        DataType::Binary => encoder.encode_field(&array.as_binary::<i32>().value(row)),
+        DataType::LargeBinary => encoder.encode_field(&array.as_binary::<i64>().value(row)),
+        DataType::BinaryView => encoder.encode_field(&array.as_binary_view().value(row)),
+        DataType::Date32 => {
+            encoder.encode_field(&array.as_primitive::<Date32Type>().value_as_date(row))
+        }
+        DataType::Date64 => {
+            encoder.encode_field(&array.as_primitive::<Date64Type>().value_as_date(row))
+        }
+        DataType::Timestamp(unit, tz) => {
+            let datetime = match unit {
+                TimeUnit::Second => {
+                    array.as_primitive::<TimestampSecondType>().value_as_datetime(row)
+                }
+                TimeUnit::Millisecond => {
+                    array.as_primitive::<TimestampMillisecondType>().value_as_datetime(row)
+                }
+                TimeUnit::Microsecond => {
+                    array.as_primitive::<TimestampMicrosecondType>().value_as_datetime(row)
+                }
+                TimeUnit::Nanosecond => {
+                    array.as_primitive::<TimestampNanosecondType>().value_as_datetime(row)
+                }
+            };
+            match tz {
+                // Arrow stores zoned timestamps as UTC instants.
+                Some(_) => encoder.encode_field(&datetime.map(|dt| dt.and_utc())),
+                None => encoder.encode_field(&datetime),
+            }
+        }
+        _ => encoder.encode_field(&formatter.value(row).to_string()),
+    }
+}
+
+/// Resolve each placeholder's pg type (as declared by the client, or inferred from the
+/// plan) together with the Arrow type the plan expects, if known.
+fn parameter_types(
+    plan: &LogicalPlan,
+    declared: &[Type],
+) -> PgWireResult<Vec<(Type, Option<DataType>)>> {
+    let inferred = plan.get_parameter_types().map_err(datafusion_error_to_pg)?;
+    let count = inferred
+        .keys()
+        .filter_map(|id| id.strip_prefix('$')?.parse::<usize>().ok())
+        .max()
+        .unwrap_or(0)
+        .max(declared.len());
+    Ok((1..=count)
+        .map(|n| {
+            let data_type = inferred.get(&format!("${}", n)).cloned().flatten();
+            let pg = match declared.get(n - 1) {
+                Some(pg) if *pg != Type::UNKNOWN => pg.clone(),
+                _ => data_type.as_ref().map(pg_type).unwrap_or(Type::TEXT),
+            };
+            (pg, data_type)
+        })
+        .collect())
+}
+
+fn parameter_values(
+    portal: &Portal<Statement>,
+    types: &[(Type, Option<DataType>)],
+) -> PgWireResult<ParamValues> {
+    if portal.parameter_len() != types.len() {
+        return Err(user_error(
+            "08P01",
+            format!("expected {} parameters, got {}", types.len(), portal.parameter_len()),
+        ));
+    }
+    let mut values = Vec::with_capacity(types.len());
+    for (idx, (pg, data_type)) in types.iter().enumerate() {
+        let value = if portal.parameter_format.is_text(idx) {
+            let text = portal.parameters[idx]
+                .as_ref()
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+            ScalarValue::Utf8(text)
+        } else {
+            binary_parameter(portal, idx, pg)?
+        };
+        let value = match data_type {
+            Some(data_type) if value.data_type() != *data_type => {
+                value.cast_to(data_type).map_err(datafusion_error_to_pg)?
+            }
+            _ => value,
+        };
+        values.push(value);
+    }
+    Ok(ParamValues::List(values))
+}
+
+fn binary_parameter(
+    portal: &Portal<Statement>,
+    idx: usize,
+    pg: &Type,
+) -> PgWireResult<ScalarValue> {
+    Ok(match *pg {
+        Type::BOOL => ScalarValue::Boolean(portal.parameter::<bool>(idx, pg)?),
+        Type::INT2 => ScalarValue::Int16(portal.parameter::<i16>(idx, pg)?),
+        Type::INT4 => ScalarValue::Int32(portal.parameter::<i32>(idx, pg)?),
+        Type::INT8 => ScalarValue::Int64(portal.parameter::<i64>(idx, pg)?),
+        Type::FLOAT4 => ScalarValue::Float32(portal.parameter::<f32>(idx, pg)?),
+        Type::FLOAT8 => ScalarValue::Float64(portal.parameter::<f64>(idx, pg)?),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => {
+            ScalarValue::Utf8(portal.parameter::<String>(idx, pg)?)
+        }
+        Type::BYTEA => ScalarValue::Binary(portal.parameter::<Vec<u8>>(idx, pg)?),
+        _ => {
+            return Err(user_error(
+                "0A000",
+                format!("binary parameters of type {} are not supported", pg.name()),
+            ))
+        }
+    })
+}
+
+/// The session variables saved in `client`'s metadata by earlier `SET` statements.
+fn session_vars<C: ClientInfo>(client: &C) -> SessionVars {
+    let mut vars = SessionVars::new();
+    for (key, value) in client.metadata() {
+        if let Some(name) = key.strip_prefix(SESSION_METADATA_PREFIX) {
+            // Validated when it was set.
+            let _ = vars.set(name, value);
+        }
+    }
+    vars
+}
+
+fn user_error(code: &str, message: impl Into<String>) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_string(),
+        code.to_string(),
+        redact(&message.into()),
+    )))
+}
+
+fn datafusion_error_to_pg(e: DataFusionError) -> PgWireError {
+    // Planner errors may arrive wrapped (e.g. with a diagnostic); classify by the root.
+    let code = match e.find_root() {
+        DataFusionError::SQL(..) => "42601",
+        DataFusionError::Plan(message) if message.contains("not found") => "42P01",
+        DataFusionError::Plan(_) | DataFusionError::SchemaError(..) => "42000",
+        DataFusionError::NotImplemented(_) => "0A000",
+        DataFusionError::ResourcesExhausted(_) => "53000",
+        _ => "XX000",
+    };
+    let error = ApiError::from(&e);
+    let mut info = ErrorInfo::new("ERROR".to_string(), code.to_string(), error.message);
+    info.detail = error.detail;
+    info.hint = error.hint;
+    PgWireError::UserError(Box::new(info))
+}
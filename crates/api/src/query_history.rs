@@ -0,0 +1,281 @@
+//! Query history.
+//!
+//! A [`QueryHistory`] keeps a [`QueryRecord`] of every completed statement: its SQL,
+//! who ran it, when it started and finished, how many rows it returned, how many
+//! bytes it scanned, whether its result came from a cache, and how it ended. It is a
+//! read-only table, registered by the coordinator as `igloo.system.query_history`
+//! (see [`QueryEngine::register_system_table`]), so a workload can be analyzed with
+//! SQL:
+//!
+//! ```sql
+//! SELECT principal, count(*), sum(scanned_bytes)
+//! FROM igloo.system.query_history
+//! WHERE outcome = 'ok'
+//! GROUP BY principal
+//! ```
+//!
+//! The most recent [`DEFAULT_MAX_QUERIES`] records are kept in memory. A history
+//! opened on a file ([`QueryHistory::open`]) also appends each record there as a JSON
+//! line and reloads the most recent records when reopened, so it survives restarts.
+//!
+//! The history hangs off an [`Auditor`](crate::audit::Auditor) (see
+//! [`Auditor::with_query_history`](crate::audit::Auditor::with_query_history)),
+//! which sees every statement a frontend executes.
+//!
+//! [`QueryEngine::register_system_table`]: igloo_engine::QueryEngine::register_system_table
+
+use crate::audit::Outcome;
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, BooleanArray, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{MemTable, Session, TableProvider};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::{Expr, TableType};
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Records kept in memory unless configured otherwise.
+pub const DEFAULT_MAX_QUERIES: usize = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryRecord {
+    /// The `query_id` of the statement's log lines.
+    pub query_id: String,
+    /// Start of execution, in milliseconds since the Unix epoch.
+    pub started_ms: u64,
+    /// When the statement finished (or was cancelled), in milliseconds since the Unix
+    /// epoch.
+    pub finished_ms: u64,
+    pub principal: Option<String>,
+    /// `http`, `websocket`, `pgwire`, `flight` or `flight_sql`.
+    pub frontend: String,
+    pub sql: Option<String>,
+    /// Rows returned (or affected, for DML), when known.
+    pub rows: Option<u64>,
+    /// Bytes read from sources, if the statement got as far as running a plan.
+    pub scanned_bytes: Option<u64>,
+    /// Whether the result was served from a cache rather than computed.
+    pub cache_hit: bool,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+}
+
+impl QueryRecord {
+    pub fn duration_ms(&self) -> u64 {
+        self.finished_ms.saturating_sub(self.started_ms)
+    }
+}
+
+/// Completed statements as a table, see the [module docs](self).
+#[derive(Debug)]
+pub struct QueryHistory {
+    records: Mutex<VecDeque<QueryRecord>>,
+    max_queries: usize,
+    file: Option<Mutex<File>>,
+}
+
+impl Default for QueryHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl QueryHistory {
+    /// A history kept in memory only.
+    pub fn new() -> Self {
+        Self { records: Mutex::default(), max_queries: DEFAULT_MAX_QUERIES, file: None }
+    }
+
+    /// A history appended to `path`, starting with the records already there.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut records = VecDeque::new();
+        if path.exists() {
+            for line in BufReader::new(File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                records.push_back(serde_json::from_str(&line)?);
+                if records.len() > DEFAULT_MAX_QUERIES {
+                    records.pop_front();
+                }
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            records: Mutex::new(records),
+            max_queries: DEFAULT_MAX_QUERIES,
+            file: Some(Mutex::new(file)),
+        })
+    }
+
+    /// Keep at most `max` records in memory, dropping the oldest first. The file of a
+    /// history opened on one keeps them all.
+    pub fn with_max_queries(self, max: usize) -> Self {
+        let mut records = self.records.into_inner().expect("query history lock poisoned");
+        while records.len() > max {
+            records.pop_front();
+        }
+        Self { records: Mutex::new(records), max_queries: max, file: self.file }
+    }
+
+    /// The records kept in memory, oldest first.
+    pub fn records(&self) -> Vec<QueryRecord> {
+        self.records.lock().expect("query history lock poisoned").iter().cloned().collect()
+    }
+
+    pub(crate) fn record(&self, record: QueryRecord) -> io::Result<()> {
+        if let Some(file) = &self.file {
+            let mut line = serde_json::to_vec(&record)?;
+            line.push(b'\n');
+            file.lock().expect("query history file lock poisoned").write_all(&line)?;
+        }
+        let mut records = self.records.lock().expect("query history lock poisoned");
+        records.push_back(record);
+        while records.len() > self.max_queries {
+            records.pop_front();
+        }
+        Ok(())
+    }
+
+    fn to_batch(&self) -> DataFusionResult<RecordBatch> {
+        let records = self.records();
+        let strings = |f: fn(&QueryRecord) -> Option<&str>| -> ArrayRef {
+            Arc::new(records.iter().map(f).collect::<StringArray>())
+        };
+        let timestamps = |f: fn(&QueryRecord) -> u64| -> ArrayRef {
+            Arc::new(
+                records
+                    .iter()
+                    .map(|r| Some(f(r) as i64))
+                    .collect::<TimestampMillisecondArray>()
+                    .with_timezone("+00:00"),
+            )
+        };
+        let columns: Vec<ArrayRef> = vec![
+            strings(|r| Some(&r.query_id)),
+            timestamps(|r| r.started_ms),
+            timestamps(|r| r.finished_ms),
+            Arc::new(records.iter().map(|r| Some(r.duration_ms())).collect::<UInt64Array>()),
+            strings(|r| r.principal.as_deref()),
+            strings(|r| Some(&r.frontend)),
+            strings(|r| r.sql.as_deref()),
+            Arc::new(records.iter().map(|r| r.rows).collect::<UInt64Array>()),
+            Arc::new(records.iter().map(|r| r.scanned_bytes).collect::<UInt64Array>()),
+            Arc::new(records.iter().map(|r| Some(r.cache_hit)).collect::<BooleanArray>()),
+            strings(|r| Some(r.outcome.as_str())),
+            strings(|r| r.error.as_deref()),
+        ];
+        Ok(RecordBatch::try_new(self.schema(), columns)?)
+    }
+}
+
+#[async_trait]
+impl TableProvider for QueryHistory {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let timestamp = DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into()));
+        Arc::new(Schema::new(vec![
+            Field::new("query_id", DataType::Utf8, false),
+            Field::new("started", timestamp.clone(), false),
+            Field::new("finished", timestamp, false),
+            Field::new("duration_ms", DataType::UInt64, false),
+            Field::new("principal", DataType::Utf8, true),
+            Field::new("frontend", DataType::Utf8, false),
+            Field::new("sql", DataType::Utf8, true),
+            Field::new("rows", DataType::UInt64, true),
+            Field::new("scanned_bytes", DataType::UInt64, true),
+            Field::new("cache_hit", DataType::Boolean, false),
+            Field::new("outcome", DataType::Utf8, false),
+            Field::new("error", DataType::Utf8, true),
+        ]))
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let snapshot = MemTable::try_new(self.schema(), vec![vec![self.to_batch()?]])?;
+        snapshot.scan(state, projection, filters, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Auditor;
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::UInt64Type;
+    use igloo_engine::QueryEngine;
+
+    #[tokio::test]
+    async fn test_statements_are_queryable_as_a_system_table() {
+        let engine = QueryEngine::new();
+        let history = Arc::new(QueryHistory::new());
+        engine.register_system_table("query_history", history.clone()).unwrap();
+        let auditor = Auditor::default().with_query_history(history.clone());
+
+        let sql = "SELECT * FROM range(5)";
+        let result = engine.query(sql).await.unwrap();
+        let mut entry = auditor.start("http", Some("alice"), None, sql);
+        entry.set_plan(&result.plan);
+        entry.succeeded(Some(5));
+        let mut entry = auditor.start("pgwire", Some("bob"), None, "SELECT * FROM missing");
+        assert!(entry.check(engine.query("SELECT * FROM missing").await).is_err());
+
+        let records = history.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].rows, Some(5));
+        assert!(records[0].scanned_bytes.is_some());
+        assert_eq!(records[1].outcome, Outcome::Error);
+        assert_eq!(records[1].scanned_bytes, None);
+
+        let result = engine
+            .query(
+                "SELECT principal, rows FROM igloo.system.query_history \
+                 WHERE outcome = 'ok' AND NOT cache_hit",
+            )
+            .await
+            .unwrap();
+        let batch = &result.batches[0];
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(batch.column(0).as_string::<i32>().value(0), "alice");
+        assert_eq!(batch.column(1).as_primitive::<UInt64Type>().value(0), 5);
+    }
+
+    #[test]
+    fn test_file_history_survives_reopening() {
+        let path = std::env::temp_dir().join(format!("igloo-history-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let auditor =
+            Auditor::default().with_query_history(Arc::new(QueryHistory::open(&path).unwrap()));
+        for sql in ["SELECT 1", "SELECT 2", "SELECT 3"] {
+            auditor.start("http", None, None, sql).succeeded(Some(1));
+        }
+
+        let history = QueryHistory::open(&path).unwrap().with_max_queries(2);
+        std::fs::remove_file(&path).unwrap();
+        let sql: Vec<_> = history.records().into_iter().map(|r| r.sql.unwrap()).collect();
+        assert_eq!(sql, ["SELECT 2", "SELECT 3"]);
+    }
+}
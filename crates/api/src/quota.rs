@@ -0,0 +1,419 @@
+//! Per-client rate limits and quotas.
+//!
+//! A [`QuotaLimiter`] tracks each principal (or API key's principal) separately against
+//! its [`Quotas`]: queries per minute (a token bucket refilled continuously), queries
+//! running at once, and bytes scanned per UTC day. Unauthenticated clients share one
+//! allowance. Frontends take a [`QuotaPermit`] before running a statement; a refused
+//! one is a [`QuotaError`], reported as a structured `quota_exceeded` error.
+//!
+//! Scanned bytes are charged once a statement finishes, so the statement that crosses
+//! the daily quota completes and the ones after it are refused.
+//!
+//! With an [`AdmissionQueue`] ([`QuotaLimiter::with_admission`]) admitted statements
+//! also wait for a slot to run in ([`QuotaPermit::admit`]), by priority class. Each
+//! frontend admits queries as `interactive` unless their session sets `priority`,
+//! asynchronous jobs as `batch`; principals given a class with
+//! [`QuotaLimiter::with_priority`] never run above it.
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::{RecordBatchStream, SendableRecordBatchStream};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::Stream;
+use igloo_common::error::ApiError;
+use igloo_engine::admission::{Admission, AdmissionQueue, Priority};
+use igloo_engine::diagnostics::scanned_bytes;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Limits for one client. Every limit is off unless set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    queries_per_minute: Option<u32>,
+    concurrent_queries: Option<u32>,
+    scanned_bytes_per_day: Option<u64>,
+}
+
+impl Quotas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bursts of up to `limit` queries, refilled at `limit` per minute.
+    pub fn with_queries_per_minute(mut self, limit: u32) -> Self {
+        self.queries_per_minute = Some(limit);
+        self
+    }
+
+    pub fn with_concurrent_queries(mut self, limit: u32) -> Self {
+        self.concurrent_queries = Some(limit);
+        self
+    }
+
+    pub fn with_scanned_bytes_per_day(mut self, limit: u64) -> Self {
+        self.scanned_bytes_per_day = Some(limit);
+        self
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum QuotaError {
+    #[error("quota exceeded: more than {limit} queries per minute")]
+    QueriesPerMinute { limit: u32, retry_after: Duration },
+    #[error("quota exceeded: {limit} queries already running")]
+    ConcurrentQueries { limit: u32 },
+    #[error("quota exceeded: {limit} bytes scanned today")]
+    ScannedBytes { limit: u64, retry_after: Duration },
+}
+
+impl QuotaError {
+    /// Which quota was exceeded.
+    pub fn quota(&self) -> &'static str {
+        match self {
+            QuotaError::QueriesPerMinute { .. } => "queries_per_minute",
+            QuotaError::ConcurrentQueries { .. } => "concurrent_queries",
+            QuotaError::ScannedBytes { .. } => "scanned_bytes_per_day",
+        }
+    }
+
+    /// How long until the quota allows another query, when that is known.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            QuotaError::QueriesPerMinute { retry_after, .. }
+            | QuotaError::ScannedBytes { retry_after, .. } => Some(*retry_after),
+            QuotaError::ConcurrentQueries { .. } => None,
+        }
+    }
+
+    pub fn to_api_error(&self) -> ApiError {
+        let hint = match self.retry_after() {
+            Some(wait) => format!("retry in {} seconds", wait.as_secs().max(1)),
+            None => "retry once a running query has finished".to_string(),
+        };
+        ApiError {
+            code: "quota_exceeded",
+            message: self.to_string(),
+            detail: Some(self.quota().to_string()),
+            hint: Some(hint),
+            retryable: true,
+        }
+    }
+}
+
+/// Enforces [`Quotas`] per principal.
+pub struct QuotaLimiter {
+    defaults: RwLock<Quotas>,
+    overrides: HashMap<String, Quotas>,
+    clients: Mutex<HashMap<String, Arc<Client>>>,
+    admission: Option<Arc<AdmissionQueue>>,
+    priorities: HashMap<String, Priority>,
+}
+
+impl QuotaLimiter {
+    /// Apply `defaults` to every client without an override.
+    pub fn new(defaults: Quotas) -> Self {
+        Self {
+            defaults: RwLock::new(defaults),
+            overrides: HashMap::new(),
+            clients: Mutex::new(HashMap::new()),
+            admission: None,
+            priorities: HashMap::new(),
+        }
+    }
+
+    /// Give the principal with this subject its own limits.
+    pub fn with_client(mut self, subject: impl Into<String>, quotas: Quotas) -> Self {
+        self.overrides.insert(subject.into(), quotas);
+        self
+    }
+
+    /// Run admitted statements in slots of `queue`, see [`QuotaPermit::admit`].
+    pub fn with_admission(mut self, queue: AdmissionQueue) -> Self {
+        self.admission = Some(Arc::new(queue));
+        self
+    }
+
+    /// Admit the statements of the principal with this subject in class `priority`
+    /// or a lower one.
+    pub fn with_priority(mut self, subject: impl Into<String>, priority: Priority) -> Self {
+        self.priorities.insert(subject.into(), priority);
+        self
+    }
+
+    /// Apply `defaults` from now on, to clients without an override. What clients used
+    /// up so far (running statements, tokens, bytes scanned today) still counts.
+    pub fn set_quotas(&self, defaults: Quotas) {
+        *self.defaults.write().expect("quota lock poisoned") = defaults;
+        let clients = self.clients.lock().expect("quota lock poisoned");
+        for (key, client) in clients.iter() {
+            if !self.overrides.contains_key(key) {
+                client.set_quotas(defaults);
+            }
+        }
+    }
+
+    /// Admit one statement for `principal`, or explain which quota refuses it.
+    pub fn acquire(&self, principal: Option<&str>) -> Result<QuotaPermit, QuotaError> {
+        let key = principal.unwrap_or_default();
+        let client = {
+            let mut clients = self.clients.lock().expect("quota lock poisoned");
+            let defaults = *self.defaults.read().expect("quota lock poisoned");
+            let quotas = self.overrides.get(key).copied().unwrap_or(defaults);
+            clients.entry(key.to_string()).or_insert_with(|| Arc::new(Client::new(quotas))).clone()
+        };
+        client.admit()?;
+        let queue = self.admission.as_ref().map(|queue| {
+            let floor = self.priorities.get(key).copied().unwrap_or_default();
+            (Arc::clone(queue), floor)
+        });
+        Ok(QuotaPermit { client: Some(client), plan: None, queue, admission: None })
+    }
+}
+
+/// Admit one statement if rate limiting is enabled.
+pub fn acquire(
+    limiter: Option<&QuotaLimiter>,
+    principal: Option<&str>,
+) -> Result<QuotaPermit, QuotaError> {
+    match limiter {
+        Some(limiter) => limiter.acquire(principal),
+        None => Ok(QuotaPermit { client: None, plan: None, queue: None, admission: None }),
+    }
+}
+
+struct Client {
+    quotas: RwLock<Quotas>,
+    usage: Mutex<Usage>,
+}
+
+struct Usage {
+    tokens: f64,
+    refilled: Instant,
+    running: u32,
+    day: u64,
+    scanned: u64,
+}
+
+impl Client {
+    fn new(quotas: Quotas) -> Self {
+        let usage = Usage {
+            tokens: quotas.queries_per_minute.unwrap_or_default() as f64,
+            refilled: Instant::now(),
+            running: 0,
+            day: 0,
+            scanned: 0,
+        };
+        Self { quotas: RwLock::new(quotas), usage: Mutex::new(usage) }
+    }
+
+    fn set_quotas(&self, quotas: Quotas) {
+        *self.quotas.write().expect("quota lock poisoned") = quotas;
+    }
+
+    fn admit(&self) -> Result<(), QuotaError> {
+        let quotas = *self.quotas.read().expect("quota lock poisoned");
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        let (day, seconds_into_day) = today();
+        if usage.day != day {
+            usage.day = day;
+            usage.scanned = 0;
+        }
+        if let Some(limit) = quotas.scanned_bytes_per_day {
+            if usage.scanned >= limit {
+                let retry_after = Duration::from_secs(SECONDS_PER_DAY - seconds_into_day);
+                return Err(QuotaError::ScannedBytes { limit, retry_after });
+            }
+        }
+        if let Some(limit) = quotas.concurrent_queries {
+            if usage.running >= limit {
+                return Err(QuotaError::ConcurrentQueries { limit });
+            }
+        }
+        if let Some(limit) = quotas.queries_per_minute {
+            let per_second = limit as f64 / 60.0;
+            let now = Instant::now();
+            let refill = now.duration_since(usage.refilled).as_secs_f64() * per_second;
+            usage.tokens = (usage.tokens + refill).min(limit as f64);
+            usage.refilled = now;
+            if usage.tokens < 1.0 {
+                let retry_after = match limit {
+                    0 => Duration::from_secs(60),
+                    _ => Duration::from_secs_f64((1.0 - usage.tokens) / per_second),
+                };
+                return Err(QuotaError::QueriesPerMinute { limit, retry_after });
+            }
+            usage.tokens -= 1.0;
+        }
+        usage.running += 1;
+        Ok(())
+    }
+
+    fn charge(&self, scanned: u64) {
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        usage.scanned = usage.scanned.saturating_add(scanned);
+    }
+
+    fn release(&self, scanned: u64) {
+        let mut usage = self.usage.lock().expect("quota lock poisoned");
+        usage.running = usage.running.saturating_sub(1);
+        usage.scanned = usage.scanned.saturating_add(scanned);
+    }
+}
+
+/// The current UTC day number and the seconds elapsed in it.
+fn today() -> (u64, u64) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    (now / SECONDS_PER_DAY, now % SECONDS_PER_DAY)
+}
+
+/// A running statement's claim on its client's quotas. Dropping it frees the
+/// concurrency slot and charges the bytes the statement scanned.
+pub struct QuotaPermit {
+    client: Option<Arc<Client>>,
+    plan: Option<Arc<dyn ExecutionPlan>>,
+    /// The admission queue and the principal's highest priority.
+    queue: Option<(Arc<AdmissionQueue>, Priority)>,
+    admission: Option<Admission>,
+}
+
+impl QuotaPermit {
+    /// Wait for a slot to run the statement in, as class `priority` unless the
+    /// principal's is lower. Returns at once without an admission queue.
+    pub async fn admit(&mut self, priority: Priority) {
+        if let (Some((queue, floor)), None) = (&self.queue, &self.admission) {
+            self.admission = Some(queue.admit(priority.max(*floor)).await);
+        }
+    }
+
+    /// Charge `bytes` of scanning now, e.g. a finished
+    /// [`QueryResult::scanned_bytes`](igloo_engine::diagnostics::QueryResult::scanned_bytes).
+    pub fn charge(&mut self, bytes: u64) {
+        if let Some(client) = &self.client {
+            client.charge(bytes);
+        }
+    }
+
+    /// Charge the bytes `plan` has scanned when the permit is released.
+    pub fn track(&mut self, plan: Arc<dyn ExecutionPlan>) {
+        self.plan = Some(plan);
+    }
+
+    /// Hold the permit until `stream` is dropped.
+    pub fn wrap(self, stream: SendableRecordBatchStream) -> SendableRecordBatchStream {
+        if self.client.is_none() {
+            return stream;
+        }
+        Box::pin(PermitStream { inner: stream, _permit: self })
+    }
+}
+
+impl Drop for QuotaPermit {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            client.release(self.plan.as_ref().map(scanned_bytes).unwrap_or_default());
+        }
+    }
+}
+
+struct PermitStream {
+    inner: SendableRecordBatchStream,
+    _permit: QuotaPermit,
+}
+
+impl Stream for PermitStream {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl RecordBatchStream for PermitStream {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queries_per_minute() {
+        let limiter = QuotaLimiter::new(Quotas::new().with_queries_per_minute(2));
+        limiter.acquire(Some("alice")).unwrap();
+        limiter.acquire(Some("alice")).unwrap();
+        let err = limiter.acquire(Some("alice")).err().unwrap();
+        assert_eq!(err.quota(), "queries_per_minute");
+        assert!(err.retry_after().unwrap() <= Duration::from_secs(30));
+        // Each principal has its own bucket.
+        limiter.acquire(Some("bob")).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_queries() {
+        let limiter = QuotaLimiter::new(Quotas::new().with_concurrent_queries(1))
+            .with_client("etl", Quotas::new());
+        let permit = limiter.acquire(None).unwrap();
+        assert!(matches!(limiter.acquire(None), Err(QuotaError::ConcurrentQueries { limit: 1 })));
+        drop(permit);
+        let _permit = limiter.acquire(None).unwrap();
+        let _etl = (limiter.acquire(Some("etl")).unwrap(), limiter.acquire(Some("etl")).unwrap());
+    }
+
+    #[test]
+    fn test_changed_quotas_apply_to_clients() {
+        let limiter = QuotaLimiter::new(Quotas::new().with_concurrent_queries(1))
+            .with_client("etl", Quotas::new().with_concurrent_queries(1));
+        let _permits = (limiter.acquire(None).unwrap(), limiter.acquire(Some("etl")).unwrap());
+        assert!(limiter.acquire(None).is_err());
+        limiter.set_quotas(Quotas::new().with_concurrent_queries(2));
+        // The running statement still counts.
+        let _second = limiter.acquire(None).unwrap();
+        assert!(limiter.acquire(None).is_err());
+        limiter.acquire(Some("alice")).unwrap();
+        // Overrides stay.
+        assert!(limiter.acquire(Some("etl")).is_err());
+    }
+
+    #[test]
+    fn test_scanned_bytes_per_day() {
+        let limiter = QuotaLimiter::new(Quotas::new().with_scanned_bytes_per_day(1000));
+        limiter.acquire(Some("alice")).unwrap().charge(600);
+        limiter.acquire(Some("alice")).unwrap().charge(600);
+        let err = limiter.acquire(Some("alice")).err().unwrap();
+        assert!(matches!(err, QuotaError::ScannedBytes { limit: 1000, .. }));
+        let api_error = err.to_api_error();
+        assert_eq!(api_error.code, "quota_exceeded");
+        assert_eq!(api_error.detail.as_deref(), Some("scanned_bytes_per_day"));
+        assert!(api_error.retryable);
+    }
+
+    #[tokio::test]
+    async fn test_principals_are_admitted_at_most_at_their_priority() {
+        let queue = AdmissionQueue::new(1).with_weight(Priority::Batch, 1);
+        let limiter = QuotaLimiter::new(Quotas::new())
+            .with_admission(queue)
+            .with_priority("exports", Priority::Batch);
+        let mut running = limiter.acquire(Some("dashboard")).unwrap();
+        running.admit(Priority::Interactive).await;
+
+        let queue = Arc::clone(limiter.admission.as_ref().unwrap());
+        let mut export = limiter.acquire(Some("exports")).unwrap();
+        let waiting = tokio::spawn(async move { export.admit(Priority::Interactive).await });
+        while queue.queued(Priority::Batch) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert_eq!(queue.queued(Priority::Interactive), 0);
+        drop(running);
+        waiting.await.unwrap();
+        assert_eq!(queue.running(), 0);
+    }
+}
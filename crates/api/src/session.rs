@@ -0,0 +1,72 @@
+//! Session variables for the frontends whose requests share no connection.
+//!
+//! pgwire and WebSocket clients keep their [`SessionVars`] with their connection. HTTP
+//! requests and Flight calls are independent, so theirs live in a [`SessionStore`],
+//! keyed by the [`SESSION_HEADER`] the client sends with each request (any value it
+//! likes, e.g. a UUID). Sessions are scoped to the authenticated principal, so a
+//! session id cannot be used to change another client's settings, and are dropped
+//! after [`SESSION_IDLE_TIMEOUT`] without use.
+
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use igloo_engine::session::SessionVars;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// HTTP header / gRPC metadata key naming the client's session.
+pub const SESSION_HEADER: &str = "x-igloo-session";
+
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Default)]
+pub struct SessionStore {
+    sessions: Mutex<HashMap<(String, String), (SessionVars, Instant)>>,
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The variables of session `id` for `principal`; defaults without a session.
+    pub fn get(&self, principal: Option<&str>, id: Option<&str>) -> SessionVars {
+        let Some(id) = id else {
+            return SessionVars::default();
+        };
+        let mut sessions = self.sessions.lock().expect("session lock poisoned");
+        match sessions.get_mut(&key(principal, id)) {
+            Some((vars, last_used)) => {
+                *last_used = Instant::now();
+                vars.clone()
+            }
+            None => SessionVars::default(),
+        }
+    }
+
+    /// Apply `SET name = value` to session `id`, creating it if needed.
+    pub fn set(
+        &self,
+        principal: Option<&str>,
+        id: Option<&str>,
+        name: &str,
+        value: &str,
+    ) -> DataFusionResult<()> {
+        let id = id.ok_or_else(|| {
+            DataFusionError::Plan(format!(
+                "SET needs a session to apply to; send a {SESSION_HEADER} header"
+            ))
+        })?;
+        let mut sessions = self.sessions.lock().expect("session lock poisoned");
+        let now = Instant::now();
+        sessions.retain(|_, (_, last_used)| now.duration_since(*last_used) < SESSION_IDLE_TIMEOUT);
+        let key = key(principal, id);
+        let mut vars = sessions.get(&key).map(|(vars, _)| vars.clone()).unwrap_or_default();
+        vars.set(name, value)?;
+        sessions.insert(key, (vars, now));
+        Ok(())
+    }
+}
+
+fn key(principal: Option<&str>, id: &str) -> (String, String) {
+    (principal.unwrap_or_default().to_string(), id.to_string())
+}
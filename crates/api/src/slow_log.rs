@@ -0,0 +1,259 @@
+//! Slow query log.
+//!
+//! Statements running longer than a [`SlowQueryLog`]'s threshold are recorded with
+//! what it takes to find out why: their plan as `EXPLAIN ANALYZE` shows it, with the
+//! metrics of the run, and how long each source took (see
+//! [`source_timings`](igloo_engine::diagnostics::source_timings)), which in a federated
+//! query tells the slow source apart. Records go to a [`SlowQuerySink`]: a JSON lines
+//! file ([`FileSlowQuerySink`]) or a table queryable from SQL ([`TableSlowQuerySink`]).
+//!
+//! The log hangs off an [`Auditor`](crate::audit::Auditor) (see
+//! [`Auditor::with_slow_query_log`](crate::audit::Auditor::with_slow_query_log)),
+//! which sees every statement a frontend executes.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, ListBuilder, StringArray, StringBuilder, StructBuilder, TimestampMillisecondArray,
+    UInt64Array, UInt64Builder,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{MemTable, Session, TableProvider};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::{Expr, TableType};
+use datafusion::physical_plan::ExecutionPlan;
+use igloo_engine::diagnostics::SourceTiming;
+use serde::Serialize;
+use std::any::Any;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SlowQueryRecord {
+    /// Start of execution, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+    /// The `query_id` of the statement's log lines.
+    pub query_id: String,
+    pub principal: Option<String>,
+    /// `http`, `websocket`, `pgwire`, `flight` or `flight_sql`.
+    pub frontend: &'static str,
+    pub sql: Option<String>,
+    pub duration_ms: u64,
+    /// The executed plan with its metrics, if the statement got as far as running one.
+    pub plan: Option<String>,
+    pub sources: Vec<SourceTiming>,
+    pub error: Option<String>,
+}
+
+/// Where slow query records are written. Sinks only ever append.
+pub trait SlowQuerySink: Send + Sync {
+    fn write(&self, record: &SlowQueryRecord) -> io::Result<()>;
+}
+
+/// Records statements that run for at least a threshold, see the [module docs](self).
+pub struct SlowQueryLog {
+    threshold: Duration,
+    sink: Arc<dyn SlowQuerySink>,
+}
+
+impl SlowQueryLog {
+    pub fn new(threshold: Duration, sink: impl SlowQuerySink + 'static) -> Self {
+        Self { threshold, sink: Arc::new(sink) }
+    }
+
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    pub(crate) fn is_slow(&self, duration: Duration) -> bool {
+        duration >= self.threshold
+    }
+
+    pub(crate) fn write(&self, record: &SlowQueryRecord) -> io::Result<()> {
+        self.sink.write(record)
+    }
+}
+
+/// Appends records to a file, one JSON object per line.
+pub struct FileSlowQuerySink {
+    file: Mutex<File>,
+}
+
+impl FileSlowQuerySink {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl SlowQuerySink for FileSlowQuerySink {
+    fn write(&self, record: &SlowQueryRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.lock().expect("slow query file lock poisoned").write_all(&line)
+    }
+}
+
+/// Keeps records in memory and exposes them as a read-only table, e.g.
+/// `engine.register_table("slow_queries", Arc::new(sink.clone()))`.
+#[derive(Debug, Clone, Default)]
+pub struct TableSlowQuerySink {
+    records: Arc<Mutex<Vec<SlowQueryRecord>>>,
+}
+
+impl TableSlowQuerySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> Vec<SlowQueryRecord> {
+        self.records.lock().expect("slow query table lock poisoned").clone()
+    }
+
+    fn source_fields() -> Fields {
+        Fields::from(vec![
+            Field::new("source", DataType::Utf8, false),
+            Field::new("rows", DataType::UInt64, true),
+            Field::new("elapsed_ms", DataType::UInt64, true),
+        ])
+    }
+
+    fn to_batch(&self) -> DataFusionResult<RecordBatch> {
+        let records = self.records();
+        let source = StructBuilder::new(
+            Self::source_fields(),
+            vec![
+                Box::new(StringBuilder::new()),
+                Box::new(UInt64Builder::new()),
+                Box::new(UInt64Builder::new()),
+            ],
+        );
+        let mut sources = ListBuilder::new(source).with_field(Self::source_item());
+        for record in &records {
+            let timings = sources.values();
+            for timing in &record.sources {
+                timings.field_builder::<StringBuilder>(0).unwrap().append_value(&timing.source);
+                timings.field_builder::<UInt64Builder>(1).unwrap().append_option(timing.rows);
+                timings.field_builder::<UInt64Builder>(2).unwrap().append_option(timing.elapsed_ms);
+                timings.append(true);
+            }
+            sources.append(true);
+        }
+        let strings = |f: fn(&SlowQueryRecord) -> Option<&str>| -> ArrayRef {
+            Arc::new(records.iter().map(f).collect::<StringArray>())
+        };
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(
+                records
+                    .iter()
+                    .map(|r| Some(r.timestamp_ms as i64))
+                    .collect::<TimestampMillisecondArray>()
+                    .with_timezone("+00:00"),
+            ),
+            strings(|r| Some(&r.query_id)),
+            strings(|r| r.principal.as_deref()),
+            strings(|r| Some(r.frontend)),
+            strings(|r| r.sql.as_deref()),
+            Arc::new(records.iter().map(|r| Some(r.duration_ms)).collect::<UInt64Array>()),
+            strings(|r| r.plan.as_deref()),
+            Arc::new(sources.finish()),
+            strings(|r| r.error.as_deref()),
+        ];
+        Ok(RecordBatch::try_new(self.schema(), columns)?)
+    }
+
+    fn source_item() -> Arc<Field> {
+        Arc::new(Field::new("item", DataType::Struct(Self::source_fields()), false))
+    }
+}
+
+impl SlowQuerySink for TableSlowQuerySink {
+    fn write(&self, record: &SlowQueryRecord) -> io::Result<()> {
+        self.records.lock().expect("slow query table lock poisoned").push(record.clone());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TableProvider for TableSlowQuerySink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new(
+                "timestamp",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into())),
+                false,
+            ),
+            Field::new("query_id", DataType::Utf8, false),
+            Field::new("principal", DataType::Utf8, true),
+            Field::new("frontend", DataType::Utf8, false),
+            Field::new("sql", DataType::Utf8, true),
+            Field::new("duration_ms", DataType::UInt64, false),
+            Field::new("plan", DataType::Utf8, true),
+            Field::new("sources", DataType::List(Self::source_item()), false),
+            Field::new("error", DataType::Utf8, true),
+        ]))
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let snapshot = MemTable::try_new(self.schema(), vec![vec![self.to_batch()?]])?;
+        snapshot.scan(state, projection, filters, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::Auditor;
+    use igloo_engine::QueryEngine;
+
+    #[tokio::test]
+    async fn test_slow_statements_are_recorded_with_their_plan() {
+        let engine = QueryEngine::new();
+        let sink = TableSlowQuerySink::new();
+        let auditor =
+            Auditor::default().with_slow_query_log(SlowQueryLog::new(Duration::ZERO, sink.clone()));
+        let sql = "SELECT * FROM range(5)";
+        let result = engine.query(sql).await.unwrap();
+        let mut entry = auditor.start("http", Some("alice"), None, sql);
+        entry.set_plan(&result.plan);
+        entry.succeeded(Some(5));
+
+        let records = sink.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].principal.as_deref(), Some("alice"));
+        assert_eq!(records[0].sql.as_deref(), Some(sql));
+        assert!(records[0].plan.as_deref().unwrap().contains("output_rows=5"));
+        assert_eq!(records[0].sources.len(), 1);
+        assert_eq!(records[0].sources[0].rows, Some(5));
+
+        engine.register_table("slow_queries", Arc::new(sink)).unwrap();
+        let result = engine.query("SELECT frontend, sources FROM slow_queries").await.unwrap();
+        assert_eq!(result.batches[0].num_rows(), 1);
+    }
+
+    #[test]
+    fn test_fast_statements_are_not_recorded() {
+        let sink = TableSlowQuerySink::new();
+        let auditor = Auditor::default()
+            .with_slow_query_log(SlowQueryLog::new(Duration::from_secs(3600), sink.clone()));
+        auditor.start("pgwire", None, None, "SELECT 1").succeeded(None);
+        assert!(sink.records().is_empty());
+    }
+}
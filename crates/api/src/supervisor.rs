@@ -0,0 +1,348 @@
+//! Supervising the long-running parts of a server.
+//!
+//! A [`Supervisor`] runs each component of a long-running process (a server, a CDC
+//! pipeline, a periodic job) as a task of its own, started by a function it calls
+//! again to restart the component when it ends, as its [`RestartPolicy`] says:
+//!
+//! - [`RestartPolicy::Never`]: the component runs once;
+//! - [`RestartPolicy::OnFailure`]: it is restarted when it fails (returns an error or
+//!   panics), up to `max_restarts` times in a row, then left failed;
+//! - [`RestartPolicy::Always`]: it is restarted whenever it ends.
+//!
+//! Restarts wait a backoff growing with the failures in a row, reset once a component
+//! has run for a minute. [`Supervisor::statuses`] reports each component's state, its
+//! restarts and its latest error, served by the HTTP API at `/admin/status` (see
+//! [`HttpOptions::with_supervisor`](crate::http::HttpOptions::with_supervisor)).
+//!
+//! Components are handed a [`Stop`] signal, set by [`Supervisor::shutdown`], and end
+//! once it is: servers stop accepting connections, pipelines commit what they hold.
+
+use futures::FutureExt;
+use igloo_common::retry::RetryPolicy;
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+
+/// How long a component runs before its failures in a row are forgotten.
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+
+/// When to restart a component that ended, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartPolicy {
+    Never,
+    OnFailure { max_restarts: u32 },
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::OnFailure { .. } => "on_failure",
+            RestartPolicy::Always => "always",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentState {
+    Running,
+    /// Waiting out the backoff before it is restarted.
+    Restarting,
+    /// Ended, and not to be restarted, or stopped by the supervisor.
+    Stopped,
+    /// Failed, and not to be restarted.
+    Failed,
+}
+
+/// A component's state, as [`Supervisor::statuses`] reports it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentStatus {
+    pub name: String,
+    pub restart_policy: &'static str,
+    pub state: ComponentState,
+    /// Times it was restarted since the supervisor started it.
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// When it entered its state, in milliseconds since the Unix epoch.
+    pub since_ms: u64,
+}
+
+/// The signal to end that components are handed, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct Stop(watch::Receiver<bool>);
+
+impl Stop {
+    pub fn is_stopped(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once the supervisor shuts down.
+    pub async fn stopped(mut self) {
+        // The supervisor dropped counts as stopped too.
+        let _ = self.0.wait_for(|stopped| *stopped).await;
+    }
+
+    /// The signal as a channel, for components taking one.
+    pub fn receiver(&self) -> watch::Receiver<bool> {
+        self.0.clone()
+    }
+}
+
+/// Runs and restarts the components of a process, see the [module](self)
+/// documentation.
+pub struct Supervisor {
+    stop: watch::Sender<bool>,
+    backoff: RetryPolicy,
+    components: Mutex<Vec<Arc<Mutex<ComponentStatus>>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervisor").field("components", &self.statuses()).finish()
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        let backoff = RetryPolicy::default()
+            .with_initial_backoff(Duration::from_secs(1))
+            .with_max_backoff(Duration::from_secs(60));
+        Self {
+            stop: watch::channel(false).0,
+            backoff,
+            components: Mutex::default(),
+            tasks: Mutex::default(),
+        }
+    }
+
+    /// Wait as `backoff` says before restarts, the first one after a failure, the
+    /// second after two in a row...
+    pub fn with_backoff(mut self, backoff: RetryPolicy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Run the component `name`: `start` is called with the stop signal, and called
+    /// again whenever the future it returned ends and `policy` says to restart it.
+    pub fn spawn<F, Fut, E>(&self, name: impl Into<String>, policy: RestartPolicy, start: F)
+    where
+        F: Fn(Stop) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let name = name.into();
+        let status = Arc::new(Mutex::new(ComponentStatus {
+            name: name.clone(),
+            restart_policy: policy.name(),
+            state: ComponentState::Running,
+            restarts: 0,
+            last_error: None,
+            since_ms: now_ms(),
+        }));
+        let stop = Stop(self.stop.subscribe());
+        let backoff = self.backoff.clone();
+        let task = tokio::spawn({
+            let status = Arc::clone(&status);
+            async move {
+                let mut failures = 0;
+                loop {
+                    set_state(&status, ComponentState::Running, None);
+                    let started = Instant::now();
+                    let run = AssertUnwindSafe(start(stop.clone())).catch_unwind();
+                    let error = match run.await {
+                        Ok(Ok(())) => None,
+                        Ok(Err(e)) => Some(e.to_string()),
+                        Err(_) => Some("panicked".to_string()),
+                    };
+                    if stop.is_stopped() {
+                        set_state(&status, ComponentState::Stopped, error);
+                        return;
+                    }
+                    if started.elapsed() >= HEALTHY_AFTER {
+                        failures = 0;
+                    }
+                    let restart = match (&error, policy) {
+                        (_, RestartPolicy::Never) | (None, RestartPolicy::OnFailure { .. }) => {
+                            false
+                        }
+                        (Some(_), RestartPolicy::OnFailure { max_restarts }) => {
+                            failures < max_restarts
+                        }
+                        (_, RestartPolicy::Always) => true,
+                    };
+                    match (&error, restart) {
+                        (Some(e), true) => warn!(component = name, error = %e, "Restarting"),
+                        (Some(e), false) => error!(component = name, error = %e, "Failed"),
+                        (None, true) => info!(component = name, "Ended; restarting"),
+                        (None, false) => info!(component = name, "Ended"),
+                    }
+                    if !restart {
+                        let state = match error {
+                            Some(_) => ComponentState::Failed,
+                            None => ComponentState::Stopped,
+                        };
+                        set_state(&status, state, error);
+                        return;
+                    }
+                    if error.is_some() {
+                        failures += 1;
+                    }
+                    set_state(&status, ComponentState::Restarting, error);
+                    let delay = backoff.backoff(failures.max(1));
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = stop.clone().stopped() => {
+                            set_state(&status, ComponentState::Stopped, None);
+                            return;
+                        }
+                    }
+                    status.lock().expect("supervisor lock poisoned").restarts += 1;
+                }
+            }
+        });
+        self.components.lock().expect("supervisor lock poisoned").push(status);
+        self.tasks.lock().expect("supervisor lock poisoned").push(task);
+    }
+
+    /// The status of every component, in the order they were spawned.
+    pub fn statuses(&self) -> Vec<ComponentStatus> {
+        let components = self.components.lock().expect("supervisor lock poisoned");
+        components
+            .iter()
+            .map(|status| status.lock().expect("supervisor lock poisoned").clone())
+            .collect()
+    }
+
+    /// Whether no component failed for good.
+    pub fn is_healthy(&self) -> bool {
+        self.statuses().iter().all(|status| status.state != ComponentState::Failed)
+    }
+
+    /// Signal every component to stop and wait for them to, up to `timeout`; those
+    /// still running then are cancelled. False if some were.
+    pub async fn shutdown(&self, timeout: Duration) -> bool {
+        self.stop.send_replace(true);
+        let deadline = Instant::now() + timeout;
+        let tasks = std::mem::take(&mut *self.tasks.lock().expect("supervisor lock poisoned"));
+        let components = self.components.lock().expect("supervisor lock poisoned").clone();
+        let mut stopped = true;
+        for (status, mut task) in components.iter().zip(tasks) {
+            if tokio::time::timeout_at(deadline, &mut task).await.is_err() {
+                task.abort();
+                let name = status.lock().expect("supervisor lock poisoned").name.clone();
+                warn!(component = name, "Cancelled at the shutdown deadline");
+                set_state(status, ComponentState::Stopped, None);
+                stopped = false;
+            }
+        }
+        stopped
+    }
+}
+
+fn set_state(status: &Mutex<ComponentStatus>, state: ComponentState, error: Option<String>) {
+    let mut status = status.lock().expect("supervisor lock poisoned");
+    if status.state != state {
+        status.since_ms = now_ms();
+    }
+    status.state = state;
+    if error.is_some() {
+        status.last_error = error;
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn supervisor() -> Supervisor {
+        let backoff = RetryPolicy::no_retry()
+            .with_initial_backoff(Duration::from_millis(1))
+            .with_jitter(false);
+        Supervisor::new().with_backoff(backoff)
+    }
+
+    #[tokio::test]
+    async fn test_components_are_restarted_as_their_policy_says() {
+        let supervisor = supervisor();
+        let runs = Arc::new(AtomicU32::new(0));
+        // Fails twice, then ends.
+        supervisor.spawn("flaky", RestartPolicy::OnFailure { max_restarts: 5 }, {
+            let runs = runs.clone();
+            move |_| {
+                let run = runs.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match run {
+                        0 | 1 => Err(format!("attempt {run} failed")),
+                        _ => Ok(()),
+                    }
+                }
+            }
+        });
+        supervisor.spawn("broken", RestartPolicy::OnFailure { max_restarts: 1 }, |_| async {
+            Err("unreachable")
+        });
+        supervisor.spawn("panicking", RestartPolicy::Never, |_| async {
+            panic!("bug");
+            #[allow(unreachable_code)]
+            Ok::<_, String>(())
+        });
+        supervisor.spawn("server", RestartPolicy::Always, |stop: Stop| async move {
+            stop.stopped().await;
+            Ok::<_, String>(())
+        });
+        // Until the first three settled.
+        let settled = |s: &ComponentStatus| {
+            matches!(s.state, ComponentState::Stopped | ComponentState::Failed)
+                || s.name == "server"
+        };
+        while !supervisor.statuses().iter().all(settled) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let statuses = supervisor.statuses();
+        let states: Vec<_> = statuses.iter().map(|s| (s.state, s.restarts)).collect();
+        use ComponentState::*;
+        assert_eq!(states, [(Stopped, 2), (Failed, 1), (Failed, 0), (Running, 0)]);
+        assert_eq!(statuses[0].last_error.as_deref(), Some("attempt 1 failed"));
+        assert_eq!(statuses[1].last_error.as_deref(), Some("unreachable"));
+        assert_eq!(statuses[2].last_error.as_deref(), Some("panicked"));
+        assert_eq!(statuses[3].restart_policy, "always");
+        assert!(!supervisor.is_healthy());
+
+        assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+        assert_eq!(supervisor.statuses()[3].state, Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_components_ignoring_the_stop_signal() {
+        let supervisor = supervisor();
+        supervisor.spawn("stuck", RestartPolicy::Always, |_| async {
+            std::future::pending::<()>().await;
+            Ok::<_, String>(())
+        });
+        assert!(supervisor.is_healthy());
+        assert!(!supervisor.shutdown(Duration::from_millis(50)).await);
+        assert_eq!(supervisor.statuses()[0].state, ComponentState::Stopped);
+    }
+}
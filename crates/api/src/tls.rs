@@ -0,0 +1,104 @@
+//! TLS for the network frontends.
+//!
+//! One [`TlsConfig`] (a PEM certificate chain and private key, plus optionally the CA
+//! that client certificates must chain to) configures every server: HTTP via
+//! [`HttpOptions::with_tls`](crate::http::HttpOptions::with_tls), pgwire via
+//! [`IglooPgServer::with_tls`](crate::pgwire::IglooPgServer::with_tls), and the gRPC,
+//! Flight and Flight SQL services via [`TlsConfig::grpc_config`].
+//!
+//! With a client CA set, connections without a certificate signed by it are refused
+//! during the handshake (mutual TLS).
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    cert_pem: Vec<u8>,
+    key_pem: Vec<u8>,
+    client_ca_pem: Option<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub fn from_pem(cert_pem: impl Into<Vec<u8>>, key_pem: impl Into<Vec<u8>>) -> Self {
+        Self { cert_pem: cert_pem.into(), key_pem: key_pem.into(), client_ca_pem: None }
+    }
+
+    pub fn from_files(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_pem(std::fs::read(cert_path)?, std::fs::read(key_path)?))
+    }
+
+    /// Require clients to present a certificate issued by this CA.
+    pub fn with_client_ca(mut self, ca_pem: impl Into<Vec<u8>>) -> Self {
+        self.client_ca_pem = Some(ca_pem.into());
+        self
+    }
+
+    pub fn with_client_ca_file(self, ca_path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.with_client_ca(std::fs::read(ca_path)?))
+    }
+
+    /// The rustls configuration used by the HTTP and pgwire servers.
+    pub fn server_config(&self) -> io::Result<ServerConfig> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?;
+        let builder = match &self.client_ca_pem {
+            Some(ca_pem) => {
+                let mut roots = RootCertStore::empty();
+                for cert in certs(ca_pem)? {
+                    roots.add(cert).map_err(invalid)?;
+                }
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .map_err(invalid)?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        builder.with_single_cert(certs(&self.cert_pem)?, self.private_key()?).map_err(invalid)
+    }
+
+    pub fn acceptor(&self) -> io::Result<TlsAcceptor> {
+        Ok(TlsAcceptor::from(Arc::new(self.server_config()?)))
+    }
+
+    /// The equivalent configuration for tonic servers.
+    pub fn grpc_config(&self) -> ServerTlsConfig {
+        let config =
+            ServerTlsConfig::new().identity(Identity::from_pem(&self.cert_pem, &self.key_pem));
+        match &self.client_ca_pem {
+            Some(ca_pem) => config.client_ca_root(Certificate::from_pem(ca_pem)),
+            None => config,
+        }
+    }
+
+    fn private_key(&self) -> io::Result<PrivateKeyDer<'static>> {
+        rustls_pemfile::private_key(&mut self.key_pem.as_slice())?
+            .ok_or_else(|| invalid("no private key found in PEM"))
+    }
+}
+
+fn certs(pem: &[u8]) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = rustls_pemfile::certs(&mut &*pem).collect::<io::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(invalid("no certificates found in PEM"));
+    }
+    Ok(certs)
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("invalid TLS configuration: {}", e.to_string()),
+    )
+}
@@ -0,0 +1,287 @@
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action as FlightAction, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PollInfo, PutResult, SchemaResult, Ticket,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::physical_plan::displayable;
+use datafusion::prelude::{CsvReadOptions, SessionContext};
+use datafusion_proto::bytes::logical_plan_from_bytes;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use igloo_api::ballista::protobuf::action::ActionType as BallistaAction;
+use igloo_api::ballista::protobuf::execute_query_params::Query;
+use igloo_api::ballista::protobuf::execute_query_result::Result as ExecuteResult;
+use igloo_api::ballista::protobuf::job_status::Status as JobState;
+use igloo_api::ballista::protobuf::scheduler_grpc_server::{SchedulerGrpc, SchedulerGrpcServer};
+use igloo_api::ballista::protobuf::{
+    Action, CancelJobParams, CancelJobResult, ExecuteQueryParams, ExecuteQueryResult,
+    ExecuteQuerySuccessResult, ExecutorMetadata, GetJobStatusParams, GetJobStatusResult, JobStatus,
+    PartitionId, PartitionLocation, RunningJob, SuccessfulJob,
+};
+use igloo_api::ballista::BallistaPlanner;
+use igloo_engine::QueryEngine;
+use prost::Message;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+/// A one-node Ballista stand-in: the scheduler runs each job with DataFusion and
+/// keeps its output, in two partitions, for the executor's Flight service to serve.
+/// Jobs are reported running when first polled.
+#[derive(Clone, Default)]
+struct Cluster {
+    executor: Arc<Mutex<Option<SocketAddr>>>,
+    jobs: Arc<Mutex<HashMap<String, Vec<Vec<RecordBatch>>>>>,
+    settings: Arc<Mutex<Vec<(String, String)>>>,
+    polls: Arc<AtomicUsize>,
+}
+
+#[tonic::async_trait]
+impl SchedulerGrpc for Cluster {
+    async fn execute_query(
+        &self,
+        request: Request<ExecuteQueryParams>,
+    ) -> Result<Response<ExecuteQueryResult>, Status> {
+        let params = request.into_inner();
+        let Some(Query::LogicalPlan(plan)) = params.query else {
+            return Err(Status::invalid_argument("expected a logical plan"));
+        };
+        let ctx = SessionContext::new();
+        let plan =
+            logical_plan_from_bytes(&plan, &ctx).map_err(|e| Status::internal(e.to_string()))?;
+        let batches = ctx
+            .execute_logical_plan(plan)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+            .collect()
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let (first, second) = batches.split_at(batches.len() / 2);
+        let job_id = format!("job-{}", self.jobs.lock().unwrap().len());
+        self.jobs.lock().unwrap().insert(job_id.clone(), vec![first.to_vec(), second.to_vec()]);
+        let settings = params.settings.into_iter().map(|kv| (kv.key, kv.value));
+        *self.settings.lock().unwrap() = settings.collect();
+        let success = ExecuteQuerySuccessResult { job_id, session_id: "session".into() };
+        Ok(Response::new(ExecuteQueryResult {
+            result: Some(ExecuteResult::Success(success)),
+            operation_id: String::new(),
+        }))
+    }
+
+    async fn get_job_status(
+        &self,
+        request: Request<GetJobStatusParams>,
+    ) -> Result<Response<GetJobStatusResult>, Status> {
+        let job_id = request.into_inner().job_id;
+        let executor = self.executor.lock().unwrap().unwrap();
+        let partitions = self.jobs.lock().unwrap().get(&job_id).map(Vec::len);
+        let Some(partitions) = partitions else {
+            return Err(Status::not_found(job_id));
+        };
+        if self.polls.fetch_add(1, Ordering::SeqCst) == 0 {
+            let running = JobState::Running(RunningJob::default());
+            let status = JobStatus { job_id, job_name: String::new(), status: Some(running) };
+            return Ok(Response::new(GetJobStatusResult { status: Some(status) }));
+        }
+        // The second partition comes first, as Ballista does not sort them either.
+        let partition_location = (0..partitions as u32)
+            .rev()
+            .map(|partition| PartitionLocation {
+                map_partition_id: partition,
+                partition_id: Some(PartitionId {
+                    job_id: job_id.clone(),
+                    stage_id: 1,
+                    partition_id: partition,
+                }),
+                executor_meta: Some(ExecutorMetadata {
+                    id: "executor".into(),
+                    host: executor.ip().to_string(),
+                    port: executor.port() as u32,
+                    grpc_port: 0,
+                }),
+                path: format!("/tmp/{job_id}/{partition}"),
+            })
+            .collect();
+        let status = JobStatus {
+            job_id,
+            job_name: String::new(),
+            status: Some(JobState::Successful(SuccessfulJob {
+                partition_location,
+                ..Default::default()
+            })),
+        };
+        Ok(Response::new(GetJobStatusResult { status: Some(status) }))
+    }
+
+    async fn cancel_job(
+        &self,
+        _request: Request<CancelJobParams>,
+    ) -> Result<Response<CancelJobResult>, Status> {
+        Ok(Response::new(CancelJobResult { cancelled: true }))
+    }
+}
+
+type FlightStream<T> = BoxStream<'static, Result<T, Status>>;
+
+#[tonic::async_trait]
+impl FlightService for Cluster {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoActionStream = FlightStream<arrow_flight::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+    type DoExchangeStream = FlightStream<FlightData>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let action = Action::decode(request.into_inner().ticket)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let Some(BallistaAction::FetchPartition(fetch)) = action.action_type else {
+            return Err(Status::invalid_argument("expected FetchPartition"));
+        };
+        let batches = self.jobs.lock().unwrap()[&fetch.job_id][fetch.partition_id as usize].clone();
+        let batches = futures::stream::iter(batches.into_iter().map(Ok));
+        let stream = FlightDataEncoderBuilder::new().build(batches).map_err(Status::from);
+        Ok(Response::new(stream.boxed()))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("handshake"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("list_flights"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(Status::unimplemented("get_flight_info"))
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("poll_flight_info"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("get_schema"))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("do_put"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<FlightAction>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("do_action"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("list_actions"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("do_exchange"))
+    }
+}
+
+/// Start the cluster's scheduler and executor and return the scheduler's URL.
+async fn start_cluster(cluster: &Cluster) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    *cluster.executor.lock().unwrap() = Some(listener.local_addr().unwrap());
+    tokio::spawn(
+        Server::builder()
+            .add_service(FlightServiceServer::new(cluster.clone()))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(SchedulerGrpcServer::new(cluster.clone()))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    format!("http://{addr}")
+}
+
+fn write_orders() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("igloo-ballista-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let rows: String = (0..100).map(|id| format!("{id},{},{}\n", id % 7, id % 10)).collect();
+    std::fs::write(dir.join("orders.csv"), format!("id,customer,amount\n{rows}")).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn test_queries_run_on_ballista() {
+    let cluster = Cluster::default();
+    let planner = BallistaPlanner::new(start_cluster(&cluster).await)
+        .with_poll_interval(Duration::from_millis(10));
+    let engine = QueryEngine::new().with_query_planner(Arc::new(planner));
+    let local = QueryEngine::new();
+    let dir = write_orders();
+    let path = dir.join("orders.csv");
+    for engine in [&engine, &local] {
+        let ctx = engine.session_context();
+        ctx.register_csv("orders", path.to_str().unwrap(), CsvReadOptions::new()).await.unwrap();
+    }
+
+    let sql = "SELECT customer, SUM(amount) AS total FROM orders GROUP BY customer \
+               ORDER BY customer";
+    let plan = engine.sql(sql).await.unwrap().create_physical_plan().await.unwrap();
+    let display = displayable(plan.as_ref()).indent(false).to_string();
+    assert!(display.starts_with("BallistaExec: scheduler=http://"), "{display}");
+    let actual = engine.query(sql).await.unwrap().batches;
+    let expected = local.query(sql).await.unwrap().batches;
+    assert_eq!(
+        pretty_format_batches(&actual).unwrap().to_string(),
+        pretty_format_batches(&expected).unwrap().to_string()
+    );
+    let settings = cluster.settings.lock().unwrap().clone();
+    assert!(settings.iter().any(|(key, _)| key == "datafusion.execution.batch_size"));
+
+    // Queries reading no table, or tables only igloo has, run locally.
+    engine.query("CREATE TABLE t AS VALUES (1), (2)").await.unwrap();
+    engine.query("SELECT * FROM t").await.unwrap();
+    engine.query("SELECT 1").await.unwrap();
+    assert_eq!(cluster.jobs.lock().unwrap().len(), 1);
+    assert_eq!(cluster.polls.load(Ordering::SeqCst), 2);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,425 @@
+use async_trait::async_trait;
+use datafusion::arrow::array::Int64Array;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::datasource::TableType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{FunctionRegistry, SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    displayable, DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+};
+use datafusion::prelude::CsvReadOptions;
+use datafusion_proto::physical_plan::PhysicalExtensionCodec;
+use igloo_api::distributed::{DistributedPlanner, WorkerExecutor};
+use igloo_api::igloo::coordinator_service_client::CoordinatorServiceClient;
+use igloo_api::igloo::coordinator_service_server::CoordinatorServiceServer;
+use igloo_api::igloo::worker_service_server::{WorkerService, WorkerServiceServer};
+use igloo_api::igloo::{
+    DataForTaskRequest, HeartbeatInfo, RemoveJobRequest, TaskDefinition, TaskStatus, WorkerInfo,
+};
+use igloo_api::membership::{Membership, MembershipService, WorkerState};
+use igloo_engine::session::SessionVars;
+use igloo_engine::QueryEngine;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+/// Start a worker on a random port and return its address.
+async fn start_worker() -> String {
+    serve_worker(WorkerExecutor::new(Arc::new(QueryEngine::new()))).await
+}
+
+async fn serve_worker(service: impl WorkerService) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(WorkerServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    addr.to_string()
+}
+
+/// A worker that stops responding, as if it died, after running `tasks` tasks.
+struct DyingWorker {
+    worker: WorkerExecutor,
+    tasks: AtomicUsize,
+}
+
+impl DyingWorker {
+    fn new(tasks: usize) -> Self {
+        let worker = WorkerExecutor::new(Arc::new(QueryEngine::new()));
+        Self { worker, tasks: AtomicUsize::new(tasks) }
+    }
+
+    #[allow(clippy::result_large_err)] // Mirrors the service's `Status` errors.
+    fn alive(&self) -> Result<(), Status> {
+        match self.tasks.load(Ordering::SeqCst) {
+            0 => Err(Status::unavailable("worker died")),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl WorkerService for DyingWorker {
+    type GetDataForTaskStream = <WorkerExecutor as WorkerService>::GetDataForTaskStream;
+
+    async fn execute_task(
+        &self,
+        request: Request<TaskDefinition>,
+    ) -> Result<Response<TaskStatus>, Status> {
+        self.alive()?;
+        let status = self.worker.execute_task(request).await?;
+        self.tasks.fetch_sub(1, Ordering::SeqCst);
+        Ok(status)
+    }
+
+    async fn get_data_for_task(
+        &self,
+        request: Request<DataForTaskRequest>,
+    ) -> Result<Response<Self::GetDataForTaskStream>, Status> {
+        self.alive()?;
+        self.worker.get_data_for_task(request).await
+    }
+
+    async fn remove_job(&self, request: Request<RemoveJobRequest>) -> Result<Response<()>, Status> {
+        self.alive()?;
+        self.worker.remove_job(request).await
+    }
+}
+
+/// Write `orders` (split over several files) and `customers` CSV tables.
+fn write_tables(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("igloo-{test}-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("orders")).unwrap();
+    std::fs::create_dir_all(dir.join("customers")).unwrap();
+    for file in 0..3 {
+        let mut csv = String::from("id,customer,amount\n");
+        for i in 0..100 {
+            let id = file * 100 + i;
+            csv.push_str(&format!("{id},{},{}\n", id % 7, id % 10));
+        }
+        std::fs::write(dir.join(format!("orders/part-{file}.csv")), csv).unwrap();
+    }
+    let customers: String = (0..7).map(|id| format!("{id},customer {id}\n")).collect();
+    std::fs::write(dir.join("customers/customers.csv"), format!("id,name\n{customers}")).unwrap();
+    dir
+}
+
+async fn register_tables(engine: &QueryEngine, dir: &std::path::Path) {
+    let ctx = engine.session_context();
+    let orders = dir.join("orders");
+    ctx.register_csv("orders", orders.to_str().unwrap(), CsvReadOptions::new()).await.unwrap();
+    let customers = dir.join("customers");
+    ctx.register_csv("customers", customers.to_str().unwrap(), CsvReadOptions::new())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_query_runs_on_workers() {
+    let membership = Membership::new().with_worker(start_worker().await);
+    let membership = Arc::new(membership.with_worker(start_worker().await));
+    let dir = write_tables("query-runs-on-workers");
+    let mut session = SessionVars::new();
+    session.set("datafusion.execution.target_partitions", "4").unwrap();
+
+    let local = QueryEngine::new().with_session(&session);
+    let distributed = QueryEngine::new()
+        .with_physical_optimizer_rule(Arc::new(DistributedPlanner::new(membership)))
+        .with_session(&session);
+    register_tables(&local, &dir).await;
+    register_tables(&distributed, &dir).await;
+
+    let sql = "SELECT c.name, COUNT(*) AS orders, SUM(o.amount) AS total \
+               FROM orders o JOIN customers c ON o.customer = c.id \
+               GROUP BY c.name ORDER BY c.name";
+    let plan = distributed.sql(sql).await.unwrap().create_physical_plan().await.unwrap();
+    let display = displayable(plan.as_ref()).indent(false).to_string();
+    assert!(display.contains("ShuffleExchangeExec"), "{display}");
+
+    let expected = local.sql(sql).await.unwrap().collect().await.unwrap();
+    let actual = distributed.sql(sql).await.unwrap().collect().await.unwrap();
+    let expected = pretty_format_batches(&expected).unwrap().to_string();
+    assert_eq!(pretty_format_batches(&actual).unwrap().to_string(), expected);
+    assert!(expected.contains("| customer 6 | 42     |"), "{expected}");
+
+    // Plans the workers cannot run stay on the coordinator.
+    let batches = distributed.query("SELECT COUNT(*) FROM (VALUES (1), (2)) t(x)").await.unwrap();
+    assert_eq!(batches.batches[0].num_rows(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_registered_workers_take_stages() {
+    let membership = Arc::new(Membership::new());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(CoordinatorServiceServer::new(MembershipService::new(membership.clone())))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let mut coordinator =
+        CoordinatorServiceClient::connect(format!("http://{addr}")).await.unwrap();
+
+    let dir = write_tables("registered-workers");
+    let mut session = SessionVars::new();
+    session.set("datafusion.execution.target_partitions", "4").unwrap();
+    let engine = QueryEngine::new()
+        .with_physical_optimizer_rule(Arc::new(DistributedPlanner::new(membership.clone())))
+        .with_session(&session);
+    register_tables(&engine, &dir).await;
+    let sql = "SELECT customer, SUM(amount) FROM orders GROUP BY customer";
+    let distributed = || async {
+        let plan = engine.sql(sql).await.unwrap().create_physical_plan().await.unwrap();
+        let display = displayable(plan.as_ref()).indent(false).to_string();
+        display.contains("ShuffleExchangeExec")
+    };
+    // Without workers everything runs on the coordinator.
+    assert!(!distributed().await);
+
+    let worker = WorkerInfo { id: "w1".into(), address: start_worker().await, capacity: 2 };
+    coordinator.register_worker(worker).await.unwrap();
+    let heartbeat = |worker_id: &str| HeartbeatInfo { worker_id: worker_id.into(), timestamp: 0 };
+    assert!(coordinator.send_heartbeat(heartbeat("w1")).await.unwrap().into_inner().ok);
+    assert!(!coordinator.send_heartbeat(heartbeat("w2")).await.unwrap().into_inner().ok);
+    let workers = membership.workers();
+    assert_eq!((workers[0].capacity, workers[0].state), (2, WorkerState::Active));
+    assert!(distributed().await);
+    assert_eq!(
+        engine.query(sql).await.unwrap().batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+        7
+    );
+
+    membership.drain("w1");
+    assert!(!distributed().await);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_query_survives_lost_worker() {
+    let membership = Membership::new().with_worker(start_worker().await);
+    let membership = membership.with_worker(start_worker().await);
+    let membership = Arc::new(membership.with_worker(serve_worker(DyingWorker::new(1)).await));
+    let dir = write_tables("lost-worker");
+    let mut session = SessionVars::new();
+    session.set("datafusion.execution.target_partitions", "4").unwrap();
+
+    let local = QueryEngine::new().with_session(&session);
+    let distributed = QueryEngine::new()
+        .with_physical_optimizer_rule(Arc::new(DistributedPlanner::new(membership)))
+        .with_session(&session);
+    register_tables(&local, &dir).await;
+    register_tables(&distributed, &dir).await;
+
+    // The dying worker runs one scan task and is gone by the time its output is read.
+    let sql = "SELECT c.name, COUNT(*) AS orders, SUM(o.amount) AS total \
+               FROM orders o JOIN customers c ON o.customer = c.id \
+               GROUP BY c.name ORDER BY c.name";
+    let expected = local.sql(sql).await.unwrap().collect().await.unwrap();
+    let actual = distributed.sql(sql).await.unwrap().collect().await.unwrap();
+    assert_eq!(
+        pretty_format_batches(&actual).unwrap().to_string(),
+        pretty_format_batches(&expected).unwrap().to_string()
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_lost_workers_fail_query_after_max_attempts() {
+    let membership = Membership::new().with_worker(start_worker().await);
+    let membership = Arc::new(membership.with_worker(serve_worker(DyingWorker::new(0)).await));
+    let dir = write_tables("max-attempts");
+    let mut session = SessionVars::new();
+    session.set("datafusion.execution.target_partitions", "4").unwrap();
+    let planner = DistributedPlanner::new(membership).with_max_attempts(1);
+    let engine =
+        QueryEngine::new().with_physical_optimizer_rule(Arc::new(planner)).with_session(&session);
+    register_tables(&engine, &dir).await;
+
+    let err = engine.query("SELECT customer, SUM(amount) FROM orders GROUP BY customer").await;
+    let err = err.unwrap_err().to_string();
+    assert!(err.contains("failed after 1 attempts, lost workers"), "{err}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// A federated scan's plan node: `rows` numbers per partition from a source that needs
+/// the secret `secret`, which only workers resolve.
+#[derive(Debug)]
+struct SecretScanExec {
+    secret: String,
+    password: Option<String>,
+    properties: PlanProperties,
+}
+
+impl SecretScanExec {
+    fn new(secret: String, password: Option<String>) -> Self {
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(secret_schema()),
+            Partitioning::UnknownPartitioning(3),
+            EmissionType::Incremental,
+            Boundedness::Bounded,
+        );
+        Self { secret, password, properties }
+    }
+}
+
+fn secret_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![Field::new("x", DataType::Int64, false)]))
+}
+
+impl DisplayAs for SecretScanExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretScanExec: secret={}", self.secret)
+    }
+}
+
+impl ExecutionPlan for SecretScanExec {
+    fn name(&self) -> &str {
+        "SecretScanExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        if self.password.as_deref() != Some("hunter2") {
+            return Err(DataFusionError::Execution("authentication failed".into()));
+        }
+        let start = partition as i64 * 100;
+        let batch = RecordBatch::try_new(
+            secret_schema(),
+            vec![Arc::new(Int64Array::from_iter_values(start..start + 100))],
+        )?;
+        let batches = futures::stream::iter([Ok(batch)]);
+        Ok(Box::pin(RecordBatchStreamAdapter::new(secret_schema(), batches)))
+    }
+}
+
+#[derive(Debug)]
+struct SecretTable;
+
+#[async_trait]
+impl TableProvider for SecretTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        secret_schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        _projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(SecretScanExec::new("warehouse-password".into(), None)))
+    }
+}
+
+/// Encodes the secret's name; decodes it with the secrets of the side it runs on.
+#[derive(Debug)]
+struct SecretScanCodec {
+    secrets: HashMap<String, String>,
+}
+
+impl PhysicalExtensionCodec for SecretScanCodec {
+    fn try_decode(
+        &self,
+        buf: &[u8],
+        _inputs: &[Arc<dyn ExecutionPlan>],
+        _registry: &dyn FunctionRegistry,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let secret = String::from_utf8(buf.to_vec()).unwrap();
+        let password = self.secrets.get(&secret).cloned();
+        Ok(Arc::new(SecretScanExec::new(secret, password)))
+    }
+
+    fn try_encode(&self, node: Arc<dyn ExecutionPlan>, buf: &mut Vec<u8>) -> DataFusionResult<()> {
+        let Some(scan) = node.as_any().downcast_ref::<SecretScanExec>() else {
+            return Err(DataFusionError::NotImplemented(node.name().to_string()));
+        };
+        buf.extend_from_slice(scan.secret.as_bytes());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_registered_plan_nodes_run_on_workers() {
+    let secrets = HashMap::from([("warehouse-password".to_string(), "hunter2".to_string())]);
+    let mut addresses = Vec::new();
+    for _ in 0..2 {
+        let codec = Arc::new(SecretScanCodec { secrets: secrets.clone() });
+        let worker = WorkerExecutor::new(Arc::new(QueryEngine::new()))
+            .with_extension_codec("secret_scan", codec);
+        addresses.push(serve_worker(worker).await);
+    }
+    let membership = addresses.into_iter().fold(Membership::new(), Membership::with_worker);
+    let membership = Arc::new(membership);
+    // The coordinator has no secrets: it cannot run the scan itself.
+    let codec = Arc::new(SecretScanCodec { secrets: HashMap::new() });
+    let planner = DistributedPlanner::new(membership).with_extension_codec("secret_scan", codec);
+    let mut session = SessionVars::new();
+    session.set("datafusion.execution.target_partitions", "4").unwrap();
+    let engine =
+        QueryEngine::new().with_physical_optimizer_rule(Arc::new(planner)).with_session(&session);
+    engine.register_table("warehouse", Arc::new(SecretTable)).unwrap();
+
+    let sql = "SELECT x % 3 AS k, COUNT(*) AS n FROM warehouse GROUP BY x % 3 ORDER BY k";
+    let plan = engine.sql(sql).await.unwrap().create_physical_plan().await.unwrap();
+    let display = displayable(plan.as_ref()).indent(false).to_string();
+    assert!(display.contains("ShuffleExchangeExec"), "{display}");
+    let batches = engine.query(sql).await.unwrap().batches;
+    let expected = [
+        "+---+-----+",
+        "| k | n   |",
+        "+---+-----+",
+        "| 0 | 100 |",
+        "| 1 | 100 |",
+        "| 2 | 100 |",
+        "+---+-----+",
+    ];
+    assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected.join("\n"));
+}
@@ -0,0 +1,192 @@
+use arrow_flight::decode::FlightRecordBatchStream;
+use arrow_flight::flight_service_client::FlightServiceClient;
+use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::{Criteria, FlightDescriptor, FlightInfo, Ticket};
+use datafusion::arrow::array::{Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::MemTable;
+use futures::TryStreamExt;
+use igloo_api::{IglooFlightService, IDEMPOTENCY_KEYS_HEADER, TABLE_TICKET_PREFIX};
+use igloo_common::catalog::MemoryCatalog;
+use igloo_engine::QueryEngine;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+use tonic::Code;
+
+/// Start a plain Flight server over a `numbers` table and a view on it.
+async fn start_server() -> FlightServiceClient<Channel> {
+    let engine = Arc::new(QueryEngine::new());
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["one", "two", "three"])),
+        ],
+    )
+    .unwrap();
+    let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+    engine.register_table("numbers", Arc::new(table)).unwrap();
+    engine.query("CREATE VIEW odd AS SELECT id FROM numbers WHERE id % 2 = 1").await.unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = IglooFlightService::new(engine, Arc::new(MemoryCatalog::new()));
+    tokio::spawn(
+        Server::builder()
+            .add_service(FlightServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let channel = Channel::from_shared(format!("http://{addr}")).unwrap().connect().await.unwrap();
+    FlightServiceClient::new(channel)
+}
+
+async fn fetch(client: &mut FlightServiceClient<Channel>, ticket: Ticket) -> Vec<RecordBatch> {
+    let stream = client.do_get(ticket).await.unwrap().into_inner();
+    FlightRecordBatchStream::new_from_flight_data(stream.map_err(Into::into))
+        .try_collect()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_list_flights_and_fetch_tables() {
+    let mut client = start_server().await;
+    let flights: Vec<FlightInfo> = client
+        .list_flights(Criteria::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .try_collect()
+        .await
+        .unwrap();
+    let paths: Vec<_> = flights
+        .iter()
+        .map(|info| info.flight_descriptor.as_ref().unwrap().path.join("."))
+        .collect();
+    assert_eq!(paths, ["datafusion.public.numbers", "datafusion.public.odd"]);
+
+    let ticket = flights[1].endpoint[0].ticket.clone().unwrap();
+    let batches = fetch(&mut client, ticket).await;
+    let rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+    assert_eq!(rows, 2);
+    assert_eq!(flights[1].clone().try_decode_schema().unwrap(), *batches[0].schema());
+}
+
+#[tokio::test]
+async fn test_path_descriptors() {
+    let mut client = start_server().await;
+    let descriptor = FlightDescriptor::new_path(vec!["numbers".to_string()]);
+    let info = client.get_flight_info(descriptor.clone()).await.unwrap().into_inner();
+    let schema = info.clone().try_decode_schema().unwrap();
+    assert_eq!(schema.fields().len(), 2);
+
+    let ticket = info.endpoint[0].ticket.clone().unwrap();
+    let batches = fetch(&mut client, ticket).await;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+
+    let result = client.get_schema(descriptor).await.unwrap().into_inner();
+    assert_eq!(Schema::try_from(&result).unwrap(), schema);
+
+    let missing = FlightDescriptor::new_path(vec!["missing".to_string()]);
+    let err = client.get_flight_info(missing).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+    let err = client.do_get(Ticket::new(format!("{TABLE_TICKET_PREFIX}missing"))).await;
+    assert_eq!(err.unwrap_err().code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_interceptor_requires_bearer_credentials() {
+    use igloo_api::auth::{flight_interceptor, Authenticator, Principal};
+
+    let auth = Arc::new(Authenticator::new().with_api_key("secret", Principal::new("etl")));
+    let service =
+        IglooFlightService::new(Arc::new(QueryEngine::new()), Arc::new(MemoryCatalog::new()));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(FlightServiceServer::with_interceptor(service, flight_interceptor(auth)))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let channel = Channel::from_shared(format!("http://{addr}")).unwrap().connect().await.unwrap();
+    let mut client = FlightServiceClient::new(channel);
+
+    let err = client.list_flights(Criteria::default()).await.unwrap_err();
+    assert_eq!(err.code(), Code::Unauthenticated);
+
+    let mut request = tonic::Request::new(Criteria::default());
+    request.metadata_mut().insert("authorization", "Bearer secret".parse().unwrap());
+    assert!(client.list_flights(request).await.is_ok());
+}
+
+#[tokio::test]
+async fn test_do_put_appends_to_a_table() {
+    use arrow_flight::encode::FlightDataEncoderBuilder;
+
+    let mut client = start_server().await;
+    // Columns by name, and cast: `id` is sent as the second column.
+    let batch = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            Field::new("name", DataType::Utf8, false),
+            Field::new("id", DataType::Int32, false),
+        ])),
+        vec![
+            Arc::new(StringArray::from(vec!["four", "five"])),
+            Arc::new(datafusion::arrow::array::Int32Array::from(vec![4, 5])),
+        ],
+    )
+    .unwrap();
+    let data: Vec<_> = FlightDataEncoderBuilder::new()
+        .with_flight_descriptor(Some(FlightDescriptor::new_path(vec!["numbers".to_string()])))
+        .build(futures::stream::iter([Ok(batch)]))
+        .try_collect()
+        .await
+        .unwrap();
+    // Sent twice, as a retrying client would, with the columns identifying rows.
+    for expected in [
+        serde_json::json!({"rows": 2, "commits": 1, "duplicates": 0}),
+        serde_json::json!({"rows": 0, "commits": 0, "duplicates": 2}),
+    ] {
+        let mut request = tonic::Request::new(futures::stream::iter(data.clone()));
+        request.metadata_mut().insert(IDEMPOTENCY_KEYS_HEADER, "id".parse().unwrap());
+        let results: Vec<_> =
+            client.do_put(request).await.unwrap().into_inner().try_collect().await.unwrap();
+        let metadata: serde_json::Value = serde_json::from_slice(&results[0].app_metadata).unwrap();
+        assert_eq!(metadata, expected);
+    }
+    let batches = fetch(&mut client, Ticket::new("SELECT sum(id) FROM numbers")).await;
+    let sum = batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap().value(0);
+    assert_eq!(sum, 15);
+
+    let mut missing = data;
+    missing[0].flight_descriptor = Some(FlightDescriptor::new_path(vec!["missing".to_string()]));
+    let err = client.do_put(futures::stream::iter(missing)).await.unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_query_results_are_streamed() {
+    let mut client = start_server().await;
+    // Far more rows than could be collected before sending.
+    let ticket = Ticket::new("SELECT value FROM range(1000000000000)");
+    let stream = client.do_get(ticket).await.unwrap().into_inner();
+    let mut batches = FlightRecordBatchStream::new_from_flight_data(stream.map_err(Into::into));
+    let first = batches.try_next().await.unwrap().unwrap();
+    assert!(first.num_rows() > 0);
+    drop(batches);
+
+    // An empty result still has a schema.
+    let descriptor = FlightDescriptor::new_cmd("SELECT id FROM numbers WHERE id > 3");
+    let info = client.get_flight_info(descriptor).await.unwrap().into_inner();
+    assert_eq!(info.try_decode_schema().unwrap().field(0).name(), "id");
+    let batches = fetch(&mut client, Ticket::new("SELECT id FROM numbers WHERE id > 3")).await;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+}
@@ -0,0 +1,147 @@
+use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::sql::client::FlightSqlServiceClient;
+use arrow_flight::sql::CommandGetTables;
+use arrow_flight::FlightInfo;
+use datafusion::arrow::array::{Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::MemTable;
+use futures::TryStreamExt;
+use igloo_api::flight_sql::IglooFlightSqlService;
+use igloo_engine::QueryEngine;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Channel, Server};
+
+/// Start a Flight SQL server over a small `numbers` table and connect a client to it.
+async fn start_server() -> FlightSqlServiceClient<Channel> {
+    let engine = Arc::new(QueryEngine::new());
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["one", "two", "three"])),
+        ],
+    )
+    .unwrap();
+    let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+    engine.register_table("numbers", Arc::new(table)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(FlightServiceServer::new(IglooFlightSqlService::new(engine)))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let channel = Channel::from_shared(format!("http://{addr}")).unwrap().connect().await.unwrap();
+    FlightSqlServiceClient::new(channel)
+}
+
+async fn fetch(client: &mut FlightSqlServiceClient<Channel>, info: FlightInfo) -> Vec<RecordBatch> {
+    let mut batches = Vec::new();
+    for endpoint in info.endpoint {
+        let ticket = endpoint.ticket.expect("endpoint has a ticket");
+        let stream = client.do_get(ticket).await.unwrap();
+        batches.extend(stream.try_collect::<Vec<_>>().await.unwrap());
+    }
+    batches
+}
+
+#[tokio::test]
+async fn test_execute_statement() {
+    let mut client = start_server().await;
+    let info =
+        client.execute("SELECT id FROM numbers WHERE id > 1".to_string(), None).await.unwrap();
+    let batches = fetch(&mut client, info).await;
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+}
+
+#[tokio::test]
+async fn test_invalid_sql_is_rejected() {
+    let mut client = start_server().await;
+    let err = client.execute("SELEC 1".to_string(), None).await.unwrap_err();
+    assert!(err.to_string().contains("InvalidArgument"), "unexpected error: {err}");
+}
+
+#[tokio::test]
+async fn test_errors_are_redacted() {
+    let mut client = start_server().await;
+    let sql = "SELECT * FROM 'postgres://igloo:hunter2@db/app/orders.csv'";
+    let err = client.execute(sql.to_string(), None).await.unwrap_err().to_string();
+    assert!(err.contains("@db/app"), "unexpected error: {err}");
+    assert!(!err.contains("hunter2"), "unexpected error: {err}");
+}
+
+#[tokio::test]
+async fn test_prepared_statement_with_parameters() {
+    let mut client = start_server().await;
+    let mut prepared =
+        client.prepare("SELECT name FROM numbers WHERE id = $1".to_string(), None).await.unwrap();
+    assert_eq!(prepared.parameter_schema().unwrap().fields().len(), 1);
+
+    let params = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![Field::new("$1", DataType::Int64, false)])),
+        vec![Arc::new(Int64Array::from(vec![2]))],
+    )
+    .unwrap();
+    prepared.set_parameters(params).unwrap();
+    let info = prepared.execute().await.unwrap();
+    let batches = fetch(&mut client, info).await;
+
+    assert_eq!(batches.len(), 1);
+    let names = batches[0].column(0).as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(names.value(0), "two");
+    prepared.close().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_get_tables_lists_registered_tables() {
+    let mut client = start_server().await;
+    let info = client
+        .get_tables(CommandGetTables {
+            catalog: None,
+            db_schema_filter_pattern: None,
+            table_name_filter_pattern: Some("num%".to_string()),
+            table_types: vec![],
+            include_schema: false,
+        })
+        .await
+        .unwrap();
+    let batches = fetch(&mut client, info).await;
+
+    assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+    let names = batches[0]
+        .column_by_name("table_name")
+        .unwrap()
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .unwrap();
+    assert_eq!(names.value(0), "numbers");
+}
+
+#[tokio::test]
+async fn test_set_applies_to_the_session() {
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use igloo_api::session::SESSION_HEADER;
+
+    let mut client = start_server().await;
+    client.set_header(SESSION_HEADER, "s1");
+    assert_eq!(client.execute_update("SET TIME ZONE '+02:00'".to_string(), None).await.unwrap(), 0);
+    let sql = "SELECT arrow_typeof(CAST('2024-01-01' AS TIMESTAMP WITH TIME ZONE))";
+    let info = client.execute(sql.to_string(), None).await.unwrap();
+    let data_type = pretty_format_batches(&fetch(&mut client, info).await).unwrap().to_string();
+    assert!(data_type.contains("+02:00"), "{data_type}");
+
+    // Another session keeps the default time zone.
+    client.set_header(SESSION_HEADER, "s2");
+    let info = client.execute(sql.to_string(), None).await.unwrap();
+    let data_type = pretty_format_batches(&fetch(&mut client, info).await).unwrap().to_string();
+    assert!(!data_type.contains("+02:00"), "{data_type}");
+}
@@ -0,0 +1,790 @@
+use axum::body::{to_bytes, Body};
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use datafusion::arrow::array::{Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{CatalogProvider, MemTable};
+use datafusion::execution::context::SessionContext;
+use datafusion::execution::object_store::ObjectStoreUrl;
+use datafusion::execution::options::CsvReadOptions;
+use igloo_api::http::health::{DrainProbe, LagProbe, ObjectStoreProbe, Readiness, TcpProbe};
+use igloo_api::http::{router, router_with_options, HttpOptions};
+use igloo_common::catalog::CatalogSource;
+use igloo_engine::catalog_store::SqliteCatalogStore;
+use igloo_engine::formats::OutputFormat;
+use igloo_engine::running::QueryStart;
+use igloo_engine::QueryEngine;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+/// Build the HTTP router over a small `numbers` table.
+fn app() -> Router {
+    router(numbers())
+}
+
+/// An engine with a small `numbers` table.
+fn numbers() -> Arc<QueryEngine> {
+    let engine = Arc::new(QueryEngine::new());
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["one", "two", "three"])),
+        ],
+    )
+    .unwrap();
+    let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+    engine.register_table("numbers", Arc::new(table)).unwrap();
+    engine
+}
+
+fn query_request(sql: &str, accept: Option<&str>) -> Request<Body> {
+    let mut builder = Request::post("/query").header(header::CONTENT_TYPE, "application/json");
+    if let Some(accept) = accept {
+        builder = builder.header(header::ACCEPT, accept);
+    }
+    builder.body(Body::from(serde_json::json!({ "sql": sql }).to_string())).unwrap()
+}
+
+async fn send(request: Request<Body>) -> (StatusCode, String, Vec<u8>) {
+    send_to(&app(), request).await
+}
+
+async fn send_to(app: &Router, request: Request<Body>) -> (StatusCode, String, Vec<u8>) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|v| v.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec();
+    (status, content_type, body)
+}
+
+#[tokio::test]
+async fn test_query_returns_json_by_default() {
+    let (status, content_type, body) =
+        send(query_request("SELECT id, name FROM numbers WHERE id < 3", None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/json");
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, serde_json::json!([{"id": 1, "name": "one"}, {"id": 2, "name": "two"}]));
+}
+
+#[tokio::test]
+async fn test_query_negotiates_csv_and_arrow() {
+    let (_, content_type, body) =
+        send(query_request("SELECT id FROM numbers", Some("text/csv"))).await;
+    assert_eq!(content_type, "text/csv");
+    assert_eq!(String::from_utf8(body).unwrap(), "id\n1\n2\n3\n");
+
+    let arrow = OutputFormat::Arrow.content_type();
+    let (_, content_type, body) = send(query_request("SELECT id FROM numbers", Some(arrow))).await;
+    assert_eq!(content_type, arrow);
+    let reader = StreamReader::try_new(body.as_slice(), None).unwrap();
+    let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+    assert_eq!(rows, 3);
+
+    let (status, _, _) = send(query_request("SELECT 1", Some("application/xml"))).await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn test_spilled_results_are_sent_from_disk() {
+    use igloo_engine::spool::ResultSpool;
+
+    let dir = std::env::temp_dir().join(format!("igloo-http-spool-{}", std::process::id()));
+    let spool = ResultSpool::new(&dir).unwrap().with_threshold(0);
+    let engine = numbers().as_ref().clone().with_result_spool(Arc::new(spool));
+    let app = router(Arc::new(engine));
+    let (status, content_type, body) =
+        send_to(&app, query_request("SELECT id FROM numbers", Some("text/csv"))).await;
+    assert_eq!((status, content_type.as_str()), (StatusCode::OK, "text/csv"));
+    assert_eq!(String::from_utf8(body).unwrap(), "id\n1\n2\n3\n");
+    // The spilled file is gone once the response is sent.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_query_errors_are_api_errors() {
+    let (status, _, body) = send(query_request("SELECT * FROM missing", None)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "plan_error");
+    assert_eq!(error["retryable"], false);
+}
+
+#[tokio::test]
+async fn test_tables_and_health() {
+    let (status, _, body) = send(Request::get("/tables").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    let tables: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        tables,
+        serde_json::json!([{"catalog": "datafusion", "schema": "public", "name": "numbers"}])
+    );
+
+    let (status, _, body) = send(Request::get("/health").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, br#"{"status":"ok"}"#);
+}
+
+#[tokio::test]
+async fn test_readiness_reports_each_probe() {
+    let engine = Arc::new(QueryEngine::new());
+    let local = ObjectStoreUrl::local_filesystem();
+    // Nothing listens on a port right after its listener is dropped.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let readiness = Readiness::new()
+        .with_probe(ObjectStoreProbe::new("object_store", engine.clone(), local))
+        .with_probe(LagProbe::new("cdc", Duration::from_secs(30), || Some(Duration::ZERO)))
+        .with_probe(TcpProbe::new("postgres", closed.to_string()));
+    let app = router_with_options(engine, HttpOptions::new().with_readiness(readiness));
+
+    let response = app.clone().oneshot(Request::get("/healthz").body(Body::empty()).unwrap());
+    assert_eq!(response.await.unwrap().status(), StatusCode::OK);
+
+    let response = app.oneshot(Request::get("/readyz").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["status"], "not_ready");
+    let statuses: Vec<_> = report["checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| (check["name"].as_str().unwrap(), check["status"].as_str().unwrap()))
+        .collect();
+    assert_eq!(statuses, [("object_store", "ok"), ("cdc", "ok"), ("postgres", "failed")]);
+    assert!(report["checks"][2]["detail"].is_string());
+}
+
+#[tokio::test]
+async fn test_draining_fails_readiness_and_new_queries() {
+    let engine = numbers();
+    let readiness = Readiness::new().with_probe(DrainProbe::new(engine.clone()));
+    let app = router_with_options(engine.clone(), HttpOptions::new().with_readiness(readiness));
+    let ready = || send_to(&app, Request::get("/readyz").body(Body::empty()).unwrap());
+    assert_eq!(ready().await.0, StatusCode::OK);
+    let (status, _, _) = send_to(&app, query_request("SELECT * FROM numbers", None)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    engine.drain();
+    let (status, _, body) = ready().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["checks"][0]["detail"], "shutting down");
+    let (status, _, body) = send_to(&app, query_request("SELECT * FROM numbers", None)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "resources_exhausted");
+    assert!(error["message"].as_str().unwrap().contains("shutting down"), "{error}");
+    assert_eq!(error["retryable"], true);
+}
+
+#[tokio::test]
+async fn test_auth_protects_everything_but_health_checks() {
+    use igloo_api::auth::{Authenticator, Principal};
+
+    let auth = Arc::new(Authenticator::new().with_api_key("secret", Principal::new("etl")));
+    let app = router_with_options(Arc::new(QueryEngine::new()), HttpOptions::new().with_auth(auth));
+    let tables = |credential: Option<(&str, &str)>| {
+        let mut request = Request::get("/tables");
+        if let Some((name, value)) = credential {
+            request = request.header(name, value);
+        }
+        app.clone().oneshot(request.body(Body::empty()).unwrap())
+    };
+
+    let response = tables(None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "unauthenticated");
+
+    let response = tables(Some(("authorization", "Bearer wrong"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = tables(Some(("authorization", "Bearer secret"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = tables(Some(("x-api-key", "secret"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app.oneshot(Request::get("/healthz").body(Body::empty()).unwrap());
+    assert_eq!(response.await.unwrap().status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_queries_follow_the_principals_policies() {
+    use igloo_api::auth::{Authenticator, Principal};
+    use igloo_engine::policy::{PolicySet, RowFilter};
+
+    let engine = Arc::new(QueryEngine::new());
+    engine.query("CREATE TABLE numbers (id BIGINT) AS VALUES (1), (2), (3)").await.unwrap();
+    engine.set_policies(
+        PolicySet::new().with_row_filter(RowFilter::new("numbers", "id > 1").except("admin")),
+    );
+    let auth = Authenticator::new()
+        .with_api_key("analyst-key", Principal::new("analyst"))
+        .with_api_key("admin-key", Principal::new("root").with_roles(["admin"]));
+    let app = router_with_options(engine, HttpOptions::new().with_auth(Arc::new(auth)));
+
+    for (key, expected) in [("analyst-key", 2), ("admin-key", 3)] {
+        let mut request = query_request("SELECT count(*) AS n FROM numbers", None);
+        request.headers_mut().insert("x-api-key", key.parse().unwrap());
+        let response = app.clone().oneshot(request).await.unwrap();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rows, serde_json::json!([{ "n": expected }]), "{key}");
+    }
+}
+
+#[tokio::test]
+async fn test_tenants_see_only_their_own_tables() {
+    use igloo_api::auth::{Authenticator, Principal};
+    use igloo_engine::tenant::Tenant;
+
+    let engine = Arc::new(QueryEngine::new());
+    for tenant in ["acme", "globex"] {
+        let tenant = engine.add_tenant(Tenant::new(tenant)).unwrap();
+        tenant.query("CREATE TABLE orders (id BIGINT) AS VALUES (1)").await.unwrap();
+    }
+    engine.tenant("acme").unwrap().query("INSERT INTO orders VALUES (2)").await.unwrap();
+    let auth = Authenticator::new()
+        .with_api_key("acme-key", Principal::new("etl").with_tenant("acme"))
+        .with_api_key("globex-key", Principal::new("etl").with_tenant("globex"))
+        .with_api_key("initech-key", Principal::new("etl").with_tenant("initech"));
+    let app = router_with_options(engine, HttpOptions::new().with_auth(Arc::new(auth)));
+    let as_tenant = |mut request: Request<Body>, key: &str| {
+        request.headers_mut().insert("x-api-key", key.parse().unwrap());
+        request
+    };
+
+    for (key, expected) in [("acme-key", 2), ("globex-key", 1)] {
+        let request = as_tenant(query_request("SELECT count(*) AS n FROM orders", None), key);
+        let (status, _, body) = send_to(&app, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(rows, serde_json::json!([{ "n": expected }]), "{key}");
+    }
+    let request = as_tenant(Request::get("/tables").body(Body::empty()).unwrap(), "acme-key");
+    let (_, _, body) = send_to(&app, request).await;
+    let tables: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        tables,
+        serde_json::json!([{"catalog": "datafusion", "schema": "public", "name": "orders"}])
+    );
+
+    let (status, _, body) =
+        send_to(&app, as_tenant(query_request("SELECT 1", None), "initech-key")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "permission_denied");
+}
+
+#[tokio::test]
+async fn test_validate_plans_without_running() {
+    use igloo_api::auth::{Authenticator, Principal};
+    use igloo_engine::tenant::Tenant;
+
+    let engine = Arc::new(QueryEngine::new());
+    let acme = engine.add_tenant(Tenant::new("acme")).unwrap();
+    acme.query("CREATE TABLE orders (id BIGINT) AS VALUES (1)").await.unwrap();
+    let auth = Authenticator::new()
+        .with_api_key("acme-key", Principal::new("etl").with_tenant("acme"))
+        .with_api_key("root-key", Principal::new("root"));
+    let app = router_with_options(engine, HttpOptions::new().with_auth(Arc::new(auth)));
+    let validate = |sql: &str, key: &str| {
+        Request::post("/validate")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-api-key", key)
+            .body(Body::from(serde_json::json!({ "sql": sql }).to_string()))
+            .unwrap()
+    };
+
+    let request = validate("SELECT id FROM orders WHERE id > 1 LIMIT 3", "acme-key");
+    let (status, _, body) = send_to(&app, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    let validation: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(validation["columns"][0]["name"], "id");
+    assert_eq!(validation["tables"], serde_json::json!(["orders"]));
+    assert_eq!(validation["scans"][0]["columns"], serde_json::json!(["id"]));
+
+    let (status, _, _) = send_to(&app, validate("DELETE FROM orders", "acme-key")).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut request = query_request("SELECT count(*) AS n FROM orders", None);
+    request.headers_mut().insert("x-api-key", "acme-key".parse().unwrap());
+    let (_, _, body) = send_to(&app, request).await;
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, serde_json::json!([{ "n": 1 }]));
+    // The tenant's tables are not the root engine's.
+    let (status, _, body) = send_to(&app, validate("SELECT id FROM orders", "root-key")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", String::from_utf8_lossy(&body));
+}
+
+#[tokio::test]
+async fn test_quality_check_results_are_served() {
+    use igloo_engine::quality::{Check, QualityCheck, QualityChecks};
+
+    let engine = numbers();
+    let checks = Arc::new(QualityChecks::new(vec![
+        QualityCheck::new("numbers", Check::Unique { columns: vec!["id".to_string()] }),
+        QualityCheck::new("missing", Check::RowCountDelta { max_change: 0.1 }),
+    ]));
+    checks.run(&engine, 0).await;
+    let app = router_with_options(engine, HttpOptions::new().with_quality(checks.clone()));
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+
+    let (status, _, body) = send_to(&app, get("/admin/quality")).await;
+    assert_eq!(status, StatusCode::OK);
+    let results: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(results[0]["name"], "unique numbers(id)");
+    assert_eq!(results[0]["status"], "passed");
+    assert_eq!(results[0]["observed"], 0.0);
+    assert_eq!(results[1]["status"], "pending");
+
+    let (_, _, body) = send_to(&app, get("/metrics")).await;
+    let text = String::from_utf8(body).unwrap();
+    assert!(text.contains("igloo_quality_check_passed{check=\"unique numbers(id)\"} 1\n"));
+    assert!(!text.contains("missing"), "{text}");
+
+    // Without checks, there is no such route.
+    let (status, _, _) = send_to(&router(numbers()), get("/admin/quality")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_component_status_is_served() {
+    use igloo_api::supervisor::{RestartPolicy, Supervisor};
+
+    let engine = numbers();
+    let supervisor = Arc::new(Supervisor::new());
+    supervisor.spawn("http", RestartPolicy::Always, |stop| async move {
+        stop.stopped().await;
+        Ok::<_, String>(())
+    });
+    let options = HttpOptions::new().with_supervisor(supervisor.clone());
+    let app = router_with_options(engine.clone(), options);
+    let status = || send_to(&app, Request::get("/admin/status").body(Body::empty()).unwrap());
+
+    let (code, _, body) = status().await;
+    assert_eq!(code, StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["status"], "ok");
+    assert_eq!(report["components"][0]["name"], "http");
+    assert_eq!(report["components"][0]["state"], "running");
+    assert_eq!(report["components"][0]["restart_policy"], "always");
+
+    supervisor.spawn("cdc", RestartPolicy::Never, |_| async { Err("proxy unreachable") });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let report: serde_json::Value = serde_json::from_slice(&status().await.2).unwrap();
+    assert_eq!(report["status"], "degraded");
+    assert_eq!(report["components"][1]["last_error"], "proxy unreachable");
+
+    engine.drain();
+    let report: serde_json::Value = serde_json::from_slice(&status().await.2).unwrap();
+    assert_eq!(report["status"], "draining");
+    assert!(supervisor.shutdown(Duration::from_secs(1)).await);
+}
+
+#[tokio::test]
+async fn test_queries_are_audited() {
+    use igloo_api::audit::{Auditor, Outcome, TableAuditSink};
+    use igloo_api::auth::{Authenticator, Principal};
+
+    let engine = Arc::new(QueryEngine::new());
+    engine.query("CREATE TABLE numbers (id BIGINT) AS VALUES (1), (2), (3)").await.unwrap();
+    let sink = TableAuditSink::new();
+    engine.register_table("audit_log", Arc::new(sink.clone())).unwrap();
+    let auth = Authenticator::new().with_api_key("key", Principal::new("analyst"));
+    let options = HttpOptions::new()
+        .with_auth(Arc::new(auth))
+        .with_audit(Arc::new(Auditor::new(sink.clone()).with_sql_text(false)));
+    let app = router_with_options(engine, options);
+
+    for sql in ["SELECT id FROM numbers WHERE id > 1", "SELECT * FROM missing"] {
+        let mut request = query_request(sql, None);
+        request.headers_mut().insert("x-api-key", "key".parse().unwrap());
+        app.clone().oneshot(request).await.unwrap();
+    }
+
+    let records = sink.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].principal.as_deref(), Some("analyst"));
+    assert_eq!(records[0].frontend, "http");
+    assert_eq!(records[0].sql, None);
+    assert_eq!(records[0].tables, ["numbers"]);
+    assert_eq!(records[0].rows, Some(2));
+    assert_eq!(records[0].outcome, Outcome::Ok);
+    assert_eq!(records[1].outcome, Outcome::Error);
+    assert!(records[1].error.as_ref().unwrap().contains("missing"));
+
+    // The log is queryable like any other table.
+    let mut request = query_request(
+        "SELECT outcome, count(*) AS n FROM audit_log GROUP BY outcome ORDER BY outcome",
+        None,
+    );
+    request.headers_mut().insert("x-api-key", "key".parse().unwrap());
+    let response = app.oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let expected = serde_json::json!([{ "outcome": "error", "n": 1 }, { "outcome": "ok", "n": 1 }]);
+    assert_eq!(rows, expected);
+}
+
+#[tokio::test]
+async fn test_quota_exceeded_is_too_many_requests() {
+    use igloo_api::quota::{QuotaLimiter, Quotas};
+
+    let quotas = QuotaLimiter::new(Quotas::new().with_queries_per_minute(1));
+    let app = router_with_options(
+        Arc::new(QueryEngine::new()),
+        HttpOptions::new().with_quotas(Arc::new(quotas)),
+    );
+    let (status, _, _) = send_to(&app, query_request("SELECT 1", None)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, body) = send_to(&app, query_request("SELECT 1", None)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["code"], "quota_exceeded");
+    assert_eq!(error["detail"], "queries_per_minute");
+    assert_eq!(error["retryable"], true);
+}
+
+#[tokio::test]
+async fn test_set_applies_to_the_session() {
+    use igloo_api::session::SESSION_HEADER;
+
+    let app = app();
+    let in_session = |sql: &str, session: &str| {
+        let mut request = query_request(sql, None);
+        request.headers_mut().insert(SESSION_HEADER, session.parse().unwrap());
+        request
+    };
+    let (status, _, _) = send_to(&app, in_session("SET output_format = 'csv'", "a")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, content_type, body) =
+        send_to(&app, in_session("SELECT id FROM numbers WHERE id = 1", "a")).await;
+    assert_eq!(content_type, "text/csv");
+    assert_eq!(String::from_utf8(body).unwrap(), "id\n1\n");
+
+    // Other sessions, and requests without one, keep the defaults.
+    let (_, content_type, _) = send_to(&app, in_session("SELECT 1", "b")).await;
+    assert_eq!(content_type, "application/json");
+    let (status, _, body) = send_to(&app, query_request("SET output_format = 'csv'", None)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", String::from_utf8_lossy(&body));
+}
+
+#[tokio::test]
+async fn test_websocket_streams_batches_then_completes() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app()).await });
+
+    let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://{addr}/query/ws")).await.unwrap();
+    let request = serde_json::json!({ "sql": "SELECT id FROM numbers ORDER BY id" });
+    socket.send(Message::text(request.to_string())).await.unwrap();
+
+    let mut types = Vec::new();
+    let mut ids = Vec::new();
+    while let Some(message) = socket.next().await {
+        let message: serde_json::Value =
+            serde_json::from_str(message.unwrap().to_text().unwrap()).unwrap();
+        let kind = message["type"].as_str().unwrap().to_string();
+        types.push(kind.clone());
+        if kind == "batch" {
+            ids.extend(message["rows"].as_array().unwrap().iter().map(|r| r["id"].clone()));
+        } else if kind == "complete" {
+            assert_eq!(message["rows"], 3);
+            break;
+        }
+    }
+    assert_eq!(types.first().map(String::as_str), Some("schema"));
+    assert!(types.contains(&"progress".to_string()));
+    assert_eq!(ids, vec![serde_json::json!(1), serde_json::json!(2), serde_json::json!(3)]);
+
+    socket.send(Message::text(r#"{"sql": "SELECT * FROM missing"}"#)).await.unwrap();
+    let message = socket.next().await.unwrap().unwrap();
+    let message: serde_json::Value = serde_json::from_str(message.to_text().unwrap()).unwrap();
+    assert_eq!(message["type"], "error");
+    assert_eq!(message["code"], "plan_error");
+}
+
+#[tokio::test]
+async fn test_jobs_spool_results_in_pages() {
+    use igloo_api::jobs::JobManager;
+    use igloo_engine::scheduler::Scheduler;
+    use object_store::memory::InMemory;
+
+    let jobs = JobManager::new(Scheduler::new(2, 8), Arc::new(InMemory::new())).with_page_rows(2);
+    let app = router_with_options(numbers(), HttpOptions::new().with_jobs(Arc::new(jobs)));
+    let submit = Request::post("/jobs")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(r#"{"sql": "SELECT id FROM numbers ORDER BY id"}"#))
+        .unwrap();
+    let (status, _, body) = send_to(&app, submit).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let id = job["id"].as_str().unwrap().to_string();
+
+    let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+    let job = loop {
+        let (status, _, body) = send_to(&app, get(format!("/jobs/{id}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        let job: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        if job["state"] != "queued" && job["state"] != "running" {
+            break job;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(job["state"], "succeeded");
+    assert_eq!((job["pages"].as_u64(), job["rows"].as_u64()), (Some(2), Some(3)));
+
+    let page = |n: usize| app.clone().oneshot(get(format!("/jobs/{id}/results?page={n}")));
+    let response = page(0).await.unwrap();
+    assert_eq!(response.headers()["x-igloo-last-page"], "false");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()[1]["id"], 2);
+    let response = page(1).await.unwrap();
+    assert_eq!(response.headers()["x-igloo-last-page"], "true");
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()[0]["id"], 3);
+    assert_eq!(page(2).await.unwrap().status(), StatusCode::NOT_FOUND);
+
+    let delete = Request::delete(format!("/jobs/{id}")).body(Body::empty()).unwrap();
+    assert_eq!(send_to(&app, delete).await.0, StatusCode::NO_CONTENT);
+    let (status, _, body) = send_to(&app, get(format!("/jobs/{id}"))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["code"], "not_found");
+}
+
+#[tokio::test]
+async fn test_state_moves_between_engines() {
+    let dir = std::env::temp_dir().join(format!("igloo-http-state-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db")).unwrap());
+    let source = router(Arc::new(QueryEngine::new().with_catalog_store(store).await.unwrap()));
+    let sql = "CREATE VIEW answer AS SELECT 42 AS n";
+    assert_eq!(send_to(&source, query_request(sql, None)).await.0, StatusCode::OK);
+
+    let (status, _, bundle) =
+        send_to(&source, Request::get("/admin/state").body(Body::empty()).unwrap()).await;
+    assert_eq!(status, StatusCode::OK);
+    let target = router(Arc::new(QueryEngine::new()));
+    let request = Request::put("/admin/state")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(bundle))
+        .unwrap();
+    let (status, _, body) = send_to(&target, request).await;
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["entries"], 1);
+    let (_, _, body) = send_to(&target, query_request("SELECT n FROM answer", None)).await;
+    let rows: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(rows, serde_json::json!([{ "n": 42 }]));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_lineage_traces_a_query_to_its_sources() {
+    let dir = std::env::temp_dir().join(format!("igloo-http-lineage-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db")).unwrap());
+    let engine = Arc::new(QueryEngine::new().with_catalog_store(store).await.unwrap());
+    let app = router(engine);
+    for sql in [
+        "CREATE TABLE raw (id BIGINT, amount DOUBLE)",
+        "CREATE TABLE totals AS SELECT id, amount * 2 AS doubled FROM raw",
+        "SELECT sum(doubled) AS total FROM totals",
+    ] {
+        assert_eq!(send_to(&app, query_request(sql, None)).await.0, StatusCode::OK);
+    }
+
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+    let uri = "/lineage?kind=query&target=SELECT%20sum(doubled)%20AS%20total%20FROM%20totals";
+    let (status, _, body) = send_to(&app, get(uri)).await;
+    assert_eq!(status, StatusCode::OK);
+    let edges: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        edges,
+        serde_json::json!([
+            {"target_kind": "query", "target": "SELECT sum(doubled) AS total FROM totals",
+             "column": "total", "source": "datafusion.public.totals", "source_column": "doubled"},
+            {"target_kind": "table", "target": "datafusion.public.totals",
+             "column": "doubled", "source": "datafusion.public.raw", "source_column": "amount"},
+        ])
+    );
+    let (_, _, body) = send_to(&app, get("/lineage?target=totals&column=id")).await;
+    let edges: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(edges[0]["source_column"], "id");
+    assert_eq!(edges.as_array().unwrap().len(), 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_catalog_sync_reports_drift() {
+    let dir = std::env::temp_dir().join(format!("igloo-http-sync-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let engine = Arc::new(QueryEngine::new());
+    let source = Arc::new(Directory(dir.clone()));
+    engine.register_catalog_source("files", source).await.unwrap();
+    let app = router(engine);
+    std::fs::write(dir.join("orders.csv"), "id,amount\n1,10\n").unwrap();
+
+    let post = |uri: &str| Request::post(uri).body(Body::empty()).unwrap();
+    let (status, _, body) = send_to(&app, post("/catalogs/files/sync?dry_run=true")).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        report,
+        serde_json::json!({
+            "catalog": "files", "applied": false, "errors": [],
+            "drift": [{"kind": "table_added", "schema": "public", "table": "orders"}],
+        })
+    );
+    let sql = "SELECT amount FROM files.public.orders";
+    assert_eq!(send_to(&app, query_request(sql, None)).await.0, StatusCode::BAD_REQUEST);
+    let (_, _, body) = send_to(&app, post("/catalogs/files/sync")).await;
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["applied"], true);
+    assert_eq!(send_to(&app, query_request(sql, None)).await.0, StatusCode::OK);
+
+    let (status, _, _) = send_to(&app, post("/catalogs/missing/sync")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// The CSV files of a directory, as the tables of a `public` schema.
+#[derive(Debug)]
+struct Directory(std::path::PathBuf);
+
+#[async_trait::async_trait]
+impl CatalogSource for Directory {
+    async fn load_catalog(&self) -> datafusion::error::Result<Arc<dyn CatalogProvider>> {
+        let ctx = SessionContext::new();
+        for entry in std::fs::read_dir(&self.0)? {
+            let path = entry?.path();
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            let path = path.to_string_lossy().to_string();
+            ctx.register_csv(&name, &path, CsvReadOptions::new()).await?;
+        }
+        Ok(ctx.catalog("datafusion").unwrap())
+    }
+}
+
+#[tokio::test]
+async fn test_memory_report_and_metrics() {
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+    let (status, _, body) = send(get("/admin/memory")).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["pools"][0]["name"], "engine");
+    assert_eq!(report["caches"][0]["name"], "ingest_dedup");
+    assert_eq!(report["queries"], serde_json::json!([]));
+
+    let (status, content_type, body) = send(get("/metrics")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/plain"));
+    let text = String::from_utf8(body).unwrap();
+    assert!(text.contains("igloo_pool_reserved_bytes{pool=\"engine\"} 0\n"), "{text}");
+}
+
+#[tokio::test]
+async fn test_failed_queries_are_reported() {
+    let app = app();
+    let get = || Request::get("/admin/errors").body(Body::empty()).unwrap();
+    let (_, _, body) = send_to(&app, get()).await;
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap(), serde_json::json!([]));
+
+    let (status, _, _) = send_to(&app, query_request("SELECT * FROM missing", None)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let sql = "SELECT * FROM 'postgres://igloo:hunter2@db/app'";
+    send_to(&app, query_request(sql, None)).await;
+    send_to(&app, query_request("SELECT 1", None)).await;
+
+    let (status, _, body) = send_to(&app, get()).await;
+    assert_eq!(status, StatusCode::OK);
+    let errors: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(errors.as_array().unwrap().len(), 2);
+    assert!(!errors[0]["sql"].as_str().unwrap().contains("hunter2"));
+    assert_eq!(errors[1]["sql"], "SELECT * FROM missing");
+    let report = errors[1]["report"].as_str().unwrap();
+    assert!(report.starts_with("Error: Error during planning: table"), "{report}");
+    assert!(errors[1]["failed_at_ms"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_running_queries_are_listed_and_killed() {
+    let engine = numbers();
+    let app = router(engine.clone());
+    let running = engine.with_running_query(QueryStart {
+        query_id: "q1".to_string(),
+        principal: Some("alice".to_string()),
+        frontend: "pgwire".to_string(),
+        sql: "SELECT * FROM numbers".to_string(),
+    });
+
+    let get = Request::get("/admin/queries").body(Body::empty()).unwrap();
+    let (status, _, body) = send_to(&app, get).await;
+    assert_eq!(status, StatusCode::OK);
+    let queries: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(queries[0]["query_id"], "q1");
+    assert_eq!(queries[0]["stage"], "planning");
+
+    let kill = |id: &str| Request::delete(format!("/admin/queries/{id}")).body(Body::empty());
+    let (status, _, _) = send_to(&app, kill("q1").unwrap()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let error = running.sql("SELECT * FROM numbers").await.unwrap().collect().await.unwrap_err();
+    assert!(error.to_string().contains("was killed"), "{error}");
+    let (status, _, _) = send_to(&app, kill("q2").unwrap()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_profiled_queries_return_a_timeline() {
+    use igloo_api::http::PROFILE_HEADER;
+    use igloo_api::session::SESSION_HEADER;
+
+    let app = app();
+    let in_session = |sql: &str| {
+        let mut request = query_request(sql, None);
+        request.headers_mut().insert(SESSION_HEADER, "a".parse().unwrap());
+        request
+    };
+    let (status, _, _) = send_to(&app, in_session("SET profile = on")).await;
+    assert_eq!(status, StatusCode::OK);
+    let response = app.clone().oneshot(in_session("SELECT * FROM numbers")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let query_id = response.headers()[PROFILE_HEADER].to_str().unwrap().to_string();
+
+    let get = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+    let (status, _, body) = send_to(&app, get(format!("/admin/profiles/{query_id}"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let timeline: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(timeline["query_id"], query_id);
+    assert_eq!(timeline["spans"][0]["name"], "queued");
+
+    let chrome = format!("/admin/profiles/{query_id}?format=chrome");
+    let (status, _, body) = send_to(&app, get(chrome)).await;
+    assert_eq!(status, StatusCode::OK);
+    let trace: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(trace["traceEvents"].as_array().unwrap().len() > 3);
+
+    let response = app.clone().oneshot(query_request("SELECT 1", None)).await.unwrap();
+    assert!(!response.headers().contains_key(PROFILE_HEADER));
+    let (status, _, _) = send_to(&app, get("/admin/profiles/unknown".to_string())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
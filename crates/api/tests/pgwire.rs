@@ -0,0 +1,143 @@
+use datafusion::arrow::array::{Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::MemTable;
+use igloo_engine::QueryEngine;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_postgres::{Client, NoTls, SimpleQueryMessage};
+
+/// Start a pgwire server over a small `numbers` table and connect a client to it.
+async fn start_server() -> Client {
+    connect(listen().await).await
+}
+
+/// Start a pgwire server over a small `numbers` table.
+async fn listen() -> SocketAddr {
+    let engine = Arc::new(QueryEngine::new());
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, false),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec!["one", "two", "three"])),
+        ],
+    )
+    .unwrap();
+    let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+    engine.register_table("numbers", Arc::new(table)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(igloo_api::pgwire::serve(listener, engine));
+    addr
+}
+
+async fn connect(addr: SocketAddr) -> Client {
+    let config = format!("host={} port={} user=igloo", addr.ip(), addr.port());
+    let (client, connection) = tokio_postgres::connect(&config, NoTls).await.unwrap();
+    tokio::spawn(connection);
+    client
+}
+
+#[tokio::test]
+async fn test_simple_query() {
+    let client = start_server().await;
+    let messages = client.simple_query("SELECT id, name FROM numbers WHERE id > 1").await.unwrap();
+    let rows: Vec<_> = messages
+        .iter()
+        .filter_map(|m| match m {
+            SimpleQueryMessage::Row(row) => Some((row.get(0).unwrap(), row.get(1).unwrap())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(rows, vec![("2", "two"), ("3", "three")]);
+}
+
+#[tokio::test]
+async fn test_extended_query_with_parameters() {
+    let client = start_server().await;
+    let rows = client.query("SELECT id, name FROM numbers WHERE id = $1", &[&2i64]).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0].get::<_, i64>(0), 2);
+    assert_eq!(rows[0].get::<_, String>(1), "two");
+}
+
+#[tokio::test]
+async fn test_dictionary_columns_are_sent_as_their_values() {
+    let client = start_server().await;
+    let sql = "SELECT arrow_cast(name, 'Dictionary(Int32, Utf8)') AS name FROM numbers ORDER BY id";
+    let rows = client.query(sql, &[]).await.unwrap();
+    assert_eq!(rows[0].columns()[0].type_(), &tokio_postgres::types::Type::VARCHAR);
+    let names: Vec<String> = rows.iter().map(|row| row.get(0)).collect();
+    assert_eq!(names, ["one", "two", "three"]);
+}
+
+#[tokio::test]
+async fn test_ddl_and_dml_report_command_tags() {
+    let client = start_server().await;
+    client.batch_execute("CREATE TABLE t (x INT)").await.unwrap();
+    let inserted = client.execute("INSERT INTO t VALUES (1), (2)", &[]).await.unwrap();
+    assert_eq!(inserted, 2);
+    let row = client.query_one("SELECT count(*) FROM t", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i64>(0), 2);
+}
+
+#[tokio::test]
+async fn test_errors_carry_sqlstate() {
+    let client = start_server().await;
+    let err = client.simple_query("SELECT * FROM missing").await.unwrap_err();
+    assert_eq!(err.code().unwrap().code(), "42P01");
+    let err = client.simple_query("SELEC 1").await.unwrap_err();
+    assert_eq!(err.code().unwrap().code(), "42601");
+    assert_eq!(err.as_db_error().unwrap().hint(), Some("Check the statement syntax."));
+}
+
+#[tokio::test]
+async fn test_password_is_checked_as_credential() {
+    use igloo_api::auth::{Authenticator, Principal};
+    use igloo_api::pgwire::IglooPgServer;
+
+    let auth = Arc::new(Authenticator::new().with_api_key("secret", Principal::new("etl")));
+    let server = IglooPgServer::new(Arc::new(QueryEngine::new())).with_auth(auth);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(igloo_api::pgwire::serve_with(listener, server));
+
+    let config = format!("host={} port={} user=igloo password=", addr.ip(), addr.port());
+    let Err(err) = tokio_postgres::connect(&format!("{config}wrong"), NoTls).await else {
+        panic!("connected with a wrong password");
+    };
+    assert_eq!(err.code().map(|c| c.code()), Some("28P01"));
+
+    let (client, connection) =
+        tokio_postgres::connect(&format!("{config}secret"), NoTls).await.unwrap();
+    tokio::spawn(connection);
+    let rows = client.query("SELECT 1::BIGINT", &[]).await.unwrap();
+    assert_eq!(rows[0].get::<_, i64>(0), 1);
+}
+
+#[tokio::test]
+async fn test_set_is_per_connection() {
+    let addr = listen().await;
+    let client = connect(addr).await;
+    client.batch_execute("CREATE SCHEMA sales; CREATE TABLE sales.orders (x INT)").await.unwrap();
+    client.batch_execute("INSERT INTO sales.orders VALUES (7)").await.unwrap();
+    client.simple_query("SELECT * FROM orders").await.unwrap_err();
+
+    client.batch_execute("SET search_path TO sales").await.unwrap();
+    let row = client.query_one("SELECT x FROM orders", &[]).await.unwrap();
+    assert_eq!(row.get::<_, i32>(0), 7);
+
+    // Other connections keep the defaults.
+    let other = connect(addr).await;
+    other.simple_query("SELECT * FROM orders").await.unwrap_err();
+    other.simple_query("SELECT * FROM numbers").await.unwrap();
+
+    let err = client.simple_query("SET no_such_variable = 1").await.unwrap_err();
+    assert!(err.as_db_error().unwrap().message().contains("no_such_variable"), "{err:?}");
+}
@@ -0,0 +1,156 @@
+use arrow_flight::flight_service_client::FlightServiceClient;
+use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::Criteria;
+use igloo_api::http::HttpOptions;
+use igloo_api::pgwire::IglooPgServer;
+use igloo_api::tls::TlsConfig;
+use igloo_api::IglooFlightService;
+use igloo_common::catalog::MemoryCatalog;
+use igloo_engine::QueryEngine;
+use rcgen::{BasicConstraints, CertificateParams, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Server};
+
+/// A throwaway CA with a `localhost` server certificate and a client certificate.
+struct Pki {
+    ca: String,
+    server: (String, String),
+    client: (String, String),
+}
+
+impl Pki {
+    fn new() -> Self {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+        let issue = |name: &str, usage| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+        Self {
+            server: issue("localhost", ExtendedKeyUsagePurpose::ServerAuth),
+            client: issue("client", ExtendedKeyUsagePurpose::ClientAuth),
+            ca: ca.pem(),
+        }
+    }
+
+    fn server_config(&self) -> TlsConfig {
+        TlsConfig::from_pem(self.server.0.as_str(), self.server.1.as_str())
+    }
+
+    /// A rustls client trusting the CA, presenting the client certificate if `mtls`.
+    fn connector(&self, mtls: bool) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut self.ca.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = if mtls {
+            let chain: Vec<CertificateDer> = rustls_pemfile::certs(&mut self.client.0.as_bytes())
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let key: PrivateKeyDer =
+                rustls_pemfile::private_key(&mut self.client.1.as_bytes()).unwrap().unwrap();
+            builder.with_client_auth_cert(chain, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+        TlsConnector::from(Arc::new(config))
+    }
+}
+
+/// `GET /healthz` over TLS, returning the status line (empty if the server hung up).
+async fn healthz(addr: std::net::SocketAddr, connector: TlsConnector) -> String {
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let server_name = ServerName::try_from("localhost").unwrap();
+    let Ok(mut stream) = connector.connect(server_name, socket).await else {
+        return String::new();
+    };
+    let request = b"GET /healthz HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+    if stream.write_all(request).await.is_err() {
+        return String::new();
+    }
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    String::from_utf8_lossy(&response).lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn test_https_with_client_certificates() {
+    let pki = Pki::new();
+    let tls = pki.server_config().with_client_ca(pki.ca.as_str());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let engine = Arc::new(QueryEngine::new());
+    let options = HttpOptions::new().with_tls(tls);
+    tokio::spawn(igloo_api::http::serve_with_options(listener, engine, options));
+
+    assert_eq!(healthz(addr, pki.connector(true)).await, "HTTP/1.1 200 OK");
+    assert_eq!(healthz(addr, pki.connector(false)).await, "");
+}
+
+#[tokio::test]
+async fn test_pgwire_accepts_ssl_requests() {
+    let pki = Pki::new();
+    let server =
+        IglooPgServer::new(Arc::new(QueryEngine::new())).with_tls(&pki.server_config()).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(igloo_api::pgwire::serve_with(listener, server));
+
+    // SSLRequest: length 8, then the magic code 80877103.
+    let mut socket = TcpStream::connect(addr).await.unwrap();
+    socket.write_all(&[0, 0, 0, 8, 0x04, 0xd2, 0x16, 0x2f]).await.unwrap();
+    assert_eq!(socket.read_u8().await.unwrap(), b'S');
+    let server_name = ServerName::try_from("localhost").unwrap();
+    pki.connector(false).connect(server_name, socket).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_flight_over_tls() {
+    let pki = Pki::new();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service =
+        IglooFlightService::new(Arc::new(QueryEngine::new()), Arc::new(MemoryCatalog::new()));
+    tokio::spawn(
+        Server::builder()
+            .tls_config(pki.server_config().grpc_config())
+            .unwrap()
+            .add_service(FlightServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let tls = ClientTlsConfig::new()
+        .ca_certificate(Certificate::from_pem(&pki.ca))
+        .domain_name("localhost");
+    let channel = Channel::from_shared(format!("https://{addr}"))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let mut client = FlightServiceClient::new(channel);
+    assert!(client.list_flights(Criteria::default()).await.is_ok());
+}
+
+#[test]
+fn test_invalid_pem_is_rejected() {
+    let err = TlsConfig::from_pem("not a certificate", "not a key").server_config().unwrap_err();
+    assert!(err.to_string().contains("invalid TLS configuration"), "{err}");
+}
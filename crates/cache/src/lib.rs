@@ -1,10 +1,20 @@
 //! Cache crate
 //!
 //! Provides caching primitives and implementations for Igloo components.
+//!
+//! A [`Cache`] holds record batches without copying them: what it is given and what
+//! it returns share their Arrow buffers, so the same data held by the result cache,
+//! a materialized view and the queries reading them is in memory once. Its
+//! [`BufferAccounting`] counts each buffer allocation once, however many entries (or
+//! caches sharing the accounting) reference it.
 
+use arrow::array::{Array, ArrayData};
+use arrow::buffer::Buffer;
 use arrow::record_batch::RecordBatch;
 use igloo_common::Error;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 use tracing::{info, warn};
 
@@ -19,6 +29,7 @@ pub struct CacheConfig {
 #[derive(Debug)]
 pub struct Cache {
     data: RwLock<HashMap<String, Vec<RecordBatch>>>,
+    accounting: Arc<BufferAccounting>,
 }
 
 impl Default for Cache {
@@ -30,11 +41,23 @@ impl Default for Cache {
 impl Cache {
     /// Create a new cache.
     pub fn new() -> Self {
+        Self::with_accounting(Arc::new(BufferAccounting::default()))
+    }
+
+    /// A cache counting its buffers in `accounting`, which caches holding the same
+    /// data can share so that data is counted once.
+    pub fn with_accounting(accounting: Arc<BufferAccounting>) -> Self {
         info!("Creating new Cache");
-        Self { data: RwLock::new(HashMap::new()) }
+        Self { data: RwLock::new(HashMap::new()), accounting }
     }
 
-    /// Get a value from the cache.
+    /// Where the cache's buffers are counted.
+    pub fn accounting(&self) -> &Arc<BufferAccounting> {
+        &self.accounting
+    }
+
+    /// Get a value from the cache. The batches share their buffers with the cached
+    /// ones.
     pub async fn get(&self, key: &str) -> Option<Vec<RecordBatch>> {
         info!(key = %key, "Attempting to get value from cache");
         let data_guard = self.data.read().await;
@@ -47,12 +70,105 @@ impl Cache {
         value
     }
 
-    /// Set a value in the cache.
+    /// Set a value in the cache, without copying it.
     pub async fn put(&self, key: String, value: Vec<RecordBatch>) {
         info!(key = %key, "Setting value in cache");
         let mut data_guard = self.data.write().await;
-        data_guard.insert(key, value);
+        self.accounting.add(&value);
+        if let Some(previous) = data_guard.insert(key, value) {
+            self.accounting.remove(&previous);
+        }
+    }
+
+    /// Remove a value from the cache.
+    pub async fn remove(&self, key: &str) -> Option<Vec<RecordBatch>> {
+        let removed = self.data.write().await.remove(key);
+        if let Some(batches) = &removed {
+            self.accounting.remove(batches);
+        }
+        removed
+    }
+}
+
+/// The buffer allocations referenced by cached batches, each counted once.
+///
+/// Slices of a batch, and batches built from the columns of another, reference the
+/// allocations of the original; they are counted by allocation, not by reference.
+#[derive(Debug, Default)]
+pub struct BufferAccounting {
+    /// References and size of each allocation, by its address.
+    allocations: Mutex<HashMap<usize, (usize, usize)>>,
+    bytes: AtomicUsize,
+}
+
+impl BufferAccounting {
+    /// Bytes of the distinct allocations referenced.
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Reference the allocations of `batches`.
+    pub fn add(&self, batches: &[RecordBatch]) {
+        let mut allocations = self.allocations.lock().expect("buffer accounting lock poisoned");
+        for_each_buffer(batches, |buffer| {
+            let (references, size) = allocations.entry(address(buffer)).or_insert((0, 0));
+            if *references == 0 {
+                *size = allocation_size(buffer);
+                self.bytes.fetch_add(*size, Ordering::Relaxed);
+            }
+            *references += 1;
+        });
+    }
+
+    /// Drop the references of [`add`](Self::add)ed `batches`.
+    pub fn remove(&self, batches: &[RecordBatch]) {
+        let mut allocations = self.allocations.lock().expect("buffer accounting lock poisoned");
+        for_each_buffer(batches, |buffer| {
+            let address = address(buffer);
+            if let Some((references, size)) = allocations.get_mut(&address) {
+                *references -= 1;
+                if *references == 0 {
+                    self.bytes.fetch_sub(*size, Ordering::Relaxed);
+                    allocations.remove(&address);
+                }
+            }
+        });
+    }
+}
+
+/// Bytes of the distinct buffer allocations of `batches`: unlike summing
+/// [`RecordBatch::get_array_memory_size`], data shared between columns or batches is
+/// counted once.
+pub fn memory_size(batches: &[RecordBatch]) -> usize {
+    let mut allocations = HashMap::new();
+    for_each_buffer(batches, |buffer| {
+        allocations.entry(address(buffer)).or_insert_with(|| allocation_size(buffer));
+    });
+    allocations.values().sum()
+}
+
+fn for_each_buffer(batches: &[RecordBatch], mut f: impl FnMut(&Buffer)) {
+    fn visit(data: &ArrayData, f: &mut impl FnMut(&Buffer)) {
+        if let Some(nulls) = data.nulls() {
+            f(nulls.buffer());
+        }
+        data.buffers().iter().for_each(&mut *f);
+        data.child_data().iter().for_each(|child| visit(child, f));
     }
+    for batch in batches {
+        for column in batch.columns() {
+            visit(&column.to_data(), &mut f);
+        }
+    }
+}
+
+fn address(buffer: &Buffer) -> usize {
+    buffer.data_ptr().as_ptr() as usize
+}
+
+/// The size of `buffer`'s allocation, or of what it covers of memory it does not own.
+fn allocation_size(buffer: &Buffer) -> usize {
+    buffer.capacity().max(buffer.ptr_offset() + buffer.len())
 }
 
 /// An in-memory cache for demonstration purposes.
@@ -165,11 +281,8 @@ mod tests {
                         }
                     } else {
                         // Get operation (on a potentially existing or non-existing key)
-                        let other_task_key = format!(
-                            "key_task{}_op{}",
-                            (i + 1) % num_tasks,
-                            j.saturating_sub(1).max(0)
-                        );
+                        let other_task_key =
+                            format!("key_task{}_op{}", (i + 1) % num_tasks, j.saturating_sub(1));
                         let _ = cache_clone.get(&other_task_key).await; // Just perform get
                     }
                 }
@@ -181,6 +294,58 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_shared_buffers_are_counted_once() {
+        let accounting = Arc::new(BufferAccounting::default());
+        let results = Cache::with_accounting(Arc::clone(&accounting));
+        let views = Cache::with_accounting(Arc::clone(&accounting));
+        let batch = create_sample_batch();
+        let size = memory_size(std::slice::from_ref(&batch));
+        assert!(size > 0);
+
+        results.put("q1".to_string(), vec![batch.clone()]).await;
+        assert_eq!(accounting.bytes(), size);
+        // The same data under another key, in another cache and sliced is not copied.
+        results.put("q2".to_string(), vec![batch.slice(1, 2)]).await;
+        let projected = batch.project(&[1]).unwrap();
+        views.put("names".to_string(), vec![projected]).await;
+        assert_eq!(accounting.bytes(), size);
+        let cached = results.get("q2").await.unwrap();
+        assert_eq!(
+            cached[0].column(0).to_data().buffers()[0].data_ptr(),
+            batch.column(0).to_data().buffers()[0].data_ptr()
+        );
+
+        let other = create_sample_batch();
+        views.put("names".to_string(), vec![other.clone()]).await;
+        assert_eq!(accounting.bytes(), size + memory_size(&[other]));
+        results.remove("q1").await.unwrap();
+        assert_eq!(accounting.bytes(), 2 * size);
+        results.remove("q2").await.unwrap();
+        assert_eq!(accounting.bytes(), size);
+        assert!(views.remove("missing").await.is_none());
+        views.remove("names").await.unwrap();
+        assert_eq!(accounting.bytes(), 0);
+    }
+
+    #[test]
+    fn test_memory_size_of_shared_columns() {
+        let batch = create_sample_batch();
+        let twice = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("a", DataType::Int32, false),
+                Field::new("b", DataType::Int32, false),
+            ])),
+            vec![batch.column(0).clone(), batch.column(0).clone()],
+        )
+        .unwrap();
+        assert_eq!(
+            memory_size(std::slice::from_ref(&twice)),
+            memory_size(&[twice.project(&[0]).unwrap()])
+        );
+        assert!(memory_size(&[twice.clone(), twice]) < 2 * batch.get_array_memory_size());
+    }
+
     #[test]
     #[cfg(feature = "in-memory")]
     fn test_in_memory_cache_set_get() {
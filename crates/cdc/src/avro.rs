@@ -0,0 +1,201 @@
+//! Avro-encoded Debezium events.
+
+use crate::debezium::{Envelope, Row};
+use crate::registry::ResolvedSchema;
+use apache_avro::schema::{RecordSchema, ResolvedSchema as AvroNames, Schema};
+use apache_avro::types::Value;
+use datafusion::arrow::datatypes::{DataType, Field, TimeUnit};
+use datafusion::scalar::ScalarValue;
+use igloo_common::error::{Error, Result};
+use std::sync::Arc;
+
+/// Decoder of the events written with one Avro schema.
+#[derive(Debug)]
+pub(crate) struct AvroCodec {
+    /// The referenced schemas, then the envelope's.
+    schemata: Vec<Schema>,
+    fields: Arc<Vec<Field>>,
+}
+
+impl AvroCodec {
+    pub(crate) fn try_new(resolved: &ResolvedSchema) -> Result<Self> {
+        let sources = resolved.references.iter().map(|(_, schema)| schema.schema.as_str());
+        let sources: Vec<&str> = sources.chain([resolved.schema.schema.as_str()]).collect();
+        let schemata = Schema::parse_list(&sources).map_err(|e| avro_error(resolved.id, e))?;
+        let names = AvroNames::try_from(schemata.iter().collect::<Vec<_>>())
+            .map_err(|e| avro_error(resolved.id, e))?;
+        let envelope = schemata.last().expect("the envelope schema is parsed last");
+        let row = match envelope {
+            Schema::Record(envelope) => ["after", "before"]
+                .iter()
+                .find_map(|name| envelope.fields.iter().find(|f| f.name == *name))
+                .map(|field| record(&field.schema, names.get_names())),
+            _ => None,
+        };
+        let Some(Some(row)) = row else {
+            return Err(Error::new(&format!(
+                "Avro schema {} is not a Debezium envelope with `before` and `after` records",
+                resolved.id
+            )));
+        };
+        let fields = row.fields.iter().map(|f| Field::new(&f.name, arrow_type(&f.schema), true));
+        let fields = Arc::new(fields.collect());
+        Ok(Self { schemata, fields })
+    }
+
+    pub(crate) fn decode(&self, id: u32, mut payload: &[u8]) -> Result<Envelope> {
+        let envelope = self.schemata.last().expect("the envelope schema is parsed last");
+        let value = apache_avro::from_avro_datum_schemata(
+            envelope,
+            self.schemata.iter().collect(),
+            &mut payload,
+            None,
+        )
+        .map_err(|e| avro_error(id, e))?;
+        let Value::Record(fields) = value else {
+            return Err(Error::new(&format!("Avro event of schema {id} is not a record")));
+        };
+        let mut envelope = Envelope::new(Arc::clone(&self.fields));
+        for (name, value) in fields {
+            match (name.as_str(), unwrap_union(value)) {
+                ("op", Value::String(op)) => envelope.op = op,
+                ("ts_ms", Value::Long(ts)) => envelope.ts_ms = Some(ts),
+                ("before", Value::Record(row)) => envelope.before = Some(self.row(row)?),
+                ("after", Value::Record(row)) => envelope.after = Some(self.row(row)?),
+                _ => {}
+            }
+        }
+        Ok(envelope)
+    }
+
+    fn row(&self, values: Vec<(String, Value)>) -> Result<Row> {
+        values
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let field = self.fields.iter().find(|f| *f.name() == name)?;
+                Some(to_scalar(value, field.data_type()).map(|scalar| (name, scalar)))
+            })
+            .collect()
+    }
+}
+
+/// The record `schema` is, or is the non-null variant of.
+fn record<'a>(
+    schema: &'a Schema,
+    names: &'a std::collections::HashMap<apache_avro::schema::Name, &'a Schema>,
+) -> Option<&'a RecordSchema> {
+    match schema {
+        Schema::Record(record) => Some(record),
+        Schema::Ref { name } => record(names.get(name)?, names),
+        Schema::Union(union) => union.variants().iter().find_map(|v| record(v, names)),
+        _ => None,
+    }
+}
+
+/// The Arrow type of columns of Avro type `schema`. Nested values are kept as JSON.
+fn arrow_type(schema: &Schema) -> DataType {
+    let utc = || Some(Arc::from("+00:00"));
+    match schema {
+        Schema::Boolean => DataType::Boolean,
+        Schema::Int => DataType::Int32,
+        Schema::Long => DataType::Int64,
+        Schema::Float => DataType::Float32,
+        Schema::Double => DataType::Float64,
+        Schema::Bytes | Schema::Fixed(_) => DataType::Binary,
+        Schema::Decimal(decimal) if decimal.precision <= 38 => {
+            DataType::Decimal128(decimal.precision as u8, decimal.scale as i8)
+        }
+        Schema::Date => DataType::Date32,
+        Schema::TimeMillis => DataType::Time32(TimeUnit::Millisecond),
+        Schema::TimeMicros => DataType::Time64(TimeUnit::Microsecond),
+        Schema::TimestampMillis => DataType::Timestamp(TimeUnit::Millisecond, utc()),
+        Schema::TimestampMicros => DataType::Timestamp(TimeUnit::Microsecond, utc()),
+        Schema::TimestampNanos => DataType::Timestamp(TimeUnit::Nanosecond, utc()),
+        Schema::LocalTimestampMillis => DataType::Timestamp(TimeUnit::Millisecond, None),
+        Schema::LocalTimestampMicros => DataType::Timestamp(TimeUnit::Microsecond, None),
+        Schema::LocalTimestampNanos => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        Schema::Union(union) => match union.variants() {
+            [Schema::Null, schema] | [schema, Schema::Null] => arrow_type(schema),
+            _ => DataType::Utf8,
+        },
+        _ => DataType::Utf8,
+    }
+}
+
+fn unwrap_union(value: Value) -> Value {
+    match value {
+        Value::Union(_, value) => unwrap_union(*value),
+        value => value,
+    }
+}
+
+fn to_scalar(value: Value, data_type: &DataType) -> Result<ScalarValue> {
+    let scalar = match unwrap_union(value) {
+        Value::Null => return ScalarValue::try_from(data_type).map_err(scalar_error),
+        Value::Boolean(b) => ScalarValue::Boolean(Some(b)),
+        Value::Int(i) => ScalarValue::Int32(Some(i)),
+        Value::Long(i) => ScalarValue::Int64(Some(i)),
+        Value::Float(f) => ScalarValue::Float32(Some(f)),
+        Value::Double(f) => ScalarValue::Float64(Some(f)),
+        Value::Bytes(bytes) | Value::Fixed(_, bytes) => ScalarValue::Binary(Some(bytes)),
+        Value::String(s) | Value::Enum(_, s) => ScalarValue::Utf8(Some(s)),
+        Value::Uuid(uuid) => ScalarValue::Utf8(Some(uuid.to_string())),
+        Value::Date(d) => ScalarValue::Date32(Some(d)),
+        Value::TimeMillis(t) => ScalarValue::Time32Millisecond(Some(t)),
+        Value::TimeMicros(t) => ScalarValue::Time64Microsecond(Some(t)),
+        Value::TimestampMillis(t) | Value::LocalTimestampMillis(t) => {
+            ScalarValue::TimestampMillisecond(Some(t), None)
+        }
+        Value::TimestampMicros(t) | Value::LocalTimestampMicros(t) => {
+            ScalarValue::TimestampMicrosecond(Some(t), None)
+        }
+        Value::TimestampNanos(t) | Value::LocalTimestampNanos(t) => {
+            ScalarValue::TimestampNanosecond(Some(t), None)
+        }
+        Value::Decimal(decimal) => match data_type {
+            DataType::Decimal128(precision, scale) => {
+                let bytes = Vec::<u8>::try_from(&decimal).map_err(value_error)?;
+                ScalarValue::Decimal128(Some(i128_from_be(&bytes)), *precision, *scale)
+            }
+            _ => ScalarValue::Utf8(Some(format!("{decimal:?}"))),
+        },
+        value => {
+            let json = serde_json::Value::try_from(value).map_err(value_error)?;
+            ScalarValue::Utf8(Some(json.to_string()))
+        }
+    };
+    scalar.cast_to(data_type).map_err(scalar_error)
+}
+
+/// A big-endian two's complement integer.
+fn i128_from_be(bytes: &[u8]) -> i128 {
+    let negative = bytes.first().is_some_and(|b| b & 0x80 != 0);
+    let mut buf = [if negative { 0xff } else { 0 }; 16];
+    let bytes = &bytes[bytes.len().saturating_sub(16)..];
+    buf[16 - bytes.len()..].copy_from_slice(bytes);
+    i128::from_be_bytes(buf)
+}
+
+fn avro_error(id: u32, e: apache_avro::Error) -> Error {
+    Error::external(format!("Failed to read Avro schema {id}"), e)
+}
+
+fn value_error(e: apache_avro::Error) -> Error {
+    Error::external("Failed to convert an Avro value", e)
+}
+
+fn scalar_error(e: datafusion::error::DataFusionError) -> Error {
+    Error::external("Failed to convert an Avro value", e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decimals_are_sign_extended() {
+        assert_eq!(i128_from_be(&[0x04, 0xd2]), 1234);
+        assert_eq!(i128_from_be(&[0xfb, 0x2e]), -1234);
+        assert_eq!(i128_from_be(&[]), 0);
+    }
+}
@@ -0,0 +1,252 @@
+//! Debezium change events in Avro or Protobuf, decoded with their registered schemas.
+//!
+//! Each message carries the ID of the schema it was written with. Schemas are fetched
+//! from the registry the first time an ID is seen, and the columns of the rows decoded
+//! so far are evolved to take in the new schema's: added columns are appended
+//! (null in earlier rows), removed ones are kept (null in later rows), and numeric
+//! columns are widened. Changes that cannot be represented, such as a column turning
+//! from a number into a string, are errors.
+
+use crate::avro::AvroCodec;
+use crate::protobuf::ProtobufCodec;
+use crate::registry::{split_message, SchemaRegistryClient, SchemaType};
+use datafusion::arrow::array::{ArrayRef, Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::scalar::ScalarValue;
+use igloo_common::error::{Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Column of the operation (`c`, `u`, `d`, `r` or `t`) in decoded batches.
+pub const OP_COLUMN: &str = "__op";
+/// Column of the time the connector processed the change, in milliseconds.
+pub const TS_COLUMN: &str = "__ts_ms";
+
+/// Values of a row, by column.
+pub type Row = BTreeMap<String, ScalarValue>;
+
+/// Kind of change, from an event's `op`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOp {
+    Create,
+    Update,
+    Delete,
+    /// A row read by a snapshot.
+    Read,
+    Truncate,
+}
+
+impl ChangeOp {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ChangeOp::Create => "c",
+            ChangeOp::Update => "u",
+            ChangeOp::Delete => "d",
+            ChangeOp::Read => "r",
+            ChangeOp::Truncate => "t",
+        }
+    }
+
+    fn parse(code: &str) -> Result<Self> {
+        Ok(match code {
+            "c" => ChangeOp::Create,
+            "u" => ChangeOp::Update,
+            "d" => ChangeOp::Delete,
+            "r" => ChangeOp::Read,
+            "t" => ChangeOp::Truncate,
+            _ => return Err(Error::new(&format!("Unknown Debezium operation '{code}'"))),
+        })
+    }
+}
+
+/// A decoded change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    pub op: ChangeOp,
+    pub ts_ms: Option<i64>,
+    /// The row after the change, or before it for deletes.
+    pub row: Row,
+}
+
+/// The fields of an event envelope, as the codecs decode them.
+#[derive(Debug)]
+pub(crate) struct Envelope {
+    /// Columns of the rows of the envelope's schema.
+    pub fields: Arc<Vec<Field>>,
+    pub op: String,
+    pub ts_ms: Option<i64>,
+    pub before: Option<Row>,
+    pub after: Option<Row>,
+}
+
+impl Envelope {
+    pub(crate) fn new(fields: Arc<Vec<Field>>) -> Self {
+        Self { fields, op: String::new(), ts_ms: None, before: None, after: None }
+    }
+}
+
+#[derive(Debug)]
+enum Codec {
+    Avro(AvroCodec),
+    Protobuf(ProtobufCodec),
+}
+
+/// Decoder of the events of one table's topic.
+#[derive(Debug)]
+pub struct DebeziumDecoder {
+    registry: Arc<SchemaRegistryClient>,
+    codecs: HashMap<u32, Arc<Codec>>,
+    columns: Vec<Field>,
+    /// Columns of the last schema evolved into `columns`.
+    evolved: Option<Arc<Vec<Field>>>,
+}
+
+impl DebeziumDecoder {
+    pub fn new(registry: Arc<SchemaRegistryClient>) -> Self {
+        Self { registry, codecs: HashMap::new(), columns: vec![], evolved: None }
+    }
+
+    /// Schema of the batches built from the events decoded so far.
+    pub fn schema(&self) -> SchemaRef {
+        let mut fields = vec![
+            Field::new(OP_COLUMN, DataType::Utf8, false),
+            Field::new(TS_COLUMN, DataType::Int64, true),
+        ];
+        fields.extend(self.columns.iter().cloned());
+        Arc::new(Schema::new(fields))
+    }
+
+    /// Decode a message of the topic, `None` for the tombstones that follow deletes.
+    pub async fn decode(&mut self, message: &[u8]) -> Result<Option<ChangeEvent>> {
+        if message.is_empty() {
+            return Ok(None);
+        }
+        let (id, payload) = split_message(message)?;
+        let codec = match self.codecs.get(&id) {
+            Some(codec) => Arc::clone(codec),
+            None => {
+                let schema = self.registry.schema(id).await?;
+                let codec = Arc::new(match schema.schema.schema_type {
+                    SchemaType::Avro => Codec::Avro(AvroCodec::try_new(&schema)?),
+                    SchemaType::Protobuf => Codec::Protobuf(ProtobufCodec::try_new(&schema)?),
+                    SchemaType::Json => {
+                        return Err(Error::new(&format!(
+                            "Schema {id} is a JSON schema; only Avro and Protobuf are supported"
+                        )))
+                    }
+                });
+                self.codecs.insert(id, Arc::clone(&codec));
+                codec
+            }
+        };
+        let envelope = match codec.as_ref() {
+            Codec::Avro(codec) => codec.decode(id, payload)?,
+            Codec::Protobuf(codec) => codec.decode(id, payload)?,
+        };
+        if !self.evolved.as_ref().is_some_and(|fields| Arc::ptr_eq(fields, &envelope.fields)) {
+            self.columns = evolve(&self.columns, &envelope.fields)?;
+            self.evolved = Some(Arc::clone(&envelope.fields));
+        }
+        let op = ChangeOp::parse(&envelope.op)?;
+        let row = match op {
+            ChangeOp::Delete => envelope.before,
+            _ => envelope.after,
+        };
+        Ok(Some(ChangeEvent { op, ts_ms: envelope.ts_ms, row: row.unwrap_or_default() }))
+    }
+
+    /// A batch of `events` with the current [`schema`](Self::schema).
+    pub fn to_record_batch(&self, events: &[ChangeEvent]) -> Result<RecordBatch> {
+        let schema = self.schema();
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(events.iter().map(|e| Some(e.op.code())).collect::<StringArray>()),
+            Arc::new(events.iter().map(|e| e.ts_ms).collect::<Int64Array>()),
+        ];
+        for field in &self.columns {
+            let values = events.iter().map(|event| match event.row.get(field.name()) {
+                Some(value) => value.cast_to(field.data_type()),
+                None => ScalarValue::try_from(field.data_type()),
+            });
+            let values = values.collect::<datafusion::error::Result<Vec<_>>>().map_err(|e| {
+                Error::external(format!("Failed to build column {}", field.name()), e)
+            })?;
+            let array = match values.is_empty() {
+                true => datafusion::arrow::array::new_empty_array(field.data_type()),
+                false => ScalarValue::iter_to_array(values).map_err(|e| {
+                    Error::external(format!("Failed to build column {}", field.name()), e)
+                })?,
+            };
+            columns.push(array);
+        }
+        RecordBatch::try_new(schema, columns)
+            .map_err(|e| Error::external("Failed to build a batch of change events", e))
+    }
+}
+
+/// `columns` evolved to hold the rows of a schema with `fields`.
+fn evolve(columns: &[Field], fields: &[Field]) -> Result<Vec<Field>> {
+    let mut columns = columns.to_vec();
+    for field in fields {
+        match columns.iter_mut().find(|column| column.name() == field.name()) {
+            None => columns.push(field.clone()),
+            Some(column) => {
+                let Some(data_type) = widen(column.data_type(), field.data_type()) else {
+                    return Err(Error::new(&format!(
+                        "Column {} changed from {} to {}, which cannot be evolved",
+                        field.name(),
+                        column.data_type(),
+                        field.data_type()
+                    )));
+                };
+                *column = Field::new(field.name(), data_type, true);
+            }
+        }
+    }
+    Ok(columns)
+}
+
+/// A type holding values of both `a` and `b`, if they are the same or numbers.
+fn widen(a: &DataType, b: &DataType) -> Option<DataType> {
+    use DataType::*;
+    let rank = |t: &DataType| match t {
+        Int8 => Some((0, 0)),
+        Int16 => Some((0, 1)),
+        Int32 => Some((0, 2)),
+        Int64 => Some((0, 3)),
+        Float32 => Some((1, 0)),
+        Float64 => Some((1, 1)),
+        _ => None,
+    };
+    match (a, b) {
+        _ if a == b => Some(a.clone()),
+        (Decimal128(p1, s1), Decimal128(p2, s2)) if s1 == s2 => Some(Decimal128(*p1.max(p2), *s1)),
+        _ => match (rank(a)?, rank(b)?) {
+            // Integers become doubles alongside floating point numbers.
+            ((ka, _), (kb, _)) if ka != kb => Some(Float64),
+            (ra, rb) => Some(if ra >= rb { a.clone() } else { b.clone() }),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evolve() {
+        let v1 =
+            [Field::new("id", DataType::Int32, true), Field::new("name", DataType::Utf8, true)];
+        let v2 =
+            [Field::new("id", DataType::Int64, true), Field::new("email", DataType::Utf8, true)];
+        let columns = evolve(&evolve(&[], &v1).unwrap(), &v2).unwrap();
+        let names: Vec<_> = columns.iter().map(|c| (c.name().as_str(), c.data_type())).collect();
+        assert_eq!(
+            names,
+            [("id", &DataType::Int64), ("name", &DataType::Utf8), ("email", &DataType::Utf8)]
+        );
+        // Narrower values fit in the wider column.
+        assert_eq!(evolve(&columns, &v1).unwrap(), columns);
+        assert!(evolve(&columns, &[Field::new("id", DataType::Utf8, true)]).is_err());
+    }
+}
@@ -2,11 +2,34 @@
 //!
 //! Provides CDC primitives and implementations for Igloo connectors.
 //!
+//! [`DebeziumDecoder`] turns Debezium change events encoded with a Confluent Schema
+//! Registry (Avro or Protobuf) into Arrow batches, following the table's schema as it
+//! evolves.
+//!
 //! # Example
-//! ```rust
-//! // Example usage will go here once implemented
+//! ```no_run
+//! # async fn example(messages: Vec<Vec<u8>>) -> igloo_common::error::Result<()> {
+//! use igloo_cdc::{DebeziumDecoder, SchemaRegistryClient};
+//! use std::sync::Arc;
+//!
+//! let registry = Arc::new(SchemaRegistryClient::new("http://registry:8081"));
+//! let mut decoder = DebeziumDecoder::new(registry);
+//! let mut events = Vec::new();
+//! for message in &messages {
+//!     events.extend(decoder.decode(message).await?);
+//! }
+//! let batch = decoder.to_record_batch(&events)?;
+//! # Ok(())
+//! # }
 //! ```
-// TODO: Implement CDC logic
+
+mod avro;
+pub mod debezium;
+mod protobuf;
+pub mod registry;
+
+pub use debezium::{ChangeEvent, ChangeOp, DebeziumDecoder};
+pub use registry::SchemaRegistryClient;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,215 @@
+//! Protobuf-encoded Debezium events.
+//!
+//! The registry holds `.proto` sources, compiled here. Messages name their type with
+//! the indexes of the message in the schema's file (then of nested messages), written
+//! between the schema ID and the payload.
+
+use crate::debezium::{Envelope, Row};
+use crate::registry::ResolvedSchema;
+use datafusion::arrow::datatypes::{DataType, Field};
+use datafusion::scalar::ScalarValue;
+use igloo_common::error::{Error, Result};
+use prost_reflect::{
+    DynamicMessage, FieldDescriptor, FileDescriptor, Kind, MessageDescriptor, ReflectMessage,
+    SerializeOptions, Value,
+};
+use protox::file::{ChainFileResolver, File, FileResolver, GoogleFileResolver};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Name the schema's own file is compiled as.
+const ROOT_FILE: &str = "igloo_registry_schema.proto";
+
+/// An envelope type, and the columns of its rows.
+type EnvelopeType = (MessageDescriptor, Arc<Vec<Field>>);
+
+/// Decoder of the events written with one Protobuf schema.
+#[derive(Debug)]
+pub(crate) struct ProtobufCodec {
+    file: FileDescriptor,
+    /// Envelope types seen so far, by message indexes.
+    envelopes: Mutex<HashMap<Vec<i32>, EnvelopeType>>,
+}
+
+/// `.proto` sources by import path.
+struct Sources(HashMap<String, String>);
+
+impl FileResolver for Sources {
+    fn open_file(&self, name: &str) -> std::result::Result<File, protox::Error> {
+        match self.0.get(name) {
+            Some(source) => File::from_source(name, source),
+            None => Err(protox::Error::file_not_found(name)),
+        }
+    }
+}
+
+impl ProtobufCodec {
+    pub(crate) fn try_new(resolved: &ResolvedSchema) -> Result<Self> {
+        let mut sources: HashMap<_, _> = resolved
+            .references
+            .iter()
+            .map(|(name, schema)| (name.clone(), schema.schema.clone()))
+            .collect();
+        sources.insert(ROOT_FILE.to_string(), resolved.schema.schema.clone());
+        let mut resolver = ChainFileResolver::new();
+        resolver.add(Sources(sources));
+        resolver.add(GoogleFileResolver::new());
+        let mut compiler = protox::Compiler::with_file_resolver(resolver);
+        compiler.open_file(ROOT_FILE).map_err(|e| {
+            Error::external(format!("Failed to compile Protobuf schema {}", resolved.id), e)
+        })?;
+        let file = compiler.descriptor_pool().get_file_by_name(ROOT_FILE);
+        let file = file.expect("the schema's file was compiled");
+        Ok(Self { file, envelopes: Mutex::default() })
+    }
+
+    pub(crate) fn decode(&self, id: u32, payload: &[u8]) -> Result<Envelope> {
+        let (indexes, payload) = message_indexes(payload)?;
+        let (descriptor, fields) = self.envelope(id, indexes)?;
+        let message = DynamicMessage::decode(descriptor, payload)
+            .map_err(|e| Error::external(format!("Failed to decode Protobuf schema {id}"), e))?;
+        let mut envelope = Envelope::new(fields);
+        for (field, value) in message.fields() {
+            match (field.name(), value) {
+                ("op", Value::String(op)) => envelope.op = op.clone(),
+                ("ts_ms", Value::I64(ts)) => envelope.ts_ms = Some(*ts),
+                ("before", Value::Message(row)) => envelope.before = Some(row_of(row)?),
+                ("after", Value::Message(row)) => envelope.after = Some(row_of(row)?),
+                _ => {}
+            }
+        }
+        Ok(envelope)
+    }
+
+    /// The envelope type at `indexes`, and the columns of its rows.
+    fn envelope(&self, id: u32, indexes: Vec<i32>) -> Result<EnvelopeType> {
+        let mut envelopes = self.envelopes.lock().unwrap();
+        if let Some((descriptor, fields)) = envelopes.get(&indexes) {
+            return Ok((descriptor.clone(), Arc::clone(fields)));
+        }
+        let unknown = || Error::new(&format!("Protobuf schema {id} has no message {indexes:?}"));
+        let mut path = indexes.iter().map(|i| usize::try_from(*i).ok());
+        let first = path.next().flatten().ok_or_else(unknown)?;
+        let mut descriptor = self.file.messages().nth(first).ok_or_else(unknown)?;
+        for index in path {
+            let child = descriptor.child_messages().nth(index.ok_or_else(unknown)?);
+            descriptor = child.ok_or_else(unknown)?;
+        }
+        let row = ["after", "before"].iter().find_map(|name| {
+            match descriptor.get_field_by_name(name)?.kind() {
+                Kind::Message(row) => Some(row),
+                _ => None,
+            }
+        });
+        let Some(row) = row else {
+            return Err(Error::new(&format!(
+                "Protobuf message {} of schema {id} is not a Debezium envelope",
+                descriptor.full_name()
+            )));
+        };
+        let fields = row.fields().map(|f| Field::new(f.name(), arrow_type(&f), true));
+        let fields = Arc::new(fields.collect::<Vec<_>>());
+        envelopes.insert(indexes, (descriptor.clone(), Arc::clone(&fields)));
+        Ok((descriptor, fields))
+    }
+}
+
+/// The message indexes at the start of `payload`, and the message after them.
+fn message_indexes(payload: &[u8]) -> Result<(Vec<i32>, &[u8])> {
+    let mut rest = payload;
+    let count = read_zigzag(&mut rest)?;
+    // A count of zero is the first message, `[0]`.
+    if count == 0 {
+        return Ok((vec![0], rest));
+    }
+    let indexes = (0..count).map(|_| read_zigzag(&mut rest)).collect::<Result<_>>()?;
+    Ok((indexes, rest))
+}
+
+fn read_zigzag(buf: &mut &[u8]) -> Result<i32> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let Some((byte, rest)) = buf.split_first() else { break };
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(((value >> 1) as i64 ^ -((value & 1) as i64)) as i32);
+        }
+    }
+    Err(Error::new("Invalid Protobuf message indexes"))
+}
+
+/// The Arrow type of columns of `field`. Nested and repeated values are kept as JSON.
+fn arrow_type(field: &FieldDescriptor) -> DataType {
+    if field.is_list() || field.is_map() {
+        return DataType::Utf8;
+    }
+    match field.kind() {
+        Kind::Double => DataType::Float64,
+        Kind::Float => DataType::Float32,
+        Kind::Int32 | Kind::Sint32 | Kind::Sfixed32 => DataType::Int32,
+        Kind::Int64 | Kind::Sint64 | Kind::Sfixed64 => DataType::Int64,
+        Kind::Uint32 | Kind::Fixed32 => DataType::UInt32,
+        Kind::Uint64 | Kind::Fixed64 => DataType::UInt64,
+        Kind::Bool => DataType::Boolean,
+        Kind::Bytes => DataType::Binary,
+        Kind::String | Kind::Enum(_) | Kind::Message(_) => DataType::Utf8,
+    }
+}
+
+fn row_of(message: &DynamicMessage) -> Result<Row> {
+    let mut json = None;
+    let mut row = Row::new();
+    for field in message.descriptor().fields() {
+        let scalar = if field.supports_presence() && !message.has_field(&field) {
+            ScalarValue::try_from(&arrow_type(&field))
+                .map_err(|e| Error::external("Failed to convert a Protobuf value", e))?
+        } else {
+            let value = message.get_field(&field);
+            match (&*value, field.kind()) {
+                (Value::Bool(b), _) => ScalarValue::Boolean(Some(*b)),
+                (Value::I32(i), _) => ScalarValue::Int32(Some(*i)),
+                (Value::I64(i), _) => ScalarValue::Int64(Some(*i)),
+                (Value::U32(i), _) => ScalarValue::UInt32(Some(*i)),
+                (Value::U64(i), _) => ScalarValue::UInt64(Some(*i)),
+                (Value::F32(f), _) => ScalarValue::Float32(Some(*f)),
+                (Value::F64(f), _) => ScalarValue::Float64(Some(*f)),
+                (Value::String(s), _) => ScalarValue::Utf8(Some(s.clone())),
+                (Value::Bytes(b), _) => ScalarValue::Binary(Some(b.to_vec())),
+                (Value::EnumNumber(n), Kind::Enum(values)) => {
+                    let name = values.get_value(*n).map(|v| v.name().to_string());
+                    ScalarValue::Utf8(Some(name.unwrap_or_else(|| n.to_string())))
+                }
+                _ => {
+                    let json = match &mut json {
+                        Some(json) => json,
+                        None => json.insert(to_json(message)?),
+                    };
+                    ScalarValue::Utf8(json.get(field.name()).map(|v| v.to_string()))
+                }
+            }
+        };
+        row.insert(field.name().to_string(), scalar);
+    }
+    Ok(row)
+}
+
+fn to_json(message: &DynamicMessage) -> Result<serde_json::Value> {
+    let options = SerializeOptions::new().use_proto_field_name(true);
+    message
+        .serialize_with_options(serde_json::value::Serializer, &options)
+        .map_err(|e| Error::external("Failed to convert a Protobuf value", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_indexes() {
+        assert_eq!(message_indexes(&[0, 9]).unwrap(), (vec![0], &[9][..]));
+        // Two indexes, 1 and 2, zigzag-encoded.
+        assert_eq!(message_indexes(&[4, 2, 4, 9]).unwrap(), (vec![1, 2], &[9][..]));
+        assert!(message_indexes(&[4, 2]).is_err());
+    }
+}
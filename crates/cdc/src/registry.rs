@@ -0,0 +1,182 @@
+//! Client of a Confluent Schema Registry.
+//!
+//! Producers using the registry's serializers prefix each message with a magic byte
+//! and the ID of the schema it was written with; [`SchemaRegistryClient::schema`]
+//! fetches that schema, and the schemas it references, once per ID.
+
+use igloo_common::error::{Error, Result};
+use igloo_common::retry::RetryPolicy;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Magic byte the registry's wire format starts with.
+pub const MAGIC_BYTE: u8 = 0;
+
+/// Format of a registered schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum SchemaType {
+    #[default]
+    Avro,
+    Protobuf,
+    Json,
+}
+
+/// A schema another schema imports, by subject and version.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SchemaReference {
+    /// Name the referencing schema uses: a type name (Avro) or an import path
+    /// (Protobuf).
+    pub name: String,
+    pub subject: String,
+    pub version: i32,
+}
+
+/// A schema as registered.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegisteredSchema {
+    #[serde(default)]
+    pub schema_type: SchemaType,
+    pub schema: String,
+    #[serde(default)]
+    pub references: Vec<SchemaReference>,
+}
+
+/// A schema with everything it references, directly or not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedSchema {
+    pub id: u32,
+    pub schema: RegisteredSchema,
+    /// Referenced schemas by name, dependencies before the schemas using them.
+    pub references: Vec<(String, RegisteredSchema)>,
+}
+
+/// Client of a Schema Registry.
+pub struct SchemaRegistryClient {
+    url: String,
+    credentials: Option<(String, String)>,
+    client: Client,
+    retry: RetryPolicy,
+    schemas: Mutex<HashMap<u32, Arc<ResolvedSchema>>>,
+}
+
+impl fmt::Debug for SchemaRegistryClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SchemaRegistryClient").field("url", &self.url).finish_non_exhaustive()
+    }
+}
+
+impl SchemaRegistryClient {
+    /// A client of the registry at `url` (e.g. `http://registry:8081`).
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            credentials: None,
+            client: Client::new(),
+            retry: RetryPolicy::default(),
+            schemas: Mutex::default(),
+        }
+    }
+
+    /// Authenticate with HTTP basic auth, as Confluent Cloud API keys do.
+    pub fn with_basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// How fetches failing on transient errors are retried. [`RetryPolicy::default`]
+    /// unless set.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// The schema registered with `id`, with its references.
+    pub async fn schema(&self, id: u32) -> Result<Arc<ResolvedSchema>> {
+        if let Some(schema) = self.schemas.lock().await.get(&id) {
+            return Ok(Arc::clone(schema));
+        }
+        let schema: RegisteredSchema = self.get(&format!("schemas/ids/{id}")).await?;
+        let mut references = Vec::new();
+        self.resolve(&schema.references, &mut references).await?;
+        let schema = Arc::new(ResolvedSchema { id, schema, references });
+        // Schemas never change once registered.
+        self.schemas.lock().await.insert(id, Arc::clone(&schema));
+        Ok(schema)
+    }
+
+    /// Fetch `references` and theirs into `resolved`, dependencies first.
+    async fn resolve(
+        &self,
+        references: &[SchemaReference],
+        resolved: &mut Vec<(String, RegisteredSchema)>,
+    ) -> Result<()> {
+        for reference in references {
+            if resolved.iter().any(|(name, _)| *name == reference.name) {
+                continue;
+            }
+            let path = format!("subjects/{}/versions/{}", reference.subject, reference.version);
+            let schema: RegisteredSchema = self.get(&path).await?;
+            Box::pin(self.resolve(&schema.references, resolved)).await?;
+            resolved.push((reference.name.clone(), schema));
+        }
+        Ok(())
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        self.retry.retry(|| self.get_once(path)).await
+    }
+
+    async fn get_once<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/{path}", self.url);
+        let mut request = self.client.get(&url);
+        if let Some((user, password)) = &self.credentials {
+            request = request.basic_auth(user, Some(password));
+        }
+        let context = || format!("Failed to fetch {url} from the schema registry");
+        let response = request.send().await.map_err(|e| Error::external(context(), e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(Error::new(&format!("{}: {status} {body}", context())));
+        }
+        response.json().await.map_err(|e| Error::external(context(), e))
+    }
+}
+
+/// The schema ID and payload of a message in the registry's wire format.
+pub fn split_message(message: &[u8]) -> Result<(u32, &[u8])> {
+    match message {
+        [MAGIC_BYTE, a, b, c, d, payload @ ..] => {
+            Ok((u32::from_be_bytes([*a, *b, *c, *d]), payload))
+        }
+        _ => Err(Error::new("Message is not in the Schema Registry wire format")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo_common::retry::Retryable;
+
+    #[tokio::test]
+    async fn test_unreachable_registries_fail_retryably() {
+        let registry = SchemaRegistryClient::new("http://127.0.0.1:1")
+            .with_retry_policy(RetryPolicy::no_retry());
+        let err = registry.schema(1).await.unwrap_err();
+        assert!(err.is_retryable(), "{err}");
+    }
+
+    #[test]
+    fn test_split_message() {
+        let (id, payload) = split_message(&[0, 0, 0, 1, 2, 42]).unwrap();
+        assert_eq!((id, payload), (258, &[42][..]));
+        assert!(split_message(&[1, 0, 0, 0, 1]).is_err());
+        assert!(split_message(b"{}").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use apache_avro::types::Value as AvroValue;
+use apache_avro::Schema as AvroSchema;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use igloo_cdc::{ChangeOp, DebeziumDecoder, SchemaRegistryClient};
+use prost_reflect::{DescriptorPool, DynamicMessage, Value as ProtoValue};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Envelope of `inventory.customers` before `email` was added and `id` widened.
+const AVRO_V1: &str = r#"{
+  "type": "record", "name": "Envelope", "namespace": "inventory.customers",
+  "fields": [
+    {"name": "before", "type": ["null", {"type": "record", "name": "Value", "fields": [
+      {"name": "id", "type": "int"},
+      {"name": "name", "type": "string"}
+    ]}], "default": null},
+    {"name": "after", "type": ["null", "Value"], "default": null},
+    {"name": "op", "type": "string"},
+    {"name": "ts_ms", "type": ["null", "long"], "default": null}
+  ]
+}"#;
+
+const AVRO_V2: &str = r#"{
+  "type": "record", "name": "Envelope", "namespace": "inventory.customers",
+  "fields": [
+    {"name": "before", "type": ["null", {"type": "record", "name": "Value", "fields": [
+      {"name": "id", "type": "long"},
+      {"name": "name", "type": "string"},
+      {"name": "email", "type": ["null", "string"], "default": null}
+    ]}], "default": null},
+    {"name": "after", "type": ["null", "Value"], "default": null},
+    {"name": "op", "type": "string"},
+    {"name": "ts_ms", "type": ["null", "long"], "default": null}
+  ]
+}"#;
+
+const PROTO: &str = r#"
+syntax = "proto3";
+package inventory.orders;
+
+message Envelope {
+  message Value {
+    int64 id = 1;
+    double amount = 2;
+    repeated string tags = 3;
+  }
+  Value before = 1;
+  Value after = 2;
+  string op = 3;
+  int64 ts_ms = 4;
+}
+"#;
+
+async fn schema(
+    State(schemas): State<Arc<HashMap<u32, Value>>>,
+    Path(id): Path<u32>,
+) -> Result<Json<Value>, StatusCode> {
+    schemas.get(&id).cloned().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn serve_registry() -> String {
+    let schemas = HashMap::from([
+        (1, json!({"schema": AVRO_V1})),
+        (2, json!({"schema": AVRO_V2})),
+        (3, json!({"schemaType": "PROTOBUF", "schema": PROTO})),
+    ]);
+    let app = Router::new().route("/schemas/ids/:id", get(schema)).with_state(Arc::new(schemas));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+fn framed(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![0];
+    message.extend(id.to_be_bytes());
+    message.extend(payload);
+    message
+}
+
+fn avro_event(
+    id: u32,
+    schema: &str,
+    op: &str,
+    before: Option<AvroValue>,
+    after: Option<AvroValue>,
+) -> Vec<u8> {
+    let schema = AvroSchema::parse_str(schema).unwrap();
+    let row = |value: Option<AvroValue>| match value {
+        Some(value) => AvroValue::Union(1, Box::new(value)),
+        None => AvroValue::Union(0, Box::new(AvroValue::Null)),
+    };
+    let envelope = AvroValue::Record(vec![
+        ("before".to_string(), row(before)),
+        ("after".to_string(), row(after)),
+        ("op".to_string(), AvroValue::String(op.to_string())),
+        ("ts_ms".to_string(), AvroValue::Union(1, Box::new(AvroValue::Long(1_700_000_000_000)))),
+    ]);
+    framed(id, &apache_avro::to_avro_datum(&schema, envelope).unwrap())
+}
+
+fn customer_v1(id: i32, name: &str) -> AvroValue {
+    AvroValue::Record(vec![
+        ("id".to_string(), AvroValue::Int(id)),
+        ("name".to_string(), AvroValue::String(name.to_string())),
+    ])
+}
+
+fn customer_v2(id: i64, name: &str, email: Option<&str>) -> AvroValue {
+    let email = match email {
+        Some(email) => AvroValue::Union(1, Box::new(AvroValue::String(email.to_string()))),
+        None => AvroValue::Union(0, Box::new(AvroValue::Null)),
+    };
+    AvroValue::Record(vec![
+        ("id".to_string(), AvroValue::Long(id)),
+        ("name".to_string(), AvroValue::String(name.to_string())),
+        ("email".to_string(), email),
+    ])
+}
+
+#[tokio::test]
+async fn test_avro_events_evolve_the_schema() {
+    let registry = Arc::new(SchemaRegistryClient::new(serve_registry().await));
+    let mut decoder = DebeziumDecoder::new(registry);
+    let messages = [
+        avro_event(1, AVRO_V1, "r", None, Some(customer_v1(1, "alice"))),
+        avro_event(1, AVRO_V1, "u", Some(customer_v1(1, "alice")), Some(customer_v1(1, "Alice"))),
+        avro_event(
+            2,
+            AVRO_V2,
+            "c",
+            None,
+            Some(customer_v2(5_000_000_000, "bob", Some("bob@example.com"))),
+        ),
+        avro_event(2, AVRO_V2, "d", Some(customer_v2(1, "Alice", None)), None),
+        // The tombstone following the delete.
+        vec![],
+    ];
+    let mut events = vec![];
+    for message in &messages {
+        events.extend(decoder.decode(message).await.unwrap());
+    }
+    let ops: Vec<_> = events.iter().map(|e| e.op).collect();
+    assert_eq!(ops, [ChangeOp::Read, ChangeOp::Update, ChangeOp::Create, ChangeOp::Delete]);
+
+    let schema = decoder.schema();
+    let columns: Vec<_> =
+        schema.fields().iter().map(|f| (f.name().as_str(), f.data_type())).collect();
+    assert_eq!(
+        columns,
+        [
+            ("__op", &DataType::Utf8),
+            ("__ts_ms", &DataType::Int64),
+            ("id", &DataType::Int64),
+            ("name", &DataType::Utf8),
+            ("email", &DataType::Utf8),
+        ]
+    );
+    let batch = decoder.to_record_batch(&events).unwrap();
+    let expected = "\
++------+---------------+------------+-------+-----------------+
+| __op | __ts_ms       | id         | name  | email           |
++------+---------------+------------+-------+-----------------+
+| r    | 1700000000000 | 1          | alice |                 |
+| u    | 1700000000000 | 1          | Alice |                 |
+| c    | 1700000000000 | 5000000000 | bob   | bob@example.com |
+| d    | 1700000000000 | 1          | Alice |                 |
++------+---------------+------------+-------+-----------------+";
+    assert_eq!(pretty_format_batches(&[batch]).unwrap().to_string(), expected);
+}
+
+#[tokio::test]
+async fn test_protobuf_events() {
+    let dir = std::env::temp_dir().join(format!("igloo-cdc-proto-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("orders.proto"), PROTO).unwrap();
+    let descriptors = protox::compile(["orders.proto"], [&dir]).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let pool = DescriptorPool::from_file_descriptor_set(descriptors).unwrap();
+    let envelope = pool.get_message_by_name("inventory.orders.Envelope").unwrap();
+    let row = pool.get_message_by_name("inventory.orders.Envelope.Value").unwrap();
+
+    let mut order = DynamicMessage::new(row);
+    order.set_field_by_name("id", ProtoValue::I64(7));
+    order.set_field_by_name("amount", ProtoValue::F64(12.5));
+    let tags = vec![ProtoValue::String("gift".to_string())];
+    order.set_field_by_name("tags", ProtoValue::List(tags));
+    let mut event = DynamicMessage::new(envelope);
+    event.set_field_by_name("after", ProtoValue::Message(order));
+    event.set_field_by_name("op", ProtoValue::String("c".to_string()));
+    event.set_field_by_name("ts_ms", ProtoValue::I64(1_700_000_000_000));
+    // A single 0 stands for the message indexes of the file's first message.
+    let mut payload = vec![0];
+    payload.extend(prost::Message::encode_to_vec(&event));
+
+    let registry = Arc::new(SchemaRegistryClient::new(serve_registry().await));
+    let mut decoder = DebeziumDecoder::new(registry);
+    let events = vec![decoder.decode(&framed(3, &payload)).await.unwrap().unwrap()];
+    let batch = decoder.to_record_batch(&events).unwrap();
+    let expected = "\
++------+---------------+----+--------+----------+
+| __op | __ts_ms       | id | amount | tags     |
++------+---------------+----+--------+----------+
+| c    | 1700000000000 | 7  | 12.5   | [\"gift\"] |
++------+---------------+----+--------+----------+";
+    assert_eq!(pretty_format_batches(&[batch]).unwrap().to_string(), expected);
+
+    let error = decoder.decode(&framed(4, &payload)).await.unwrap_err();
+    assert!(error.to_string().contains("404"), "{error}");
+}
@@ -0,0 +1,355 @@
+//! `igloo bench`: timing the TPC-H queries over generated data, read from local
+//! Parquet files, from Postgres, or from both at once.
+
+use crate::tpch::{self, Table, QUERIES, TABLES};
+use bytes::Bytes;
+use clap::{Args, ValueEnum};
+use futures::{SinkExt, StreamExt};
+use igloo::connectors::postgres::{PostgresSnapshot, SnapshotOptions};
+use igloo::datafusion::arrow::csv::WriterBuilder;
+use igloo::datafusion::error::Result as DataFusionResult;
+use igloo::datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use igloo::IglooEngine;
+use std::fmt;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_postgres::NoTls;
+
+/// The file recording the scale of the data in the data directory.
+const SCALE_FILE: &str = "scale";
+
+/// The tables a `mixed` configuration reads from Parquet, the others from Postgres.
+const FACTS: [&str; 2] = ["orders", "lineitem"];
+
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// TPC-H scale factor; 1 is about 1 GB of data.
+    #[arg(long, default_value_t = 0.1, value_name = "SF")]
+    scale: f64,
+
+    /// Where the Parquet files are generated. Data of the same scale already there is
+    /// reused.
+    #[arg(long, default_value = "tpch", value_name = "DIR")]
+    dir: PathBuf,
+
+    /// Connection string of a Postgres database to load the tables into, e.g.
+    /// `host=localhost user=igloo dbname=tpch`. Replaces tables of the same names.
+    #[arg(long, value_name = "CONFIG")]
+    postgres: Option<String>,
+
+    /// Use the tables already loaded into Postgres.
+    #[arg(long, requires = "postgres")]
+    no_load: bool,
+
+    /// Where the tables are read from, comma-separated. Defaults to all configurations
+    /// the data is available for.
+    #[arg(long, value_delimiter = ',', value_name = "CONFIGS")]
+    configs: Vec<Configuration>,
+
+    /// The queries to run by number, comma-separated. Defaults to all 22.
+    #[arg(long, value_delimiter = ',', value_name = "N")]
+    queries: Vec<usize>,
+
+    /// Times each query is run; the fastest is reported.
+    #[arg(long, default_value_t = 3, value_name = "N")]
+    iterations: usize,
+
+    /// Chunks each Postgres table is read in, in parallel.
+    #[arg(long, default_value_t = 4, value_name = "N")]
+    chunks: usize,
+
+    /// Generate (and load) the data without running the queries.
+    #[arg(long)]
+    generate_only: bool,
+}
+
+/// Where the tables of a run are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Configuration {
+    /// Every table from Parquet files.
+    Parquet,
+    /// Every table from Postgres.
+    Postgres,
+    /// `orders` and `lineitem` from Parquet files, the dimensions from Postgres.
+    Mixed,
+}
+
+impl fmt::Display for Configuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Configuration::Parquet => "parquet",
+            Configuration::Postgres => "postgres",
+            Configuration::Mixed => "mixed",
+        })
+    }
+}
+
+impl Configuration {
+    fn in_postgres(self, table: &Table) -> bool {
+        match self {
+            Configuration::Parquet => false,
+            Configuration::Postgres => true,
+            Configuration::Mixed => !FACTS.contains(&table.name),
+        }
+    }
+}
+
+/// The timing of one query in one configuration.
+enum Timing {
+    Ran { elapsed: Duration, rows: usize },
+    Failed(String),
+}
+
+/// Generate the data, then run the queries in each configuration.
+pub async fn run(args: BenchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if args.scale.is_nan() || args.scale <= 0.0 {
+        return Err(format!("invalid --scale {}, expected a positive number", args.scale).into());
+    }
+    if let Some(query) = args.queries.iter().find(|&&query| !(1..=QUERIES.len()).contains(&query)) {
+        return Err(format!("invalid query {query}, expected 1 to {}", QUERIES.len()).into());
+    }
+    generate(&args.dir, args.scale)?;
+    if let (Some(postgres), false) = (&args.postgres, args.no_load) {
+        load(postgres, &args.dir).await?;
+    }
+    if args.generate_only {
+        return Ok(());
+    }
+
+    let configs = match (args.configs.is_empty(), &args.postgres) {
+        (false, _) => args.configs.clone(),
+        (true, None) => vec![Configuration::Parquet],
+        (true, Some(_)) => Configuration::value_variants().to_vec(),
+    };
+    let queries: Vec<usize> =
+        if args.queries.is_empty() { (1..=QUERIES.len()).collect() } else { args.queries.clone() };
+    let mut timings = Vec::new();
+    for config in &configs {
+        let engine = IglooEngine::new();
+        register(&engine, *config, &args).await?;
+        let mut config_timings = Vec::new();
+        for &query in &queries {
+            eprintln!("Running Q{query} ({config}).");
+            let timing = time(&engine, QUERIES[query - 1], args.iterations.max(1)).await;
+            if let Timing::Failed(error) = &timing {
+                eprintln!("Q{query} ({config}) failed: {error}");
+            }
+            config_timings.push(timing);
+        }
+        timings.push(config_timings);
+    }
+    print!("{}", report(&configs, &queries, &timings));
+    Ok(())
+}
+
+/// Generate the Parquet files, unless those of `scale` are there already.
+fn generate(dir: &Path, scale: f64) -> Result<(), Box<dyn std::error::Error>> {
+    let generated = std::fs::read_to_string(dir.join(SCALE_FILE)).ok();
+    let complete = TABLES.iter().all(|table| table.path(dir).exists());
+    if complete && generated.as_deref() == Some(scale.to_string().as_str()) {
+        eprintln!("Using the data of scale {scale} in {}.", dir.display());
+        return Ok(());
+    }
+    eprintln!("Generating the data of scale {scale} in {}.", dir.display());
+    for (table, rows) in tpch::generate(dir, scale)? {
+        eprintln!("{table}: {rows} rows");
+    }
+    std::fs::write(dir.join(SCALE_FILE), scale.to_string())?;
+    Ok(())
+}
+
+/// Copy the generated tables into Postgres, replacing them.
+async fn load(config: &str, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let (client, connection) = tokio_postgres::connect(config, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Postgres connection error: {e}");
+        }
+    });
+    for table in &TABLES {
+        let columns: Vec<String> = table
+            .columns
+            .iter()
+            .map(|(name, kind)| format!("{name} {} NOT NULL", kind.sql_type()))
+            .collect();
+        client
+            .batch_execute(&format!(
+                "DROP TABLE IF EXISTS {name};
+                 CREATE TABLE {name} ({}, PRIMARY KEY ({}))",
+                columns.join(", "),
+                table.primary_key.join(", "),
+                name = table.name,
+            ))
+            .await?;
+        let sink = client.copy_in(&format!("COPY {} FROM STDIN (FORMAT csv)", table.name)).await?;
+        futures::pin_mut!(sink);
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(table.path(dir))?)?;
+        for batch in reader.build()? {
+            let mut csv = Vec::new();
+            WriterBuilder::new().with_header(false).build(&mut csv).write(&batch?)?;
+            sink.send(Bytes::from(csv)).await?;
+        }
+        let rows = sink.finish().await?;
+        client.batch_execute(&format!("ANALYZE {}", table.name)).await?;
+        eprintln!("Loaded {rows} rows into Postgres table {}.", table.name);
+    }
+    Ok(())
+}
+
+/// Register the tables where `config` reads them from.
+async fn register(
+    engine: &IglooEngine,
+    config: Configuration,
+    args: &BenchArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for table in &TABLES {
+        if !config.in_postgres(table) {
+            engine.register_file(table.name, &table.path(&args.dir).to_string_lossy()).await?;
+            continue;
+        }
+        let postgres = args
+            .postgres
+            .as_deref()
+            .ok_or_else(|| format!("the {config} configuration needs --postgres"))?;
+        let options = SnapshotOptions::default()
+            .with_chunks(args.chunks)
+            .with_chunk_column(table.primary_key[0]);
+        let snapshot =
+            PostgresSnapshot::try_new(postgres, &format!("public.{}", table.name), options).await?;
+        engine.register_table(table.name, Arc::new(snapshot))?;
+    }
+    Ok(())
+}
+
+/// The fastest of `iterations` runs of `sql`.
+async fn time(engine: &IglooEngine, sql: &str, iterations: usize) -> Timing {
+    let mut fastest: Option<(Duration, usize)> = None;
+    for _ in 0..iterations {
+        match run_query(engine, sql).await {
+            Ok((elapsed, rows)) => {
+                if fastest.map_or(true, |(fastest, _)| elapsed < fastest) {
+                    fastest = Some((elapsed, rows));
+                }
+            }
+            Err(e) => return Timing::Failed(e.to_string()),
+        }
+    }
+    let (elapsed, rows) = fastest.expect("queries run at least once");
+    Timing::Ran { elapsed, rows }
+}
+
+/// How long `sql` took to read all of its result, and the rows it returned.
+async fn run_query(engine: &IglooEngine, sql: &str) -> DataFusionResult<(Duration, usize)> {
+    let start = Instant::now();
+    let mut stream = engine.query_stream(sql).await?;
+    let mut rows = 0;
+    while let Some(batch) = stream.batches.next().await {
+        rows += batch?.num_rows();
+    }
+    Ok((start.elapsed(), rows))
+}
+
+/// A table of the timings in milliseconds, a row per query and a column per
+/// configuration, followed by the rows each query returned.
+fn report(configs: &[Configuration], queries: &[usize], timings: &[Vec<Timing>]) -> String {
+    let mut header = vec!["query".to_string()];
+    header.extend(configs.iter().map(|config| format!("{config} ms")));
+    header.push("rows".to_string());
+    let mut lines = vec![header];
+    let mut totals = vec![Duration::ZERO; configs.len()];
+    for (i, query) in queries.iter().enumerate() {
+        let mut line = vec![format!("Q{query}")];
+        let mut rows = Vec::new();
+        for (config, timings) in timings.iter().enumerate() {
+            match &timings[i] {
+                Timing::Ran { elapsed, rows: returned } => {
+                    totals[config] += *elapsed;
+                    line.push(elapsed.as_millis().to_string());
+                    if !rows.contains(returned) {
+                        rows.push(*returned);
+                    }
+                }
+                Timing::Failed(_) => line.push("failed".to_string()),
+            }
+        }
+        // Configurations disagreeing on the rows are all shown.
+        let rows: Vec<String> = rows.iter().map(usize::to_string).collect();
+        line.push(rows.join("/"));
+        lines.push(line);
+    }
+    let mut total = vec!["total".to_string()];
+    total.extend(totals.iter().map(|total| total.as_millis().to_string()));
+    total.push(String::new());
+    lines.push(total);
+
+    let widths: Vec<usize> = (0..lines[0].len())
+        .map(|column| lines.iter().map(|line| line[column].len()).max().unwrap_or(0))
+        .collect();
+    let mut report = String::new();
+    for line in &lines {
+        let cells: Vec<String> = line
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(column, (cell, width))| match column {
+                0 => format!("{cell:<width$}"),
+                _ => format!("{cell:>width$}"),
+            })
+            .collect();
+        report.push_str(cells.join("  ").trim_end());
+        report.push('\n');
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_queries_run_over_parquet() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("igloo-bench-{}", std::process::id()));
+        let args = BenchArgs {
+            scale: 0.001,
+            dir: dir.clone(),
+            postgres: None,
+            no_load: false,
+            configs: vec![],
+            queries: vec![],
+            iterations: 1,
+            chunks: 1,
+            generate_only: false,
+        };
+        generate(&args.dir, args.scale)?;
+        let engine = IglooEngine::new();
+        register(&engine, Configuration::Parquet, &args).await?;
+        let mut timings = Vec::new();
+        for (i, sql) in QUERIES.iter().enumerate() {
+            let timing = time(&engine, sql, 1).await;
+            if let Timing::Failed(error) = &timing {
+                panic!("Q{} failed: {error}", i + 1);
+            }
+            timings.push(timing);
+        }
+        let rows = |query: usize| match timings[query - 1] {
+            Timing::Ran { rows, .. } => rows,
+            Timing::Failed(_) => unreachable!(),
+        };
+        // Every return flag and line status, and one sum.
+        assert_eq!(rows(1), 4);
+        assert_eq!(rows(6), 1);
+        assert_eq!(rows(12), 2);
+
+        let queries: Vec<usize> = (1..=QUERIES.len()).collect();
+        let report = report(&[Configuration::Parquet], &queries, &[timings]);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), QUERIES.len() + 2);
+        assert!(lines[0].starts_with("query  parquet ms  rows"), "{report}");
+        assert!(lines[1].starts_with("Q1 ") && lines[1].ends_with(" 4"), "{report}");
+        assert!(lines[QUERIES.len() + 1].starts_with("total"), "{report}");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+//! `igloo catalog`: keeping the external catalogs of a running coordinator in step
+//! with their sources, through its HTTP API.
+
+use clap::{Args, Subcommand};
+use igloo::SyncReport;
+
+#[derive(Debug, Args)]
+pub struct CatalogArgs {
+    /// The coordinator's HTTP API.
+    #[arg(long, default_value = "http://127.0.0.1:8080", value_name = "URL", global = true)]
+    server: String,
+
+    /// API key or token to authenticate to the coordinator with.
+    #[arg(long, value_name = "TOKEN", global = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    action: CatalogAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum CatalogAction {
+    /// Re-read the metadata of an external catalog and register it, reporting the
+    /// schemas and tables added, dropped or altered since the last sync.
+    Sync {
+        /// The catalog, e.g. `hive` or `iceberg`.
+        catalog: String,
+
+        /// Only report what differs from the source.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Run the catalog command, printing its report.
+pub async fn run(args: CatalogArgs) -> Result<(), Box<dyn std::error::Error>> {
+    match args.action {
+        CatalogAction::Sync { catalog, dry_run } => {
+            let report = sync(&args.server, args.token.as_deref(), &catalog, dry_run).await?;
+            println!("{report}");
+        }
+    }
+    Ok(())
+}
+
+/// Ask the coordinator serving HTTP at `server` to sync `catalog`.
+async fn sync(
+    server: &str,
+    token: Option<&str>,
+    catalog: &str,
+    dry_run: bool,
+) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    let url = format!("{}/catalogs/{catalog}/sync", server.trim_end_matches('/'));
+    let mut request = reqwest::Client::new().post(url).query(&[("dry_run", dry_run)]);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response.json().await?);
+    }
+    // Errors are an `ApiError` body, or a bare status from a proxy.
+    let error: serde_json::Value = response.json().await.unwrap_or_default();
+    let message = match error["message"].as_str() {
+        Some(message) => message.to_string(),
+        None => status.to_string(),
+    };
+    Err(format!("cannot sync catalog {catalog}: {message}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo::datafusion::arrow::datatypes::Schema;
+    use igloo::datafusion::catalog::{
+        CatalogProvider, MemoryCatalogProvider, MemorySchemaProvider, SchemaProvider,
+    };
+    use igloo::datafusion::datasource::empty::EmptyTable;
+    use igloo::datafusion::error::Result as DataFusionResult;
+    use igloo::{CatalogSource, IglooEngine};
+    use std::sync::{Arc, Mutex};
+
+    /// A catalog of empty tables in a `public` schema.
+    #[derive(Debug, Default)]
+    struct Tables(Mutex<Vec<&'static str>>);
+
+    #[async_trait::async_trait]
+    impl CatalogSource for Tables {
+        async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+            let schema = MemorySchemaProvider::new();
+            for name in self.0.lock().unwrap().iter() {
+                let table = Arc::new(EmptyTable::new(Arc::new(Schema::empty())));
+                schema.register_table(name.to_string(), table)?;
+            }
+            let catalog = MemoryCatalogProvider::new();
+            catalog.register_schema("public", Arc::new(schema))?;
+            Ok(Arc::new(catalog))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catalogs_are_synced_by_the_coordinator() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let source = Arc::new(Tables::default());
+        source.0.lock().unwrap().push("orders");
+        let engine = IglooEngine::new();
+        engine.register_catalog_source("lake", source.clone()).await?;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let server = format!("http://{}", listener.local_addr()?);
+        let query_engine = Arc::new(engine.query_engine().clone());
+        tokio::spawn(igloo_api::http::serve(listener, query_engine));
+
+        let report = sync(&server, None, "lake", false).await?;
+        assert_eq!(report.to_string(), "catalog lake is up to date");
+
+        source.0.lock().unwrap().push("customers");
+        let report = sync(&server, None, "lake", true).await?;
+        assert_eq!(
+            report.to_string(),
+            "catalog lake differs from its source (1 changes)\n+ table public.customers"
+        );
+        assert!(engine.query("SELECT * FROM lake.public.customers").await.is_err());
+        let report = sync(&format!("{server}/"), None, "lake", false).await?;
+        assert!(report.applied);
+        assert!(engine.query("SELECT * FROM lake.public.customers").await.is_ok());
+
+        let error = sync(&server, None, "missing", false).await.unwrap_err();
+        assert!(error.to_string().starts_with("cannot sync catalog missing: "), "{error}");
+        Ok(())
+    }
+}
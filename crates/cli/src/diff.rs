@@ -0,0 +1,30 @@
+//! `igloo diff`: checking the copy of a Postgres table in the lake for schema drift.
+
+use clap::Args;
+use igloo::IglooEngine;
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    /// Connection string of the Postgres database, e.g. `host=db user=igloo dbname=shop`.
+    #[arg(long, value_name = "CONFIG")]
+    postgres: String,
+
+    /// The Postgres table, `schema.table` or a table of `public`.
+    #[arg(long, value_name = "TABLE")]
+    source: String,
+
+    /// The copy of the table, e.g. an Iceberg table of the `iceberg` catalog or a table
+    /// registered with --table.
+    #[arg(long, value_name = "NAME")]
+    table: String,
+}
+
+/// Print how the copy's columns differ from the source's, failing if they do.
+pub async fn run(engine: &IglooEngine, args: DiffArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let diff = engine.diff_postgres_schema(&args.postgres, &args.source, &args.table).await?;
+    println!("{diff}");
+    match diff.has_drifted() {
+        true => Err(format!("{} has drifted from {}", args.table, args.source).into()),
+        false => Ok(()),
+    }
+}
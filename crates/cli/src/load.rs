@@ -0,0 +1,77 @@
+//! `igloo load`: bulk loading a CSV or JSON file into a table.
+
+use clap::Args;
+use igloo::{IglooEngine, IngestOptions, LoadFormat, LoadOptions, LoadProgress};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Args)]
+pub struct LoadArgs {
+    /// The table to append to, e.g. `iceberg.sales.orders`.
+    #[arg(long, value_name = "NAME")]
+    table: String,
+
+    /// The file to load.
+    #[arg(long, value_name = "FILE")]
+    path: PathBuf,
+
+    /// `csv` or `json` (one object per line). Defaults to the file's extension.
+    #[arg(long, value_name = "FORMAT")]
+    format: Option<LoadFormat>,
+
+    /// The CSV file has no header line; its columns are the table's, in order.
+    #[arg(long)]
+    no_header: bool,
+
+    /// Field delimiter of the CSV file.
+    #[arg(long, default_value_t = ',', value_name = "CHAR")]
+    delimiter: char,
+
+    /// Skip up to this many records that do not fit the table before failing.
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    max_bad_records: usize,
+
+    /// Columns identifying a record, comma-separated: records repeating the keys of an
+    /// earlier one are left out.
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS")]
+    keys: Vec<String>,
+}
+
+/// Load the file, reporting progress on stderr.
+pub async fn run(engine: &IglooEngine, args: LoadArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let delimiter = u8::try_from(args.delimiter).map_err(|_| {
+        format!("invalid --delimiter '{}', expected an ASCII character", args.delimiter)
+    })?;
+    let mut options = LoadOptions::default()
+        .with_header(!args.no_header)
+        .with_delimiter(delimiter)
+        .with_max_bad_records(args.max_bad_records)
+        .with_ingest(IngestOptions::default().with_keys(args.keys));
+    if let Some(format) = args.format {
+        options = options.with_format(format);
+    }
+    let path = args.path.to_string_lossy();
+    let progress = |progress: &LoadProgress| {
+        let percent = match progress.total_bytes {
+            0 => 100,
+            total => progress.bytes_read * 100 / total,
+        };
+        eprint!("\r{} rows read ({percent}%)", progress.rows);
+        let _ = std::io::stderr().flush();
+    };
+    let report = engine.load(&args.table, &path, options, progress).await;
+    eprintln!();
+    let report = report?;
+    for record in &report.bad_records {
+        eprintln!("skipped line {}: {}", record.line, record.reason);
+    }
+    println!(
+        "Loaded {} rows into {} in {} commits, skipping {} bad records and {} duplicates.",
+        report.rows,
+        args.table,
+        report.commits,
+        report.bad_records.len(),
+        report.duplicates
+    );
+    Ok(())
+}
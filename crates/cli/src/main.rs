@@ -0,0 +1,204 @@
+//! `igloo`: an interactive SQL shell over an embedded [`IglooEngine`], and subcommands:
+//! `igloo serve` to start the coordinator's servers and CDC pipelines, `igloo query`
+//! to run statements, `igloo run` to run SQL script files, `igloo load` for bulk
+//! loading files into tables, `igloo snapshot` for copying Postgres tables, `igloo
+//! diff` for checking their copies for schema drift, `igloo catalog sync` for
+//! re-reading a coordinator's external catalogs, `igloo state` for copying the state
+//! of one coordinator to another, and `igloo bench` for timing the TPC-H queries.
+
+mod bench;
+mod catalog;
+mod diff;
+mod load;
+mod query;
+mod script;
+mod shell;
+mod snapshot;
+mod state;
+mod tpch;
+
+use clap::{Parser, Subcommand};
+use igloo::connectors::hive::{HiveCatalogProvider, HiveMetastoreClient};
+use igloo::connectors::iceberg::{IcebergCatalogProvider, RestCatalog};
+use igloo::{IglooEngine, OutputFormat};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use shell::{InputHelper, Shell};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+#[derive(Debug, Parser)]
+#[command(name = "igloo", version, about = "Igloo SQL shell, servers and tools")]
+struct Args {
+    /// Register a file as a table. CSV, Parquet and newline-delimited JSON are
+    /// recognised by extension. May be repeated.
+    #[arg(long = "table", value_name = "NAME=PATH")]
+    tables: Vec<String>,
+
+    /// Attach the Hive Metastore at this Thrift address as the `hive` catalog.
+    #[arg(long, value_name = "HOST:PORT")]
+    hive_metastore: Option<String>,
+
+    /// Attach the Iceberg REST catalog at this URI as the `iceberg` catalog.
+    #[arg(long, value_name = "URI")]
+    iceberg_rest: Option<String>,
+
+    /// The warehouse to request from the Iceberg REST catalog.
+    #[arg(long, value_name = "NAME", requires = "iceberg_rest")]
+    iceberg_warehouse: Option<String>,
+
+    /// Bearer token for the Iceberg REST catalog.
+    #[arg(long, value_name = "TOKEN", requires = "iceberg_rest")]
+    iceberg_token: Option<String>,
+
+    /// Run a single statement or metacommand, then exit.
+    #[arg(short, long)]
+    command: Option<String>,
+
+    /// How to render results.
+    #[arg(short, long, default_value = "table", value_name = "FORMAT")]
+    format: OutputFormat,
+
+    /// Write results to this file instead of stdout (e.g. with `--format parquet`).
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Where to keep line history. Defaults to `~/.igloo_history`.
+    #[arg(long, value_name = "FILE")]
+    history: Option<PathBuf>,
+
+    #[command(subcommand)]
+    action: Option<Action>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    /// Start the coordinator: its servers, sources and CDC pipelines, as configured by
+    /// its configuration file, the environment and these flags.
+    Serve(igloo_coordinator::config::Args),
+    /// Run SQL statements, printing their results, or check them with `--dry-run`, then
+    /// exit.
+    Query(query::QueryArgs),
+    /// Run a SQL script file, with `${NAME}` replaced by the values of `--var`, then
+    /// exit.
+    Run(script::ScriptArgs),
+    /// Append the records of a CSV or JSON file to a table, then exit.
+    Load(load::LoadArgs),
+    /// Copy a Postgres table, as of one snapshot, into a new Parquet table or an
+    /// existing table, then exit.
+    Snapshot(snapshot::SnapshotArgs),
+    /// Compare the columns of a Postgres table with those of its copy in the lake,
+    /// failing if they differ.
+    Diff(diff::DiffArgs),
+    /// Generate TPC-H data, optionally load it into Postgres, and time the 22 queries
+    /// over Parquet, Postgres or both, then exit.
+    Bench(bench::BenchArgs),
+    /// Manage the external catalogs of a running coordinator.
+    Catalog(catalog::CatalogArgs),
+    /// Export the tables, views and policies of a running coordinator, or import those
+    /// of another.
+    State(state::StateArgs),
+}
+
+// Counts heap usage for `GET /admin/memory` and `GET /metrics` of `igloo serve`
+#[global_allocator]
+static ALLOCATOR: igloo_common::memory::CountingAllocator = igloo_common::memory::CountingAllocator;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Args::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("igloo: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    // These set up engines of their own, or use a coordinator's.
+    let action = match args.action {
+        Some(Action::Serve(serve)) => return igloo_coordinator::run(serve).await,
+        Some(Action::Bench(bench)) => return bench::run(bench).await,
+        Some(Action::Catalog(catalog)) => return catalog::run(catalog).await,
+        Some(Action::State(state)) => return state::run(state).await,
+        action => action,
+    };
+    let engine = IglooEngine::new();
+    for spec in &args.tables {
+        let (name, path) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid --table '{spec}', expected NAME=PATH"))?;
+        engine.register_file(name, path).await?;
+    }
+    // External catalogs are registered as sources so `\sync` can re-read them.
+    if let Some(addr) = args.hive_metastore {
+        let client = Arc::new(HiveMetastoreClient::new(addr));
+        let catalog = Arc::new(HiveCatalogProvider::try_new(client).await?);
+        engine.register_catalog_source("hive", catalog).await?;
+    }
+    if let Some(uri) = args.iceberg_rest {
+        let mut catalog = RestCatalog::new(uri);
+        if let Some(warehouse) = args.iceberg_warehouse {
+            catalog = catalog.with_warehouse(warehouse);
+        }
+        if let Some(token) = args.iceberg_token {
+            catalog = catalog.with_token(token);
+        }
+        let catalog = Arc::new(IcebergCatalogProvider::try_new(Arc::new(catalog)).await?);
+        engine.register_catalog_source("iceberg", catalog).await?;
+    }
+    let mut shell = Shell::new(engine.clone()).with_format(args.format).with_output(args.output);
+    match action {
+        Some(Action::Load(load)) => return load::run(&engine, load).await,
+        Some(Action::Snapshot(snapshot)) => return snapshot::run(&engine, snapshot).await,
+        Some(Action::Diff(diff)) => return diff::run(&engine, diff).await,
+        Some(Action::Query(query)) => return query::run(&engine, &shell, query).await,
+        Some(Action::Run(script)) => return script::run(&shell, script).await,
+        _ => {}
+    }
+    let mut stdout = std::io::stdout();
+
+    if let Some(command) = args.command {
+        let _ = shell.execute(&command, &mut stdout).await;
+        return Ok(());
+    }
+
+    let history = args.history.or_else(|| {
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".igloo_history"))
+    });
+    let mut editor: Editor<InputHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(InputHelper));
+    if let Some(history) = &history {
+        // A missing history file just means this is the first session.
+        let _ = editor.load_history(history);
+    }
+
+    println!("Igloo shell. Type \\? for help, \\q to quit.");
+    loop {
+        match editor.readline("igloo> ") {
+            Ok(input) => {
+                if input.trim().is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(input.as_str())?;
+                if shell.execute(&input, &mut stdout).await.is_break() {
+                    break;
+                }
+            }
+            // Ctrl-C abandons the current input, as in psql.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if let Some(history) = &history {
+        if let Err(e) = editor.save_history(history) {
+            eprintln!("Failed to save history to {}: {}", history.display(), e);
+        }
+    }
+    Ok(())
+}
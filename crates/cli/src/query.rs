@@ -0,0 +1,100 @@
+//! `igloo query`: running SQL statements without the interactive shell.
+
+use crate::shell::Shell;
+use clap::Args;
+use igloo::{IglooEngine, Validation};
+use std::io::Write;
+
+#[derive(Debug, Args)]
+pub struct QueryArgs {
+    /// A statement to run. May be repeated; statements run in order.
+    #[arg(short = 'e', long = "execute", value_name = "SQL", required = true)]
+    statements: Vec<String>,
+    /// Only check the statements: plan each, without running it or reading from any
+    /// source, and report the tables and pushdown of its scans.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Run the statements, stopping at the first that fails. With `--dry-run`, check every
+/// statement and fail if any is invalid.
+pub async fn run(
+    engine: &IglooEngine,
+    shell: &Shell,
+    args: QueryArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut stdout = std::io::stdout();
+    if !args.dry_run {
+        for sql in &args.statements {
+            shell.run_sql(sql, &mut stdout).await?;
+        }
+        return Ok(());
+    }
+    let mut invalid = 0;
+    for sql in &args.statements {
+        match engine.validate(sql).await {
+            Ok(validation) => report(sql, &validation, &mut stdout)?,
+            Err(e) => {
+                invalid += 1;
+                writeln!(stdout, "INVALID: {}\n  {e}", sql.trim())?;
+            }
+        }
+    }
+    match invalid {
+        0 => Ok(()),
+        _ => Err(format!("{invalid} of {} statements are invalid", args.statements.len()).into()),
+    }
+}
+
+/// Write what running `sql` would do.
+fn report(sql: &str, validation: &Validation, out: &mut impl Write) -> std::io::Result<()> {
+    writeln!(out, "OK: {}", sql.trim())?;
+    if !validation.tables.is_empty() {
+        writeln!(out, "  tables: {}", validation.tables.join(", "))?;
+    }
+    for scan in &validation.scans {
+        write!(out, "  scan {}: columns {}", scan.table, scan.columns.join(", "))?;
+        if !scan.filters.is_empty() {
+            write!(out, "; filters {}", scan.filters.join(" AND "))?;
+        }
+        if let Some(limit) = scan.limit {
+            write!(out, "; limit {limit}")?;
+        }
+        writeln!(out)?;
+    }
+    for diagnostic in &validation.diagnostics {
+        writeln!(out, "  {diagnostic}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dry_runs_report_the_scans() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = IglooEngine::new();
+        engine.query("CREATE TABLE orders (id BIGINT, total DOUBLE) AS VALUES (1, 9.5)").await?;
+        let sql = "SELECT id FROM orders LIMIT 5";
+        let mut out = Vec::new();
+        report(sql, &engine.validate(sql).await?, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "OK: SELECT id FROM orders LIMIT 5\n  tables: orders\n  \
+             scan orders: columns id; limit 5\n"
+        );
+
+        let shell = Shell::new(engine.clone());
+        let args = |statements: &[&str]| QueryArgs {
+            statements: statements.iter().map(|sql| sql.to_string()).collect(),
+            dry_run: true,
+        };
+        run(&engine, &shell, args(&["DELETE FROM orders", "SELECT 1"])).await?;
+        let error =
+            run(&engine, &shell, args(&["SELECT 1", "SELECT x FROM missing"])).await.unwrap_err();
+        assert_eq!(error.to_string(), "1 of 2 statements are invalid");
+        assert_eq!(engine.query("SELECT id FROM orders").await?.batches[0].num_rows(), 1);
+        Ok(())
+    }
+}
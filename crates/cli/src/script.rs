@@ -0,0 +1,208 @@
+//! `igloo run`: running SQL script files, e.g. for scheduled reports.
+//!
+//! A script holds statements separated by `;`. Before it is split, each `${name}` in
+//! it is replaced by the value given with `--var name=value`; a variable with no value
+//! is an error. Results go to stdout in the shell's format, and the time each
+//! statement took, or its error, to stderr.
+
+use crate::shell::Shell;
+use clap::Args;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Instant;
+
+#[derive(Debug, Args)]
+pub struct ScriptArgs {
+    /// The SQL script to run.
+    file: PathBuf,
+    /// A value for `${NAME}` in the script. May be repeated.
+    #[arg(long = "var", value_name = "NAME=VALUE")]
+    vars: Vec<String>,
+    /// Stop at the first statement that fails, instead of running the others.
+    #[arg(long)]
+    stop_on_error: bool,
+}
+
+/// Run the script, failing if any of its statements did.
+pub async fn run(shell: &Shell, args: ScriptArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let script = std::fs::read_to_string(&args.file)
+        .map_err(|e| format!("cannot read {}: {e}", args.file.display()))?;
+    let mut vars = HashMap::new();
+    for spec in &args.vars {
+        let (name, value) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("invalid --var '{spec}', expected NAME=VALUE"))?;
+        vars.insert(name, value);
+    }
+    let script = substitute(&script, &vars)?;
+    let (mut stdout, mut stderr) = (std::io::stdout(), std::io::stderr());
+    let failed = run_script(shell, &script, args.stop_on_error, &mut stdout, &mut stderr).await?;
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{}: {failed} of its statements failed", args.file.display()).into()),
+    }
+}
+
+/// Run the statements of `script`, writing their results to `out` and their timings
+/// and errors to `log`, and return how many failed.
+async fn run_script(
+    shell: &Shell,
+    script: &str,
+    stop_on_error: bool,
+    out: &mut (impl Write + Send),
+    log: &mut impl Write,
+) -> std::io::Result<usize> {
+    let statements = split_statements(script);
+    let started = Instant::now();
+    let (mut ran, mut failed) = (0, 0);
+    for (n, (line, sql)) in statements.iter().enumerate() {
+        let statement_started = Instant::now();
+        let result = shell.run_sql(sql, out).await;
+        let elapsed_ms = statement_started.elapsed().as_secs_f64() * 1000.0;
+        ran += 1;
+        match result {
+            Ok(()) => writeln!(log, "Statement {} (line {line}): {elapsed_ms:.3} ms", n + 1)?,
+            Err(e) => {
+                failed += 1;
+                writeln!(log, "Statement {} (line {line}) failed: {e}", n + 1)?;
+                if stop_on_error {
+                    break;
+                }
+            }
+        }
+    }
+    let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+    writeln!(
+        log,
+        "Ran {ran} of {} statements, {failed} failed, in {elapsed_ms:.3} ms",
+        statements.len()
+    )?;
+    Ok(failed)
+}
+
+/// `script` with each `${name}` replaced by the value of `name`.
+fn substitute(script: &str, vars: &HashMap<&str, &str>) -> Result<String, String> {
+    let mut substituted = String::with_capacity(script.len());
+    let mut rest = script;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..].find('}').ok_or("unterminated ${ in the script")? + start;
+        let name = &rest[start + 2..end];
+        let value = vars.get(name).ok_or_else(|| format!("no value for ${{{name}}}, set --var"))?;
+        substituted.push_str(&rest[..start]);
+        substituted.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    substituted.push_str(rest);
+    Ok(substituted)
+}
+
+/// The statements of `script`, split at the `;`s outside of quotes and comments, each
+/// with the line it starts on. Statements with nothing but comments are left out.
+fn split_statements(script: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut line = 1;
+    // Where the current statement starts, if it has started, and on which line.
+    let mut start: Option<(usize, usize)> = None;
+    let mut chars = script.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '-' if chars.peek().map(|&(_, c)| c) == Some('-') => {
+                while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+            }
+            '/' if chars.peek().map(|&(_, c)| c) == Some('*') => {
+                chars.next();
+                let mut previous = ' ';
+                for (_, c) in chars.by_ref() {
+                    line += usize::from(c == '\n');
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            ';' => {
+                if let Some((begin, begin_line)) = start.take() {
+                    statements.push((begin_line, script[begin..i].trim_end()));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                start.get_or_insert((i, line));
+                // A doubled quote inside quotes closes and reopens them.
+                if c == '\'' || c == '"' {
+                    for (_, next) in chars.by_ref() {
+                        line += usize::from(next == '\n');
+                        if next == c {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let Some((begin, begin_line)) = start {
+        statements.push((begin_line, script[begin..].trim_end()));
+    }
+    statements
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo::IglooEngine;
+
+    #[test]
+    fn test_scripts_are_split_outside_quotes_and_comments() {
+        let script = "-- daily report\nCREATE TABLE t AS SELECT 'a;b' AS x;\n\n\
+                      SELECT x /* ; */ FROM t; -- done;\n/* trailing */";
+        assert_eq!(
+            split_statements(script),
+            [(2, "CREATE TABLE t AS SELECT 'a;b' AS x"), (4, "SELECT x /* ; */ FROM t")]
+        );
+        assert_eq!(
+            split_statements("SELECT 'it''s';SELECT 2"),
+            [(1, "SELECT 'it''s'"), (1, "SELECT 2")]
+        );
+        assert!(split_statements("  -- nothing\n").is_empty());
+    }
+
+    #[test]
+    fn test_variables_are_substituted() {
+        let vars = HashMap::from([("date", "2024-01-01"), ("table", "orders")]);
+        assert_eq!(
+            substitute("SELECT * FROM ${table} WHERE day = '${date}'", &vars).unwrap(),
+            "SELECT * FROM orders WHERE day = '2024-01-01'"
+        );
+        assert_eq!(
+            substitute("SELECT ${missing}", &vars).unwrap_err(),
+            "no value for ${missing}, set --var"
+        );
+        assert!(substitute("SELECT ${date", &vars).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scripts_run_until_the_end_or_the_first_error(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let engine = IglooEngine::new();
+        let shell = Shell::new(engine.clone());
+        let script = "CREATE TABLE t (x INT);\nSELECT * FROM missing;\nINSERT INTO t VALUES (1);";
+        let (mut out, mut log) = (Vec::new(), Vec::new());
+        assert_eq!(run_script(&shell, script, true, &mut out, &mut log).await?, 1);
+        let log = String::from_utf8(log)?;
+        assert!(log.starts_with("Statement 1 (line 1): "), "{log}");
+        assert!(log.contains("Statement 2 (line 2) failed: "), "{log}");
+        assert!(log.contains("Ran 2 of 3 statements, 1 failed"), "{log}");
+        let result = engine.query("SELECT * FROM t").await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+
+        let script = script.replace("CREATE TABLE t", "CREATE TABLE IF NOT EXISTS t");
+        let (mut out, mut log) = (Vec::new(), Vec::new());
+        assert_eq!(run_script(&shell, &script, false, &mut out, &mut log).await?, 1);
+        assert!(String::from_utf8(log)?.contains("Ran 3 of 3 statements, 1 failed"));
+        let result = engine.query("SELECT * FROM t").await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+        Ok(())
+    }
+}
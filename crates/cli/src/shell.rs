@@ -0,0 +1,365 @@
+//! Interactive SQL shell.
+//!
+//! Lines are accumulated until a statement ends with `;`, so SQL can span several
+//! lines. Input starting with `\` is a metacommand and runs immediately:
+//!
+//! | Command         | Effect                               |
+//! |-----------------|--------------------------------------|
+//! | `\dt`           | list tables                          |
+//! | `\d <table>`    | describe a table's columns           |
+//! | `\timing [on\|off]` | toggle display of query run time |
+//! | `\format [name]` | show or set the result format        |
+//! | `\o [file]`      | write results to a file, or back to stdout |
+//! | `\sync <catalog> [--dry-run]` | re-read an external catalog and apply its drift |
+//! | `\?`            | show help                            |
+//! | `\q`            | quit                                 |
+
+use igloo::datafusion::arrow::array::{ArrayRef, StringArray};
+use igloo::datafusion::arrow::record_batch::RecordBatch;
+use igloo::datafusion::arrow::util::pretty::pretty_format_batches;
+use igloo::datafusion::datasource::TableType;
+use igloo::datafusion::error::Result as DataFusionResult;
+use igloo::{IglooEngine, OutputFormat};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Completer, Helper, Highlighter, Hinter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub const HELP: &str = "\
+\\dt                 list tables
+\\d <table>          describe a table
+\\timing [on|off]    toggle display of query run time
+\\format [name]      show or set the result format (table, csv, json, jsonl, parquet, arrow)
+\\o [file]           write results to a file; without a file, back to stdout
+\\sync <catalog> [--dry-run]
+                    re-read an external catalog and apply what changed at its
+                    source; with --dry-run, only report it
+\\?                  show this help
+\\q                  quit
+
+SQL statements end with `;` and may span several lines.";
+
+/// A parsed line of shell input.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command<'a> {
+    Quit,
+    Help,
+    ListTables,
+    Describe(&'a str),
+    /// `None` toggles the current setting.
+    Timing(Option<bool>),
+    /// `None` shows the current format.
+    Format(Option<&'a str>),
+    /// `None` resets output to stdout.
+    Output(Option<&'a str>),
+    Sync {
+        catalog: &'a str,
+        dry_run: bool,
+    },
+    Sql(&'a str),
+    Unknown(&'a str),
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(input: &'a str) -> Self {
+        let input = input.trim();
+        let Some(meta) = input.strip_prefix('\\') else {
+            return Command::Sql(input);
+        };
+        let (name, arg) = match meta.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (meta, ""),
+        };
+        match (name, arg) {
+            ("q" | "quit", _) => Command::Quit,
+            ("?" | "h" | "help", _) => Command::Help,
+            ("dt", _) => Command::ListTables,
+            ("d", "") => Command::ListTables,
+            ("d", table) => Command::Describe(table),
+            ("timing", "") => Command::Timing(None),
+            ("timing", "on") => Command::Timing(Some(true)),
+            ("timing", "off") => Command::Timing(Some(false)),
+            ("format", "") => Command::Format(None),
+            ("format", format) => Command::Format(Some(format)),
+            ("o" | "out", "") => Command::Output(None),
+            ("o" | "out", file) => Command::Output(Some(file)),
+            ("sync", arg) => match arg.split_whitespace().collect::<Vec<_>>()[..] {
+                [catalog] => Command::Sync { catalog, dry_run: false },
+                [catalog, "--dry-run"] => Command::Sync { catalog, dry_run: true },
+                _ => Command::Unknown(input),
+            },
+            _ => Command::Unknown(input),
+        }
+    }
+}
+
+/// Whether `input` is ready to run: a metacommand, or SQL terminated by `;`.
+pub fn is_complete(input: &str) -> bool {
+    let input = input.trim();
+    input.is_empty() || input.starts_with('\\') || input.ends_with(';')
+}
+
+/// rustyline helper that keeps reading lines until the input [`is_complete`].
+#[derive(Completer, Helper, Highlighter, Hinter)]
+pub struct InputHelper;
+
+impl Validator for InputHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if is_complete(ctx.input()) {
+            ValidationResult::Valid(None)
+        } else {
+            ValidationResult::Incomplete
+        })
+    }
+}
+
+pub struct Shell {
+    engine: IglooEngine,
+    timing: bool,
+    format: OutputFormat,
+    /// Results go here instead of the shell's output when set.
+    output: Option<PathBuf>,
+}
+
+impl Shell {
+    pub fn new(engine: IglooEngine) -> Self {
+        Self { engine, timing: false, format: OutputFormat::Table, output: None }
+    }
+
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_output(mut self, output: Option<PathBuf>) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Run one complete input, writing its output to `out`. Errors are reported to
+    /// `out` as well; only [`Command::Quit`] breaks the loop.
+    pub async fn execute(&mut self, input: &str, out: &mut (impl Write + Send)) -> ControlFlow<()> {
+        let result = match Command::parse(input) {
+            Command::Quit => return ControlFlow::Break(()),
+            Command::Help => writeln!(out, "{HELP}").map_err(Into::into),
+            Command::ListTables => self.list_tables(out).await,
+            Command::Describe(table) => self.describe(table, out).await,
+            Command::Timing(setting) => {
+                self.timing = setting.unwrap_or(!self.timing);
+                let state = if self.timing { "on" } else { "off" };
+                writeln!(out, "Timing is {state}.").map_err(Into::into)
+            }
+            Command::Format(None) => {
+                writeln!(out, "Output format is {}.", self.format).map_err(Into::into)
+            }
+            Command::Format(Some(name)) => name.parse().and_then(|format| {
+                self.format = format;
+                writeln!(out, "Output format is {format}.").map_err(Into::into)
+            }),
+            Command::Output(file) => {
+                self.output = file.map(PathBuf::from);
+                match &self.output {
+                    Some(path) => writeln!(out, "Writing results to {}.", path.display()),
+                    None => writeln!(out, "Writing results to stdout."),
+                }
+                .map_err(Into::into)
+            }
+            Command::Sync { catalog, dry_run } => {
+                let report = match dry_run {
+                    true => self.engine.diff_catalog(catalog).await,
+                    false => self.engine.sync_catalog(catalog).await,
+                };
+                report.and_then(|report| writeln!(out, "{report}").map_err(Into::into))
+            }
+            Command::Sql("") => Ok(()),
+            Command::Sql(sql) => self.run_sql(sql, out).await,
+            Command::Unknown(command) => {
+                writeln!(out, "Invalid command {command}. Try \\? for help.").map_err(Into::into)
+            }
+        };
+        if let Err(e) = result {
+            let _ = writeln!(out, "Error: {e}");
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Run one SQL statement, writing its result to `out` or the output file.
+    pub async fn run_sql(&self, sql: &str, out: &mut (impl Write + Send)) -> DataFusionResult<()> {
+        let started = Instant::now();
+        let result = self.engine.query(sql).await?;
+        let elapsed = started.elapsed();
+
+        // Status lines only go inline with human-readable output; machine formats on
+        // stdout stay clean and get them on stderr instead.
+        let inline_status = self.output.is_some() || self.format == OutputFormat::Table;
+        for diagnostic in &result.diagnostics {
+            status(out, inline_status, diagnostic)?;
+        }
+
+        let rows: usize = result.batches.iter().map(|b| b.num_rows()).sum();
+        let noun = if rows == 1 { "row" } else { "rows" };
+        match &self.output {
+            Some(path) => {
+                let file = BufWriter::new(File::create(path)?);
+                self.format.write(&result.schema, &result.batches, file)?;
+                status(out, inline_status, format!("Wrote {rows} {noun} to {}", path.display()))?;
+            }
+            None => {
+                // Statements such as DDL produce no columns; there is nothing to render.
+                if !result.schema.fields().is_empty() {
+                    self.format.write(&result.schema, &result.batches, &mut *out)?;
+                }
+                if self.format == OutputFormat::Table {
+                    status(out, inline_status, format!("({rows} {noun})"))?;
+                }
+            }
+        }
+        if self.timing {
+            let elapsed_ms = elapsed.as_secs_f64() * 1000.0;
+            status(out, inline_status, format!("Time: {elapsed_ms:.3} ms"))?;
+        }
+        Ok(())
+    }
+
+    async fn list_tables(&self, out: &mut impl Write) -> DataFusionResult<()> {
+        let ctx = self.engine.session_context();
+        let (mut schemas, mut names, mut types) = (Vec::new(), Vec::new(), Vec::<&str>::new());
+        for catalog_name in ctx.catalog_names() {
+            let Some(catalog) = ctx.catalog(&catalog_name) else { continue };
+            for schema_name in catalog.schema_names() {
+                let Some(schema) = catalog.schema(&schema_name) else { continue };
+                let mut tables = schema.table_names();
+                tables.sort();
+                for table in tables {
+                    let provider = schema.table(&table).await?;
+                    schemas.push(schema_name.clone());
+                    names.push(table);
+                    types.push(match provider.map(|p| p.table_type()) {
+                        Some(TableType::Base) => "table",
+                        Some(TableType::View) => "view",
+                        Some(TableType::Temporary) => "temporary",
+                        None => "",
+                    });
+                }
+            }
+        }
+        if names.is_empty() {
+            writeln!(out, "Did not find any tables.")?;
+            return Ok(());
+        }
+        let batch = string_batch(&[
+            ("Schema", schemas),
+            ("Name", names),
+            ("Type", types.into_iter().map(String::from).collect()),
+        ])?;
+        writeln!(out, "{}", pretty_format_batches(&[batch])?)?;
+        Ok(())
+    }
+
+    async fn describe(&self, table: &str, out: &mut impl Write) -> DataFusionResult<()> {
+        let provider = self.engine.session_context().table_provider(table).await?;
+        let schema = provider.schema();
+        let (mut columns, mut types, mut nullable) = (Vec::new(), Vec::new(), Vec::new());
+        for field in schema.fields() {
+            columns.push(field.name().clone());
+            types.push(field.data_type().to_string());
+            nullable.push(if field.is_nullable() { "YES" } else { "NO" }.to_string());
+        }
+        let batch = string_batch(&[("Column", columns), ("Type", types), ("Nullable", nullable)])?;
+        writeln!(out, "{}", pretty_format_batches(&[batch])?)?;
+        Ok(())
+    }
+}
+
+fn status(out: &mut impl Write, inline: bool, line: impl std::fmt::Display) -> std::io::Result<()> {
+    if inline {
+        writeln!(out, "{line}")
+    } else {
+        writeln!(std::io::stderr(), "{line}")
+    }
+}
+
+fn string_batch(columns: &[(&str, Vec<String>)]) -> DataFusionResult<RecordBatch> {
+    let columns = columns
+        .iter()
+        .map(|(name, values)| (*name, Arc::new(StringArray::from(values.clone())) as ArrayRef));
+    Ok(RecordBatch::try_from_iter(columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo::datafusion::arrow::array::AsArray;
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("\\q"), Command::Quit);
+        assert_eq!(Command::parse("\\dt"), Command::ListTables);
+        assert_eq!(Command::parse("\\d  orders "), Command::Describe("orders"));
+        assert_eq!(Command::parse("\\timing on"), Command::Timing(Some(true)));
+        assert_eq!(Command::parse("\\timing"), Command::Timing(None));
+        assert_eq!(Command::parse(" SELECT 1; "), Command::Sql("SELECT 1;"));
+        assert_eq!(Command::parse("\\format csv"), Command::Format(Some("csv")));
+        assert_eq!(Command::parse("\\o"), Command::Output(None));
+        assert_eq!(
+            Command::parse("\\sync iceberg --dry-run"),
+            Command::Sync { catalog: "iceberg", dry_run: true }
+        );
+        assert_eq!(Command::parse("\\sync"), Command::Unknown("\\sync"));
+        assert_eq!(Command::parse("\\frobnicate"), Command::Unknown("\\frobnicate"));
+    }
+
+    #[test]
+    fn test_statements_span_lines_until_semicolon() {
+        assert!(!is_complete("SELECT *\nFROM t"));
+        assert!(is_complete("SELECT *\nFROM t;  "));
+        assert!(is_complete("\\dt"));
+        assert!(is_complete(""));
+    }
+
+    #[tokio::test]
+    async fn test_execute_sql_and_metacommands() {
+        let mut shell = Shell::new(IglooEngine::new());
+        let mut out = Vec::new();
+        let _ = shell.execute("CREATE TABLE t (x INT) AS VALUES (1), (2);", &mut out).await;
+        let _ = shell.execute("SELECT x FROM t ORDER BY x;", &mut out).await;
+        let _ = shell.execute("\\dt", &mut out).await;
+        let _ = shell.execute("\\d t", &mut out).await;
+        let _ = shell.execute("SELECT * FROM missing;", &mut out).await;
+        assert!(shell.execute("\\q", &mut out).await.is_break());
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("(2 rows)"), "{out}");
+        assert!(out.contains("| public | t    | table |"), "{out}");
+        assert!(out.contains("| x      | Int32 | YES      |"), "{out}");
+        assert!(out.contains("Error: "), "{out}");
+    }
+
+    #[tokio::test]
+    async fn test_format_and_output_file() {
+        let mut shell = Shell::new(IglooEngine::new());
+        let mut out = Vec::new();
+        let _ = shell.execute("\\format csv", &mut out).await;
+        let _ = shell.execute("SELECT 1 AS a;", &mut out).await;
+        assert_eq!(String::from_utf8(out).unwrap(), "Output format is csv.\na\n1\n");
+
+        let path = std::env::temp_dir().join(format!("igloo-shell-{}.parquet", std::process::id()));
+        let mut out = Vec::new();
+        let _ = shell.execute("\\format parquet", &mut out).await;
+        let _ = shell.execute(&format!("\\o {}", path.display()), &mut out).await;
+        let _ = shell.execute("SELECT 1 AS a UNION ALL SELECT 2;", &mut out).await;
+        assert!(String::from_utf8(out).unwrap().contains("Wrote 2 rows to"));
+        let engine = IglooEngine::new();
+        engine.register_file("written", path.to_str().unwrap()).await.unwrap();
+        let result = engine.query("SELECT sum(a) FROM written").await.unwrap();
+        let sum = result.batches[0]
+            .column(0)
+            .as_primitive::<igloo::datafusion::arrow::datatypes::Int64Type>();
+        assert_eq!(sum.value(0), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+//! `igloo snapshot`: copying a Postgres table into the lake, as the initial load ahead
+//! of CDC.
+
+use clap::Args;
+use igloo::connectors::postgres::{PostgresSnapshot, SnapshotOptions};
+use igloo::IglooEngine;
+use std::sync::Arc;
+
+/// The name the snapshot is registered under while it is copied.
+const SOURCE: &str = "postgres_snapshot";
+
+#[derive(Debug, Args)]
+pub struct SnapshotArgs {
+    /// Connection string of the Postgres database, e.g. `host=db user=igloo dbname=shop`.
+    #[arg(long, value_name = "CONFIG")]
+    postgres: String,
+
+    /// The Postgres table to copy, `schema.table` or a table of `public`.
+    #[arg(long, value_name = "TABLE")]
+    source: String,
+
+    /// The table to create, or with no --location an existing table to append to (e.g.
+    /// an Iceberg table of the `iceberg` catalog).
+    #[arg(long, value_name = "NAME")]
+    table: String,
+
+    /// Create the table as Parquet files under this directory.
+    #[arg(long, value_name = "URL")]
+    location: Option<String>,
+
+    /// Copy the table in this many chunks, in parallel.
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    chunks: usize,
+
+    /// The integer column chunks are ranges of. Defaults to the primary key.
+    #[arg(long, value_name = "COLUMN")]
+    chunk_column: Option<String>,
+}
+
+/// Copy the table as of one snapshot.
+pub async fn run(
+    engine: &IglooEngine,
+    args: SnapshotArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut options = SnapshotOptions::default().with_chunks(args.chunks);
+    if let Some(column) = args.chunk_column {
+        options = options.with_chunk_column(column);
+    }
+    let snapshot = PostgresSnapshot::try_new(&args.postgres, &args.source, options).await?;
+    eprintln!(
+        "Copying {} as of snapshot {} in {} chunks.",
+        args.source,
+        snapshot.snapshot_id(),
+        snapshot.chunks()
+    );
+    engine.register_table(SOURCE, Arc::new(snapshot))?;
+    let sql = match &args.location {
+        Some(location) => format!(
+            "CREATE TABLE {} WITH (location = '{}') AS SELECT * FROM {SOURCE}",
+            args.table,
+            location.replace('\'', "''")
+        ),
+        None => format!("INSERT INTO {} SELECT * FROM {SOURCE}", args.table),
+    };
+    engine.query(&sql).await?;
+    println!("Copied {} into {}.", args.source, args.table);
+    Ok(())
+}
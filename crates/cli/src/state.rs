@@ -0,0 +1,141 @@
+//! `igloo state`: moving the tables, views and policies set up at runtime from one
+//! coordinator to another, e.g. from staging to prod, through their HTTP APIs.
+
+use clap::{Args, Subcommand};
+use igloo::{ImportReport, StateBundle};
+use std::path::PathBuf;
+
+#[derive(Debug, Args)]
+pub struct StateArgs {
+    /// The coordinator's HTTP API.
+    #[arg(long, default_value = "http://127.0.0.1:8080", value_name = "URL", global = true)]
+    server: String,
+
+    /// API key or token to authenticate to the coordinator with.
+    #[arg(long, value_name = "TOKEN", global = true)]
+    token: Option<String>,
+
+    #[command(subcommand)]
+    action: StateAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum StateAction {
+    /// Write the coordinator's state to a JSON bundle.
+    Export {
+        /// The bundle to write; stdout if not given.
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Set up what a bundle exported from another coordinator holds, failing if any
+    /// of it could not be.
+    Import {
+        /// The bundle to read.
+        file: PathBuf,
+    },
+}
+
+/// Run the state command.
+pub async fn run(args: StateArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let client = Coordinator { server: args.server.trim_end_matches('/'), token: args.token };
+    match args.action {
+        StateAction::Export { output } => {
+            let bundle = serde_json::to_string_pretty(&client.export().await?)?;
+            match output {
+                Some(path) => std::fs::write(&path, bundle + "\n")
+                    .map_err(|e| format!("cannot write {}: {e}", path.display()))?,
+                None => println!("{bundle}"),
+            }
+        }
+        StateAction::Import { file } => {
+            let bundle = std::fs::read_to_string(&file)
+                .map_err(|e| format!("cannot read {}: {e}", file.display()))?;
+            let bundle: StateBundle = serde_json::from_str(&bundle)
+                .map_err(|e| format!("{} is not a state bundle: {e}", file.display()))?;
+            let report = client.import(&bundle).await?;
+            println!("{report}");
+            if !report.errors.is_empty() {
+                return Err(format!("{} parts of the bundle failed", report.errors.len()).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Coordinator<'a> {
+    server: &'a str,
+    token: Option<String>,
+}
+
+impl Coordinator<'_> {
+    async fn export(&self) -> Result<StateBundle, Box<dyn std::error::Error>> {
+        let request = reqwest::Client::new().get(format!("{}/admin/state", self.server));
+        Ok(self.send(request, "export").await?.json().await?)
+    }
+
+    async fn import(
+        &self,
+        bundle: &StateBundle,
+    ) -> Result<ImportReport, Box<dyn std::error::Error>> {
+        let request = reqwest::Client::new().put(format!("{}/admin/state", self.server));
+        Ok(self.send(request.json(bundle), "import").await?.json().await?)
+    }
+
+    async fn send(
+        &self,
+        mut request: reqwest::RequestBuilder,
+        action: &str,
+    ) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        // Errors are an `ApiError` body, or a bare status from a proxy.
+        let error: serde_json::Value = response.json().await.unwrap_or_default();
+        let message = match error["message"].as_str() {
+            Some(message) => message.to_string(),
+            None => status.to_string(),
+        };
+        Err(format!("cannot {action} the state of {}: {message}", self.server).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo::IglooEngine;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_state_is_copied_between_coordinators() -> Result<(), Box<dyn std::error::Error>> {
+        let mut servers = Vec::new();
+        for _ in 0..2 {
+            let engine = IglooEngine::new();
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+            servers.push(format!("http://{}", listener.local_addr()?));
+            let query_engine = Arc::new(engine.query_engine().clone());
+            tokio::spawn(igloo_api::http::serve(listener, query_engine));
+        }
+        let staging = Coordinator { server: &servers[0], token: None };
+        let mut bundle = staging.export().await?;
+        assert!(bundle.entries.is_empty() && bundle.warm_up.is_empty());
+        let policies = r#"{"row_filters": [{"table": "orders", "predicate": "id > 0"}]}"#;
+        bundle.policies = serde_json::from_str(policies)?;
+        let prod = Coordinator { server: &servers[1], token: None };
+        let report = prod.import(&bundle).await?;
+        assert_eq!(report.to_string(), "imported 0 catalog entries and the policies");
+        // Policy tables are kept fully qualified.
+        let qualified =
+            r#"{"row_filters": [{"table": "datafusion.public.orders", "predicate": "id > 0"}]}"#;
+        assert_eq!(prod.export().await?.policies, serde_json::from_str(qualified)?);
+
+        bundle.format += 1;
+        let error = prod.import(&bundle).await.unwrap_err();
+        assert!(error.to_string().contains("unsupported bundle format"), "{error}");
+        Ok(())
+    }
+}
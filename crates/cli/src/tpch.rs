@@ -0,0 +1,679 @@
+//! TPC-H data and queries for `igloo bench`.
+//!
+//! The generator follows the schema, cardinalities and value domains of the TPC-H
+//! specification closely enough that the 22 queries, with their validation
+//! parameters, select rows at any scale. It is not `dbgen`: text is drawn from a
+//! smaller vocabulary, order keys are dense, and the answers are not the
+//! specification's. The same scale always generates the same data.
+
+use igloo::datafusion::arrow::array::{
+    ArrayRef, Date32Builder, Decimal128Builder, Int32Builder, Int64Builder, StringBuilder,
+};
+use igloo::datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use igloo::datafusion::arrow::record_batch::RecordBatch;
+use igloo::datafusion::error::Result as DataFusionResult;
+use igloo::datafusion::parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The type of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// An identifier, `BIGINT`.
+    Key,
+    Int,
+    /// `DECIMAL(15, 2)`.
+    Money,
+    Date,
+    Text,
+}
+
+impl Kind {
+    fn data_type(self) -> DataType {
+        match self {
+            Kind::Key => DataType::Int64,
+            Kind::Int => DataType::Int32,
+            Kind::Money => DataType::Decimal128(15, 2),
+            Kind::Date => DataType::Date32,
+            Kind::Text => DataType::Utf8,
+        }
+    }
+
+    /// The type of the column in Postgres.
+    pub fn sql_type(self) -> &'static str {
+        match self {
+            Kind::Key => "bigint",
+            Kind::Int => "integer",
+            Kind::Money => "numeric(15, 2)",
+            Kind::Date => "date",
+            Kind::Text => "text",
+        }
+    }
+}
+
+/// A TPC-H table.
+#[derive(Debug)]
+pub struct Table {
+    pub name: &'static str,
+    pub columns: &'static [(&'static str, Kind)],
+    /// The columns of the primary key, the first an integer.
+    pub primary_key: &'static [&'static str],
+}
+
+impl Table {
+    pub fn schema(&self) -> SchemaRef {
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .map(|(name, kind)| Field::new(*name, kind.data_type(), false))
+            .collect();
+        Arc::new(Schema::new(fields))
+    }
+
+    /// The table's file in the directory data is generated in.
+    pub fn path(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.parquet", self.name))
+    }
+}
+
+use Kind::{Date, Int, Key, Money, Text};
+
+pub const TABLES: [Table; 8] = [
+    Table {
+        name: "region",
+        columns: &[("r_regionkey", Key), ("r_name", Text), ("r_comment", Text)],
+        primary_key: &["r_regionkey"],
+    },
+    Table {
+        name: "nation",
+        columns: &[
+            ("n_nationkey", Key),
+            ("n_name", Text),
+            ("n_regionkey", Key),
+            ("n_comment", Text),
+        ],
+        primary_key: &["n_nationkey"],
+    },
+    Table {
+        name: "supplier",
+        columns: &[
+            ("s_suppkey", Key),
+            ("s_name", Text),
+            ("s_address", Text),
+            ("s_nationkey", Key),
+            ("s_phone", Text),
+            ("s_acctbal", Money),
+            ("s_comment", Text),
+        ],
+        primary_key: &["s_suppkey"],
+    },
+    Table {
+        name: "part",
+        columns: &[
+            ("p_partkey", Key),
+            ("p_name", Text),
+            ("p_mfgr", Text),
+            ("p_brand", Text),
+            ("p_type", Text),
+            ("p_size", Int),
+            ("p_container", Text),
+            ("p_retailprice", Money),
+            ("p_comment", Text),
+        ],
+        primary_key: &["p_partkey"],
+    },
+    Table {
+        name: "partsupp",
+        columns: &[
+            ("ps_partkey", Key),
+            ("ps_suppkey", Key),
+            ("ps_availqty", Int),
+            ("ps_supplycost", Money),
+            ("ps_comment", Text),
+        ],
+        primary_key: &["ps_partkey", "ps_suppkey"],
+    },
+    Table {
+        name: "customer",
+        columns: &[
+            ("c_custkey", Key),
+            ("c_name", Text),
+            ("c_address", Text),
+            ("c_nationkey", Key),
+            ("c_phone", Text),
+            ("c_acctbal", Money),
+            ("c_mktsegment", Text),
+            ("c_comment", Text),
+        ],
+        primary_key: &["c_custkey"],
+    },
+    Table {
+        name: "orders",
+        columns: &[
+            ("o_orderkey", Key),
+            ("o_custkey", Key),
+            ("o_orderstatus", Text),
+            ("o_totalprice", Money),
+            ("o_orderdate", Date),
+            ("o_orderpriority", Text),
+            ("o_clerk", Text),
+            ("o_shippriority", Int),
+            ("o_comment", Text),
+        ],
+        primary_key: &["o_orderkey"],
+    },
+    Table {
+        name: "lineitem",
+        columns: &[
+            ("l_orderkey", Key),
+            ("l_partkey", Key),
+            ("l_suppkey", Key),
+            ("l_linenumber", Int),
+            ("l_quantity", Money),
+            ("l_extendedprice", Money),
+            ("l_discount", Money),
+            ("l_tax", Money),
+            ("l_returnflag", Text),
+            ("l_linestatus", Text),
+            ("l_shipdate", Date),
+            ("l_commitdate", Date),
+            ("l_receiptdate", Date),
+            ("l_shipinstruct", Text),
+            ("l_shipmode", Text),
+            ("l_comment", Text),
+        ],
+        primary_key: &["l_orderkey", "l_linenumber"],
+    },
+];
+
+/// The table named `name`.
+pub fn table(name: &str) -> &'static Table {
+    TABLES.iter().find(|table| table.name == name).expect("not a TPC-H table")
+}
+
+/// The 22 queries, with the specification's validation parameters. Q15's view is a
+/// common table expression.
+pub const QUERIES: [&str; 22] = [
+    include_str!("tpch/q1.sql"),
+    include_str!("tpch/q2.sql"),
+    include_str!("tpch/q3.sql"),
+    include_str!("tpch/q4.sql"),
+    include_str!("tpch/q5.sql"),
+    include_str!("tpch/q6.sql"),
+    include_str!("tpch/q7.sql"),
+    include_str!("tpch/q8.sql"),
+    include_str!("tpch/q9.sql"),
+    include_str!("tpch/q10.sql"),
+    include_str!("tpch/q11.sql"),
+    include_str!("tpch/q12.sql"),
+    include_str!("tpch/q13.sql"),
+    include_str!("tpch/q14.sql"),
+    include_str!("tpch/q15.sql"),
+    include_str!("tpch/q16.sql"),
+    include_str!("tpch/q17.sql"),
+    include_str!("tpch/q18.sql"),
+    include_str!("tpch/q19.sql"),
+    include_str!("tpch/q20.sql"),
+    include_str!("tpch/q21.sql"),
+    include_str!("tpch/q22.sql"),
+];
+
+const NATIONS: [(&str, i64); 25] = [
+    ("ALGERIA", 0),
+    ("ARGENTINA", 1),
+    ("BRAZIL", 1),
+    ("CANADA", 1),
+    ("EGYPT", 4),
+    ("ETHIOPIA", 0),
+    ("FRANCE", 3),
+    ("GERMANY", 3),
+    ("INDIA", 2),
+    ("INDONESIA", 2),
+    ("IRAN", 4),
+    ("IRAQ", 4),
+    ("JAPAN", 2),
+    ("JORDAN", 4),
+    ("KENYA", 0),
+    ("MOROCCO", 0),
+    ("MOZAMBIQUE", 0),
+    ("PERU", 1),
+    ("CHINA", 2),
+    ("ROMANIA", 3),
+    ("SAUDI ARABIA", 4),
+    ("VIETNAM", 2),
+    ("RUSSIA", 3),
+    ("UNITED KINGDOM", 3),
+    ("UNITED STATES", 1),
+];
+const REGIONS: [&str; 5] = ["AFRICA", "AMERICA", "ASIA", "EUROPE", "MIDDLE EAST"];
+/// The words part names are made of.
+const COLORS: &str =
+    "almond antique aquamarine azure beige bisque black blanched blue blush brown \
+    burlywood burnished chartreuse chiffon chocolate coral cornflower cornsilk cream cyan \
+    dark deep dim dodger drab firebrick floral forest frosted gainsboro ghost goldenrod \
+    green grey honeydew hot indian ivory khaki lace lavender lawn lemon light lime linen \
+    magenta maroon medium metallic midnight mint misty moccasin navajo navy olive orange \
+    orchid pale papaya peach peru pink plum powder puff purple red rose rosy royal saddle \
+    salmon sandy seashell sienna sky slate smoke snow spring steel tan thistle tomato \
+    turquoise violet wheat white yellow";
+const TYPES: [&[&str]; 3] = [
+    &["STANDARD", "SMALL", "MEDIUM", "LARGE", "ECONOMY", "PROMO"],
+    &["ANODIZED", "BURNISHED", "PLATED", "POLISHED", "BRUSHED"],
+    &["TIN", "NICKEL", "BRASS", "STEEL", "COPPER"],
+];
+const CONTAINERS: [&[&str]; 2] = [
+    &["SM", "LG", "MED", "JUMBO", "WRAP"],
+    &["CASE", "BOX", "BAG", "JAR", "PKG", "PACK", "CAN", "DRUM"],
+];
+const SEGMENTS: [&str; 5] = ["AUTOMOBILE", "BUILDING", "FURNITURE", "MACHINERY", "HOUSEHOLD"];
+const PRIORITIES: [&str; 5] = ["1-URGENT", "2-HIGH", "3-MEDIUM", "4-NOT SPECIFIED", "5-LOW"];
+const INSTRUCTIONS: [&str; 4] = ["DELIVER IN PERSON", "COLLECT COD", "NONE", "TAKE BACK RETURN"];
+const MODES: [&str; 7] = ["REG AIR", "AIR", "RAIL", "SHIP", "TRUCK", "MAIL", "FOB"];
+/// The words comments are made of.
+const WORDS: &str = "furiously quickly carefully blithely slyly fluffily ironic final regular \
+    express pending bold special even silent unusual deposits requests accounts packages \
+    theodolites instructions foxes pinto beans ideas platelets asymptotes dependencies \
+    excuses dolphins courts sleep wake haggle nag use boost affix detect integrate cajole \
+    among above along across against about";
+
+/// Days since the Unix epoch of a date.
+fn days(year: i32, month: i32, day: i32) -> i32 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Rows of a table with `base` rows at scale 1.
+fn rows(base: f64, scale: f64) -> i64 {
+    ((base * scale).round() as i64).max(1)
+}
+
+/// A small deterministic random number generator (SplitMix64).
+struct Random {
+    state: u64,
+    words: Vec<&'static str>,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self { state: seed, words: WORDS.split(' ').collect() }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from `low` to `high`, both included.
+    fn range(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next() % (high - low + 1) as u64) as i64
+    }
+
+    fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+        values[self.range(0, values.len() as i64 - 1) as usize]
+    }
+
+    /// Between `min` and `max` words.
+    fn text(&mut self, min: i64, max: i64) -> String {
+        let count = self.range(min, max);
+        let mut words = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let word = self.range(0, self.words.len() as i64 - 1) as usize;
+            words.push(self.words[word]);
+        }
+        words.join(" ")
+    }
+
+    /// Between `min` and `max` letters and digits.
+    fn address(&mut self, min: i64, max: i64) -> String {
+        const CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+        (0..self.range(min, max))
+            .map(|_| CHARS[self.range(0, CHARS.len() as i64 - 1) as usize] as char)
+            .collect()
+    }
+
+    fn phone(&mut self, nation: i64) -> String {
+        let (a, b, c) = (self.range(100, 999), self.range(100, 999), self.range(1000, 9999));
+        format!("{}-{a}-{b}-{c}", nation + 10)
+    }
+}
+
+/// The retail price of a part, in cents.
+fn retail_price(part: i64) -> i128 {
+    (90_000 + (part / 10) % 20_001 + 100 * (part % 1_000)) as i128
+}
+
+/// The `i`th (0 to 3) supplier of a part.
+fn part_supplier(part: i64, i: i64, suppliers: i64) -> i64 {
+    (part + i * (suppliers / 4 + (part - 1) / suppliers)) % suppliers + 1
+}
+
+/// Rows are written to Parquet in batches of this many.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Writes a table's rows, appended a column at a time.
+struct TableWriter {
+    schema: SchemaRef,
+    columns: Vec<ColumnBuilder>,
+    column: usize,
+    rows: usize,
+    written: usize,
+    writer: ArrowWriter<File>,
+}
+
+enum ColumnBuilder {
+    Key(Int64Builder),
+    Int(Int32Builder),
+    Money(Decimal128Builder),
+    Date(Date32Builder),
+    Text(StringBuilder),
+}
+
+impl ColumnBuilder {
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Key(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Money(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Date(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Text(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
+
+impl TableWriter {
+    fn create(dir: &Path, table: &Table) -> DataFusionResult<Self> {
+        let schema = table.schema();
+        let columns = table
+            .columns
+            .iter()
+            .map(|(_, kind)| match kind {
+                Kind::Key => ColumnBuilder::Key(Int64Builder::new()),
+                Kind::Int => ColumnBuilder::Int(Int32Builder::new()),
+                Kind::Money => {
+                    ColumnBuilder::Money(Decimal128Builder::new().with_data_type(kind.data_type()))
+                }
+                Kind::Date => ColumnBuilder::Date(Date32Builder::new()),
+                Kind::Text => ColumnBuilder::Text(StringBuilder::new()),
+            })
+            .collect();
+        let file = File::create(table.path(dir))?;
+        let writer = ArrowWriter::try_new(file, Arc::clone(&schema), None)?;
+        Ok(Self { schema, columns, column: 0, rows: 0, written: 0, writer })
+    }
+
+    fn next(&mut self) -> &mut ColumnBuilder {
+        self.column += 1;
+        &mut self.columns[self.column - 1]
+    }
+
+    fn key(&mut self, value: i64) -> &mut Self {
+        let ColumnBuilder::Key(builder) = self.next() else { unreachable!("not a key") };
+        builder.append_value(value);
+        self
+    }
+
+    fn int(&mut self, value: i64) -> &mut Self {
+        let ColumnBuilder::Int(builder) = self.next() else { unreachable!("not an integer") };
+        builder.append_value(value as i32);
+        self
+    }
+
+    /// An amount in cents.
+    fn money(&mut self, cents: i128) -> &mut Self {
+        let ColumnBuilder::Money(builder) = self.next() else { unreachable!("not money") };
+        builder.append_value(cents);
+        self
+    }
+
+    fn date(&mut self, days: i32) -> &mut Self {
+        let ColumnBuilder::Date(builder) = self.next() else { unreachable!("not a date") };
+        builder.append_value(days);
+        self
+    }
+
+    fn text(&mut self, value: &str) -> &mut Self {
+        let ColumnBuilder::Text(builder) = self.next() else { unreachable!("not text") };
+        builder.append_value(value);
+        self
+    }
+
+    fn end_row(&mut self) -> DataFusionResult<()> {
+        debug_assert_eq!(self.column, self.columns.len());
+        self.column = 0;
+        self.rows += 1;
+        if self.rows == BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DataFusionResult<()> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let columns = self.columns.iter_mut().map(ColumnBuilder::finish).collect();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns)?;
+        self.writer.write(&batch)?;
+        self.written += self.rows;
+        self.rows = 0;
+        Ok(())
+    }
+
+    /// Finish the file, returning the rows written.
+    fn close(mut self) -> DataFusionResult<usize> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(self.written)
+    }
+}
+
+/// Write the tables at `scale` (1 is about 1 GB) as `<dir>/<table>.parquet`, returning
+/// the rows of each table.
+pub fn generate(dir: &Path, scale: f64) -> DataFusionResult<Vec<(&'static str, usize)>> {
+    std::fs::create_dir_all(dir)?;
+    let suppliers = rows(10_000.0, scale);
+    let parts = rows(200_000.0, scale);
+    let customers = rows(150_000.0, scale);
+    let orders = rows(1_500_000.0, scale);
+    let mut generated = Vec::new();
+
+    let mut random = Random::new(1);
+    let mut region = TableWriter::create(dir, table("region"))?;
+    for (key, name) in REGIONS.iter().enumerate() {
+        region.key(key as i64).text(name).text(&random.text(4, 12)).end_row()?;
+    }
+    generated.push(("region", region.close()?));
+
+    let mut nation = TableWriter::create(dir, table("nation"))?;
+    for (key, (name, region)) in NATIONS.iter().enumerate() {
+        nation.key(key as i64).text(name).key(*region).text(&random.text(4, 12)).end_row()?;
+    }
+    generated.push(("nation", nation.close()?));
+
+    let mut random = Random::new(2);
+    let mut supplier = TableWriter::create(dir, table("supplier"))?;
+    for key in 1..=suppliers {
+        let nation = random.range(0, 24);
+        // Some suppliers have complaints against them, which Q16 leaves out.
+        let comment = match random.range(1, 2_000) {
+            1 => format!("{} Customer {} Complaints", random.text(1, 4), random.text(1, 4)),
+            _ => random.text(4, 12),
+        };
+        supplier
+            .key(key)
+            .text(&format!("Supplier#{key:09}"))
+            .text(&random.address(10, 40))
+            .key(nation)
+            .text(&random.phone(nation))
+            .money(random.range(-99_999, 999_999) as i128)
+            .text(&comment)
+            .end_row()?;
+    }
+    generated.push(("supplier", supplier.close()?));
+
+    let mut random = Random::new(3);
+    let colors: Vec<&str> = COLORS.split(' ').collect();
+    let mut part = TableWriter::create(dir, table("part"))?;
+    let mut partsupp = TableWriter::create(dir, table("partsupp"))?;
+    for key in 1..=parts {
+        let mut name: Vec<&str> = Vec::with_capacity(5);
+        while name.len() < 5 {
+            let color = random.pick(&colors);
+            if !name.contains(&color) {
+                name.push(color);
+            }
+        }
+        let manufacturer = random.range(1, 5);
+        let kind: Vec<&str> = TYPES.iter().map(|words| random.pick(words)).collect();
+        let container: Vec<&str> = CONTAINERS.iter().map(|words| random.pick(words)).collect();
+        part.key(key)
+            .text(&name.join(" "))
+            .text(&format!("Manufacturer#{manufacturer}"))
+            .text(&format!("Brand#{manufacturer}{}", random.range(1, 5)))
+            .text(&kind.join(" "))
+            .int(random.range(1, 50))
+            .text(&container.join(" "))
+            .money(retail_price(key))
+            .text(&random.text(2, 6))
+            .end_row()?;
+        for i in 0..4 {
+            partsupp
+                .key(key)
+                .key(part_supplier(key, i, suppliers))
+                .int(random.range(1, 9_999))
+                .money(random.range(100, 100_000) as i128)
+                .text(&random.text(8, 20))
+                .end_row()?;
+        }
+    }
+    generated.push(("part", part.close()?));
+    generated.push(("partsupp", partsupp.close()?));
+
+    let mut random = Random::new(4);
+    let mut customer = TableWriter::create(dir, table("customer"))?;
+    for key in 1..=customers {
+        let nation = random.range(0, 24);
+        customer
+            .key(key)
+            .text(&format!("Customer#{key:09}"))
+            .text(&random.address(10, 40))
+            .key(nation)
+            .text(&random.phone(nation))
+            .money(random.range(-99_999, 999_999) as i128)
+            .text(random.pick(&SEGMENTS))
+            .text(&random.text(4, 14))
+            .end_row()?;
+    }
+    generated.push(("customer", customer.close()?));
+
+    let start = days(1992, 1, 1);
+    let current = days(1995, 6, 17);
+    let end = days(1998, 12, 31);
+    let clerks = rows(1_000.0, scale);
+    let mut random = Random::new(5);
+    let mut order = TableWriter::create(dir, table("orders"))?;
+    let mut lineitem = TableWriter::create(dir, table("lineitem"))?;
+    for key in 1..=orders {
+        // A third of the customers place no orders.
+        let mut customer = random.range(1, customers);
+        if customer % 3 == 0 {
+            customer -= 1;
+        }
+        let ordered = random.range(start as i64, end as i64 - 151) as i32;
+        let (mut total, mut shipped, lines) = (0, 0, random.range(1, 7));
+        for line in 1..=lines {
+            let part = random.range(1, parts);
+            let quantity = random.range(1, 50);
+            let price = quantity as i128 * retail_price(part);
+            let (discount, tax) = (random.range(0, 10) as i128, random.range(0, 8) as i128);
+            total += price * (100 + tax) * (100 - discount) / 10_000;
+            let ship = ordered + random.range(1, 121) as i32;
+            let commit = ordered + random.range(30, 90) as i32;
+            let receipt = ship + random.range(1, 30) as i32;
+            let returned = if receipt <= current { random.pick(&["R", "A"]) } else { "N" };
+            if ship <= current {
+                shipped += 1;
+            }
+            lineitem
+                .key(key)
+                .key(part)
+                .key(part_supplier(part, random.range(0, 3), suppliers))
+                .int(line)
+                .money(quantity as i128 * 100)
+                .money(price)
+                .money(discount)
+                .money(tax)
+                .text(returned)
+                .text(if ship > current { "O" } else { "F" })
+                .date(ship)
+                .date(commit)
+                .date(receipt)
+                .text(random.pick(&INSTRUCTIONS))
+                .text(random.pick(&MODES))
+                .text(&random.text(2, 6))
+                .end_row()?;
+        }
+        let status = match shipped {
+            0 => "O",
+            shipped if shipped == lines => "F",
+            _ => "P",
+        };
+        order
+            .key(key)
+            .key(customer)
+            .text(status)
+            .money(total)
+            .date(ordered)
+            .text(random.pick(&PRIORITIES))
+            .text(&format!("Clerk#{:09}", random.range(1, clerks)))
+            .int(0)
+            .text(&random.text(3, 10))
+            .end_row()?;
+    }
+    generated.push(("orders", order.close()?));
+    generated.push(("lineitem", lineitem.close()?));
+    Ok(generated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use igloo::datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    #[test]
+    fn test_tables_are_generated_at_scale() -> DataFusionResult<()> {
+        assert_eq!(days(1970, 1, 1), 0);
+        assert_eq!(days(1995, 6, 17), 9_298);
+
+        let dir = std::env::temp_dir().join(format!("igloo-tpch-{}", std::process::id()));
+        let generated = generate(&dir, 0.001)?;
+        let rows = |name| generated.iter().find(|(table, _)| *table == name).unwrap().1;
+        assert_eq!(rows("region"), 5);
+        assert_eq!(rows("nation"), 25);
+        assert_eq!(rows("supplier"), 10);
+        assert_eq!(rows("part"), 200);
+        assert_eq!(rows("partsupp"), 800);
+        assert_eq!(rows("customer"), 150);
+        assert_eq!(rows("orders"), 1_500);
+        assert!((1_500..=10_500).contains(&rows("lineitem")));
+
+        for table in &TABLES {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(table.path(&dir))?)?;
+            assert_eq!(reader.schema(), &table.schema(), "{}", table.name);
+        }
+        // The same scale generates the same data.
+        let first = std::fs::read(table("lineitem").path(&dir))?;
+        generate(&dir, 0.001)?;
+        assert_eq!(std::fs::read(table("lineitem").path(&dir))?, first);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -1,4 +0,0 @@
-// TODO: Implement client logic
-fn main() {
-    println!("igloo-client starting up...");
-}
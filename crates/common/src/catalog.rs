@@ -1,5 +1,9 @@
+use async_trait::async_trait;
+use datafusion::catalog::CatalogProvider;
 use datafusion::datasource::TableProvider;
+use datafusion::error::Result as DataFusionResult;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
 pub struct MemoryCatalog {
@@ -25,3 +29,11 @@ impl MemoryCatalog {
         self.tables.get(name).cloned()
     }
 }
+
+/// A remote system whose metadata can be read as a catalog, such as a Hive Metastore
+/// or an Iceberg REST catalog.
+#[async_trait]
+pub trait CatalogSource: fmt::Debug + Send + Sync {
+    /// The source's schemas and tables as they are now.
+    async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>>;
+}
@@ -1,21 +1,279 @@
+use crate::redact::redact;
+use crate::retry::Retryable;
+use datafusion::error::DataFusionError;
+use serde::Serialize;
+use std::backtrace::{Backtrace, BacktraceStatus};
+use std::fmt::Write;
 use thiserror::Error;
 
 /// Unified error type for Igloo crates.
 use sqlparser::parser::ParserError;
 
+/// Boxed error used to keep the original cause of an [`Error::External`].
+pub type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Every variant captures a [`Backtrace`] when it is created. Capturing follows the
+/// standard `RUST_BACKTRACE` / `RUST_LIB_BACKTRACE` switches, so it costs nothing
+/// unless one of them is enabled. Backtraces are boxed to keep `Result<T>` small.
 #[derive(Debug, Error)]
 pub enum Error {
-    #[error("An unknown error occurred: {0}")]
-    Unknown(String),
+    #[error("An unknown error occurred: {message}")]
+    Unknown { message: String, backtrace: Box<Backtrace> },
     // Add more error variants as needed
-    #[error("SQL parsing error: {0}")]
-    SqlParser(#[from] ParserError),
+    #[error("SQL parsing error: {source}")]
+    SqlParser {
+        #[source]
+        source: ParserError,
+        backtrace: Box<Backtrace>,
+    },
+    /// An error raised by a dependency (IO, CSV, drivers, ...), kept as the source so
+    /// the full cause chain survives.
+    #[error("{context}: {source}")]
+    External {
+        context: String,
+        #[source]
+        source: BoxError,
+        backtrace: Box<Backtrace>,
+    },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Stable, client-facing representation of an [`Error`], serialized as JSON by the
+/// network frontends. Text fields are redacted, so connection strings and passwords
+/// echoed by drivers never reach clients.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ApiError {
+    /// Machine-readable error code, stable across releases.
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    pub retryable: bool,
+}
+
+impl ApiError {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("ApiError is always serializable")
+    }
+}
+
+impl From<&DataFusionError> for ApiError {
+    /// Classified by the root of `e`, as planner errors may arrive wrapped (e.g. with a
+    /// diagnostic), the causes of which are the detail.
+    fn from(e: &DataFusionError) -> Self {
+        let (code, hint) = match e.find_root() {
+            DataFusionError::SQL(..) => ("sql_parse_error", Some("Check the statement syntax.")),
+            DataFusionError::Plan(_) | DataFusionError::SchemaError(..) => ("plan_error", None),
+            DataFusionError::NotImplemented(_) => ("not_implemented", None),
+            DataFusionError::ResourcesExhausted(_) => ("resources_exhausted", None),
+            _ => ("execution_error", None),
+        };
+        let retryable = e.is_retryable();
+        let causes = std::iter::successors(std::error::Error::source(e), |e| e.source());
+        let causes: Vec<String> = causes.map(|cause| redact(&cause.to_string())).collect();
+        let hint = match hint {
+            Some(hint) => Some(hint.to_string()),
+            None if retryable => Some("The error is transient; retry the request.".to_string()),
+            None => None,
+        };
+        ApiError {
+            code,
+            message: redact(&e.to_string()),
+            detail: (!causes.is_empty()).then(|| causes.join(": ")),
+            hint,
+            retryable,
+        }
+    }
+}
+
 impl Error {
     pub fn new(msg: &str) -> Self {
-        Error::Unknown(msg.to_string())
+        Error::Unknown { message: msg.to_string(), backtrace: Box::new(Backtrace::capture()) }
+    }
+
+    /// Wrap an error from another library, recording what Igloo was doing at the time.
+    pub fn external(context: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Error::External {
+            context: context.into(),
+            source: source.into(),
+            backtrace: Box::new(Backtrace::capture()),
+        }
+    }
+
+    /// Machine-readable code identifying the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Unknown { .. } => "unknown",
+            Error::SqlParser { .. } => "sql_parse_error",
+            Error::External { .. } => "external_error",
+        }
+    }
+
+    /// Convert into the JSON-serializable shape returned by the API frontends.
+    pub fn to_api_error(&self) -> ApiError {
+        let causes: Vec<String> = self.chain().map(|cause| redact(&cause.to_string())).collect();
+        let retryable = self.is_retryable();
+        let hint = match self {
+            Error::SqlParser { .. } => Some("Check the statement syntax.".to_string()),
+            _ if retryable => Some("The error is transient; retry the request.".to_string()),
+            _ => None,
+        };
+        ApiError {
+            code: self.code(),
+            message: redact(&self.to_string()),
+            detail: (!causes.is_empty()).then(|| causes.join(": ")),
+            hint,
+            retryable,
+        }
+    }
+
+    /// The backtrace captured when this error was created.
+    pub fn backtrace(&self) -> &Backtrace {
+        match self {
+            Error::Unknown { backtrace, .. }
+            | Error::SqlParser { backtrace, .. }
+            | Error::External { backtrace, .. } => backtrace.as_ref(),
+        }
+    }
+
+    /// Iterate over the causes of this error, starting with its direct source.
+    pub fn chain(&self) -> impl Iterator<Item = &(dyn std::error::Error + 'static)> {
+        std::iter::successors(std::error::Error::source(self), |e| e.source())
+    }
+
+    /// Render the error, its cause chain and (if captured) its backtrace as a multi-line
+    /// report suitable for logs and diagnostic endpoints.
+    pub fn report(&self) -> String {
+        report_error(self)
+    }
+}
+
+/// Render `error` as [`Error::report`] does, for errors of any type: its cause chain,
+/// and the backtrace of the first [`Error`] in it, such as one a driver error wrapped
+/// by DataFusion carries.
+pub fn report_error(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut out = format!("Error: {error}");
+    let causes = std::iter::successors(error.source(), |e| e.source());
+    for (i, cause) in causes.enumerate() {
+        if i == 0 {
+            out.push_str("\n\nCaused by:");
+        }
+        let _ = write!(out, "\n    {i}: {cause}");
+    }
+    let backtrace = std::iter::successors(Some(error), |e| e.source())
+        .find_map(|e| e.downcast_ref::<Error>())
+        .map(Error::backtrace);
+    if let Some(backtrace) = backtrace.filter(|b| b.status() == BacktraceStatus::Captured) {
+        let _ = write!(out, "\n\nBacktrace:\n{backtrace}");
+    }
+    out
+}
+
+impl From<ParserError> for Error {
+    fn from(source: ParserError) -> Self {
+        Error::SqlParser { source, backtrace: Box::new(Backtrace::capture()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[derive(Debug, Error)]
+    #[error("connection reset")]
+    struct Outer {
+        #[source]
+        source: io::Error,
+    }
+
+    #[test]
+    fn test_external_preserves_source_chain() {
+        let inner = io::Error::new(io::ErrorKind::ConnectionReset, "peer went away");
+        let err = Error::external("Failed to read table", Outer { source: inner });
+
+        let causes: Vec<String> = err.chain().map(|e| e.to_string()).collect();
+        assert_eq!(causes, vec!["connection reset".to_string(), "peer went away".to_string()]);
+    }
+
+    #[test]
+    fn test_report_lists_causes() {
+        let inner = io::Error::new(io::ErrorKind::NotFound, "missing.csv");
+        let err = Error::external("Failed to open file", inner);
+
+        let report = err.report();
+        assert!(report.starts_with("Error: Failed to open file: missing.csv"));
+        assert!(report.contains("Caused by:\n    0: missing.csv"));
+    }
+
+    #[test]
+    fn test_report_finds_wrapped_errors() {
+        #[derive(Debug, Error)]
+        #[error("query failed")]
+        struct Query {
+            #[source]
+            source: Error,
+        }
+
+        let err = Query { source: Error::new("boom") };
+        let report = report_error(&err);
+        assert!(report.starts_with("Error: query failed"));
+        assert!(report.contains("Caused by:\n    0: An unknown error occurred: boom"));
+        let captured = err.source.backtrace().status() == BacktraceStatus::Captured;
+        assert_eq!(report.contains("\n\nBacktrace:\n"), captured);
+    }
+
+    #[test]
+    fn test_api_error_shape() {
+        let err = Error::from(ParserError::ParserError("Expected: SELECT".to_string()));
+        let api = err.to_api_error();
+        assert_eq!(api.code, "sql_parse_error");
+        assert!(!api.retryable);
+        assert_eq!(
+            api.to_json(),
+            r#"{"code":"sql_parse_error","message":"SQL parsing error: sql parser error: Expected: SELECT","detail":"sql parser error: Expected: SELECT","hint":"Check the statement syntax.","retryable":false}"#
+        );
+    }
+
+    #[test]
+    fn test_datafusion_errors_convert_to_api_errors() {
+        let plan = DataFusionError::Plan("table 'missing' not found".to_string());
+        let api = ApiError::from(&DataFusionError::Context("planning".to_string(), Box::new(plan)));
+        assert_eq!((api.code, api.retryable), ("plan_error", false));
+        assert_eq!(api.detail.as_deref(), Some("Error during planning: table 'missing' not found"));
+
+        let inner = io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "reading postgres://igloo:hunter2@db/app",
+        );
+        let api = ApiError::from(&DataFusionError::External(Box::new(inner)));
+        assert_eq!((api.code, api.retryable), ("execution_error", true));
+        assert_eq!(api.hint.as_deref(), Some("The error is transient; retry the request."));
+        assert!(!api.to_json().contains("hunter2"));
+
+        let api = ApiError::from(&DataFusionError::ResourcesExhausted("shutting down".into()));
+        assert_eq!((api.code, api.retryable, api.detail), ("resources_exhausted", true, None));
+    }
+
+    #[test]
+    fn test_api_error_redacts_connection_strings() {
+        let inner = io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "connecting to postgres://igloo:hunter2@db/app",
+        );
+        let api = Error::external("Failed to scan orders", inner).to_api_error();
+        assert!(api.retryable);
+        assert!(!api.message.contains("hunter2"));
+        assert!(!api.detail.unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn test_new_has_no_causes() {
+        let err = Error::new("boom");
+        assert_eq!(err.chain().count(), 0);
+        assert!(err.report().starts_with("Error: An unknown error occurred: boom"));
     }
 }
@@ -11,4 +11,10 @@
 
 pub mod catalog;
 pub mod error;
+pub mod logging;
+pub mod memory;
+pub mod redact;
+pub mod retry;
+pub mod secrets;
+pub mod shutdown;
 pub use error::Error;
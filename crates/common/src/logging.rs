@@ -0,0 +1,236 @@
+//! Structured logging for the Igloo servers.
+//!
+//! Servers log through [`tracing`]. Lines logged while a statement runs are within
+//! its `query` span, whose `query_id`, `session_id` and `source` (the frontend) every
+//! such line carries. [`init_from_env`] installs the subscriber:
+//!
+//! - `IGLOO_LOG` (or `RUST_LOG`): which lines to keep, as an
+//!   [`EnvFilter`](tracing_subscriber::EnvFilter) directive such as
+//!   `info,igloo_api=debug`. Defaults to `info`.
+//! - `IGLOO_LOG_FORMAT`: `text` (the default) for human-readable lines, or `json`
+//!   for one JSON object per line, for log pipelines.
+//!
+//! Credentials are masked in every line (see [`redact`](crate::redact)).
+
+use crate::redact::redact;
+use crate::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::reload;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// Lines kept unless `IGLOO_LOG` or `RUST_LOG` says otherwise.
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(Error::new(&format!("unknown log format '{s}', expected text or json"))),
+        }
+    }
+}
+
+/// Install the global subscriber as configured by the environment, see the
+/// [module docs](self). Fails if one is installed already.
+pub fn init_from_env() -> Result<LogFilter, Error> {
+    let format = match std::env::var("IGLOO_LOG_FORMAT") {
+        Ok(format) => format.parse()?,
+        Err(_) => LogFormat::default(),
+    };
+    let directives = std::env::var("IGLOO_LOG")
+        .or_else(|_| std::env::var("RUST_LOG"))
+        .unwrap_or_else(|_| DEFAULT_FILTER.to_string());
+    init(format, &directives)
+}
+
+/// Install the global subscriber, writing lines kept by `directives` to stderr.
+pub fn init(format: LogFormat, directives: &str) -> Result<LogFilter, Error> {
+    let (subscriber, filter) = subscriber(format, directives, std::io::stderr)?;
+    subscriber.try_init().map_err(|e| Error::external("failed to install the logger", e))?;
+    Ok(filter)
+}
+
+/// Changes which lines the subscriber it came with keeps.
+#[derive(Clone)]
+pub struct LogFilter {
+    reload: Arc<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>,
+}
+
+impl fmt::Debug for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogFilter").finish_non_exhaustive()
+    }
+}
+
+impl LogFilter {
+    fn new<S: 'static>(handle: reload::Handle<EnvFilter, S>) -> Self {
+        Self { reload: Arc::new(move |filter| handle.reload(filter)) }
+    }
+
+    /// Keep the lines `directives` keeps from now on.
+    pub fn set(&self, directives: &str) -> Result<(), Error> {
+        (self.reload)(env_filter(directives)?)
+            .map_err(|e| Error::external("failed to change the log filter", e))
+    }
+}
+
+fn env_filter(directives: &str) -> Result<EnvFilter, Error> {
+    EnvFilter::try_new(directives)
+        .map_err(|e| Error::external(format!("invalid log filter '{directives}'"), e))
+}
+
+/// A subscriber writing lines kept by `directives` to `writer`, and its filter.
+fn subscriber<W>(
+    format: LogFormat,
+    directives: &str,
+    writer: W,
+) -> Result<(Box<dyn Subscriber + Send + Sync>, LogFilter), Error>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(env_filter(directives)?)
+        .with_writer(Redacting(writer));
+    Ok(match format {
+        LogFormat::Text => {
+            let builder = builder.with_filter_reloading();
+            let filter = LogFilter::new(builder.reload_handle());
+            (Box::new(builder.finish()), filter)
+        }
+        // The fields of the innermost span (the statement's) beside the event's own.
+        LogFormat::Json => {
+            let builder = builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(false)
+                .with_filter_reloading();
+            let filter = LogFilter::new(builder.reload_handle());
+            (Box::new(builder.finish()), filter)
+        }
+    })
+}
+
+/// Writes lines with credentials masked, see [`redact`](crate::redact).
+struct Redacting<W>(W);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = Redacting<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Redacting(self.0.make_writer())
+    }
+}
+
+impl<W: io::Write> io::Write for Redacting<W> {
+    /// Lines are written whole, one call each.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        self.0.write_all(redact(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_log_format_from_str() {
+        assert_eq!("JSON".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!("text".parse::<LogFormat>().unwrap(), LogFormat::Text);
+        assert!("yaml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn test_json_lines_carry_the_query_span() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let lines = Arc::clone(&lines);
+            move || Lines(Arc::clone(&lines))
+        };
+        let (subscriber, _) = subscriber(LogFormat::Json, "info", writer).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            let span =
+                tracing::info_span!("query", query_id = "q1", session_id = "s1", source = "http");
+            span.in_scope(|| tracing::info!(rows = 3, "statement finished"));
+            tracing::debug!("filtered out");
+        });
+        let lines = lines.lock().unwrap();
+        let line: serde_json::Value = serde_json::from_slice(&lines).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["message"], "statement finished");
+        assert_eq!(line["rows"], 3);
+        assert_eq!(line["span"]["query_id"], "q1");
+        assert_eq!(line["span"]["session_id"], "s1");
+        assert_eq!(line["span"]["source"], "http");
+    }
+
+    #[test]
+    fn test_lines_are_redacted() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let lines = Arc::clone(&lines);
+            move || Lines(Arc::clone(&lines))
+        };
+        let (subscriber, _) = subscriber(LogFormat::Text, "info", writer).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::warn!(store = "postgres://igloo:hunter2@db/app", "cannot connect");
+        });
+        let lines = String::from_utf8(lines.lock().unwrap().clone()).unwrap();
+        assert!(lines.contains("postgres://igloo:********@db/app"), "{lines}");
+        assert!(!lines.contains("hunter2"), "{lines}");
+    }
+
+    #[test]
+    fn test_filter_changes_take_effect() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let writer = {
+            let lines = Arc::clone(&lines);
+            move || Lines(Arc::clone(&lines))
+        };
+        let (subscriber, filter) = subscriber(LogFormat::Text, "info", writer).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("before");
+            filter.set("debug").unwrap();
+            tracing::debug!("after");
+        });
+        assert!(filter.set("info,=bad=").is_err());
+        let lines = String::from_utf8(lines.lock().unwrap().clone()).unwrap();
+        assert!(!lines.contains("before") && lines.contains("after"), "{lines}");
+    }
+
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+}
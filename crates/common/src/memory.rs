@@ -0,0 +1,134 @@
+//! Process memory statistics.
+//!
+//! [`ProcessMemory::current`] reports what the process holds: its resident set as the
+//! operating system sees it (on Linux) and, in binaries that install
+//! [`CountingAllocator`] as their global allocator, the bytes currently allocated on
+//! the heap and their peak:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOCATOR: igloo_common::memory::CountingAllocator =
+//!     igloo_common::memory::CountingAllocator;
+//! ```
+
+use serde::Serialize;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting the bytes it hands out.
+pub struct CountingAllocator;
+
+impl CountingAllocator {
+    fn allocated(size: usize) {
+        let allocated = ALLOCATED.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(allocated, Ordering::Relaxed);
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        if !INSTALLED.load(Ordering::Relaxed) {
+            INSTALLED.store(true, Ordering::Relaxed);
+        }
+    }
+
+    fn freed(size: usize) {
+        ALLOCATED.fetch_sub(size, Ordering::Relaxed);
+        ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::allocated(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        Self::freed(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            Self::freed(layout.size());
+            Self::allocated(new_size);
+        }
+        new
+    }
+}
+
+/// Heap usage counted by [`CountingAllocator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct AllocatorStats {
+    pub allocated_bytes: usize,
+    pub peak_bytes: usize,
+    /// Allocations not freed yet.
+    pub allocations: usize,
+}
+
+impl AllocatorStats {
+    /// `None` unless [`CountingAllocator`] is the global allocator.
+    pub fn current() -> Option<Self> {
+        INSTALLED.load(Ordering::Relaxed).then(|| AllocatorStats {
+            allocated_bytes: ALLOCATED.load(Ordering::Relaxed),
+            peak_bytes: PEAK.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ProcessMemory {
+    /// Resident set size, where the platform reports it.
+    pub resident_bytes: Option<u64>,
+    pub allocator: Option<AllocatorStats>,
+}
+
+impl ProcessMemory {
+    pub fn current() -> Self {
+        Self { resident_bytes: resident_bytes(), allocator: AllocatorStats::current() }
+    }
+}
+
+/// `VmRSS` from `/proc/self/status`.
+fn resident_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kib: u64 =
+        line.trim_start_matches("VmRSS:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    fn test_counting_allocator_reports_heap_usage() {
+        let buffer = vec![1u8; 1 << 20];
+        let memory = ProcessMemory::current();
+        let allocator = memory.allocator.unwrap();
+        assert!(allocator.allocated_bytes >= buffer.len());
+        assert!(allocator.peak_bytes >= allocator.allocated_bytes);
+        if cfg!(target_os = "linux") {
+            assert!(memory.resident_bytes.unwrap() >= buffer.len() as u64);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+//! Retry policies shared by components that talk to remote systems.
+//!
+//! Call sites describe *what* to retry; the policy decides *how often* and *how long
+//! to wait*, so connectors and services don't each grow their own backoff loop.
+//!
+//! Policies retry connecting to Postgres, requests to the Kafka REST Proxy and the
+//! Schema Registry, a worker's registration and heartbeats, and restarts of supervised
+//! tasks. Object-store reads are retried by the `object_store` clients themselves.
+//! Loops that change what they run on every attempt stay with their owners: an
+//! Iceberg commit reloads the table after a conflicting commit, and a distributed
+//! stage reschedules the tasks of lost workers.
+
+use crate::error::Error;
+use datafusion::error::DataFusionError;
+use std::future::Future;
+use std::time::Duration;
+
+/// Classifies errors as transient (worth retrying) or permanent.
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        self.chain().any(is_transient)
+    }
+}
+
+impl Retryable for DataFusionError {
+    /// The engine ran out of memory or is shutting down, or a source failed on a
+    /// transient error.
+    fn is_retryable(&self) -> bool {
+        matches!(self.find_root(), DataFusionError::ResourcesExhausted(_))
+            || std::iter::successors(std::error::Error::source(self), |e| e.source())
+                .any(is_transient)
+    }
+}
+
+/// Whether `cause` is an IO or gRPC error worth retrying.
+fn is_transient(cause: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+        io.is_retryable()
+    } else if let Some(status) = cause.downcast_ref::<tonic::Status>() {
+        status.is_retryable()
+    } else if let Some(error) = cause.downcast_ref::<Error>() {
+        error.is_retryable()
+    } else {
+        false
+    }
+}
+
+impl Retryable for std::io::Error {
+    fn is_retryable(&self) -> bool {
+        use std::io::ErrorKind::*;
+        matches!(
+            self.kind(),
+            ConnectionRefused
+                | ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | TimedOut
+                | Interrupted
+                | WouldBlock
+                | UnexpectedEof
+        )
+    }
+}
+
+impl Retryable for tonic::Status {
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self.code(),
+            tonic::Code::Unavailable | tonic::Code::DeadlineExceeded | tonic::Code::Aborted
+        )
+    }
+}
+
+impl Retryable for tonic::transport::Error {
+    /// Transport errors are raised while establishing or holding a connection, which is
+    /// exactly the kind of failure a peer coming back up resolves.
+    fn is_retryable(&self) -> bool {
+        true
+    }
+}
+
+/// Exponential backoff with optional full jitter.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// When set, each delay is drawn uniformly from `[0, backoff]`.
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        Self { max_attempts: max_attempts.max(1), ..Default::default() }
+    }
+
+    /// A policy that runs the operation exactly once.
+    pub fn no_retry() -> Self {
+        Self::new(1)
+    }
+
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The un-jittered delay before retry number `retry` (starting at 1).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self.multiplier.powi(retry.saturating_sub(1) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_backoff.as_secs_f64()))
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.backoff(retry);
+        if self.jitter && !backoff.is_zero() {
+            backoff.mul_f64(rand::random::<f64>())
+        } else {
+            backoff
+        }
+    }
+
+    /// Run `op` until it succeeds, fails with a non-retryable error, or the attempt
+    /// budget is exhausted. The last error is returned on failure.
+    pub async fn retry<T, E, F, Fut>(&self, op: F) -> std::result::Result<T, E>
+    where
+        E: Retryable,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+    {
+        self.retry_if(op, E::is_retryable).await
+    }
+
+    /// Like [`RetryPolicy::retry`], with a caller-supplied classification.
+    pub async fn retry_if<T, E, F, Fut, P>(
+        &self,
+        mut op: F,
+        should_retry: P,
+    ) -> std::result::Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+        P: Fn(&E) -> bool,
+    {
+        let mut attempt = 1;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_attempts && should_retry(&e) => {
+                    tokio::time::sleep(self.delay(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(max_attempts)
+            .with_initial_backoff(Duration::from_millis(1))
+            .with_jitter(false)
+    }
+
+    fn transient() -> Error {
+        Error::external("scan", std::io::Error::from(std::io::ErrorKind::ConnectionReset))
+    }
+
+    #[test]
+    fn test_backoff_grows_and_is_capped() {
+        let policy = RetryPolicy::new(10)
+            .with_initial_backoff(Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(500));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_classification() {
+        assert!(transient().is_retryable());
+        assert!(!Error::new("bad config").is_retryable());
+        assert!(tonic::Status::unavailable("down").is_retryable());
+        assert!(!tonic::Status::invalid_argument("bad sql").is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors_until_success() {
+        let calls = AtomicU32::new(0);
+        let result = fast_policy(5)
+            .retry(|| async {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(transient())
+                } else {
+                    Ok(42)
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), Error> = fast_policy(3)
+            .retry(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(transient())
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_errors_are_not_retried() {
+        let calls = AtomicU32::new(0);
+        let result: Result<(), Error> = fast_policy(3)
+            .retry(|| async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::new("permission denied"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
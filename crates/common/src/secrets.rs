@@ -0,0 +1,230 @@
+//! Secrets kept out of configuration and the catalog.
+//!
+//! Wherever a credential is expected (a setting, the option of a table), a reference to
+//! where it is kept can be given instead:
+//!
+//! - `env:NAME`: the environment variable `NAME`;
+//! - `file:/path`: the contents of a file, without a trailing newline, as Docker and
+//!   Kubernetes mount secrets;
+//! - `scheme:path` for any [`SecretProvider`] registered with
+//!   [`Secrets::with_provider`], such as [`VaultProvider`] for HashiCorp Vault
+//!   (`vault:secret/data/igloo#password`).
+//!
+//! Values naming no known scheme are credentials themselves. Every resolved credential
+//! is registered with [`redact`](crate::redact), so it is masked in logs and errors
+//! from then on, however it is formatted. Errors name the reference, never the value.
+
+use crate::error::{Error, Result};
+use crate::redact;
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Where secrets of one scheme are kept.
+#[async_trait]
+pub trait SecretProvider: fmt::Debug + Send + Sync {
+    /// The secret at `path`, the part of a reference after the scheme.
+    async fn get(&self, path: &str) -> Result<String>;
+}
+
+/// The providers secret references are resolved with, by scheme.
+#[derive(Debug, Clone)]
+pub struct Secrets {
+    providers: BTreeMap<String, Arc<dyn SecretProvider>>,
+}
+
+impl Default for Secrets {
+    /// Environment variables (`env:`) and files (`file:`).
+    fn default() -> Self {
+        Self { providers: BTreeMap::new() }
+            .with_provider("env", Arc::new(EnvProvider))
+            .with_provider("file", Arc::new(FileProvider))
+    }
+}
+
+impl Secrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve references `scheme:path` with `provider`.
+    pub fn with_provider(mut self, scheme: &str, provider: Arc<dyn SecretProvider>) -> Self {
+        self.providers.insert(scheme.to_ascii_lowercase(), provider);
+        self
+    }
+
+    /// Whether `value` references a secret rather than being one.
+    pub fn is_reference(&self, value: &str) -> bool {
+        self.provider(value).is_some()
+    }
+
+    /// The secret `value` references, or `value` itself if it is no reference.
+    pub async fn resolve(&self, value: &str) -> Result<String> {
+        let Some((provider, path)) = self.provider(value) else {
+            return Ok(value.to_string());
+        };
+        let secret = provider
+            .get(path)
+            .await
+            .map_err(|e| Error::external(format!("cannot resolve secret '{value}'"), e))?;
+        redact::register_secret(&secret);
+        Ok(secret)
+    }
+
+    fn provider<'a>(&self, value: &'a str) -> Option<(&Arc<dyn SecretProvider>, &'a str)> {
+        let (scheme, path) = value.split_once(':')?;
+        // `scheme://...` is a URL, such as a connection string.
+        if path.starts_with("//") {
+            return None;
+        }
+        Some((self.providers.get(&scheme.to_ascii_lowercase())?, path))
+    }
+}
+
+/// `env:NAME`
+#[derive(Debug)]
+struct EnvProvider;
+
+#[async_trait]
+impl SecretProvider for EnvProvider {
+    async fn get(&self, name: &str) -> Result<String> {
+        std::env::var(name)
+            .map_err(|_| Error::new(&format!("environment variable {name} is not set")))
+    }
+}
+
+/// `file:/path`
+#[derive(Debug)]
+struct FileProvider;
+
+#[async_trait]
+impl SecretProvider for FileProvider {
+    async fn get(&self, path: &str) -> Result<String> {
+        let text = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| Error::external(format!("cannot read {path}"), e))?;
+        Ok(text.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// Secrets of a HashiCorp Vault server, referenced as `vault:path#key`: the field `key`
+/// (`value` if left out) of the secret read from `GET /v1/path`. Both the KV version 2
+/// engine (`vault:secret/data/igloo#password`) and version 1 are understood.
+pub struct VaultProvider {
+    addr: String,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl fmt::Debug for VaultProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VaultProvider").field("addr", &self.addr).finish_non_exhaustive()
+    }
+}
+
+impl VaultProvider {
+    /// The server at `addr` (`https://vault:8200`), authenticated with `token`.
+    pub fn new(addr: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultProvider {
+    async fn get(&self, reference: &str) -> Result<String> {
+        let (path, key) = reference.split_once('#').unwrap_or((reference, "value"));
+        let url = format!("{}/v1/{}", self.addr, path.trim_start_matches('/'));
+        let failed =
+            |e: reqwest::Error| Error::external(format!("cannot read {path} from Vault"), e);
+        let response = self
+            .client
+            .get(&url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(failed)?;
+        let body: serde_json::Value = response.json().await.map_err(failed)?;
+        // KV version 2 nests the fields in `data.data`, version 1 in `data`.
+        let data = &body["data"];
+        let fields = if data["data"].is_object() { &data["data"] } else { data };
+        match &fields[key] {
+            serde_json::Value::String(secret) => Ok(secret.clone()),
+            serde_json::Value::Null => {
+                Err(Error::new(&format!("Vault secret {path} has no field '{key}'")))
+            }
+            other => Ok(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::{Json, Router};
+
+    #[tokio::test]
+    async fn test_resolves_references() {
+        std::env::set_var("IGLOO_TEST_SECRET_TOKEN", "s3cr3t-token");
+        let path = std::env::temp_dir().join(format!("igloo-secret-{}", std::process::id()));
+        std::fs::write(&path, "file-password\n").unwrap();
+        let secrets = Secrets::new();
+
+        assert_eq!(secrets.resolve("env:IGLOO_TEST_SECRET_TOKEN").await.unwrap(), "s3cr3t-token");
+        let reference = format!("file:{}", path.display());
+        assert_eq!(secrets.resolve(&reference).await.unwrap(), "file-password");
+        std::fs::remove_file(path).unwrap();
+
+        // Not references: credentials as they are, and URLs.
+        assert_eq!(secrets.resolve("hunter2").await.unwrap(), "hunter2");
+        let url = "postgres://igloo@db/app";
+        assert!(!secrets.is_reference(url));
+        assert!(!secrets.is_reference("vault:secret/data/igloo"));
+
+        // Resolved values are masked from now on.
+        assert_eq!(redact::redact("token is s3cr3t-token"), "token is ********");
+    }
+
+    #[tokio::test]
+    async fn test_errors_name_the_reference() {
+        let error = Secrets::new().resolve("env:IGLOO_TEST_UNSET_SECRET").await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "cannot resolve secret 'env:IGLOO_TEST_UNSET_SECRET': An unknown error occurred: \
+             environment variable IGLOO_TEST_UNSET_SECRET is not set"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vault_provider_reads_kv_secrets() {
+        let app = Router::new()
+            .route(
+                "/v1/secret/data/igloo",
+                get(|| async {
+                    Json(serde_json::json!({"data": {"data": {"password": "v2-pass"}}}))
+                }),
+            )
+            .route(
+                "/v1/kv/igloo",
+                get(|| async { Json(serde_json::json!({"data": {"value": "v1-pass"}})) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let vault = VaultProvider::new(format!("http://{addr}"), "root");
+        let secrets = Secrets::new().with_provider("vault", Arc::new(vault));
+        let secret = secrets.resolve("vault:secret/data/igloo#password").await.unwrap();
+        assert_eq!(secret, "v2-pass");
+        assert_eq!(secrets.resolve("vault:kv/igloo").await.unwrap(), "v1-pass");
+        let error = secrets.resolve("vault:secret/data/igloo#user").await.unwrap_err();
+        assert!(error.to_string().contains("has no field 'user'"), "{error}");
+        assert!(secrets.resolve("vault:missing#value").await.is_err());
+    }
+}
@@ -0,0 +1,19 @@
+//! Waiting for the signal to shut down.
+
+/// Resolves once the process is asked to stop: on `SIGTERM`, which orchestrators send
+/// before killing a container, or `SIGINT` (Ctrl-C).
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            interrupted = tokio::signal::ctrl_c() => {
+                interrupted.expect("failed to listen for SIGINT");
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.expect("failed to listen for SIGINT");
+}
@@ -0,0 +1,6 @@
+fn main() {
+    tonic_build::configure()
+        .build_server(true)
+        .compile_protos(&["proto/google/cloud/bigquery/storage/v1/storage.proto"], &["proto"])
+        .unwrap();
+}
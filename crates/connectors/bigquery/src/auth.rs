@@ -0,0 +1,158 @@
+//! OAuth 2.0 access tokens for Google Cloud.
+//!
+//! Requests are authorized with an access token of the [`Credentials`] given: a token
+//! obtained elsewhere, such as with `gcloud auth print-access-token`; one a service
+//! account's key is exchanged for, with a JWT signed by the key; or one of the
+//! service account of the VM, from the metadata server. Tokens exchanged for are
+//! reused until shortly before they expire.
+
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// The scope of the tokens.
+pub const SCOPE: &str = "https://www.googleapis.com/auth/bigquery";
+/// The metadata server's endpoint of the tokens of the VM's service account.
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// How long before it expires a token is replaced.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// What requests are authorized with, see the [module docs](self).
+#[derive(Clone)]
+pub enum Credentials {
+    Token(String),
+    ServiceAccount(ServiceAccountKey),
+    Metadata,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Token(_) => f.write_str("Token(..)"),
+            Credentials::ServiceAccount(key) => {
+                f.debug_tuple("ServiceAccount").field(&key.client_email).finish()
+            }
+            Credentials::Metadata => f.write_str("Metadata"),
+        }
+    }
+}
+
+/// The JSON key of a service account, as downloaded from the console.
+#[derive(Clone, Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    #[serde(default = "default_token_uri")]
+    pub token_uri: String,
+}
+
+fn default_token_uri() -> String {
+    "https://oauth2.googleapis.com/token".to_string()
+}
+
+impl ServiceAccountKey {
+    pub fn from_json(json: &str) -> DataFusionResult<Self> {
+        serde_json::from_str(json).map_err(|e| {
+            DataFusionError::Configuration(format!("invalid service account key: {e}"))
+        })
+    }
+}
+
+/// The access tokens of credentials.
+pub struct TokenSource {
+    credentials: Credentials,
+    http: reqwest::Client,
+    /// The token exchanged for last, and when it expires.
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl fmt::Debug for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSource")
+            .field("credentials", &self.credentials)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The token endpoints' response.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl TokenSource {
+    pub fn new(credentials: Credentials) -> Self {
+        Self { credentials, http: reqwest::Client::new(), cached: Mutex::new(None) }
+    }
+
+    /// A token that is valid for a while still.
+    pub async fn token(&self) -> DataFusionResult<String> {
+        let key = match &self.credentials {
+            Credentials::Token(token) => return Ok(token.clone()),
+            Credentials::ServiceAccount(key) => Some(key),
+            Credentials::Metadata => None,
+        };
+        let mut cached = self.cached.lock().await;
+        if let Some((token, expires)) = &*cached {
+            if Instant::now() + EXPIRY_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+        let request = match key {
+            Some(key) => {
+                let form = [
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", &assertion(key)?),
+                ];
+                self.http.post(&key.token_uri).form(&form)
+            }
+            None => self.http.get(METADATA_TOKEN_URL).header("Metadata-Flavor", "Google"),
+        };
+        let response = request.send().await.map_err(http_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(DataFusionError::Execution(format!(
+                "Google OAuth token endpoint responded {status}: {body}"
+            )));
+        }
+        let response: TokenResponse = response.json().await.map_err(http_error)?;
+        let expires = Instant::now() + Duration::from_secs(response.expires_in);
+        *cached = Some((response.access_token.clone(), expires));
+        Ok(response.access_token)
+    }
+}
+
+/// The JWT asserting `key`'s service account, for an hour.
+fn assertion(key: &ServiceAccountKey) -> DataFusionResult<String> {
+    #[derive(Serialize)]
+    struct Claims<'a> {
+        iss: &'a str,
+        scope: &'a str,
+        aud: &'a str,
+        iat: u64,
+        exp: u64,
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let claims = Claims {
+        iss: &key.client_email,
+        scope: SCOPE,
+        aud: &key.token_uri,
+        iat: now,
+        exp: now + 3600,
+    };
+    let invalid =
+        |e| DataFusionError::Configuration(format!("invalid service account private key: {e}"));
+    let signing_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes()).map_err(invalid)?;
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &signing_key).map_err(invalid)
+}
+
+fn http_error(e: reqwest::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
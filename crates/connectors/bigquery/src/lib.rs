@@ -0,0 +1,31 @@
+//! Google BigQuery tables.
+//!
+//! [`BigQueryTable`] reads a table through the Storage Read API, in Arrow format, in
+//! parallel streams of the projected columns and of the rows matching the filters
+//! BigQuery evaluates (see [`table`]). Requests are authorized with OAuth 2.0 access
+//! tokens, of a service account's key or of the VM's service account (see [`auth`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_bigquery::{BigQueryClient, BigQueryTable, Credentials, ServiceAccountKey};
+//! use std::sync::Arc;
+//!
+//! let key = ServiceAccountKey::from_json(&std::fs::read_to_string("key.json")?)?;
+//! let client = BigQueryClient::connect("analytics-billing", Credentials::ServiceAccount(key))?;
+//! let table = BigQueryTable::try_new(client, "bigquery-public-data.samples.shakespeare").await?;
+//! ctx.register_table("shakespeare", Arc::new(table))?;
+//! ctx.sql("SELECT word, word_count FROM shakespeare WHERE corpus = 'hamlet'").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod auth;
+pub mod table;
+
+pub mod storage {
+    //! The messages and client of the Storage Read API.
+    tonic::include_proto!("google.cloud.bigquery.storage.v1");
+}
+
+pub use auth::{Credentials, ServiceAccountKey};
+pub use table::{BigQueryClient, BigQueryTable};
@@ -0,0 +1,554 @@
+//! Reading BigQuery tables.
+//!
+//! A [`BigQueryTable`] reads a table through the Storage Read API, in Arrow format.
+//! Each scan creates a read session of the projected columns, and of the rows
+//! matching the filters BigQuery evaluates as the engine would, translated to a
+//! GoogleSQL row restriction. BigQuery splits the rows of a session into streams, up
+//! to the session's target partitions, which are the partitions of the scan; they
+//! all read the table as of when the session was created.
+//!
+//! Sessions are billed to the project of the [`BigQueryClient`], whose credentials
+//! need the `bigquery.readsessions.create` permission there and read access to the
+//! tables.
+
+use crate::auth::{Credentials, TokenSource};
+use crate::storage::big_query_read_client::BigQueryReadClient;
+use crate::storage::read_rows_response::Rows;
+use crate::storage::read_session::{Schema as SessionSchema, TableReadOptions};
+use crate::storage::{CreateReadSessionRequest, DataFormat, ReadRowsRequest, ReadSession};
+use async_trait::async_trait;
+use datafusion::arrow::buffer::Buffer;
+use datafusion::arrow::datatypes::{
+    DataType, Decimal128Type, Decimal256Type, DecimalType, Field, SchemaRef,
+};
+use datafusion::arrow::ipc::reader::{StreamDecoder, StreamReader};
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::arrow::temporal_conversions::{
+    date32_to_datetime, time64us_to_time, timestamp_us_to_datetime,
+};
+use datafusion::catalog::Session;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::{Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use std::any::Any;
+use std::fmt::{self, Write};
+use std::io::Cursor;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::metadata::AsciiMetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::Request;
+
+/// The endpoint of the Storage API.
+pub const ENDPOINT: &str = "https://bigquerystorage.googleapis.com";
+/// The largest response read, as for Google's clients; tonic's default is 4 MiB.
+const MAX_MESSAGE_SIZE: usize = 128 << 20;
+
+/// A client of the Storage Read API, billing the sessions it creates to a project.
+#[derive(Clone)]
+pub struct BigQueryClient {
+    read: BigQueryReadClient<Channel>,
+    tokens: Arc<TokenSource>,
+    project: String,
+}
+
+impl fmt::Debug for BigQueryClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BigQueryClient").field("project", &self.project).finish_non_exhaustive()
+    }
+}
+
+impl BigQueryClient {
+    /// A client billing `project`; it connects once first used.
+    pub fn connect(project: impl Into<String>, credentials: Credentials) -> DataFusionResult<Self> {
+        Self::connect_to(ENDPOINT, project, credentials)
+    }
+
+    /// A client of the API at `endpoint` rather than Google's, such as a proxy's.
+    pub fn connect_to(
+        endpoint: &str,
+        project: impl Into<String>,
+        credentials: Credentials,
+    ) -> DataFusionResult<Self> {
+        let mut channel = Channel::from_shared(endpoint.to_string()).map_err(|e| {
+            DataFusionError::Configuration(format!("invalid BigQuery endpoint {endpoint}: {e}"))
+        })?;
+        if endpoint.starts_with("https://") {
+            channel = channel
+                .tls_config(ClientTlsConfig::new().with_webpki_roots())
+                .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        }
+        let read = BigQueryReadClient::new(channel.connect_lazy())
+            .max_decoding_message_size(MAX_MESSAGE_SIZE);
+        Ok(Self { read, tokens: Arc::new(TokenSource::new(credentials)), project: project.into() })
+    }
+
+    /// `message`, authorized and routed by the request `params`.
+    async fn request<T>(&self, message: T, params: &str) -> DataFusionResult<Request<T>> {
+        let token = self.tokens.token().await?;
+        let mut request = Request::new(message);
+        let metadata = request.metadata_mut();
+        metadata.insert("authorization", header(&format!("Bearer {token}"))?);
+        metadata.insert("x-goog-request-params", header(params)?);
+        Ok(request)
+    }
+
+    /// A session reading `table`, as `projects/{project}/datasets/{dataset}/tables/{table}`,
+    /// in at most `streams` streams.
+    async fn create_session(
+        &self,
+        table: &str,
+        options: TableReadOptions,
+        streams: usize,
+    ) -> DataFusionResult<ReadSession> {
+        let message = CreateReadSessionRequest {
+            parent: format!("projects/{}", self.project),
+            read_session: Some(ReadSession {
+                table: table.to_string(),
+                data_format: DataFormat::Arrow as i32,
+                read_options: Some(options),
+                ..Default::default()
+            }),
+            max_stream_count: i32::try_from(streams).unwrap_or(i32::MAX),
+        };
+        let params = format!("read_session.table={}", escape(table));
+        let request = self.request(message, &params).await?;
+        let session = self.read.clone().create_read_session(request).await.map_err(grpc_error)?;
+        Ok(session.into_inner())
+    }
+}
+
+/// A table, see the [module docs](self).
+#[derive(Debug)]
+pub struct BigQueryTable {
+    client: BigQueryClient,
+    /// `projects/{project}/datasets/{dataset}/tables/{table}`.
+    table: String,
+    schema: SchemaRef,
+}
+
+impl BigQueryTable {
+    /// The table `name`, as `project.dataset.table`, with the schema BigQuery reads it
+    /// as.
+    pub async fn try_new(client: BigQueryClient, name: &str) -> DataFusionResult<Self> {
+        let mut parts = name.rsplitn(3, '.');
+        let (Some(table), Some(dataset), Some(project)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(DataFusionError::Configuration(format!(
+                "BigQuery table names are project.dataset.table, not {name}"
+            )));
+        };
+        let table = format!("projects/{project}/datasets/{dataset}/tables/{table}");
+        // A session that is never read costs nothing.
+        let session = client.create_session(&table, TableReadOptions::default(), 1).await?;
+        let schema =
+            StreamReader::try_new(Cursor::new(serialized_schema(&session)?), None)?.schema();
+        Ok(Self { client, table, schema })
+    }
+}
+
+#[async_trait]
+impl TableProvider for BigQueryTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match self.restriction(filter) {
+                Some(_) => TableProviderFilterPushDown::Exact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let indices: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&indices)?);
+        // Sessions read their fields in the table's order, and all of them if none is
+        // selected, as for `SELECT count(*)`.
+        let mut selected = indices.clone();
+        selected.sort_unstable();
+        selected.dedup();
+        if selected.is_empty() {
+            selected.push(0);
+        }
+        let columns: Arc<[usize]> =
+            indices.iter().filter_map(|i| selected.binary_search(i).ok()).collect();
+        let restrictions: Vec<_> = filters
+            .iter()
+            .filter_map(|filter| self.restriction(filter))
+            .map(|restriction| format!("({restriction})"))
+            .collect();
+        let options = TableReadOptions {
+            selected_fields: selected
+                .iter()
+                .map(|&i| self.schema.field(i).name().clone())
+                .collect(),
+            row_restriction: restrictions.join(" AND "),
+        };
+        let streams = state.config().target_partitions();
+        let session = self.client.create_session(&self.table, options, streams).await?;
+        // Tables without rows have no stream.
+        if session.streams.is_empty() {
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+        let serialized_schema = serialized_schema(&session)?;
+        let partitions = session
+            .streams
+            .into_iter()
+            .map(|stream| {
+                Arc::new(Read {
+                    client: self.client.clone(),
+                    stream: stream.name,
+                    serialized_schema: serialized_schema.clone(),
+                    schema: Arc::clone(&schema),
+                    columns: Arc::clone(&columns),
+                }) as Arc<dyn PartitionStream>
+            })
+            .collect();
+        Ok(Arc::new(StreamingTableExec::try_new(schema, partitions, None, None, false, limit)?))
+    }
+}
+
+impl BigQueryTable {
+    /// The row restriction of `filter`, if BigQuery evaluates it as the engine would.
+    fn restriction(&self, filter: &Expr) -> Option<String> {
+        let field = |expr: &Expr| {
+            let Expr::Column(column) = expr else { return None };
+            self.schema.field_with_name(&column.name).ok()
+        };
+        // NaN orders first in BigQuery, but last in the engine.
+        let ordered = |field: &Field| !field.data_type().is_floating();
+        match filter {
+            Expr::BinaryExpr(BinaryExpr {
+                left,
+                op: op @ (Operator::And | Operator::Or),
+                right,
+            }) => {
+                let (left, right) = (self.restriction(left)?, self.restriction(right)?);
+                let op = if *op == Operator::And { "AND" } else { "OR" };
+                Some(format!("({left}) {op} ({right})"))
+            }
+            Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+                let (field, op, value) = match (field(left), field(right)) {
+                    (Some(field), None) => (field, *op, right),
+                    (None, Some(field)) => (field, op.swap()?, left),
+                    _ => return None,
+                };
+                let op = match op {
+                    Operator::Eq => "=",
+                    Operator::NotEq => "!=",
+                    Operator::Lt if ordered(field) => "<",
+                    Operator::LtEq if ordered(field) => "<=",
+                    Operator::Gt if ordered(field) => ">",
+                    Operator::GtEq if ordered(field) => ">=",
+                    _ => return None,
+                };
+                let value = literal(value, field.data_type())?;
+                Some(format!("{} {op} {value}", identifier(field.name())))
+            }
+            Expr::Column(_) => {
+                let field =
+                    field(filter).filter(|field| *field.data_type() == DataType::Boolean)?;
+                Some(identifier(field.name()))
+            }
+            Expr::Not(expr) => Some(format!("NOT ({})", self.restriction(expr)?)),
+            Expr::IsNull(expr) => Some(format!("{} IS NULL", identifier(field(expr)?.name()))),
+            Expr::IsNotNull(expr) => {
+                Some(format!("{} IS NOT NULL", identifier(field(expr)?.name())))
+            }
+            Expr::Between(Between { expr, negated, low, high }) => {
+                let field = field(expr).filter(|field| ordered(field))?;
+                let low = literal(low, field.data_type())?;
+                let high = literal(high, field.data_type())?;
+                let not = if *negated { "NOT " } else { "" };
+                Some(format!("{} {not}BETWEEN {low} AND {high}", identifier(field.name())))
+            }
+            Expr::InList(InList { expr, list, negated }) => {
+                let field = field(expr)?;
+                let values: Vec<_> = list
+                    .iter()
+                    .map(|item| literal(item, field.data_type()))
+                    .collect::<Option<_>>()?;
+                let not = if *negated { "NOT " } else { "" };
+                Some(format!("{} {not}IN ({})", identifier(field.name()), values.join(", ")))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `value` as a GoogleSQL literal of the type BigQuery reads as `data_type`.
+fn literal(value: &Expr, data_type: &DataType) -> Option<String> {
+    let Expr::Literal(value, _) = value else { return None };
+    if value.data_type() != *data_type {
+        return None;
+    }
+    let timestamp =
+        |micros: i64| timestamp_us_to_datetime(micros).map(|t| t.format("%Y-%m-%d %H:%M:%S%.6f"));
+    match value {
+        ScalarValue::Boolean(Some(v)) => Some(if *v { "TRUE" } else { "FALSE" }.to_string()),
+        ScalarValue::Int64(Some(v)) => Some(v.to_string()),
+        ScalarValue::Float64(Some(v)) if v.is_finite() => Some(format!("{v:?}")),
+        ScalarValue::Decimal128(Some(v), p, s) => {
+            Some(format!("NUMERIC '{}'", Decimal128Type::format_decimal(*v, *p, *s)))
+        }
+        ScalarValue::Decimal256(Some(v), p, s) => {
+            Some(format!("BIGNUMERIC '{}'", Decimal256Type::format_decimal(*v, *p, *s)))
+        }
+        ScalarValue::Utf8(Some(v)) => Some(string(v)),
+        ScalarValue::Binary(Some(v)) => Some(
+            v.iter().fold("b'".to_string(), |mut s, b| {
+                let _ = write!(s, "\\x{b:02x}");
+                s
+            }) + "'",
+        ),
+        ScalarValue::Date32(Some(v)) => {
+            Some(format!("DATE '{}'", date32_to_datetime(*v)?.format("%Y-%m-%d")))
+        }
+        ScalarValue::Time64Microsecond(Some(v)) => {
+            Some(format!("TIME '{}'", time64us_to_time(*v)?.format("%H:%M:%S%.6f")))
+        }
+        ScalarValue::TimestampMicrosecond(Some(v), None) => {
+            Some(format!("DATETIME '{}'", timestamp(*v)?))
+        }
+        ScalarValue::TimestampMicrosecond(Some(v), Some(_)) => {
+            Some(format!("TIMESTAMP '{}+00'", timestamp(*v)?))
+        }
+        _ => None,
+    }
+}
+
+/// `value` as a GoogleSQL string literal.
+fn string(value: &str) -> String {
+    let mut quoted = String::from("'");
+    for c in value.chars() {
+        match c {
+            '\\' | '\'' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// `name` as a quoted GoogleSQL identifier.
+fn identifier(name: &str) -> String {
+    format!("`{}`", name.replace('\\', "\\\\").replace('`', "\\`"))
+}
+
+/// The Arrow IPC message of the schema of `session`.
+fn serialized_schema(session: &ReadSession) -> DataFusionResult<Vec<u8>> {
+    match &session.schema {
+        Some(SessionSchema::ArrowSchema(schema)) => Ok(schema.serialized_schema.clone()),
+        _ => Err(DataFusionError::Execution(format!(
+            "BigQuery read session of {} has no Arrow schema",
+            session.table
+        ))),
+    }
+}
+
+/// The read of a stream of a session.
+#[derive(Debug, Clone)]
+struct Read {
+    client: BigQueryClient,
+    /// The stream's name.
+    stream: String,
+    serialized_schema: Vec<u8>,
+    schema: SchemaRef,
+    /// The index of each projected column among the session's fields.
+    columns: Arc<[usize]>,
+}
+
+type Sender = mpsc::Sender<DataFusionResult<RecordBatch>>;
+
+impl Read {
+    /// Send the batches read into `sender` until it is closed.
+    async fn run(&self, sender: &Sender) -> DataFusionResult<()> {
+        let mut decoder = StreamDecoder::new();
+        let mut schema = Buffer::from_vec(self.serialized_schema.clone());
+        while !schema.is_empty() {
+            decoder.decode(&mut schema)?;
+        }
+        let message = ReadRowsRequest { read_stream: self.stream.clone(), offset: 0 };
+        let params = format!("read_stream={}", escape(&self.stream));
+        let request = self.client.request(message, &params).await?;
+        let mut responses =
+            self.client.read.clone().read_rows(request).await.map_err(grpc_error)?.into_inner();
+        while let Some(response) = responses.message().await.map_err(grpc_error)? {
+            let Some(Rows::ArrowRecordBatch(batch)) = response.rows else { continue };
+            let mut buffer = Buffer::from_vec(batch.serialized_record_batch);
+            while !buffer.is_empty() {
+                let Some(batch) = decoder.decode(&mut buffer)? else { continue };
+                if sender.send(self.project(batch)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The projected columns of a `batch` of the session's fields.
+    fn project(&self, batch: RecordBatch) -> DataFusionResult<RecordBatch> {
+        let arrays = self.columns.iter().map(|&i| Arc::clone(batch.column(i))).collect();
+        // Batches of scans projecting no column (`count(*)`) have rows all the same.
+        let options = RecordBatchOptions::new().with_row_count(Some(batch.num_rows()));
+        Ok(RecordBatch::try_new_with_options(Arc::clone(&self.schema), arrays, &options)?)
+    }
+}
+
+impl PartitionStream for Read {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let read = self.clone();
+        let (sender, receiver) = mpsc::channel(2);
+        tokio::spawn(async move {
+            if let Err(e) = read.run(&sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+/// `value` as a header value.
+fn header(value: &str) -> DataFusionResult<AsciiMetadataValue> {
+    value.parse().map_err(|_| {
+        DataFusionError::Execution(format!("BigQuery request header {value:?} is not ASCII"))
+    })
+}
+
+/// `value` percent-encoded, as the values of `x-goog-request-params` are.
+fn escape(value: &str) -> String {
+    value.bytes().fold(String::new(), |mut escaped, b| {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                escaped.push(b as char)
+            }
+            b => {
+                let _ = write!(escaped, "%{b:02X}");
+            }
+        }
+        escaped
+    })
+}
+
+fn grpc_error(e: tonic::Status) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{Schema, TimeUnit};
+    use datafusion::prelude::{col, lit};
+
+    fn table() -> BigQueryTable {
+        let client = BigQueryClient::connect_to(
+            "http://127.0.0.1:1",
+            "billing",
+            Credentials::Token("t".into()),
+        )
+        .unwrap();
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("score", DataType::Float64, true),
+            Field::new("active", DataType::Boolean, true),
+            Field::new("at", DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())), true),
+        ]);
+        BigQueryTable {
+            client,
+            table: "projects/p/datasets/d/tables/t".to_string(),
+            schema: Arc::new(schema),
+        }
+    }
+
+    #[test]
+    fn test_literals_are_googlesql() {
+        let literal =
+            |value: ScalarValue| literal(&Expr::Literal(value.clone(), None), &value.data_type());
+        assert_eq!(literal(ScalarValue::from("it's \\ \n")).unwrap(), r"'it\'s \\ \u000a'");
+        assert_eq!(literal(ScalarValue::Float64(Some(0.5))).unwrap(), "0.5");
+        assert_eq!(literal(ScalarValue::Float64(Some(f64::NAN))), None);
+        assert_eq!(literal(ScalarValue::Date32(Some(19_723))).unwrap(), "DATE '2024-01-01'");
+        assert_eq!(
+            literal(ScalarValue::Time64Microsecond(Some(3_600_000_001))).unwrap(),
+            "TIME '01:00:00.000001'"
+        );
+        let at = ScalarValue::TimestampMicrosecond(Some(1_704_067_200_000_001), Some("UTC".into()));
+        assert_eq!(literal(at).unwrap(), "TIMESTAMP '2024-01-01 00:00:00.000001+00'");
+        assert_eq!(
+            literal(ScalarValue::Decimal128(Some(12_345_000_000), 38, 9)).unwrap(),
+            "NUMERIC '12.345000000'"
+        );
+        assert_eq!(literal(ScalarValue::Binary(Some(vec![0, 255]))).unwrap(), r"b'\x00\xff'");
+        assert_eq!(escape("projects/p/tables/t"), "projects%2Fp%2Ftables%2Ft");
+    }
+
+    #[tokio::test]
+    async fn test_filters_become_row_restrictions() {
+        let table = table();
+        let restriction = |filter: Expr| table.restriction(&filter);
+        assert_eq!(
+            restriction(col("id").gt(lit(3_i64)).and(col("name").is_not_null())).unwrap(),
+            "(`id` > 3) AND (`name` IS NOT NULL)"
+        );
+        assert_eq!(
+            restriction(lit(3_i64).lt_eq(col("id")).or(!col("active"))).unwrap(),
+            "(`id` >= 3) OR (NOT (`active`))"
+        );
+        assert_eq!(
+            restriction(col("name").in_list(vec![lit("a"), lit("b")], true)).unwrap(),
+            "`name` NOT IN ('a', 'b')"
+        );
+        assert_eq!(restriction(col("score").eq(lit(1.5))).unwrap(), "`score` = 1.5");
+
+        // Floats order NaN differently, and integers are not compared with strings.
+        assert_eq!(restriction(col("score").gt(lit(1.5))), None);
+        assert_eq!(restriction(col("id").eq(lit("3"))), None);
+        assert_eq!(restriction(col("name").like(lit("a%"))), None);
+    }
+}
@@ -0,0 +1,228 @@
+use datafusion::arrow::array::{Float64Array, Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::ipc::writer::{
+    write_message, DictionaryTracker, IpcDataGenerator, IpcWriteOptions,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::prelude::{SessionConfig, SessionContext};
+use igloo_connector_bigquery::storage::big_query_read_server::{BigQueryRead, BigQueryReadServer};
+use igloo_connector_bigquery::storage::read_rows_response::Rows;
+use igloo_connector_bigquery::storage::read_session::{Schema as SessionSchema, TableReadOptions};
+use igloo_connector_bigquery::storage::{
+    ArrowRecordBatch, ArrowSchema, CreateReadSessionRequest, ReadRowsRequest, ReadRowsResponse,
+    ReadSession, ReadStream,
+};
+use igloo_connector_bigquery::{BigQueryClient, BigQueryTable, Credentials};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+
+const TABLE: &str = "projects/shop/datasets/sales/tables/orders";
+
+/// A Storage Read API serving the table `shop.sales.orders`, two streams per session.
+#[derive(Clone, Default)]
+struct Mock {
+    /// The read options of the sessions created.
+    sessions: Arc<Mutex<Vec<TableReadOptions>>>,
+}
+
+fn table() -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("customer", DataType::Utf8, true),
+        Field::new("total", DataType::Float64, true),
+    ]);
+    RecordBatch::try_new(
+        Arc::new(schema),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(StringArray::from(vec![Some("ada"), None, Some("bob")])),
+            Arc::new(Float64Array::from(vec![9.5, 20.0, 3.25])),
+        ],
+    )
+    .unwrap()
+}
+
+fn authorized<T>(request: &Request<T>, params: &str) {
+    let metadata = request.metadata();
+    assert_eq!(metadata.get("authorization").unwrap(), "Bearer secret");
+    assert_eq!(metadata.get("x-goog-request-params").unwrap(), params);
+}
+
+/// The columns of `fields` of `batch`, all of them if there are none.
+fn select(batch: &RecordBatch, fields: &[String]) -> RecordBatch {
+    if fields.is_empty() {
+        return batch.clone();
+    }
+    let schema = batch.schema();
+    let indices: Vec<_> = fields.iter().map(|field| schema.index_of(field).unwrap()).collect();
+    batch.project(&indices).unwrap()
+}
+
+fn ipc_schema(schema: &SchemaRef) -> Vec<u8> {
+    let options = IpcWriteOptions::default();
+    let mut tracker = DictionaryTracker::new(false);
+    let encoded = IpcDataGenerator::default().schema_to_bytes_with_dictionary_tracker(
+        schema,
+        &mut tracker,
+        &options,
+    );
+    let mut bytes = Vec::new();
+    write_message(&mut bytes, encoded, &options).unwrap();
+    bytes
+}
+
+fn ipc_batch(batch: &RecordBatch) -> Vec<u8> {
+    let options = IpcWriteOptions::default();
+    let mut tracker = DictionaryTracker::new(false);
+    let (_, encoded) =
+        IpcDataGenerator::default().encoded_batch(batch, &mut tracker, &options).unwrap();
+    let mut bytes = Vec::new();
+    write_message(&mut bytes, encoded, &options).unwrap();
+    bytes
+}
+
+#[tonic::async_trait]
+impl BigQueryRead for Mock {
+    async fn create_read_session(
+        &self,
+        request: Request<CreateReadSessionRequest>,
+    ) -> Result<Response<ReadSession>, Status> {
+        authorized(
+            &request,
+            "read_session.table=projects%2Fshop%2Fdatasets%2Fsales%2Ftables%2Forders",
+        );
+        let request = request.into_inner();
+        assert_eq!(request.parent, "projects/billing");
+        let session = request.read_session.unwrap();
+        assert_eq!(session.table, TABLE);
+        let options = session.read_options.unwrap_or_default();
+        let index = {
+            let mut sessions = self.sessions.lock().unwrap();
+            sessions.push(options.clone());
+            sessions.len()
+        };
+        let schema = select(&table(), &options.selected_fields).schema();
+        let name = format!("projects/billing/locations/us/sessions/s{index}");
+        let streams = (0..request.max_stream_count.min(2))
+            .map(|i| ReadStream { name: format!("{name}/streams/{i}") })
+            .collect();
+        Ok(Response::new(ReadSession {
+            name,
+            table: TABLE.to_string(),
+            schema: Some(SessionSchema::ArrowSchema(ArrowSchema {
+                serialized_schema: ipc_schema(&schema),
+            })),
+            streams,
+            ..Default::default()
+        }))
+    }
+
+    type ReadRowsStream = Pin<Box<dyn Stream<Item = Result<ReadRowsResponse, Status>> + Send>>;
+
+    async fn read_rows(
+        &self,
+        request: Request<ReadRowsRequest>,
+    ) -> Result<Response<Self::ReadRowsStream>, Status> {
+        let stream = request.get_ref().read_stream.clone();
+        let params = format!("read_stream={}", stream.replace('/', "%2F"));
+        authorized(&request, &params);
+        let options = self.sessions.lock().unwrap().last().cloned().unwrap();
+        // The one restriction the tests read with.
+        let table = table();
+        let rows: Vec<usize> = match options.row_restriction.as_str() {
+            "" => (0..3).collect(),
+            "((`total` = 20.0) OR (`customer` IS NOT NULL)) AND (`id` >= 2)" => vec![1, 2],
+            restriction => panic!("unexpected restriction {restriction}"),
+        };
+        // The first stream reads the first row, the second the others.
+        let rows: Vec<_> = match stream.ends_with("/0") {
+            true => rows.into_iter().take(1).collect(),
+            false => rows.into_iter().skip(1).collect(),
+        };
+        let responses: Vec<_> = rows
+            .into_iter()
+            .map(|row| ReadRowsResponse {
+                rows: Some(Rows::ArrowRecordBatch(ArrowRecordBatch {
+                    serialized_record_batch: ipc_batch(&select(
+                        &table.slice(row, 1),
+                        &options.selected_fields,
+                    )),
+                })),
+                row_count: 1,
+            })
+            .collect();
+        Ok(Response::new(Box::pin(tokio_stream::iter(responses.into_iter().map(Ok)))))
+    }
+}
+
+async fn serve() -> (Mock, BigQueryClient) {
+    let mock = Mock::default();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let server = tonic::transport::Server::builder()
+        .add_service(BigQueryReadServer::new(mock.clone()))
+        .serve_with_incoming(TcpListenerStream::new(listener));
+    tokio::spawn(async move { server.await.unwrap() });
+    let client =
+        BigQueryClient::connect_to(&endpoint, "billing", Credentials::Token("secret".into()))
+            .unwrap();
+    (mock, client)
+}
+
+async fn query(client: BigQueryClient, sql: &str) -> String {
+    let ctx = SessionContext::new_with_config(SessionConfig::new().with_target_partitions(4));
+    let table = BigQueryTable::try_new(client, "shop.sales.orders").await.unwrap();
+    ctx.register_table("orders", Arc::new(table)).unwrap();
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_scans_read_the_projected_columns_of_matching_rows() {
+    let (mock, client) = serve().await;
+    let sql = "SELECT total, id FROM orders \
+               WHERE (total = 20.0 OR customer IS NOT NULL) AND id >= 2 AND total * 2 > 1 \
+               ORDER BY id";
+    assert_eq!(
+        query(client, sql).await,
+        "+-------+----+\n\
+         | total | id |\n\
+         +-------+----+\n\
+         | 20.0  | 2  |\n\
+         | 3.25  | 3  |\n\
+         +-------+----+"
+    );
+    let sessions = mock.sessions.lock().unwrap();
+    // The table's schema is read first, without selecting fields.
+    assert_eq!(sessions[0], TableReadOptions::default());
+    assert_eq!(sessions[1].selected_fields, ["id", "total"]);
+    assert_eq!(
+        sessions[1].row_restriction,
+        "((`total` = 20.0) OR (`customer` IS NOT NULL)) AND (`id` >= 2)"
+    );
+}
+
+#[tokio::test]
+async fn test_counts_read_a_column() {
+    let (mock, client) = serve().await;
+    assert_eq!(
+        query(client, "SELECT count(*) AS n FROM orders").await,
+        "+---+\n\
+         | n |\n\
+         +---+\n\
+         | 3 |\n\
+         +---+"
+    );
+    assert_eq!(mock.sessions.lock().unwrap()[1].selected_fields, ["id"]);
+}
+
+#[tokio::test]
+async fn test_table_names_have_three_parts() {
+    let (_, client) = serve().await;
+    let error = BigQueryTable::try_new(client, "sales.orders").await.unwrap_err();
+    assert!(error.to_string().contains("project.dataset.table, not sales.orders"), "{error}");
+}
@@ -0,0 +1,212 @@
+//! Unity Catalog catalogs and Delta Sharing shares as DataFusion catalogs.
+//!
+//! Both list their schemas and table names when created, and look tables up each time
+//! a query names them, so queries see the table's current version and columns. Schemas
+//! and tables created since are picked up by loading the catalog again through its
+//! [`CatalogSource`].
+
+use crate::sharing::{SharedTableRef, SharingClient};
+use crate::table::{DeltaTable, SharedTable};
+use crate::unity::UnityCatalog;
+use async_trait::async_trait;
+use datafusion::catalog::{CatalogProvider, SchemaProvider};
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use igloo_common::catalog::CatalogSource;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// A catalog of a Unity Catalog server. Only its Delta tables can be read.
+#[derive(Debug)]
+pub struct UnityCatalogProvider {
+    client: Arc<UnityCatalog>,
+    catalog: String,
+    schemas: BTreeMap<String, Arc<UnitySchemaProvider>>,
+}
+
+impl UnityCatalogProvider {
+    /// The source of the schemas of `catalog`, listing none until loaded through its
+    /// [`CatalogSource`]. Does not reach the server.
+    pub fn new(client: Arc<UnityCatalog>, catalog: impl Into<String>) -> Self {
+        Self { client, catalog: catalog.into(), schemas: BTreeMap::new() }
+    }
+
+    /// List the schemas of `catalog` and their tables.
+    pub async fn try_new(
+        client: Arc<UnityCatalog>,
+        catalog: impl Into<String>,
+    ) -> DataFusionResult<Self> {
+        let catalog = catalog.into();
+        let mut schemas = BTreeMap::new();
+        for schema in client.list_schemas(&catalog).await? {
+            let tables = client.list_tables(&catalog, &schema).await?;
+            let provider = UnitySchemaProvider {
+                client: Arc::clone(&client),
+                prefix: format!("{catalog}.{schema}"),
+                tables: tables.into_iter().map(|table| table.name).collect(),
+            };
+            schemas.insert(schema, Arc::new(provider));
+        }
+        Ok(Self { client, catalog, schemas })
+    }
+}
+
+#[async_trait]
+impl CatalogSource for UnityCatalogProvider {
+    async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+        Ok(Arc::new(Self::try_new(Arc::clone(&self.client), &self.catalog).await?))
+    }
+}
+
+impl CatalogProvider for UnityCatalogProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.schemas.keys().cloned().collect()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        self.schemas.get(name).map(|schema| Arc::clone(schema) as Arc<dyn SchemaProvider>)
+    }
+}
+
+/// The tables of a Unity Catalog schema.
+#[derive(Debug)]
+pub struct UnitySchemaProvider {
+    client: Arc<UnityCatalog>,
+    /// `catalog.schema`.
+    prefix: String,
+    /// Names listed when the catalog was created.
+    tables: Vec<String>,
+}
+
+#[async_trait]
+impl SchemaProvider for UnitySchemaProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.clone()
+    }
+
+    async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
+        let Some(info) = self.client.get_table(&format!("{}.{name}", self.prefix)).await? else {
+            return Ok(None);
+        };
+        let format = info.data_source_format.as_deref().unwrap_or_default();
+        if !format.eq_ignore_ascii_case("DELTA") {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Unity Catalog table {} is stored as {format}; only Delta tables can be read",
+                info.full_name()
+            )));
+        }
+        let Some(location) = &info.storage_location else {
+            return Err(DataFusionError::Plan(format!(
+                "Unity Catalog table {} has no storage location",
+                info.full_name()
+            )));
+        };
+        let schema = Arc::new(info.schema()?);
+        Ok(Some(Arc::new(DeltaTable::try_new(location, schema, &info.partition_columns())?)))
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.tables.iter().any(|table| table == name)
+    }
+}
+
+/// A share of a Delta Sharing server, its schemas as schemas. Its tables are read-only.
+#[derive(Debug)]
+pub struct ShareCatalogProvider {
+    client: Arc<SharingClient>,
+    share: String,
+    schemas: BTreeMap<String, Arc<SharedSchemaProvider>>,
+}
+
+impl ShareCatalogProvider {
+    /// The source of the schemas of `share`, listing none until loaded through its
+    /// [`CatalogSource`]. Does not reach the server.
+    pub fn new(client: Arc<SharingClient>, share: impl Into<String>) -> Self {
+        Self { client, share: share.into(), schemas: BTreeMap::new() }
+    }
+
+    /// List the schemas of `share` and their tables.
+    pub async fn try_new(
+        client: Arc<SharingClient>,
+        share: impl Into<String>,
+    ) -> DataFusionResult<Self> {
+        let share = share.into();
+        let mut schemas = BTreeMap::new();
+        for schema in client.list_schemas(&share).await? {
+            let tables = client.list_tables(&share, &schema).await?;
+            let provider = SharedSchemaProvider {
+                client: Arc::clone(&client),
+                share: share.clone(),
+                schema: schema.clone(),
+                tables,
+            };
+            schemas.insert(schema, Arc::new(provider));
+        }
+        Ok(Self { client, share, schemas })
+    }
+}
+
+#[async_trait]
+impl CatalogSource for ShareCatalogProvider {
+    async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+        Ok(Arc::new(Self::try_new(Arc::clone(&self.client), &self.share).await?))
+    }
+}
+
+impl CatalogProvider for ShareCatalogProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.schemas.keys().cloned().collect()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        self.schemas.get(name).map(|schema| Arc::clone(schema) as Arc<dyn SchemaProvider>)
+    }
+}
+
+/// The tables of a schema of a share.
+#[derive(Debug)]
+pub struct SharedSchemaProvider {
+    client: Arc<SharingClient>,
+    share: String,
+    schema: String,
+    /// Names listed when the catalog was created.
+    tables: Vec<String>,
+}
+
+#[async_trait]
+impl SchemaProvider for SharedSchemaProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.clone()
+    }
+
+    async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
+        let table = SharedTableRef {
+            share: self.share.clone(),
+            schema: self.schema.clone(),
+            name: name.to_string(),
+        };
+        let table = SharedTable::try_new(Arc::clone(&self.client), table).await?;
+        Ok(table.map(|table| Arc::new(table) as Arc<dyn TableProvider>))
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.tables.iter().any(|table| table == name)
+    }
+}
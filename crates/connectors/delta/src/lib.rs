@@ -0,0 +1,32 @@
+//! Delta Lake tables, through Unity Catalog or Delta Sharing.
+//!
+//! [`UnityCatalog`] talks to a Unity Catalog server, so Delta tables are found by name
+//! and read from their storage location; [`SharingClient`] talks to a Delta Sharing
+//! server, which hands out the files of the tables shared with a recipient. Both are
+//! exposed to SQL as catalogs, and their tables are read-only:
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_delta::{ShareCatalogProvider, SharingClient, SharingProfile};
+//! use std::sync::Arc;
+//!
+//! let profile = SharingProfile::from_file("config.share")?;
+//! let client = Arc::new(SharingClient::from_profile(&profile));
+//! let provider = ShareCatalogProvider::try_new(client, "sales_share").await?;
+//! ctx.register_catalog("shared", Arc::new(provider));
+//! ctx.sql("SELECT count(*) FROM shared.sales.orders").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod catalog;
+pub mod log;
+pub mod schema;
+pub mod sharing;
+pub mod table;
+pub mod unity;
+
+pub use catalog::{ShareCatalogProvider, UnityCatalogProvider};
+pub use sharing::{SharedTableRef, SharingClient, SharingProfile};
+pub use table::{DeltaTable, SharedTable};
+pub use unity::UnityCatalog;
@@ -0,0 +1,161 @@
+//! The Delta transaction log.
+//!
+//! A table's state is the replay of the JSON commits under its `_delta_log/`
+//! directory: the latest `metaData` and `protocol`, and the data files `add`ed and not
+//! since `remove`d. Commits are replayed from the first; tables whose early commits
+//! have been cleaned up after a checkpoint are refused, as are tables needing reader
+//! features this reader lacks (deletion vectors, column mapping).
+
+use crate::schema::StructType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// Reader features that need nothing of this reader.
+const SUPPORTED_READER_FEATURES: &[&str] = &["timestampNtz", "vacuumProtocolCheck"];
+
+/// One line of a commit: one action, `None` for the others. `commitInfo`, `txn`,
+/// `cdc` and other actions are nothing a reader needs.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Action {
+    protocol: Option<Protocol>,
+    meta_data: Option<Metadata>,
+    add: Option<Add>,
+    remove: Option<Remove>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Protocol {
+    pub min_reader_version: u32,
+    #[serde(default)]
+    pub reader_features: Option<Vec<String>>,
+}
+
+impl Protocol {
+    /// Fail unless this reader can read tables of this protocol.
+    pub fn check(&self, metadata: Option<&Metadata>) -> DataFusionResult<()> {
+        let unsupported = |what: &str| {
+            DataFusionError::NotImplemented(format!("Delta tables with {what} are not supported"))
+        };
+        if self.min_reader_version > 3 {
+            return Err(unsupported(&format!("reader version {}", self.min_reader_version)));
+        }
+        for feature in self.reader_features.iter().flatten() {
+            if !SUPPORTED_READER_FEATURES.contains(&feature.as_str()) {
+                return Err(unsupported(&format!("the {feature} reader feature")));
+            }
+        }
+        let mapping = metadata.and_then(|m| m.configuration.get("delta.columnMapping.mode"));
+        match mapping.and_then(Option::as_deref) {
+            None | Some("none") => Ok(()),
+            Some(_) => Err(unsupported("column mapping")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub schema_string: String,
+    #[serde(default)]
+    pub partition_columns: Vec<String>,
+    #[serde(default)]
+    pub configuration: HashMap<String, Option<String>>,
+}
+
+impl Metadata {
+    pub fn schema(&self) -> DataFusionResult<StructType> {
+        StructType::parse(&self.schema_string)
+    }
+}
+
+/// A data file added to the table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Add {
+    /// Relative to the table's location and URL-encoded, or an absolute URI.
+    pub path: String,
+    #[serde(default)]
+    pub partition_values: HashMap<String, Option<String>>,
+    pub size: u64,
+    #[serde(default)]
+    pub deletion_vector: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Remove {
+    pub path: String,
+}
+
+/// A table's state at its latest version.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub version: u64,
+    pub protocol: Protocol,
+    pub metadata: Metadata,
+    /// Live data files, in path order.
+    pub files: Vec<Add>,
+}
+
+impl Snapshot {
+    /// Replay the log of the table at `root` in `store`.
+    pub async fn load(store: &dyn ObjectStore, root: &Path) -> DataFusionResult<Self> {
+        let log = root.child("_delta_log");
+        let mut commits = BTreeMap::new();
+        let mut listing = store.list(Some(&log));
+        while let Some(object) = listing.try_next().await? {
+            let name = object.location.filename().unwrap_or_default();
+            if let Some(version) = name.strip_suffix(".json").and_then(|v| v.parse::<u64>().ok()) {
+                commits.insert(version, object.location);
+            }
+        }
+        let Some(&version) = commits.keys().next_back() else {
+            return Err(DataFusionError::Plan(format!("no Delta table at {root}")));
+        };
+        if commits.len() as u64 != version + 1 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "the Delta log of {root} no longer starts at its first commit"
+            )));
+        }
+        let (mut protocol, mut metadata) = (None, None);
+        let mut files = BTreeMap::new();
+        for location in commits.values() {
+            let bytes = store.get(location).await?.bytes().await?;
+            for line in bytes.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+                let action: Action =
+                    serde_json::from_slice(line).map_err(|e| log_error(location, e))?;
+                protocol = action.protocol.or(protocol);
+                metadata = action.meta_data.or(metadata);
+                if let Some(add) = action.add {
+                    files.insert(add.path.clone(), add);
+                }
+                if let Some(remove) = action.remove {
+                    files.remove(&remove.path);
+                }
+            }
+        }
+        let (Some(protocol), Some(metadata)) = (protocol, metadata) else {
+            return Err(DataFusionError::Execution(format!(
+                "the Delta log of {root} has no protocol or metadata"
+            )));
+        };
+        protocol.check(Some(&metadata))?;
+        if files.values().any(|add| add.deletion_vector.is_some()) {
+            return Err(DataFusionError::NotImplemented(
+                "Delta tables with deletion vectors are not supported".to_string(),
+            ));
+        }
+        Ok(Self { version, protocol, metadata, files: files.into_values().collect() })
+    }
+}
+
+fn log_error(location: &Path, e: serde_json::Error) -> DataFusionError {
+    DataFusionError::Execution(format!("invalid Delta commit {location}: {e}"))
+}
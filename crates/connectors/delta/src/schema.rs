@@ -0,0 +1,158 @@
+//! Delta table schemas, as found in the `schemaString` of a table's metadata and in
+//! the `type_json` of Unity Catalog columns: Spark's JSON encoding of a struct type.
+
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Schema, TimeUnit};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// A struct type: the schema of a table, or of a nested struct column.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StructType {
+    pub fields: Vec<StructField>,
+}
+
+impl StructType {
+    /// Parse a `schemaString`.
+    pub fn parse(json: &str) -> DataFusionResult<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| DataFusionError::Execution(format!("invalid Delta schema: {e}")))
+    }
+
+    pub fn to_arrow(&self) -> DataFusionResult<Schema> {
+        Ok(Schema::new(self.arrow_fields()?))
+    }
+
+    fn arrow_fields(&self) -> DataFusionResult<Fields> {
+        self.fields.iter().map(StructField::to_arrow).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct StructField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub data_type: DeltaType,
+    #[serde(default = "nullable")]
+    pub nullable: bool,
+}
+
+fn nullable() -> bool {
+    true
+}
+
+impl StructField {
+    pub fn to_arrow(&self) -> DataFusionResult<Field> {
+        Ok(Field::new(&self.name, self.data_type.to_arrow()?, self.nullable))
+    }
+}
+
+/// A Delta type: a primitive (`"long"`, `"decimal(10,2)"`, ...) or a nested one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum DeltaType {
+    Primitive(String),
+    Nested(Box<NestedType>),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NestedType {
+    Struct(StructType),
+    #[serde(rename_all = "camelCase")]
+    Array {
+        element_type: DeltaType,
+        contains_null: bool,
+    },
+    #[serde(rename_all = "camelCase")]
+    Map {
+        key_type: DeltaType,
+        value_type: DeltaType,
+        value_contains_null: bool,
+    },
+}
+
+impl DeltaType {
+    pub fn to_arrow(&self) -> DataFusionResult<DataType> {
+        match self {
+            DeltaType::Primitive(name) => primitive(name),
+            DeltaType::Nested(nested) => match nested.as_ref() {
+                NestedType::Struct(fields) => Ok(DataType::Struct(fields.arrow_fields()?)),
+                NestedType::Array { element_type, contains_null } => {
+                    let element = Field::new("element", element_type.to_arrow()?, *contains_null);
+                    Ok(DataType::List(Arc::new(element)))
+                }
+                NestedType::Map { key_type, value_type, value_contains_null } => {
+                    let entries = Fields::from(vec![
+                        Field::new("key", key_type.to_arrow()?, false),
+                        Field::new("value", value_type.to_arrow()?, *value_contains_null),
+                    ]);
+                    let entries = Field::new("key_value", DataType::Struct(entries), false);
+                    Ok(DataType::Map(Arc::new(entries), false))
+                }
+            },
+        }
+    }
+}
+
+fn primitive(name: &str) -> DataFusionResult<DataType> {
+    Ok(match name {
+        "boolean" => DataType::Boolean,
+        "byte" => DataType::Int8,
+        "short" => DataType::Int16,
+        "integer" => DataType::Int32,
+        "long" => DataType::Int64,
+        "float" => DataType::Float32,
+        "double" => DataType::Float64,
+        "string" => DataType::Utf8,
+        "binary" => DataType::Binary,
+        "date" => DataType::Date32,
+        "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        "timestamp_ntz" => DataType::Timestamp(TimeUnit::Microsecond, None),
+        _ => match name.strip_prefix("decimal(").and_then(|rest| rest.strip_suffix(')')) {
+            Some(args) => decimal(args).ok_or_else(|| unsupported(name))?,
+            None => return Err(unsupported(name)),
+        },
+    })
+}
+
+fn decimal(args: &str) -> Option<DataType> {
+    let (precision, scale) = args.split_once(',')?;
+    Some(DataType::Decimal128(precision.trim().parse().ok()?, scale.trim().parse().ok()?))
+}
+
+fn unsupported(name: &str) -> DataFusionError {
+    DataFusionError::NotImplemented(format!("Delta type {name} is not supported"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_to_arrow() {
+        let schema = StructType::parse(
+            r#"{"type": "struct", "fields": [
+                {"name": "id", "type": "long", "nullable": false, "metadata": {}},
+                {"name": "price", "type": "decimal(10, 2)", "nullable": true, "metadata": {}},
+                {"name": "tags", "type": {"type": "array", "elementType": "string",
+                    "containsNull": true}, "nullable": true, "metadata": {}},
+                {"name": "address", "type": {"type": "struct", "fields": [
+                    {"name": "city", "type": "string", "nullable": true, "metadata": {}}
+                ]}, "nullable": true, "metadata": {}}
+            ]}"#,
+        )
+        .unwrap();
+        let schema = schema.to_arrow().unwrap();
+        assert_eq!(schema.field(0), &Field::new("id", DataType::Int64, false));
+        assert_eq!(schema.field(1).data_type(), &DataType::Decimal128(10, 2));
+        let element = Field::new("element", DataType::Utf8, true);
+        assert_eq!(schema.field(2).data_type(), &DataType::List(Arc::new(element)));
+        let city = Fields::from(vec![Field::new("city", DataType::Utf8, true)]);
+        assert_eq!(schema.field(3).data_type(), &DataType::Struct(city));
+        assert!(StructType::parse(r#"{"fields": [{"name": "v", "type": "variant"}]}"#)
+            .unwrap()
+            .to_arrow()
+            .is_err());
+    }
+}
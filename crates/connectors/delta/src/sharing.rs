@@ -0,0 +1,318 @@
+//! Client of the Delta Sharing protocol.
+//!
+//! A sharing server (Databricks, or the open-source reference server) exposes shares,
+//! each holding schemas of tables. Recipients get a profile file naming the server's
+//! endpoint and a bearer token, which [`SharingProfile`] reads. Querying a table
+//! returns its metadata and short-lived pre-signed URLs of its Parquet files, which are
+//! fetched without the token.
+
+use crate::log::{Metadata, Protocol};
+use bytes::Bytes;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+/// Header of the table version in metadata and query responses.
+const VERSION_HEADER: &str = "delta-table-version";
+
+/// The credentials of a recipient, as found in a `.share` profile file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharingProfile {
+    pub share_credentials_version: u32,
+    pub endpoint: String,
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    #[serde(default)]
+    pub expiration_time: Option<String>,
+}
+
+impl SharingProfile {
+    pub fn from_json(json: &str) -> DataFusionResult<Self> {
+        let profile: Self = serde_json::from_str(json)
+            .map_err(|e| DataFusionError::Plan(format!("invalid Delta Sharing profile: {e}")))?;
+        if profile.share_credentials_version != 1 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Delta Sharing profiles of version {} are not supported",
+                profile.share_credentials_version
+            )));
+        }
+        Ok(profile)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> DataFusionResult<Self> {
+        Self::from_json(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A table of a share.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SharedTableRef {
+    pub share: String,
+    pub schema: String,
+    pub name: String,
+}
+
+impl fmt::Display for SharedTableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.share, self.schema, self.name)
+    }
+}
+
+/// A shared table's metadata at its current version.
+#[derive(Debug, Clone)]
+pub struct SharedTableMetadata {
+    pub version: Option<u64>,
+    pub protocol: Protocol,
+    pub metadata: Metadata,
+}
+
+/// A data file of a queried table.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedFile {
+    /// Pre-signed, valid for a limited time.
+    pub url: String,
+    pub id: String,
+    #[serde(default)]
+    pub partition_values: HashMap<String, Option<String>>,
+    pub size: u64,
+}
+
+/// One line of a metadata or query response: one of these, `None` for the others.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Line {
+    protocol: Option<Protocol>,
+    meta_data: Option<Metadata>,
+    file: Option<SharedFile>,
+}
+
+/// Client of a Delta Sharing server.
+pub struct SharingClient {
+    endpoint: String,
+    token: Option<String>,
+    client: Client,
+}
+
+impl fmt::Debug for SharingClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharingClient").field("endpoint", &self.endpoint).finish_non_exhaustive()
+    }
+}
+
+impl SharingClient {
+    /// A client of the server at `endpoint` (e.g. `https://sharing.example.com/delta-sharing`).
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            token: None,
+            client: Client::new(),
+        }
+    }
+
+    /// A client of the server of `profile`, with its token.
+    pub fn from_profile(profile: &SharingProfile) -> Self {
+        let client = Self::new(&profile.endpoint);
+        match &profile.bearer_token {
+            Some(token) => client.with_token(token),
+            None => client,
+        }
+    }
+
+    /// Authenticate with a bearer token.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// The shares the recipient can read.
+    pub async fn list_shares(&self) -> DataFusionResult<Vec<String>> {
+        self.list("shares").await
+    }
+
+    pub async fn list_schemas(&self, share: &str) -> DataFusionResult<Vec<String>> {
+        self.list(&format!("shares/{}/schemas", encode_component(share))).await
+    }
+
+    pub async fn list_tables(&self, share: &str, schema: &str) -> DataFusionResult<Vec<String>> {
+        let (share, schema) = (encode_component(share), encode_component(schema));
+        self.list(&format!("shares/{share}/schemas/{schema}/tables")).await
+    }
+
+    /// The table's current metadata, `None` if there is no such table.
+    pub async fn table_metadata(
+        &self,
+        table: &SharedTableRef,
+    ) -> DataFusionResult<Option<SharedTableMetadata>> {
+        let request = self.client.get(self.url(&format!("{}/metadata", table_path(table))));
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let (metadata, files) = parse_lines(response).await?;
+        if !files.is_empty() {
+            return Err(protocol_error("files in a metadata response"));
+        }
+        Ok(Some(metadata))
+    }
+
+    /// The table's current metadata and data files. The server may return fewer
+    /// files when given a `limit` of rows, or a `predicate` (a JSON predicate hint of
+    /// the protocol) the rows of the others do not match.
+    pub async fn query_table(
+        &self,
+        table: &SharedTableRef,
+        predicate: Option<&serde_json::Value>,
+        limit: Option<usize>,
+    ) -> DataFusionResult<(SharedTableMetadata, Vec<SharedFile>)> {
+        let mut body = serde_json::Map::new();
+        if let Some(predicate) = predicate {
+            body.insert("jsonPredicateHints".to_string(), predicate.to_string().into());
+        }
+        if let Some(limit) = limit {
+            body.insert("limitHint".to_string(), limit.into());
+        }
+        let request = self.client.post(self.url(&format!("{}/query", table_path(table))));
+        let response = self.send(request.json(&body)).await?;
+        parse_lines(response).await
+    }
+
+    /// The contents of a shared file.
+    pub async fn fetch(&self, file: &SharedFile) -> DataFusionResult<Bytes> {
+        // Pre-signed URLs are authorized by their signature, not the token.
+        let response = self.client.get(&file.url).send().await.map_err(http_error)?;
+        if !response.status().is_success() {
+            return Err(DataFusionError::Execution(format!(
+                "fetching shared file {} returned {}",
+                file.id,
+                response.status()
+            )));
+        }
+        response.bytes().await.map_err(http_error)
+    }
+
+    /// The names of the items of every page of `path`.
+    async fn list(&self, path: &str) -> DataFusionResult<Vec<String>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Page {
+            #[serde(default)]
+            items: Vec<Item>,
+            #[serde(default)]
+            next_page_token: Option<String>,
+        }
+        #[derive(Deserialize)]
+        struct Item {
+            name: String,
+        }
+        let mut names = Vec::new();
+        let mut token = None;
+        loop {
+            let mut request = self.client.get(self.url(path));
+            if let Some(token) = &token {
+                request = request.query(&[("pageToken", token)]);
+            }
+            let page: Page = parse(self.send(request).await?).await?;
+            names.extend(page.items.into_iter().map(|item| item.name));
+            match page.next_page_token {
+                Some(next) if !next.is_empty() => token = Some(next),
+                _ => return Ok(names),
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{path}", self.endpoint)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> DataFusionResult<Response> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        request.send().await.map_err(http_error)
+    }
+}
+
+fn table_path(table: &SharedTableRef) -> String {
+    let [share, schema, name] =
+        [&table.share, &table.schema, &table.name].map(|c| encode_component(c));
+    format!("shares/{share}/schemas/{schema}/tables/{name}")
+}
+
+/// The metadata and files of a newline-delimited JSON response.
+async fn parse_lines(
+    response: Response,
+) -> DataFusionResult<(SharedTableMetadata, Vec<SharedFile>)> {
+    let status = response.status();
+    let version = response.headers().get(VERSION_HEADER);
+    let version = version.and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok());
+    let body = response.text().await.map_err(http_error)?;
+    if !status.is_success() {
+        return Err(server_error(status, &body));
+    }
+    let (mut protocol, mut metadata, mut files) = (None, None, vec![]);
+    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+        let line: Line = serde_json::from_str(line)
+            .map_err(|e| protocol_error(&format!("an invalid line ({e})")))?;
+        protocol = line.protocol.or(protocol);
+        metadata = line.meta_data.or(metadata);
+        files.extend(line.file);
+    }
+    let (Some(protocol), Some(metadata)) = (protocol, metadata) else {
+        return Err(protocol_error("no protocol or metadata"));
+    };
+    Ok((SharedTableMetadata { version, protocol, metadata }, files))
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> DataFusionResult<T> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(server_error(status, &response.text().await.unwrap_or_default()));
+    }
+    response.json().await.map_err(http_error)
+}
+
+pub(crate) fn encode_component(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+pub(crate) fn http_error(e: reqwest::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+fn protocol_error(what: &str) -> DataFusionError {
+    DataFusionError::Execution(format!("Delta Sharing server sent {what}"))
+}
+
+/// The server's error response (`{"errorCode", "message"}`) as an error.
+fn server_error(status: StatusCode, body: &str) -> DataFusionError {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ErrorResponse {
+        error_code: String,
+        message: String,
+    }
+    let message = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => format!("{}: {}", response.error_code, response.message),
+        Err(_) => body.to_string(),
+    };
+    let message = format!("Delta Sharing server returned {status}: {message}");
+    match status {
+        StatusCode::NOT_FOUND => DataFusionError::Plan(message),
+        _ => DataFusionError::Execution(message),
+    }
+}
@@ -0,0 +1,381 @@
+//! Reading Delta tables, from storage or through a share.
+//!
+//! A [`DeltaTable`] is read at its latest version each time it is scanned, by replaying
+//! its log (see [`log`](crate::log)) from the object store the session has registered
+//! for its location (local files need none). A [`SharedTable`] asks the sharing server
+//! for its files each time it is scanned and fetches them whole over their pre-signed
+//! URLs, no more of them than a `LIMIT` needs. The filters comparing its columns to
+//! literals are sent along as predicate hints, for the server to leave out the files
+//! none of whose rows match; they are still applied to the rows read.
+//!
+//! Delta does not store partition columns in the data files; their values come from
+//! the log or the share, per file.
+
+use crate::log::Snapshot;
+use crate::sharing::{SharedFile, SharedTableRef, SharingClient};
+use async_trait::async_trait;
+use datafusion::arrow::array::{new_null_array, ArrayRef, RecordBatch};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::catalog::Session;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::memory::MemorySourceConfig;
+use datafusion::datasource::physical_plan::{FileGroup, FileScanConfigBuilder, ParquetSource};
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::ExecutionPlan;
+use futures::{StreamExt, TryStreamExt};
+use object_store::path::Path;
+use serde_json::{json, Value};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Shared files fetched at once.
+const FETCH_CONCURRENCY: usize = 8;
+
+/// A table's columns split into those read from its files and its partition columns,
+/// which DataFusion appends after the others.
+#[derive(Debug)]
+struct Layout {
+    schema: SchemaRef,
+    file_schema: SchemaRef,
+    partition_fields: Vec<Field>,
+    /// The position of each of the table's columns among the file columns followed by
+    /// the partition columns.
+    positions: Vec<usize>,
+}
+
+impl Layout {
+    fn try_new(schema: SchemaRef, partition_columns: &[String]) -> DataFusionResult<Self> {
+        let mut partition_fields = Vec::with_capacity(partition_columns.len());
+        for name in partition_columns {
+            partition_fields.push(schema.field_with_name(name)?.clone());
+        }
+        let is_partition = |field: &Field| partition_columns.contains(field.name());
+        let file_fields: Vec<Field> = schema
+            .fields()
+            .iter()
+            .filter(|f| !is_partition(f))
+            .map(|f| f.as_ref().clone())
+            .collect();
+        let positions = schema
+            .fields()
+            .iter()
+            .map(|field| match partition_columns.iter().position(|c| c == field.name()) {
+                Some(i) => file_fields.len() + i,
+                None => file_fields.iter().position(|f| f.name() == field.name()).unwrap(),
+            })
+            .collect();
+        let file_schema = Arc::new(Schema::new(file_fields));
+        Ok(Self { schema, file_schema, partition_fields, positions })
+    }
+
+    /// The positions, among the file columns followed by the partition columns, of the
+    /// table columns of `projection`.
+    fn projection(&self, projection: Option<&Vec<usize>>) -> Vec<usize> {
+        match projection {
+            Some(projection) => projection.iter().map(|i| self.positions[*i]).collect(),
+            None => self.positions.clone(),
+        }
+    }
+
+    fn projected_schema(&self, projection: Option<&Vec<usize>>) -> DataFusionResult<SchemaRef> {
+        match projection {
+            Some(projection) => Ok(Arc::new(self.schema.project(projection)?)),
+            None => Ok(Arc::clone(&self.schema)),
+        }
+    }
+
+    /// A file's values of the partition columns, parsed from their string form.
+    fn partition_values(
+        &self,
+        values: &HashMap<String, Option<String>>,
+    ) -> DataFusionResult<Vec<ScalarValue>> {
+        self.partition_fields
+            .iter()
+            .map(|field| match values.get(field.name()).cloned().flatten() {
+                Some(value) => ScalarValue::try_from_string(value, field.data_type()),
+                None => ScalarValue::try_from(field.data_type()),
+            })
+            .collect()
+    }
+}
+
+/// A Delta table in storage.
+#[derive(Debug)]
+pub struct DeltaTable {
+    location: String,
+    layout: Layout,
+}
+
+impl DeltaTable {
+    /// The table at `location` (a URL), whose columns are `schema`.
+    pub fn try_new(
+        location: impl Into<String>,
+        schema: SchemaRef,
+        partition_columns: &[String],
+    ) -> DataFusionResult<Self> {
+        Ok(Self { location: location.into(), layout: Layout::try_new(schema, partition_columns)? })
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+#[async_trait]
+impl TableProvider for DeltaTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.layout.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let location = ListingTableUrl::parse(&self.location)?;
+        let store_url = location.object_store();
+        let store = state.runtime_env().object_store(&store_url)?;
+        let snapshot = Snapshot::load(store.as_ref(), location.prefix()).await?;
+        if snapshot.files.is_empty() {
+            return Ok(Arc::new(EmptyExec::new(self.layout.projected_schema(projection)?)));
+        }
+        let partitions = state.config().target_partitions().clamp(1, snapshot.files.len());
+        let mut groups = vec![Vec::new(); partitions];
+        for (i, add) in snapshot.files.iter().enumerate() {
+            let path = if add.path.contains("://") {
+                // Absolute, and expected in the same store.
+                ListingTableUrl::parse(&add.path)?.prefix().clone()
+            } else {
+                Path::from_url_path(format!("{}/{}", location.prefix(), add.path))?
+            };
+            let mut file = PartitionedFile::new(path.to_string(), add.size);
+            file.partition_values = self.layout.partition_values(&add.partition_values)?;
+            groups[i % partitions].push(file);
+        }
+        let config = FileScanConfigBuilder::new(
+            store_url,
+            Arc::clone(&self.layout.file_schema),
+            Arc::new(ParquetSource::default()),
+        )
+        .with_table_partition_cols(self.layout.partition_fields.clone())
+        .with_file_groups(groups.into_iter().map(FileGroup::new).collect())
+        .with_projection(Some(self.layout.projection(projection)))
+        .with_limit(limit)
+        .build();
+        Ok(DataSourceExec::from_data_source(config))
+    }
+}
+
+/// A table of a Delta Sharing share.
+#[derive(Debug)]
+pub struct SharedTable {
+    client: Arc<SharingClient>,
+    table: SharedTableRef,
+    layout: Layout,
+}
+
+impl SharedTable {
+    /// The shared `table`, whose columns and partition columns are read from the
+    /// server.
+    pub async fn try_new(
+        client: Arc<SharingClient>,
+        table: SharedTableRef,
+    ) -> DataFusionResult<Option<Self>> {
+        let Some(metadata) = client.table_metadata(&table).await? else {
+            return Ok(None);
+        };
+        metadata.protocol.check(Some(&metadata.metadata))?;
+        let schema = Arc::new(metadata.metadata.schema()?.to_arrow()?);
+        let layout = Layout::try_new(schema, &metadata.metadata.partition_columns)?;
+        Ok(Some(Self { client, table, layout }))
+    }
+
+    /// The rows of `file`, with the table's columns.
+    async fn read(&self, file: SharedFile) -> DataFusionResult<Vec<RecordBatch>> {
+        let bytes = self.client.fetch(&file).await?;
+        let partition_values = self.layout.partition_values(&file.partition_values)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()?;
+        let mut batches = vec![];
+        for batch in reader {
+            let batch = batch?;
+            let mut columns = Vec::with_capacity(self.layout.schema.fields().len());
+            for field in self.layout.schema.fields() {
+                let column: ArrayRef =
+                    match self.layout.partition_fields.iter().position(|f| f == field.as_ref()) {
+                        Some(i) => partition_values[i].to_array_of_size(batch.num_rows())?,
+                        // Columns added since the file was written are null in it.
+                        None => match batch.column_by_name(field.name()) {
+                            Some(column) => cast(column, field.data_type())?,
+                            None => new_null_array(field.data_type(), batch.num_rows()),
+                        },
+                    };
+                columns.push(column);
+            }
+            batches.push(RecordBatch::try_new(Arc::clone(&self.layout.schema), columns)?);
+        }
+        Ok(batches)
+    }
+}
+
+#[async_trait]
+impl TableProvider for SharedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.layout.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match predicate_hint(filter, &self.layout.schema) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let hints: Vec<_> = filters
+            .iter()
+            .filter_map(|filter| predicate_hint(filter, &self.layout.schema))
+            .collect();
+        let predicate = match <[Value; 1]>::try_from(hints) {
+            Ok([hint]) => Some(hint),
+            Err(hints) if hints.is_empty() => None,
+            Err(hints) => Some(json!({"op": "and", "children": hints})),
+        };
+        let (metadata, files) =
+            self.client.query_table(&self.table, predicate.as_ref(), limit).await?;
+        metadata.protocol.check(Some(&metadata.metadata))?;
+        if metadata.metadata.schema()?.to_arrow()?.fields() != self.layout.schema.fields() {
+            return Err(DataFusionError::Plan(format!(
+                "the schema of shared table {} has changed since it was loaded",
+                self.table
+            )));
+        }
+        let mut fetches = futures::stream::iter(files.into_iter().map(|file| self.read(file)))
+            .buffered(FETCH_CONCURRENCY);
+        let (mut batches, mut rows) = (vec![], 0);
+        while let Some(file_batches) = fetches.try_next().await? {
+            rows += file_batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+            batches.extend(file_batches);
+            if limit.is_some_and(|limit| rows >= limit) {
+                break;
+            }
+        }
+        let projection = projection.cloned();
+        let exec = MemorySourceConfig::try_new_exec(&[batches], self.schema(), projection)?;
+        Ok(exec)
+    }
+}
+
+/// The JSON predicate hint of the Delta Sharing protocol of `filter`, if it compares
+/// columns of `schema` to literals.
+fn predicate_hint(filter: &Expr, schema: &Schema) -> Option<Value> {
+    hint(filter, schema, true)
+}
+
+/// The hint of `filter`, matching the rows it does and, if `weaker`, maybe others:
+/// conjuncts without one are left out then, which they cannot be under a `NOT`.
+fn hint(filter: &Expr, schema: &Schema, weaker: bool) -> Option<Value> {
+    let op = |op: &str, children: Vec<Value>| json!({"op": op, "children": children});
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op: Operator::And, right }) => {
+            match (hint(left, schema, weaker), hint(right, schema, weaker)) {
+                (Some(left), Some(right)) => Some(op("and", vec![left, right])),
+                (left, right) if weaker => left.or(right),
+                _ => None,
+            }
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op: Operator::Or, right }) => {
+            Some(op("or", vec![hint(left, schema, weaker)?, hint(right, schema, weaker)?]))
+        }
+        Expr::BinaryExpr(BinaryExpr { left, op: operator, right }) => {
+            let (column, operator, literal) = match (left.as_ref(), right.as_ref()) {
+                (Expr::Column(column), Expr::Literal(literal, _)) => (column, *operator, literal),
+                (Expr::Literal(literal, _), Expr::Column(column)) => {
+                    (column, operator.swap()?, literal)
+                }
+                _ => return None,
+            };
+            let field = schema.field_with_name(&column.name).ok()?;
+            let value_type = value_type(field.data_type())?;
+            let literal = literal.cast_to(field.data_type()).ok().filter(|l| !l.is_null())?;
+            let children = vec![
+                json!({"op": "column", "name": column.name, "valueType": value_type}),
+                json!({"op": "literal", "value": literal.to_string(), "valueType": value_type}),
+            ];
+            Some(match operator {
+                Operator::Eq => op("equal", children),
+                Operator::NotEq => op("not", vec![op("equal", children)]),
+                Operator::Lt => op("lessThan", children),
+                Operator::LtEq => op("lessThanOrEqual", children),
+                Operator::Gt => op("greaterThan", children),
+                Operator::GtEq => op("greaterThanOrEqual", children),
+                _ => return None,
+            })
+        }
+        Expr::IsNull(expr) | Expr::IsNotNull(expr) => {
+            let Expr::Column(column) = expr.as_ref() else {
+                return None;
+            };
+            let value_type = value_type(schema.field_with_name(&column.name).ok()?.data_type())?;
+            let column = json!({"op": "column", "name": column.name, "valueType": value_type});
+            let is_null = op("isNull", vec![column]);
+            Some(match filter {
+                Expr::IsNull(_) => is_null,
+                _ => op("not", vec![is_null]),
+            })
+        }
+        Expr::Not(expr) => Some(op("not", vec![hint(expr, schema, false)?])),
+        _ => None,
+    }
+}
+
+/// The protocol's name of the type of columns of `data_type` hints can compare.
+fn value_type(data_type: &DataType) -> Option<&'static str> {
+    match data_type {
+        DataType::Boolean => Some("bool"),
+        DataType::Int32 => Some("int"),
+        DataType::Int64 => Some("long"),
+        DataType::Utf8 => Some("string"),
+        DataType::Date32 => Some("date"),
+        DataType::Float32 => Some("float"),
+        DataType::Float64 => Some("double"),
+        _ => None,
+    }
+}
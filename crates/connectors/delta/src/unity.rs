@@ -0,0 +1,220 @@
+//! Client of the Unity Catalog REST API.
+//!
+//! Unity Catalog (the open-source server, or Databricks' `api/2.1/unity-catalog`)
+//! organizes tables in catalogs of schemas and records, for each table, its columns,
+//! format and storage location. Requests carry a bearer token if one is configured.
+
+use crate::schema::{StructField, StructType};
+use crate::sharing::{encode_component, http_error};
+use datafusion::arrow::datatypes::Schema;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::fmt;
+
+/// Path of the API under a server's URI.
+const API_PATH: &str = "api/2.1/unity-catalog";
+
+/// A table as Unity Catalog describes it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TableInfo {
+    pub name: String,
+    pub catalog_name: String,
+    pub schema_name: String,
+    /// `MANAGED` or `EXTERNAL`.
+    #[serde(default)]
+    pub table_type: Option<String>,
+    /// `DELTA`, `PARQUET`, `CSV`, ...
+    #[serde(default)]
+    pub data_source_format: Option<String>,
+    #[serde(default)]
+    pub columns: Vec<ColumnInfo>,
+    #[serde(default)]
+    pub storage_location: Option<String>,
+}
+
+impl TableInfo {
+    /// `catalog.schema.table`.
+    pub fn full_name(&self) -> String {
+        format!("{}.{}.{}", self.catalog_name, self.schema_name, self.name)
+    }
+
+    /// The table's columns in order, as an Arrow schema.
+    pub fn schema(&self) -> DataFusionResult<Schema> {
+        let mut columns = self.columns.clone();
+        columns.sort_by_key(|column| column.position);
+        let fields = columns.iter().map(ColumnInfo::field).collect::<DataFusionResult<_>>()?;
+        StructType { fields }.to_arrow()
+    }
+
+    /// The names of the table's partition columns, in partition order.
+    pub fn partition_columns(&self) -> Vec<String> {
+        let mut columns: Vec<_> =
+            self.columns.iter().filter(|c| c.partition_index.is_some()).collect();
+        columns.sort_by_key(|column| column.partition_index);
+        columns.into_iter().map(|column| column.name.clone()).collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColumnInfo {
+    pub name: String,
+    /// The column as a field of a Delta schema.
+    #[serde(default)]
+    pub type_json: Option<String>,
+    #[serde(default)]
+    pub type_text: Option<String>,
+    #[serde(default)]
+    pub position: Option<u32>,
+    #[serde(default)]
+    pub partition_index: Option<u32>,
+}
+
+impl ColumnInfo {
+    fn field(&self) -> DataFusionResult<StructField> {
+        let Some(json) = &self.type_json else {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Unity Catalog column {} has no type_json",
+                self.name
+            )));
+        };
+        serde_json::from_str(json).map_err(|e| {
+            DataFusionError::Execution(format!("invalid type of column {}: {e}", self.name))
+        })
+    }
+}
+
+/// Client of a Unity Catalog server.
+pub struct UnityCatalog {
+    uri: String,
+    token: Option<String>,
+    client: Client,
+}
+
+impl fmt::Debug for UnityCatalog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UnityCatalog").field("uri", &self.uri).finish_non_exhaustive()
+    }
+}
+
+impl UnityCatalog {
+    /// A client of the server at `uri` (e.g. `http://localhost:8080`).
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            uri: uri.into().trim_end_matches('/').to_string(),
+            token: None,
+            client: Client::new(),
+        }
+    }
+
+    /// Authenticate with a bearer token (a Databricks personal access token, ...).
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    pub async fn list_catalogs(&self) -> DataFusionResult<Vec<String>> {
+        #[derive(Deserialize)]
+        struct Catalog {
+            name: String,
+        }
+        let catalogs: Vec<Catalog> = self.list("catalogs", "catalogs", &[]).await?;
+        Ok(catalogs.into_iter().map(|catalog| catalog.name).collect())
+    }
+
+    pub async fn list_schemas(&self, catalog: &str) -> DataFusionResult<Vec<String>> {
+        #[derive(Deserialize)]
+        struct SchemaInfo {
+            name: String,
+        }
+        let query = [("catalog_name", catalog.to_string())];
+        let schemas: Vec<SchemaInfo> = self.list("schemas", "schemas", &query).await?;
+        Ok(schemas.into_iter().map(|schema| schema.name).collect())
+    }
+
+    pub async fn list_tables(
+        &self,
+        catalog: &str,
+        schema: &str,
+    ) -> DataFusionResult<Vec<TableInfo>> {
+        let query = [("catalog_name", catalog.to_string()), ("schema_name", schema.to_string())];
+        self.list("tables", "tables", &query).await
+    }
+
+    /// The table `catalog.schema.table`, `None` if there is no such table.
+    pub async fn get_table(&self, full_name: &str) -> DataFusionResult<Option<TableInfo>> {
+        let request = self.client.get(self.url(&format!("tables/{}", encode_component(full_name))));
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        parse(response).await.map(Some)
+    }
+
+    /// The `key` items of every page of `path`.
+    async fn list<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        key: &str,
+        query: &[(&str, String)],
+    ) -> DataFusionResult<Vec<T>> {
+        let mut items = Vec::new();
+        let mut query = query.to_vec();
+        loop {
+            let request = self.client.get(self.url(path)).query(&query);
+            let mut page: serde_json::Map<String, serde_json::Value> =
+                parse(self.send(request).await?).await?;
+            if let Some(page_items) = page.remove(key).filter(|items| !items.is_null()) {
+                let page_items: Vec<T> = serde_json::from_value(page_items)
+                    .map_err(|e| DataFusionError::Execution(format!("invalid {key}: {e}")))?;
+                items.extend(page_items);
+            }
+            match page.remove("next_page_token") {
+                Some(serde_json::Value::String(token)) if !token.is_empty() => {
+                    query.retain(|(key, _)| *key != "page_token");
+                    query.push(("page_token", token));
+                }
+                _ => return Ok(items),
+            }
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{API_PATH}/{path}", self.uri)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> DataFusionResult<Response> {
+        let request = match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        request.send().await.map_err(http_error)
+    }
+}
+
+async fn parse<T: DeserializeOwned>(response: Response) -> DataFusionResult<T> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(catalog_error(status, &response.text().await.unwrap_or_default()));
+    }
+    response.json().await.map_err(http_error)
+}
+
+/// The server's error response (`{"error_code", "message"}`) as an error.
+fn catalog_error(status: StatusCode, body: &str) -> DataFusionError {
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        error_code: String,
+        message: String,
+    }
+    let message = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => format!("{}: {}", response.error_code, response.message),
+        Err(_) => body.to_string(),
+    };
+    let message = format!("Unity Catalog returned {status}: {message}");
+    match status {
+        StatusCode::NOT_FOUND => DataFusionError::Plan(message),
+        _ => DataFusionError::Execution(message),
+    }
+}
@@ -0,0 +1,377 @@
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use datafusion::arrow::array::{Float64Array, Int64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::prelude::SessionContext;
+use igloo_connector_delta::{
+    ShareCatalogProvider, SharingClient, SharingProfile, UnityCatalog, UnityCatalogProvider,
+};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+const TOKEN: &str = "secret-token";
+
+const SCHEMA_STRING: &str = r#"{"type": "struct", "fields": [
+    {"name": "id", "type": "long", "nullable": false, "metadata": {}},
+    {"name": "amount", "type": "double", "nullable": true, "metadata": {}},
+    {"name": "region", "type": "string", "nullable": true, "metadata": {}}
+]}"#;
+
+fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = format!("Bearer {TOKEN}");
+    match headers.get("authorization") {
+        Some(value) if value == expected.as_str() => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Write a Parquet file of orders (without their partition column) and return its size.
+fn write_parquet(path: &std::path::Path, ids: Vec<i64>, amounts: Vec<f64>) -> u64 {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("amount", DataType::Float64, true),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![Arc::new(Int64Array::from(ids)), Arc::new(Float64Array::from(amounts))],
+    )
+    .unwrap();
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut writer =
+        ArrowWriter::try_new(std::fs::File::create(path).unwrap(), schema, None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    std::fs::metadata(path).unwrap().len()
+}
+
+/// Write a Delta table of orders partitioned by region over two commits, the second
+/// replacing one of the files of the first.
+fn write_table(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("igloo-delta-{name}-{}", std::process::id()));
+    let eu = write_parquet(&dir.join("region=eu/part-0.parquet"), vec![1, 2], vec![10.0, 20.0]);
+    let us = write_parquet(&dir.join("region=us/part-1.parquet"), vec![3], vec![30.0]);
+    let us2 = write_parquet(&dir.join("region=us/part-2.parquet"), vec![3, 4], vec![35.0, 40.0]);
+    let add = |path: &str, region: &str, size| {
+        json!({"add": {"path": path, "partitionValues": {"region": region}, "size": size,
+            "modificationTime": 0, "dataChange": true}})
+    };
+    let commits = [
+        vec![
+            json!({"commitInfo": {"operation": "CREATE TABLE"}}),
+            json!({"protocol": {"minReaderVersion": 1, "minWriterVersion": 2}}),
+            json!({"metaData": {"id": "orders", "format": {"provider": "parquet"},
+                "schemaString": SCHEMA_STRING, "partitionColumns": ["region"],
+                "configuration": {}}}),
+            add("region=eu/part-0.parquet", "eu", eu),
+            add("region=us/part-1.parquet", "us", us),
+        ],
+        vec![
+            json!({"remove": {"path": "region=us/part-1.parquet", "dataChange": true}}),
+            add("region=us/part-2.parquet", "us", us2),
+        ],
+    ];
+    std::fs::create_dir_all(dir.join("_delta_log")).unwrap();
+    for (version, actions) in commits.iter().enumerate() {
+        let lines: Vec<String> = actions.iter().map(Value::to_string).collect();
+        let path = dir.join(format!("_delta_log/{version:020}.json"));
+        std::fs::write(path, lines.join("\n")).unwrap();
+    }
+    dir
+}
+
+async fn serve(app: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+async fn query(ctx: &SessionContext, sql: &str) -> String {
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+/// A Unity Catalog server with one catalog, `main`, holding `sales.orders` (a Delta
+/// table) and `sales.events` (CSV files).
+async fn unity_tables(
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    authorized(&headers)?;
+    assert_eq!((query["catalog_name"].as_str(), query["schema_name"].as_str()), ("main", "sales"));
+    // Two pages.
+    Ok(Json(match query.get("page_token") {
+        None => json!({"tables": [{"name": "orders", "catalog_name": "main",
+            "schema_name": "sales"}], "next_page_token": "2"}),
+        Some(_) => json!({"tables": [{"name": "events", "catalog_name": "main",
+            "schema_name": "sales"}], "next_page_token": null}),
+    }))
+}
+
+async fn unity_table(
+    State(dir): State<PathBuf>,
+    headers: HeaderMap,
+    Path(full_name): Path<String>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    authorized(&headers).map_err(|status| (status, Json(json!({}))))?;
+    let column = |name: &str, type_name: &str, position, partition_index: Option<u32>| {
+        let type_json = json!({"name": name, "type": type_name, "nullable": true, "metadata": {}});
+        json!({"name": name, "type_text": type_name, "type_json": type_json.to_string(),
+            "position": position, "partition_index": partition_index})
+    };
+    let format = match full_name.as_str() {
+        "main.sales.orders" => "DELTA",
+        "main.sales.events" => "CSV",
+        _ => {
+            let message = format!("Table not found: {full_name}");
+            let error = json!({"error_code": "TABLE_DOES_NOT_EXIST", "message": message});
+            return Err((StatusCode::NOT_FOUND, Json(error)));
+        }
+    };
+    let (catalog, rest) = full_name.split_once('.').unwrap();
+    let (schema, name) = rest.split_once('.').unwrap();
+    Ok(Json(json!({
+        "name": name,
+        "catalog_name": catalog,
+        "schema_name": schema,
+        "table_type": "EXTERNAL",
+        "data_source_format": format,
+        "storage_location": format!("file://{}", dir.display()),
+        "columns": [
+            column("region", "string", 2, Some(0)),
+            column("id", "long", 0, None),
+            column("amount", "double", 1, None),
+        ]
+    })))
+}
+
+#[tokio::test]
+async fn test_unity_catalog_delta_tables_are_read_from_storage() {
+    let dir = write_table("unity");
+    let app = Router::new()
+        .route(
+            "/api/2.1/unity-catalog/schemas",
+            get(|headers: HeaderMap| async move {
+                authorized(&headers)?;
+                Ok::<_, StatusCode>(Json(json!({"schemas": [{"name": "sales"}]})))
+            }),
+        )
+        .route("/api/2.1/unity-catalog/tables", get(unity_tables))
+        .route("/api/2.1/unity-catalog/tables/:full_name", get(unity_table))
+        .with_state(dir.clone());
+    let uri = serve(app).await;
+    let client = Arc::new(UnityCatalog::new(uri.clone()).with_token(TOKEN));
+
+    let tables = client.list_tables("main", "sales").await.unwrap();
+    let names: Vec<_> = tables.iter().map(|table| table.name.as_str()).collect();
+    assert_eq!(names, ["orders", "events"]);
+
+    let ctx = SessionContext::new();
+    let provider = UnityCatalogProvider::try_new(client, "main").await.unwrap();
+    ctx.register_catalog("main", Arc::new(provider));
+    let expected = "\
++----+--------+--------+
+| id | amount | region |
++----+--------+--------+
+| 1  | 10.0   | eu     |
+| 2  | 20.0   | eu     |
+| 3  | 35.0   | us     |
+| 4  | 40.0   | us     |
++----+--------+--------+";
+    assert_eq!(query(&ctx, "SELECT * FROM main.sales.orders ORDER BY id").await, expected);
+    let expected = "\
++--------+-------+
+| region | total |
++--------+-------+
+| us     | 75.0  |
++--------+-------+";
+    let sql = "SELECT region, sum(amount) AS total FROM main.sales.orders \
+        WHERE region = 'us' GROUP BY region";
+    assert_eq!(query(&ctx, sql).await, expected);
+
+    let error = ctx.sql("SELECT * FROM main.sales.events").await.unwrap_err();
+    assert!(error.to_string().contains("only Delta tables"), "{error}");
+    let insert = ctx.sql("INSERT INTO main.sales.orders VALUES (5, 1.0, 'eu')").await.unwrap();
+    let error = insert.collect().await.unwrap_err();
+    assert!(error.to_string().contains("Insert into not implemented"), "{error}");
+
+    let error = UnityCatalog::new(uri).list_schemas("main").await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{error}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+/// A sharing server sharing `orders` of the table in `dir` as `retail.sales.orders`,
+/// and serving its files at `/files/`.
+#[derive(Clone)]
+struct Share {
+    dir: PathBuf,
+    uri: String,
+    /// The predicate hints of the queries.
+    hints: Arc<Mutex<Vec<Value>>>,
+}
+
+fn ndjson(lines: Vec<Value>) -> Response {
+    let body = lines.iter().map(Value::to_string).collect::<Vec<_>>().join("\n");
+    let headers = [
+        (header::CONTENT_TYPE, "application/x-ndjson"),
+        (header::HeaderName::from_static("delta-table-version"), "1"),
+    ];
+    (headers, body).into_response()
+}
+
+fn table_lines() -> Vec<Value> {
+    vec![
+        json!({"protocol": {"minReaderVersion": 1}}),
+        json!({"metaData": {"id": "orders", "format": {"provider": "parquet"},
+            "schemaString": SCHEMA_STRING, "partitionColumns": ["region"]}}),
+    ]
+}
+
+async fn shared_table(
+    headers: HeaderMap,
+    Path((share, schema, table)): Path<(String, String, String)>,
+) -> Result<Response, StatusCode> {
+    authorized(&headers)?;
+    if (share.as_str(), schema.as_str(), table.as_str()) != ("retail", "sales", "orders") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    Ok(ndjson(table_lines()))
+}
+
+async fn query_shared_table(
+    State(share): State<Share>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Response, StatusCode> {
+    authorized(&headers)?;
+    let file = |name: &str, region: &str| {
+        let size = std::fs::metadata(share.dir.join(format!("region={region}/{name}"))).unwrap();
+        json!({"file": {"url": format!("{}/files/{region}/{name}", share.uri), "id": name,
+            "partitionValues": {"region": region}, "size": size.len()}})
+    };
+    let hint = body.get("jsonPredicateHints").and_then(Value::as_str);
+    let hint: Value = hint.map_or(Value::Null, |hint| serde_json::from_str(hint).unwrap());
+    // Hints naming `eu` skip the other region.
+    let eu_only = hint.to_string().contains(r#""value":"eu""#);
+    share.hints.lock().unwrap().push(hint);
+    let mut lines = table_lines();
+    lines.push(file("part-0.parquet", "eu"));
+    // A limit of a row or two is met by the first file.
+    let limit = body.get("limitHint").and_then(Value::as_u64);
+    if limit.map_or(true, |limit| limit > 2) && !eu_only {
+        lines.push(file("part-2.parquet", "us"));
+    }
+    Ok(ndjson(lines))
+}
+
+async fn shared_file(
+    State(share): State<Share>,
+    headers: HeaderMap,
+    Path((region, name)): Path<(String, String)>,
+) -> Result<Bytes, StatusCode> {
+    // Pre-signed: no token.
+    assert!(headers.get("authorization").is_none());
+    let path = share.dir.join(format!("region={region}/{name}"));
+    std::fs::read(path).map(Bytes::from).map_err(|_| StatusCode::NOT_FOUND)
+}
+
+#[tokio::test]
+async fn test_shared_tables_are_queried_through_the_sharing_server() {
+    let dir = write_table("sharing");
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uri = format!("http://{}", listener.local_addr().unwrap());
+    let share = Share { dir: dir.clone(), uri: uri.clone(), hints: Arc::default() };
+    let hints = Arc::clone(&share.hints);
+    let list = |items: Value| {
+        move |headers: HeaderMap| async move {
+            authorized(&headers)?;
+            Ok::<_, StatusCode>(Json(json!({"items": items})))
+        }
+    };
+    let app = Router::new()
+        .route("/delta-sharing/shares", get(list(json!([{"name": "retail"}]))))
+        .route("/delta-sharing/shares/retail/schemas", get(list(json!([{"name": "sales"}]))))
+        .route(
+            "/delta-sharing/shares/retail/schemas/sales/tables",
+            get(list(json!([{"name": "orders", "schema": "sales", "share": "retail"}]))),
+        )
+        .route(
+            "/delta-sharing/shares/:share/schemas/:schema/tables/:table/metadata",
+            get(shared_table),
+        )
+        .route(
+            "/delta-sharing/shares/retail/schemas/sales/tables/orders/query",
+            post(query_shared_table),
+        )
+        .route("/files/:region/:name", get(shared_file))
+        .with_state(share);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let profile = SharingProfile::from_json(
+        &json!({"shareCredentialsVersion": 1, "endpoint": format!("{uri}/delta-sharing/"),
+            "bearerToken": TOKEN})
+        .to_string(),
+    )
+    .unwrap();
+    let client = Arc::new(SharingClient::from_profile(&profile));
+    assert_eq!(client.list_shares().await.unwrap(), ["retail"]);
+
+    let ctx = SessionContext::new();
+    let provider = ShareCatalogProvider::try_new(client, "retail").await.unwrap();
+    ctx.register_catalog("shared", Arc::new(provider));
+    let expected = "\
++--------+--------+-------+
+| region | orders | total |
++--------+--------+-------+
+| eu     | 2      | 30.0  |
+| us     | 2      | 75.0  |
++--------+--------+-------+";
+    let sql = "SELECT region, count(*) AS orders, sum(amount) AS total \
+        FROM shared.sales.orders GROUP BY region ORDER BY region";
+    assert_eq!(query(&ctx, sql).await, expected);
+    let expected = "\
++----+--------+
+| id | region |
++----+--------+
+| 1  | eu     |
++----+--------+";
+    assert_eq!(query(&ctx, "SELECT id, region FROM shared.sales.orders LIMIT 1").await, expected);
+    assert_eq!(*hints.lock().unwrap(), [Value::Null, Value::Null]);
+
+    let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 2  | 20.0   |
++----+--------+";
+    let sql = "SELECT id, amount FROM shared.sales.orders \
+        WHERE region = 'eu' AND (amount > 15 OR amount IS NULL) AND id * 2 > 0";
+    assert_eq!(query(&ctx, sql).await, expected);
+    let column = |name, value_type| json!({"op": "column", "name": name, "valueType": value_type});
+    let literal =
+        |value, value_type| json!({"op": "literal", "value": value, "valueType": value_type});
+    let predicate = json!({"op": "and", "children": [
+        {"op": "equal", "children": [column("region", "string"), literal("eu", "string")]},
+        {"op": "or", "children": [
+            {"op": "greaterThan", "children": [column("amount", "double"), literal("15", "double")]},
+            {"op": "isNull", "children": [column("amount", "double")]},
+        ]},
+    ]});
+    assert_eq!(hints.lock().unwrap()[2], predicate);
+
+    let error = ctx.sql("SELECT * FROM shared.sales.missing").await.unwrap_err();
+    assert!(error.to_string().contains("not found"), "{error}");
+    assert!(
+        SharingProfile::from_json(r#"{"shareCredentialsVersion": 2, "endpoint": "x"}"#).is_err()
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
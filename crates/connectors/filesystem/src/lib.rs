@@ -32,12 +32,14 @@ impl CsvTable {
 
 impl TableProvider for CsvTable {
     fn scan(&self) -> Result<Box<dyn Iterator<Item = Row>>> {
-        let file = File::open(&self.path).map_err(|e| Error::Unknown(e.to_string()))?; // Map std::io::Error
+        let file = File::open(&self.path)
+            .map_err(|e| Error::external(format!("Failed to open {}", self.path), e))?;
         let mut rdr = ReaderBuilder::new().has_headers(self.has_header).from_reader(file);
 
         let mut rows: Vec<Row> = Vec::new();
         for result in rdr.records() {
-            let record = result.map_err(|e| Error::Unknown(e.to_string()))?; // Map csv::Error
+            let record =
+                result.map_err(|e| Error::external(format!("Failed to read {}", self.path), e))?;
             let row: Row = record.iter().map(|field| field.to_string()).collect();
             rows.push(row);
         }
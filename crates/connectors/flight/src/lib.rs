@@ -0,0 +1,23 @@
+//! Remote Arrow Flight servers.
+//!
+//! [`FlightTable`] reads a table or query of a Flight SQL server, or a flight of a
+//! plain Flight server (see [`table`]). As igloo serves both, an igloo can federate
+//! others, such as edge instances exposing the data of their region to a central one:
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_flight::{client, FlightSource, FlightTable};
+//! use std::sync::Arc;
+//!
+//! let mut eu = client("http://igloo-eu:50051")?;
+//! eu.set_token("secret".to_string());
+//! let orders = FlightTable::try_new(eu, FlightSource::Table("sales.orders".to_string())).await?;
+//! ctx.register_table("orders_eu", Arc::new(orders))?;
+//! ctx.sql("SELECT count(*) FROM orders_eu WHERE total > 100").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod table;
+
+pub use table::{client, FlightSource, FlightTable};
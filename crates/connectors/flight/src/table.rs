@@ -0,0 +1,343 @@
+//! Reading flights of remote Arrow Flight servers.
+//!
+//! A [`FlightTable`] reads a [`FlightSource`] of a server, which may be another igloo:
+//!
+//! - A table, or the result of a query, of a Flight SQL server. Each scan runs a
+//!   `SELECT` of the projected columns, with the filters on columns and literals in
+//!   its `WHERE` clause. As the server may evaluate them differently, those filters
+//!   are applied again by the engine; a limit is pushed down when there are none.
+//! - A flight of a plain Flight server, by its descriptor, such as the path of a
+//!   table of an igloo. Each scan reads the whole flight.
+//!
+//! The endpoints of a flight are the partitions of its scan, read in parallel from
+//! their first location, or from the server itself if they have none.
+
+use arrow_flight::sql::client::FlightSqlServiceClient;
+use arrow_flight::{FlightDescriptor, FlightEndpoint, FlightInfo};
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::catalog::Session;
+use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion};
+use datafusion::common::Column;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::{Expr, TableProviderFilterPushDown};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::sql::unparser::Unparser;
+use futures::TryStreamExt;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tonic::transport::{Channel, ClientTlsConfig};
+use tonic::Request;
+
+/// The location of endpoints read from the server their flight is of.
+const REUSE_CONNECTION: &str = "arrow-flight-reuse-connection:";
+
+/// A client of the server at `url`, such as `http://edge-eu:50051`, which connects
+/// once first used. Authenticate with its `handshake` or `set_token`, and set the
+/// headers of its requests, such as an igloo session's, with `set_header`.
+pub fn client(url: &str) -> DataFusionResult<FlightSqlServiceClient<Channel>> {
+    // Flight locations are `grpc+tcp://` and `grpc+tls://` URIs.
+    let endpoint = match (url.strip_prefix("grpc+tcp://"), url.strip_prefix("grpc+tls://")) {
+        (Some(address), _) => format!("http://{address}"),
+        (_, Some(address)) => format!("https://{address}"),
+        _ => url.to_string(),
+    };
+    let mut channel = Channel::from_shared(endpoint.clone()).map_err(|e| {
+        DataFusionError::Configuration(format!("invalid Flight server URL {url}: {e}"))
+    })?;
+    if endpoint.starts_with("https://") {
+        channel = channel
+            .tls_config(ClientTlsConfig::new().with_webpki_roots())
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    }
+    Ok(FlightSqlServiceClient::new(channel.connect_lazy()))
+}
+
+/// What a [`FlightTable`] reads.
+#[derive(Debug, Clone)]
+pub enum FlightSource {
+    /// A table of a Flight SQL server, as the server names it, such as `sales.orders`.
+    Table(String),
+    /// The result of a query of a Flight SQL server.
+    Query(String),
+    /// A flight of a Flight server. It is requested with the client's token, but
+    /// without its other headers.
+    Descriptor(FlightDescriptor),
+}
+
+/// A table or flight of a remote server, see the [module docs](self).
+pub struct FlightTable {
+    client: FlightSqlServiceClient<Channel>,
+    source: FlightSource,
+    schema: SchemaRef,
+}
+
+impl fmt::Debug for FlightTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlightTable").field("source", &self.source).finish_non_exhaustive()
+    }
+}
+
+impl FlightTable {
+    /// The `source` of the server of `client`, with the schema the server reports.
+    pub async fn try_new(
+        client: FlightSqlServiceClient<Channel>,
+        source: FlightSource,
+    ) -> DataFusionResult<Self> {
+        let info = match relation(&source) {
+            // Servers may run the query to tell its schema.
+            Some(relation) => {
+                let sql = format!("SELECT * FROM {relation} LIMIT 0");
+                client.clone().execute(sql, None).await?
+            }
+            None => flight_info(&client, &source).await?,
+        };
+        let schema = Arc::new(info.try_decode_schema()?);
+        Ok(Self { client, source, schema })
+    }
+}
+
+/// The relation of `source` in the `FROM` clause of a `SELECT`, if it is read with SQL.
+fn relation(source: &FlightSource) -> Option<String> {
+    match source {
+        FlightSource::Table(table) => Some(table.clone()),
+        FlightSource::Query(query) => Some(format!("({query}) AS remote")),
+        FlightSource::Descriptor(_) => None,
+    }
+}
+
+/// The flight of the descriptor of `source`.
+async fn flight_info(
+    client: &FlightSqlServiceClient<Channel>,
+    source: &FlightSource,
+) -> DataFusionResult<FlightInfo> {
+    let FlightSource::Descriptor(descriptor) = source else {
+        return Err(DataFusionError::Internal("Flight source has no descriptor".to_string()));
+    };
+    let mut request = Request::new(descriptor.clone());
+    if let Some(token) = client.token() {
+        let value = format!("Bearer {token}").parse().map_err(|_| {
+            DataFusionError::Configuration("Flight token is not a valid header".to_string())
+        })?;
+        request.metadata_mut().insert("authorization", value);
+    }
+    let info = client.inner().clone().get_flight_info(request).await.map_err(grpc_error)?;
+    Ok(info.into_inner())
+}
+
+#[async_trait]
+impl TableProvider for FlightTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        let sql = relation(&self.source).is_some();
+        Ok(filters
+            .iter()
+            .map(|filter| match predicate(filter) {
+                Some(_) if sql => TableProviderFilterPushDown::Inexact,
+                _ => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let indices: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&indices)?);
+        let (info, columns): (_, Arc<[usize]>) = match relation(&self.source) {
+            Some(relation) => {
+                let names: Vec<_> =
+                    schema.fields().iter().map(|field| quote_ident(field.name())).collect();
+                // `SELECT count(*)` projects no column.
+                let select = if names.is_empty() { "1".to_string() } else { names.join(", ") };
+                let mut sql = format!("SELECT {select} FROM {relation}");
+                let predicates: Vec<_> = filters.iter().filter_map(predicate).collect();
+                if !predicates.is_empty() {
+                    sql += &format!(" WHERE {}", predicates.join(" AND "));
+                }
+                if let Some(limit) = limit.filter(|_| filters.is_empty()) {
+                    sql += &format!(" LIMIT {limit}");
+                }
+                let info = self.client.clone().execute(sql, None).await?;
+                (info, (0..names.len()).collect())
+            }
+            None => (flight_info(&self.client, &self.source).await?, indices.into()),
+        };
+        if info.endpoint.is_empty() {
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+        let partitions = info
+            .endpoint
+            .into_iter()
+            .map(|endpoint| {
+                Arc::new(Read {
+                    client: self.client.clone(),
+                    endpoint,
+                    schema: Arc::clone(&schema),
+                    columns: Arc::clone(&columns),
+                }) as Arc<dyn PartitionStream>
+            })
+            .collect();
+        Ok(Arc::new(StreamingTableExec::try_new(schema, partitions, None, None, false, limit)?))
+    }
+}
+
+/// `filter` in SQL, if it only compares columns and literals.
+fn predicate(filter: &Expr) -> Option<String> {
+    let mut portable = true;
+    filter
+        .apply(|expr| {
+            portable &= matches!(
+                expr,
+                Expr::Column(_)
+                    | Expr::Literal(..)
+                    | Expr::BinaryExpr(_)
+                    | Expr::Not(_)
+                    | Expr::IsNull(_)
+                    | Expr::IsNotNull(_)
+                    | Expr::Between(_)
+                    | Expr::InList(_)
+                    | Expr::Like(_)
+            );
+            Ok(TreeNodeRecursion::Continue)
+        })
+        .ok()?;
+    if !portable {
+        return None;
+    }
+    // Columns are named as by the server, not qualified by the local table.
+    let filter = filter
+        .clone()
+        .transform(|expr| match expr {
+            Expr::Column(column) => {
+                Ok(Transformed::yes(Expr::Column(Column::new_unqualified(column.name))))
+            }
+            expr => Ok(Transformed::no(expr)),
+        })
+        .ok()?
+        .data;
+    Unparser::default().expr_to_sql(&filter).ok().map(|sql| sql.to_string())
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The read of an endpoint of a flight.
+#[derive(Debug, Clone)]
+struct Read {
+    client: FlightSqlServiceClient<Channel>,
+    endpoint: FlightEndpoint,
+    schema: SchemaRef,
+    /// The index of each projected column among those of the flight.
+    columns: Arc<[usize]>,
+}
+
+type Sender = mpsc::Sender<DataFusionResult<RecordBatch>>;
+
+impl Read {
+    /// Send the batches read into `sender` until it is closed.
+    async fn run(&self, sender: &Sender) -> DataFusionResult<()> {
+        let mut client = match self.endpoint.location.first() {
+            Some(location) if !location.uri.starts_with(REUSE_CONNECTION) => {
+                let mut remote = client(&location.uri)?;
+                if let Some(token) = self.client.token() {
+                    remote.set_token(token.clone());
+                }
+                remote
+            }
+            _ => self.client.clone(),
+        };
+        let ticket = self.endpoint.ticket.clone().ok_or_else(|| {
+            DataFusionError::Execution("Flight endpoint has no ticket".to_string())
+        })?;
+        let mut batches = client.do_get(ticket).await?;
+        while let Some(batch) = batches.try_next().await.map_err(flight_error)? {
+            let arrays = self.columns.iter().map(|&i| Arc::clone(batch.column(i))).collect();
+            // Batches of scans projecting no column (`count(*)`) have rows all the same.
+            let options = RecordBatchOptions::new().with_row_count(Some(batch.num_rows()));
+            let batch =
+                RecordBatch::try_new_with_options(Arc::clone(&self.schema), arrays, &options);
+            if sender.send(batch.map_err(Into::into)).await.is_err() {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PartitionStream for Read {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let read = self.clone();
+        let (sender, receiver) = mpsc::channel(2);
+        tokio::spawn(async move {
+            if let Err(e) = read.run(&sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+fn grpc_error(e: tonic::Status) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+fn flight_error(e: arrow_flight::error::FlightError) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::prelude::{col, lit};
+
+    #[test]
+    fn test_filters_become_sql() {
+        let filter =
+            col("orders.total").gt(lit(10)).and(col("region").in_list(vec![lit("eu")], false));
+        assert_eq!(predicate(&filter).unwrap(), "((total > 10) AND region IN ('eu'))");
+        assert_eq!(predicate(&col("name").like(lit("a%"))).unwrap(), "\"name\" LIKE 'a%'");
+        // Functions may not exist remotely.
+        assert_eq!(
+            predicate(&datafusion::functions::expr_fn::upper(col("name")).eq(lit("A"))),
+            None
+        );
+    }
+}
@@ -0,0 +1,128 @@
+use arrow_flight::flight_service_server::FlightServiceServer;
+use arrow_flight::FlightDescriptor;
+use datafusion::arrow::array::{Float64Array, Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::catalog::MemTable;
+use datafusion::prelude::SessionContext;
+use igloo_api::flight_sql::IglooFlightSqlService;
+use igloo_api::IglooFlightService;
+use igloo_common::catalog::MemoryCatalog;
+use igloo_connector_flight::{client, FlightSource, FlightTable};
+use igloo_engine::QueryEngine;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+
+/// An igloo of the region's `orders`.
+fn edge() -> Arc<QueryEngine> {
+    let engine = Arc::new(QueryEngine::new());
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("region", DataType::Utf8, false),
+        Field::new("total", DataType::Float64, true),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3, 4])),
+            Arc::new(StringArray::from(vec!["eu", "eu", "us", "eu"])),
+            Arc::new(Float64Array::from(vec![Some(120.0), Some(15.5), Some(300.0), None])),
+        ],
+    )
+    .unwrap();
+    let table = MemTable::try_new(schema, vec![vec![batch]]).unwrap();
+    engine.register_table("orders", Arc::new(table)).unwrap();
+    engine
+}
+
+/// The URL of an edge igloo serving Flight SQL, or plain Flight.
+async fn serve(sql: bool) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let incoming = TcpListenerStream::new(listener);
+    let engine = edge();
+    match sql {
+        true => tokio::spawn(
+            Server::builder()
+                .add_service(FlightServiceServer::new(IglooFlightSqlService::new(engine)))
+                .serve_with_incoming(incoming),
+        ),
+        false => tokio::spawn(
+            Server::builder()
+                .add_service(FlightServiceServer::new(IglooFlightService::new(
+                    engine,
+                    Arc::new(MemoryCatalog::new()),
+                )))
+                .serve_with_incoming(incoming),
+        ),
+    };
+    url
+}
+
+async fn query(source: FlightSource, url: &str, sql: &str) -> String {
+    let table = FlightTable::try_new(client(url).unwrap(), source).await.unwrap();
+    let ctx = SessionContext::new();
+    ctx.register_table("remote", Arc::new(table)).unwrap();
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_tables_of_flight_sql_servers() {
+    let url = serve(true).await;
+    let source = FlightSource::Table("orders".to_string());
+    let sql = "SELECT id, total FROM remote WHERE region = 'eu' AND total > 100 ORDER BY id";
+    assert_eq!(
+        query(source.clone(), &url, sql).await,
+        "+----+-------+\n\
+         | id | total |\n\
+         +----+-------+\n\
+         | 1  | 120.0 |\n\
+         +----+-------+"
+    );
+    assert_eq!(
+        query(source, &url, "SELECT count(*) AS n FROM remote").await,
+        "+---+\n\
+         | n |\n\
+         +---+\n\
+         | 4 |\n\
+         +---+"
+    );
+}
+
+#[tokio::test]
+async fn test_queries_of_flight_sql_servers() {
+    let url = serve(true).await;
+    let source = FlightSource::Query(
+        "SELECT region, sum(total) AS total FROM orders GROUP BY region".to_string(),
+    );
+    let sql = "SELECT * FROM remote WHERE region <> 'us' LIMIT 5";
+    assert_eq!(
+        query(source, &url, sql).await,
+        "+--------+-------+\n\
+         | region | total |\n\
+         +--------+-------+\n\
+         | eu     | 135.5 |\n\
+         +--------+-------+"
+    );
+}
+
+#[tokio::test]
+async fn test_flights_of_flight_servers() {
+    let url = serve(false).await;
+    let path = ["datafusion", "public", "orders"].map(String::from);
+    let source = FlightSource::Descriptor(FlightDescriptor::new_path(path.to_vec()));
+    let sql = "SELECT region, count(*) AS n FROM remote GROUP BY region ORDER BY region";
+    assert_eq!(
+        query(source, &url, sql).await,
+        "+--------+---+\n\
+         | region | n |\n\
+         +--------+---+\n\
+         | eu     | 3 |\n\
+         | us     | 1 |\n\
+         +--------+---+"
+    );
+}
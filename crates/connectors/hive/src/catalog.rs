@@ -0,0 +1,227 @@
+//! A Hive Metastore as a DataFusion catalog.
+//!
+//! Each database is a schema, listed with its tables when the catalog is created.
+//! Tables are resolved from the metastore each time a query names them, so queries
+//! see the current schema, location and partitions. Databases and tables created
+//! since are picked up by loading the catalog again through its [`CatalogSource`].
+
+use crate::metastore::HiveMetastoreClient;
+use crate::table::listing_table;
+use async_trait::async_trait;
+use datafusion::catalog::{CatalogProvider, SchemaProvider};
+use datafusion::datasource::TableProvider;
+use datafusion::error::Result as DataFusionResult;
+use igloo_common::catalog::CatalogSource;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The databases of a Hive Metastore, as schemas.
+#[derive(Debug)]
+pub struct HiveCatalogProvider {
+    client: Arc<HiveMetastoreClient>,
+    schemas: BTreeMap<String, Arc<DatabaseProvider>>,
+}
+
+impl HiveCatalogProvider {
+    /// The source of `client`'s databases, listing none until loaded through its
+    /// [`CatalogSource`]. Does not reach the metastore.
+    pub fn new(client: Arc<HiveMetastoreClient>) -> Self {
+        Self { client, schemas: BTreeMap::new() }
+    }
+
+    /// List the databases of `client` and their tables.
+    pub async fn try_new(client: Arc<HiveMetastoreClient>) -> DataFusionResult<Self> {
+        let mut schemas = BTreeMap::new();
+        for database in client.get_all_databases().await? {
+            let tables = client.get_all_tables(&database).await?;
+            let provider = DatabaseProvider {
+                client: Arc::clone(&client),
+                database: database.clone(),
+                tables,
+            };
+            schemas.insert(database, Arc::new(provider));
+        }
+        Ok(Self { client, schemas })
+    }
+}
+
+#[async_trait]
+impl CatalogSource for HiveCatalogProvider {
+    async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+        Ok(Arc::new(Self::try_new(Arc::clone(&self.client)).await?))
+    }
+}
+
+impl CatalogProvider for HiveCatalogProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.schemas.keys().cloned().collect()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        self.schemas.get(name).map(|schema| Arc::clone(schema) as Arc<dyn SchemaProvider>)
+    }
+}
+
+/// The tables of a database.
+#[derive(Debug)]
+pub struct DatabaseProvider {
+    client: Arc<HiveMetastoreClient>,
+    database: String,
+    /// Names listed when the catalog was created.
+    tables: Vec<String>,
+}
+
+#[async_trait]
+impl SchemaProvider for DatabaseProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.clone()
+    }
+
+    async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
+        let Some(table) = self.client.get_table(&self.database, name).await? else {
+            return Ok(None);
+        };
+        let partitions = match table.partition_keys.is_empty() {
+            true => vec![],
+            false => self.client.get_partitions(&self.database, name).await?,
+        };
+        Ok(Some(Arc::new(listing_table(&table, &partitions)?)))
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.tables.iter().any(|table| table == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::thrift::{self, Struct, Value};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::prelude::SessionContext;
+    use std::io::{BufReader, BufWriter};
+    use std::net::TcpListener;
+    use std::path::Path;
+
+    fn strings(values: &[&str]) -> Value {
+        Value::List(values.iter().map(|v| Value::string(v)).collect())
+    }
+
+    fn column(name: &str, data_type: &str) -> Value {
+        Value::Struct(
+            Struct::default().with(1, Value::string(name)).with(2, Value::string(data_type)),
+        )
+    }
+
+    fn storage(location: &str, columns: Vec<Value>) -> Value {
+        let serde = Struct::default()
+            .with(2, Value::string("org.apache.hadoop.hive.serde2.lazy.LazySimpleSerDe"))
+            .with(3, Value::Map(vec![(Value::string("field.delim"), Value::string(","))]));
+        Value::Struct(
+            Struct::default()
+                .with(1, Value::List(columns))
+                .with(2, Value::string(location))
+                .with(3, Value::string("org.apache.hadoop.mapred.TextInputFormat"))
+                .with(7, Value::Struct(serde)),
+        )
+    }
+
+    /// A metastore with the `sales.orders` text table, partitioned by `dt`.
+    fn serve(listener: TcpListener, dir: &Path) {
+        let location = format!("file://{}/orders", dir.display());
+        let stream = listener.accept().unwrap().0;
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = BufWriter::new(stream);
+        while let Ok((name, _, seq, args)) = thrift::read_message(&mut reader) {
+            let result = match (name.as_str(), args.str(1), args.str(2)) {
+                ("get_all_databases", ..) => Struct::default().with(0, strings(&["sales"])),
+                ("get_all_tables", Some("sales"), _) => {
+                    Struct::default().with(0, strings(&["orders"]))
+                }
+                ("get_table", Some("sales"), Some("orders")) => {
+                    let columns = vec![column("id", "bigint"), column("amount", "decimal(10,2)")];
+                    let table = Struct::default()
+                        .with(1, Value::string("orders"))
+                        .with(2, Value::string("sales"))
+                        .with(7, storage(&location, columns))
+                        .with(8, Value::List(vec![column("dt", "string")]))
+                        .with(12, Value::string("EXTERNAL_TABLE"));
+                    Struct::default().with(0, Value::Struct(table))
+                }
+                ("get_table", ..) => {
+                    let exception = Struct::default().with(1, Value::string("no such table"));
+                    Struct::default().with(2, Value::Struct(exception))
+                }
+                ("get_partitions", Some("sales"), Some("orders")) => {
+                    let partitions = ["2024-01-01", "2024-01-02"].map(|dt| {
+                        let location = format!("{location}/dt={dt}");
+                        Value::Struct(
+                            Struct::default()
+                                .with(1, strings(&[dt]))
+                                .with(6, storage(&location, vec![])),
+                        )
+                    });
+                    Struct::default().with(0, Value::List(partitions.to_vec()))
+                }
+                _ => panic!("unexpected call {name}"),
+            };
+            thrift::write_message(&mut writer, &name, thrift::REPLY, seq, &result).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_tables_are_pruned() {
+        let dir = std::env::temp_dir().join(format!("igloo-hive-{}", std::process::id()));
+        for (dt, rows) in [("2024-01-01", "1,10.50\n2,20.00\n"), ("2024-01-02", "3,5.25\n")] {
+            std::fs::create_dir_all(dir.join(format!("orders/dt={dt}"))).unwrap();
+            std::fs::write(dir.join(format!("orders/dt={dt}/000000_0")), rows).unwrap();
+        }
+        // Unreadable, so scanning it fails the query.
+        std::fs::create_dir_all(dir.join("orders/dt=2024-01-03")).unwrap();
+        std::fs::write(dir.join("orders/dt=2024-01-03/000000_0"), "not,a,number\n").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn({
+            let dir = dir.clone();
+            move || serve(listener, &dir)
+        });
+
+        let client = Arc::new(HiveMetastoreClient::new(addr.to_string()));
+        let ctx = SessionContext::new();
+        ctx.register_catalog("hive", Arc::new(HiveCatalogProvider::try_new(client).await.unwrap()));
+        let batches = ctx
+            .sql(
+                "SELECT dt, count(*) AS orders, sum(amount) AS total FROM hive.sales.orders \
+                 WHERE dt <= '2024-01-02' GROUP BY dt ORDER BY dt",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        let expected = "\
++------------+--------+-------+
+| dt         | orders | total |
++------------+--------+-------+
+| 2024-01-01 | 2      | 30.50 |
+| 2024-01-02 | 1      | 5.25  |
++------------+--------+-------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+        let error = ctx.sql("SELECT * FROM hive.sales.missing").await.unwrap_err();
+        assert!(error.to_string().contains("not found"), "{error}");
+
+        drop(ctx);
+        server.join().unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
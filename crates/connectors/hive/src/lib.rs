@@ -0,0 +1,29 @@
+//! Hive Metastore tables.
+//!
+//! [`HiveMetastoreClient`] talks to a Hive Metastore over Thrift, and
+//! [`HiveCatalogProvider`] exposes its databases to SQL, each table being read as a
+//! listing table over its location with its partition keys as partition columns:
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_hive::{HiveCatalogProvider, HiveMetastoreClient};
+//! use std::sync::Arc;
+//!
+//! let client = HiveMetastoreClient::new("metastore.example.com:9083");
+//! let provider = HiveCatalogProvider::try_new(Arc::new(client)).await?;
+//! ctx.register_catalog("hive", Arc::new(provider));
+//! ctx.sql("SELECT count(*) FROM hive.sales.orders WHERE dt = '2024-01-01'").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Tables on HDFS or object stores are read through the object store the session has
+//! registered for their location's scheme and authority.
+
+pub mod catalog;
+pub mod metastore;
+pub mod table;
+mod thrift;
+
+pub use catalog::HiveCatalogProvider;
+pub use metastore::HiveMetastoreClient;
@@ -0,0 +1,237 @@
+//! Client of the Hive Metastore Thrift service.
+//!
+//! Only the read calls needed to resolve tables are made. Calls share one connection,
+//! made on first use and again after an I/O error.
+
+use crate::thrift::{self, Struct, Value};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use std::collections::BTreeMap;
+use std::io::{BufReader, BufWriter};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long a call may wait on the metastore.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `tableType` of views, which have no data of their own.
+pub const VIRTUAL_VIEW: &str = "VIRTUAL_VIEW";
+
+/// A column of a table, or a partition key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HiveColumn {
+    pub name: String,
+    /// The Hive type, such as `bigint` or `decimal(10,2)`.
+    pub data_type: String,
+    pub comment: Option<String>,
+}
+
+/// A table as the metastore describes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HiveTable {
+    pub database: String,
+    pub name: String,
+    /// `MANAGED_TABLE`, `EXTERNAL_TABLE` or [`VIRTUAL_VIEW`].
+    pub table_type: String,
+    pub location: String,
+    pub input_format: String,
+    pub serialization_lib: String,
+    pub serde_parameters: BTreeMap<String, String>,
+    pub columns: Vec<HiveColumn>,
+    pub partition_keys: Vec<HiveColumn>,
+    pub parameters: BTreeMap<String, String>,
+}
+
+/// A partition of a table: its values of the partition keys, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HivePartition {
+    pub values: Vec<String>,
+    pub location: String,
+}
+
+/// Client of a Hive Metastore.
+#[derive(Debug)]
+pub struct HiveMetastoreClient {
+    addr: String,
+    timeout: Duration,
+    connection: Arc<Mutex<Option<Connection>>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    seq: i32,
+}
+
+impl HiveMetastoreClient {
+    /// A client of the metastore at `addr` (`host:port`, usually port 9083).
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into(), timeout: DEFAULT_TIMEOUT, connection: Arc::default() }
+    }
+
+    /// Wait at most `timeout` on the metastore ([`DEFAULT_TIMEOUT`] by default).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub async fn get_all_databases(&self) -> DataFusionResult<Vec<String>> {
+        let result = self.call("get_all_databases", Struct::default()).await?;
+        Ok(success(&result, "get_all_databases")?.strings(0))
+    }
+
+    pub async fn get_all_tables(&self, database: &str) -> DataFusionResult<Vec<String>> {
+        let args = Struct::default().with(1, Value::string(database));
+        let result = self.call("get_all_tables", args).await?;
+        Ok(success(&result, "get_all_tables")?.strings(0))
+    }
+
+    /// The table, `None` if there is no such table.
+    pub async fn get_table(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> DataFusionResult<Option<HiveTable>> {
+        let args = Struct::default().with(1, Value::string(database)).with(2, Value::string(name));
+        let result = self.call("get_table", args).await?;
+        // `NoSuchObjectException`
+        if result.get(2).is_some() {
+            return Ok(None);
+        }
+        let result = success(&result, "get_table")?;
+        let table = result.get(0).and_then(Value::as_struct).ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "Hive metastore returned no table {database}.{name}"
+            ))
+        })?;
+        Ok(Some(table_from_thrift(table)))
+    }
+
+    /// All partitions of the table.
+    pub async fn get_partitions(
+        &self,
+        database: &str,
+        table: &str,
+    ) -> DataFusionResult<Vec<HivePartition>> {
+        let args = Struct::default()
+            .with(1, Value::string(database))
+            .with(2, Value::string(table))
+            // No limit.
+            .with(3, Value::I16(-1));
+        let result = self.call("get_partitions", args).await?;
+        let partitions = success(&result, "get_partitions")?.structs(0);
+        Ok(partitions
+            .map(|partition| HivePartition {
+                values: partition.strings(1),
+                // `sd`
+                location: storage(partition, 6).string(2),
+            })
+            .collect())
+    }
+
+    /// Call `method` and return its result struct.
+    async fn call(&self, method: &'static str, args: Struct) -> DataFusionResult<Struct> {
+        let connection = Arc::clone(&self.connection);
+        let addr = self.addr.clone();
+        let timeout = self.timeout;
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            let result = call(&mut connection, &addr, timeout, method, &args);
+            if result.is_err() {
+                // The connection may be mid-message; start over on the next call.
+                *connection = None;
+            }
+            result
+        })
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))?
+        .map_err(|e| {
+            DataFusionError::Execution(format!(
+                "Hive metastore {addr} {method} failed: {e}",
+                addr = self.addr
+            ))
+        })
+    }
+}
+
+fn call(
+    connection: &mut Option<Connection>,
+    addr: &str,
+    timeout: Duration,
+    method: &str,
+    args: &Struct,
+) -> std::io::Result<Struct> {
+    let connection = match connection {
+        Some(connection) => connection,
+        None => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            stream.set_nodelay(true)?;
+            connection.insert(Connection {
+                reader: BufReader::new(stream.try_clone()?),
+                writer: BufWriter::new(stream),
+                seq: 0,
+            })
+        }
+    };
+    connection.seq = connection.seq.wrapping_add(1);
+    thrift::write_message(&mut connection.writer, method, thrift::CALL, connection.seq, args)?;
+    let (name, kind, seq, body) = thrift::read_message(&mut connection.reader)?;
+    let invalid = |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+    if name != method || seq != connection.seq {
+        return Err(invalid(format!("unexpected reply {name} #{seq}")));
+    }
+    match kind {
+        thrift::REPLY => Ok(body),
+        // `TApplicationException`, e.g. for a method the metastore does not know.
+        thrift::EXCEPTION => Err(invalid(body.string(1))),
+        _ => Err(invalid(format!("unexpected message type {kind}"))),
+    }
+}
+
+/// The result struct of a call that succeeded, or the exception it threw as an error.
+fn success<'a>(result: &'a Struct, method: &str) -> DataFusionResult<&'a Struct> {
+    if result.get(0).is_some() {
+        return Ok(result);
+    }
+    // Exceptions (`MetaException`, ...) are the other fields, with a message first.
+    let message = result
+        .fields()
+        .find_map(|(_, exception)| exception.as_struct())
+        .map_or_else(|| "no result".to_string(), |exception| exception.string(1));
+    Err(DataFusionError::Execution(format!("Hive metastore {method} failed: {message}")))
+}
+
+/// The `StorageDescriptor` field `id` of a table or partition.
+fn storage(s: &Struct, id: i16) -> Struct {
+    s.get(id).and_then(Value::as_struct).cloned().unwrap_or_default()
+}
+
+fn columns(s: &Struct, id: i16) -> Vec<HiveColumn> {
+    s.structs(id)
+        .map(|column| HiveColumn {
+            name: column.string(1),
+            data_type: column.string(2),
+            comment: column.str(3).filter(|c| !c.is_empty()).map(str::to_string),
+        })
+        .collect()
+}
+
+fn table_from_thrift(table: &Struct) -> HiveTable {
+    let sd = storage(table, 7);
+    let serde = sd.get(7).and_then(Value::as_struct);
+    HiveTable {
+        database: table.string(2),
+        name: table.string(1),
+        table_type: table.string(12),
+        location: sd.string(2),
+        input_format: sd.string(3),
+        serialization_lib: serde.map(|s| s.string(2)).unwrap_or_default(),
+        serde_parameters: serde.map(|s| s.string_map(3)).unwrap_or_default(),
+        columns: columns(&sd, 1),
+        partition_keys: columns(table, 8),
+        parameters: table.string_map(9),
+    }
+}
@@ -0,0 +1,163 @@
+//! Hive tables as DataFusion listing tables.
+//!
+//! A table's files are listed under its location, partitions being the `key=value`
+//! directories Hive lays them out in, so filters on partition keys skip the
+//! directories of partitions they rule out. Parquet, delimited text and JSON tables
+//! are read; ORC, Avro and other formats are refused.
+
+use crate::metastore::{HivePartition, HiveTable, VIRTUAL_VIEW};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::file_format::json::JsonFormat;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use std::sync::Arc;
+
+/// Field delimiter of text tables that do not set one (Ctrl-A).
+pub const DEFAULT_FIELD_DELIMITER: u8 = 0x01;
+
+/// A listing table reading `table`, whose partitions are `partitions`.
+///
+/// Partitions registered outside the table's directory layout are not read, and are
+/// reported when the table is resolved.
+pub fn listing_table(
+    table: &HiveTable,
+    partitions: &[HivePartition],
+) -> DataFusionResult<ListingTable> {
+    if table.table_type == VIRTUAL_VIEW {
+        return Err(DataFusionError::NotImplemented(format!(
+            "Hive view {}.{} cannot be read",
+            table.database, table.name
+        )));
+    }
+    let fields = table
+        .columns
+        .iter()
+        .map(|column| Ok(Field::new(&column.name, hive_type_to_arrow(&column.data_type)?, true)))
+        .collect::<DataFusionResult<Vec<_>>>()?;
+    let partition_cols = table
+        .partition_keys
+        .iter()
+        .map(|key| Ok((key.name.clone(), hive_type_to_arrow(&key.data_type)?)))
+        .collect::<DataFusionResult<Vec<_>>>()?;
+
+    // Listed as a directory, whether or not the location ends with a `/`.
+    let location = format!("{}/", table.location.trim_end_matches('/'));
+    for partition in partitions {
+        let expected = table.partition_keys.iter().zip(&partition.values);
+        let expected = expected.map(|(key, value)| format!("{}={value}", key.name));
+        let expected = format!("{location}{}", expected.collect::<Vec<_>>().join("/"));
+        if partition.location.trim_end_matches('/') != expected {
+            tracing::warn!(
+                partition = ?partition.values,
+                table = format!("{}.{}", table.database, table.name),
+                location = partition.location,
+                "Hive partition is outside the table's layout; it is not read"
+            );
+        }
+    }
+
+    // Hive names data files without extensions (`000000_0`).
+    let options = ListingOptions::new(file_format(table)?)
+        .with_file_extension("")
+        .with_table_partition_cols(partition_cols)
+        .with_collect_stat(false);
+    let config = ListingTableConfig::new(ListingTableUrl::parse(location)?)
+        .with_listing_options(options)
+        .with_schema(Arc::new(Schema::new(fields)));
+    ListingTable::try_new(config)
+}
+
+fn file_format(table: &HiveTable) -> DataFusionResult<Arc<dyn FileFormat>> {
+    let format = format!("{} {}", table.input_format, table.serialization_lib).to_lowercase();
+    if format.contains("parquet") {
+        Ok(Arc::new(ParquetFormat::default()))
+    } else if format.contains("json") {
+        Ok(Arc::new(JsonFormat::default()))
+    } else if format.contains("textinputformat") {
+        let parameter = |key: &str| table.serde_parameters.get(key).map(String::as_str);
+        let delimiter = match parameter("field.delim").or(parameter("separatorChar")) {
+            Some(delimiter) => single_byte(table, delimiter)?,
+            None => DEFAULT_FIELD_DELIMITER,
+        };
+        let mut format = CsvFormat::default().with_has_header(false).with_delimiter(delimiter);
+        if let Some(quote) = parameter("quoteChar") {
+            format = format.with_quote(single_byte(table, quote)?);
+        }
+        Ok(Arc::new(format))
+    } else {
+        Err(DataFusionError::NotImplemented(format!(
+            "Hive table {}.{} is stored as {}, which is not supported",
+            table.database, table.name, table.input_format
+        )))
+    }
+}
+
+fn single_byte(table: &HiveTable, value: &str) -> DataFusionResult<u8> {
+    match value.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(DataFusionError::NotImplemented(format!(
+            "Hive table {}.{} uses the delimiter {value:?}, which is not supported",
+            table.database, table.name
+        ))),
+    }
+}
+
+/// The Arrow type columns of Hive type `name` are read as.
+pub fn hive_type_to_arrow(name: &str) -> DataFusionResult<DataType> {
+    let name = name.trim().to_lowercase();
+    // Lengths of character types do not change how they are read.
+    let base = name.split('(').next().unwrap_or_default().trim();
+    Ok(match base {
+        "boolean" => DataType::Boolean,
+        "tinyint" => DataType::Int8,
+        "smallint" => DataType::Int16,
+        "int" | "integer" => DataType::Int32,
+        "bigint" => DataType::Int64,
+        "float" => DataType::Float32,
+        "double" | "double precision" => DataType::Float64,
+        "string" | "varchar" | "char" => DataType::Utf8,
+        "binary" => DataType::Binary,
+        "date" => DataType::Date32,
+        // Hive writes timestamps to Parquet as INT96, read with nanoseconds.
+        "timestamp" => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        "decimal" | "numeric" => {
+            let args = name.strip_prefix(base).unwrap_or_default();
+            let args = args.trim().trim_start_matches('(').trim_end_matches(')');
+            let mut args = args.split(',').map(|arg| arg.trim().parse::<u8>());
+            // Hive's `decimal` alone is `decimal(10,0)`.
+            match (args.next(), args.next()) {
+                (Some(Ok(precision)), Some(Ok(scale))) => {
+                    DataType::Decimal128(precision, scale as i8)
+                }
+                (Some(Ok(precision)), None) => DataType::Decimal128(precision, 0),
+                _ if name == base => DataType::Decimal128(10, 0),
+                _ => return Err(unsupported_type(&name)),
+            }
+        }
+        _ => return Err(unsupported_type(&name)),
+    })
+}
+
+fn unsupported_type(name: &str) -> DataFusionError {
+    DataFusionError::NotImplemented(format!("Hive type '{name}' is not supported"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hive_types() {
+        assert_eq!(hive_type_to_arrow("BIGINT").unwrap(), DataType::Int64);
+        assert_eq!(hive_type_to_arrow("varchar(20)").unwrap(), DataType::Utf8);
+        assert_eq!(hive_type_to_arrow("decimal(12, 2)").unwrap(), DataType::Decimal128(12, 2));
+        assert_eq!(hive_type_to_arrow("decimal").unwrap(), DataType::Decimal128(10, 0));
+        assert!(hive_type_to_arrow("array<int>").is_err());
+        assert!(hive_type_to_arrow("decimal(x)").is_err());
+    }
+}
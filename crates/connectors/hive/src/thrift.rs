@@ -0,0 +1,315 @@
+//! The Thrift binary protocol, as the Hive Metastore speaks it over a buffered
+//! (unframed) socket.
+//!
+//! Values are read and written generically, structs being maps from field ID to value,
+//! so only the fields a caller looks at need to be known.
+
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+/// Message type of calls.
+pub const CALL: u8 = 1;
+/// Message type of replies.
+pub const REPLY: u8 = 2;
+/// Message type of `TApplicationException` replies.
+pub const EXCEPTION: u8 = 3;
+
+const VERSION_1: u32 = 0x8001_0000;
+const VERSION_MASK: u32 = 0xffff_0000;
+/// Longest string or collection read, so a corrupt reply cannot exhaust memory.
+const MAX_LENGTH: usize = 256 << 20;
+
+const STOP: u8 = 0;
+const BOOL: u8 = 2;
+const BYTE: u8 = 3;
+const DOUBLE: u8 = 4;
+const I16: u8 = 6;
+const I32: u8 = 8;
+const I64: u8 = 10;
+const STRING: u8 = 11;
+const STRUCT: u8 = 12;
+const MAP: u8 = 13;
+const SET: u8 = 14;
+const LIST: u8 = 15;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Byte(i8),
+    Double(f64),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    String(Vec<u8>),
+    Struct(Struct),
+    Map(Vec<(Value, Value)>),
+    Set(Vec<Value>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    pub fn string(s: &str) -> Self {
+        Value::String(s.as_bytes().to_vec())
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_struct(&self) -> Option<&Struct> {
+        match self {
+            Value::Struct(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(values) | Value::Set(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn type_id(&self) -> u8 {
+        match self {
+            Value::Bool(_) => BOOL,
+            Value::Byte(_) => BYTE,
+            Value::Double(_) => DOUBLE,
+            Value::I16(_) => I16,
+            Value::I32(_) => I32,
+            Value::I64(_) => I64,
+            Value::String(_) => STRING,
+            Value::Struct(_) => STRUCT,
+            Value::Map(_) => MAP,
+            Value::Set(_) => SET,
+            Value::List(_) => LIST,
+        }
+    }
+}
+
+/// A struct, by field ID.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Struct(BTreeMap<i16, Value>);
+
+impl Struct {
+    pub fn with(mut self, id: i16, value: Value) -> Self {
+        self.0.insert(id, value);
+        self
+    }
+
+    pub fn get(&self, id: i16) -> Option<&Value> {
+        self.0.get(&id)
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (i16, &Value)> {
+        self.0.iter().map(|(id, value)| (*id, value))
+    }
+
+    pub fn str(&self, id: i16) -> Option<&str> {
+        self.get(id).and_then(Value::as_str)
+    }
+
+    pub fn string(&self, id: i16) -> String {
+        self.str(id).unwrap_or_default().to_string()
+    }
+
+    pub fn strings(&self, id: i16) -> Vec<String> {
+        let values = self.get(id).and_then(Value::as_list).unwrap_or_default();
+        values.iter().filter_map(Value::as_str).map(str::to_string).collect()
+    }
+
+    pub fn structs(&self, id: i16) -> impl Iterator<Item = &Struct> {
+        let values = self.get(id).and_then(Value::as_list).unwrap_or_default();
+        values.iter().filter_map(Value::as_struct)
+    }
+
+    /// A `map<string, string>` field.
+    pub fn string_map(&self, id: i16) -> BTreeMap<String, String> {
+        let Some(Value::Map(entries)) = self.get(id) else {
+            return BTreeMap::new();
+        };
+        entries
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str()?.to_string(), v.as_str()?.to_string())))
+            .collect()
+    }
+}
+
+/// Write a message of `kind` whose body is `body`.
+pub fn write_message(
+    w: &mut impl Write,
+    name: &str,
+    kind: u8,
+    seq: i32,
+    body: &Struct,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(&(VERSION_1 | kind as u32).to_be_bytes());
+    write_bytes(&mut buf, name.as_bytes());
+    buf.extend_from_slice(&seq.to_be_bytes());
+    write_struct(&mut buf, body);
+    w.write_all(&buf)?;
+    w.flush()
+}
+
+/// Read a message: its name, kind, sequence number and body.
+pub fn read_message(r: &mut impl Read) -> io::Result<(String, u8, i32, Struct)> {
+    let header = read_i32(r)? as u32;
+    if header & VERSION_MASK != VERSION_1 {
+        return Err(invalid("unsupported Thrift protocol version"));
+    }
+    let name = String::from_utf8(read_bytes(r)?).map_err(|_| invalid("invalid method name"))?;
+    let seq = read_i32(r)?;
+    let body = read_struct(r)?;
+    Ok((name, (header & 0xff) as u8, seq, body))
+}
+
+fn write_struct(buf: &mut Vec<u8>, s: &Struct) {
+    for (id, value) in s.fields() {
+        buf.push(value.type_id());
+        buf.extend_from_slice(&id.to_be_bytes());
+        write_value(buf, value);
+    }
+    buf.push(STOP);
+}
+
+fn write_value(buf: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Bool(b) => buf.push(*b as u8),
+        Value::Byte(b) => buf.push(*b as u8),
+        Value::Double(d) => buf.extend_from_slice(&d.to_bits().to_be_bytes()),
+        Value::I16(i) => buf.extend_from_slice(&i.to_be_bytes()),
+        Value::I32(i) => buf.extend_from_slice(&i.to_be_bytes()),
+        Value::I64(i) => buf.extend_from_slice(&i.to_be_bytes()),
+        Value::String(bytes) => write_bytes(buf, bytes),
+        Value::Struct(s) => write_struct(buf, s),
+        Value::Map(entries) => {
+            // The entry types of an empty map do not matter.
+            let (k, v) =
+                entries.first().map_or((STRING, STRING), |(k, v)| (k.type_id(), v.type_id()));
+            buf.extend_from_slice(&[k, v]);
+            buf.extend_from_slice(&(entries.len() as i32).to_be_bytes());
+            for (k, v) in entries {
+                write_value(buf, k);
+                write_value(buf, v);
+            }
+        }
+        Value::Set(values) | Value::List(values) => {
+            buf.push(values.first().map_or(STRING, Value::type_id));
+            buf.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values {
+                write_value(buf, value);
+            }
+        }
+    }
+}
+
+fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn read_struct(r: &mut impl Read) -> io::Result<Struct> {
+    let mut s = Struct::default();
+    loop {
+        let kind = read_u8(r)?;
+        if kind == STOP {
+            return Ok(s);
+        }
+        let id = i16::from_be_bytes(read_array(r)?);
+        s.0.insert(id, read_value(r, kind)?);
+    }
+}
+
+fn read_value(r: &mut impl Read, kind: u8) -> io::Result<Value> {
+    Ok(match kind {
+        BOOL => Value::Bool(read_u8(r)? != 0),
+        BYTE => Value::Byte(read_u8(r)? as i8),
+        DOUBLE => Value::Double(f64::from_bits(u64::from_be_bytes(read_array(r)?))),
+        I16 => Value::I16(i16::from_be_bytes(read_array(r)?)),
+        I32 => Value::I32(read_i32(r)?),
+        I64 => Value::I64(i64::from_be_bytes(read_array(r)?)),
+        STRING => Value::String(read_bytes(r)?),
+        STRUCT => Value::Struct(read_struct(r)?),
+        MAP => {
+            let [k, v] = read_array(r)?;
+            let len = read_length(r)?;
+            let mut entries = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                entries.push((read_value(r, k)?, read_value(r, v)?));
+            }
+            Value::Map(entries)
+        }
+        SET | LIST => {
+            let element = read_u8(r)?;
+            let len = read_length(r)?;
+            let mut values = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                values.push(read_value(r, element)?);
+            }
+            if kind == SET {
+                Value::Set(values)
+            } else {
+                Value::List(values)
+            }
+        }
+        _ => return Err(invalid(&format!("unknown Thrift type {kind}"))),
+    })
+}
+
+fn read_bytes(r: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut bytes = vec![0; read_length(r)?];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_length(r: &mut impl Read) -> io::Result<usize> {
+    let len = read_i32(r)?;
+    match usize::try_from(len) {
+        Ok(len) if len <= MAX_LENGTH => Ok(len),
+        _ => Err(invalid(&format!("invalid Thrift length {len}"))),
+    }
+}
+
+fn read_i32(r: &mut impl Read) -> io::Result<i32> {
+    Ok(i32::from_be_bytes(read_array(r)?))
+}
+
+fn read_u8(r: &mut impl Read) -> io::Result<u8> {
+    Ok(read_array::<1>(r)?[0])
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let body = Struct::default()
+            .with(0, Value::List(vec![Value::string("default"), Value::string("sales")]))
+            .with(1, Value::Map(vec![(Value::string("k"), Value::string("v"))]))
+            .with(3, Value::Struct(Struct::default().with(1, Value::I16(-1))));
+        let mut buf = Vec::new();
+        write_message(&mut buf, "get_all_databases", REPLY, 7, &body).unwrap();
+        let (name, kind, seq, read) = read_message(&mut &buf[..]).unwrap();
+        assert_eq!((name.as_str(), kind, seq), ("get_all_databases", REPLY, 7));
+        assert_eq!(read, body);
+        assert_eq!(read.strings(0), ["default", "sales"]);
+        assert_eq!(read.string_map(1)["k"], "v");
+        // Truncated messages are errors, not hangs or panics.
+        assert!(read_message(&mut &buf[..buf.len() - 1]).is_err());
+    }
+}
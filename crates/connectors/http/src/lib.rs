@@ -0,0 +1,28 @@
+//! JSON APIs.
+//!
+//! [`HttpTable`] reads the records of a REST endpoint answering with JSON, page after
+//! page, and keeps them for a while, to join the data of SaaS APIs with the rest (see
+//! [`table`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_http::{HttpTable, Pagination};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let tickets = HttpTable::new("https://support.example.com/api/v2/tickets")
+//!     .with_header("Authorization", "Bearer secret")?
+//!     .with_records_path("tickets")
+//!     .with_pagination(Pagination::NextUrl { path: "next_page".to_string() })
+//!     .with_ttl(Duration::from_secs(300))
+//!     .infer_schema()
+//!     .await?;
+//! ctx.register_table("tickets", Arc::new(tickets))?;
+//! ctx.sql("SELECT status, count(*) FROM tickets GROUP BY status").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod table;
+
+pub use table::{HttpTable, Pagination};
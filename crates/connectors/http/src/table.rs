@@ -0,0 +1,418 @@
+//! Reading JSON APIs.
+//!
+//! An [`HttpTable`] reads the records of a REST endpoint answering with JSON: the
+//! objects of the array at the table's path in each response (the response itself by
+//! default), a row each, whose fields are the columns of the same name, as
+//! [`arrow::json`] decodes them. The schema is declared, or inferred from the records
+//! of the first page.
+//!
+//! Endpoints answering a page of records at a time are read page after page, as
+//! their [`Pagination`] tells, until a page has no record, up to a maximum number of
+//! pages.
+//!
+//! Records are fetched again by the first scan after those fetched last are older
+//! than the table's TTL; scans in between read the same records, without calling the
+//! endpoint.
+//!
+//! Endpoints answering with all fields of their records are read whole. Those taking
+//! the fields to answer with as a query parameter (see
+//! [`HttpTable::with_fields_param`]) are asked for the columns a scan reads only, and
+//! their records are fetched again by scans reading columns not fetched before.
+
+use arrow::json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::datasource::{MemTable, TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::ExecutionPlan;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, LINK};
+use reqwest::Url;
+use serde_json::Value;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+/// How long fetched records are read unless configured otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+/// Pages read at most unless configured otherwise.
+pub const DEFAULT_MAX_PAGES: usize = 1000;
+
+/// How an endpoint pages its records.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Pagination {
+    /// All records are in one response.
+    #[default]
+    None,
+    /// The number of the page, from 1, is the query parameter `param`.
+    Page { param: String },
+    /// The offset of the first record is the query parameter `offset_param`, and the
+    /// number of records per page, `limit`, the query parameter `limit_param`.
+    Offset { offset_param: String, limit_param: String, limit: usize },
+    /// The value at `path` in each response, absent or null in the last, is the
+    /// query parameter `param` of the next request.
+    Cursor { path: String, param: String },
+    /// The URL at `path` in each response, absent or null in the last, is that of
+    /// the next page.
+    NextUrl { path: String },
+    /// The URL of the next page is that of the `Link` header with `rel="next"`.
+    LinkHeader,
+}
+
+/// The records of an endpoint, see the [module docs](self).
+pub struct HttpTable {
+    http: reqwest::Client,
+    url: String,
+    headers: HeaderMap,
+    /// The path of the records in responses, such as `data.items`.
+    records: String,
+    pagination: Pagination,
+    max_pages: usize,
+    ttl: Duration,
+    schema: SchemaRef,
+    /// The query parameter listing the fields to answer with, if the endpoint takes one.
+    fields_param: Option<String>,
+    fetched: Mutex<Option<Fetched>>,
+}
+
+/// The records fetched last.
+struct Fetched {
+    at: Instant,
+    /// The indices of the columns of `batches` in the table's schema.
+    columns: Vec<usize>,
+    batches: Vec<RecordBatch>,
+}
+
+impl fmt::Debug for HttpTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpTable")
+            .field("url", &self.url)
+            .field("pagination", &self.pagination)
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpTable {
+    /// The records of the endpoint at `url`, with the columns declared by
+    /// [`with_schema`](Self::with_schema) or inferred by
+    /// [`infer_schema`](Self::infer_schema).
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            url: url.into(),
+            headers: HeaderMap::new(),
+            records: String::new(),
+            pagination: Pagination::None,
+            max_pages: DEFAULT_MAX_PAGES,
+            ttl: DEFAULT_TTL,
+            schema: Arc::new(Schema::empty()),
+            fields_param: None,
+            fetched: Mutex::new(None),
+        }
+    }
+
+    /// Send `name: value` with each request, such as an `Authorization` header.
+    pub fn with_header(mut self, name: &str, value: &str) -> DataFusionResult<Self> {
+        let invalid = |e: &dyn fmt::Display| {
+            DataFusionError::Configuration(format!("invalid HTTP header {name}: {e}"))
+        };
+        let name = HeaderName::try_from(name).map_err(|e| invalid(&e))?;
+        let mut value = HeaderValue::try_from(value).map_err(|e| invalid(&e))?;
+        value.set_sensitive(true);
+        self.headers.insert(name, value);
+        Ok(self)
+    }
+
+    /// Read the records at `path` in responses, keys separated by dots.
+    pub fn with_records_path(mut self, path: impl Into<String>) -> Self {
+        self.records = path.into();
+        self
+    }
+
+    pub fn with_pagination(mut self, pagination: Pagination) -> Self {
+        self.pagination = pagination;
+        self
+    }
+
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages.max(1);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_schema(mut self, schema: SchemaRef) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Ask for the fields of the columns scanned only, as the comma-separated query
+    /// parameter `param`, such as `fields`.
+    pub fn with_fields_param(mut self, param: impl Into<String>) -> Self {
+        self.fields_param = Some(param.into());
+        self
+    }
+
+    /// Infer the schema from the records of the first page.
+    pub async fn infer_schema(mut self) -> DataFusionResult<Self> {
+        let (_, body) = self.get(self.first_url()?).await?;
+        let records = self.records_of(&body)?;
+        let schema = infer_json_schema_from_iterator(records.iter().map(Ok::<_, ArrowError>))?;
+        self.schema = Arc::new(schema);
+        Ok(self)
+    }
+
+    /// The `columns` of the records, fetched again if those fetched last are too old
+    /// or lack some of them.
+    async fn records(&self, columns: &[usize]) -> DataFusionResult<Vec<RecordBatch>> {
+        let mut cached = self.fetched.lock().await;
+        let fetched = match cached.take() {
+            Some(fetched)
+                if fetched.at.elapsed() < self.ttl
+                    && columns.iter().all(|c| fetched.columns.contains(c)) =>
+            {
+                fetched
+            }
+            _ => {
+                let fetch_columns: Vec<usize> = match &self.fields_param {
+                    // Some field, so that each record still is one.
+                    Some(_) if columns.is_empty() => {
+                        (0..self.schema.fields().len().min(1)).collect()
+                    }
+                    Some(_) => columns.to_vec(),
+                    None => (0..self.schema.fields().len()).collect(),
+                };
+                let batches = self.fetch(&fetch_columns).await?;
+                Fetched { at: Instant::now(), columns: fetch_columns, batches }
+            }
+        };
+        let indices: Vec<usize> = columns
+            .iter()
+            .filter_map(|c| fetched.columns.iter().position(|fetched| fetched == c))
+            .collect();
+        let batches = fetched.batches.iter().map(|batch| batch.project(&indices));
+        let batches = batches.collect::<Result<Vec<_>, _>>()?;
+        *cached = Some(fetched);
+        Ok(batches)
+    }
+
+    /// The `columns` of the records of each page.
+    async fn fetch(&self, columns: &[usize]) -> DataFusionResult<Vec<RecordBatch>> {
+        let schema = Arc::new(self.schema.project(columns)?);
+        let mut url = self.first_url()?;
+        if let Some(param) = &self.fields_param {
+            let fields: Vec<_> =
+                schema.fields().iter().map(|field| field.name().as_str()).collect();
+            url = with_param(&url, param, &fields.join(","));
+        }
+        let (mut batches, mut read) = (Vec::new(), 0);
+        for page in 1..=self.max_pages {
+            let (headers, body) = self.get(url.clone()).await?;
+            let records = self.records_of(&body)?;
+            if records.is_empty() {
+                break;
+            }
+            read += records.len();
+            let mut decoder = ReaderBuilder::new(Arc::clone(&schema)).build_decoder()?;
+            decoder.serialize(records).map_err(|e| {
+                DataFusionError::Execution(format!(
+                    "records of {} do not match the table's schema: {e}",
+                    redacted(&url)
+                ))
+            })?;
+            batches.extend(decoder.flush()?);
+            let next = match &self.pagination {
+                Pagination::None => None,
+                Pagination::Page { param } => {
+                    Some(with_param(&url, param, &(page + 1).to_string()))
+                }
+                Pagination::Offset { offset_param, limit, .. } => (records.len() >= *limit)
+                    .then(|| with_param(&url, offset_param, &read.to_string())),
+                Pagination::Cursor { path, param } => match at(&body, path) {
+                    Some(Value::String(cursor)) if !cursor.is_empty() => {
+                        Some(with_param(&url, param, cursor))
+                    }
+                    Some(Value::Number(cursor)) => {
+                        Some(with_param(&url, param, &cursor.to_string()))
+                    }
+                    _ => None,
+                },
+                Pagination::NextUrl { path } => match at(&body, path) {
+                    Some(Value::String(next)) if !next.is_empty() => Some(join(&url, next)?),
+                    _ => None,
+                },
+                Pagination::LinkHeader => match next_link(&headers) {
+                    Some(next) => Some(join(&url, &next)?),
+                    None => None,
+                },
+            };
+            match next {
+                Some(next) => url = next,
+                None => break,
+            }
+        }
+        Ok(batches)
+    }
+
+    /// The URL of the first page.
+    fn first_url(&self) -> DataFusionResult<Url> {
+        let url = Url::parse(&self.url).map_err(|e| {
+            DataFusionError::Configuration(format!("invalid HTTP API URL {}: {e}", self.url))
+        })?;
+        Ok(match &self.pagination {
+            Pagination::Page { param } => with_param(&url, param, "1"),
+            Pagination::Offset { offset_param, limit_param, limit } => {
+                with_param(&with_param(&url, offset_param, "0"), limit_param, &limit.to_string())
+            }
+            _ => url,
+        })
+    }
+
+    /// The headers and body of the response to a request of `url`.
+    async fn get(&self, url: Url) -> DataFusionResult<(HeaderMap, Value)> {
+        let response = self
+            .http
+            .get(url.clone())
+            .headers(self.headers.clone())
+            .send()
+            .await
+            .map_err(http_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(DataFusionError::Execution(format!(
+                "{} responded {status}: {body}",
+                redacted(&url)
+            )));
+        }
+        let headers = response.headers().clone();
+        let body = response.json().await.map_err(http_error)?;
+        Ok((headers, body))
+    }
+
+    /// The records of a response.
+    fn records_of<'a>(&self, body: &'a Value) -> DataFusionResult<&'a [Value]> {
+        match at(body, &self.records) {
+            Some(Value::Array(records)) => Ok(records),
+            Some(Value::Null) | None => Ok(&[]),
+            Some(_) => Err(DataFusionError::Execution(format!(
+                "HTTP API responses hold no array of records at {:?}",
+                self.records
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl TableProvider for HttpTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let columns: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&columns)?);
+        let table = MemTable::try_new(schema, vec![self.records(&columns).await?])?;
+        table.scan(state, None, filters, limit).await
+    }
+}
+
+/// The value at `path`, keys separated by dots, in `value`; `value` itself for an
+/// empty path.
+fn at<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').filter(|key| !key.is_empty()).try_fold(value, |value, key| value.get(key))
+}
+
+/// `url` with the query parameter `name` set to `value`.
+fn with_param(url: &Url, name: &str, value: &str) -> Url {
+    let mut url = url.clone();
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _)| key != name)
+        .map(|(k, v)| (k.into(), v.into()))
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs).append_pair(name, value);
+    url
+}
+
+/// `next`, relative to `url`.
+fn join(url: &Url, next: &str) -> DataFusionResult<Url> {
+    url.join(next).map_err(|e| {
+        DataFusionError::Execution(format!("invalid URL of the next page {next}: {e}"))
+    })
+}
+
+/// The URL of the `Link` header with `rel="next"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(LINK).iter().filter_map(|value| value.to_str().ok()).find_map(|value| {
+        value.split(',').find_map(|link| {
+            let (url, params) = link.split_once(';')?;
+            let next =
+                params.split(';').any(|param| matches!(param.trim(), "rel=\"next\"" | "rel=next"));
+            next.then(|| url.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+        })
+    })
+}
+
+/// `url` without its query, which may hold secrets.
+fn redacted(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.to_string()
+}
+
+fn http_error(e: reqwest::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e.without_url()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_pages_are_linked() {
+        let body = json!({"data": {"items": [1, 2]}, "next": null});
+        assert_eq!(at(&body, "data.items"), Some(&json!([1, 2])));
+        assert_eq!(at(&body, ""), Some(&body));
+        assert_eq!(at(&body, "data.cursor"), None);
+
+        let url = Url::parse("https://api.example.com/v1/users?page=1&sort=id").unwrap();
+        assert_eq!(
+            with_param(&url, "page", "2").as_str(),
+            "https://api.example.com/v1/users?sort=id&page=2"
+        );
+
+        let mut headers = HeaderMap::new();
+        let link = "</v1/users?page=1>; rel=\"prev\", </v1/users?page=3>; rel=\"next\"";
+        headers.insert(LINK, HeaderValue::from_static(link));
+        assert_eq!(next_link(&headers).unwrap(), "/v1/users?page=3");
+        assert_eq!(next_link(&HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,234 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::prelude::SessionContext;
+use igloo_connector_http::{HttpTable, Pagination};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The requests answered.
+type Requests = Arc<AtomicUsize>;
+
+/// Five users, two per page.
+fn users(from: usize) -> Vec<Value> {
+    let names = ["ada", "bob", "cyd", "dee", "eve"];
+    (from..names.len().min(from + 2))
+        .map(|i| json!({"id": i + 1, "name": names[i], "admin": i == 0}))
+        .collect()
+}
+
+fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+    match headers.get(header::AUTHORIZATION).and_then(|value| value.to_str().ok()) {
+        Some("Bearer secret") => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Users after the cursor, the index of the first.
+async fn cursor(
+    State(requests): State<Requests>,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    authorized(&headers)?;
+    requests.fetch_add(1, Ordering::SeqCst);
+    let from = params.get("after").map_or(0, |after| after.parse().unwrap());
+    let next = (from + 2 < 5).then(|| (from + 2).to_string());
+    Ok(Json(json!({"data": {"users": users(from)}, "meta": {"next": next}})))
+}
+
+/// Users of the page, empty after the last.
+async fn page(Query(params): Query<HashMap<String, String>>) -> Json<Value> {
+    let page: usize = params["page"].parse().unwrap();
+    Json(Value::Array(users((page - 1) * 2)))
+}
+
+/// Users from the offset, linking the next page.
+async fn linked(Query(params): Query<HashMap<String, String>>) -> Response {
+    let from = params.get("from").map_or(0, |from| from.parse().unwrap());
+    let mut response = Json(Value::Array(users(from))).into_response();
+    if from + 2 < 5 {
+        let link =
+            format!("</linked?from=0>; rel=\"first\", </linked?from={}>; rel=\"next\"", from + 2);
+        response.headers_mut().insert(header::LINK, link.parse().unwrap());
+    }
+    response
+}
+
+async fn serve() -> (String, Requests) {
+    let requests = Requests::default();
+    let app = Router::new()
+        .route("/cursor", get(cursor))
+        .route("/page", get(page))
+        .route("/linked", get(linked))
+        .with_state(requests.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (url, requests)
+}
+
+async fn query(table: HttpTable, sql: &str) -> String {
+    let ctx = SessionContext::new();
+    ctx.register_table("users", Arc::new(table)).unwrap();
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+const ALL: &str = "+----+------+\n\
+                   | id | name |\n\
+                   +----+------+\n\
+                   | 1  | ada  |\n\
+                   | 2  | bob  |\n\
+                   | 3  | cyd  |\n\
+                   | 4  | dee  |\n\
+                   | 5  | eve  |\n\
+                   +----+------+";
+
+#[tokio::test]
+async fn test_schemas_are_inferred_from_the_first_page() {
+    let (url, _) = serve().await;
+    let table = HttpTable::new(format!("{url}/cursor"))
+        .with_header("Authorization", "Bearer secret")
+        .unwrap()
+        .with_records_path("data.users")
+        .with_pagination(Pagination::Cursor {
+            path: "meta.next".to_string(),
+            param: "after".to_string(),
+        })
+        .infer_schema()
+        .await
+        .unwrap();
+    assert_eq!(
+        query(table, "SELECT id, name FROM users WHERE NOT admin ORDER BY id DESC LIMIT 2").await,
+        "+----+------+\n\
+         | id | name |\n\
+         +----+------+\n\
+         | 5  | eve  |\n\
+         | 4  | dee  |\n\
+         +----+------+"
+    );
+}
+
+#[tokio::test]
+async fn test_pages_are_read_until_the_last() {
+    let (url, _) = serve().await;
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("name", DataType::Utf8, true),
+    ]));
+    let page = HttpTable::new(format!("{url}/page"))
+        .with_schema(schema.clone())
+        .with_pagination(Pagination::Page { param: "page".to_string() });
+    assert_eq!(query(page, "SELECT * FROM users ORDER BY id").await, ALL);
+    let linked = HttpTable::new(format!("{url}/linked"))
+        .with_schema(schema.clone())
+        .with_pagination(Pagination::LinkHeader);
+    assert_eq!(query(linked, "SELECT * FROM users ORDER BY id").await, ALL);
+    let limited = HttpTable::new(format!("{url}/linked"))
+        .with_schema(schema)
+        .with_pagination(Pagination::LinkHeader)
+        .with_max_pages(2);
+    assert_eq!(
+        query(limited, "SELECT count(*) AS n FROM users").await,
+        "+---+\n\
+         | n |\n\
+         +---+\n\
+         | 4 |\n\
+         +---+"
+    );
+}
+
+#[tokio::test]
+async fn test_records_are_cached_for_the_ttl() {
+    let (url, requests) = serve().await;
+    let table = HttpTable::new(format!("{url}/cursor"))
+        .with_header("Authorization", "Bearer secret")
+        .unwrap()
+        .with_records_path("data.users")
+        .with_pagination(Pagination::Cursor {
+            path: "meta.next".to_string(),
+            param: "after".to_string(),
+        })
+        .with_ttl(Duration::from_millis(200))
+        .infer_schema()
+        .await
+        .unwrap();
+    let ctx = SessionContext::new();
+    ctx.register_table("users", Arc::new(table)).unwrap();
+    let count = || async { ctx.sql("SELECT count(*) FROM users").await.unwrap().collect().await };
+    requests.store(0, Ordering::SeqCst);
+    count().await.unwrap();
+    count().await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    count().await.unwrap();
+    assert_eq!(requests.load(Ordering::SeqCst), 6);
+}
+
+#[tokio::test]
+async fn test_errors_do_not_leak_secrets() {
+    let (url, _) = serve().await;
+    let error = HttpTable::new(format!("{url}/cursor?api_key=secret"))
+        .infer_schema()
+        .await
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("/cursor responded 401 Unauthorized"), "{error}");
+    assert!(!error.contains("secret"), "{error}");
+}
+
+/// The fields of all users asked for, recording what was asked.
+async fn fields(
+    State(asked): State<Arc<Mutex<Vec<String>>>>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Value> {
+    let fields = params.get("fields").cloned().unwrap_or_default();
+    asked.lock().unwrap().push(fields.clone());
+    let users = (0..5).step_by(2).flat_map(users).map(|user| {
+        let fields = fields.split(',').filter_map(|f| Some((f.to_string(), user.get(f)?.clone())));
+        Value::Object(fields.collect())
+    });
+    Json(Value::Array(users.collect()))
+}
+
+#[tokio::test]
+async fn test_only_scanned_fields_are_asked_for() {
+    let asked = Arc::new(Mutex::new(Vec::new()));
+    let app = Router::new().route("/users", get(fields)).with_state(Arc::clone(&asked));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::Int64, true),
+        Field::new("name", DataType::Utf8, true),
+        Field::new("admin", DataType::Boolean, true),
+    ]);
+    let table = HttpTable::new(format!("{url}/users"))
+        .with_schema(Arc::new(schema))
+        .with_fields_param("fields");
+    let ctx = SessionContext::new();
+    ctx.register_table("users", Arc::new(table)).unwrap();
+    let query = |sql: &'static str| {
+        let ctx = ctx.clone();
+        async move {
+            let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+            pretty_format_batches(&batches).unwrap().to_string()
+        }
+    };
+    assert_eq!(query("SELECT id, name FROM users ORDER BY id").await, ALL);
+    // Served from the records fetched, which hold the names.
+    let names = query("SELECT count(*) FROM users WHERE name < 'c'").await;
+    assert!(names.contains("| 2 "), "{names}");
+    let admins = query("SELECT name FROM users WHERE admin").await;
+    assert!(admins.contains("| ada "), "{admins}");
+    assert_eq!(*asked.lock().unwrap(), ["id,name", "name,admin"]);
+}
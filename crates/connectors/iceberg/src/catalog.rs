@@ -0,0 +1,111 @@
+//! An Iceberg REST catalog as a DataFusion catalog.
+//!
+//! Each namespace is a schema (nested namespaces named with their levels joined by
+//! `.`), listed when the catalog is created. Tables are loaded from the catalog each
+//! time a query names them, so queries read the table's current snapshot and `INSERT`s
+//! commit new snapshots through the catalog. Namespaces and tables created since are
+//! picked up by loading the catalog again through its [`CatalogSource`].
+
+use crate::rest::{Namespace, RestCatalog, TableIdent};
+use crate::table::IcebergTable;
+use async_trait::async_trait;
+use datafusion::catalog::{CatalogProvider, SchemaProvider};
+use datafusion::datasource::TableProvider;
+use datafusion::error::Result as DataFusionResult;
+use igloo_common::catalog::CatalogSource;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The namespaces of an Iceberg REST catalog, as schemas.
+#[derive(Debug)]
+pub struct IcebergCatalogProvider {
+    catalog: Arc<RestCatalog>,
+    schemas: BTreeMap<String, Arc<NamespaceProvider>>,
+}
+
+impl IcebergCatalogProvider {
+    /// The source of `catalog`'s namespaces, listing none until loaded through its
+    /// [`CatalogSource`]. Does not reach the catalog.
+    pub fn new(catalog: Arc<RestCatalog>) -> Self {
+        Self { catalog, schemas: BTreeMap::new() }
+    }
+
+    /// List the namespaces of `catalog` and their tables.
+    pub async fn try_new(catalog: Arc<RestCatalog>) -> DataFusionResult<Self> {
+        let mut schemas = BTreeMap::new();
+        let mut pending = catalog.list_namespaces(None).await?;
+        while let Some(namespace) = pending.pop() {
+            // Catalogs without nested namespaces may ignore the parent.
+            let children = catalog.list_namespaces(Some(&namespace)).await?;
+            pending.extend(
+                children
+                    .into_iter()
+                    .filter(|child| child.len() > namespace.len() && child.starts_with(&namespace)),
+            );
+            let tables = catalog.list_tables(&namespace).await?;
+            let tables = tables.into_iter().map(|table| table.name).collect();
+            let provider = NamespaceProvider {
+                catalog: Arc::clone(&catalog),
+                namespace: namespace.clone(),
+                tables,
+            };
+            schemas.insert(namespace.join("."), Arc::new(provider));
+        }
+        Ok(Self { catalog, schemas })
+    }
+}
+
+#[async_trait]
+impl CatalogSource for IcebergCatalogProvider {
+    async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+        Ok(Arc::new(Self::try_new(Arc::clone(&self.catalog)).await?))
+    }
+}
+
+impl CatalogProvider for IcebergCatalogProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.schemas.keys().cloned().collect()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        self.schemas.get(name).map(|schema| Arc::clone(schema) as Arc<dyn SchemaProvider>)
+    }
+}
+
+/// The tables of a namespace.
+#[derive(Debug)]
+pub struct NamespaceProvider {
+    catalog: Arc<RestCatalog>,
+    namespace: Namespace,
+    /// Names listed when the catalog was created.
+    tables: Vec<String>,
+}
+
+#[async_trait]
+impl SchemaProvider for NamespaceProvider {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.tables.clone()
+    }
+
+    async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
+        let ident = TableIdent { namespace: self.namespace.clone(), name: name.to_string() };
+        let Some(table) = self.catalog.load_table(&ident).await? else {
+            return Ok(None);
+        };
+        let table = IcebergTable::try_new(table.metadata)?;
+        Ok(Some(Arc::new(table.with_catalog(Arc::clone(&self.catalog), ident))))
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.tables.iter().any(|table| table == name)
+    }
+}
@@ -0,0 +1,177 @@
+//! Compacting small data files.
+//!
+//! Tables appended to often (streaming ingestion, small `INSERT`s) accumulate data
+//! files far below the target file size, and scans slow down with the number of files
+//! they open. Compacting a table rewrites its data files smaller than a minimum size,
+//! by default three quarters of the table's `write.target-file-size-bytes`, into files
+//! of the target size, and commits the rewrite as a `replace` snapshot. Tables with
+//! fewer small files than [`CompactionOptions::min_input_files`] are left alone.
+//!
+//! The rewrite removes the files it read, so it fails rather than resurrect rows if a
+//! concurrent commit removed one of them in the meantime; the next run tries again.
+//! [`Compactor`] compacts every table of a catalog, one table per task, as the
+//! coordinator schedules it.
+
+use crate::metadata::TableMetadata;
+use crate::rest::{RestCatalog, TableIdent};
+use crate::table::IcebergTable;
+use crate::write::{
+    adapt, check_writable, object_path, property, DataFileWriter, SnapshotUpdate,
+    DEFAULT_TARGET_FILE_SIZE, TARGET_FILE_SIZE_PROPERTY,
+};
+use datafusion::catalog::Session;
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use object_store::ObjectStore;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Small files a table needs for a compaction unless configured otherwise.
+pub const DEFAULT_MIN_INPUT_FILES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionOptions {
+    /// Compact only tables with at least this many small files.
+    pub min_input_files: usize,
+    /// Size below which a file is small, in bytes; by default three quarters of the
+    /// table's target file size.
+    pub min_file_size: Option<u64>,
+}
+
+impl Default for CompactionOptions {
+    fn default() -> Self {
+        Self { min_input_files: DEFAULT_MIN_INPUT_FILES, min_file_size: None }
+    }
+}
+
+impl CompactionOptions {
+    pub fn with_min_input_files(mut self, min_input_files: usize) -> Self {
+        self.min_input_files = min_input_files;
+        self
+    }
+
+    pub fn with_min_file_size(mut self, min_file_size: u64) -> Self {
+        self.min_file_size = Some(min_file_size);
+        self
+    }
+}
+
+/// What a compaction rewrote.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Small files removed.
+    pub rewritten_files: usize,
+    /// Files they were rewritten into.
+    pub added_files: usize,
+    pub rows: i64,
+}
+
+/// Compact `table`, see the [module docs](self). `None` if it had too few small files.
+pub(crate) async fn compact(
+    table: &IcebergTable,
+    catalog: &RestCatalog,
+    ident: &TableIdent,
+    store: Arc<dyn ObjectStore>,
+    options: &CompactionOptions,
+) -> DataFusionResult<Option<CompactionReport>> {
+    let metadata = table.metadata();
+    check_writable(metadata)?;
+    let min_file_size = match options.min_file_size {
+        Some(size) => size,
+        None => target_file_size(metadata)? / 4 * 3,
+    };
+    let small: Vec<_> = table
+        .data_files(store.as_ref())
+        .await?
+        .into_iter()
+        .filter(|file| (file.file_size_in_bytes as u64) < min_file_size)
+        .collect();
+    // Rewriting a single file gains nothing.
+    if small.len() < options.min_input_files.max(2) {
+        return Ok(None);
+    }
+    let schema = table.schema();
+    let mut writer = DataFileWriter::try_new(Arc::clone(&store), metadata)?;
+    for file in &small {
+        let bytes = store.get(&object_path(&file.file_path)?).await?.bytes().await?;
+        for batch in ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()? {
+            writer.write(&adapt(&schema, &batch?)?).await?;
+        }
+    }
+    let update = SnapshotUpdate {
+        added: writer.finish().await?,
+        removed: small.iter().map(|file| file.file_path.clone()).collect::<HashSet<_>>(),
+        replace: true,
+        ..Default::default()
+    };
+    update.commit(catalog, ident, store.as_ref(), metadata.clone()).await?;
+    Ok(Some(CompactionReport {
+        rewritten_files: small.len(),
+        added_files: update.added.len(),
+        rows: small.iter().map(|file| file.record_count).sum(),
+    }))
+}
+
+fn target_file_size(metadata: &TableMetadata) -> DataFusionResult<u64> {
+    let size: usize = property(metadata, TARGET_FILE_SIZE_PROPERTY, DEFAULT_TARGET_FILE_SIZE)?;
+    Ok(size as u64)
+}
+
+/// Compacts the tables of a catalog, through the object stores of a session.
+pub struct Compactor {
+    catalog: Arc<RestCatalog>,
+    state: Arc<dyn Session>,
+    options: CompactionOptions,
+}
+
+impl fmt::Debug for Compactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Compactor")
+            .field("catalog", &self.catalog)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Compactor {
+    pub fn new(catalog: Arc<RestCatalog>, state: Arc<dyn Session>) -> Self {
+        Self { catalog, state, options: CompactionOptions::default() }
+    }
+
+    pub fn with_options(mut self, options: CompactionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The tables of every namespace of the catalog.
+    pub async fn tables(&self) -> DataFusionResult<Vec<TableIdent>> {
+        let mut tables = Vec::new();
+        let mut pending = self.catalog.list_namespaces(None).await?;
+        while let Some(namespace) = pending.pop() {
+            // Catalogs without nested namespaces may ignore the parent.
+            let children = self.catalog.list_namespaces(Some(&namespace)).await?;
+            pending.extend(
+                children
+                    .into_iter()
+                    .filter(|child| child.len() > namespace.len() && child.starts_with(&namespace)),
+            );
+            tables.extend(self.catalog.list_tables(&namespace).await?);
+        }
+        Ok(tables)
+    }
+
+    /// Compact the table `ident`, if it has enough small files.
+    pub async fn compact(&self, ident: &TableIdent) -> DataFusionResult<Option<CompactionReport>> {
+        let Some(loaded) = self.catalog.load_table(ident).await? else {
+            return Err(DataFusionError::Plan(format!(
+                "Iceberg table {} does not exist",
+                ident.name
+            )));
+        };
+        let table = IcebergTable::try_new(loaded.metadata)?
+            .with_catalog(Arc::clone(&self.catalog), ident.clone());
+        table.compact(self.state.as_ref(), &self.options).await
+    }
+}
@@ -0,0 +1,36 @@
+//! Apache Iceberg tables.
+//!
+//! [`RestCatalog`] talks to catalogs implementing the Iceberg REST protocol (Nessie,
+//! Polaris, Tabular, Gravitino, ...), so tables are found by name rather than by the
+//! location of their metadata files. [`IcebergCatalogProvider`] exposes such a catalog
+//! to SQL, and `INSERT`s into its tables commit snapshots other engines can read (see
+//! [`write`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_iceberg::{IcebergCatalogProvider, RestCatalog};
+//! use std::sync::Arc;
+//!
+//! let catalog = RestCatalog::new("https://polaris.example.com/api/catalog")
+//!     .with_warehouse("analytics")
+//!     .with_credential("client-id:client-secret");
+//! let provider = IcebergCatalogProvider::try_new(Arc::new(catalog)).await?;
+//! ctx.register_catalog("iceberg", Arc::new(provider));
+//! ctx.sql("SELECT count(*) FROM iceberg.sales.orders").await?;
+//! ctx.sql("INSERT INTO iceberg.sales.orders VALUES (4, 40.0)").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod catalog;
+pub mod compaction;
+pub mod metadata;
+pub mod partition;
+pub mod rest;
+pub mod retention;
+pub mod table;
+pub mod write;
+
+pub use catalog::IcebergCatalogProvider;
+pub use rest::RestCatalog;
+pub use table::IcebergTable;
@@ -0,0 +1,285 @@
+//! Iceberg table metadata, as served by catalogs and stored in `metadata.json`.
+//!
+//! Only what reading a table and committing snapshots to it needs is modelled; other
+//! fields are ignored.
+
+use crate::partition::PartitionField;
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Key of the Arrow field metadata holding an Iceberg field ID, as Parquet uses it.
+pub const FIELD_ID_KEY: &str = "PARQUET:field_id";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TableMetadata {
+    pub format_version: u8,
+    #[serde(default)]
+    pub table_uuid: Option<String>,
+    pub location: String,
+    #[serde(default)]
+    pub current_schema_id: Option<i32>,
+    #[serde(default)]
+    pub schemas: Vec<IcebergSchema>,
+    /// The only schema of format version 1 tables written without `schemas`.
+    #[serde(default)]
+    pub schema: Option<IcebergSchema>,
+    #[serde(default)]
+    pub current_snapshot_id: Option<i64>,
+    #[serde(default)]
+    pub snapshots: Vec<Snapshot>,
+    /// Branches and tags, by name.
+    #[serde(default)]
+    pub refs: HashMap<String, SnapshotRef>,
+    /// The sequence number of the latest snapshot, `0` in format version 1.
+    #[serde(default)]
+    pub last_sequence_number: i64,
+    #[serde(default)]
+    pub default_spec_id: i32,
+    #[serde(default)]
+    pub partition_specs: Vec<PartitionSpec>,
+    #[serde(default)]
+    pub properties: HashMap<String, String>,
+}
+
+impl TableMetadata {
+    /// The table's current schema.
+    pub fn current_schema(&self) -> DataFusionResult<&IcebergSchema> {
+        let current = match self.current_schema_id {
+            Some(id) => self.schemas.iter().find(|schema| schema.schema_id == id),
+            None => self.schema.as_ref().or(self.schemas.last()),
+        };
+        current.ok_or_else(|| {
+            DataFusionError::Execution(format!("Iceberg table at {} has no schema", self.location))
+        })
+    }
+
+    /// The snapshot readers see, `None` for a table with no data yet.
+    pub fn current_snapshot(&self) -> Option<&Snapshot> {
+        // `-1` stands for no snapshot in older metadata.
+        let id = self.current_snapshot_id.filter(|id| *id != -1)?;
+        self.snapshots.iter().find(|snapshot| snapshot.snapshot_id == id)
+    }
+
+    /// Whether files of the partition spec `spec_id` are unpartitioned.
+    pub fn is_unpartitioned(&self, spec_id: i32) -> bool {
+        let spec = self.partition_specs.iter().find(|spec| spec.spec_id == spec_id);
+        spec.map_or(spec_id == 0, |spec| spec.fields.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Snapshot {
+    pub snapshot_id: i64,
+    #[serde(default)]
+    pub timestamp_ms: i64,
+    /// Avro file listing the snapshot's manifests.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest_list: Option<String>,
+    /// Manifests of format version 1 snapshots written without a manifest list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifests: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_snapshot_id: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence_number: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_id: Option<i32>,
+    /// The snapshot's `operation` (`append`, `overwrite`, ...) and counts of what it
+    /// changed.
+    #[serde(default)]
+    pub summary: HashMap<String, String>,
+}
+
+/// A branch or tag.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct SnapshotRef {
+    pub snapshot_id: i64,
+    /// `branch` or `tag`.
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PartitionSpec {
+    pub spec_id: i32,
+    #[serde(default)]
+    pub fields: Vec<PartitionField>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IcebergSchema {
+    #[serde(default)]
+    pub schema_id: i32,
+    pub fields: Vec<NestedField>,
+}
+
+impl IcebergSchema {
+    /// The Arrow schema data files of this schema are read as.
+    pub fn to_arrow(&self) -> DataFusionResult<SchemaRef> {
+        let fields =
+            self.fields.iter().map(NestedField::to_arrow).collect::<DataFusionResult<Vec<_>>>()?;
+        Ok(Arc::new(Schema::new(fields)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NestedField {
+    pub id: i32,
+    pub name: String,
+    pub required: bool,
+    #[serde(rename = "type")]
+    pub field_type: IcebergType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub doc: Option<String>,
+}
+
+impl NestedField {
+    fn to_arrow(&self) -> DataFusionResult<Field> {
+        Ok(field(&self.name, self.field_type.to_arrow()?, !self.required, self.id))
+    }
+}
+
+/// An Iceberg type: a primitive (`"long"`, `"decimal(10,2)"`, ...) or a nested one.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum IcebergType {
+    Primitive(String),
+    Nested(NestedType),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum NestedType {
+    Struct {
+        fields: Vec<NestedField>,
+    },
+    #[serde(rename_all = "kebab-case")]
+    List {
+        element_id: i32,
+        element: Box<IcebergType>,
+        element_required: bool,
+    },
+    #[serde(rename_all = "kebab-case")]
+    Map {
+        key_id: i32,
+        key: Box<IcebergType>,
+        value_id: i32,
+        value: Box<IcebergType>,
+        value_required: bool,
+    },
+}
+
+impl IcebergType {
+    pub fn to_arrow(&self) -> DataFusionResult<DataType> {
+        match self {
+            IcebergType::Primitive(name) => primitive_to_arrow(name),
+            IcebergType::Nested(NestedType::Struct { fields }) => {
+                let fields = fields
+                    .iter()
+                    .map(NestedField::to_arrow)
+                    .collect::<DataFusionResult<Fields>>()?;
+                Ok(DataType::Struct(fields))
+            }
+            IcebergType::Nested(NestedType::List { element_id, element, element_required }) => {
+                let element = field("element", element.to_arrow()?, !element_required, *element_id);
+                Ok(DataType::List(Arc::new(element)))
+            }
+            IcebergType::Nested(NestedType::Map {
+                key_id,
+                key,
+                value_id,
+                value,
+                value_required,
+            }) => {
+                let entries = Fields::from(vec![
+                    field("key", key.to_arrow()?, false, *key_id),
+                    field("value", value.to_arrow()?, !value_required, *value_id),
+                ]);
+                let entries = Field::new("key_value", DataType::Struct(entries), false);
+                Ok(DataType::Map(Arc::new(entries), false))
+            }
+        }
+    }
+}
+
+fn field(name: &str, data_type: DataType, nullable: bool, id: i32) -> Field {
+    let metadata = HashMap::from([(FIELD_ID_KEY.to_string(), id.to_string())]);
+    Field::new(name, data_type, nullable).with_metadata(metadata)
+}
+
+fn primitive_to_arrow(name: &str) -> DataFusionResult<DataType> {
+    let utc = || Some(Arc::from("+00:00"));
+    Ok(match name {
+        "boolean" => DataType::Boolean,
+        "int" => DataType::Int32,
+        "long" => DataType::Int64,
+        "float" => DataType::Float32,
+        "double" => DataType::Float64,
+        "date" => DataType::Date32,
+        "time" => DataType::Time64(TimeUnit::Microsecond),
+        "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, None),
+        "timestamptz" => DataType::Timestamp(TimeUnit::Microsecond, utc()),
+        "timestamp_ns" => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        "timestamptz_ns" => DataType::Timestamp(TimeUnit::Nanosecond, utc()),
+        "string" => DataType::Utf8,
+        "uuid" => DataType::FixedSizeBinary(16),
+        "binary" => DataType::Binary,
+        _ => {
+            if let Some(length) = name.strip_prefix("fixed[").and_then(|n| n.strip_suffix(']')) {
+                DataType::FixedSizeBinary(parse_number(name, length)?)
+            } else if let Some(args) =
+                name.strip_prefix("decimal(").and_then(|n| n.strip_suffix(')'))
+            {
+                let (precision, scale) = args.split_once(',').unwrap_or((args, "0"));
+                DataType::Decimal128(parse_number(name, precision)?, parse_number(name, scale)?)
+            } else {
+                return Err(DataFusionError::NotImplemented(format!(
+                    "Iceberg type '{name}' is not supported"
+                )));
+            }
+        }
+    })
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, number: &str) -> DataFusionResult<T> {
+    number
+        .trim()
+        .parse()
+        .map_err(|_| DataFusionError::Execution(format!("invalid Iceberg type '{name}'")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_to_arrow() {
+        let schema: IcebergSchema = serde_json::from_str(
+            r#"{"schema-id": 1, "fields": [
+                {"id": 1, "name": "id", "required": true, "type": "long"},
+                {"id": 2, "name": "price", "required": false, "type": "decimal(10, 2)"},
+                {"id": 3, "name": "tags", "required": false, "type": {
+                    "type": "list", "element-id": 4, "element": "string",
+                    "element-required": true}}
+            ]}"#,
+        )
+        .unwrap();
+        let arrow = schema.to_arrow().unwrap();
+        assert_eq!(arrow.field(0).data_type(), &DataType::Int64);
+        assert!(!arrow.field(0).is_nullable());
+        assert_eq!(arrow.field(1).data_type(), &DataType::Decimal128(10, 2));
+        let DataType::List(element) = arrow.field(2).data_type() else {
+            panic!("expected a list");
+        };
+        assert_eq!(element.metadata()[FIELD_ID_KEY], "4");
+        assert!(IcebergType::Primitive("variant".into()).to_arrow().is_err());
+    }
+}
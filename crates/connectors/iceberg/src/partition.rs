@@ -0,0 +1,647 @@
+//! Partitioning rows by a table's partition spec.
+//!
+//! A partition spec derives the partition of a row from its columns with transforms:
+//! `identity`, `bucket[N]` (a hash of the value, modulo `N`), `truncate[W]`, `year`,
+//! `month`, `day` and `hour` of dates and timestamps, and `void`. Rows written to a
+//! partitioned table go to files of their partition only, in a directory of its own
+//! under `data/` (`data/day=2024-05-01/bucket=3/...`), and the partition values of each
+//! file are recorded in its manifest entry for readers to prune files by. Only
+//! top-level columns can be partitioned by.
+
+use crate::metadata::TableMetadata;
+use apache_avro::types::Value as AvroValue;
+use datafusion::arrow::array::{
+    new_empty_array, new_null_array, Array, ArrayRef, AsArray, Int32Array, PrimitiveArray,
+    StringArray, UInt32Array,
+};
+use datafusion::arrow::compute::take;
+use datafusion::arrow::datatypes::{
+    DataType, Date32Type, Decimal128Type, Int32Type, Int64Type, Time64MicrosecondType, TimeUnit,
+    TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+    TimestampSecondType,
+};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::scalar::ScalarValue;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// How a partition value is derived from a column.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Transform {
+    Identity,
+    Bucket(u32),
+    Truncate(u32),
+    Year,
+    Month,
+    Day,
+    Hour,
+    Void,
+    /// A transform this crate does not know, which tables partitioned by cannot be
+    /// written to.
+    Other(String),
+}
+
+impl From<String> for Transform {
+    fn from(name: String) -> Self {
+        let argument = |prefix: &str| {
+            name.strip_prefix(prefix)?.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+        };
+        match name.as_str() {
+            "identity" => Transform::Identity,
+            "year" => Transform::Year,
+            "month" => Transform::Month,
+            "day" => Transform::Day,
+            "hour" => Transform::Hour,
+            "void" => Transform::Void,
+            _ => match (argument("bucket"), argument("truncate")) {
+                (Some(n), _) if n > 0 => Transform::Bucket(n),
+                (_, Some(width)) if width > 0 => Transform::Truncate(width),
+                _ => Transform::Other(name),
+            },
+        }
+    }
+}
+
+impl From<Transform> for String {
+    fn from(transform: Transform) -> Self {
+        transform.to_string()
+    }
+}
+
+impl FromStr for Transform {
+    type Err = std::convert::Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(Transform::from(name.to_string()))
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Transform::Identity => f.write_str("identity"),
+            Transform::Bucket(n) => write!(f, "bucket[{n}]"),
+            Transform::Truncate(width) => write!(f, "truncate[{width}]"),
+            Transform::Year => f.write_str("year"),
+            Transform::Month => f.write_str("month"),
+            Transform::Day => f.write_str("day"),
+            Transform::Hour => f.write_str("hour"),
+            Transform::Void => f.write_str("void"),
+            Transform::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Transform {
+    /// The partition values of `column`.
+    pub fn apply(&self, column: &ArrayRef) -> DataFusionResult<ArrayRef> {
+        let data_type = column.data_type();
+        let unsupported = || {
+            DataFusionError::NotImplemented(format!(
+                "partition transform {self} of {data_type} columns is not supported"
+            ))
+        };
+        Ok(match self {
+            Transform::Identity => Arc::clone(column),
+            Transform::Void => new_null_array(data_type, column.len()),
+            Transform::Bucket(n) => Arc::new(bucket(column, *n).ok_or_else(unsupported)?),
+            Transform::Truncate(width) => truncate(column, *width).ok_or_else(unsupported)?,
+            Transform::Year | Transform::Month | Transform::Day => {
+                let days = days(column).ok_or_else(unsupported)?;
+                match self {
+                    Transform::Day => Arc::new(days.unary::<_, Date32Type>(|days| days as i32)),
+                    Transform::Month => Arc::new(days.unary::<_, Int32Type>(|days| {
+                        let (year, month, _) = civil_from_days(days);
+                        ((year - 1970) * 12 + month as i64 - 1) as i32
+                    })),
+                    _ => Arc::new(
+                        days.unary::<_, Int32Type>(|days| (civil_from_days(days).0 - 1970) as i32),
+                    ),
+                }
+            }
+            Transform::Hour => {
+                let micros = micros(column).ok_or_else(unsupported)?;
+                Arc::new(
+                    micros
+                        .unary::<_, Int32Type>(|micros| micros.div_euclid(MICROS_PER_HOUR) as i32),
+                )
+            }
+            Transform::Other(_) => return Err(unsupported()),
+        })
+    }
+
+    /// `value`, a partition value of this transform, as it appears in paths.
+    fn human_string(&self, value: &ScalarValue) -> String {
+        match (self, value) {
+            (_, value) if value.is_null() => "null".to_string(),
+            (Transform::Year, ScalarValue::Int32(Some(years))) => format!("{}", 1970 + years),
+            (Transform::Month, ScalarValue::Int32(Some(months))) => {
+                let (years, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+                format!("{}-{month:02}", 1970 + years)
+            }
+            (Transform::Hour, ScalarValue::Int32(Some(hours))) => {
+                let (year, month, day) = civil_from_days(hours.div_euclid(24) as i64);
+                format!("{year}-{month:02}-{day:02}-{:02}", hours.rem_euclid(24))
+            }
+            (_, value) => value.to_string(),
+        }
+    }
+}
+
+/// A field of a partition spec.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PartitionField {
+    /// The ID of the column the partition value is derived from.
+    pub source_id: i32,
+    /// Absent from the specs of some format version 1 tables.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field_id: Option<i32>,
+    pub name: String,
+    pub transform: Transform,
+}
+
+/// The partition values of a data file, by partition field name, as JSON values:
+/// numbers for integers, dates (days) and timestamps (microseconds).
+pub type PartitionValues = BTreeMap<String, Value>;
+
+/// Splits rows by the partition spec of a table, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub(crate) struct Partitioner {
+    fields: Vec<PartitionColumn>,
+}
+
+#[derive(Debug, Clone)]
+struct PartitionColumn {
+    field: PartitionField,
+    field_id: i32,
+    /// The index of the source column in the table's schema.
+    source: usize,
+    data_type: DataType,
+}
+
+impl Partitioner {
+    /// The partitioner of the partition spec `spec_id` of a table, whose rows have the
+    /// columns of its current schema.
+    pub(crate) fn try_new(metadata: &TableMetadata, spec_id: i32) -> DataFusionResult<Self> {
+        let spec = metadata.partition_specs.iter().find(|spec| spec.spec_id == spec_id);
+        let fields = match spec {
+            Some(spec) => spec.fields.clone(),
+            None if spec_id == 0 => vec![],
+            None => {
+                return Err(DataFusionError::Execution(format!(
+                    "Iceberg table at {} has no partition spec {spec_id}",
+                    metadata.location
+                )))
+            }
+        };
+        let schema = metadata.current_schema()?;
+        let mut columns = vec![];
+        for (i, field) in fields.into_iter().enumerate() {
+            let Some(source) = schema.fields.iter().position(|f| f.id == field.source_id) else {
+                return Err(DataFusionError::NotImplemented(format!(
+                    "partition field {} is not of a top-level column",
+                    field.name
+                )));
+            };
+            let source_type = schema.fields[source].field_type.to_arrow()?;
+            let data_type = field.transform.apply(&new_empty_array(&source_type))?;
+            let data_type = data_type.data_type().clone();
+            avro_type(&data_type)?;
+            // IDs of partition fields start at 1000 where they are implicit.
+            let field_id = field.field_id.unwrap_or(1000 + i as i32);
+            columns.push(PartitionColumn { field, field_id, source, data_type });
+        }
+        Ok(Self { fields: columns })
+    }
+
+    /// The rows of `batch`, a batch of the table's columns, by partition, partitions in
+    /// the order their first rows are in.
+    pub(crate) fn split(
+        &self,
+        batch: &RecordBatch,
+    ) -> DataFusionResult<Vec<(Vec<ScalarValue>, RecordBatch)>> {
+        if self.fields.is_empty() {
+            return Ok(vec![(vec![], batch.clone())]);
+        }
+        let values = self
+            .fields
+            .iter()
+            .map(|column| column.field.transform.apply(batch.column(column.source)))
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        let mut partitions: Vec<(Vec<ScalarValue>, Vec<u32>)> = vec![];
+        let mut positions = HashMap::new();
+        for row in 0..batch.num_rows() {
+            let key = values
+                .iter()
+                .map(|values| ScalarValue::try_from_array(values, row))
+                .collect::<DataFusionResult<Vec<_>>>()?;
+            let position = *positions.entry(key.clone()).or_insert_with(|| {
+                partitions.push((key, vec![]));
+                partitions.len() - 1
+            });
+            partitions[position].1.push(row as u32);
+        }
+        partitions
+            .into_iter()
+            .map(|(key, rows)| {
+                let rows = UInt32Array::from(rows);
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|column| Ok(take(column, &rows, None)?))
+                    .collect::<DataFusionResult<Vec<_>>>()?;
+                Ok((key, RecordBatch::try_new(batch.schema(), columns)?))
+            })
+            .collect()
+    }
+
+    /// The directory of the files of the partition of `key` under `data/`, ending with a
+    /// `/` unless empty.
+    pub(crate) fn path(&self, key: &[ScalarValue]) -> String {
+        self.fields
+            .iter()
+            .zip(key)
+            .map(|(column, value)| {
+                let value = column.field.transform.human_string(value);
+                format!("{}={}/", escape(&column.field.name), escape(&value))
+            })
+            .collect()
+    }
+
+    /// The partition values of `key`, as data files record them.
+    pub(crate) fn values(&self, key: &[ScalarValue]) -> DataFusionResult<PartitionValues> {
+        self.fields
+            .iter()
+            .zip(key)
+            .map(|(column, value)| Ok((column.field.name.clone(), to_json(value)?)))
+            .collect()
+    }
+
+    /// The fields of the Avro record of partition values in manifests.
+    pub(crate) fn avro_fields(&self) -> DataFusionResult<Vec<Value>> {
+        self.fields
+            .iter()
+            .map(|column| {
+                Ok(json!({
+                    "name": column.field.name,
+                    "type": ["null", avro_type(&column.data_type)?],
+                    "default": null,
+                    "field-id": column.field_id,
+                }))
+            })
+            .collect()
+    }
+
+    /// `values` as an Avro record of [`Self::avro_fields`].
+    pub(crate) fn to_avro(&self, values: &PartitionValues) -> DataFusionResult<AvroValue> {
+        let fields = self.fields.iter().map(|column| {
+            let name = &column.field.name;
+            let value = match values.get(name).unwrap_or(&Value::Null) {
+                Value::Null => AvroValue::Union(0, Box::new(AvroValue::Null)),
+                value => {
+                    let value = to_avro(value, &column.data_type).ok_or_else(|| {
+                        DataFusionError::Execution(format!(
+                            "invalid value {value} of partition field {name}"
+                        ))
+                    })?;
+                    AvroValue::Union(1, Box::new(value))
+                }
+            };
+            Ok((name.clone(), value))
+        });
+        Ok(AvroValue::Record(fields.collect::<DataFusionResult<_>>()?))
+    }
+
+    /// The spec's fields, as manifests record them in their metadata.
+    pub(crate) fn spec_json(&self) -> String {
+        let fields: Vec<_> = self
+            .fields
+            .iter()
+            .map(|column| PartitionField {
+                field_id: Some(column.field_id),
+                ..column.field.clone()
+            })
+            .collect();
+        serde_json::to_string(&fields).expect("partition fields are serializable")
+    }
+}
+
+fn avro_type(data_type: &DataType) -> DataFusionResult<Value> {
+    Ok(match data_type {
+        DataType::Boolean => json!("boolean"),
+        DataType::Int32 => json!("int"),
+        DataType::Int64 => json!("long"),
+        DataType::Float32 => json!("float"),
+        DataType::Float64 => json!("double"),
+        DataType::Utf8 => json!("string"),
+        DataType::Date32 => json!({"type": "int", "logicalType": "date"}),
+        DataType::Time64(TimeUnit::Microsecond) => {
+            json!({"type": "long", "logicalType": "time-micros"})
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, zone) => {
+            json!({"type": "long", "logicalType": "timestamp-micros", "adjust-to-utc": zone.is_some()})
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, zone) => {
+            json!({"type": "long", "logicalType": "timestamp-nanos", "adjust-to-utc": zone.is_some()})
+        }
+        data_type => {
+            return Err(DataFusionError::NotImplemented(format!(
+                "partition values of type {data_type} are not supported"
+            )))
+        }
+    })
+}
+
+fn to_json(value: &ScalarValue) -> DataFusionResult<Value> {
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    Ok(match value {
+        ScalarValue::Boolean(Some(value)) => json!(value),
+        ScalarValue::Int32(Some(value)) | ScalarValue::Date32(Some(value)) => json!(value),
+        ScalarValue::Int64(Some(value))
+        | ScalarValue::Time64Microsecond(Some(value))
+        | ScalarValue::TimestampMicrosecond(Some(value), _)
+        | ScalarValue::TimestampNanosecond(Some(value), _) => json!(value),
+        ScalarValue::Float32(Some(value)) => json!(value),
+        ScalarValue::Float64(Some(value)) => json!(value),
+        ScalarValue::Utf8(Some(value)) => json!(value),
+        value => {
+            return Err(DataFusionError::NotImplemented(format!(
+                "partition value {value} is not supported"
+            )))
+        }
+    })
+}
+
+fn to_avro(value: &Value, data_type: &DataType) -> Option<AvroValue> {
+    Some(match data_type {
+        DataType::Boolean => AvroValue::Boolean(value.as_bool()?),
+        DataType::Int32 => AvroValue::Int(value.as_i64()?.try_into().ok()?),
+        DataType::Date32 => AvroValue::Date(value.as_i64()?.try_into().ok()?),
+        DataType::Int64 => AvroValue::Long(value.as_i64()?),
+        DataType::Time64(_) => AvroValue::TimeMicros(value.as_i64()?),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            AvroValue::TimestampMicros(value.as_i64()?)
+        }
+        DataType::Timestamp(_, _) => AvroValue::TimestampNanos(value.as_i64()?),
+        DataType::Float32 => AvroValue::Float(value.as_f64()? as f32),
+        DataType::Float64 => AvroValue::Double(value.as_f64()?),
+        DataType::Utf8 => AvroValue::String(value.as_str()?.to_string()),
+        _ => return None,
+    })
+}
+
+/// `value` percent-encoded for a path segment.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                escaped.push(byte as char)
+            }
+            byte => escaped.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    escaped
+}
+
+/// Days since the epoch of dates and timestamps.
+fn days(column: &ArrayRef) -> Option<PrimitiveArray<Int64Type>> {
+    match column.data_type() {
+        DataType::Date32 => Some(column.as_primitive::<Date32Type>().unary(|days| days as i64)),
+        _ => Some(micros(column)?.unary(|micros| micros.div_euclid(MICROS_PER_DAY))),
+    }
+}
+
+/// Microseconds since the epoch of timestamps.
+fn micros(column: &ArrayRef) -> Option<PrimitiveArray<Int64Type>> {
+    let DataType::Timestamp(unit, _) = column.data_type() else {
+        return None;
+    };
+    Some(match unit {
+        TimeUnit::Second => column.as_primitive::<TimestampSecondType>().unary(|s| s * 1_000_000),
+        TimeUnit::Millisecond => {
+            column.as_primitive::<TimestampMillisecondType>().unary(|ms| ms * 1000)
+        }
+        TimeUnit::Microsecond => column.as_primitive::<TimestampMicrosecondType>().unary(|us| us),
+        TimeUnit::Nanosecond => {
+            column.as_primitive::<TimestampNanosecondType>().unary(|ns| ns.div_euclid(1000))
+        }
+    })
+}
+
+/// The buckets of the values of `column`, by the 32-bit Murmur3 hash of their bytes as
+/// the Iceberg spec defines them.
+fn bucket(column: &ArrayRef, n: u32) -> Option<Int32Array> {
+    let bucket = |hash: u32| ((hash & i32::MAX as u32) % n) as i32;
+    let long = |value: i64| bucket(murmur3_32(&value.to_le_bytes()));
+    Some(match column.data_type() {
+        DataType::Int32 => column.as_primitive::<Int32Type>().unary(|v| long(v as i64)),
+        DataType::Date32 => column.as_primitive::<Date32Type>().unary(|v| long(v as i64)),
+        DataType::Int64 => column.as_primitive::<Int64Type>().unary(long),
+        DataType::Time64(TimeUnit::Microsecond) => {
+            column.as_primitive::<Time64MicrosecondType>().unary(long)
+        }
+        DataType::Timestamp(_, _) => micros(column)?.unary(long),
+        DataType::Utf8 => column
+            .as_string::<i32>()
+            .iter()
+            .map(|v| Some(bucket(murmur3_32(v?.as_bytes()))))
+            .collect(),
+        DataType::Binary => {
+            column.as_binary::<i32>().iter().map(|v| Some(bucket(murmur3_32(v?)))).collect()
+        }
+        DataType::FixedSizeBinary(_) => {
+            column.as_fixed_size_binary().iter().map(|v| Some(bucket(murmur3_32(v?)))).collect()
+        }
+        DataType::Decimal128(_, _) => {
+            let decimals = column.as_primitive::<Decimal128Type>();
+            decimals.iter().map(|v| Some(bucket(murmur3_32(&decimal_bytes(v?))))).collect()
+        }
+        _ => return None,
+    })
+}
+
+/// The unscaled value of a decimal as the fewest big-endian two's complement bytes.
+fn decimal_bytes(unscaled: i128) -> Vec<u8> {
+    let bytes = unscaled.to_be_bytes();
+    let sign = if unscaled < 0 { 0xff } else { 0 };
+    // Leading sign bytes are redundant while the next byte carries the sign bit.
+    let start =
+        (0..15).find(|&i| bytes[i] != sign || (bytes[i + 1] & 0x80) != (sign & 0x80)).unwrap_or(15);
+    bytes[start..].to_vec()
+}
+
+fn truncate(column: &ArrayRef, width: u32) -> Option<ArrayRef> {
+    Some(match column.data_type() {
+        DataType::Int32 => {
+            let width = width as i32;
+            Arc::new(
+                column
+                    .as_primitive::<Int32Type>()
+                    .unary::<_, Int32Type>(|v| v - v.rem_euclid(width)),
+            )
+        }
+        DataType::Int64 => {
+            let width = width as i64;
+            Arc::new(
+                column
+                    .as_primitive::<Int64Type>()
+                    .unary::<_, Int64Type>(|v| v - v.rem_euclid(width)),
+            )
+        }
+        DataType::Decimal128(precision, scale) => {
+            let width = width as i128;
+            let decimals = column.as_primitive::<Decimal128Type>();
+            let truncated = decimals.unary::<_, Decimal128Type>(|v| v - v.rem_euclid(width));
+            Arc::new(truncated.with_precision_and_scale(*precision, *scale).ok()?)
+        }
+        DataType::Utf8 => {
+            let strings = column.as_string::<i32>().iter();
+            let truncated: StringArray = strings
+                .map(|v| Some(v?.chars().take(width as usize).collect::<String>()))
+                .collect();
+            Arc::new(truncated)
+        }
+        _ => return None,
+    })
+}
+
+/// The year, month and day of `days` since 1970-01-01, in the proleptic Gregorian
+/// calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The days since 1970-01-01 of `year`, `month` and `day`, in the proleptic Gregorian
+/// calendar.
+pub(crate) fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = i64::from((month + 9) % 12);
+    let day_of_year = (153 * shifted_month + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// MurmurHash3's x86 32-bit hash of `data`, with seed 0.
+fn murmur3_32(data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mut hash = 0u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().expect("chunks of 4 bytes"));
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash = (hash ^ k).rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, byte) in tail.iter().enumerate() {
+            k ^= (*byte as u32) << (8 * i);
+        }
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{
+        Date32Array, Decimal128Array, Int64Array, TimestampMicrosecondArray,
+    };
+
+    #[test]
+    fn test_bucket_hashes_match_the_spec() {
+        // The examples of the Iceberg spec's appendix B.
+        let hash = |bytes: &[u8]| murmur3_32(bytes) as i32;
+        assert_eq!(hash(&34i64.to_le_bytes()), 2017239379);
+        assert_eq!(hash(&decimal_bytes(1420)), -500754589);
+        assert_eq!(hash(&17486i64.to_le_bytes()), -653330422);
+        assert_eq!(hash(&1510871468000000i64.to_le_bytes()), -2047944441);
+        assert_eq!(hash(b"iceberg"), 1210000089);
+        assert_eq!(decimal_bytes(-1), [0xff]);
+        assert_eq!(decimal_bytes(128), [0x00, 0x80]);
+
+        let column: ArrayRef = Arc::new(Int64Array::from(vec![Some(34), None]));
+        let buckets = Transform::Bucket(16).apply(&column).unwrap();
+        assert_eq!(
+            buckets.as_primitive::<Int32Type>().iter().collect::<Vec<_>>(),
+            [Some(2017239379 % 16), None]
+        );
+        let column: ArrayRef =
+            Arc::new(Decimal128Array::from(vec![1420]).with_precision_and_scale(4, 2).unwrap());
+        assert!(Transform::Bucket(4).apply(&column).is_ok());
+    }
+
+    #[test]
+    fn test_time_transforms() {
+        // 2017-11-16T22:31:08 and 1969-12-31T23:00:00.
+        let column: ArrayRef =
+            Arc::new(TimestampMicrosecondArray::from(vec![1_510_871_468_000_000, -3_600_000_000]));
+        let values = |transform: Transform| {
+            let values = transform.apply(&column).unwrap();
+            (0..2)
+                .map(|i| {
+                    let value = ScalarValue::try_from_array(&values, i).unwrap();
+                    transform.human_string(&value)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(Transform::Year), ["2017", "1969"]);
+        assert_eq!(values(Transform::Month), ["2017-11", "1969-12"]);
+        assert_eq!(values(Transform::Day), ["2017-11-16", "1969-12-31"]);
+        assert_eq!(values(Transform::Hour), ["2017-11-16-22", "1969-12-31-23"]);
+
+        let dates: ArrayRef = Arc::new(Date32Array::from(vec![17486]));
+        let months = Transform::Month.apply(&dates).unwrap();
+        assert_eq!(months.as_primitive::<Int32Type>().value(0), 47 * 12 + 10);
+        assert!(Transform::Hour.apply(&dates).is_err());
+        for days in [-719_468, -1, 0, 17486, 2_932_896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_transforms_parse() {
+        let transforms = ["identity", "bucket[16]", "truncate[4]", "day", "void", "zorder"];
+        for name in transforms {
+            assert_eq!(Transform::from(name.to_string()).to_string(), name);
+        }
+        assert_eq!(Transform::from("bucket[0]".to_string()), Transform::Other("bucket[0]".into()));
+        let truncated =
+            Transform::Truncate(10).apply(&(Arc::new(Int64Array::from(vec![-1, 19])) as ArrayRef));
+        assert_eq!(truncated.unwrap().as_primitive::<Int64Type>().values(), &[-10, 10]);
+        assert_eq!(escape("a b/ü"), "a%20b%2F%C3%BC");
+    }
+}
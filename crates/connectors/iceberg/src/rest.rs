@@ -0,0 +1,397 @@
+//! Client of the Iceberg REST catalog protocol.
+//!
+//! Requests carry a bearer token: either a fixed one, or one obtained with OAuth2
+//! client credentials from the catalog's token endpoint (`v1/oauth/tokens`) and
+//! renewed before it expires. The `prefix` and defaults the catalog returns from
+//! `v1/config` for the configured warehouse are applied to every request.
+
+use crate::metadata::{Snapshot, TableMetadata};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// Scope requested with client credentials unless configured otherwise.
+pub const DEFAULT_SCOPE: &str = "catalog";
+
+/// A namespace, one name per level.
+pub type Namespace = Vec<String>;
+
+/// A table in a namespace.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct TableIdent {
+    pub namespace: Namespace,
+    pub name: String,
+}
+
+/// A table loaded from the catalog.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct LoadedTable {
+    #[serde(default)]
+    pub metadata_location: Option<String>,
+    pub metadata: TableMetadata,
+    /// Table-specific configuration, such as storage credentials.
+    #[serde(default)]
+    pub config: HashMap<String, String>,
+}
+
+/// A condition a commit is applied under.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum TableRequirement {
+    AssertTableUuid {
+        uuid: String,
+    },
+    /// The branch `reference` is at `snapshot_id`, or does not exist if `None`.
+    #[serde(rename_all = "kebab-case")]
+    AssertRefSnapshotId {
+        #[serde(rename = "ref")]
+        reference: String,
+        snapshot_id: Option<i64>,
+    },
+}
+
+/// A change a commit makes to a table's metadata.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum TableUpdate {
+    AddSnapshot {
+        snapshot: Snapshot,
+    },
+    /// Point the branch `ref_name` at `snapshot_id`.
+    #[serde(rename_all = "kebab-case")]
+    SetSnapshotRef {
+        ref_name: String,
+        #[serde(rename = "type")]
+        kind: String,
+        snapshot_id: i64,
+    },
+    #[serde(rename_all = "kebab-case")]
+    RemoveSnapshots {
+        snapshot_ids: Vec<i64>,
+    },
+}
+
+/// Client of an Iceberg REST catalog.
+pub struct RestCatalog {
+    uri: String,
+    warehouse: Option<String>,
+    auth: Auth,
+    scope: String,
+    client: Client,
+    state: Mutex<State>,
+}
+
+enum Auth {
+    None,
+    Token(String),
+    Credential { client_id: String, client_secret: String },
+}
+
+#[derive(Default)]
+struct State {
+    /// The catalog's `v1/config` response, once fetched.
+    prefix: Option<String>,
+    token: Option<(String, Option<Instant>)>,
+}
+
+impl fmt::Debug for RestCatalog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RestCatalog")
+            .field("uri", &self.uri)
+            .field("warehouse", &self.warehouse)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RestCatalog {
+    /// A client of the catalog at `uri` (e.g. `https://polaris.example.com/api/catalog`).
+    pub fn new(uri: impl Into<String>) -> Self {
+        Self {
+            uri: uri.into().trim_end_matches('/').to_string(),
+            warehouse: None,
+            auth: Auth::None,
+            scope: DEFAULT_SCOPE.to_string(),
+            client: Client::new(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Use the catalog's `warehouse`.
+    pub fn with_warehouse(mut self, warehouse: impl Into<String>) -> Self {
+        self.warehouse = Some(warehouse.into());
+        self
+    }
+
+    /// Authenticate with a fixed bearer token.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.auth = Auth::Token(token.into());
+        self
+    }
+
+    /// Authenticate with OAuth2 client credentials. `credential` is
+    /// `client_id:client_secret`, or a client secret alone.
+    pub fn with_credential(mut self, credential: &str) -> Self {
+        let (client_id, client_secret) = credential.split_once(':').unwrap_or(("", credential));
+        self.auth = Auth::Credential {
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+        };
+        self
+    }
+
+    /// Scope requested with client credentials ([`DEFAULT_SCOPE`] by default).
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = scope.into();
+        self
+    }
+
+    /// Namespaces under `parent`, or top-level ones.
+    pub async fn list_namespaces(
+        &self,
+        parent: Option<&Namespace>,
+    ) -> DataFusionResult<Vec<Namespace>> {
+        #[derive(Deserialize)]
+        struct Page {
+            namespaces: Vec<Namespace>,
+            #[serde(rename = "next-page-token", default)]
+            next_page_token: Option<String>,
+        }
+        let mut query = Vec::new();
+        if let Some(parent) = parent {
+            query.push(("parent", encode_namespace(parent)));
+        }
+        let mut namespaces = Vec::new();
+        loop {
+            let page: Page = self.get("namespaces", &query).await?;
+            namespaces.extend(page.namespaces);
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => set_page_token(&mut query, token),
+                _ => return Ok(namespaces),
+            }
+        }
+    }
+
+    /// Tables in `namespace`.
+    pub async fn list_tables(&self, namespace: &Namespace) -> DataFusionResult<Vec<TableIdent>> {
+        #[derive(Deserialize)]
+        struct Page {
+            identifiers: Vec<TableIdent>,
+            #[serde(rename = "next-page-token", default)]
+            next_page_token: Option<String>,
+        }
+        let path = format!("namespaces/{}/tables", encode_namespace(namespace));
+        let mut query = Vec::new();
+        let mut tables = Vec::new();
+        loop {
+            let page: Page = self.get(&path, &query).await?;
+            tables.extend(page.identifiers);
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => set_page_token(&mut query, token),
+                _ => return Ok(tables),
+            }
+        }
+    }
+
+    /// The table's current metadata, `None` if there is no such table.
+    pub async fn load_table(&self, table: &TableIdent) -> DataFusionResult<Option<LoadedTable>> {
+        let request = self.request(Method::GET, &table_path(table), &[]).await?;
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        parse(response).await.map(Some)
+    }
+
+    /// Apply `updates` to the table's metadata if all `requirements` hold, returning
+    /// the new metadata. `None` if a requirement no longer holds because another
+    /// commit came first: the caller may reload the table and try again.
+    pub async fn commit_table(
+        &self,
+        table: &TableIdent,
+        requirements: Vec<TableRequirement>,
+        updates: Vec<TableUpdate>,
+    ) -> DataFusionResult<Option<LoadedTable>> {
+        #[derive(Serialize)]
+        struct CommitTableRequest {
+            requirements: Vec<TableRequirement>,
+            updates: Vec<TableUpdate>,
+        }
+        let body = CommitTableRequest { requirements, updates };
+        let request = self.request(Method::POST, &table_path(table), &[]).await?.json(&body);
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::CONFLICT {
+            return Ok(None);
+        }
+        parse(response).await.map(Some)
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> DataFusionResult<T> {
+        parse(self.send(self.request(Method::GET, path, query).await?).await?).await
+    }
+
+    /// A request of `path` under the catalog's prefix.
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> DataFusionResult<RequestBuilder> {
+        let prefix = self.prefix().await?;
+        Ok(self.client.request(method, format!("{}/v1/{prefix}{path}", self.uri)).query(query))
+    }
+
+    /// Send `request` with the bearer token.
+    async fn send(&self, request: RequestBuilder) -> DataFusionResult<Response> {
+        let request = match self.token().await? {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        };
+        let response = request.send().await.map_err(http_error)?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            // Fetched again on the next request, in case it was revoked early.
+            self.state.lock().await.token = None;
+        }
+        Ok(response)
+    }
+
+    /// The path prefix from the catalog's configuration, with a trailing `/`.
+    async fn prefix(&self) -> DataFusionResult<String> {
+        if let Some(prefix) = &self.state.lock().await.prefix {
+            return Ok(prefix.clone());
+        }
+        #[derive(Deserialize)]
+        struct Config {
+            #[serde(default)]
+            defaults: HashMap<String, String>,
+            #[serde(default)]
+            overrides: HashMap<String, String>,
+        }
+        let mut request = self.client.get(format!("{}/v1/config", self.uri));
+        if let Some(warehouse) = &self.warehouse {
+            request = request.query(&[("warehouse", warehouse)]);
+        }
+        let config: Config = parse(self.send(request).await?).await?;
+        let prefix = config.overrides.get("prefix").or(config.defaults.get("prefix"));
+        let prefix = prefix.map(|p| format!("{}/", p.trim_matches('/'))).unwrap_or_default();
+        self.state.lock().await.prefix = Some(prefix.clone());
+        Ok(prefix)
+    }
+
+    async fn token(&self) -> DataFusionResult<Option<String>> {
+        let (client_id, client_secret) = match &self.auth {
+            Auth::None => return Ok(None),
+            Auth::Token(token) => return Ok(Some(token.clone())),
+            Auth::Credential { client_id, client_secret } => (client_id, client_secret),
+        };
+        let mut state = self.state.lock().await;
+        if let Some((token, expires)) = &state.token {
+            if expires.map_or(true, |expires| Instant::now() < expires) {
+                return Ok(Some(token.clone()));
+            }
+        }
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            #[serde(default)]
+            expires_in: Option<u64>,
+        }
+        let form = [
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("scope", &self.scope),
+        ];
+        let response =
+            self.client.post(format!("{}/v1/oauth/tokens", self.uri)).form(&form).send().await;
+        let token: TokenResponse = parse(response.map_err(http_error)?).await?;
+        // Renewed a little early, so it does not expire in flight.
+        let expires =
+            token.expires_in.map(|secs| Instant::now() + Duration::from_secs(secs).mul_f64(0.9));
+        state.token = Some((token.access_token.clone(), expires));
+        Ok(Some(token.access_token))
+    }
+}
+
+/// The body of a successful response, or the catalog's error.
+async fn parse<T: DeserializeOwned>(response: Response) -> DataFusionResult<T> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(catalog_error(status, &response.text().await.unwrap_or_default()));
+    }
+    response.json().await.map_err(http_error)
+}
+
+fn set_page_token(query: &mut Vec<(&str, String)>, token: String) {
+    query.retain(|(key, _)| *key != "pageToken");
+    query.push(("pageToken", token));
+}
+
+fn table_path(table: &TableIdent) -> String {
+    let namespace = encode_namespace(&table.namespace);
+    format!("namespaces/{namespace}/tables/{}", encode_component(&table.name))
+}
+
+/// A namespace in a URL path: its levels separated by the unit separator.
+fn encode_namespace(namespace: &Namespace) -> String {
+    namespace.iter().map(|level| encode_component(level)).collect::<Vec<_>>().join("%1F")
+}
+
+fn encode_component(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+fn http_error(e: reqwest::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+/// The catalog's error response (`{"error": {"message", "type", "code"}}`) as an error.
+fn catalog_error(status: StatusCode, body: &str) -> DataFusionError {
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        error: ErrorModel,
+    }
+    #[derive(Deserialize)]
+    struct ErrorModel {
+        message: String,
+        #[serde(rename = "type", default)]
+        kind: String,
+    }
+    let message = match serde_json::from_str::<ErrorResponse>(body) {
+        Ok(response) => format!("{}: {}", response.error.kind, response.error.message),
+        Err(_) => body.to_string(),
+    };
+    let message = format!("Iceberg REST catalog returned {status}: {message}");
+    match status {
+        StatusCode::NOT_FOUND => DataFusionError::Plan(message),
+        _ => DataFusionError::Execution(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_namespaces_in_paths() {
+        let namespace = vec!["sales".to_string(), "eu west".to_string()];
+        assert_eq!(encode_namespace(&namespace), "sales%1Feu%20west");
+    }
+}
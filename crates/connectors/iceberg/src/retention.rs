@@ -0,0 +1,342 @@
+//! Dropping what tables no longer need.
+//!
+//! Tables appended to for good grow for good: in rows, in snapshots, and in files no
+//! snapshot references any more. A [`RetentionPolicy`] bounds each of them:
+//!
+//! - `partition_max_age`: the data files of partitions whose time ended longer ago
+//!   are removed, as one `delete` snapshot. The time of a partition is that of its
+//!   `year`, `month`, `day` or `hour` partition fields, or `identity` fields of date
+//!   and timestamp columns; a file is dropped once all of them ended;
+//! - `snapshot_max_age`: older snapshots are expired, except the current one, those
+//!   branches and tags point at, and the `min_snapshots` latest;
+//! - `orphan_file_min_age`: files under the table's `data/` directory, and manifests
+//!   and manifest lists under its `metadata/` directory, that no snapshot left
+//!   references and older than this are deleted. The age keeps the files of commits
+//!   still being written from being taken for orphans; metadata files are left to the
+//!   catalog.
+//!
+//! The rules apply in that order, so the files only expired snapshots referenced are
+//! deleted by the same run. A dry run reports what would be dropped, expired and
+//! deleted without changing anything, leaving aside the snapshot dropping partitions
+//! would add. [`Retention`] applies policies to tables of a catalog, as the
+//! coordinator schedules it.
+
+use crate::metadata::TableMetadata;
+use crate::partition::{days_from_civil, PartitionValues, Transform};
+use crate::rest::{RestCatalog, TableIdent, TableRequirement, TableUpdate};
+use crate::table::{referenced_files, IcebergTable};
+use crate::write::{object_path, SnapshotUpdate, MAIN_BRANCH};
+use datafusion::arrow::datatypes::{DataType, TimeUnit as ArrowTimeUnit};
+use datafusion::catalog::Session;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use futures::TryStreamExt;
+use object_store::ObjectStore;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MILLIS_PER_HOUR: i64 = 3_600_000;
+const MILLIS_PER_DAY: i64 = 24 * MILLIS_PER_HOUR;
+
+/// What a table keeps, see the [module docs](self). Rules left unset keep everything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub partition_max_age: Option<Duration>,
+    pub snapshot_max_age: Option<Duration>,
+    /// The latest snapshots kept however old, at least one.
+    pub min_snapshots: usize,
+    pub orphan_file_min_age: Option<Duration>,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            partition_max_age: None,
+            snapshot_max_age: None,
+            min_snapshots: 1,
+            orphan_file_min_age: None,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn with_partition_max_age(mut self, age: Duration) -> Self {
+        self.partition_max_age = Some(age);
+        self
+    }
+
+    pub fn with_snapshot_max_age(mut self, age: Duration) -> Self {
+        self.snapshot_max_age = Some(age);
+        self
+    }
+
+    pub fn with_min_snapshots(mut self, min_snapshots: usize) -> Self {
+        self.min_snapshots = min_snapshots;
+        self
+    }
+
+    pub fn with_orphan_file_min_age(mut self, age: Duration) -> Self {
+        self.orphan_file_min_age = Some(age);
+        self
+    }
+}
+
+/// What applying a policy dropped, expired and deleted, or would have for a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    /// The values of the partitions dropped.
+    pub dropped_partitions: Vec<PartitionValues>,
+    /// The data files of those partitions.
+    pub dropped_files: usize,
+    pub dropped_rows: i64,
+    pub expired_snapshots: Vec<i64>,
+    /// The paths of the orphan files deleted.
+    pub orphan_files: Vec<String>,
+}
+
+impl RetentionReport {
+    /// Whether the policy left the table as it was.
+    pub fn is_empty(&self) -> bool {
+        self.dropped_files == 0 && self.expired_snapshots.is_empty() && self.orphan_files.is_empty()
+    }
+}
+
+/// Apply `policy` to `table`, see the [module docs](self).
+pub(crate) async fn apply(
+    table: &IcebergTable,
+    catalog: &RestCatalog,
+    ident: &TableIdent,
+    store: Arc<dyn ObjectStore>,
+    policy: &RetentionPolicy,
+    dry_run: bool,
+) -> DataFusionResult<RetentionReport> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+    let cutoff = |age: Duration| now.saturating_sub(age.as_millis() as i64);
+    let mut metadata = table.metadata().clone();
+    let mut report = RetentionReport { dry_run, ..Default::default() };
+
+    if let Some(age) = policy.partition_max_age {
+        let times = PartitionTimes::try_new(&metadata)?;
+        let cutoff = cutoff(age);
+        let dropped: Vec<_> = table
+            .data_files(store.as_ref())
+            .await?
+            .into_iter()
+            .filter(|file| times.end_ms(&file.partition).is_some_and(|end| end <= cutoff))
+            .collect();
+        for file in &dropped {
+            if !report.dropped_partitions.contains(&file.partition) {
+                report.dropped_partitions.push(file.partition.clone());
+            }
+        }
+        report.dropped_files = dropped.len();
+        report.dropped_rows = dropped.iter().map(|file| file.record_count).sum();
+        if !dry_run && !dropped.is_empty() {
+            let update = SnapshotUpdate {
+                removed: dropped.into_iter().map(|file| file.file_path).collect(),
+                ..Default::default()
+            };
+            metadata = update.commit(catalog, ident, store.as_ref(), metadata).await?;
+        }
+    }
+
+    if let Some(age) = policy.snapshot_max_age {
+        let cutoff = cutoff(age);
+        let mut kept: HashSet<i64> =
+            metadata.refs.values().map(|reference| reference.snapshot_id).collect();
+        kept.extend(metadata.current_snapshot().map(|snapshot| snapshot.snapshot_id));
+        let mut latest: Vec<_> = metadata.snapshots.iter().collect();
+        latest.sort_by_key(|snapshot| Reverse(snapshot.timestamp_ms));
+        let min_snapshots = policy.min_snapshots.max(1);
+        kept.extend(latest.iter().take(min_snapshots).map(|snapshot| snapshot.snapshot_id));
+        report.expired_snapshots = metadata
+            .snapshots
+            .iter()
+            .filter(|snapshot| snapshot.timestamp_ms <= cutoff)
+            .map(|snapshot| snapshot.snapshot_id)
+            .filter(|id| !kept.contains(id))
+            .collect();
+        if !report.expired_snapshots.is_empty() {
+            metadata = match dry_run {
+                true => {
+                    let expired = &report.expired_snapshots;
+                    metadata.snapshots.retain(|snapshot| !expired.contains(&snapshot.snapshot_id));
+                    metadata
+                }
+                false => expire(catalog, ident, metadata, &report.expired_snapshots).await?,
+            };
+        }
+    }
+
+    if let Some(age) = policy.orphan_file_min_age {
+        let cutoff = cutoff(age);
+        let referenced = referenced_files(store.as_ref(), &metadata.snapshots)
+            .await?
+            .iter()
+            .map(|file| object_path(file))
+            .collect::<DataFusionResult<HashSet<_>>>()?;
+        let location = object_path(&metadata.location)?;
+        for (directory, manifests_only) in [("data", false), ("metadata", true)] {
+            let mut objects = store.list(Some(&location.child(directory)));
+            while let Some(object) = objects.try_next().await? {
+                let orphan = !referenced.contains(&object.location)
+                    && object.last_modified.timestamp_millis() <= cutoff
+                    && (!manifests_only || object.location.extension() == Some("avro"));
+                if !orphan {
+                    continue;
+                }
+                if !dry_run {
+                    store.delete(&object.location).await?;
+                }
+                report.orphan_files.push(object.location.to_string());
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Remove the snapshots `expired` from the table, returning its new metadata.
+async fn expire(
+    catalog: &RestCatalog,
+    ident: &TableIdent,
+    metadata: TableMetadata,
+    expired: &[i64],
+) -> DataFusionResult<TableMetadata> {
+    let mut requirements = vec![TableRequirement::AssertRefSnapshotId {
+        reference: MAIN_BRANCH.to_string(),
+        snapshot_id: metadata.current_snapshot().map(|snapshot| snapshot.snapshot_id),
+    }];
+    if let Some(uuid) = &metadata.table_uuid {
+        requirements.push(TableRequirement::AssertTableUuid { uuid: uuid.clone() });
+    }
+    let updates = vec![TableUpdate::RemoveSnapshots { snapshot_ids: expired.to_vec() }];
+    match catalog.commit_table(ident, requirements, updates).await? {
+        Some(table) => Ok(table.metadata),
+        // Expired by the next run instead.
+        None => Err(DataFusionError::Execution(format!(
+            "Iceberg table at {} changed while expiring its snapshots",
+            metadata.location
+        ))),
+    }
+}
+
+/// How the partition fields of a table's specs that are times tell when a partition
+/// ended.
+#[derive(Debug)]
+struct PartitionTimes {
+    /// The unit of each field's values, by name.
+    fields: HashMap<String, Unit>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Unit {
+    Years,
+    Months,
+    Days,
+    Hours,
+    Micros,
+    Nanos,
+}
+
+impl PartitionTimes {
+    fn try_new(metadata: &TableMetadata) -> DataFusionResult<Self> {
+        let schema = metadata.current_schema()?;
+        let mut fields = HashMap::new();
+        for field in metadata.partition_specs.iter().flat_map(|spec| &spec.fields) {
+            let unit = match &field.transform {
+                Transform::Year => Unit::Years,
+                Transform::Month => Unit::Months,
+                Transform::Day => Unit::Days,
+                Transform::Hour => Unit::Hours,
+                Transform::Identity => {
+                    let source = schema.fields.iter().find(|f| f.id == field.source_id);
+                    match source.map(|source| source.field_type.to_arrow()).transpose()? {
+                        Some(DataType::Date32) => Unit::Days,
+                        Some(DataType::Timestamp(ArrowTimeUnit::Nanosecond, _)) => Unit::Nanos,
+                        Some(DataType::Timestamp(_, _)) => Unit::Micros,
+                        _ => continue,
+                    }
+                }
+                _ => continue,
+            };
+            fields.insert(field.name.clone(), unit);
+        }
+        if fields.is_empty() {
+            return Err(DataFusionError::Plan(format!(
+                "Iceberg table at {} has no date or time partition field to drop partitions by",
+                metadata.location
+            )));
+        }
+        Ok(Self { fields })
+    }
+
+    /// When the partition of `values` ended, in milliseconds since the Unix epoch;
+    /// `None` if it has no time.
+    fn end_ms(&self, values: &PartitionValues) -> Option<i64> {
+        let ends = self.fields.iter().filter_map(|(name, unit)| {
+            let value = values.get(name)?.as_i64()?;
+            Some(match unit {
+                Unit::Years => days_from_civil(1970 + value + 1, 1, 1) * MILLIS_PER_DAY,
+                Unit::Months => {
+                    let next = value + 1;
+                    let (year, month) = (1970 + next.div_euclid(12), next.rem_euclid(12) + 1);
+                    days_from_civil(year, month as u32, 1) * MILLIS_PER_DAY
+                }
+                Unit::Days => (value + 1) * MILLIS_PER_DAY,
+                Unit::Hours => (value + 1) * MILLIS_PER_HOUR,
+                Unit::Micros => value.div_euclid(1_000),
+                Unit::Nanos => value.div_euclid(1_000_000),
+            })
+        });
+        ends.max()
+    }
+}
+
+/// Applies retention policies to the tables of a catalog, through the object stores
+/// of a session.
+pub struct Retention {
+    catalog: Arc<RestCatalog>,
+    state: Arc<dyn Session>,
+    dry_run: bool,
+}
+
+impl fmt::Debug for Retention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retention")
+            .field("catalog", &self.catalog)
+            .field("dry_run", &self.dry_run)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Retention {
+    pub fn new(catalog: Arc<RestCatalog>, state: Arc<dyn Session>) -> Self {
+        Self { catalog, state, dry_run: false }
+    }
+
+    /// Only report what policies would drop, expire and delete.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Apply `policy` to the table `ident`.
+    pub async fn apply(
+        &self,
+        ident: &TableIdent,
+        policy: &RetentionPolicy,
+    ) -> DataFusionResult<RetentionReport> {
+        let Some(loaded) = self.catalog.load_table(ident).await? else {
+            return Err(DataFusionError::Plan(format!(
+                "Iceberg table {} does not exist",
+                ident.name
+            )));
+        };
+        let table = IcebergTable::try_new(loaded.metadata)?
+            .with_catalog(Arc::clone(&self.catalog), ident.clone());
+        table.apply_retention(self.state.as_ref(), policy, self.dry_run).await
+    }
+}
@@ -0,0 +1,354 @@
+//! Reading an Iceberg table's current snapshot, and writing new ones.
+//!
+//! A scan lists the snapshot's data files from its manifest list and manifests (Avro
+//! files next to the data) and reads them as Parquet with the table's current schema,
+//! columns being matched by name. Tables with delete files (merge-on-read updates and
+//! deletes) and data files in other formats are refused rather than read wrongly.
+//!
+//! Files are read through the object store the session has registered for the
+//! table's location (local files need none). Tables loaded through a REST catalog can
+//! also be written, see [`write`](crate::write), compacted, and have retention
+//! policies applied (see [`retention`](crate::retention)).
+
+use crate::compaction::{self, CompactionOptions, CompactionReport};
+use crate::metadata::{Snapshot, TableMetadata, FIELD_ID_KEY};
+use crate::partition::PartitionValues;
+use crate::rest::{RestCatalog, TableIdent};
+use crate::retention::{self, RetentionPolicy, RetentionReport};
+use crate::write::{self, IcebergSink};
+use apache_avro::from_value;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::common::{Column, DFSchema};
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::physical_plan::{FileGroup, FileScanConfigBuilder, ParquetSource};
+use datafusion::datasource::sink::DataSinkExec;
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::ExecutionPlan;
+use object_store::ObjectStore;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::any::Any;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Manifest `content` of data files, as opposed to delete files.
+pub(crate) const DATA: i32 = 0;
+/// Manifest entry `status` of files removed by the snapshot.
+pub(crate) const DELETED: i32 = 2;
+
+/// A data file of a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct DataFile {
+    #[serde(default)]
+    pub content: i32,
+    pub file_path: String,
+    pub file_format: String,
+    /// Values of the partition the file holds rows of; empty for unpartitioned tables.
+    #[serde(default)]
+    pub partition: PartitionValues,
+    pub record_count: i64,
+    pub file_size_in_bytes: i64,
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    manifest_path: String,
+    #[serde(default)]
+    content: i32,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    status: i32,
+    data_file: DataFile,
+}
+
+/// An Iceberg table, read at the snapshot current when it was loaded.
+#[derive(Debug)]
+pub struct IcebergTable {
+    metadata: TableMetadata,
+    schema: SchemaRef,
+    /// The catalog commits go through, and the table's name in it.
+    catalog: Option<(Arc<RestCatalog>, TableIdent)>,
+}
+
+impl IcebergTable {
+    pub fn try_new(metadata: TableMetadata) -> DataFusionResult<Self> {
+        let schema = metadata.current_schema()?.to_arrow()?;
+        // Field IDs are not used to read the files, and would make the schema differ
+        // from the files'.
+        let fields = schema.fields().iter().map(|field| {
+            let mut metadata = field.metadata().clone();
+            metadata.remove(FIELD_ID_KEY);
+            field.as_ref().clone().with_metadata(metadata)
+        });
+        let schema = Arc::new(Schema::new(fields.collect::<Vec<_>>()));
+        Ok(Self { metadata, schema, catalog: None })
+    }
+
+    /// Commit to the table through `catalog`, which it was loaded from as `ident`.
+    pub fn with_catalog(mut self, catalog: Arc<RestCatalog>, ident: TableIdent) -> Self {
+        self.catalog = Some((catalog, ident));
+        self
+    }
+
+    pub fn metadata(&self) -> &TableMetadata {
+        &self.metadata
+    }
+
+    /// Apply a batch of row changes as one snapshot, and return the table's new
+    /// metadata. `changes` holds an [`OP_COLUMN`](write::OP_COLUMN) of each row's
+    /// operation and the table's columns, in the order the changes were made, as CDC
+    /// decoders produce them: `c`, `r` and `u` insert the row or replace the one of the
+    /// same `keys`, `d` deletes the row of its keys and `t` deletes every row.
+    ///
+    /// Data files holding replaced or deleted rows are rewritten without them (copy on
+    /// write), so readers need no support for delete files.
+    pub async fn apply_changes(
+        &self,
+        state: &dyn Session,
+        keys: &[String],
+        changes: &RecordBatch,
+    ) -> DataFusionResult<TableMetadata> {
+        let (catalog, ident) = self.catalog()?;
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store = state.runtime_env().object_store(location.object_store())?;
+        write::apply_changes(self, catalog, ident, store, keys, changes).await
+    }
+
+    /// Delete the rows `predicate` is true of, or every row without one, as one
+    /// snapshot, and return the number of rows deleted. The predicate's columns may be
+    /// qualified, as those of `DELETE` plans are.
+    ///
+    /// Like [`Self::apply_changes`], data files holding deleted rows are rewritten
+    /// without them (copy on write), and those left empty are dropped.
+    pub async fn delete(
+        &self,
+        state: &dyn Session,
+        predicate: Option<&Expr>,
+    ) -> DataFusionResult<u64> {
+        let (catalog, ident) = self.catalog()?;
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store = state.runtime_env().object_store(location.object_store())?;
+        let predicate = match predicate {
+            Some(predicate) => {
+                let predicate = predicate.clone().transform(|expr| match expr {
+                    Expr::Column(column) => {
+                        Ok(Transformed::yes(Expr::Column(Column::new_unqualified(column.name))))
+                    }
+                    expr => Ok(Transformed::no(expr)),
+                })?;
+                let schema = DFSchema::try_from(self.schema.as_ref().clone())?;
+                Some(state.create_physical_expr(predicate.data, &schema)?)
+            }
+            None => None,
+        };
+        write::delete(self, catalog, ident, store, predicate).await
+    }
+
+    /// Rewrite the table's small data files into files of its target size, as one
+    /// `replace` snapshot, see [`compaction`](crate::compaction). `None` if it has too
+    /// few small files to be worth it.
+    pub async fn compact(
+        &self,
+        state: &dyn Session,
+        options: &CompactionOptions,
+    ) -> DataFusionResult<Option<CompactionReport>> {
+        let (catalog, ident) = self.catalog()?;
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store = state.runtime_env().object_store(location.object_store())?;
+        compaction::compact(self, catalog, ident, store, options).await
+    }
+
+    /// Drop the old partitions, snapshots and orphan files of the table as `policy`
+    /// says, or only report them if `dry_run`, see [`retention`](crate::retention).
+    pub async fn apply_retention(
+        &self,
+        state: &dyn Session,
+        policy: &RetentionPolicy,
+        dry_run: bool,
+    ) -> DataFusionResult<RetentionReport> {
+        let (catalog, ident) = self.catalog()?;
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store = state.runtime_env().object_store(location.object_store())?;
+        retention::apply(self, catalog, ident, store, policy, dry_run).await
+    }
+
+    fn catalog(&self) -> DataFusionResult<(&Arc<RestCatalog>, &TableIdent)> {
+        match &self.catalog {
+            Some((catalog, ident)) => Ok((catalog, ident)),
+            None => Err(DataFusionError::NotImplemented(format!(
+                "Iceberg table at {} was not loaded through a catalog, and cannot be written",
+                self.metadata.location
+            ))),
+        }
+    }
+
+    /// The data files of the current snapshot, read from its manifests in `store`.
+    pub async fn data_files(&self, store: &dyn ObjectStore) -> DataFusionResult<Vec<DataFile>> {
+        let Some(snapshot) = self.metadata.current_snapshot() else {
+            return Ok(vec![]);
+        };
+        let manifests = match (&snapshot.manifest_list, &snapshot.manifests) {
+            (Some(list), _) => read_avro::<ManifestFile>(store, list).await?,
+            (None, Some(paths)) => paths
+                .iter()
+                .map(|path| ManifestFile { manifest_path: path.clone(), content: DATA })
+                .collect(),
+            (None, None) => vec![],
+        };
+        let mut files = Vec::new();
+        for manifest in manifests {
+            if manifest.content != DATA {
+                return Err(unsupported(&self.metadata, "delete files"));
+            }
+            for entry in read_avro::<ManifestEntry>(store, &manifest.manifest_path).await? {
+                if entry.status == DELETED {
+                    continue;
+                }
+                if entry.data_file.content != DATA {
+                    return Err(unsupported(&self.metadata, "delete files"));
+                }
+                if !entry.data_file.file_format.eq_ignore_ascii_case("parquet") {
+                    let format = format!("{} data files", entry.data_file.file_format);
+                    return Err(unsupported(&self.metadata, &format));
+                }
+                files.push(entry.data_file);
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[async_trait]
+impl TableProvider for IcebergTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store_url = location.object_store();
+        let store = state.runtime_env().object_store(&store_url)?;
+        let files = self.data_files(store.as_ref()).await?;
+        if files.is_empty() {
+            let schema = match projection {
+                Some(projection) => Arc::new(self.schema.project(projection)?),
+                None => self.schema(),
+            };
+            return Ok(Arc::new(EmptyExec::new(schema)));
+        }
+        let partitions = state.config().target_partitions().clamp(1, files.len());
+        let mut groups = vec![Vec::new(); partitions];
+        for (i, file) in files.into_iter().enumerate() {
+            let path = ListingTableUrl::parse(&file.file_path)?.prefix().to_string();
+            groups[i % partitions].push(PartitionedFile::new(path, file.file_size_in_bytes as u64));
+        }
+        let config = FileScanConfigBuilder::new(
+            store_url,
+            self.schema(),
+            Arc::new(ParquetSource::default()),
+        )
+        .with_file_groups(groups.into_iter().map(FileGroup::new).collect())
+        .with_projection(projection.cloned())
+        .with_limit(limit)
+        .build();
+        Ok(DataSourceExec::from_data_source(config))
+    }
+
+    async fn insert_into(
+        &self,
+        _state: &dyn Session,
+        input: Arc<dyn ExecutionPlan>,
+        insert_op: InsertOp,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let (catalog, ident) = self.catalog()?;
+        if insert_op != InsertOp::Append {
+            return Err(DataFusionError::NotImplemented(format!(
+                "{insert_op} of Iceberg tables is not supported"
+            )));
+        }
+        let sink = IcebergSink::new(
+            Arc::clone(catalog),
+            ident.clone(),
+            self.metadata.clone(),
+            self.schema(),
+        );
+        Ok(Arc::new(DataSinkExec::new(input, Arc::new(sink), None)))
+    }
+}
+
+/// The files `snapshots` reference: their manifest lists, their manifests, and the
+/// data and delete files live in those.
+pub(crate) async fn referenced_files(
+    store: &dyn ObjectStore,
+    snapshots: &[Snapshot],
+) -> DataFusionResult<HashSet<String>> {
+    let mut files = HashSet::new();
+    for snapshot in snapshots {
+        let manifests = match (&snapshot.manifest_list, &snapshot.manifests) {
+            (Some(list), _) => {
+                files.insert(list.clone());
+                let manifests = read_avro::<ManifestFile>(store, list).await?;
+                manifests.into_iter().map(|manifest| manifest.manifest_path).collect()
+            }
+            (None, Some(paths)) => paths.clone(),
+            (None, None) => vec![],
+        };
+        for manifest in manifests {
+            // Manifests are carried over from snapshot to snapshot.
+            if !files.insert(manifest.clone()) {
+                continue;
+            }
+            let entries = read_avro::<ManifestEntry>(store, &manifest).await?;
+            let live = entries.into_iter().filter(|entry| entry.status != DELETED);
+            files.extend(live.map(|entry| entry.data_file.file_path));
+        }
+    }
+    Ok(files)
+}
+
+/// The records of the Avro file at `location`.
+pub(crate) async fn read_avro<T: DeserializeOwned>(
+    store: &dyn ObjectStore,
+    location: &str,
+) -> DataFusionResult<Vec<T>> {
+    let path = ListingTableUrl::parse(location)?.prefix().clone();
+    let bytes = store.get(&path).await?.bytes().await?;
+    let reader = apache_avro::Reader::new(&bytes[..]).map_err(avro_error)?;
+    reader.map(|value| from_value(&value.map_err(avro_error)?).map_err(avro_error)).collect()
+}
+
+fn avro_error(e: apache_avro::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+fn unsupported(metadata: &TableMetadata, what: &str) -> DataFusionError {
+    DataFusionError::NotImplemented(format!(
+        "Iceberg table at {} has {what}, which are not supported",
+        metadata.location
+    ))
+}
@@ -0,0 +1,934 @@
+//! Committing snapshots to Iceberg tables.
+//!
+//! Rows are written as Parquet data files under the table's `data/` directory, with
+//! the Iceberg field IDs of their columns so that engines matching columns by ID
+//! (Spark, Trino, ...) read them. A commit writes a manifest of the data files it
+//! adds, rewrites the manifests holding files it removes, and writes a manifest list
+//! carrying the other manifests of the current snapshot over. The new snapshot is
+//! committed through the REST catalog on the condition that the table's `main` branch
+//! is still at the snapshot it was built on; when another commit came first, the
+//! table is reloaded and the manifest list built again over its new snapshot, up to
+//! `commit.retry.num-retries` times. Commits removing files fail instead if one of
+//! them has been removed since.
+//!
+//! Rows of partitioned tables are split by the table's default partition spec, each
+//! partition's going to files of their own (see [`crate::partition`]). Only tables of
+//! format version 2 can be written.
+
+use crate::metadata::{Snapshot, TableMetadata};
+use crate::partition::{PartitionValues, Partitioner};
+use crate::rest::{RestCatalog, TableIdent, TableRequirement, TableUpdate};
+use crate::table::{read_avro, DataFile, IcebergTable, DATA, DELETED};
+use apache_avro::types::Value as AvroValue;
+use apache_avro::{Schema as AvroSchema, Writer};
+use async_trait::async_trait;
+use datafusion::arrow::array::{new_null_array, ArrayRef, BooleanArray, StringArray, UInt32Array};
+use datafusion::arrow::compute::{cast, filter_record_batch, take};
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::row::{OwnedRow, RowConverter, Rows, SortField};
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::datasource::sink::DataSink;
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::physical_expr::PhysicalExpr;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType};
+use datafusion::scalar::ScalarValue;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Table property of the size data files are rolled over at, in bytes.
+pub const TARGET_FILE_SIZE_PROPERTY: &str = "write.target-file-size-bytes";
+pub const DEFAULT_TARGET_FILE_SIZE: usize = 512 * 1024 * 1024;
+/// Table property of the number of times a commit is retried after a conflict.
+pub const COMMIT_RETRIES_PROPERTY: &str = "commit.retry.num-retries";
+pub const DEFAULT_COMMIT_RETRIES: usize = 4;
+/// Column of the operation of each row in the changes [`IcebergTable::apply_changes`]
+/// takes, as CDC decoders name it.
+pub const OP_COLUMN: &str = "__op";
+
+/// Branch commits advance.
+pub(crate) const MAIN_BRANCH: &str = "main";
+/// Manifest entry `status` of files carried over from an earlier snapshot.
+const EXISTING: i32 = 0;
+/// Manifest entry `status` of files added by the snapshot.
+const ADDED: i32 = 1;
+
+/// Manifest entries as written: data files with the fields the spec requires, and no
+/// column statistics. The fields of the partition record depend on the partition spec.
+const MANIFEST_ENTRY_SCHEMA: &str = r#"{"type": "record", "name": "manifest_entry", "fields": [
+    {"name": "status", "type": "int", "field-id": 0},
+    {"name": "snapshot_id", "type": ["null", "long"], "default": null, "field-id": 1},
+    {"name": "sequence_number", "type": ["null", "long"], "default": null, "field-id": 3},
+    {"name": "file_sequence_number", "type": ["null", "long"], "default": null, "field-id": 4},
+    {"name": "data_file", "field-id": 2, "type": {"type": "record", "name": "r2", "fields": [
+        {"name": "content", "type": "int", "field-id": 134},
+        {"name": "file_path", "type": "string", "field-id": 100},
+        {"name": "file_format", "type": "string", "field-id": 101},
+        {"name": "partition", "field-id": 102,
+            "type": {"type": "record", "name": "r102", "fields": PARTITION_FIELDS}},
+        {"name": "record_count", "type": "long", "field-id": 103},
+        {"name": "file_size_in_bytes", "type": "long", "field-id": 104}
+    ]}}
+]}"#;
+
+const MANIFEST_FILE_SCHEMA: &str = r#"{"type": "record", "name": "manifest_file", "fields": [
+    {"name": "manifest_path", "type": "string", "field-id": 500},
+    {"name": "manifest_length", "type": "long", "field-id": 501},
+    {"name": "partition_spec_id", "type": "int", "field-id": 502},
+    {"name": "content", "type": "int", "field-id": 517},
+    {"name": "sequence_number", "type": "long", "field-id": 515},
+    {"name": "min_sequence_number", "type": "long", "field-id": 516},
+    {"name": "added_snapshot_id", "type": "long", "field-id": 503},
+    {"name": "added_files_count", "type": "int", "field-id": 504},
+    {"name": "existing_files_count", "type": "int", "field-id": 505},
+    {"name": "deleted_files_count", "type": "int", "field-id": 506},
+    {"name": "added_rows_count", "type": "long", "field-id": 512},
+    {"name": "existing_rows_count", "type": "long", "field-id": 513},
+    {"name": "deleted_rows_count", "type": "long", "field-id": 514}
+]}"#;
+
+#[derive(Debug, Serialize)]
+struct EntryRecord {
+    status: i32,
+    snapshot_id: Option<i64>,
+    sequence_number: Option<i64>,
+    file_sequence_number: Option<i64>,
+    data_file: DataFileRecord,
+}
+
+#[derive(Debug, Serialize)]
+struct DataFileRecord {
+    content: i32,
+    file_path: String,
+    file_format: String,
+    /// Serialized as an empty record, replaced by the file's typed partition values.
+    partition: Partition,
+    #[serde(skip)]
+    partition_values: PartitionValues,
+    record_count: i64,
+    file_size_in_bytes: i64,
+}
+
+impl From<&DataFile> for DataFileRecord {
+    fn from(file: &DataFile) -> Self {
+        Self {
+            content: file.content,
+            file_path: file.file_path.clone(),
+            file_format: file.file_format.clone(),
+            partition: Partition::default(),
+            partition_values: file.partition.clone(),
+            record_count: file.record_count,
+            file_size_in_bytes: file.file_size_in_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Partition {}
+
+impl EntryRecord {
+    /// The entry as an Avro value of a manifest of the spec of `partitioner`.
+    fn to_avro(&self, partitioner: &Partitioner) -> DataFusionResult<AvroValue> {
+        let mut value = apache_avro::to_value(self).map_err(avro_error)?;
+        let partition = record_field(&mut value, "data_file")
+            .and_then(|data_file| record_field(data_file, "partition"))
+            .expect("manifest entries have a partition");
+        *partition = partitioner.to_avro(&self.data_file.partition_values)?;
+        Ok(value)
+    }
+}
+
+fn record_field<'a>(value: &'a mut AvroValue, name: &str) -> Option<&'a mut AvroValue> {
+    let AvroValue::Record(fields) = value else {
+        return None;
+    };
+    fields.iter_mut().find(|(field, _)| field == name).map(|(_, value)| value)
+}
+
+/// A manifest entry as read, sequence numbers and snapshot ID being `None` where
+/// they are inherited from the manifest.
+#[derive(Debug, Deserialize)]
+struct ManifestEntry {
+    status: i32,
+    #[serde(default)]
+    snapshot_id: Option<i64>,
+    #[serde(default)]
+    sequence_number: Option<i64>,
+    #[serde(default)]
+    file_sequence_number: Option<i64>,
+    data_file: DataFile,
+}
+
+/// An entry of a manifest list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ManifestFile {
+    manifest_path: String,
+    manifest_length: i64,
+    partition_spec_id: i32,
+    #[serde(default)]
+    content: i32,
+    #[serde(default)]
+    sequence_number: i64,
+    #[serde(default)]
+    min_sequence_number: i64,
+    added_snapshot_id: i64,
+    #[serde(default)]
+    added_files_count: i32,
+    #[serde(default)]
+    existing_files_count: i32,
+    #[serde(default)]
+    deleted_files_count: i32,
+    #[serde(default)]
+    added_rows_count: i64,
+    #[serde(default)]
+    existing_rows_count: i64,
+    #[serde(default)]
+    deleted_rows_count: i64,
+}
+
+/// Writes rows as Parquet data files of a table, a file at a time for each partition,
+/// starting a new file of a partition each time one reaches the table's target file
+/// size.
+pub struct DataFileWriter {
+    store: Arc<dyn ObjectStore>,
+    location: String,
+    /// The table's current schema, with field IDs.
+    schema: SchemaRef,
+    target_size: usize,
+    /// Unique to the writer, prefixing the names of its files.
+    prefix: Uuid,
+    partitioner: Partitioner,
+    /// The file being written of each partition, by partition values.
+    open: HashMap<Vec<ScalarValue>, OpenFile>,
+    /// Files started, numbering their names.
+    started: usize,
+    files: Vec<DataFile>,
+}
+
+struct OpenFile {
+    writer: ArrowWriter<Vec<u8>>,
+    path: String,
+    rows: i64,
+}
+
+impl fmt::Debug for DataFileWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataFileWriter").field("location", &self.location).finish_non_exhaustive()
+    }
+}
+
+impl DataFileWriter {
+    pub fn try_new(
+        store: Arc<dyn ObjectStore>,
+        metadata: &TableMetadata,
+    ) -> DataFusionResult<Self> {
+        Ok(Self {
+            store,
+            location: metadata.location.trim_end_matches('/').to_string(),
+            schema: metadata.current_schema()?.to_arrow()?,
+            target_size: property(metadata, TARGET_FILE_SIZE_PROPERTY, DEFAULT_TARGET_FILE_SIZE)?,
+            prefix: Uuid::new_v4(),
+            partitioner: Partitioner::try_new(metadata, metadata.default_spec_id)?,
+            open: HashMap::new(),
+            started: 0,
+            files: vec![],
+        })
+    }
+
+    /// Write `batch`, whose columns are the table's, in order.
+    pub async fn write(&mut self, batch: &RecordBatch) -> DataFusionResult<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), batch.columns().to_vec())?;
+        for (partition, rows) in self.partitioner.split(&batch)? {
+            let file = match self.open.entry(partition.clone()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let name = format!("{}-{:05}.parquet", self.prefix, self.started);
+                    let directory = self.partitioner.path(&partition);
+                    self.started += 1;
+                    entry.insert(OpenFile {
+                        writer: ArrowWriter::try_new(Vec::new(), Arc::clone(&self.schema), None)?,
+                        path: format!("{}/data/{directory}{name}", self.location),
+                        rows: 0,
+                    })
+                }
+            };
+            file.writer.write(&rows)?;
+            file.rows += rows.num_rows() as i64;
+            if file.writer.bytes_written() + file.writer.in_progress_size() >= self.target_size {
+                let file = self.open.remove(&partition).expect("the file was just written");
+                self.close(&partition, file).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The data files written.
+    pub async fn finish(mut self) -> DataFusionResult<Vec<DataFile>> {
+        let mut open: Vec<_> = std::mem::take(&mut self.open).into_iter().collect();
+        open.sort_by(|(_, a), (_, b)| a.path.cmp(&b.path));
+        for (partition, file) in open {
+            self.close(&partition, file).await?;
+        }
+        Ok(self.files)
+    }
+
+    async fn close(&mut self, partition: &[ScalarValue], file: OpenFile) -> DataFusionResult<()> {
+        let bytes = file.writer.into_inner()?;
+        let size = bytes.len() as i64;
+        self.store.put(&object_path(&file.path)?, bytes.into()).await?;
+        self.files.push(DataFile {
+            content: DATA,
+            file_path: file.path,
+            file_format: "PARQUET".to_string(),
+            partition: self.partitioner.values(partition)?,
+            record_count: file.rows,
+            file_size_in_bytes: size,
+        });
+        Ok(())
+    }
+}
+
+/// Data files added to and removed from a table by one snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotUpdate {
+    pub added: Vec<DataFile>,
+    /// Paths of the data files removed.
+    pub removed: HashSet<String>,
+    /// Properties added to the snapshot's summary, such as the position in a stream
+    /// the snapshot has ingested up to.
+    pub summary: HashMap<String, String>,
+    /// Whether the update rewrites rows without changing them, as compaction does, so
+    /// that the snapshot is a `replace` readers of changes can skip.
+    pub replace: bool,
+}
+
+impl SnapshotUpdate {
+    /// The snapshot `operation`.
+    pub fn operation(&self) -> &'static str {
+        if self.replace {
+            return "replace";
+        }
+        match (self.added.is_empty(), self.removed.is_empty()) {
+            (_, true) => "append",
+            (true, false) => "delete",
+            (false, false) => "overwrite",
+        }
+    }
+
+    /// Commit the update as a new snapshot of the table `ident` of `catalog`, built on
+    /// `metadata`, and return the table's new metadata.
+    pub async fn commit(
+        &self,
+        catalog: &RestCatalog,
+        ident: &TableIdent,
+        store: &dyn ObjectStore,
+        mut metadata: TableMetadata,
+    ) -> DataFusionResult<TableMetadata> {
+        check_writable(&metadata)?;
+        let snapshot_id = new_snapshot_id();
+        let retries = property(&metadata, COMMIT_RETRIES_PROPERTY, DEFAULT_COMMIT_RETRIES)?;
+        // The sequence numbers of added files are inherited from the manifest list, so
+        // their manifest holds whichever snapshot the update ends up committed on.
+        let added = match self.added.is_empty() {
+            true => None,
+            false => Some(self.write_added_manifest(store, &metadata, snapshot_id).await?),
+        };
+        for attempt in 0..=retries {
+            let snapshot =
+                self.build_snapshot(store, &metadata, snapshot_id, added.clone(), attempt).await?;
+            let mut requirements = vec![TableRequirement::AssertRefSnapshotId {
+                reference: MAIN_BRANCH.to_string(),
+                snapshot_id: metadata.current_snapshot().map(|snapshot| snapshot.snapshot_id),
+            }];
+            if let Some(uuid) = &metadata.table_uuid {
+                requirements.push(TableRequirement::AssertTableUuid { uuid: uuid.clone() });
+            }
+            let updates = vec![
+                TableUpdate::AddSnapshot { snapshot },
+                TableUpdate::SetSnapshotRef {
+                    ref_name: MAIN_BRANCH.to_string(),
+                    kind: "branch".to_string(),
+                    snapshot_id,
+                },
+            ];
+            if let Some(table) = catalog.commit_table(ident, requirements, updates).await? {
+                return Ok(table.metadata);
+            }
+            let Some(table) = catalog.load_table(ident).await? else {
+                return Err(DataFusionError::Plan(format!(
+                    "Iceberg table {} was dropped while committing to it",
+                    ident.name
+                )));
+            };
+            metadata = table.metadata;
+            check_writable(&metadata)?;
+        }
+        Err(DataFusionError::Execution(format!(
+            "gave up committing to Iceberg table at {} after {retries} conflicting commits",
+            metadata.location
+        )))
+    }
+
+    async fn write_added_manifest(
+        &self,
+        store: &dyn ObjectStore,
+        metadata: &TableMetadata,
+        snapshot_id: i64,
+    ) -> DataFusionResult<ManifestFile> {
+        let entries = self.added.iter().map(|file| EntryRecord {
+            status: ADDED,
+            snapshot_id: Some(snapshot_id),
+            sequence_number: None,
+            file_sequence_number: None,
+            data_file: file.into(),
+        });
+        let path = metadata_path(metadata, &format!("{}-m0.avro", Uuid::new_v4()));
+        let spec_id = metadata.default_spec_id;
+        let length = write_manifest(store, metadata, spec_id, &path, entries.collect()).await?;
+        Ok(ManifestFile {
+            manifest_path: path,
+            manifest_length: length,
+            partition_spec_id: spec_id,
+            content: DATA,
+            // Set once the snapshot is.
+            sequence_number: 0,
+            min_sequence_number: 0,
+            added_snapshot_id: snapshot_id,
+            added_files_count: self.added.len() as i32,
+            existing_files_count: 0,
+            deleted_files_count: 0,
+            added_rows_count: self.added.iter().map(|file| file.record_count).sum(),
+            existing_rows_count: 0,
+            deleted_rows_count: 0,
+        })
+    }
+
+    /// The snapshot of the update on top of the current snapshot of `metadata`, its
+    /// manifest list written.
+    async fn build_snapshot(
+        &self,
+        store: &dyn ObjectStore,
+        metadata: &TableMetadata,
+        snapshot_id: i64,
+        added: Option<ManifestFile>,
+        attempt: usize,
+    ) -> DataFusionResult<Snapshot> {
+        let sequence_number = metadata.last_sequence_number + 1;
+        let parent = metadata.current_snapshot();
+        let mut manifests = Vec::new();
+        let mut removed = Vec::new();
+        if let Some(parent) = parent {
+            let Some(list) = &parent.manifest_list else {
+                return Err(DataFusionError::NotImplemented(format!(
+                    "Iceberg table at {} has a snapshot without a manifest list, which cannot \
+                     be committed on",
+                    metadata.location
+                )));
+            };
+            for manifest in read_avro::<ManifestFile>(store, list).await? {
+                if self.removed.is_empty() || manifest.content != DATA {
+                    manifests.push(manifest);
+                    continue;
+                }
+                let entries = read_avro::<ManifestEntry>(store, &manifest.manifest_path).await?;
+                let removes = |entry: &ManifestEntry| {
+                    entry.status != DELETED && self.removed.contains(&entry.data_file.file_path)
+                };
+                if !entries.iter().any(removes) {
+                    manifests.push(manifest);
+                    continue;
+                }
+                let (rewritten, files) = self
+                    .rewrite_manifest(
+                        store,
+                        metadata,
+                        &manifest,
+                        entries,
+                        snapshot_id,
+                        sequence_number,
+                    )
+                    .await?;
+                manifests.push(rewritten);
+                removed.extend(files);
+            }
+        }
+        if removed.len() != self.removed.len() {
+            return Err(DataFusionError::Execution(format!(
+                "data files of Iceberg table at {} were removed by a concurrent commit",
+                metadata.location
+            )));
+        }
+        if let Some(added) = added {
+            manifests.push(ManifestFile {
+                sequence_number,
+                min_sequence_number: sequence_number,
+                ..added
+            });
+        }
+        let name = format!("snap-{snapshot_id}-{attempt}-{}.avro", Uuid::new_v4());
+        let manifest_list = metadata_path(metadata, &name);
+        let mut file_metadata = vec![
+            ("snapshot-id", snapshot_id.to_string()),
+            ("sequence-number", sequence_number.to_string()),
+            ("format-version", "2".to_string()),
+        ];
+        if let Some(parent) = parent {
+            file_metadata.push(("parent-snapshot-id", parent.snapshot_id.to_string()));
+        }
+        let manifests = manifests
+            .iter()
+            .map(|manifest| apache_avro::to_value(manifest).map_err(avro_error))
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        let bytes = avro_bytes(MANIFEST_FILE_SCHEMA, manifests, &file_metadata)?;
+        store.put(&object_path(&manifest_list)?, bytes.into()).await?;
+
+        let mut summary = self.summary.clone();
+        summary.insert("operation".to_string(), self.operation().to_string());
+        let mut count = |key: &str, value: i64| {
+            if value > 0 {
+                summary.insert(key.to_string(), value.to_string());
+            }
+        };
+        count("added-data-files", self.added.len() as i64);
+        count("added-records", self.added.iter().map(|file| file.record_count).sum());
+        count("added-files-size", self.added.iter().map(|file| file.file_size_in_bytes).sum());
+        count("deleted-data-files", removed.len() as i64);
+        count("deleted-records", removed.iter().map(|file| file.record_count).sum());
+        count("removed-files-size", removed.iter().map(|file| file.file_size_in_bytes).sum());
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        Ok(Snapshot {
+            snapshot_id,
+            timestamp_ms: timestamp_ms as i64,
+            manifest_list: Some(manifest_list),
+            manifests: None,
+            parent_snapshot_id: parent.map(|parent| parent.snapshot_id),
+            sequence_number: Some(sequence_number),
+            schema_id: metadata.current_schema_id,
+            summary,
+        })
+    }
+
+    /// `manifest` with the files the update removes marked deleted and the others
+    /// existing, and the files removed.
+    async fn rewrite_manifest(
+        &self,
+        store: &dyn ObjectStore,
+        metadata: &TableMetadata,
+        manifest: &ManifestFile,
+        entries: Vec<ManifestEntry>,
+        snapshot_id: i64,
+        sequence_number: i64,
+    ) -> DataFusionResult<(ManifestFile, Vec<DataFile>)> {
+        let mut rewritten = ManifestFile {
+            manifest_path: metadata_path(metadata, &format!("{}-m0.avro", Uuid::new_v4())),
+            sequence_number,
+            min_sequence_number: sequence_number,
+            added_snapshot_id: snapshot_id,
+            added_files_count: 0,
+            existing_files_count: 0,
+            deleted_files_count: 0,
+            added_rows_count: 0,
+            existing_rows_count: 0,
+            deleted_rows_count: 0,
+            ..manifest.clone()
+        };
+        let mut records = Vec::new();
+        let mut removed = Vec::new();
+        let mut min_sequence_number = None::<i64>;
+        for entry in entries.into_iter().filter(|entry| entry.status != DELETED) {
+            // Inherited by files added by the manifest's snapshot.
+            let data_sequence_number = entry.sequence_number.unwrap_or(manifest.sequence_number);
+            let file_sequence_number =
+                entry.file_sequence_number.unwrap_or(manifest.sequence_number);
+            let file = entry.data_file;
+            let (status, entry_snapshot_id) = if self.removed.contains(&file.file_path) {
+                rewritten.deleted_files_count += 1;
+                rewritten.deleted_rows_count += file.record_count;
+                (DELETED, snapshot_id)
+            } else {
+                rewritten.existing_files_count += 1;
+                rewritten.existing_rows_count += file.record_count;
+                let min = min_sequence_number.get_or_insert(data_sequence_number);
+                *min = (*min).min(data_sequence_number);
+                (EXISTING, entry.snapshot_id.unwrap_or(manifest.added_snapshot_id))
+            };
+            records.push(EntryRecord {
+                status,
+                snapshot_id: Some(entry_snapshot_id),
+                sequence_number: Some(data_sequence_number),
+                file_sequence_number: Some(file_sequence_number),
+                data_file: (&file).into(),
+            });
+            if status == DELETED {
+                removed.push(file);
+            }
+        }
+        rewritten.min_sequence_number = min_sequence_number.unwrap_or(sequence_number);
+        let spec_id = manifest.partition_spec_id;
+        rewritten.manifest_length =
+            write_manifest(store, metadata, spec_id, &rewritten.manifest_path, records).await?;
+        Ok((rewritten, removed))
+    }
+}
+
+/// Appends the rows of `INSERT`s to an Iceberg table as one snapshot.
+#[derive(Debug)]
+pub struct IcebergSink {
+    catalog: Arc<RestCatalog>,
+    ident: TableIdent,
+    metadata: TableMetadata,
+    schema: SchemaRef,
+}
+
+impl IcebergSink {
+    pub fn new(
+        catalog: Arc<RestCatalog>,
+        ident: TableIdent,
+        metadata: TableMetadata,
+        schema: SchemaRef,
+    ) -> Self {
+        Self { catalog, ident, metadata, schema }
+    }
+}
+
+impl DisplayAs for IcebergSink {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IcebergSink: location={}", self.metadata.location)
+    }
+}
+
+#[async_trait]
+impl DataSink for IcebergSink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    async fn write_all(
+        &self,
+        mut data: SendableRecordBatchStream,
+        context: &Arc<TaskContext>,
+    ) -> DataFusionResult<u64> {
+        let location = ListingTableUrl::parse(&self.metadata.location)?;
+        let store = context.runtime_env().object_store(location.object_store())?;
+        let mut writer = DataFileWriter::try_new(Arc::clone(&store), &self.metadata)?;
+        let mut rows = 0;
+        while let Some(batch) = data.try_next().await? {
+            rows += batch.num_rows() as u64;
+            writer.write(&batch).await?;
+        }
+        let update = SnapshotUpdate { added: writer.finish().await?, ..Default::default() };
+        if !update.added.is_empty() {
+            let metadata = self.metadata.clone();
+            update.commit(&self.catalog, &self.ident, store.as_ref(), metadata).await?;
+        }
+        Ok(rows)
+    }
+}
+
+/// Apply `changes` to the table, see [`IcebergTable::apply_changes`].
+pub(crate) async fn apply_changes(
+    table: &IcebergTable,
+    catalog: &RestCatalog,
+    ident: &TableIdent,
+    store: Arc<dyn ObjectStore>,
+    keys: &[String],
+    changes: &RecordBatch,
+) -> DataFusionResult<TableMetadata> {
+    let metadata = table.metadata();
+    let schema = table.schema();
+    let net = NetChanges::try_new(&schema, keys, changes)?;
+    let mut writer = DataFileWriter::try_new(Arc::clone(&store), metadata)?;
+    let mut update = SnapshotUpdate::default();
+    if net.truncated || !net.rows.is_empty() {
+        for file in table.data_files(store.as_ref()).await? {
+            let bytes = store.get(&object_path(&file.file_path)?).await?.bytes().await?;
+            let mut kept = Vec::new();
+            for batch in ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()? {
+                let batch = adapt(&schema, &batch?)?;
+                let keep = net.keeps(&batch)?;
+                kept.push(filter_record_batch(&batch, &keep)?);
+            }
+            let rows = kept.iter().map(RecordBatch::num_rows).sum::<usize>() as i64;
+            if rows < file.record_count {
+                update.removed.insert(file.file_path);
+                for batch in &kept {
+                    writer.write(batch).await?;
+                }
+            }
+        }
+    }
+    writer.write(&net.upserts(&schema, changes)?).await?;
+    update.added = writer.finish().await?;
+    if update.added.is_empty() && update.removed.is_empty() {
+        return Ok(metadata.clone());
+    }
+    update.commit(catalog, ident, store.as_ref(), metadata.clone()).await
+}
+
+/// Delete the rows `predicate` is true of, see [`IcebergTable::delete`].
+pub(crate) async fn delete(
+    table: &IcebergTable,
+    catalog: &RestCatalog,
+    ident: &TableIdent,
+    store: Arc<dyn ObjectStore>,
+    predicate: Option<Arc<dyn PhysicalExpr>>,
+) -> DataFusionResult<u64> {
+    let metadata = table.metadata();
+    check_writable(metadata)?;
+    let schema = table.schema();
+    let mut writer = DataFileWriter::try_new(Arc::clone(&store), metadata)?;
+    let mut update = SnapshotUpdate::default();
+    let mut deleted = 0;
+    for file in table.data_files(store.as_ref()).await? {
+        let Some(predicate) = &predicate else {
+            deleted += file.record_count as u64;
+            update.removed.insert(file.file_path);
+            continue;
+        };
+        let bytes = store.get(&object_path(&file.file_path)?).await?.bytes().await?;
+        let mut kept = Vec::new();
+        for batch in ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()? {
+            let batch = adapt(&schema, &batch?)?;
+            let matches = predicate.evaluate(&batch)?.into_array(batch.num_rows())?;
+            let matches = matches.as_any().downcast_ref::<BooleanArray>().ok_or_else(|| {
+                DataFusionError::Plan("DELETE ... WHERE is not a boolean".to_string())
+            })?;
+            // Rows the predicate is null for are kept, as a filter drops them.
+            let keep: BooleanArray = matches.iter().map(|m| Some(m != Some(true))).collect();
+            kept.push(filter_record_batch(&batch, &keep)?);
+        }
+        let rows = kept.iter().map(RecordBatch::num_rows).sum::<usize>() as i64;
+        if rows < file.record_count {
+            deleted += (file.record_count - rows) as u64;
+            update.removed.insert(file.file_path);
+            for batch in &kept {
+                writer.write(batch).await?;
+            }
+        }
+    }
+    update.added = writer.finish().await?;
+    if !update.removed.is_empty() {
+        update.commit(catalog, ident, store.as_ref(), metadata.clone()).await?;
+    }
+    Ok(deleted)
+}
+
+/// The net effect of a batch of changes on each key.
+struct NetChanges {
+    converter: RowConverter,
+    key_columns: Vec<(String, DataType)>,
+    /// The row of the latest change of each key, `None` if it deleted the key.
+    rows: HashMap<OwnedRow, Option<usize>>,
+    /// Whether the table was truncated, before the changes of `rows`.
+    truncated: bool,
+}
+
+impl NetChanges {
+    fn try_new(
+        schema: &SchemaRef,
+        keys: &[String],
+        changes: &RecordBatch,
+    ) -> DataFusionResult<Self> {
+        if keys.is_empty() {
+            return Err(DataFusionError::Plan("changes need at least one key column".to_string()));
+        }
+        let key_columns = keys
+            .iter()
+            .map(|key| Ok((key.clone(), schema.field_with_name(key)?.data_type().clone())))
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        let fields = key_columns.iter().map(|(_, data_type)| SortField::new(data_type.clone()));
+        let converter = RowConverter::new(fields.collect())?;
+        let mut net = Self { converter, key_columns, rows: HashMap::new(), truncated: false };
+        let ops = changes
+            .column_by_name(OP_COLUMN)
+            .and_then(|ops| ops.as_any().downcast_ref::<StringArray>())
+            .ok_or_else(|| DataFusionError::Plan(format!("changes have no {OP_COLUMN} column")))?;
+        let key_rows = net.key_rows(changes)?;
+        for (i, op) in ops.iter().enumerate() {
+            match op.unwrap_or_default() {
+                "c" | "r" | "u" => net.rows.insert(key_rows.row(i).owned(), Some(i)),
+                "d" => net.rows.insert(key_rows.row(i).owned(), None),
+                "t" => {
+                    net.rows.clear();
+                    net.truncated = true;
+                    continue;
+                }
+                op => {
+                    return Err(DataFusionError::Execution(format!(
+                        "unknown change operation '{op}'"
+                    )))
+                }
+            };
+        }
+        Ok(net)
+    }
+
+    fn key_rows(&self, batch: &RecordBatch) -> DataFusionResult<Rows> {
+        let columns = self
+            .key_columns
+            .iter()
+            .map(|(name, data_type)| {
+                let column = batch
+                    .column_by_name(name)
+                    .ok_or_else(|| DataFusionError::Plan(format!("no key column {name}")))?;
+                Ok(cast(column, data_type)?)
+            })
+            .collect::<DataFusionResult<Vec<ArrayRef>>>()?;
+        Ok(self.converter.convert_columns(&columns)?)
+    }
+
+    /// Which rows of `batch`, a batch of the table's rows, are left as they are.
+    fn keeps(&self, batch: &RecordBatch) -> DataFusionResult<BooleanArray> {
+        if self.truncated {
+            return Ok(BooleanArray::from(vec![false; batch.num_rows()]));
+        }
+        let rows = self.key_rows(batch)?;
+        Ok(rows.iter().map(|row| Some(!self.rows.contains_key(&row.owned()))).collect())
+    }
+
+    /// The latest rows of the keys not deleted, with the table's columns.
+    fn upserts(&self, schema: &SchemaRef, changes: &RecordBatch) -> DataFusionResult<RecordBatch> {
+        let mut indices: Vec<u32> = self.rows.values().flatten().map(|i| *i as u32).collect();
+        indices.sort_unstable();
+        let indices = UInt32Array::from(indices);
+        let changed = adapt(schema, changes)?;
+        let columns = changed
+            .columns()
+            .iter()
+            .map(|column| Ok(take(column, &indices, None)?))
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+    }
+}
+
+/// `batch` with the columns of `schema`, matched by name: cast to their types, and
+/// null where `batch` lacks them.
+pub(crate) fn adapt(schema: &SchemaRef, batch: &RecordBatch) -> DataFusionResult<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| match batch.column_by_name(field.name()) {
+            Some(column) => Ok(cast(column, field.data_type())?),
+            None => Ok(new_null_array(field.data_type(), batch.num_rows())),
+        })
+        .collect::<DataFusionResult<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+}
+
+/// Write a manifest of `entries`, of files of the partition spec `spec_id`, to `path`
+/// and return its length.
+async fn write_manifest(
+    store: &dyn ObjectStore,
+    metadata: &TableMetadata,
+    spec_id: i32,
+    path: &str,
+    entries: Vec<EntryRecord>,
+) -> DataFusionResult<i64> {
+    let partitioner = Partitioner::try_new(metadata, spec_id)?;
+    let schema = metadata.current_schema()?;
+    let schema_json = serde_json::to_string(schema)
+        .map_err(|e| DataFusionError::Execution(format!("cannot encode Iceberg schema: {e}")))?;
+    let file_metadata = [
+        ("schema", schema_json),
+        ("schema-id", schema.schema_id.to_string()),
+        ("partition-spec", partitioner.spec_json()),
+        ("partition-spec-id", spec_id.to_string()),
+        ("format-version", "2".to_string()),
+        ("content", "data".to_string()),
+    ];
+    let partition_fields = serde_json::to_string(&partitioner.avro_fields()?)
+        .expect("partition fields are serializable");
+    let records = entries
+        .iter()
+        .map(|entry| entry.to_avro(&partitioner))
+        .collect::<DataFusionResult<Vec<_>>>()?;
+    let schema = MANIFEST_ENTRY_SCHEMA.replace("PARTITION_FIELDS", &partition_fields);
+    let bytes = avro_bytes(&schema, records, &file_metadata)?;
+    let length = bytes.len() as i64;
+    store.put(&object_path(path)?, bytes.into()).await?;
+    Ok(length)
+}
+
+/// An Avro file of `records`, with `file_metadata` in its header.
+fn avro_bytes(
+    schema: &str,
+    records: Vec<AvroValue>,
+    file_metadata: &[(&str, String)],
+) -> DataFusionResult<Vec<u8>> {
+    let schema = AvroSchema::parse_str(schema).map_err(avro_error)?;
+    let mut writer = Writer::new(&schema, Vec::new());
+    for (key, value) in file_metadata {
+        writer.add_user_metadata(key.to_string(), value).map_err(avro_error)?;
+    }
+    for record in records {
+        writer.append(record).map_err(avro_error)?;
+    }
+    writer.into_inner().map_err(avro_error)
+}
+
+fn avro_error(e: apache_avro::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+pub(crate) fn check_writable(metadata: &TableMetadata) -> DataFusionResult<()> {
+    if metadata.format_version < 2 {
+        return Err(unsupported(metadata, "writing to format version 1 Iceberg tables"));
+    }
+    Partitioner::try_new(metadata, metadata.default_spec_id)?;
+    Ok(())
+}
+
+fn unsupported(metadata: &TableMetadata, what: &str) -> DataFusionError {
+    DataFusionError::NotImplemented(format!("{what} is not supported ({})", metadata.location))
+}
+
+/// A table property, or `default` if unset.
+pub(crate) fn property<T: std::str::FromStr>(
+    metadata: &TableMetadata,
+    key: &str,
+    default: T,
+) -> DataFusionResult<T> {
+    match metadata.properties.get(key) {
+        Some(value) => value.parse().map_err(|_| {
+            DataFusionError::Plan(format!(
+                "invalid value '{value}' of Iceberg table property {key}"
+            ))
+        }),
+        None => Ok(default),
+    }
+}
+
+/// A new snapshot ID: random and positive.
+fn new_snapshot_id() -> i64 {
+    let (high, low) = Uuid::new_v4().as_u64_pair();
+    ((high ^ low) & i64::MAX as u64) as i64
+}
+
+/// The URL of the file `name` in the table's metadata directory.
+fn metadata_path(metadata: &TableMetadata, name: &str) -> String {
+    format!("{}/metadata/{name}", metadata.location.trim_end_matches('/'))
+}
+
+pub(crate) fn object_path(url: &str) -> DataFusionResult<Path> {
+    Ok(ListingTableUrl::parse(url)?.prefix().clone())
+}
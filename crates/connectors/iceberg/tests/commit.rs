@@ -0,0 +1,505 @@
+use apache_avro::from_value;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use datafusion::arrow::array::{Float64Array, Int64Array, StringArray};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use datafusion::prelude::{col, lit, SessionContext};
+use igloo_connector_iceberg::compaction::{CompactionOptions, CompactionReport, Compactor};
+use igloo_connector_iceberg::metadata::FIELD_ID_KEY;
+use igloo_connector_iceberg::rest::TableIdent;
+use igloo_connector_iceberg::retention::{Retention, RetentionPolicy};
+use igloo_connector_iceberg::write::OP_COLUMN;
+use igloo_connector_iceberg::{IcebergCatalogProvider, IcebergTable, RestCatalog};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A REST catalog of one empty table, `sales.orders`, applying the commits it is sent
+/// once failing the first `conflicts` of them as if another commit had come first.
+#[derive(Clone)]
+struct Catalog {
+    metadata: Arc<Mutex<Value>>,
+    conflicts: Arc<AtomicUsize>,
+}
+
+async fn config() -> Json<Value> {
+    Json(json!({"defaults": {}, "overrides": {}}))
+}
+
+async fn namespaces() -> Json<Value> {
+    Json(json!({"namespaces": [["sales"]]}))
+}
+
+async fn tables() -> Json<Value> {
+    Json(json!({"identifiers": [{"namespace": ["sales"], "name": "orders"}]}))
+}
+
+async fn load_table(
+    State(catalog): State<Catalog>,
+    Path((namespace, table)): Path<(String, String)>,
+) -> Result<Json<Value>, StatusCode> {
+    if (namespace.as_str(), table.as_str()) != ("sales", "orders") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    Ok(Json(json!({"metadata": metadata})))
+}
+
+async fn commit_table(
+    State(catalog): State<Catalog>,
+    Json(request): Json<Value>,
+) -> Result<Json<Value>, StatusCode> {
+    let mut metadata = catalog.metadata.lock().unwrap();
+    for requirement in request["requirements"].as_array().unwrap() {
+        let holds = match requirement["type"].as_str().unwrap() {
+            "assert-ref-snapshot-id" => {
+                assert_eq!(requirement["ref"], "main");
+                requirement["snapshot-id"] == metadata["current-snapshot-id"]
+            }
+            "assert-table-uuid" => requirement["uuid"] == metadata["table-uuid"],
+            other => panic!("unexpected requirement {other}"),
+        };
+        if !holds {
+            return Err(StatusCode::CONFLICT);
+        }
+    }
+    if catalog.conflicts.load(Ordering::SeqCst) > 0 {
+        catalog.conflicts.fetch_sub(1, Ordering::SeqCst);
+        return Err(StatusCode::CONFLICT);
+    }
+    for update in request["updates"].as_array().unwrap() {
+        match update["action"].as_str().unwrap() {
+            "add-snapshot" => {
+                let snapshot = update["snapshot"].clone();
+                metadata["last-sequence-number"] = snapshot["sequence-number"].clone();
+                metadata["snapshots"].as_array_mut().unwrap().push(snapshot);
+            }
+            "set-snapshot-ref" => {
+                assert_eq!(
+                    (&update["ref-name"], &update["type"]),
+                    (&json!("main"), &json!("branch"))
+                );
+                metadata["current-snapshot-id"] = update["snapshot-id"].clone();
+            }
+            "remove-snapshots" => {
+                let expired = update["snapshot-ids"].as_array().unwrap();
+                let snapshots = metadata["snapshots"].as_array_mut().unwrap();
+                snapshots.retain(|snapshot| !expired.contains(&snapshot["snapshot-id"]));
+            }
+            other => panic!("unexpected update {other}"),
+        }
+    }
+    Ok(Json(json!({"metadata-location": "unused", "metadata": metadata.clone()})))
+}
+
+async fn start(catalog: Catalog) -> String {
+    let app = Router::new()
+        .route("/v1/config", get(config))
+        .route("/v1/namespaces", get(namespaces))
+        .route("/v1/namespaces/:namespace/tables", get(tables))
+        .route("/v1/namespaces/:namespace/tables/:table", get(load_table).post(commit_table))
+        .with_state(catalog);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+#[derive(Deserialize)]
+struct ManifestFile {
+    manifest_path: String,
+    sequence_number: i64,
+    added_files_count: i32,
+    existing_files_count: i32,
+    deleted_files_count: i32,
+}
+
+fn read_avro<T: serde::de::DeserializeOwned>(url: &str) -> Vec<T> {
+    let bytes = std::fs::read(url.strip_prefix("file://").unwrap()).unwrap();
+    let reader = apache_avro::Reader::new(&bytes[..]).unwrap();
+    reader.map(|value| from_value(&value.unwrap()).unwrap()).collect()
+}
+
+async fn query(ctx: &SessionContext, sql: &str) -> String {
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+/// The metadata of an empty table at `dir`.
+fn empty_table(dir: &std::path::Path) -> Value {
+    json!({
+        "format-version": 2,
+        "table-uuid": "7a1d9e4b-0000-4000-8000-000000000000",
+        "location": format!("file://{}", dir.display()),
+        "last-sequence-number": 0,
+        "current-schema-id": 0,
+        "schemas": [{"schema-id": 0, "fields": [
+            {"id": 1, "name": "id", "required": true, "type": "long"},
+            {"id": 2, "name": "amount", "required": false, "type": "double"}
+        ]}],
+        "default-spec-id": 0,
+        "partition-specs": [{"spec-id": 0, "fields": []}],
+        "snapshots": []
+    })
+}
+
+#[tokio::test]
+async fn test_inserts_and_changes_are_committed_as_snapshots() {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-commit-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let catalog = Catalog {
+        metadata: Arc::new(Mutex::new(empty_table(&dir))),
+        conflicts: Arc::new(AtomicUsize::new(0)),
+    };
+    let rest = Arc::new(RestCatalog::new(start(catalog.clone()).await));
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(rest.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+
+    let inserted = "\
++-------+
+| count |
++-------+
+| 2     |
++-------+";
+    let sql = "INSERT INTO iceberg.sales.orders VALUES (1, 10.0), (2, 20.0)";
+    assert_eq!(query(&ctx, sql).await, inserted);
+    // The second insert loses a race, and is committed again on the new snapshot.
+    catalog.conflicts.store(1, Ordering::SeqCst);
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (3, 30.0)").await;
+    assert_eq!(catalog.conflicts.load(Ordering::SeqCst), 0);
+    let expected = "\
++--------+-------+
+| orders | total |
++--------+-------+
+| 3      | 60.0  |
++--------+-------+";
+    let sql = "SELECT count(*) AS orders, sum(amount) AS total FROM iceberg.sales.orders";
+    assert_eq!(query(&ctx, sql).await, expected);
+
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    let snapshots = metadata["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[1]["parent-snapshot-id"], snapshots[0]["snapshot-id"]);
+    assert_eq!(snapshots[1]["summary"]["operation"], "append");
+    assert_eq!(snapshots[1]["summary"]["added-records"], "1");
+    let list = read_avro::<ManifestFile>(snapshots[1]["manifest-list"].as_str().unwrap());
+    let sequence_numbers: Vec<_> = list.iter().map(|m| m.sequence_number).collect();
+    assert_eq!(sequence_numbers, [1, 2]);
+    // Data files carry field IDs, for readers matching columns by them.
+    let entries = read_avro::<Value>(&list[0].manifest_path);
+    let path = entries[0]["data_file"]["file_path"].as_str().unwrap();
+    let file = std::fs::File::open(path.strip_prefix("file://").unwrap()).unwrap();
+    let schema = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().schema().clone();
+    assert_eq!(schema.field_with_name("amount").unwrap().metadata()[FIELD_ID_KEY], "2");
+
+    // Changes as a CDC decoder produces them: order 2 updated twice, order 1 deleted
+    // and order 4 created.
+    let changes = RecordBatch::try_new(
+        Arc::new(Schema::new(vec![
+            Field::new(OP_COLUMN, DataType::Utf8, false),
+            Field::new("id", DataType::Int64, true),
+            Field::new("amount", DataType::Float64, true),
+        ])),
+        vec![
+            Arc::new(StringArray::from(vec!["u", "d", "c", "u"])),
+            Arc::new(Int64Array::from(vec![2, 1, 4, 2])),
+            Arc::new(Float64Array::from(vec![Some(21.0), None, Some(40.0), Some(25.0)])),
+        ],
+    )
+    .unwrap();
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    let loaded = rest.load_table(&ident).await.unwrap().unwrap();
+    let table = IcebergTable::try_new(loaded.metadata).unwrap().with_catalog(rest, ident);
+    let metadata = table.apply_changes(&ctx.state(), &["id".to_string()], &changes).await.unwrap();
+    let summary = &metadata.current_snapshot().unwrap().summary;
+    assert_eq!(summary["operation"], "overwrite");
+    assert_eq!(summary["deleted-data-files"], "1");
+    let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 2  | 25.0   |
+| 3  | 30.0   |
+| 4  | 40.0   |
++----+--------+";
+    assert_eq!(query(&ctx, "SELECT * FROM iceberg.sales.orders ORDER BY id").await, expected);
+    let list = read_avro::<ManifestFile>(
+        metadata.current_snapshot().unwrap().manifest_list.as_ref().unwrap(),
+    );
+    let counts: Vec<_> = list
+        .iter()
+        .map(|m| (m.added_files_count, m.existing_files_count, m.deleted_files_count))
+        .collect();
+    // The first insert's manifest rewritten with its file deleted, the second's carried
+    // over as it was, and a file of the changed rows added.
+    assert_eq!(counts, [(0, 0, 1), (1, 0, 0), (1, 0, 0)]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_small_files_are_compacted_into_a_replace_snapshot() {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-compact-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let catalog = Catalog {
+        metadata: Arc::new(Mutex::new(empty_table(&dir))),
+        conflicts: Arc::new(AtomicUsize::new(0)),
+    };
+    let rest = Arc::new(RestCatalog::new(start(catalog.clone()).await));
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(rest.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    for id in 1..=6 {
+        let sql = format!("INSERT INTO iceberg.sales.orders VALUES ({id}, {id}0.0)");
+        query(&ctx, &sql).await;
+    }
+
+    let compactor = Compactor::new(rest, Arc::new(ctx.state()));
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    assert_eq!(compactor.tables().await.unwrap(), std::slice::from_ref(&ident));
+    let report = compactor.compact(&ident).await.unwrap();
+    assert_eq!(report, Some(CompactionReport { rewritten_files: 6, added_files: 1, rows: 6 }));
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    let summary = &metadata["snapshots"].as_array().unwrap()[6]["summary"];
+    assert_eq!(summary["operation"], "replace");
+    assert_eq!(summary["deleted-data-files"], "6");
+    assert_eq!(summary["added-records"], "6");
+    let expected = "\
++--------+-------+
+| orders | total |
++--------+-------+
+| 6      | 210.0 |
++--------+-------+";
+    let sql = "SELECT count(*) AS orders, sum(amount) AS total FROM iceberg.sales.orders";
+    assert_eq!(query(&ctx, sql).await, expected);
+    // One file is as compact as it gets.
+    assert_eq!(compactor.compact(&ident).await.unwrap(), None);
+    // Files of at least the minimum size are left as they are.
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (7, 70.0)").await;
+    let compactor = compactor
+        .with_options(CompactionOptions::default().with_min_input_files(2).with_min_file_size(1));
+    assert_eq!(compactor.compact(&ident).await.unwrap(), None);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_deletes_rewrite_the_files_holding_deleted_rows() {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-delete-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let catalog = Catalog {
+        metadata: Arc::new(Mutex::new(empty_table(&dir))),
+        conflicts: Arc::new(AtomicUsize::new(0)),
+    };
+    let rest = Arc::new(RestCatalog::new(start(catalog.clone()).await));
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(rest.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (1, 10.0), (2, NULL)").await;
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (3, 30.0)").await;
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (4, 40.0)").await;
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    let table = || async {
+        let loaded = rest.load_table(&ident).await.unwrap().unwrap();
+        IcebergTable::try_new(loaded.metadata).unwrap().with_catalog(rest.clone(), ident.clone())
+    };
+
+    // Order 2, whose amount is null, is kept; order 3's file is dropped.
+    let predicate = col("orders.amount").lt(lit(35.0));
+    assert_eq!(table().await.delete(&ctx.state(), Some(&predicate)).await.unwrap(), 2);
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    let summary = &metadata["snapshots"][3]["summary"];
+    assert_eq!(summary["operation"], "overwrite");
+    assert_eq!(
+        (&summary["deleted-data-files"], &summary["added-records"]),
+        (&json!("2"), &json!("1"))
+    );
+    let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 2  |        |
+| 4  | 40.0   |
++----+--------+";
+    assert_eq!(query(&ctx, "SELECT * FROM iceberg.sales.orders ORDER BY id").await, expected);
+    // Nothing to delete, nothing committed.
+    let predicate = col("id").eq(lit(1));
+    assert_eq!(table().await.delete(&ctx.state(), Some(&predicate)).await.unwrap(), 0);
+    assert_eq!(catalog.metadata.lock().unwrap()["snapshots"].as_array().unwrap().len(), 4);
+
+    assert_eq!(table().await.delete(&ctx.state(), None).await.unwrap(), 2);
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    assert_eq!(metadata["snapshots"][4]["summary"]["operation"], "delete");
+    let sql = "SELECT count(*) AS orders FROM iceberg.sales.orders";
+    assert!(query(&ctx, sql).await.contains("| 0      |"));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_rows_are_written_by_partition() {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-partition-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut metadata = empty_table(&dir);
+    metadata["schemas"][0]["fields"] = json!([
+        {"id": 1, "name": "id", "required": true, "type": "long"},
+        {"id": 2, "name": "region", "required": false, "type": "string"},
+        {"id": 3, "name": "placed", "required": false, "type": "timestamp"}
+    ]);
+    metadata["partition-specs"] = json!([{"spec-id": 0, "fields": [
+        {"source-id": 3, "field-id": 1000, "name": "placed_day", "transform": "day"},
+        {"source-id": 2, "field-id": 1001, "name": "region", "transform": "identity"},
+        {"source-id": 1, "field-id": 1002, "name": "id_bucket", "transform": "bucket[4]"}
+    ]}]);
+    let catalog = Catalog {
+        metadata: Arc::new(Mutex::new(metadata)),
+        conflicts: Arc::new(AtomicUsize::new(0)),
+    };
+    let rest = Arc::new(RestCatalog::new(start(catalog.clone()).await));
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(rest.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    let sql = "INSERT INTO iceberg.sales.orders VALUES \
+        (1, 'eu', TIMESTAMP '2024-05-01 10:00:00'), \
+        (1, 'eu', TIMESTAMP '2024-05-01 23:59:59'), \
+        (1, 'us/east', TIMESTAMP '2024-05-02 00:00:00'), \
+        (1, NULL, NULL)";
+    query(&ctx, sql).await;
+
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    let list =
+        read_avro::<ManifestFile>(metadata["snapshots"][0]["manifest-list"].as_str().unwrap());
+    let entries = read_avro::<Value>(&list[0].manifest_path);
+    let mut files: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let file = &entry["data_file"];
+            let (_, path) = file["file_path"].as_str().unwrap().split_once("/data/").unwrap();
+            let directory = path.rsplit_once('/').unwrap().0.to_string();
+            (directory, file["partition"].clone(), file["record_count"].clone())
+        })
+        .collect();
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let bucket = files[0].1["id_bucket"].as_i64().unwrap();
+    assert!((0..4).contains(&bucket));
+    // 2024-05-01 is day 19844.
+    assert_eq!(
+        files,
+        [
+            (
+                format!("placed_day=2024-05-01/region=eu/id_bucket={bucket}"),
+                json!({"placed_day": 19844, "region": "eu", "id_bucket": bucket}),
+                json!(2)
+            ),
+            (
+                format!("placed_day=2024-05-02/region=us%2Feast/id_bucket={bucket}"),
+                json!({"placed_day": 19845, "region": "us/east", "id_bucket": bucket}),
+                json!(1)
+            ),
+            (
+                format!("placed_day=null/region=null/id_bucket={bucket}"),
+                json!({"placed_day": null, "region": null, "id_bucket": bucket}),
+                json!(1)
+            ),
+        ]
+    );
+
+    // Manifests of partitioned tables are rewritten like any other.
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    let loaded = rest.load_table(&ident).await.unwrap().unwrap();
+    let table = IcebergTable::try_new(loaded.metadata).unwrap().with_catalog(rest, ident);
+    let predicate = col("region").eq(lit("eu"));
+    assert_eq!(table.delete(&ctx.state(), Some(&predicate)).await.unwrap(), 2);
+    let expected = "\
++---------+---------------------+
+| region  | placed              |
++---------+---------------------+
+|         |                     |
+| us/east | 2024-05-02T00:00:00 |
++---------+---------------------+";
+    let sql = "SELECT region, placed FROM iceberg.sales.orders ORDER BY region NULLS FIRST";
+    assert_eq!(query(&ctx, sql).await, expected);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_retention_drops_old_partitions_snapshots_and_orphan_files() {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-retention-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut metadata = empty_table(&dir);
+    metadata["schemas"][0]["fields"] = json!([
+        {"id": 1, "name": "id", "required": true, "type": "long"},
+        {"id": 2, "name": "placed", "required": false, "type": "timestamp"}
+    ]);
+    metadata["partition-specs"] = json!([{"spec-id": 0, "fields": [
+        {"source-id": 2, "field-id": 1000, "name": "placed_month", "transform": "month"}
+    ]}]);
+    let catalog = Catalog {
+        metadata: Arc::new(Mutex::new(metadata)),
+        conflicts: Arc::new(AtomicUsize::new(0)),
+    };
+    let rest = Arc::new(RestCatalog::new(start(catalog.clone()).await));
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(rest.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (1, TIMESTAMP '2020-01-31 23:00:00')")
+        .await;
+    query(&ctx, "INSERT INTO iceberg.sales.orders VALUES (2, TIMESTAMP '2200-01-01 00:00:00')")
+        .await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    let policy = RetentionPolicy::default()
+        .with_partition_max_age(Duration::from_secs(365 * 24 * 3600))
+        .with_snapshot_max_age(Duration::ZERO)
+        .with_orphan_file_min_age(Duration::ZERO);
+    let retention = Retention::new(rest.clone(), Arc::new(ctx.state())).with_dry_run(true);
+    let report = retention.apply(&ident, &policy).await.unwrap();
+    // 2020-01 is month 600.
+    let partition = BTreeMap::from([("placed_month".to_string(), json!(600))]);
+    assert_eq!(report.dropped_partitions, [partition]);
+    assert_eq!((report.dropped_files, report.dropped_rows), (1, 1));
+    let first = catalog.metadata.lock().unwrap()["snapshots"][0]["snapshot-id"].as_i64().unwrap();
+    assert_eq!(report.expired_snapshots, [first]);
+    // The manifest list of the first snapshot; its manifest is carried over.
+    assert_eq!(report.orphan_files.len(), 1, "{:?}", report.orphan_files);
+    assert_eq!(catalog.metadata.lock().unwrap()["snapshots"].as_array().unwrap().len(), 2);
+    assert!(std::path::Path::new("/").join(&report.orphan_files[0]).exists());
+
+    let retention = retention.with_dry_run(false);
+    let report = retention.apply(&ident, &policy).await.unwrap();
+    assert_eq!((report.dropped_files, report.dropped_rows), (1, 1));
+    assert_eq!(report.expired_snapshots.len(), 2);
+    // The manifest lists of both, the manifest of the first, rewritten by the delete,
+    // and the data file dropped.
+    let mut orphans: Vec<_> =
+        report.orphan_files.iter().map(|file| file.rsplit_once('.').unwrap().1).collect();
+    orphans.sort();
+    assert_eq!(orphans, ["avro", "avro", "avro", "parquet"]);
+    assert!(report.orphan_files.iter().all(|file| !std::path::Path::new("/").join(file).exists()));
+    let metadata = catalog.metadata.lock().unwrap().clone();
+    let snapshots = metadata["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0]["summary"]["operation"], "delete");
+    let expected = "\
++----+---------------------+
+| id | placed              |
++----+---------------------+
+| 2  | 2200-01-01T00:00:00 |
++----+---------------------+";
+    assert_eq!(query(&ctx, "SELECT * FROM iceberg.sales.orders").await, expected);
+    assert!(retention.apply(&ident, &policy).await.unwrap().is_empty());
+
+    // Tables without a time to partition by have no partitions to age.
+    let policy = RetentionPolicy::default().with_partition_max_age(Duration::ZERO);
+    catalog.metadata.lock().unwrap()["partition-specs"] = json!([{"spec-id": 0, "fields": []}]);
+    let error = retention.apply(&ident, &policy).await.unwrap_err();
+    assert!(error.to_string().contains("no date or time partition field"), "{error}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,273 @@
+use apache_avro::{Schema as AvroSchema, Writer};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Form, Json, Router};
+use datafusion::arrow::array::{Float64Array, Int64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::prelude::SessionContext;
+use igloo_connector_iceberg::{IcebergCatalogProvider, RestCatalog};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const TOKEN: &str = "secret-token";
+
+/// A REST catalog with one table, `sales.orders`, under the `analytics` warehouse,
+/// handing out tokens for the `igloo:s3cret` credential.
+#[derive(Clone)]
+struct Catalog {
+    dir: PathBuf,
+    tokens_issued: Arc<AtomicUsize>,
+}
+
+fn authorized(headers: &HeaderMap) -> Result<(), StatusCode> {
+    let expected = format!("Bearer {TOKEN}");
+    match headers.get("authorization") {
+        Some(value) if value == expected.as_str() => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+async fn tokens(
+    State(catalog): State<Catalog>,
+    Form(form): Form<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    if form["grant_type"] != "client_credentials"
+        || form["client_id"] != "igloo"
+        || form["client_secret"] != "s3cret"
+    {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    catalog.tokens_issued.fetch_add(1, Ordering::SeqCst);
+    Ok(Json(json!({"access_token": TOKEN, "token_type": "bearer", "expires_in": 3600})))
+}
+
+async fn config(
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    authorized(&headers)?;
+    assert_eq!(query["warehouse"], "analytics");
+    Ok(Json(json!({"defaults": {}, "overrides": {"prefix": "analytics"}})))
+}
+
+async fn namespaces(
+    headers: HeaderMap,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Json<Value>, StatusCode> {
+    authorized(&headers)?;
+    let namespaces = if query.contains_key("parent") { json!([]) } else { json!([["sales"]]) };
+    Ok(Json(json!({"namespaces": namespaces})))
+}
+
+async fn tables(
+    headers: HeaderMap,
+    Path(namespace): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    authorized(&headers)?;
+    assert_eq!(namespace, "sales");
+    Ok(Json(json!({"identifiers": [{"namespace": ["sales"], "name": "orders"}]})))
+}
+
+async fn load_table(
+    State(catalog): State<Catalog>,
+    headers: HeaderMap,
+    Path((namespace, table)): Path<(String, String)>,
+) -> Result<Json<Value>, (StatusCode, Json<Value>)> {
+    authorized(&headers).map_err(|status| (status, Json(json!({}))))?;
+    if namespace != "sales" || table != "orders" {
+        let error = json!({"error": {
+            "message": format!("Table does not exist: {namespace}.{table}"),
+            "type": "NoSuchTableException",
+            "code": 404
+        }});
+        return Err((StatusCode::NOT_FOUND, Json(error)));
+    }
+    let dir = catalog.dir.display();
+    Ok(Json(json!({
+        "metadata-location": format!("file://{dir}/metadata/v2.metadata.json"),
+        "metadata": {
+            "format-version": 2,
+            "table-uuid": "5e9f6b4a-0000-4000-8000-000000000000",
+            "location": format!("file://{dir}"),
+            "current-schema-id": 0,
+            "schemas": [{"schema-id": 0, "fields": [
+                {"id": 1, "name": "id", "required": true, "type": "long"},
+                {"id": 2, "name": "amount", "required": false, "type": "double"}
+            ]}],
+            "current-snapshot-id": 7,
+            "snapshots": [{
+                "snapshot-id": 7,
+                "timestamp-ms": 1700000000000i64,
+                "manifest-list": format!("file://{dir}/metadata/snap-7.avro")
+            }]
+        }
+    })))
+}
+
+async fn start(catalog: Catalog) -> String {
+    let app = Router::new()
+        .route("/v1/oauth/tokens", post(tokens))
+        .route("/v1/config", get(config))
+        .route("/v1/analytics/namespaces", get(namespaces))
+        .route("/v1/analytics/namespaces/:namespace/tables", get(tables))
+        .route("/v1/analytics/namespaces/:namespace/tables/:table", get(load_table))
+        .with_state(catalog);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    manifest_path: String,
+    manifest_length: i64,
+    partition_spec_id: i32,
+    content: i32,
+    added_snapshot_id: i64,
+}
+
+#[derive(Serialize)]
+struct ManifestEntry {
+    status: i32,
+    snapshot_id: i64,
+    data_file: DataFile,
+}
+
+#[derive(Serialize)]
+struct DataFile {
+    content: i32,
+    file_path: String,
+    file_format: String,
+    record_count: i64,
+    file_size_in_bytes: i64,
+}
+
+fn write_avro<T: Serialize>(path: PathBuf, schema: &str, records: Vec<T>) {
+    let schema = AvroSchema::parse_str(schema).unwrap();
+    let mut writer = Writer::new(&schema, Vec::new());
+    for record in records {
+        writer.append_ser(record).unwrap();
+    }
+    std::fs::write(path, writer.into_inner().unwrap()).unwrap();
+}
+
+/// Write a table of three orders, whose snapshot also records a removed file.
+fn write_table() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("igloo-iceberg-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("data")).unwrap();
+    std::fs::create_dir_all(dir.join("metadata")).unwrap();
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Int64, false),
+        Field::new("amount", DataType::Float64, true),
+    ]));
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(Int64Array::from(vec![1, 2, 3])),
+            Arc::new(Float64Array::from(vec![10.0, 20.0, 30.0])),
+        ],
+    )
+    .unwrap();
+    let data = dir.join("data/00000.parquet");
+    let mut writer =
+        ArrowWriter::try_new(std::fs::File::create(&data).unwrap(), schema, None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let file = |path: &PathBuf, size| DataFile {
+        content: 0,
+        file_path: format!("file://{}", path.display()),
+        file_format: "PARQUET".into(),
+        record_count: 3,
+        file_size_in_bytes: size,
+    };
+    let size = std::fs::metadata(&data).unwrap().len() as i64;
+    let removed = dir.join("data/removed.parquet");
+    write_avro(
+        dir.join("metadata/manifest-1.avro"),
+        r#"{"type": "record", "name": "manifest_entry", "fields": [
+            {"name": "status", "type": "int"},
+            {"name": "snapshot_id", "type": "long"},
+            {"name": "data_file", "type": {"type": "record", "name": "r2", "fields": [
+                {"name": "content", "type": "int"},
+                {"name": "file_path", "type": "string"},
+                {"name": "file_format", "type": "string"},
+                {"name": "record_count", "type": "long"},
+                {"name": "file_size_in_bytes", "type": "long"}
+            ]}}
+        ]}"#,
+        vec![
+            ManifestEntry { status: 1, snapshot_id: 7, data_file: file(&data, size) },
+            ManifestEntry { status: 2, snapshot_id: 7, data_file: file(&removed, 100) },
+        ],
+    );
+    write_avro(
+        dir.join("metadata/snap-7.avro"),
+        r#"{"type": "record", "name": "manifest_file", "fields": [
+            {"name": "manifest_path", "type": "string"},
+            {"name": "manifest_length", "type": "long"},
+            {"name": "partition_spec_id", "type": "int"},
+            {"name": "content", "type": "int"},
+            {"name": "added_snapshot_id", "type": "long"}
+        ]}"#,
+        vec![ManifestFile {
+            manifest_path: format!("file://{}/metadata/manifest-1.avro", dir.display()),
+            manifest_length: 0,
+            partition_spec_id: 0,
+            content: 0,
+            added_snapshot_id: 7,
+        }],
+    );
+    dir
+}
+
+#[tokio::test]
+async fn test_tables_are_read_through_the_rest_catalog() {
+    let dir = write_table();
+    let tokens_issued = Arc::new(AtomicUsize::new(0));
+    let uri = start(Catalog { dir: dir.clone(), tokens_issued: tokens_issued.clone() }).await;
+    let catalog = Arc::new(
+        RestCatalog::new(uri.clone()).with_warehouse("analytics").with_credential("igloo:s3cret"),
+    );
+
+    assert_eq!(catalog.list_namespaces(None).await.unwrap(), vec![vec!["sales".to_string()]]);
+    let tables = catalog.list_tables(&vec!["sales".to_string()]).await.unwrap();
+    assert_eq!(tables[0].name, "orders");
+
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(catalog.clone()).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    let batches = ctx
+        .sql("SELECT count(*) AS orders, sum(amount) AS total FROM iceberg.sales.orders")
+        .await
+        .unwrap()
+        .collect()
+        .await
+        .unwrap();
+    let expected = "\
++--------+-------+
+| orders | total |
++--------+-------+
+| 3      | 60.0  |
++--------+-------+";
+    assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+    let error = ctx.sql("SELECT * FROM iceberg.sales.missing").await.unwrap_err();
+    assert!(error.to_string().contains("not found"), "{error}");
+    // One token serves every request until it expires.
+    assert_eq!(tokens_issued.load(Ordering::SeqCst), 1);
+
+    let wrong = RestCatalog::new(uri).with_warehouse("analytics").with_credential("igloo:guess");
+    let error = wrong.list_namespaces(None).await.unwrap_err();
+    assert!(error.to_string().contains("401"), "{error}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,231 @@
+//! Kafka record values as Arrow rows.
+//!
+//! Values are decoded into the columns of a given schema, the target table's, matched
+//! by name: fields without a column are ignored, and columns without a field are
+//! null (an error for non-nullable ones). JSON values are objects, one per record.
+//! Avro values are in the Schema Registry's wire format, each read with the schema it
+//! was written with, and converted on the way like JSON: dates, times and timestamps
+//! as Arrow parses them from strings, nested records as structs.
+
+use apache_avro::schema::Schema as AvroSchema;
+use apache_avro::types::Value;
+use arrow::json::reader::{Decoder, ReaderBuilder};
+use arrow::temporal_conversions::{
+    date32_to_datetime, time32ms_to_time, time64us_to_time, timestamp_ms_to_datetime,
+    timestamp_ns_to_datetime, timestamp_us_to_datetime,
+};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use igloo_cdc::registry::split_message;
+use igloo_cdc::SchemaRegistryClient;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Rows of the batches decoded values are gathered in.
+pub const BATCH_SIZE: usize = 8192;
+
+/// How record values are encoded.
+#[derive(Clone)]
+pub enum RecordFormat {
+    Json,
+    /// Avro in the wire format of the registry holding the schemas.
+    Avro(Arc<SchemaRegistryClient>),
+}
+
+impl fmt::Debug for RecordFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordFormat::Json => f.write_str("Json"),
+            RecordFormat::Avro(registry) => f.debug_tuple("Avro").field(registry).finish(),
+        }
+    }
+}
+
+/// Decodes record values into batches of a schema, see the [module docs](self).
+pub struct RecordDecoder {
+    format: RecordFormat,
+    decoder: Decoder,
+    /// Parsed Avro schemas by ID, those referenced first.
+    avro_schemas: HashMap<u32, Arc<Vec<AvroSchema>>>,
+    batches: Vec<RecordBatch>,
+}
+
+impl fmt::Debug for RecordDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordDecoder").field("format", &self.format).finish_non_exhaustive()
+    }
+}
+
+impl RecordDecoder {
+    pub fn try_new(format: RecordFormat, schema: SchemaRef) -> DataFusionResult<Self> {
+        let decoder = ReaderBuilder::new(schema).with_batch_size(BATCH_SIZE).build_decoder()?;
+        Ok(Self { format, decoder, avro_schemas: HashMap::new(), batches: vec![] })
+    }
+
+    /// Decode the value of a record as a row.
+    pub async fn push(&mut self, value: &[u8]) -> DataFusionResult<()> {
+        if self.decoder.len() >= BATCH_SIZE {
+            self.batches.extend(self.decoder.flush()?);
+        }
+        match &self.format {
+            RecordFormat::Json => {
+                let rows = self.decoder.len();
+                let read = self.decoder.decode(value)?;
+                // Ends the document, so one missing its closing brace is not continued
+                // by the next record's.
+                self.decoder.decode(b"\n")?;
+                if read < value.len()
+                    || self.decoder.has_partial_record()
+                    || self.decoder.len() != rows + 1
+                {
+                    return Err(DataFusionError::Execution(
+                        "Kafka record value is not one JSON document".to_string(),
+                    ));
+                }
+            }
+            RecordFormat::Avro(registry) => {
+                let (id, mut payload) = split_message(value).map_err(cdc_error)?;
+                let schemata = match self.avro_schemas.get(&id) {
+                    Some(schemata) => Arc::clone(schemata),
+                    None => {
+                        let resolved = registry.schema(id).await.map_err(cdc_error)?;
+                        let references = resolved.references.iter().map(|(_, s)| &s.schema);
+                        let sources: Vec<&str> = references
+                            .chain([&resolved.schema.schema])
+                            .map(String::as_str)
+                            .collect();
+                        let schemata = AvroSchema::parse_list(&sources)
+                            .map_err(|e| avro_error(&format!("schema {id}"), e))?;
+                        let schemata = Arc::new(schemata);
+                        self.avro_schemas.insert(id, Arc::clone(&schemata));
+                        schemata
+                    }
+                };
+                let schema = schemata.last().expect("the record schema is parsed last");
+                let value = apache_avro::from_avro_datum_schemata(
+                    schema,
+                    schemata.iter().collect(),
+                    &mut payload,
+                    None,
+                )
+                .map_err(|e| avro_error(&format!("a record of schema {id}"), e))?;
+                let row = to_json(value)?;
+                if !row.is_object() {
+                    return Err(DataFusionError::Execution(format!(
+                        "Avro schema {id} of a Kafka record value is not a record"
+                    )));
+                }
+                self.decoder.serialize(&[row])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rows decoded since the last flush.
+    pub fn flush(&mut self) -> DataFusionResult<Vec<RecordBatch>> {
+        self.batches.extend(self.decoder.flush()?);
+        Ok(std::mem::take(&mut self.batches))
+    }
+}
+
+/// `value` as JSON, see the [module docs](self).
+fn to_json(value: Value) -> DataFusionResult<serde_json::Value> {
+    let invalid = || DataFusionError::Execution("Avro value out of range".to_string());
+    let json = match value {
+        Value::Union(_, value) => return to_json(*value),
+        Value::Record(fields) => {
+            let fields = fields.into_iter().map(|(name, value)| Ok((name, to_json(value)?)));
+            serde_json::Value::Object(fields.collect::<DataFusionResult<_>>()?)
+        }
+        Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(to_json).collect::<Result<_, _>>()?)
+        }
+        Value::Map(entries) => {
+            let entries = entries.into_iter().map(|(key, value)| Ok((key, to_json(value)?)));
+            serde_json::Value::Object(entries.collect::<DataFusionResult<_>>()?)
+        }
+        Value::Date(days) => {
+            let date = date32_to_datetime(days).ok_or_else(invalid)?.date();
+            serde_json::Value::String(date.to_string())
+        }
+        Value::TimeMillis(t) => time32ms_to_time(t).ok_or_else(invalid)?.to_string().into(),
+        Value::TimeMicros(t) => time64us_to_time(t).ok_or_else(invalid)?.to_string().into(),
+        Value::TimestampMillis(t) | Value::LocalTimestampMillis(t) => {
+            timestamp_ms_to_datetime(t).ok_or_else(invalid)?.to_string().into()
+        }
+        Value::TimestampMicros(t) | Value::LocalTimestampMicros(t) => {
+            timestamp_us_to_datetime(t).ok_or_else(invalid)?.to_string().into()
+        }
+        Value::TimestampNanos(t) | Value::LocalTimestampNanos(t) => {
+            timestamp_ns_to_datetime(t).ok_or_else(invalid)?.to_string().into()
+        }
+        Value::Decimal(_) | Value::BigDecimal(_) => {
+            return Err(DataFusionError::NotImplemented(
+                "ingesting Avro decimals from Kafka".to_string(),
+            ))
+        }
+        value => serde_json::Value::try_from(value).map_err(|e| avro_error("a value", e))?,
+    };
+    Ok(json)
+}
+
+fn avro_error(what: &str, e: apache_avro::Error) -> DataFusionError {
+    DataFusionError::Execution(format!("failed to read Avro {what}: {e}"))
+}
+
+fn cdc_error(e: igloo_common::error::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Array, AsArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Int64Type, Schema, TimeUnit};
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("amount", DataType::Float64, true),
+            Field::new("at", DataType::Timestamp(TimeUnit::Microsecond, None), true),
+        ]))
+    }
+
+    #[tokio::test]
+    async fn test_json_values_are_rows() {
+        let mut decoder = RecordDecoder::try_new(RecordFormat::Json, schema()).unwrap();
+        decoder.push(br#"{"id": 1, "amount": 2.5, "extra": true}"#).await.unwrap();
+        decoder.push(br#"{"id": 2, "at": "2024-05-01T10:00:00"}"#).await.unwrap();
+        let batches = decoder.flush().unwrap();
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.schema(), schema());
+        let ids = batch.column(0).as_primitive::<Int64Type>();
+        assert_eq!(ids.values(), &[1, 2]);
+        assert_eq!(batch.column(1).null_count(), 1);
+        assert!(decoder.flush().unwrap().is_empty());
+
+        assert!(decoder.push(br#"{"id": 3"#).await.is_err());
+        let mut decoder = RecordDecoder::try_new(RecordFormat::Json, schema()).unwrap();
+        assert!(decoder.push(br#"{"id": 3} {"id": 4}"#).await.is_err());
+    }
+
+    #[test]
+    fn test_avro_values_as_json() {
+        let value = Value::Record(vec![
+            ("id".to_string(), Value::Union(1, Box::new(Value::Long(7)))),
+            ("at".to_string(), Value::TimestampMillis(1_714_557_600_123)),
+            ("day".to_string(), Value::Date(19_844)),
+            ("tags".to_string(), Value::Array(vec![Value::String("a".to_string())])),
+        ]);
+        let expected = serde_json::json!({
+            "id": 7,
+            "at": "2024-05-01 10:00:00.123",
+            "day": "2024-05-01",
+            "tags": ["a"],
+        });
+        assert_eq!(to_json(value).unwrap(), expected);
+    }
+}
@@ -0,0 +1,39 @@
+//! Apache Kafka topics.
+//!
+//! Topics are consumed through a Confluent REST Proxy ([`KafkaRestClient`]).
+//! [`KafkaIngestion`] continuously appends the records of a topic, JSON or Avro, to an
+//! Iceberg table, a snapshot per commit interval, checkpointing the offsets it has
+//! ingested up to in the snapshots themselves (see [`pipeline`]):
+//!
+//! ```no_run
+//! # async fn example(store: std::sync::Arc<dyn object_store::ObjectStore>) -> datafusion::error::Result<()> {
+//! use igloo_connector_iceberg::rest::TableIdent;
+//! use igloo_connector_iceberg::RestCatalog;
+//! use igloo_connector_kafka::{KafkaIngestion, KafkaRestClient};
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! let proxy = KafkaRestClient::new("http://rest-proxy:8082");
+//! let catalog = Arc::new(RestCatalog::new("https://polaris.example.com/api/catalog"));
+//! let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+//! KafkaIngestion::new(proxy, "orders", catalog, ident, store)
+//!     .with_commit_interval(Duration::from_secs(30))
+//!     .run()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A topic is also a table: [`KafkaTable`] reads a range of its records at each scan,
+//! and [`KafkaWindow`] keeps those of the last while, consumed continuously (see
+//! [`table`]).
+
+pub mod decode;
+pub mod pipeline;
+pub mod rest;
+pub mod table;
+
+pub use decode::RecordFormat;
+pub use pipeline::KafkaIngestion;
+pub use rest::KafkaRestClient;
+pub use table::{KafkaTable, KafkaWindow};
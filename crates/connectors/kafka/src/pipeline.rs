@@ -0,0 +1,299 @@
+//! Continuous ingestion of a Kafka topic into an Iceberg table.
+//!
+//! A [`KafkaIngestion`] consumes every partition of a topic through a REST Proxy,
+//! decodes record values into the table's columns (see [`decode`](crate::decode)),
+//! writes them as Parquet data files and commits them as one snapshot per commit
+//! interval, or sooner once enough records are buffered. Tombstones are skipped.
+//!
+//! The offsets consumed up to are stored in the summary of each snapshot committed,
+//! under `igloo.kafka.offsets.<topic>`, so that the table is the checkpoint: a
+//! restarted ingestion continues after the records of the latest snapshot carrying
+//! them, and records are appended exactly once even when it stopped between writing
+//! a snapshot and anything else. The offsets are also committed to the consumer
+//! group, for lag monitoring only. The offsets are looked for in the current snapshot
+//! and its ancestors; one ingestion writes each topic to a table at a time.
+//!
+//! An ingestion given a stop signal (see [`KafkaIngestion::with_stop`]) ends its
+//! commit interval early once signalled, commits what it consumed and closes its
+//! consumer, so that shutting down loses nothing and leaves no consumer behind.
+
+use crate::decode::{RecordDecoder, RecordFormat};
+use crate::rest::{Consumer, KafkaRestClient};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use igloo_connector_iceberg::metadata::TableMetadata;
+use igloo_connector_iceberg::rest::TableIdent;
+use igloo_connector_iceberg::write::{DataFileWriter, SnapshotUpdate};
+use igloo_connector_iceberg::RestCatalog;
+use object_store::ObjectStore;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// Prefix of the snapshot summary property of the offsets ingested of a topic.
+pub const OFFSETS_PROPERTY_PREFIX: &str = "igloo.kafka.offsets.";
+pub const DEFAULT_COMMIT_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_MAX_RECORDS: usize = 1_000_000;
+/// Longest a fetch of records waits for some.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// What one commit of an ingestion appended.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IngestionCommit {
+    pub records: usize,
+    pub data_files: usize,
+    /// Offset of the next record to ingest, by partition.
+    pub offsets: BTreeMap<i32, i64>,
+}
+
+/// Ingests a topic into a table, see the [module docs](self).
+pub struct KafkaIngestion {
+    proxy: KafkaRestClient,
+    topic: String,
+    group: String,
+    format: RecordFormat,
+    catalog: Arc<RestCatalog>,
+    ident: TableIdent,
+    store: Arc<dyn ObjectStore>,
+    commit_interval: Duration,
+    max_records: usize,
+    stop: Option<watch::Receiver<bool>>,
+    /// Set up by the first commit.
+    state: Option<State>,
+}
+
+struct State {
+    consumer: Consumer,
+    metadata: TableMetadata,
+    offsets: BTreeMap<i32, i64>,
+}
+
+impl fmt::Debug for KafkaIngestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaIngestion")
+            .field("topic", &self.topic)
+            .field("table", &self.ident)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KafkaIngestion {
+    /// Ingest JSON records of `topic` into the table `ident` of `catalog`, whose files
+    /// are in `store`. The consumer group is named after the table.
+    pub fn new(
+        proxy: KafkaRestClient,
+        topic: impl Into<String>,
+        catalog: Arc<RestCatalog>,
+        ident: TableIdent,
+        store: Arc<dyn ObjectStore>,
+    ) -> Self {
+        let mut path = ident.namespace.clone();
+        path.push(ident.name.clone());
+        Self {
+            proxy,
+            topic: topic.into(),
+            group: format!("igloo-{}", path.join(".")),
+            format: RecordFormat::Json,
+            catalog,
+            ident,
+            store,
+            commit_interval: DEFAULT_COMMIT_INTERVAL,
+            max_records: DEFAULT_MAX_RECORDS,
+            stop: None,
+            state: None,
+        }
+    }
+
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_commit_interval(mut self, interval: Duration) -> Self {
+        self.commit_interval = interval;
+        self
+    }
+
+    /// Commit once `max_records` records are buffered, before the interval is over.
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records.max(1);
+        self
+    }
+
+    /// Stop once `stop` is set to true, see the [module docs](self).
+    pub fn with_stop(mut self, stop: watch::Receiver<bool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Ingest until an error or the stop signal, to be run as a background task (on
+    /// the engine's `Scheduler`, say).
+    pub async fn run(mut self) -> DataFusionResult<()> {
+        while !self.stopped() {
+            if let Err(e) = self.ingest_once().await {
+                // Best effort: the proxy drops idle consumers anyway.
+                let _ = self.close().await;
+                return Err(e);
+            }
+        }
+        self.close().await
+    }
+
+    fn stopped(&self) -> bool {
+        self.stop.as_ref().is_some_and(|stop| *stop.borrow())
+    }
+
+    /// Consume for one commit interval, or until enough records are buffered, and
+    /// commit what was consumed. `None` if there was nothing new.
+    pub async fn ingest_once(&mut self) -> DataFusionResult<Option<IngestionCommit>> {
+        let state = match &mut self.state {
+            Some(state) => state,
+            None => {
+                let state = self.open().await?;
+                self.state.insert(state)
+            }
+        };
+        let schema = state.metadata.current_schema()?.to_arrow()?;
+        let mut decoder = RecordDecoder::try_new(self.format.clone(), schema)?;
+        let mut writer = DataFileWriter::try_new(Arc::clone(&self.store), &state.metadata)?;
+        let mut offsets = state.offsets.clone();
+        let mut records = 0;
+        let deadline = Instant::now() + self.commit_interval;
+        while records < self.max_records {
+            let timeout = deadline.saturating_duration_since(Instant::now()).min(POLL_TIMEOUT);
+            if timeout.is_zero() || self.stop.as_ref().is_some_and(|stop| *stop.borrow()) {
+                break;
+            }
+            for record in state.consumer.records(timeout).await? {
+                if record.topic != self.topic {
+                    continue;
+                }
+                offsets.insert(record.partition, record.offset + 1);
+                if let Some(value) = &record.value {
+                    decoder.push(value).await?;
+                    records += 1;
+                }
+            }
+            for batch in decoder.flush()? {
+                writer.write(&batch).await?;
+            }
+        }
+        if offsets == state.offsets {
+            return Ok(None);
+        }
+        let mut update = SnapshotUpdate { added: writer.finish().await?, ..Default::default() };
+        let property = serde_json::to_string(&offsets).expect("offsets are serializable");
+        update.summary.insert(format!("{OFFSETS_PROPERTY_PREFIX}{}", self.topic), property);
+        let metadata = state.metadata.clone();
+        state.metadata =
+            update.commit(&self.catalog, &self.ident, self.store.as_ref(), metadata).await?;
+        state.offsets = offsets.clone();
+        state.consumer.commit(&self.topic, &offsets).await?;
+        Ok(Some(IngestionCommit { records, data_files: update.added.len(), offsets }))
+    }
+
+    /// Drop the ingestion's consumer from the proxy.
+    pub async fn close(&mut self) -> DataFusionResult<()> {
+        match self.state.take() {
+            Some(state) => state.consumer.close().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Load the table and position a new consumer after what it holds.
+    async fn open(&self) -> DataFusionResult<State> {
+        let Some(table) = self.catalog.load_table(&self.ident).await? else {
+            return Err(DataFusionError::Plan(format!(
+                "Iceberg table {} does not exist",
+                self.ident.name
+            )));
+        };
+        let offsets = ingested_offsets(&table.metadata, &self.topic)?;
+        let partitions = self.proxy.partitions(&self.topic).await?;
+        let consumer = self.proxy.create_consumer(&self.group).await?;
+        if let Err(e) = self.position(&consumer, &partitions, &offsets).await {
+            let _ = consumer.close().await;
+            return Err(e);
+        }
+        Ok(State { consumer, metadata: table.metadata, offsets })
+    }
+
+    async fn position(
+        &self,
+        consumer: &Consumer,
+        partitions: &[i32],
+        offsets: &BTreeMap<i32, i64>,
+    ) -> DataFusionResult<()> {
+        consumer.assign(&self.topic, partitions).await?;
+        let (seen, unseen): (Vec<i32>, Vec<i32>) =
+            partitions.iter().partition(|partition| offsets.contains_key(partition));
+        let seen = seen.iter().map(|partition| (*partition, offsets[partition])).collect();
+        consumer.seek(&self.topic, &seen).await?;
+        consumer.seek_to_beginning(&self.topic, &unseen).await
+    }
+}
+
+/// The offsets of the next records of `topic` to ingest into the table, by partition,
+/// from the latest snapshot recording them.
+pub fn ingested_offsets(
+    metadata: &TableMetadata,
+    topic: &str,
+) -> DataFusionResult<BTreeMap<i32, i64>> {
+    let key = format!("{OFFSETS_PROPERTY_PREFIX}{topic}");
+    let mut snapshot = metadata.current_snapshot();
+    while let Some(current) = snapshot {
+        if let Some(offsets) = current.summary.get(&key) {
+            return serde_json::from_str(offsets).map_err(|e| {
+                DataFusionError::Execution(format!(
+                    "invalid {key} of snapshot {}: {e}",
+                    current.snapshot_id
+                ))
+            });
+        }
+        snapshot = current
+            .parent_snapshot_id
+            .and_then(|parent| metadata.snapshots.iter().find(|s| s.snapshot_id == parent));
+    }
+    Ok(BTreeMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_ingested_offsets_are_those_of_the_latest_ingesting_snapshot() {
+        let snapshot = |id: i64, parent: Option<i64>, offsets: Option<&str>| {
+            let mut summary = json!({"operation": "append"});
+            if let Some(offsets) = offsets {
+                summary["igloo.kafka.offsets.orders"] = json!(offsets);
+            }
+            json!({"snapshot-id": id, "parent-snapshot-id": parent, "summary": summary})
+        };
+        let metadata = |current: i64| -> TableMetadata {
+            serde_json::from_value(json!({
+                "format-version": 2,
+                "location": "file:///tmp/orders",
+                "current-snapshot-id": current,
+                "snapshots": [
+                    snapshot(1, None, Some(r#"{"0":5}"#)),
+                    snapshot(2, Some(1), Some(r#"{"0":9,"1":3}"#)),
+                    // An INSERT, not recording offsets.
+                    snapshot(3, Some(2), None),
+                ],
+            }))
+            .unwrap()
+        };
+        assert_eq!(ingested_offsets(&metadata(3), "orders").unwrap(), [(0, 9), (1, 3)].into());
+        assert_eq!(ingested_offsets(&metadata(1), "orders").unwrap(), [(0, 5)].into());
+        assert!(ingested_offsets(&metadata(3), "payments").unwrap().is_empty());
+    }
+}
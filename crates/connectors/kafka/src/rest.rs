@@ -0,0 +1,320 @@
+//! Client of the Confluent REST Proxy (API v2).
+//!
+//! The proxy consumes topics on behalf of its clients: a [`Consumer`] is an instance
+//! the proxy keeps for a consumer group, assigned partitions and positioned in them
+//! explicitly, whose records are fetched in binary format (keys and values as
+//! base64). Instances the proxy has not heard from for a while are dropped by it;
+//! [`Consumer::close`] drops one right away.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use igloo_common::retry::RetryPolicy;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+/// Content type of the requests of API v2.
+const V2_JSON: &str = "application/vnd.kafka.v2+json";
+/// Accepted content type of fetched records, keys and values base64-encoded.
+const V2_BINARY: &str = "application/vnd.kafka.binary.v2+json";
+
+/// A record of a topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaRecord {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<Vec<u8>>,
+    /// `None` for tombstones.
+    pub value: Option<Vec<u8>>,
+}
+
+/// Client of a REST Proxy.
+#[derive(Clone)]
+pub struct KafkaRestClient {
+    url: String,
+    credentials: Option<(String, String)>,
+    client: Client,
+    retry: RetryPolicy,
+}
+
+impl fmt::Debug for KafkaRestClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaRestClient").field("url", &self.url).finish_non_exhaustive()
+    }
+}
+
+impl KafkaRestClient {
+    /// A client of the proxy at `url` (e.g. `http://rest-proxy:8082`).
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            credentials: None,
+            client: Client::new(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Authenticate with HTTP basic auth.
+    pub fn with_basic_auth(mut self, user: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((user.into(), password.into()));
+        self
+    }
+
+    /// How requests the proxy could not be reached for, or was unavailable for, are
+    /// retried. [`RetryPolicy::default`] unless set.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// The partitions of `topic`, in order.
+    pub async fn partitions(&self, topic: &str) -> DataFusionResult<Vec<i32>> {
+        #[derive(Deserialize)]
+        struct Partition {
+            partition: i32,
+        }
+        let url = format!("{}/topics/{}/partitions", self.url, encode_component(topic));
+        let response = self.send(self.client.get(url).header(ACCEPT, V2_JSON)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(DataFusionError::Plan(format!("Kafka topic {topic} does not exist")));
+        }
+        let partitions: Vec<Partition> = parse(response).await?;
+        let mut partitions: Vec<_> = partitions.into_iter().map(|p| p.partition).collect();
+        partitions.sort_unstable();
+        Ok(partitions)
+    }
+
+    /// The offsets of the earliest record of `partition` of `topic`, and of the record
+    /// to be appended next.
+    pub async fn offsets(&self, topic: &str, partition: i32) -> DataFusionResult<(i64, i64)> {
+        #[derive(Deserialize)]
+        struct Offsets {
+            beginning_offset: i64,
+            end_offset: i64,
+        }
+        let url = format!(
+            "{}/topics/{}/partitions/{partition}/offsets",
+            self.url,
+            encode_component(topic)
+        );
+        let offsets: Offsets =
+            parse(self.send(self.client.get(url).header(ACCEPT, V2_JSON)).await?).await?;
+        Ok((offsets.beginning_offset, offsets.end_offset))
+    }
+
+    /// A new consumer instance of `group`. It commits offsets only when told to, and
+    /// starts from the earliest records of partitions it is not positioned in.
+    pub async fn create_consumer(&self, group: &str) -> DataFusionResult<Consumer> {
+        #[derive(Deserialize)]
+        struct Created {
+            base_uri: String,
+        }
+        let body = json!({
+            "format": "binary",
+            "auto.offset.reset": "earliest",
+            "auto.commit.enable": "false",
+        });
+        let url = format!("{}/consumers/{}", self.url, encode_component(group));
+        let created: Created = parse(self.send(self.post(url, &body)).await?).await?;
+        Ok(Consumer { client: self.clone(), base_uri: created.base_uri })
+    }
+
+    fn post(&self, url: String, body: &impl Serialize) -> RequestBuilder {
+        let body = serde_json::to_vec(body).expect("request bodies are always serializable");
+        self.client.post(url).header(CONTENT_TYPE, V2_JSON).body(body)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> DataFusionResult<Response> {
+        let request = match &self.credentials {
+            Some((user, password)) => request.basic_auth(user, Some(password)),
+            None => request,
+        };
+        // Only requests the proxy did not act on are retried: fetching records moves a
+        // consumer's position, so a fetch whose answer was lost must not be repeated.
+        let attempt = || async {
+            let request = request.try_clone().expect("request bodies are buffered");
+            let response = request.send().await?;
+            match response.status() {
+                StatusCode::SERVICE_UNAVAILABLE => response.error_for_status(),
+                _ => Ok(response),
+            }
+        };
+        let unavailable = |e: &reqwest::Error| {
+            e.is_connect() || e.status() == Some(StatusCode::SERVICE_UNAVAILABLE)
+        };
+        self.retry.retry_if(attempt, unavailable).await.map_err(http_error)
+    }
+}
+
+/// A consumer instance of the proxy.
+#[derive(Debug)]
+pub struct Consumer {
+    client: KafkaRestClient,
+    base_uri: String,
+}
+
+impl Consumer {
+    /// Consume `partitions` of `topic`, instead of what the instance was assigned.
+    pub async fn assign(&self, topic: &str, partitions: &[i32]) -> DataFusionResult<()> {
+        let body = json!({ "partitions": topic_partitions(topic, partitions) });
+        self.post("assignments", &body).await
+    }
+
+    /// Continue each partition of `topic` at its offset in `offsets`.
+    pub async fn seek(&self, topic: &str, offsets: &BTreeMap<i32, i64>) -> DataFusionResult<()> {
+        if offsets.is_empty() {
+            return Ok(());
+        }
+        self.post("positions", &json!({ "offsets": topic_offsets(topic, offsets) })).await
+    }
+
+    /// Continue `partitions` of `topic` at their earliest records.
+    pub async fn seek_to_beginning(&self, topic: &str, partitions: &[i32]) -> DataFusionResult<()> {
+        if partitions.is_empty() {
+            return Ok(());
+        }
+        let body = json!({ "partitions": topic_partitions(topic, partitions) });
+        self.post("positions/beginning", &body).await
+    }
+
+    /// Continue `partitions` of `topic` after their latest records.
+    pub async fn seek_to_end(&self, topic: &str, partitions: &[i32]) -> DataFusionResult<()> {
+        if partitions.is_empty() {
+            return Ok(());
+        }
+        let body = json!({ "partitions": topic_partitions(topic, partitions) });
+        self.post("positions/end", &body).await
+    }
+
+    /// The next records of the assigned partitions, waiting up to `timeout` for some.
+    pub async fn records(&self, timeout: Duration) -> DataFusionResult<Vec<KafkaRecord>> {
+        #[derive(Deserialize)]
+        struct Record {
+            topic: String,
+            partition: i32,
+            offset: i64,
+            key: Option<String>,
+            value: Option<String>,
+        }
+        let request = self
+            .client
+            .client
+            .get(format!("{}/records", self.base_uri))
+            .query(&[("timeout", timeout.as_millis() as u64)])
+            .header(ACCEPT, V2_BINARY);
+        let records: Vec<Record> = parse(self.client.send(request).await?).await?;
+        let decode = |data: Option<String>| {
+            data.map(|data| BASE64.decode(data)).transpose().map_err(|e| {
+                DataFusionError::Execution(format!("invalid base64 in a Kafka record: {e}"))
+            })
+        };
+        records
+            .into_iter()
+            .map(|record| {
+                Ok(KafkaRecord {
+                    topic: record.topic,
+                    partition: record.partition,
+                    offset: record.offset,
+                    key: decode(record.key)?,
+                    value: decode(record.value)?,
+                })
+            })
+            .collect()
+    }
+
+    /// Commit, for the consumer group, `offsets` of the next records to consume in
+    /// each partition of `topic`.
+    pub async fn commit(&self, topic: &str, offsets: &BTreeMap<i32, i64>) -> DataFusionResult<()> {
+        if offsets.is_empty() {
+            return Ok(());
+        }
+        // The proxy commits the offset after the one it is given, that of the last
+        // record consumed.
+        let consumed: BTreeMap<_, _> =
+            offsets.iter().map(|(partition, next)| (*partition, next - 1)).collect();
+        self.post("offsets", &json!({ "offsets": topic_offsets(topic, &consumed) })).await
+    }
+
+    /// Drop the instance from the proxy.
+    pub async fn close(self) -> DataFusionResult<()> {
+        let request = self.client.client.delete(&self.base_uri).header(CONTENT_TYPE, V2_JSON);
+        check(self.client.send(request).await?).await
+    }
+
+    async fn post(&self, path: &str, body: &serde_json::Value) -> DataFusionResult<()> {
+        let request = self.client.post(format!("{}/{path}", self.base_uri), body);
+        check(self.client.send(request).await?).await
+    }
+}
+
+fn topic_partitions(topic: &str, partitions: &[i32]) -> Vec<serde_json::Value> {
+    partitions.iter().map(|p| json!({ "topic": topic, "partition": p })).collect()
+}
+
+fn topic_offsets(topic: &str, offsets: &BTreeMap<i32, i64>) -> Vec<serde_json::Value> {
+    offsets.iter().map(|(p, o)| json!({ "topic": topic, "partition": p, "offset": o })).collect()
+}
+
+/// The body of a successful response, or the proxy's error.
+async fn parse<T: DeserializeOwned>(response: Response) -> DataFusionResult<T> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(proxy_error(status, &response.text().await.unwrap_or_default()));
+    }
+    response.json().await.map_err(http_error)
+}
+
+async fn check(response: Response) -> DataFusionResult<()> {
+    let status = response.status();
+    if !status.is_success() {
+        return Err(proxy_error(status, &response.text().await.unwrap_or_default()));
+    }
+    Ok(())
+}
+
+fn http_error(e: reqwest::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+/// The proxy's error response (`{"error_code", "message"}`) as an error.
+fn proxy_error(status: StatusCode, body: &str) -> DataFusionError {
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        message: String,
+    }
+    let message = serde_json::from_str::<ErrorResponse>(body)
+        .map(|error| error.message)
+        .unwrap_or_else(|_| body.to_string());
+    DataFusionError::Execution(format!("Kafka REST Proxy responded {status}: {message}"))
+}
+
+/// `component` percent-encoded for a URL path.
+fn encode_component(component: &str) -> String {
+    component
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_component() {
+        assert_eq!(encode_component("orders.v1"), "orders.v1");
+        assert_eq!(encode_component("a b/c"), "a%20b%2Fc");
+    }
+}
@@ -0,0 +1,392 @@
+//! Kafka topics as tables.
+//!
+//! A [`KafkaTable`] reads a bounded range of a topic's records at each scan: in each
+//! partition, from a given offset or the earliest record, up to a given offset or the
+//! end of the partition when the scan starts. Each scan consumes through a consumer
+//! instance of its own, committing no offsets. The REST Proxy does not tell when
+//! records were written, so ranges are of offsets; a range of times is a filter on a
+//! column of the values. Offsets missing from a partition, as after compaction, end
+//! it after a few fetches without records.
+//!
+//! A [`KafkaWindow`] consumes a topic continuously instead, from given offsets or from
+//! the end of each partition, and keeps the records consumed within its length in
+//! memory for queries to scan, such as joins of recent events with the dimension
+//! tables of a database.
+//!
+//! Record values are decoded into the table's columns as for ingestion (see
+//! [`decode`](crate::decode)); tombstones are skipped.
+
+use crate::decode::{RecordDecoder, RecordFormat};
+use crate::rest::{Consumer, KafkaRestClient};
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::project_schema;
+use datafusion::datasource::{MemTable, TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Longest a fetch of records waits for some.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
+/// Fetches in a row without records after which a scan ends.
+const IDLE_POLLS: usize = 3;
+
+/// A bounded range of a topic's records, see the [module docs](self).
+#[derive(Debug, Clone)]
+pub struct KafkaTable {
+    proxy: KafkaRestClient,
+    topic: String,
+    group: String,
+    schema: SchemaRef,
+    format: RecordFormat,
+    start: BTreeMap<i32, i64>,
+    end: BTreeMap<i32, i64>,
+}
+
+impl KafkaTable {
+    /// The records of `topic` whose values are JSON rows of `schema`.
+    pub fn new(proxy: KafkaRestClient, topic: impl Into<String>, schema: SchemaRef) -> Self {
+        let topic = topic.into();
+        Self {
+            proxy,
+            group: format!("igloo-read-{topic}"),
+            topic,
+            schema,
+            format: RecordFormat::Json,
+            start: BTreeMap::new(),
+            end: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: RecordFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Consume as part of `group` rather than `igloo-read-<topic>`.
+    pub fn with_group(mut self, group: impl Into<String>) -> Self {
+        self.group = group.into();
+        self
+    }
+
+    /// Start the partitions in `offsets` at their offset there.
+    pub fn with_start_offsets(mut self, offsets: BTreeMap<i32, i64>) -> Self {
+        self.start = offsets;
+        self
+    }
+
+    /// Stop the partitions in `offsets` before their offset there.
+    pub fn with_end_offsets(mut self, offsets: BTreeMap<i32, i64>) -> Self {
+        self.end = offsets;
+        self
+    }
+
+    /// Consume the topic continuously into a window of the records of the last
+    /// `length`, starting at the start offsets or the end of each partition. End
+    /// offsets do not apply.
+    pub async fn into_window(self, length: Duration) -> DataFusionResult<KafkaWindow> {
+        let partitions = self.proxy.partitions(&self.topic).await?;
+        let consumer = self.proxy.create_consumer(&self.group).await?;
+        let positioned = async {
+            consumer.assign(&self.topic, &partitions).await?;
+            consumer.seek(&self.topic, &self.start).await?;
+            let unseen: Vec<_> =
+                partitions.iter().copied().filter(|p| !self.start.contains_key(p)).collect();
+            consumer.seek_to_end(&self.topic, &unseen).await
+        };
+        if let Err(e) = positioned.await {
+            let _ = consumer.close().await;
+            return Err(e);
+        }
+        let window = Arc::new(Mutex::new(Window::default()));
+        tokio::spawn(follow(
+            consumer,
+            self.topic.clone(),
+            self.format.clone(),
+            Arc::clone(&self.schema),
+            Arc::downgrade(&window),
+            length,
+        ));
+        Ok(KafkaWindow { topic: self.topic, schema: self.schema, length, window })
+    }
+
+    /// The offset ranges to read in each partition, that have records.
+    async fn ranges(&self) -> DataFusionResult<BTreeMap<i32, (i64, i64)>> {
+        let mut ranges = BTreeMap::new();
+        for partition in self.proxy.partitions(&self.topic).await? {
+            let (beginning, end) = self.proxy.offsets(&self.topic, partition).await?;
+            let start = self.start.get(&partition).map_or(beginning, |&start| start.max(beginning));
+            let end = self.end.get(&partition).map_or(end, |&stop| stop.min(end));
+            if start < end {
+                ranges.insert(partition, (start, end));
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+#[async_trait]
+impl TableProvider for KafkaTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let scan = Scan {
+            table: self.clone(),
+            schema: project_schema(&self.schema, projection)?,
+            projection: projection.cloned(),
+            limit,
+        };
+        Ok(Arc::new(StreamingTableExec::try_new(
+            Arc::clone(&scan.schema),
+            vec![Arc::new(scan)],
+            None,
+            None,
+            false,
+            limit,
+        )?))
+    }
+}
+
+/// A read of the ranges of a [`KafkaTable`].
+#[derive(Debug, Clone)]
+struct Scan {
+    table: KafkaTable,
+    schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    limit: Option<usize>,
+}
+
+impl Scan {
+    /// Send the batches read into `sender` until it is closed.
+    async fn read(
+        &self,
+        sender: &mpsc::Sender<DataFusionResult<RecordBatch>>,
+    ) -> DataFusionResult<()> {
+        let mut ranges = self.table.ranges().await?;
+        if ranges.is_empty() {
+            return Ok(());
+        }
+        let consumer = self.table.proxy.create_consumer(&self.table.group).await?;
+        let read = self.consume(&consumer, &mut ranges, sender).await;
+        // Best effort: the proxy drops idle consumers anyway.
+        let _ = consumer.close().await;
+        read
+    }
+
+    async fn consume(
+        &self,
+        consumer: &Consumer,
+        ranges: &mut BTreeMap<i32, (i64, i64)>,
+        sender: &mpsc::Sender<DataFusionResult<RecordBatch>>,
+    ) -> DataFusionResult<()> {
+        let topic = &self.table.topic;
+        let partitions: Vec<_> = ranges.keys().copied().collect();
+        consumer.assign(topic, &partitions).await?;
+        let starts = ranges.iter().map(|(partition, (start, _))| (*partition, *start)).collect();
+        consumer.seek(topic, &starts).await?;
+        let mut decoder =
+            RecordDecoder::try_new(self.table.format.clone(), Arc::clone(&self.table.schema))?;
+        let (mut rows, mut idle) = (0, 0);
+        while !ranges.is_empty() && idle < IDLE_POLLS {
+            let records = consumer.records(POLL_TIMEOUT).await?;
+            idle = if records.is_empty() { idle + 1 } else { 0 };
+            for record in records {
+                let Some((_, end)) = ranges.get(&record.partition) else { continue };
+                if record.topic != *topic || record.offset >= *end {
+                    continue;
+                }
+                if record.offset + 1 == *end {
+                    ranges.remove(&record.partition);
+                }
+                if let Some(value) = &record.value {
+                    decoder.push(value).await?;
+                }
+            }
+            for batch in decoder.flush()? {
+                rows += batch.num_rows();
+                let batch = match &self.projection {
+                    Some(projection) => batch.project(projection)?,
+                    None => batch,
+                };
+                if sender.send(Ok(batch)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            if self.limit.is_some_and(|limit| rows >= limit) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PartitionStream for Scan {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let scan = self.clone();
+        let (sender, receiver) = mpsc::channel(2);
+        tokio::spawn(async move {
+            if let Err(e) = scan.read(&sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+/// The records of a topic consumed within the last while, see the
+/// [module docs](self). Consuming stops once it is dropped, within a fetch.
+pub struct KafkaWindow {
+    topic: String,
+    schema: SchemaRef,
+    length: Duration,
+    window: Arc<Mutex<Window>>,
+}
+
+/// The batches of a window, with when they were consumed.
+#[derive(Default)]
+struct Window {
+    batches: VecDeque<(Instant, RecordBatch)>,
+    /// Why consuming stopped, if it did.
+    error: Option<String>,
+}
+
+impl Window {
+    /// Drop the batches consumed longer than `length` ago.
+    fn expire(&mut self, length: Duration) {
+        let Some(oldest) = Instant::now().checked_sub(length) else { return };
+        while self.batches.front().is_some_and(|(consumed, _)| *consumed < oldest) {
+            self.batches.pop_front();
+        }
+    }
+}
+
+impl fmt::Debug for KafkaWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaWindow")
+            .field("topic", &self.topic)
+            .field("length", &self.length)
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl TableProvider for KafkaWindow {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let batches = {
+            let mut window = self.window.lock().unwrap();
+            if let Some(error) = &window.error {
+                return Err(DataFusionError::Execution(format!(
+                    "consuming Kafka topic {} failed: {error}",
+                    self.topic
+                )));
+            }
+            window.expire(self.length);
+            window.batches.iter().map(|(_, batch)| batch.clone()).collect()
+        };
+        let table = MemTable::try_new(Arc::clone(&self.schema), vec![batches])?;
+        table.scan(state, projection, filters, limit).await
+    }
+}
+
+/// Consume into `window` until it is dropped or consuming fails.
+async fn follow(
+    consumer: Consumer,
+    topic: String,
+    format: RecordFormat,
+    schema: SchemaRef,
+    window: Weak<Mutex<Window>>,
+    length: Duration,
+) {
+    let consumed = async {
+        let mut decoder = RecordDecoder::try_new(format, schema)?;
+        loop {
+            for record in consumer.records(POLL_TIMEOUT).await? {
+                match &record.value {
+                    Some(value) if record.topic == topic => decoder.push(value).await?,
+                    _ => {}
+                }
+            }
+            let batches = decoder.flush()?;
+            let Some(window) = window.upgrade() else { return Ok(()) };
+            let mut window = window.lock().unwrap();
+            let now = Instant::now();
+            window.batches.extend(batches.into_iter().map(|batch| (now, batch)));
+            window.expire(length);
+        }
+    };
+    let result: DataFusionResult<()> = consumed.await;
+    if let (Err(e), Some(window)) = (&result, window.upgrade()) {
+        window.lock().unwrap().error = Some(e.to_string());
+    }
+    let _ = consumer.close().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+
+    #[test]
+    fn test_windows_expire_batches_consumed_before_their_length() {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+        let batch = RecordBatch::new_empty(schema);
+        let mut window = Window::default();
+        window.batches.push_back((Instant::now() - Duration::from_secs(10), batch.clone()));
+        window.batches.push_back((Instant::now(), batch));
+        window.expire(Duration::from_secs(5));
+        assert_eq!(window.batches.len(), 1);
+    }
+}
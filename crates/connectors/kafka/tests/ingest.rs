@@ -0,0 +1,259 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::prelude::SessionContext;
+use igloo_connector_iceberg::rest::TableIdent;
+use igloo_connector_iceberg::{IcebergCatalogProvider, RestCatalog};
+use igloo_connector_kafka::pipeline::IngestionCommit;
+use igloo_connector_kafka::{KafkaIngestion, KafkaRestClient};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A REST Proxy serving the topic `orders`, and a REST catalog of one empty table,
+/// `sales.orders`, applying the commits it is sent.
+#[derive(Clone, Default)]
+struct Mock {
+    url: Arc<Mutex<String>>,
+    /// The values of the records of each partition, `None` for tombstones.
+    partitions: Arc<Mutex<Vec<Vec<Option<Value>>>>>,
+    /// The position of consumers in each partition.
+    positions: Arc<Mutex<HashMap<i64, i64>>>,
+    /// What consumers seeked to, as requested.
+    seeks: Arc<Mutex<Vec<Value>>>,
+    /// The group's committed offsets, by partition.
+    committed: Arc<Mutex<BTreeMap<i64, i64>>>,
+    metadata: Arc<Mutex<Value>>,
+    /// Consumers closed.
+    closed: Arc<Mutex<usize>>,
+}
+
+async fn partitions(State(mock): State<Mock>, Path(topic): Path<String>) -> Json<Value> {
+    assert_eq!(topic, "orders");
+    let count = mock.partitions.lock().unwrap().len();
+    Json((0..count).map(|partition| json!({"partition": partition})).collect())
+}
+
+async fn create_consumer(
+    State(mock): State<Mock>,
+    Path(group): Path<String>,
+    Json(request): Json<Value>,
+) -> Json<Value> {
+    assert_eq!(group, "igloo-sales.orders");
+    assert_eq!(
+        (&request["format"], &request["auto.commit.enable"]),
+        (&json!("binary"), &json!("false"))
+    );
+    mock.positions.lock().unwrap().clear();
+    let url = mock.url.lock().unwrap().clone();
+    Json(json!({"instance_id": "i1", "base_uri": format!("{url}/consumers/{group}/instances/i1")}))
+}
+
+async fn assign(Json(request): Json<Value>) -> StatusCode {
+    assert_eq!(request["partitions"].as_array().unwrap().len(), 2);
+    StatusCode::NO_CONTENT
+}
+
+async fn seek(State(mock): State<Mock>, Json(request): Json<Value>) -> StatusCode {
+    for offset in request["offsets"].as_array().unwrap() {
+        let partition = offset["partition"].as_i64().unwrap();
+        mock.positions.lock().unwrap().insert(partition, offset["offset"].as_i64().unwrap());
+    }
+    mock.seeks.lock().unwrap().push(request);
+    StatusCode::NO_CONTENT
+}
+
+async fn seek_to_beginning(State(mock): State<Mock>, Json(request): Json<Value>) -> StatusCode {
+    for partition in request["partitions"].as_array().unwrap() {
+        mock.positions.lock().unwrap().insert(partition["partition"].as_i64().unwrap(), 0);
+    }
+    mock.seeks.lock().unwrap().push(request);
+    StatusCode::NO_CONTENT
+}
+
+async fn records(State(mock): State<Mock>) -> Json<Value> {
+    let partitions = mock.partitions.lock().unwrap();
+    let mut positions = mock.positions.lock().unwrap();
+    let mut records = Vec::new();
+    for (partition, values) in partitions.iter().enumerate() {
+        let position = positions.entry(partition as i64).or_insert(0);
+        for (offset, value) in values.iter().enumerate().skip(*position as usize) {
+            let value = value.as_ref().map(|value| BASE64.encode(value.to_string()));
+            records.push(json!({
+                "topic": "orders",
+                "key": null,
+                "value": value,
+                "partition": partition,
+                "offset": offset,
+            }));
+        }
+        *position = values.len() as i64;
+    }
+    Json(Value::Array(records))
+}
+
+async fn commit_offsets(State(mock): State<Mock>, Json(request): Json<Value>) -> StatusCode {
+    for offset in request["offsets"].as_array().unwrap() {
+        let partition = offset["partition"].as_i64().unwrap();
+        mock.committed.lock().unwrap().insert(partition, offset["offset"].as_i64().unwrap());
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn close(State(mock): State<Mock>) -> StatusCode {
+    *mock.closed.lock().unwrap() += 1;
+    StatusCode::NO_CONTENT
+}
+
+async fn config() -> Json<Value> {
+    Json(json!({"defaults": {}, "overrides": {}}))
+}
+
+async fn namespaces() -> Json<Value> {
+    Json(json!({"namespaces": [["sales"]]}))
+}
+
+async fn tables() -> Json<Value> {
+    Json(json!({"identifiers": [{"namespace": ["sales"], "name": "orders"}]}))
+}
+
+async fn load_table(State(mock): State<Mock>) -> Json<Value> {
+    Json(json!({"metadata": mock.metadata.lock().unwrap().clone()}))
+}
+
+async fn commit_table(State(mock): State<Mock>, Json(request): Json<Value>) -> Json<Value> {
+    let mut metadata = mock.metadata.lock().unwrap();
+    for update in request["updates"].as_array().unwrap() {
+        match update["action"].as_str().unwrap() {
+            "add-snapshot" => {
+                let snapshot = update["snapshot"].clone();
+                metadata["last-sequence-number"] = snapshot["sequence-number"].clone();
+                metadata["snapshots"].as_array_mut().unwrap().push(snapshot);
+            }
+            "set-snapshot-ref" => metadata["current-snapshot-id"] = update["snapshot-id"].clone(),
+            other => panic!("unexpected update {other}"),
+        }
+    }
+    Json(json!({"metadata-location": "unused", "metadata": metadata.clone()}))
+}
+
+async fn start(mock: Mock) -> String {
+    let instance = "/consumers/:group/instances/:instance";
+    let app = Router::new()
+        .route("/topics/:topic/partitions", get(partitions))
+        .route("/consumers/:group", post(create_consumer))
+        .route(&format!("{instance}/assignments"), post(assign))
+        .route(&format!("{instance}/positions"), post(seek))
+        .route(&format!("{instance}/positions/beginning"), post(seek_to_beginning))
+        .route(&format!("{instance}/records"), get(records))
+        .route(&format!("{instance}/offsets"), post(commit_offsets))
+        .route(instance, axum::routing::delete(close))
+        .route("/v1/config", get(config))
+        .route("/v1/namespaces", get(namespaces))
+        .route("/v1/namespaces/:namespace/tables", get(tables))
+        .route("/v1/namespaces/:namespace/tables/:table", get(load_table).post(commit_table))
+        .with_state(mock.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    *mock.url.lock().unwrap() = url.clone();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+#[tokio::test]
+async fn test_topic_is_ingested_exactly_once_across_restarts() {
+    let dir = std::env::temp_dir().join(format!("igloo-kafka-ingest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mock = Mock::default();
+    *mock.metadata.lock().unwrap() = json!({
+        "format-version": 2,
+        "table-uuid": "5b0c4a8e-0000-4000-8000-000000000000",
+        "location": format!("file://{}", dir.display()),
+        "last-sequence-number": 0,
+        "current-schema-id": 0,
+        "schemas": [{"schema-id": 0, "fields": [
+            {"id": 1, "name": "id", "required": true, "type": "long"},
+            {"id": 2, "name": "amount", "required": false, "type": "double"}
+        ]}],
+        "default-spec-id": 0,
+        "partition-specs": [{"spec-id": 0, "fields": []}],
+        "snapshots": []
+    });
+    *mock.partitions.lock().unwrap() = vec![
+        vec![Some(json!({"id": 1, "amount": 10.0})), Some(json!({"id": 2, "amount": 20.0}))],
+        vec![Some(json!({"id": 3, "note": "ignored"})), None],
+    ];
+    let url = start(mock.clone()).await;
+    let catalog = Arc::new(RestCatalog::new(url.clone()));
+    let ident = TableIdent { namespace: vec!["sales".to_string()], name: "orders".to_string() };
+    let ingestion = || {
+        let store = Arc::new(object_store::local::LocalFileSystem::new());
+        KafkaIngestion::new(
+            KafkaRestClient::new(&url),
+            "orders",
+            catalog.clone(),
+            ident.clone(),
+            store,
+        )
+        .with_commit_interval(Duration::from_millis(100))
+    };
+
+    let mut first = ingestion();
+    let commit = first.ingest_once().await.unwrap();
+    let expected = IngestionCommit { records: 3, data_files: 1, offsets: [(0, 2), (1, 2)].into() };
+    assert_eq!(commit, Some(expected));
+    assert_eq!(first.ingest_once().await.unwrap(), None);
+    assert_eq!(*mock.committed.lock().unwrap(), [(0, 1), (1, 1)].into());
+    first.close().await.unwrap();
+
+    // Restarted, the ingestion continues after what the table holds.
+    mock.partitions.lock().unwrap()[0].push(Some(json!({"id": 4, "amount": 40.0})));
+    mock.seeks.lock().unwrap().clear();
+    let mut second = ingestion();
+    let commit = second.ingest_once().await.unwrap().unwrap();
+    assert_eq!((commit.records, commit.offsets), (1, [(0, 3), (1, 2)].into()));
+    let seeks = mock.seeks.lock().unwrap().clone();
+    assert_eq!(
+        seeks,
+        [json!({"offsets": [
+            {"topic": "orders", "partition": 0, "offset": 2},
+            {"topic": "orders", "partition": 1, "offset": 2}
+        ]})]
+    );
+
+    let metadata = mock.metadata.lock().unwrap().clone();
+    let snapshots = metadata["snapshots"].as_array().unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[1]["summary"]["igloo.kafka.offsets.orders"], r#"{"0":3,"1":2}"#);
+    second.close().await.unwrap();
+
+    // Stopped, an ingestion commits what it consumed without waiting out the interval.
+    mock.partitions.lock().unwrap()[1].push(Some(json!({"id": 5, "amount": 50.0})));
+    let (stop, stopped) = tokio::sync::watch::channel(false);
+    let third = ingestion().with_commit_interval(Duration::from_secs(3600)).with_stop(stopped);
+    let running = tokio::spawn(third.run());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    stop.send_replace(true);
+    tokio::time::timeout(Duration::from_secs(5), running).await.unwrap().unwrap().unwrap();
+    assert_eq!(mock.metadata.lock().unwrap()["snapshots"].as_array().unwrap().len(), 3);
+    assert_eq!(*mock.committed.lock().unwrap(), [(0, 2), (1, 2)].into());
+    assert_eq!(*mock.closed.lock().unwrap(), 3);
+    let ctx = SessionContext::new();
+    let provider = IcebergCatalogProvider::try_new(catalog).await.unwrap();
+    ctx.register_catalog("iceberg", Arc::new(provider));
+    let sql = "SELECT count(*) AS orders, sum(amount) AS total FROM iceberg.sales.orders";
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    let expected = "\
++--------+-------+
+| orders | total |
++--------+-------+
+| 5      | 120.0 |
++--------+-------+";
+    assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,234 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::prelude::SessionContext;
+use igloo_common::retry::RetryPolicy;
+use igloo_connector_kafka::{KafkaRestClient, KafkaTable};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A REST Proxy serving the topic `clicks`.
+#[derive(Clone, Default)]
+struct Mock {
+    url: Arc<Mutex<String>>,
+    /// The values of the records of each partition, `None` for tombstones.
+    partitions: Arc<Mutex<Vec<Vec<Option<Value>>>>>,
+    /// The position of consumers in each partition.
+    positions: Arc<Mutex<HashMap<i64, i64>>>,
+    /// The consumers not closed yet.
+    open: Arc<Mutex<usize>>,
+    /// Requests for partitions to answer 503 Service Unavailable before serving them.
+    unavailable: Arc<Mutex<usize>>,
+}
+
+async fn partitions(
+    State(mock): State<Mock>,
+    Path(topic): Path<String>,
+) -> Result<Json<Value>, StatusCode> {
+    assert_eq!(topic, "clicks");
+    let mut unavailable = mock.unavailable.lock().unwrap();
+    if *unavailable > 0 {
+        *unavailable -= 1;
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+    let count = mock.partitions.lock().unwrap().len();
+    Ok(Json((0..count).map(|partition| json!({"partition": partition})).collect()))
+}
+
+async fn offsets(
+    State(mock): State<Mock>,
+    Path((_, partition)): Path<(String, usize)>,
+) -> Json<Value> {
+    let end = mock.partitions.lock().unwrap()[partition].len();
+    Json(json!({"beginning_offset": 0, "end_offset": end}))
+}
+
+async fn create_consumer(State(mock): State<Mock>, Path(group): Path<String>) -> Json<Value> {
+    assert_eq!(group, "igloo-read-clicks");
+    mock.positions.lock().unwrap().clear();
+    *mock.open.lock().unwrap() += 1;
+    let url = mock.url.lock().unwrap().clone();
+    Json(json!({"instance_id": "i1", "base_uri": format!("{url}/consumers/{group}/instances/i1")}))
+}
+
+async fn assign() -> StatusCode {
+    StatusCode::NO_CONTENT
+}
+
+async fn seek(State(mock): State<Mock>, Json(request): Json<Value>) -> StatusCode {
+    for offset in request["offsets"].as_array().unwrap() {
+        let partition = offset["partition"].as_i64().unwrap();
+        mock.positions.lock().unwrap().insert(partition, offset["offset"].as_i64().unwrap());
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn seek_to_end(State(mock): State<Mock>, Json(request): Json<Value>) -> StatusCode {
+    let partitions = mock.partitions.lock().unwrap();
+    for partition in request["partitions"].as_array().unwrap() {
+        let partition = partition["partition"].as_i64().unwrap();
+        let end = partitions[partition as usize].len() as i64;
+        mock.positions.lock().unwrap().insert(partition, end);
+    }
+    StatusCode::NO_CONTENT
+}
+
+async fn records(State(mock): State<Mock>) -> Json<Value> {
+    let partitions = mock.partitions.lock().unwrap();
+    let mut positions = mock.positions.lock().unwrap();
+    let mut records = Vec::new();
+    for (partition, position) in positions.iter_mut() {
+        let values = &partitions[*partition as usize];
+        for (offset, value) in values.iter().enumerate().skip(*position as usize) {
+            let value = value.as_ref().map(|value| BASE64.encode(value.to_string()));
+            records.push(json!({
+                "topic": "clicks",
+                "key": null,
+                "value": value,
+                "partition": partition,
+                "offset": offset,
+            }));
+        }
+        *position = values.len() as i64;
+    }
+    Json(Value::Array(records))
+}
+
+async fn close(State(mock): State<Mock>) -> StatusCode {
+    *mock.open.lock().unwrap() -= 1;
+    StatusCode::NO_CONTENT
+}
+
+async fn start(mock: Mock) -> String {
+    let instance = "/consumers/:group/instances/:instance";
+    let app = Router::new()
+        .route("/topics/:topic/partitions", get(partitions))
+        .route("/topics/:topic/partitions/:partition/offsets", get(offsets))
+        .route("/consumers/:group", post(create_consumer))
+        .route(&format!("{instance}/assignments"), post(assign))
+        .route(&format!("{instance}/positions"), post(seek))
+        .route(&format!("{instance}/positions/end"), post(seek_to_end))
+        .route(&format!("{instance}/records"), get(records))
+        .route(instance, axum::routing::delete(close))
+        .with_state(mock.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    *mock.url.lock().unwrap() = url.clone();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, true),
+        Field::new("clicks", DataType::Int64, true),
+    ]))
+}
+
+async fn query(ctx: &SessionContext, sql: &str) -> String {
+    let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_tables_read_ranges_of_offsets() {
+    let mock = Mock::default();
+    *mock.partitions.lock().unwrap() = vec![
+        vec![
+            Some(json!({"name": "ada", "clicks": 1})),
+            None,
+            Some(json!({"name": "bob", "clicks": 2})),
+        ],
+        vec![Some(json!({"name": "ada", "clicks": 4}))],
+    ];
+    let url = start(mock.clone()).await;
+    let table = KafkaTable::new(KafkaRestClient::new(url), "clicks", schema());
+    let ctx = SessionContext::new();
+    ctx.register_table("clicks", Arc::new(table.clone())).unwrap();
+    let sql = "SELECT name, sum(clicks) AS clicks FROM clicks GROUP BY name ORDER BY name";
+    let expected = "\
++------+--------+
+| name | clicks |
++------+--------+
+| ada  | 5      |
+| bob  | 2      |
++------+--------+";
+    assert_eq!(query(&ctx, sql).await, expected);
+    // Records appended since are read by the next scan.
+    mock.partitions.lock().unwrap()[1].push(Some(json!({"name": "bob", "clicks": 8})));
+    let count = query(&ctx, "SELECT count(*) AS records FROM clicks").await;
+    assert_eq!(count.lines().nth(3), Some("| 4       |"));
+    assert_eq!(*mock.open.lock().unwrap(), 0);
+
+    let range = table.with_start_offsets([(0, 1), (1, 1)].into()).with_end_offsets([(1, 1)].into());
+    ctx.register_table("range", Arc::new(range)).unwrap();
+    let expected = "\
++--------+
+| clicks |
++--------+
+| 2      |
++--------+";
+    assert_eq!(query(&ctx, "SELECT clicks FROM range").await, expected);
+}
+
+#[tokio::test]
+async fn test_windows_hold_the_records_consumed_since_they_started() {
+    let mock = Mock::default();
+    *mock.partitions.lock().unwrap() = vec![vec![Some(json!({"name": "old", "clicks": 1}))]];
+    let url = start(mock.clone()).await;
+    let table = KafkaTable::new(KafkaRestClient::new(url), "clicks", schema());
+    let window = table.into_window(Duration::from_secs(3600)).await.unwrap();
+    let ctx = SessionContext::new();
+    ctx.register_table("recent", Arc::new(window)).unwrap();
+    let empty = "++\n++";
+    assert_eq!(query(&ctx, "SELECT * FROM recent").await, empty);
+
+    mock.partitions.lock().unwrap()[0].push(Some(json!({"name": "new", "clicks": 2})));
+    let mut seen = String::new();
+    for _ in 0..50 {
+        seen = query(&ctx, "SELECT name FROM recent").await;
+        if seen != empty {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let expected = "\
++------+
+| name |
++------+
+| new  |
++------+";
+    assert_eq!(seen, expected);
+
+    // Dropping the window stops consuming.
+    ctx.deregister_table("recent").unwrap();
+    for _ in 0..50 {
+        if *mock.open.lock().unwrap() == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(*mock.open.lock().unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_requests_are_retried_while_the_proxy_is_unavailable() {
+    let mock = Mock::default();
+    *mock.partitions.lock().unwrap() = vec![vec![], vec![]];
+    *mock.unavailable.lock().unwrap() = 2;
+    let url = start(mock.clone()).await;
+    let retry = RetryPolicy::new(3).with_initial_backoff(Duration::from_millis(1));
+    let proxy = KafkaRestClient::new(url).with_retry_policy(retry);
+    assert_eq!(proxy.partitions("clicks").await.unwrap(), [0, 1]);
+
+    *mock.unavailable.lock().unwrap() = 3;
+    let err = proxy.partitions("clicks").await.unwrap_err();
+    assert!(err.to_string().contains("503"), "{err}");
+}
@@ -0,0 +1,23 @@
+//! MongoDB collections.
+//!
+//! [`MongoTable`] reads a MongoDB collection as a table whose schema is inferred from
+//! sampled documents, embedded documents flattened into columns or kept as structs.
+//! Filters and limits of queries become `$match` and `$limit` stages evaluated by
+//! MongoDB (see [`table`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_mongodb::{client, MongoTable, Nesting};
+//! use std::sync::Arc;
+//!
+//! let client = client("mongodb://db:27017").await?;
+//! let orders = MongoTable::try_new(client.database("shop").collection("orders"), 1000).await?;
+//! ctx.register_table("orders", Arc::new(orders.with_nesting(Nesting::Struct)))?;
+//! ctx.sql("SELECT count(*) FROM orders WHERE amount > 100").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod table;
+
+pub use table::{client, MongoTable, Nesting};
@@ -0,0 +1,820 @@
+//! Reading a MongoDB collection.
+//!
+//! A [`MongoTable`] infers its schema from a sample of the collection's documents:
+//! the types seen for a field are merged, 32-bit integers widening to 64-bit ones and
+//! integers to doubles, and a field seen with types that do not merge becomes a string
+//! of the values' extended JSON (of strings, the strings themselves). Doubles, strings, booleans, integers, dates (to
+//! millisecond timestamps in UTC) and binary data map to their Arrow types, object ids
+//! to strings of their hex form, arrays to lists, and every other BSON type to
+//! strings. Every column is nullable, a missing field being null.
+//!
+//! With [`Nesting::Flatten`], the default, the fields of embedded documents become
+//! columns of their own, named by their path (`address.city`); with
+//! [`Nesting::Struct`], each top-level field is a column and embedded documents are
+//! structs. Documents within arrays are structs either way.
+//!
+//! Each scan is one aggregation of the collection. Filters comparing a column of a
+//! scalar type with values, `AND`/`OR`, `IS [NOT] NULL`, `BETWEEN` and `IN` become a
+//! `$match` stage, which MongoDB evaluates as the engine would, so a limit is pushed
+//! down as a `$limit` stage when every filter was; a `$project` stage keeps the fields
+//! of the projected columns only.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, BinaryArray, BooleanArray, Float64Array, Int32Array, Int64Array, ListArray,
+    StringArray, StructArray, TimestampMillisecondArray,
+};
+use datafusion::arrow::buffer::{NullBuffer, OffsetBuffer};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::catalog::Session;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::{Between, BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::{StreamExt, TryStreamExt};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::{Client, Collection};
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// Documents per batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// How embedded documents become columns, see the [module docs](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Nesting {
+    /// A column per field of embedded documents, named by its path.
+    #[default]
+    Flatten,
+    /// A struct column per embedded document.
+    Struct,
+}
+
+/// The types seen for a field in the sampled documents.
+#[derive(Debug, Clone, PartialEq)]
+enum Shape {
+    /// Only nulls.
+    Null,
+    Boolean,
+    Int32,
+    Int64,
+    Double,
+    DateTime,
+    ObjectId,
+    String,
+    Binary,
+    /// Fields in order of first appearance.
+    Document(Vec<(String, Shape)>),
+    Array(Box<Shape>),
+    /// Other types, or types that do not merge.
+    Other,
+}
+
+impl Shape {
+    fn of(value: &Bson) -> Self {
+        match value {
+            Bson::Null | Bson::Undefined => Shape::Null,
+            Bson::Boolean(_) => Shape::Boolean,
+            Bson::Int32(_) => Shape::Int32,
+            Bson::Int64(_) => Shape::Int64,
+            Bson::Double(_) => Shape::Double,
+            Bson::DateTime(_) => Shape::DateTime,
+            Bson::ObjectId(_) => Shape::ObjectId,
+            Bson::String(_) => Shape::String,
+            Bson::Binary(_) => Shape::Binary,
+            Bson::Document(document) => Shape::Document(fields(std::iter::once(document))),
+            Bson::Array(items) => {
+                Shape::Array(Box::new(items.iter().map(Shape::of).fold(Shape::Null, Shape::merge)))
+            }
+            _ => Shape::Other,
+        }
+    }
+
+    /// The shape of a field seen as both `self` and `other`.
+    fn merge(self, other: Shape) -> Self {
+        match (self, other) {
+            (Shape::Null, shape) | (shape, Shape::Null) => shape,
+            (Shape::Int32, Shape::Int64) | (Shape::Int64, Shape::Int32) => Shape::Int64,
+            (Shape::Int32 | Shape::Int64, Shape::Double)
+            | (Shape::Double, Shape::Int32 | Shape::Int64) => Shape::Double,
+            (Shape::Document(mut fields), Shape::Document(others)) => {
+                for (name, other) in others {
+                    match fields.iter_mut().find(|(field, _)| *field == name) {
+                        Some((_, shape)) => {
+                            *shape = std::mem::replace(shape, Shape::Null).merge(other)
+                        }
+                        None => fields.push((name, other)),
+                    }
+                }
+                Shape::Document(fields)
+            }
+            (Shape::Array(item), Shape::Array(other)) => Shape::Array(Box::new(item.merge(*other))),
+            (shape, other) if shape == other => shape,
+            _ => Shape::Other,
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Shape::Boolean => DataType::Boolean,
+            Shape::Int32 => DataType::Int32,
+            Shape::Int64 => DataType::Int64,
+            Shape::Double => DataType::Float64,
+            Shape::DateTime => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            Shape::Binary => DataType::Binary,
+            Shape::Document(fields) if !fields.is_empty() => DataType::Struct(
+                fields
+                    .iter()
+                    .map(|(name, shape)| Field::new(name, shape.data_type(), true))
+                    .collect(),
+            ),
+            Shape::Array(item) => DataType::new_list(item.data_type(), true),
+            Shape::Null | Shape::ObjectId | Shape::String | Shape::Document(_) | Shape::Other => {
+                DataType::Utf8
+            }
+        }
+    }
+
+    /// Whether MongoDB compares values of this shape as the engine does.
+    fn is_comparable(&self) -> bool {
+        matches!(
+            self,
+            Shape::Boolean
+                | Shape::Int32
+                | Shape::Int64
+                | Shape::Double
+                | Shape::DateTime
+                | Shape::ObjectId
+                | Shape::String
+        )
+    }
+}
+
+/// The merged fields of `documents`.
+fn fields<'a>(documents: impl IntoIterator<Item = &'a Document>) -> Vec<(String, Shape)> {
+    let shape = documents.into_iter().fold(Shape::Document(vec![]), |shape, document| {
+        let fields = document.iter().map(|(name, value)| (name.clone(), Shape::of(value)));
+        shape.merge(Shape::Document(fields.collect()))
+    });
+    match shape {
+        Shape::Document(fields) => fields,
+        _ => unreachable!("documents merge into a document"),
+    }
+}
+
+/// Where a column's values are in the documents.
+#[derive(Debug, Clone)]
+struct Column {
+    path: Vec<String>,
+    shape: Shape,
+}
+
+impl Column {
+    /// The value of the column in `document`, if any.
+    fn get<'a>(&self, document: &'a Document) -> Option<&'a Bson> {
+        let (last, parents) = self.path.split_last()?;
+        let mut document = document;
+        for name in parents {
+            document = document.get_document(name).ok()?;
+        }
+        document.get(last)
+    }
+}
+
+/// The columns of `fields`, nested as `nesting` says.
+fn columns(fields: &[(String, Shape)], nesting: Nesting) -> Vec<Column> {
+    fn flatten(path: &[String], fields: &[(String, Shape)], columns: &mut Vec<Column>) {
+        for (name, shape) in fields {
+            let path = [path, std::slice::from_ref(name)].concat();
+            match shape {
+                Shape::Document(fields) if !fields.is_empty() => flatten(&path, fields, columns),
+                _ => columns.push(Column { path, shape: shape.clone() }),
+            }
+        }
+    }
+    let mut columns = Vec::new();
+    match nesting {
+        Nesting::Flatten => flatten(&[], fields, &mut columns),
+        Nesting::Struct => columns.extend(
+            fields
+                .iter()
+                .map(|(name, shape)| Column { path: vec![name.clone()], shape: shape.clone() }),
+        ),
+    }
+    columns
+}
+
+/// A client of the deployment at `uri`, such as `mongodb://db:27017`.
+pub async fn client(uri: &str) -> DataFusionResult<Client> {
+    Client::with_uri_str(uri).await.map_err(mongo_error)
+}
+
+/// A MongoDB collection, see the [module docs](self).
+pub struct MongoTable {
+    collection: Collection<Document>,
+    fields: Vec<(String, Shape)>,
+    columns: Vec<Column>,
+    schema: SchemaRef,
+    batch_size: usize,
+}
+
+impl fmt::Debug for MongoTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MongoTable")
+            .field("collection", &self.collection.namespace().to_string())
+            .finish_non_exhaustive()
+    }
+}
+
+impl MongoTable {
+    /// Infer the schema of `collection` from `sample_size` of its documents, picked at
+    /// random.
+    pub async fn try_new(
+        collection: Collection<Document>,
+        sample_size: usize,
+    ) -> DataFusionResult<Self> {
+        let sample: Vec<Document> = collection
+            .aggregate([doc! { "$sample": { "size": sample_size as i64 } }])
+            .await
+            .map_err(mongo_error)?
+            .try_collect()
+            .await
+            .map_err(mongo_error)?;
+        if sample.is_empty() {
+            return Err(DataFusionError::Plan(format!(
+                "MongoDB collection {} has no documents to infer a schema from",
+                collection.namespace()
+            )));
+        }
+        Ok(Self::from_sample(collection, &sample))
+    }
+
+    /// Infer the schema of `collection` from `sample`.
+    pub fn from_sample(collection: Collection<Document>, sample: &[Document]) -> Self {
+        let fields = fields(sample);
+        let columns = columns(&fields, Nesting::default());
+        let schema = schema(&columns);
+        Self { collection, fields, columns, schema, batch_size: DEFAULT_BATCH_SIZE }
+    }
+
+    pub fn with_nesting(mut self, nesting: Nesting) -> Self {
+        self.columns = columns(&self.fields, nesting);
+        self.schema = schema(&self.columns);
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+fn schema(columns: &[Column]) -> SchemaRef {
+    Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|column| Field::new(column.path.join("."), column.shape.data_type(), true))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+#[async_trait]
+impl TableProvider for MongoTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match self.predicate(filter) {
+                Some(_) => TableProviderFilterPushDown::Exact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let indices: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.columns.len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&indices)?);
+        let columns: Vec<_> = indices.iter().map(|&i| self.columns[i].clone()).collect();
+
+        let predicates: Vec<_> =
+            filters.iter().filter_map(|filter| self.predicate(filter)).collect();
+        let mut pipeline = Vec::new();
+        if !predicates.is_empty() {
+            pipeline.push(doc! { "$match": { "$and": predicates.clone() } });
+        }
+        if let Some(limit) = limit.filter(|_| predicates.len() == filters.len()) {
+            pipeline.push(doc! { "$limit": limit as i64 });
+        }
+        let mut project = Document::new();
+        if !columns.iter().any(|column| column.path == ["_id"]) {
+            project.insert("_id", 0);
+        }
+        for column in &columns {
+            project.insert(column.path.join("."), 1);
+        }
+        // `SELECT count(*)` projects no column.
+        if columns.is_empty() {
+            project = doc! { "_id": 1 };
+        }
+        pipeline.push(doc! { "$project": project });
+
+        let query = Query {
+            collection: self.collection.clone(),
+            pipeline,
+            schema: Arc::clone(&schema),
+            columns,
+            batch_size: self.batch_size,
+        };
+        Ok(Arc::new(StreamingTableExec::try_new(
+            schema,
+            vec![Arc::new(query)],
+            None,
+            None,
+            false,
+            limit,
+        )?))
+    }
+}
+
+impl MongoTable {
+    /// The `$match` condition of `filter`, if MongoDB evaluates it as the engine would.
+    fn predicate(&self, filter: &Expr) -> Option<Document> {
+        let column = |expr: &Expr| {
+            let Expr::Column(column) = expr else { return None };
+            let i = self.schema.index_of(&column.name).ok()?;
+            Some(&self.columns[i])
+        };
+        let path = |column: &Column| column.path.join(".");
+        match filter {
+            Expr::BinaryExpr(BinaryExpr {
+                left,
+                op: op @ (Operator::And | Operator::Or),
+                right,
+            }) => {
+                let (left, right) = (self.predicate(left)?, self.predicate(right)?);
+                let op = if *op == Operator::And { "$and" } else { "$or" };
+                Some(doc! { op: [left, right] })
+            }
+            Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+                let (column, op, value) = match (column(left), column(right)) {
+                    (Some(column), None) => (column, *op, right),
+                    (None, Some(column)) => (column, op.swap()?, left),
+                    _ => return None,
+                };
+                let value = literal(value, &column.shape)?;
+                let condition = match op {
+                    Operator::Eq => doc! { "$eq": value },
+                    // `$ne` alone matches missing and null fields.
+                    Operator::NotEq => doc! { "$nin": [value, Bson::Null] },
+                    Operator::Lt => doc! { "$lt": value },
+                    Operator::LtEq => doc! { "$lte": value },
+                    Operator::Gt => doc! { "$gt": value },
+                    Operator::GtEq => doc! { "$gte": value },
+                    _ => return None,
+                };
+                Some(doc! { path(column): condition })
+            }
+            Expr::Column(_) => {
+                let column = column(filter).filter(|column| column.shape == Shape::Boolean)?;
+                Some(doc! { path(column): true })
+            }
+            Expr::Not(expr) => {
+                let column = column(expr).filter(|column| column.shape == Shape::Boolean)?;
+                Some(doc! { path(column): false })
+            }
+            // An array containing null would match too.
+            Expr::IsNull(expr) => {
+                let column = column(expr).filter(|column| column.shape.is_comparable())?;
+                Some(doc! { path(column): Bson::Null })
+            }
+            Expr::IsNotNull(expr) => {
+                let column = column(expr).filter(|column| column.shape.is_comparable())?;
+                Some(doc! { path(column): { "$ne": Bson::Null } })
+            }
+            Expr::Between(Between { expr, negated, low, high }) => {
+                let column = column(expr)?;
+                let (low, high) = (literal(low, &column.shape)?, literal(high, &column.shape)?);
+                Some(match negated {
+                    false => doc! { path(column): { "$gte": low, "$lte": high } },
+                    true => doc! {
+                        "$or": [{ path(column): { "$lt": low } }, { path(column): { "$gt": high } }]
+                    },
+                })
+            }
+            Expr::InList(InList { expr, list, negated }) => {
+                let column = column(expr)?;
+                let mut values: Vec<_> =
+                    list.iter().map(|item| literal(item, &column.shape)).collect::<Option<_>>()?;
+                Some(match negated {
+                    false => doc! { path(column): { "$in": values } },
+                    true => {
+                        values.push(Bson::Null);
+                        doc! { path(column): { "$nin": values } }
+                    }
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// `value` as MongoDB compares it with values of `shape`, if it can.
+fn literal(value: &Expr, shape: &Shape) -> Option<Bson> {
+    let Expr::Literal(value, _) = value else { return None };
+    let millis = |value: i64, per_milli: i64| {
+        (value % per_milli == 0)
+            .then(|| Bson::DateTime(mongodb::bson::DateTime::from_millis(value / per_milli)))
+    };
+    let number = |value: i64| match shape {
+        Shape::Int32 | Shape::Int64 | Shape::Double => Some(Bson::Int64(value)),
+        _ => None,
+    };
+    match value {
+        ScalarValue::Boolean(Some(v)) if *shape == Shape::Boolean => Some(Bson::Boolean(*v)),
+        ScalarValue::Int8(Some(v)) => number(*v as i64),
+        ScalarValue::Int16(Some(v)) => number(*v as i64),
+        ScalarValue::Int32(Some(v)) => number(*v as i64),
+        ScalarValue::Int64(Some(v)) => number(*v),
+        ScalarValue::UInt8(Some(v)) => number(*v as i64),
+        ScalarValue::UInt16(Some(v)) => number(*v as i64),
+        ScalarValue::UInt32(Some(v)) => number(*v as i64),
+        ScalarValue::UInt64(Some(v)) => number(i64::try_from(*v).ok()?),
+        ScalarValue::Float32(Some(v)) if *shape == Shape::Double => Some(Bson::Double(*v as f64)),
+        ScalarValue::Float64(Some(v)) if *shape == Shape::Double => Some(Bson::Double(*v)),
+        ScalarValue::Utf8(Some(v))
+        | ScalarValue::LargeUtf8(Some(v))
+        | ScalarValue::Utf8View(Some(v)) => {
+            match shape {
+                Shape::String => Some(Bson::String(v.clone())),
+                // Hex strings order as the ids do.
+                Shape::ObjectId if v.len() == 24 && v == &v.to_ascii_lowercase() => {
+                    ObjectId::parse_str(v).ok().map(Bson::ObjectId)
+                }
+                _ => None,
+            }
+        }
+        ScalarValue::TimestampSecond(Some(v), _) if *shape == Shape::DateTime => {
+            millis(v.checked_mul(1000)?, 1)
+        }
+        ScalarValue::TimestampMillisecond(Some(v), _) if *shape == Shape::DateTime => millis(*v, 1),
+        ScalarValue::TimestampMicrosecond(Some(v), _) if *shape == Shape::DateTime => {
+            millis(*v, 1000)
+        }
+        ScalarValue::TimestampNanosecond(Some(v), _) if *shape == Shape::DateTime => {
+            millis(*v, 1_000_000)
+        }
+        _ => None,
+    }
+}
+
+/// The aggregation of a scan.
+#[derive(Debug)]
+struct Query {
+    collection: Collection<Document>,
+    pipeline: Vec<Document>,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    batch_size: usize,
+}
+
+impl PartitionStream for Query {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let (collection, pipeline) = (self.collection.clone(), self.pipeline.clone());
+        let (schema, columns) = (Arc::clone(&self.schema), self.columns.clone());
+        let batch_size = self.batch_size;
+        let documents = futures::stream::once(async move {
+            let cursor = collection
+                .aggregate(pipeline)
+                .batch_size(batch_size.min(u32::MAX as usize) as u32)
+                .await
+                .map_err(mongo_error)?;
+            Ok::<_, DataFusionError>(cursor.map_err(mongo_error))
+        })
+        .try_flatten();
+        let batches = documents.try_chunks(batch_size).map(move |documents| {
+            let documents = documents.map_err(|e| e.1)?;
+            decode(&documents, &columns, &schema)
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+/// The batch of `documents`.
+fn decode(
+    documents: &[Document],
+    columns: &[Column],
+    schema: &SchemaRef,
+) -> DataFusionResult<RecordBatch> {
+    let arrays = columns
+        .iter()
+        .zip(schema.fields())
+        .map(|(column, field)| {
+            let values: Vec<_> = documents.iter().map(|document| column.get(document)).collect();
+            array(field.name(), &values, field.data_type())
+        })
+        .collect::<DataFusionResult<Vec<_>>>()?;
+    // Batches of scans projecting no column (`count(*)`) have rows all the same.
+    let options = RecordBatchOptions::new().with_row_count(Some(documents.len()));
+    Ok(RecordBatch::try_new_with_options(Arc::clone(schema), arrays, &options)?)
+}
+
+/// The array of the values of field `name`, of type `data_type`.
+fn array(name: &str, values: &[Option<&Bson>], data_type: &DataType) -> DataFusionResult<ArrayRef> {
+    let values: Vec<_> = values
+        .iter()
+        .map(|value| value.filter(|value| !matches!(value, Bson::Null | Bson::Undefined)))
+        .collect();
+    let unexpected = |value: &Bson| {
+        DataFusionError::Execution(format!(
+            "MongoDB field {name} holds a value of type {:?}, but is of type {data_type} as \
+             inferred from the sampled documents",
+            value.element_type()
+        ))
+    };
+    let each = |convert: &dyn Fn(&Bson) -> Option<()>| {
+        values
+            .iter()
+            .flatten()
+            .try_for_each(|value| convert(value).ok_or_else(|| unexpected(value)))
+    };
+    Ok(match data_type {
+        DataType::Boolean => {
+            each(&|value| matches!(value, Bson::Boolean(_)).then_some(()))?;
+            Arc::new(
+                values.iter().map(|value| value.and_then(Bson::as_bool)).collect::<BooleanArray>(),
+            )
+        }
+        DataType::Int32 => {
+            each(&|value| value.as_i32().map(|_| ()))?;
+            Arc::new(
+                values.iter().map(|value| value.and_then(Bson::as_i32)).collect::<Int32Array>(),
+            )
+        }
+        DataType::Int64 => {
+            let int = |value: &Bson| match value {
+                Bson::Int32(v) => Some(*v as i64),
+                Bson::Int64(v) => Some(*v),
+                _ => None,
+            };
+            each(&|value| int(value).map(|_| ()))?;
+            Arc::new(values.iter().map(|value| value.and_then(int)).collect::<Int64Array>())
+        }
+        DataType::Float64 => {
+            let double = |value: &Bson| match value {
+                Bson::Int32(v) => Some(*v as f64),
+                Bson::Int64(v) => Some(*v as f64),
+                Bson::Double(v) => Some(*v),
+                _ => None,
+            };
+            each(&|value| double(value).map(|_| ()))?;
+            Arc::new(values.iter().map(|value| value.and_then(double)).collect::<Float64Array>())
+        }
+        DataType::Timestamp(_, zone) => {
+            let millis = |value: &Bson| value.as_datetime().map(|time| time.timestamp_millis());
+            each(&|value| millis(value).map(|_| ()))?;
+            let array: TimestampMillisecondArray =
+                values.iter().map(|value| value.and_then(millis)).collect();
+            Arc::new(array.with_timezone_opt(zone.clone()))
+        }
+        DataType::Binary => {
+            fn bytes(value: &Bson) -> Option<&[u8]> {
+                match value {
+                    Bson::Binary(binary) => Some(&binary.bytes),
+                    _ => None,
+                }
+            }
+            each(&|value| bytes(value).map(|_| ()))?;
+            Arc::new(values.iter().map(|value| value.and_then(bytes)).collect::<BinaryArray>())
+        }
+        DataType::Struct(fields) => {
+            each(&|value| value.as_document().map(|_| ()))?;
+            let children = fields
+                .iter()
+                .map(|field| {
+                    let values: Vec<_> = values
+                        .iter()
+                        .map(|value| value.and_then(Bson::as_document)?.get(field.name()))
+                        .collect();
+                    array(&format!("{name}.{}", field.name()), &values, field.data_type())
+                })
+                .collect::<DataFusionResult<Vec<_>>>()?;
+            let nulls = NullBuffer::from_iter(values.iter().map(Option::is_some));
+            Arc::new(StructArray::try_new(fields.clone(), children, Some(nulls))?)
+        }
+        DataType::List(field) => {
+            each(&|value| value.as_array().map(|_| ()))?;
+            let arrays: Vec<_> =
+                values.iter().map(|value| value.and_then(Bson::as_array)).collect();
+            let lengths = arrays.iter().map(|items| items.map_or(0, Vec::len));
+            let items: Vec<_> =
+                arrays.iter().flatten().flat_map(|items| items.iter().map(Some)).collect();
+            let nulls = NullBuffer::from_iter(arrays.iter().map(Option::is_some));
+            Arc::new(ListArray::try_new(
+                Arc::clone(field),
+                OffsetBuffer::from_lengths(lengths),
+                array(name, &items, field.data_type())?,
+                Some(nulls),
+            )?)
+        }
+        _ => Arc::new(values.iter().map(|value| value.map(text)).collect::<StringArray>()),
+    })
+}
+
+/// The text of a value in a string column.
+fn text(value: &Bson) -> String {
+    match value {
+        Bson::String(v) => v.clone(),
+        Bson::ObjectId(id) => id.to_hex(),
+        value => value.clone().into_relaxed_extjson().to_string(),
+    }
+}
+
+fn mongo_error(e: mongodb::error::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Fields;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::common::Column as ColumnName;
+    use datafusion::prelude::{col, lit};
+
+    fn sample() -> Vec<Document> {
+        let id = |hex: &str| ObjectId::parse_str(hex).unwrap();
+        vec![
+            doc! {
+                "_id": id("64b7f0a1c2d3e4f5a6b7c8d1"),
+                "name": "Ada",
+                "age": 36,
+                "address": { "city": "London", "zip": "N1" },
+                "tags": ["math", "engines"],
+                "joined": mongodb::bson::DateTime::from_millis(1_700_000_000_000),
+                "code": 7,
+            },
+            doc! {
+                "_id": id("64b7f0a1c2d3e4f5a6b7c8d2"),
+                "name": "Grace",
+                "age": 85_i64,
+                "address": { "city": "New York", "geo": { "lat": 40.7 } },
+                "tags": [],
+                "code": "A",
+            },
+            doc! { "_id": id("64b7f0a1c2d3e4f5a6b7c8d3"), "name": Bson::Null, "age": 2.5 },
+        ]
+    }
+
+    async fn table() -> MongoTable {
+        // Clients connect lazily, so no server is needed to plan.
+        let client = client("mongodb://127.0.0.1:1").await.unwrap();
+        MongoTable::from_sample(client.database("test").collection("people"), &sample())
+    }
+
+    fn fields(table: &MongoTable) -> Vec<String> {
+        let fields = table.schema.fields().iter();
+        fields.map(|field| format!("{}: {}", field.name(), field.data_type())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_schemas_are_inferred_from_samples() {
+        let table = table().await;
+        assert_eq!(
+            fields(&table),
+            [
+                "_id: Utf8",
+                "name: Utf8",
+                "age: Float64",
+                "address.city: Utf8",
+                "address.zip: Utf8",
+                "address.geo.lat: Float64",
+                "tags: List(Field { name: \"item\", data_type: Utf8, nullable: true, dict_id: 0, \
+                 dict_is_ordered: false, metadata: {} })",
+                "joined: Timestamp(Millisecond, Some(\"UTC\"))",
+                "code: Utf8",
+            ]
+        );
+        let table = table.with_nesting(Nesting::Struct);
+        assert_eq!(table.schema.fields().len(), 7);
+        assert_eq!(
+            table.schema.field(3).data_type(),
+            &DataType::Struct(Fields::from(vec![
+                Field::new("city", DataType::Utf8, true),
+                Field::new("zip", DataType::Utf8, true),
+                Field::new(
+                    "geo",
+                    DataType::Struct(vec![Field::new("lat", DataType::Float64, true)].into()),
+                    true
+                ),
+            ]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_documents_decode_to_batches() {
+        let table = table().await;
+        let batch = decode(&sample(), &table.columns, &table.schema).unwrap();
+        assert_eq!(
+            pretty_format_batches(&[batch]).unwrap().to_string(),
+            "+--------------------------+-------+------+--------------+-------------+-----------------+-----------------+----------------------+------+\n\
+             | _id                      | name  | age  | address.city | address.zip | address.geo.lat | tags            | joined               | code |\n\
+             +--------------------------+-------+------+--------------+-------------+-----------------+-----------------+----------------------+------+\n\
+             | 64b7f0a1c2d3e4f5a6b7c8d1 | Ada   | 36.0 | London       | N1          |                 | [math, engines] | 2023-11-14T22:13:20Z | 7    |\n\
+             | 64b7f0a1c2d3e4f5a6b7c8d2 | Grace | 85.0 | New York     |             | 40.7            | []              |                      | A    |\n\
+             | 64b7f0a1c2d3e4f5a6b7c8d3 |       | 2.5  |              |             |                 |                 |                      |      |\n\
+             +--------------------------+-------+------+--------------+-------------+-----------------+-----------------+----------------------+------+"
+        );
+
+        let table = table.with_nesting(Nesting::Struct);
+        let batch = decode(&sample(), &table.columns, &table.schema).unwrap();
+        let addresses = pretty_format_batches(&[batch.project(&[3]).unwrap()]).unwrap();
+        assert_eq!(
+            addresses.to_string(),
+            "+-------------------------------------------+\n\
+             | address                                   |\n\
+             +-------------------------------------------+\n\
+             | {city: London, zip: N1, geo: }            |\n\
+             | {city: New York, zip: , geo: {lat: 40.7}} |\n\
+             |                                           |\n\
+             +-------------------------------------------+"
+        );
+
+        let error = decode(&[doc! { "age": "old" }], &table.columns, &table.schema).unwrap_err();
+        assert!(
+            error.to_string().contains("MongoDB field age holds a value of type String"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filters_become_match_conditions() {
+        let table = table().await;
+        let city = || Expr::Column(ColumnName::from_name("address.city"));
+        assert_eq!(
+            table.predicate(&col("age").gt(lit(40))),
+            Some(doc! { "age": { "$gt": 40_i64 } })
+        );
+        assert_eq!(
+            table.predicate(&lit("London").not_eq(city()).or(col("name").is_null())),
+            Some(doc! {
+                "$or": [{ "address.city": { "$nin": ["London", Bson::Null] } }, { "name": Bson::Null }]
+            })
+        );
+        assert_eq!(
+            table.predicate(&col("_id").in_list(vec![lit("64b7f0a1c2d3e4f5a6b7c8d2")], false)),
+            Some(
+                doc! { "_id": { "$in": [ObjectId::parse_str("64b7f0a1c2d3e4f5a6b7c8d2").unwrap()] } }
+            )
+        );
+        assert_eq!(
+            table.predicate(&col("age").between(lit(1), lit(50))),
+            Some(doc! { "age": { "$gte": 1_i64, "$lte": 50_i64 } })
+        );
+
+        let unsupported = [
+            city().like(lit("Lon%")),
+            col("code").eq(lit("A")),
+            col("tags").is_not_null(),
+            col("name").eq(lit(1)),
+            col("_id").eq(lit("not an id")),
+        ];
+        let filters: Vec<_> = unsupported.iter().collect();
+        let pushdown = table.supports_filters_pushdown(&filters).unwrap();
+        assert!(pushdown.iter().all(|p| *p == TableProviderFilterPushDown::Unsupported));
+    }
+}
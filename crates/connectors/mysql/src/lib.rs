@@ -1,9 +1,24 @@
-// TODO: Implement MySQL connector logic
+//! MySQL tables.
+//!
+//! [`MySqlTable`] reads a MySQL (or MariaDB) table over the native protocol, with
+//! connections taken from a pool shared by every table of the database. The schema is
+//! discovered from `information_schema`, and filters and limits of queries are
+//! evaluated by MySQL, so only the rows and columns a query needs are sent (see
+//! [`table`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_mysql::{pool, MySqlTable};
+//! use std::sync::Arc;
+//!
+//! let pool = pool("mysql://igloo:secret@db:3306/shop")?;
+//! ctx.register_table("orders", Arc::new(MySqlTable::try_new(pool.clone(), "orders").await?))?;
+//! ctx.register_table("customers", Arc::new(MySqlTable::try_new(pool, "customers").await?))?;
+//! ctx.sql("SELECT count(*) FROM orders WHERE amount > 100").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn sample_test() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+pub mod table;
+
+pub use table::{pool, MySqlTable};
@@ -1,9 +1,27 @@
-// TODO: Implement Postgres connector logic
+//! PostgreSQL tables.
+//!
+//! [`PostgresSnapshot`] reads a table as of one consistent snapshot, in chunks copied
+//! in parallel, for the initial load of a table whose changes are then streamed by
+//! CDC (see [`snapshot`]). Registered with a session, it is copied into the lake like
+//! any other table:
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_postgres::{PostgresSnapshot, SnapshotOptions};
+//! use std::sync::Arc;
+//!
+//! let options = SnapshotOptions::default().with_chunks(8);
+//! let snapshot = PostgresSnapshot::try_new("host=db user=igloo", "public.orders", options).await?;
+//! ctx.register_table("orders_snapshot", Arc::new(snapshot))?;
+//! ctx.sql("COPY orders_snapshot TO 's3://lake/orders/' STORED AS PARQUET").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`table_schema`] reads the live schema of a table, to check its copy in the lake
+//! for drift.
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn sample_test() {
-        assert_eq!(2 + 2, 4);
-    }
-}
+mod copy;
+pub mod snapshot;
+
+pub use snapshot::{table_schema, PostgresSnapshot, SnapshotOptions};
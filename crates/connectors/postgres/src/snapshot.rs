@@ -0,0 +1,732 @@
+//! Copying a PostgreSQL table as of one snapshot.
+//!
+//! Opening a [`PostgresSnapshot`] starts a `REPEATABLE READ` transaction and exports
+//! its snapshot. The table is read with `COPY ... TO STDOUT (FORMAT binary)` in chunks,
+//! each over a connection of its own whose transaction imports that snapshot, so every
+//! chunk sees the table as of the same moment however long the copy takes; what is
+//! committed afterwards is left to CDC. The exporting transaction stays open as long
+//! as the provider or a scan of it does.
+//!
+//! Chunks split the range of an integer column, by default the table's primary key
+//! when that is a single integer column, into equal ranges, and are the partitions of
+//! scans, so they are copied in parallel. Tables without such a column are read in one
+//! chunk.
+//!
+//! Scans copy only the columns they project. Filters comparing columns to literals,
+//! including `IN` lists, are added to the copy's `WHERE` clause, so that, e.g., the
+//! keys of a join passed to the scan (see the engine's `sideways` module) only copy
+//! the rows that can join. Columns read as strings are compared as their text form in
+//! byte order (`COLLATE "C"`), as the engine compares strings, rather than in the
+//! database's collation. The engine still applies filters to the rows copied.
+//!
+//! Columns map to Arrow types: `boolean`, `smallint`, `integer`, `bigint`, `real` and
+//! `double precision` to their Arrow counterparts, `numeric` of a precision up to 38
+//! to decimals, `date` and `timestamp`s to dates and microsecond timestamps (in UTC
+//! for `timestamp with time zone`), `bytea` to binary, and everything else to strings
+//! of its text form. Text columns Postgres estimates (from `pg_stats`) to have at most
+//! [`SnapshotOptions::max_dictionary_values`] distinct values are dictionary-encoded
+//! strings, so values such as status codes are held once per batch.
+
+use crate::copy::CopyDecoder;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::catalog::Session;
+use datafusion::common::ScalarValue;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::{BinaryExpr, Expr, Operator, TableProviderFilterPushDown};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::{StreamExt, TryStreamExt};
+use igloo_common::retry::RetryPolicy;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio_postgres::types::Type;
+use tokio_postgres::{Client, NoTls};
+
+/// Rows per batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// Distinct values up to which a text column is dictionary-encoded unless configured
+/// otherwise.
+pub const DEFAULT_MAX_DICTIONARY_VALUES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotOptions {
+    /// Chunks the table is copied in, in parallel.
+    pub chunks: usize,
+    /// The integer column chunks are ranges of; by default the table's primary key.
+    pub chunk_column: Option<String>,
+    pub batch_size: usize,
+    /// Text columns estimated to have at most this many distinct values are
+    /// dictionary-encoded; 0 encodes none.
+    pub max_dictionary_values: usize,
+}
+
+impl Default for SnapshotOptions {
+    fn default() -> Self {
+        Self {
+            chunks: 1,
+            chunk_column: None,
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_dictionary_values: DEFAULT_MAX_DICTIONARY_VALUES,
+        }
+    }
+}
+
+impl SnapshotOptions {
+    pub fn with_chunks(mut self, chunks: usize) -> Self {
+        self.chunks = chunks.max(1);
+        self
+    }
+
+    pub fn with_chunk_column(mut self, column: impl Into<String>) -> Self {
+        self.chunk_column = Some(column.into());
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_max_dictionary_values(mut self, values: usize) -> Self {
+        self.max_dictionary_values = values;
+        self
+    }
+}
+
+/// How a column is copied and what it becomes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ColumnKind {
+    Boolean,
+    Int16,
+    Int32,
+    Int64,
+    Float32,
+    Float64,
+    Decimal(u8, i8),
+    Date,
+    /// Whether the timestamp is `with time zone`.
+    Timestamp(bool),
+    Binary,
+    Text,
+    /// Text of few distinct values, dictionary-encoded.
+    Dictionary,
+}
+
+impl ColumnKind {
+    /// The kind of a column of `information_schema.columns`.
+    fn new(data_type: &str, precision: Option<i32>, scale: Option<i32>) -> Self {
+        match data_type {
+            "boolean" => ColumnKind::Boolean,
+            "smallint" => ColumnKind::Int16,
+            "integer" => ColumnKind::Int32,
+            "bigint" => ColumnKind::Int64,
+            "real" => ColumnKind::Float32,
+            "double precision" => ColumnKind::Float64,
+            "numeric" => match (precision, scale) {
+                (Some(precision @ 1..=38), Some(scale)) => {
+                    ColumnKind::Decimal(precision as u8, scale as i8)
+                }
+                // Unconstrained numerics keep their exact text.
+                _ => ColumnKind::Text,
+            },
+            "date" => ColumnKind::Date,
+            "timestamp without time zone" => ColumnKind::Timestamp(false),
+            "timestamp with time zone" => ColumnKind::Timestamp(true),
+            "bytea" => ColumnKind::Binary,
+            _ => ColumnKind::Text,
+        }
+    }
+
+    pub(crate) fn data_type(&self) -> DataType {
+        match self {
+            ColumnKind::Boolean => DataType::Boolean,
+            ColumnKind::Int16 => DataType::Int16,
+            ColumnKind::Int32 => DataType::Int32,
+            ColumnKind::Int64 => DataType::Int64,
+            ColumnKind::Float32 => DataType::Float32,
+            ColumnKind::Float64 => DataType::Float64,
+            ColumnKind::Decimal(precision, scale) => DataType::Decimal128(*precision, *scale),
+            ColumnKind::Date => DataType::Date32,
+            ColumnKind::Timestamp(with_zone) => {
+                DataType::Timestamp(TimeUnit::Microsecond, with_zone.then(|| "UTC".into()))
+            }
+            ColumnKind::Binary => DataType::Binary,
+            ColumnKind::Text => DataType::Utf8,
+            ColumnKind::Dictionary => {
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+            }
+        }
+    }
+
+    /// The type the column is copied as: decimals and text as `text`.
+    fn copy_type(&self) -> Type {
+        match self {
+            ColumnKind::Boolean => Type::BOOL,
+            ColumnKind::Int16 => Type::INT2,
+            ColumnKind::Int32 => Type::INT4,
+            ColumnKind::Int64 => Type::INT8,
+            ColumnKind::Float32 => Type::FLOAT4,
+            ColumnKind::Float64 => Type::FLOAT8,
+            ColumnKind::Date => Type::DATE,
+            ColumnKind::Timestamp(false) => Type::TIMESTAMP,
+            ColumnKind::Timestamp(true) => Type::TIMESTAMPTZ,
+            ColumnKind::Binary => Type::BYTEA,
+            ColumnKind::Decimal(..) | ColumnKind::Text | ColumnKind::Dictionary => Type::TEXT,
+        }
+    }
+
+    /// The column in the copy's select list.
+    fn select(&self, name: &str) -> String {
+        match self.copy_type() {
+            Type::TEXT => format!("{}::text", quote_ident(name)),
+            _ => quote_ident(name),
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        matches!(self, ColumnKind::Int16 | ColumnKind::Int32 | ColumnKind::Int64)
+    }
+}
+
+/// A PostgreSQL table as of one snapshot, see the [module docs](self).
+pub struct PostgresSnapshot {
+    config: String,
+    table: String,
+    schema: SchemaRef,
+    kinds: Vec<ColumnKind>,
+    /// The predicates of the chunks.
+    chunks: Vec<String>,
+    snapshot_id: String,
+    batch_size: usize,
+    /// Holds the snapshot's transaction open while the table may still be read.
+    exporter: Arc<Client>,
+}
+
+impl fmt::Debug for PostgresSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresSnapshot")
+            .field("table", &self.table)
+            .field("snapshot_id", &self.snapshot_id)
+            .field("chunks", &self.chunks.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresSnapshot {
+    /// Export a snapshot of `table` (`schema.table`, or a table of `public`) of the
+    /// database at `config`, a libpq-style connection string.
+    pub async fn try_new(
+        config: &str,
+        table: &str,
+        options: SnapshotOptions,
+    ) -> DataFusionResult<Self> {
+        let (schema_name, table_name) = table.split_once('.').unwrap_or(("public", table));
+        let exporter = connect(config).await?;
+        exporter
+            .batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .await
+            .map_err(postgres_error)?;
+        let snapshot_id: String = exporter
+            .query_one("SELECT pg_export_snapshot()", &[])
+            .await
+            .map_err(postgres_error)?
+            .get(0);
+        let mut columns = table_columns(&exporter, table).await?;
+        let qualified = format!("{}.{}", quote_ident(schema_name), quote_ident(table_name));
+        if options.max_dictionary_values > 0 {
+            let distinct = distinct_values(&exporter, &qualified).await?;
+            for (name, kind, _) in &mut columns {
+                let few = distinct
+                    .get(name)
+                    .is_some_and(|&n| n > 0.0 && n <= options.max_dictionary_values as f64);
+                if *kind == ColumnKind::Text && few {
+                    *kind = ColumnKind::Dictionary;
+                }
+            }
+        }
+        let schema = columns_schema(&columns);
+
+        let chunk_column = match options.chunk_column {
+            Some(column) => Some(column),
+            None if options.chunks > 1 => primary_key(&exporter, &qualified).await?,
+            None => None,
+        };
+        let predicates = match chunk_column {
+            Some(column) if options.chunks > 1 => {
+                let Some((_, kind, _)) = columns.iter().find(|(name, ..)| *name == column) else {
+                    return Err(DataFusionError::Plan(format!(
+                        "Postgres table {table} has no column {column}"
+                    )));
+                };
+                if !kind.is_integer() {
+                    return Err(DataFusionError::Plan(format!(
+                        "cannot chunk Postgres table {table} by {column}, which is not an integer"
+                    )));
+                }
+                let column = quote_ident(&column);
+                let sql =
+                    format!("SELECT min({column})::int8, max({column})::int8 FROM {qualified}");
+                let row = exporter.query_one(&sql, &[]).await.map_err(postgres_error)?;
+                match (row.get::<_, Option<i64>>(0), row.get::<_, Option<i64>>(1)) {
+                    (Some(min), Some(max)) => chunk_predicates(&column, min, max, options.chunks),
+                    _ => vec!["TRUE".to_string()],
+                }
+            }
+            _ => vec!["TRUE".to_string()],
+        };
+
+        Ok(Self {
+            config: config.to_string(),
+            table: qualified,
+            schema,
+            kinds: columns.into_iter().map(|(_, kind, _)| kind).collect(),
+            chunks: predicates,
+            snapshot_id,
+            batch_size: options.batch_size,
+            exporter: Arc::new(exporter),
+        })
+    }
+
+    /// The ID of the exported snapshot, as `SET TRANSACTION SNAPSHOT` takes it.
+    pub fn snapshot_id(&self) -> &str {
+        &self.snapshot_id
+    }
+
+    /// Chunks the table is copied in.
+    pub fn chunks(&self) -> usize {
+        self.chunks.len()
+    }
+}
+
+#[async_trait]
+impl TableProvider for PostgresSnapshot {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match filter_sql(filter, &self.schema) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let columns: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.kinds.len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&columns)?);
+        let (select, kinds) = copied_columns(&self.schema, &self.kinds, &columns);
+        let conditions: String = filters
+            .iter()
+            .filter_map(|filter| filter_sql(filter, &self.schema))
+            .map(|sql| format!(" AND {sql}"))
+            .collect();
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|predicate| {
+                Arc::new(Chunk {
+                    config: self.config.clone(),
+                    snapshot_id: self.snapshot_id.clone(),
+                    sql: format!(
+                        "COPY (SELECT {select} FROM {} WHERE {predicate}{conditions}) \
+                         TO STDOUT (FORMAT binary)",
+                        self.table
+                    ),
+                    schema: Arc::clone(&schema),
+                    kinds: kinds.clone(),
+                    batch_size: self.batch_size,
+                    exporter: Arc::clone(&self.exporter),
+                }) as Arc<dyn PartitionStream>
+            })
+            .collect();
+        Ok(Arc::new(StreamingTableExec::try_new(schema, chunks, None, None, false, limit)?))
+    }
+}
+
+/// The select list copying `columns` of a table of `schema` and `kinds`, and the kinds
+/// of what it copies: `TRUE` when no column is, as for `SELECT count(*)`.
+fn copied_columns(
+    schema: &Schema,
+    kinds: &[ColumnKind],
+    columns: &[usize],
+) -> (String, Vec<ColumnKind>) {
+    if columns.is_empty() {
+        return ("TRUE".to_string(), vec![ColumnKind::Boolean]);
+    }
+    let select = columns.iter().map(|&i| kinds[i].select(schema.field(i).name()));
+    (select.collect::<Vec<_>>().join(", "), columns.iter().map(|&i| kinds[i].clone()).collect())
+}
+
+/// One range of a table, copied over a connection of its own.
+struct Chunk {
+    config: String,
+    snapshot_id: String,
+    /// The `COPY` statement.
+    sql: String,
+    schema: SchemaRef,
+    /// The kinds of the copied columns, those of `schema` unless it has none.
+    kinds: Vec<ColumnKind>,
+    batch_size: usize,
+    /// Holds the snapshot's transaction open while the chunk may still be read.
+    exporter: Arc<Client>,
+}
+
+impl fmt::Debug for Chunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chunk").field("sql", &self.sql).finish_non_exhaustive()
+    }
+}
+
+impl PartitionStream for Chunk {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let (config, snapshot_id, sql) =
+            (self.config.clone(), self.snapshot_id.clone(), self.sql.clone());
+        let (schema, kinds, batch_size) =
+            (Arc::clone(&self.schema), self.kinds.clone(), self.batch_size);
+        let exporter = Arc::clone(&self.exporter);
+        let batches = async move {
+            let client = connect(&config).await?;
+            let begin = format!(
+                "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY; SET TRANSACTION SNAPSHOT {}",
+                quote_literal(&snapshot_id)
+            );
+            client.batch_execute(&begin).await.map_err(postgres_error)?;
+            let copy = client.copy_out(sql.as_str()).await.map_err(postgres_error)?;
+            let mut decoder = CopyDecoder::new(schema, kinds, batch_size);
+            // `None` marks the end of the copy, after which the last rows are flushed.
+            let data = copy.map_ok(Some).chain(futures::stream::iter([Ok(None)]));
+            let batches = data.map(move |data| {
+                // The connection and the snapshot live as long as the stream.
+                let _ = (&client, &exporter);
+                let batches = match data.map_err(postgres_error)? {
+                    Some(data) => decoder.push(&data)?,
+                    None => decoder.finish()?.into_iter().collect(),
+                };
+                Ok::<_, DataFusionError>(futures::stream::iter(batches.into_iter().map(Ok)))
+            });
+            Ok::<_, DataFusionError>(batches.try_flatten())
+        };
+        Box::pin(RecordBatchStreamAdapter::new(
+            Arc::clone(&self.schema),
+            futures::stream::once(batches).try_flatten(),
+        ))
+    }
+}
+
+/// `filter` in Postgres, if it can be pushed down.
+fn filter_sql(filter: &Expr, schema: &Schema) -> Option<String> {
+    let operand = |expr: &Expr| filter_sql(expr, schema);
+    match filter {
+        Expr::Column(column) => {
+            let field = schema.field_with_name(&column.name).ok()?;
+            let name = quote_ident(&column.name);
+            match field.data_type() {
+                DataType::Utf8 | DataType::Dictionary(_, _) => {
+                    Some(format!("({name}::text COLLATE \"C\")"))
+                }
+                _ => Some(name),
+            }
+        }
+        Expr::Literal(value, _) => literal_sql(value),
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            let op = match op {
+                Operator::And => "AND",
+                Operator::Or => "OR",
+                Operator::Eq => "=",
+                Operator::NotEq => "<>",
+                Operator::Lt => "<",
+                Operator::LtEq => "<=",
+                Operator::Gt => ">",
+                Operator::GtEq => ">=",
+                _ => return None,
+            };
+            Some(format!("({} {op} {})", operand(left)?, operand(right)?))
+        }
+        Expr::Not(expr) => Some(format!("(NOT {})", operand(expr)?)),
+        Expr::IsNull(expr) => Some(format!("({} IS NULL)", operand(expr)?)),
+        Expr::IsNotNull(expr) => Some(format!("({} IS NOT NULL)", operand(expr)?)),
+        Expr::InList(InList { expr, list, negated }) if !list.is_empty() => {
+            let list = list.iter().map(operand).collect::<Option<Vec<_>>>()?;
+            let not = if *negated { " NOT" } else { "" };
+            Some(format!("({}{not} IN ({}))", operand(expr)?, list.join(", ")))
+        }
+        _ => None,
+    }
+}
+
+/// `value` as a Postgres literal, for the types compared to here.
+fn literal_sql(value: &ScalarValue) -> Option<String> {
+    if value.is_null() {
+        return Some("NULL".to_string());
+    }
+    Some(match value {
+        ScalarValue::Boolean(Some(v)) => v.to_string().to_uppercase(),
+        ScalarValue::Int8(Some(v)) => v.to_string(),
+        ScalarValue::Int16(Some(v)) => v.to_string(),
+        ScalarValue::Int32(Some(v)) => v.to_string(),
+        ScalarValue::Int64(Some(v)) => v.to_string(),
+        ScalarValue::UInt8(Some(v)) => v.to_string(),
+        ScalarValue::UInt16(Some(v)) => v.to_string(),
+        ScalarValue::UInt32(Some(v)) => v.to_string(),
+        ScalarValue::UInt64(Some(v)) => v.to_string(),
+        ScalarValue::Float32(Some(v)) if v.is_finite() => v.to_string(),
+        ScalarValue::Float64(Some(v)) if v.is_finite() => v.to_string(),
+        ScalarValue::Utf8(Some(v))
+        | ScalarValue::LargeUtf8(Some(v))
+        | ScalarValue::Utf8View(Some(v)) => quote_literal(v),
+        ScalarValue::Dictionary(_, value) => return literal_sql(value),
+        _ => return None,
+    })
+}
+
+/// The live schema of the Postgres `table` (`schema.table`, or a table of `public`) of
+/// the database at `config`: the columns a [`PostgresSnapshot`] of it copies, with text
+/// columns as plain strings whatever their number of distinct values.
+pub async fn table_schema(config: &str, table: &str) -> DataFusionResult<SchemaRef> {
+    let client = connect(config).await?;
+    Ok(columns_schema(&table_columns(&client, table).await?))
+}
+
+/// The name, kind and nullability of each column of `table`, in order.
+async fn table_columns(
+    client: &Client,
+    table: &str,
+) -> DataFusionResult<Vec<(String, ColumnKind, bool)>> {
+    let (schema_name, table_name) = table.split_once('.').unwrap_or(("public", table));
+    let rows = client
+        .query(
+            "SELECT column_name::text, data_type::text, numeric_precision::int4,
+                    numeric_scale::int4, is_nullable = 'YES'
+             FROM information_schema.columns
+             WHERE table_schema = $1 AND table_name = $2
+             ORDER BY ordinal_position",
+            &[&schema_name, &table_name],
+        )
+        .await
+        .map_err(postgres_error)?;
+    if rows.is_empty() {
+        return Err(DataFusionError::Plan(format!("Postgres table {table} does not exist")));
+    }
+    Ok(rows
+        .iter()
+        .map(|row| (row.get(0), ColumnKind::new(row.get(1), row.get(2), row.get(3)), row.get(4)))
+        .collect())
+}
+
+fn columns_schema(columns: &[(String, ColumnKind, bool)]) -> SchemaRef {
+    let fields: Vec<_> = columns
+        .iter()
+        .map(|(name, kind, nullable)| Field::new(name, kind.data_type(), *nullable))
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+/// The single-column integer primary key of `table`, if it has one.
+async fn primary_key(client: &Client, table: &str) -> DataFusionResult<Option<String>> {
+    let rows = client
+        .query(
+            "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod) IN
+                    ('smallint', 'integer', 'bigint')
+             FROM pg_index i
+             JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey)
+             WHERE i.indrelid = $1::text::regclass AND i.indisprimary",
+            &[&table],
+        )
+        .await
+        .map_err(postgres_error)?;
+    Ok(match &rows[..] {
+        [row] if row.get::<_, bool>(1) => Some(row.get(0)),
+        _ => None,
+    })
+}
+
+/// Postgres's estimate of the distinct values of each column of `table`, for the
+/// columns it has statistics of.
+async fn distinct_values(client: &Client, table: &str) -> DataFusionResult<HashMap<String, f64>> {
+    // A negative `n_distinct` is a fraction of the table's rows.
+    let rows = client
+        .query(
+            "SELECT s.attname::text, CASE WHEN s.n_distinct >= 0 THEN s.n_distinct
+                                           ELSE -s.n_distinct * c.reltuples END::float8
+             FROM pg_class c
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+             JOIN pg_stats s ON s.schemaname = n.nspname AND s.tablename = c.relname
+             WHERE c.oid = $1::text::regclass",
+            &[&table],
+        )
+        .await
+        .map_err(postgres_error)?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// Predicates splitting the values `min..=max` of `column` into up to `chunks` equal
+/// ranges, the first also taking nulls.
+fn chunk_predicates(column: &str, min: i64, max: i64, chunks: usize) -> Vec<String> {
+    let span = max as i128 - min as i128 + 1;
+    let chunks = (chunks as i128).min(span).max(1);
+    let step = (span + chunks - 1) / chunks;
+    let bounds: Vec<i128> =
+        (1..chunks).map(|i| min as i128 + i * step).filter(|b| *b <= max as i128).collect();
+    if bounds.is_empty() {
+        return vec!["TRUE".to_string()];
+    }
+    let mut predicates = vec![format!("({column} < {} OR {column} IS NULL)", bounds[0])];
+    for window in bounds.windows(2) {
+        predicates.push(format!("{column} >= {} AND {column} < {}", window[0], window[1]));
+    }
+    predicates.push(format!("{column} >= {}", bounds[bounds.len() - 1]));
+    predicates
+}
+
+/// Connect to `config`, retrying connection failures such as a database restarting.
+async fn connect(config: &str) -> DataFusionResult<Client> {
+    let (client, connection) = RetryPolicy::default()
+        .retry(|| async { tokio_postgres::connect(config, NoTls).await.map_err(postgres_error) })
+        .await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            tracing::error!(error = %e, "Postgres snapshot connection error");
+        }
+    });
+    Ok(client)
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn postgres_error(e: tokio_postgres::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::prelude::{col, lit};
+    use igloo_common::retry::Retryable;
+
+    #[tokio::test]
+    async fn test_connection_failures_are_retryable() {
+        let config = "host=127.0.0.1 port=1 user=igloo connect_timeout=1";
+        let err = tokio_postgres::connect(config, NoTls).await.map(|_| ()).unwrap_err();
+        assert!(postgres_error(err).is_retryable());
+    }
+
+    #[test]
+    fn test_column_kinds() {
+        assert_eq!(
+            ColumnKind::new("numeric", Some(10), Some(2)).data_type(),
+            DataType::Decimal128(10, 2)
+        );
+        assert_eq!(ColumnKind::new("numeric", None, None), ColumnKind::Text);
+        assert_eq!(
+            ColumnKind::new("timestamp with time zone", None, None).data_type(),
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into()))
+        );
+        assert_eq!(
+            ColumnKind::new("uuid", None, None).select("Order \"id\""),
+            "\"Order \"\"id\"\"\"::text"
+        );
+        assert_eq!(ColumnKind::Int64.select("id"), "\"id\"");
+        assert_eq!(ColumnKind::Dictionary.select("status"), "\"status\"::text");
+        assert_eq!(
+            ColumnKind::Dictionary.data_type(),
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8))
+        );
+    }
+
+    #[test]
+    fn test_filters_are_translated_to_sql() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]);
+        let sql = |filter: Expr| filter_sql(&filter, &schema);
+        assert_eq!(
+            sql(col("id").in_list(vec![lit(1i64), lit(3i64)], false)).as_deref(),
+            Some("(\"id\" IN (1, 3))")
+        );
+        assert_eq!(
+            sql(col("name").eq(lit("O'Brien")).or(col("name").is_null())).as_deref(),
+            Some(
+                "(((\"name\"::text COLLATE \"C\") = 'O''Brien') OR \
+                 ((\"name\"::text COLLATE \"C\") IS NULL))"
+            )
+        );
+        assert_eq!(
+            sql(col("name").lt(lit("b"))).as_deref(),
+            Some("((\"name\"::text COLLATE \"C\") < 'b')")
+        );
+        assert_eq!(sql(col("id").gt(lit(f64::NAN))), None);
+        assert_eq!(sql(col("missing").eq(lit(1))), None);
+        assert_eq!(sql(col("name").like(lit("a%"))), None);
+    }
+
+    #[test]
+    fn test_only_projected_columns_are_copied() {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("price", DataType::Decimal128(10, 2), true),
+            Field::new("note", DataType::Utf8, true),
+        ]);
+        let kinds = [ColumnKind::Int64, ColumnKind::Decimal(10, 2), ColumnKind::Text];
+        let (select, copied) = copied_columns(&schema, &kinds, &[2, 0]);
+        assert_eq!(select, "\"note\"::text, \"id\"");
+        assert_eq!(copied, [ColumnKind::Text, ColumnKind::Int64]);
+        assert_eq!(
+            copied_columns(&schema, &kinds, &[]),
+            ("TRUE".into(), vec![ColumnKind::Boolean])
+        );
+    }
+
+    #[test]
+    fn test_chunk_predicates_cover_the_range() {
+        assert_eq!(
+            chunk_predicates("id", 1, 10, 3),
+            ["(id < 5 OR id IS NULL)", "id >= 5 AND id < 9", "id >= 9"]
+        );
+        assert_eq!(chunk_predicates("id", 7, 7, 4), ["TRUE"]);
+        assert_eq!(chunk_predicates("id", 0, 1, 4), ["(id < 1 OR id IS NULL)", "id >= 1"]);
+        let extreme = chunk_predicates("id", i64::MIN, i64::MAX, 2);
+        assert_eq!(extreme, ["(id < 0 OR id IS NULL)", "id >= 0"]);
+    }
+}
@@ -0,0 +1,27 @@
+//! Redis key spaces.
+//!
+//! [`RedisTable`] reads Redis keys as a read-only table, to join operational state
+//! with analytical data: the hashes whose keys match a pattern, a row per hash, or a
+//! sorted set, a row per member (see [`table`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use datafusion::arrow::datatypes::{DataType, Field, Schema};
+//! use igloo_connector_redis::{client, RedisTable};
+//! use std::sync::Arc;
+//!
+//! let client = client("redis://cache:6379")?;
+//! let fields = Schema::new(vec![
+//!     Field::new("status", DataType::Utf8, true),
+//!     Field::new("items", DataType::Int64, true),
+//! ]);
+//! ctx.register_table("carts", Arc::new(RedisTable::hashes(client.clone(), "cart:*", &fields)))?;
+//! ctx.register_table("leaders", Arc::new(RedisTable::sorted_set(client, "leaderboard")))?;
+//! ctx.sql("SELECT c.key, c.items FROM carts c WHERE c.status = 'open'").await?.collect().await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod table;
+
+pub use table::{client, RedisTable};
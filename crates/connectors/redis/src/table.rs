@@ -0,0 +1,404 @@
+//! Reading Redis keys.
+//!
+//! A [`RedisTable`] reads either hashes or a sorted set, on a connection of its own
+//! for each scan:
+//!
+//! - The hashes whose keys match a pattern are a row each, found with `SCAN` (with
+//!   `TYPE hash`, so Redis 6 or later) and read with `HMGET` of the projected fields,
+//!   pipelined for a batch of keys. The columns are `key`, then those declared, a
+//!   column per field; values are cast from their string to the column's type, and
+//!   missing fields are null. As `SCAN` guarantees, keys added or removed during a
+//!   scan may or may not be read.
+//! - The members of a sorted set are a row each, in order of score, with the columns
+//!   `member` and `score`, read with `ZRANGE` a batch at a time. Members added or
+//!   removed during a scan shift those read after.
+//!
+//! Scans stop at the query's limit.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{ArrayRef, Float64Array, StringArray};
+use datafusion::arrow::compute::{cast_with_options, CastOptions};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::catalog::Session;
+use datafusion::common::project_schema;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use redis::aio::MultiplexedConnection;
+use redis::Client;
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Keys, or members, read per batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// A client of the server at `url`, such as `redis://cache:6379/0`.
+pub fn client(url: &str) -> DataFusionResult<Client> {
+    Client::open(url).map_err(redis_error)
+}
+
+/// What a table reads.
+#[derive(Debug, Clone)]
+enum Keys {
+    /// The hashes of keys matching the pattern.
+    Hashes(String),
+    /// The sorted set at the key.
+    SortedSet(String),
+}
+
+/// Hashes or a sorted set, see the [module docs](self).
+pub struct RedisTable {
+    client: Client,
+    keys: Keys,
+    schema: SchemaRef,
+    batch_size: usize,
+}
+
+impl fmt::Debug for RedisTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RedisTable").field("keys", &self.keys).finish_non_exhaustive()
+    }
+}
+
+impl RedisTable {
+    /// The hashes whose keys match `pattern` (as for `SCAN`, such as `cart:*`), with
+    /// the columns `key`, then those of `fields`.
+    pub fn hashes(client: Client, pattern: impl Into<String>, fields: &Schema) -> Self {
+        let key = Field::new("key", DataType::Utf8, false);
+        let columns: Vec<_> =
+            std::iter::once(Arc::new(key)).chain(fields.fields().iter().cloned()).collect();
+        Self {
+            client,
+            keys: Keys::Hashes(pattern.into()),
+            schema: Arc::new(Schema::new(columns)),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    /// The members of the sorted set at `key`, with the columns `member` and `score`.
+    pub fn sorted_set(client: Client, key: impl Into<String>) -> Self {
+        let schema = Schema::new(vec![
+            Field::new("member", DataType::Utf8, false),
+            Field::new("score", DataType::Float64, false),
+        ]);
+        Self {
+            client,
+            keys: Keys::SortedSet(key.into()),
+            schema: Arc::new(schema),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl TableProvider for RedisTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let schema = project_schema(&self.schema, projection)?;
+        let read = Read {
+            client: self.client.clone(),
+            keys: self.keys.clone(),
+            schema: Arc::clone(&schema),
+            projection: projection.cloned(),
+            batch_size: self.batch_size,
+            limit,
+        };
+        Ok(Arc::new(StreamingTableExec::try_new(
+            schema,
+            vec![Arc::new(read)],
+            None,
+            None,
+            false,
+            limit,
+        )?))
+    }
+}
+
+/// The read of a scan.
+#[derive(Debug, Clone)]
+struct Read {
+    client: Client,
+    keys: Keys,
+    schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    batch_size: usize,
+    limit: Option<usize>,
+}
+
+type Sender = mpsc::Sender<DataFusionResult<RecordBatch>>;
+
+impl Read {
+    /// Send the batches read into `sender` until it is closed.
+    async fn run(&self, sender: &Sender) -> DataFusionResult<()> {
+        let mut conn = self.client.get_multiplexed_async_connection().await.map_err(redis_error)?;
+        match &self.keys {
+            Keys::Hashes(pattern) => self.hashes(&mut conn, pattern, sender).await,
+            Keys::SortedSet(key) => self.sorted_set(&mut conn, key, sender).await,
+        }
+    }
+
+    async fn hashes(
+        &self,
+        conn: &mut MultiplexedConnection,
+        pattern: &str,
+        sender: &Sender,
+    ) -> DataFusionResult<()> {
+        // The hash field of each projected column, `None` for the key.
+        let indices: Vec<usize> = match &self.projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.schema.fields().len()).collect(),
+        };
+        let columns: Vec<Option<String>> = indices
+            .iter()
+            .zip(self.schema.fields())
+            .map(|(&i, field)| (i > 0).then(|| field.name().clone()))
+            .collect();
+        let fields: Vec<_> = columns.iter().flatten().collect();
+        // `SCAN` may return a key more than once.
+        let mut seen = HashSet::new();
+        let (mut cursor, mut rows) = (0_u64, 0);
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(self.batch_size)
+                .arg("TYPE")
+                .arg("hash")
+                .query_async(conn)
+                .await
+                .map_err(redis_error)?;
+            let mut keys: Vec<_> =
+                keys.into_iter().filter(|key| seen.insert(key.clone())).collect();
+            if let Some(limit) = self.limit {
+                keys.truncate(limit - rows);
+            }
+            if !keys.is_empty() {
+                let values: Vec<Vec<Option<String>>> = if fields.is_empty() {
+                    vec![vec![]; keys.len()]
+                } else {
+                    let mut pipeline = redis::pipe();
+                    for key in &keys {
+                        pipeline.cmd("HMGET").arg(key).arg(&fields);
+                    }
+                    pipeline.query_async(conn).await.map_err(redis_error)?
+                };
+                rows += keys.len();
+                let batch = hash_batch(&self.schema, &columns, keys, values)?;
+                if sender.send(Ok(batch)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            cursor = next;
+            if cursor == 0 || self.limit.is_some_and(|limit| rows >= limit) {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn sorted_set(
+        &self,
+        conn: &mut MultiplexedConnection,
+        key: &str,
+        sender: &Sender,
+    ) -> DataFusionResult<()> {
+        let mut start = 0;
+        loop {
+            let mut count = self.batch_size;
+            if let Some(limit) = self.limit {
+                count = count.min(limit - start);
+            }
+            if count == 0 {
+                return Ok(());
+            }
+            let members: Vec<(String, f64)> = redis::cmd("ZRANGE")
+                .arg(key)
+                .arg(start)
+                .arg(start + count - 1)
+                .arg("WITHSCORES")
+                .query_async(conn)
+                .await
+                .map_err(redis_error)?;
+            let read = members.len();
+            if read > 0 {
+                let batch = sorted_set_batch(&self.schema, members)?;
+                if sender.send(Ok(batch)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            if read < count {
+                return Ok(());
+            }
+            start += read;
+        }
+    }
+}
+
+impl PartitionStream for Read {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let read = self.clone();
+        let (sender, receiver) = mpsc::channel(2);
+        tokio::spawn(async move {
+            if let Err(e) = read.run(&sender).await {
+                let _ = sender.send(Err(e)).await;
+            }
+        });
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+/// The rows of the hashes at `keys`, with the `values` of the fields of `columns`.
+fn hash_batch(
+    schema: &SchemaRef,
+    columns: &[Option<String>],
+    keys: Vec<String>,
+    values: Vec<Vec<Option<String>>>,
+) -> DataFusionResult<RecordBatch> {
+    let rows = keys.len();
+    let options = CastOptions { safe: false, ..Default::default() };
+    let mut fields = 0;
+    let arrays = columns
+        .iter()
+        .zip(schema.fields())
+        .map(|(column, field)| {
+            if column.is_none() {
+                return Ok(Arc::new(StringArray::from(keys.clone())) as ArrayRef);
+            }
+            let strings: StringArray = values.iter().map(|row| row[fields].as_deref()).collect();
+            fields += 1;
+            cast_with_options(&strings, field.data_type(), &options).map_err(|e| {
+                DataFusionError::Execution(format!(
+                    "Redis hash field {} is not of type {}: {e}",
+                    field.name(),
+                    field.data_type()
+                ))
+            })
+        })
+        .collect::<DataFusionResult<Vec<_>>>()?;
+    // Batches of scans projecting no column (`count(*)`) have rows all the same.
+    let options = RecordBatchOptions::new().with_row_count(Some(rows));
+    Ok(RecordBatch::try_new_with_options(Arc::clone(schema), arrays, &options)?)
+}
+
+/// The rows of `members` of a sorted set, projected as `schema`.
+fn sorted_set_batch(
+    schema: &SchemaRef,
+    members: Vec<(String, f64)>,
+) -> DataFusionResult<RecordBatch> {
+    let rows = members.len();
+    let (names, scores): (Vec<_>, Vec<_>) = members.into_iter().unzip();
+    let arrays = schema
+        .fields()
+        .iter()
+        .map(|field| match field.name().as_str() {
+            "member" => Arc::new(StringArray::from(names.clone())) as ArrayRef,
+            _ => Arc::new(Float64Array::from(scores.clone())),
+        })
+        .collect();
+    let options = RecordBatchOptions::new().with_row_count(Some(rows));
+    Ok(RecordBatch::try_new_with_options(Arc::clone(schema), arrays, &options)?)
+}
+
+fn redis_error(e: redis::RedisError) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    #[test]
+    fn test_hash_values_are_cast_to_their_columns() {
+        let fields = Schema::new(vec![
+            Field::new("status", DataType::Utf8, true),
+            Field::new("items", DataType::Int64, true),
+        ]);
+        let table = RedisTable::hashes(client("redis://127.0.0.1/").unwrap(), "cart:*", &fields);
+        let schema = table.schema();
+        let columns = [None, Some("status".to_string()), Some("items".to_string())];
+        let keys = vec!["cart:1".to_string(), "cart:2".to_string()];
+        let values = vec![
+            vec![Some("open".to_string()), Some("3".to_string())],
+            vec![None, Some("12".to_string())],
+        ];
+        let batch = hash_batch(&schema, &columns, keys, values).unwrap();
+        assert_eq!(
+            pretty_format_batches(&[batch]).unwrap().to_string(),
+            "+--------+--------+-------+\n\
+             | key    | status | items |\n\
+             +--------+--------+-------+\n\
+             | cart:1 | open   | 3     |\n\
+             | cart:2 |        | 12    |\n\
+             +--------+--------+-------+"
+        );
+
+        let projected = Arc::new(schema.project(&[2]).unwrap());
+        let values = vec![vec![Some("many".to_string())]];
+        let error =
+            hash_batch(&projected, &columns[2..], vec!["cart:3".to_string()], values).unwrap_err();
+        assert!(
+            error.to_string().contains("Redis hash field items is not of type Int64"),
+            "{error}"
+        );
+
+        let counted = Arc::new(schema.project(&[]).unwrap());
+        let batch = hash_batch(&counted, &[], vec!["cart:1".to_string()], vec![vec![]]).unwrap();
+        assert_eq!(batch.num_rows(), 1);
+    }
+
+    #[test]
+    fn test_sorted_set_members_are_rows() {
+        let table = RedisTable::sorted_set(client("redis://127.0.0.1/").unwrap(), "leaderboard");
+        let schema = Arc::new(table.schema().project(&[1, 0]).unwrap());
+        let members = vec![("ada".to_string(), 1.5), ("bob".to_string(), 7.0)];
+        let batch = sorted_set_batch(&schema, members).unwrap();
+        assert_eq!(
+            pretty_format_batches(&[batch]).unwrap().to_string(),
+            "+-------+--------+\n\
+             | score | member |\n\
+             +-------+--------+\n\
+             | 1.5   | ada    |\n\
+             | 7.0   | bob    |\n\
+             +-------+--------+"
+        );
+    }
+}
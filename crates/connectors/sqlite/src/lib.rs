@@ -0,0 +1,24 @@
+//! SQLite tables.
+//!
+//! [`SqliteTable`] reads a table of a local SQLite database file, so it can be joined
+//! with lake data: fixtures of tests and demos, or the state of an embedded
+//! application (see [`table`]):
+//!
+//! ```no_run
+//! # async fn example(ctx: &datafusion::prelude::SessionContext) -> datafusion::error::Result<()> {
+//! use igloo_connector_sqlite::SqliteTable;
+//! use std::sync::Arc;
+//!
+//! let customers = SqliteTable::try_new("app.db", "customers").await?;
+//! ctx.register_table("customers", Arc::new(customers))?;
+//! ctx.sql("SELECT c.name, sum(o.amount) FROM customers c JOIN lake.orders o ON o.customer_id = c.id GROUP BY c.name")
+//!     .await?
+//!     .collect()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod table;
+
+pub use table::SqliteTable;
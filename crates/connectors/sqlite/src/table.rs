@@ -0,0 +1,440 @@
+//! Reading a SQLite table.
+//!
+//! A [`SqliteTable`] knows its columns from `PRAGMA table_info`. Each scan opens the
+//! file read-only and runs one `SELECT` of the projected columns, with the query's
+//! limit, on a blocking thread, sending rows in batches as they are read.
+//!
+//! Columns map to Arrow types by their declared type, as SQLite decides their
+//! affinity: `INT` types to 64-bit integers, `REAL`, `FLOAT`, `DOUBLE`, `NUMERIC` and
+//! `DECIMAL` to 64-bit floats, `BOOL` to booleans, `BLOB` to binary, and everything
+//! else, untyped columns included, to strings. As a column may hold values of any
+//! type, SQLite casts each value to its column's type.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, BinaryBuilder, BooleanBuilder, Float64Builder, Int64Builder, StringBuilder,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::catalog::Session;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::Expr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::{PartitionStream, StreamingTableExec};
+use datafusion::physical_plan::ExecutionPlan;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags};
+use std::any::Any;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// Rows per batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// How a column is read and what it becomes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    Integer,
+    Real,
+    Boolean,
+    Blob,
+    Text,
+}
+
+impl ColumnKind {
+    /// The kind of a column of declared type `declared`.
+    fn new(declared: &str) -> Self {
+        let declared = declared.to_ascii_uppercase();
+        let has = |part: &str| declared.contains(part);
+        if has("INT") {
+            ColumnKind::Integer
+        } else if has("BOOL") {
+            ColumnKind::Boolean
+        } else if ["REAL", "FLOA", "DOUB", "NUMERIC", "DEC"].iter().any(|part| has(part)) {
+            ColumnKind::Real
+        } else if has("BLOB") {
+            ColumnKind::Blob
+        } else {
+            ColumnKind::Text
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            ColumnKind::Integer => DataType::Int64,
+            ColumnKind::Real => DataType::Float64,
+            ColumnKind::Boolean => DataType::Boolean,
+            ColumnKind::Blob => DataType::Binary,
+            ColumnKind::Text => DataType::Utf8,
+        }
+    }
+
+    /// The column in the select list, cast to its type.
+    fn select(&self, name: &str) -> String {
+        let name = quote_ident(name);
+        match self {
+            ColumnKind::Integer | ColumnKind::Boolean => format!("CAST({name} AS INTEGER)"),
+            ColumnKind::Real => format!("CAST({name} AS REAL)"),
+            ColumnKind::Blob => format!("CAST({name} AS BLOB)"),
+            ColumnKind::Text => format!("CAST({name} AS TEXT)"),
+        }
+    }
+}
+
+/// A table of a SQLite database file, see the [module docs](self).
+pub struct SqliteTable {
+    path: PathBuf,
+    table: String,
+    schema: SchemaRef,
+    kinds: Vec<ColumnKind>,
+    batch_size: usize,
+}
+
+impl fmt::Debug for SqliteTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SqliteTable")
+            .field("path", &self.path)
+            .field("table", &self.table)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SqliteTable {
+    /// Discover the columns of `table` of the database file at `path`.
+    pub async fn try_new(path: impl AsRef<Path>, table: &str) -> DataFusionResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let columns = {
+            let (path, table) = (path.clone(), table.to_string());
+            blocking(move || {
+                let conn = open(&path)?;
+                let sql = format!("PRAGMA table_info({})", quote_ident(&table));
+                let mut statement = conn.prepare(&sql).map_err(sqlite_error)?;
+                let columns = statement
+                    .query_map([], |row| {
+                        Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?, row.get(3)?))
+                    })
+                    .map_err(sqlite_error)?;
+                columns.collect::<Result<Vec<(String, String, bool)>, _>>().map_err(sqlite_error)
+            })
+            .await?
+        };
+        if columns.is_empty() {
+            return Err(DataFusionError::Plan(format!(
+                "SQLite table {table} does not exist in {}",
+                path.display()
+            )));
+        }
+        let kinds: Vec<_> =
+            columns.iter().map(|(_, declared, _)| ColumnKind::new(declared)).collect();
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .zip(&kinds)
+                .map(|((name, _, not_null), kind)| Field::new(name, kind.data_type(), !not_null))
+                .collect::<Vec<_>>(),
+        ));
+        Ok(Self { path, table: table.to_string(), schema, kinds, batch_size: DEFAULT_BATCH_SIZE })
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+}
+
+#[async_trait]
+impl TableProvider for SqliteTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        _filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let columns: Vec<usize> = match projection {
+            Some(projection) => projection.clone(),
+            None => (0..self.kinds.len()).collect(),
+        };
+        let schema = Arc::new(self.schema.project(&columns)?);
+        let select: Vec<_> =
+            columns.iter().map(|&i| self.kinds[i].select(self.schema.field(i).name())).collect();
+        // `SELECT count(*)` projects no column.
+        let select = if select.is_empty() { "1".to_string() } else { select.join(", ") };
+        let mut sql = format!("SELECT {select} FROM {}", quote_ident(&self.table));
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+        let query = Query {
+            path: self.path.clone(),
+            sql,
+            schema: Arc::clone(&schema),
+            kinds: columns.iter().map(|&i| self.kinds[i]).collect(),
+            batch_size: self.batch_size,
+        };
+        Ok(Arc::new(StreamingTableExec::try_new(
+            schema,
+            vec![Arc::new(query)],
+            None,
+            None,
+            false,
+            limit,
+        )?))
+    }
+}
+
+/// The `SELECT` of a scan.
+#[derive(Debug)]
+struct Query {
+    path: PathBuf,
+    sql: String,
+    schema: SchemaRef,
+    kinds: Vec<ColumnKind>,
+    batch_size: usize,
+}
+
+impl PartitionStream for Query {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let (path, sql) = (self.path.clone(), self.sql.clone());
+        let (schema, kinds, batch_size) =
+            (Arc::clone(&self.schema), self.kinds.clone(), self.batch_size);
+        // The blocking thread sends batches into the channel until the scan stops
+        // reading.
+        let (sender, receiver) = mpsc::channel(2);
+        tokio::task::spawn_blocking(move || {
+            let read = || {
+                let conn = open(&path)?;
+                let mut statement = conn.prepare(&sql).map_err(sqlite_error)?;
+                let mut rows = statement.query([]).map_err(sqlite_error)?;
+                let mut batch = Batch::new(&kinds, batch_size);
+                while let Some(row) = rows.next().map_err(sqlite_error)? {
+                    for (i, column) in batch.columns.iter_mut().enumerate() {
+                        column.append(row.get_ref(i).map_err(sqlite_error)?)?;
+                    }
+                    batch.rows += 1;
+                    if batch.rows == batch_size {
+                        let full = std::mem::replace(&mut batch, Batch::new(&kinds, batch_size));
+                        if sender.blocking_send(full.finish(&schema)).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                if batch.rows > 0 {
+                    let _ = sender.blocking_send(batch.finish(&schema));
+                }
+                Ok::<_, DataFusionError>(())
+            };
+            if let Err(e) = read() {
+                let _ = sender.blocking_send(Err(e));
+            }
+        });
+        let batches = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|batch| (batch, receiver))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batches))
+    }
+}
+
+/// The rows of a batch read so far.
+struct Batch {
+    columns: Vec<Column>,
+    rows: usize,
+}
+
+impl Batch {
+    fn new(kinds: &[ColumnKind], capacity: usize) -> Self {
+        let columns = kinds
+            .iter()
+            .map(|kind| match kind {
+                ColumnKind::Integer => Column::Integer(Int64Builder::with_capacity(capacity)),
+                ColumnKind::Real => Column::Real(Float64Builder::with_capacity(capacity)),
+                ColumnKind::Boolean => Column::Boolean(BooleanBuilder::with_capacity(capacity)),
+                ColumnKind::Blob => Column::Blob(BinaryBuilder::new()),
+                ColumnKind::Text => Column::Text(StringBuilder::new()),
+            })
+            .collect();
+        Self { columns, rows: 0 }
+    }
+
+    fn finish(self, schema: &SchemaRef) -> DataFusionResult<RecordBatch> {
+        let columns: Vec<ArrayRef> = self
+            .columns
+            .into_iter()
+            .map(|column| match column {
+                Column::Integer(mut builder) => Arc::new(builder.finish()) as ArrayRef,
+                Column::Real(mut builder) => Arc::new(builder.finish()),
+                Column::Boolean(mut builder) => Arc::new(builder.finish()),
+                Column::Blob(mut builder) => Arc::new(builder.finish()),
+                Column::Text(mut builder) => Arc::new(builder.finish()),
+            })
+            .collect();
+        // Batches of scans projecting no column (`count(*)`) have rows all the same.
+        let options = RecordBatchOptions::new().with_row_count(Some(self.rows));
+        Ok(RecordBatch::try_new_with_options(Arc::clone(schema), columns, &options)?)
+    }
+}
+
+enum Column {
+    Integer(Int64Builder),
+    Real(Float64Builder),
+    Boolean(BooleanBuilder),
+    Blob(BinaryBuilder),
+    Text(StringBuilder),
+}
+
+impl Column {
+    /// Append `value`, which SQLite cast to the column's type.
+    fn append(&mut self, value: ValueRef<'_>) -> DataFusionResult<()> {
+        match (self, value) {
+            (Column::Integer(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Integer(builder), ValueRef::Integer(v)) => builder.append_value(v),
+            (Column::Real(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Real(builder), ValueRef::Real(v)) => builder.append_value(v),
+            (Column::Boolean(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Boolean(builder), ValueRef::Integer(v)) => builder.append_value(v != 0),
+            (Column::Blob(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Blob(builder), ValueRef::Blob(v)) => builder.append_value(v),
+            (Column::Text(builder), ValueRef::Null) => builder.append_null(),
+            (Column::Text(builder), ValueRef::Text(v)) => {
+                let text = std::str::from_utf8(v).map_err(|e| {
+                    DataFusionError::Execution(format!("SQLite text is not UTF-8: {e}"))
+                })?;
+                builder.append_value(text)
+            }
+            (_, value) => {
+                return Err(DataFusionError::Execution(format!(
+                    "unexpected SQLite value of type {}",
+                    value.data_type()
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> DataFusionResult<Connection> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    Connection::open_with_flags(path, flags).map_err(sqlite_error)
+}
+
+/// Run `f` on a thread where blocking is fine.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> DataFusionResult<T> + Send + 'static,
+) -> DataFusionResult<T> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| DataFusionError::External(Box::new(e)))?
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sqlite_error(e: rusqlite::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::prelude::SessionContext;
+
+    #[test]
+    fn test_column_kinds() {
+        assert_eq!(ColumnKind::new("BIGINT"), ColumnKind::Integer);
+        assert_eq!(ColumnKind::new("varchar(20)"), ColumnKind::Text);
+        assert_eq!(ColumnKind::new("DECIMAL(10,2)"), ColumnKind::Real);
+        assert_eq!(ColumnKind::new("boolean"), ColumnKind::Boolean);
+        assert_eq!(ColumnKind::new(""), ColumnKind::Text);
+        assert_eq!(ColumnKind::Blob.select("a \"b\""), "CAST(\"a \"\"b\"\"\" AS BLOB)");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_tables_join_with_other_tables() {
+        let path = std::env::temp_dir().join(format!("igloo-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE customers (
+                     id INTEGER PRIMARY KEY, name TEXT NOT NULL, balance REAL, vip BOOLEAN,
+                     note
+                 );
+                 INSERT INTO customers VALUES
+                     (1, 'Ada', 10.5, 1, 'first'), (2, 'Grace', NULL, 0, 42), (3, 'Linus', 7, NULL, NULL);",
+            )
+            .unwrap();
+
+        let table = SqliteTable::try_new(&path, "customers").await.unwrap().with_batch_size(2);
+        let fields: Vec<_> = table
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| (f.name().clone(), f.data_type().clone()))
+            .collect();
+        assert_eq!(fields[2], ("balance".to_string(), DataType::Float64));
+        assert!(!table.schema().field(1).is_nullable());
+
+        let ctx = SessionContext::new();
+        ctx.register_table("customers", Arc::new(table)).unwrap();
+        let orders = RecordBatch::try_from_iter([
+            ("customer_id", Arc::new(Int64Array::from(vec![1, 1, 3])) as ArrayRef),
+            ("item", Arc::new(StringArray::from(vec!["tea", "cake", "pie"]))),
+        ])
+        .unwrap();
+        ctx.register_batch("orders", orders).unwrap();
+        let batches = ctx
+            .sql(
+                "SELECT c.name, c.balance, c.vip, c.note, count(o.item) AS orders
+                 FROM customers c LEFT JOIN orders o ON o.customer_id = c.id
+                 GROUP BY c.name, c.balance, c.vip, c.note ORDER BY c.name",
+            )
+            .await
+            .unwrap()
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            pretty_format_batches(&batches).unwrap().to_string(),
+            "+-------+---------+-------+-------+--------+\n\
+             | name  | balance | vip   | note  | orders |\n\
+             +-------+---------+-------+-------+--------+\n\
+             | Ada   | 10.5    | true  | first | 2      |\n\
+             | Grace |         | false | 42    | 0      |\n\
+             | Linus | 7.0     |       |       | 1      |\n\
+             +-------+---------+-------+-------+--------+"
+        );
+        let batches =
+            ctx.sql("SELECT id FROM customers LIMIT 2").await.unwrap().collect().await.unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 2);
+        let count =
+            ctx.sql("SELECT count(*) FROM customers").await.unwrap().collect().await.unwrap();
+        assert_eq!(
+            pretty_format_batches(&count).unwrap().to_string().lines().nth(3),
+            Some("| 3        |")
+        );
+
+        let error = SqliteTable::try_new(&path, "orders").await.unwrap_err();
+        assert!(error.to_string().contains("SQLite table orders does not exist"), "{error}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1522 @@
+//! Coordinator configuration.
+//!
+//! Settings are layered, each layer overriding the ones before it:
+//!
+//! 1. a TOML file, named by `--config` or else `IGLOO_CONFIG`;
+//! 2. the profile of the file named by `--profile` or else `IGLOO_PROFILE`, if any;
+//! 3. the `IGLOO_*` environment variables of [`ENV_VARS`];
+//! 4. command-line flags: `--set section.key=value` for any setting, and the
+//!    shorthands `--http`, `--pgwire` and `--flight-sql`.
+//!
+//! The result is checked before anything starts: an unknown key, a value of the wrong
+//! type or settings that contradict each other fail with a [`ConfigError`] naming the
+//! setting and where it came from. While the coordinator runs, changes to the file
+//! that take no restart are applied (see [`reload`](crate::reload)).
+//!
+//! ```toml
+//! [server]
+//! flight_addr = "0.0.0.0:50051"
+//! http_addr = "0.0.0.0:8080"
+//! workers = ["worker-1:50052", "worker-2:50052"]
+//!
+//! [logging]
+//! level = "info,igloo_api=debug"
+//! format = "json"
+//!
+//! [catalog]
+//! store = "postgres://igloo@catalog-db/igloo"
+//!
+//! [sources.iceberg]
+//! uri = "https://polaris.example.com/api/catalog"
+//! warehouse = "analytics"
+//! credential = "vault:secret/data/igloo/polaris#credential"
+//! compaction_secs = 3600
+//!
+//! [cache]
+//! catalog_refresh_secs = 30
+//!
+//! [[cdc.kafka]]
+//! proxy = "http://rest-proxy:8082"
+//! topic = "orders"
+//! table = "sales.orders"
+//!
+//! [quality]
+//! interval_secs = 300
+//!
+//! [[quality.checks]]
+//! table = "iceberg.sales.orders"
+//! check = "not_null"
+//! column = "customer_id"
+//!
+//! [[quality.checks]]
+//! table = "iceberg.sales.orders"
+//! check = "freshness"
+//! column = "placed_at"
+//! max_age_secs = 3600
+//!
+//! [retention]
+//! interval_secs = 86400
+//!
+//! [[retention.tables]]
+//! table = "sales.orders"
+//! partition_max_age_days = 730
+//! snapshot_max_age_days = 7
+//! orphan_file_min_age_days = 3
+//!
+//! [limits]
+//! queries_per_minute = 600
+//! admission_slots = 16
+//! priority_principals = { etl = "batch" }
+//!
+//! [limits.resource_classes.reporting]
+//! memory_bytes = 8589934592
+//! cpu_weight = 2
+//!
+//! [joins]
+//! strategy = "auto"
+//! broadcast_max_rows = 1000000
+//!
+//! [scans]
+//! files = 32
+//! max_requests = 128
+//! ```
+//!
+//! Profiles keep the settings of several deployments (`dev`, `staging`, `prod`...) in
+//! one file: `[profiles.NAME]` holds sections like the file's own, whose settings
+//! replace the file's when the profile is selected. Other profiles are ignored.
+//!
+//! ```toml
+//! [catalog]
+//! store = "igloo_catalog.db"
+//!
+//! [profiles.prod.catalog]
+//! store = "postgres://igloo@catalog-db/igloo"
+//!
+//! [profiles.prod.limits]
+//! queries_per_minute = 600
+//! ```
+//!
+//! `--validate-config` checks the configuration and that every source it declares can
+//! be reached, printing a readiness report instead of starting (see
+//! [`readiness`](crate::readiness)).
+//!
+//! Without a file, settings default as documented on each field, so a coordinator
+//! configured by the environment alone starts as it always has.
+//!
+//! Credentials (`catalog.store`, the tokens and credentials of sources, API keys and
+//! the JWT secret) can be secret references instead, `env:NAME`, `file:/path`, or
+//! `vault:path#key` with `[secrets.vault]` configured (see
+//! [`igloo_common::secrets`]). They are resolved by [`Config::resolve_secrets`], after
+//! validation.
+//!
+//! ```toml
+//! [secrets.vault]
+//! addr = "https://vault.example.com:8200"
+//! token = "file:/var/run/secrets/vault-token"
+//! ```
+
+use clap::Parser;
+use igloo_api::quota::Quotas;
+use igloo_common::logging::LogFormat;
+use igloo_common::secrets::{Secrets, VaultProvider};
+use igloo_connector_iceberg::rest::TableIdent;
+use igloo_connector_iceberg::retention::RetentionPolicy;
+use igloo_engine::admission::Priority;
+use igloo_engine::join_strategy::JoinStrategy;
+use igloo_engine::options::IglooOptions;
+use igloo_engine::quality::{Check, QualityCheck};
+use igloo_engine::scan_io::ScanIo;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use toml::{Table, Value};
+
+/// Command-line flags of the coordinator.
+#[derive(Debug, Clone, Default, Parser)]
+#[command(name = "igloo-coordinator", version, about = "Igloo coordinator")]
+pub struct Args {
+    /// TOML configuration file (default: `IGLOO_CONFIG`, if set)
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Apply the `[profiles.NAME]` settings of the configuration file (default:
+    /// `IGLOO_PROFILE`, if set)
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Check the configuration and that its sources can be reached, print a readiness
+    /// report and exit instead of starting
+    #[arg(long)]
+    pub validate_config: bool,
+    /// Override a setting of the configuration file or the environment, e.g.
+    /// `--set server.http_addr=0.0.0.0:8080`
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    /// Serve the HTTP API, on `server.http_addr` or else 127.0.0.1:8080
+    #[arg(long)]
+    pub http: bool,
+    /// Serve the PostgreSQL wire protocol, on `server.pgwire_addr` or else
+    /// 127.0.0.1:5432
+    #[arg(long)]
+    pub pgwire: bool,
+    /// Serve Flight SQL instead of plain Arrow Flight
+    #[arg(long)]
+    pub flight_sql: bool,
+}
+
+impl Args {
+    /// The configuration file, `--config` or else `IGLOO_CONFIG` of `env`.
+    pub fn config_file(&self, env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+        self.config.clone().or_else(|| env("IGLOO_CONFIG").map(PathBuf::from))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("cannot read configuration file {path}: {source}")]
+    Read { path: PathBuf, source: std::io::Error },
+    #[error("invalid configuration file {path}: {message}")]
+    File { path: PathBuf, message: String },
+    #[error("invalid {origin}: {message}")]
+    Override { origin: String, message: String },
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+    #[error("{setting}: {source}")]
+    Secret { setting: String, source: igloo_common::Error },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub logging: LoggingConfig,
+    pub catalog: CatalogConfig,
+    pub sources: SourcesConfig,
+    pub cache: CacheConfig,
+    pub cdc: CdcConfig,
+    pub quality: QualityConfig,
+    pub retention: RetentionConfig,
+    pub limits: LimitsConfig,
+    pub tenants: TenantsConfig,
+    pub auth: AuthConfig,
+    pub audit: AuditConfig,
+    pub secrets: SecretsConfig,
+    pub joins: JoinsConfig,
+    pub scans: ScansConfig,
+    /// Settings by profile, applied over the rest of the file when selected.
+    pub profiles: BTreeMap<String, Table>,
+    /// The profile selected.
+    #[serde(skip)]
+    pub profile: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Where Arrow Flight (or Flight SQL) and worker registration are served.
+    pub flight_addr: SocketAddr,
+    /// Serve Flight SQL instead of plain Arrow Flight.
+    pub flight_sql: bool,
+    /// Serve the HTTP API here, if set.
+    pub http_addr: Option<SocketAddr>,
+    /// Serve the PostgreSQL wire protocol here, if set.
+    pub pgwire_addr: Option<SocketAddr>,
+    /// `host:port` addresses of workers that do not register themselves.
+    pub workers: Vec<String>,
+    /// Times a stage's tasks are run when workers are lost, before the query fails.
+    pub max_task_attempts: Option<usize>,
+    /// Execute queries on this Ballista scheduler instead, planning them here.
+    pub ballista_scheduler: Option<String>,
+    /// Where asynchronous query jobs spool their results (default: a temporary
+    /// directory).
+    pub spool_dir: Option<PathBuf>,
+    /// Jobs run at once.
+    pub job_workers: usize,
+    /// Log ingested rows here until they are committed.
+    pub ingest_wal_dir: Option<PathBuf>,
+    /// Column masking and row-level security policies, as JSON (see
+    /// `igloo_engine::policy`).
+    pub policy_file: Option<PathBuf>,
+    /// TLS for every frontend.
+    pub tls: Option<TlsFiles>,
+    /// How often the configuration file is checked for changes to apply (see
+    /// [`reload`](crate::reload)); `0` turns reloading off.
+    pub reload_secs: u64,
+    /// Read each source's catalog in the background at startup, rather than when a
+    /// query first names one of its tables.
+    pub warm_up_sources: bool,
+    /// On `SIGTERM` or `SIGINT`, how long running queries and CDC commits have to
+    /// finish before the coordinator exits anyway.
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            flight_addr: SocketAddr::from(([127, 0, 0, 1], 50051)),
+            flight_sql: false,
+            http_addr: None,
+            pgwire_addr: None,
+            workers: Vec::new(),
+            max_task_attempts: None,
+            ballista_scheduler: None,
+            spool_dir: None,
+            job_workers: 4,
+            ingest_wal_dir: None,
+            policy_file: None,
+            tls: None,
+            reload_secs: 5,
+            warm_up_sources: true,
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// PEM files.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Require client certificates issued by this CA.
+    pub client_ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Which lines to keep, as an `EnvFilter` directive such as `info,igloo_api=debug`.
+    pub level: String,
+    /// `text` or `json`.
+    pub format: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self { level: "info".to_string(), format: "text".to_string() }
+    }
+}
+
+impl LoggingConfig {
+    pub fn format(&self) -> Result<LogFormat, ConfigError> {
+        self.format.parse().map_err(|e| ConfigError::Invalid(format!("logging.format: {e}")))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogConfig {
+    /// A `postgres://` URL, for coordinators sharing one catalog, or the path of a
+    /// SQLite database.
+    pub store: String,
+}
+
+impl Default for CatalogConfig {
+    fn default() -> Self {
+        Self { store: "igloo_catalog.db".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    /// An Iceberg REST catalog, registered as `iceberg`.
+    pub iceberg: Option<IcebergSource>,
+    /// A Hive Metastore, registered as `hive`.
+    pub hive: Option<HiveSource>,
+    /// A catalog of a Unity Catalog server, registered under its own name.
+    pub unity: Option<UnitySource>,
+    /// A share of a Delta Sharing server, registered as a catalog.
+    pub delta_sharing: Option<DeltaSharingSource>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IcebergSource {
+    pub uri: String,
+    pub warehouse: Option<String>,
+    /// Bearer token.
+    pub token: Option<String>,
+    /// OAuth client credentials, `client_id:client_secret`.
+    pub credential: Option<String>,
+    /// Compact the small files of the catalog's tables this often, if set.
+    pub compaction_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HiveSource {
+    /// `host:port` of the metastore.
+    pub metastore: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnitySource {
+    pub uri: String,
+    /// Bearer token.
+    pub token: Option<String>,
+    /// The catalog to register.
+    #[serde(default = "default_unity_catalog")]
+    pub name: String,
+}
+
+fn default_unity_catalog() -> String {
+    "unity".to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeltaSharingSource {
+    /// The path of the recipient's profile file (`config.share`), naming the server and
+    /// its bearer token.
+    pub profile: PathBuf,
+    /// The share to register.
+    pub share: String,
+    /// The catalog the share is registered as, the share's name unless set.
+    pub name: Option<String>,
+}
+
+impl DeltaSharingSource {
+    pub fn catalog(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.share)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// How often the tables and views cached from the catalog store are refreshed,
+    /// picking up those other coordinators created.
+    pub catalog_refresh_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self { catalog_refresh_secs: 10 }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CdcConfig {
+    /// Kafka topics continuously ingested into tables of the Iceberg source.
+    pub kafka: Vec<KafkaPipeline>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaPipeline {
+    /// URL of the Confluent REST Proxy.
+    pub proxy: String,
+    pub topic: String,
+    /// `namespace.table` in the Iceberg source.
+    pub table: String,
+    /// Consumer group (default: named after the table).
+    pub group: Option<String>,
+    /// Schema Registry URL, for Avro records; JSON records without one.
+    pub schema_registry: Option<String>,
+    pub commit_interval_secs: Option<u64>,
+    /// Commit once this many records are buffered, before the interval is over.
+    pub max_records: Option<usize>,
+}
+
+/// Data quality checks run against registered tables (see `igloo_engine::quality`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualityConfig {
+    /// How often every check runs.
+    pub interval_secs: u64,
+    pub checks: Vec<QualityCheckConfig>,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self { interval_secs: 300, checks: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QualityCheckConfig {
+    /// The table checked, as queries name it.
+    pub table: String,
+    /// `not_null`, `unique`, `range`, `row_count_delta` or `freshness`.
+    pub check: String,
+    /// The column of `not_null`, `range` and `freshness` checks, or the one column of
+    /// a `unique` check.
+    pub column: Option<String>,
+    /// The columns of a `unique` check, whose values together must be unique.
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// Bounds of a `range` check, as SQL literals: `0`, `'2024-01-01'`...
+    pub min: Option<String>,
+    pub max: Option<String>,
+    /// How much the rows of a `row_count_delta` check may change from one run to the
+    /// next, as a fraction of them: 0.1 for 10%.
+    pub max_change: Option<f64>,
+    /// How old the latest value of a `freshness` check may be.
+    pub max_age_secs: Option<u64>,
+}
+
+impl QualityConfig {
+    /// The engine's checks, once validated.
+    pub fn checks(&self) -> Result<Vec<QualityCheck>, ConfigError> {
+        self.checks.iter().map(QualityCheckConfig::check).collect()
+    }
+}
+
+/// Retention policies of tables of the Iceberg source (see
+/// `igloo_connector_iceberg::retention`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// How often every policy is applied.
+    pub interval_secs: u64,
+    /// Only log what the policies would drop, expire and delete.
+    pub dry_run: bool,
+    pub tables: Vec<RetentionTableConfig>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self { interval_secs: 24 * 3600, dry_run: false, tables: Vec::new() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionTableConfig {
+    /// The table, `namespace.table` in the Iceberg source.
+    pub table: String,
+    /// Drop partitions whose time ended longer ago than this.
+    pub partition_max_age_days: Option<u64>,
+    /// Expire snapshots older than this, but the current one and the latest
+    /// `min_snapshots`.
+    pub snapshot_max_age_days: Option<u64>,
+    pub min_snapshots: Option<usize>,
+    /// Delete files no snapshot references once older than this.
+    pub orphan_file_min_age_days: Option<u64>,
+}
+
+impl RetentionConfig {
+    /// The policy of each table, once validated.
+    pub fn policies(&self) -> Result<Vec<(TableIdent, RetentionPolicy)>, ConfigError> {
+        self.tables.iter().map(RetentionTableConfig::policy).collect()
+    }
+}
+
+impl RetentionTableConfig {
+    fn policy(&self) -> Result<(TableIdent, RetentionPolicy), ConfigError> {
+        let Some((namespace, name)) = self.table.rsplit_once('.') else {
+            return Err(ConfigError::Invalid(format!(
+                "retention.tables: table '{}' must be namespace.table",
+                self.table
+            )));
+        };
+        let ages = [
+            self.partition_max_age_days,
+            self.snapshot_max_age_days,
+            self.orphan_file_min_age_days,
+        ];
+        if ages.iter().all(Option::is_none) {
+            return Err(ConfigError::Invalid(format!(
+                "retention.tables: the policy of {} needs partition_max_age_days, snapshot_max_age_days or orphan_file_min_age_days",
+                self.table
+            )));
+        }
+        let days = |days: u64| Duration::from_secs(days * 24 * 3600);
+        let mut policy = RetentionPolicy::default();
+        if let Some(age) = self.partition_max_age_days {
+            policy = policy.with_partition_max_age(days(age));
+        }
+        if let Some(age) = self.snapshot_max_age_days {
+            policy = policy.with_snapshot_max_age(days(age));
+        }
+        if let Some(min_snapshots) = self.min_snapshots {
+            policy = policy.with_min_snapshots(min_snapshots);
+        }
+        if let Some(age) = self.orphan_file_min_age_days {
+            policy = policy.with_orphan_file_min_age(days(age));
+        }
+        let ident = TableIdent {
+            namespace: namespace.split('.').map(str::to_string).collect(),
+            name: name.to_string(),
+        };
+        Ok((ident, policy))
+    }
+}
+
+impl QualityCheckConfig {
+    fn check(&self) -> Result<QualityCheck, ConfigError> {
+        let needs = |setting: &str| {
+            ConfigError::Invalid(format!(
+                "quality.checks: the {} check of {} needs {setting}",
+                self.check, self.table
+            ))
+        };
+        let column = || self.column.clone().ok_or_else(|| needs("column"));
+        let check = match self.check.as_str() {
+            "not_null" => Check::NotNull { column: column()? },
+            "unique" => {
+                let mut columns = self.columns.clone();
+                columns.extend(self.column.clone());
+                if columns.is_empty() {
+                    return Err(needs("column or columns"));
+                }
+                Check::Unique { columns }
+            }
+            "range" => {
+                if self.min.is_none() && self.max.is_none() {
+                    return Err(needs("min or max"));
+                }
+                Check::Range { column: column()?, min: self.min.clone(), max: self.max.clone() }
+            }
+            "row_count_delta" => Check::RowCountDelta {
+                max_change: self.max_change.ok_or_else(|| needs("max_change"))?,
+            },
+            "freshness" => Check::Freshness {
+                column: column()?,
+                max_age: Duration::from_secs(
+                    self.max_age_secs.ok_or_else(|| needs("max_age_secs"))?,
+                ),
+            },
+            other => {
+                return Err(ConfigError::Invalid(format!(
+                    "quality.checks: unknown check '{other}' of {}, expected not_null, unique, range, row_count_delta or freshness",
+                    self.table
+                )))
+            }
+        };
+        Ok(QualityCheck::new(self.table.clone(), check))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Per principal.
+    pub queries_per_minute: Option<u32>,
+    /// Per principal.
+    pub concurrent_queries: Option<u32>,
+    /// Per principal.
+    pub scanned_bytes_per_day: Option<u64>,
+    /// Queries running at once, by priority class.
+    pub admission_slots: Option<usize>,
+    /// Priority class principals are capped at, by subject.
+    pub priority_principals: BTreeMap<String, String>,
+    /// Per-query budgets by class; queries of principals without a class run in
+    /// `default`.
+    pub resource_classes: BTreeMap<String, ResourceClassConfig>,
+    /// Resource class by subject.
+    pub resource_principals: BTreeMap<String, String>,
+    /// Queries producing batches at once (default: the number of CPUs).
+    pub cpu_slots: Option<usize>,
+}
+
+impl LimitsConfig {
+    /// The per-principal limits.
+    pub fn quotas(&self) -> Quotas {
+        let mut quotas = Quotas::new();
+        if let Some(limit) = self.queries_per_minute {
+            quotas = quotas.with_queries_per_minute(limit);
+        }
+        if let Some(limit) = self.concurrent_queries {
+            quotas = quotas.with_concurrent_queries(limit);
+        }
+        if let Some(limit) = self.scanned_bytes_per_day {
+            quotas = quotas.with_scanned_bytes_per_day(limit);
+        }
+        quotas
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        self.queries_per_minute.is_some()
+            || self.concurrent_queries.is_some()
+            || self.scanned_bytes_per_day.is_some()
+            || self.admission_slots.is_some()
+    }
+}
+
+/// How hash joins move their inputs by default, which sessions override with `SET
+/// igloo.join_strategy` and so on (see `igloo_engine::join_strategy`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JoinsConfig {
+    /// `auto`, `broadcast` or `partitioned`.
+    pub strategy: String,
+    /// Estimated rows up to which `auto` broadcasts a join side.
+    pub broadcast_max_rows: usize,
+    /// Estimated bytes up to which `auto` broadcasts a join side.
+    pub broadcast_max_bytes: usize,
+}
+
+impl Default for JoinsConfig {
+    fn default() -> Self {
+        let options = IglooOptions::default();
+        Self {
+            strategy: options.join_strategy.to_string(),
+            broadcast_max_rows: options.broadcast_max_rows,
+            broadcast_max_bytes: options.broadcast_max_bytes,
+        }
+    }
+}
+
+impl JoinsConfig {
+    /// The engine's options, once validated.
+    pub fn options(&self) -> Result<IglooOptions, ConfigError> {
+        let mut options = IglooOptions::default();
+        options.join_strategy = self
+            .strategy
+            .parse::<JoinStrategy>()
+            .map_err(|e| ConfigError::Invalid(format!("joins.strategy: {e}")))?;
+        options.broadcast_max_rows = self.broadcast_max_rows;
+        options.broadcast_max_bytes = self.broadcast_max_bytes;
+        Ok(options)
+    }
+}
+
+/// How file scans read their files (see `igloo_engine::scan_io`).
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScansConfig {
+    /// Files a scan reads at once; unset, each partition of a scan reads its files
+    /// one after the next and the other settings are ignored.
+    pub files: Option<usize>,
+    /// Batches each partition of a scan reads ahead.
+    pub prefetch_batches: usize,
+    /// Requests in flight per object store.
+    pub max_requests: usize,
+}
+
+impl Default for ScansConfig {
+    fn default() -> Self {
+        let io = ScanIo::default();
+        Self { files: None, prefetch_batches: io.prefetch_batches, max_requests: io.max_requests }
+    }
+}
+
+impl ScansConfig {
+    /// The engine's settings, if scans read their files in parallel.
+    pub fn scan_io(&self) -> Option<ScanIo> {
+        let files = self.files?;
+        Some(ScanIo {
+            files,
+            prefetch_batches: self.prefetch_batches,
+            max_requests: self.max_requests,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceClassConfig {
+    pub memory_bytes: Option<usize>,
+    pub cpu_weight: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantsConfig {
+    pub names: Vec<String>,
+    /// Memory each tenant's queries may use together.
+    pub memory_limit_bytes: Option<usize>,
+    pub statement_timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// `subject` or `subject@tenant`, by API key.
+    pub api_keys: BTreeMap<String, String>,
+    /// HS256 secret of accepted JWTs.
+    pub jwt_secret: Option<String>,
+    pub jwt_issuer: Option<String>,
+    pub jwt_audience: Option<String>,
+    /// Claim holding the tenant of a JWT's principal.
+    pub jwt_tenant_claim: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Append audit records to this JSON lines file.
+    pub log: Option<PathBuf>,
+    /// Record statement text.
+    pub sql: bool,
+    /// Record statements running at least this long as slow.
+    pub slow_query_ms: Option<u64>,
+    /// Append slow statements to this JSON lines file rather than
+    /// `igloo.system.slow_queries`.
+    pub slow_query_log: Option<PathBuf>,
+    /// Persist the query history in this file.
+    pub query_history: Option<PathBuf>,
+    /// Statements kept in `igloo.system.query_history`; `0` turns it off.
+    pub query_history_max: usize,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            log: None,
+            sql: true,
+            slow_query_ms: None,
+            slow_query_log: None,
+            query_history: None,
+            query_history_max: igloo_api::query_history::DEFAULT_MAX_QUERIES,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    /// Resolve `vault:` references with this Vault server.
+    pub vault: Option<VaultConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+    pub addr: String,
+    /// Itself an `env:` or `file:` reference, or the token.
+    pub token: String,
+}
+
+/// How an environment variable's value becomes a setting.
+#[derive(Debug, Clone, Copy)]
+enum EnvValue {
+    String,
+    Integer,
+    Boolean,
+    /// Comma-separated.
+    List,
+    /// Comma-separated `key=value` pairs.
+    Pairs,
+    /// Comma-separated `name:memory_bytes:cpu_weight` entries, either number may be
+    /// empty.
+    ResourceClasses,
+}
+
+/// The environment variables overriding settings, by the setting they override. Of
+/// two variables for one setting, the later wins.
+pub const ENV_VARS: &[(&str, &str)] = &[
+    ("RUST_LOG", "logging.level"),
+    ("IGLOO_LOG", "logging.level"),
+    ("IGLOO_LOG_FORMAT", "logging.format"),
+    ("IGLOO_WORKERS", "server.workers"),
+    ("IGLOO_MAX_TASK_ATTEMPTS", "server.max_task_attempts"),
+    ("IGLOO_BALLISTA_SCHEDULER", "server.ballista_scheduler"),
+    ("IGLOO_SPOOL_DIR", "server.spool_dir"),
+    ("IGLOO_JOB_WORKERS", "server.job_workers"),
+    ("IGLOO_INGEST_WAL_DIR", "server.ingest_wal_dir"),
+    ("IGLOO_POLICY_FILE", "server.policy_file"),
+    ("IGLOO_CONFIG_RELOAD_SECS", "server.reload_secs"),
+    ("IGLOO_WARM_UP_SOURCES", "server.warm_up_sources"),
+    ("IGLOO_SHUTDOWN_TIMEOUT_SECS", "server.shutdown_timeout_secs"),
+    ("IGLOO_TLS_CERT", "server.tls.cert"),
+    ("IGLOO_TLS_KEY", "server.tls.key"),
+    ("IGLOO_TLS_CLIENT_CA", "server.tls.client_ca"),
+    ("IGLOO_CATALOG_STORE", "catalog.store"),
+    ("IGLOO_ICEBERG_REST_URI", "sources.iceberg.uri"),
+    ("IGLOO_ICEBERG_WAREHOUSE", "sources.iceberg.warehouse"),
+    ("IGLOO_ICEBERG_TOKEN", "sources.iceberg.token"),
+    ("IGLOO_ICEBERG_CREDENTIAL", "sources.iceberg.credential"),
+    ("IGLOO_ICEBERG_COMPACTION_SECS", "sources.iceberg.compaction_secs"),
+    ("IGLOO_HIVE_METASTORE", "sources.hive.metastore"),
+    ("IGLOO_UNITY_CATALOG_URI", "sources.unity.uri"),
+    ("IGLOO_UNITY_CATALOG_TOKEN", "sources.unity.token"),
+    ("IGLOO_UNITY_CATALOG_NAME", "sources.unity.name"),
+    ("IGLOO_DELTA_SHARING_PROFILE", "sources.delta_sharing.profile"),
+    ("IGLOO_DELTA_SHARING_SHARE", "sources.delta_sharing.share"),
+    ("IGLOO_DELTA_SHARING_NAME", "sources.delta_sharing.name"),
+    ("IGLOO_CATALOG_REFRESH_SECS", "cache.catalog_refresh_secs"),
+    ("IGLOO_RETENTION_INTERVAL_SECS", "retention.interval_secs"),
+    ("IGLOO_RETENTION_DRY_RUN", "retention.dry_run"),
+    ("IGLOO_QUOTA_QUERIES_PER_MINUTE", "limits.queries_per_minute"),
+    ("IGLOO_QUOTA_CONCURRENT_QUERIES", "limits.concurrent_queries"),
+    ("IGLOO_QUOTA_SCANNED_BYTES_PER_DAY", "limits.scanned_bytes_per_day"),
+    ("IGLOO_ADMISSION_SLOTS", "limits.admission_slots"),
+    ("IGLOO_PRIORITY_PRINCIPALS", "limits.priority_principals"),
+    ("IGLOO_RESOURCE_CLASSES", "limits.resource_classes"),
+    ("IGLOO_RESOURCE_PRINCIPALS", "limits.resource_principals"),
+    ("IGLOO_CPU_SLOTS", "limits.cpu_slots"),
+    ("IGLOO_JOIN_STRATEGY", "joins.strategy"),
+    ("IGLOO_BROADCAST_MAX_ROWS", "joins.broadcast_max_rows"),
+    ("IGLOO_BROADCAST_MAX_BYTES", "joins.broadcast_max_bytes"),
+    ("IGLOO_SCAN_FILES", "scans.files"),
+    ("IGLOO_SCAN_PREFETCH_BATCHES", "scans.prefetch_batches"),
+    ("IGLOO_SCAN_MAX_REQUESTS", "scans.max_requests"),
+    ("IGLOO_TENANTS", "tenants.names"),
+    ("IGLOO_TENANT_MEMORY_LIMIT", "tenants.memory_limit_bytes"),
+    ("IGLOO_TENANT_STATEMENT_TIMEOUT_MS", "tenants.statement_timeout_ms"),
+    ("IGLOO_API_KEYS", "auth.api_keys"),
+    ("IGLOO_JWT_SECRET", "auth.jwt_secret"),
+    ("IGLOO_JWT_ISSUER", "auth.jwt_issuer"),
+    ("IGLOO_JWT_AUDIENCE", "auth.jwt_audience"),
+    ("IGLOO_JWT_TENANT_CLAIM", "auth.jwt_tenant_claim"),
+    ("IGLOO_AUDIT_LOG", "audit.log"),
+    ("IGLOO_AUDIT_SQL", "audit.sql"),
+    ("IGLOO_SLOW_QUERY_MS", "audit.slow_query_ms"),
+    ("IGLOO_SLOW_QUERY_LOG", "audit.slow_query_log"),
+    ("IGLOO_QUERY_HISTORY", "audit.query_history"),
+    ("IGLOO_QUERY_HISTORY_MAX", "audit.query_history_max"),
+    ("IGLOO_VAULT_ADDR", "secrets.vault.addr"),
+    ("IGLOO_VAULT_TOKEN", "secrets.vault.token"),
+];
+
+fn env_value(path: &str) -> EnvValue {
+    match path {
+        "server.workers" | "tenants.names" => EnvValue::List,
+        "limits.priority_principals" | "limits.resource_principals" | "auth.api_keys" => {
+            EnvValue::Pairs
+        }
+        "limits.resource_classes" => EnvValue::ResourceClasses,
+        "audit.sql" | "server.warm_up_sources" | "retention.dry_run" => EnvValue::Boolean,
+        "server.max_task_attempts"
+        | "server.job_workers"
+        | "server.reload_secs"
+        | "server.shutdown_timeout_secs"
+        | "sources.iceberg.compaction_secs"
+        | "cache.catalog_refresh_secs"
+        | "retention.interval_secs"
+        | "limits.queries_per_minute"
+        | "limits.concurrent_queries"
+        | "limits.scanned_bytes_per_day"
+        | "limits.admission_slots"
+        | "limits.cpu_slots"
+        | "joins.broadcast_max_rows"
+        | "joins.broadcast_max_bytes"
+        | "scans.files"
+        | "scans.prefetch_batches"
+        | "scans.max_requests"
+        | "tenants.memory_limit_bytes"
+        | "tenants.statement_timeout_ms"
+        | "audit.slow_query_ms"
+        | "audit.query_history_max" => EnvValue::Integer,
+        _ => EnvValue::String,
+    }
+}
+
+impl Config {
+    /// The configuration of `args`, over that of the process's environment, over
+    /// that of the configuration file.
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        Self::load_with(args, |name| std::env::var(name).ok())
+    }
+
+    /// [`Config::load`] with the environment variables of `env`.
+    pub fn load_with(
+        args: &Args,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let path = args.config_file(&env);
+        let file = match &path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|source| ConfigError::Read { path: path.clone(), source })?;
+                let table = text.parse::<Table>().map_err(|e| ConfigError::File {
+                    path: path.clone(),
+                    message: e.to_string(),
+                })?;
+                Some((path.as_path(), text, table))
+            }
+            None => None,
+        };
+        // Every override as (where it came from, setting, value)
+        let mut overrides = Vec::new();
+        for (name, key) in ENV_VARS {
+            let Some(value) = env(name).filter(|value| !value.is_empty()) else {
+                continue;
+            };
+            let value = parse_env(&value, env_value(key))
+                .map_err(|message| ConfigError::Override { origin: name.to_string(), message })?;
+            overrides.push((name.to_string(), key.to_string(), value));
+        }
+        for arg in &args.overrides {
+            let origin = format!("--set {arg}");
+            let Some((key, value)) = arg.split_once('=') else {
+                let message = "expected KEY=VALUE".to_string();
+                return Err(ConfigError::Override { origin, message });
+            };
+            overrides.push((origin, key.trim().to_string(), parse_flag(value.trim())));
+        }
+        let mut settings = file.as_ref().map(|(_, _, table)| table.clone()).unwrap_or_default();
+        let selected = args.profile.clone().or_else(|| env("IGLOO_PROFILE"));
+        let profile = match (&selected, &file) {
+            (None, _) => None,
+            (Some(name), None) => {
+                return Err(ConfigError::Invalid(format!(
+                    "profile '{name}' is selected, but there is no configuration file"
+                )))
+            }
+            (Some(name), Some((path, _, table))) => {
+                let layer = profile_settings(table, name)
+                    .map_err(|message| ConfigError::File { path: path.to_path_buf(), message })?;
+                merge(&mut settings, &layer);
+                Some((name.as_str(), layer))
+            }
+        };
+        for (origin, key, value) in &overrides {
+            set(&mut settings, key, value.clone())
+                .map_err(|message| ConfigError::Override { origin: origin.clone(), message })?;
+        }
+        let mut config = match Config::from_settings(settings) {
+            Ok(config) => config,
+            Err(message) => return Err(blame(file, profile, &overrides, message)),
+        };
+        config.profile = selected.clone();
+        let local = |port| Some(SocketAddr::from(([127, 0, 0, 1], port)));
+        if args.http && config.server.http_addr.is_none() {
+            config.server.http_addr = local(8080);
+        }
+        if args.pgwire && config.server.pgwire_addr.is_none() {
+            config.server.pgwire_addr = local(5432);
+        }
+        config.server.flight_sql |= args.flight_sql;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The providers secret references are resolved with.
+    pub async fn secrets(&self) -> Result<Secrets, ConfigError> {
+        let mut secrets = Secrets::new();
+        if let Some(vault) = &self.secrets.vault {
+            let token = secrets.resolve(&vault.token).await.map_err(|source| {
+                ConfigError::Secret { setting: "secrets.vault.token".to_string(), source }
+            })?;
+            secrets =
+                secrets.with_provider("vault", Arc::new(VaultProvider::new(&vault.addr, token)));
+        }
+        Ok(secrets)
+    }
+
+    /// Replace the secret references among the credentials with the secrets.
+    pub async fn resolve_secrets(&mut self, secrets: &Secrets) -> Result<(), ConfigError> {
+        let mut credentials = vec![("catalog.store", &mut self.catalog.store)];
+        if let Some(iceberg) = &mut self.sources.iceberg {
+            credentials.extend(iceberg.token.as_mut().map(|v| ("sources.iceberg.token", v)));
+            let credential = iceberg.credential.as_mut();
+            credentials.extend(credential.map(|v| ("sources.iceberg.credential", v)));
+        }
+        if let Some(unity) = &mut self.sources.unity {
+            credentials.extend(unity.token.as_mut().map(|v| ("sources.unity.token", v)));
+        }
+        credentials.extend(self.auth.jwt_secret.as_mut().map(|v| ("auth.jwt_secret", v)));
+        for (setting, value) in credentials {
+            *value = resolve(secrets, setting, value).await?;
+        }
+        let mut api_keys = BTreeMap::new();
+        for (key, subject) in std::mem::take(&mut self.auth.api_keys) {
+            api_keys.insert(resolve(secrets, "auth.api_keys", &key).await?, subject);
+        }
+        self.auth.api_keys = api_keys;
+        Ok(())
+    }
+
+    fn from_settings(settings: Table) -> Result<Self, String> {
+        Value::Table(settings).try_into().map_err(|e: toml::de::Error| e.message().to_string())
+    }
+
+    /// Check the settings that deserializing cannot.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+        self.logging.format()?;
+        let server = &self.server;
+        let addrs = [Some(server.flight_addr), server.http_addr, server.pgwire_addr];
+        let addrs: Vec<_> = addrs.into_iter().flatten().collect();
+        if let Some(addr) =
+            addrs.iter().enumerate().find_map(|(i, a)| addrs[..i].contains(a).then_some(a))
+        {
+            return invalid(format!("{addr} is configured for two of server.flight_addr, server.http_addr and server.pgwire_addr"));
+        }
+        if server.job_workers == 0 {
+            return invalid("server.job_workers must be at least 1".to_string());
+        }
+        if let Some(iceberg) = &self.sources.iceberg {
+            if iceberg.token.is_some() && iceberg.credential.is_some() {
+                return invalid(
+                    "sources.iceberg: set either token or credential, not both".to_string(),
+                );
+            }
+        }
+        for pipeline in &self.cdc.kafka {
+            if self.sources.iceberg.is_none() {
+                return invalid(format!(
+                    "cdc.kafka: topic {} is ingested into the Iceberg source, but sources.iceberg is not configured",
+                    pipeline.topic
+                ));
+            }
+            if !pipeline.table.contains('.') {
+                return invalid(format!(
+                    "cdc.kafka: table '{}' of topic {} must be namespace.table",
+                    pipeline.table, pipeline.topic
+                ));
+            }
+        }
+        self.quality.checks()?;
+        if !self.quality.checks.is_empty() && self.quality.interval_secs == 0 {
+            return invalid("quality.interval_secs must be at least 1".to_string());
+        }
+        self.retention.policies()?;
+        if !self.retention.tables.is_empty() {
+            if self.sources.iceberg.is_none() {
+                return invalid(
+                    "retention.tables apply to the Iceberg source, but sources.iceberg is not configured"
+                        .to_string(),
+                );
+            }
+            if self.retention.interval_secs == 0 {
+                return invalid("retention.interval_secs must be at least 1".to_string());
+            }
+        }
+        let limits = &self.limits;
+        if limits.concurrent_queries == Some(0) || limits.admission_slots == Some(0) {
+            return invalid(
+                "limits.concurrent_queries and limits.admission_slots must be at least 1"
+                    .to_string(),
+            );
+        }
+        for (subject, priority) in &limits.priority_principals {
+            if let Err(e) = priority.parse::<Priority>() {
+                return invalid(format!("limits.priority_principals.{subject}: {e}"));
+            }
+        }
+        for (subject, class) in &limits.resource_principals {
+            if class != "default" && !limits.resource_classes.contains_key(class) {
+                return invalid(format!(
+                    "limits.resource_principals.{subject}: no resource class '{class}' in limits.resource_classes"
+                ));
+            }
+        }
+        if !limits.resource_principals.is_empty() && limits.resource_classes.is_empty() {
+            return invalid("limits.resource_principals needs limits.resource_classes".to_string());
+        }
+        self.joins.options()?;
+        if self.scans.files == Some(0) || self.scans.max_requests == 0 {
+            return invalid("scans.files and scans.max_requests must be at least 1".to_string());
+        }
+        for (key, subject) in &self.auth.api_keys {
+            if key.is_empty() || subject.trim().is_empty() || subject.ends_with('@') {
+                return invalid(
+                    "auth.api_keys: every key needs a subject, or subject@tenant".to_string(),
+                );
+            }
+        }
+        let jwt = [&self.auth.jwt_issuer, &self.auth.jwt_audience, &self.auth.jwt_tenant_claim];
+        if self.auth.jwt_secret.is_none() && jwt.iter().any(|setting| setting.is_some()) {
+            return invalid("auth.jwt_* settings need auth.jwt_secret".to_string());
+        }
+        let audit = &self.audit;
+        if audit.slow_query_log.is_some() && audit.slow_query_ms.is_none() {
+            return invalid("audit.slow_query_log needs audit.slow_query_ms".to_string());
+        }
+        if audit.query_history.is_some() && audit.query_history_max == 0 {
+            return invalid(
+                "audit.query_history is set, but audit.query_history_max = 0 turns it off"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+async fn resolve(secrets: &Secrets, setting: &str, value: &str) -> Result<String, ConfigError> {
+    secrets
+        .resolve(value)
+        .await
+        .map_err(|source| ConfigError::Secret { setting: setting.to_string(), source })
+}
+
+/// The error of the first layer of settings that does not deserialize: the file, its
+/// profile, or else the override that broke it.
+fn blame(
+    file: Option<(&Path, String, Table)>,
+    profile: Option<(&str, Table)>,
+    overrides: &[(String, String, Value)],
+    message: String,
+) -> ConfigError {
+    let mut settings = Table::new();
+    if let Some((path, text, table)) = file {
+        // Parsing the text again, for an error pointing at the line
+        if let Err(e) = toml::from_str::<Config>(&text) {
+            return ConfigError::File { path: path.to_path_buf(), message: e.to_string() };
+        }
+        settings = table;
+        if let Some((name, layer)) = profile {
+            merge(&mut settings, &layer);
+            if let Err(message) = Config::from_settings(settings.clone()) {
+                let message = format!("profile '{name}': {message}");
+                return ConfigError::File { path: path.to_path_buf(), message };
+            }
+        }
+    }
+    for (origin, key, value) in overrides {
+        let _ = set(&mut settings, key, value.clone());
+        if let Err(message) = Config::from_settings(settings.clone()) {
+            return ConfigError::Override { origin: origin.clone(), message };
+        }
+    }
+    ConfigError::Invalid(message)
+}
+
+/// The settings of the profile `name` of the file `table`.
+fn profile_settings(table: &Table, name: &str) -> Result<Table, String> {
+    let profiles = table.get("profiles").and_then(Value::as_table);
+    match profiles.and_then(|profiles| profiles.get(name)) {
+        Some(Value::Table(settings)) => Ok(settings.clone()),
+        Some(_) => Err(format!("profiles.{name} is not a section")),
+        None => {
+            let names: Vec<_> = profiles.into_iter().flat_map(|p| p.keys().cloned()).collect();
+            if names.is_empty() {
+                return Err(format!("no profile '{name}'; the file defines none"));
+            }
+            Err(format!("no profile '{name}'; the file defines {}", names.join(", ")))
+        }
+    }
+}
+
+/// Replace the settings of `settings` with those of `layer`, section by section.
+fn merge(settings: &mut Table, layer: &Table) {
+    for (key, value) in layer {
+        match (settings.get_mut(key), value) {
+            (Some(Value::Table(section)), Value::Table(layer)) => merge(section, layer),
+            _ => {
+                settings.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+fn parse_env(value: &str, kind: EnvValue) -> Result<Value, String> {
+    let items = || value.split(',').map(str::trim).filter(|item| !item.is_empty());
+    let pairs = |separator: char| {
+        items()
+            .map(|item| {
+                let (key, value) = item.split_once(separator).ok_or_else(|| {
+                    format!("expected comma-separated KEY{separator}VALUE pairs, got '{item}'")
+                })?;
+                Ok((key.trim().to_string(), value.trim()))
+            })
+            .collect::<Result<Vec<_>, String>>()
+    };
+    Ok(match kind {
+        EnvValue::String => Value::String(value.to_string()),
+        EnvValue::Integer => Value::Integer(
+            value.trim().parse().map_err(|_| format!("expected a number, got '{value}'"))?,
+        ),
+        EnvValue::Boolean => match value.trim().to_ascii_lowercase().as_str() {
+            "true" | "1" => Value::Boolean(true),
+            "false" | "0" => Value::Boolean(false),
+            _ => return Err(format!("expected true or false, got '{value}'")),
+        },
+        EnvValue::List => {
+            Value::Array(items().map(|item| Value::String(item.to_string())).collect())
+        }
+        EnvValue::Pairs => Value::Table(
+            pairs('=')?.into_iter().map(|(k, v)| (k, Value::String(v.to_string()))).collect(),
+        ),
+        EnvValue::ResourceClasses => {
+            let mut classes = Table::new();
+            for entry in items() {
+                let mut parts = entry.split(':').map(str::trim);
+                let name = parts.next().unwrap_or_default().to_string();
+                let mut class = Table::new();
+                for key in ["memory_bytes", "cpu_weight"] {
+                    if let Some(number) = parts.next().filter(|part| !part.is_empty()) {
+                        let number = number.parse().map_err(|_| {
+                            format!("expected NAME:MEMORY_BYTES:CPU_WEIGHT entries, got '{entry}'")
+                        })?;
+                        class.insert(key.to_string(), Value::Integer(number));
+                    }
+                }
+                classes.insert(name, Value::Table(class));
+            }
+            Value::Table(classes)
+        }
+    })
+}
+
+/// A `--set` value: a TOML value (`8`, `true`, `["a", "b"]`), or else a string.
+fn parse_flag(value: &str) -> Value {
+    match format!("value = {value}").parse::<Table>() {
+        Ok(mut table) => table.remove("value").unwrap_or_else(|| Value::String(value.to_string())),
+        Err(_) => Value::String(value.to_string()),
+    }
+}
+
+/// Set the setting at `path` (`section.key`, or deeper) to `value`, creating tables
+/// on the way.
+fn set(settings: &mut Table, path: &str, value: Value) -> Result<(), String> {
+    let mut keys: Vec<_> = path.split('.').collect();
+    let last = keys.pop().filter(|key| !key.is_empty()).ok_or("expected a setting name")?;
+    let mut table = settings;
+    for (i, key) in keys.iter().enumerate() {
+        let entry = table.entry(key.to_string()).or_insert_with(|| Value::Table(Table::new()));
+        table = entry
+            .as_table_mut()
+            .ok_or_else(|| format!("{} is not a section", keys[..=i].join(".")))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(
+        file: Option<&str>,
+        env: &[(&str, &str)],
+        flags: &[&str],
+    ) -> Result<Config, ConfigError> {
+        let path = file.map(|text| {
+            let path = std::env::temp_dir().join(format!(
+                "igloo-config-{}-{}.toml",
+                std::process::id(),
+                text.len()
+            ));
+            std::fs::write(&path, text).unwrap();
+            path
+        });
+        let env: HashMap<_, _> =
+            env.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let args = Args::try_parse_from(
+            ["igloo-coordinator"]
+                .into_iter()
+                .chain(flags.iter().copied())
+                .chain(path.iter().flat_map(|path| ["--config", path.to_str().unwrap()])),
+        )
+        .unwrap();
+        let config = Config::load_with(&args, |name| env.get(name).cloned());
+        if let Some(path) = path {
+            std::fs::remove_file(path).unwrap();
+        }
+        config
+    }
+
+    #[test]
+    fn test_defaults_without_a_file() {
+        let config = load(None, &[], &[]).unwrap();
+        assert_eq!(config, Config::default());
+        assert_eq!(config.server.flight_addr.to_string(), "127.0.0.1:50051");
+        assert_eq!(config.catalog.store, "igloo_catalog.db");
+        assert!(config.audit.sql);
+    }
+
+    #[test]
+    fn test_environment_and_flags_override_the_file() {
+        let file = r#"
+            [server]
+            http_addr = "0.0.0.0:8080"
+            workers = ["w1:50052"]
+
+            [limits]
+            queries_per_minute = 10
+            priority_principals = { etl = "batch" }
+        "#;
+        let env = [
+            ("IGLOO_WORKERS", "w2:50052, w3:50052"),
+            ("IGLOO_RESOURCE_CLASSES", "reporting:1024:2,adhoc::1"),
+            ("IGLOO_AUDIT_SQL", "false"),
+            ("IGLOO_BROADCAST_MAX_ROWS", "5000"),
+            ("IGLOO_SCAN_FILES", "32"),
+        ];
+        let flags = ["--set", "limits.queries_per_minute=20", "--pgwire", "--flight-sql"];
+        let config = load(Some(file), &env, &flags).unwrap();
+        assert_eq!(config.server.http_addr.unwrap().to_string(), "0.0.0.0:8080");
+        assert_eq!(config.server.workers, ["w2:50052", "w3:50052"]);
+        assert_eq!(config.server.pgwire_addr.unwrap().to_string(), "127.0.0.1:5432");
+        assert!(config.server.flight_sql);
+        assert_eq!(config.limits.queries_per_minute, Some(20));
+        assert_eq!(config.limits.priority_principals["etl"], "batch");
+        assert_eq!(config.joins.options().unwrap().broadcast_max_rows, 5000);
+        assert_eq!(config.scans.scan_io().unwrap().files, 32);
+        let reporting = &config.limits.resource_classes["reporting"];
+        assert_eq!((reporting.memory_bytes, reporting.cpu_weight), (Some(1024), Some(2)));
+        assert_eq!(config.limits.resource_classes["adhoc"].memory_bytes, None);
+        assert!(!config.audit.sql);
+    }
+
+    #[test]
+    fn test_profiles_apply_over_the_file() {
+        let file = r#"
+            [server]
+            http_addr = "127.0.0.1:8080"
+            workers = ["w1:50052"]
+
+            [limits]
+            queries_per_minute = 10
+
+            [profiles.prod.server]
+            workers = ["w2:50052", "w3:50052"]
+
+            [profiles.prod.limits]
+            concurrent_queries = 4
+
+            [profiles.dev.logging]
+            level = "debug"
+        "#;
+        let config = load(Some(file), &[("IGLOO_PROFILE", "prod")], &[]).unwrap();
+        assert_eq!(config.profile.as_deref(), Some("prod"));
+        assert_eq!(config.server.http_addr.unwrap().to_string(), "127.0.0.1:8080");
+        assert_eq!(config.server.workers, ["w2:50052", "w3:50052"]);
+        assert_eq!(config.limits.queries_per_minute, Some(10));
+        assert_eq!(config.limits.concurrent_queries, Some(4));
+        assert_eq!(config.logging.level, "info");
+
+        let flags = ["--profile", "dev"];
+        let config = load(Some(file), &[("IGLOO_PROFILE", "prod")], &flags).unwrap();
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.server.workers, ["w1:50052"]);
+
+        let error = load(Some(file), &[], &["--profile", "staging"]).unwrap_err();
+        assert!(error.to_string().ends_with("no profile 'staging'; the file defines dev, prod"));
+        let file = "[profiles.prod.server]\nhtp_addr = \"0.0.0.0:8080\"\n";
+        let error = load(Some(file), &[], &["--profile", "prod"]).unwrap_err();
+        assert!(error.to_string().contains("profile 'prod': unknown field `htp_addr`"), "{error}");
+        let error = load(None, &[], &["--profile", "prod"]).unwrap_err();
+        assert!(error.to_string().contains("there is no configuration file"), "{error}");
+    }
+
+    /// Secrets by path.
+    #[derive(Debug)]
+    struct TestSecrets;
+
+    #[async_trait::async_trait]
+    impl igloo_common::secrets::SecretProvider for TestSecrets {
+        async fn get(&self, path: &str) -> igloo_common::error::Result<String> {
+            match path {
+                "db" => Ok("postgres://igloo:db-password@db/igloo".to_string()),
+                "key" => Ok("api-key-1".to_string()),
+                _ => Err(igloo_common::Error::new(&format!("no secret {path}"))),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_references_are_resolved() {
+        let file = r#"
+            [catalog]
+            store = "test:db"
+
+            [auth]
+            api_keys = { "test:key" = "alice", plain = "bob" }
+        "#;
+        let env = [("IGLOO_JWT_SECRET", "env:IGLOO_TEST_CONFIG_UNSET")];
+        let mut config = load(Some(file), &env, &[]).unwrap();
+        let secrets = Secrets::new().with_provider("test", Arc::new(TestSecrets));
+        let error = config.clone().resolve_secrets(&secrets).await.unwrap_err();
+        assert!(error.to_string().starts_with("auth.jwt_secret: cannot resolve secret"), "{error}");
+
+        config.auth.jwt_secret = None;
+        config.resolve_secrets(&secrets).await.unwrap();
+        assert_eq!(config.catalog.store, "postgres://igloo:db-password@db/igloo");
+        assert_eq!(config.auth.api_keys["api-key-1"], "alice");
+        assert_eq!(config.auth.api_keys["plain"], "bob");
+    }
+
+    #[test]
+    fn test_errors_name_the_setting() {
+        let error = load(Some("[server]\nhtp_addr = \"0.0.0.0:8080\"\n"), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("unknown field `htp_addr`"), "{error}");
+
+        let error = load(None, &[("IGLOO_JOB_WORKERS", "four")], &[]).unwrap_err();
+        assert_eq!(error.to_string(), "invalid IGLOO_JOB_WORKERS: expected a number, got 'four'");
+
+        let file = "[server]\nhttp_addr = \"0.0.0.0:8080\"\n";
+        let error = load(Some(file), &[], &["--set", "server.http_addr=localhost"]).unwrap_err();
+        let message = error.to_string();
+        assert!(message.starts_with("invalid --set server.http_addr=localhost: "), "{message}");
+        assert!(message.contains("invalid socket address"), "{message}");
+
+        let flags = ["--set", "limits.priority_principals.etl=urgent"];
+        let error = load(None, &[], &flags).unwrap_err();
+        assert!(error.to_string().contains("limits.priority_principals.etl"), "{error}");
+
+        let file = "[[cdc.kafka]]\nproxy = \"http://proxy\"\ntopic = \"t\"\ntable = \"s.t\"\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("sources.iceberg is not configured"), "{error}");
+
+        let flags = ["--set", "server.pgwire_addr=127.0.0.1:50051"];
+        let error = load(None, &[], &flags).unwrap_err();
+        assert!(error.to_string().contains("configured for two"), "{error}");
+
+        let error = load(None, &[("IGLOO_JOIN_STRATEGY", "sideways")], &[]).unwrap_err();
+        assert!(error.to_string().contains("joins.strategy: "), "{error}");
+    }
+
+    #[test]
+    fn test_quality_checks() {
+        let file = r#"
+            [quality]
+            interval_secs = 60
+
+            [[quality.checks]]
+            table = "orders"
+            check = "unique"
+            columns = ["region", "id"]
+
+            [[quality.checks]]
+            table = "orders"
+            check = "range"
+            column = "total"
+            min = "0"
+        "#;
+        let config = load(Some(file), &[], &[]).unwrap();
+        let checks = config.quality.checks().unwrap();
+        assert_eq!(checks[0].name(), "unique orders(region, id)");
+        assert_eq!(
+            checks[1].check,
+            Check::Range { column: "total".to_string(), min: Some("0".to_string()), max: None }
+        );
+
+        let file = "[[quality.checks]]\ntable = \"orders\"\ncheck = \"freshness\"\n\
+                    column = \"at\"\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid configuration: quality.checks: the freshness check of orders needs max_age_secs"
+        );
+        let file = "[[quality.checks]]\ntable = \"orders\"\ncheck = \"sorted\"\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("unknown check 'sorted'"), "{error}");
+    }
+
+    #[test]
+    fn test_retention_policies() {
+        let file = r#"
+            [sources.iceberg]
+            uri = "http://polaris:8181/api/catalog"
+
+            [[retention.tables]]
+            table = "sales.eu.orders"
+            snapshot_max_age_days = 7
+            min_snapshots = 10
+        "#;
+        let config = load(Some(file), &[("IGLOO_RETENTION_DRY_RUN", "true")], &[]).unwrap();
+        assert!(config.retention.dry_run);
+        assert_eq!(config.retention.interval_secs, 86400);
+        let policies = config.retention.policies().unwrap();
+        let ident = TableIdent {
+            namespace: vec!["sales".to_string(), "eu".to_string()],
+            name: "orders".to_string(),
+        };
+        let policy = RetentionPolicy::default()
+            .with_snapshot_max_age(Duration::from_secs(7 * 24 * 3600))
+            .with_min_snapshots(10);
+        assert_eq!(policies, [(ident, policy)]);
+
+        let file = "[[retention.tables]]\ntable = \"orders\"\nsnapshot_max_age_days = 7\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("must be namespace.table"), "{error}");
+        let file = "[[retention.tables]]\ntable = \"sales.orders\"\nmin_snapshots = 3\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("needs partition_max_age_days"), "{error}");
+        let file = "[[retention.tables]]\ntable = \"sales.orders\"\nsnapshot_max_age_days = 7\n";
+        let error = load(Some(file), &[], &[]).unwrap_err();
+        assert!(error.to_string().contains("sources.iceberg is not configured"), "{error}");
+    }
+}
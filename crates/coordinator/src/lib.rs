@@ -0,0 +1,889 @@
+//! The Igloo coordinator: its servers, the sources and CDC pipelines of its
+//! configuration, and the background work it does. Started by the `igloo-coordinator`
+//! binary and by `igloo serve`.
+
+pub mod config;
+mod readiness;
+mod reload;
+
+use config::{Args, Config, ConfigError, DeltaSharingSource};
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::error::Result as DataFusionResult;
+use igloo_connector_delta::{
+    ShareCatalogProvider, SharingClient, SharingProfile, UnityCatalog, UnityCatalogProvider,
+};
+use igloo_connector_hive::{HiveCatalogProvider, HiveMetastoreClient};
+use igloo_connector_iceberg::compaction::Compactor;
+use igloo_connector_iceberg::rest::TableIdent;
+use igloo_connector_iceberg::retention::{Retention, RetentionPolicy};
+use igloo_connector_iceberg::{IcebergCatalogProvider, RestCatalog};
+use igloo_connector_kafka::{KafkaIngestion, KafkaRestClient, RecordFormat};
+use igloo_engine::admission::AdmissionQueue;
+use igloo_engine::catalog_store::{CatalogStore, PostgresCatalogStore, SqliteCatalogStore};
+use igloo_engine::ingest::IngestWal;
+use igloo_engine::policy::PolicySet;
+use igloo_engine::quality::{CheckStatus, QualityChecks};
+use igloo_engine::resources::{ResourceClass, ResourceManager};
+use igloo_engine::scheduler::{Scheduler, TaskId};
+use igloo_engine::tenant::Tenant;
+use igloo_engine::QueryEngine;
+use reload::{Live, Reloader};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+use arrow_flight::flight_service_server::FlightServiceServer;
+use igloo_api::audit::{Auditor, FileAuditSink};
+use igloo_api::auth::{Authenticator, JwtConfig, Principal};
+use igloo_api::ballista::BallistaPlanner;
+use igloo_api::distributed::DistributedPlanner;
+use igloo_api::flight_sql::IglooFlightSqlService;
+use igloo_api::http::health::{DrainProbe, Readiness};
+use igloo_api::http::HttpOptions;
+use igloo_api::igloo::coordinator_service_server::CoordinatorServiceServer;
+use igloo_api::jobs::JobManager;
+use igloo_api::membership::{Membership, MembershipService};
+use igloo_api::pgwire::IglooPgServer;
+use igloo_api::query_history::QueryHistory;
+use igloo_api::quota::QuotaLimiter;
+use igloo_api::slow_log::{FileSlowQuerySink, SlowQueryLog, TableSlowQuerySink};
+use igloo_api::supervisor::{RestartPolicy, Stop, Supervisor};
+use igloo_api::tls::TlsConfig;
+use igloo_api::IglooFlightService;
+use igloo_common::catalog::{CatalogSource, MemoryCatalog};
+use igloo_common::secrets::Secrets;
+use tonic::service::Routes;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tracing::{debug, info, warn};
+
+/// How often a server is restarted in a row before it is left failed.
+const SERVER_RESTARTS: RestartPolicy = RestartPolicy::OnFailure { max_restarts: 5 };
+
+/// The least time components have to stop on shutdown.
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// Start the coordinator as `args` and its configuration say, serving until
+/// interrupted. Exits the process if the configuration is invalid, or with
+/// `--validate-config` once it is checked.
+pub async fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    // Settings from the configuration file, the environment and flags (see `config`),
+    // checked before anything starts
+    let (args, config, secrets) = match load_config(args).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("igloo-coordinator: {}", igloo_common::redact::redact(&e.to_string()));
+            std::process::exit(2);
+        }
+    };
+    if args.validate_config {
+        let report = readiness::check(&config).await;
+        println!("{}", igloo_common::redact::redact(&report.to_string()));
+        std::process::exit(if report.is_ready() { 0 } else { 1 });
+    }
+    let log_filter = igloo_common::logging::init(config.logging.format()?, &config.logging.level)?;
+
+    // 1. Instantiate the query engine, distributing queries across the workers that
+    // are configured or register with the coordinator, and register the sources
+    let membership = Arc::new(membership_from_config(&config));
+    let mut planner = DistributedPlanner::new(membership.clone());
+    if let Some(attempts) = config.server.max_task_attempts {
+        planner = planner.with_max_attempts(attempts);
+    }
+    let mut engine = QueryEngine::new()
+        .with_physical_optimizer_rule(Arc::new(planner))
+        .with_options(config.joins.options()?)
+        .with_scan_io(config.scans.scan_io())
+        .with_secrets(secrets.clone());
+    if let Some(scheduler) = &config.server.ballista_scheduler {
+        engine = engine.with_query_planner(Arc::new(BallistaPlanner::new(scheduler.clone())));
+    }
+    if let Some(resources) = resources_from_config(&config) {
+        engine = engine.with_resource_manager(Arc::new(resources));
+    }
+    if let Some(path) = &config.server.policy_file {
+        engine.set_policies(PolicySet::from_json(&std::fs::read_to_string(path)?)?);
+    }
+    let iceberg = register_sources(&config, &engine).await?;
+
+    // 2. Restore the tables and views created at runtime, and keep up with those
+    // other coordinators sharing the catalog store create
+    let mut engine = engine.with_catalog_store(catalog_store_from_config(&config).await?).await?;
+    // Log ingested rows until they are committed, and commit those a crash left behind
+    if let Some(dir) = &config.server.ingest_wal_dir {
+        engine = engine.with_ingest_wal(IngestWal::open(dir)?);
+        let replayed = engine.replay_ingest_wal().await?;
+        if replayed.commits > 0 {
+            info!(rows = replayed.rows, "Replayed ingested rows from the write-ahead log.");
+        }
+    }
+    let engine = Arc::new(engine);
+
+    // 3. Run the servers, the CDC pipelines and the periodic jobs as components of a
+    // supervisor, restarting those that fail
+    let supervisor = Arc::new(Supervisor::new());
+    let refresh = Arc::new(AtomicU64::new(config.cache.catalog_refresh_secs));
+    supervisor.spawn("catalog_refresh", RestartPolicy::Always, {
+        let (engine, refresh) = (engine.clone(), refresh.clone());
+        move |stop: Stop| {
+            let (engine, refresh) = (engine.clone(), refresh.clone());
+            async move {
+                loop {
+                    // Reloading the configuration may change the period.
+                    let period = Duration::from_secs(refresh.load(Ordering::Relaxed));
+                    tokio::select! {
+                        _ = tokio::time::sleep(period) => {}
+                        _ = stop.clone().stopped() => return Ok::<_, String>(()),
+                    }
+                    if let Err(e) = engine.refresh_catalog().await {
+                        warn!(error = %e, "failed to refresh the catalog");
+                    }
+                }
+            }
+        }
+    });
+
+    if let Some(catalog) = iceberg {
+        spawn_cdc_from_config(&supervisor, &config, &engine, catalog.clone()).await?;
+        if let Some(secs) = config.sources.iceberg.as_ref().and_then(|i| i.compaction_secs) {
+            spawn_compaction(&supervisor, &engine, catalog.clone(), Duration::from_secs(secs));
+        }
+        let policies = config.retention.policies()?;
+        if !policies.is_empty() {
+            let retention = Retention::new(catalog, Arc::new(engine.session_context().state()))
+                .with_dry_run(config.retention.dry_run);
+            let period = Duration::from_secs(config.retention.interval_secs);
+            spawn_retention(&supervisor, retention, policies, period);
+        }
+    }
+
+    let quality = match config.quality.checks()? {
+        checks if checks.is_empty() => None,
+        checks => {
+            let checks = Arc::new(QualityChecks::new(checks));
+            let period = Duration::from_secs(config.quality.interval_secs);
+            spawn_quality_checks(&supervisor, &engine, checks.clone(), period);
+            Some(checks)
+        }
+    };
+
+    tenants_from_config(&config, &engine)?;
+    let auth = authenticator_from_config(&config);
+    if auth.is_none() {
+        info!("No credentials configured; frontends accept unauthenticated clients.");
+    }
+    let tls = tls_from_config(&config)?;
+    let audit = auditor_from_config(&config, &engine)?;
+    let quotas = quotas_from_config(&config)?;
+
+    // Apply changes to the configuration file that take no restart
+    if let Some(file) = args.config_file(|name| std::env::var(name).ok()) {
+        if config.server.reload_secs > 0 {
+            let period = Duration::from_secs(config.server.reload_secs);
+            let live = Live {
+                engine: engine.clone(),
+                supervisor: supervisor.clone(),
+                log_filter,
+                catalog_refresh_secs: refresh,
+                quotas: quotas.clone(),
+                secrets,
+            };
+            Reloader::new(args, config.clone(), live).spawn(&supervisor, file, period);
+        }
+    }
+
+    // Additionally accept PostgreSQL clients (psql, drivers, BI tools)
+    if let Some(pg_addr) = config.server.pgwire_addr {
+        let listener = Listener::bind(pg_addr).await?;
+        info!(addr = %pg_addr, "Coordinator PostgreSQL wire protocol listening");
+        let mut server = IglooPgServer::new(engine.clone());
+        if let Some(auth) = &auth {
+            server = server.with_auth(auth.clone());
+        }
+        if let Some(tls) = &tls {
+            server = server.with_tls(tls)?;
+        }
+        if let Some(audit) = &audit {
+            server = server.with_audit(audit.clone());
+        }
+        if let Some(quotas) = &quotas {
+            server = server.with_quotas(quotas.clone());
+        }
+        supervisor.spawn("pgwire", SERVER_RESTARTS, move |stop: Stop| {
+            let (listener, server) = (listener.clone(), server.clone());
+            async move {
+                let serve = igloo_api::pgwire::serve_with(listener.take().await?, server);
+                tokio::select! {
+                    served = serve => served,
+                    _ = stop.stopped() => Ok(()),
+                }
+            }
+        });
+    }
+
+    // Additionally serve the HTTP/JSON API
+    if let Some(http_addr) = config.server.http_addr {
+        let listener = Listener::bind(http_addr).await?;
+        info!(addr = %http_addr, "Coordinator HTTP API listening");
+        // Not ready once draining, so that load balancers move on to other coordinators
+        let readiness = Readiness::new().with_probe(DrainProbe::new(engine.clone()));
+        let mut options = HttpOptions::new()
+            .with_jobs(Arc::new(jobs_from_config(&config)?))
+            .with_readiness(readiness)
+            .with_supervisor(supervisor.clone());
+        if let Some(auth) = &auth {
+            options = options.with_auth(auth.clone());
+        }
+        if let Some(tls) = &tls {
+            options = options.with_tls(tls.clone());
+        }
+        if let Some(audit) = &audit {
+            options = options.with_audit(audit.clone());
+        }
+        if let Some(quotas) = &quotas {
+            options = options.with_quotas(quotas.clone());
+        }
+        if let Some(quality) = &quality {
+            options = options.with_quality(quality.clone());
+        }
+        let engine = engine.clone();
+        supervisor.spawn("http", SERVER_RESTARTS, move |stop: Stop| {
+            let (listener, engine, options) = (listener.clone(), engine.clone(), options.clone());
+            async move {
+                let listener = listener.take().await?;
+                let serve = igloo_api::http::serve_with_options(listener, engine, options);
+                tokio::select! {
+                    served = serve => served,
+                    _ = stop.stopped() => Ok(()),
+                }
+            }
+        });
+    }
+
+    // Flight SQL instead of plain Arrow Flight, if configured
+    let addr = config.server.flight_addr;
+    let listener = Listener::bind(addr).await?;
+    let mut check = auth.map(igloo_api::auth::flight_interceptor);
+    #[allow(clippy::result_large_err)] // tonic interceptors return `Status` directly.
+    let interceptor = move |request| match check.as_mut() {
+        Some(check) => check(request),
+        None => Ok(request),
+    };
+    let routes = if config.server.flight_sql {
+        info!(%addr, "Coordinator Flight SQL listening");
+        let mut service = IglooFlightSqlService::new(engine.clone());
+        if let Some(audit) = audit {
+            service = service.with_audit(audit);
+        }
+        if let Some(quotas) = quotas {
+            service = service.with_quotas(quotas);
+        }
+        Routes::new(FlightServiceServer::with_interceptor(service, interceptor))
+    } else {
+        info!(%addr, "Coordinator Flight listening");
+        let mut service =
+            IglooFlightService::new(engine.clone(), Arc::new(MemoryCatalog::default()));
+        if let Some(audit) = audit {
+            service = service.with_audit(audit);
+        }
+        if let Some(quotas) = quotas {
+            service = service.with_quotas(quotas);
+        }
+        Routes::new(FlightServiceServer::with_interceptor(service, interceptor))
+    };
+    // Workers register and send heartbeats on the same port
+    let routes =
+        routes.add_service(CoordinatorServiceServer::new(MembershipService::new(membership)));
+    let grpc_tls = tls.map(|tls| tls.grpc_config());
+    supervisor.spawn("flight", SERVER_RESTARTS, move |stop: Stop| {
+        let (listener, routes, grpc_tls) = (listener.clone(), routes.clone(), grpc_tls.clone());
+        async move {
+            let mut builder = Server::builder();
+            if let Some(tls) = grpc_tls {
+                builder = builder.tls_config(tls)?;
+            }
+            let incoming = TcpIncoming::from_listener(listener.take().await?, true, None)?;
+            builder
+                .add_routes(routes)
+                .serve_with_incoming_shutdown(incoming, stop.stopped())
+                .await?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        }
+    });
+
+    igloo_common::shutdown::signal().await;
+    info!("Shutting down coordinator gracefully...");
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    shut_down(&engine, &supervisor, timeout).await;
+    Ok(())
+}
+
+/// The configuration, with the secrets it references resolved, and the providers
+/// they were resolved with.
+async fn load_config(args: Args) -> Result<(Args, Config, Secrets), ConfigError> {
+    let mut config = Config::load(&args)?;
+    let secrets = config.secrets().await?;
+    config.resolve_secrets(&secrets).await?;
+    Ok((args, config, secrets))
+}
+
+/// Register the catalogs of `sources` with `engine`, returning the Iceberg REST catalog.
+/// None is reached here: each is read when a query first names one of its tables, or
+/// in the background if `server.warm_up_sources`.
+async fn register_sources(
+    config: &Config,
+    engine: &QueryEngine,
+) -> Result<Option<Arc<RestCatalog>>, Box<dyn std::error::Error>> {
+    let warm_up = config.server.warm_up_sources;
+    let iceberg = iceberg_catalog_from_config(config);
+    if let Some(catalog) = &iceberg {
+        let source = Arc::new(IcebergCatalogProvider::new(catalog.clone()));
+        register_source(engine, "iceberg", source, warm_up).await?;
+        info!("Registered the Iceberg REST catalog as 'iceberg'.");
+    }
+    if let Some(hive) = &config.sources.hive {
+        let client = Arc::new(HiveMetastoreClient::new(hive.metastore.clone()));
+        register_source(engine, "hive", Arc::new(HiveCatalogProvider::new(client)), warm_up)
+            .await?;
+        info!("Registered the Hive Metastore as 'hive'.");
+    }
+    if let Some((name, catalog)) = unity_catalog_from_config(config) {
+        register_source(engine, &name, catalog, warm_up).await?;
+        info!("Registered Unity Catalog catalog '{}'.", name);
+    }
+    if let Some(source) = &config.sources.delta_sharing {
+        let client = Arc::new(sharing_client_from_config(source)?);
+        let catalog = Arc::new(ShareCatalogProvider::new(client, &source.share));
+        register_source(engine, source.catalog(), catalog, warm_up).await?;
+        info!("Registered Delta Sharing share '{}' as '{}'.", source.share, source.catalog());
+    }
+    Ok(iceberg)
+}
+
+/// Register `source` as the catalog `name` without reading it, and read it in the
+/// background if `warm_up`. A source that cannot be reached fails only the queries
+/// naming its tables, each of which tries it again.
+async fn register_source(
+    engine: &QueryEngine,
+    name: &str,
+    source: Arc<dyn CatalogSource>,
+    warm_up: bool,
+) -> DataFusionResult<()> {
+    engine.register_lazy_catalog_source(name, source).await?;
+    if warm_up {
+        let (engine, name) = (engine.clone(), name.to_string());
+        tokio::spawn(async move {
+            match engine.warm_up_catalog(&name).await {
+                Ok(()) => info!(catalog = %name, "Loaded the catalog."),
+                Err(e) => warn!(
+                    catalog = %name,
+                    error = %e,
+                    "Could not load the catalog; queries naming it will try again."
+                ),
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Workers that do not register themselves, from `server.workers`.
+fn membership_from_config(config: &Config) -> Membership {
+    let mut membership = Membership::new();
+    for worker in &config.server.workers {
+        membership = membership.with_worker(worker);
+        info!("Configured worker at {}.", worker);
+    }
+    membership
+}
+
+/// The catalog store of `catalog.store`: Postgres for a `postgres://` URL, or else a
+/// SQLite database at that path.
+async fn catalog_store_from_config(
+    config: &Config,
+) -> Result<Arc<dyn CatalogStore>, Box<dyn std::error::Error>> {
+    let store = &config.catalog.store;
+    if store.starts_with("postgres://") || store.starts_with("postgresql://") {
+        info!("Persisting the catalog in Postgres.");
+        return Ok(Arc::new(PostgresCatalogStore::connect(store).await?));
+    }
+    info!("Persisting the catalog in {}.", store);
+    Ok(Arc::new(SqliteCatalogStore::open(store)?))
+}
+
+/// The Iceberg REST catalog of `sources.iceberg`, if configured.
+fn iceberg_catalog_from_config(config: &Config) -> Option<Arc<RestCatalog>> {
+    let source = config.sources.iceberg.as_ref()?;
+    let mut catalog = RestCatalog::new(source.uri.clone());
+    if let Some(warehouse) = &source.warehouse {
+        catalog = catalog.with_warehouse(warehouse.clone());
+    }
+    if let Some(token) = &source.token {
+        catalog = catalog.with_token(token.clone());
+    } else if let Some(credential) = &source.credential {
+        catalog = catalog.with_credential(credential);
+    }
+    Some(Arc::new(catalog))
+}
+
+/// Ingest the topics of `cdc.kafka` into their Iceberg tables, each as a component of
+/// `supervisor`, restarted whenever it fails. A table that does not exist fails startup.
+async fn spawn_cdc_from_config(
+    supervisor: &Supervisor,
+    config: &Config,
+    engine: &QueryEngine,
+    catalog: Arc<RestCatalog>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let runtime = engine.session_context().runtime_env();
+    for pipeline in &config.cdc.kafka {
+        let (namespace, name) = pipeline.table.rsplit_once('.').unwrap_or_default();
+        let ident = TableIdent {
+            namespace: namespace.split('.').map(str::to_string).collect(),
+            name: name.to_string(),
+        };
+        let table = catalog
+            .load_table(&ident)
+            .await?
+            .ok_or_else(|| format!("cdc.kafka: no Iceberg table {}", pipeline.table))?;
+        let location = ListingTableUrl::parse(&table.metadata.location)?;
+        let store = runtime.object_store(location.object_store())?;
+        info!(topic = pipeline.topic, table = pipeline.table, "Ingesting the Kafka topic.");
+        let (pipeline, catalog) = (pipeline.clone(), catalog.clone());
+        let name = format!("cdc.kafka.{}", pipeline.topic);
+        supervisor.spawn(name, RestartPolicy::Always, move |stop: Stop| {
+            let proxy = KafkaRestClient::new(pipeline.proxy.clone());
+            let (topic, ident, store) = (pipeline.topic.clone(), ident.clone(), store.clone());
+            // Stopped, it commits what it consumed
+            let mut ingestion = KafkaIngestion::new(proxy, topic, catalog.clone(), ident, store)
+                .with_stop(stop.receiver());
+            if let Some(group) = &pipeline.group {
+                ingestion = ingestion.with_group(group.clone());
+            }
+            if let Some(registry) = &pipeline.schema_registry {
+                let registry = Arc::new(igloo_cdc::SchemaRegistryClient::new(registry.clone()));
+                ingestion = ingestion.with_format(RecordFormat::Avro(registry));
+            }
+            if let Some(secs) = pipeline.commit_interval_secs {
+                ingestion = ingestion.with_commit_interval(Duration::from_secs(secs));
+            }
+            if let Some(max_records) = pipeline.max_records {
+                ingestion = ingestion.with_max_records(max_records);
+            }
+            ingestion.run()
+        });
+    }
+    Ok(())
+}
+
+/// Stop admitting queries, give the running ones until `timeout` to finish, then stop
+/// the supervised components: the servers, and the CDC pipelines, which commit what
+/// they consumed. Components get what is left of `timeout`, and at least
+/// [`STOP_GRACE`]. Rows ingested but not committed by then stay in the ingestion
+/// write-ahead log, if there is one.
+async fn shut_down(engine: &QueryEngine, supervisor: &Supervisor, timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    engine.drain();
+    if !engine.wait_idle(timeout).await {
+        let running = engine.running_queries().len();
+        warn!(running, "Queries still running at the shutdown deadline are cancelled.");
+    }
+    let left = deadline.saturating_duration_since(tokio::time::Instant::now());
+    if !supervisor.shutdown(left.max(STOP_GRACE)).await {
+        warn!("Components still running at the shutdown deadline were cancelled.");
+    }
+}
+
+/// Compact the small files of the Iceberg catalog's tables every `period`, as a
+/// component of `supervisor`: each table is a task of a scheduler of its own, so
+/// compactions never hold up query jobs, and a table still being compacted is skipped.
+fn spawn_compaction(
+    supervisor: &Supervisor,
+    engine: &QueryEngine,
+    catalog: Arc<RestCatalog>,
+    period: Duration,
+) {
+    let state = Arc::new(engine.session_context().state());
+    let compactor = Arc::new(Compactor::new(catalog, state));
+    info!("Compacting Iceberg tables every {} seconds.", period.as_secs());
+    supervisor.spawn("compaction", RestartPolicy::Always, move |stop: Stop| {
+        let compactor = compactor.clone();
+        async move {
+            let scheduler = Scheduler::new(1, 64);
+            let mut interval = tokio::time::interval(period);
+            let mut running: HashMap<TableIdent, TaskId> = HashMap::new();
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stop.clone().stopped() => return Ok::<_, String>(()),
+                }
+                let tables = match compactor.tables().await {
+                    Ok(tables) => tables,
+                    Err(e) => {
+                        warn!(error = %e, "failed to list the Iceberg tables to compact");
+                        continue;
+                    }
+                };
+                running.retain(|_, id| scheduler.status(*id).is_some_and(|s| !s.is_finished()));
+                for ident in tables {
+                    if running.contains_key(&ident) {
+                        continue;
+                    }
+                    let name = format!("compact {}.{}", ident.namespace.join("."), ident.name);
+                    let task = {
+                        let (compactor, ident, name) =
+                            (compactor.clone(), ident.clone(), name.clone());
+                        async move {
+                            match compactor.compact(&ident).await {
+                                Ok(Some(report)) => info!(
+                                    task = name,
+                                    rewritten_files = report.rewritten_files,
+                                    added_files = report.added_files,
+                                    "compacted"
+                                ),
+                                Ok(None) => {}
+                                Err(e) => {
+                                    warn!(task = name, error = %e, "compaction failed");
+                                    return Err(e);
+                                }
+                            }
+                            Ok(())
+                        }
+                    };
+                    match scheduler.submit(name, task) {
+                        Ok(id) => {
+                            running.insert(ident, id);
+                        }
+                        Err(e) => warn!(error = %e, "failed to schedule a compaction"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Apply the retention `policies` of Iceberg tables every `period`, as a component of
+/// `supervisor`, one table after the other.
+fn spawn_retention(
+    supervisor: &Supervisor,
+    retention: Retention,
+    policies: Vec<(TableIdent, RetentionPolicy)>,
+    period: Duration,
+) {
+    let (retention, policies) = (Arc::new(retention), Arc::new(policies));
+    info!(
+        tables = policies.len(),
+        "Applying retention policies every {} seconds.",
+        period.as_secs()
+    );
+    supervisor.spawn("retention", RestartPolicy::Always, move |stop: Stop| {
+        let (retention, policies) = (retention.clone(), policies.clone());
+        async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stop.clone().stopped() => return Ok::<_, String>(()),
+                }
+                for (ident, policy) in policies.iter() {
+                    if stop.is_stopped() {
+                        break;
+                    }
+                    let table = format!("{}.{}", ident.namespace.join("."), ident.name);
+                    match retention.apply(ident, policy).await {
+                        Ok(report) if report.is_empty() => {}
+                        Ok(report) => {
+                            info!(
+                                table,
+                                dry_run = report.dry_run,
+                                dropped_partitions = report.dropped_partitions.len(),
+                                dropped_files = report.dropped_files,
+                                dropped_rows = report.dropped_rows,
+                                expired_snapshots = report.expired_snapshots.len(),
+                                orphan_files = report.orphan_files.len(),
+                                "{}",
+                                match report.dry_run {
+                                    true => "retention dry run",
+                                    false => "applied retention",
+                                }
+                            );
+                            debug!(table, ?report, "retention report");
+                        }
+                        Err(e) => warn!(table, error = %e, "retention failed"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// Run the data quality checks every `period`, as a component of `supervisor`, each
+/// check as a task of a scheduler of its own; a check still running from the previous
+/// period is skipped.
+fn spawn_quality_checks(
+    supervisor: &Supervisor,
+    engine: &Arc<QueryEngine>,
+    checks: Arc<QualityChecks>,
+    period: Duration,
+) {
+    let engine = engine.clone();
+    info!(
+        checks = checks.checks().len(),
+        "Checking data quality every {} seconds.",
+        period.as_secs()
+    );
+    supervisor.spawn("quality", RestartPolicy::Always, move |stop: Stop| {
+        let (engine, checks) = (engine.clone(), checks.clone());
+        async move {
+            let scheduler = Scheduler::new(1, 64);
+            let mut interval = tokio::time::interval(period);
+            let mut running: HashMap<usize, TaskId> = HashMap::new();
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stop.clone().stopped() => return Ok::<_, String>(()),
+                }
+                running.retain(|_, id| scheduler.status(*id).is_some_and(|s| !s.is_finished()));
+                for (index, check) in checks.checks().iter().enumerate() {
+                    if running.contains_key(&index) {
+                        continue;
+                    }
+                    let name = format!("quality check {}", check.name());
+                    let task = {
+                        let (engine, checks) = (engine.clone(), checks.clone());
+                        async move {
+                            let result = checks.run(&engine, index).await;
+                            if result.status != CheckStatus::Passed {
+                                warn!(
+                                    check = result.name,
+                                    status = ?result.status,
+                                    "{}",
+                                    result.message
+                                );
+                            }
+                            Ok(())
+                        }
+                    };
+                    match scheduler.submit(name, task) {
+                        Ok(id) => {
+                            running.insert(index, id);
+                        }
+                        Err(e) => warn!(error = %e, "failed to schedule a data quality check"),
+                    }
+                }
+            }
+        }
+    });
+}
+
+/// A server's listener: bound when the coordinator starts, so that an address in use
+/// fails startup, and bound again when the server is restarted.
+#[derive(Clone)]
+struct Listener {
+    addr: SocketAddr,
+    bound: Arc<std::sync::Mutex<Option<TcpListener>>>,
+}
+
+impl Listener {
+    async fn bind(addr: SocketAddr) -> std::io::Result<Self> {
+        let bound = Some(TcpListener::bind(addr).await?);
+        Ok(Self { addr, bound: Arc::new(std::sync::Mutex::new(bound)) })
+    }
+
+    async fn take(&self) -> std::io::Result<TcpListener> {
+        let bound = self.bound.lock().expect("listener lock poisoned").take();
+        match bound {
+            Some(listener) => Ok(listener),
+            None => TcpListener::bind(self.addr).await,
+        }
+    }
+}
+
+/// The catalog of the Unity Catalog server of `sources.unity`, registered under its
+/// own name. `None` if not configured.
+fn unity_catalog_from_config(config: &Config) -> Option<(String, Arc<UnityCatalogProvider>)> {
+    let source = config.sources.unity.as_ref()?;
+    let mut client = UnityCatalog::new(source.uri.clone());
+    if let Some(token) = &source.token {
+        client = client.with_token(token.clone());
+    }
+    let catalog = UnityCatalogProvider::new(Arc::new(client), &source.name);
+    Some((source.name.clone(), Arc::new(catalog)))
+}
+
+/// The client of the Delta Sharing server of the profile of `sources.delta_sharing`.
+fn sharing_client_from_config(
+    source: &DeltaSharingSource,
+) -> Result<SharingClient, Box<dyn std::error::Error>> {
+    let profile = SharingProfile::from_file(&source.profile)
+        .map_err(|e| format!("sources.delta_sharing.profile {}: {e}", source.profile.display()))?;
+    Ok(SharingClient::from_profile(&profile))
+}
+
+/// Asynchronous query jobs, spooling results under `server.spool_dir` and running up
+/// to `server.job_workers` at once.
+fn jobs_from_config(config: &Config) -> Result<JobManager, Box<dyn std::error::Error>> {
+    let dir = match &config.server.spool_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::temp_dir().join("igloo-jobs"),
+    };
+    let workers = config.server.job_workers;
+    // Jobs beyond the running ones wait in a queue of bounded length
+    let jobs = JobManager::local(Scheduler::new(workers, workers * 16), &dir)?;
+    info!("Spooling job results to {}.", dir.display());
+    Ok(jobs)
+}
+
+/// TLS for every frontend, from `server.tls`. `None` if not configured.
+fn tls_from_config(config: &Config) -> Result<Option<TlsConfig>, Box<dyn std::error::Error>> {
+    let Some(files) = &config.server.tls else {
+        return Ok(None);
+    };
+    let mut tls = TlsConfig::from_files(&files.cert, &files.key)?;
+    if let Some(ca) = &files.client_ca {
+        tls = tls.with_client_ca_file(ca)?;
+    }
+    Ok(Some(tls))
+}
+
+/// The audit log, slow query log and query history of `audit`, registering the
+/// system tables they are kept in. `None` if no log or history is kept.
+fn auditor_from_config(
+    config: &Config,
+    engine: &QueryEngine,
+) -> Result<Option<Arc<Auditor>>, Box<dyn std::error::Error>> {
+    let audit = &config.audit;
+    let max_queries = audit.query_history_max;
+    let history = match &audit.query_history {
+        _ if max_queries == 0 => None,
+        Some(path) => Some(QueryHistory::open(path)?),
+        None => Some(QueryHistory::new()),
+    }
+    .map(|history| Arc::new(history.with_max_queries(max_queries)));
+    if let Some(history) = &history {
+        engine.register_system_table("query_history", history.clone())?;
+    }
+    let slow_log = match audit.slow_query_ms {
+        Some(ms) => {
+            let threshold = Duration::from_millis(ms);
+            Some(match &audit.slow_query_log {
+                Some(path) => SlowQueryLog::new(threshold, FileSlowQuerySink::open(path)?),
+                None => {
+                    let sink = TableSlowQuerySink::new();
+                    engine.register_system_table("slow_queries", Arc::new(sink.clone()))?;
+                    SlowQueryLog::new(threshold, sink)
+                }
+            })
+        }
+        None => None,
+    };
+    let auditor = match &audit.log {
+        Some(path) => Auditor::new(FileAuditSink::open(path)?),
+        None if slow_log.is_some() || history.is_some() => Auditor::default(),
+        None => return Ok(None),
+    };
+    let mut auditor = auditor.with_sql_text(audit.sql);
+    if let Some(slow_log) = slow_log {
+        auditor = auditor.with_slow_query_log(slow_log);
+    }
+    if let Some(history) = history {
+        auditor = auditor.with_query_history(history);
+    }
+    Ok(Some(Arc::new(auditor)))
+}
+
+/// Per-principal limits and admission slots of `limits`. `None` if none is set.
+fn quotas_from_config(
+    config: &Config,
+) -> Result<Option<Arc<QuotaLimiter>>, Box<dyn std::error::Error>> {
+    let limits = &config.limits;
+    if !limits.has_quotas() {
+        return Ok(None);
+    }
+    let mut limiter = QuotaLimiter::new(limits.quotas());
+    if let Some(slots) = limits.admission_slots {
+        limiter = limiter.with_admission(AdmissionQueue::new(slots));
+    }
+    for (subject, priority) in &limits.priority_principals {
+        limiter = limiter.with_priority(subject, priority.parse()?);
+    }
+    Ok(Some(Arc::new(limiter)))
+}
+
+/// Per-query budgets of `limits.resource_classes`. `None` if no classes are
+/// configured.
+fn resources_from_config(config: &Config) -> Option<ResourceManager> {
+    let limits = &config.limits;
+    if limits.resource_classes.is_empty() {
+        return None;
+    }
+    let slots = limits
+        .cpu_slots
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4, usize::from));
+    let mut resources = ResourceManager::new(slots);
+    for (name, budget) in &limits.resource_classes {
+        let mut class = ResourceClass::new(name);
+        if let Some(bytes) = budget.memory_bytes {
+            class = class.with_memory_limit(bytes);
+        }
+        if let Some(weight) = budget.cpu_weight {
+            class = class.with_cpu_weight(weight);
+        }
+        resources = resources.with_class(class);
+    }
+    for (subject, class) in &limits.resource_principals {
+        resources = resources.with_principal(subject, class);
+    }
+    Some(resources)
+}
+
+/// The tenants of `tenants.names`, each limited as `tenants` says.
+fn tenants_from_config(
+    config: &Config,
+    engine: &QueryEngine,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tenants = &config.tenants;
+    for name in &tenants.names {
+        let mut tenant = Tenant::new(name);
+        if let Some(bytes) = tenants.memory_limit_bytes {
+            tenant = tenant.with_memory_limit(bytes);
+        }
+        if let Some(ms) = tenants.statement_timeout_ms {
+            tenant = tenant.with_statement_timeout(Duration::from_millis(ms));
+        }
+        engine.add_tenant(tenant)?;
+        info!("Added tenant '{}'.", name);
+    }
+    Ok(())
+}
+
+/// The API keys and JWT settings of `auth`. `None` if neither is configured.
+fn authenticator_from_config(config: &Config) -> Option<Arc<Authenticator>> {
+    let auth_config = &config.auth;
+    if auth_config.api_keys.is_empty() && auth_config.jwt_secret.is_none() {
+        return None;
+    }
+    let mut auth = Authenticator::new();
+    for (key, subject) in &auth_config.api_keys {
+        let principal = match subject.split_once('@') {
+            Some((subject, tenant)) => Principal::new(subject.trim()).with_tenant(tenant.trim()),
+            None => Principal::new(subject.trim()),
+        };
+        auth = auth.with_api_key(key.trim(), principal);
+    }
+    if let Some(secret) = &auth_config.jwt_secret {
+        let mut jwt = JwtConfig::hs256(secret.as_bytes());
+        if let Some(issuer) = &auth_config.jwt_issuer {
+            jwt = jwt.with_issuer(issuer);
+        }
+        if let Some(audience) = &auth_config.jwt_audience {
+            jwt = jwt.with_audience(audience);
+        }
+        if let Some(claim) = &auth_config.jwt_tenant_claim {
+            jwt = jwt.with_tenant_claim(claim);
+        }
+        auth = auth.with_jwt(jwt);
+    }
+    Some(Arc::new(auth))
+}
@@ -0,0 +1,218 @@
+//! Whether a configuration is ready to start a coordinator, for `--validate-config`.
+//!
+//! Every dependency the configuration declares is reached as the coordinator would:
+//! the catalog store, each source, each Kafka topic ingested, each worker, and the TLS
+//! files, including sources the coordinator would only read when first needed. A
+//! dependency that does not answer within [`TIMEOUT`] fails its check. Nothing is
+//! created or changed; a SQLite catalog store only needs its directory to exist.
+
+use crate::config::Config;
+use igloo_common::retry::RetryPolicy;
+use igloo_connector_delta::{SharingClient, SharingProfile, UnityCatalog};
+use igloo_connector_hive::HiveMetastoreClient;
+use igloo_connector_kafka::KafkaRestClient;
+use igloo_engine::catalog_store::PostgresCatalogStore;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long each dependency has to answer.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The check of one dependency.
+#[derive(Debug)]
+pub struct Check {
+    /// The setting declaring the dependency, such as `sources.hive`.
+    pub setting: String,
+    /// What was reached, such as an address.
+    pub target: String,
+    /// Why it cannot be used, if it cannot.
+    pub error: Option<String>,
+}
+
+/// The checks of a configuration.
+#[derive(Debug)]
+pub struct Report {
+    pub profile: Option<String>,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Whether every check passed.
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+}
+
+impl fmt::Display for Report {
+    /// A line per check, then whether the coordinator is ready to start.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.profile {
+            Some(profile) => writeln!(f, "The configuration is valid (profile {profile}).")?,
+            None => writeln!(f, "The configuration is valid.")?,
+        }
+        let width = self.checks.iter().map(|check| check.setting.len()).max().unwrap_or(0);
+        for check in &self.checks {
+            let (status, error) = match &check.error {
+                None => ("ok", String::new()),
+                Some(error) => ("failed", format!(": {error}")),
+            };
+            writeln!(f, "  {status:<6}  {:<width$}  {}{error}", check.setting, check.target)?;
+        }
+        let failed = self.checks.iter().filter(|check| check.error.is_some()).count();
+        match failed {
+            0 => write!(f, "Ready: {} of {} checks passed.", self.checks.len(), self.checks.len()),
+            _ => write!(f, "Not ready: {failed} of {} checks failed.", self.checks.len()),
+        }
+    }
+}
+
+/// Check the dependencies of `config`.
+pub async fn check(config: &Config) -> Report {
+    let mut checks = Vec::new();
+
+    let store = &config.catalog.store;
+    let result = if store.starts_with("postgres://") || store.starts_with("postgresql://") {
+        reach(async { PostgresCatalogStore::connect(store).await.map(|_| ()) }).await
+    } else {
+        let dir = Path::new(store).parent().filter(|dir| !dir.as_os_str().is_empty());
+        match dir {
+            Some(dir) if !dir.is_dir() => {
+                Err(format!("directory {} does not exist", dir.display()))
+            }
+            _ => Ok(()),
+        }
+    };
+    checks.push(Check::new("catalog.store", store, result));
+
+    if let (Some(source), Some(catalog)) =
+        (&config.sources.iceberg, crate::iceberg_catalog_from_config(config))
+    {
+        let result = reach(async { catalog.list_namespaces(None).await.map(|_| ()) }).await;
+        checks.push(Check::new("sources.iceberg", &source.uri, result));
+    }
+    if let Some(source) = &config.sources.hive {
+        let client = HiveMetastoreClient::new(source.metastore.clone()).with_timeout(TIMEOUT);
+        let result = reach(async { client.get_all_databases().await.map(|_| ()) }).await;
+        checks.push(Check::new("sources.hive", &source.metastore, result));
+    }
+    if let Some(source) = &config.sources.unity {
+        let mut client = UnityCatalog::new(source.uri.clone());
+        if let Some(token) = &source.token {
+            client = client.with_token(token.clone());
+        }
+        let result = reach(async { client.list_schemas(&source.name).await.map(|_| ()) }).await;
+        let target = format!("{} (catalog {})", source.uri, source.name);
+        checks.push(Check::new("sources.unity", &target, result));
+    }
+    if let Some(source) = &config.sources.delta_sharing {
+        let result = match SharingProfile::from_file(&source.profile) {
+            Ok(profile) => {
+                let client = SharingClient::from_profile(&profile);
+                reach(async { client.list_schemas(&source.share).await.map(|_| ()) }).await
+            }
+            Err(e) => Err(format!("profile {}: {e}", source.profile.display())),
+        };
+        let target = format!("{} (share {})", source.profile.display(), source.share);
+        checks.push(Check::new("sources.delta_sharing", &target, result));
+    }
+    for pipeline in &config.cdc.kafka {
+        // A probe reports the proxy as it is now.
+        let proxy =
+            KafkaRestClient::new(pipeline.proxy.clone()).with_retry_policy(RetryPolicy::no_retry());
+        let result = reach(async { proxy.partitions(&pipeline.topic).await.map(|_| ()) }).await;
+        let target = format!("{} (topic {})", pipeline.proxy, pipeline.topic);
+        checks.push(Check::new("cdc.kafka", &target, result));
+    }
+    for worker in &config.server.workers {
+        let result =
+            reach(async { tokio::net::TcpStream::connect(worker).await.map(|_| ()) }).await;
+        checks.push(Check::new("server.workers", worker, result));
+    }
+    if let Some(files) = &config.server.tls {
+        let result = crate::tls_from_config(config).map(|_| ()).map_err(|e| e.to_string());
+        checks.push(Check::new("server.tls", &files.cert.display().to_string(), result));
+    }
+
+    Report { profile: config.profile.clone(), checks }
+}
+
+impl Check {
+    fn new(setting: &str, target: &str, result: Result<(), String>) -> Self {
+        Self { setting: setting.to_string(), target: target.to_string(), error: result.err() }
+    }
+}
+
+/// Await `reached`, failing after [`TIMEOUT`].
+async fn reach<E: fmt::Display>(
+    reached: impl Future<Output = Result<(), E>>,
+) -> Result<(), String> {
+    match tokio::time::timeout(TIMEOUT, reached).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("no answer within {} seconds", TIMEOUT.as_secs())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeltaSharingSource, HiveSource};
+
+    #[tokio::test]
+    async fn test_report_names_what_cannot_be_reached() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let worker = listener.local_addr().unwrap().to_string();
+        // A port nothing listens on any more
+        let closed = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+
+        let mut config = Config::default();
+        config.server.workers = vec![worker.clone()];
+        let report = check(&config).await;
+        assert!(report.is_ready(), "{report}");
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "The configuration is valid.\n  \
+                 ok      catalog.store   igloo_catalog.db\n  \
+                 ok      server.workers  {worker}\n\
+                 Ready: 2 of 2 checks passed."
+            )
+        );
+
+        config.profile = Some("prod".to_string());
+        config.catalog.store = "/nonexistent/igloo/catalog.db".to_string();
+        config.sources.hive = Some(HiveSource { metastore: closed.clone() });
+        let report = check(&config).await;
+        assert!(!report.is_ready());
+        let errors: Vec<_> = report.checks.iter().map(|check| check.error.is_some()).collect();
+        assert_eq!(errors, [true, true, false]);
+        let text = report.to_string();
+        assert!(text.starts_with("The configuration is valid (profile prod).\n"), "{text}");
+        assert!(text.contains("directory /nonexistent/igloo does not exist"), "{text}");
+        assert!(text.contains(&format!("failed  sources.hive    {closed}: ")), "{text}");
+        assert!(text.ends_with("Not ready: 2 of 3 checks failed."), "{text}");
+    }
+
+    #[tokio::test]
+    async fn test_delta_sharing_profiles_are_read() {
+        let mut config = Config::default();
+        config.sources.delta_sharing = Some(DeltaSharingSource {
+            profile: "/nonexistent/config.share".into(),
+            share: "retail".to_string(),
+            name: None,
+        });
+        let report = check(&config).await;
+        let text = report.to_string();
+        assert!(
+            text.contains(
+                "failed  sources.delta_sharing  /nonexistent/config.share (share retail): \
+                 profile /nonexistent/config.share: "
+            ),
+            "{text}"
+        );
+    }
+}
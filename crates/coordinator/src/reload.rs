@@ -0,0 +1,317 @@
+//! Applying changes to the configuration while the coordinator runs.
+//!
+//! When started with a configuration file, the coordinator checks it every
+//! `server.reload_secs` and, when it was modified, loads the configuration again
+//! (the file, then the environment and flags as at startup) and applies what changed:
+//!
+//! - `logging.level`: which lines are logged;
+//! - `cache.catalog_refresh_secs`: how often the catalog is refreshed, from the next
+//!   refresh on;
+//! - `limits.queries_per_minute`, `limits.concurrent_queries` and
+//!   `limits.scanned_bytes_per_day`, if any of them was set at startup;
+//! - `sources`: sources added are registered, and compacted if so configured.
+//!
+//! Any other change (a listener address, the catalog store, a source changed or
+//! removed...) needs a restart: the whole reload is rejected, logging the settings
+//! that changed, and the running configuration is kept. So is it when the file cannot
+//! be loaded.
+
+use crate::config::{Args, Config, ConfigError, SourcesConfig};
+use igloo_api::quota::{QuotaLimiter, Quotas};
+use igloo_api::supervisor::{RestartPolicy, Stop, Supervisor};
+use igloo_common::logging::LogFilter;
+use igloo_common::secrets::Secrets;
+use igloo_engine::QueryEngine;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+/// What of the running coordinator a reload changes.
+pub struct Live {
+    pub engine: Arc<QueryEngine>,
+    pub log_filter: LogFilter,
+    /// Seconds between refreshes of the catalog.
+    pub catalog_refresh_secs: Arc<AtomicU64>,
+    /// The limiter of the frontends, if rate limits were configured at startup.
+    pub quotas: Option<Arc<QuotaLimiter>>,
+    /// Resolves the secrets the reloaded configuration references.
+    pub secrets: Secrets,
+    /// Runs the compaction of the sources added.
+    pub supervisor: Arc<Supervisor>,
+}
+
+/// The changes a reload applies.
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub log_level: Option<String>,
+    pub catalog_refresh_secs: Option<u64>,
+    pub quotas: Option<Quotas>,
+    /// The sources added.
+    pub sources: SourcesConfig,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for Changes {
+    /// The settings changed, such as `logging.level, sources.hive`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let settings = [
+            ("logging.level", self.log_level.is_some()),
+            ("cache.catalog_refresh_secs", self.catalog_refresh_secs.is_some()),
+            ("limits", self.quotas.is_some()),
+            ("sources.iceberg", self.sources.iceberg.is_some()),
+            ("sources.hive", self.sources.hive.is_some()),
+            ("sources.unity", self.sources.unity.is_some()),
+            ("sources.delta_sharing", self.sources.delta_sharing.is_some()),
+        ];
+        let changed: Vec<_> =
+            settings.iter().filter(|(_, changed)| *changed).map(|(name, _)| *name).collect();
+        write!(f, "{}", changed.join(", "))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error("{} changed, which takes a restart to apply", .0.join(", "))]
+    NeedsRestart(Vec<String>),
+    #[error("cannot apply {setting}: {message}")]
+    Apply { setting: &'static str, message: String },
+}
+
+/// The changes from `running` to `new`, or the settings changed that take a restart.
+pub fn changes(running: &Config, new: &Config) -> Result<Changes, ReloadError> {
+    let mut restart = Vec::new();
+    let mut check = |setting: &str, same: bool| {
+        if !same {
+            restart.push(setting.to_string());
+        }
+    };
+    check("server", running.server == new.server);
+    check("logging.format", running.logging.format == new.logging.format);
+    check("catalog", running.catalog == new.catalog);
+    check("cdc", running.cdc == new.cdc);
+    check("quality", running.quality == new.quality);
+    check("retention", running.retention == new.retention);
+    check("tenants", running.tenants == new.tenants);
+    check("auth", running.auth == new.auth);
+    check("audit", running.audit == new.audit);
+    check("secrets", running.secrets == new.secrets);
+    check("joins", running.joins == new.joins);
+    check("scans", running.scans == new.scans);
+    let (limits, new_limits) = (&running.limits, &new.limits);
+    check("limits.admission_slots", limits.admission_slots == new_limits.admission_slots);
+    check(
+        "limits.priority_principals",
+        limits.priority_principals == new_limits.priority_principals,
+    );
+    check("limits.resource_classes", limits.resource_classes == new_limits.resource_classes);
+    check(
+        "limits.resource_principals",
+        limits.resource_principals == new_limits.resource_principals,
+    );
+    check("limits.cpu_slots", limits.cpu_slots == new_limits.cpu_slots);
+    let quotas = (limits.quotas() != new_limits.quotas()).then(|| new_limits.quotas());
+    // Without rate limits at startup, the frontends have no limiter to change.
+    check("limits (rate limits were off at startup)", quotas.is_none() || limits.has_quotas());
+
+    let mut sources = SourcesConfig::default();
+    added("sources.iceberg", &running.sources.iceberg, &new.sources.iceberg, &mut sources.iceberg)
+        .unwrap_or_else(|setting| restart.push(setting));
+    added("sources.hive", &running.sources.hive, &new.sources.hive, &mut sources.hive)
+        .unwrap_or_else(|setting| restart.push(setting));
+    added("sources.unity", &running.sources.unity, &new.sources.unity, &mut sources.unity)
+        .unwrap_or_else(|setting| restart.push(setting));
+    let delta_sharing = (&running.sources.delta_sharing, &new.sources.delta_sharing);
+    added("sources.delta_sharing", delta_sharing.0, delta_sharing.1, &mut sources.delta_sharing)
+        .unwrap_or_else(|setting| restart.push(setting));
+    if !restart.is_empty() {
+        return Err(ReloadError::NeedsRestart(restart));
+    }
+
+    Ok(Changes {
+        log_level: (running.logging.level != new.logging.level).then(|| new.logging.level.clone()),
+        catalog_refresh_secs: (running.cache.catalog_refresh_secs
+            != new.cache.catalog_refresh_secs)
+            .then_some(new.cache.catalog_refresh_secs),
+        quotas,
+        sources,
+    })
+}
+
+/// Sets `added` to the source `new` if it was added, and fails if it was changed or
+/// removed.
+fn added<T: Clone + PartialEq>(
+    setting: &str,
+    running: &Option<T>,
+    new: &Option<T>,
+    added: &mut Option<T>,
+) -> Result<(), String> {
+    match (running, new) {
+        (None, Some(new)) => *added = Some(new.clone()),
+        (running, new) if running != new => return Err(setting.to_string()),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Reloads the configuration of `args` and applies its changes to the coordinator.
+pub struct Reloader {
+    args: Args,
+    running: Config,
+    live: Live,
+}
+
+impl Reloader {
+    /// Changes `live` as the configuration loaded for `args` changes from `running`.
+    pub fn new(args: Args, running: Config, live: Live) -> Self {
+        Self { args, running, live }
+    }
+
+    /// Load the configuration and apply what changed.
+    pub async fn reload(&mut self) -> Result<Changes, ReloadError> {
+        let mut config = Config::load(&self.args)?;
+        config.resolve_secrets(&self.live.secrets).await?;
+        let changes = changes(&self.running, &config)?;
+        self.apply(&changes).await?;
+        self.running = config;
+        Ok(changes)
+    }
+
+    /// Changes applied before one fails stay applied; they are applied again with the
+    /// rest at the next reload.
+    async fn apply(&self, changes: &Changes) -> Result<(), ReloadError> {
+        if let Some(level) = &changes.log_level {
+            self.live.log_filter.set(level).map_err(|e| ReloadError::Apply {
+                setting: "logging.level",
+                message: e.to_string(),
+            })?;
+        }
+        if let Some(secs) = changes.catalog_refresh_secs {
+            self.live.catalog_refresh_secs.store(secs, Ordering::Relaxed);
+        }
+        if let (Some(quotas), Some(limiter)) = (&changes.quotas, &self.live.quotas) {
+            limiter.set_quotas(*quotas);
+        }
+        let sources = Config {
+            server: self.running.server.clone(),
+            sources: changes.sources.clone(),
+            ..Config::default()
+        };
+        let iceberg = crate::register_sources(&sources, &self.live.engine)
+            .await
+            .map_err(|e| ReloadError::Apply { setting: "sources", message: e.to_string() })?;
+        if let Some(catalog) = iceberg {
+            if let Some(secs) = sources.sources.iceberg.as_ref().and_then(|i| i.compaction_secs) {
+                let period = Duration::from_secs(secs);
+                crate::spawn_compaction(&self.live.supervisor, &self.live.engine, catalog, period);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `file` every `period`, reloading when it was modified, as the
+    /// `config_reload` component of `supervisor`.
+    pub fn spawn(self, supervisor: &Supervisor, file: PathBuf, period: Duration) {
+        info!("Applying changes to the configuration file every {} seconds.", period.as_secs());
+        let reloader = Arc::new(tokio::sync::Mutex::new(self));
+        supervisor.spawn("config_reload", RestartPolicy::Always, move |stop: Stop| {
+            let reloader = reloader.clone();
+            let file = file.clone();
+            async move {
+                let modified = || -> Option<SystemTime> { file.metadata().ok()?.modified().ok() };
+                let mut reloader = reloader.lock().await;
+                let mut seen = modified();
+                let mut interval = tokio::time::interval(period);
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = stop.clone().stopped() => return Ok::<_, ReloadError>(()),
+                    }
+                    let now = modified();
+                    if now == seen {
+                        continue;
+                    }
+                    seen = now;
+                    match reloader.reload().await {
+                        Ok(changes) if changes.is_empty() => {
+                            info!("The configuration file changed, but nothing to apply.")
+                        }
+                        Ok(changes) => info!(%changes, "Applied the changed configuration."),
+                        Err(e) => error!(
+                            error = %e,
+                            "Rejected the changed configuration; keeping the running one."
+                        ),
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HiveSource;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_safe_changes_are_applied() {
+        let running = config("[limits]\nqueries_per_minute = 60\n");
+        let new = config(
+            r#"
+            [logging]
+            level = "debug"
+            [cache]
+            catalog_refresh_secs = 30
+            [limits]
+            queries_per_minute = 120
+            [sources.hive]
+            metastore = "thrift://hms:9083"
+            "#,
+        );
+        let changes = changes(&running, &new).unwrap();
+        assert_eq!(changes.log_level.as_deref(), Some("debug"));
+        assert_eq!(changes.catalog_refresh_secs, Some(30));
+        assert_eq!(changes.quotas, Some(Quotas::new().with_queries_per_minute(120)));
+        let hive = HiveSource { metastore: "thrift://hms:9083".to_string() };
+        assert_eq!(changes.sources.hive, Some(hive));
+        assert_eq!(
+            changes.to_string(),
+            "logging.level, cache.catalog_refresh_secs, limits, sources.hive"
+        );
+        assert!(super::changes(&new, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unsafe_changes_are_rejected() {
+        let running = config("[sources.hive]\nmetastore = \"thrift://hms:9083\"\n");
+        let new = config(
+            r#"
+            [server]
+            http_addr = "0.0.0.0:8080"
+            [logging]
+            level = "debug"
+            [limits]
+            concurrent_queries = 4
+            "#,
+        );
+        let error = changes(&running, &new).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "server, limits (rate limits were off at startup), sources.hive changed, which \
+             takes a restart to apply"
+        );
+    }
+}
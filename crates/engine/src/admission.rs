@@ -0,0 +1,268 @@
+//! Admission of queries by priority class.
+//!
+//! An [`AdmissionQueue`] lets a fixed number of queries run at once. Queries beyond
+//! that wait in one queue per [`Priority`], first come first served within a class.
+//! A freed slot goes to the waiting class that has been admitted least for its
+//! weight, so with the default weights interactive queries (dashboards) get 16 slots
+//! for every 4 of batch queries (exports, asynchronous jobs) and 1 of background work
+//! (materialized view refreshes), and no class is starved.
+//!
+//! The priority of a query is the one its session asks for (the `priority` session
+//! variable, see [`crate::session`]) or its frontend's default, but never higher than
+//! the one configured for its principal.
+
+use datafusion::error::DataFusionError;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::oneshot;
+
+/// Priority classes, from the highest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Batch,
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Batch, Priority::Background];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Batch => "batch",
+            Priority::Background => "background",
+        }
+    }
+
+    /// Admissions of the class for each one of a class of weight 1, when both wait.
+    pub fn default_weight(&self) -> u32 {
+        match self {
+            Priority::Interactive => 16,
+            Priority::Batch => 4,
+            Priority::Background => 1,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Priority {
+    type Err = DataFusionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(s.trim())).ok_or_else(
+            || {
+                DataFusionError::Plan(format!(
+                    "invalid priority '{s}', expected interactive, batch or background"
+                ))
+            },
+        )
+    }
+}
+
+/// Admits queries `slots` at a time, by weighted fair share between priority classes.
+#[derive(Debug)]
+pub struct AdmissionQueue {
+    slots: usize,
+    weights: [u32; 3],
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: usize,
+    waiting: [VecDeque<oneshot::Sender<()>>; 3],
+    /// Admissions of each class divided by its weight.
+    pass: [f64; 3],
+    /// Pass of the latest admission, where classes that start waiting catch up to.
+    now: f64,
+}
+
+impl AdmissionQueue {
+    /// A queue running up to `slots` queries at once, with the default weights.
+    pub fn new(slots: usize) -> Self {
+        Self {
+            slots: slots.max(1),
+            weights: Priority::ALL.map(|p| p.default_weight()),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Admit `priority` queries `weight` times (at least 1) as often as a class of
+    /// weight 1 when both wait.
+    pub fn with_weight(mut self, priority: Priority, weight: u32) -> Self {
+        self.weights[priority.index()] = weight.max(1);
+        self
+    }
+
+    /// Wait for a slot for a query of class `priority`. It is held until the returned
+    /// [`Admission`] is dropped.
+    pub async fn admit(self: &Arc<Self>, priority: Priority) -> Admission {
+        let class = priority.index();
+        let woken = {
+            let mut state = self.lock();
+            if state.waiting[class].is_empty() {
+                // Credit is not saved up while a class has nothing waiting.
+                state.pass[class] = state.pass[class].max(state.now);
+            }
+            if state.running < self.slots && state.waiting.iter().all(VecDeque::is_empty) {
+                state.running += 1;
+                self.charge(&mut state, class);
+                return Admission { queue: Arc::clone(self) };
+            }
+            let (wake, woken) = oneshot::channel();
+            state.waiting[class].push_back(wake);
+            Waiting { queue: Arc::clone(self), woken }
+        };
+        woken.wait().await;
+        Admission { queue: Arc::clone(self) }
+    }
+
+    /// Queries of class `priority` waiting for a slot.
+    pub fn queued(&self, priority: Priority) -> usize {
+        self.lock().waiting[priority.index()].len()
+    }
+
+    /// Queries holding a slot.
+    pub fn running(&self) -> usize {
+        self.lock().running
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("admission lock poisoned")
+    }
+
+    fn charge(&self, state: &mut State, class: usize) {
+        state.now = state.now.max(state.pass[class]);
+        state.pass[class] += 1.0 / self.weights[class] as f64;
+    }
+
+    /// Free a slot, handing it to the next waiting query if there is one.
+    fn release(&self) {
+        let mut state = self.lock();
+        loop {
+            let next = (0..3)
+                .filter(|&class| !state.waiting[class].is_empty())
+                .min_by(|&a, &b| state.pass[a].total_cmp(&state.pass[b]));
+            let Some(class) = next else {
+                state.running -= 1;
+                return;
+            };
+            let wake = state.waiting[class].pop_front().expect("class has waiters");
+            // A query that gave up waiting no longer needs the slot.
+            if wake.send(()).is_ok() {
+                self.charge(&mut state, class);
+                return;
+            }
+        }
+    }
+}
+
+/// A slot being waited for. Dropped after the slot was handed over but before the
+/// query woke up, it passes the slot on.
+struct Waiting {
+    queue: Arc<AdmissionQueue>,
+    woken: oneshot::Receiver<()>,
+}
+
+impl Waiting {
+    async fn wait(mut self) {
+        // The queue outlives its waiters, so the sender is never dropped unsent.
+        let _ = (&mut self.woken).await;
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.woken.try_recv().is_ok() {
+            self.queue.release();
+        }
+    }
+}
+
+/// A query's slot in an [`AdmissionQueue`], freed when dropped.
+#[derive(Debug)]
+pub struct Admission {
+    queue: Arc<AdmissionQueue>,
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!("Batch".parse::<Priority>().unwrap(), Priority::Batch);
+        assert!("urgent".parse::<Priority>().is_err());
+        assert!(Priority::Interactive < Priority::Background);
+    }
+
+    #[tokio::test]
+    async fn test_classes_are_admitted_by_weight() {
+        let queue = Arc::new(
+            AdmissionQueue::new(1)
+                .with_weight(Priority::Interactive, 3)
+                .with_weight(Priority::Batch, 1),
+        );
+        let running = queue.admit(Priority::Batch).await;
+        let (order, mut admitted) = tokio::sync::mpsc::unbounded_channel();
+        for (i, priority) in
+            [Priority::Batch; 4].into_iter().chain([Priority::Interactive; 8]).enumerate()
+        {
+            let (waiter, order) = (Arc::clone(&queue), order.clone());
+            tokio::spawn(async move {
+                let _admission = waiter.admit(priority).await;
+                order.send(priority).unwrap();
+            });
+            // Queue them in order.
+            while queue.queued(Priority::Batch) + queue.queued(Priority::Interactive) <= i {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }
+        drop(running);
+        let mut classes = Vec::new();
+        for _ in 0..12 {
+            classes.push(admitted.recv().await.unwrap().name().chars().next().unwrap());
+        }
+        assert_eq!(classes.into_iter().collect::<String>(), "iiiibiiibibb");
+        assert_eq!(queue.running(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_abandoned_waiters_pass_their_slot_on() {
+        let queue = Arc::new(AdmissionQueue::new(1));
+        let running = queue.admit(Priority::Interactive).await;
+        let abandoned = tokio::spawn({
+            let queue = Arc::clone(&queue);
+            async move { queue.admit(Priority::Interactive).await }
+        });
+        while queue.queued(Priority::Interactive) == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        abandoned.abort();
+        drop(running);
+        let _admission = tokio::time::timeout(Duration::from_secs(5), queue.admit(Priority::Batch))
+            .await
+            .expect("slot was freed");
+        assert_eq!(queue.running(), 1);
+    }
+}
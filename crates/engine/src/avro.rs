@@ -0,0 +1,795 @@
+//! Reading Avro object container files.
+//!
+//! [`AvroFormat`] reads Avro files as listing tables, like DataFusion's formats read
+//! Parquet, CSV and JSON ones: `CREATE EXTERNAL TABLE ... STORED AS AVRO`, or
+//! [`ListingOptions`](datafusion::datasource::listing::ListingOptions) of it. Files
+//! hold records, whose fields are the columns; the table's schema is that of the
+//! files' writer schemas merged. Files compressed with the `deflate`, `snappy` or
+//! `zstandard` codecs are read whole, each by one partition.
+//!
+//! Avro types are read as the Arrow types of the same values: `int` and `long` as
+//! `Int32` and `Int64`, `enum` and `uuid` as `Utf8`, `fixed` as `FixedSizeBinary`,
+//! `decimal` (of precision up to 38) as `Decimal128`, timestamps as `Timestamp` (in
+//! UTC unless local), and arrays, maps and records as `List`, `Map` and `Struct`. A
+//! union of `null` and another type is a nullable column of the other; other unions,
+//! `duration`, big decimals and recursive types are not supported.
+//!
+//! ```sql
+//! CREATE EXTERNAL TABLE events STORED AS AVRO LOCATION 's3://lake/events/';
+//! ```
+
+use apache_avro::schema::{RecordField, Schema as AvroSchema};
+use apache_avro::types::Value;
+use apache_avro::Reader;
+use async_trait::async_trait;
+use datafusion::arrow::array::{
+    ArrayRef, ArrowPrimitiveType, BinaryArray, BooleanArray, FixedSizeBinaryArray, ListArray,
+    MapArray, NullArray, PrimitiveArray, RecordBatch, RecordBatchOptions, StringArray, StructArray,
+};
+use datafusion::arrow::buffer::{NullBuffer, OffsetBuffer};
+use datafusion::arrow::datatypes::{
+    DataType, Date32Type, Decimal128Type, Field, Fields, Float32Type, Float64Type, Int32Type,
+    Int64Type, Schema, SchemaRef, Time32MillisecondType, Time64MicrosecondType, TimeUnit,
+    TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType,
+};
+use datafusion::arrow::error::ArrowError;
+use datafusion::catalog::Session;
+use datafusion::common::{GetExt, Statistics};
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::datasource::file_format::{FileFormat, FileFormatFactory};
+use datafusion::datasource::physical_plan::{
+    FileMeta, FileOpenFuture, FileOpener, FileScanConfig, FileScanConfigBuilder, FileSource,
+};
+use datafusion::datasource::schema_adapter::SchemaAdapterFactory;
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::physical_expr::LexOrdering;
+use datafusion::physical_plan::metrics::ExecutionPlanMetricsSet;
+use datafusion::physical_plan::ExecutionPlan;
+use futures::StreamExt;
+use object_store::{ObjectMeta, ObjectStore};
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+pub const DEFAULT_AVRO_EXTENSION: &str = ".avro";
+
+/// Makes [`AvroFormat`]s, for `STORED AS AVRO`.
+#[derive(Debug, Default)]
+pub struct AvroFormatFactory;
+
+impl FileFormatFactory for AvroFormatFactory {
+    fn create(
+        &self,
+        _state: &dyn Session,
+        _format_options: &HashMap<String, String>,
+    ) -> DataFusionResult<Arc<dyn FileFormat>> {
+        Ok(Arc::new(AvroFormat))
+    }
+
+    fn default(&self) -> Arc<dyn FileFormat> {
+        Arc::new(AvroFormat)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl GetExt for AvroFormatFactory {
+    fn get_ext(&self) -> String {
+        DEFAULT_AVRO_EXTENSION[1..].to_string()
+    }
+}
+
+/// Avro object container files, see the [module docs](self).
+#[derive(Debug, Default)]
+pub struct AvroFormat;
+
+#[async_trait]
+impl FileFormat for AvroFormat {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn get_ext(&self) -> String {
+        AvroFormatFactory.get_ext()
+    }
+
+    fn get_ext_with_compression(
+        &self,
+        file_compression_type: &FileCompressionType,
+    ) -> DataFusionResult<String> {
+        match file_compression_type.is_compressed() {
+            // Avro files compress their blocks themselves.
+            true => Err(DataFusionError::NotImplemented(
+                "Avro files are compressed by their codec, not as a whole".to_string(),
+            )),
+            false => Ok(self.get_ext()),
+        }
+    }
+
+    async fn infer_schema(
+        &self,
+        _state: &dyn Session,
+        store: &Arc<dyn ObjectStore>,
+        objects: &[ObjectMeta],
+    ) -> DataFusionResult<SchemaRef> {
+        let mut schemas = Vec::with_capacity(objects.len());
+        for object in objects {
+            let bytes = store.get(&object.location).await?.bytes().await?;
+            let reader = Reader::new(Cursor::new(bytes)).map_err(avro_error)?;
+            schemas.push(arrow_schema(reader.writer_schema())?);
+        }
+        Ok(Arc::new(Schema::try_merge(schemas)?))
+    }
+
+    async fn infer_stats(
+        &self,
+        _state: &dyn Session,
+        _store: &Arc<dyn ObjectStore>,
+        table_schema: SchemaRef,
+        _object: &ObjectMeta,
+    ) -> DataFusionResult<Statistics> {
+        Ok(Statistics::new_unknown(&table_schema))
+    }
+
+    async fn create_physical_plan(
+        &self,
+        _state: &dyn Session,
+        conf: FileScanConfig,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let conf = FileScanConfigBuilder::from(conf).with_source(self.file_source()).build();
+        Ok(DataSourceExec::from_data_source(conf))
+    }
+
+    fn file_source(&self) -> Arc<dyn FileSource> {
+        Arc::new(AvroSource::default())
+    }
+}
+
+/// Opens [`AvroOpener`]s.
+#[derive(Debug, Clone, Default)]
+struct AvroSource {
+    batch_size: Option<usize>,
+    metrics: ExecutionPlanMetricsSet,
+    projected_statistics: Option<Statistics>,
+    schema_adapter_factory: Option<Arc<dyn SchemaAdapterFactory>>,
+}
+
+impl FileSource for AvroSource {
+    fn create_file_opener(
+        &self,
+        store: Arc<dyn ObjectStore>,
+        config: &FileScanConfig,
+        _partition: usize,
+    ) -> Arc<dyn FileOpener> {
+        Arc::new(AvroOpener {
+            store,
+            schema: config.projected_file_schema(),
+            batch_size: self.batch_size.unwrap_or(8192),
+        })
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn with_batch_size(&self, batch_size: usize) -> Arc<dyn FileSource> {
+        Arc::new(Self { batch_size: Some(batch_size), ..self.clone() })
+    }
+
+    fn with_schema(&self, _schema: SchemaRef) -> Arc<dyn FileSource> {
+        Arc::new(self.clone())
+    }
+
+    fn with_projection(&self, _config: &FileScanConfig) -> Arc<dyn FileSource> {
+        Arc::new(self.clone())
+    }
+
+    fn with_statistics(&self, statistics: Statistics) -> Arc<dyn FileSource> {
+        Arc::new(Self { projected_statistics: Some(statistics), ..self.clone() })
+    }
+
+    fn metrics(&self) -> &ExecutionPlanMetricsSet {
+        &self.metrics
+    }
+
+    fn statistics(&self) -> DataFusionResult<Statistics> {
+        self.projected_statistics.clone().ok_or_else(|| {
+            DataFusionError::Internal("the statistics of Avro scans are not set".to_string())
+        })
+    }
+
+    fn file_type(&self) -> &str {
+        "avro"
+    }
+
+    /// Files are read whole: blocks are found from the start.
+    fn repartitioned(
+        &self,
+        _target_partitions: usize,
+        _repartition_file_min_size: usize,
+        _output_ordering: Option<LexOrdering>,
+        _config: &FileScanConfig,
+    ) -> DataFusionResult<Option<FileScanConfig>> {
+        Ok(None)
+    }
+
+    fn with_schema_adapter_factory(
+        &self,
+        schema_adapter_factory: Arc<dyn SchemaAdapterFactory>,
+    ) -> DataFusionResult<Arc<dyn FileSource>> {
+        Ok(Arc::new(Self { schema_adapter_factory: Some(schema_adapter_factory), ..self.clone() }))
+    }
+
+    fn schema_adapter_factory(&self) -> Option<Arc<dyn SchemaAdapterFactory>> {
+        self.schema_adapter_factory.clone()
+    }
+}
+
+/// Reads the projected columns of files, `batch_size` records at a time.
+struct AvroOpener {
+    store: Arc<dyn ObjectStore>,
+    schema: SchemaRef,
+    batch_size: usize,
+}
+
+impl FileOpener for AvroOpener {
+    fn open(&self, file_meta: FileMeta) -> DataFusionResult<FileOpenFuture> {
+        let store = Arc::clone(&self.store);
+        let schema = Arc::clone(&self.schema);
+        let batch_size = self.batch_size;
+        Ok(Box::pin(async move {
+            let bytes = store.get(file_meta.location()).await?.bytes().await?;
+            let mut reader = Reader::new(Cursor::new(bytes)).map_err(avro_error)?;
+            let batches = std::iter::from_fn(move || {
+                let records = match reader.by_ref().take(batch_size).collect::<Result<Vec<_>, _>>()
+                {
+                    Ok(records) if records.is_empty() => return None,
+                    Ok(records) => records,
+                    Err(e) => return Some(Err(ArrowError::ExternalError(Box::new(e)))),
+                };
+                Some(record_batch(&schema, &records))
+            });
+            Ok(futures::stream::iter(batches).boxed())
+        }))
+    }
+}
+
+/// The Arrow schema of the records of Avro `schema`.
+pub fn arrow_schema(schema: &AvroSchema) -> Result<Schema, ArrowError> {
+    match data_type(schema, &mut HashMap::new())? {
+        DataType::Struct(fields) => Ok(Schema::new(fields)),
+        _ => Err(ArrowError::NotYetImplemented(format!(
+            "Avro files of {} values, not records, are not supported",
+            type_name(schema)
+        ))),
+    }
+}
+
+/// The field of values of `schema`, nullable for a union with `null`.
+fn field(
+    name: &str,
+    schema: &AvroSchema,
+    named: &mut HashMap<String, DataType>,
+) -> Result<Field, ArrowError> {
+    match schema {
+        AvroSchema::Null => Ok(Field::new(name, DataType::Null, true)),
+        AvroSchema::Union(union) => {
+            let variants: Vec<_> =
+                union.variants().iter().filter(|variant| **variant != AvroSchema::Null).collect();
+            match variants[..] {
+                [] => Ok(Field::new(name, DataType::Null, true)),
+                [variant] if union.is_nullable() => {
+                    Ok(Field::new(name, data_type(variant, named)?, true))
+                }
+                _ => Err(ArrowError::NotYetImplemented(format!(
+                    "the Avro union of {} of {name} is not supported",
+                    union.variants().iter().map(type_name).collect::<Vec<_>>().join(", ")
+                ))),
+            }
+        }
+        _ => Ok(Field::new(name, data_type(schema, named)?, false)),
+    }
+}
+
+/// The Arrow type of values of `schema`, given those of the named types before it.
+fn data_type(
+    schema: &AvroSchema,
+    named: &mut HashMap<String, DataType>,
+) -> Result<DataType, ArrowError> {
+    let unsupported = || {
+        ArrowError::NotYetImplemented(format!("Avro type {} is not supported", type_name(schema)))
+    };
+    let data_type = match schema {
+        AvroSchema::Null => DataType::Null,
+        AvroSchema::Boolean => DataType::Boolean,
+        AvroSchema::Int => DataType::Int32,
+        AvroSchema::Long => DataType::Int64,
+        AvroSchema::Float => DataType::Float32,
+        AvroSchema::Double => DataType::Float64,
+        AvroSchema::Bytes => DataType::Binary,
+        AvroSchema::String | AvroSchema::Uuid => DataType::Utf8,
+        AvroSchema::Enum(schema) => {
+            named.insert(schema.name.fullname(None), DataType::Utf8);
+            DataType::Utf8
+        }
+        AvroSchema::Fixed(schema) => {
+            let size = i32::try_from(schema.size).map_err(|_| unsupported())?;
+            named.insert(schema.name.fullname(None), DataType::FixedSizeBinary(size));
+            DataType::FixedSizeBinary(size)
+        }
+        AvroSchema::Decimal(schema) => {
+            match (u8::try_from(schema.precision), i8::try_from(schema.scale)) {
+                (Ok(precision @ 1..=38), Ok(scale)) => DataType::Decimal128(precision, scale),
+                _ => return Err(unsupported()),
+            }
+        }
+        AvroSchema::Date => DataType::Date32,
+        AvroSchema::TimeMillis => DataType::Time32(TimeUnit::Millisecond),
+        AvroSchema::TimeMicros => DataType::Time64(TimeUnit::Microsecond),
+        AvroSchema::TimestampMillis => {
+            DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into()))
+        }
+        AvroSchema::TimestampMicros => {
+            DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into()))
+        }
+        AvroSchema::TimestampNanos => {
+            DataType::Timestamp(TimeUnit::Nanosecond, Some("+00:00".into()))
+        }
+        AvroSchema::LocalTimestampMillis => DataType::Timestamp(TimeUnit::Millisecond, None),
+        AvroSchema::LocalTimestampMicros => DataType::Timestamp(TimeUnit::Microsecond, None),
+        AvroSchema::LocalTimestampNanos => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        AvroSchema::Array(schema) => DataType::List(Arc::new(field("item", &schema.items, named)?)),
+        AvroSchema::Map(schema) => {
+            let entries = Fields::from(vec![
+                Field::new("key", DataType::Utf8, false),
+                field("value", &schema.types, named)?,
+            ]);
+            DataType::Map(Arc::new(Field::new("entries", DataType::Struct(entries), false)), false)
+        }
+        AvroSchema::Record(schema) => {
+            let fields = schema
+                .fields
+                .iter()
+                .map(|RecordField { name, schema, .. }| field(name, schema, named))
+                .collect::<Result<Fields, _>>()?;
+            named.insert(schema.name.fullname(None), DataType::Struct(fields.clone()));
+            DataType::Struct(fields)
+        }
+        // Named types are referred to once defined, unless they are recursive.
+        AvroSchema::Ref { name } => {
+            return named.get(&name.fullname(None)).cloned().ok_or_else(unsupported)
+        }
+        AvroSchema::Union(_) | AvroSchema::BigDecimal | AvroSchema::Duration => {
+            return Err(unsupported())
+        }
+    };
+    Ok(data_type)
+}
+
+/// The name of the type of `schema`, for errors.
+fn type_name(schema: &AvroSchema) -> String {
+    match schema {
+        AvroSchema::Array(_) => "array".to_string(),
+        AvroSchema::Map(_) => "map".to_string(),
+        AvroSchema::Union(_) => "union".to_string(),
+        AvroSchema::Record(schema) => schema.name.fullname(None),
+        AvroSchema::Enum(schema) => schema.name.fullname(None),
+        AvroSchema::Fixed(schema) => schema.name.fullname(None),
+        AvroSchema::Ref { name } => name.fullname(None),
+        AvroSchema::Null => "null".to_string(),
+        AvroSchema::Boolean => "boolean".to_string(),
+        AvroSchema::Int => "int".to_string(),
+        AvroSchema::Long => "long".to_string(),
+        AvroSchema::Float => "float".to_string(),
+        AvroSchema::Double => "double".to_string(),
+        AvroSchema::Bytes => "bytes".to_string(),
+        AvroSchema::String => "string".to_string(),
+        AvroSchema::Decimal(_) | AvroSchema::BigDecimal => "decimal".to_string(),
+        AvroSchema::Uuid => "uuid".to_string(),
+        AvroSchema::Date => "date".to_string(),
+        AvroSchema::TimeMillis => "time-millis".to_string(),
+        AvroSchema::TimeMicros => "time-micros".to_string(),
+        AvroSchema::TimestampMillis => "timestamp-millis".to_string(),
+        AvroSchema::TimestampMicros => "timestamp-micros".to_string(),
+        AvroSchema::TimestampNanos => "timestamp-nanos".to_string(),
+        AvroSchema::LocalTimestampMillis => "local-timestamp-millis".to_string(),
+        AvroSchema::LocalTimestampMicros => "local-timestamp-micros".to_string(),
+        AvroSchema::LocalTimestampNanos => "local-timestamp-nanos".to_string(),
+        AvroSchema::Duration => "duration".to_string(),
+    }
+}
+
+/// The batch of the columns of `schema` of `records`.
+fn record_batch(schema: &SchemaRef, records: &[Value]) -> Result<RecordBatch, ArrowError> {
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            let values: Vec<_> =
+                records.iter().map(|record| field_of(record, field.name())).collect();
+            array(field.data_type(), &values)
+        })
+        .collect::<Result<_, _>>()?;
+    let options = RecordBatchOptions::new().with_row_count(Some(records.len()));
+    RecordBatch::try_new_with_options(Arc::clone(schema), columns, &options)
+}
+
+/// The value of the field `name` of `record`, if it has one.
+fn field_of<'a>(record: &'a Value, name: &str) -> Option<&'a Value> {
+    match record {
+        Value::Record(fields) => fields.iter().find(|(field, _)| field == name).map(|(_, v)| v),
+        _ => None,
+    }
+}
+
+/// The array of `values` of `data_type`, `None` or `null` being null.
+fn array(data_type: &DataType, values: &[Option<&Value>]) -> Result<ArrayRef, ArrowError> {
+    let values: Vec<_> = values.iter().map(|value| value.and_then(non_null)).collect();
+    let values = values.as_slice();
+    let mismatch = |value: &Value| {
+        ArrowError::ParseError(format!("Avro value {value:?} is not of type {data_type}"))
+    };
+    let array: ArrayRef = match data_type {
+        DataType::Null => Arc::new(NullArray::new(values.len())),
+        DataType::Boolean => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(Value::Boolean(b)) => Ok(Some(*b)),
+                    Some(value) => Err(mismatch(value)),
+                    None => Ok(None),
+                })
+                .collect::<Result<BooleanArray, _>>()?,
+        ),
+        DataType::Int32 => primitive::<Int32Type>(data_type, values, |value| match value {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        })?,
+        DataType::Int64 => primitive::<Int64Type>(data_type, values, |value| match value {
+            Value::Long(i) => Some(*i),
+            _ => None,
+        })?,
+        DataType::Float32 => primitive::<Float32Type>(data_type, values, |value| match value {
+            Value::Float(f) => Some(*f),
+            _ => None,
+        })?,
+        DataType::Float64 => primitive::<Float64Type>(data_type, values, |value| match value {
+            Value::Double(f) => Some(*f),
+            _ => None,
+        })?,
+        DataType::Date32 => primitive::<Date32Type>(data_type, values, |value| match value {
+            Value::Date(days) => Some(*days),
+            _ => None,
+        })?,
+        DataType::Time32(TimeUnit::Millisecond) => {
+            primitive::<Time32MillisecondType>(data_type, values, |value| match value {
+                Value::TimeMillis(ms) => Some(*ms),
+                _ => None,
+            })?
+        }
+        DataType::Time64(TimeUnit::Microsecond) => {
+            primitive::<Time64MicrosecondType>(data_type, values, |value| match value {
+                Value::TimeMicros(us) => Some(*us),
+                _ => None,
+            })?
+        }
+        DataType::Timestamp(TimeUnit::Millisecond, _) => {
+            primitive::<TimestampMillisecondType>(data_type, values, |value| match value {
+                Value::TimestampMillis(ms) | Value::LocalTimestampMillis(ms) => Some(*ms),
+                _ => None,
+            })?
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            primitive::<TimestampMicrosecondType>(data_type, values, |value| match value {
+                Value::TimestampMicros(us) | Value::LocalTimestampMicros(us) => Some(*us),
+                _ => None,
+            })?
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            primitive::<TimestampNanosecondType>(data_type, values, |value| match value {
+                Value::TimestampNanos(ns) | Value::LocalTimestampNanos(ns) => Some(*ns),
+                _ => None,
+            })?
+        }
+        DataType::Decimal128(..) => {
+            primitive::<Decimal128Type>(data_type, values, |value| match value {
+                Value::Decimal(decimal) => Vec::<u8>::try_from(decimal).ok().and_then(|bytes| {
+                    // Big-endian two's complement, of 16 bytes at most.
+                    let sign = if bytes.first().is_some_and(|b| b & 0x80 != 0) { 0xff } else { 0 };
+                    let mut be = [sign; 16];
+                    let start = 16usize.checked_sub(bytes.len())?;
+                    be[start..].copy_from_slice(&bytes);
+                    Some(i128::from_be_bytes(be))
+                }),
+                _ => None,
+            })?
+        }
+        DataType::Binary => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(Value::Bytes(bytes) | Value::Fixed(_, bytes)) => {
+                        Ok(Some(bytes.as_slice()))
+                    }
+                    Some(value) => Err(mismatch(value)),
+                    None => Ok(None),
+                })
+                .collect::<Result<BinaryArray, _>>()?,
+        ),
+        DataType::FixedSizeBinary(size) => {
+            let bytes = values
+                .iter()
+                .map(|value| match value {
+                    Some(Value::Fixed(_, bytes)) => Ok(Some(bytes.as_slice())),
+                    Some(value) => Err(mismatch(value)),
+                    None => Ok(None),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Arc::new(FixedSizeBinaryArray::try_from_sparse_iter_with_size(
+                bytes.into_iter(),
+                *size,
+            )?)
+        }
+        DataType::Utf8 => Arc::new(
+            values
+                .iter()
+                .map(|value| match value {
+                    Some(Value::String(s) | Value::Enum(_, s)) => Ok(Some(s.clone())),
+                    Some(Value::Uuid(uuid)) => Ok(Some(uuid.to_string())),
+                    Some(value) => Err(mismatch(value)),
+                    None => Ok(None),
+                })
+                .collect::<Result<StringArray, _>>()?,
+        ),
+        DataType::List(item) => {
+            let mut lengths = Vec::with_capacity(values.len());
+            let mut items = Vec::new();
+            for value in values {
+                match value {
+                    Some(Value::Array(values)) => {
+                        lengths.push(values.len());
+                        items.extend(values.iter().map(Some));
+                    }
+                    Some(value) => return Err(mismatch(value)),
+                    None => lengths.push(0),
+                }
+            }
+            Arc::new(ListArray::try_new(
+                Arc::clone(item),
+                OffsetBuffer::from_lengths(lengths),
+                array(item.data_type(), &items)?,
+                nulls(values),
+            )?)
+        }
+        DataType::Map(entries, _) => {
+            let DataType::Struct(fields) = entries.data_type() else {
+                return Err(ArrowError::NotYetImplemented(format!("Avro maps as {data_type}")));
+            };
+            let mut lengths = Vec::with_capacity(values.len());
+            let (mut keys, mut items) = (Vec::new(), Vec::new());
+            for value in values {
+                match value {
+                    Some(Value::Map(map)) => {
+                        let mut map: Vec<_> = map.iter().collect();
+                        map.sort_unstable_by_key(|(key, _)| *key);
+                        lengths.push(map.len());
+                        keys.extend(map.iter().map(|(key, _)| key.as_str()));
+                        items.extend(map.iter().map(|(_, item)| Some(*item)));
+                    }
+                    Some(value) => return Err(mismatch(value)),
+                    None => lengths.push(0),
+                }
+            }
+            let entries_array = StructArray::try_new(
+                fields.clone(),
+                vec![Arc::new(StringArray::from(keys)), array(fields[1].data_type(), &items)?],
+                None,
+            )?;
+            Arc::new(MapArray::try_new(
+                Arc::clone(entries),
+                OffsetBuffer::from_lengths(lengths),
+                entries_array,
+                nulls(values),
+                false,
+            )?)
+        }
+        DataType::Struct(fields) => {
+            if let Some(value) =
+                values.iter().flatten().find(|value| !matches!(value, Value::Record(_)))
+            {
+                return Err(mismatch(value));
+            }
+            let columns = fields
+                .iter()
+                .map(|field| {
+                    let values: Vec<_> = values
+                        .iter()
+                        .map(|value| value.and_then(|value| field_of(value, field.name())))
+                        .collect();
+                    array(field.data_type(), &values)
+                })
+                .collect::<Result<_, _>>()?;
+            Arc::new(StructArray::try_new(fields.clone(), columns, nulls(values))?)
+        }
+        _ => return Err(ArrowError::NotYetImplemented(format!("Avro values as {data_type}"))),
+    };
+    Ok(array)
+}
+
+/// The array of `values` of the primitive `data_type`, their native values `native`.
+fn primitive<T: ArrowPrimitiveType>(
+    data_type: &DataType,
+    values: &[Option<&Value>],
+    native: impl Fn(&Value) -> Option<T::Native>,
+) -> Result<ArrayRef, ArrowError> {
+    let array = values
+        .iter()
+        .map(|value| {
+            value
+                .map(|value| {
+                    native(value).ok_or_else(|| {
+                        ArrowError::ParseError(format!(
+                            "Avro value {value:?} is not of type {data_type}"
+                        ))
+                    })
+                })
+                .transpose()
+        })
+        .collect::<Result<PrimitiveArray<T>, _>>()?;
+    Ok(Arc::new(array.with_data_type(data_type.clone())))
+}
+
+/// `value`, the value of the branch of a union, unless it is `null`.
+fn non_null(value: &Value) -> Option<&Value> {
+    match value {
+        Value::Union(_, value) => non_null(value),
+        Value::Null => None,
+        value => Some(value),
+    }
+}
+
+fn nulls(values: &[Option<&Value>]) -> Option<NullBuffer> {
+    let nulls = NullBuffer::from_iter(values.iter().map(Option::is_some));
+    (nulls.null_count() > 0).then_some(nulls)
+}
+
+fn avro_error(e: apache_avro::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use apache_avro::{Codec, Decimal, Writer};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    const SCHEMA: &str = r#"{
+        "type": "record", "name": "Order", "namespace": "shop",
+        "fields": [
+            {"name": "id", "type": "long"},
+            {"name": "status", "type": {"type": "enum", "name": "Status", "symbols": ["OPEN", "SHIPPED"]}},
+            {"name": "total", "type": {"type": "bytes", "logicalType": "decimal", "precision": 10, "scale": 2}},
+            {"name": "placed", "type": {"type": "long", "logicalType": "timestamp-millis"}},
+            {"name": "note", "type": ["null", "string"]},
+            {"name": "tags", "type": {"type": "array", "items": "string"}},
+            {"name": "extra", "type": {"type": "map", "values": "int"}},
+            {"name": "address", "type": ["null", {"type": "record", "name": "Address",
+                "fields": [{"name": "city", "type": "string"}]}]}
+        ]
+    }"#;
+
+    #[test]
+    fn test_avro_types_are_arrow_types() {
+        let schema = arrow_schema(&AvroSchema::parse_str(SCHEMA).unwrap()).unwrap();
+        let utf8 = |name| Field::new(name, DataType::Utf8, false);
+        let entries = Fields::from(vec![utf8("key"), Field::new("value", DataType::Int32, false)]);
+        let expected = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            utf8("status"),
+            Field::new("total", DataType::Decimal128(10, 2), false),
+            Field::new(
+                "placed",
+                DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into())),
+                false,
+            ),
+            Field::new("note", DataType::Utf8, true),
+            Field::new("tags", DataType::List(Arc::new(utf8("item"))), false),
+            Field::new(
+                "extra",
+                DataType::Map(
+                    Arc::new(Field::new("entries", DataType::Struct(entries), false)),
+                    false,
+                ),
+                false,
+            ),
+            Field::new("address", DataType::Struct(Fields::from(vec![utf8("city")])), true),
+        ]);
+        assert_eq!(schema, expected);
+        let union = AvroSchema::parse_str(r#"["int", "string"]"#).unwrap();
+        let error = field("x", &union, &mut HashMap::new()).unwrap_err();
+        assert!(error.to_string().contains("union of int, string of x"), "{error}");
+        let values = AvroSchema::parse_str(r#"{"type": "array", "items": "long"}"#).unwrap();
+        assert!(arrow_schema(&values).unwrap_err().to_string().contains("array values"));
+    }
+
+    fn order(schema: &AvroSchema, id: i64, note: Option<&str>, city: Option<&str>) -> Value {
+        let mut record = apache_avro::types::Record::new(schema).unwrap();
+        record.put("id", id);
+        record
+            .put("status", Value::Enum(id as u32 % 2, ["OPEN", "SHIPPED"][id as usize % 2].into()));
+        record.put("total", Value::Decimal(Decimal::from((id * 1050).to_be_bytes())));
+        record.put("placed", Value::TimestampMillis(1_700_000_000_000 + id * 86_400_000));
+        record.put(
+            "note",
+            note.map_or(Value::Union(0, Box::new(Value::Null)), |note| {
+                Value::Union(1, Box::new(Value::String(note.into())))
+            }),
+        );
+        record.put("tags", Value::Array(vec![Value::String(format!("t{id}"))]));
+        record.put(
+            "extra",
+            Value::Map(HashMap::from([
+                ("b".into(), Value::Int(2)),
+                ("a".into(), Value::Int(id as i32)),
+            ])),
+        );
+        record.put(
+            "address",
+            city.map_or(Value::Union(0, Box::new(Value::Null)), |city| {
+                let address = Value::Record(vec![("city".into(), Value::String(city.into()))]);
+                Value::Union(1, Box::new(address))
+            }),
+        );
+        record.into()
+    }
+
+    #[tokio::test]
+    async fn test_external_tables_stored_as_avro() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-avro-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let schema = AvroSchema::parse_str(SCHEMA).map_err(avro_error)?;
+        for (file, codec, orders) in [
+            ("a.avro", Codec::Snappy, vec![(1, Some("gift"), Some("Oslo")), (2, None, None)]),
+            ("b.avro", Codec::Deflate, vec![(3, None, Some("Lima"))]),
+        ] {
+            let mut writer = Writer::with_codec(&schema, Vec::new(), codec);
+            for (id, note, city) in orders {
+                writer.append(order(&schema, id, note, city)).map_err(avro_error)?;
+            }
+            std::fs::write(dir.join(file), writer.into_inner().map_err(avro_error)?)?;
+        }
+        let engine = QueryEngine::new();
+        let sql =
+            format!("CREATE EXTERNAL TABLE orders STORED AS AVRO LOCATION '{}/'", dir.display());
+        engine.query(&sql).await?;
+        let sql = "SELECT id, status, total, placed, note, tags, extra, address['city'] AS city \
+                   FROM orders WHERE id > 1 OR note IS NOT NULL ORDER BY id";
+        let result = engine.query(sql).await?;
+        let expected = "\
++----+---------+-------+----------------------+------+------+--------------+------+
+| id | status  | total | placed               | note | tags | extra        | city |
++----+---------+-------+----------------------+------+------+--------------+------+
+| 1  | SHIPPED | 10.50 | 2023-11-15T22:13:20Z | gift | [t1] | {a: 1, b: 2} | Oslo |
+| 2  | OPEN    | 21.00 | 2023-11-16T22:13:20Z |      | [t2] | {a: 2, b: 2} |      |
+| 3  | SHIPPED | 31.50 | 2023-11-17T22:13:20Z |      | [t3] | {a: 3, b: 2} | Lima |
++----+---------+-------+----------------------+------+------+--------------+------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        let result = engine.query("SELECT count(*) AS n FROM orders").await?;
+        assert_eq!(
+            pretty_format_batches(&result.batches)?.to_string(),
+            "\
++---+
+| n |
++---+
+| 3 |
++---+"
+        );
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,254 @@
+//! Sizing record batches by source and consumer.
+//!
+//! One batch size in rows does not fit every scan: a batch of a table of a few
+//! integers is a fraction of the size of one of wide text rows, and a query streaming
+//! rows to a client wants them early, while an aggregation wants few, large batches.
+//! [`BatchSizeRule`], which every [`QueryEngine`](crate::QueryEngine) runs after the
+//! built-in physical optimizer rules unless configured otherwise (see
+//! [`QueryEngine::with_batch_sizing`](crate::QueryEngine::with_batch_sizing)), sets the
+//! batch size of each file scan to the rows of its [`row_width`] that fit in:
+//!
+//! - [`BatchSizing::bulk_bytes`] when the scan feeds an operator that emits only once
+//!   its input is consumed (an aggregation, a sort, the build side of a join), so
+//!   fewer, larger batches amortize the per-batch overhead;
+//! - [`BatchSizing::streaming_bytes`] otherwise, as its rows stream through to the
+//!   result, so the first of them arrive early; a limit on the way caps it further.
+//!
+//! Scans whose batch size was set explicitly are left alone. Other sources size their
+//! batches themselves (see their `with_batch_size`).
+
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::config::ConfigOptions;
+use datafusion::datasource::physical_plan::{FileScanConfig, FileScanConfigBuilder};
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::execution_plan::EmissionType;
+use datafusion::physical_plan::joins::{CrossJoinExec, HashJoinExec, NestedLoopJoinExec};
+use datafusion::physical_plan::ExecutionPlan;
+use std::sync::Arc;
+
+/// Bytes a value of a variable width type (strings, binary) is assumed to take.
+pub const VARIABLE_WIDTH_BYTES: usize = 32;
+
+/// Target sizes of the batches of file scans, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSizing {
+    /// Bytes per batch of scans whose rows stream to the result.
+    pub streaming_bytes: usize,
+    /// Bytes per batch of scans feeding an aggregation, a sort or a join's build side.
+    pub bulk_bytes: usize,
+    pub min_rows: usize,
+    pub max_rows: usize,
+}
+
+impl Default for BatchSizing {
+    fn default() -> Self {
+        BatchSizing {
+            streaming_bytes: 256 * 1024,
+            bulk_bytes: 4 * 1024 * 1024,
+            min_rows: 1024,
+            max_rows: 128 * 1024,
+        }
+    }
+}
+
+impl BatchSizing {
+    /// Rows per batch of a scan of `schema`, feeding a bulk consumer or not and with
+    /// at most `fetch` rows wanted.
+    pub fn rows(&self, schema: &Schema, bulk: bool, fetch: Option<usize>) -> usize {
+        let bytes = if bulk { self.bulk_bytes } else { self.streaming_bytes };
+        let rows =
+            (bytes / row_width(schema)).clamp(self.min_rows, self.max_rows.max(self.min_rows));
+        fetch.map_or(rows, |fetch| rows.min(fetch)).max(1)
+    }
+}
+
+/// Estimated bytes of a row of `schema`.
+pub fn row_width(schema: &Schema) -> usize {
+    schema.fields().iter().map(|field| value_width(field.data_type())).sum::<usize>().max(1)
+}
+
+fn value_width(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Null | DataType::Boolean => 1,
+        DataType::Utf8
+        | DataType::LargeUtf8
+        | DataType::Utf8View
+        | DataType::Binary
+        | DataType::LargeBinary
+        | DataType::BinaryView => VARIABLE_WIDTH_BYTES,
+        DataType::FixedSizeBinary(width) => *width as usize,
+        DataType::FixedSizeList(field, length) => *length as usize * value_width(field.data_type()),
+        // An offset and a few values.
+        DataType::List(field)
+        | DataType::LargeList(field)
+        | DataType::ListView(field)
+        | DataType::LargeListView(field)
+        | DataType::Map(field, _) => 8 + 4 * value_width(field.data_type()),
+        DataType::Struct(fields) => fields.iter().map(|f| value_width(f.data_type())).sum(),
+        DataType::Union(fields, _) => {
+            1 + fields.iter().map(|(_, f)| value_width(f.data_type())).max().unwrap_or(0)
+        }
+        DataType::Dictionary(key, _) => value_width(key),
+        DataType::RunEndEncoded(_, values) => value_width(values.data_type()),
+        data_type => data_type.primitive_width().unwrap_or(VARIABLE_WIDTH_BYTES),
+    }
+}
+
+/// Sizes the batches of file scans, see the [module](self) documentation.
+#[derive(Debug, Default)]
+pub struct BatchSizeRule {
+    sizing: BatchSizing,
+}
+
+impl BatchSizeRule {
+    pub const NAME: &'static str = "batch_size";
+
+    pub fn new(sizing: BatchSizing) -> Self {
+        Self { sizing }
+    }
+
+    /// `plan` with its scans sized, those under it feeding a bulk consumer if `bulk`
+    /// and wanted for at most `fetch` rows.
+    fn resize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        bulk: bool,
+        fetch: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if let Some(scan) = plan.as_any().downcast_ref::<DataSourceExec>() {
+            let Some(config) = scan.data_source().as_any().downcast_ref::<FileScanConfig>() else {
+                return Ok(plan);
+            };
+            if config.batch_size.is_some() {
+                return Ok(plan);
+            }
+            let fetch = config.limit.or(fetch);
+            let rows = self.sizing.rows(&config.projected_schema(), bulk, fetch);
+            let config = FileScanConfigBuilder::from(config.clone()).with_batch_size(Some(rows));
+            return Ok(DataSourceExec::from_data_source(config.build()));
+        }
+        // Below an operator emitting once all is read, a limit above no longer caps
+        // what is read.
+        let (bulk, fetch) = match plan.properties().emission_type {
+            EmissionType::Final => (true, None),
+            _ if bulk => (true, None),
+            _ => (false, plan.fetch().or(fetch)),
+        };
+        let any = plan.as_any();
+        let collects_build_side =
+            any.is::<HashJoinExec>() || any.is::<NestedLoopJoinExec>() || any.is::<CrossJoinExec>();
+        let children = plan.children();
+        let mut resized = Vec::with_capacity(children.len());
+        for (i, child) in children.iter().enumerate() {
+            let build_side = collects_build_side && i == 0;
+            let fetch = if build_side { None } else { fetch };
+            resized.push(self.resize(Arc::clone(child), bulk || build_side, fetch)?);
+        }
+        if children.iter().zip(&resized).all(|(child, resized)| Arc::ptr_eq(child, resized)) {
+            return Ok(plan);
+        }
+        plan.with_new_children(resized)
+    }
+}
+
+impl PhysicalOptimizerRule for BatchSizeRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        self.resize(plan, false, None)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::datatypes::Field;
+    use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+
+    #[test]
+    fn test_row_widths() {
+        let item = Arc::new(Field::new("item", DataType::Int32, true));
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("active", DataType::Boolean, true),
+            Field::new("scores", DataType::List(item), true),
+        ]);
+        assert_eq!(row_width(&schema), 8 + 32 + 1 + (8 + 4 * 4));
+        assert_eq!(row_width(&Schema::empty()), 1);
+
+        let sizing = BatchSizing::default();
+        let narrow = Schema::new(vec![Field::new("id", DataType::Int32, false)]);
+        assert_eq!(sizing.rows(&narrow, true, None), sizing.max_rows);
+        assert_eq!(sizing.rows(&schema, false, None), 256 * 1024 / 65);
+        assert_eq!(sizing.rows(&schema, true, None), 4 * 1024 * 1024 / 65);
+        assert_eq!(sizing.rows(&schema, false, Some(10)), 10);
+    }
+
+    /// Batch sizes of the file scans of `sql`'s physical plan.
+    async fn scan_batch_sizes(engine: &QueryEngine, sql: &str) -> DataFusionResult<Vec<usize>> {
+        let plan = engine.sql(sql).await?.create_physical_plan().await?;
+        let mut sizes = vec![];
+        plan.apply(|node| {
+            let config = node
+                .as_any()
+                .downcast_ref::<DataSourceExec>()
+                .and_then(|scan| scan.data_source().as_any().downcast_ref::<FileScanConfig>());
+            sizes.extend(config.and_then(|config| config.batch_size));
+            Ok(TreeNodeRecursion::Continue)
+        })?;
+        Ok(sizes)
+    }
+
+    #[tokio::test]
+    async fn test_scans_are_sized_by_row_width_and_consumer() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-batch-size-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let rows: String = (0..1000).map(|i| format!("{i},name{i}\n")).collect();
+        std::fs::write(dir.join("people.csv"), format!("id,name\n{rows}"))?;
+
+        // 10 rows of both columns (8 + 32 bytes) per streamed batch, 100 per bulk batch.
+        let sizing =
+            BatchSizing { streaming_bytes: 400, bulk_bytes: 4000, min_rows: 1, max_rows: 1000 };
+        let engine = QueryEngine::new().with_batch_sizing(Some(sizing));
+        let location = dir.join("people.csv");
+        let create = format!(
+            "CREATE EXTERNAL TABLE people (id BIGINT, name TEXT) STORED AS CSV LOCATION '{}' \
+             OPTIONS ('format.has_header' 'true')",
+            location.display()
+        );
+        engine.sql(&create).await?;
+
+        assert_eq!(scan_batch_sizes(&engine, "SELECT * FROM people").await?, vec![10]);
+        assert_eq!(scan_batch_sizes(&engine, "SELECT id FROM people").await?, vec![50]);
+        assert_eq!(scan_batch_sizes(&engine, "SELECT * FROM people LIMIT 3").await?, vec![3]);
+        let aggregate = "SELECT name, count(*) FROM people GROUP BY name ORDER BY name LIMIT 3";
+        assert_eq!(scan_batch_sizes(&engine, aggregate).await?, vec![125]);
+        let join = "SELECT * FROM people a JOIN (SELECT id FROM people) b ON a.id = b.id";
+        let mut sizes = scan_batch_sizes(&engine, join).await?;
+        sizes.sort();
+        assert_eq!(sizes, vec![50, 100]);
+
+        let result = engine.query("SELECT * FROM people").await?;
+        assert!(result.batches.iter().all(|batch| batch.num_rows() <= 10));
+        assert_eq!(result.batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 1000);
+
+        let fixed = engine.with_batch_sizing(None);
+        assert!(scan_batch_sizes(&fixed, "SELECT * FROM people").await?.is_empty());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Exporting an engine's state, to set up another engine like it.
+//!
+//! [`QueryEngine::export_state`](crate::QueryEngine::export_state) returns a
+//! [`StateBundle`] of what was set up at runtime rather than configured: the tables,
+//! views, schemas, statistics and placements recorded in the engine's catalog store
+//! (see [`catalog_store`](crate::catalog_store); none without one), its data policies
+//! (see [`policy`](crate::policy)), and the external catalogs it has read, which the
+//! engine importing the bundle warms up (see
+//! [`QueryEngine::warm_up_catalog`](crate::QueryEngine::warm_up_catalog)). Bundles
+//! serialize to JSON, so that those of several deployments (dev, staging, prod...) can
+//! be kept and compared.
+//!
+//! [`QueryEngine::import_state`](crate::QueryEngine::import_state) applies a bundle:
+//! its entries are created, replacing those of the same names, and recorded in the
+//! importing engine's catalog store; its policies replace the engine's; and the
+//! catalogs it lists are warmed up, if the engine has them. An entry that fails, e.g.
+//! a table whose files the engine cannot reach, is reported and skipped.
+//!
+//! As in the catalog store, tables keep their credentials as secret references, which
+//! the importing engine resolves with its own secrets.
+
+use crate::catalog_store::{CatalogChange, EntryKind};
+use crate::policy::PolicySet;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Version of the bundle format, checked on import.
+pub const BUNDLE_FORMAT: u32 = 1;
+
+/// An engine's state, see the [module](self) documentation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateBundle {
+    pub format: u32,
+    /// In the order they are to be applied.
+    pub entries: Vec<BundleEntry>,
+    pub policies: PolicySet,
+    /// The external catalogs to warm up.
+    pub warm_up: Vec<String>,
+}
+
+/// A catalog store entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleEntry {
+    /// `table`, `view`, `statistics`, `schema` or `placement`.
+    pub kind: String,
+    pub name: String,
+    /// As the catalog store records it: the SQL of a view, the JSON of statistics, the
+    /// name a table is placed at, nothing for a schema; and for a table, its `CREATE
+    /// EXTERNAL TABLE` plan in datafusion-proto's encoding, in hex.
+    pub definition: String,
+}
+
+impl BundleEntry {
+    pub(crate) fn from_change(change: &CatalogChange) -> Self {
+        let definition = change.definition.as_deref().unwrap_or_default();
+        let definition = match change.kind {
+            EntryKind::Table => definition.iter().map(|byte| format!("{byte:02x}")).collect(),
+            _ => String::from_utf8_lossy(definition).into_owned(),
+        };
+        Self { kind: change.kind.name().to_string(), name: change.name.clone(), definition }
+    }
+
+    pub(crate) fn to_change(&self) -> DataFusionResult<CatalogChange> {
+        let kind = EntryKind::parse(&self.kind)?;
+        let definition = match kind {
+            EntryKind::Table => from_hex(&self.definition).ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "the definition of table {} is not hex",
+                    self.name
+                ))
+            })?,
+            _ => self.definition.clone().into_bytes(),
+        };
+        Ok(CatalogChange {
+            version: 0,
+            kind,
+            name: self.name.clone(),
+            definition: Some(definition),
+        })
+    }
+}
+
+/// What importing a bundle did.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// Entries created.
+    pub entries: usize,
+    pub warmed_up: Vec<String>,
+    /// The entries and catalogs that failed, and why.
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "imported {} catalog entries and the policies", self.entries)?;
+        if !self.warmed_up.is_empty() {
+            write!(f, ", warmed up {}", self.warmed_up.join(", "))?;
+        }
+        for error in &self.errors {
+            write!(f, "\n! {error}")?;
+        }
+        Ok(())
+    }
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog_store::SqliteCatalogStore;
+    use crate::policy::RowFilter;
+    use crate::QueryEngine;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_state_is_exported_and_imported() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let csv = dir.join("orders.csv");
+        std::fs::write(&csv, "id,region\n1,EU\n2,US\n")?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("dev.db"))?);
+        let dev = QueryEngine::new().with_catalog_store(store).await?;
+        dev.query("CREATE SCHEMA sales").await?;
+        dev.query(&format!(
+            "CREATE EXTERNAL TABLE sales.orders STORED AS CSV LOCATION '{}' \
+             OPTIONS ('format.has_header' 'true')",
+            csv.display()
+        ))
+        .await?;
+        dev.query("CREATE VIEW eu AS SELECT id FROM sales.orders WHERE region = 'EU'").await?;
+        dev.set_policies(PolicySet::new().with_row_filter(RowFilter::new("orders", "id > 0")));
+        let bundle = dev.export_state().await?;
+        let kinds: Vec<_> = bundle.entries.iter().map(|entry| entry.kind.as_str()).collect();
+        assert_eq!(kinds, ["schema", "table", "view"]);
+        assert_eq!(
+            bundle.entries[2].definition,
+            "CREATE VIEW eu AS SELECT id FROM sales.orders WHERE region = 'EU'"
+        );
+
+        let json = serde_json::to_string(&bundle).unwrap();
+        let bundle: StateBundle = serde_json::from_str(&json).unwrap();
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("prod.db"))?);
+        let prod = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        let report = prod.import_state(&bundle).await?;
+        assert_eq!(report.entries, 3, "{report}");
+        assert!(report.errors.is_empty(), "{report}");
+        assert_eq!(prod.policies(), dev.policies());
+        let result = prod.query("SELECT * FROM eu").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        // Recorded in the importing engine's store.
+        let restarted = QueryEngine::new().with_catalog_store(store).await?;
+        restarted.query("SELECT * FROM sales.orders").await?;
+
+        let mut broken = bundle.clone();
+        broken.entries[1].definition.push('z');
+        broken.warm_up.push("missing".to_string());
+        let report = QueryEngine::new().import_state(&broken).await?;
+        assert_eq!(report.errors.len(), 3, "{report}");
+        broken.format = BUNDLE_FORMAT + 1;
+        assert!(QueryEngine::new().import_state(&broken).await.is_err());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,769 @@
+//! Persistence of the catalog across restarts and nodes.
+//!
+//! Without a [`CatalogStore`], tables and views created at runtime exist only in the
+//! memory of the process that created them. With one (see
+//! [`QueryEngine::with_catalog_store`](crate::QueryEngine::with_catalog_store)),
+//! every `CREATE EXTERNAL TABLE`, `CREATE VIEW`, `DROP TABLE` and `DROP VIEW` run
+//! through the engine is recorded, and the recorded definitions are replayed when an
+//! engine is started over the store. A table is defined by its `CREATE EXTERNAL TABLE`
+//! plan in datafusion-proto's encoding, a view by its SQL. The statistics of tables
+//! analyzed with `ANALYZE TABLE` (see [`statistics`](crate::statistics)) are entries
+//! too, in JSON, and so are schemas and the schemas tables are placed in (see
+//! [`namespace`](crate::namespace)).
+//!
+//! The store is a log of changes: each one has a version, and only the latest change
+//! of an entry is kept, a drop leaving no definition. Engines sharing a store (the
+//! coordinators of a multi-node deployment sharing a Postgres database) pick up each
+//! other's changes with
+//! [`QueryEngine::refresh_catalog`](crate::QueryEngine::refresh_catalog).
+//!
+//! Credentials among the options of a table (`password`, `aws.secret_access_key`, ...)
+//! are never recorded: a persisted table must reference them as secrets
+//! (`'password' 'env:ORDERS_DB_PASSWORD'`, see [`igloo_common::secrets`]), which are
+//! resolved whenever the table is created, at first and when replayed.
+//!
+//! Views are replayed with the default session settings, so they should name
+//! tables outside the default schema in full. In-memory tables (`CREATE TABLE`) and
+//! `TEMPORARY` objects are not persisted, and neither are tenants' catalogs.
+
+use crate::lineage::{ColumnLineage, Lineage, LineageEdge, TargetKind};
+use crate::namespace::{self, Placements};
+use crate::statistics::{AnalyzedTables, TableStatistics};
+use async_trait::async_trait;
+use datafusion::common::config::CatalogOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use datafusion::sql::TableReference;
+use datafusion_proto::bytes::{logical_plan_from_bytes, logical_plan_to_bytes};
+use igloo_common::redact::is_credential_key;
+use igloo_common::secrets::Secrets;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio_postgres::{Client, NoTls};
+
+/// What a catalog entry describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    Table,
+    View,
+    /// The statistics of a table.
+    Statistics,
+    Schema,
+    /// Where a table registered under the entry's name is placed.
+    Placement,
+}
+
+impl EntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::Table => "table",
+            EntryKind::View => "view",
+            EntryKind::Statistics => "statistics",
+            EntryKind::Schema => "schema",
+            EntryKind::Placement => "placement",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> DataFusionResult<Self> {
+        match name {
+            "table" => Ok(EntryKind::Table),
+            "view" => Ok(EntryKind::View),
+            "statistics" => Ok(EntryKind::Statistics),
+            "schema" => Ok(EntryKind::Schema),
+            "placement" => Ok(EntryKind::Placement),
+            _ => Err(DataFusionError::Execution(format!("unknown catalog entry kind '{name}'"))),
+        }
+    }
+}
+
+impl fmt::Display for EntryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The latest change of a catalog entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogChange {
+    pub version: u64,
+    pub kind: EntryKind,
+    /// Fully qualified, quoted where needed (`catalog.schema.table`, or
+    /// `catalog.schema` for schemas).
+    pub name: String,
+    /// The definition of the entry (in a form depending on its kind), or `None` if
+    /// it was dropped.
+    pub definition: Option<Vec<u8>>,
+}
+
+/// Durable storage of catalog changes.
+#[async_trait]
+pub trait CatalogStore: fmt::Debug + Send + Sync {
+    /// Record the definition of an entry (`None` when dropped), replacing its previous
+    /// change, and return the new change's version. Versions only increase.
+    async fn record(
+        &self,
+        kind: EntryKind,
+        name: &str,
+        definition: Option<&[u8]>,
+    ) -> DataFusionResult<u64>;
+
+    /// Changes with a version above `version`, oldest first.
+    async fn changes_since(&self, version: u64) -> DataFusionResult<Vec<CatalogChange>>;
+
+    /// Record the lineage of a target (see [`lineage`](crate::lineage)).
+    async fn record_lineage(&self, lineage: &Lineage) -> DataFusionResult<()>;
+
+    /// Every recorded lineage edge.
+    async fn lineage(&self) -> DataFusionResult<Vec<LineageEdge>>;
+}
+
+/// A catalog store in an embedded SQLite database, the default.
+#[derive(Debug, Clone)]
+pub struct SqliteCatalogStore {
+    conn: Arc<Mutex<rusqlite::Connection>>,
+}
+
+impl SqliteCatalogStore {
+    /// Open the database at `path`, creating it if needed.
+    pub fn open(path: impl AsRef<Path>) -> DataFusionResult<Self> {
+        let conn = rusqlite::Connection::open(path).map_err(sqlite_error)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS igloo_catalog (
+                 version INTEGER PRIMARY KEY AUTOINCREMENT,
+                 kind TEXT NOT NULL,
+                 name TEXT NOT NULL,
+                 definition BLOB,
+                 UNIQUE (kind, name)
+             );
+             CREATE TABLE IF NOT EXISTS igloo_lineage (
+                 target_kind TEXT NOT NULL,
+                 target TEXT NOT NULL,
+                 target_column TEXT NOT NULL,
+                 source TEXT NOT NULL,
+                 source_column TEXT NOT NULL,
+                 UNIQUE (target_kind, target, target_column, source, source_column)
+             )",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> DataFusionResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut rusqlite::Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("catalog store lock poisoned");
+            f(&mut conn).map_err(sqlite_error)
+        })
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))?
+    }
+}
+
+#[async_trait]
+impl CatalogStore for SqliteCatalogStore {
+    async fn record(
+        &self,
+        kind: EntryKind,
+        name: &str,
+        definition: Option<&[u8]>,
+    ) -> DataFusionResult<u64> {
+        let (name, definition) = (name.to_string(), definition.map(<[u8]>::to_vec));
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM igloo_catalog WHERE kind = ?1 AND name = ?2",
+                (kind.name(), &name),
+            )?;
+            tx.execute(
+                "INSERT INTO igloo_catalog (kind, name, definition) VALUES (?1, ?2, ?3)",
+                (kind.name(), &name, &definition),
+            )?;
+            let version = tx.last_insert_rowid() as u64;
+            tx.commit()?;
+            Ok(version)
+        })
+        .await
+    }
+
+    async fn changes_since(&self, version: u64) -> DataFusionResult<Vec<CatalogChange>> {
+        let rows = self
+            .with_conn(move |conn| {
+                let mut statement = conn.prepare(
+                    "SELECT version, kind, name, definition FROM igloo_catalog
+                     WHERE version > ?1 ORDER BY version",
+                )?;
+                let rows = statement.query_map([version as i64], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })?;
+                rows.collect::<rusqlite::Result<Vec<(i64, String, String, Option<Vec<u8>>)>>>()
+            })
+            .await?;
+        rows.into_iter()
+            .map(|(version, kind, name, definition)| {
+                Ok(CatalogChange {
+                    version: version as u64,
+                    kind: EntryKind::parse(&kind)?,
+                    name,
+                    definition,
+                })
+            })
+            .collect()
+    }
+
+    async fn record_lineage(&self, lineage: &Lineage) -> DataFusionResult<()> {
+        let lineage = lineage.clone();
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            let target = (lineage.kind.name(), &lineage.target);
+            if lineage.replace {
+                tx.execute(
+                    "DELETE FROM igloo_lineage WHERE target_kind = ?1 AND target = ?2",
+                    target,
+                )?;
+            }
+            for edge in &lineage.edges {
+                tx.execute(
+                    "INSERT OR IGNORE INTO igloo_lineage
+                     (target_kind, target, target_column, source, source_column)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    (target.0, target.1, &edge.column, &edge.source, &edge.source_column),
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    async fn lineage(&self) -> DataFusionResult<Vec<LineageEdge>> {
+        let rows = self
+            .with_conn(|conn| {
+                let mut statement = conn.prepare(
+                    "SELECT target_kind, target, target_column, source, source_column
+                     FROM igloo_lineage ORDER BY target_kind, target, target_column, source",
+                )?;
+                let rows = statement.query_map([], |row| {
+                    Ok([row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?])
+                })?;
+                rows.collect::<rusqlite::Result<Vec<[String; 5]>>>()
+            })
+            .await?;
+        rows.into_iter().map(lineage_edge).collect()
+    }
+}
+
+fn sqlite_error(e: rusqlite::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+/// A catalog store in a Postgres database, for deployments whose coordinators share
+/// one catalog.
+pub struct PostgresCatalogStore {
+    client: tokio::sync::Mutex<Client>,
+}
+
+impl fmt::Debug for PostgresCatalogStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresCatalogStore").finish_non_exhaustive()
+    }
+}
+
+impl PostgresCatalogStore {
+    /// Connect with a libpq-style connection string or URL, creating the catalog
+    /// table if needed.
+    pub async fn connect(config: &str) -> DataFusionResult<Self> {
+        let (client, connection) =
+            tokio_postgres::connect(config, NoTls).await.map_err(postgres_error)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!(error = %e, "catalog store connection error");
+            }
+        });
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS igloo_catalog (
+                     version BIGSERIAL PRIMARY KEY,
+                     kind TEXT NOT NULL,
+                     name TEXT NOT NULL,
+                     definition BYTEA,
+                     UNIQUE (kind, name)
+                 );
+                 CREATE TABLE IF NOT EXISTS igloo_lineage (
+                     target_kind TEXT NOT NULL,
+                     target TEXT NOT NULL,
+                     target_column TEXT NOT NULL,
+                     source TEXT NOT NULL,
+                     source_column TEXT NOT NULL,
+                     UNIQUE (target_kind, target, target_column, source, source_column)
+                 )",
+            )
+            .await
+            .map_err(postgres_error)?;
+        Ok(Self { client: tokio::sync::Mutex::new(client) })
+    }
+}
+
+#[async_trait]
+impl CatalogStore for PostgresCatalogStore {
+    async fn record(
+        &self,
+        kind: EntryKind,
+        name: &str,
+        definition: Option<&[u8]>,
+    ) -> DataFusionResult<u64> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await.map_err(postgres_error)?;
+        // Versions become visible in order, so no node skips one committed late.
+        tx.batch_execute("LOCK TABLE igloo_catalog IN EXCLUSIVE MODE")
+            .await
+            .map_err(postgres_error)?;
+        tx.execute(
+            "DELETE FROM igloo_catalog WHERE kind = $1 AND name = $2",
+            &[&kind.name(), &name],
+        )
+        .await
+        .map_err(postgres_error)?;
+        let row = tx
+            .query_one(
+                "INSERT INTO igloo_catalog (kind, name, definition) VALUES ($1, $2, $3)
+                 RETURNING version",
+                &[&kind.name(), &name, &definition],
+            )
+            .await
+            .map_err(postgres_error)?;
+        tx.commit().await.map_err(postgres_error)?;
+        Ok(row.get::<_, i64>(0) as u64)
+    }
+
+    async fn changes_since(&self, version: u64) -> DataFusionResult<Vec<CatalogChange>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "SELECT version, kind, name, definition FROM igloo_catalog
+                 WHERE version > $1 ORDER BY version",
+                &[&(version as i64)],
+            )
+            .await
+            .map_err(postgres_error)?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(CatalogChange {
+                    version: row.get::<_, i64>(0) as u64,
+                    kind: EntryKind::parse(row.get(1))?,
+                    name: row.get(2),
+                    definition: row.get(3),
+                })
+            })
+            .collect()
+    }
+
+    async fn record_lineage(&self, lineage: &Lineage) -> DataFusionResult<()> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await.map_err(postgres_error)?;
+        let kind = lineage.kind.name();
+        if lineage.replace {
+            tx.execute(
+                "DELETE FROM igloo_lineage WHERE target_kind = $1 AND target = $2",
+                &[&kind, &lineage.target],
+            )
+            .await
+            .map_err(postgres_error)?;
+        }
+        for edge in &lineage.edges {
+            tx.execute(
+                "INSERT INTO igloo_lineage
+                 (target_kind, target, target_column, source, source_column)
+                 VALUES ($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+                &[&kind, &lineage.target, &edge.column, &edge.source, &edge.source_column],
+            )
+            .await
+            .map_err(postgres_error)?;
+        }
+        tx.commit().await.map_err(postgres_error)
+    }
+
+    async fn lineage(&self) -> DataFusionResult<Vec<LineageEdge>> {
+        let rows = self
+            .client
+            .lock()
+            .await
+            .query(
+                "SELECT target_kind, target, target_column, source, source_column
+                 FROM igloo_lineage ORDER BY target_kind, target, target_column, source",
+                &[],
+            )
+            .await
+            .map_err(postgres_error)?;
+        rows.into_iter().map(|row| lineage_edge([0, 1, 2, 3, 4].map(|i| row.get(i)))).collect()
+    }
+}
+
+fn postgres_error(e: tokio_postgres::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+fn lineage_edge(
+    [kind, target, column, source, source_column]: [String; 5],
+) -> DataFusionResult<LineageEdge> {
+    Ok(LineageEdge {
+        target_kind: TargetKind::parse(&kind)?,
+        target,
+        column: ColumnLineage { column, source, source_column },
+    })
+}
+
+/// An engine's view of its store: the version its catalog is up to date with.
+#[derive(Debug)]
+pub(crate) struct CatalogSync {
+    store: Arc<dyn CatalogStore>,
+    version: tokio::sync::Mutex<u64>,
+    /// Resolves the secrets of replayed tables.
+    secrets: Arc<Secrets>,
+}
+
+impl CatalogSync {
+    pub(crate) fn new(store: Arc<dyn CatalogStore>, secrets: Arc<Secrets>) -> Self {
+        Self { store, version: tokio::sync::Mutex::new(0), secrets }
+    }
+
+    /// Apply the changes made since the last refresh to `ctx`'s catalog, `analyzed`
+    /// and `placements`, returning how many there were. Entries whose definition fails
+    /// (e.g. a table whose files are gone) are reported and skipped.
+    pub(crate) async fn refresh(
+        &self,
+        ctx: &SessionContext,
+        analyzed: &AnalyzedTables,
+        placements: &Placements,
+    ) -> DataFusionResult<usize> {
+        let mut version = self.version.lock().await;
+        let changes = self.store.changes_since(*version).await?;
+        for change in &changes {
+            if let Err(e) = apply(ctx, analyzed, placements, &self.secrets, change).await {
+                tracing::warn!(
+                    kind = %change.kind,
+                    name = change.name,
+                    error = %e,
+                    "skipping catalog change"
+                );
+            }
+            *version = change.version;
+        }
+        Ok(changes.len())
+    }
+
+    /// Record `lineage`. Failing to is reported rather than failing the statement.
+    pub(crate) async fn record_lineage(&self, lineage: &Lineage) {
+        if let Err(e) = self.store.record_lineage(lineage).await {
+            tracing::warn!(
+                kind = %lineage.kind,
+                target = lineage.target,
+                error = %e,
+                "failed to record lineage"
+            );
+        }
+    }
+
+    pub(crate) async fn lineage(&self) -> DataFusionResult<Vec<LineageEdge>> {
+        self.store.lineage().await
+    }
+
+    /// The latest change of every entry that was not dropped, oldest first.
+    pub(crate) async fn entries(&self) -> DataFusionResult<Vec<CatalogChange>> {
+        let changes = self.store.changes_since(0).await?;
+        Ok(changes.into_iter().filter(|change| change.definition.is_some()).collect())
+    }
+
+    /// Record `change`, made to this engine's catalog.
+    pub(crate) async fn record(&self, change: &Change) -> DataFusionResult<()> {
+        let recorded =
+            self.store.record(change.kind, &change.name, change.definition.as_deref()).await?;
+        let mut version = self.version.lock().await;
+        // Otherwise a change from another node came first; refresh applies both.
+        if recorded == *version + 1 {
+            *version = recorded;
+        }
+        Ok(())
+    }
+}
+
+/// A change to be recorded, taken from a plan before it runs.
+#[derive(Debug)]
+pub(crate) struct Change {
+    kind: EntryKind,
+    name: String,
+    definition: Option<Vec<u8>>,
+}
+
+impl Change {
+    /// Recording the `statistics` of table `name`, `None` when discarded.
+    pub(crate) fn statistics(
+        name: &str,
+        statistics: Option<&TableStatistics>,
+    ) -> DataFusionResult<Self> {
+        let definition = statistics
+            .map(serde_json::to_vec)
+            .transpose()
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        Ok(Self { kind: EntryKind::Statistics, name: name.to_string(), definition })
+    }
+
+    /// Recording `change`, made to another engine's catalog.
+    pub(crate) fn copy(change: &CatalogChange) -> Self {
+        Self { kind: change.kind, name: change.name.clone(), definition: change.definition.clone() }
+    }
+
+    /// Dropping the table or view registered as `name`.
+    pub(crate) fn drop(kind: EntryKind, name: &str) -> Self {
+        Self { kind, name: name.to_string(), definition: None }
+    }
+
+    /// Placing the table registered as `name` at `placed`; at `name` itself when no
+    /// longer placed elsewhere.
+    pub(crate) fn placement(name: &str, placed: &str) -> Self {
+        let definition = (placed != name).then(|| placed.as_bytes().to_vec());
+        Self { kind: EntryKind::Placement, name: name.to_string(), definition }
+    }
+
+    /// The change `plan` makes to `ctx`'s catalog, if it is one that persists. Tables
+    /// and views are recorded under the names their placements are registered under.
+    /// Drops of tables and views are taken from
+    /// [`dropped_tables`](crate::namespace::dropped_tables) instead.
+    pub(crate) fn of(
+        plan: &LogicalPlan,
+        ctx: &SessionContext,
+        placements: &Placements,
+        secrets: &Secrets,
+    ) -> DataFusionResult<Option<Self>> {
+        if let Some((name, exists)) = namespace::schema_change(plan, ctx) {
+            let definition = exists.then(Vec::new);
+            return Ok(Some(Self { kind: EntryKind::Schema, name, definition }));
+        }
+        let LogicalPlan::Ddl(ddl) = plan else {
+            return Ok(None);
+        };
+        let (kind, name, definition) = match ddl {
+            DdlStatement::CreateExternalTable(create) if !create.temporary => {
+                // Creating an existing table is a no-op, not a new definition.
+                if create.if_not_exists && ctx.table_exist(create.name.clone())? {
+                    return Ok(None);
+                }
+                let mut options: Vec<_> = create.options.iter().collect();
+                options.sort();
+                let raw = options
+                    .into_iter()
+                    .find(|(key, value)| is_credential_key(key) && !secrets.is_reference(value));
+                if let Some((key, _)) = raw {
+                    return Err(DataFusionError::Plan(format!(
+                        "option '{key}' of table {} is a credential, which is not stored in \
+                         the catalog; reference a secret instead, such as 'env:NAME' or \
+                         'file:/path'",
+                        create.name
+                    )));
+                }
+                (EntryKind::Table, &create.name, Some(logical_plan_to_bytes(plan)?.to_vec()))
+            }
+            DdlStatement::CreateView(create) if !create.temporary => {
+                let Some(definition) = &create.definition else {
+                    return Ok(None);
+                };
+                (EntryKind::View, &create.name, Some(definition.clone().into_bytes()))
+            }
+            _ => return Ok(None),
+        };
+        let options = ctx.state().config().options().catalog.clone();
+        let name = placements.registered_name(&full_name(name.clone(), &options));
+        Ok(Some(Self { kind, name, definition }))
+    }
+}
+
+/// `plan` with the secrets referenced by the credential options of a `CREATE EXTERNAL
+/// TABLE` resolved, for creating the table; the catalog keeps the references.
+pub(crate) async fn resolve_secrets(
+    plan: LogicalPlan,
+    secrets: &Secrets,
+) -> DataFusionResult<LogicalPlan> {
+    let mut create = match plan {
+        LogicalPlan::Ddl(DdlStatement::CreateExternalTable(create)) => create,
+        plan => return Ok(plan),
+    };
+    for (key, value) in create.options.iter_mut() {
+        if is_credential_key(key) && secrets.is_reference(value) {
+            *value =
+                secrets.resolve(value).await.map_err(|e| DataFusionError::External(Box::new(e)))?;
+        }
+    }
+    Ok(LogicalPlan::Ddl(DdlStatement::CreateExternalTable(create)))
+}
+
+/// `name` resolved against the default catalog and schema, quoted where needed.
+pub(crate) fn full_name(name: TableReference, options: &CatalogOptions) -> String {
+    let name = name.resolve(&options.default_catalog, &options.default_schema);
+    TableReference::full(name.catalog, name.schema, name.table).to_quoted_string()
+}
+
+/// Apply `change` to `ctx`'s catalog, `analyzed` and `placements`.
+pub(crate) async fn apply(
+    ctx: &SessionContext,
+    analyzed: &AnalyzedTables,
+    placements: &Placements,
+    secrets: &Secrets,
+    change: &CatalogChange,
+) -> DataFusionResult<()> {
+    let utf8 =
+        |bytes| std::str::from_utf8(bytes).map_err(|e| DataFusionError::External(Box::new(e)));
+    match change.kind {
+        EntryKind::Statistics => {
+            return analyzed.apply(ctx, &change.name, change.definition.as_deref()).await;
+        }
+        EntryKind::Schema => {
+            return namespace::apply_schema(ctx, &change.name, change.definition.is_some());
+        }
+        EntryKind::Placement => {
+            let placed = match &change.definition {
+                Some(placed) => utf8(placed)?,
+                None => &change.name,
+            };
+            return placements.place(ctx, &change.name, placed);
+        }
+        EntryKind::Table | EntryKind::View => {}
+    }
+    let placed = placements.placed_name(&change.name);
+    // Nothing is left of a table dropped along with its schema.
+    if ctx.state().schema_for_ref(placed.as_str()).is_ok() {
+        ctx.deregister_table(placed.as_str())?;
+    }
+    match (change.kind, &change.definition) {
+        (_, None) => return Ok(()),
+        (EntryKind::Table, Some(plan)) => {
+            let plan = resolve_secrets(logical_plan_from_bytes(plan, ctx)?, secrets).await?;
+            ctx.execute_logical_plan(plan).await?;
+        }
+        (EntryKind::View, Some(sql)) => {
+            ctx.sql(utf8(sql)?).await?;
+        }
+        (_, Some(_)) => unreachable!("only tables and views are applied here"),
+    }
+    placements.restore(ctx, &change.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::catalog::{Session, TableProviderFactory};
+    use datafusion::datasource::empty::EmptyTable;
+    use datafusion::datasource::TableProvider;
+    use datafusion::logical_expr::CreateExternalTable;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_ddl_survives_restarts_and_reaches_other_nodes() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-catalog-store-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let csv = dir.join("orders.csv");
+        std::fs::write(&csv, "id,amount\n1,10\n2,20\n3,30\n")?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db"))?);
+
+        let first = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        first
+            .query(&format!(
+                "CREATE EXTERNAL TABLE orders STORED AS CSV LOCATION '{}' \
+                 OPTIONS ('format.has_header' 'true')",
+                csv.display()
+            ))
+            .await?;
+        first.query("CREATE VIEW big AS SELECT id FROM orders WHERE amount > 15").await?;
+        first.query("CREATE TABLE scratch AS VALUES (1)").await?;
+
+        // A restart, or another node, over the same store.
+        let second = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        let result = second.query("SELECT count(*) FROM big").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        assert!(second.query("SELECT * FROM scratch").await.is_err());
+
+        first.query("DROP VIEW big").await?;
+        assert_eq!(second.refresh_catalog().await?, 1);
+        assert!(second.query("SELECT * FROM big").await.is_err());
+        assert_eq!(second.refresh_catalog().await?, 0);
+        // A node's own changes are not applied again.
+        assert_eq!(first.refresh_catalog().await?, 0);
+
+        let third = QueryEngine::new().with_catalog_store(store).await?;
+        assert!(third.query("SELECT * FROM big").await.is_err());
+        third.query("SELECT * FROM orders").await?;
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    /// Creates empty tables, recording the options they were created with.
+    #[derive(Debug, Default)]
+    struct RecordingFactory {
+        options: Mutex<Vec<HashMap<String, String>>>,
+    }
+
+    #[async_trait]
+    impl TableProviderFactory for RecordingFactory {
+        async fn create(
+            &self,
+            _state: &dyn Session,
+            cmd: &CreateExternalTable,
+        ) -> DataFusionResult<Arc<dyn TableProvider>> {
+            self.options.lock().unwrap().push(cmd.options.clone());
+            Ok(Arc::new(EmptyTable::new(Arc::new(cmd.schema.as_ref().into()))))
+        }
+    }
+
+    fn with_factory(engine: QueryEngine, factory: &Arc<RecordingFactory>) -> QueryEngine {
+        let state = engine.session_context().state_ref();
+        state.write().table_factories_mut().insert("REMOTE".to_string(), factory.clone());
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_credentials_are_stored_as_secret_references() -> DataFusionResult<()> {
+        std::env::set_var("IGLOO_TEST_REMOTE_PASSWORD", "remote-hunter2");
+        let dir =
+            std::env::temp_dir().join(format!("igloo-catalog-secrets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db"))?);
+        let factory = Arc::new(RecordingFactory::default());
+
+        let engine = with_factory(QueryEngine::new(), &factory);
+        let engine = engine.with_catalog_store(store.clone()).await?;
+        let error = engine
+            .query(
+                "CREATE EXTERNAL TABLE raw (id INT) STORED AS REMOTE LOCATION 'db' \
+                 OPTIONS ('user' 'igloo', 'password' 'hunter2')",
+            )
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("option 'format.password' of table raw"), "{error}");
+        engine
+            .query(
+                "CREATE EXTERNAL TABLE remote (id INT) STORED AS REMOTE LOCATION 'db' \
+                 OPTIONS ('user' 'igloo', 'password' 'env:IGLOO_TEST_REMOTE_PASSWORD')",
+            )
+            .await?;
+        // The table is created with the secret, the catalog keeps the reference.
+        assert_eq!(factory.options.lock().unwrap()[0]["format.password"], "remote-hunter2");
+        let changes = store.changes_since(0).await?;
+        let definition = changes[0].definition.as_deref().unwrap();
+        let contains = |text: &str| definition.windows(text.len()).any(|w| w == text.as_bytes());
+        assert!(contains("env:IGLOO_TEST_REMOTE_PASSWORD"));
+        assert!(!contains("remote-hunter2"));
+
+        // Replayed tables resolve it again.
+        let replayed = with_factory(QueryEngine::new(), &factory);
+        replayed.with_catalog_store(store).await?;
+        assert_eq!(factory.options.lock().unwrap()[1]["format.password"], "remote-hunter2");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
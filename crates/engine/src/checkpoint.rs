@@ -0,0 +1,170 @@
+//! Checkpointing query results to files.
+//!
+//! [`QueryEngine::save_result`] writes the result of a query to one Arrow IPC or
+//! Parquet file, on local disk or in any object store registered with the engine, and
+//! [`QueryEngine::load_result`] reads such a file back as a table to register. A
+//! pipeline of several steps can so keep the result of an expensive step (such as a
+//! federated query) and build on it without running it again:
+//!
+//! ```no_run
+//! # async fn pipeline(engine: igloo_engine::QueryEngine) -> datafusion::error::Result<()> {
+//! use igloo_engine::checkpoint::ResultFormat;
+//!
+//! let sql = "SELECT o.id, c.region FROM pg.orders o JOIN mysql.customers c ON o.customer = c.id";
+//! engine.save_result(sql, "s3://lake/checkpoints/orders.arrow", ResultFormat::Arrow).await?;
+//! let orders = engine.load_result("s3://lake/checkpoints/orders.arrow").await?;
+//! engine.register_table("orders_by_region", orders)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The format of a file is told by its extension, `.arrow` or `.parquet`, which the
+//! paths given to [`QueryEngine::save_result`] must end in. Arrow IPC files are faster
+//! to write and read back, Parquet files smaller and readable by other tools.
+//!
+//! [`QueryEngine::save_result`]: crate::QueryEngine::save_result
+//! [`QueryEngine::load_result`]: crate::QueryEngine::load_result
+
+use crate::parquet_sink;
+use datafusion::common::file_options::file_type::FileType;
+use datafusion::datasource::file_format::arrow::{ArrowFormat, ArrowFormatFactory};
+use datafusion::datasource::file_format::format_as_file_type;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use std::fmt;
+use std::sync::Arc;
+
+/// The file format of a checkpointed result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultFormat {
+    /// Arrow IPC files.
+    Arrow,
+    Parquet,
+}
+
+impl ResultFormat {
+    /// The format of the file at `path`, by its extension.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension {
+            "arrow" => Some(ResultFormat::Arrow),
+            "parquet" => Some(ResultFormat::Parquet),
+            _ => None,
+        }
+    }
+
+    /// The extension of files of the format, without the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ResultFormat::Arrow => "arrow",
+            ResultFormat::Parquet => "parquet",
+        }
+    }
+
+    /// `path`'s format, which must be `self`.
+    pub(crate) fn check_path(&self, path: &str) -> DataFusionResult<()> {
+        if path.ends_with('/') || ResultFormat::from_path(path) != Some(*self) {
+            return Err(DataFusionError::Plan(format!(
+                "results are saved as {self} to paths ending in .{}, not '{path}'",
+                self.extension()
+            )));
+        }
+        Ok(())
+    }
+
+    /// What `COPY` writes the format as.
+    pub(crate) fn file_type(&self) -> Arc<dyn FileType> {
+        match self {
+            ResultFormat::Arrow => format_as_file_type(Arc::new(ArrowFormatFactory)),
+            ResultFormat::Parquet => parquet_sink::parquet_file_type(),
+        }
+    }
+
+    /// What files of the format are read with.
+    pub(crate) fn file_format(&self) -> Arc<dyn FileFormat> {
+        match self {
+            ResultFormat::Arrow => Arc::new(ArrowFormat),
+            ResultFormat::Parquet => Arc::new(ParquetFormat::default()),
+        }
+    }
+}
+
+impl fmt::Display for ResultFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResultFormat::Arrow => write!(f, "Arrow IPC"),
+            ResultFormat::Parquet => write!(f, "Parquet"),
+        }
+    }
+}
+
+/// The format of the result saved at `path`.
+pub(crate) fn path_format(path: &str) -> DataFusionResult<ResultFormat> {
+    ResultFormat::from_path(path).ok_or_else(|| {
+        DataFusionError::Plan(format!(
+            "'{path}' is not a saved result, which end in .{} or .{}",
+            ResultFormat::Arrow.extension(),
+            ResultFormat::Parquet.extension()
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::common::GetExt;
+
+    #[test]
+    fn test_formats_by_extension() {
+        assert_eq!(
+            ResultFormat::from_path("s3://lake/a.b/orders.arrow"),
+            Some(ResultFormat::Arrow)
+        );
+        assert_eq!(ResultFormat::from_path("/tmp/orders.parquet"), Some(ResultFormat::Parquet));
+        assert_eq!(ResultFormat::from_path("/tmp/orders.csv"), None);
+        assert_eq!(ResultFormat::from_path("/tmp/orders"), None);
+        assert_eq!(ResultFormat::from_path("/tmp/orders.PARQUET"), None);
+        assert_eq!(ArrowFormatFactory.get_ext(), ResultFormat::Arrow.extension());
+    }
+
+    #[tokio::test]
+    async fn test_saved_results_load_as_tables() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-checkpoint-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir)?;
+        let engine = QueryEngine::new();
+        engine
+            .query("CREATE TABLE orders (id INT, amount DOUBLE) AS VALUES (1, 9.5), (2, 20.0), (3, 31.5)")
+            .await?;
+        let sql = "SELECT id, amount * 2 AS doubled FROM orders WHERE amount > 10";
+        let expected = "\
++----+---------+
+| id | doubled |
++----+---------+
+| 2  | 40.0    |
+| 3  | 63.0    |
++----+---------+";
+        for format in [ResultFormat::Arrow, ResultFormat::Parquet] {
+            let path = dir.join(format!("big_orders.{}", format.extension()));
+            let path = path.to_str().expect("temp dir is UTF-8");
+            assert_eq!(engine.save_result(sql, path, format).await?, 2);
+            let table = engine.load_result(path).await?;
+            let name = format!("big_orders_{}", format.extension());
+            engine.register_table(&name, table)?;
+            let result = engine.query(&format!("SELECT * FROM {name} ORDER BY id")).await?;
+            assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        }
+
+        let path = dir.join("orders.parquet");
+        let error = engine.save_result(sql, path.to_str().unwrap(), ResultFormat::Arrow).await;
+        assert!(error.unwrap_err().to_string().contains("paths ending in .arrow"));
+        assert!(!path.exists());
+        let error = engine.load_result(dir.join("orders.csv").to_str().unwrap()).await;
+        assert!(error.unwrap_err().to_string().contains("is not a saved result"));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
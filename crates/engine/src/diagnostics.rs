@@ -0,0 +1,279 @@
+//! Non-fatal diagnostics attached to query results.
+//!
+//! A query can succeed while still doing something the user should know about, such as
+//! evaluating a filter in Igloo because the source could not. Diagnostics carry those
+//! findings next to the result so each frontend can surface them in its own way.
+
+use crate::spool::SpooledResult;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::stats::Precision;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::logical_expr::utils::split_conjunction;
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::{displayable, ExecutionPlan};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+/// Longest description of a source kept in a [`SourceTiming`].
+const MAX_SOURCE_LEN: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Informational; nothing is wrong.
+    Notice,
+    /// The result is correct, but may be slower or less fresh than expected.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Notice => write!(f, "NOTICE"),
+            Severity::Warning => write!(f, "WARNING"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Machine-readable identifier, e.g. `filter_not_pushed_down`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn notice(code: &'static str, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Notice, code, message: message.into() }
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, code, message: message.into() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
+
+/// The batches produced by a query together with any diagnostics raised while
+/// planning or executing it.
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    /// Schema of the result, also available when no batches were produced.
+    pub schema: SchemaRef,
+    pub batches: Vec<RecordBatch>,
+    /// The result, if it outgrew the engine's result spool and was written to disk
+    /// (see [`spool`](crate::spool)), in which case `batches` is empty.
+    pub spilled: Option<SpooledResult>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The tables the query read, see [`source_tables`].
+    pub tables: Vec<String>,
+    /// Bytes read from the sources, see [`scanned_bytes`].
+    pub scanned_bytes: u64,
+    /// The columns each scan fetched, see [`scan_columns`].
+    pub scan_columns: Vec<ScanColumns>,
+    /// The executed physical plan, with its metrics.
+    pub plan: Arc<dyn ExecutionPlan>,
+    /// Whether the batches were served from the engine's result cache (see
+    /// [`result_cache`](crate::result_cache)), in which case nothing was scanned.
+    pub cache_hit: bool,
+}
+
+/// A query being executed, see [`QueryEngine::query_stream`](crate::QueryEngine::query_stream).
+pub struct QueryStream {
+    pub schema: SchemaRef,
+    /// The result's batches, as they are produced.
+    pub batches: SendableRecordBatchStream,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The tables the query reads, see [`source_tables`].
+    pub tables: Vec<String>,
+    /// The physical plan executing; its metrics, such as [`scanned_bytes`], are final
+    /// once `batches` has ended.
+    pub plan: Arc<dyn ExecutionPlan>,
+    /// Whether the batches are served from the engine's result cache.
+    pub cache_hit: bool,
+}
+
+/// How long one source of an executed plan (a leaf: a scan, a remote query) took.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceTiming {
+    /// The leaf as `EXPLAIN` shows it, shortened.
+    pub source: String,
+    pub rows: Option<u64>,
+    /// CPU time spent producing its batches, where the source reports it.
+    pub elapsed_ms: Option<u64>,
+}
+
+/// The columns one scan fetches of those its table has.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanColumns {
+    pub table: String,
+    pub fetched: usize,
+    pub available: usize,
+}
+
+/// An executed physical plan as `EXPLAIN ANALYZE` shows it, with its metrics.
+pub fn explain_analyze(plan: &Arc<dyn ExecutionPlan>) -> String {
+    DisplayableExecutionPlan::with_metrics(plan.as_ref()).indent(true).to_string()
+}
+
+/// The timings of the sources of an executed physical plan, in plan order.
+pub fn source_timings(plan: &Arc<dyn ExecutionPlan>) -> Vec<SourceTiming> {
+    let mut timings = Vec::new();
+    let _ = plan.apply(|node| {
+        if node.children().is_empty() {
+            let mut source = displayable(node.as_ref()).one_line().to_string();
+            source = source.trim_end().to_string();
+            if source.len() > MAX_SOURCE_LEN {
+                let end = (0..=MAX_SOURCE_LEN).rev().find(|&i| source.is_char_boundary(i));
+                source.truncate(end.unwrap_or(0));
+                source.push('…');
+            }
+            let metrics = node.metrics();
+            timings.push(SourceTiming {
+                source,
+                rows: metrics.as_ref().and_then(|m| m.output_rows()).map(|rows| rows as u64),
+                elapsed_ms: metrics
+                    .and_then(|m| m.elapsed_compute())
+                    .map(|nanos| (nanos / 1_000_000) as u64),
+            });
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    timings
+}
+
+/// The distinct tables scanned by `plan` (including its subqueries and any views it
+/// expands), in the order they are first scanned.
+pub fn source_tables(plan: &LogicalPlan) -> Vec<String> {
+    let mut tables = Vec::new();
+    let _ = plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            let name = scan.table_name.to_string();
+            if !tables.contains(&name) {
+                tables.push(name);
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    tables
+}
+
+/// The columns each scan of `plan` (including its subqueries) asks its source for, in
+/// plan order.
+pub fn scan_columns(plan: &LogicalPlan) -> Vec<ScanColumns> {
+    let mut scans = Vec::new();
+    let _ = plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            let available = scan.source.schema().fields().len();
+            scans.push(ScanColumns {
+                table: scan.table_name.to_string(),
+                fetched: scan.projection.as_ref().map_or(available, Vec::len),
+                available,
+            });
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    scans
+}
+
+/// Bytes read from the sources by an executed physical plan: the `bytes_scanned`
+/// metric where a scan reports it (e.g. Parquet), otherwise the in-memory size of the
+/// scan's data when its statistics know it exactly (e.g. in-memory tables).
+pub fn scanned_bytes(plan: &Arc<dyn ExecutionPlan>) -> u64 {
+    let mut total = 0;
+    let _ = plan.apply(|node| {
+        let reported = node
+            .metrics()
+            .and_then(|metrics| metrics.sum_by_name("bytes_scanned"))
+            .map(|value| value.as_usize() as u64);
+        match reported {
+            Some(bytes) => total += bytes,
+            None if node.children().is_empty() => {
+                let statistics = node.partition_statistics(None);
+                if let Ok(Precision::Exact(bytes)) = statistics.map(|s| s.total_byte_size) {
+                    total += bytes as u64;
+                }
+            }
+            None => {}
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    total
+}
+
+/// Inspect an optimized logical plan for conditions worth reporting to the user.
+pub fn inspect_plan(plan: &LogicalPlan) -> DataFusionResult<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    plan.apply(|node| {
+        if let LogicalPlan::Filter(filter) = node {
+            if let LogicalPlan::TableScan(scan) = filter.input.as_ref() {
+                for predicate in split_conjunction(&filter.predicate) {
+                    if !scan.filters.contains(predicate) {
+                        diagnostics.push(Diagnostic::warning(
+                            "filter_not_pushed_down",
+                            format!(
+                                "filter `{}` is not pushed down to table `{}`; all rows are \
+                                 fetched and filtered by Igloo",
+                                predicate, scan.table_name
+                            ),
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    diagnostics.extend(unread_columns(plan)?);
+    Ok(diagnostics)
+}
+
+/// Warnings for the columns scans fetch that no part of `plan` reads, which a scan
+/// should not fetch: the plan's columns were not pruned.
+fn unread_columns(plan: &LogicalPlan) -> DataFusionResult<Vec<Diagnostic>> {
+    // By name only, which misses some unread columns but reports no read one.
+    let mut read: HashSet<String> =
+        plan.schema().fields().iter().map(|field| field.name().clone()).collect();
+    plan.apply_with_subqueries(|node| {
+        node.apply_expressions(|expr| {
+            read.extend(expr.column_refs().into_iter().map(|column| column.name.clone()));
+            Ok(TreeNodeRecursion::Continue)
+        })
+    })?;
+    let mut diagnostics = Vec::new();
+    plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            let unread: Vec<_> = scan
+                .projected_schema
+                .fields()
+                .iter()
+                .map(|field| field.name())
+                .filter(|name| !read.contains(*name))
+                .map(|name| format!("`{name}`"))
+                .collect();
+            if !unread.is_empty() {
+                diagnostics.push(Diagnostic::warning(
+                    "columns_not_pruned",
+                    format!(
+                        "table `{}` is scanned for columns {} the query does not read; they \
+                         are fetched from the source and dropped",
+                        scan.table_name,
+                        unread.join(", ")
+                    ),
+                ));
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    Ok(diagnostics)
+}
@@ -0,0 +1,520 @@
+//! External catalogs, kept in step with their source on request.
+//!
+//! The schemas and tables of an external catalog (a Hive Metastore, an Iceberg REST
+//! catalog, ...) are listed when it is registered with
+//! [`QueryEngine::register_catalog_source`](crate::QueryEngine::register_catalog_source).
+//! Tables created, dropped or altered remotely afterwards drift from that listing.
+//! [`QueryEngine::sync_catalog`](crate::QueryEngine::sync_catalog) re-reads the
+//! source's metadata, diffs it against the registered catalog, registers the new
+//! listing and returns a [`SyncReport`] of what changed, so drift is taken on when an
+//! operator decides to rather than in the middle of a query.
+//! [`QueryEngine::diff_catalog`](crate::QueryEngine::diff_catalog) only reports it.
+//!
+//! Alters are found by comparing the columns of each table with those recorded at the
+//! last sync. Registering a catalog records only names, so its first sync reports
+//! added and dropped schemas and tables but no alters.
+//!
+//! A catalog registered with
+//! [`QueryEngine::register_lazy_catalog_source`](crate::QueryEngine::register_lazy_catalog_source)
+//! is not read until a query names one of its tables or it is warmed up with
+//! [`QueryEngine::warm_up_catalog`](crate::QueryEngine::warm_up_catalog), so an
+//! unreachable source fails only the queries that need it, and is tried again by the
+//! next one. Until it is loaded, it lists no schemas and records nothing to sync
+//! against.
+
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::catalog::{CatalogProvider, SchemaProvider};
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use igloo_common::catalog::CatalogSource;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+
+/// Tables by schema, with their columns where known.
+pub type CatalogSnapshot = BTreeMap<String, BTreeMap<String, Option<SchemaRef>>>;
+
+/// How an entry of the source differs from the registered catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    SchemaAdded,
+    SchemaDropped,
+    TableAdded,
+    TableDropped,
+    /// Columns were added, dropped or changed type.
+    TableAltered,
+}
+
+/// A difference between the source and the registered catalog.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Drift {
+    pub kind: DriftKind,
+    pub schema: String,
+    /// `None` for schemas.
+    pub table: Option<String>,
+    /// The column changes of an altered table.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<String>,
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (sign, noun) = match self.kind {
+            DriftKind::SchemaAdded => ('+', "schema"),
+            DriftKind::SchemaDropped => ('-', "schema"),
+            DriftKind::TableAdded => ('+', "table"),
+            DriftKind::TableDropped => ('-', "table"),
+            DriftKind::TableAltered => ('~', "table"),
+        };
+        write!(f, "{sign} {noun} {}", self.schema)?;
+        if let Some(table) = &self.table {
+            write!(f, ".{table}")?;
+        }
+        if !self.changes.is_empty() {
+            write!(f, ": {}", self.changes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// What a sync found, and whether it was applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncReport {
+    pub catalog: String,
+    pub applied: bool,
+    pub drift: Vec<Drift>,
+    /// Tables whose metadata could not be read; they are kept as they were.
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SyncReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = if self.applied { "synced" } else { "differs from its source" };
+        match self.drift.len() {
+            0 => write!(f, "catalog {} is up to date", self.catalog)?,
+            n => write!(f, "catalog {} {outcome} ({n} changes)", self.catalog)?,
+        }
+        for drift in &self.drift {
+            write!(f, "\n{drift}")?;
+        }
+        for error in &self.errors {
+            write!(f, "\n! {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A registered external catalog: where to read it, and what was read last.
+#[derive(Debug)]
+pub(crate) struct ExternalCatalog {
+    source: Arc<dyn CatalogSource>,
+    snapshot: CatalogSnapshot,
+    /// Registered lazily and not replaced by a sync since.
+    lazy: Option<Arc<LazyLoader>>,
+}
+
+/// An engine's external catalogs by name. Syncs of one engine run one at a time.
+pub(crate) type ExternalCatalogs = tokio::sync::Mutex<BTreeMap<String, ExternalCatalog>>;
+
+/// Load `source` and register it in `ctx` as `name`, recording its listing.
+pub(crate) async fn register(
+    ctx: &SessionContext,
+    catalogs: &ExternalCatalogs,
+    name: &str,
+    source: Arc<dyn CatalogSource>,
+) -> DataFusionResult<()> {
+    let catalog = source.load_catalog().await?;
+    let snapshot = listing(catalog.as_ref());
+    ctx.register_catalog(name, catalog);
+    let external = ExternalCatalog { source, snapshot, lazy: None };
+    catalogs.lock().await.insert(name.to_string(), external);
+    Ok(())
+}
+
+/// The names of the catalogs whose sources have been read.
+pub(crate) async fn loaded(catalogs: &ExternalCatalogs) -> Vec<String> {
+    let catalogs = catalogs.lock().await;
+    let loaded = |external: &ExternalCatalog| {
+        external.lazy.as_ref().map_or(true, |lazy| lazy.loaded.get().is_some())
+    };
+    catalogs.iter().filter(|(_, external)| loaded(external)).map(|(name, _)| name.clone()).collect()
+}
+
+/// Register `source` in `ctx` as `name` without reading it; see [`LazyCatalog`].
+pub(crate) async fn register_lazy(
+    ctx: &SessionContext,
+    catalogs: &ExternalCatalogs,
+    name: &str,
+    source: Arc<dyn CatalogSource>,
+) {
+    let loader = Arc::new(LazyLoader {
+        name: name.to_string(),
+        source: Arc::clone(&source),
+        loaded: OnceLock::new(),
+        loading: tokio::sync::Mutex::new(()),
+    });
+    ctx.register_catalog(name, Arc::new(LazyCatalog(Arc::clone(&loader))));
+    let external = ExternalCatalog { source, snapshot: CatalogSnapshot::new(), lazy: Some(loader) };
+    catalogs.lock().await.insert(name.to_string(), external);
+}
+
+/// Load the catalog `name` if it was registered lazily and is not loaded yet.
+pub(crate) async fn warm_up(catalogs: &ExternalCatalogs, name: &str) -> DataFusionResult<()> {
+    let lazy = match catalogs.lock().await.get(name) {
+        Some(external) => external.lazy.clone(),
+        None => {
+            return Err(DataFusionError::Plan(format!("no catalog source is registered as {name}")))
+        }
+    };
+    // Not holding the lock while the source is read, which may take a while.
+    match lazy {
+        Some(loader) => loader.load().await.map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// Diff the source of catalog `name` against what `ctx` has registered, and register
+/// the source's current listing if `apply`.
+pub(crate) async fn sync(
+    ctx: &SessionContext,
+    catalogs: &ExternalCatalogs,
+    name: &str,
+    apply: bool,
+) -> DataFusionResult<SyncReport> {
+    let mut catalogs = catalogs.lock().await;
+    let external = catalogs.get_mut(name).ok_or_else(|| {
+        DataFusionError::Plan(format!("no catalog source is registered as {name}"))
+    })?;
+    if let Some(loaded) = external.lazy.as_ref().and_then(|loader| loader.loaded.get()) {
+        if external.snapshot.is_empty() {
+            external.snapshot = listing(loaded.as_ref());
+        }
+    }
+    let catalog = external.source.load_catalog().await?;
+    let mut snapshot = listing(catalog.as_ref());
+    let mut errors = vec![];
+    for (schema_name, tables) in &mut snapshot {
+        let Some(schema) = catalog.schema(schema_name) else {
+            continue;
+        };
+        for (table, columns) in tables {
+            match schema.table(table).await {
+                Ok(Some(provider)) => *columns = Some(provider.schema()),
+                Ok(None) => errors.push(format!("{schema_name}.{table}: listed but not found")),
+                Err(e) => errors.push(format!("{schema_name}.{table}: {e}")),
+            }
+            // Keep what was known of tables that could not be read this time.
+            if columns.is_none() {
+                *columns = known(&external.snapshot, schema_name, table);
+            }
+        }
+    }
+    let drift = diff(&external.snapshot, &snapshot);
+    if apply {
+        ctx.register_catalog(name, catalog);
+        external.snapshot = snapshot;
+        external.lazy = None;
+    }
+    Ok(SyncReport { catalog: name.to_string(), applied: apply, drift, errors })
+}
+
+/// Loads a lazily registered catalog from its source, once it can.
+#[derive(Debug)]
+pub(crate) struct LazyLoader {
+    name: String,
+    source: Arc<dyn CatalogSource>,
+    loaded: OnceLock<Arc<dyn CatalogProvider>>,
+    /// Held while the source is read, so that it is read once at a time.
+    loading: tokio::sync::Mutex<()>,
+}
+
+impl LazyLoader {
+    /// The catalog, read from the source unless it was already. Failures are not
+    /// kept: the next call reads the source again.
+    async fn load(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+        if let Some(catalog) = self.loaded.get() {
+            return Ok(Arc::clone(catalog));
+        }
+        let _loading = self.loading.lock().await;
+        if let Some(catalog) = self.loaded.get() {
+            return Ok(Arc::clone(catalog));
+        }
+        let catalog = self.source.load_catalog().await.map_err(|e| {
+            DataFusionError::Context(format!("catalog {} is unavailable", self.name), Box::new(e))
+        })?;
+        Ok(Arc::clone(self.loaded.get_or_init(|| catalog)))
+    }
+}
+
+/// A catalog read from its source when a query first resolves one of its tables.
+/// Before that, it lists no schemas but hands out any schema asked for, whose tables
+/// load the catalog when looked up.
+#[derive(Debug)]
+struct LazyCatalog(Arc<LazyLoader>);
+
+impl CatalogProvider for LazyCatalog {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.0.loaded.get().map(|catalog| catalog.schema_names()).unwrap_or_default()
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        match self.0.loaded.get() {
+            Some(catalog) => catalog.schema(name),
+            None => Some(Arc::new(LazySchema { loader: Arc::clone(&self.0), name: name.into() })),
+        }
+    }
+}
+
+/// A schema of a [`LazyCatalog`] that was not loaded when the schema was asked for.
+#[derive(Debug)]
+struct LazySchema {
+    loader: Arc<LazyLoader>,
+    name: String,
+}
+
+impl LazySchema {
+    fn loaded(&self) -> Option<Arc<dyn SchemaProvider>> {
+        self.loader.loaded.get()?.schema(&self.name)
+    }
+}
+
+#[async_trait]
+impl SchemaProvider for LazySchema {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.loaded().map(|schema| schema.table_names()).unwrap_or_default()
+    }
+
+    async fn table(&self, name: &str) -> DataFusionResult<Option<Arc<dyn TableProvider>>> {
+        match self.loader.load().await?.schema(&self.name) {
+            Some(schema) => schema.table(name).await,
+            None => Ok(None),
+        }
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        self.loaded().is_some_and(|schema| schema.table_exist(name))
+    }
+}
+
+/// The schemas and tables of `catalog`, without their columns.
+fn listing(catalog: &dyn CatalogProvider) -> CatalogSnapshot {
+    let mut snapshot = CatalogSnapshot::new();
+    for name in catalog.schema_names() {
+        let tables = match catalog.schema(&name) {
+            Some(schema) => schema.table_names().into_iter().map(|t| (t, None)).collect(),
+            None => BTreeMap::new(),
+        };
+        snapshot.insert(name, tables);
+    }
+    snapshot
+}
+
+fn known(snapshot: &CatalogSnapshot, schema: &str, table: &str) -> Option<SchemaRef> {
+    snapshot.get(schema)?.get(table)?.clone()
+}
+
+/// How `new` differs from `old`, in schema and table order.
+pub fn diff(old: &CatalogSnapshot, new: &CatalogSnapshot) -> Vec<Drift> {
+    let drift = |kind, schema: &str, table: Option<&str>, changes| Drift {
+        kind,
+        schema: schema.to_string(),
+        table: table.map(str::to_string),
+        changes,
+    };
+    let mut report = vec![];
+    for (schema, old_tables) in old {
+        if !new.contains_key(schema) {
+            report.push(drift(DriftKind::SchemaDropped, schema, None, vec![]));
+        }
+        for table in old_tables.keys() {
+            if !new.get(schema).is_some_and(|tables| tables.contains_key(table)) {
+                report.push(drift(DriftKind::TableDropped, schema, Some(table), vec![]));
+            }
+        }
+    }
+    for (schema, new_tables) in new {
+        let old_tables = old.get(schema);
+        if old_tables.is_none() {
+            report.push(drift(DriftKind::SchemaAdded, schema, None, vec![]));
+        }
+        for (table, columns) in new_tables {
+            match old_tables.and_then(|tables| tables.get(table)) {
+                None => report.push(drift(DriftKind::TableAdded, schema, Some(table), vec![])),
+                Some(Some(before)) => {
+                    let Some(after) = columns else {
+                        continue;
+                    };
+                    let changes = column_changes(before, after);
+                    if !changes.is_empty() {
+                        report.push(drift(DriftKind::TableAltered, schema, Some(table), changes));
+                    }
+                }
+                Some(None) => {}
+            }
+        }
+    }
+    report
+}
+
+/// The columns added, dropped or retyped from `before` to `after`.
+fn column_changes(before: &Schema, after: &Schema) -> Vec<String> {
+    let mut changes = vec![];
+    for field in before.fields() {
+        match after.field_with_name(field.name()) {
+            Err(_) => changes.push(format!("dropped column {}", field.name())),
+            Ok(new) if new.data_type() != field.data_type() => changes.push(format!(
+                "column {} changed from {} to {}",
+                field.name(),
+                field.data_type(),
+                new.data_type()
+            )),
+            Ok(_) => {}
+        }
+    }
+    for field in after.fields() {
+        if before.field_with_name(field.name()).is_err() {
+            changes.push(format!("added column {} {}", field.name(), field.data_type()));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::datatypes::{DataType, Field};
+    use datafusion::catalog::{MemTable, MemoryCatalogProvider, MemorySchemaProvider};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    type RemoteTable = (&'static str, &'static str, Vec<(&'static str, DataType)>);
+
+    /// A remote catalog whose tables are `(schema, table, columns)`.
+    #[derive(Debug, Default)]
+    struct Remote(Mutex<Vec<RemoteTable>>);
+
+    #[async_trait]
+    impl CatalogSource for Remote {
+        async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+            let catalog = MemoryCatalogProvider::new();
+            for (schema_name, table, columns) in self.0.lock().unwrap().iter() {
+                if catalog.schema(schema_name).is_none() {
+                    catalog.register_schema(schema_name, Arc::new(MemorySchemaProvider::new()))?;
+                }
+                let fields: Vec<_> =
+                    columns.iter().map(|(name, t)| Field::new(*name, t.clone(), true)).collect();
+                let table_provider = MemTable::try_new(Arc::new(Schema::new(fields)), vec![])?;
+                let schema = catalog.schema(schema_name).unwrap();
+                schema.register_table(table.to_string(), Arc::new(table_provider))?;
+            }
+            Ok(Arc::new(catalog))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_reports_and_applies_drift() -> DataFusionResult<()> {
+        let remote = Arc::new(Remote::default());
+        *remote.0.lock().unwrap() = vec![
+            ("sales", "orders", vec![("id", DataType::Int32)]),
+            ("sales", "refunds", vec![("id", DataType::Int32)]),
+            ("legacy", "events", vec![("id", DataType::Int32)]),
+        ];
+        let engine = QueryEngine::new();
+        engine.register_catalog_source("remote", remote.clone()).await?;
+        engine.query("SELECT * FROM remote.sales.orders").await?;
+        // A first sync records the columns.
+        let report = engine.sync_catalog("remote").await?;
+        assert!(report.drift.is_empty(), "{report}");
+
+        *remote.0.lock().unwrap() = vec![
+            ("sales", "orders", vec![("id", DataType::Int64), ("email", DataType::Utf8)]),
+            ("sales", "customers", vec![("id", DataType::Int32)]),
+            ("marketing", "campaigns", vec![("id", DataType::Int32)]),
+        ];
+        let report = engine.diff_catalog("remote").await?;
+        let expected = "\
+catalog remote differs from its source (7 changes)
+- schema legacy
+- table legacy.events
+- table sales.refunds
++ schema marketing
++ table marketing.campaigns
++ table sales.customers
+~ table sales.orders: column id changed from Int32 to Int64, added column email Utf8";
+        assert_eq!(report.to_string(), expected);
+        // Only reported, not applied.
+        assert!(engine.query("SELECT * FROM remote.sales.customers").await.is_err());
+        engine.query("SELECT * FROM remote.legacy.events").await?;
+
+        let report = engine.sync_catalog("remote").await?;
+        assert!(report.applied);
+        assert_eq!(report.drift.len(), 7);
+        engine.query("SELECT email FROM remote.sales.orders").await?;
+        engine.query("SELECT * FROM remote.sales.customers").await?;
+        assert!(engine.query("SELECT * FROM remote.legacy.events").await.is_err());
+        assert!(engine.sync_catalog("remote").await?.drift.is_empty());
+        assert!(engine.sync_catalog("missing").await.is_err());
+        Ok(())
+    }
+
+    /// A [`Remote`] that cannot be reached while `down`, counting its loads.
+    #[derive(Debug, Default)]
+    struct Flaky {
+        remote: Remote,
+        down: AtomicBool,
+        loads: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CatalogSource for Flaky {
+        async fn load_catalog(&self) -> DataFusionResult<Arc<dyn CatalogProvider>> {
+            self.loads.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(DataFusionError::External("connection refused".into()));
+            }
+            self.remote.load_catalog().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lazy_catalogs_are_loaded_when_first_needed() -> DataFusionResult<()> {
+        let flaky = Arc::new(Flaky::default());
+        *flaky.remote.0.lock().unwrap() = vec![("sales", "orders", vec![("id", DataType::Int32)])];
+        flaky.down.store(true, Ordering::SeqCst);
+        let engine = QueryEngine::new();
+        engine.register_lazy_catalog_source("remote", flaky.clone()).await?;
+        assert_eq!(flaky.loads.load(Ordering::SeqCst), 0);
+
+        // Queries that do not need the source run while it is down.
+        engine.query("SELECT 1").await?;
+        assert_eq!(flaky.loads.load(Ordering::SeqCst), 0);
+        let error = engine.query("SELECT * FROM remote.sales.orders").await.unwrap_err();
+        assert!(error.to_string().contains("catalog remote is unavailable"), "{error}");
+        assert!(engine.warm_up_catalog("remote").await.is_err());
+
+        flaky.down.store(false, Ordering::SeqCst);
+        engine.warm_up_catalog("remote").await?;
+        let loads = flaky.loads.load(Ordering::SeqCst);
+        engine.query("SELECT * FROM remote.sales.orders").await?;
+        engine.warm_up_catalog("remote").await?;
+        assert_eq!(flaky.loads.load(Ordering::SeqCst), loads);
+        // The listing loaded is what the first sync compares against.
+        assert!(engine.sync_catalog("remote").await?.drift.is_empty());
+        assert!(engine.warm_up_catalog("missing").await.is_err());
+        Ok(())
+    }
+}
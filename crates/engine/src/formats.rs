@@ -0,0 +1,270 @@
+//! Rendering query results in the formats clients ask for.
+//!
+//! Frontends pick an [`OutputFormat`] (the CLI from `--format`, the HTTP server from the
+//! `Accept` header) and hand it the result batches; each format knows its own encoding
+//! and media type.
+
+use datafusion::arrow::csv::WriterBuilder as CsvWriterBuilder;
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::json::{ArrayWriter, LineDelimitedWriter};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::parquet::arrow::ArrowWriter;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable ASCII table.
+    Table,
+    Csv,
+    /// A single JSON array of row objects.
+    Json,
+    /// One JSON object per line.
+    JsonLines,
+    Parquet,
+    /// Arrow IPC streaming format.
+    Arrow,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 6] = [
+        OutputFormat::Table,
+        OutputFormat::Csv,
+        OutputFormat::Json,
+        OutputFormat::JsonLines,
+        OutputFormat::Parquet,
+        OutputFormat::Arrow,
+    ];
+
+    /// The name accepted by [`FromStr`], e.g. on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Table => "table",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Arrow => "arrow",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Table => "text/plain",
+            OutputFormat::Csv => "text/csv",
+            OutputFormat::Json => "application/json",
+            OutputFormat::JsonLines => "application/x-ndjson",
+            OutputFormat::Parquet => "application/vnd.apache.parquet",
+            OutputFormat::Arrow => "application/vnd.apache.arrow.stream",
+        }
+    }
+
+    /// Whether the encoding is text meant for a terminal or text-based tooling.
+    pub fn is_text(self) -> bool {
+        !matches!(self, OutputFormat::Parquet | OutputFormat::Arrow)
+    }
+
+    /// Map a media type (without parameters) to a format. Wildcards resolve to JSON
+    /// for `application/*` and to CSV for `text/*`.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type.trim() {
+            "*/*" | "application/*" => Some(OutputFormat::Json),
+            "text/*" => Some(OutputFormat::Csv),
+            media_type => Self::ALL.into_iter().find(|format| format.content_type() == media_type),
+        }
+    }
+
+    /// Pick the first supported media type listed in an `Accept` header, in the
+    /// client's order. Quality values are ignored.
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .find_map(|range| Self::from_media_type(range.split(';').next().unwrap_or_default()))
+    }
+
+    /// Encode `batches` (all with `schema`) into `out`.
+    pub fn write<W: Write + Send>(
+        self,
+        schema: &Schema,
+        batches: &[RecordBatch],
+        out: W,
+    ) -> DataFusionResult<()> {
+        self.write_batches(schema, batches.iter().cloned().map(Ok), out)
+    }
+
+    /// Encode `batches` (all with `schema`) into `out` as they are read, e.g. from a
+    /// [`SpooledResult`](crate::spool::SpooledResult). Only [`OutputFormat::Table`]
+    /// holds them all, to size its columns.
+    pub fn write_batches<W: Write + Send>(
+        self,
+        schema: &Schema,
+        batches: impl IntoIterator<Item = DataFusionResult<RecordBatch>>,
+        mut out: W,
+    ) -> DataFusionResult<()> {
+        let mut batches = batches.into_iter().peekable();
+        match self {
+            OutputFormat::Table => {
+                let mut batches = batches.collect::<DataFusionResult<Vec<_>>>()?;
+                // `pretty_format_batches` needs a batch to print the header from.
+                if batches.is_empty() {
+                    batches.push(RecordBatch::new_empty(schema.clone().into()));
+                }
+                writeln!(out, "{}", pretty_format_batches(&batches)?)?;
+            }
+            OutputFormat::Csv => {
+                let mut writer = CsvWriterBuilder::new().with_header(true).build(out);
+                if batches.peek().is_none() {
+                    writer.write(&RecordBatch::new_empty(schema.clone().into()))?;
+                }
+                for batch in batches {
+                    writer.write(&batch?)?;
+                }
+            }
+            OutputFormat::Json => {
+                // Writes `[]` for an empty result.
+                let mut writer = ArrayWriter::new(out);
+                for batch in batches {
+                    writer.write(&batch?)?;
+                }
+                writer.finish()?;
+            }
+            OutputFormat::JsonLines => {
+                let mut writer = LineDelimitedWriter::new(out);
+                for batch in batches {
+                    writer.write(&batch?)?;
+                }
+                writer.finish()?;
+            }
+            OutputFormat::Parquet => {
+                let mut writer = ArrowWriter::try_new(out, schema.clone().into(), None)?;
+                for batch in batches {
+                    writer.write(&batch?)?;
+                }
+                writer.close()?;
+            }
+            OutputFormat::Arrow => {
+                let mut writer = StreamWriter::try_new(out, schema)?;
+                for batch in batches {
+                    writer.write(&batch?)?;
+                }
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode `batches` into a new buffer.
+    pub fn to_bytes(self, schema: &Schema, batches: &[RecordBatch]) -> DataFusionResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(schema, batches, &mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = DataFusionError;
+
+    fn from_str(s: &str) -> DataFusionResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "table" | "pretty" => Ok(OutputFormat::Table),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" | "json-lines" => Ok(OutputFormat::JsonLines),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "arrow" | "ipc" => Ok(OutputFormat::Arrow),
+            other => Err(DataFusionError::Configuration(format!(
+                "unknown output format '{other}', expected one of: {}",
+                Self::ALL.map(OutputFormat::name).join(", ")
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field};
+    use datafusion::arrow::ipc::reader::StreamReader;
+    use datafusion::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::sync::Arc;
+
+    fn batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2])),
+                Arc::new(StringArray::from(vec![Some("one"), None])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn render(format: OutputFormat, batches: &[RecordBatch]) -> Vec<u8> {
+        format.to_bytes(&batch().schema(), batches).unwrap()
+    }
+
+    #[test]
+    fn test_text_formats() {
+        let batches = [batch()];
+        let table = String::from_utf8(render(OutputFormat::Table, &batches)).unwrap();
+        assert!(table.contains("| 1  | one  |"), "{table}");
+        assert_eq!(render(OutputFormat::Csv, &batches), b"id,name\n1,one\n2,\n");
+        assert_eq!(render(OutputFormat::Json, &batches), br#"[{"id":1,"name":"one"},{"id":2}]"#);
+        assert_eq!(
+            render(OutputFormat::JsonLines, &batches),
+            b"{\"id\":1,\"name\":\"one\"}\n{\"id\":2}\n"
+        );
+    }
+
+    #[test]
+    fn test_empty_results_keep_their_shape() {
+        assert_eq!(render(OutputFormat::Json, &[]), b"[]");
+        assert_eq!(render(OutputFormat::Csv, &[]), b"id,name\n");
+        let table = String::from_utf8(render(OutputFormat::Table, &[])).unwrap();
+        assert!(table.contains("| id | name |"), "{table}");
+    }
+
+    #[test]
+    fn test_binary_formats_round_trip() {
+        let batches = [batch()];
+        let ipc = render(OutputFormat::Arrow, &batches);
+        let read: Vec<_> =
+            StreamReader::try_new(ipc.as_slice(), None).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(read, batches);
+
+        let parquet = render(OutputFormat::Parquet, &batches);
+        let read: Vec<_> = ParquetRecordBatchReaderBuilder::try_new(bytes::Bytes::from(parquet))
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, batches);
+    }
+
+    #[test]
+    fn test_negotiation_and_names() {
+        assert_eq!(OutputFormat::negotiate("text/html, text/csv;q=0.9"), Some(OutputFormat::Csv));
+        assert_eq!(OutputFormat::negotiate("*/*"), Some(OutputFormat::Json));
+        assert_eq!(OutputFormat::negotiate("application/xml"), None);
+        for format in OutputFormat::ALL {
+            assert_eq!(format.name().parse::<OutputFormat>().unwrap(), format);
+        }
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,597 @@
+//! Appending pushed Arrow data to tables.
+//!
+//! [`QueryEngine::ingest`] appends a stream of batches to a registered table that
+//! takes `INSERT`s (an Iceberg table, a Parquet listing table, a memory table).
+//! Batches are buffered and committed together, each commit one `INSERT` (one
+//! snapshot of an Iceberg table, one set of files of a listing table): once
+//! [`IngestOptions::max_rows`] rows are buffered, once [`IngestOptions::interval`]
+//! has passed since the first of them arrived, and at the end of the stream. A
+//! failed commit ends the ingestion; what was committed before it stays.
+//!
+//! Batches are matched to the table's columns by name and cast to their types;
+//! nullable columns a batch lacks are null.
+//!
+//! # Write-ahead log
+//!
+//! Buffered rows live in memory until their commit, so a crash loses them. An engine
+//! with an [`IngestWal`] (see [`QueryEngine::with_ingest_wal`]) first writes every
+//! received batch to a segment of the log on local disk, one segment per commit, and
+//! removes the segment once the commit succeeds or the ingestion fails (the caller
+//! learns of the failure). Only a crash leaves segments behind:
+//! [`QueryEngine::replay_ingest_wal`], run on startup, commits each to the table it
+//! was for and removes it. A crash after a commit but before the removal of its
+//! segment commits its rows twice.
+//!
+//! # Deduplication
+//!
+//! Sources that deliver at least once (a Kafka consumer restarted from an older
+//! offset, a client retrying a `DoPut`) resend rows. An ingestion with
+//! [`IngestOptions::keys`] leaves out rows whose values of those columns match a row
+//! it has already buffered, or one committed to the table recently. The engine keeps
+//! the keys of the last [`IngestOptions::dedup_window`] rows committed to each table
+//! in memory, shared by its ingestions into the table; replaying the write-ahead log
+//! fills the index again after a restart, but rows committed before the restart
+//! are not in it. Rows left out are counted in [`IngestReport::duplicates`].
+//!
+//! [`QueryEngine::ingest`]: crate::QueryEngine::ingest
+//! [`QueryEngine::with_ingest_wal`]: crate::QueryEngine::with_ingest_wal
+//! [`QueryEngine::replay_ingest_wal`]: crate::QueryEngine::replay_ingest_wal
+
+use datafusion::arrow::array::{new_null_array, ArrayRef, BooleanBuilder};
+use datafusion::arrow::compute::{cast, filter_record_batch};
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::row::{RowConverter, SortField};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Rows buffered before a commit unless configured otherwise.
+pub const DEFAULT_MAX_ROWS: usize = 100_000;
+
+/// Longest rows wait for a commit unless configured otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Keys of committed rows remembered per table unless configured otherwise.
+pub const DEFAULT_DEDUP_WINDOW: usize = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IngestOptions {
+    pub max_rows: usize,
+    pub interval: Duration,
+    /// Columns identifying a row, to leave out duplicates (see the
+    /// [module docs](self#deduplication)). Empty to append every row.
+    pub keys: Vec<String>,
+    pub dedup_window: usize,
+}
+
+impl Default for IngestOptions {
+    fn default() -> Self {
+        Self {
+            max_rows: DEFAULT_MAX_ROWS,
+            interval: DEFAULT_INTERVAL,
+            keys: vec![],
+            dedup_window: DEFAULT_DEDUP_WINDOW,
+        }
+    }
+}
+
+impl IngestOptions {
+    pub fn with_max_rows(mut self, max_rows: usize) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_keys(mut self, keys: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.keys = keys.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_dedup_window(mut self, dedup_window: usize) -> Self {
+        self.dedup_window = dedup_window;
+        self
+    }
+}
+
+/// What an ingestion appended.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestReport {
+    pub rows: u64,
+    pub commits: usize,
+    /// Rows left out as duplicates of rows ingested before.
+    pub duplicates: u64,
+}
+
+/// Keys of the rows most recently committed to each table, by fully qualified name.
+#[derive(Debug, Default)]
+pub(crate) struct DedupIndexes(Mutex<HashMap<String, Arc<Mutex<DedupIndex>>>>);
+
+#[derive(Debug, Default)]
+struct DedupIndex {
+    keys: HashSet<Arc<[u8]>>,
+    /// The keys, oldest first.
+    order: VecDeque<Arc<[u8]>>,
+}
+
+impl DedupIndex {
+    fn insert(&mut self, key: Arc<[u8]>, window: usize) {
+        if self.keys.insert(Arc::clone(&key)) {
+            self.order.push_back(key);
+        }
+        while self.order.len() > window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl DedupIndexes {
+    /// Approximate bytes held by the keys of all tables' indexes.
+    pub(crate) fn size_bytes(&self) -> usize {
+        // Each key is shared by the set and the queue, behind reference counts.
+        let overhead = 2 * std::mem::size_of::<usize>() + 2 * std::mem::size_of::<Arc<[u8]>>();
+        let indexes = self.0.lock().expect("dedup index lock poisoned");
+        indexes
+            .values()
+            .map(|index| {
+                let index = index.lock().expect("dedup index lock poisoned");
+                index.order.iter().map(|key| key.len() + overhead).sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Deduplication of rows of `schema` by the columns `keys` for one ingestion into
+    /// `table`, or `None` without keys.
+    pub(crate) fn dedup(
+        &self,
+        table: &str,
+        schema: &SchemaRef,
+        keys: &[String],
+        window: usize,
+    ) -> DataFusionResult<Option<Dedup>> {
+        if keys.is_empty() {
+            return Ok(None);
+        }
+        let columns = keys
+            .iter()
+            .map(|key| {
+                schema.index_of(key).map_err(|_| {
+                    DataFusionError::Plan(format!("ingestion key {key} is not a column of {table}"))
+                })
+            })
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        let fields = columns.iter().map(|&i| SortField::new(schema.field(i).data_type().clone()));
+        let converter = RowConverter::new(fields.collect())?;
+        let mut indexes = self.0.lock().expect("dedup index lock poisoned");
+        let index = Arc::clone(indexes.entry(table.to_string()).or_default());
+        Ok(Some(Dedup {
+            columns,
+            converter,
+            window,
+            index,
+            pending: HashSet::new(),
+            pending_order: vec![],
+        }))
+    }
+}
+
+/// Leaves out duplicate rows for one ingestion, see the [module docs](self#deduplication).
+pub(crate) struct Dedup {
+    columns: Vec<usize>,
+    converter: RowConverter,
+    window: usize,
+    index: Arc<Mutex<DedupIndex>>,
+    /// Keys of the rows buffered for the next commit, and the same in order.
+    pending: HashSet<Arc<[u8]>>,
+    pending_order: Vec<Arc<[u8]>>,
+}
+
+impl Dedup {
+    /// `batch` without the rows whose keys are pending or were committed recently, and
+    /// the number of rows left out. The rows kept are pending until [`Self::committed`].
+    pub(crate) fn filter(&mut self, batch: &RecordBatch) -> DataFusionResult<(RecordBatch, u64)> {
+        let keys: Vec<ArrayRef> =
+            self.columns.iter().map(|&i| Arc::clone(batch.column(i))).collect();
+        let rows = self.converter.convert_columns(&keys)?;
+        let index = self.index.lock().expect("dedup index lock poisoned");
+        let mut keep = BooleanBuilder::with_capacity(rows.num_rows());
+        for row in rows.iter() {
+            let key = row.as_ref();
+            let new = !index.keys.contains(key) && !self.pending.contains(key);
+            if new {
+                let key: Arc<[u8]> = key.into();
+                self.pending.insert(Arc::clone(&key));
+                self.pending_order.push(key);
+            }
+            keep.append_value(new);
+        }
+        let keep = keep.finish();
+        Ok((filter_record_batch(batch, &keep)?, keep.false_count() as u64))
+    }
+
+    /// Remember the keys of the pending rows, once committed.
+    pub(crate) fn committed(&mut self) {
+        let mut index = self.index.lock().expect("dedup index lock poisoned");
+        self.pending.clear();
+        for key in self.pending_order.drain(..) {
+            index.insert(key, self.window);
+        }
+    }
+}
+
+/// Extension of the segment files of a write-ahead log.
+const SEGMENT_EXTENSION: &str = "arrows";
+
+/// Schema metadata key of the table, fully qualified, a segment's rows are for.
+const TABLE_KEY: &str = "igloo.ingest.table";
+
+/// Schema metadata key of the ingestion's [`IngestOptions::keys`], as a JSON array.
+const KEYS_KEY: &str = "igloo.ingest.keys";
+
+/// A write-ahead log of ingested rows in a local directory, see the
+/// [module docs](self).
+#[derive(Debug)]
+pub struct IngestWal {
+    dir: PathBuf,
+    next: AtomicU64,
+}
+
+impl IngestWal {
+    /// Log to the directory `dir`, creating it if needed. Segments already in it are
+    /// left for [`QueryEngine::replay_ingest_wal`](crate::QueryEngine::replay_ingest_wal).
+    pub fn open(dir: impl Into<PathBuf>) -> DataFusionResult<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let next = sequence_numbers(&dir)?.last().map_or(0, |(seq, _)| seq + 1);
+        Ok(Self { dir, next: AtomicU64::new(next) })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Paths of the segments in the log, oldest first.
+    pub fn segments(&self) -> DataFusionResult<Vec<PathBuf>> {
+        Ok(sequence_numbers(&self.dir)?.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Start a segment of rows of `schema` for the table `table`, fully qualified, by an
+    /// ingestion deduplicating by `keys`.
+    pub(crate) async fn create(
+        &self,
+        table: &str,
+        keys: &[String],
+        schema: &SchemaRef,
+    ) -> DataFusionResult<WalSegment> {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{seq:020}.{SEGMENT_EXTENSION}"));
+        let mut metadata = schema.metadata().clone();
+        metadata.insert(TABLE_KEY.to_string(), table.to_string());
+        if !keys.is_empty() {
+            let keys =
+                serde_json::to_string(keys).map_err(|e| DataFusionError::External(e.into()))?;
+            metadata.insert(KEYS_KEY.to_string(), keys);
+        }
+        let schema = Schema::new_with_metadata(schema.fields().clone(), metadata);
+        let (dir, file) = (self.dir.clone(), path.clone());
+        let writer = blocking(move || {
+            let file = OpenOptions::new().write(true).create_new(true).open(file)?;
+            let mut writer = StreamWriter::try_new(file, &schema)?;
+            sync(&mut writer)?;
+            // Make the new file's directory entry durable too.
+            File::open(&dir)?.sync_all()?;
+            Ok(writer)
+        })
+        .await?;
+        Ok(WalSegment { path: Some(path), writer: Arc::new(Mutex::new(writer)) })
+    }
+}
+
+/// Run the file I/O `f` on a blocking thread, keeping it off the async runtime's.
+pub(crate) async fn blocking<T, F>(f: F) -> DataFusionResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> DataFusionResult<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await.map_err(|e| DataFusionError::External(Box::new(e)))?
+}
+
+/// The segment files in `dir` by sequence number, in order.
+fn sequence_numbers(dir: &Path) -> DataFusionResult<Vec<(u64, PathBuf)>> {
+    let mut segments = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+            continue;
+        }
+        if let Some(seq) = path.file_stem().and_then(|s| s.to_str()?.parse().ok()) {
+            segments.push((seq, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// The segment of one commit's rows. Removed once [`WalSegment::remove`]d or dropped;
+/// a crash leaves it.
+pub(crate) struct WalSegment {
+    /// `None` once removed.
+    path: Option<PathBuf>,
+    writer: Arc<Mutex<StreamWriter<File>>>,
+}
+
+impl WalSegment {
+    /// Log `batch`, returning once it is on disk.
+    pub(crate) async fn append(&self, batch: &RecordBatch) -> DataFusionResult<()> {
+        let (writer, batch) = (Arc::clone(&self.writer), batch.clone());
+        blocking(move || {
+            let mut writer = writer.lock().expect("ingestion log segment lock poisoned");
+            writer.write(&batch)?;
+            sync(&mut writer)
+        })
+        .await
+    }
+
+    /// Remove the segment, its rows committed.
+    pub(crate) async fn remove(mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = blocking(move || Ok(std::fs::remove_file(path)?)).await;
+        }
+    }
+}
+
+fn sync(writer: &mut StreamWriter<File>) -> DataFusionResult<()> {
+    writer.flush()?;
+    Ok(writer.get_ref().sync_data()?)
+}
+
+impl Drop for WalSegment {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// The rows of one segment of a write-ahead log.
+#[derive(Debug)]
+pub(crate) struct LoggedRows {
+    /// The table, fully qualified.
+    pub table: String,
+    pub keys: Vec<String>,
+    pub batches: Vec<RecordBatch>,
+}
+
+/// The rows of the segment at `path`, or `None` if it holds none (a crash cut it off
+/// before its first batch). A final batch cut off while being logged was never
+/// received, and is left out.
+pub(crate) fn read_segment(path: &Path) -> DataFusionResult<Option<LoggedRows>> {
+    let Ok(reader) = StreamReader::try_new(BufReader::new(File::open(path)?), None) else {
+        return Ok(None);
+    };
+    let schema = reader.schema();
+    let Some(table) = schema.metadata().get(TABLE_KEY).cloned() else {
+        return Err(DataFusionError::Execution(format!(
+            "ingestion log segment {} names no table",
+            path.display()
+        )));
+    };
+    let keys = match schema.metadata().get(KEYS_KEY) {
+        Some(keys) => {
+            serde_json::from_str(keys).map_err(|e| DataFusionError::External(e.into()))?
+        }
+        None => vec![],
+    };
+    let batches: Vec<_> = reader.map_while(Result::ok).collect();
+    Ok((!batches.is_empty()).then_some(LoggedRows { table, keys, batches }))
+}
+
+/// `batch` with the columns of `schema`, see the [module docs](self).
+pub(crate) fn conform(batch: &RecordBatch, schema: &SchemaRef) -> DataFusionResult<RecordBatch> {
+    let mut columns = Vec::with_capacity(schema.fields().len());
+    for field in schema.fields() {
+        let column = match batch.column_by_name(field.name()) {
+            Some(column) => cast(column, field.data_type())?,
+            None if field.is_nullable() => new_null_array(field.data_type(), batch.num_rows()),
+            None => {
+                return Err(DataFusionError::Plan(format!(
+                    "ingested batch has no column {}",
+                    field.name()
+                )))
+            }
+        };
+        columns.push(column);
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{Int32Array, Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    fn events(ids: std::ops::Range<i32>) -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("kind", DataType::Utf8, false),
+            Field::new("id", DataType::Int32, false),
+        ]);
+        let kinds: Vec<_> = ids.clone().map(|id| format!("event-{id}")).collect();
+        RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(kinds)),
+                Arc::new(Int32Array::from(ids.collect::<Vec<_>>())),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_conform_matches_columns_by_name() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("kind", DataType::Utf8, true),
+            Field::new("note", DataType::Utf8, true),
+        ]));
+        let batch = conform(&events(0..2), &schema).unwrap();
+        assert_eq!(batch.schema(), schema);
+        assert_eq!(batch.column(0).as_ref(), &Int64Array::from(vec![0, 1]));
+        assert_eq!(batch.column(2).null_count(), 2);
+
+        let schema = Arc::new(Schema::new(vec![Field::new("user", DataType::Utf8, false)]));
+        assert!(conform(&events(0..2), &schema).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ingest_commits_every_max_rows() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-ingest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let engine = QueryEngine::new();
+        let sql = format!(
+            "CREATE EXTERNAL TABLE events (id BIGINT, kind VARCHAR) STORED AS PARQUET \
+             LOCATION '{}/'",
+            dir.display()
+        );
+        engine.query(&sql).await?;
+        let batches = (0..5).map(|i| Ok(events(i * 10..i * 10 + 10)));
+        let options = IngestOptions::default().with_max_rows(20);
+        let report = engine.ingest("events", futures::stream::iter(batches), options).await?;
+        assert_eq!(report, IngestReport { rows: 50, commits: 3, duplicates: 0 });
+        // One file of each commit.
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 3);
+        let result = engine.query("SELECT count(*), max(id) FROM events").await?;
+        let expected = "\
++----------+----------------+
+| count(*) | max(events.id) |
++----------+----------------+
+| 50       | 49             |
++----------+----------------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_commits_on_interval() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE events (id INT, kind VARCHAR) AS VALUES (-1, 'seed')").await?;
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let options = IngestOptions::default().with_interval(Duration::from_millis(50));
+        let ingest = {
+            let engine = engine.clone();
+            let stream = rx.map(Ok);
+            tokio::spawn(async move { engine.ingest("events", stream, options).await })
+        };
+        tx.unbounded_send(events(0..3)).unwrap();
+        // Committed while the stream is still open.
+        let mut rows = 0;
+        for _ in 0..100 {
+            let result = engine.query("SELECT count(*) AS n FROM events").await?;
+            let count = result.batches[0].column(0).as_any().downcast_ref::<Int64Array>();
+            rows = count.unwrap().value(0);
+            if rows == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(rows, 4);
+        tx.unbounded_send(events(3..5)).unwrap();
+        drop(tx);
+        let report = ingest.await.unwrap()?;
+        assert_eq!(report, IngestReport { rows: 5, commits: 2, duplicates: 0 });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_leaves_out_duplicate_keys() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE events (id INT, kind VARCHAR) AS VALUES (-1, 'seed')").await?;
+        let options = IngestOptions::default().with_max_rows(3).with_keys(["id"]);
+        // Duplicates of committed rows and of buffered ones.
+        let batches = [events(0..3), events(2..5), events(4..5), events(0..2)].map(Ok);
+        let report =
+            engine.ingest("events", futures::stream::iter(batches), options.clone()).await?;
+        assert_eq!(report, IngestReport { rows: 5, commits: 2, duplicates: 4 });
+
+        // Another ingestion into the table, deduplicated against what was committed.
+        let batches = futures::stream::iter([Ok(events(3..7))]);
+        let report = engine.ingest("events", batches, options.with_dedup_window(6)).await?;
+        assert_eq!(report, IngestReport { rows: 2, commits: 1, duplicates: 2 });
+        // With a window of 6 keys, the first of them are forgotten.
+        let batches = futures::stream::iter([Ok(events(0..2))]);
+        let options = IngestOptions::default().with_keys(["id"]);
+        let report = engine.ingest("events", batches, options).await?;
+        assert_eq!(report, IngestReport { rows: 1, commits: 1, duplicates: 1 });
+
+        let batches = futures::stream::iter([Ok(events(0..1))]);
+        let options = IngestOptions::default().with_keys(["user"]);
+        let err = engine.ingest("events", batches, options).await.unwrap_err();
+        assert!(err.to_string().contains("ingestion key user"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_wal_replays_rows_left_by_a_crash() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-ingest-wal-{}", std::process::id()));
+        let engine = QueryEngine::new().with_ingest_wal(IngestWal::open(&dir)?);
+        engine
+            .query("CREATE TABLE events (id BIGINT, kind VARCHAR) AS VALUES (-1, 'seed')")
+            .await?;
+        let batches = futures::stream::iter(vec![Ok(events(0..3))]);
+        engine.ingest("events", batches, IngestOptions::default()).await?;
+        // Committed, so nothing is left to replay.
+        assert!(IngestWal::open(&dir)?.segments()?.is_empty());
+
+        // A crash: rows logged but never committed, and a segment cut off before
+        // its first batch.
+        let wal = IngestWal::open(&dir)?;
+        let schema = engine.session_context().table("events").await?.schema().inner().clone();
+        let segment = wal.create("datafusion.public.events", &[], &schema).await?;
+        segment.append(&conform(&events(3..5), &schema)?).await?;
+        segment.append(&conform(&events(5..6), &schema)?).await?;
+        std::mem::forget(segment);
+        std::mem::forget(wal.create("datafusion.public.events", &[], &schema).await?);
+        assert_eq!(wal.segments()?.len(), 2);
+
+        let engine = engine.with_ingest_wal(IngestWal::open(&dir)?);
+        let report = engine.replay_ingest_wal().await?;
+        assert_eq!(report, IngestReport { rows: 3, commits: 1, duplicates: 0 });
+        assert!(wal.segments()?.is_empty());
+        let result = engine.query("SELECT count(*), max(id) FROM events").await?;
+        let expected = "\
++----------+----------------+
+| count(*) | max(events.id) |
++----------+----------------+
+| 7        | 5              |
++----------+----------------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ingest_into_unknown_table_fails() {
+        let engine = QueryEngine::new();
+        let batches = futures::stream::iter(vec![Ok(events(0..1))]);
+        let err = engine.ingest("missing", batches, IngestOptions::default()).await.unwrap_err();
+        assert!(err.to_string().contains("missing"), "{err}");
+    }
+}
@@ -0,0 +1,301 @@
+//! Choosing how hash joins move their inputs.
+//!
+//! A hash join either broadcasts its build side, collecting it once and probing it
+//! from every partition of the other side (`mode=CollectLeft` in `EXPLAIN`), or
+//! repartitions both sides by the join keys and joins the partitions pairwise
+//! (`mode=Partitioned`). Broadcasting a small side saves shuffling the large one, which
+//! matters most when the sides come from different sources; broadcasting a large side
+//! holds all of it in memory. DataFusion decides with thresholds sized for local
+//! tables and, when a source reports no statistics, always repartitions.
+//!
+//! [`JoinStrategyRule`], which every [`QueryEngine`](crate::QueryEngine) runs right
+//! after DataFusion's own join selection, decides again by these
+//! [`IglooOptions`], which [`QueryEngine::with_options`](crate::QueryEngine::with_options)
+//! sets for an engine and `SET` changes for a session:
+//!
+//! - `igloo.join_strategy = 'auto'` (the default) broadcasts the smaller side if its
+//!   estimated rows and bytes are within `igloo.broadcast_max_rows` and
+//!   `igloo.broadcast_max_bytes`, and repartitions both otherwise. Estimates come from
+//!   the sources and from `ANALYZE TABLE` (see [`statistics`](crate::statistics)), so
+//!   a small analyzed table is broadcast even when the other side's source knows
+//!   nothing of its size. Joins of two sides of unknown size are left as DataFusion
+//!   planned them.
+//! - `'broadcast'` broadcasts the side estimated smaller, or the build side DataFusion
+//!   chose when neither is known.
+//! - `'partitioned'` repartitions both sides.
+//!
+//! The exchanges the chosen mode needs are added by DataFusion's distribution
+//! enforcement, which runs afterwards. `EXPLAIN VERBOSE` shows the plan after this
+//! rule as `physical_plan after join_strategy`.
+
+use crate::options::IglooOptions;
+use datafusion::common::config::{ConfigField, Visit};
+use datafusion::common::stats::Precision;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::common::Statistics;
+use datafusion::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::joins::{HashJoinExec, PartitionMode};
+use datafusion::physical_plan::ExecutionPlan;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How hash joins move their inputs, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JoinStrategy {
+    #[default]
+    Auto,
+    Broadcast,
+    Partitioned,
+}
+
+impl FromStr for JoinStrategy {
+    type Err = DataFusionError;
+
+    fn from_str(s: &str) -> DataFusionResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(JoinStrategy::Auto),
+            "broadcast" => Ok(JoinStrategy::Broadcast),
+            "partitioned" => Ok(JoinStrategy::Partitioned),
+            _ => Err(DataFusionError::Configuration(format!(
+                "invalid join strategy '{s}', expected auto, broadcast or partitioned"
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for JoinStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            JoinStrategy::Auto => "auto",
+            JoinStrategy::Broadcast => "broadcast",
+            JoinStrategy::Partitioned => "partitioned",
+        })
+    }
+}
+
+impl ConfigField for JoinStrategy {
+    fn visit<V: Visit>(&self, v: &mut V, key: &str, description: &'static str) {
+        v.some(key, self, description)
+    }
+
+    fn set(&mut self, _key: &str, value: &str) -> DataFusionResult<()> {
+        *self = value.parse()?;
+        Ok(())
+    }
+}
+
+/// Sets the mode of each hash join, see the [module](self) documentation.
+#[derive(Debug, Default)]
+pub struct JoinStrategyRule;
+
+impl JoinStrategyRule {
+    pub const NAME: &'static str = "join_strategy";
+}
+
+/// Which side of a join to build on, if it is to be broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Build {
+    Left,
+    Right,
+}
+
+impl PhysicalOptimizerRule for JoinStrategyRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let options = config.extensions.get::<IglooOptions>().cloned().unwrap_or_default();
+        let plan = plan.transform_up(|node| {
+            let Some(join) = node.as_any().downcast_ref::<HashJoinExec>() else {
+                return Ok(Transformed::no(node));
+            };
+            let smaller = smaller_side(join)?;
+            let build = match options.join_strategy {
+                JoinStrategy::Partitioned => None,
+                // DataFusion builds on the left side, having swapped the inputs if it
+                // knew better.
+                JoinStrategy::Broadcast => Some(smaller.unwrap_or(Build::Left)),
+                JoinStrategy::Auto if smaller.is_none() => return Ok(Transformed::no(node)),
+                JoinStrategy::Auto => match smaller {
+                    Some(side) if fits(side.of(join), &options)? => Some(side),
+                    _ => None,
+                },
+            };
+            let (mode, swap) = match build {
+                Some(Build::Left) => (PartitionMode::CollectLeft, false),
+                Some(Build::Right) if join.join_type().supports_swap() => {
+                    (PartitionMode::CollectLeft, true)
+                }
+                // The join cannot build on its right side.
+                Some(Build::Right) => return Ok(Transformed::no(node)),
+                None => (PartitionMode::Partitioned, false),
+            };
+            if swap {
+                Ok(Transformed::yes(join.swap_inputs(mode)?))
+            } else if mode != *join.partition_mode() {
+                Ok(Transformed::yes(Arc::new(with_mode(join, mode)?)))
+            } else {
+                Ok(Transformed::no(node))
+            }
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+impl Build {
+    fn of(self, join: &HashJoinExec) -> &Arc<dyn ExecutionPlan> {
+        match self {
+            Build::Left => join.left(),
+            Build::Right => join.right(),
+        }
+    }
+}
+
+/// The side of `join` estimated smaller: by bytes if both sides' are known, or else
+/// by rows, or else the only side with an estimate. `None` if neither has one.
+fn smaller_side(join: &HashJoinExec) -> DataFusionResult<Option<Build>> {
+    let left = join.left().partition_statistics(None)?;
+    let right = join.right().partition_statistics(None)?;
+    let right_smaller = |left: &Precision<usize>, right: &Precision<usize>| {
+        Some(right.get_value()? < left.get_value()?)
+    };
+    let known = |stats: &Statistics| {
+        stats.total_byte_size.get_value().is_some() || stats.num_rows.get_value().is_some()
+    };
+    let compared = right_smaller(&left.total_byte_size, &right.total_byte_size)
+        .or_else(|| right_smaller(&left.num_rows, &right.num_rows));
+    Ok(match compared {
+        Some(true) => Some(Build::Right),
+        Some(false) => Some(Build::Left),
+        None if known(&left) => Some(Build::Left),
+        None if known(&right) => Some(Build::Right),
+        None => None,
+    })
+}
+
+/// Whether the estimated rows and bytes of `plan`, those that are known, are within
+/// the broadcast limits.
+fn fits(plan: &Arc<dyn ExecutionPlan>, options: &IglooOptions) -> DataFusionResult<bool> {
+    let stats = plan.partition_statistics(None)?;
+    let rows = stats.num_rows.get_value().map_or(true, |&rows| rows <= options.broadcast_max_rows);
+    let bytes = stats
+        .total_byte_size
+        .get_value()
+        .map_or(true, |&bytes| bytes <= options.broadcast_max_bytes);
+    Ok(rows && bytes)
+}
+
+fn with_mode(join: &HashJoinExec, mode: PartitionMode) -> DataFusionResult<HashJoinExec> {
+    HashJoinExec::try_new(
+        Arc::clone(join.left()),
+        Arc::clone(join.right()),
+        join.on().to_vec(),
+        join.filter().cloned(),
+        join.join_type(),
+        join.projection.clone(),
+        mode,
+        join.null_equals_null(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::SessionVars;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::datasource::MemTable;
+    use datafusion::error::Result as DataFusionResult;
+    use datafusion::physical_plan::displayable;
+    use std::sync::Arc;
+
+    const SQL: &str =
+        "SELECT f.amount, d.label FROM facts f JOIN dim d ON f.id = d.id ORDER BY amount";
+
+    fn engine() -> DataFusionResult<QueryEngine> {
+        let engine = QueryEngine::new();
+        let dim_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("label", DataType::Utf8, false),
+        ]));
+        let dim = RecordBatch::try_new(
+            Arc::clone(&dim_schema),
+            vec![
+                Arc::new(Int64Array::from(vec![3, 1, 5])),
+                Arc::new(StringArray::from(vec!["c", "a", "e"])),
+            ],
+        )?;
+        engine.register_table("dim", Arc::new(MemTable::try_new(dim_schema, vec![vec![dim]])?))?;
+        let fact_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("amount", DataType::Int64, false),
+        ]));
+        let facts = RecordBatch::try_new(
+            Arc::clone(&fact_schema),
+            vec![
+                Arc::new(Int64Array::from_iter_values(1..=1000)),
+                Arc::new(Int64Array::from_iter_values((1..=1000).map(|i| i * 10))),
+            ],
+        )?;
+        let facts = MemTable::try_new(fact_schema, vec![vec![facts]])?;
+        engine.register_table("facts", Arc::new(facts))?;
+        Ok(engine)
+    }
+
+    /// The join mode `settings` plan [`SQL`] with, after checking its result.
+    async fn mode(engine: &QueryEngine, settings: &[(&str, &str)]) -> DataFusionResult<String> {
+        let mut vars = SessionVars::new();
+        vars.set("datafusion.execution.target_partitions", "4")?;
+        // DataFusion's own choice would otherwise be to repartition.
+        vars.set("datafusion.optimizer.hash_join_single_partition_threshold", "0")?;
+        vars.set("datafusion.optimizer.hash_join_single_partition_threshold_rows", "0")?;
+        for (name, value) in settings {
+            vars.set(name, value)?;
+        }
+        let engine = engine.with_session(&vars);
+
+        let result = engine.query(SQL).await?;
+        let batch = &result.batches[0];
+        let amounts = batch.column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(amounts.values(), &[10, 30, 50]);
+
+        let plan = engine.sql(SQL).await?.create_physical_plan().await?;
+        let display = displayable(plan.as_ref()).indent(false).to_string();
+        let join = display.lines().find(|line| line.contains("HashJoinExec")).unwrap();
+        Ok(join.split("mode=").nth(1).unwrap().split(',').next().unwrap().to_string())
+    }
+
+    #[tokio::test]
+    async fn test_small_join_sides_are_broadcast() -> DataFusionResult<()> {
+        let engine = engine()?;
+        assert_eq!(mode(&engine, &[]).await?, "CollectLeft");
+        let limit = [("igloo.broadcast_max_rows", "2")];
+        assert_eq!(mode(&engine, &limit).await?, "Partitioned");
+        let limit = [("igloo.broadcast_max_bytes", "10")];
+        assert_eq!(mode(&engine, &limit).await?, "Partitioned");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_join_strategy_can_be_forced() -> DataFusionResult<()> {
+        let engine = engine()?;
+        let partitioned = [("igloo.join_strategy", "partitioned")];
+        assert_eq!(mode(&engine, &partitioned).await?, "Partitioned");
+        let broadcast = [("igloo.join_strategy", "broadcast"), ("igloo.broadcast_max_rows", "0")];
+        assert_eq!(mode(&engine, &broadcast).await?, "CollectLeft");
+        Ok(())
+    }
+}
@@ -10,22 +10,155 @@
 //! # TODO
 //! Implement query engine logic
 
+pub mod admission;
+pub mod avro;
+pub mod batch_size;
+pub mod bundle;
+pub mod catalog_store;
+pub mod checkpoint;
+pub mod diagnostics;
+pub mod external_catalog;
+pub mod formats;
+pub mod ingest;
+pub mod join_strategy;
+pub mod lineage;
+pub mod load;
+pub mod materialized_views;
+pub mod memory;
+pub mod merge;
+pub mod namespace;
+pub mod options;
+pub mod parquet_sink;
+pub mod plugin;
+pub mod policy;
+pub mod prefetch;
+pub mod profile;
+pub mod quality;
+pub mod resources;
+pub mod result_cache;
+pub mod running;
+pub mod scan_io;
+pub mod scheduler;
+pub mod schema_drift;
+pub mod session;
+pub mod sideways;
+pub mod spool;
+pub mod statistics;
+pub mod tenant;
+pub mod union_coercion;
+pub mod validate;
+#[cfg(feature = "wasm")]
+pub mod wasm_udf;
+
 // std
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::pin;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
 
 // datafusion -> arrow
-use datafusion::arrow::array::{Array, ArrayRef, StringArray, StringBuilder};
-use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::array::{Array, ArrayRef, StringArray, StringBuilder, UInt64Array};
+use datafusion::arrow::compute::concat_batches;
+use datafusion::arrow::datatypes::{DataType, Schema, SchemaRef};
 use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{
+    CatalogProvider, MemTable, MemoryCatalogProvider, MemorySchemaProvider, SchemaProvider,
+};
 
 // datafusion -> core
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use datafusion::datasource::memory::MemorySourceConfig;
+use datafusion::datasource::{provider_as_source, source_as_provider, TableProvider};
 use datafusion::error::{DataFusionError, Result as DataFusionResult};
-use datafusion::execution::context::SessionContext;
-use datafusion::logical_expr::{create_udf, ColumnarValue, ScalarUDF, Volatility};
+use datafusion::execution::context::{QueryPlanner, SessionConfig, SessionContext};
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::execution::session_state::{SessionState, SessionStateBuilder};
+use datafusion::logical_expr::dml::{CopyTo, DmlStatement, InsertOp, WriteOp};
+use datafusion::logical_expr::{create_udf, ColumnarValue, LogicalPlan, ScalarUDF, Volatility};
+use datafusion::logical_expr::{LogicalPlanBuilder, TableSource};
+use datafusion::optimizer::{Analyzer, AnalyzerRule};
+use datafusion::physical_optimizer::optimizer::PhysicalOptimizer;
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::sql::TableReference;
+
+use admission::Priority;
+use avro::AvroFormatFactory;
+use batch_size::{BatchSizeRule, BatchSizing};
+use bundle::{BundleEntry, ImportReport, StateBundle, BUNDLE_FORMAT};
+use catalog_store::{full_name, CatalogStore, CatalogSync, Change, EntryKind};
+use checkpoint::ResultFormat;
+use datafusion::physical_plan::{collect, execute_stream, ExecutionPlan};
+use diagnostics::{
+    inspect_plan, scan_columns, scanned_bytes, source_tables, Diagnostic, QueryResult, QueryStream,
+    ScanColumns,
+};
+use external_catalog::{ExternalCatalogs, SyncReport};
+use futures::{Stream, StreamExt};
+use igloo_common::catalog::CatalogSource;
+use igloo_common::secrets::Secrets;
+use igloo_connector_iceberg::IcebergTable;
+use ingest::{DedupIndexes, IngestOptions, IngestReport, IngestWal};
+use join_strategy::JoinStrategyRule;
+use lineage::{Lineage, LineageEdge, LineageTable, TargetKind};
+use load::{LoadOptions, LoadProgress, LoadReport};
+use materialized_views::{MaterializedViewRule, MaterializedViews};
+use memory::{pool_memory, CacheMemory, MemoryReport, MemoryTracker};
+use merge::MergeInto;
+use namespace::Placements;
+use options::IglooOptions;
+use parquet_sink::CreateTableAs;
+use plugin::{CachePolicy, ConnectorFactory, ConnectorTables, QueryRewriter, RewriterRule};
+use policy::{PolicyRule, PolicySet};
+use prefetch::PrefetchRule;
+use profile::{Profile, ProfileRule, Profiles};
+use resources::ResourceManager;
+use result_cache::{CanonicalPlan, ResultCache};
+use running::{QueryStart, RunningQueries, RunningQuery};
+use scan_io::{LimitedStores, PrefetchScansRule, ScanIo, SplitScansRule};
+use schema_drift::{diff_schemas, SchemaDiff};
+use session::{timeout_error, SessionVars};
+use sideways::{SidewaysRule, SidewaysScanRule};
+use spool::ResultSpool;
+use statistics::{AnalyzePolicy, AnalyzedTable, AnalyzedTables, StripStatisticsRule, TableWrite};
+use tenant::{min_timeout, tenant_state, Tenant};
+use union_coercion::{union_diagnostics, UnionCoercionRule};
+use validate::{scan_pushdown, Validation};
+
+/// Catalog and schema of Igloo's system tables, see
+/// [`QueryEngine::register_system_table`].
+pub const SYSTEM_CATALOG: &str = "igloo";
+pub const SYSTEM_SCHEMA: &str = "system";
 
 #[derive(Clone)]
 pub struct QueryEngine {
     ctx: SessionContext,
+    policies: Arc<RwLock<PolicySet>>,
+    policy_rule: Arc<PolicyRule>,
+    statement_timeout: Option<Duration>,
+    priority: Option<Priority>,
+    tenants: Arc<RwLock<BTreeMap<String, QueryEngine>>>,
+    resources: Option<Arc<ResourceManager>>,
+    catalog_sync: Option<Arc<CatalogSync>>,
+    analyzed: Arc<AnalyzedTables>,
+    analyze_policy: AnalyzePolicy,
+    external_catalogs: Arc<ExternalCatalogs>,
+    placements: Arc<Placements>,
+    materialized_views: Arc<MaterializedViews>,
+    ingest_wal: Option<Arc<IngestWal>>,
+    dedup: Arc<DedupIndexes>,
+    memory: Arc<MemoryTracker>,
+    running: Arc<RunningQueries>,
+    profiling: bool,
+    profiles: Arc<Profiles>,
+    profile: Option<Arc<Profile>>,
+    secrets: Arc<Secrets>,
+    result_cache: Option<Arc<ResultCache>>,
+    cache_policy: Option<Arc<dyn CachePolicy>>,
+    result_spool: Option<Arc<ResultSpool>>,
 }
 
 impl Default for QueryEngine {
@@ -36,24 +169,1505 @@ impl Default for QueryEngine {
 
 impl QueryEngine {
     pub fn new() -> Self {
-        let ctx = SessionContext::new();
+        // Join modes are chosen before DataFusion plans the exchanges they need.
+        let mut rules = PhysicalOptimizer::new().rules;
+        let at = rules.iter().position(|rule| rule.name() == "join_selection").map_or(0, |i| i + 1);
+        rules.insert(at, Arc::new(JoinStrategyRule));
+        // Unions are cast to common types before DataFusion coerces them its own way.
+        let mut analyzers = Analyzer::new().rules;
+        let at = analyzers.iter().position(|rule| rule.name() == "type_coercion").unwrap_or(0);
+        analyzers.insert(at, Arc::new(UnionCoercionRule));
+        let materialized_views = Arc::new(MaterializedViews::default());
+        let rule = MaterializedViewRule::new(Arc::clone(&materialized_views));
+        analyzers.insert(0, Arc::new(rule));
+        let mut builder = SessionStateBuilder::new()
+            .with_default_features()
+            .with_config(SessionConfig::new().with_option_extension(IglooOptions::default()))
+            .with_analyzer_rules(analyzers)
+            .with_optimizer_rule(Arc::new(SidewaysScanRule))
+            .with_physical_optimizer_rules(rules)
+            .with_physical_optimizer_rule(Arc::new(SidewaysRule::default()))
+            .with_physical_optimizer_rule(Arc::new(PrefetchRule))
+            .with_physical_optimizer_rule(Arc::new(StripStatisticsRule))
+            .with_physical_optimizer_rule(Arc::new(BatchSizeRule::default()));
+        // `STORED AS AVRO`, alongside the formats DataFusion reads.
+        builder.file_formats().get_or_insert_with(Vec::new).push(Arc::new(AvroFormatFactory));
+        let state = builder.build();
+        let policies = Arc::new(RwLock::new(PolicySet::new()));
+        let policy_rule =
+            Arc::new(PolicyRule::new(Arc::clone(&policies), state.config().options()));
+        let ctx = SessionContext::new_with_state(with_policy_rule(state, policy_rule.clone()));
         let capitalize_udf = make_capitalize_udf();
         ctx.register_udf(capitalize_udf);
-        QueryEngine { ctx }
+        QueryEngine {
+            ctx,
+            policies,
+            policy_rule,
+            statement_timeout: None,
+            priority: None,
+            tenants: Arc::default(),
+            resources: None,
+            catalog_sync: None,
+            analyzed: Arc::default(),
+            analyze_policy: AnalyzePolicy::default(),
+            external_catalogs: Arc::default(),
+            placements: Arc::default(),
+            materialized_views,
+            ingest_wal: None,
+            dedup: Arc::default(),
+            memory: Arc::default(),
+            running: Arc::default(),
+            profiling: false,
+            profiles: Arc::default(),
+            profile: None,
+            secrets: Arc::default(),
+            result_cache: None,
+            cache_policy: None,
+            result_spool: None,
+        }
+    }
+
+    /// Run `rule` after the built-in physical optimizer rules, for this engine and
+    /// tenants added to it afterwards (e.g. to distribute plans across workers).
+    pub fn with_physical_optimizer_rule(
+        self,
+        rule: Arc<dyn PhysicalOptimizerRule + Send + Sync>,
+    ) -> Self {
+        let state = SessionStateBuilder::new_from_existing(self.ctx.state())
+            .with_physical_optimizer_rule(rule)
+            .build();
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Rewrite the logical plans of statements with `rewriter`, after the rewriters
+    /// registered before, for this engine and tenants added to it afterwards; see
+    /// [`plugin`].
+    pub fn with_query_rewriter(self, rewriter: Arc<dyn QueryRewriter>) -> Self {
+        let state = SessionStateBuilder::new_from_existing(self.ctx.state())
+            .with_analyzer_rule(Arc::new(RewriterRule(rewriter)))
+            .build();
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Create the tables `STORED AS` `connector`'s name with it, in place of any
+    /// connector or format of the same name, for this engine and tenants added to it
+    /// afterwards; see [`plugin`]. Register it before [`Self::with_catalog_store`],
+    /// which replays tables.
+    pub fn with_connector(self, connector: Arc<dyn ConnectorFactory>) -> Self {
+        let mut state = self.ctx.state();
+        let name = connector.name().to_uppercase();
+        state.table_factories_mut().insert(name, Arc::new(ConnectorTables(connector)));
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Read the files of file scans in parallel and ahead as `io` says, or one after
+    /// the next per partition if `None` (the default), for this engine and tenants
+    /// added to it afterwards; see [`scan_io`]. The requests in flight are limited for
+    /// the object stores of this engine, which tenants do not share, and stay limited
+    /// once set.
+    pub fn with_scan_io(self, io: Option<ScanIo>) -> Self {
+        let state = self.ctx.state();
+        let mut rules: Vec<Arc<dyn PhysicalOptimizerRule + Send + Sync>> = state
+            .physical_optimizers()
+            .iter()
+            .filter(|rule| ![SplitScansRule::NAME, PrefetchScansRule::NAME].contains(&rule.name()))
+            .cloned()
+            .collect();
+        let Some(io) = io else {
+            let state = SessionStateBuilder::new_from_existing(state)
+                .with_physical_optimizer_rules(rules)
+                .build();
+            return QueryEngine { ctx: SessionContext::new_with_state(state), ..self };
+        };
+        // Files are spread over partitions before the exchanges are planned.
+        let at = rules.iter().position(|rule| rule.name() == JoinStrategyRule::NAME);
+        rules.insert(at.map_or(0, |i| i + 1), Arc::new(SplitScansRule::new(io)));
+        rules.push(Arc::new(PrefetchScansRule::new(io)));
+        let runtime = state.runtime_env();
+        let stores =
+            LimitedStores::new(Arc::clone(&runtime.object_store_registry), io.max_requests);
+        let runtime = Arc::new(RuntimeEnv {
+            memory_pool: Arc::clone(&runtime.memory_pool),
+            disk_manager: Arc::clone(&runtime.disk_manager),
+            cache_manager: Arc::clone(&runtime.cache_manager),
+            object_store_registry: Arc::new(stores),
+        });
+        let state = SessionStateBuilder::new_from_existing(state)
+            .with_physical_optimizer_rules(rules)
+            .with_runtime_env(runtime)
+            .build();
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Set the `igloo.*` options, unless a session sets otherwise, for this engine and
+    /// tenants added to it afterwards; see [`options`].
+    pub fn with_options(self, options: IglooOptions) -> Self {
+        let mut state = self.ctx.state();
+        state.config_mut().options_mut().extensions.insert(options);
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Size the batches of file scans by `sizing`, or by the session's batch size if
+    /// `None`, for this engine and tenants added to it afterwards; see [`batch_size`].
+    pub fn with_batch_sizing(self, sizing: Option<BatchSizing>) -> Self {
+        let state = self.ctx.state();
+        let mut rules: Vec<Arc<dyn PhysicalOptimizerRule + Send + Sync>> = state
+            .physical_optimizers()
+            .iter()
+            .filter(|rule| rule.name() != BatchSizeRule::NAME)
+            .cloned()
+            .collect();
+        if let Some(sizing) = sizing {
+            rules.push(Arc::new(BatchSizeRule::new(sizing)));
+        }
+        let state = SessionStateBuilder::new_from_existing(state)
+            .with_physical_optimizer_rules(rules)
+            .build();
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Log ingested rows to `wal` until they are committed (see [`ingest`]), for this
+    /// engine but not tenants added to it.
+    pub fn with_ingest_wal(self, wal: IngestWal) -> Self {
+        QueryEngine { ingest_wal: Some(Arc::new(wal)), ..self }
+    }
+
+    /// Resolve the secrets referenced by the options of tables with `secrets` (see
+    /// [`catalog_store`]), for this engine and tenants added to it afterwards. Set it
+    /// before [`Self::with_catalog_store`], which replays tables.
+    pub fn with_secrets(self, secrets: Secrets) -> Self {
+        QueryEngine { secrets: Arc::new(secrets), ..self }
+    }
+
+    /// Budget queries by `resources` (see [`resources`]), for this engine and tenants
+    /// added to it afterwards. Budgets apply to engines from [`Self::with_resources`].
+    pub fn with_resource_manager(self, resources: Arc<ResourceManager>) -> Self {
+        QueryEngine { resources: Some(resources), ..self }
+    }
+
+    /// An engine over the same tables and functions whose queries share one budget of
+    /// `principal`'s resource class. Without a resource manager that is `self`.
+    pub fn with_resources(&self, principal: Option<&str>) -> QueryEngine {
+        let Some(resources) = &self.resources else {
+            return self.clone();
+        };
+        let state = resources.query_state(self.ctx.state(), principal);
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self.clone() }
+    }
+
+    /// An engine whose queries' memory reservations are reported under `query_id` by
+    /// [`memory_report`](Self::memory_report), see [`memory`].
+    pub fn with_memory_tracking(&self, query_id: &str) -> QueryEngine {
+        let state = self.memory.track(self.ctx.state(), query_id);
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self.clone() }
+    }
+
+    /// An engine whose queries are listed among the running queries as `start` until
+    /// it and the plans it created are dropped, and stopped by [`Self::kill_query`]; see
+    /// [`running`]. Their memory reservations are tracked as by
+    /// [`Self::with_memory_tracking`], and with the session variable `profile` on they
+    /// are profiled under the query id, see [`profile`].
+    pub fn with_running_query(&self, start: QueryStart) -> QueryEngine {
+        let query_id = start.query_id.clone();
+        let state = self.memory.track(self.ctx.state(), &query_id);
+        let mut builder = SessionStateBuilder::new_from_existing(state)
+            .with_physical_optimizer_rule(Arc::new(self.running.start(start)));
+        let profile = self.profiling.then(|| self.profiles.start(&query_id));
+        if let Some(profile) = &profile {
+            let rule = ProfileRule { profile: Arc::clone(profile) };
+            builder = builder.with_physical_optimizer_rule(Arc::new(rule));
+        }
+        let ctx = SessionContext::new_with_state(builder.build());
+        QueryEngine { ctx, profile, ..self.clone() }
+    }
+
+    /// The profile of the query this engine runs, if it is profiled (see
+    /// [`Self::with_running_query`]).
+    pub fn profiling(&self) -> Option<&Arc<Profile>> {
+        self.profile.as_ref()
+    }
+
+    /// The profile of the query `query_id` of this engine or its tenants, if it is one
+    /// of the most recent profiled queries.
+    pub fn profile(&self, query_id: &str) -> Option<Arc<Profile>> {
+        self.profiles.get(query_id)
+    }
+
+    /// The running queries of this engine and its tenants, longest running first.
+    pub fn running_queries(&self) -> Vec<RunningQuery> {
+        let memory = self.memory.queries();
+        let mut queries = self.running.list(&memory);
+        for tenant in self.tenants.read().expect("tenant lock poisoned").values() {
+            queries.extend(tenant.running.list(&memory));
+        }
+        queries.sort_by_key(|query| std::cmp::Reverse(query.elapsed_ms));
+        queries
+    }
+
+    /// Kill the running query `query_id` of this engine or one of its tenants. False if
+    /// there is no such query.
+    pub fn kill_query(&self, query_id: &str) -> bool {
+        self.running.kill(query_id)
+            || self
+                .tenants
+                .read()
+                .expect("tenant lock poisoned")
+                .values()
+                .any(|tenant| tenant.running.kill(query_id))
+    }
+
+    /// Stop admitting statements to this engine and its tenants, e.g. before shutting
+    /// down: [`Self::sql`] fails from now on, while running queries go on; see
+    /// [`running`].
+    pub fn drain(&self) {
+        self.running.drain();
+        for tenant in self.tenants.read().expect("tenant lock poisoned").values() {
+            tenant.running.drain();
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.running.is_draining()
+    }
+
+    /// Wait until none of the queries of this engine and its tenants is running, or
+    /// `timeout` has passed. False if some still are.
+    pub async fn wait_idle(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.running_queries().is_empty() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+
+    /// Report cache `name`'s size in [`memory_report`](Self::memory_report), as
+    /// returned by `bytes`. Replaces any cache registered as `name` before.
+    pub fn register_cache(
+        &self,
+        name: impl Into<String>,
+        bytes: impl Fn() -> usize + Send + Sync + 'static,
+    ) {
+        self.memory.register_cache(name.into(), Arc::new(bytes));
+    }
+
+    /// Serve the results of [`Self::query`] from `cache` when a query of the same plan
+    /// ran before, for this engine but not tenants added to it; see [`result_cache`].
+    /// Its size is reported as the cache `results`.
+    pub fn with_result_cache(self, cache: Arc<ResultCache>) -> Self {
+        let size = Arc::clone(&cache);
+        self.memory.register_cache("results".to_string(), Arc::new(move || size.size_bytes()));
+        QueryEngine { result_cache: Some(cache), ..self }
+    }
+
+    /// Cache only the results `policy` deems cacheable, for as long as it says, in
+    /// the cache of [`Self::with_result_cache`]; see [`plugin`].
+    pub fn with_cache_policy(self, policy: Arc<dyn CachePolicy>) -> Self {
+        QueryEngine { cache_policy: Some(policy), ..self }
+    }
+
+    /// Spill the results of [`Self::query`] outgrowing `spool`'s threshold to disk, for
+    /// this engine and tenants added to it afterwards; see [`spool`].
+    pub fn with_result_spool(self, spool: Arc<ResultSpool>) -> Self {
+        QueryEngine { result_spool: Some(spool), ..self }
+    }
+
+    /// Where the process's memory is, see [`memory`].
+    pub fn memory_report(&self) -> MemoryReport {
+        let mut pools = vec![pool_memory("engine", self.ctx.runtime_env().memory_pool.as_ref())];
+        let mut dedup_bytes = self.dedup.size_bytes();
+        for (name, tenant) in self.tenants.read().expect("tenant lock poisoned").iter() {
+            pools.push(pool_memory(name, tenant.ctx.runtime_env().memory_pool.as_ref()));
+            dedup_bytes += tenant.dedup.size_bytes();
+        }
+        let mut caches = vec![CacheMemory { name: "ingest_dedup".to_string(), bytes: dedup_bytes }];
+        caches.extend(self.memory.caches());
+        MemoryReport {
+            process: igloo_common::memory::ProcessMemory::current(),
+            pools,
+            queries: self.memory.queries(),
+            caches,
+        }
+    }
+
+    /// Persist tables, views and schemas created at runtime, the statistics of analyzed
+    /// tables and where tables were moved, in `store` (see [`catalog_store`]), first restoring those already
+    /// recorded there, and record lineage there (see [`lineage`]).
+    pub async fn with_catalog_store(self, store: Arc<dyn CatalogStore>) -> DataFusionResult<Self> {
+        let schema = MemorySchemaProvider::new();
+        schema.register_table("lineage".to_string(), Arc::new(LineageTable::new(store.clone())))?;
+        let catalog = self.ctx.state().config().options().catalog.default_catalog.clone();
+        if let Some(catalog) = self.ctx.catalog(&catalog) {
+            catalog.register_schema("information_schema", Arc::new(schema))?;
+        }
+        let sync = CatalogSync::new(store, Arc::clone(&self.secrets));
+        sync.refresh(&self.ctx, &self.analyzed, &self.placements).await?;
+        Ok(QueryEngine { catalog_sync: Some(Arc::new(sync)), ..self })
+    }
+
+    /// Apply the changes other engines recorded in the catalog store since the last
+    /// refresh, returning how many there were (none without a store).
+    pub async fn refresh_catalog(&self) -> DataFusionResult<usize> {
+        match &self.catalog_sync {
+            Some(sync) => sync.refresh(&self.ctx, &self.analyzed, &self.placements).await,
+            None => Ok(0),
+        }
+    }
+
+    /// Every recorded lineage edge (none without a catalog store).
+    pub async fn lineage(&self) -> DataFusionResult<Vec<LineageEdge>> {
+        match &self.catalog_sync {
+            Some(sync) => sync.lineage().await,
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The lineage edges `target` derives from, directly or not (see
+    /// [`lineage::upstream`]). Table names are resolved like in queries.
+    pub async fn trace_lineage(
+        &self,
+        kind: TargetKind,
+        target: &str,
+        column: Option<&str>,
+    ) -> DataFusionResult<Vec<LineageEdge>> {
+        let target = match kind {
+            TargetKind::Table => {
+                full_name(target.into(), &self.ctx.state().config().options().catalog)
+            }
+            TargetKind::Query => target.trim().to_string(),
+        };
+        Ok(lineage::upstream(&self.lineage().await?, kind, &target, column))
+    }
+
+    /// Register the catalog `source` reads as `name`, replacing any catalog of that
+    /// name. Its listing is kept until [`Self::sync_catalog`] (see
+    /// [`external_catalog`]).
+    pub async fn register_catalog_source(
+        &self,
+        name: &str,
+        source: Arc<dyn CatalogSource>,
+    ) -> DataFusionResult<()> {
+        external_catalog::register(&self.ctx, &self.external_catalogs, name, source).await
+    }
+
+    /// Like [`Self::register_catalog_source`], without reading `source` until a query
+    /// names one of its tables or [`Self::warm_up_catalog`] is called. Until then,
+    /// queries that do not need it run whether it can be reached or not.
+    pub async fn register_lazy_catalog_source(
+        &self,
+        name: &str,
+        source: Arc<dyn CatalogSource>,
+    ) -> DataFusionResult<()> {
+        external_catalog::register_lazy(&self.ctx, &self.external_catalogs, name, source).await;
+        Ok(())
+    }
+
+    /// Read the source of the external catalog `name` now if it was registered lazily
+    /// and has not been read yet.
+    pub async fn warm_up_catalog(&self, name: &str) -> DataFusionResult<()> {
+        external_catalog::warm_up(&self.external_catalogs, name).await
+    }
+
+    /// Re-read the metadata of the external catalog `name` and register it, reporting
+    /// the schemas and tables added, dropped or altered since the last sync.
+    pub async fn sync_catalog(&self, name: &str) -> DataFusionResult<SyncReport> {
+        external_catalog::sync(&self.ctx, &self.external_catalogs, name, true).await
+    }
+
+    /// Like [`Self::sync_catalog`], without registering anything.
+    pub async fn diff_catalog(&self, name: &str) -> DataFusionResult<SyncReport> {
+        external_catalog::sync(&self.ctx, &self.external_catalogs, name, false).await
+    }
+
+    /// How the registered table `replica`, a copy of the table `source` elsewhere,
+    /// differs from `schema`, the live schema of the source; see [`schema_drift`].
+    pub async fn diff_schema(
+        &self,
+        source: &str,
+        schema: &Schema,
+        replica: TableReference,
+    ) -> DataFusionResult<SchemaDiff> {
+        let copy = self.ctx.table_provider(replica.clone()).await?.schema();
+        Ok(SchemaDiff {
+            source: source.to_string(),
+            replica: replica.to_string(),
+            drift: diff_schemas(schema, &copy),
+        })
+    }
+
+    /// The tables, views and policies set up at runtime, and the external catalogs
+    /// read, for setting up another engine like this one; see [`bundle`].
+    pub async fn export_state(&self) -> DataFusionResult<StateBundle> {
+        let entries = match &self.catalog_sync {
+            Some(sync) => sync.entries().await?,
+            None => vec![],
+        };
+        Ok(StateBundle {
+            format: BUNDLE_FORMAT,
+            entries: entries.iter().map(BundleEntry::from_change).collect(),
+            policies: self.policies(),
+            warm_up: external_catalog::loaded(&self.external_catalogs).await,
+        })
+    }
+
+    /// Set up what `bundle`, exported from another engine, holds; see [`bundle`].
+    /// Entries and catalogs that fail are reported and skipped.
+    pub async fn import_state(&self, bundle: &StateBundle) -> DataFusionResult<ImportReport> {
+        if bundle.format != BUNDLE_FORMAT {
+            return Err(DataFusionError::Plan(format!(
+                "unsupported bundle format {}, expected {BUNDLE_FORMAT}",
+                bundle.format
+            )));
+        }
+        let mut report = ImportReport::default();
+        for entry in &bundle.entries {
+            let imported = async {
+                let change = entry.to_change()?;
+                let (ctx, secrets) = (&self.ctx, &self.secrets);
+                catalog_store::apply(ctx, &self.analyzed, &self.placements, secrets, &change)
+                    .await?;
+                match &self.catalog_sync {
+                    Some(sync) => sync.record(&Change::copy(&change)).await,
+                    None => Ok(()),
+                }
+            };
+            match imported.await {
+                Ok(()) => report.entries += 1,
+                Err(e) => report.errors.push(format!("{} {}: {e}", entry.kind, entry.name)),
+            }
+        }
+        self.set_policies(bundle.policies.clone());
+        for name in &bundle.warm_up {
+            match self.warm_up_catalog(name).await {
+                Ok(()) => report.warmed_up.push(name.clone()),
+                Err(e) => report.errors.push(format!("catalog {name}: {e}")),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Move the table or view `table` to `name`, in another schema of its catalog or
+    /// not, recording the move in the catalog store if there is one. This is what
+    /// `ALTER TABLE table RENAME TO name` runs; see [`namespace`].
+    pub async fn rename_table(
+        &self,
+        table: TableReference,
+        name: TableReference,
+    ) -> DataFusionResult<()> {
+        let options = self.ctx.state().config().options().catalog.clone();
+        let (from, to) = (full_name(table.clone(), &options), full_name(name.clone(), &options));
+        let catalog = |name: TableReference| {
+            name.resolve(&options.default_catalog, &options.default_schema).catalog
+        };
+        if catalog(table.clone()) != catalog(name.clone()) {
+            return Err(DataFusionError::Plan(format!("cannot move {table} to another catalog")));
+        }
+        if !self.ctx.table_exist(table.clone())? {
+            return Err(DataFusionError::Plan(format!("table {table} not found")));
+        }
+        if self.ctx.table_exist(name.clone())? {
+            return Err(DataFusionError::Plan(format!("table {name} already exists")));
+        }
+        let registered = self.placements.registered_name(&from);
+        self.placements.place(&self.ctx, &registered, &to)?;
+        // Statistics are kept by name, so those of the old one no longer apply.
+        self.track_write(TableWrite::Replaced(from)).await;
+        if let Some(sync) = &self.catalog_sync {
+            sync.record(&Change::placement(&registered, &to)).await?;
+        }
+        Ok(())
+    }
+
+    /// Forget where the `dropped` tables were placed and record their drop in the
+    /// catalog store if there is one.
+    async fn forget_dropped(&self, dropped: Vec<(EntryKind, String)>) -> DataFusionResult<()> {
+        for (kind, name) in dropped {
+            let registered = self.placements.forget(&name);
+            let Some(sync) = &self.catalog_sync else {
+                continue;
+            };
+            let entry = registered.as_deref().unwrap_or(&name);
+            sync.record(&Change::drop(kind, entry)).await?;
+            if let Some(registered) = &registered {
+                sync.record(&Change::placement(registered, registered)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-analyze tables whose statistics `policy` considers stale when they are
+    /// queried (see [`statistics`]), for this engine and tenants added to it afterwards.
+    pub fn with_analyze_policy(self, policy: AnalyzePolicy) -> Self {
+        QueryEngine { analyze_policy: policy, ..self }
+    }
+
+    /// Compute the statistics of `table`'s `columns` (all if empty), serve them to
+    /// the planner and record them in the catalog store if there is one. This is
+    /// what `ANALYZE TABLE` runs.
+    pub async fn analyze(
+        &self,
+        table: TableReference,
+        columns: &[String],
+    ) -> DataFusionResult<Arc<statistics::TableStatistics>> {
+        let name = full_name(table.clone(), &self.ctx.state().config().options().catalog);
+        let provider = self.ctx.table_provider(table.clone()).await?;
+        if provider.get_logical_plan().is_some() {
+            return Err(DataFusionError::Plan(format!("cannot analyze view {table}")));
+        }
+        let computed = statistics::compute(self.ctx.table(table).await?, columns).await?;
+        let computed = Arc::new(computed);
+        self.analyzed.insert(&name, Arc::clone(&computed));
+        statistics::install(&self.ctx, &name, Arc::clone(&computed)).await?;
+        if let Some(sync) = &self.catalog_sync {
+            sync.record(&Change::statistics(&name, Some(&computed))?).await?;
+        }
+        Ok(computed)
+    }
+
+    /// The statistics of `table` as of its last `ANALYZE`, if it has been analyzed.
+    pub fn table_statistics(&self, table: &str) -> Option<statistics::TableStatistics> {
+        let name = full_name(table.into(), &self.ctx.state().config().options().catalog);
+        self.analyzed.get(&name).map(|statistics| statistics.as_ref().clone())
+    }
+
+    /// Follow `write` in the statistics of the table it writes. Failing to record the
+    /// change is reported rather than failing the statement.
+    async fn track_write(&self, write: TableWrite) {
+        if let (Some(cache), TableWrite::Modified(name) | TableWrite::Replaced(name)) =
+            (&self.result_cache, &write)
+        {
+            cache.invalidate(name);
+        }
+        let (name, statistics) = match write {
+            TableWrite::Modified(name) => match self.analyzed.modified(&name) {
+                Some(statistics) => (name, Some(statistics)),
+                None => return,
+            },
+            TableWrite::Replaced(name) => match self.analyzed.remove(&name) {
+                Some(_) => (name, None),
+                None => return,
+            },
+        };
+        if let Some(sync) = &self.catalog_sync {
+            let change = Change::statistics(&name, statistics.as_deref());
+            if let Err(e) = async { sync.record(&change?).await }.await {
+                tracing::warn!(table = name, error = %e, "failed to record statistics");
+            }
+        }
+    }
+
+    /// Re-analyze, in the background, those of `tables` whose statistics are stale.
+    fn reanalyze_stale(&self, tables: &[String]) {
+        for name in self.analyzed.start_stale(tables, &self.analyze_policy) {
+            let engine = self.clone();
+            tokio::spawn(async move {
+                let columns = match engine.analyzed.get(&name) {
+                    Some(statistics) => statistics.columns.iter().map(|c| c.name.clone()).collect(),
+                    None => vec![],
+                };
+                let table = TableReference::parse_str(&name);
+                if let Err(e) = engine.analyze(table, &columns).await {
+                    tracing::warn!(table = name, error = %e, "failed to re-analyze");
+                }
+                engine.analyzed.finished(&name);
+            });
+        }
+    }
+
+    /// Turn logical plans into physical ones with `planner` (e.g. to run them on
+    /// another execution backend), for this engine and tenants added to it afterwards.
+    pub fn with_query_planner(self, planner: Arc<dyn QueryPlanner + Send + Sync>) -> Self {
+        let state = SessionStateBuilder::new_from_existing(self.ctx.state())
+            .with_query_planner(planner)
+            .build();
+        QueryEngine { ctx: SessionContext::new_with_state(state), ..self }
+    }
+
+    /// Replace the column masking and row-level security policies (see [`policy`]).
+    /// They take effect for every query planned afterwards. Their table names are
+    /// qualified with the engine's default catalog and schema.
+    pub fn set_policies(&self, policies: PolicySet) {
+        self.policy_rule.install(policies);
+    }
+
+    pub fn policies(&self) -> PolicySet {
+        self.policies.read().expect("policy lock poisoned").clone()
+    }
+
+    /// An engine over the same tables and functions whose queries are subject to the
+    /// policies for a caller holding `roles`. Queries through `self` are evaluated
+    /// with no roles.
+    pub fn for_roles(&self, roles: &[String]) -> QueryEngine {
+        if roles.is_empty() {
+            return self.clone();
+        }
+        let rule = Arc::new(self.policy_rule.with_roles(roles.to_vec()));
+        QueryEngine {
+            ctx: SessionContext::new_with_state(with_policy_rule(self.ctx.state(), rule)),
+            ..self.clone()
+        }
+    }
+
+    /// An engine over the same tables and functions that plans and runs queries with
+    /// a connection's session variables (see [`session`]) applied.
+    pub fn with_session(&self, session: &SessionVars) -> QueryEngine {
+        if *session == SessionVars::default() {
+            return self.clone();
+        }
+        let mut state = self.ctx.state();
+        let options = state.config_mut().options_mut();
+        for (name, value) in &session.options {
+            // Already validated by `SessionVars::set`.
+            let _ = options.set(name, value);
+        }
+        if let Some(time_zone) = &session.time_zone {
+            options.execution.time_zone = Some(time_zone.clone());
+        }
+        if let Some(catalog) = &session.catalog {
+            options.catalog.default_catalog = catalog.clone();
+        }
+        if let Some(schema) = &session.schema {
+            options.catalog.default_schema = schema.clone();
+        }
+        QueryEngine {
+            ctx: SessionContext::new_with_state(state),
+            statement_timeout: min_timeout(session.statement_timeout, self.statement_timeout),
+            priority: session.priority.or(self.priority),
+            profiling: session.profile,
+            ..self.clone()
+        }
+    }
+
+    /// An engine over the same tables and functions whose queries run against their
+    /// sources, reading neither materialized views (see [`materialized_views`]) nor
+    /// the result cache.
+    pub fn fresh(&self) -> QueryEngine {
+        QueryEngine {
+            ctx: SessionContext::new_with_state(without_materialized_views(self.ctx.state())),
+            result_cache: None,
+            ..self.clone()
+        }
+    }
+
+    /// Add `tenant` (see [`tenant`]), replacing any tenant of the same name, and return
+    /// its engine for registering its tables.
+    pub fn add_tenant(&self, tenant: Tenant) -> DataFusionResult<QueryEngine> {
+        let materialized_views = Arc::new(MaterializedViews::default());
+        let state = tenant_state(&self.ctx.state(), &tenant)?;
+        let state = with_materialized_views(state, Arc::clone(&materialized_views));
+        let engine = QueryEngine {
+            ctx: SessionContext::new_with_state(state),
+            policies: Arc::clone(&self.policies),
+            policy_rule: Arc::clone(&self.policy_rule),
+            statement_timeout: tenant.statement_timeout,
+            priority: None,
+            tenants: Arc::default(),
+            resources: self.resources.clone(),
+            catalog_sync: None,
+            analyzed: Arc::default(),
+            analyze_policy: self.analyze_policy,
+            external_catalogs: Arc::default(),
+            placements: Arc::default(),
+            materialized_views,
+            ingest_wal: None,
+            dedup: Arc::default(),
+            memory: Arc::clone(&self.memory),
+            running: Arc::default(),
+            profiling: false,
+            profiles: Arc::clone(&self.profiles),
+            secrets: Arc::clone(&self.secrets),
+            profile: None,
+            result_cache: None,
+            cache_policy: None,
+            result_spool: self.result_spool.clone(),
+        };
+        if self.is_draining() {
+            engine.running.drain();
+        }
+        let mut tenants = self.tenants.write().expect("tenant lock poisoned");
+        tenants.insert(tenant.name, engine.clone());
+        Ok(engine)
+    }
+
+    /// The engine of the tenant named `name`, if it has been added.
+    pub fn tenant(&self, name: &str) -> Option<QueryEngine> {
+        self.tenants.read().expect("tenant lock poisoned").get(name).cloned()
+    }
+
+    /// Names of every tenant, in order.
+    pub fn tenant_names(&self) -> Vec<String> {
+        self.tenants.read().expect("tenant lock poisoned").keys().cloned().collect()
+    }
+
+    /// The priority class the session asked its queries to be admitted in, if any.
+    pub fn priority(&self) -> Option<Priority> {
+        self.priority
+    }
+
+    /// How long a statement may run before it is cancelled, if limited.
+    pub fn statement_timeout(&self) -> Option<Duration> {
+        self.statement_timeout
+    }
+
+    /// The DataFusion session backing this engine, for callers that need direct access
+    /// to its catalog (e.g. metadata endpoints).
+    pub fn session_context(&self) -> &SessionContext {
+        &self.ctx
+    }
+
+    /// Plan `sql` without executing it. `ANALYZE TABLE` runs right away, see
+    /// [`statistics`], and so do `ALTER TABLE ... RENAME TO`, see [`namespace`],
+    /// `CREATE TABLE ... WITH (location = ...) AS`, see [`parquet_sink`], and `MERGE
+    /// INTO`, see [`merge`], as well as `SHOW QUERIES` and `KILL`, see [`running`], and
+    /// `INSERT`, `UPDATE` and `DELETE`, see [`result_cache`].
+    /// Fails once the engine drains, see [`Self::drain`].
+    pub async fn sql(&self, sql: &str) -> DataFusionResult<DataFrame> {
+        self.running.check_admitted()?;
+        if let Some(profile) = &self.profile {
+            profile.planning();
+        }
+        if let Some((table, columns)) = statistics::parse_analyze_sql(sql)? {
+            let computed = self.analyze(table.clone(), &columns).await?;
+            return self.ctx.read_batch(computed.to_batch(table.table())?);
+        }
+        if let Some((table, name)) = namespace::parse_rename_sql(sql)? {
+            self.rename_table(table, name).await?;
+            return self.ctx.read_empty();
+        }
+        if let Some(create) = parquet_sink::parse_create_table_as_sql(sql)? {
+            self.create_table_as(create).await?;
+            return self.ctx.read_empty();
+        }
+        if let Some(merge) = merge::parse_merge_sql(sql)? {
+            let rows = self.merge(merge).await?;
+            return self.row_count(rows);
+        }
+        if running::is_show_queries_sql(sql) {
+            return self.ctx.read_batch(running::to_batch(&self.running_queries())?);
+        }
+        if let Some(query_id) = running::parse_kill_sql(sql)? {
+            if !self.kill_query(&query_id) {
+                return Err(DataFusionError::Plan(format!("no running query {query_id}")));
+            }
+            return self.ctx.read_empty();
+        }
+        let plan = self.ctx.state().create_logical_plan(sql).await?;
+        self.execute_logical_plan(plan).await
+    }
+
+    /// Like [`SessionContext::execute_logical_plan`], running DDL right away and
+    /// recording it, and the lineage of statements writing tables, in the catalog
+    /// store if there is one. Writes to analyzed tables are tracked in their
+    /// statistics, and drops in the placements of tables (see [`namespace`]). `COPY`
+    /// to Parquet writes through a [`parquet_sink::ParquetSink`]. `DELETE` from an
+    /// Iceberg table runs right away, as a snapshot rewriting the files holding the
+    /// deleted rows (see [`IcebergTable::delete`]).
+    pub async fn execute_logical_plan(&self, plan: LogicalPlan) -> DataFusionResult<DataFrame> {
+        let plan = parquet_sink::with_parquet_sink(plan);
+        let write = TableWrite::of(&plan, &self.ctx)?;
+        if let Some(deleted) = self.delete_from_iceberg(&plan).await? {
+            if let Some(write) = write {
+                self.track_write(write).await;
+            }
+            return self.row_count(deleted);
+        }
+        let dropped = namespace::dropped_tables(&plan, &self.ctx).await?;
+        let Some(sync) = &self.catalog_sync else {
+            let plan = catalog_store::resolve_secrets(plan, &self.secrets).await?;
+            let df = self.execute_plan(plan).await?;
+            let df = self.finish_write(df, write).await?;
+            self.forget_dropped(dropped).await?;
+            return Ok(df);
+        };
+        let change = Change::of(&plan, &self.ctx, &self.placements, &self.secrets)?;
+        let lineage = Lineage::of_statement(&plan, &self.ctx.state().config().options().catalog);
+        let plan = catalog_store::resolve_secrets(plan, &self.secrets).await?;
+        let df = self.execute_plan(plan).await?;
+        let df = self.finish_write(df, write).await?;
+        // Before the change, so a schema's tables are dropped ahead of the schema.
+        self.forget_dropped(dropped).await?;
+        if let Some(change) = change {
+            sync.record(&change).await?;
+        }
+        if let Some(lineage) = lineage {
+            sync.record_lineage(&lineage).await;
+        }
+        Ok(df)
+    }
+
+    /// `df`, having run it if it is an `INSERT`, `UPDATE` or `DELETE` DataFusion would
+    /// run lazily, and track `write` once it is committed: results cached before are
+    /// then no longer served (see [`result_cache`]).
+    async fn finish_write(
+        &self,
+        df: DataFrame,
+        write: Option<TableWrite>,
+    ) -> DataFusionResult<DataFrame> {
+        let Some(write) = write else {
+            return Ok(df);
+        };
+        let df = match df.logical_plan() {
+            LogicalPlan::Dml(_) => {
+                let schema = df.schema().inner().clone();
+                let batches = df.collect().await?;
+                self.ctx.read_table(Arc::new(MemTable::try_new(schema, vec![batches])?))?
+            }
+            _ => df,
+        };
+        self.track_write(write).await;
+        Ok(df)
+    }
+
+    /// Run `plan` on the engine's context. A `CREATE TABLE ... AS` replacing the table
+    /// of a materialized view runs against the view's sources, as the context analyzes
+    /// its query alone, and the view then reads the new table.
+    async fn execute_plan(&self, plan: LogicalPlan) -> DataFusionResult<DataFrame> {
+        let options = self.ctx.state().config().options().catalog.clone();
+        let view = match &plan {
+            LogicalPlan::Ddl(_) => self.materialized_views.written(&plan, &options),
+            _ => None,
+        };
+        let Some(view) = view else {
+            return self.ctx.execute_logical_plan(plan).await;
+        };
+        let ctx = SessionContext::new_with_state(without_materialized_views(self.ctx.state()));
+        let df = ctx.execute_logical_plan(plan).await?;
+        let table = self.ctx.table_provider(view.clone()).await?;
+        self.materialized_views.refresh(&view, table);
+        Ok(df)
+    }
+
+    /// Write the result of `plan` as Parquet files under `url` (or to the one file a
+    /// URL with an extension names), returning the number of rows written. `options`
+    /// are those of `COPY ... OPTIONS`, see [`parquet_sink`].
+    pub async fn write_parquet(
+        &self,
+        plan: LogicalPlan,
+        url: &str,
+        options: HashMap<String, String>,
+    ) -> DataFusionResult<u64> {
+        let copy = LogicalPlan::Copy(CopyTo {
+            input: Arc::new(plan),
+            output_url: url.to_string(),
+            partition_by: vec![],
+            file_type: parquet_sink::parquet_file_type(),
+            options: parquet_sink::format_options(options),
+        });
+        written_rows(&self.ctx.execute_logical_plan(copy).await?.collect().await?)
+    }
+
+    /// Write the result of `query` to the one file at `path` as `format`, returning the
+    /// number of rows written; see [`checkpoint`].
+    pub async fn save_result(
+        &self,
+        query: &str,
+        path: &str,
+        format: ResultFormat,
+    ) -> DataFusionResult<u64> {
+        format.check_path(path)?;
+        let plan = self.sql(query).await?.into_unoptimized_plan();
+        let copy = LogicalPlan::Copy(CopyTo {
+            input: Arc::new(plan),
+            output_url: path.to_string(),
+            partition_by: vec![],
+            file_type: format.file_type(),
+            options: HashMap::new(),
+        });
+        written_rows(&self.ctx.execute_logical_plan(copy).await?.collect().await?)
+    }
+
+    /// The result [`Self::save_result`] wrote to `path`, as a table to register.
+    pub async fn load_result(&self, path: &str) -> DataFusionResult<Arc<dyn TableProvider>> {
+        let format = checkpoint::path_format(path)?;
+        let url = ListingTableUrl::parse(path)?;
+        let options =
+            ListingOptions::new(format.file_format()).with_file_extension(format.extension());
+        let schema = options.infer_schema(&self.ctx.state(), &url).await?;
+        let config = ListingTableConfig::new(url).with_listing_options(options).with_schema(schema);
+        Ok(Arc::new(ListingTable::try_new(config)?))
     }
 
+    /// Write the result of `create`'s query under its location and register an
+    /// external table over the files.
+    async fn create_table_as(&self, create: CreateTableAs) -> DataFusionResult<()> {
+        if self.ctx.table_exist(create.name.clone())? {
+            if create.if_not_exists {
+                return Ok(());
+            }
+            return Err(DataFusionError::Plan(format!("table '{}' already exists", create.name)));
+        }
+        let plan = self.ctx.state().create_logical_plan(&create.query).await?;
+        self.write_parquet(plan, &create.location, create.options).await?;
+        let sql = format!(
+            "CREATE EXTERNAL TABLE {} STORED AS PARQUET LOCATION '{}'",
+            create.name.to_quoted_string(),
+            create.location.replace('\'', "''")
+        );
+        let plan = self.ctx.state().create_logical_plan(&sql).await?;
+        self.execute_logical_plan(plan).await?;
+        Ok(())
+    }
+
+    /// Apply `merge` to its target, an Iceberg table, as one snapshot, returning the
+    /// number of rows inserted, updated and deleted. This is what `MERGE INTO` runs;
+    /// see [`merge`].
+    pub async fn merge(&self, merge: MergeInto) -> DataFusionResult<u64> {
+        let provider = self.ctx.table_provider(merge.target.clone()).await?;
+        let provider = AnalyzedTable::unwrap(provider);
+        let Some(table) = provider.as_any().downcast_ref::<IcebergTable>() else {
+            return Err(DataFusionError::NotImplemented(format!(
+                "MERGE into {} is not supported, only into Iceberg tables",
+                merge.target
+            )));
+        };
+        let changes = merge::changes(&self.ctx, &merge, &table.schema()).await?;
+        if changes.num_rows() > 0 {
+            table.apply_changes(&self.ctx.state(), &merge.keys, &changes).await?;
+            let options = self.ctx.state().config().options().catalog.clone();
+            self.track_write(TableWrite::Modified(full_name(merge.target, &options))).await;
+        }
+        Ok(changes.num_rows() as u64)
+    }
+
+    /// Run `plan` if it is a `DELETE` from an Iceberg table, returning the number of
+    /// rows deleted. DataFusion plans deletes, but leaves running them to tables.
+    async fn delete_from_iceberg(&self, plan: &LogicalPlan) -> DataFusionResult<Option<u64>> {
+        let LogicalPlan::Dml(DmlStatement { op: WriteOp::Delete, target, input, .. }) = plan else {
+            return Ok(None);
+        };
+        let provider = AnalyzedTable::unwrap(source_as_provider(target)?);
+        let Some(table) = provider.as_any().downcast_ref::<IcebergTable>() else {
+            return Ok(None);
+        };
+        let predicate = match input.as_ref() {
+            LogicalPlan::Filter(filter) if matches!(*filter.input, LogicalPlan::TableScan(_)) => {
+                Some(&filter.predicate)
+            }
+            LogicalPlan::TableScan(_) => None,
+            _ => {
+                return Err(DataFusionError::NotImplemented(format!(
+                    "DELETE from Iceberg tables of rows selected by {input} is not supported"
+                )))
+            }
+        };
+        Ok(Some(table.delete(&self.ctx.state(), predicate).await?))
+    }
+
+    /// The result of a statement changing `rows` rows, as DataFusion reports inserts.
+    fn row_count(&self, rows: u64) -> DataFusionResult<DataFrame> {
+        let count: ArrayRef = Arc::new(UInt64Array::from(vec![rows]));
+        self.ctx.read_batch(RecordBatch::try_from_iter([("count", count)])?)
+    }
+
+    /// Append `batches` to `table` in commits, see [`ingest`].
+    pub async fn ingest(
+        &self,
+        table: impl Into<TableReference>,
+        batches: impl Stream<Item = DataFusionResult<RecordBatch>> + Send,
+        options: IngestOptions,
+    ) -> DataFusionResult<IngestReport> {
+        let table = table.into();
+        let target = provider_as_source(self.ctx.table_provider(table.clone()).await?);
+        let schema = target.schema();
+        let logged_name = full_name(table.clone(), &self.ctx.state().config().options().catalog);
+        let mut dedup =
+            self.dedup.dedup(&logged_name, &schema, &options.keys, options.dedup_window)?;
+        let mut batches = pin!(batches);
+        let mut report = IngestReport::default();
+        let (mut pending, mut pending_rows) = (vec![], 0);
+        let mut segment = None;
+        let mut deadline = Instant::now();
+        loop {
+            let next = tokio::select! {
+                next = batches.next() => Some(next),
+                _ = tokio::time::sleep_until(deadline), if !pending.is_empty() => None,
+            };
+            let (timed_out, done) = (next.is_none(), matches!(next, Some(None)));
+            if let Some(Some(batch)) = next {
+                let mut batch = ingest::conform(&batch?, &schema)?;
+                if let Some(dedup) = &mut dedup {
+                    let duplicates;
+                    (batch, duplicates) = dedup.filter(&batch)?;
+                    report.duplicates += duplicates;
+                    if batch.num_rows() == 0 && duplicates > 0 {
+                        continue;
+                    }
+                }
+                if pending.is_empty() {
+                    deadline = Instant::now() + options.interval;
+                    if let Some(wal) = &self.ingest_wal {
+                        segment = Some(wal.create(&logged_name, &options.keys, &schema).await?);
+                    }
+                }
+                if let Some(segment) = &segment {
+                    segment.append(&batch).await?;
+                }
+                pending_rows += batch.num_rows();
+                pending.push(batch);
+            }
+            if !pending.is_empty() && (timed_out || done || pending_rows >= options.max_rows) {
+                let batches = std::mem::take(&mut pending);
+                report.rows += self.commit_ingested(&table, &target, &batches).await?;
+                report.commits += 1;
+                pending_rows = 0;
+                if let Some(dedup) = &mut dedup {
+                    dedup.committed();
+                }
+                // Committed, so no longer needed in the log.
+                if let Some(segment) = segment.take() {
+                    segment.remove().await;
+                }
+            }
+            if done {
+                return Ok(report);
+            }
+        }
+    }
+
+    /// Commit the rows left in the ingestion write-ahead log by a crash (see
+    /// [`ingest`]), each segment of them as one commit. To be run on startup, before
+    /// ingesting.
+    pub async fn replay_ingest_wal(&self) -> DataFusionResult<IngestReport> {
+        let mut report = IngestReport::default();
+        let Some(wal) = &self.ingest_wal else {
+            return Ok(report);
+        };
+        for path in wal.segments()? {
+            let segment = path.clone();
+            if let Some(logged) = ingest::blocking(move || ingest::read_segment(&segment)).await? {
+                let table = TableReference::parse_str(&logged.table);
+                let target = self.ctx.table_provider(table.clone()).await.map_err(|e| {
+                    e.context(format!("replaying ingestion log segment {}", path.display()))
+                })?;
+                let target = provider_as_source(target);
+                let schema = target.schema();
+                let mut dedup = self.dedup.dedup(
+                    &logged.table,
+                    &schema,
+                    &logged.keys,
+                    ingest::DEFAULT_DEDUP_WINDOW,
+                )?;
+                let mut batches = vec![];
+                for batch in &logged.batches {
+                    let mut batch = ingest::conform(batch, &schema)?;
+                    if let Some(dedup) = &mut dedup {
+                        let duplicates;
+                        (batch, duplicates) = dedup.filter(&batch)?;
+                        report.duplicates += duplicates;
+                    }
+                    batches.push(batch);
+                }
+                report.rows += self.commit_ingested(&table, &target, &batches).await?;
+                report.commits += 1;
+                if let Some(dedup) = &mut dedup {
+                    dedup.committed();
+                }
+            }
+            ingest::blocking(move || Ok(std::fs::remove_file(path)?)).await?;
+        }
+        Ok(report)
+    }
+
+    /// Append `batches`, conformed to `target`, to `table` in one commit, returning the
+    /// number of rows written.
+    async fn commit_ingested(
+        &self,
+        table: &TableReference,
+        target: &Arc<dyn TableSource>,
+        batches: &[RecordBatch],
+    ) -> DataFusionResult<u64> {
+        // One batch, so one commit writes as few files as it can.
+        let batch = concat_batches(&target.schema(), batches)?;
+        let input = self.ctx.read_batch(batch)?;
+        let insert = LogicalPlanBuilder::insert_into(
+            input.into_unoptimized_plan(),
+            table.clone(),
+            Arc::clone(target),
+            InsertOp::Append,
+        )?
+        .build()?;
+        written_rows(&self.execute_logical_plan(insert).await?.collect().await?)
+    }
+
+    /// Append the records of the CSV or JSON file at `path` to `table`, calling
+    /// `progress` after every batch read; see [`load`].
+    pub async fn load(
+        &self,
+        table: impl Into<TableReference>,
+        path: &str,
+        options: LoadOptions,
+        mut progress: impl FnMut(&LoadProgress) + Send,
+    ) -> DataFusionResult<LoadReport> {
+        let table = table.into();
+        let schema = self.ctx.table_provider(table.clone()).await?.schema();
+        let ingest = options.ingest.clone();
+        let mut loader = load::Loader::try_new(path, &schema, options, &mut progress)?;
+        let report = self.ingest(table, futures::stream::iter(&mut loader), ingest).await?;
+        Ok(LoadReport {
+            rows: report.rows,
+            commits: report.commits,
+            duplicates: report.duplicates,
+            bad_records: loader.into_bad_records(),
+        })
+    }
+
+    /// Register `table` as `name`, or where a table registered as `name` has been
+    /// moved to (see [`namespace`]).
     pub fn register_table(
         &self,
         name: &str,
         table: Arc<dyn datafusion::datasource::TableProvider>,
     ) -> datafusion::error::Result<Option<Arc<dyn datafusion::datasource::TableProvider>>> {
-        self.ctx.register_table(name, table)
+        let name = full_name(name.into(), &self.ctx.state().config().options().catalog);
+        if let Some(cache) = &self.result_cache {
+            cache.invalidate(&name);
+        }
+        self.ctx.register_table(self.placements.placed_name(&name).as_str(), table)
+    }
+
+    /// Answer queries matching `query` from the registered table `name`, which holds
+    /// its result, in place of any materialized view of that name; see
+    /// [`materialized_views`].
+    pub async fn register_materialized_view(
+        &self,
+        name: &str,
+        query: &str,
+    ) -> DataFusionResult<()> {
+        let name = TableReference::from(name);
+        let table = self.ctx.table_provider(name.clone()).await?;
+        let plan = self.ctx.state().create_logical_plan(query).await?;
+        self.materialized_views.register(name, table, plan)
+    }
+
+    /// Stop answering queries from the materialized view `name`, returning whether it
+    /// was registered. Its table is kept.
+    pub fn deregister_materialized_view(&self, name: &str) -> bool {
+        self.materialized_views.deregister(&TableReference::from(name))
+    }
+
+    /// Register `table` as `igloo.system.name`, creating the system catalog and schema
+    /// on first use. Tenants do not see system tables.
+    pub fn register_system_table(
+        &self,
+        name: &str,
+        table: Arc<dyn TableProvider>,
+    ) -> DataFusionResult<()> {
+        let catalog = match self.ctx.catalog(SYSTEM_CATALOG) {
+            Some(catalog) => catalog,
+            None => {
+                let catalog: Arc<dyn CatalogProvider> = Arc::new(MemoryCatalogProvider::new());
+                self.ctx.register_catalog(SYSTEM_CATALOG, Arc::clone(&catalog));
+                catalog
+            }
+        };
+        let schema = match catalog.schema(SYSTEM_SCHEMA) {
+            Some(schema) => schema,
+            None => {
+                let schema: Arc<dyn SchemaProvider> = Arc::new(MemorySchemaProvider::new());
+                catalog.register_schema(SYSTEM_SCHEMA, Arc::clone(&schema))?;
+                schema
+            }
+        };
+        schema.register_table(name.to_string(), table)?;
+        Ok(())
+    }
+
+    /// Register a scalar function implemented by a WebAssembly module, callable from
+    /// SQL as `name`. See [`wasm_udf`] for the module's calling convention.
+    #[cfg(feature = "wasm")]
+    pub fn register_wasm_udf(
+        &self,
+        name: &str,
+        wasm: &[u8],
+        arg_types: Vec<DataType>,
+        return_type: DataType,
+    ) -> DataFusionResult<()> {
+        let udf = wasm_udf::WasmScalarUdf::try_new(name, wasm, arg_types, return_type)?;
+        self.ctx.register_udf(udf.into());
+        Ok(())
     }
 
     pub async fn execute(&self, sql: &str) -> Vec<RecordBatch> {
-        let df = self.ctx.sql(sql).await.expect("SQL execution failed");
+        let df = self.sql(sql).await.expect("SQL execution failed");
         df.collect().await.expect("Failed to collect results")
     }
+
+    /// Execute `sql`, returning its batches along with any non-fatal diagnostics
+    /// (e.g. filters that could not be pushed down to a source).
+    pub async fn query(&self, sql: &str) -> DataFusionResult<QueryResult> {
+        match self.statement_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.run(sql))
+                .await
+                .unwrap_or_else(|_| Err(timeout_error(timeout))),
+            None => self.run(sql).await,
+        }
+    }
+
+    /// Execute `sql`, returning its batches as they are produced rather than once
+    /// they all are, so that results of any size pass through bounded memory. The
+    /// statement timeout applies until the stream ends. Results served from the
+    /// result cache are streamed from it, but streamed results are not cached.
+    pub async fn query_stream(&self, sql: &str) -> DataFusionResult<QueryStream> {
+        let prepared = match self.statement_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.prepare(sql))
+                .await
+                .unwrap_or_else(|_| Err(timeout_error(timeout)))?,
+            None => self.prepare(sql).await?,
+        };
+        let schema = prepared.schema.clone();
+        let cached = prepared.cached.as_ref().and_then(|(cache, plan)| cache.get(plan, &schema));
+        let cache_hit = cached.is_some();
+        self.record_lineage(prepared.lineage).await;
+        let task_ctx = Arc::new(prepared.df.task_ctx());
+        let plan: Arc<dyn ExecutionPlan> = match cached {
+            Some(batches) => MemorySourceConfig::try_new_exec(&[batches], schema.clone(), None)?,
+            None => prepared.df.create_physical_plan().await?,
+        };
+        let batches = execute_stream(plan.clone(), task_ctx)?;
+        Ok(QueryStream {
+            schema,
+            batches: session::with_timeout(batches, self.statement_timeout),
+            diagnostics: prepared.diagnostics,
+            tables: prepared.tables,
+            plan,
+            cache_hit,
+        })
+    }
+
+    /// Parse and plan `sql`, reporting what running it would do, without running it or
+    /// reading from any source; see [`validate`].
+    pub async fn validate(&self, sql: &str) -> DataFusionResult<Validation> {
+        if let Some((table, columns)) = statistics::parse_analyze_sql(sql)? {
+            return self.validate_table(table, &columns).await;
+        }
+        if let Some((table, _)) = namespace::parse_rename_sql(sql)? {
+            return self.validate_table(table, &[]).await;
+        }
+        if let Some(create) = parquet_sink::parse_create_table_as_sql(sql)? {
+            return self.validate_plan(&create.query).await;
+        }
+        if let Some(merge) = merge::parse_merge_sql(sql)? {
+            let target = self.validate_table(merge.target, &[]).await?;
+            let mut validation =
+                self.validate_plan(&format!("SELECT * FROM {}", merge.source)).await?;
+            validation.schema = target.schema;
+            validation.tables.retain(|table| !target.tables.contains(table));
+            validation.tables.splice(0..0, target.tables);
+            return Ok(validation);
+        }
+        if running::is_show_queries_sql(sql) || running::parse_kill_sql(sql)?.is_some() {
+            return Ok(Validation {
+                schema: Arc::new(Schema::empty()),
+                tables: vec![],
+                scans: vec![],
+                diagnostics: vec![],
+                plan: String::new(),
+            });
+        }
+        self.validate_plan(sql).await
+    }
+
+    /// The validation of a statement Igloo runs over `table` and its `columns`.
+    async fn validate_table(
+        &self,
+        table: TableReference,
+        columns: &[String],
+    ) -> DataFusionResult<Validation> {
+        let schema = self.ctx.table_provider(table.clone()).await?.schema();
+        for column in columns {
+            schema.field_with_name(column)?;
+        }
+        Ok(Validation {
+            schema: Arc::new(Schema::empty()),
+            tables: vec![table.to_string()],
+            scans: vec![],
+            diagnostics: vec![],
+            plan: String::new(),
+        })
+    }
+
+    async fn validate_plan(&self, sql: &str) -> DataFusionResult<Validation> {
+        let state = self.ctx.state();
+        let plan = state.create_logical_plan(sql).await?;
+        let optimized = state.optimize(&plan)?;
+        let display = optimized.display_indent().to_string();
+        let mut diagnostics = inspect_plan(&optimized)?;
+        diagnostics.extend(union_diagnostics(&plan)?);
+        Ok(Validation {
+            schema: plan.schema().inner().clone(),
+            tables: source_tables(&plan),
+            scans: scan_pushdown(&optimized),
+            diagnostics,
+            plan: display,
+        })
+    }
+
+    async fn run(&self, sql: &str) -> DataFusionResult<QueryResult> {
+        let PreparedQuery { df, schema, diagnostics, tables, scans, lineage, cached } =
+            self.prepare(sql).await?;
+        if let Some(batches) = cached.as_ref().and_then(|(cache, plan)| cache.get(plan, &schema)) {
+            self.record_lineage(lineage).await;
+            let plan = MemorySourceConfig::try_new_exec(
+                std::slice::from_ref(&batches),
+                schema.clone(),
+                None,
+            )?;
+            return Ok(QueryResult {
+                schema,
+                batches,
+                spilled: None,
+                diagnostics,
+                tables,
+                scanned_bytes: 0,
+                scan_columns: vec![],
+                plan,
+                cache_hit: true,
+            });
+        }
+        let task_ctx = Arc::new(df.task_ctx());
+        let plan = df.create_physical_plan().await?;
+        let (batches, spilled) = match &self.result_spool {
+            Some(spool) => {
+                match spool.spool(execute_stream(plan.clone(), task_ctx)?).await?.into_batches() {
+                    Ok(batches) => (batches, None),
+                    Err(spilled) => (vec![], Some(spilled)),
+                }
+            }
+            None => (collect(plan.clone(), task_ctx).await?, None),
+        };
+        let scanned_bytes = scanned_bytes(&plan);
+        self.record_lineage(lineage).await;
+        if let Some((cache, canonical)) = cached.filter(|_| spilled.is_none()) {
+            let ttl = self.cache_policy.as_ref().and_then(|policy| policy.ttl(canonical.tables()));
+            cache.put_with_ttl(&canonical, &batches, ttl)?;
+        }
+        Ok(QueryResult {
+            schema,
+            batches,
+            spilled,
+            diagnostics,
+            tables,
+            scanned_bytes,
+            scan_columns: scans,
+            plan,
+            cache_hit: false,
+        })
+    }
+
+    /// Plan `sql` and work out what [`Self::query`] and [`Self::query_stream`] report
+    /// of it, short of executing it.
+    async fn prepare(&self, sql: &str) -> DataFusionResult<PreparedQuery> {
+        let df = self.sql(sql).await?;
+        let tables = source_tables(df.logical_plan());
+        let state = self.ctx.state();
+        let options = &state.config().options().catalog;
+        self.reanalyze_stale(&statistics::scanned_tables(df.logical_plan(), options));
+        let lineage = match &self.catalog_sync {
+            Some(_) => Lineage::of_query(sql, df.logical_plan(), options),
+            None => None,
+        };
+        let optimized = df.clone().into_optimized_plan()?;
+        let mut diagnostics = inspect_plan(&optimized)?;
+        diagnostics.extend(union_diagnostics(df.logical_plan())?);
+        diagnostics.extend(self.materialized_view_reads(&tables, &optimized));
+        let scans = scan_columns(&optimized);
+        // Analysis may change the types of the unanalyzed plan's columns, e.g. of unions.
+        let schema = optimized.schema().inner().clone();
+        let cached = match &self.result_cache {
+            Some(cache) => {
+                let config = state.config().options();
+                let plan = CanonicalPlan::of(&optimized, config)?.filter(|plan| {
+                    self.cache_policy
+                        .as_ref()
+                        .map_or(true, |policy| policy.cacheable(plan.tables()))
+                });
+                plan.map(|plan| (Arc::clone(cache), plan))
+            }
+            None => None,
+        };
+        Ok(PreparedQuery { df, schema, diagnostics, tables, scans, lineage, cached })
+    }
+
+    /// Notices of the materialized views `optimized` reads in place of some of
+    /// `tables`, those the query names.
+    fn materialized_view_reads(
+        &self,
+        tables: &[String],
+        optimized: &LogicalPlan,
+    ) -> Vec<Diagnostic> {
+        let scanned = source_tables(optimized);
+        let views = self.materialized_views.names().into_iter().map(|name| name.to_string());
+        views
+            .filter(|view| scanned.contains(view) && !tables.contains(view))
+            .map(|view| {
+                Diagnostic::notice(
+                    "materialized_view_read",
+                    format!("the query reads materialized view `{view}` in place of its sources"),
+                )
+            })
+            .collect()
+    }
+
+    async fn record_lineage(&self, lineage: Option<Lineage>) {
+        if let (Some(sync), Some(lineage)) = (&self.catalog_sync, lineage) {
+            sync.record_lineage(&lineage).await;
+        }
+    }
+}
+
+/// A planned statement, see [`QueryEngine::prepare`].
+struct PreparedQuery {
+    df: DataFrame,
+    schema: SchemaRef,
+    diagnostics: Vec<Diagnostic>,
+    tables: Vec<String>,
+    scans: Vec<ScanColumns>,
+    lineage: Option<Lineage>,
+    /// Where the result is cached, if it can be.
+    cached: Option<(Arc<ResultCache>, CanonicalPlan)>,
+}
+
+/// The row count a `COPY` or an `INSERT` returns.
+fn written_rows(batches: &[RecordBatch]) -> DataFusionResult<u64> {
+    let count = batches.first().and_then(|batch| {
+        batch.column(0).as_any().downcast_ref::<UInt64Array>().map(|count| count.value(0))
+    });
+    count.ok_or_else(|| DataFusionError::Internal("no row count was returned".to_string()))
+}
+
+/// `state` with `igloo.use_materialized_views` off.
+fn without_materialized_views(mut state: SessionState) -> SessionState {
+    let options = &mut state.config_mut().options_mut().extensions;
+    if let Some(options) = options.get_mut::<IglooOptions>() {
+        options.use_materialized_views = false;
+    }
+    state
+}
+
+/// Install a rule reading `views` in place of the existing materialized view rule.
+fn with_materialized_views(state: SessionState, views: Arc<MaterializedViews>) -> SessionState {
+    let rules = state
+        .analyzer()
+        .rules
+        .iter()
+        .map(|rule| match rule.name() {
+            MaterializedViewRule::NAME => Arc::new(MaterializedViewRule::new(Arc::clone(&views))),
+            _ => Arc::clone(rule),
+        })
+        .collect();
+    SessionStateBuilder::new_from_existing(state).with_analyzer_rules(rules).build()
+}
+
+/// Install `rule` in place of any existing policy rule, ahead of type coercion so the
+/// expressions it injects get typed.
+fn with_policy_rule(state: SessionState, rule: Arc<PolicyRule>) -> SessionState {
+    let mut rules: Vec<Arc<dyn AnalyzerRule + Send + Sync>> = vec![rule];
+    rules.extend(state.analyzer().rules.iter().filter(|r| r.name() != PolicyRule::NAME).cloned());
+    SessionStateBuilder::new_from_existing(state).with_analyzer_rules(rules).build()
 }
 
 /// Capitalizes the first string array in the input.
@@ -146,6 +1760,8 @@ mod tests {
     use datafusion::arrow::record_batch::RecordBatch;
     use datafusion::catalog::MemTable; // Corrected path
                                        // DataFusionResult is brought in by super::*
+    use datafusion::prelude::col;
+    use futures::TryStreamExt;
     use std::sync::Arc;
 
     #[tokio::test]
@@ -224,4 +1840,161 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_query_reports_unpushed_filters() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(vec![1, 2, 3]))])?;
+        // MemTable evaluates no filters itself, so every predicate stays in Igloo.
+        engine
+            .register_table("numbers", Arc::new(MemTable::try_new(schema, vec![vec![batch]])?))?;
+
+        let result = engine.query("SELECT id FROM numbers WHERE id > 1").await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+        assert_eq!(result.tables, ["numbers"]);
+        assert!(result.scanned_bytes >= 3 * 8, "{}", result.scanned_bytes);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].code, "filter_not_pushed_down");
+        assert!(result.diagnostics[0].message.contains("numbers"));
+        // The executed plan, with its metrics.
+        assert!(diagnostics::explain_analyze(&result.plan).contains("output_rows=2"));
+        let timings = diagnostics::source_timings(&result.plan);
+        assert_eq!(timings.len(), 1);
+        assert!(timings[0].source.starts_with("DataSourceExec"), "{timings:?}");
+
+        let result = engine.query("SELECT id FROM numbers").await?;
+        assert!(result.diagnostics.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_results_over_the_spool_threshold_are_spilled() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-engine-spool-{}", std::process::id()));
+        let spool = spool::ResultSpool::new(&dir)?.with_threshold(1024);
+        let engine = QueryEngine::new().with_result_spool(Arc::new(spool));
+
+        let small = engine.query("SELECT 1 AS one").await?;
+        assert!(small.spilled.is_none());
+        assert_eq!(small.batches[0].num_rows(), 1);
+
+        let large = engine.query("SELECT value FROM range(10000)").await?;
+        assert!(large.batches.is_empty());
+        let spilled = large.spilled.as_ref().expect("result was not spilled");
+        assert_eq!(spilled.num_rows(), 10_000);
+        let rows =
+            spilled.batches()?.map(|b| b.map(|b| b.num_rows())).sum::<DataFusionResult<usize>>()?;
+        assert_eq!(rows, 10_000);
+        let path = spilled.path().unwrap().to_path_buf();
+        drop(large);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_stream_yields_batches_before_the_result_ends() -> DataFusionResult<()> {
+        let cache = Arc::new(result_cache::ResultCache::new(1 << 20));
+        let engine = QueryEngine::new().with_result_cache(cache);
+
+        // Far more rows than could be collected.
+        let mut stream = engine.query_stream("SELECT value FROM range(1000000000000)").await?;
+        assert!(!stream.cache_hit);
+        let first = stream.batches.next().await.unwrap()?;
+        assert_eq!(first.num_rows(), engine.ctx.state().config().batch_size());
+        assert_eq!(first.schema(), stream.schema);
+        drop(stream);
+
+        engine.query("SELECT value FROM range(10)").await?;
+        let stream = engine.query_stream("SELECT value FROM range(10)").await?;
+        assert!(stream.cache_hit);
+        let batches: Vec<_> = stream.batches.try_collect().await?;
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scans_fetch_the_columns_read() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE wide (id BIGINT, name TEXT, amount BIGINT, note TEXT)").await?;
+        engine.query("INSERT INTO wide VALUES (1, 'a', 10, 'x'), (2, 'b', 20, 'y')").await?;
+
+        let result = engine.query("SELECT name FROM wide WHERE amount > 10").await?;
+        let scans = [ScanColumns { table: "wide".to_string(), fetched: 2, available: 4 }];
+        assert_eq!(result.scan_columns, scans);
+        assert!(result.diagnostics.iter().all(|d| d.code != "columns_not_pruned"));
+        let result = engine.query("SELECT count(*) FROM wide").await?;
+        assert_eq!(result.scan_columns[0].fetched, 0);
+
+        // A plan whose scan was not pruned.
+        let table = provider_as_source(engine.session_context().table_provider("wide").await?);
+        let plan = LogicalPlanBuilder::scan("wide", table, None)?.project(vec![col("name")])?;
+        let diagnostics = inspect_plan(&plan.build()?)?;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "columns_not_pruned");
+        assert!(diagnostics[0].message.contains("`id`, `amount`, `note`"), "{diagnostics:?}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_isolated() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE SCHEMA sales").await?;
+        engine.query("CREATE TABLE sales.orders AS VALUES (1), (2)").await?;
+
+        let mut vars = SessionVars::new();
+        vars.set("search_path", "sales").unwrap();
+        vars.set("time_zone", "+02:00").unwrap();
+        let session = engine.with_session(&vars);
+        let result = session.query("SELECT count(*) FROM orders").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        let options = session.session_context().state().config_options().clone();
+        assert_eq!(options.execution.time_zone.as_deref(), Some("+02:00"));
+
+        // The shared engine keeps its defaults.
+        assert!(engine.query("SELECT count(*) FROM orders").await.is_err());
+        let options = engine.session_context().state().config_options().clone();
+        assert_eq!(options.execution.time_zone.as_deref(), Some("+00:00"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tenants_are_isolated() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE shared AS VALUES (1)").await?;
+        let acme = engine.add_tenant(Tenant::new("acme"))?;
+        let globex = engine.add_tenant(Tenant::new("globex").with_memory_limit(1))?;
+        acme.query("CREATE TABLE orders AS VALUES (1), (2)").await?;
+
+        let result = engine.tenant("acme").unwrap().query("SELECT count(*) FROM orders").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        // Functions are shared; tables are not.
+        acme.query("SELECT capitalize(arrow_cast(column1, 'Utf8')) FROM orders").await?;
+        assert!(acme.query("SELECT * FROM shared").await.is_err());
+        assert!(globex.query("SELECT * FROM orders").await.is_err());
+        assert!(engine.query("SELECT * FROM orders").await.is_err());
+        assert_eq!(engine.tenant_names(), ["acme", "globex"]);
+
+        // globex's queries draw on its own, tiny memory pool.
+        globex.query("CREATE TABLE t AS SELECT * FROM generate_series(1, 100000)").await?;
+        let err = globex.query("SELECT * FROM t ORDER BY value DESC").await.unwrap_err();
+        assert!(err.to_string().contains("Resources exhausted"), "{err}");
+        acme.query("CREATE TABLE t AS SELECT * FROM generate_series(1, 100000)").await?;
+        acme.query("SELECT * FROM t ORDER BY value DESC").await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_tenants_plan_with_the_engines_rules() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let acme = engine.add_tenant(Tenant::new("acme"))?;
+        let state = acme.session_context().state();
+        let optimizers: Vec<_> = state.optimizer().rules.iter().map(|r| r.name()).collect();
+        assert!(optimizers.contains(&"sideways_scans"), "{optimizers:?}");
+        let physical: Vec<_> = state.physical_optimizers().iter().map(|r| r.name()).collect();
+        assert!(physical.contains(&"join_strategy"), "{physical:?}");
+        assert!(state.get_file_format_factory("avro").is_some());
+        Ok(())
+    }
 }
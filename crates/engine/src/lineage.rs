@@ -0,0 +1,458 @@
+//! Column-level lineage of queries and materializations.
+//!
+//! With a catalog store (see [`catalog_store`](crate::catalog_store)), the engine
+//! records which source columns fed each output column:
+//!
+//! - of every query run through [`QueryEngine::query`](crate::QueryEngine::query),
+//!   keyed by its SQL (running the same SQL again replaces its lineage);
+//! - of every view and `CREATE TABLE AS`, replacing the lineage of any earlier
+//!   definition, and of every `INSERT INTO`, adding to the table's lineage. Dropping
+//!   or redefining a table without a query (`CREATE EXTERNAL TABLE`) clears it.
+//!
+//! Views are expanded when queries are planned, so a query over a view points at the
+//! view's tables; [`upstream`] follows lineage through tables filled by `CREATE TABLE
+//! AS` and `INSERT INTO` back to where their rows came from. Only values flowing into an output column count: columns read by a filter or join
+//! condition alone are not sources.
+//!
+//! Edges are listed by
+//! [`QueryEngine::lineage`](crate::QueryEngine::lineage) and queryable as
+//! `information_schema.lineage` in the default catalog (which DataFusion's own
+//! `information_schema` hides when `datafusion.catalog.information_schema` is on).
+
+use crate::catalog_store::{full_name, CatalogStore};
+use async_trait::async_trait;
+use datafusion::arrow::array::StringArray;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::{MemTable, Session, TableProvider};
+use datafusion::common::config::CatalogOptions;
+use datafusion::common::tree_node::{TreeNode, TreeNodeRecursion};
+use datafusion::common::{Column, DFSchema};
+use datafusion::datasource::TableType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::expr::{Exists, InSubquery};
+use datafusion::logical_expr::{DdlStatement, DmlStatement, Expr, LogicalPlan, WriteOp};
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// What lineage flows into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    /// A table or view, by its fully qualified name.
+    Table,
+    /// The result of a query, by its SQL.
+    Query,
+}
+
+impl TargetKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TargetKind::Table => "table",
+            TargetKind::Query => "query",
+        }
+    }
+
+    pub(crate) fn parse(name: &str) -> DataFusionResult<Self> {
+        match name {
+            "table" => Ok(TargetKind::Table),
+            "query" => Ok(TargetKind::Query),
+            _ => Err(DataFusionError::Execution(format!("unknown lineage target kind '{name}'"))),
+        }
+    }
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A source column feeding a column of a target.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+pub struct ColumnLineage {
+    pub column: String,
+    /// Fully qualified, quoted where needed (`catalog.schema.table`).
+    pub source: String,
+    pub source_column: String,
+}
+
+/// The lineage a statement records for its target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lineage {
+    pub kind: TargetKind,
+    pub target: String,
+    /// Whether `edges` replace the target's previous edges rather than add to them.
+    pub replace: bool,
+    pub edges: Vec<ColumnLineage>,
+}
+
+/// A recorded edge, from a source column to a column of a target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LineageEdge {
+    pub target_kind: TargetKind,
+    pub target: String,
+    #[serde(flatten)]
+    pub column: ColumnLineage,
+}
+
+impl Lineage {
+    /// The lineage of running the query `sql`, planned as `plan`, if it reads any
+    /// table.
+    pub fn of_query(sql: &str, plan: &LogicalPlan, options: &CatalogOptions) -> Option<Self> {
+        if matches!(plan, LogicalPlan::Ddl(_) | LogicalPlan::Dml(_)) {
+            return None;
+        }
+        let edges = edges(plan, options);
+        let lineage =
+            Self { kind: TargetKind::Query, target: sql.trim().to_string(), replace: true, edges };
+        (!lineage.edges.is_empty()).then_some(lineage)
+    }
+
+    /// The lineage of the table or view `plan` creates, drops or inserts into, if any.
+    pub fn of_statement(plan: &LogicalPlan, options: &CatalogOptions) -> Option<Self> {
+        let (name, replace, edges) = match plan {
+            LogicalPlan::Ddl(DdlStatement::CreateView(create)) => {
+                (&create.name, true, edges(&create.input, options))
+            }
+            LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(create)) => {
+                (&create.name, true, edges(&create.input, options))
+            }
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(create)) => {
+                (&create.name, true, vec![])
+            }
+            LogicalPlan::Ddl(DdlStatement::DropTable(drop)) => (&drop.name, true, vec![]),
+            LogicalPlan::Ddl(DdlStatement::DropView(drop)) => (&drop.name, true, vec![]),
+            LogicalPlan::Dml(DmlStatement {
+                table_name, op: WriteOp::Insert(_), input, ..
+            }) => (table_name, false, edges(input, options)),
+            _ => return None,
+        };
+        let target = full_name(name.clone(), options);
+        Some(Self { kind: TargetKind::Table, target, replace, edges })
+    }
+}
+
+/// The edges from the tables `plan` reads to its output columns.
+fn edges(plan: &LogicalPlan, options: &CatalogOptions) -> Vec<ColumnLineage> {
+    let sources = column_sources(plan, options);
+    let mut edges = BTreeSet::new();
+    for ((_, field), sources) in plan.schema().iter().zip(sources) {
+        for (source, source_column) in sources {
+            let column = field.name().clone();
+            edges.insert(ColumnLineage { column, source, source_column });
+        }
+    }
+    edges.into_iter().collect()
+}
+
+/// Source columns, as (table, column), by output column.
+type Sources = BTreeSet<(String, String)>;
+
+/// The source columns of each output column of `plan`.
+fn column_sources(plan: &LogicalPlan, options: &CatalogOptions) -> Vec<Sources> {
+    match plan {
+        // Metadata, such as lineage itself, is no source.
+        LogicalPlan::TableScan(scan) if scan.table_name.schema() == Some("information_schema") => {
+            vec![Sources::new(); scan.projected_schema.fields().len()]
+        }
+        LogicalPlan::TableScan(scan) => {
+            let table = full_name(scan.table_name.clone(), options);
+            let fields = scan.projected_schema.fields().iter();
+            fields.map(|f| Sources::from([(table.clone(), f.name().clone())])).collect()
+        }
+        LogicalPlan::Projection(projection) => {
+            let input = column_sources(&projection.input, options);
+            let schema = projection.input.schema();
+            let exprs = projection.expr.iter();
+            exprs.map(|e| expr_sources(e, schema, &input, options)).collect()
+        }
+        LogicalPlan::Aggregate(aggregate) => {
+            let input = column_sources(&aggregate.input, options);
+            let schema = aggregate.input.schema();
+            let of = |e: &Expr| expr_sources(e, schema, &input, options);
+            let mut sources = vec![];
+            for expr in &aggregate.group_expr {
+                match expr {
+                    Expr::GroupingSet(set) => {
+                        sources.extend(set.distinct_expr().into_iter().map(of));
+                        // The grouping ID, which reads no column.
+                        sources.push(Sources::new());
+                    }
+                    expr => sources.push(of(expr)),
+                }
+            }
+            sources.extend(aggregate.aggr_expr.iter().map(of));
+            sources
+        }
+        LogicalPlan::Window(window) => {
+            let input = column_sources(&window.input, options);
+            let schema = window.input.schema();
+            let exprs = window.window_expr.iter().map(|e| expr_sources(e, schema, &input, options));
+            let exprs: Vec<_> = exprs.collect();
+            input.into_iter().chain(exprs).collect()
+        }
+        LogicalPlan::Union(union) => {
+            let mut sources = vec![Sources::new(); union.schema.fields().len()];
+            for input in &union.inputs {
+                for (all, of_input) in sources.iter_mut().zip(column_sources(input, options)) {
+                    all.extend(of_input);
+                }
+            }
+            sources
+        }
+        plan => {
+            let inputs = plan.inputs();
+            let of_inputs: Vec<_> = inputs.iter().map(|i| column_sources(i, options)).collect();
+            // Filters, sorts, aliases, joins and the like output their inputs' columns.
+            let concatenated: Vec<_> = of_inputs.iter().flatten().cloned().collect();
+            if concatenated.len() == plan.schema().fields().len() {
+                return concatenated;
+            }
+            plan.schema()
+                .iter()
+                .map(|(qualifier, field)| {
+                    let column = Column::new(qualifier.cloned(), field.name());
+                    inputs
+                        .iter()
+                        .zip(&of_inputs)
+                        .find_map(|(input, sources)| {
+                            let index = input.schema().maybe_index_of_column(&column)?;
+                            sources.get(index).cloned()
+                        })
+                        .unwrap_or_default()
+                })
+                .collect()
+        }
+    }
+}
+
+/// The source columns of the columns `expr` reads from an input with `schema` and
+/// `input` sources, and of the subqueries it runs.
+fn expr_sources(
+    expr: &Expr,
+    schema: &DFSchema,
+    input: &[Sources],
+    options: &CatalogOptions,
+) -> Sources {
+    let mut sources = Sources::new();
+    for column in expr.column_refs() {
+        if let Some(index) = schema.maybe_index_of_column(column) {
+            sources.extend(input[index].iter().cloned());
+        }
+    }
+    let _ = expr.apply(|e| {
+        let subquery = match e {
+            Expr::ScalarSubquery(subquery) => subquery,
+            Expr::Exists(Exists { subquery, .. }) => subquery,
+            Expr::InSubquery(InSubquery { subquery, .. }) => subquery,
+            _ => return Ok(TreeNodeRecursion::Continue),
+        };
+        sources.extend(column_sources(&subquery.subquery, options).into_iter().flatten());
+        Ok(TreeNodeRecursion::Continue)
+    });
+    sources
+}
+
+/// The edges `target` (of kind `kind`) derives from, directly or through other
+/// targets, limited to those into `column` of it if given.
+pub fn upstream(
+    edges: &[LineageEdge],
+    kind: TargetKind,
+    target: &str,
+    column: Option<&str>,
+) -> Vec<LineageEdge> {
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: Vec<(TargetKind, &str, Option<&str>)> = vec![(kind, target, column)];
+    while let Some((kind, target, column)) = pending.pop() {
+        for edge in edges {
+            let matches = edge.target_kind == kind
+                && edge.target == target
+                && column.map_or(true, |c| c == edge.column.column);
+            if matches && seen.insert((kind, target, edge.column.clone())) {
+                found.push(edge.clone());
+                let source = (&edge.column.source, &edge.column.source_column);
+                pending.push((TargetKind::Table, source.0, Some(source.1)));
+            }
+        }
+    }
+    found
+}
+
+/// `information_schema.lineage`: every recorded edge, read from the store on each scan.
+#[derive(Debug)]
+pub struct LineageTable {
+    store: Arc<dyn CatalogStore>,
+}
+
+impl LineageTable {
+    pub fn new(store: Arc<dyn CatalogStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl TableProvider for LineageTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        let columns = ["target_kind", "target", "column", "source", "source_column"];
+        Arc::new(Schema::new(columns.map(|c| Field::new(c, DataType::Utf8, false)).to_vec()))
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::View
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let edges = self.store.lineage().await?;
+        let column = |f: fn(&LineageEdge) -> &str| {
+            Arc::new(edges.iter().map(|e| Some(f(e))).collect::<StringArray>()) as _
+        };
+        let batch = RecordBatch::try_new(
+            self.schema(),
+            vec![
+                column(|e| e.target_kind.name()),
+                column(|e| &e.target),
+                column(|e| &e.column.column),
+                column(|e| &e.column.source),
+                column(|e| &e.column.source_column),
+            ],
+        )?;
+        let table = MemTable::try_new(self.schema(), vec![vec![batch]])?;
+        table.scan(state, projection, filters, limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+
+    async fn edges_of(engine: &QueryEngine, sql: &str) -> Vec<(String, String, String)> {
+        let plan = engine.session_context().state().create_logical_plan(sql).await.unwrap();
+        let options = engine.session_context().state().config().options().catalog.clone();
+        let edges = match Lineage::of_statement(&plan, &options) {
+            Some(lineage) => lineage.edges,
+            None => Lineage::of_query(sql, &plan, &options).map(|l| l.edges).unwrap_or_default(),
+        };
+        edges
+            .into_iter()
+            .map(|e| (e.column, e.source.replace("datafusion.public.", ""), e.source_column))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_column_sources() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE orders (id INT, customer INT, amount DOUBLE)").await?;
+        engine.query("CREATE TABLE customers (id INT, region TEXT)").await?;
+        let edge = |column: &str, source: &str, source_column: &str| {
+            (column.to_string(), source.to_string(), source_column.to_string())
+        };
+
+        let sql = "SELECT c.region, sum(o.amount) * 2 AS total FROM orders o \
+                   JOIN customers c ON o.customer = c.id WHERE o.id > 0 GROUP BY c.region";
+        assert_eq!(
+            edges_of(&engine, sql).await,
+            [edge("region", "customers", "region"), edge("total", "orders", "amount")]
+        );
+
+        let sql = "SELECT id, (SELECT max(amount) FROM orders) AS top FROM customers \
+                   UNION ALL SELECT customer, amount FROM orders";
+        assert_eq!(
+            edges_of(&engine, sql).await,
+            [
+                edge("id", "customers", "id"),
+                edge("id", "orders", "customer"),
+                edge("top", "orders", "amount"),
+            ]
+        );
+
+        let sql = "INSERT INTO customers SELECT customer, 'EU' FROM orders";
+        assert_eq!(edges_of(&engine, sql).await, [edge("id", "orders", "customer")]);
+        assert!(edges_of(&engine, "SELECT 1").await.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lineage_is_recorded_in_the_catalog_store() -> DataFusionResult<()> {
+        use crate::catalog_store::SqliteCatalogStore;
+
+        let dir = std::env::temp_dir().join(format!("igloo-lineage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db"))?);
+        let engine = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        engine.query("CREATE TABLE raw_orders (region TEXT, amount DOUBLE)").await?;
+        engine.query("CREATE TABLE orders AS SELECT * FROM raw_orders").await?;
+        engine.query("CREATE VIEW revenue AS SELECT region, sum(amount) AS total FROM orders GROUP BY region").await?;
+        let dashboard = "SELECT total FROM revenue WHERE region = 'EU'";
+        engine.query(dashboard).await?;
+        engine.query(dashboard).await?;
+
+        let edges = engine.lineage().await?;
+        let traced = upstream(&edges, TargetKind::Query, dashboard, None);
+        let traced: Vec<_> = traced
+            .iter()
+            .map(|e| (e.target.as_str(), e.column.source.as_str(), e.column.source_column.as_str()))
+            .collect();
+        assert_eq!(
+            traced,
+            [
+                (dashboard, "datafusion.public.orders", "amount"),
+                ("datafusion.public.orders", "datafusion.public.raw_orders", "amount"),
+            ]
+        );
+
+        // Another node over the same store sees it too, in SQL.
+        let other = QueryEngine::new().with_catalog_store(store).await?;
+        let result = other
+            .query("SELECT target, source_column FROM information_schema.lineage WHERE target_kind = 'query'")
+            .await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+        // The view's lineage, the table's two columns' and the query's.
+        assert_eq!(engine.lineage().await?.len(), 5);
+        engine.query("DROP VIEW revenue").await?;
+        assert_eq!(engine.lineage().await?.len(), 3);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_upstream_follows_targets_back() {
+        let edge =
+            |kind, target: &str, column: &str, source: &str, source_column: &str| LineageEdge {
+                target_kind: kind,
+                target: target.to_string(),
+                column: ColumnLineage {
+                    column: column.to_string(),
+                    source: source.to_string(),
+                    source_column: source_column.to_string(),
+                },
+            };
+        let edges = [
+            edge(TargetKind::Query, "SELECT * FROM v", "total", "v", "total"),
+            edge(TargetKind::Query, "SELECT * FROM v", "region", "v", "region"),
+            edge(TargetKind::Table, "v", "total", "orders", "amount"),
+            edge(TargetKind::Table, "v", "region", "customers", "region"),
+            edge(TargetKind::Table, "other", "total", "orders", "amount"),
+        ];
+        let traced = upstream(&edges, TargetKind::Query, "SELECT * FROM v", Some("total"));
+        assert_eq!(traced, [edges[0].clone(), edges[2].clone()]);
+        assert_eq!(upstream(&edges, TargetKind::Table, "v", None).len(), 2);
+    }
+}
@@ -0,0 +1,632 @@
+//! Bulk loading CSV and JSON files into tables.
+//!
+//! [`QueryEngine::load`] appends the records of a CSV file or a newline-delimited JSON
+//! file to a registered table that takes `INSERT`s, committing them as
+//! [`QueryEngine::ingest`] does. The file's columns are its CSV header (the table's
+//! columns, in order, for files without one) or the keys of its first JSON records,
+//! and are validated against the table before anything is written: each must be a
+//! column of the table and the table's required columns must all be there. Values are
+//! cast to the types of their columns, and a column none of whose values in the first
+//! batch cast is taken for a mismatched file rather than bad values.
+//!
+//! Records that cannot be loaded (a wrong number of fields, malformed JSON, a value
+//! not of its column's type, no value for a required column) are skipped and reported
+//! as bad records, up to [`LoadOptions::max_bad_records`] of them; one more fails the
+//! load, keeping what was committed before.
+//!
+//! [`QueryEngine::load`]: crate::QueryEngine::load
+//! [`QueryEngine::ingest`]: crate::QueryEngine::ingest
+
+use crate::ingest::IngestOptions;
+use datafusion::arrow::array::{Array, ArrayRef, BooleanArray, StringArray};
+use datafusion::arrow::compute::{cast_with_options, filter, CastOptions};
+use datafusion::arrow::datatypes::{FieldRef, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Records per batch unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+/// JSON records read for the file's columns.
+const SAMPLE_RECORDS: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadFormat {
+    Csv,
+    /// One JSON object per line.
+    Json,
+}
+
+impl LoadFormat {
+    /// The format of files named like `path`: `.csv`, `.tsv`, or `.json`, `.ndjson`
+    /// and `.jsonl`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        match extension.to_ascii_lowercase().as_str() {
+            "csv" | "tsv" => Some(LoadFormat::Csv),
+            "json" | "ndjson" | "jsonl" => Some(LoadFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LoadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadFormat::Csv => f.write_str("csv"),
+            LoadFormat::Json => f.write_str("json"),
+        }
+    }
+}
+
+impl FromStr for LoadFormat {
+    type Err = DataFusionError;
+
+    fn from_str(s: &str) -> DataFusionResult<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(LoadFormat::Csv),
+            "json" | "ndjson" | "jsonl" => Ok(LoadFormat::Json),
+            other => Err(DataFusionError::Configuration(format!(
+                "unknown load format '{other}', expected csv or json"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// `None` to go by the file's extension.
+    pub format: Option<LoadFormat>,
+    /// Whether the first line of a CSV file names its columns.
+    pub has_header: bool,
+    pub delimiter: u8,
+    /// Bad records skipped before the load fails.
+    pub max_bad_records: usize,
+    pub batch_size: usize,
+    /// When the records read are committed.
+    pub ingest: IngestOptions,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            format: None,
+            has_header: true,
+            delimiter: b',',
+            max_bad_records: 0,
+            batch_size: DEFAULT_BATCH_SIZE,
+            ingest: IngestOptions::default(),
+        }
+    }
+}
+
+impl LoadOptions {
+    pub fn with_format(mut self, format: LoadFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn with_header(mut self, has_header: bool) -> Self {
+        self.has_header = has_header;
+        self
+    }
+
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn with_max_bad_records(mut self, max_bad_records: usize) -> Self {
+        self.max_bad_records = max_bad_records;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    pub fn with_ingest(mut self, ingest: IngestOptions) -> Self {
+        self.ingest = ingest;
+        self
+    }
+}
+
+/// A record skipped by a load.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadRecord {
+    /// The line of the file the record starts on, from 1.
+    pub line: u64,
+    pub reason: String,
+}
+
+/// How far a load is, reported after every batch read.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub bytes_read: u64,
+    /// The size of the file.
+    pub total_bytes: u64,
+    /// Rows read to be loaded, committed or not.
+    pub rows: u64,
+    pub bad_records: u64,
+}
+
+/// What a load appended and skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadReport {
+    pub rows: u64,
+    pub commits: usize,
+    /// Records left out as duplicates, see [`IngestOptions::keys`].
+    pub duplicates: u64,
+    pub bad_records: Vec<BadRecord>,
+}
+
+enum Records {
+    Csv(csv::Reader<File>),
+    Json {
+        lines: BufReader<File>,
+        /// Lines read for the file's columns and not loaded yet, with their numbers.
+        sample: VecDeque<(u64, String)>,
+        line: u64,
+        bytes_read: u64,
+    },
+}
+
+/// The values of a record, by file column, or why it is bad.
+type Record = Result<Vec<Option<String>>, String>;
+
+impl Records {
+    fn open(path: &str, options: &LoadOptions, format: LoadFormat) -> DataFusionResult<Self> {
+        let file = File::open(path)?;
+        Ok(match format {
+            LoadFormat::Csv => Records::Csv(
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .delimiter(options.delimiter)
+                    .from_reader(file),
+            ),
+            LoadFormat::Json => Records::Json {
+                lines: BufReader::new(file),
+                sample: VecDeque::new(),
+                line: 0,
+                bytes_read: 0,
+            },
+        })
+    }
+
+    fn bytes_read(&self) -> u64 {
+        match self {
+            Records::Csv(reader) => reader.position().byte(),
+            Records::Json { bytes_read, .. } => *bytes_read,
+        }
+    }
+
+    /// The next non-empty line of a JSON file and its number.
+    fn next_line(&mut self) -> DataFusionResult<Option<(u64, String)>> {
+        let Records::Json { lines, sample, line, bytes_read } = self else {
+            unreachable!("lines of a CSV file")
+        };
+        if let Some(next) = sample.pop_front() {
+            return Ok(Some(next));
+        }
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            let read = lines.read_line(&mut buf)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            *line += 1;
+            *bytes_read += read as u64;
+            if !buf.trim().is_empty() {
+                return Ok(Some((*line, buf.trim_end().to_string())));
+            }
+        }
+    }
+
+    /// The keys of the first JSON records, in the order they first appear.
+    fn sample_keys(&mut self) -> DataFusionResult<Vec<String>> {
+        let mut lines = VecDeque::new();
+        while lines.len() < SAMPLE_RECORDS {
+            match self.next_line()? {
+                Some(line) => lines.push_back(line),
+                None => break,
+            }
+        }
+        let mut keys: Vec<String> = vec![];
+        for (_, line) in &lines {
+            // Malformed lines are reported as bad records once loaded.
+            if let Ok(record) = serde_json::from_str::<serde_json::Map<String, Value>>(line) {
+                for key in record.keys() {
+                    if !keys.contains(key) {
+                        keys.push(key.clone());
+                    }
+                }
+            }
+        }
+        if let Records::Json { sample, .. } = self {
+            *sample = lines;
+        }
+        Ok(keys)
+    }
+
+    /// The next record and the line it starts on, its values by the file's `columns`.
+    fn next_record(
+        &mut self,
+        columns: &HashMap<String, usize>,
+    ) -> DataFusionResult<Option<(u64, Record)>> {
+        match self {
+            Records::Csv(reader) => {
+                let mut record = csv::StringRecord::new();
+                let read = match reader.read_record(&mut record) {
+                    Ok(read) => read,
+                    Err(e) => match e.kind() {
+                        csv::ErrorKind::Io(_) => return Err(DataFusionError::External(e.into())),
+                        _ => {
+                            let line = e.position().map_or(0, |position| position.line());
+                            return Ok(Some((line, Err(e.to_string()))));
+                        }
+                    },
+                };
+                if !read {
+                    return Ok(None);
+                }
+                let line = record.position().map_or(0, |position| position.line());
+                if record.len() != columns.len() {
+                    let reason =
+                        format!("expected {} fields, found {}", columns.len(), record.len());
+                    return Ok(Some((line, Err(reason))));
+                }
+                let values = record
+                    .iter()
+                    .map(|value| (!value.is_empty()).then(|| value.to_string()))
+                    .collect();
+                Ok(Some((line, Ok(values))))
+            }
+            Records::Json { .. } => {
+                let Some((line, text)) = self.next_line()? else {
+                    return Ok(None);
+                };
+                Ok(Some((line, json_record(&text, columns))))
+            }
+        }
+    }
+}
+
+fn json_record(text: &str, columns: &HashMap<String, usize>) -> Record {
+    let record = serde_json::from_str::<serde_json::Map<String, Value>>(text)
+        .map_err(|e| format!("invalid JSON record: {e}"))?;
+    let mut values = vec![None; columns.len()];
+    for (key, value) in record {
+        let Some(column) = columns.get(&key) else {
+            return Err(format!("unknown column {key}"));
+        };
+        values[*column] = match value {
+            Value::Null => None,
+            Value::String(value) => Some(value),
+            value => Some(value.to_string()),
+        };
+    }
+    Ok(values)
+}
+
+/// Reads the batches of a file to load, see the [module docs](self).
+pub(crate) struct Loader<'a> {
+    path: String,
+    records: Records,
+    /// The file's columns, by name.
+    columns: HashMap<String, usize>,
+    /// The table's fields of the file's columns, in the file's order.
+    schema: SchemaRef,
+    options: LoadOptions,
+    progress: LoadProgress,
+    on_progress: &'a mut (dyn FnMut(&LoadProgress) + Send),
+    bad_records: Vec<BadRecord>,
+    /// Whether the file's columns have been validated against a first batch.
+    validated: bool,
+    done: bool,
+}
+
+impl<'a> Loader<'a> {
+    /// A loader of the file at `path` into a table of `table_schema`.
+    pub(crate) fn try_new(
+        path: &str,
+        table_schema: &SchemaRef,
+        options: LoadOptions,
+        on_progress: &'a mut (dyn FnMut(&LoadProgress) + Send),
+    ) -> DataFusionResult<Self> {
+        let format = match options.format.or_else(|| LoadFormat::from_path(path)) {
+            Some(format) => format,
+            None => {
+                return Err(DataFusionError::Plan(format!(
+                    "cannot tell the format of {path}, expected csv or json"
+                )))
+            }
+        };
+        let mut records = Records::open(path, &options, format)?;
+        let names: Vec<String> = match (&mut records, format) {
+            (Records::Csv(reader), _) if options.has_header => {
+                let mut header = csv::StringRecord::new();
+                reader.read_record(&mut header).map_err(|e| DataFusionError::External(e.into()))?;
+                header.iter().map(|name| name.trim_start_matches('\u{feff}').to_string()).collect()
+            }
+            (records, LoadFormat::Json) => records.sample_keys()?,
+            _ => table_schema.fields().iter().map(|field| field.name().clone()).collect(),
+        };
+        let fields = names
+            .iter()
+            .map(|name| match table_schema.field_with_name(name) {
+                Ok(field) => Ok(Arc::new(field.clone())),
+                Err(_) => Err(DataFusionError::Plan(format!(
+                    "column {name} of {path} is not a column of the table"
+                ))),
+            })
+            .collect::<DataFusionResult<Vec<FieldRef>>>()?;
+        if let Some(missing) = table_schema
+            .fields()
+            .iter()
+            .find(|field| !field.is_nullable() && !names.contains(field.name()))
+        {
+            return Err(DataFusionError::Plan(format!(
+                "{path} has no column {} the table requires",
+                missing.name()
+            )));
+        }
+        let total_bytes = std::fs::metadata(path)?.len();
+        Ok(Self {
+            path: path.to_string(),
+            records,
+            columns: names.into_iter().enumerate().map(|(i, name)| (name, i)).collect(),
+            schema: Arc::new(Schema::new(fields)),
+            options,
+            progress: LoadProgress { total_bytes, ..Default::default() },
+            on_progress,
+            bad_records: vec![],
+            validated: false,
+            done: false,
+        })
+    }
+
+    /// The records skipped.
+    pub(crate) fn into_bad_records(self) -> Vec<BadRecord> {
+        self.bad_records
+    }
+
+    fn skip(&mut self, line: u64, reason: String) -> DataFusionResult<()> {
+        if self.bad_records.len() == self.options.max_bad_records {
+            return Err(DataFusionError::Execution(format!(
+                "{} has more than {} bad records, line {line}: {reason}",
+                self.path, self.options.max_bad_records
+            )));
+        }
+        self.bad_records.push(BadRecord { line, reason });
+        Ok(())
+    }
+
+    /// The next batch of loaded rows, `None` at the end of the file.
+    fn next_batch(&mut self) -> DataFusionResult<Option<RecordBatch>> {
+        let mut lines = Vec::with_capacity(self.options.batch_size);
+        let mut values = vec![Vec::with_capacity(self.options.batch_size); self.columns.len()];
+        while lines.len() < self.options.batch_size {
+            let Some((line, record)) = self.records.next_record(&self.columns)? else {
+                self.done = true;
+                break;
+            };
+            match record {
+                Ok(record) => {
+                    lines.push(line);
+                    for (column, value) in values.iter_mut().zip(record) {
+                        column.push(value);
+                    }
+                }
+                Err(reason) => self.skip(line, reason)?,
+            }
+        }
+        if lines.is_empty() {
+            return Ok(None);
+        }
+        let batch = self.convert(&lines, values)?;
+        self.progress.bytes_read = self.records.bytes_read();
+        self.progress.rows += batch.num_rows() as u64;
+        self.progress.bad_records = self.bad_records.len() as u64;
+        (self.on_progress)(&self.progress);
+        Ok(Some(batch))
+    }
+
+    /// The records of `lines` cast to the types of their columns, without bad ones.
+    fn convert(
+        &mut self,
+        lines: &[u64],
+        values: Vec<Vec<Option<String>>>,
+    ) -> DataFusionResult<RecordBatch> {
+        let options = CastOptions { safe: true, ..Default::default() };
+        let mut reasons: Vec<Option<String>> = vec![None; lines.len()];
+        let mut columns = Vec::with_capacity(values.len());
+        for (field, values) in self.schema.fields().iter().zip(values) {
+            let strings = StringArray::from(values);
+            let column: ArrayRef = cast_with_options(&strings, field.data_type(), &options)?;
+            let invalid = |row: usize| strings.is_valid(row) && column.is_null(row);
+            let values = || (0..strings.len()).filter(|row| strings.is_valid(*row));
+            if !self.validated && values().next().is_some() && values().all(invalid) {
+                return Err(DataFusionError::Plan(format!(
+                    "column {} of {} holds values like {:?}, not of type {}",
+                    field.name(),
+                    self.path,
+                    strings.iter().flatten().next().unwrap_or_default(),
+                    field.data_type()
+                )));
+            }
+            for (row, reason) in reasons.iter_mut().enumerate() {
+                if reason.is_some() {
+                    continue;
+                }
+                if invalid(row) {
+                    *reason = Some(format!(
+                        "invalid {} value {:?} of column {}",
+                        field.data_type(),
+                        strings.value(row),
+                        field.name()
+                    ));
+                } else if column.is_null(row) && !field.is_nullable() {
+                    *reason = Some(format!("no value of required column {}", field.name()));
+                }
+            }
+            columns.push(column);
+        }
+        self.validated = true;
+        if reasons.iter().any(Option::is_some) {
+            // Bad records go before the batch is built, nulls of required columns with them.
+            let keep: BooleanArray = reasons.iter().map(|reason| Some(reason.is_none())).collect();
+            for (line, reason) in lines.iter().zip(reasons) {
+                if let Some(reason) = reason {
+                    self.skip(*line, reason)?;
+                }
+            }
+            columns = columns
+                .iter()
+                .map(|column| Ok(filter(column, &keep)?))
+                .collect::<DataFusionResult<_>>()?;
+        }
+        Ok(RecordBatch::try_new(Arc::clone(&self.schema), columns)?)
+    }
+}
+
+impl Iterator for Loader<'_> {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_batch() {
+            Ok(batch) => batch.map(Ok),
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    fn write_file(name: &str, contents: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("igloo-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path.display().to_string()
+    }
+
+    async fn orders(engine: &QueryEngine) -> String {
+        let result = engine.query("SELECT * FROM orders ORDER BY id").await.unwrap();
+        pretty_format_batches(&result.batches).unwrap().to_string()
+    }
+
+    async fn engine() -> QueryEngine {
+        let engine = QueryEngine::new();
+        let sql = "CREATE TABLE orders (id BIGINT NOT NULL, amount DOUBLE, placed DATE) \
+                   AS VALUES (0, 0.0, DATE '2024-01-01')";
+        engine.query(sql).await.unwrap();
+        engine
+    }
+
+    #[tokio::test]
+    async fn test_load_csv_skips_bad_records() -> DataFusionResult<()> {
+        let engine = engine().await;
+        let path = write_file(
+            "orders.csv",
+            "id,placed,amount\n1,2024-05-01,10.5\nx,2024-05-01,1\n2,,\n3,2024-05-03\n,2024-05-04,4\n",
+        );
+        let mut reports = vec![];
+        let options = LoadOptions::default().with_max_bad_records(3).with_batch_size(2);
+        let report =
+            engine.load("orders", &path, options, |progress| reports.push(*progress)).await?;
+        assert_eq!((report.rows, report.commits), (2, 1));
+        let lines: Vec<_> = report.bad_records.iter().map(|record| record.line).collect();
+        assert_eq!(lines, [3, 5, 6]);
+        assert!(report.bad_records[0].reason.contains("\"x\""), "{:?}", report.bad_records);
+        assert_eq!(report.bad_records[1].reason, "expected 3 fields, found 2");
+        assert_eq!(report.bad_records[2].reason, "no value of required column id");
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[1].bytes_read, reports[1].total_bytes);
+        let expected = "\
++----+--------+------------+
+| id | amount | placed     |
++----+--------+------------+
+| 0  | 0.0    | 2024-01-01 |
+| 1  | 10.5   | 2024-05-01 |
+| 2  |        |            |
++----+--------+------------+";
+        assert_eq!(orders(&engine).await, expected);
+
+        // One bad record too many fails the load.
+        let options = LoadOptions::default().with_max_bad_records(2);
+        let err = engine.load("orders", &path, options, |_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("more than 2 bad records, line 6"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_json() -> DataFusionResult<()> {
+        let engine = engine().await;
+        let path = write_file(
+            "orders.ndjson",
+            "{\"id\": 1, \"amount\": 10}\n\n{\"id\": 2, \"placed\": \"2024-05-02\"}\n{\"id\": 3,\n",
+        );
+        let options = LoadOptions::default().with_max_bad_records(1);
+        let report = engine.load("orders", &path, options, |_| {}).await?;
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.bad_records[0].line, 4);
+        let expected = "\
++----+--------+------------+
+| id | amount | placed     |
++----+--------+------------+
+| 0  | 0.0    | 2024-01-01 |
+| 1  | 10.0   |            |
+| 2  |        | 2024-05-02 |
++----+--------+------------+";
+        assert_eq!(orders(&engine).await, expected);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_load_validates_columns() {
+        let engine = engine().await;
+        let load = |name: &str, contents: &str| {
+            let path = write_file(name, contents);
+            let engine = engine.clone();
+            async move { engine.load("orders", &path, LoadOptions::default(), |_| {}).await }
+        };
+        let err = load("unknown.csv", "id,customer\n1,a\n").await.unwrap_err();
+        assert!(err.to_string().contains("column customer"), "{err}");
+        let err = load("missing.csv", "amount\n1.0\n").await.unwrap_err();
+        assert!(err.to_string().contains("no column id"), "{err}");
+        let err = load("mismatched.csv", "id,amount\n1,cheap\n2,free\n").await.unwrap_err();
+        assert!(err.to_string().contains("holds values like \"cheap\""), "{err}");
+        let err = load("orders.txt", "").await.unwrap_err();
+        assert!(err.to_string().contains("cannot tell the format"), "{err}");
+        assert_eq!(orders(&engine).await.lines().count(), 5);
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(LoadFormat::from_path("a/b.JSONL"), Some(LoadFormat::Json));
+        assert_eq!(LoadFormat::from_path("a.csv"), Some(LoadFormat::Csv));
+        assert_eq!(LoadFormat::from_path("csv"), None);
+        assert_eq!("ndjson".parse::<LoadFormat>().unwrap(), LoadFormat::Json);
+        assert!("xml".parse::<LoadFormat>().is_err());
+    }
+}
@@ -0,0 +1,366 @@
+//! Answering queries from materialized views.
+//!
+//! A table holding the result of a query, such as one written by `CREATE TABLE ... AS`
+//! or [`QueryEngine::load_result`], is registered as a materialized view of it with
+//! [`QueryEngine::register_materialized_view`]. [`MaterializedViewRule`] then reads the
+//! table in place of the parts of queries it answers:
+//!
+//! - a query, or subquery, planned as the view's query is, reads the table as it is;
+//! - a query selecting from the same tables (or joins, subqueries, ...) as a view
+//!   that selects columns, optionally filtered, reads the table when the view's
+//!   filters are among its own and it reads no column the view does not select. Its
+//!   other filters and its projection are applied to the table:
+//!
+//! ```sql
+//! -- materialized view `big_orders`
+//! SELECT id, region, amount FROM orders WHERE amount > 1000
+//! -- answered from `big_orders` as `SELECT id FROM big_orders WHERE region = 'EU'`
+//! SELECT id FROM orders WHERE amount > 1000 AND region = 'EU'
+//! ```
+//!
+//! Queries whose tables are filtered by [policies](crate::policy) of the caller do not
+//! match, as the view holds rows the caller may not read. Statements refreshing a view,
+//! an `INSERT INTO` or `CREATE OR REPLACE TABLE ... AS` writing its table, read no view:
+//! their query runs against its sources, and the view reads the replacing table. Tables
+//! are otherwise read as they are, however stale: setting `igloo.use_materialized_views` to `false` (see
+//! [`session`](crate::session)), or running a query through
+//! [`QueryEngine::fresh`], runs queries against their sources.
+//!
+//! [`QueryEngine::load_result`]: crate::QueryEngine::load_result
+//! [`QueryEngine::register_materialized_view`]: crate::QueryEngine::register_materialized_view
+//! [`QueryEngine::fresh`]: crate::QueryEngine::fresh
+
+use crate::catalog_store::full_name;
+use crate::options::IglooOptions;
+use datafusion::arrow::datatypes::Fields;
+use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion};
+use datafusion::common::Column;
+use datafusion::config::{CatalogOptions, ConfigOptions};
+use datafusion::datasource::{provider_as_source, TableProvider};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::utils::{conjunction, split_conjunction};
+use datafusion::logical_expr::{DdlStatement, Expr, LogicalPlan, LogicalPlanBuilder};
+use datafusion::optimizer::AnalyzerRule;
+use datafusion::sql::TableReference;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// A table holding the result of a query.
+#[derive(Debug, Clone)]
+struct MaterializedView {
+    name: TableReference,
+    table: Arc<dyn TableProvider>,
+    /// The query, planned but not analyzed.
+    plan: LogicalPlan,
+}
+
+/// The materialized views of an engine, by the name of their table.
+#[derive(Debug, Default)]
+pub(crate) struct MaterializedViews {
+    views: RwLock<Vec<MaterializedView>>,
+}
+
+impl MaterializedViews {
+    /// Answer queries matching `plan` from `table`, registered as `name`, in place of
+    /// any view of that name. `plan` is planned from SQL but not analyzed.
+    pub(crate) fn register(
+        &self,
+        name: TableReference,
+        table: Arc<dyn TableProvider>,
+        plan: LogicalPlan,
+    ) -> DataFusionResult<()> {
+        let types =
+            |fields: &Fields| fields.iter().map(|f| f.data_type().clone()).collect::<Vec<_>>();
+        let (columns, query) = (types(table.schema().fields()), types(plan.schema().fields()));
+        if columns != query {
+            return Err(DataFusionError::Plan(format!(
+                "table {name} does not hold the result of the view's query: its columns are \
+                 of types {columns:?}, the query's of {query:?}"
+            )));
+        }
+        let mut views = self.views.write().expect("materialized view lock poisoned");
+        views.retain(|view| view.name != name);
+        views.push(MaterializedView { name, table, plan });
+        Ok(())
+    }
+
+    /// Stop answering queries from the view `name`, returning whether it was registered.
+    pub(crate) fn deregister(&self, name: &TableReference) -> bool {
+        let mut views = self.views.write().expect("materialized view lock poisoned");
+        let count = views.len();
+        views.retain(|view| &view.name != name);
+        views.len() < count
+    }
+
+    /// The view whose table `plan` writes to, if it is an `INSERT INTO` or
+    /// `CREATE TABLE ... AS` refreshing one.
+    pub(crate) fn written(
+        &self,
+        plan: &LogicalPlan,
+        options: &CatalogOptions,
+    ) -> Option<TableReference> {
+        let target = match plan {
+            LogicalPlan::Dml(dml) => &dml.table_name,
+            LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(create)) => &create.name,
+            _ => return None,
+        };
+        let target = full_name(target.clone(), options);
+        let views = self.views.read().expect("materialized view lock poisoned");
+        views
+            .iter()
+            .find(|view| full_name(view.name.clone(), options) == target)
+            .map(|view| view.name.clone())
+    }
+
+    /// Answer the view `name` from `table`, which replaced its table, or stop answering
+    /// from it if `table` cannot hold the view's result.
+    pub(crate) fn refresh(&self, name: &TableReference, table: Arc<dyn TableProvider>) {
+        let views = self.views.read().expect("materialized view lock poisoned");
+        let Some(plan) = views.iter().find(|view| &view.name == name).map(|view| view.plan.clone())
+        else {
+            return;
+        };
+        drop(views);
+        if self.register(name.clone(), table, plan).is_err() {
+            self.deregister(name);
+        }
+    }
+
+    /// Names of the views, in the order they were registered.
+    pub(crate) fn names(&self) -> Vec<TableReference> {
+        let views = self.views.read().expect("materialized view lock poisoned");
+        views.iter().map(|view| view.name.clone()).collect()
+    }
+}
+
+/// Reads materialized views in place of the plans they answer, see the
+/// [module](self) documentation.
+#[derive(Debug)]
+pub struct MaterializedViewRule {
+    views: Arc<MaterializedViews>,
+}
+
+impl MaterializedViewRule {
+    pub const NAME: &'static str = "materialized_views";
+
+    pub(crate) fn new(views: Arc<MaterializedViews>) -> Self {
+        MaterializedViewRule { views }
+    }
+}
+
+impl AnalyzerRule for MaterializedViewRule {
+    fn analyze(&self, plan: LogicalPlan, config: &ConfigOptions) -> DataFusionResult<LogicalPlan> {
+        let options = config.extensions.get::<IglooOptions>().cloned().unwrap_or_default();
+        // A refresh reads the view's sources, never the table it writes.
+        if self.views.written(&plan, &config.catalog).is_some() {
+            return Ok(plan);
+        }
+        let views = self.views.views.read().expect("materialized view lock poisoned");
+        if !options.use_materialized_views || views.is_empty() {
+            return Ok(plan);
+        }
+        let plan = plan.transform_down_with_subqueries(|node| {
+            for view in views.iter() {
+                if let Some(answered) = answer(&node, view)? {
+                    return Ok(Transformed::new(answered, true, TreeNodeRecursion::Jump));
+                }
+            }
+            Ok(Transformed::no(node))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+}
+
+/// `node` reading `view` in place of its inputs, if the view answers it.
+fn answer(node: &LogicalPlan, view: &MaterializedView) -> DataFusionResult<Option<LogicalPlan>> {
+    let scan = || {
+        let table = provider_as_source(Arc::clone(&view.table));
+        LogicalPlanBuilder::scan(view.name.clone(), table, None)?.build()
+    };
+    let answered = if *node == view.plan {
+        scan()?
+    } else {
+        let (Some(query), Some(selection)) = (Selection::of(node), Selection::of(&view.plan))
+        else {
+            return Ok(None);
+        };
+        if query.input != selection.input
+            || !selection.filters.iter().all(|filter| query.filters.contains(filter))
+        {
+            return Ok(None);
+        }
+        let scan = scan()?;
+        // The table's column for each column of the view's input it selects.
+        let mut columns = HashMap::new();
+        for (expr, (qualifier, field)) in selection.exprs.iter().zip(scan.schema().iter()) {
+            let Expr::Column(column) = expr.clone().unalias() else {
+                return Ok(None);
+            };
+            columns.insert(column, Expr::Column(Column::from((qualifier, field))));
+        }
+        let from_table = |expr: &Expr| {
+            let mut unselected = false;
+            let expr = expr.clone().transform_up(|expr| match expr {
+                Expr::Column(column) => match columns.get(&column) {
+                    Some(column) => Ok(Transformed::yes(column.clone())),
+                    None => {
+                        unselected = true;
+                        Ok(Transformed::no(Expr::Column(column)))
+                    }
+                },
+                expr => Ok(Transformed::no(expr)),
+            });
+            expr.map(|expr| (!unselected).then_some(expr.data))
+        };
+        let filters =
+            query.filters.iter().copied().filter(|filter| !selection.filters.contains(filter));
+        let Some(filters) =
+            filters.map(from_table).collect::<DataFusionResult<Option<Vec<_>>>>()?
+        else {
+            return Ok(None);
+        };
+        let Some(exprs) =
+            query.exprs.iter().map(from_table).collect::<DataFusionResult<Option<Vec<_>>>>()?
+        else {
+            return Ok(None);
+        };
+        let mut builder = LogicalPlanBuilder::from(scan);
+        if let Some(predicate) = conjunction(filters) {
+            builder = builder.filter(predicate)?;
+        }
+        builder.project(exprs)?.build()?
+    };
+    // Under the names (and qualifiers) the plans above `node` refer to.
+    let exprs: Vec<_> = answered
+        .schema()
+        .iter()
+        .zip(node.schema().iter())
+        .map(|((qualifier, field), (name_qualifier, name))| {
+            Expr::Column(Column::from((qualifier, field)))
+                .alias_qualified(name_qualifier.cloned(), name.name())
+        })
+        .collect();
+    Ok(Some(LogicalPlanBuilder::from(answered).project(exprs)?.build()?))
+}
+
+/// A projection of the rows of a plan matching some filters.
+struct Selection<'a> {
+    exprs: &'a [Expr],
+    /// The conjuncts of the filter, if any.
+    filters: Vec<&'a Expr>,
+    input: &'a LogicalPlan,
+}
+
+impl<'a> Selection<'a> {
+    fn of(plan: &'a LogicalPlan) -> Option<Self> {
+        let LogicalPlan::Projection(projection) = plan else {
+            return None;
+        };
+        Some(match projection.input.as_ref() {
+            LogicalPlan::Filter(filter) => Selection {
+                exprs: &projection.expr,
+                filters: split_conjunction(&filter.predicate),
+                input: &filter.input,
+            },
+            input => Selection { exprs: &projection.expr, filters: vec![], input },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::session::SessionVars;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::display::array_value_to_string;
+    use datafusion::error::Result as DataFusionResult;
+
+    const VIEW: &str = "SELECT id, region, amount FROM orders WHERE amount > 1000";
+
+    /// The values of the first column of `sql`'s result, and whether it read a view.
+    async fn ids(engine: &QueryEngine, sql: &str) -> DataFusionResult<(String, bool)> {
+        let result = engine.query(sql).await?;
+        let mut values = vec![];
+        for batch in &result.batches {
+            for row in 0..batch.num_rows() {
+                values.push(array_value_to_string(batch.column(0), row)?);
+            }
+        }
+        let routed = result.diagnostics.iter().any(|d| d.code == "materialized_view_read");
+        Ok((values.join(" "), routed))
+    }
+
+    #[tokio::test]
+    async fn test_queries_read_matching_views() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE orders (id INT, region VARCHAR, amount BIGINT) AS VALUES \
+                 (1, 'EU', 500), (2, 'EU', 1500), (3, 'US', 2500), (4, 'EU', 3500)",
+            )
+            .await?;
+        engine.query(&format!("CREATE TABLE big_orders AS {VIEW}")).await?;
+        engine.register_materialized_view("big_orders", VIEW).await?;
+        // Not in the view until it is refreshed, so queries it answers do not see it.
+        engine.query("INSERT INTO orders VALUES (5, 'EU', 4500)").await?;
+
+        let sql = "SELECT id FROM orders WHERE amount > 1000 AND region = 'EU' ORDER BY id";
+        assert_eq!(ids(&engine, sql).await?, ("2 4".to_string(), true));
+        let sql = format!("SELECT count(*) FROM ({VIEW}) AS big");
+        assert_eq!(ids(&engine, &sql).await?, ("3".to_string(), true));
+        let sql = "SELECT amount, id FROM orders WHERE id > 2 AND amount > 1000 ORDER BY id";
+        assert_eq!(ids(&engine, sql).await?, ("2500 3500".to_string(), true));
+        // Rows or columns the view does not hold.
+        let sql = "SELECT id FROM orders WHERE amount > 100 AND region = 'EU' ORDER BY id";
+        assert_eq!(ids(&engine, sql).await?, ("1 2 4 5".to_string(), false));
+        let sql = "SELECT id FROM orders WHERE amount > 2000 ORDER BY id";
+        assert_eq!(ids(&engine, sql).await?, ("3 4 5".to_string(), false));
+
+        let sql = "SELECT id FROM orders WHERE amount > 1000 AND region = 'EU' ORDER BY id";
+        assert_eq!(ids(&engine.fresh(), sql).await?, ("2 4 5".to_string(), false));
+        let mut session = SessionVars::new();
+        session.set("igloo.use_materialized_views", "false")?;
+        assert_eq!(ids(&engine.with_session(&session), sql).await?, ("2 4 5".to_string(), false));
+        let tenant = engine.add_tenant(crate::tenant::Tenant::new("acme"))?;
+        tenant.query("CREATE TABLE orders (id INT, region VARCHAR, amount BIGINT) AS VALUES (9, 'EU', 9000)").await?;
+        assert_eq!(ids(&tenant, sql).await?, ("9".to_string(), false));
+
+        assert!(engine.deregister_materialized_view("big_orders"));
+        assert_eq!(ids(&engine, sql).await?, ("2 4 5".to_string(), false));
+        let error = engine.register_materialized_view("big_orders", "SELECT id FROM orders").await;
+        assert!(error.unwrap_err().to_string().contains("does not hold the result"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_refreshes_read_the_sources() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE orders (id INT, region VARCHAR, amount BIGINT) AS VALUES \
+                 (1, 'EU', 500), (2, 'EU', 1500)",
+            )
+            .await?;
+        engine.query(&format!("CREATE TABLE big_orders AS {VIEW}")).await?;
+        engine.register_materialized_view("big_orders", VIEW).await?;
+        let sql = "SELECT id FROM orders WHERE amount > 1000 ORDER BY id";
+
+        // Answered from `big_orders` itself, the insert would copy its own rows.
+        engine.query("INSERT INTO orders VALUES (3, 'US', 2500)").await?;
+        engine.query(&format!("INSERT INTO big_orders {VIEW} AND id > 2")).await?;
+        assert_eq!(ids(&engine, sql).await?, ("2 3".to_string(), true));
+
+        engine.query("INSERT INTO orders VALUES (4, 'EU', 3500)").await?;
+        engine.query(&format!("CREATE OR REPLACE TABLE big_orders AS {VIEW}")).await?;
+        assert_eq!(ids(&engine, sql).await?, ("2 3 4".to_string(), true));
+
+        // Replaced by a table not holding the view's result.
+        engine.query("CREATE OR REPLACE TABLE big_orders AS SELECT id FROM orders").await?;
+        assert_eq!(ids(&engine, sql).await?, ("2 3 4".to_string(), false));
+        assert!(!engine.deregister_materialized_view("big_orders"));
+        Ok(())
+    }
+}
@@ -0,0 +1,229 @@
+//! Memory accounting.
+//!
+//! [`QueryEngine::memory_report`](crate::QueryEngine::memory_report) attributes the
+//! memory of the process to what holds it, so memory pressure can be traced to a
+//! query or a cache:
+//!
+//! - the process: its resident set and heap (see [`igloo_common::memory`]);
+//! - pools: the bytes reserved from the engine's memory pool and each tenant's, which
+//!   is what operators hold for hash tables, sorts and buffered batches;
+//! - queries: an engine returned by
+//!   [`QueryEngine::with_memory_tracking`](crate::QueryEngine::with_memory_tracking)
+//!   counts the reservations of its queries under a query id, with their peak, for
+//!   as long as it or a plan it created is alive;
+//! - caches: the ingest deduplication indexes (see [`ingest`](crate::ingest)) and
+//!   caches registered with
+//!   [`QueryEngine::register_cache`](crate::QueryEngine::register_cache).
+
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::memory_pool::{
+    MemoryConsumer, MemoryLimit, MemoryPool, MemoryReservation,
+};
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::execution::session_state::{SessionState, SessionStateBuilder};
+use igloo_common::memory::ProcessMemory;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryReport {
+    pub process: ProcessMemory,
+    pub pools: Vec<PoolMemory>,
+    /// Tracked queries still running, most memory first.
+    pub queries: Vec<QueryMemory>,
+    pub caches: Vec<CacheMemory>,
+}
+
+/// A memory pool: the engine's (`engine`) or a tenant's (by its name).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PoolMemory {
+    pub name: String,
+    pub reserved_bytes: usize,
+    /// `None` if the pool is unbounded.
+    pub limit_bytes: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QueryMemory {
+    pub query_id: String,
+    pub reserved_bytes: usize,
+    pub peak_bytes: usize,
+    pub elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CacheMemory {
+    pub name: String,
+    /// Approximate bytes held.
+    pub bytes: usize,
+}
+
+type CacheSize = Arc<dyn Fn() -> usize + Send + Sync>;
+
+/// The tracked queries and registered caches of an engine and its tenants.
+#[derive(Default)]
+pub(crate) struct MemoryTracker {
+    queries: Mutex<Vec<Weak<QueryUsage>>>,
+    caches: RwLock<BTreeMap<String, CacheSize>>,
+}
+
+impl fmt::Debug for MemoryTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let caches = self.caches.read().expect("cache registry lock poisoned");
+        f.debug_struct("MemoryTracker").field("caches", &caches.keys()).finish_non_exhaustive()
+    }
+}
+
+impl MemoryTracker {
+    /// `state` with the reservations of its queries counted under `query_id`.
+    pub(crate) fn track(&self, state: SessionState, query_id: &str) -> SessionState {
+        let usage = Arc::new(QueryUsage {
+            query_id: query_id.to_string(),
+            started: Instant::now(),
+            reserved: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        });
+        let mut queries = self.queries.lock().expect("memory tracker lock poisoned");
+        queries.retain(|query| query.strong_count() > 0);
+        queries.push(Arc::downgrade(&usage));
+        drop(queries);
+
+        let runtime = state.runtime_env();
+        let pool = TrackedMemoryPool { pool: Arc::clone(&runtime.memory_pool), usage };
+        let runtime = Arc::new(RuntimeEnv {
+            memory_pool: Arc::new(pool),
+            disk_manager: Arc::clone(&runtime.disk_manager),
+            cache_manager: Arc::clone(&runtime.cache_manager),
+            object_store_registry: Arc::clone(&runtime.object_store_registry),
+        });
+        SessionStateBuilder::new_from_existing(state).with_runtime_env(runtime).build()
+    }
+
+    pub(crate) fn register_cache(&self, name: String, size: CacheSize) {
+        self.caches.write().expect("cache registry lock poisoned").insert(name, size);
+    }
+
+    pub(crate) fn queries(&self) -> Vec<QueryMemory> {
+        let mut queries: Vec<_> = self
+            .queries
+            .lock()
+            .expect("memory tracker lock poisoned")
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|usage| QueryMemory {
+                query_id: usage.query_id.clone(),
+                reserved_bytes: usage.reserved.load(Ordering::Relaxed),
+                peak_bytes: usage.peak.load(Ordering::Relaxed),
+                elapsed_ms: usage.started.elapsed().as_millis() as u64,
+            })
+            .collect();
+        queries.sort_by_key(|query| std::cmp::Reverse(query.reserved_bytes));
+        queries
+    }
+
+    pub(crate) fn caches(&self) -> Vec<CacheMemory> {
+        let caches = self.caches.read().expect("cache registry lock poisoned");
+        caches
+            .iter()
+            .map(|(name, size)| CacheMemory { name: name.clone(), bytes: size() })
+            .collect()
+    }
+}
+
+pub(crate) fn pool_memory(name: &str, pool: &dyn MemoryPool) -> PoolMemory {
+    let limit_bytes = match pool.memory_limit() {
+        MemoryLimit::Finite(limit) => Some(limit),
+        MemoryLimit::Infinite | MemoryLimit::Unknown => None,
+    };
+    PoolMemory { name: name.to_string(), reserved_bytes: pool.reserved(), limit_bytes }
+}
+
+#[derive(Debug)]
+struct QueryUsage {
+    query_id: String,
+    started: Instant,
+    reserved: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl QueryUsage {
+    fn grow(&self, additional: usize) {
+        let reserved = self.reserved.fetch_add(additional, Ordering::Relaxed) + additional;
+        self.peak.fetch_max(reserved, Ordering::Relaxed);
+    }
+}
+
+/// Memory pool counting a query's reservations, taken from the engine's pool.
+#[derive(Debug)]
+struct TrackedMemoryPool {
+    pool: Arc<dyn MemoryPool>,
+    usage: Arc<QueryUsage>,
+}
+
+impl MemoryPool for TrackedMemoryPool {
+    fn register(&self, consumer: &MemoryConsumer) {
+        self.pool.register(consumer)
+    }
+
+    fn unregister(&self, consumer: &MemoryConsumer) {
+        self.pool.unregister(consumer)
+    }
+
+    fn grow(&self, reservation: &MemoryReservation, additional: usize) {
+        self.pool.grow(reservation, additional);
+        self.usage.grow(additional);
+    }
+
+    fn shrink(&self, reservation: &MemoryReservation, shrink: usize) {
+        self.pool.shrink(reservation, shrink);
+        self.usage.reserved.fetch_sub(shrink, Ordering::Relaxed);
+    }
+
+    fn try_grow(&self, reservation: &MemoryReservation, additional: usize) -> DataFusionResult<()> {
+        self.pool.try_grow(reservation, additional)?;
+        self.usage.grow(additional);
+        Ok(())
+    }
+
+    fn reserved(&self) -> usize {
+        self.pool.reserved()
+    }
+
+    fn memory_limit(&self) -> MemoryLimit {
+        self.pool.memory_limit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::QueryEngine;
+    use datafusion::error::Result as DataFusionResult;
+    use datafusion::physical_plan::collect;
+
+    #[tokio::test]
+    async fn test_reservations_are_attributed_to_the_query() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.register_cache("results", || 42);
+        let tracked = engine.with_memory_tracking("q1");
+        let df = tracked.sql("SELECT value FROM range(100000) ORDER BY value DESC").await?;
+        let task_ctx = df.task_ctx();
+        let plan = df.create_physical_plan().await?;
+        collect(plan.clone(), task_ctx.into()).await?;
+
+        let report = engine.memory_report();
+        assert_eq!(report.queries.len(), 1);
+        assert_eq!(report.queries[0].query_id, "q1");
+        assert!(report.queries[0].peak_bytes > 0);
+        assert_eq!(report.pools[0].name, "engine");
+        let cache = report.caches.iter().find(|cache| cache.name == "results").unwrap();
+        assert_eq!(cache.bytes, 42);
+
+        drop((tracked, plan));
+        assert!(engine.memory_report().queries.is_empty());
+        Ok(())
+    }
+}
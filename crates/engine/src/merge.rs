@@ -0,0 +1,460 @@
+//! `MERGE INTO` Iceberg tables.
+//!
+//! ```sql
+//! MERGE INTO iceberg.sales.orders t USING order_updates s ON t.id = s.id
+//!     WHEN MATCHED AND s.cancelled THEN DELETE
+//!     WHEN MATCHED THEN UPDATE SET amount = s.amount
+//!     WHEN NOT MATCHED THEN INSERT (id, amount) VALUES (s.id, s.amount);
+//! ```
+//!
+//! The target is keyed by the columns its `ON` condition equates with source
+//! expressions, and the condition may be nothing else. Each source row takes the first
+//! `WHEN` clause it satisfies, matched or not; rows satisfying none change nothing. The
+//! rows to insert, update and delete are computed with a query joining the source to
+//! the target, and applied as one snapshot by
+//! [`IcebergTable::apply_changes`](igloo_connector_iceberg::IcebergTable::apply_changes),
+//! copy on write. A target row matched by more than one source row is an error, as is
+//! updating a key column. `WHEN NOT MATCHED BY SOURCE` is not supported.
+
+use datafusion::arrow::compute::{cast, concat_batches};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::functions_aggregate::expr_fn::count;
+use datafusion::prelude::{col, lit};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::planner::{object_name_to_table_reference, IdentNormalizer};
+use datafusion::sql::sqlparser::ast::{
+    self, AssignmentTarget, BinaryOperator, Expr, Ident, MergeClauseKind, MergeInsertKind,
+    ObjectName, Statement, TableFactor,
+};
+use datafusion::sql::TableReference;
+use igloo_connector_iceberg::write::OP_COLUMN;
+use std::sync::Arc;
+
+/// Column marking the rows of the target that a source row matched.
+const MATCHED_COLUMN: &str = "__merge_matched";
+
+/// A `MERGE INTO` statement, its expressions as SQL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeInto {
+    pub target: TableReference,
+    /// What the statement calls the target: its alias, or else its table name.
+    pub alias: String,
+    /// The source table or subquery, with its alias.
+    pub source: String,
+    pub on: String,
+    /// The target columns `on` equates with source expressions.
+    pub keys: Vec<String>,
+    pub clauses: Vec<MergeClause>,
+}
+
+/// A `WHEN [NOT] MATCHED [AND predicate] THEN action` clause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeClause {
+    pub matched: bool,
+    pub predicate: Option<String>,
+    pub action: MergeAction,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeAction {
+    /// The columns set, with their new values.
+    Update(Vec<(String, String)>),
+    Delete,
+    /// The values of the row inserted, of `columns`, or of all the target's columns in
+    /// order if none are listed.
+    Insert {
+        columns: Vec<String>,
+        values: Vec<String>,
+    },
+}
+
+/// If `sql` is a single `MERGE` statement, what it merges. SQL that does not parse is
+/// `None`; planning it reports the error.
+pub fn parse_merge_sql(sql: &str) -> DataFusionResult<Option<MergeInto>> {
+    let statements = match DFParser::parse_sql(sql) {
+        Ok(statements) if statements.len() == 1 => statements,
+        _ => return Ok(None),
+    };
+    let DFStatement::Statement(statement) = &statements[0] else {
+        return Ok(None);
+    };
+    let Statement::Merge { table, source, on, clauses, .. } = statement.as_ref() else {
+        return Ok(None);
+    };
+    let TableFactor::Table { name, alias, args: None, version: None, .. } = table else {
+        return Err(DataFusionError::NotImplemented(format!(
+            "MERGE into {table} is not supported, only into tables"
+        )));
+    };
+    if alias.as_ref().is_some_and(|alias| !alias.columns.is_empty()) {
+        return Err(DataFusionError::NotImplemented(
+            "MERGE target aliases with columns are not supported".to_string(),
+        ));
+    }
+    let normalizer = IdentNormalizer::new(true);
+    let target = object_name_to_table_reference(name.clone(), true)?;
+    let alias = match alias {
+        Some(alias) => normalizer.normalize(alias.name.clone()),
+        None => target.table().to_string(),
+    };
+    let mut keys = vec![];
+    collect_keys(on, &alias, &mut keys)?;
+    let mut merge_clauses = vec![];
+    for clause in clauses {
+        let matched = match clause.clause_kind {
+            MergeClauseKind::Matched => true,
+            MergeClauseKind::NotMatched | MergeClauseKind::NotMatchedByTarget => false,
+            MergeClauseKind::NotMatchedBySource => {
+                return Err(DataFusionError::NotImplemented(
+                    "MERGE ... WHEN NOT MATCHED BY SOURCE is not supported".to_string(),
+                ))
+            }
+        };
+        let action = match (&clause.action, matched) {
+            (ast::MergeAction::Update { assignments }, true) => {
+                let mut set = vec![];
+                for assignment in assignments {
+                    let AssignmentTarget::ColumnName(column) = &assignment.target else {
+                        return Err(DataFusionError::NotImplemented(
+                            "MERGE ... UPDATE SET of tuples is not supported".to_string(),
+                        ));
+                    };
+                    let column = column_name(column)?;
+                    if keys.contains(&column) {
+                        return Err(DataFusionError::Plan(format!(
+                            "MERGE cannot update the key column {column}"
+                        )));
+                    }
+                    set.push((column, assignment.value.to_string()));
+                }
+                MergeAction::Update(set)
+            }
+            (ast::MergeAction::Delete, true) => MergeAction::Delete,
+            (ast::MergeAction::Insert(insert), false) => {
+                let MergeInsertKind::Values(values) = &insert.kind else {
+                    return Err(DataFusionError::NotImplemented(
+                        "MERGE ... INSERT ROW is not supported".to_string(),
+                    ));
+                };
+                let [row] = &values.rows[..] else {
+                    return Err(DataFusionError::Plan(
+                        "MERGE ... INSERT takes a single row of values".to_string(),
+                    ));
+                };
+                let columns: Vec<_> =
+                    insert.columns.iter().map(|c| normalizer.normalize(c.clone())).collect();
+                if !columns.is_empty() && columns.len() != row.len() {
+                    return Err(DataFusionError::Plan(format!(
+                        "MERGE ... INSERT of {} columns has {} values",
+                        columns.len(),
+                        row.len()
+                    )));
+                }
+                MergeAction::Insert { columns, values: row.iter().map(Expr::to_string).collect() }
+            }
+            (action, _) => {
+                return Err(DataFusionError::Plan(format!(
+                    "MERGE ... WHEN {} cannot {action}",
+                    clause.clause_kind
+                )))
+            }
+        };
+        let predicate = clause.predicate.as_ref().map(Expr::to_string);
+        merge_clauses.push(MergeClause { matched, predicate, action });
+    }
+    Ok(Some(MergeInto {
+        target,
+        alias,
+        source: source.to_string(),
+        on: on.to_string(),
+        keys,
+        clauses: merge_clauses,
+    }))
+}
+
+/// Add the target columns of the equalities `on` is a conjunction of to `keys`.
+fn collect_keys(on: &Expr, alias: &str, keys: &mut Vec<String>) -> DataFusionResult<()> {
+    match on {
+        Expr::Nested(on) => collect_keys(on, alias, keys),
+        Expr::BinaryOp { left, op: BinaryOperator::And, right } => {
+            collect_keys(left, alias, keys)?;
+            collect_keys(right, alias, keys)
+        }
+        Expr::BinaryOp { left, op: BinaryOperator::Eq, right } => {
+            match (target_column(left, alias), target_column(right, alias)) {
+                (Some(key), None) | (None, Some(key)) => {
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                    Ok(())
+                }
+                _ => Err(not_a_key(on)),
+            }
+        }
+        _ => Err(not_a_key(on)),
+    }
+}
+
+/// The column of the target `expr` is, if it is one qualified with `alias`.
+fn target_column(expr: &Expr, alias: &str) -> Option<String> {
+    let normalizer = IdentNormalizer::new(true);
+    match expr {
+        Expr::Nested(expr) => target_column(expr, alias),
+        Expr::CompoundIdentifier(parts) => match &parts[..] {
+            [qualifier, column] if normalizer.normalize(qualifier.clone()) == alias => {
+                Some(normalizer.normalize(column.clone()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn not_a_key(on: &Expr) -> DataFusionError {
+    DataFusionError::Plan(format!(
+        "MERGE ... ON must equate columns of the target with source expressions, as in \
+         t.id = s.id, not {on}"
+    ))
+}
+
+/// The column an `UPDATE SET` assigns, qualified or not.
+fn column_name(name: &ObjectName) -> DataFusionResult<String> {
+    let reference = object_name_to_table_reference(name.clone(), true)?;
+    Ok(reference.table().to_string())
+}
+
+/// The rows `merge` changes in a target of `schema`, in one batch: an
+/// [`OP_COLUMN`] of `c`, `u` or `d` and the target's columns, as
+/// [`IcebergTable::apply_changes`](igloo_connector_iceberg::IcebergTable::apply_changes)
+/// takes them.
+pub async fn changes(
+    ctx: &SessionContext,
+    merge: &MergeInto,
+    schema: &SchemaRef,
+) -> DataFusionResult<RecordBatch> {
+    let sql = changes_sql(merge, schema)?;
+    let df = ctx.sql(&sql).await?;
+    let df_schema: SchemaRef = Arc::new(df.schema().as_arrow().clone());
+    let batch = concat_batches(&df_schema, &df.collect().await?)?;
+    let keys = merge.keys.iter().map(|key| col(quoted(key))).collect();
+    let matched_twice = ctx
+        .read_batch(batch.clone())?
+        .filter(col(MATCHED_COLUMN).is_not_null())?
+        .aggregate(keys, vec![count(lit(1)).alias("matches")])?
+        .filter(col("matches").gt(lit(1)))?
+        .count()
+        .await?;
+    if matched_twice > 0 {
+        return Err(DataFusionError::Execution(format!(
+            "MERGE matched {matched_twice} rows of {} with more than one source row",
+            merge.target
+        )));
+    }
+    // String literals may be planned as views, and changes have an op of strings.
+    let mut fields = vec![Arc::new(Field::new(OP_COLUMN, DataType::Utf8, true))];
+    let mut columns = vec![cast(batch.column(0), &DataType::Utf8)?];
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()).skip(1) {
+        if field.name() != MATCHED_COLUMN {
+            fields.push(Arc::clone(field));
+            columns.push(Arc::clone(column));
+        }
+    }
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?)
+}
+
+/// The query of the changes of `merge`, see [`changes`].
+fn changes_sql(merge: &MergeInto, schema: &SchemaRef) -> DataFusionResult<String> {
+    let columns: Vec<&String> = schema.fields().iter().map(|field| field.name()).collect();
+    let check = |column: &String| match columns.contains(&column) {
+        true => Ok(()),
+        false => {
+            Err(DataFusionError::Plan(format!("column {column} not found in {}", merge.target)))
+        }
+    };
+    for key in &merge.keys {
+        check(key)?;
+    }
+    let alias = quoted(&merge.alias);
+    let matched = format!("{alias}.{MATCHED_COLUMN} IS NOT NULL");
+    let mut conditions = vec![];
+    for clause in &merge.clauses {
+        let mut condition = match clause.matched {
+            true => matched.clone(),
+            false => format!("NOT ({matched})"),
+        };
+        if let Some(predicate) = &clause.predicate {
+            condition = format!("{condition} AND ({predicate})");
+        }
+        conditions.push(condition);
+        match &clause.action {
+            MergeAction::Update(set) => set.iter().try_for_each(|(column, _)| check(column))?,
+            MergeAction::Insert { columns: inserted, values } => {
+                inserted.iter().try_for_each(check)?;
+                if inserted.is_empty() && values.len() != columns.len() {
+                    return Err(DataFusionError::Plan(format!(
+                        "MERGE ... INSERT has {} values for the {} columns of {}",
+                        values.len(),
+                        columns.len(),
+                        merge.target
+                    )));
+                }
+            }
+            MergeAction::Delete => {}
+        }
+    }
+    // One CASE per column, taking the value of the clause a row satisfies first.
+    let case = |value: &dyn Fn(&MergeClause) -> String| {
+        let branches = conditions
+            .iter()
+            .zip(&merge.clauses)
+            .map(|(condition, clause)| format!(" WHEN {condition} THEN {}", value(clause)));
+        format!("CASE{} END", branches.collect::<String>())
+    };
+    let op = case(&|clause| match clause.action {
+        MergeAction::Update(_) => "'u'".to_string(),
+        MergeAction::Delete => "'d'".to_string(),
+        MergeAction::Insert { .. } => "'c'".to_string(),
+    });
+    let mut projection = vec![format!("{op} AS {}", quoted(OP_COLUMN))];
+    for (i, column) in columns.iter().enumerate() {
+        let target = format!("{alias}.{}", quoted(column));
+        let value = case(&|clause| match &clause.action {
+            MergeAction::Update(set) => match set.iter().find(|(name, _)| name == *column) {
+                Some((_, value)) => value.clone(),
+                None => target.clone(),
+            },
+            MergeAction::Delete => target.clone(),
+            MergeAction::Insert { columns: inserted, values } if inserted.is_empty() => {
+                values[i].clone()
+            }
+            MergeAction::Insert { columns: inserted, values } => {
+                match inserted.iter().position(|name| name == *column) {
+                    Some(position) => values[position].clone(),
+                    None => "NULL".to_string(),
+                }
+            }
+        });
+        projection.push(format!("{value} AS {}", quoted(column)));
+    }
+    projection.push(format!("{alias}.{MATCHED_COLUMN}"));
+    Ok(format!(
+        "SELECT * FROM (SELECT {} FROM {} LEFT JOIN (SELECT *, TRUE AS {MATCHED_COLUMN} FROM {}) \
+         AS {alias} ON {}) WHERE {} IS NOT NULL",
+        projection.join(", "),
+        merge.source,
+        merge.target.to_quoted_string(),
+        merge.on,
+        quoted(OP_COLUMN)
+    ))
+}
+
+fn quoted(name: &str) -> String {
+    Ident::with_quote('"', name).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    const MERGE: &str = "MERGE INTO orders t USING updates s ON t.id = s.id \
+        WHEN MATCHED AND s.amount IS NULL THEN DELETE \
+        WHEN MATCHED THEN UPDATE SET amount = t.amount + s.amount \
+        WHEN NOT MATCHED THEN INSERT (id, amount) VALUES (s.id, s.amount)";
+
+    async fn context() -> SessionContext {
+        let ctx = SessionContext::new();
+        let sql = [
+            "CREATE TABLE orders (id BIGINT, amount DOUBLE, note VARCHAR)",
+            "INSERT INTO orders VALUES (1, 10.0, 'a'), (2, 20.0, 'b'), (3, 30.0, 'c')",
+            "CREATE TABLE updates (id BIGINT, amount DOUBLE)",
+            "INSERT INTO updates VALUES (1, 5.0), (2, NULL), (4, 40.0), (5, NULL)",
+        ];
+        for sql in sql {
+            ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        }
+        ctx
+    }
+
+    async fn orders_schema(ctx: &SessionContext) -> SchemaRef {
+        ctx.table_provider("orders").await.unwrap().schema()
+    }
+
+    #[test]
+    fn test_parse_merge_sql() {
+        let merge = parse_merge_sql(MERGE).unwrap().unwrap();
+        assert_eq!(merge.target, TableReference::bare("orders"));
+        assert_eq!((merge.alias.as_str(), merge.source.as_str()), ("t", "updates AS s"));
+        assert_eq!(merge.keys, ["id"]);
+        assert_eq!(merge.clauses.len(), 3);
+        assert_eq!(merge.clauses[0].action, MergeAction::Delete);
+        assert_eq!(
+            merge.clauses[1].action,
+            MergeAction::Update(vec![("amount".to_string(), "t.amount + s.amount".to_string())])
+        );
+        assert!(!merge.clauses[2].matched);
+
+        let sql = "MERGE INTO db.orders USING updates ON orders.id = updates.id \
+            AND orders.region = updates.region WHEN MATCHED THEN DELETE";
+        let merge = parse_merge_sql(sql).unwrap().unwrap();
+        assert_eq!(merge.alias, "orders");
+        assert_eq!(merge.keys, ["id", "region"]);
+        assert_eq!(parse_merge_sql("SELECT 1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_unsupported_merges_are_rejected() {
+        let merges = [
+            // Not only key equalities.
+            "MERGE INTO orders t USING updates s ON t.id > s.id WHEN MATCHED THEN DELETE",
+            "MERGE INTO orders t USING updates s ON t.id = s.id AND s.amount > 0 \
+                WHEN MATCHED THEN DELETE",
+            "MERGE INTO orders t USING updates s ON t.id = s.id \
+                WHEN NOT MATCHED BY SOURCE THEN DELETE",
+            "MERGE INTO orders t USING updates s ON t.id = s.id \
+                WHEN MATCHED THEN UPDATE SET id = s.id + 1",
+        ];
+        for sql in merges {
+            assert!(parse_merge_sql(sql).is_err(), "{sql}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_changes_of_a_merge() {
+        let ctx = context().await;
+        let merge = parse_merge_sql(MERGE).unwrap().unwrap();
+        let changes = changes(&ctx, &merge, &orders_schema(&ctx).await).await.unwrap();
+        let sorted = ctx.read_batch(changes).unwrap().sort_by(vec![col("id")]).unwrap();
+        let batches = sorted.collect().await.unwrap();
+        let expected = "\
++------+----+--------+------+
+| __op | id | amount | note |
++------+----+--------+------+
+| u    | 1  | 15.0   | a    |
+| d    | 2  | 20.0   | b    |
+| c    | 4  | 40.0   |      |
+| c    | 5  |        |      |
++------+----+--------+------+";
+        assert_eq!(pretty_format_batches(&batches).unwrap().to_string(), expected);
+        assert_eq!(batches[0].schema().field(0).data_type(), &DataType::Utf8);
+    }
+
+    #[tokio::test]
+    async fn test_target_rows_matched_twice_are_an_error() {
+        let ctx = context().await;
+        ctx.sql("INSERT INTO updates VALUES (1, 6.0)").await.unwrap().collect().await.unwrap();
+        let merge = parse_merge_sql(MERGE).unwrap().unwrap();
+        let error = changes(&ctx, &merge, &orders_schema(&ctx).await).await.unwrap_err();
+        assert!(error.to_string().contains("more than one source row"), "{error}");
+
+        // Unless a clause skips all but one of them.
+        let sql = "MERGE INTO orders t USING updates s ON t.id = s.id \
+            WHEN MATCHED AND s.amount = 6.0 THEN UPDATE SET amount = s.amount";
+        let merge = parse_merge_sql(sql).unwrap().unwrap();
+        let changes = changes(&ctx, &merge, &orders_schema(&ctx).await).await.unwrap();
+        assert_eq!(changes.num_rows(), 1);
+    }
+}
@@ -0,0 +1,284 @@
+//! Schemas, and the schemas tables are placed in.
+//!
+//! Schemas are created with `CREATE SCHEMA [IF NOT EXISTS] name` and dropped with
+//! `DROP SCHEMA [IF EXISTS] name [CASCADE]`, in the default catalog unless named as
+//! `catalog.schema`. `ALTER TABLE name RENAME TO schema.table` (or
+//! [`QueryEngine::rename_table`](crate::QueryEngine::rename_table)) moves a table or
+//! view to another schema of its catalog, renaming it or not. Any registered table can
+//! be moved, including the sources a deployment registers with
+//! [`QueryEngine::register_table`](crate::QueryEngine::register_table) when it starts.
+//!
+//! A moved table is placed rather than redefined: its source goes on registering it
+//! under its own name, and the engine puts it in its place whenever it is registered
+//! again. With a catalog store (see [`catalog_store`](crate::catalog_store)) schemas
+//! and placements are recorded alongside tables and views, so a deployment's layout
+//! survives restarts and reaches the other engines sharing the store. Dropping a table,
+//! or a schema with `CASCADE`, forgets the placements of what it drops.
+
+use crate::catalog_store::{full_name, EntryKind};
+use datafusion::catalog::MemorySchemaProvider;
+use datafusion::common::SchemaReference;
+use datafusion::config::CatalogOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::logical_expr::{DdlStatement, LogicalPlan};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::planner::object_name_to_table_reference;
+use datafusion::sql::sqlparser::ast::{AlterTableOperation, Statement};
+use datafusion::sql::TableReference;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+/// The table and its new name if `sql` is `ALTER TABLE name RENAME TO new_name`.
+pub fn parse_rename_sql(sql: &str) -> DataFusionResult<Option<(TableReference, TableReference)>> {
+    let statements = match DFParser::parse_sql(sql) {
+        Ok(statements) if statements.len() == 1 => statements,
+        _ => return Ok(None),
+    };
+    let DFStatement::Statement(statement) = &statements[0] else {
+        return Ok(None);
+    };
+    let Statement::AlterTable { name, if_exists, operations, .. } = statement.as_ref() else {
+        return Ok(None);
+    };
+    let [AlterTableOperation::RenameTable { table_name }] = &operations[..] else {
+        return Ok(None);
+    };
+    if *if_exists {
+        return Err(DataFusionError::NotImplemented(
+            "ALTER TABLE IF EXISTS is not supported".to_string(),
+        ));
+    }
+    let table = object_name_to_table_reference(name.clone(), true)?;
+    Ok(Some((table, object_name_to_table_reference(table_name.clone(), true)?)))
+}
+
+/// The name of schema `schema` of `catalog` in the catalog store, quoted where needed.
+pub(crate) fn schema_entry(catalog: &str, schema: &str) -> String {
+    // A partial table reference quotes and splits two-part names the same way.
+    TableReference::partial(catalog, schema).to_quoted_string()
+}
+
+/// The catalog and schema named by a [`schema_entry`].
+fn parse_schema_entry(name: &str) -> DataFusionResult<(String, String)> {
+    match TableReference::parse_str(name) {
+        TableReference::Partial { schema, table } => Ok((schema.to_string(), table.to_string())),
+        _ => Err(DataFusionError::Execution(format!("invalid schema entry '{name}'"))),
+    }
+}
+
+/// The catalog and schema `name` (`schema` or `catalog.schema`) refers to, split like
+/// DataFusion splits the names of `CREATE SCHEMA`.
+pub(crate) fn resolve_schema(name: &str, options: &CatalogOptions) -> (String, String) {
+    match name.split_once('.') {
+        Some((catalog, schema)) => (catalog.to_string(), schema.to_string()),
+        None => (options.default_catalog.clone(), name.to_string()),
+    }
+}
+
+fn resolve_schema_reference(name: &SchemaReference, options: &CatalogOptions) -> (String, String) {
+    match name {
+        SchemaReference::Bare { schema } => (options.default_catalog.clone(), schema.to_string()),
+        SchemaReference::Full { schema, catalog } => (catalog.to_string(), schema.to_string()),
+    }
+}
+
+/// The schema entry `plan` creates (`true`) or drops (`false`), if it is schema DDL
+/// that changes anything.
+pub(crate) fn schema_change(plan: &LogicalPlan, ctx: &SessionContext) -> Option<(String, bool)> {
+    let options = ctx.state().config().options().catalog.clone();
+    let exists = |catalog: &str, schema: &str| {
+        ctx.catalog(catalog).is_some_and(|catalog| catalog.schema(schema).is_some())
+    };
+    match plan {
+        LogicalPlan::Ddl(DdlStatement::CreateCatalogSchema(create)) => {
+            let (catalog, schema) = resolve_schema(&create.schema_name, &options);
+            // Creating an existing schema is a no-op, not a new entry.
+            (!(create.if_not_exists && exists(&catalog, &schema)))
+                .then(|| (schema_entry(&catalog, &schema), true))
+        }
+        LogicalPlan::Ddl(DdlStatement::DropCatalogSchema(drop)) => {
+            let (catalog, schema) = resolve_schema_reference(&drop.name, &options);
+            exists(&catalog, &schema).then(|| (schema_entry(&catalog, &schema), false))
+        }
+        _ => None,
+    }
+}
+
+/// Create or drop the schema of a [`schema_entry`] in `ctx`, replaying its change.
+pub(crate) fn apply_schema(ctx: &SessionContext, name: &str, exists: bool) -> DataFusionResult<()> {
+    let (catalog_name, schema) = parse_schema_entry(name)?;
+    let catalog = ctx
+        .catalog(&catalog_name)
+        .ok_or_else(|| DataFusionError::Plan(format!("catalog {catalog_name} not found")))?;
+    if !exists {
+        catalog.deregister_schema(&schema, true)?;
+    } else if catalog.schema(&schema).is_none() {
+        catalog.register_schema(&schema, Arc::new(MemorySchemaProvider::new()))?;
+    }
+    Ok(())
+}
+
+/// The tables and views `plan` drops, by full name.
+pub(crate) async fn dropped_tables(
+    plan: &LogicalPlan,
+    ctx: &SessionContext,
+) -> DataFusionResult<Vec<(EntryKind, String)>> {
+    let LogicalPlan::Ddl(ddl) = plan else {
+        return Ok(vec![]);
+    };
+    let options = ctx.state().config().options().catalog.clone();
+    let exists = |name: &TableReference| matches!(ctx.table_exist(name.clone()), Ok(true));
+    let dropped = match ddl {
+        DdlStatement::DropTable(drop) if exists(&drop.name) => {
+            vec![(EntryKind::Table, full_name(drop.name.clone(), &options))]
+        }
+        DdlStatement::DropView(drop) if exists(&drop.name) => {
+            vec![(EntryKind::View, full_name(drop.name.clone(), &options))]
+        }
+        DdlStatement::DropCatalogSchema(drop) if drop.cascade => {
+            let (catalog, schema) = resolve_schema_reference(&drop.name, &options);
+            let Some(provider) = ctx.catalog(&catalog).and_then(|c| c.schema(&schema)) else {
+                return Ok(vec![]);
+            };
+            let mut dropped = vec![];
+            for table in provider.table_names() {
+                let kind = match provider.table(&table).await? {
+                    Some(view) if view.get_logical_plan().is_some() => EntryKind::View,
+                    _ => EntryKind::Table,
+                };
+                let name = TableReference::full(catalog.as_str(), schema.as_str(), table);
+                dropped.push((kind, name.to_quoted_string()));
+            }
+            dropped
+        }
+        _ => vec![],
+    };
+    Ok(dropped)
+}
+
+/// Where the tables of an engine that have been moved are placed: their full names
+/// by the full names their sources register them under.
+#[derive(Debug, Default)]
+pub(crate) struct Placements(RwLock<BTreeMap<String, String>>);
+
+impl Placements {
+    /// Where the table registered as `name` belongs.
+    pub(crate) fn placed_name(&self, name: &str) -> String {
+        let placements = self.0.read().expect("placement lock poisoned");
+        placements.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    /// The name the table at `placed` is registered under.
+    pub(crate) fn registered_name(&self, placed: &str) -> String {
+        let placements = self.0.read().expect("placement lock poisoned");
+        let registered = placements.iter().find(|(_, name)| *name == placed);
+        registered.map_or_else(|| placed.to_string(), |(name, _)| name.clone())
+    }
+
+    /// Place the table registered as `name` at `placed`, moving it there from where it
+    /// was if it is registered. Placing it at `name` removes its placement.
+    pub(crate) fn place(
+        &self,
+        ctx: &SessionContext,
+        name: &str,
+        placed: &str,
+    ) -> DataFusionResult<()> {
+        let from = self.placed_name(name);
+        {
+            let mut placements = self.0.write().expect("placement lock poisoned");
+            match placed == name {
+                true => placements.remove(name),
+                false => placements.insert(name.to_string(), placed.to_string()),
+            };
+        }
+        if from != placed {
+            if let Some(table) = ctx.deregister_table(from.as_str())? {
+                ctx.register_table(placed, table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the table just registered as `name` to its place, if it has one.
+    pub(crate) fn restore(&self, ctx: &SessionContext, name: &str) -> DataFusionResult<()> {
+        let placed = self.placed_name(name);
+        if placed != name {
+            if let Some(table) = ctx.deregister_table(name)? {
+                ctx.register_table(placed.as_str(), table)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget the placement of the table at `placed`, returning the name it is
+    /// registered under if it had one.
+    pub(crate) fn forget(&self, placed: &str) -> Option<String> {
+        let mut placements = self.0.write().expect("placement lock poisoned");
+        let name = placements.iter().find(|(_, name)| *name == placed)?.0.clone();
+        placements.remove(&name);
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog_store::SqliteCatalogStore;
+    use crate::QueryEngine;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::catalog::MemTable;
+
+    #[test]
+    fn test_parse_rename_sql() {
+        let (table, name) =
+            parse_rename_sql("ALTER TABLE Orders RENAME TO sales.orders").unwrap().unwrap();
+        assert_eq!(table, TableReference::bare("orders"));
+        assert_eq!(name, TableReference::partial("sales", "orders"));
+        assert_eq!(parse_rename_sql("ALTER TABLE orders ADD COLUMN id INT").unwrap(), None);
+        assert_eq!(parse_rename_sql("SELECT 1").unwrap(), None);
+        assert!(parse_rename_sql("ALTER TABLE IF EXISTS a RENAME TO b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_schemas_and_placements_are_persisted() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-namespace-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db"))?);
+        // A source registered by the deployment each time an engine starts.
+        let start = || async {
+            let engine = QueryEngine::new();
+            let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+            engine.register_table("orders", Arc::new(MemTable::try_new(schema, vec![])?))?;
+            engine.with_catalog_store(store.clone()).await
+        };
+
+        let first = start().await?;
+        first.query("CREATE SCHEMA sales").await?;
+        first.query("CREATE SCHEMA IF NOT EXISTS sales").await?;
+        first.query("ALTER TABLE orders RENAME TO sales.orders").await?;
+        first.query("CREATE VIEW sales.order_ids AS SELECT id FROM sales.orders").await?;
+        assert!(first.query("SELECT * FROM orders").await.is_err());
+        assert!(first.query("ALTER TABLE sales.orders RENAME TO other.orders").await.is_err());
+
+        // A restart: the source is registered under its own name and placed again.
+        let second = start().await?;
+        second.query("SELECT * FROM sales.order_ids").await?;
+        assert!(second.query("SELECT * FROM orders").await.is_err());
+        second.query("CREATE SCHEMA archive").await?;
+        second.query("ALTER TABLE sales.orders RENAME TO archive.old_orders").await?;
+        assert_eq!(first.refresh_catalog().await?, 2);
+        first.query("SELECT * FROM archive.old_orders").await?;
+        assert!(first.query("SELECT * FROM sales.orders").await.is_err());
+
+        // Dropping a schema drops its tables, wherever they are registered.
+        second.query("DROP SCHEMA archive CASCADE").await?;
+        let third = start().await?;
+        assert!(third.query("SELECT * FROM archive.old_orders").await.is_err());
+        assert!(third.query("SELECT * FROM orders").await.is_err());
+        third.query("DROP SCHEMA sales CASCADE").await?;
+        assert!(start().await?.query("SELECT * FROM sales.order_ids").await.is_err());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
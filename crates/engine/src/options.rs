@@ -0,0 +1,29 @@
+//! The `igloo.*` options of a session.
+//!
+//! [`IglooOptions`] are set for an engine by
+//! [`QueryEngine::with_options`](crate::QueryEngine::with_options) and changed for a
+//! session by `SET` (see [`session`](crate::session)). Each option is described where
+//! it takes effect: the join options in [`join_strategy`](crate::join_strategy),
+//! `igloo.use_materialized_views` in [`materialized_views`](crate::materialized_views).
+
+use crate::join_strategy::JoinStrategy;
+use datafusion::common::config::ConfigExtension;
+use datafusion::common::extensions_options;
+
+extensions_options! {
+    /// The `igloo.*` options of a session.
+    pub struct IglooOptions {
+        /// How hash joins move their inputs: auto, broadcast or partitioned.
+        pub join_strategy: JoinStrategy, default = JoinStrategy::Auto
+        /// Estimated rows up to which `auto` broadcasts a join side.
+        pub broadcast_max_rows: usize, default = 1_000_000
+        /// Estimated bytes up to which `auto` broadcasts a join side.
+        pub broadcast_max_bytes: usize, default = 64 * 1024 * 1024
+        /// Whether queries read materialized views in place of the plans they answer.
+        pub use_materialized_views: bool, default = true
+    }
+}
+
+impl ConfigExtension for IglooOptions {
+    const PREFIX: &'static str = "igloo";
+}
@@ -0,0 +1,610 @@
+//! Writing query results as Parquet files.
+//!
+//! `COPY ... TO ... STORED AS PARQUET`, `CREATE TABLE name WITH (location = '...') AS
+//! query` and materializations ([`QueryEngine::write_parquet`]) write through a
+//! [`ParquetSink`]. It takes DataFusion's Parquet writer options, from the session's
+//! `datafusion.execution.parquet.*` settings overridden per statement (e.g.
+//! `max_row_group_size`, `compression` such as `'zstd(3)'`, `dictionary_enabled`),
+//! and one of its own, `target_file_size`: once a file holds that many bytes (a number
+//! with an optional `KB`, `MB` or `GB` suffix, [`DEFAULT_TARGET_FILE_SIZE`] unless
+//! set) it is closed and the rest of the result written to the next one. Output to a
+//! single file, a URL with an extension and no trailing `/`, is never split.
+//!
+//! ```sql
+//! COPY orders TO 's3://lake/orders/' STORED AS PARQUET
+//!     OPTIONS (compression 'zstd(3)', max_row_group_size 100000, target_file_size '128MB');
+//! CREATE TABLE big_orders WITH (location = 's3://lake/big_orders/', compression = 'snappy')
+//!     AS SELECT * FROM orders WHERE amount > 1000;
+//! ```
+//!
+//! A `CREATE TABLE ... AS` with a location is registered as an external Parquet table
+//! over the files it wrote, and recorded in the catalog store like one.
+//!
+//! [`QueryEngine::write_parquet`]: crate::QueryEngine::write_parquet
+
+use crate::session::expr_to_value;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::config::{ConfigFileType, TableParquetOptions};
+use datafusion::common::file_options::parquet_writer::ParquetWriterOptions;
+use datafusion::common::runtime::SpawnedTask;
+use datafusion::common::{GetExt, Statistics};
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::write::demux::DemuxedStreamReceiver;
+use datafusion::datasource::file_format::write::get_writer_schema;
+use datafusion::datasource::file_format::{format_as_file_type, FileFormat, FileFormatFactory};
+use datafusion::datasource::physical_plan::{FileScanConfig, FileSink, FileSinkConfig, FileSource};
+use datafusion::datasource::sink::{DataSink, DataSinkExec};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::memory_pool::MemoryConsumer;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::dml::{CopyTo, InsertOp};
+use datafusion::logical_expr::LogicalPlan;
+use datafusion::parquet::arrow::arrow_writer::ArrowWriterOptions;
+use datafusion::parquet::arrow::AsyncArrowWriter;
+use datafusion::parquet::file::properties::WriterProperties;
+use datafusion::physical_expr::LexRequirement;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::planner::object_name_to_table_reference;
+use datafusion::sql::sqlparser::ast::{CreateTable, SqlOption, Statement};
+use datafusion::sql::TableReference;
+use object_store::buffered::BufWriter;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinSet;
+
+/// The option giving the size in bytes at which a file is closed for the next one.
+pub const TARGET_FILE_SIZE_OPTION: &str = "format.target_file_size";
+
+pub const DEFAULT_TARGET_FILE_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Makes [`ParquetSinkFormat`]s of statement options, as DataFusion's
+/// [`ParquetFormatFactory`](datafusion::datasource::file_format::parquet::ParquetFormatFactory)
+/// does [`ParquetFormat`]s.
+#[derive(Debug, Default)]
+pub struct ParquetSinkFormatFactory;
+
+impl FileFormatFactory for ParquetSinkFormatFactory {
+    fn create(
+        &self,
+        state: &dyn Session,
+        format_options: &HashMap<String, String>,
+    ) -> DataFusionResult<Arc<dyn FileFormat>> {
+        let mut options = format_options.clone();
+        let target_file_size = match options.remove(TARGET_FILE_SIZE_OPTION) {
+            Some(size) => parse_size(&size)?,
+            None => DEFAULT_TARGET_FILE_SIZE,
+        };
+        let mut table_options = state.default_table_options();
+        table_options.set_config_format(ConfigFileType::PARQUET);
+        table_options.alter_with_string_hash_map(&options)?;
+        Ok(Arc::new(ParquetSinkFormat::new(table_options.parquet, target_file_size)))
+    }
+
+    fn default(&self) -> Arc<dyn FileFormat> {
+        Arc::new(ParquetSinkFormat::new(TableParquetOptions::default(), DEFAULT_TARGET_FILE_SIZE))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl GetExt for ParquetSinkFormatFactory {
+    fn get_ext(&self) -> String {
+        ParquetFormat::default().get_ext()
+    }
+}
+
+/// Parquet read like DataFusion's [`ParquetFormat`] and written by a [`ParquetSink`].
+#[derive(Debug)]
+pub struct ParquetSinkFormat {
+    format: ParquetFormat,
+    target_file_size: u64,
+}
+
+impl ParquetSinkFormat {
+    pub fn new(options: TableParquetOptions, target_file_size: u64) -> Self {
+        Self { format: ParquetFormat::default().with_options(options), target_file_size }
+    }
+}
+
+#[async_trait]
+impl FileFormat for ParquetSinkFormat {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn get_ext(&self) -> String {
+        self.format.get_ext()
+    }
+
+    fn get_ext_with_compression(
+        &self,
+        file_compression_type: &FileCompressionType,
+    ) -> DataFusionResult<String> {
+        self.format.get_ext_with_compression(file_compression_type)
+    }
+
+    async fn infer_schema(
+        &self,
+        state: &dyn Session,
+        store: &Arc<dyn ObjectStore>,
+        objects: &[ObjectMeta],
+    ) -> DataFusionResult<SchemaRef> {
+        self.format.infer_schema(state, store, objects).await
+    }
+
+    async fn infer_stats(
+        &self,
+        state: &dyn Session,
+        store: &Arc<dyn ObjectStore>,
+        table_schema: SchemaRef,
+        object: &ObjectMeta,
+    ) -> DataFusionResult<Statistics> {
+        self.format.infer_stats(state, store, table_schema, object).await
+    }
+
+    async fn create_physical_plan(
+        &self,
+        state: &dyn Session,
+        conf: FileScanConfig,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        self.format.create_physical_plan(state, conf).await
+    }
+
+    async fn create_writer_physical_plan(
+        &self,
+        input: Arc<dyn ExecutionPlan>,
+        _state: &dyn Session,
+        conf: FileSinkConfig,
+        order_requirements: Option<LexRequirement>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if conf.insert_op != InsertOp::Append {
+            return Err(DataFusionError::NotImplemented(
+                "overwriting Parquet output is not supported".to_string(),
+            ));
+        }
+        let sink = ParquetSink::new(conf, self.format.options().clone(), self.target_file_size);
+        Ok(Arc::new(DataSinkExec::new(input, Arc::new(sink), order_requirements)))
+    }
+
+    fn file_source(&self) -> Arc<dyn FileSource> {
+        self.format.file_source()
+    }
+}
+
+/// Writes Parquet files of at most about `target_file_size` bytes each.
+#[derive(Debug)]
+pub struct ParquetSink {
+    config: FileSinkConfig,
+    options: TableParquetOptions,
+    target_file_size: u64,
+}
+
+impl ParquetSink {
+    pub fn new(
+        config: FileSinkConfig,
+        options: TableParquetOptions,
+        target_file_size: u64,
+    ) -> Self {
+        Self { config, options, target_file_size }
+    }
+
+    fn writer_properties(&self) -> DataFusionResult<WriterProperties> {
+        let mut options = self.options.clone();
+        if !options.global.skip_arrow_metadata {
+            options.arrow_schema(&get_writer_schema(&self.config));
+        }
+        Ok(ParquetWriterOptions::try_from(&options)?.writer_options().clone())
+    }
+}
+
+impl DisplayAs for ParquetSink {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ParquetSink(url={}, target_file_size={})",
+            self.config.original_url, self.target_file_size
+        )
+    }
+}
+
+#[async_trait]
+impl DataSink for ParquetSink {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> &SchemaRef {
+        self.config.output_schema()
+    }
+
+    async fn write_all(
+        &self,
+        data: SendableRecordBatchStream,
+        context: &Arc<TaskContext>,
+    ) -> DataFusionResult<u64> {
+        FileSink::write_all(self, data, context).await
+    }
+}
+
+#[async_trait]
+impl FileSink for ParquetSink {
+    fn config(&self) -> &FileSinkConfig {
+        &self.config
+    }
+
+    async fn spawn_writer_tasks_and_join(
+        &self,
+        context: &Arc<TaskContext>,
+        demux_task: SpawnedTask<DataFusionResult<()>>,
+        mut file_stream_rx: DemuxedStreamReceiver,
+        object_store: Arc<dyn ObjectStore>,
+    ) -> DataFusionResult<u64> {
+        let properties = self.writer_properties()?;
+        let single_file = self.config.table_paths[0].prefix().clone();
+        let mut tasks = JoinSet::new();
+        while let Some((path, batches)) = file_stream_rx.recv().await {
+            let writer = FileWriter {
+                store: Arc::clone(&object_store),
+                context: Arc::clone(context),
+                schema: get_writer_schema(&self.config),
+                options: ArrowWriterOptions::new()
+                    .with_properties(properties.clone())
+                    .with_skip_arrow_metadata(self.options.global.skip_arrow_metadata),
+                max_row_group_size: properties.max_row_group_size(),
+                target_file_size: (path != single_file).then_some(self.target_file_size),
+            };
+            tasks.spawn(writer.write(path, batches));
+        }
+        let mut rows = 0;
+        while let Some(result) = tasks.join_next().await {
+            rows += result.map_err(|e| DataFusionError::External(Box::new(e)))??;
+        }
+        demux_task.join_unwind().await.map_err(|e| DataFusionError::External(Box::new(e)))??;
+        Ok(rows)
+    }
+}
+
+/// Writes one of the demuxer's streams to its path, and to further paths of the same
+/// name suffixed `-1`, `-2`, ... each time a file reaches the target size.
+struct FileWriter {
+    store: Arc<dyn ObjectStore>,
+    context: Arc<TaskContext>,
+    schema: SchemaRef,
+    options: ArrowWriterOptions,
+    max_row_group_size: usize,
+    target_file_size: Option<u64>,
+}
+
+impl FileWriter {
+    async fn write(self, path: Path, mut batches: Receiver<RecordBatch>) -> DataFusionResult<u64> {
+        let mut reservation = MemoryConsumer::new(format!("ParquetSink[{path}]"))
+            .register(self.context.memory_pool());
+        // The first file is written even for no rows, so the output has a schema.
+        let mut writer = Some(self.open(&path)?);
+        let (mut part, mut rows) = (0, 0);
+        while let Some(batch) = batches.recv().await {
+            // Written no more than a row group at a time, so a file's size is checked
+            // each time one is flushed, even within a large batch.
+            let mut offset = 0;
+            while offset < batch.num_rows() {
+                let file = match &mut writer {
+                    Some(file) => file,
+                    None => {
+                        part += 1;
+                        writer.insert(self.open(&part_path(&path, part)?)?)
+                    }
+                };
+                let room = self.max_row_group_size - file.in_progress_rows();
+                let slice = batch.slice(offset, room.min(batch.num_rows() - offset));
+                file.write(&slice).await?;
+                offset += slice.num_rows();
+                reservation.try_resize(file.memory_size())?;
+                let size = (file.bytes_written() + file.in_progress_size()) as u64;
+                if self.target_file_size.is_some_and(|target| size >= target) {
+                    writer.take().expect("writer is open").close().await?;
+                    reservation.free();
+                }
+            }
+            rows += batch.num_rows() as u64;
+        }
+        if let Some(file) = writer {
+            file.close().await?;
+        }
+        Ok(rows)
+    }
+
+    fn open(&self, path: &Path) -> DataFusionResult<AsyncArrowWriter<BufWriter>> {
+        let buffer_size =
+            self.context.session_config().options().execution.objectstore_writer_buffer_size;
+        let buffer = BufWriter::with_capacity(Arc::clone(&self.store), path.clone(), buffer_size);
+        let options = self.options.clone();
+        Ok(AsyncArrowWriter::try_new_with_options(buffer, Arc::clone(&self.schema), options)?)
+    }
+}
+
+/// `path` with `-{part}` ahead of its extension.
+fn part_path(path: &Path, part: usize) -> DataFusionResult<Path> {
+    let path = path.as_ref();
+    let name = match path.rsplit_once('.') {
+        Some((stem, extension)) if !extension.contains('/') => format!("{stem}-{part}.{extension}"),
+        _ => format!("{path}-{part}"),
+    };
+    Path::parse(name).map_err(|e| DataFusionError::External(Box::new(e)))
+}
+
+/// Parse a size in bytes, e.g. `1048576`, `512KB`, `128MB` or `1GB`.
+fn parse_size(value: &str) -> DataFusionResult<u64> {
+    let value = value.trim().to_ascii_lowercase();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let invalid = || {
+        DataFusionError::Configuration(format!(
+            "invalid target_file_size '{value}', expected e.g. 1048576, 512KB, 128MB or 1GB"
+        ))
+    };
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let size = match unit.trim() {
+        "" | "b" => amount,
+        "kb" => amount * 1024,
+        "mb" => amount * 1024 * 1024,
+        "gb" => amount * 1024 * 1024 * 1024,
+        _ => return Err(invalid()),
+    };
+    if size == 0 {
+        return Err(invalid());
+    }
+    Ok(size)
+}
+
+/// `plan` writing through a [`ParquetSink`] if it is a `COPY` to Parquet.
+pub(crate) fn with_parquet_sink(plan: LogicalPlan) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Copy(copy)
+            if copy.file_type.get_ext() == ParquetFormat::default().get_ext() =>
+        {
+            LogicalPlan::Copy(CopyTo { file_type: parquet_file_type(), ..copy })
+        }
+        plan => plan,
+    }
+}
+
+pub(crate) fn parquet_file_type() -> Arc<dyn datafusion::common::file_options::file_type::FileType>
+{
+    format_as_file_type(Arc::new(ParquetSinkFormatFactory))
+}
+
+/// Options as `COPY ... OPTIONS` takes them, with the `format.` prefix implied for
+/// names without one.
+pub(crate) fn format_options(options: HashMap<String, String>) -> HashMap<String, String> {
+    options
+        .into_iter()
+        .map(|(name, value)| match name.contains('.') {
+            true => (name.to_lowercase(), value),
+            false => (format!("format.{}", name.to_lowercase()), value),
+        })
+        .collect()
+}
+
+/// A `CREATE TABLE ... WITH (location = ...) AS query`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateTableAs {
+    pub name: TableReference,
+    pub if_not_exists: bool,
+    /// Where the files are written, a directory.
+    pub location: String,
+    /// The other `WITH` options, as for [`format_options`].
+    pub options: HashMap<String, String>,
+    pub query: String,
+}
+
+/// If `sql` is a single `CREATE TABLE ... AS` with `WITH` options, what it creates.
+/// Without options, or for SQL that does not parse, it is `None`: DataFusion plans
+/// those, as in-memory tables.
+pub fn parse_create_table_as_sql(sql: &str) -> DataFusionResult<Option<CreateTableAs>> {
+    let statements = match DFParser::parse_sql(sql) {
+        Ok(statements) if statements.len() == 1 => statements,
+        _ => return Ok(None),
+    };
+    let DFStatement::Statement(statement) = &statements[0] else {
+        return Ok(None);
+    };
+    let Statement::CreateTable(CreateTable {
+        name,
+        query: Some(query),
+        with_options,
+        columns,
+        or_replace,
+        temporary,
+        if_not_exists,
+        ..
+    }) = statement.as_ref()
+    else {
+        return Ok(None);
+    };
+    if with_options.is_empty() {
+        return Ok(None);
+    }
+    if *or_replace || *temporary || !columns.is_empty() {
+        return Err(DataFusionError::NotImplemented(
+            "CREATE TABLE ... AS with a location and OR REPLACE, TEMPORARY or columns is not \
+             supported"
+                .to_string(),
+        ));
+    }
+    let mut options = HashMap::new();
+    for option in with_options {
+        let SqlOption::KeyValue { key, value } = option else {
+            return Err(DataFusionError::Plan(format!("unsupported table option {option}")));
+        };
+        options.insert(key.value.clone(), expr_to_value(value)?);
+    }
+    let Some(mut location) = options.remove("location") else {
+        return Err(DataFusionError::Plan(
+            "CREATE TABLE ... AS with options needs a location".to_string(),
+        ));
+    };
+    if !location.ends_with('/') {
+        location.push('/');
+    }
+    Ok(Some(CreateTableAs {
+        name: object_name_to_table_reference(name.clone(), true)?,
+        if_not_exists: *if_not_exists,
+        location,
+        options: format_options(options),
+        query: query.to_string(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::parquet::basic::Compression;
+    use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+
+    fn parquet_files(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let mut files: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|e| e == "parquet"))
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1048576").unwrap(), 1 << 20);
+        assert_eq!(parse_size("512KB").unwrap(), 512 << 10);
+        assert_eq!(parse_size(" 128mb ").unwrap(), 128 << 20);
+        assert_eq!(parse_size("1GB").unwrap(), 1 << 30);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("5 parsecs").is_err());
+    }
+
+    #[test]
+    fn test_parse_create_table_as_sql() {
+        let sql = "CREATE TABLE IF NOT EXISTS s.t WITH (location = '/tmp/t', compression = \
+                   'zstd(3)', max_row_group_size = 10) AS SELECT 1";
+        let create = parse_create_table_as_sql(sql).unwrap().unwrap();
+        assert_eq!(create.name, TableReference::partial("s", "t"));
+        assert!(create.if_not_exists);
+        assert_eq!(create.location, "/tmp/t/");
+        assert_eq!(create.options["format.compression"], "zstd(3)");
+        assert_eq!(create.options["format.max_row_group_size"], "10");
+        assert_eq!(create.query, "SELECT 1");
+        assert_eq!(parse_create_table_as_sql("CREATE TABLE t AS SELECT 1").unwrap(), None);
+        assert!(parse_create_table_as_sql("CREATE TABLE t WITH (a = 1) AS SELECT 1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_copy_splits_files_at_the_target_size() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-parquet-sink-{}", std::process::id()));
+        let engine = QueryEngine::new();
+        // A row group of 1000 rows is more than the target size, so each is a file.
+        let sql = format!(
+            "COPY (SELECT value AS id, value * 2 AS doubled FROM generate_series(1, 5000)) \
+             TO '{}/' STORED AS PARQUET OPTIONS (max_row_group_size 1000, compression \
+             'zstd(1)', dictionary_enabled false, target_file_size '1KB')",
+            dir.display()
+        );
+        let result = engine.query(&sql).await?;
+        let expected = "\
++-------+
+| count |
++-------+
+| 5000  |
++-------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+
+        let files = parquet_files(&dir);
+        assert_eq!(files.len(), 5);
+        for file in &files {
+            let reader = SerializedFileReader::new(std::fs::File::open(file)?)?;
+            let metadata = reader.metadata();
+            assert_eq!(metadata.num_row_groups(), 1);
+            let row_group = metadata.row_group(0);
+            assert_eq!(row_group.num_rows(), 1000);
+            let column = row_group.column(0);
+            assert!(matches!(column.compression(), Compression::ZSTD(_)));
+            assert_eq!(column.dictionary_page_offset(), None);
+        }
+        let sql =
+            format!("CREATE EXTERNAL TABLE copied STORED AS PARQUET LOCATION '{}/'", dir.display());
+        engine.query(&sql).await?;
+        let result = engine.query("SELECT count(*), sum(doubled) FROM copied").await?;
+        let expected = "\
++----------+---------------------+
+| count(*) | sum(copied.doubled) |
++----------+---------------------+
+| 5000     | 25005000            |
++----------+---------------------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_single_file_output_is_not_split() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-parquet-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let engine = QueryEngine::new();
+        let sql = format!(
+            "COPY (SELECT * FROM generate_series(1, 5000)) TO '{}/out.parquet' \
+             OPTIONS (max_row_group_size 1000, target_file_size '1KB')",
+            dir.display()
+        );
+        engine.query(&sql).await?;
+        let files = parquet_files(&dir);
+        assert_eq!(files.len(), 1);
+        let reader = SerializedFileReader::new(std::fs::File::open(&files[0])?)?;
+        assert_eq!(reader.metadata().num_row_groups(), 5);
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_create_table_as_with_location() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-parquet-ctas-{}", std::process::id()));
+        let engine = QueryEngine::new();
+        let sql = "CREATE TABLE orders (id BIGINT, amount DOUBLE) AS \
+                   VALUES (1, 10.0), (2, 25.0), (3, 40.0)";
+        engine.query(sql).await?;
+        let sql = format!(
+            "CREATE TABLE big_orders WITH (location = '{}', compression = 'snappy') AS \
+             SELECT * FROM orders WHERE amount > 20",
+            dir.display()
+        );
+        engine.query(&sql).await?;
+        let files = parquet_files(&dir);
+        assert_eq!(files.len(), 1);
+        let reader = SerializedFileReader::new(std::fs::File::open(&files[0])?)?;
+        assert_eq!(reader.metadata().row_group(0).column(0).compression(), Compression::SNAPPY);
+        let result = engine.query("SELECT * FROM big_orders ORDER BY id").await?;
+        let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 2  | 25.0   |
+| 3  | 40.0   |
++----+--------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        let err = engine.query(&sql).await.unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err}");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
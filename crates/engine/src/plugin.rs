@@ -0,0 +1,277 @@
+//! Extending the engine from other crates.
+//!
+//! Crates depending on `igloo-engine` ship extensions by implementing these traits and
+//! registering them on the engine as it is built, for it and tenants added to it
+//! afterwards:
+//!
+//! - a [`QueryRewriter`] rewrites the logical plans of statements once they are
+//!   analyzed and before they are optimized, registered by
+//!   [`QueryEngine::with_query_rewriter`](crate::QueryEngine::with_query_rewriter).
+//!   Rewriters run in the order they were registered;
+//! - a [`ConnectorFactory`] creates the tables of `CREATE EXTERNAL TABLE ... STORED AS
+//!   <name>`, registered by
+//!   [`QueryEngine::with_connector`](crate::QueryEngine::with_connector). Tables are
+//!   persisted by the catalog store like those of built-in formats, so the connector
+//!   must be registered again before tables are restored;
+//! - a [`CachePolicy`] decides which results the result cache keeps and for how long,
+//!   registered by
+//!   [`QueryEngine::with_cache_policy`](crate::QueryEngine::with_cache_policy).
+//!
+//! ```
+//! use datafusion::common::tree_node::Transformed;
+//! use datafusion::config::ConfigOptions;
+//! use datafusion::error::Result;
+//! use datafusion::logical_expr::LogicalPlan;
+//! use igloo_engine::plugin::QueryRewriter;
+//! use igloo_engine::QueryEngine;
+//! use std::sync::Arc;
+//!
+//! #[derive(Debug)]
+//! struct Unchanged;
+//!
+//! impl QueryRewriter for Unchanged {
+//!     fn name(&self) -> &str {
+//!         "unchanged"
+//!     }
+//!
+//!     fn rewrite(
+//!         &self,
+//!         plan: LogicalPlan,
+//!         _config: &ConfigOptions,
+//!     ) -> Result<Transformed<LogicalPlan>> {
+//!         Ok(Transformed::no(plan))
+//!     }
+//! }
+//!
+//! let engine = QueryEngine::new().with_query_rewriter(Arc::new(Unchanged));
+//! ```
+
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::catalog::{Session, TableProviderFactory};
+use datafusion::common::tree_node::Transformed;
+use datafusion::config::ConfigOptions;
+use datafusion::datasource::TableProvider;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::{CreateExternalTable, LogicalPlan};
+use datafusion::optimizer::AnalyzerRule;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Rewrites the logical plans of statements, see the [module docs](self).
+pub trait QueryRewriter: fmt::Debug + Send + Sync {
+    /// The rewriter's name, as `EXPLAIN VERBOSE` shows it.
+    fn name(&self) -> &str;
+
+    /// `plan` rewritten, or as it is. `config` holds the options of the session the
+    /// statement is planned in.
+    fn rewrite(
+        &self,
+        plan: LogicalPlan,
+        config: &ConfigOptions,
+    ) -> DataFusionResult<Transformed<LogicalPlan>>;
+}
+
+/// Creates the tables of a connector, see the [module docs](self).
+#[async_trait]
+pub trait ConnectorFactory: fmt::Debug + Send + Sync {
+    /// The name tables of the connector are created `STORED AS`, case-insensitive.
+    fn name(&self) -> &str;
+
+    /// The table at `location`, of the `schema` declared (no columns if none were) and
+    /// with the `options` given.
+    async fn create(
+        &self,
+        state: &dyn Session,
+        location: &str,
+        schema: SchemaRef,
+        options: &HashMap<String, String>,
+    ) -> DataFusionResult<Arc<dyn TableProvider>>;
+}
+
+/// Which results the result cache keeps, and for how long, see the
+/// [module docs](self).
+pub trait CachePolicy: fmt::Debug + Send + Sync {
+    /// Whether the result of a query reading `tables`, by full name, is cached.
+    fn cacheable(&self, tables: &[String]) -> bool;
+
+    /// How long the result of a query reading `tables` is served, or `None` for as
+    /// long as the cache's own expiry allows.
+    fn ttl(&self, _tables: &[String]) -> Option<Duration> {
+        None
+    }
+}
+
+/// Runs a [`QueryRewriter`] as an analyzer rule.
+#[derive(Debug)]
+pub(crate) struct RewriterRule(pub(crate) Arc<dyn QueryRewriter>);
+
+impl AnalyzerRule for RewriterRule {
+    fn analyze(&self, plan: LogicalPlan, config: &ConfigOptions) -> DataFusionResult<LogicalPlan> {
+        Ok(self.0.rewrite(plan, config)?.data)
+    }
+
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+}
+
+/// Creates the tables of a [`ConnectorFactory`] for DataFusion.
+#[derive(Debug)]
+pub(crate) struct ConnectorTables(pub(crate) Arc<dyn ConnectorFactory>);
+
+#[async_trait]
+impl TableProviderFactory for ConnectorTables {
+    async fn create(
+        &self,
+        state: &dyn Session,
+        cmd: &CreateExternalTable,
+    ) -> DataFusionResult<Arc<dyn TableProvider>> {
+        let schema = Arc::new(cmd.schema.as_ref().into());
+        // DataFusion qualifies the options given without a prefix as `format.` ones.
+        let options = cmd
+            .options
+            .iter()
+            .map(|(key, value)| {
+                let key = key.strip_prefix("format.").unwrap_or(key);
+                (key.to_string(), value.clone())
+            })
+            .collect();
+        self.0.create(state, &cmd.location, schema, &options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result_cache::ResultCache;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::datasource::MemTable;
+    use datafusion::error::DataFusionError;
+    use datafusion::logical_expr::LogicalPlanBuilder;
+    use std::sync::Mutex;
+
+    /// Returns the first row of queries only.
+    #[derive(Debug)]
+    struct FirstRow;
+
+    impl QueryRewriter for FirstRow {
+        fn name(&self) -> &str {
+            "first_row"
+        }
+
+        fn rewrite(
+            &self,
+            plan: LogicalPlan,
+            _config: &ConfigOptions,
+        ) -> DataFusionResult<Transformed<LogicalPlan>> {
+            match plan {
+                LogicalPlan::Projection(_) | LogicalPlan::Sort(_) => {
+                    let plan = LogicalPlanBuilder::from(plan).limit(0, Some(1))?.build()?;
+                    Ok(Transformed::yes(plan))
+                }
+                plan => Ok(Transformed::no(plan)),
+            }
+        }
+    }
+
+    /// Creates tables of the numbers from 0 up to their location.
+    #[derive(Debug, Default)]
+    struct Numbers {
+        options: Mutex<Vec<HashMap<String, String>>>,
+    }
+
+    #[async_trait]
+    impl ConnectorFactory for Numbers {
+        fn name(&self) -> &str {
+            "numbers"
+        }
+
+        async fn create(
+            &self,
+            _state: &dyn Session,
+            location: &str,
+            _schema: SchemaRef,
+            options: &HashMap<String, String>,
+        ) -> DataFusionResult<Arc<dyn TableProvider>> {
+            self.options.lock().unwrap().push(options.clone());
+            let Ok(end) = location.parse::<i64>() else {
+                return Err(DataFusionError::Plan(format!("'{location}' is not a number")));
+            };
+            let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+            let numbers = Arc::new(Int64Array::from_iter_values(0..end));
+            let batch = RecordBatch::try_new(schema.clone(), vec![numbers])?;
+            Ok(Arc::new(MemTable::try_new(schema, vec![vec![batch]])?))
+        }
+    }
+
+    /// Caches no result of tables named `live`, and expires those of tables named
+    /// `stale` at once.
+    #[derive(Debug)]
+    struct NotLive;
+
+    impl CachePolicy for NotLive {
+        fn cacheable(&self, tables: &[String]) -> bool {
+            !tables.iter().any(|table| table.ends_with(".live"))
+        }
+
+        fn ttl(&self, tables: &[String]) -> Option<Duration> {
+            tables.iter().any(|table| table.ends_with(".stale")).then_some(Duration::ZERO)
+        }
+    }
+
+    async fn rows(engine: &QueryEngine, sql: &str) -> DataFusionResult<String> {
+        let result = engine.query(sql).await?;
+        Ok(pretty_format_batches(&result.batches)?.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_plugins_extend_the_engine() -> DataFusionResult<()> {
+        let numbers = Arc::new(Numbers::default());
+        let cache = Arc::new(ResultCache::new(1 << 20));
+        let engine = QueryEngine::new()
+            .with_connector(numbers.clone())
+            .with_result_cache(cache.clone())
+            .with_cache_policy(Arc::new(NotLive));
+        let sql = "CREATE EXTERNAL TABLE three STORED AS NUMBERS LOCATION '3' OPTIONS ('step' '1')";
+        engine.query(sql).await?;
+        engine.query("CREATE EXTERNAL TABLE live STORED AS numbers LOCATION '2'").await?;
+        let expected = "\
++-------+
+| total |
++-------+
+| 3     |
++-------+";
+        assert_eq!(rows(&engine, "SELECT sum(n) AS total FROM three").await?, expected);
+        assert_eq!(numbers.options.lock().unwrap()[0]["step"], "1");
+        let error = engine.query("CREATE EXTERNAL TABLE t STORED AS numbers LOCATION 'x'").await;
+        assert!(error.unwrap_err().to_string().contains("'x' is not a number"));
+        engine.query("CREATE EXTERNAL TABLE stale STORED AS numbers LOCATION '2'").await?;
+        let cached = cache.len();
+        rows(&engine, "SELECT n FROM live").await?;
+        assert_eq!(cache.len(), cached);
+        engine.query("SELECT n FROM stale").await?;
+        assert_eq!(cache.len(), cached + 1);
+        assert!(!engine.query("SELECT n FROM stale").await?.cache_hit);
+        assert!(engine.query("SELECT sum(n) AS total FROM three").await?.cache_hit);
+
+        let engine = engine.with_query_rewriter(Arc::new(FirstRow));
+        let expected = "\
++---+
+| n |
++---+
+| 2 |
++---+";
+        assert_eq!(rows(&engine, "SELECT n FROM three ORDER BY n DESC").await?, expected);
+        let tenant = engine.add_tenant(crate::tenant::Tenant::new("acme"))?;
+        tenant.query("CREATE EXTERNAL TABLE two STORED AS numbers LOCATION '2'").await?;
+        assert_eq!(rows(&tenant, "SELECT n FROM two").await?.lines().count(), 5);
+        Ok(())
+    }
+}
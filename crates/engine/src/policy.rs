@@ -0,0 +1,497 @@
+//! Column masking and row-level security.
+//!
+//! A [`PolicySet`] declares which columns are masked and which rows are hidden, per
+//! role. Policies are enforced by an analyzer rule that rewrites every scan of a
+//! governed table into `Projection(masks) <- Filter(row filters) <- TableScan`, so
+//! they hold for every query path (SQL, DataFrames, views, subqueries) and a user's
+//! own predicates only ever see masked values.
+//!
+//! Policies are plain data and can be loaded from configuration:
+//!
+//! ```json
+//! {
+//!   "masks": [
+//!     {"table": "customers", "column": "email", "mask": "hash", "exempt_roles": ["admin"]},
+//!     {"table": "customers", "column": "card", "mask": {"partial": {"visible": 4}}}
+//!   ],
+//!   "row_filters": [
+//!     {"table": "orders", "predicate": "region = 'EU'", "roles": ["eu_analyst"]}
+//!   ]
+//! }
+//! ```
+//!
+//! A policy applies to a query when the caller holds one of its `roles` (or `roles` is
+//! empty, meaning everyone) and none of its `exempt_roles`. Queries without a caller
+//! are evaluated with no roles, so unscoped policies always apply to them.
+//!
+//! Table names are qualified with the engine's default catalog and schema when the
+//! policies are set, so `customers` keeps naming `datafusion.public.customers` for
+//! sessions that change their own defaults (`SET search_path`, `SET catalog`).
+
+use datafusion::common::config::ConfigOptions;
+use datafusion::common::tree_node::Transformed;
+use datafusion::common::{Column, DFSchema, ScalarValue, TableReference};
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::session_state::{SessionState, SessionStateBuilder};
+use datafusion::functions::expr_fn::{character_length, encode, greatest, repeat, right, sha256};
+use datafusion::logical_expr::{
+    binary_expr, cast, lit, Expr, Filter, LogicalPlan, Operator, Projection, TableScan,
+};
+use datafusion::optimizer::AnalyzerRule;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// How a masked column's values are replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskKind {
+    /// Hex SHA-256 of the value. Deterministic, so masked columns can still be joined
+    /// and grouped on. String columns only.
+    Hash,
+    /// Always NULL.
+    Null,
+    /// Only the last `visible` characters are shown, e.g. `************1234`. String
+    /// columns only.
+    Partial { visible: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnMask {
+    pub table: String,
+    pub column: String,
+    pub mask: MaskKind,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub exempt_roles: Vec<String>,
+}
+
+impl ColumnMask {
+    pub fn new(table: impl Into<String>, column: impl Into<String>, mask: MaskKind) -> Self {
+        Self {
+            table: table.into(),
+            column: column.into(),
+            mask,
+            roles: Vec::new(),
+            exempt_roles: Vec::new(),
+        }
+    }
+
+    /// Only mask for callers holding `role` (may be repeated).
+    pub fn for_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    /// Never mask for callers holding `role` (may be repeated).
+    pub fn except(mut self, role: impl Into<String>) -> Self {
+        self.exempt_roles.push(role.into());
+        self
+    }
+}
+
+/// A SQL predicate rows must satisfy to be visible.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RowFilter {
+    pub table: String,
+    pub predicate: String,
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub exempt_roles: Vec<String>,
+}
+
+impl RowFilter {
+    pub fn new(table: impl Into<String>, predicate: impl Into<String>) -> Self {
+        Self {
+            table: table.into(),
+            predicate: predicate.into(),
+            roles: Vec::new(),
+            exempt_roles: Vec::new(),
+        }
+    }
+
+    /// Only filter for callers holding `role` (may be repeated).
+    pub fn for_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    /// Never filter for callers holding `role` (may be repeated).
+    pub fn except(mut self, role: impl Into<String>) -> Self {
+        self.exempt_roles.push(role.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicySet {
+    #[serde(default)]
+    pub masks: Vec<ColumnMask>,
+    #[serde(default)]
+    pub row_filters: Vec<RowFilter>,
+}
+
+impl PolicySet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_mask(mut self, mask: ColumnMask) -> Self {
+        self.masks.push(mask);
+        self
+    }
+
+    pub fn with_row_filter(mut self, filter: RowFilter) -> Self {
+        self.row_filters.push(filter);
+        self
+    }
+
+    pub fn from_json(json: &str) -> DataFusionResult<Self> {
+        serde_json::from_str(json)
+            .map_err(|e| DataFusionError::Configuration(format!("invalid policies: {e}")))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.masks.is_empty() && self.row_filters.is_empty()
+    }
+
+    /// The same policies with their table names fully qualified, unqualified parts
+    /// taken from `catalog` and `schema`.
+    fn qualified(mut self, catalog: &str, schema: &str) -> Self {
+        let qualify = |table: &mut String| {
+            let resolved = TableReference::from(table.as_str()).resolve(catalog, schema);
+            let full = TableReference::full(resolved.catalog, resolved.schema, resolved.table);
+            *table = full.to_quoted_string();
+        };
+        self.masks.iter_mut().for_each(|mask| qualify(&mut mask.table));
+        self.row_filters.iter_mut().for_each(|filter| qualify(&mut filter.table));
+        self
+    }
+}
+
+fn applies(policy_roles: &[String], exempt_roles: &[String], roles: &[String]) -> bool {
+    (policy_roles.is_empty() || policy_roles.iter().any(|role| roles.contains(role)))
+        && !exempt_roles.iter().any(|role| roles.contains(role))
+}
+
+/// Analyzer rule enforcing the engine's policies for the given caller roles. It must
+/// run before type coercion, which then types the injected expressions.
+pub(crate) struct PolicyRule {
+    policies: Arc<RwLock<PolicySet>>,
+    /// The engine's own default catalog and schema, which policy tables are qualified
+    /// with rather than a session's.
+    defaults: (String, String),
+    roles: Vec<String>,
+    /// Plans row filter predicates; only built-in functions are available to them.
+    parser: Arc<SessionState>,
+}
+
+impl fmt::Debug for PolicyRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyRule").field("roles", &self.roles).finish_non_exhaustive()
+    }
+}
+
+impl PolicyRule {
+    pub(crate) const NAME: &'static str = "igloo_policies";
+
+    pub(crate) fn new(policies: Arc<RwLock<PolicySet>>, config: &ConfigOptions) -> Self {
+        let parser = Arc::new(SessionStateBuilder::new().with_default_features().build());
+        let defaults =
+            (config.catalog.default_catalog.clone(), config.catalog.default_schema.clone());
+        Self { policies, defaults, roles: Vec::new(), parser }
+    }
+
+    /// The same policies, enforced for a caller holding `roles`.
+    pub(crate) fn with_roles(&self, roles: Vec<String>) -> Self {
+        Self {
+            policies: Arc::clone(&self.policies),
+            defaults: self.defaults.clone(),
+            roles,
+            parser: Arc::clone(&self.parser),
+        }
+    }
+
+    /// Replace the enforced policies with `policies`, their tables qualified.
+    pub(crate) fn install(&self, policies: PolicySet) {
+        let (catalog, schema) = &self.defaults;
+        *self.policies.write().expect("policy lock poisoned") = policies.qualified(catalog, schema);
+    }
+
+    fn rewrite_scan(
+        &self,
+        policies: &PolicySet,
+        scan: TableScan,
+        config: &ConfigOptions,
+    ) -> DataFusionResult<Transformed<LogicalPlan>> {
+        // The scan's table is found with the session's defaults, policy tables are
+        // already fully qualified.
+        let defaults = &config.catalog;
+        let resolve = |table: TableReference| {
+            table.resolve(&defaults.default_catalog, &defaults.default_schema)
+        };
+        let table = resolve(scan.table_name.clone());
+        let governs = |name: &str| resolve(TableReference::from(name)) == table;
+
+        let filters: Vec<&RowFilter> = policies
+            .row_filters
+            .iter()
+            .filter(|f| governs(&f.table) && applies(&f.roles, &f.exempt_roles, &self.roles))
+            .collect();
+        let masks: Vec<&ColumnMask> = policies
+            .masks
+            .iter()
+            .filter(|m| governs(&m.table) && applies(&m.roles, &m.exempt_roles, &self.roles))
+            .collect();
+        if filters.is_empty() && masks.is_empty() {
+            return Ok(Transformed::no(LogicalPlan::TableScan(scan)));
+        }
+
+        // Row filters may reference columns the scan does not project, so scan them all
+        // and restore the original columns on top.
+        let output = Arc::clone(&scan.projected_schema);
+        let full = TableScan::try_new(
+            scan.table_name.clone(),
+            scan.source,
+            None,
+            scan.filters,
+            scan.fetch,
+        )?;
+        let full_schema = Arc::clone(&full.projected_schema);
+        let mut plan = LogicalPlan::TableScan(full);
+        if !filters.is_empty() {
+            let predicate = filters
+                .iter()
+                .map(|f| self.plan_predicate(f, &scan.table_name, &full_schema))
+                .reduce(|a, b| Ok(a?.and(b?)))
+                .expect("checked non-empty")?;
+            plan = LogicalPlan::Filter(Filter::try_new(predicate, Arc::new(plan))?);
+        }
+
+        let exprs = output
+            .iter()
+            .map(|(qualifier, field)| {
+                let column = Expr::Column(Column::new(qualifier.cloned(), field.name()));
+                match masks.iter().find(|m| m.column == *field.name()) {
+                    Some(mask) => Ok(masked(column, &mask.mask, field.data_type(), &table)?
+                        .alias_qualified(qualifier.cloned(), field.name())),
+                    None => Ok(column),
+                }
+            })
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        let projection = Projection::try_new(exprs, Arc::new(plan))?;
+        Ok(Transformed::yes(LogicalPlan::Projection(projection)))
+    }
+
+    fn plan_predicate(
+        &self,
+        filter: &RowFilter,
+        table: &TableReference,
+        schema: &DFSchema,
+    ) -> DataFusionResult<Expr> {
+        self.parser.create_logical_expr(&filter.predicate, schema).map_err(|e| {
+            DataFusionError::Plan(format!(
+                "invalid row filter '{}' on {table}: {e}",
+                filter.predicate
+            ))
+        })
+    }
+}
+
+fn masked(
+    column: Expr,
+    mask: &MaskKind,
+    data_type: &datafusion::arrow::datatypes::DataType,
+    table: &impl fmt::Display,
+) -> DataFusionResult<Expr> {
+    use datafusion::arrow::datatypes::DataType;
+
+    let is_string = matches!(data_type, DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View);
+    let string_only = |kind: &str| {
+        if is_string {
+            Ok(())
+        } else {
+            Err(DataFusionError::Plan(format!(
+                "{kind} mask on {table} requires a string column, found {data_type}"
+            )))
+        }
+    };
+    // Masks keep the column's type so the plan's schema does not change.
+    let expr = match mask {
+        MaskKind::Null => lit(ScalarValue::try_from(data_type)?),
+        MaskKind::Hash => {
+            string_only("hash")?;
+            cast(encode(sha256(column), lit("hex")), data_type.clone())
+        }
+        MaskKind::Partial { visible } => {
+            string_only("partial")?;
+            let visible = *visible as i64;
+            let hidden = greatest(vec![character_length(column.clone()) - lit(visible), lit(0i64)]);
+            let shown = right(column, lit(visible));
+            cast(
+                binary_expr(repeat(lit("*"), hidden), Operator::StringConcat, shown),
+                data_type.clone(),
+            )
+        }
+    };
+    Ok(expr)
+}
+
+impl AnalyzerRule for PolicyRule {
+    fn analyze(&self, plan: LogicalPlan, config: &ConfigOptions) -> DataFusionResult<LogicalPlan> {
+        let policies = self.policies.read().expect("policy lock poisoned");
+        if policies.is_empty() {
+            return Ok(plan);
+        }
+        let mut rewritten = false;
+        let plan = plan
+            .transform_up_with_subqueries(|plan| match plan {
+                LogicalPlan::TableScan(scan) => {
+                    let result = self.rewrite_scan(&policies, scan, config)?;
+                    rewritten |= result.transformed;
+                    Ok(result)
+                }
+                _ => Ok(Transformed::no(plan)),
+            })?
+            .data;
+        if !rewritten {
+            return Ok(plan);
+        }
+        // Masks can make columns nullable, so refresh the schemas above the scans.
+        plan.transform_up_with_subqueries(|plan| Ok(Transformed::yes(plan.recompute_schema()?)))
+            .map(|t| t.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{AsArray, RecordBatch};
+    use datafusion::arrow::compute::cast;
+    use datafusion::arrow::datatypes::{DataType, Int64Type};
+
+    async fn engine() -> DataFusionResult<QueryEngine> {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE customers (id BIGINT, email VARCHAR, card VARCHAR, region VARCHAR) \
+                 AS VALUES (1, 'ann@example.com', '4111111111111111', 'EU'), \
+                 (2, 'bob@example.com', NULL, 'US')",
+            )
+            .await?;
+        engine.set_policies(
+            PolicySet::new()
+                .with_mask(ColumnMask::new("customers", "email", MaskKind::Hash).except("admin"))
+                .with_mask(ColumnMask::new("customers", "card", MaskKind::Partial { visible: 4 }))
+                .with_mask(
+                    ColumnMask::new("customers", "id", MaskKind::Null).for_role("eu_analyst"),
+                )
+                .with_row_filter(
+                    RowFilter::new("customers", "region = 'EU'").for_role("eu_analyst"),
+                ),
+        );
+        Ok(engine)
+    }
+
+    fn strings(batches: &[RecordBatch], column: usize) -> Vec<Option<String>> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                let array = cast(b.column(column), &DataType::Utf8).unwrap();
+                array.as_string::<i32>().iter().map(|v| v.map(str::to_string)).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_masks_apply_per_role() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        let sql = "SELECT id, email, card FROM customers ORDER BY region";
+
+        let result = engine.query(sql).await?;
+        let emails = strings(&result.batches, 1);
+        assert_eq!(emails[0].as_ref().map(String::len), Some(64));
+        assert_ne!(emails[0].as_deref(), Some("ann@example.com"));
+        assert_eq!(strings(&result.batches, 2), [Some("************1111".into()), None]);
+        assert_eq!(result.batches[0].column(0).as_primitive::<Int64Type>().value(1), 2);
+
+        let admin = engine.for_roles(&["admin".to_string()]);
+        let result = admin.query(sql).await?;
+        assert_eq!(strings(&result.batches, 1)[0].as_deref(), Some("ann@example.com"));
+
+        // A user's own predicates only see masked values.
+        let result =
+            engine.query("SELECT id FROM customers WHERE email = 'ann@example.com'").await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_row_filters_apply_through_views() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        engine.query("CREATE VIEW regions AS SELECT id, region FROM customers").await?;
+        let analyst = engine.for_roles(&["eu_analyst".to_string()]);
+
+        let result = analyst.query("SELECT id, region FROM regions").await?;
+        assert_eq!(strings(&result.batches, 1), [Some("EU".to_string())]);
+        assert!(result.batches[0].column(0).is_null(0));
+
+        let result = engine.query("SELECT count(*) FROM regions").await?;
+        assert_eq!(result.batches[0].column(0).as_primitive::<Int64Type>().value(0), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_policies_are_errors() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        engine.set_policies(PolicySet::new().with_mask(ColumnMask::new(
+            "customers",
+            "id",
+            MaskKind::Hash,
+        )));
+        let err = engine.query("SELECT id FROM customers").await.unwrap_err();
+        assert!(err.to_string().contains("requires a string column"), "{err}");
+
+        let policies = PolicySet::from_json(
+            r#"{"masks": [{"table": "customers", "column": "card", "mask": {"partial": {"visible": 2}}}]}"#,
+        )?;
+        assert_eq!(policies.masks[0].mask, MaskKind::Partial { visible: 2 });
+        assert!(PolicySet::from_json(r#"{"masks": [{"mask": "blur"}]}"#).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_policies_hold_whatever_the_session_schema() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        engine.query("CREATE SCHEMA sales").await?;
+        assert_eq!(engine.policies().masks[0].table, "datafusion.public.customers");
+        let analyst = engine.for_roles(&["eu_analyst".to_string()]);
+        for (name, value) in [
+            ("search_path", "sales, public"),
+            ("schema", "sales"),
+            ("datafusion.catalog.default_schema", "sales"),
+            ("catalog", "other"),
+        ] {
+            let mut session = crate::session::SessionVars::new();
+            session.set(name, value)?;
+            let session = analyst.with_session(&session);
+            let result =
+                session.query("SELECT region, email FROM datafusion.public.customers").await?;
+            assert_eq!(strings(&result.batches, 0), [Some("EU".to_string())], "{name}");
+            assert_ne!(strings(&result.batches, 1)[0].as_deref(), Some("ann@example.com"));
+        }
+
+        // Nor does changing the engine's own defaults.
+        analyst.query("SET datafusion.catalog.default_schema = 'sales'").await?;
+        let result = analyst.query("SELECT region FROM public.customers").await?;
+        assert_eq!(strings(&result.batches, 0), [Some("EU".to_string())]);
+        Ok(())
+    }
+}
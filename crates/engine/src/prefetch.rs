@@ -0,0 +1,200 @@
+//! Overlapping the two sides of a join.
+//!
+//! A hash, nested loop or cross join first collects its build (left) side and only
+//! then reads its probe (right) side, so a scan of a remote source on the probe side
+//! does not even connect until the build side is in memory. [`PrefetchRule`], which
+//! every [`QueryEngine`](crate::QueryEngine) runs after the built-in physical
+//! optimizer rules, puts a [`PrefetchExec`] on the probe side of such joins: it
+//! starts its input as soon as the join is executed and keeps up to
+//! [`DEFAULT_PREFETCH_BATCHES`] batches per partition ready, so the probe side's
+//! fetch latency overlaps with reading the build side.
+//!
+//! When the build side turns out empty the probe side is not needed; it is then
+//! stopped with at most its prefetched batches read.
+
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::config::ConfigOptions;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::joins::{CrossJoinExec, HashJoinExec, NestedLoopJoinExec};
+use datafusion::physical_plan::stream::RecordBatchReceiverStream;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties};
+use futures::StreamExt;
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// Batches a [`PrefetchExec`] reads ahead per partition unless configured otherwise.
+pub const DEFAULT_PREFETCH_BATCHES: usize = 2;
+
+/// Prefetches the probe side of joins that collect their build side first.
+#[derive(Debug, Default)]
+pub struct PrefetchRule;
+
+impl PhysicalOptimizerRule for PrefetchRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan = plan.transform_up(|node| {
+            let any = node.as_any();
+            let collects_build_side = any.is::<HashJoinExec>()
+                || any.is::<NestedLoopJoinExec>()
+                || any.is::<CrossJoinExec>();
+            let children = node.children();
+            if !collects_build_side || children[1].as_any().is::<PrefetchExec>() {
+                return Ok(Transformed::no(node));
+            }
+            let (build, probe) = (Arc::clone(children[0]), Arc::clone(children[1]));
+            let probe = Arc::new(PrefetchExec::new(probe, DEFAULT_PREFETCH_BATCHES));
+            Ok(Transformed::yes(node.with_new_children(vec![build, probe])?))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        "prefetch"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Runs its input from when it is executed rather than first polled, keeping up to
+/// `batches` batches per partition buffered.
+#[derive(Debug)]
+pub struct PrefetchExec {
+    input: Arc<dyn ExecutionPlan>,
+    batches: usize,
+}
+
+impl PrefetchExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>, batches: usize) -> Self {
+        Self { input, batches: batches.max(1) }
+    }
+
+    /// Batches read ahead per partition.
+    pub fn batches(&self) -> usize {
+        self.batches
+    }
+}
+
+impl DisplayAs for PrefetchExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PrefetchExec: batches={}", self.batches)
+    }
+}
+
+impl ExecutionPlan for PrefetchExec {
+    fn name(&self) -> &str {
+        "PrefetchExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self::new(children.swap_remove(0), self.batches)))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let mut input = self.input.execute(partition, context)?;
+        let mut builder = RecordBatchReceiverStream::builder(input.schema(), self.batches);
+        let output = builder.tx();
+        // Stopped when the returned stream is dropped.
+        builder.spawn(async move {
+            while let Some(batch) = input.next().await {
+                let failed = batch.is_err();
+                if output.send(batch).await.is_err() || failed {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::catalog::streaming::StreamingTable;
+    use datafusion::physical_plan::displayable;
+    use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+    use datafusion::physical_plan::streaming::PartitionStream;
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+
+    /// A slow source recording when it was first polled and when it was done.
+    #[derive(Debug)]
+    struct SlowSource {
+        schema: SchemaRef,
+        times: Arc<Mutex<Vec<(Instant, Instant)>>>,
+    }
+
+    impl PartitionStream for SlowSource {
+        fn schema(&self) -> &SchemaRef {
+            &self.schema
+        }
+
+        fn execute(&self, _ctx: Arc<TaskContext>) -> SendableRecordBatchStream {
+            let (schema, times) = (Arc::clone(&self.schema), Arc::clone(&self.times));
+            let batch = futures::stream::once(async move {
+                let started = Instant::now();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                times.lock().unwrap().push((started, Instant::now()));
+                let ids = Int64Array::from(vec![1, 2, 3]);
+                Ok(RecordBatch::try_new(schema, vec![Arc::new(ids)])?)
+            });
+            Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&self.schema), batch))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_join_sides_are_read_concurrently() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let times = Arc::new(Mutex::new(Vec::new()));
+        for name in ["a", "b"] {
+            let schema = Arc::new(Schema::new(vec![Field::new(name, DataType::Int64, false)]));
+            let source = SlowSource { schema: Arc::clone(&schema), times: Arc::clone(&times) };
+            let table = StreamingTable::try_new(schema, vec![Arc::new(source)])?;
+            engine.register_table(name, Arc::new(table))?;
+        }
+
+        let sql = "SELECT * FROM a JOIN b ON a.a = b.b";
+        let plan = engine.sql(sql).await?.create_physical_plan().await?;
+        let display = displayable(plan.as_ref()).indent(false).to_string();
+        assert!(display.contains("PrefetchExec"), "{display}");
+        let result = engine.query(sql).await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+        // Both sides were started before either was done.
+        let times = times.lock().unwrap();
+        let last_started = times.iter().map(|(started, _)| *started).max().unwrap();
+        let first_done = times.iter().map(|(_, done)| *done).min().unwrap();
+        assert!(last_started < first_done, "{times:?}");
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+//! Query profiles.
+//!
+//! Aggregate metrics (`EXPLAIN ANALYZE`, see [`diagnostics`](crate::diagnostics)) say
+//! how much time each operator took in total; a profile says when. With the session
+//! variable `profile` on (`SET profile = on`, see [`session`](crate::session)), every
+//! query run through
+//! [`QueryEngine::with_running_query`](crate::QueryEngine::with_running_query)
+//! records a [`Profile`]: a timeline of
+//!
+//! - its phases: `queued` until it starts planning (waiting for admission), then
+//!   `planning` until its physical plan is ready, then `executing` until its last
+//!   operator finished;
+//! - every operator of its plan, per partition, from being executed until its output
+//!   ended, with the rows and batches it produced. Joins are split in a `build` phase,
+//!   until their first output batch, and a `probe` phase.
+//!
+//! The most recent [`MAX_PROFILES`] profiles are kept, by query id (see
+//! [`QueryEngine::profile`](crate::QueryEngine::profile)). [`Profile::timeline`] is
+//! the timeline as JSON, [`Profile::chrome_trace`] in the Chrome trace event format,
+//! for `chrome://tracing` or Perfetto, with a track per partition.
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::config::ConfigOptions;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::common::Statistics;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::{RecordBatchStream, SendableRecordBatchStream, TaskContext};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties};
+use futures::Stream;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Profiles kept by an engine.
+pub const MAX_PROFILES: usize = 100;
+
+/// The timeline of one query, see the [module docs](self).
+#[derive(Debug)]
+pub struct Profile {
+    query_id: String,
+    created: Instant,
+    planning: OnceLock<Instant>,
+    planned: OnceLock<Instant>,
+    spans: Mutex<Vec<ProfileSpan>>,
+}
+
+/// A span of a [`Timeline`], in microseconds since the query was started.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProfileSpan {
+    pub name: String,
+    /// `phase` or `operator`.
+    pub category: &'static str,
+    /// The partition an operator span ran in.
+    pub partition: Option<usize>,
+    pub start_us: u64,
+    pub duration_us: u64,
+    pub rows: Option<u64>,
+    pub batches: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Timeline {
+    pub query_id: String,
+    pub duration_us: u64,
+    /// Phases first, then operator spans by start.
+    pub spans: Vec<ProfileSpan>,
+}
+
+impl Profile {
+    pub(crate) fn new(query_id: &str) -> Self {
+        Self {
+            query_id: query_id.to_string(),
+            created: Instant::now(),
+            planning: OnceLock::new(),
+            planned: OnceLock::new(),
+            spans: Mutex::default(),
+        }
+    }
+
+    pub fn query_id(&self) -> &str {
+        &self.query_id
+    }
+
+    /// Mark the start of planning, the first time it is called.
+    pub(crate) fn planning(&self) {
+        let _ = self.planning.set(Instant::now());
+    }
+
+    pub fn timeline(&self) -> Timeline {
+        let mut operators = self.spans.lock().expect("profile lock poisoned").clone();
+        operators.sort_by_key(|span| (span.start_us, span.partition));
+        let planning = self.planning.get().map(|&at| self.micros(at)).unwrap_or(0);
+        let planned = self.planned.get().map(|&at| self.micros(at)).unwrap_or(planning);
+        let mut spans = vec![phase("queued", 0, planning), phase("planning", planning, planned)];
+        let end = operators.iter().map(|span| span.start_us + span.duration_us).max();
+        if let Some(end) = end {
+            let start = operators.iter().map(|span| span.start_us).min().unwrap_or(end);
+            spans.push(phase("executing", start, end));
+        }
+        let duration_us = end.unwrap_or(planned);
+        spans.extend(operators);
+        Timeline { query_id: self.query_id.clone(), duration_us, spans }
+    }
+
+    /// The timeline in the Chrome trace event format: phases on the `query` track,
+    /// operators on one track per partition.
+    pub fn chrome_trace(&self) -> Value {
+        let timeline = self.timeline();
+        let track = |span: &ProfileSpan| span.partition.map_or(0, |partition| partition + 1);
+        let mut tracks: Vec<_> = timeline.spans.iter().map(track).collect();
+        tracks.sort_unstable();
+        tracks.dedup();
+        let mut events: Vec<_> = tracks
+            .into_iter()
+            .map(|tid| {
+                let name = match tid {
+                    0 => "query".to_string(),
+                    tid => format!("partition {}", tid - 1),
+                };
+                json!({"name": "thread_name", "ph": "M", "pid": 0, "tid": tid, "args": {"name": name}})
+            })
+            .collect();
+        events.extend(timeline.spans.iter().map(|span| {
+            json!({
+                "name": span.name,
+                "cat": span.category,
+                "ph": "X",
+                "ts": span.start_us,
+                "dur": span.duration_us,
+                "pid": 0,
+                "tid": track(span),
+                "args": {"rows": span.rows, "batches": span.batches},
+            })
+        }));
+        json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+            "otherData": {"query_id": timeline.query_id},
+        })
+    }
+
+    fn micros(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.created).as_micros() as u64
+    }
+
+    fn record(
+        &self,
+        name: String,
+        partition: usize,
+        (start, end): (Instant, Instant),
+        output: [u64; 2],
+    ) {
+        let start_us = self.micros(start);
+        let span = ProfileSpan {
+            name,
+            category: "operator",
+            partition: Some(partition),
+            start_us,
+            duration_us: self.micros(end).saturating_sub(start_us),
+            rows: Some(output[0]),
+            batches: Some(output[1]),
+        };
+        self.spans.lock().expect("profile lock poisoned").push(span);
+    }
+}
+
+fn phase(name: &str, start_us: u64, end_us: u64) -> ProfileSpan {
+    ProfileSpan {
+        name: name.to_string(),
+        category: "phase",
+        partition: None,
+        start_us,
+        duration_us: end_us.saturating_sub(start_us),
+        rows: None,
+        batches: None,
+    }
+}
+
+/// The most recent profiles of an engine and its tenants.
+#[derive(Debug, Default)]
+pub(crate) struct Profiles {
+    profiles: Mutex<VecDeque<Arc<Profile>>>,
+}
+
+impl Profiles {
+    pub(crate) fn start(&self, query_id: &str) -> Arc<Profile> {
+        let profile = Arc::new(Profile::new(query_id));
+        let mut profiles = self.profiles.lock().expect("profiles lock poisoned");
+        profiles.push_back(Arc::clone(&profile));
+        while profiles.len() > MAX_PROFILES {
+            profiles.pop_front();
+        }
+        profile
+    }
+
+    pub(crate) fn get(&self, query_id: &str) -> Option<Arc<Profile>> {
+        let profiles = self.profiles.lock().expect("profiles lock poisoned");
+        profiles.iter().rev().find(|profile| profile.query_id == query_id).cloned()
+    }
+}
+
+/// Profiles every operator of a plan, ending the `planning` phase.
+#[derive(Debug)]
+pub(crate) struct ProfileRule {
+    pub(crate) profile: Arc<Profile>,
+}
+
+impl PhysicalOptimizerRule for ProfileRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let _ = self.profile.planned.set(Instant::now());
+        plan.transform_up(|node| {
+            if node.as_any().is::<ProfiledExec>() {
+                return Ok(Transformed::no(node));
+            }
+            let profile = Arc::clone(&self.profile);
+            Ok(Transformed::yes(Arc::new(ProfiledExec { inner: node, profile })))
+        })
+        .map(|transformed| transformed.data)
+    }
+
+    fn name(&self) -> &str {
+        "profile"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// An operator timed for a profile. It shows, and has the children of, the operator
+/// itself, so plans display as they would without profiling.
+#[derive(Debug)]
+struct ProfiledExec {
+    inner: Arc<dyn ExecutionPlan>,
+    profile: Arc<Profile>,
+}
+
+impl DisplayAs for ProfiledExec {
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_as(t, f)
+    }
+}
+
+impl ExecutionPlan for ProfiledExec {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.inner.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        self.inner.children()
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let inner = Arc::clone(&self.inner).with_new_children(children)?;
+        Ok(Arc::new(ProfiledExec { inner, profile: Arc::clone(&self.profile) }))
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        self.inner.metrics()
+    }
+
+    fn partition_statistics(&self, partition: Option<usize>) -> DataFusionResult<Statistics> {
+        self.inner.partition_statistics(partition)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let started = Instant::now();
+        let inner = self.inner.execute(partition, context)?;
+        Ok(Box::pin(ProfiledStream {
+            inner,
+            profile: Arc::clone(&self.profile),
+            name: self.inner.name().to_string(),
+            partition,
+            started,
+            first_batch: None,
+            output: [0, 0],
+            finished: false,
+        }))
+    }
+}
+
+/// An operator's output, recorded in its profile when it ends or is dropped early.
+struct ProfiledStream {
+    inner: SendableRecordBatchStream,
+    profile: Arc<Profile>,
+    name: String,
+    partition: usize,
+    started: Instant,
+    first_batch: Option<Instant>,
+    /// Rows and batches produced.
+    output: [u64; 2],
+    finished: bool,
+}
+
+impl Stream for ProfiledStream {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(batch))) => {
+                let rows = batch.num_rows() as u64;
+                self.first_batch.get_or_insert_with(Instant::now);
+                self.output[0] += rows;
+                self.output[1] += 1;
+            }
+            Poll::Ready(_) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl RecordBatchStream for ProfiledStream {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+impl Drop for ProfiledStream {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+impl ProfiledStream {
+    /// Record the operator's spans, once.
+    fn finish(&mut self) {
+        if std::mem::replace(&mut self.finished, true) {
+            return;
+        }
+        let ended = Instant::now();
+        let (name, partition, profile) = (&self.name, self.partition, &self.profile);
+        match self.first_batch {
+            Some(first_batch) if name.contains("Join") => {
+                profile.record(
+                    format!("{name} build"),
+                    partition,
+                    (self.started, first_batch),
+                    [0, 0],
+                );
+                profile.record(
+                    format!("{name} probe"),
+                    partition,
+                    (first_batch, ended),
+                    self.output,
+                );
+            }
+            _ => profile.record(name.clone(), partition, (self.started, ended), self.output),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::running::QueryStart;
+    use crate::session::SessionVars;
+    use crate::QueryEngine;
+    use datafusion::error::Result as DataFusionResult;
+
+    fn start(query_id: &str) -> QueryStart {
+        QueryStart {
+            query_id: query_id.to_string(),
+            principal: None,
+            frontend: "http".to_string(),
+            sql: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_profiled_queries_have_a_timeline() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let mut session = SessionVars::new();
+        session.set("profile", "on")?;
+        let profiled = engine.with_session(&session).with_running_query(start("q1"));
+        profiled
+            .query(
+                "SELECT count(*) FROM range(1000) a \
+                 JOIN range(100) b ON a.value = b.value",
+            )
+            .await?;
+
+        let timeline = engine.profile("q1").unwrap().timeline();
+        let names: Vec<_> = timeline.spans.iter().map(|span| span.name.as_str()).collect();
+        assert_eq!(names[..3], ["queued", "planning", "executing"]);
+        assert!(names.iter().any(|name| name.ends_with("JoinExec build")), "{names:?}");
+        assert!(names.iter().any(|name| name.ends_with("JoinExec probe")), "{names:?}");
+        let aggregate = timeline.spans.iter().find(|span| span.name == "AggregateExec").unwrap();
+        assert_eq!(aggregate.category, "operator");
+        assert!(timeline.duration_us >= aggregate.start_us + aggregate.duration_us);
+
+        let trace = engine.profile("q1").unwrap().chrome_trace();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert!(events.iter().any(|e| e["ph"] == "M" && e["args"]["name"] == "query"));
+        assert!(events.iter().any(|e| e["ph"] == "X" && e["name"] == "planning"));
+
+        engine.with_running_query(start("q2")).query("SELECT 1").await?;
+        assert!(engine.profile("q2").is_none());
+        Ok(())
+    }
+}
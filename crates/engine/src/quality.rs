@@ -0,0 +1,317 @@
+//! Data quality checks.
+//!
+//! A [`QualityCheck`] asserts something of a registered table and is run as a SQL
+//! query against it, which measures one value:
+//!
+//! - [`Check::NotNull`]: the rows where the column is null, none allowed;
+//! - [`Check::Unique`]: the values of the columns held by more than one row, none
+//!   allowed;
+//! - [`Check::Range`]: the rows where the column is below `min` or above `max`, none
+//!   allowed;
+//! - [`Check::RowCountDelta`]: the rows of the table, which may differ from those of
+//!   the previous run by at most `max_change` of them (0.1 for 10%); the first run
+//!   only records the count;
+//! - [`Check::Freshness`]: the seconds since the latest value of the column, at most
+//!   `max_age`. An empty table fails.
+//!
+//! [`QualityChecks`] holds the checks of a deployment and the [`CheckResult`] of the
+//! latest run of each. Running them periodically is up to the caller, e.g. as tasks
+//! of a [`Scheduler`](crate::scheduler::Scheduler).
+
+use crate::QueryEngine;
+use datafusion::arrow::array::{Array, Float64Array};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What a [`QualityCheck`] asserts of its table, see the [module](self) documentation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Check {
+    NotNull {
+        column: String,
+    },
+    Unique {
+        columns: Vec<String>,
+    },
+    /// `min` and `max` are SQL literals, e.g. `0` or `'2024-01-01'`; either may be
+    /// left out.
+    Range {
+        column: String,
+        min: Option<String>,
+        max: Option<String>,
+    },
+    RowCountDelta {
+        max_change: f64,
+    },
+    Freshness {
+        column: String,
+        max_age: Duration,
+    },
+}
+
+impl Check {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Check::NotNull { .. } => "not_null",
+            Check::Unique { .. } => "unique",
+            Check::Range { .. } => "range",
+            Check::RowCountDelta { .. } => "row_count_delta",
+            Check::Freshness { .. } => "freshness",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityCheck {
+    pub table: String,
+    pub check: Check,
+}
+
+impl QualityCheck {
+    pub fn new(table: impl Into<String>, check: Check) -> Self {
+        Self { table: table.into(), check }
+    }
+
+    /// The check's kind and what it checks, e.g. `not_null orders.id`.
+    pub fn name(&self) -> String {
+        let (kind, table) = (self.check.kind(), &self.table);
+        match &self.check {
+            Check::NotNull { column }
+            | Check::Range { column, .. }
+            | Check::Freshness { column, .. } => format!("{kind} {table}.{column}"),
+            Check::Unique { columns } => format!("{kind} {table}({})", columns.join(", ")),
+            Check::RowCountDelta { .. } => format!("{kind} {table}"),
+        }
+    }
+
+    /// The query measuring the check's value.
+    fn sql(&self) -> String {
+        let table = &self.table;
+        match &self.check {
+            Check::NotNull { column } => {
+                format!("SELECT count(*) FROM {table} WHERE {} IS NULL", quote_ident(column))
+            }
+            Check::Unique { columns } => {
+                let columns: Vec<_> = columns.iter().map(|c| quote_ident(c)).collect();
+                format!(
+                    "SELECT count(*) FROM (SELECT {columns} FROM {table} GROUP BY {columns} \
+                     HAVING count(*) > 1)",
+                    columns = columns.join(", ")
+                )
+            }
+            Check::Range { column, min, max } => {
+                let column = quote_ident(column);
+                let mut outside = Vec::new();
+                if let Some(min) = min {
+                    outside.push(format!("{column} < {min}"));
+                }
+                if let Some(max) = max {
+                    outside.push(format!("{column} > {max}"));
+                }
+                if outside.is_empty() {
+                    return "SELECT 0".to_string();
+                }
+                format!("SELECT count(*) FROM {table} WHERE {}", outside.join(" OR "))
+            }
+            Check::RowCountDelta { .. } => format!("SELECT count(*) FROM {table}"),
+            Check::Freshness { column, .. } => format!(
+                "SELECT date_part('epoch', now()) - date_part('epoch', max({})) FROM {table}",
+                quote_ident(column)
+            ),
+        }
+    }
+
+    /// Whether `observed` passes the check, given the value of the previous run, and
+    /// why not.
+    fn evaluate(&self, observed: Option<f64>, previous: Option<f64>) -> Result<(), String> {
+        let Some(value) = observed else {
+            return Err("the table has no rows".to_string());
+        };
+        match &self.check {
+            Check::NotNull { .. } if value > 0.0 => Err(format!("{value} rows are null")),
+            Check::Unique { .. } if value > 0.0 => {
+                Err(format!("{value} values are held by more than one row"))
+            }
+            Check::Range { .. } if value > 0.0 => Err(format!("{value} rows are out of range")),
+            Check::RowCountDelta { max_change } => {
+                let Some(previous) = previous else {
+                    return Ok(());
+                };
+                let change = (value - previous).abs() / previous.max(1.0);
+                if change <= *max_change {
+                    return Ok(());
+                }
+                Err(format!(
+                    "{value} rows, {previous} at the previous check: a change of {:.1}%",
+                    change * 100.0
+                ))
+            }
+            Check::Freshness { max_age, .. } if value > max_age.as_secs_f64() => Err(format!(
+                "the latest value is {value:.0} seconds old, more than {}",
+                max_age.as_secs()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    /// Not run yet.
+    Pending,
+    Passed,
+    Failed,
+    /// The check could not be run, e.g. because the table is missing.
+    Error,
+}
+
+/// The outcome of the latest run of a check.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub table: String,
+    pub check: &'static str,
+    pub status: CheckStatus,
+    /// The value measured, see the [module](self) documentation.
+    pub observed: Option<f64>,
+    /// Why the check failed or could not be run; empty otherwise.
+    pub message: String,
+    /// When it was run, in milliseconds since the Unix epoch.
+    pub checked_at_ms: Option<u64>,
+}
+
+/// A deployment's checks and their latest results.
+#[derive(Debug)]
+pub struct QualityChecks {
+    checks: Vec<QualityCheck>,
+    results: Mutex<Vec<CheckResult>>,
+}
+
+impl QualityChecks {
+    pub fn new(checks: Vec<QualityCheck>) -> Self {
+        let results = checks
+            .iter()
+            .map(|check| CheckResult {
+                name: check.name(),
+                table: check.table.clone(),
+                check: check.check.kind(),
+                status: CheckStatus::Pending,
+                observed: None,
+                message: String::new(),
+                checked_at_ms: None,
+            })
+            .collect();
+        Self { checks, results: Mutex::new(results) }
+    }
+
+    pub fn checks(&self) -> &[QualityCheck] {
+        &self.checks
+    }
+
+    /// The latest result of every check, in the order of the checks.
+    pub fn results(&self) -> Vec<CheckResult> {
+        self.results.lock().unwrap().clone()
+    }
+
+    /// Run the check at `index` with `engine`, recording and returning its result.
+    pub async fn run(&self, engine: &QueryEngine, index: usize) -> CheckResult {
+        let check = &self.checks[index];
+        let measured = measure(engine, &check.sql()).await;
+        let mut results = self.results.lock().unwrap();
+        let result = &mut results[index];
+        let previous = result.observed;
+        result.checked_at_ms =
+            Some(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64));
+        match measured {
+            Ok(observed) => {
+                result.observed = observed;
+                (result.status, result.message) = match check.evaluate(observed, previous) {
+                    Ok(()) => (CheckStatus::Passed, String::new()),
+                    Err(message) => (CheckStatus::Failed, message),
+                };
+            }
+            Err(e) => {
+                result.observed = None;
+                result.status = CheckStatus::Error;
+                result.message = e.to_string();
+            }
+        }
+        result.clone()
+    }
+}
+
+/// The single value returned by `sql`, as a float; `None` if it is null.
+async fn measure(engine: &QueryEngine, sql: &str) -> DataFusionResult<Option<f64>> {
+    let result = engine.query(sql).await?;
+    let Some(column) = result.batches.iter().find(|b| b.num_rows() > 0).map(|b| b.column(0)) else {
+        return Err(DataFusionError::Execution(format!("{sql} returned no rows")));
+    };
+    let values = cast(column, &DataType::Float64)?;
+    let values = values.as_any().downcast_ref::<Float64Array>().expect("cast to Float64");
+    Ok(values.is_valid(0).then(|| values.value(0)))
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_checks_pass_fail_and_compare_with_the_previous_run() {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE orders (id BIGINT, total DOUBLE, placed TIMESTAMP) AS VALUES \
+                 (1, 10.0, now()), (2, NULL, now()), (2, -5.0, now() - INTERVAL '2 hours')",
+            )
+            .await
+            .unwrap();
+        let range = |min: &str, max: Option<&str>| Check::Range {
+            column: "total".to_string(),
+            min: Some(min.to_string()),
+            max: max.map(str::to_string),
+        };
+        let checks = QualityChecks::new(vec![
+            QualityCheck::new("orders", Check::NotNull { column: "id".to_string() }),
+            QualityCheck::new("orders", Check::NotNull { column: "total".to_string() }),
+            QualityCheck::new("orders", Check::Unique { columns: vec!["id".to_string()] }),
+            QualityCheck::new("orders", range("0", Some("100"))),
+            QualityCheck::new("orders", range("-10", None)),
+            QualityCheck::new("orders", Check::RowCountDelta { max_change: 0.5 }),
+            QualityCheck::new(
+                "orders",
+                Check::Freshness {
+                    column: "placed".to_string(),
+                    max_age: Duration::from_secs(600),
+                },
+            ),
+            QualityCheck::new("missing", Check::RowCountDelta { max_change: 0.5 }),
+        ]);
+        assert!(checks.results().iter().all(|r| r.status == CheckStatus::Pending));
+        for index in 0..checks.checks().len() {
+            checks.run(&engine, index).await;
+        }
+        let results = checks.results();
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        use CheckStatus::*;
+        assert_eq!(statuses, [Passed, Failed, Failed, Failed, Passed, Passed, Passed, Error]);
+        assert_eq!(results[1].name, "not_null orders.total");
+        assert_eq!(results[1].message, "1 rows are null");
+        assert_eq!(results[2].name, "unique orders(id)");
+        assert_eq!(results[3].observed, Some(1.0));
+        assert!(results[6].observed.unwrap() < 600.0);
+
+        engine.query("INSERT INTO orders VALUES (3, 1.0, now()), (4, 2.0, now())").await.unwrap();
+        let result = checks.run(&engine, 5).await;
+        assert_eq!(result.status, Failed);
+        assert_eq!(result.message, "5 rows, 3 at the previous check: a change of 66.7%");
+        assert_eq!(checks.run(&engine, 5).await.status, Passed);
+    }
+}
@@ -0,0 +1,487 @@
+//! Per-query memory and CPU budgets.
+//!
+//! A [`ResourceManager`] installed with
+//! [`QueryEngine::with_resource_manager`](crate::QueryEngine::with_resource_manager)
+//! sorts queries into [`ResourceClass`]es by the principal running them, or into the
+//! [`DEFAULT_CLASS`] for principals without one. The engine returned by
+//! [`QueryEngine::with_resources`](crate::QueryEngine::with_resources) runs its
+//! queries within their class's budget:
+//!
+//! - memory: the queries get a reservation of at most the class's memory limit,
+//!   taken from the engine's memory pool (and with it the tenant's). Operators that
+//!   can spill do so once it is reached; a query that cannot is stopped with a
+//!   `ResourcesExhausted` error rather than taking memory other queries need;
+//! - CPU: queries take turns producing their result batches, at most the manager's
+//!   number of slots at once. When queries wait, the next turn goes to the one that
+//!   has used the least time for its class's CPU weight, so a query of weight 2 gets
+//!   about twice the time of one of weight 1 and a long-running query falls behind
+//!   newly started ones. The time of a turn includes any wait on the query's sources.
+
+use datafusion::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::memory_pool::{
+    MemoryConsumer, MemoryLimit, MemoryPool, MemoryReservation,
+};
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::execution::session_state::{SessionState, SessionStateBuilder};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties};
+use futures::StreamExt;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+/// Class of queries whose principal has none assigned.
+pub const DEFAULT_CLASS: &str = "default";
+
+/// A class of queries and the budget each of its queries gets. Unlimited memory and a
+/// CPU weight of 1 by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceClass {
+    name: String,
+    memory_limit: Option<usize>,
+    cpu_weight: u32,
+}
+
+impl ResourceClass {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), memory_limit: None, cpu_weight: 1 }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Bytes of memory a query of the class may hold.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Share of CPU time relative to other classes' queries (at least 1).
+    pub fn with_cpu_weight(mut self, weight: u32) -> Self {
+        self.cpu_weight = weight.max(1);
+        self
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    pub fn cpu_weight(&self) -> u32 {
+        self.cpu_weight
+    }
+}
+
+/// Assigns queries to resource classes and schedules their CPU time.
+#[derive(Debug)]
+pub struct ResourceManager {
+    classes: HashMap<String, ResourceClass>,
+    principals: HashMap<String, String>,
+    cpu: Arc<CpuScheduler>,
+}
+
+impl ResourceManager {
+    /// A manager letting `cpu_slots` queries produce batches at once, with only the
+    /// unlimited [`DEFAULT_CLASS`].
+    pub fn new(cpu_slots: usize) -> Self {
+        let default = ResourceClass::new(DEFAULT_CLASS);
+        Self {
+            classes: HashMap::from([(DEFAULT_CLASS.to_string(), default)]),
+            principals: HashMap::new(),
+            cpu: Arc::new(CpuScheduler::new(cpu_slots)),
+        }
+    }
+
+    /// Add `class`, replacing any class of the same name (including the default one).
+    pub fn with_class(mut self, class: ResourceClass) -> Self {
+        self.classes.insert(class.name.clone(), class);
+        self
+    }
+
+    /// Run the queries of the principal with subject `subject` in class `class`.
+    pub fn with_principal(mut self, subject: impl Into<String>, class: impl Into<String>) -> Self {
+        self.principals.insert(subject.into(), class.into());
+        self
+    }
+
+    /// The class of `principal`'s queries. Principals assigned an unknown class get
+    /// the default one.
+    pub fn class_for(&self, principal: Option<&str>) -> &ResourceClass {
+        principal
+            .and_then(|subject| self.principals.get(subject))
+            .and_then(|class| self.classes.get(class))
+            .unwrap_or_else(|| &self.classes[DEFAULT_CLASS])
+    }
+
+    /// `state` with a budget of `principal`'s class: a memory pool of its own drawing
+    /// on `state`'s, and CPU turns for every plan's output.
+    pub(crate) fn query_state(&self, state: SessionState, principal: Option<&str>) -> SessionState {
+        let class = self.class_for(principal).clone();
+        let runtime = state.runtime_env();
+        let pool = QueryMemoryPool::new(Arc::clone(&runtime.memory_pool), class.clone());
+        let runtime = Arc::new(RuntimeEnv {
+            memory_pool: Arc::new(pool),
+            disk_manager: Arc::clone(&runtime.disk_manager),
+            cache_manager: Arc::clone(&runtime.cache_manager),
+            object_store_registry: Arc::clone(&runtime.object_store_registry),
+        });
+        let share = CpuShare::new(Arc::clone(&self.cpu), class);
+        SessionStateBuilder::new_from_existing(state)
+            .with_runtime_env(runtime)
+            .with_physical_optimizer_rule(Arc::new(CpuShareRule { share }))
+            .build()
+    }
+}
+
+/// Memory pool of a query's budget: reservations count against the class's limit
+/// and are taken from the engine's pool.
+#[derive(Debug)]
+struct QueryMemoryPool {
+    pool: Arc<dyn MemoryPool>,
+    class: ResourceClass,
+    used: AtomicUsize,
+}
+
+impl QueryMemoryPool {
+    fn new(pool: Arc<dyn MemoryPool>, class: ResourceClass) -> Self {
+        Self { pool, class, used: AtomicUsize::new(0) }
+    }
+}
+
+impl MemoryPool for QueryMemoryPool {
+    fn register(&self, consumer: &MemoryConsumer) {
+        self.pool.register(consumer)
+    }
+
+    fn unregister(&self, consumer: &MemoryConsumer) {
+        self.pool.unregister(consumer)
+    }
+
+    fn grow(&self, reservation: &MemoryReservation, additional: usize) {
+        self.pool.grow(reservation, additional);
+        self.used.fetch_add(additional, Ordering::Relaxed);
+    }
+
+    fn shrink(&self, reservation: &MemoryReservation, shrink: usize) {
+        self.pool.shrink(reservation, shrink);
+        self.used.fetch_sub(shrink, Ordering::Relaxed);
+    }
+
+    fn try_grow(&self, reservation: &MemoryReservation, additional: usize) -> DataFusionResult<()> {
+        let limit = self.class.memory_limit.unwrap_or(usize::MAX);
+        let grown = self.used.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+            used.checked_add(additional).filter(|&total| total <= limit)
+        });
+        if let Err(used) = grown {
+            return Err(DataFusionError::ResourcesExhausted(format!(
+                "{} could not allocate {additional} bytes: query of resource class '{}' \
+                 would exceed its memory budget of {limit} bytes ({used} bytes in use)",
+                reservation.consumer().name(),
+                self.class.name,
+            )));
+        }
+        let grown = self.pool.try_grow(reservation, additional);
+        if grown.is_err() {
+            self.used.fetch_sub(additional, Ordering::Relaxed);
+        }
+        grown
+    }
+
+    fn reserved(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn memory_limit(&self) -> MemoryLimit {
+        match self.class.memory_limit {
+            Some(limit) => MemoryLimit::Finite(limit),
+            None => self.pool.memory_limit(),
+        }
+    }
+}
+
+/// Hands out turns to produce a batch, `slots` at a time, by weighted fair share.
+#[derive(Debug)]
+struct CpuScheduler {
+    slots: usize,
+    state: Mutex<CpuState>,
+}
+
+#[derive(Debug, Default)]
+struct CpuState {
+    running: usize,
+    waiting: Vec<Waiter>,
+    next_seq: u64,
+    /// Virtual time of the latest turn handed out, where new queries start.
+    now: f64,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    pass: f64,
+    seq: u64,
+    wake: oneshot::Sender<()>,
+}
+
+impl CpuScheduler {
+    fn new(slots: usize) -> Self {
+        Self { slots: slots.max(1), state: Mutex::default() }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CpuState> {
+        self.state.lock().expect("cpu scheduler lock poisoned")
+    }
+
+    /// Free a slot, handing it to the waiter furthest behind if there is one.
+    fn release(&self) {
+        let mut state = self.lock();
+        loop {
+            let next = (0..state.waiting.len()).min_by(|&a, &b| {
+                let (a, b) = (&state.waiting[a], &state.waiting[b]);
+                a.pass.total_cmp(&b.pass).then(a.seq.cmp(&b.seq))
+            });
+            let Some(next) = next else {
+                state.running -= 1;
+                return;
+            };
+            let waiter = state.waiting.swap_remove(next);
+            state.now = state.now.max(waiter.pass);
+            // A waiter that gave up no longer needs the slot.
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+    }
+}
+
+/// One query's claim on the CPU scheduler. Clones (one per output partition) share
+/// the query's time used.
+#[derive(Debug, Clone)]
+struct CpuShare {
+    scheduler: Arc<CpuScheduler>,
+    class: ResourceClass,
+    /// Time used divided by the class's weight, in virtual seconds.
+    pass: Arc<Mutex<f64>>,
+}
+
+impl CpuShare {
+    fn new(scheduler: Arc<CpuScheduler>, class: ResourceClass) -> Self {
+        let now = scheduler.lock().now;
+        Self { scheduler, class, pass: Arc::new(Mutex::new(now)) }
+    }
+
+    fn pass(&self) -> f64 {
+        *self.pass.lock().expect("cpu share lock poisoned")
+    }
+
+    async fn turn(&self) -> Turn {
+        let wake = {
+            let mut state = self.scheduler.lock();
+            if state.running < self.scheduler.slots && state.waiting.is_empty() {
+                state.running += 1;
+                state.now = state.now.max(self.pass());
+                return Turn { share: self.clone(), started: Instant::now() };
+            }
+            let (wake, woken) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter { pass: self.pass(), seq, wake });
+            Waiting { scheduler: Arc::clone(&self.scheduler), woken }
+        };
+        wake.wait().await;
+        Turn { share: self.clone(), started: Instant::now() }
+    }
+
+    /// `stream` producing each batch in a turn of its own.
+    fn wrap(&self, stream: SendableRecordBatchStream) -> SendableRecordBatchStream {
+        let schema = stream.schema();
+        let share = self.clone();
+        let batches = futures::stream::unfold(stream, move |mut stream| {
+            let share = share.clone();
+            async move {
+                let _turn = share.turn().await;
+                let batch = stream.next().await?;
+                Some((batch, stream))
+            }
+        });
+        Box::pin(RecordBatchStreamAdapter::new(schema, batches))
+    }
+}
+
+/// A turn being waited for. Dropped after the slot was handed over but before the
+/// waiter woke up, it passes the slot on.
+struct Waiting {
+    scheduler: Arc<CpuScheduler>,
+    woken: oneshot::Receiver<()>,
+}
+
+impl Waiting {
+    async fn wait(mut self) {
+        // The scheduler outlives its waiters, so the sender is never dropped unsent.
+        let _ = (&mut self.woken).await;
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        if self.woken.try_recv().is_ok() {
+            self.scheduler.release();
+        }
+    }
+}
+
+/// A slot held while a batch is produced. Dropping it charges the time to the query.
+struct Turn {
+    share: CpuShare,
+    started: Instant,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let used = self.started.elapsed().as_secs_f64() / self.share.class.cpu_weight as f64;
+        *self.share.pass.lock().expect("cpu share lock poisoned") += used;
+        self.share.scheduler.release();
+    }
+}
+
+/// Puts a [`CpuShareExec`] on top of every plan.
+#[derive(Debug)]
+struct CpuShareRule {
+    share: CpuShare,
+}
+
+impl PhysicalOptimizerRule for CpuShareRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if plan.as_any().is::<CpuShareExec>() {
+            return Ok(plan);
+        }
+        Ok(Arc::new(CpuShareExec::new(plan, self.share.clone())))
+    }
+
+    fn name(&self) -> &str {
+        "cpu_share"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Produces its input's batches in turns of the query's CPU share.
+#[derive(Debug)]
+pub struct CpuShareExec {
+    input: Arc<dyn ExecutionPlan>,
+    share: CpuShare,
+}
+
+impl CpuShareExec {
+    fn new(input: Arc<dyn ExecutionPlan>, share: CpuShare) -> Self {
+        Self { input, share }
+    }
+}
+
+impl DisplayAs for CpuShareExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        let class = &self.share.class;
+        write!(f, "CpuShareExec: class={}, weight={}", class.name, class.cpu_weight)
+    }
+}
+
+impl ExecutionPlan for CpuShareExec {
+    fn name(&self) -> &str {
+        "CpuShareExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self::new(children.swap_remove(0), self.share.clone())))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        Ok(self.share.wrap(self.input.execute(partition, context)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_queries_stay_within_memory_budget() -> DataFusionResult<()> {
+        let resources = ResourceManager::new(4)
+            .with_class(ResourceClass::new("small").with_memory_limit(1))
+            .with_principal("alice", "small");
+        let engine = QueryEngine::new().with_resource_manager(Arc::new(resources));
+        engine.query("CREATE TABLE t AS SELECT * FROM generate_series(1, 100000)").await?;
+
+        let sql = "SELECT * FROM t ORDER BY value DESC";
+        let err = engine.with_resources(Some("alice")).query(sql).await.unwrap_err();
+        assert!(err.to_string().contains("memory budget of 1 bytes"), "{err}");
+        let result = engine.with_resources(Some("bob")).query(sql).await?;
+        assert_eq!(result.batches.iter().map(|b| b.num_rows()).sum::<usize>(), 100000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_turns_go_to_queries_furthest_behind() {
+        let scheduler = Arc::new(CpuScheduler::new(1));
+        let share = |weight| {
+            let class = ResourceClass::new(format!("w{weight}")).with_cpu_weight(weight);
+            CpuShare::new(Arc::clone(&scheduler), class)
+        };
+        let (light, heavy) = (share(1), share(4));
+        // Both have used the same time, which counts a quarter for `heavy`.
+        for share in [&light, &heavy] {
+            let mut turn = share.turn().await;
+            turn.started -= Duration::from_secs(4);
+        }
+        assert!(light.pass() >= 4.0 && heavy.pass() < 2.0);
+
+        let running = share(1).turn().await;
+        let (order, mut turns) = tokio::sync::mpsc::unbounded_channel();
+        for (name, share) in [("light", light), ("heavy", heavy)] {
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _turn = share.turn().await;
+                order.send(name).unwrap();
+            });
+        }
+        tokio::task::yield_now().await;
+        while scheduler.lock().waiting.len() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        drop(running);
+        assert_eq!(turns.recv().await, Some("heavy"));
+        assert_eq!(turns.recv().await, Some("light"));
+    }
+}
@@ -0,0 +1,601 @@
+//! Caching query results by plan.
+//!
+//! A [`ResultCache`] given to [`QueryEngine::with_result_cache`](crate::QueryEngine::with_result_cache)
+//! serves the results of [`QueryEngine::query`](crate::QueryEngine::query) again when
+//! a query of the same plan is run. Results are keyed by their [`CanonicalPlan`]: the
+//! optimized logical plan, written so that queries meaning the same share it:
+//!
+//! - literals are parameters (`$1`, `$2`, ...) whose values are part of the key, so
+//!   queries differing only in constants share the plan part of their keys;
+//! - the output columns are in a canonical order and without their aliases, the
+//!   result being put back in the order and under the names each query asks for;
+//! - the conjuncts of predicates are in a canonical order;
+//! - tables scanned once under an alias are referred to by their names;
+//! - tables are referred to by their full names, so sessions with other default
+//!   catalogs or schemas do not share results of tables named alike.
+//!
+//! The session settings results depend on, such as the time zone, are part of the
+//! key too. Parentheses and other differences of the SQL text are gone once planned. Queries
+//! calling volatile functions (`random()`), reading system tables or not reading
+//! (statements) are not cached.
+//!
+//! Writes through the engine (`INSERT`, `MERGE`, drops, ingestion, ...) evict the
+//! results of the tables they write once they are committed, so `INSERT`, `UPDATE`
+//! and `DELETE` run when planned rather than when their count is read. Changes the engine does not see, such as new
+//! files under an external table, are picked up once results expire, see
+//! [`ResultCache::with_ttl`].
+//!
+//! Results are not copied: their buffers are shared with the queries they are served
+//! to, and counted once in the cache's [`BufferAccounting`].
+
+use crate::catalog_store::full_name;
+use crate::tenant::min_timeout;
+use crate::SYSTEM_CATALOG;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::{RecordBatch, RecordBatchOptions};
+use datafusion::common::config::{CatalogOptions, ConfigOptions};
+use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion};
+use datafusion::common::{Column, ScalarValue};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::expr::Placeholder;
+use datafusion::logical_expr::utils::{conjunction, split_conjunction_owned};
+use datafusion::logical_expr::{Expr, LogicalPlan};
+use datafusion::sql::TableReference;
+use igloo_cache::BufferAccounting;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What a result is cached under: its canonical plan and the values of its
+/// parameters.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PlanKey {
+    /// The canonical plan, as `EXPLAIN` shows it.
+    pub plan: String,
+    pub params: Vec<ScalarValue>,
+    /// The session settings results depend on, such as the time zone, as `key=value`.
+    pub settings: Vec<String>,
+}
+
+/// Session settings the results of a plan depend on.
+const RESULT_SETTINGS: &[&str] = &["datafusion.execution.time_zone"];
+
+/// A query plan in canonical form, see the [module](self) documentation.
+#[derive(Debug, Clone)]
+pub struct CanonicalPlan {
+    pub key: PlanKey,
+    /// The query's output column at each position of the canonical plan's output.
+    columns: Vec<usize>,
+    /// Full names of the tables scanned.
+    tables: Vec<String>,
+}
+
+impl CanonicalPlan {
+    /// The canonical form of the optimized `plan`, or `None` if its result cannot be
+    /// cached. Table names resolve against the default catalog and schema of `config`.
+    pub fn of(plan: &LogicalPlan, config: &ConfigOptions) -> DataFusionResult<Option<Self>> {
+        let options = &config.catalog;
+        let Some(tables) = cacheable_tables(plan, options)? else {
+            return Ok(None);
+        };
+        let (plan, columns) = output_order(plan.clone());
+        let plan = without_table_aliases(plan)?;
+        let mut params = vec![];
+        let plan = plan
+            .transform_down_with_subqueries(|node| {
+                let node = match node {
+                    LogicalPlan::TableScan(mut scan) => {
+                        scan.filters.sort_by_cached_key(ToString::to_string);
+                        let name = scan
+                            .table_name
+                            .clone()
+                            .resolve(&options.default_catalog, &options.default_schema);
+                        scan.table_name =
+                            TableReference::full(name.catalog, name.schema, name.table);
+                        LogicalPlan::TableScan(scan)
+                    }
+                    node => node,
+                };
+                node.map_expressions(|expr| {
+                    sorted_conjuncts(expr).transform_up(|expr| match expr {
+                        Expr::Literal(value, _) => {
+                            params.push(value);
+                            let id = format!("${}", params.len());
+                            Ok(Transformed::yes(Expr::Placeholder(Placeholder::new(id, None))))
+                        }
+                        expr => Ok(Transformed::no(expr)),
+                    })
+                })
+            })?
+            .data;
+        let settings = config
+            .entries()
+            .into_iter()
+            .filter(|entry| RESULT_SETTINGS.contains(&entry.key.as_str()))
+            .map(|entry| format!("{}={}", entry.key, entry.value.unwrap_or_default()))
+            .collect();
+        let key = PlanKey { plan: plan.display_indent().to_string(), params, settings };
+        Ok(Some(CanonicalPlan { key, columns, tables }))
+    }
+
+    /// Full names of the tables the plan scans.
+    pub fn tables(&self) -> &[String] {
+        &self.tables
+    }
+}
+
+/// Full names of the tables `plan` scans, or `None` if its result cannot be cached.
+fn cacheable_tables(
+    plan: &LogicalPlan,
+    options: &CatalogOptions,
+) -> DataFusionResult<Option<Vec<String>>> {
+    let mut tables = Some(vec![]);
+    plan.apply_with_subqueries(|node| {
+        let cacheable = match node {
+            LogicalPlan::Dml(_)
+            | LogicalPlan::Ddl(_)
+            | LogicalPlan::Copy(_)
+            | LogicalPlan::Explain(_)
+            | LogicalPlan::Analyze(_)
+            | LogicalPlan::Statement(_)
+            | LogicalPlan::DescribeTable(_)
+            | LogicalPlan::Extension(_) => false,
+            LogicalPlan::TableScan(scan) => {
+                let name = scan.table_name.clone();
+                let resolved =
+                    name.clone().resolve(&options.default_catalog, &options.default_schema);
+                let system = resolved.catalog.as_ref() == SYSTEM_CATALOG
+                    || resolved.schema.as_ref() == "information_schema";
+                if let Some(tables) = &mut tables {
+                    tables.push(full_name(name, options));
+                }
+                !system
+            }
+            node => !node.expressions().iter().any(Expr::is_volatile),
+        };
+        if cacheable {
+            return Ok(TreeNodeRecursion::Continue);
+        }
+        tables = None;
+        Ok(TreeNodeRecursion::Stop)
+    })?;
+    Ok(tables)
+}
+
+/// `plan` with its output columns unaliased and in canonical order, and the position
+/// in `plan`'s output of each of them. Only the columns of a projection at the root
+/// (or under a limit) are reordered: above it nothing refers to them by name.
+fn output_order(plan: LogicalPlan) -> (LogicalPlan, Vec<usize>) {
+    match plan {
+        LogicalPlan::Projection(mut projection) => {
+            let exprs: Vec<_> = projection.expr.into_iter().map(Expr::unalias).collect();
+            let mut columns: Vec<usize> = (0..exprs.len()).collect();
+            columns.sort_by_cached_key(|&i| exprs[i].to_string());
+            projection.expr = columns.iter().map(|&i| exprs[i].clone()).collect();
+            (LogicalPlan::Projection(projection), columns)
+        }
+        LogicalPlan::Limit(mut limit) => {
+            let (input, columns) = output_order(limit.input.as_ref().clone());
+            limit.input = Arc::new(input);
+            (LogicalPlan::Limit(limit), columns)
+        }
+        plan => {
+            let columns = (0..plan.schema().fields().len()).collect();
+            (plan, columns)
+        }
+    }
+}
+
+/// `plan` without the aliases of tables it scans once, their columns qualified by
+/// the table's name instead, and without the projections of only columns this leaves
+/// under another projection. Aliases also naming something else are kept, so no two
+/// relations end up under one name.
+fn without_table_aliases(plan: LogicalPlan) -> DataFusionResult<LogicalPlan> {
+    let mut scans: HashMap<TableReference, usize> = HashMap::new();
+    let mut aliases: HashMap<TableReference, usize> = HashMap::new();
+    plan.apply_with_subqueries(|node| {
+        match node {
+            LogicalPlan::TableScan(scan) => *scans.entry(scan.table_name.clone()).or_default() += 1,
+            LogicalPlan::SubqueryAlias(alias) => {
+                *aliases.entry(alias.alias.clone()).or_default() += 1
+            }
+            _ => {}
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    let mut renames = HashMap::new();
+    plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::SubqueryAlias(alias) = node {
+            if let Some(table) = single_table(&alias.input)? {
+                if scans[&table] == 1 && aliases[&alias.alias] == 1 && !aliases.contains_key(&table)
+                {
+                    renames.insert(alias.alias.clone(), table);
+                }
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    })?;
+    if renames.is_empty() {
+        return Ok(plan);
+    }
+    let plan = plan.transform_up_with_subqueries(|node| match node {
+        LogicalPlan::SubqueryAlias(alias) if renames.contains_key(&alias.alias) => {
+            Ok(Transformed::yes(alias.input.as_ref().clone()))
+        }
+        node => {
+            let node = match node {
+                LogicalPlan::Projection(mut projection) => {
+                    if let LogicalPlan::Projection(input) = projection.input.as_ref() {
+                        if input.expr.iter().all(|expr| matches!(expr, Expr::Column(_))) {
+                            projection.input = Arc::clone(&input.input);
+                        }
+                    }
+                    LogicalPlan::Projection(projection)
+                }
+                node => node,
+            };
+            node.map_expressions(|expr| {
+                expr.transform(|expr| match expr {
+                    Expr::Column(Column { relation: Some(relation), name, .. })
+                        if renames.contains_key(&relation) =>
+                    {
+                        let table = renames[&relation].clone();
+                        Ok(Transformed::yes(Expr::Column(Column::new(Some(table), name))))
+                    }
+                    expr => Ok(Transformed::no(expr)),
+                })
+            })
+        }
+    })?;
+    Ok(plan.data)
+}
+
+/// The table `plan` reads, if it only projects and filters the scan of one table.
+fn single_table(plan: &LogicalPlan) -> DataFusionResult<Option<TableReference>> {
+    let mut table = None;
+    let mut single = true;
+    plan.apply(|node| {
+        match node {
+            LogicalPlan::TableScan(scan) if table.is_none() => {
+                table = Some(scan.table_name.clone())
+            }
+            LogicalPlan::Projection(_) | LogicalPlan::Filter(_) => {}
+            _ => single = false,
+        }
+        Ok(if single { TreeNodeRecursion::Continue } else { TreeNodeRecursion::Stop })
+    })?;
+    Ok(table.filter(|table| single && plan.schema().iter().all(|(q, _)| q == Some(table))))
+}
+
+/// `expr` with its conjuncts in canonical order, if it is a conjunction.
+fn sorted_conjuncts(expr: Expr) -> Expr {
+    let mut conjuncts = split_conjunction_owned(expr);
+    conjuncts.sort_by_cached_key(ToString::to_string);
+    conjunction(conjuncts).expect("a split expression has a conjunct")
+}
+
+/// The results of queries, by [`CanonicalPlan`], least recently used evicted first
+/// beyond its capacity.
+#[derive(Debug)]
+pub struct ResultCache {
+    capacity_bytes: usize,
+    ttl: Option<Duration>,
+    accounting: Arc<BufferAccounting>,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    results: HashMap<PlanKey, CachedResult>,
+    /// Incremented on each use, for least recently used eviction.
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CachedResult {
+    /// In the canonical plan's column order.
+    batches: Vec<RecordBatch>,
+    tables: Vec<String>,
+    cached: Instant,
+    /// How long it is served, if less than forever.
+    ttl: Option<Duration>,
+    used: u64,
+}
+
+impl ResultCache {
+    /// A cache holding up to `capacity_bytes` of results, which never expire.
+    pub fn new(capacity_bytes: usize) -> Self {
+        ResultCache {
+            capacity_bytes,
+            ttl: None,
+            accounting: Arc::default(),
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Results expire `ttl` after they were cached.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        ResultCache { ttl: Some(ttl), ..self }
+    }
+
+    /// Count the buffers of results in `accounting`, shared with other caches holding
+    /// the same data (see [`igloo_cache::Cache::with_accounting`]). The capacity then
+    /// bounds the bytes of all of them.
+    pub fn with_accounting(self, accounting: Arc<BufferAccounting>) -> Self {
+        ResultCache { accounting, ..self }
+    }
+
+    /// Bytes held by the cache's [`BufferAccounting`], buffers shared between results
+    /// counted once.
+    pub fn size_bytes(&self) -> usize {
+        self.accounting.bytes()
+    }
+
+    /// Number of results cached.
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("result cache lock poisoned").results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cached result of `plan`, as `schema`.
+    pub fn get(&self, plan: &CanonicalPlan, schema: &SchemaRef) -> Option<Vec<RecordBatch>> {
+        let mut entries = self.entries.lock().expect("result cache lock poisoned");
+        let result = entries.results.get(&plan.key)?;
+        if result.ttl.is_some_and(|ttl| result.cached.elapsed() >= ttl) {
+            let expired = entries.results.remove(&plan.key).expect("present");
+            self.accounting.remove(&expired.batches);
+            return None;
+        }
+        // The canonical position of each of the query's columns.
+        let mut positions = vec![0; plan.columns.len()];
+        for (position, &column) in plan.columns.iter().enumerate() {
+            positions[column] = position;
+        }
+        let batches = result
+            .batches
+            .iter()
+            .map(|batch| {
+                let columns = positions.iter().map(|&p| Arc::clone(batch.column(p))).collect();
+                let options = RecordBatchOptions::new().with_row_count(Some(batch.num_rows()));
+                RecordBatch::try_new_with_options(Arc::clone(schema), columns, &options).ok()
+            })
+            .collect::<Option<Vec<_>>>()?;
+        entries.clock += 1;
+        let clock = entries.clock;
+        entries.results.get_mut(&plan.key).expect("present").used = clock;
+        Some(batches)
+    }
+
+    /// Cache `batches` as the result of `plan`, evicting the least recently used
+    /// results beyond the capacity. Results larger than the capacity are not cached.
+    pub fn put(&self, plan: &CanonicalPlan, batches: &[RecordBatch]) -> DataFusionResult<()> {
+        self.put_with_ttl(plan, batches, None)
+    }
+
+    /// [`Self::put`], the result expiring after `ttl` if that comes before the
+    /// cache's own expiry.
+    pub fn put_with_ttl(
+        &self,
+        plan: &CanonicalPlan,
+        batches: &[RecordBatch],
+        ttl: Option<Duration>,
+    ) -> DataFusionResult<()> {
+        if igloo_cache::memory_size(batches) > self.capacity_bytes {
+            return Ok(());
+        }
+        let batches = batches
+            .iter()
+            .map(|batch| batch.project(&plan.columns))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut entries = self.entries.lock().expect("result cache lock poisoned");
+        entries.clock += 1;
+        self.accounting.add(&batches);
+        let result = CachedResult {
+            batches,
+            tables: plan.tables.clone(),
+            cached: Instant::now(),
+            ttl: min_timeout(self.ttl, ttl),
+            used: entries.clock,
+        };
+        if let Some(replaced) = entries.results.insert(plan.key.clone(), result) {
+            self.accounting.remove(&replaced.batches);
+        }
+        while self.accounting.bytes() > self.capacity_bytes {
+            let oldest = entries.results.iter().min_by_key(|(_, result)| result.used);
+            let Some(key) = oldest.map(|(key, _)| key.clone()) else {
+                break;
+            };
+            let evicted = entries.results.remove(&key).expect("present");
+            self.accounting.remove(&evicted.batches);
+        }
+        Ok(())
+    }
+
+    /// Evict the results reading `table`, by full name.
+    pub fn invalidate(&self, table: &str) {
+        let mut entries = self.entries.lock().expect("result cache lock poisoned");
+        entries.results.retain(|_, result| {
+            let keep = !result.tables.iter().any(|t| t == table);
+            if !keep {
+                self.accounting.remove(&result.batches);
+            }
+            keep
+        });
+    }
+
+    /// Evict every result.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().expect("result cache lock poisoned");
+        for (_, result) in entries.results.drain() {
+            self.accounting.remove(&result.batches);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+
+    async fn engine() -> DataFusionResult<QueryEngine> {
+        let engine = QueryEngine::new().with_result_cache(Arc::new(ResultCache::new(1 << 20)));
+        engine.sql("CREATE TABLE orders (id INT, region TEXT, amount DOUBLE)").await?;
+        let insert = "INSERT INTO orders VALUES (1, 'eu', 12.5), (2, 'us', 30), (3, 'eu', 40)";
+        engine.sql(insert).await?.collect().await?;
+        Ok(engine)
+    }
+
+    async fn canonical(engine: &QueryEngine, sql: &str) -> DataFusionResult<Option<PlanKey>> {
+        let plan = engine.session_context().sql(sql).await?.into_optimized_plan()?;
+        let state = engine.session_context().state();
+        let canonical = CanonicalPlan::of(&plan, state.config().options())?;
+        Ok(canonical.map(|canonical| canonical.key))
+    }
+
+    #[tokio::test]
+    async fn test_equivalent_queries_share_keys() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        let key = canonical(
+            &engine,
+            "SELECT o.id AS order_id, o.amount FROM orders o \
+             WHERE (o.region = 'eu') AND (o.amount > 10)",
+        )
+        .await?
+        .unwrap();
+        let same = "SELECT amount, id FROM orders WHERE amount > 10 AND region = 'eu'";
+        assert_eq!(canonical(&engine, same).await?.unwrap(), key);
+        assert_eq!(
+            key.params,
+            vec![ScalarValue::Float64(Some(10.0)), ScalarValue::Utf8(Some("eu".to_string()))]
+        );
+
+        let other =
+            canonical(&engine, "SELECT amount, id FROM orders WHERE amount > 20 AND region = 'eu'")
+                .await?
+                .unwrap();
+        assert_eq!(other.plan, key.plan);
+        assert_ne!(other.params, key.params);
+        let sum = "SELECT sum(amount) FROM orders WHERE amount > 10 AND region = 'eu'";
+        assert_ne!(canonical(&engine, sum).await?.unwrap().plan, key.plan);
+
+        assert_eq!(canonical(&engine, "SELECT random() FROM orders").await?, None);
+        assert_eq!(canonical(&engine, "INSERT INTO orders VALUES (4, 'eu', 1)").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_results_are_served_until_written() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        let first = engine.query("SELECT id, amount FROM orders WHERE region = 'eu'").await?;
+        assert!(!first.cache_hit);
+
+        let sql = "SELECT o.amount AS total, o.id FROM orders AS o WHERE o.region = 'eu'";
+        let hit = engine.query(sql).await?;
+        assert!(hit.cache_hit);
+        assert_eq!(hit.scanned_bytes, 0);
+        assert_eq!(
+            pretty_format_batches(&hit.batches)?.to_string(),
+            "+-------+----+\n\
+             | total | id |\n\
+             +-------+----+\n\
+             | 12.5  | 1  |\n\
+             | 40.0  | 3  |\n\
+             +-------+----+"
+        );
+        let report = engine.memory_report();
+        let results = report.caches.iter().find(|cache| cache.name == "results").unwrap();
+        assert!(results.bytes > 0);
+        assert!(!engine.query("SELECT id FROM orders WHERE region = 'us'").await?.cache_hit);
+
+        // Run, and the results it changes evicted, before its count is read.
+        let insert = engine.sql("INSERT INTO orders VALUES (4, 'eu', 5)").await?;
+        let written = engine.query(sql).await?;
+        assert!(!written.cache_hit);
+        assert_eq!(written.batches.iter().map(RecordBatch::num_rows).sum::<usize>(), 3);
+        let count = pretty_format_batches(&insert.collect().await?)?.to_string();
+        assert_eq!(count, "+-------+\n| count |\n+-------+\n| 1     |\n+-------+");
+        assert!(engine.query(sql).await?.cache_hit);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sessions_only_share_results_of_the_same_tables() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        engine.sql("CREATE SCHEMA sales").await?;
+        engine
+            .sql("CREATE TABLE sales.orders (id INT, region TEXT, amount DOUBLE) AS VALUES (9, 'eu', 1)")
+            .await?;
+        let sql = "SELECT id FROM orders WHERE region = 'eu' ORDER BY id";
+        let ids = |result: &crate::QueryResult| {
+            pretty_format_batches(&result.batches).map(|t| t.to_string())
+        };
+        let public = engine.query(sql).await?;
+        assert!(!public.cache_hit);
+
+        let mut session = crate::session::SessionVars::new();
+        session.set("search_path", "sales")?;
+        let sales = engine.with_session(&session);
+        let result = sales.query(sql).await?;
+        assert!(!result.cache_hit);
+        assert_eq!(ids(&result)?, "+----+\n| id |\n+----+\n| 9  |\n+----+");
+        assert!(sales.query(sql).await?.cache_hit);
+        assert_eq!(ids(&engine.query(sql).await?)?, ids(&public)?);
+
+        let mut session = crate::session::SessionVars::new();
+        session.set("time_zone", "+02:00")?;
+        let key = canonical(&engine, sql).await?.unwrap();
+        let shifted = canonical(&engine.with_session(&session), sql).await?.unwrap();
+        assert_eq!((&shifted.plan, &shifted.params), (&key.plan, &key.params));
+        assert_ne!(shifted.settings, key.settings);
+        assert!(!engine.with_session(&session).query(sql).await?.cache_hit);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_results_are_evicted() -> DataFusionResult<()> {
+        let engine = engine().await?;
+        let state = engine.session_context().state();
+        let options = state.config().options();
+        let mut plans = vec![];
+        let mut results = vec![];
+        for sql in [
+            "SELECT amount + 1 FROM orders",
+            "SELECT amount + 2 FROM orders",
+            "SELECT amount + 3 FROM orders",
+        ] {
+            let df = engine.sql(sql).await?;
+            plans.push(CanonicalPlan::of(&df.clone().into_optimized_plan()?, options)?.unwrap());
+            results.push(df.collect().await?);
+        }
+        let size = igloo_cache::memory_size(&results[0]);
+        assert!(results.iter().all(|result| igloo_cache::memory_size(result) == size));
+        let schema = results[0][0].schema();
+        let cache = ResultCache::new(2 * size);
+        cache.put(&plans[0], &results[0])?;
+        cache.put(&plans[1], &results[1])?;
+        assert_eq!(cache.size_bytes(), 2 * size);
+        cache.get(&plans[0], &schema).unwrap();
+        cache.put(&plans[2], &results[2])?;
+        assert!(cache.get(&plans[1], &schema).is_none());
+        assert!(cache.get(&plans[0], &schema).is_some());
+        assert_eq!((cache.len(), cache.size_bytes()), (2, 2 * size));
+
+        // Results sharing buffers take no more room.
+        let renamed = engine.sql("SELECT amount + 1 AS total FROM orders WHERE id > 0").await?;
+        let renamed = CanonicalPlan::of(&renamed.into_optimized_plan()?, options)?.unwrap();
+        cache.put(&renamed, &results[0])?;
+        assert_eq!((cache.len(), cache.size_bytes()), (3, 2 * size));
+        cache.invalidate("datafusion.public.orders");
+        assert_eq!((cache.len(), cache.size_bytes()), (0, 0));
+
+        let expiring = ResultCache::new(size).with_ttl(Duration::ZERO);
+        expiring.put(&plans[0], &results[0])?;
+        assert!(expiring.get(&plans[0], &schema).is_none());
+        assert!(expiring.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,463 @@
+//! Running queries.
+//!
+//! An engine returned by
+//! [`QueryEngine::with_running_query`](crate::QueryEngine::with_running_query) lists
+//! its statement among the engine's running queries for as long as it or a plan it
+//! created is alive, with what it is doing and what it uses:
+//!
+//! ```sql
+//! SHOW QUERIES
+//! ```
+//!
+//! returns one row per [`RunningQuery`]: its id, principal, frontend and SQL, its
+//! [`Stage`], how long it has been running, the rows it has returned, the bytes it has
+//! scanned and the memory it holds (see [`memory`](crate::memory)). A tenant's engine
+//! lists the queries of that tenant, the engine it was added to those of all tenants.
+//!
+//! ```sql
+//! KILL '<query_id>'
+//! ```
+//!
+//! stops a running query: its result stream fails with an error saying it was
+//! killed, releasing what its plan holds, and a query not executing yet fails as
+//! soon as it starts to. `QUERY` may follow `KILL`, and the quotes may be left out.
+//!
+//! # Draining
+//!
+//! Before shutting down, [`QueryEngine::drain`](crate::QueryEngine::drain) stops an
+//! engine and its tenants from admitting statements: [`QueryEngine::sql`] fails with
+//! a `ResourcesExhausted` error saying the engine is shutting down, while the queries
+//! already running go on.
+//! [`QueryEngine::wait_idle`](crate::QueryEngine::wait_idle) then waits, up to a
+//! deadline, for them to finish.
+
+use crate::diagnostics::scanned_bytes;
+use crate::memory::QueryMemory;
+use datafusion::arrow::array::{ArrayRef, StringArray, UInt64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::common::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties};
+use futures::StreamExt;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// A statement about to run, as it is listed among the running queries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryStart {
+    pub query_id: String,
+    pub principal: Option<String>,
+    /// The frontend it came through.
+    pub frontend: String,
+    pub sql: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Parsing, planning or waiting to be admitted.
+    Planning,
+    /// Producing its result.
+    Executing,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Planning => "planning",
+            Stage::Executing => "executing",
+        }
+    }
+}
+
+/// A running query, as listed by `SHOW QUERIES`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunningQuery {
+    pub query_id: String,
+    pub principal: Option<String>,
+    pub frontend: String,
+    pub sql: String,
+    pub stage: Stage,
+    pub elapsed_ms: u64,
+    /// Rows returned so far.
+    pub rows: u64,
+    /// Bytes read from sources so far.
+    pub scanned_bytes: u64,
+    /// Memory reserved from the memory pool, and the most it has reserved at once.
+    pub memory_bytes: u64,
+    pub peak_memory_bytes: u64,
+}
+
+/// The queries of one engine, tracked for as long as something still uses them.
+#[derive(Debug, Default)]
+pub(crate) struct RunningQueries {
+    queries: Mutex<HashMap<String, Weak<QueryState>>>,
+    /// No statements are admitted once set, see [`Self::drain`].
+    draining: AtomicBool,
+}
+
+impl RunningQueries {
+    /// Track `start`, returning the physical optimizer rule that ties the query's
+    /// plans to it.
+    pub(crate) fn start(&self, start: QueryStart) -> RunningQueryRule {
+        let query_id = start.query_id.clone();
+        let state = Arc::new(QueryState {
+            start,
+            started: Instant::now(),
+            executing: AtomicBool::new(false),
+            rows: AtomicU64::new(0),
+            plan: Mutex::new(None),
+            killed: watch::channel(false).0,
+        });
+        let mut queries = self.lock();
+        queries.retain(|_, query| query.strong_count() > 0);
+        queries.insert(query_id, Arc::downgrade(&state));
+        RunningQueryRule { state }
+    }
+
+    /// The queries still running, longest running first, with the memory they hold
+    /// as reported by `memory`.
+    pub(crate) fn list(&self, memory: &[QueryMemory]) -> Vec<RunningQuery> {
+        let states: Vec<_> = self.lock().values().filter_map(Weak::upgrade).collect();
+        let mut queries: Vec<_> = states
+            .iter()
+            .map(|state| {
+                let plan = state.plan.lock().expect("running query lock poisoned").clone();
+                let memory = memory.iter().find(|m| m.query_id == state.start.query_id);
+                RunningQuery {
+                    query_id: state.start.query_id.clone(),
+                    principal: state.start.principal.clone(),
+                    frontend: state.start.frontend.clone(),
+                    sql: state.start.sql.clone(),
+                    stage: match state.executing.load(Ordering::Relaxed) {
+                        true => Stage::Executing,
+                        false => Stage::Planning,
+                    },
+                    elapsed_ms: state.started.elapsed().as_millis() as u64,
+                    rows: state.rows.load(Ordering::Relaxed),
+                    scanned_bytes: plan.as_ref().map(scanned_bytes).unwrap_or_default(),
+                    memory_bytes: memory.map_or(0, |m| m.reserved_bytes as u64),
+                    peak_memory_bytes: memory.map_or(0, |m| m.peak_bytes as u64),
+                }
+            })
+            .collect();
+        queries.sort_by_key(|query| std::cmp::Reverse(query.elapsed_ms));
+        queries
+    }
+
+    /// Kill the query `query_id`. False if it is not running.
+    pub(crate) fn kill(&self, query_id: &str) -> bool {
+        let Some(state) = self.lock().get(query_id).and_then(Weak::upgrade) else {
+            return false;
+        };
+        state.killed.send_replace(true);
+        true
+    }
+
+    /// Stop admitting statements, see the [module](self) documentation.
+    pub(crate) fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    /// The error of a statement submitted once draining, if it is.
+    pub(crate) fn check_admitted(&self) -> DataFusionResult<()> {
+        if self.draining.load(Ordering::Relaxed) {
+            return Err(DataFusionError::ResourcesExhausted(
+                "the engine is shutting down and admits no new queries".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Weak<QueryState>>> {
+        self.queries.lock().expect("running queries lock poisoned")
+    }
+}
+
+/// What a running query has done so far.
+#[derive(Debug)]
+struct QueryState {
+    start: QueryStart,
+    started: Instant,
+    executing: AtomicBool,
+    rows: AtomicU64,
+    plan: Mutex<Option<Arc<dyn ExecutionPlan>>>,
+    killed: watch::Sender<bool>,
+}
+
+impl QueryState {
+    fn killed_error(&self) -> DataFusionError {
+        DataFusionError::Execution(format!("query {} was killed", self.start.query_id))
+    }
+}
+
+/// Puts a [`RunningQueryExec`] on top of every plan of a running query.
+#[derive(Debug)]
+pub(crate) struct RunningQueryRule {
+    state: Arc<QueryState>,
+}
+
+impl PhysicalOptimizerRule for RunningQueryRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if plan.as_any().is::<RunningQueryExec>() {
+            return Ok(plan);
+        }
+        *self.state.plan.lock().expect("running query lock poisoned") = Some(Arc::clone(&plan));
+        Ok(Arc::new(RunningQueryExec { input: plan, state: Arc::clone(&self.state) }))
+    }
+
+    fn name(&self) -> &str {
+        "running_query"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Counts the rows of its input for its running query, and fails once it is killed.
+#[derive(Debug)]
+pub(crate) struct RunningQueryExec {
+    input: Arc<dyn ExecutionPlan>,
+    state: Arc<QueryState>,
+}
+
+impl DisplayAs for RunningQueryExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RunningQueryExec: query_id={}", self.state.start.query_id)
+    }
+}
+
+impl ExecutionPlan for RunningQueryExec {
+    fn name(&self) -> &str {
+        "RunningQueryExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let input = children.swap_remove(0);
+        Ok(Arc::new(RunningQueryExec { input, state: Arc::clone(&self.state) }))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        if *self.state.killed.borrow() {
+            return Err(self.state.killed_error());
+        }
+        self.state.executing.store(true, Ordering::Relaxed);
+        let stream = self.input.execute(partition, context)?;
+        let schema = stream.schema();
+        let state = Arc::clone(&self.state);
+        let batches = futures::stream::unfold(Some(stream), move |stream| {
+            let state = Arc::clone(&state);
+            async move {
+                let mut stream = stream?;
+                let mut killed = state.killed.subscribe();
+                tokio::select! {
+                    biased;
+                    // The sender lives as long as `state`.
+                    _ = killed.wait_for(|killed| *killed) => {
+                        Some((Err(state.killed_error()), None))
+                    }
+                    batch = stream.next() => {
+                        let batch = batch?;
+                        if let Ok(batch) = &batch {
+                            state.rows.fetch_add(batch.num_rows() as u64, Ordering::Relaxed);
+                        }
+                        Some((batch, Some(stream)))
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(schema, batches)))
+    }
+}
+
+/// `SHOW QUERIES`, with or without a trailing semicolon.
+pub(crate) fn is_show_queries_sql(sql: &str) -> bool {
+    let words: Vec<_> = sql.trim().trim_end_matches(';').split_whitespace().collect();
+    matches!(words.as_slice(), [show, queries]
+        if show.eq_ignore_ascii_case("show") && queries.eq_ignore_ascii_case("queries"))
+}
+
+/// The query id of `KILL [QUERY] '<query_id>'`, or `None` if `sql` is something else.
+pub(crate) fn parse_kill_sql(sql: &str) -> DataFusionResult<Option<String>> {
+    let words: Vec<_> = sql.trim().trim_end_matches(';').split_whitespace().collect();
+    let id = match words.as_slice() {
+        [kill, rest @ ..] if kill.eq_ignore_ascii_case("kill") => match rest {
+            [query, id] if query.eq_ignore_ascii_case("query") => id,
+            [id] => id,
+            _ => return Err(DataFusionError::Plan("expected KILL [QUERY] '<query_id>'".into())),
+        },
+        _ => return Ok(None),
+    };
+    let id = id.strip_prefix('\'').and_then(|id| id.strip_suffix('\'')).unwrap_or(id);
+    Ok(Some(id.to_string()))
+}
+
+/// `queries` as the result of `SHOW QUERIES`.
+pub(crate) fn to_batch(queries: &[RunningQuery]) -> DataFusionResult<RecordBatch> {
+    let schema = Schema::new(vec![
+        Field::new("query_id", DataType::Utf8, false),
+        Field::new("principal", DataType::Utf8, true),
+        Field::new("frontend", DataType::Utf8, false),
+        Field::new("sql", DataType::Utf8, false),
+        Field::new("stage", DataType::Utf8, false),
+        Field::new("elapsed_ms", DataType::UInt64, false),
+        Field::new("rows", DataType::UInt64, false),
+        Field::new("scanned_bytes", DataType::UInt64, false),
+        Field::new("memory_bytes", DataType::UInt64, false),
+        Field::new("peak_memory_bytes", DataType::UInt64, false),
+    ]);
+    let strings = |f: fn(&RunningQuery) -> Option<&str>| -> ArrayRef {
+        Arc::new(queries.iter().map(f).collect::<StringArray>())
+    };
+    let numbers = |f: fn(&RunningQuery) -> u64| -> ArrayRef {
+        Arc::new(queries.iter().map(|q| Some(f(q))).collect::<UInt64Array>())
+    };
+    let columns = vec![
+        strings(|q| Some(&q.query_id)),
+        strings(|q| q.principal.as_deref()),
+        strings(|q| Some(&q.frontend)),
+        strings(|q| Some(&q.sql)),
+        strings(|q| Some(q.stage.name())),
+        numbers(|q| q.elapsed_ms),
+        numbers(|q| q.rows),
+        numbers(|q| q.scanned_bytes),
+        numbers(|q| q.memory_bytes),
+        numbers(|q| q.peak_memory_bytes),
+    ];
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::AsArray;
+    use std::time::Duration;
+
+    fn start(query_id: &str, sql: &str) -> QueryStart {
+        QueryStart {
+            query_id: query_id.to_string(),
+            principal: Some("alice".to_string()),
+            frontend: "http".to_string(),
+            sql: sql.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_kill_sql() {
+        assert_eq!(parse_kill_sql("KILL 'q1'").unwrap(), Some("q1".to_string()));
+        assert_eq!(parse_kill_sql("kill query q1;").unwrap(), Some("q1".to_string()));
+        assert_eq!(parse_kill_sql("SELECT 1").unwrap(), None);
+        assert!(parse_kill_sql("KILL").is_err());
+        assert!(is_show_queries_sql("show queries;"));
+        assert!(!is_show_queries_sql("SHOW TABLES"));
+    }
+
+    #[tokio::test]
+    async fn test_running_queries_are_listed_and_killed() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let sql = "SELECT * FROM range(100000000)";
+        let running = engine.with_running_query(start("q1", sql));
+        let df = running.sql(sql).await?;
+        let mut stream = df.execute_stream().await?;
+        stream.next().await.unwrap()?;
+
+        let queries = engine.running_queries();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].stage, Stage::Executing);
+        assert!(queries[0].rows > 0);
+        let batches = engine.sql("SHOW QUERIES").await?.collect().await?;
+        assert_eq!(batches[0].column(0).as_string::<i32>().value(0), "q1");
+        assert_eq!(batches[0].column(4).as_string::<i32>().value(0), "executing");
+
+        engine.sql("KILL 'q1'").await?;
+        let error = stream.next().await.unwrap().unwrap_err();
+        assert!(error.to_string().contains("query q1 was killed"), "{error}");
+        assert!(engine.sql("KILL 'q2'").await.is_err());
+
+        drop((running, stream));
+        assert!(engine.running_queries().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tenants_list_only_their_own_queries() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let globex = engine.add_tenant(crate::tenant::Tenant::new("globex"))?;
+        let _root = engine.with_running_query(start("q1", "SELECT 1"));
+        let _tenant = globex.with_running_query(start("q2", "SELECT 2"));
+
+        let ids = |queries: Vec<RunningQuery>| -> Vec<String> {
+            let mut ids: Vec<_> = queries.into_iter().map(|query| query.query_id).collect();
+            ids.sort();
+            ids
+        };
+        assert_eq!(ids(engine.running_queries()), ["q1", "q2"]);
+        assert_eq!(ids(globex.running_queries()), ["q2"]);
+        assert!(!globex.kill_query("q1"));
+        assert!(engine.kill_query("q2"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_draining_stops_admission_and_waits_for_running_queries() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let globex = engine.add_tenant(crate::tenant::Tenant::new("globex"))?;
+        let running = engine.with_running_query(start("q1", "SELECT 1"));
+        let df = running.sql("SELECT 1").await?;
+
+        engine.drain();
+        assert!(engine.is_draining());
+        for engine in [&engine, &running, &globex] {
+            let error = engine.sql("SELECT 2").await.unwrap_err();
+            assert!(error.to_string().contains("shutting down"), "{error}");
+        }
+        let later = engine.add_tenant(crate::tenant::Tenant::new("initech"))?;
+        assert!(later.sql("SELECT 2").await.is_err());
+        assert!(!engine.wait_idle(Duration::from_millis(100)).await);
+
+        let batches = df.collect().await?;
+        assert_eq!(batches[0].num_rows(), 1);
+        drop(running);
+        assert!(engine.wait_idle(Duration::from_secs(1)).await);
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+//! Reading the files of a scan in parallel.
+//!
+//! A file scan reads each of its partitions' files one after the next, so a scan of a
+//! few partitions over many files on an object store waits on one request per
+//! partition at a time and uses a fraction of the network bandwidth. With a
+//! [`ScanIo`] given to [`QueryEngine::with_scan_io`](crate::QueryEngine::with_scan_io):
+//!
+//! - [`SplitScansRule`], run right after the join strategy is chosen (so before
+//!   DataFusion plans exchanges), spreads the files of a scan over up to
+//!   [`ScanIo::files`] partitions, which are read at once;
+//! - [`PrefetchScansRule`], run after the other physical optimizer rules, reads up to
+//!   [`ScanIo::prefetch_batches`] batches of each partition ahead (see
+//!   [`PrefetchExec`]), so a Parquet scan fetches its next row groups while the ones
+//!   read are processed;
+//! - [`LimitedStores`] keeps up to [`ScanIo::max_requests`] requests in flight per
+//!   object store, so parallel scans and queries do not overload it.
+//!
+//! Scans with a limit are not split, as they may not need most of their files.
+
+use crate::prefetch::PrefetchExec;
+use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion};
+use datafusion::config::ConfigOptions;
+use datafusion::datasource::listing::PartitionedFile;
+use datafusion::datasource::physical_plan::{FileGroup, FileScanConfig, FileScanConfigBuilder};
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::object_store::ObjectStoreRegistry;
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::ExecutionPlan;
+use object_store::limit::LimitStore;
+use object_store::ObjectStore;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use url::Url;
+
+/// How file scans read their files, see the [module](self) documentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanIo {
+    /// Files a scan reads at once.
+    pub files: usize,
+    /// Batches each partition of a scan reads ahead.
+    pub prefetch_batches: usize,
+    /// Requests in flight per object store.
+    pub max_requests: usize,
+}
+
+impl Default for ScanIo {
+    fn default() -> Self {
+        ScanIo { files: 16, prefetch_batches: 4, max_requests: 64 }
+    }
+}
+
+/// The file scan configuration of `plan`, if it is a file scan.
+fn file_scan(plan: &Arc<dyn ExecutionPlan>) -> Option<&FileScanConfig> {
+    let scan = plan.as_any().downcast_ref::<DataSourceExec>()?;
+    scan.data_source().as_any().downcast_ref::<FileScanConfig>()
+}
+
+/// Spreads the files of scans over partitions, see the [module](self) documentation.
+#[derive(Debug)]
+pub struct SplitScansRule {
+    files: usize,
+}
+
+impl SplitScansRule {
+    pub const NAME: &'static str = "split_scans";
+
+    pub fn new(io: ScanIo) -> Self {
+        Self { files: io.files.max(1) }
+    }
+}
+
+impl PhysicalOptimizerRule for SplitScansRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan = plan.transform_up(|node| {
+            let Some(config) = file_scan(&node) else {
+                return Ok(Transformed::no(node));
+            };
+            let groups = config.file_groups.len();
+            let files: Vec<PartitionedFile> =
+                config.file_groups.iter().flat_map(|group| group.iter().cloned()).collect();
+            if config.limit.is_some() || groups >= self.files || files.len() <= groups {
+                return Ok(Transformed::no(node));
+            }
+            let split = FileGroup::new(files).split_files(self.files);
+            let config = FileScanConfigBuilder::from(config.clone()).with_file_groups(split);
+            Ok(Transformed::yes(DataSourceExec::from_data_source(config.build())))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// Reads file scans ahead, see the [module](self) documentation.
+#[derive(Debug)]
+pub struct PrefetchScansRule {
+    batches: usize,
+}
+
+impl PrefetchScansRule {
+    pub const NAME: &'static str = "prefetch_scans";
+
+    pub fn new(io: ScanIo) -> Self {
+        Self { batches: io.prefetch_batches }
+    }
+}
+
+impl PhysicalOptimizerRule for PrefetchScansRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        if self.batches == 0 {
+            return Ok(plan);
+        }
+        let plan = plan.transform_down(|node| {
+            // Scans on the probe side of joins are prefetched already.
+            if let Some(prefetch) = node.as_any().downcast_ref::<PrefetchExec>() {
+                if file_scan(prefetch.children()[0]).is_some() {
+                    return Ok(Transformed::new(node, false, TreeNodeRecursion::Jump));
+                }
+            }
+            if file_scan(&node).is_none() {
+                return Ok(Transformed::no(node));
+            }
+            let prefetch = Arc::new(PrefetchExec::new(node, self.batches));
+            Ok(Transformed::new(prefetch, true, TreeNodeRecursion::Jump))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// An object store registry whose stores each take up to `max_requests` requests at
+/// once, those over it waiting for one to finish.
+#[derive(Debug)]
+pub struct LimitedStores {
+    inner: Arc<dyn ObjectStoreRegistry>,
+    max_requests: usize,
+    /// The limited store by store key, with the store it limits.
+    limited: RwLock<HashMap<String, Limited>>,
+}
+
+/// A store and the same store taking a limited number of requests at once.
+type Limited = (Arc<dyn ObjectStore>, Arc<dyn ObjectStore>);
+
+impl LimitedStores {
+    pub fn new(inner: Arc<dyn ObjectStoreRegistry>, max_requests: usize) -> Self {
+        Self { inner, max_requests: max_requests.max(1), limited: RwLock::default() }
+    }
+}
+
+/// The key stores are registered under, `scheme://host:port`.
+fn store_key(url: &Url) -> String {
+    format!("{}://{}", url.scheme(), &url[url::Position::BeforeHost..url::Position::AfterPort])
+}
+
+impl ObjectStoreRegistry for LimitedStores {
+    fn register_store(
+        &self,
+        url: &Url,
+        store: Arc<dyn ObjectStore>,
+    ) -> Option<Arc<dyn ObjectStore>> {
+        self.inner.register_store(url, store)
+    }
+
+    fn get_store(&self, url: &Url) -> DataFusionResult<Arc<dyn ObjectStore>> {
+        let store = self.inner.get_store(url)?;
+        let key = store_key(url);
+        // One limit per store, replaced along with the store.
+        let cached = |limited: &HashMap<String, Limited>| match limited.get(&key) {
+            Some((inner, limited)) if Arc::ptr_eq(inner, &store) => Some(Arc::clone(limited)),
+            _ => None,
+        };
+        if let Some(limited) = cached(&self.limited.read().expect("object store lock poisoned")) {
+            return Ok(limited);
+        }
+        let mut limited = self.limited.write().expect("object store lock poisoned");
+        if let Some(limited) = cached(&limited) {
+            return Ok(limited);
+        }
+        let limit: Arc<dyn ObjectStore> =
+            Arc::new(LimitStore::new(Arc::clone(&store), self.max_requests));
+        limited.insert(key, (store, Arc::clone(&limit)));
+        Ok(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::SessionVars;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::execution::object_store::DefaultObjectStoreRegistry;
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::physical_plan::displayable;
+    use object_store::memory::InMemory;
+
+    /// The line of the scan in the plan of `sql`, and the sum it returns.
+    async fn scan(engine: &QueryEngine, sql: &str) -> DataFusionResult<(String, i64)> {
+        let mut vars = SessionVars::new();
+        vars.set("datafusion.execution.target_partitions", "2")?;
+        let engine = engine.with_session(&vars);
+        let plan = engine.sql(sql).await?.create_physical_plan().await?;
+        let display = displayable(plan.as_ref()).indent(false).to_string();
+        let lines: Vec<_> = display.lines().map(str::trim).collect();
+        let at = lines.iter().position(|line| line.starts_with("DataSourceExec")).unwrap();
+        let line = if at > 0 && lines[at - 1].starts_with("PrefetchExec") {
+            format!("{} {}", lines[at - 1], lines[at])
+        } else {
+            lines[at].to_string()
+        };
+        let result = engine.query(sql).await?;
+        let sums = result.batches[0].column(0).as_any().downcast_ref::<Int64Array>().unwrap();
+        Ok((line, sums.value(0)))
+    }
+
+    #[tokio::test]
+    async fn test_scans_read_their_files_in_parallel() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-scan-io-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+        for i in 0..8 {
+            let values = Int64Array::from_iter_values(i * 10..(i + 1) * 10);
+            let batch = RecordBatch::try_new(Arc::clone(&schema), vec![Arc::new(values)])?;
+            let file = std::fs::File::create(dir.join(format!("part-{i}.parquet")))?;
+            let mut writer = ArrowWriter::try_new(file, Arc::clone(&schema), None)?;
+            writer.write(&batch)?;
+            writer.close()?;
+        }
+        let create = format!(
+            "CREATE EXTERNAL TABLE numbers STORED AS PARQUET LOCATION '{}/'",
+            dir.display()
+        );
+        let sql = "SELECT sum(n) FROM numbers";
+
+        let engine = QueryEngine::new();
+        engine.query(&create).await?;
+        let (line, sum) = scan(&engine, sql).await?;
+        assert!(line.starts_with("DataSourceExec: file_groups={1 group:"), "{line}");
+        assert_eq!(sum, (0..80).sum::<i64>());
+
+        let io = ScanIo { files: 4, prefetch_batches: 2, max_requests: 2 };
+        let engine = QueryEngine::new().with_scan_io(Some(io));
+        engine.query(&create).await?;
+        let (line, sum) = scan(&engine, sql).await?;
+        let expected = "PrefetchExec: batches=2 DataSourceExec: file_groups={4 groups";
+        assert!(line.starts_with(expected), "{line}");
+        assert_eq!(sum, (0..80).sum::<i64>());
+        // With a limit most files are not needed.
+        let (line, _) = scan(&engine, "SELECT sum(n) FROM (SELECT n FROM numbers LIMIT 5)").await?;
+        assert!(line.contains("file_groups={1 group:"), "{line}");
+
+        let engine = engine.with_scan_io(None);
+        let (line, _) = scan(&engine, sql).await?;
+        assert!(line.starts_with("DataSourceExec: file_groups={1 group:"), "{line}");
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_stores_are_limited_until_replaced() -> DataFusionResult<()> {
+        let url = Url::parse("s3://lake/orders/").unwrap();
+        let stores = LimitedStores::new(Arc::new(DefaultObjectStoreRegistry::new()), 4);
+        assert!(stores.get_store(&url).is_err());
+
+        stores.register_store(&url, Arc::new(InMemory::new()));
+        let limited = stores.get_store(&url)?;
+        assert!(Arc::ptr_eq(&limited, &stores.get_store(&Url::parse("s3://lake/").unwrap())?));
+        assert!(limited.to_string().starts_with("LimitStore(4"), "{limited}");
+
+        stores.register_store(&url, Arc::new(InMemory::new()));
+        assert!(!Arc::ptr_eq(&limited, &stores.get_store(&url)?));
+        Ok(())
+    }
+}
@@ -0,0 +1,289 @@
+//! Local scheduler for background work.
+//!
+//! Maintenance jobs (materialized view refreshes, cache warming, compaction, CDC
+//! apply) run on a [`Scheduler`] instead of ad-hoc `tokio::spawn`ed tasks. A fixed
+//! pool of workers takes tasks from a bounded queue, so background work never holds
+//! more than its share of the runtime and a backlog is refused rather than piling up.
+//! Tasks are addressed by [`TaskId`]: they can be cancelled, waited for and their
+//! status queried, and the most recently finished ones are kept for inspection.
+
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use futures::future::{abortable, AbortHandle, BoxFuture};
+use futures::FutureExt;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::{mpsc, watch};
+
+pub type TaskId = u64;
+
+/// Finished tasks kept for [`Scheduler::tasks`] unless configured otherwise.
+pub const DEFAULT_HISTORY: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed(String),
+    Cancelled,
+}
+
+impl TaskStatus {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, TaskStatus::Queued | TaskStatus::Running)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub status: TaskStatus,
+    pub submitted: SystemTime,
+    pub started: Option<SystemTime>,
+    pub finished: Option<SystemTime>,
+}
+
+/// A bounded pool of workers running background tasks. Clones share the pool.
+#[derive(Clone)]
+pub struct Scheduler {
+    state: Arc<State>,
+    queue: mpsc::Sender<Job>,
+}
+
+struct State {
+    tasks: Mutex<Tasks>,
+    next_id: AtomicU64,
+    queue_capacity: usize,
+    history: usize,
+}
+
+#[derive(Default)]
+struct Tasks {
+    entries: HashMap<TaskId, Entry>,
+    finished: VecDeque<TaskId>,
+}
+
+struct Entry {
+    info: TaskInfo,
+    abort: Option<AbortHandle>,
+    done: watch::Sender<Option<TaskStatus>>,
+}
+
+struct Job {
+    id: TaskId,
+    task: BoxFuture<'static, DataFusionResult<()>>,
+}
+
+impl Scheduler {
+    /// Start `workers` workers taking tasks from a queue of up to `queue_capacity`
+    /// tasks. Must be called within a tokio runtime.
+    pub fn new(workers: usize, queue_capacity: usize) -> Self {
+        Self::with_history(workers, queue_capacity, DEFAULT_HISTORY)
+    }
+
+    /// Like [`Scheduler::new`], keeping the `history` most recently finished tasks.
+    pub fn with_history(workers: usize, queue_capacity: usize, history: usize) -> Self {
+        let queue_capacity = queue_capacity.max(1);
+        let (queue, receiver) = mpsc::channel(queue_capacity);
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let state = Arc::new(State {
+            tasks: Mutex::new(Tasks::default()),
+            next_id: AtomicU64::new(1),
+            queue_capacity,
+            history,
+        });
+        for _ in 0..workers.max(1) {
+            tokio::spawn(work(Arc::clone(&state), Arc::clone(&receiver)));
+        }
+        Self { state, queue }
+    }
+
+    /// Queue `task` to run on the pool, failing if the queue is full.
+    pub fn submit<F>(&self, name: impl Into<String>, task: F) -> DataFusionResult<TaskId>
+    where
+        F: Future<Output = DataFusionResult<()>> + Send + 'static,
+    {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        let info = TaskInfo {
+            id,
+            name: name.into(),
+            status: TaskStatus::Queued,
+            submitted: SystemTime::now(),
+            started: None,
+            finished: None,
+        };
+        let entry = Entry { info, abort: None, done: watch::Sender::new(None) };
+        self.state.lock().entries.insert(id, entry);
+        match self.queue.try_send(Job { id, task: task.boxed() }) {
+            Ok(()) => Ok(id),
+            Err(e) => {
+                self.state.lock().entries.remove(&id);
+                Err(match e {
+                    mpsc::error::TrySendError::Full(_) => {
+                        DataFusionError::ResourcesExhausted(format!(
+                            "background task queue is full ({} tasks waiting)",
+                            self.state.queue_capacity
+                        ))
+                    }
+                    mpsc::error::TrySendError::Closed(_) => {
+                        DataFusionError::Execution("scheduler has shut down".to_string())
+                    }
+                })
+            }
+        }
+    }
+
+    pub fn status(&self, id: TaskId) -> Option<TaskStatus> {
+        self.info(id).map(|info| info.status)
+    }
+
+    pub fn info(&self, id: TaskId) -> Option<TaskInfo> {
+        self.state.lock().entries.get(&id).map(|entry| entry.info.clone())
+    }
+
+    /// Every unfinished task and the most recently finished ones, oldest first.
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> =
+            self.state.lock().entries.values().map(|entry| entry.info.clone()).collect();
+        tasks.sort_by_key(|info| info.id);
+        tasks
+    }
+
+    /// Cancel a task: a queued one never starts, a running one is dropped at its next
+    /// `.await`. Returns whether the task was still unfinished.
+    pub fn cancel(&self, id: TaskId) -> bool {
+        let mut tasks = self.state.lock();
+        let Some(entry) = tasks.entries.get_mut(&id) else {
+            return false;
+        };
+        match entry.info.status {
+            TaskStatus::Queued => {
+                self.state.finish(&mut tasks, id, TaskStatus::Cancelled);
+                true
+            }
+            TaskStatus::Running => {
+                if let Some(abort) = &entry.abort {
+                    abort.abort();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Wait for a task to finish, returning how it did. `None` for unknown tasks.
+    pub async fn wait(&self, id: TaskId) -> Option<TaskStatus> {
+        let mut done = self.state.lock().entries.get(&id)?.done.subscribe();
+        let status = done.wait_for(Option::is_some).await.ok()?;
+        status.clone()
+    }
+}
+
+impl State {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Tasks> {
+        self.tasks.lock().expect("scheduler lock poisoned")
+    }
+
+    /// Record that task `id` has finished, forgetting the oldest finished tasks beyond
+    /// the history limit.
+    fn finish(&self, tasks: &mut Tasks, id: TaskId, status: TaskStatus) {
+        let Some(entry) = tasks.entries.get_mut(&id) else {
+            return;
+        };
+        entry.info.status = status.clone();
+        entry.info.finished = Some(SystemTime::now());
+        entry.abort = None;
+        entry.done.send_replace(Some(status));
+        tasks.finished.push_back(id);
+        while tasks.finished.len() > self.history {
+            if let Some(oldest) = tasks.finished.pop_front() {
+                tasks.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+async fn work(state: Arc<State>, queue: Arc<tokio::sync::Mutex<mpsc::Receiver<Job>>>) {
+    loop {
+        let Some(Job { id, task }) = queue.lock().await.recv().await else {
+            return;
+        };
+        let task = {
+            let mut tasks = state.lock();
+            let Some(entry) = tasks.entries.get_mut(&id) else { continue };
+            // Cancelled while queued.
+            if entry.info.status != TaskStatus::Queued {
+                continue;
+            }
+            let (task, abort) = abortable(task);
+            entry.info.status = TaskStatus::Running;
+            entry.info.started = Some(SystemTime::now());
+            entry.abort = Some(abort);
+            task
+        };
+        // A panicking task fails on its own without taking the worker down.
+        let status = match AssertUnwindSafe(task).catch_unwind().await {
+            Ok(Ok(Ok(()))) => TaskStatus::Succeeded,
+            Ok(Ok(Err(e))) => TaskStatus::Failed(e.to_string()),
+            Ok(Err(_aborted)) => TaskStatus::Cancelled,
+            Err(_panic) => TaskStatus::Failed("task panicked".to_string()),
+        };
+        let mut tasks = state.lock();
+        state.finish(&mut tasks, id, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_tasks_run_on_a_bounded_pool() {
+        let scheduler = Scheduler::new(1, 2);
+        let (release, released) = oneshot::channel::<()>();
+        let first = scheduler
+            .submit("refresh", async move {
+                released.await.ok();
+                Ok(())
+            })
+            .unwrap();
+        let second = scheduler
+            .submit("compact", async { Err(DataFusionError::Execution("disk full".into())) })
+            .unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(scheduler.status(first), Some(TaskStatus::Running));
+        assert_eq!(scheduler.status(second), Some(TaskStatus::Queued));
+
+        release.send(()).unwrap();
+        assert_eq!(scheduler.wait(first).await, Some(TaskStatus::Succeeded));
+        assert_eq!(
+            scheduler.wait(second).await,
+            Some(TaskStatus::Failed("Execution error: disk full".into()))
+        );
+        let names: Vec<_> = scheduler.tasks().into_iter().map(|info| info.name).collect();
+        assert_eq!(names, ["refresh", "compact"]);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_and_queue_limit() {
+        let scheduler = Scheduler::with_history(1, 1, 1);
+        let running = scheduler.submit("warm", futures::future::pending()).unwrap();
+        tokio::task::yield_now().await;
+        let queued = scheduler.submit("apply", async { Ok(()) }).unwrap();
+        let err = scheduler.submit("apply", async { Ok(()) }).unwrap_err();
+        assert!(matches!(err, DataFusionError::ResourcesExhausted(_)), "{err}");
+
+        assert!(scheduler.cancel(queued));
+        assert!(scheduler.cancel(running));
+        assert_eq!(scheduler.wait(running).await, Some(TaskStatus::Cancelled));
+        assert!(!scheduler.cancel(running));
+        // Only the latest finished task is kept.
+        assert_eq!(scheduler.status(queued), None);
+    }
+}
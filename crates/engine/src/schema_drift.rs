@@ -0,0 +1,186 @@
+//! Schema drift between source tables and their copies in the lake.
+//!
+//! A table copied into the lake (by a snapshot, then kept up to date by CDC) has the
+//! columns its source had when it was copied. Columns added, dropped or retyped at the
+//! source afterwards break the pipeline, or the queries of the copy, unless the copy
+//! is altered to match. [`QueryEngine::diff_schema`](crate::QueryEngine::diff_schema)
+//! compares the live schema of a source table with the registered copy's and returns
+//! a [`SchemaDiff`] of the columns that differ.
+//!
+//! Types are compared as the lake stores them, so that copying a column is not drift:
+//! dictionary-encoded columns compare as their values, strings and binaries of any
+//! layout alike, integers narrower than 32 bits as 32-bit ones, and timestamps with a
+//! time zone as UTC ones.
+
+use datafusion::arrow::datatypes::{DataType, Schema};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How a column of the source differs from the copy's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnDriftKind {
+    /// The source has a column the copy does not.
+    Added,
+    /// The copy has a column the source no longer does.
+    Removed,
+    /// The column's type differs.
+    Retyped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnDrift {
+    pub kind: ColumnDriftKind,
+    pub column: String,
+    /// The column's type at the source, unless it was removed.
+    pub source_type: Option<String>,
+    /// The column's type in the copy, unless it was added.
+    pub replica_type: Option<String>,
+}
+
+impl fmt::Display for ColumnDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let source_type = self.source_type.as_deref().unwrap_or_default();
+        let replica_type = self.replica_type.as_deref().unwrap_or_default();
+        match self.kind {
+            ColumnDriftKind::Added => write!(f, "+ column {} {source_type}", self.column),
+            ColumnDriftKind::Removed => write!(f, "- column {} {replica_type}", self.column),
+            ColumnDriftKind::Retyped => write!(
+                f,
+                "~ column {} is {source_type} at the source, {replica_type} in the copy",
+                self.column
+            ),
+        }
+    }
+}
+
+/// How a copy's columns differ from its source's.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaDiff {
+    pub source: String,
+    pub replica: String,
+    /// In source column order, then the removed columns in copy order.
+    pub drift: Vec<ColumnDrift>,
+}
+
+impl SchemaDiff {
+    pub fn has_drifted(&self) -> bool {
+        !self.drift.is_empty()
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.drift.len() {
+            0 => write!(f, "table {} matches {}", self.replica, self.source)?,
+            n => write!(f, "table {} drifted from {} ({n} changes)", self.replica, self.source)?,
+        }
+        for drift in &self.drift {
+            write!(f, "\n{drift}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The columns of `replica` that differ from those of `source`, see [`SchemaDiff::drift`].
+pub fn diff_schemas(source: &Schema, replica: &Schema) -> Vec<ColumnDrift> {
+    let mut drift = vec![];
+    for field in source.fields() {
+        let source_type = Some(field.data_type().to_string());
+        match replica.field_with_name(field.name()) {
+            Err(_) => drift.push(ColumnDrift {
+                kind: ColumnDriftKind::Added,
+                column: field.name().clone(),
+                source_type,
+                replica_type: None,
+            }),
+            Ok(copy) if stored_type(copy.data_type()) != stored_type(field.data_type()) => {
+                drift.push(ColumnDrift {
+                    kind: ColumnDriftKind::Retyped,
+                    column: field.name().clone(),
+                    source_type,
+                    replica_type: Some(copy.data_type().to_string()),
+                });
+            }
+            Ok(_) => {}
+        }
+    }
+    for field in replica.fields() {
+        if source.field_with_name(field.name()).is_err() {
+            drift.push(ColumnDrift {
+                kind: ColumnDriftKind::Removed,
+                column: field.name().clone(),
+                source_type: None,
+                replica_type: Some(field.data_type().to_string()),
+            });
+        }
+    }
+    drift
+}
+
+/// `data_type` as the lake stores it, see the [module](self) documentation.
+fn stored_type(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Dictionary(_, values) => stored_type(values),
+        DataType::LargeUtf8 | DataType::Utf8View => DataType::Utf8,
+        DataType::LargeBinary | DataType::BinaryView => DataType::Binary,
+        DataType::Int8 | DataType::Int16 => DataType::Int32,
+        DataType::Timestamp(unit, Some(_)) => DataType::Timestamp(*unit, Some("UTC".into())),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::datatypes::{Field, TimeUnit};
+    use datafusion::error::Result as DataFusionResult;
+
+    #[tokio::test]
+    async fn test_copies_are_diffed_with_their_source() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE orders (id BIGINT, status TEXT, placed TIMESTAMP, total INT) \
+                 AS VALUES (1, 'new', now(), 10)",
+            )
+            .await?;
+        let source = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new(
+                "status",
+                DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+                true,
+            ),
+            Field::new("placed", DataType::Timestamp(TimeUnit::Nanosecond, None), true),
+            Field::new("total", DataType::Int16, true),
+        ]);
+        let diff = engine.diff_schema("public.orders", &source, "orders".into()).await?;
+        assert!(!diff.has_drifted(), "{diff}");
+        assert_eq!(diff.to_string(), "table orders matches public.orders");
+
+        let source = Schema::new(vec![
+            Field::new("id", DataType::Utf8, false),
+            Field::new("status", DataType::Utf8, true),
+            Field::new("total", DataType::Int32, true),
+            Field::new("channel", DataType::Utf8, true),
+        ]);
+        let diff = engine.diff_schema("public.orders", &source, "orders".into()).await?;
+        assert_eq!(
+            diff.to_string(),
+            "table orders drifted from public.orders (3 changes)\n\
+             ~ column id is Utf8 at the source, Int64 in the copy\n\
+             + column channel Utf8\n\
+             - column placed Timestamp(Nanosecond, None)"
+        );
+        let kinds: Vec<_> = diff.drift.iter().map(|drift| drift.kind).collect();
+        assert_eq!(
+            kinds,
+            [ColumnDriftKind::Retyped, ColumnDriftKind::Added, ColumnDriftKind::Removed]
+        );
+
+        assert!(engine.diff_schema("public.orders", &source, "missing".into()).await.is_err());
+        Ok(())
+    }
+}
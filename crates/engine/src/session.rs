@@ -0,0 +1,330 @@
+//! Per-connection session variables.
+//!
+//! `SET name = value` (or `SET name TO value`, `SET TIME ZONE value`) changes a
+//! connection's [`SessionVars`] rather than the engine shared by every client.
+//! Frontends keep one `SessionVars` per connection and plan each statement with
+//! [`QueryEngine::with_session`](crate::QueryEngine::with_session). `SET name TO
+//! DEFAULT` clears a variable.
+//!
+//! Recognized variables:
+//!
+//! - `time_zone` (alias `timezone`): time zone for timestamp functions and casts;
+//! - `catalog`, `schema` (alias `search_path`, of which the first entry is used):
+//!   where unqualified table names are resolved;
+//! - `statement_timeout`: milliseconds, or a number with a `ms`, `s`, `min` or `h`
+//!   suffix; `0` turns it off;
+//! - `output_format`: default result format for frontends that offer a choice;
+//! - `priority`: `interactive`, `batch` or `background`, the class the connection's
+//!   queries are admitted in (see [`crate::admission`]);
+//! - `profile`: `on` or `off`, whether the connection's queries are profiled (see
+//!   [`crate::profile`]);
+//! - `igloo.join_strategy`, `igloo.broadcast_max_rows`, `igloo.broadcast_max_bytes`:
+//!   how hash joins move their inputs (see [`crate::join_strategy`]);
+//! - `igloo.use_materialized_views`: whether queries read materialized views (see
+//!   [`crate::materialized_views`]);
+//! - any `datafusion.*` configuration option.
+
+use crate::admission::Priority;
+use crate::formats::OutputFormat;
+use crate::options::IglooOptions;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::config::ConfigOptions;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::{RecordBatchStream, SendableRecordBatchStream};
+use datafusion::sql::parser::{DFParser, Statement};
+use datafusion::sql::sqlparser::ast::{
+    Expr, OneOrManyWithParens, Statement as SqlStatement, UnaryOperator, Value,
+};
+use futures::Stream;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::Sleep;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionVars {
+    pub time_zone: Option<String>,
+    pub catalog: Option<String>,
+    pub schema: Option<String>,
+    pub statement_timeout: Option<Duration>,
+    pub output_format: Option<OutputFormat>,
+    pub priority: Option<Priority>,
+    pub profile: bool,
+    /// Other `datafusion.*` options, by full name.
+    pub options: BTreeMap<String, String>,
+}
+
+impl SessionVars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Assign `value` to the variable `name`, or clear it if `value` is `DEFAULT`.
+    pub fn set(&mut self, name: &str, value: &str) -> DataFusionResult<()> {
+        let name = name.to_ascii_lowercase();
+        let value = (!value.eq_ignore_ascii_case("default")).then_some(value);
+        match name.as_str() {
+            "time_zone" | "timezone" | "datafusion.execution.time_zone" => {
+                self.time_zone = value.map(str::to_string);
+            }
+            "catalog" | "datafusion.catalog.default_catalog" => {
+                self.catalog = value.map(str::to_string);
+            }
+            "schema" | "search_path" | "datafusion.catalog.default_schema" => {
+                self.schema = value.and_then(|v| v.split(',').next()).map(|v| v.trim().to_string());
+            }
+            "statement_timeout" => {
+                self.statement_timeout = value.map(parse_timeout).transpose()?.flatten();
+            }
+            "output_format" => self.output_format = value.map(str::parse).transpose()?,
+            "priority" => self.priority = value.map(str::parse).transpose()?,
+            "profile" => self.profile = value.map(parse_bool).transpose()?.unwrap_or(false),
+            option if is_config_option(option) => match value {
+                Some(value) => {
+                    // Reject unknown options and invalid values now rather than on
+                    // every later query.
+                    let mut options = ConfigOptions::new();
+                    options.extensions.insert(IglooOptions::default());
+                    options.set(option, value)?;
+                    self.options.insert(name, value.to_string());
+                }
+                None => {
+                    self.options.remove(option);
+                }
+            },
+            _ => {
+                return Err(DataFusionError::Plan(format!(
+                    "unrecognized configuration parameter \"{name}\""
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Every variable that is set, as `(name, value)` pairs that [`SessionVars::set`]
+    /// accepts, for frontends that keep session state as strings.
+    pub fn settings(&self) -> Vec<(String, String)> {
+        let mut settings = Vec::new();
+        let mut push = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                settings.push((name.to_string(), value));
+            }
+        };
+        push("time_zone", self.time_zone.clone());
+        push("catalog", self.catalog.clone());
+        push("schema", self.schema.clone());
+        push("statement_timeout", self.statement_timeout.map(|t| format!("{}ms", t.as_millis())));
+        push("output_format", self.output_format.map(|f| f.name().to_string()));
+        push("priority", self.priority.map(|p| p.name().to_string()));
+        push("profile", self.profile.then(|| "on".to_string()));
+        settings.extend(self.options.iter().map(|(k, v)| (k.clone(), v.clone())));
+        settings
+    }
+}
+
+/// Whether `name` is a DataFusion option or one of the engine's own.
+fn is_config_option(name: &str) -> bool {
+    name.starts_with("datafusion.") || name.starts_with("igloo.")
+}
+
+/// Parse a boolean variable's value: `on`, `true`, `1` or `off`, `false`, `0`.
+fn parse_bool(value: &str) -> DataFusionResult<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(DataFusionError::Plan(format!("invalid boolean '{value}', expected on or off"))),
+    }
+}
+
+/// Parse a `statement_timeout` value; `None` for `0`.
+fn parse_timeout(value: &str) -> DataFusionResult<Option<Duration>> {
+    let value = value.trim().to_ascii_lowercase();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let invalid = || {
+        DataFusionError::Plan(format!(
+            "invalid statement_timeout '{value}', expected e.g. 5000, 500ms, 30s, 5min or 1h"
+        ))
+    };
+    let amount: u64 = amount.parse().map_err(|_| invalid())?;
+    let timeout = match unit.trim() {
+        "" | "ms" => Duration::from_millis(amount),
+        "s" => Duration::from_secs(amount),
+        "min" => Duration::from_secs(amount * 60),
+        "h" => Duration::from_secs(amount * 60 * 60),
+        _ => return Err(invalid()),
+    };
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// If `statement` is a session `SET`, the variable it assigns and the value.
+pub fn parse_set(statement: &Statement) -> DataFusionResult<Option<(String, String)>> {
+    let Statement::Statement(statement) = statement else {
+        return Ok(None);
+    };
+    match statement.as_ref() {
+        SqlStatement::SetTimeZone { local: false, value } => {
+            Ok(Some(("time_zone".to_string(), expr_to_value(value)?)))
+        }
+        SqlStatement::SetVariable {
+            local: false,
+            hivevar: false,
+            variables: OneOrManyWithParens::One(name),
+            value,
+        } => {
+            let values = value.iter().map(expr_to_value).collect::<DataFusionResult<Vec<_>>>()?;
+            Ok(Some((name.to_string(), values.join(","))))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// [`parse_set`] for SQL text. Anything that is not a single `SET` statement,
+/// including SQL that does not parse, is `None`; planning it reports any error.
+pub fn parse_set_sql(sql: &str) -> DataFusionResult<Option<(String, String)>> {
+    match DFParser::parse_sql(sql) {
+        Ok(statements) if statements.len() == 1 => parse_set(&statements[0]),
+        _ => Ok(None),
+    }
+}
+
+pub(crate) fn expr_to_value(expr: &Expr) -> DataFusionResult<String> {
+    match expr {
+        Expr::Identifier(ident) => Ok(ident.value.clone()),
+        Expr::Value(value) => match &value.value {
+            Value::SingleQuotedString(s) | Value::DoubleQuotedString(s) => Ok(s.clone()),
+            Value::Number(n, _) => Ok(n.clone()),
+            Value::Boolean(b) => Ok(b.to_string()),
+            other => Err(DataFusionError::Plan(format!("unsupported SET value {other}"))),
+        },
+        Expr::UnaryOp { op: UnaryOperator::Minus, expr } => Ok(format!("-{expr}")),
+        Expr::UnaryOp { op: UnaryOperator::Plus, expr } => Ok(format!("+{expr}")),
+        other => Err(DataFusionError::Plan(format!("unsupported SET value {other}"))),
+    }
+}
+
+/// Fail `stream` once `timeout` has passed since this call. Without a timeout the
+/// stream is returned as is.
+pub fn with_timeout(
+    stream: SendableRecordBatchStream,
+    timeout: Option<Duration>,
+) -> SendableRecordBatchStream {
+    match timeout {
+        Some(timeout) => Box::pin(TimeoutStream {
+            inner: stream,
+            deadline: Box::pin(tokio::time::sleep(timeout)),
+            timeout,
+            expired: false,
+        }),
+        None => stream,
+    }
+}
+
+/// The error reported for a statement that ran past its `statement_timeout`.
+pub fn timeout_error(timeout: Duration) -> DataFusionError {
+    DataFusionError::Execution(format!(
+        "canceling statement due to statement timeout ({}ms)",
+        timeout.as_millis()
+    ))
+}
+
+struct TimeoutStream {
+    inner: SendableRecordBatchStream,
+    deadline: Pin<Box<Sleep>>,
+    timeout: Duration,
+    expired: bool,
+}
+
+impl Stream for TimeoutStream {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.expired {
+            return Poll::Ready(None);
+        }
+        if self.deadline.as_mut().poll(cx).is_ready() {
+            self.expired = true;
+            return Poll::Ready(Some(Err(timeout_error(self.timeout))));
+        }
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl RecordBatchStream for TimeoutStream {
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(sql: &str) -> Option<(String, String)> {
+        parse_set_sql(sql).unwrap()
+    }
+
+    #[test]
+    fn test_parse_set() {
+        assert_eq!(set("SET TIME ZONE '+02:00'"), Some(("time_zone".into(), "+02:00".into())));
+        assert_eq!(
+            set("SET search_path TO sales, public"),
+            Some(("search_path".into(), "sales,public".into()))
+        );
+        assert_eq!(
+            set("SET statement_timeout = 500"),
+            Some(("statement_timeout".into(), "500".into()))
+        );
+        assert_eq!(set("SELECT 1"), None);
+        assert_eq!(set("SET x = 1; SET y = 2"), None);
+    }
+
+    #[test]
+    fn test_set_variables() {
+        let mut vars = SessionVars::new();
+        vars.set("TimeZone", "+02:00").unwrap();
+        vars.set("search_path", "sales,public").unwrap();
+        vars.set("statement_timeout", "2s").unwrap();
+        vars.set("output_format", "csv").unwrap();
+        vars.set("datafusion.execution.batch_size", "1024").unwrap();
+        vars.set("igloo.join_strategy", "broadcast").unwrap();
+        assert!(vars.set("igloo.join_strategy", "sideways").is_err());
+        assert!(vars.set("igloo.no_such_option", "1").is_err());
+        assert_eq!(vars.time_zone.as_deref(), Some("+02:00"));
+        assert_eq!(vars.schema.as_deref(), Some("sales"));
+        assert_eq!(vars.statement_timeout, Some(Duration::from_secs(2)));
+        assert_eq!(vars.output_format, Some(OutputFormat::Csv));
+
+        let mut copy = SessionVars::new();
+        for (name, value) in vars.settings() {
+            copy.set(&name, &value).unwrap();
+        }
+        assert_eq!(copy, vars);
+
+        vars.set("statement_timeout", "0").unwrap();
+        vars.set("time_zone", "DEFAULT").unwrap();
+        assert_eq!((vars.statement_timeout, vars.time_zone.as_deref()), (None, None));
+
+        assert!(vars.set("no_such_variable", "1").is_err());
+        assert!(vars.set("datafusion.execution.no_such_option", "1").is_err());
+        assert!(vars.set("statement_timeout", "soon").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_timeout_fails_the_stream() {
+        use datafusion::arrow::datatypes::Schema;
+        use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+        use futures::StreamExt;
+        use std::sync::Arc;
+
+        let pending = futures::stream::pending::<DataFusionResult<RecordBatch>>();
+        let stream = Box::pin(RecordBatchStreamAdapter::new(Arc::new(Schema::empty()), pending));
+        let mut stream = with_timeout(stream, Some(Duration::from_millis(10)));
+        let err = stream.next().await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("statement timeout"), "{err}");
+        assert!(stream.next().await.is_none());
+    }
+}
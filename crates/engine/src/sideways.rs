@@ -0,0 +1,837 @@
+//! Passing a join's keys sideways into the scan of its probe side.
+//!
+//! A hash join collecting a small build side, such as a Parquet dimension, still reads
+//! all of its probe side, though only rows matching one of a few keys can join; when
+//! the probe side is a remote table, e.g. a Postgres fact table, that is a full scan
+//! over the network. Every [`QueryEngine`](crate::QueryEngine) therefore runs:
+//!
+//! - [`SidewaysScanRule`], which wraps the scans under join keys of tables that take
+//!   `key IN (...)` filters in a [`SidewaysTable`];
+//! - [`SidewaysRule`], which, for hash joins collecting their build side whose result
+//!   does not need the probe side's unmatched rows, collects the distinct keys of the
+//!   build side as it is read ([`KeyCollectorExec`]) and hands them to the scan of the
+//!   probe side ([`SidewaysScanExec`]). That scan waits for them, then scans its table
+//!   again with `key IN (...)` added to its filters, so the table's source only sends
+//!   the rows that can join.
+//!
+//! Scans of files and memory, read in place rather than fetched from a remote source,
+//! are left as planned. A build side with more than [`DEFAULT_MAX_JOIN_KEYS`] distinct
+//! keys, by default, is not worth filtering by: the probe side is then scanned as
+//! planned, as it is when the build side fails.
+
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::Session;
+use datafusion::common::tree_node::{Transformed, TreeNode};
+use datafusion::common::{Column, Constraints, JoinType, ScalarValue, Statistics};
+use datafusion::config::ConfigOptions;
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::datasource::{provider_as_source, source_as_provider, TableProvider, TableType};
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::session_state::SessionState;
+use datafusion::execution::{RecordBatchStream, SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::expr::InList;
+use datafusion::logical_expr::{Expr, LogicalPlan, TableProviderFilterPushDown};
+use datafusion::optimizer::{OptimizerConfig, OptimizerRule};
+use datafusion::physical_expr::expressions::Column as ColumnExpr;
+use datafusion::physical_expr::PhysicalExprRef;
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::filter::FilterExec;
+use datafusion::physical_plan::joins::{HashJoinExec, PartitionMode};
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, EmptyRecordBatchStream, ExecutionPlan, ExecutionPlanProperties,
+    PlanProperties,
+};
+use futures::{Stream, StreamExt, TryStreamExt};
+use std::any::Any;
+use std::collections::HashSet;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::sync::watch;
+
+use crate::prefetch::PrefetchExec;
+
+/// Distinct build side keys up to which the probe side is filtered by them.
+pub const DEFAULT_MAX_JOIN_KEYS: usize = 10_000;
+
+/// Wraps the scans under join keys in [`SidewaysTable`]s, see the [module](self)
+/// documentation.
+#[derive(Debug, Default)]
+pub struct SidewaysScanRule;
+
+impl OptimizerRule for SidewaysScanRule {
+    fn name(&self) -> &str {
+        "sideways_scans"
+    }
+
+    fn supports_rewrite(&self) -> bool {
+        true
+    }
+
+    fn rewrite(
+        &self,
+        plan: LogicalPlan,
+        _config: &dyn OptimizerConfig,
+    ) -> DataFusionResult<Transformed<LogicalPlan>> {
+        plan.transform_up_with_subqueries(|node| {
+            let LogicalPlan::Join(join) = &node else {
+                return Ok(Transformed::no(node));
+            };
+            if join.null_equals_null {
+                return Ok(Transformed::no(node));
+            }
+            let mut inputs = vec![join.left.as_ref().clone(), join.right.as_ref().clone()];
+            let mut changed = false;
+            for (left, right) in &join.on {
+                for (input, key) in inputs.iter_mut().zip([left, right]) {
+                    let Expr::Column(column) = key else { continue };
+                    if let Some(wrapped) = sideways_scan(input, column)? {
+                        *input = wrapped;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return Ok(Transformed::no(node));
+            }
+            let exprs = node.expressions();
+            Ok(Transformed::yes(node.with_new_exprs(exprs, inputs)?))
+        })
+    }
+}
+
+/// `plan` with the scan `column` comes from wrapped in a [`SidewaysTable`], if its
+/// table takes `IN` filters on it and it is not wrapped yet.
+fn sideways_scan(plan: &LogicalPlan, column: &Column) -> DataFusionResult<Option<LogicalPlan>> {
+    let (input, column) = match plan {
+        LogicalPlan::TableScan(scan) => {
+            let Ok(table) = source_as_provider(&scan.source) else {
+                return Ok(None);
+            };
+            if table.as_any().is::<SidewaysTable>() {
+                return Ok(None);
+            }
+            let schema = table.schema();
+            let Ok(field) = schema.field_with_name(&column.name) else {
+                return Ok(None);
+            };
+            let Some(probe) = probe_value(field.data_type()) else {
+                return Ok(None);
+            };
+            let filter = key_filter(&column.name, vec![probe]);
+            let pushdown = table.supports_filters_pushdown(&[&filter])?;
+            if pushdown.first() == Some(&TableProviderFilterPushDown::Unsupported) {
+                return Ok(None);
+            }
+            let mut scan = scan.clone();
+            let table = SidewaysTable::new(table, column.name.clone());
+            scan.source = provider_as_source(Arc::new(table));
+            return Ok(Some(LogicalPlan::TableScan(scan)));
+        }
+        LogicalPlan::Filter(filter) => (&filter.input, Column::from_name(&column.name)),
+        LogicalPlan::SubqueryAlias(alias) => (&alias.input, Column::from_name(&column.name)),
+        LogicalPlan::Projection(projection) => {
+            let Ok(index) = projection.schema.index_of_column(column) else {
+                return Ok(None);
+            };
+            match projection.expr[index].clone().unalias() {
+                Expr::Column(column) => (&projection.input, column),
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+    let Some(input) = sideways_scan(input, &column)? else {
+        return Ok(None);
+    };
+    Ok(Some(plan.with_new_exprs(plan.expressions(), vec![input])?))
+}
+
+/// A value of `data_type` to ask a table whether it takes `IN` filters on it.
+fn probe_value(data_type: &DataType) -> Option<ScalarValue> {
+    ScalarValue::new_zero(data_type)
+        .or_else(|_| ScalarValue::try_from_string(String::new(), data_type))
+        .ok()
+}
+
+/// `column IN (keys)`.
+fn key_filter(column: &str, keys: Vec<ScalarValue>) -> Expr {
+    let list = keys.into_iter().map(|key| Expr::Literal(key, None)).collect();
+    Expr::InList(InList::new(Box::new(Expr::Column(Column::from_name(column))), list, false))
+}
+
+/// A table whose scans can be filtered by the keys of the join they feed, see the
+/// [module](self) documentation.
+#[derive(Debug)]
+pub struct SidewaysTable {
+    table: Arc<dyn TableProvider>,
+    column: String,
+}
+
+impl SidewaysTable {
+    /// `table`, scans of which can be filtered by keys of `column`.
+    pub fn new(table: Arc<dyn TableProvider>, column: String) -> Self {
+        Self { table, column }
+    }
+
+    pub fn table(&self) -> &Arc<dyn TableProvider> {
+        &self.table
+    }
+}
+
+#[async_trait]
+impl TableProvider for SidewaysTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.table.schema()
+    }
+
+    fn constraints(&self) -> Option<&Constraints> {
+        self.table.constraints()
+    }
+
+    fn table_type(&self) -> TableType {
+        self.table.table_type()
+    }
+
+    fn statistics(&self) -> Option<Statistics> {
+        self.table.statistics()
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        self.table.supports_filters_pushdown(filters)
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let scan = self.table.scan(state, projection, filters, limit).await?;
+        let Some(state) = state.as_any().downcast_ref::<SessionState>() else {
+            return Ok(scan);
+        };
+        // Files and memory are read in place; their scans are left as planned.
+        if scan.as_any().is::<DataSourceExec>() || scan.schema().index_of(&self.column).is_err() {
+            return Ok(scan);
+        }
+        Ok(Arc::new(SidewaysScanExec {
+            scan,
+            table: Arc::clone(&self.table),
+            state: Arc::new(state.clone()),
+            projection: projection.cloned(),
+            filters: filters.to_vec(),
+            limit,
+            column: self.column.clone(),
+            keys: None,
+        }))
+    }
+}
+
+/// Distinct keys of a join's build side, published once it is read.
+#[derive(Debug)]
+pub struct JoinKeys {
+    keys: watch::Sender<Option<Keys>>,
+}
+
+/// What a [`JoinKeys`] was published as.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Keys {
+    /// All distinct non-null keys, sorted.
+    Collected(Arc<[ScalarValue]>),
+    /// Too many keys to filter by, or the build side failed.
+    Unfiltered,
+}
+
+impl Default for JoinKeys {
+    fn default() -> Self {
+        Self { keys: watch::channel(None).0 }
+    }
+}
+
+impl JoinKeys {
+    /// Publish `keys`, unless published before.
+    pub fn publish(&self, keys: Keys) {
+        self.keys.send_if_modified(|published| {
+            if published.is_some() {
+                return false;
+            }
+            *published = Some(keys);
+            true
+        });
+    }
+
+    /// The keys, once published.
+    pub async fn wait(&self) -> Keys {
+        let mut receiver = self.keys.subscribe();
+        let keys = receiver.wait_for(Option::is_some).await;
+        keys.ok().and_then(|keys| keys.clone()).unwrap_or(Keys::Unfiltered)
+    }
+}
+
+/// A scan of a [`SidewaysTable`], filtered by the keys of a join if given them.
+#[derive(Debug, Clone)]
+pub struct SidewaysScanExec {
+    /// The scan as planned, run until given keys.
+    scan: Arc<dyn ExecutionPlan>,
+    table: Arc<dyn TableProvider>,
+    state: Arc<SessionState>,
+    projection: Option<Vec<usize>>,
+    filters: Vec<Expr>,
+    limit: Option<usize>,
+    column: String,
+    keys: Option<Arc<JoinKeys>>,
+}
+
+impl SidewaysScanExec {
+    /// The column filtered by join keys.
+    pub fn column(&self) -> &str {
+        &self.column
+    }
+
+    /// This scan filtered by `keys` once they are published.
+    pub fn with_keys(&self, keys: Arc<JoinKeys>) -> Self {
+        Self { keys: Some(keys), ..self.clone() }
+    }
+
+    pub fn keys(&self) -> Option<&Arc<JoinKeys>> {
+        self.keys.as_ref()
+    }
+}
+
+impl DisplayAs for SidewaysScanExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SidewaysScanExec: column={}, join_keys={}", self.column, self.keys.is_some())
+    }
+}
+
+impl ExecutionPlan for SidewaysScanExec {
+    fn name(&self) -> &str {
+        "SidewaysScanExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.scan.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.scan]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self { scan: children.swap_remove(0), ..self.as_ref().clone() }))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let Some(keys) = self.keys.clone() else {
+            return self.scan.execute(partition, context);
+        };
+        let this = self.clone();
+        let schema = self.schema();
+        let stream = async move {
+            let keys = match keys.wait().await {
+                Keys::Collected(keys) => keys,
+                Keys::Unfiltered => return this.scan.execute(partition, context),
+            };
+            // No probe row can join an empty build side.
+            if keys.is_empty() {
+                return Ok(Box::pin(EmptyRecordBatchStream::new(this.schema()))
+                    as SendableRecordBatchStream);
+            }
+            let filter = key_filter(&this.column, keys.to_vec());
+            let pushdown = this.table.supports_filters_pushdown(&[&filter])?;
+            if pushdown.first() == Some(&TableProviderFilterPushDown::Unsupported) {
+                return this.scan.execute(partition, context);
+            }
+            let mut filters = this.filters.clone();
+            filters.push(filter);
+            let filtered = this
+                .table
+                .scan(this.state.as_ref(), this.projection.as_ref(), &filters, this.limit)
+                .await?;
+            let partitions = filtered.output_partitioning().partition_count();
+            if partitions == this.scan.output_partitioning().partition_count() {
+                return filtered.execute(partition, context);
+            }
+            // Partitioned differently: the first partition reads them all.
+            if partition > 0 {
+                return Ok(Box::pin(EmptyRecordBatchStream::new(this.schema())));
+            }
+            let streams = (0..partitions)
+                .map(|partition| filtered.execute(partition, Arc::clone(&context)))
+                .collect::<DataFusionResult<Vec<_>>>()?;
+            let merged = futures::stream::select_all(streams);
+            Ok(Box::pin(RecordBatchStreamAdapter::new(this.schema(), merged)) as _)
+        };
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            schema,
+            futures::stream::once(stream).try_flatten(),
+        )))
+    }
+}
+
+/// Passes the keys of hash joins' build sides to the scans of their probe sides, see
+/// the [module](self) documentation.
+#[derive(Debug)]
+pub struct SidewaysRule {
+    max_keys: usize,
+}
+
+impl Default for SidewaysRule {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_JOIN_KEYS)
+    }
+}
+
+impl SidewaysRule {
+    pub const NAME: &'static str = "sideways";
+
+    /// Filter probe sides by up to `max_keys` distinct keys.
+    pub fn new(max_keys: usize) -> Self {
+        Self { max_keys }
+    }
+}
+
+impl PhysicalOptimizerRule for SidewaysRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan = plan.transform_up(|node| {
+            let Some(join) = node.as_any().downcast_ref::<HashJoinExec>() else {
+                return Ok(Transformed::no(node));
+            };
+            // Joins keeping unmatched probe rows need them all.
+            let keeps_probe_rows = !matches!(
+                join.join_type(),
+                JoinType::Inner
+                    | JoinType::Left
+                    | JoinType::LeftSemi
+                    | JoinType::LeftAnti
+                    | JoinType::LeftMark
+                    | JoinType::RightSemi
+            );
+            if *join.partition_mode() != PartitionMode::CollectLeft
+                || join.null_equals_null()
+                || keeps_probe_rows
+                || join.left().as_any().is::<KeyCollectorExec>()
+            {
+                return Ok(Transformed::no(node));
+            }
+            for (build_key, probe_key) in join.on() {
+                let Some(column) = probe_key.as_any().downcast_ref::<ColumnExpr>() else {
+                    continue;
+                };
+                let keys = Arc::new(JoinKeys::default());
+                let Some(probe) = with_join_keys(join.right(), column.index(), &keys)? else {
+                    continue;
+                };
+                let build = Arc::clone(join.left());
+                let build =
+                    KeyCollectorExec::new(build, Arc::clone(build_key), keys, self.max_keys);
+                return Ok(Transformed::yes(node.with_new_children(vec![Arc::new(build), probe])?));
+            }
+            Ok(Transformed::no(node))
+        })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// `plan` with the [`SidewaysScanExec`] its column `index` comes from unchanged given
+/// `keys`, if there is one.
+fn with_join_keys(
+    plan: &Arc<dyn ExecutionPlan>,
+    index: usize,
+    keys: &Arc<JoinKeys>,
+) -> DataFusionResult<Option<Arc<dyn ExecutionPlan>>> {
+    let any = plan.as_any();
+    if let Some(scan) = any.downcast_ref::<SidewaysScanExec>() {
+        if scan.keys.is_some() || *scan.schema().field(index).name() != scan.column {
+            return Ok(None);
+        }
+        return Ok(Some(Arc::new(scan.with_keys(Arc::clone(keys)))));
+    }
+    let index = if let Some(projection) = any.downcast_ref::<ProjectionExec>() {
+        let (expr, _) = &projection.expr()[index];
+        match expr.as_any().downcast_ref::<ColumnExpr>() {
+            Some(column) => column.index(),
+            None => return Ok(None),
+        }
+    } else if let Some(filter) = any.downcast_ref::<FilterExec>() {
+        filter.projection().map_or(index, |projection| projection[index])
+    } else if any.is::<RepartitionExec>()
+        || any.is::<CoalesceBatchesExec>()
+        || any.is::<CoalescePartitionsExec>()
+        || any.is::<PrefetchExec>()
+    {
+        index
+    } else {
+        return Ok(None);
+    };
+    let Some(child) = with_join_keys(plan.children()[0], index, keys)? else {
+        return Ok(None);
+    };
+    Ok(Some(Arc::clone(plan).with_new_children(vec![child])?))
+}
+
+/// Collects the distinct keys of a join's build side as it passes through, publishing
+/// them once all its partitions are read.
+#[derive(Debug)]
+pub struct KeyCollectorExec {
+    input: Arc<dyn ExecutionPlan>,
+    collector: Arc<Collector>,
+}
+
+impl KeyCollectorExec {
+    /// Collect up to `max_keys` distinct values of `key` over `input` into `keys`.
+    pub fn new(
+        input: Arc<dyn ExecutionPlan>,
+        key: PhysicalExprRef,
+        keys: Arc<JoinKeys>,
+        max_keys: usize,
+    ) -> Self {
+        let pending = input.output_partitioning().partition_count();
+        let state = Mutex::new(Collected { keys: HashSet::new(), overflowed: false, pending });
+        Self { input, collector: Arc::new(Collector { key, keys, max_keys, state }) }
+    }
+}
+
+impl DisplayAs for KeyCollectorExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "KeyCollectorExec: key={}, max_keys={}",
+            self.collector.key, self.collector.max_keys
+        )
+    }
+}
+
+impl ExecutionPlan for KeyCollectorExec {
+    fn name(&self) -> &str {
+        "KeyCollectorExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let collector = &self.collector;
+        Ok(Arc::new(Self::new(
+            children.swap_remove(0),
+            Arc::clone(&collector.key),
+            Arc::clone(&collector.keys),
+            collector.max_keys,
+        )))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        let input = match self.input.execute(partition, context) {
+            Ok(input) => input,
+            Err(e) => {
+                self.collector.keys.publish(Keys::Unfiltered);
+                return Err(e);
+            }
+        };
+        let collector = Arc::clone(&self.collector);
+        Ok(Box::pin(CollectingStream { input, collector, done: false }))
+    }
+}
+
+#[derive(Debug)]
+struct Collector {
+    key: PhysicalExprRef,
+    keys: Arc<JoinKeys>,
+    max_keys: usize,
+    state: Mutex<Collected>,
+}
+
+#[derive(Debug)]
+struct Collected {
+    keys: HashSet<ScalarValue>,
+    overflowed: bool,
+    /// Partitions not read to the end yet.
+    pending: usize,
+}
+
+impl Collector {
+    fn add(&self, batch: &RecordBatch) {
+        // Keys that cannot be collected cannot filter the probe side.
+        if self.try_add(batch).is_err() {
+            self.keys.publish(Keys::Unfiltered);
+        }
+    }
+
+    fn try_add(&self, batch: &RecordBatch) -> DataFusionResult<()> {
+        let values = self.key.evaluate(batch)?.into_array(batch.num_rows())?;
+        let mut state = self.state.lock().unwrap();
+        if state.overflowed {
+            return Ok(());
+        }
+        for i in 0..values.len() {
+            if values.is_null(i) {
+                continue;
+            }
+            state.keys.insert(ScalarValue::try_from_array(&values, i)?);
+            if state.keys.len() > self.max_keys {
+                state.overflowed = true;
+                state.keys = HashSet::new();
+                // The probe side need not wait for the rest.
+                self.keys.publish(Keys::Unfiltered);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_partition(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pending = state.pending.saturating_sub(1);
+        if state.pending > 0 || state.overflowed {
+            return;
+        }
+        let mut keys: Vec<_> = state.keys.drain().collect();
+        keys.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        self.keys.publish(Keys::Collected(keys.into()));
+    }
+}
+
+/// A partition of the build side, its keys collected as it is read.
+struct CollectingStream {
+    input: SendableRecordBatchStream,
+    collector: Arc<Collector>,
+    done: bool,
+}
+
+impl Stream for CollectingStream {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.input.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(batch))) => self.collector.add(batch),
+            Poll::Ready(Some(Err(_))) => self.collector.keys.publish(Keys::Unfiltered),
+            Poll::Ready(None) if !self.done => {
+                self.done = true;
+                self.collector.finish_partition();
+            }
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl RecordBatchStream for CollectingStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Drop for CollectingStream {
+    fn drop(&mut self) {
+        // A partition left unread leaves the keys incomplete.
+        if !self.done {
+            self.collector.keys.publish(Keys::Unfiltered);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{Field, Schema};
+    use datafusion::datasource::MemTable;
+    use datafusion::physical_expr::expressions::col;
+    use datafusion::physical_plan::displayable;
+    use datafusion::prelude::SessionContext;
+
+    /// A table taking `IN` filters on `id`, though leaving them to the engine, that
+    /// records the filters of its scans.
+    #[derive(Debug)]
+    struct RemoteTable {
+        table: MemTable,
+        scans: Mutex<Vec<Vec<Expr>>>,
+    }
+
+    #[async_trait]
+    impl TableProvider for RemoteTable {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            self.table.schema()
+        }
+
+        fn table_type(&self) -> TableType {
+            TableType::Base
+        }
+
+        fn supports_filters_pushdown(
+            &self,
+            filters: &[&Expr],
+        ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+            Ok(filters
+                .iter()
+                .map(|filter| match filter {
+                    Expr::InList(list) if list.expr.as_ref() == &Expr::Column("id".into()) => {
+                        TableProviderFilterPushDown::Inexact
+                    }
+                    _ => TableProviderFilterPushDown::Unsupported,
+                })
+                .collect())
+        }
+
+        async fn scan(
+            &self,
+            state: &dyn Session,
+            projection: Option<&Vec<usize>>,
+            filters: &[Expr],
+            limit: Option<usize>,
+        ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+            self.scans.lock().unwrap().push(filters.to_vec());
+            // Not read in place, as a scan of memory is.
+            let scan = self.table.scan(state, projection, &[], limit).await?;
+            Ok(Arc::new(CoalescePartitionsExec::new(scan)))
+        }
+    }
+
+    fn ids(ids: Vec<Option<i64>>) -> DataFusionResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+        Ok(RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(ids))])?)
+    }
+
+    #[tokio::test]
+    async fn test_probe_scans_are_filtered_by_build_side_keys() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let dim_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("label", DataType::Utf8, false),
+        ]));
+        let dim = RecordBatch::try_new(
+            Arc::clone(&dim_schema),
+            vec![
+                Arc::new(Int64Array::from(vec![3, 1, 3])),
+                Arc::new(StringArray::from(vec!["c", "a", "c2"])),
+            ],
+        )?;
+        engine.register_table("dim", Arc::new(MemTable::try_new(dim_schema, vec![vec![dim]])?))?;
+        let fact_schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("amount", DataType::Int64, false),
+        ]));
+        let facts = RecordBatch::try_new(
+            Arc::clone(&fact_schema),
+            vec![
+                Arc::new(Int64Array::from_iter_values(1..=1000)),
+                Arc::new(Int64Array::from_iter_values((1..=1000).map(|i| i * 10))),
+            ],
+        )?;
+        let remote = Arc::new(RemoteTable {
+            table: MemTable::try_new(fact_schema, vec![vec![facts]])?,
+            scans: Mutex::default(),
+        });
+        engine.register_table("facts", Arc::clone(&remote) as Arc<dyn TableProvider>)?;
+
+        let sql = "SELECT d.label, f.amount FROM dim d JOIN facts f ON d.id = f.id ORDER BY label";
+        let plan = engine.sql(sql).await?.create_physical_plan().await?;
+        let display = displayable(plan.as_ref()).indent(false).to_string();
+        assert!(display.contains("KeyCollectorExec"), "{display}");
+        assert!(display.contains("SidewaysScanExec: column=id, join_keys=true"), "{display}");
+
+        remote.scans.lock().unwrap().clear();
+        let result = engine.query(sql).await?;
+        let batch = &result.batches[0];
+        let amounts = batch.column(1).as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(amounts.values(), &[10, 30, 30]);
+        // Scanned as planned, then again by the distinct keys.
+        let scans = remote.scans.lock().unwrap().clone();
+        let keys = vec![ScalarValue::Int64(Some(1)), ScalarValue::Int64(Some(3))];
+        assert_eq!(scans, vec![vec![], vec![key_filter("id", keys)]]);
+
+        // No key, no rows to scan for.
+        remote.scans.lock().unwrap().clear();
+        let empty = "SELECT * FROM dim d JOIN facts f ON d.id = f.id WHERE d.label = 'z'";
+        assert_eq!(
+            engine.query(empty).await?.batches.iter().map(|b| b.num_rows()).sum::<usize>(),
+            0
+        );
+        assert_eq!(remote.scans.lock().unwrap().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_collected_up_to_the_maximum() -> DataFusionResult<()> {
+        let batches = vec![ids(vec![Some(2), Some(1)])?, ids(vec![None, Some(2), Some(3)])?];
+        let ctx = SessionContext::new();
+        let table = MemTable::try_new(batches[0].schema(), vec![batches])?;
+        let input = table.scan(&ctx.state(), None, &[], None).await?;
+        let key = col("id", &input.schema())?;
+
+        let keys = Arc::new(JoinKeys::default());
+        let collector =
+            KeyCollectorExec::new(Arc::clone(&input), Arc::clone(&key), Arc::clone(&keys), 3);
+        datafusion::physical_plan::collect(Arc::new(collector), ctx.task_ctx()).await?;
+        let expected: Vec<_> = (1..=3).map(|i| ScalarValue::Int64(Some(i))).collect();
+        assert_eq!(keys.wait().await, Keys::Collected(expected.into()));
+
+        let keys = Arc::new(JoinKeys::default());
+        let collector = KeyCollectorExec::new(input, key, Arc::clone(&keys), 2);
+        datafusion::physical_plan::collect(Arc::new(collector), ctx.task_ctx()).await?;
+        assert_eq!(keys.wait().await, Keys::Unfiltered);
+        Ok(())
+    }
+}
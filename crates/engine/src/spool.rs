@@ -0,0 +1,270 @@
+//! Spilling oversized query results to disk.
+//!
+//! A [`ResultSpool`] given to [`QueryEngine::with_result_spool`](crate::QueryEngine::with_result_spool)
+//! collects the results of [`QueryEngine::query`](crate::QueryEngine::query) in memory
+//! until they hold more than its threshold ([`DEFAULT_SPILL_THRESHOLD`] unless set).
+//! Past it, the batches collected so far and the rest of the result are written to a
+//! temporary Arrow IPC file in the spool's directory instead, and the query's
+//! [`QueryResult::spilled`](crate::diagnostics::QueryResult::spilled) reads them back
+//! one batch at a time, so a result only has to fit on disk. The file is removed
+//! once the [`SpooledResult`] is dropped.
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::reader::StreamReader;
+use datafusion::arrow::ipc::writer::StreamWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::SendableRecordBatchStream;
+use futures::StreamExt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Bytes of batches a result holds in memory before it is spilled, unless configured
+/// otherwise.
+pub const DEFAULT_SPILL_THRESHOLD: usize = 256 * 1024 * 1024;
+
+/// Collects results, spilling those over a threshold to a local directory.
+#[derive(Debug)]
+pub struct ResultSpool {
+    dir: PathBuf,
+    threshold: usize,
+    next: AtomicU64,
+}
+
+impl ResultSpool {
+    /// Spill results to files in the directory `dir`, creating it if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> DataFusionResult<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, threshold: DEFAULT_SPILL_THRESHOLD, next: AtomicU64::new(0) })
+    }
+
+    /// Spill results once their batches hold more than `bytes`.
+    pub fn with_threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    /// Run `stream` to its end, keeping its batches in memory or, past the
+    /// threshold, in a file.
+    pub async fn spool(
+        &self,
+        mut stream: SendableRecordBatchStream,
+    ) -> DataFusionResult<SpooledResult> {
+        let schema = stream.schema();
+        let (mut batches, mut rows, mut bytes) = (vec![], 0, 0);
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+            rows += batch.num_rows();
+            bytes += batch.get_array_memory_size();
+            batches.push(batch);
+            if bytes > self.threshold {
+                return self.spill(schema, batches, rows, stream).await;
+            }
+        }
+        Ok(SpooledResult { schema, rows, storage: Storage::Memory(batches) })
+    }
+
+    /// Write `batches` and the rest of `stream` to a new file.
+    async fn spill(
+        &self,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+        mut rows: usize,
+        mut stream: SendableRecordBatchStream,
+    ) -> DataFusionResult<SpooledResult> {
+        let seq = self.next.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("result-{}-{seq}.arrow", std::process::id()));
+        let out = OpenOptions::new().write(true).create_new(true).open(&path)?;
+        // From here on the file goes with the result, or is removed on error.
+        let file = Arc::new(SpillFile { path });
+        // The stream format, unlike the file format, takes batches whose dictionaries
+        // differ.
+        let mut writer = StreamWriter::try_new(BufWriter::new(out), &schema)?;
+        for batch in batches {
+            writer.write(&batch)?;
+        }
+        while let Some(batch) = stream.next().await {
+            let batch = batch?;
+            rows += batch.num_rows();
+            writer.write(&batch)?;
+        }
+        writer.finish()?;
+        writer.into_inner()?.into_inner().map_err(|e| e.into_error())?.sync_data()?;
+        Ok(SpooledResult { schema, rows, storage: Storage::Disk(file) })
+    }
+}
+
+/// A result collected by a [`ResultSpool`], in memory or in a file. Clones share
+/// the file.
+#[derive(Debug, Clone)]
+pub struct SpooledResult {
+    schema: SchemaRef,
+    rows: usize,
+    storage: Storage,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    Memory(Vec<RecordBatch>),
+    Disk(Arc<SpillFile>),
+}
+
+impl SpooledResult {
+    pub fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Whether the result was written to a file.
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.storage, Storage::Disk(_))
+    }
+
+    /// The file holding the result, if it was spilled.
+    pub fn path(&self) -> Option<&Path> {
+        match &self.storage {
+            Storage::Memory(_) => None,
+            Storage::Disk(file) => Some(&file.path),
+        }
+    }
+
+    /// The batches of a result kept in memory, or the result itself if it was spilled.
+    pub fn into_batches(self) -> Result<Vec<RecordBatch>, Self> {
+        match self.storage {
+            Storage::Memory(batches) => Ok(batches),
+            Storage::Disk(_) => Err(self),
+        }
+    }
+
+    /// Read the result's batches in order, a spilled result one batch at a time.
+    pub fn batches(&self) -> DataFusionResult<SpooledBatches> {
+        Ok(match &self.storage {
+            Storage::Memory(batches) => SpooledBatches::Memory(batches.clone().into_iter()),
+            Storage::Disk(file) => {
+                let reader = StreamReader::try_new(BufReader::new(File::open(&file.path)?), None)?;
+                SpooledBatches::Disk(reader, Arc::clone(file))
+            }
+        })
+    }
+}
+
+/// The batches of a [`SpooledResult`], see [`SpooledResult::batches`]. Keeps a
+/// spilled result's file until dropped.
+pub enum SpooledBatches {
+    Memory(std::vec::IntoIter<RecordBatch>),
+    Disk(StreamReader<BufReader<File>>, Arc<SpillFile>),
+}
+
+impl Iterator for SpooledBatches {
+    type Item = DataFusionResult<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            SpooledBatches::Memory(batches) => batches.next().map(Ok),
+            SpooledBatches::Disk(reader, _) => reader.next().map(|b| b.map_err(Into::into)),
+        }
+    }
+}
+
+/// A spilled result's file, removed when dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{AsArray, DictionaryArray, Int64Array};
+    use datafusion::arrow::datatypes::{DataType, Field, Int32Type, Schema};
+    use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+
+    fn stream(batches: usize) -> SendableRecordBatchStream {
+        let schema = Arc::new(Schema::new(vec![Field::new("n", DataType::Int64, false)]));
+        let batch = |i: i64| {
+            let values = Int64Array::from_iter_values(i * 100..(i + 1) * 100);
+            RecordBatch::try_new(Arc::clone(&schema), vec![Arc::new(values)]).unwrap()
+        };
+        let batches: Vec<_> = (0..batches as i64).map(|i| Ok(batch(i))).collect();
+        Box::pin(RecordBatchStreamAdapter::new(Arc::clone(&schema), futures::stream::iter(batches)))
+    }
+
+    fn values(result: &SpooledResult) -> Vec<i64> {
+        let batches: Vec<_> = result.batches().unwrap().collect::<Result<_, _>>().unwrap();
+        batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0).as_any().downcast_ref::<Int64Array>().unwrap().values().to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_results_over_the_threshold_are_spilled() {
+        let dir = std::env::temp_dir().join(format!("igloo-spool-{}", std::process::id()));
+        let spool = ResultSpool::new(&dir).unwrap().with_threshold(2_000);
+
+        let small = spool.spool(stream(1)).await.unwrap();
+        assert!(!small.is_spilled());
+        assert_eq!(small.num_rows(), 100);
+        assert_eq!(small.into_batches().unwrap().len(), 1);
+
+        let large = spool.spool(stream(10)).await.unwrap();
+        assert!(large.is_spilled());
+        assert_eq!(large.num_rows(), 1_000);
+        assert_eq!(values(&large), (0..1_000).collect::<Vec<_>>());
+        let path = large.path().unwrap().to_path_buf();
+        assert!(path.exists());
+
+        // The file outlives the result while its batches are being read.
+        let mut batches = large.batches().unwrap();
+        drop(large);
+        assert_eq!(batches.next().unwrap().unwrap().num_rows(), 100);
+        drop(batches);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spilled_batches_keep_their_own_dictionaries() {
+        let dir = std::env::temp_dir().join(format!("igloo-spool-dict-{}", std::process::id()));
+        let spool = ResultSpool::new(&dir).unwrap().with_threshold(0);
+        let status = DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8));
+        let schema = Arc::new(Schema::new(vec![Field::new("status", status, false)]));
+        let batch = |values: Vec<&str>| {
+            let statuses: DictionaryArray<Int32Type> = values.into_iter().collect();
+            Ok(RecordBatch::try_new(Arc::clone(&schema), vec![Arc::new(statuses)]).unwrap())
+        };
+        let batches = vec![batch(vec!["paid", "open"]), batch(vec!["void"])];
+        let stream = RecordBatchStreamAdapter::new(schema.clone(), futures::stream::iter(batches));
+
+        let result = spool.spool(Box::pin(stream)).await.unwrap();
+        assert!(result.is_spilled());
+        let batches: Vec<_> = result.batches().unwrap().collect::<Result<_, _>>().unwrap();
+        let last = batches[1].column(0).as_dictionary::<Int32Type>();
+        assert_eq!(last.values().as_string::<i32>().value(0), "void");
+        drop((batches, result));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
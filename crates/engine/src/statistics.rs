@@ -0,0 +1,877 @@
+//! Table statistics gathered by `ANALYZE TABLE`.
+//!
+//! Many sources (remote databases, CSV and JSON files, custom connectors) cannot tell
+//! the planner how large they are, so it picks join orders and build sides blind.
+//! `ANALYZE TABLE name [FOR COLUMNS a, b]` scans the table once and computes its row
+//! count and, per column, the null count, an approximate distinct count and the
+//! minimum and maximum. The statistics are then served to the planner with the
+//! table's scans: the table is re-registered as an [`AnalyzedTable`], whose scans
+//! report them wherever the source itself knows nothing better. Every value is served
+//! as inexact, so they guide planning but are never taken as the answer to a query.
+//!
+//! With a catalog store (see [`catalog_store`](crate::catalog_store)) the statistics
+//! are recorded there too, so they survive restarts and reach the other engines
+//! sharing the store.
+//!
+//! Statistics go stale as the table changes. Each `INSERT`, `UPDATE` or `DELETE`
+//! run through the engine counts as a modification of the table, and the statistics
+//! record when they were gathered. An [`AnalyzePolicy`] (see
+//! [`QueryEngine::with_analyze_policy`](crate::QueryEngine::with_analyze_policy))
+//! bounds both; a query over a table whose statistics exceed either re-analyzes it in
+//! the background. Replacing or dropping a table discards its statistics.
+
+use async_trait::async_trait;
+use datafusion::arrow::array::{ArrayRef, RecordBatch, StringArray, UInt64Array};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::catalog::{Session, TableProvider};
+use datafusion::common::stats::Precision;
+use datafusion::common::tree_node::{Transformed, TreeNode, TreeNodeRecursion};
+use datafusion::common::{Column, Constraints, ScalarValue, Statistics};
+use datafusion::config::{CatalogOptions, ConfigOptions};
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::physical_plan::{FileScanConfig, FileScanConfigBuilder};
+use datafusion::datasource::source::DataSourceExec;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::functions_aggregate::expr_fn::{approx_distinct, count, max, min};
+use datafusion::logical_expr::dml::InsertOp;
+use datafusion::logical_expr::{
+    cast, col, lit, DdlStatement, Expr, LogicalPlan, TableProviderFilterPushDown, TableType,
+};
+use datafusion::physical_optimizer::PhysicalOptimizerRule;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, ExecutionPlan, PlanProperties};
+use datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use datafusion::sql::planner::{object_name_to_table_reference, IdentNormalizer};
+use datafusion::sql::sqlparser::ast::Statement;
+use datafusion::sql::TableReference;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::catalog_store::full_name;
+
+/// Statistics of one column.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ColumnStatistics {
+    pub name: String,
+    pub null_count: u64,
+    /// Approximate; `None` for nested types.
+    pub distinct_count: Option<u64>,
+    /// Bounds, as text castable back to the column's type. `None` for types without
+    /// an order, or when every value is null.
+    pub min: Option<String>,
+    pub max: Option<String>,
+}
+
+/// Statistics of a table, as of its last `ANALYZE`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TableStatistics {
+    pub num_rows: u64,
+    /// The analyzed columns; others have no statistics.
+    pub columns: Vec<ColumnStatistics>,
+    /// When the table was analyzed, in milliseconds since the Unix epoch.
+    pub analyzed_at_ms: u64,
+    /// Statements that wrote to the table since.
+    #[serde(default)]
+    pub modifications: u64,
+}
+
+impl TableStatistics {
+    /// Time since the table was analyzed.
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.analyzed_at_ms))
+    }
+
+    pub fn column(&self, name: &str) -> Option<&ColumnStatistics> {
+        self.columns.iter().find(|column| column.name == name)
+    }
+
+    /// These statistics for the planner, matched to `schema`'s columns by name.
+    pub fn to_statistics(&self, schema: &Schema) -> Statistics {
+        let mut statistics = Statistics::new_unknown(schema);
+        statistics.num_rows = Precision::Inexact(self.num_rows as usize);
+        for (field, target) in schema.fields().iter().zip(&mut statistics.column_statistics) {
+            let Some(column) = self.column(field.name()) else {
+                continue;
+            };
+            let bound = |value: &Option<String>| {
+                let value =
+                    value.clone().map(|v| ScalarValue::try_from_string(v, field.data_type()));
+                match value {
+                    Some(Ok(value)) => Precision::Inexact(value),
+                    _ => Precision::Absent,
+                }
+            };
+            target.null_count = Precision::Inexact(column.null_count as usize);
+            if let Some(distinct_count) = column.distinct_count {
+                target.distinct_count = Precision::Inexact(distinct_count as usize);
+            }
+            target.min_value = bound(&column.min);
+            target.max_value = bound(&column.max);
+        }
+        statistics
+    }
+
+    /// The result of `ANALYZE` for `table`: a row per analyzed column.
+    pub(crate) fn to_batch(&self, table: &str) -> DataFusionResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("table_name", DataType::Utf8, false),
+            Field::new("row_count", DataType::UInt64, false),
+            Field::new("column_name", DataType::Utf8, false),
+            Field::new("null_count", DataType::UInt64, false),
+            Field::new("distinct_count", DataType::UInt64, true),
+            Field::new("min_value", DataType::Utf8, true),
+            Field::new("max_value", DataType::Utf8, true),
+        ]));
+        let columns = &self.columns;
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec![table; columns.len()])),
+            Arc::new(UInt64Array::from(vec![self.num_rows; columns.len()])),
+            Arc::new(columns.iter().map(|c| Some(c.name.as_str())).collect::<StringArray>()),
+            Arc::new(columns.iter().map(|c| Some(c.null_count)).collect::<UInt64Array>()),
+            Arc::new(columns.iter().map(|c| c.distinct_count).collect::<UInt64Array>()),
+            Arc::new(columns.iter().map(|c| c.min.as_deref()).collect::<StringArray>()),
+            Arc::new(columns.iter().map(|c| c.max.as_deref()).collect::<StringArray>()),
+        ];
+        Ok(RecordBatch::try_new(schema, arrays)?)
+    }
+}
+
+/// When statistics are stale enough to be gathered again. Neither bound is set by
+/// default, so tables are only analyzed on request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AnalyzePolicy {
+    max_age: Option<Duration>,
+    max_modifications: Option<u64>,
+}
+
+impl AnalyzePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Re-analyze tables analyzed longer than `max_age` ago.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Re-analyze tables written by more than `max_modifications` statements since.
+    pub fn with_max_modifications(mut self, max_modifications: u64) -> Self {
+        self.max_modifications = Some(max_modifications);
+        self
+    }
+
+    pub fn is_stale(&self, statistics: &TableStatistics) -> bool {
+        self.max_age.is_some_and(|max_age| statistics.age() > max_age)
+            || self.max_modifications.is_some_and(|max| statistics.modifications > max)
+    }
+}
+
+/// The table and columns of an `ANALYZE` statement, if `sql` is a single one.
+/// Columns are empty unless listed with `FOR COLUMNS`. SQL that does not parse is
+/// `None`; planning it reports the error.
+pub fn parse_analyze_sql(sql: &str) -> DataFusionResult<Option<(TableReference, Vec<String>)>> {
+    let statements = match DFParser::parse_sql(sql) {
+        Ok(statements) if statements.len() == 1 => statements,
+        _ => return Ok(None),
+    };
+    let DFStatement::Statement(statement) = &statements[0] else {
+        return Ok(None);
+    };
+    let Statement::Analyze { table_name, partitions, columns, noscan, .. } = statement.as_ref()
+    else {
+        return Ok(None);
+    };
+    if partitions.is_some() || *noscan {
+        return Err(DataFusionError::NotImplemented(
+            "ANALYZE with PARTITION or NOSCAN is not supported".to_string(),
+        ));
+    }
+    let table = object_name_to_table_reference(table_name.clone(), true)?;
+    let normalizer = IdentNormalizer::new(true);
+    Ok(Some((table, columns.iter().map(|c| normalizer.normalize(c.clone())).collect())))
+}
+
+/// Scan `table` and compute the statistics of its `columns` (all if empty).
+pub(crate) async fn compute(
+    table: DataFrame,
+    columns: &[String],
+) -> DataFusionResult<TableStatistics> {
+    let schema = table.schema().clone();
+    let mut fields = vec![];
+    for field in schema.fields() {
+        if columns.is_empty() || columns.contains(field.name()) {
+            fields.push(field.clone());
+        }
+    }
+    if let Some(missing) = columns.iter().find(|c| !fields.iter().any(|f| f.name() == *c)) {
+        return Err(DataFusionError::Plan(format!("column {missing} not found")));
+    }
+    let mut aggregates = vec![count(lit(1)).alias("__rows")];
+    for (i, field) in fields.iter().enumerate() {
+        let column = col(Column::from_name(field.name()));
+        let data_type = field.data_type();
+        aggregates.push(count(column.clone()).alias(format!("__count_{i}")));
+        if let Some(distinct) = distinct_input(column.clone(), data_type) {
+            aggregates.push(approx_distinct(distinct).alias(format!("__distinct_{i}")));
+        }
+        if orderable(data_type) {
+            aggregates.push(min(column.clone()).alias(format!("__min_{i}")));
+            aggregates.push(max(column).alias(format!("__max_{i}")));
+        }
+    }
+    let batches = table.aggregate(vec![], aggregates)?.collect().await?;
+    let batch = batches
+        .first()
+        .filter(|b| b.num_rows() == 1)
+        .ok_or_else(|| DataFusionError::Execution("ANALYZE produced no statistics".to_string()))?;
+    let value = |name: &str| -> DataFusionResult<Option<ScalarValue>> {
+        match batch.column_by_name(name) {
+            Some(array) => Ok(Some(ScalarValue::try_from_array(array, 0)?)),
+            None => Ok(None),
+        }
+    };
+    let bound = |name: &str| -> DataFusionResult<Option<String>> {
+        match batch.column_by_name(name) {
+            Some(array) if array.is_valid(0) => Ok(Some(array_value_to_string(array, 0)?)),
+            _ => Ok(None),
+        }
+    };
+    let num_rows = as_u64(value("__rows")?).unwrap_or_default();
+    let mut statistics = vec![];
+    for (i, field) in fields.iter().enumerate() {
+        let non_null = as_u64(value(&format!("__count_{i}"))?).unwrap_or_default();
+        statistics.push(ColumnStatistics {
+            name: field.name().clone(),
+            null_count: num_rows.saturating_sub(non_null),
+            distinct_count: as_u64(value(&format!("__distinct_{i}"))?),
+            min: bound(&format!("__min_{i}"))?,
+            max: bound(&format!("__max_{i}"))?,
+        });
+    }
+    Ok(TableStatistics {
+        num_rows,
+        columns: statistics,
+        analyzed_at_ms: now_ms(),
+        modifications: 0,
+    })
+}
+
+/// What `approx_distinct` counts for a column of `data_type`: the column itself where
+/// supported, its text otherwise, and nothing for nested types.
+fn distinct_input(column: Expr, data_type: &DataType) -> Option<Expr> {
+    use DataType::*;
+    match data_type {
+        Int8 | Int16 | Int32 | Int64 | UInt8 | UInt16 | UInt32 | UInt64 => Some(column),
+        Utf8 | LargeUtf8 | Utf8View | Binary | LargeBinary => Some(column),
+        data_type if data_type.is_nested() => None,
+        _ => Some(cast(column, Utf8)),
+    }
+}
+
+fn orderable(data_type: &DataType) -> bool {
+    use DataType::*;
+    match data_type {
+        Interval(_) => false,
+        Boolean | Utf8 | LargeUtf8 | Utf8View => true,
+        data_type => data_type.is_numeric() || data_type.is_temporal(),
+    }
+}
+
+fn as_u64(value: Option<ScalarValue>) -> Option<u64> {
+    match value? {
+        ScalarValue::Int64(Some(n)) => Some(n as u64),
+        ScalarValue::UInt64(Some(n)) => Some(n),
+        _ => None,
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// A table whose scans report its analyzed statistics.
+#[derive(Debug)]
+pub struct AnalyzedTable {
+    inner: Arc<dyn TableProvider>,
+    statistics: Arc<TableStatistics>,
+}
+
+impl AnalyzedTable {
+    pub fn new(inner: Arc<dyn TableProvider>, statistics: Arc<TableStatistics>) -> Self {
+        Self { inner, statistics }
+    }
+
+    /// The table as registered before it was analyzed.
+    pub fn inner(&self) -> &Arc<dyn TableProvider> {
+        &self.inner
+    }
+
+    pub fn statistics(&self) -> &TableStatistics {
+        &self.statistics
+    }
+
+    /// `provider` without the statistics of an earlier `ANALYZE`, if it has any.
+    pub fn unwrap(provider: Arc<dyn TableProvider>) -> Arc<dyn TableProvider> {
+        match provider.as_any().downcast_ref::<AnalyzedTable>() {
+            Some(analyzed) => Arc::clone(&analyzed.inner),
+            None => provider,
+        }
+    }
+
+    /// `plan`, a scan of the inner table with `projection`, reporting the statistics.
+    fn with_statistics(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        projection: Option<&Vec<usize>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        // File scans keep statistics of their files' columns, before projection.
+        if let Some(exec) = plan.as_any().downcast_ref::<DataSourceExec>() {
+            if let Some(config) = exec.data_source().as_any().downcast_ref::<FileScanConfig>() {
+                let known = config.file_source.statistics()?;
+                let analyzed = self.statistics.to_statistics(&config.file_schema);
+                let config = FileScanConfigBuilder::from(config.clone())
+                    .with_statistics(merge(known, &analyzed))
+                    .build();
+                return Ok(DataSourceExec::from_data_source(config));
+            }
+        }
+        let statistics = self.statistics.to_statistics(&self.schema()).project(projection);
+        if statistics.column_statistics.len() != plan.schema().fields().len() {
+            return Ok(plan);
+        }
+        Ok(Arc::new(StatisticsExec::new(plan, statistics)))
+    }
+}
+
+#[async_trait]
+impl TableProvider for AnalyzedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.inner.schema()
+    }
+
+    fn constraints(&self) -> Option<&Constraints> {
+        self.inner.constraints()
+    }
+
+    fn table_type(&self) -> TableType {
+        self.inner.table_type()
+    }
+
+    fn get_table_definition(&self) -> Option<&str> {
+        self.inner.get_table_definition()
+    }
+
+    fn get_logical_plan(&self) -> Option<Cow<'_, LogicalPlan>> {
+        self.inner.get_logical_plan()
+    }
+
+    fn get_column_default(&self, column: &str) -> Option<&Expr> {
+        self.inner.get_column_default(column)
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan = self.inner.scan(state, projection, filters, limit).await?;
+        self.with_statistics(plan, projection)
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> DataFusionResult<Vec<TableProviderFilterPushDown>> {
+        self.inner.supports_filters_pushdown(filters)
+    }
+
+    fn statistics(&self) -> Option<Statistics> {
+        Some(self.statistics.to_statistics(&self.schema()))
+    }
+
+    async fn insert_into(
+        &self,
+        state: &dyn Session,
+        input: Arc<dyn ExecutionPlan>,
+        insert_op: InsertOp,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        self.inner.insert_into(state, input, insert_op).await
+    }
+}
+
+/// `known`, with what it leaves unknown taken from `analyzed`.
+fn merge(known: Statistics, analyzed: &Statistics) -> Statistics {
+    fn or<T>(known: Precision<T>, analyzed: &Precision<T>) -> Precision<T>
+    where
+        T: fmt::Debug + Clone + PartialEq + Eq + PartialOrd,
+    {
+        match known {
+            Precision::Absent => analyzed.clone(),
+            known => known,
+        }
+    }
+    let columns = known.column_statistics.into_iter().zip(&analyzed.column_statistics);
+    Statistics {
+        num_rows: or(known.num_rows, &analyzed.num_rows),
+        total_byte_size: or(known.total_byte_size, &analyzed.total_byte_size),
+        column_statistics: columns
+            .map(|(known, analyzed)| datafusion::common::ColumnStatistics {
+                null_count: or(known.null_count, &analyzed.null_count),
+                max_value: or(known.max_value, &analyzed.max_value),
+                min_value: or(known.min_value, &analyzed.min_value),
+                sum_value: or(known.sum_value, &analyzed.sum_value),
+                distinct_count: or(known.distinct_count, &analyzed.distinct_count),
+            })
+            .collect(),
+    }
+}
+
+/// Reports analyzed statistics for a scan that keeps none of its own. Only present
+/// while the physical optimizer runs: [`StripStatisticsRule`] removes it afterwards.
+#[derive(Debug)]
+pub struct StatisticsExec {
+    input: Arc<dyn ExecutionPlan>,
+    statistics: Statistics,
+}
+
+impl StatisticsExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>, statistics: Statistics) -> Self {
+        Self { input, statistics }
+    }
+}
+
+impl DisplayAs for StatisticsExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "StatisticsExec: rows={}", self.statistics.num_rows)
+    }
+}
+
+impl ExecutionPlan for StatisticsExec {
+    fn name(&self) -> &str {
+        "StatisticsExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        self.input.properties()
+    }
+
+    fn maintains_input_order(&self) -> Vec<bool> {
+        vec![true]
+    }
+
+    fn benefits_from_input_partitioning(&self) -> Vec<bool> {
+        vec![false]
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(Self::new(children.swap_remove(0), self.statistics.clone())))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> DataFusionResult<SendableRecordBatchStream> {
+        self.input.execute(partition, context)
+    }
+
+    fn partition_statistics(&self, partition: Option<usize>) -> DataFusionResult<Statistics> {
+        let known = self.input.partition_statistics(partition)?;
+        match partition {
+            None => Ok(merge(known, &self.statistics)),
+            Some(_) => Ok(known),
+        }
+    }
+}
+
+/// Removes every [`StatisticsExec`] once the built-in rules have used their
+/// statistics, so plans only hold operators that other nodes can run.
+#[derive(Debug, Default)]
+pub struct StripStatisticsRule;
+
+impl PhysicalOptimizerRule for StripStatisticsRule {
+    fn optimize(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _config: &ConfigOptions,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let plan =
+            plan.transform_up(|node| match node.as_any().downcast_ref::<StatisticsExec>() {
+                Some(exec) => Ok(Transformed::yes(Arc::clone(&exec.input))),
+                None => Ok(Transformed::no(node)),
+            })?;
+        Ok(plan.data)
+    }
+
+    fn name(&self) -> &str {
+        "strip_statistics"
+    }
+
+    fn schema_check(&self) -> bool {
+        true
+    }
+}
+
+/// A statement changing the data of a table, which its statistics should follow.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum TableWrite {
+    /// Rows were inserted, updated or deleted.
+    Modified(String),
+    /// The table was dropped or replaced by a new one.
+    Replaced(String),
+}
+
+impl TableWrite {
+    pub(crate) fn of(plan: &LogicalPlan, ctx: &SessionContext) -> DataFusionResult<Option<Self>> {
+        let options = ctx.state().config().options().catalog.clone();
+        let name = |name: &TableReference| full_name(name.clone(), &options);
+        Ok(match plan {
+            LogicalPlan::Dml(dml) => Some(TableWrite::Modified(name(&dml.table_name))),
+            LogicalPlan::Ddl(DdlStatement::DropTable(drop)) => {
+                Some(TableWrite::Replaced(name(&drop.name)))
+            }
+            LogicalPlan::Ddl(DdlStatement::CreateMemoryTable(create))
+                if !(create.if_not_exists && ctx.table_exist(create.name.clone())?) =>
+            {
+                Some(TableWrite::Replaced(name(&create.name)))
+            }
+            LogicalPlan::Ddl(DdlStatement::CreateExternalTable(create))
+                if !(create.if_not_exists && ctx.table_exist(create.name.clone())?) =>
+            {
+                Some(TableWrite::Replaced(name(&create.name)))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// The analyzed tables of an engine, by full name (see
+/// [`full_name`](crate::catalog_store::full_name)).
+#[derive(Debug, Default)]
+pub(crate) struct AnalyzedTables {
+    tables: RwLock<HashMap<String, Arc<TableStatistics>>>,
+    /// Tables being re-analyzed in the background.
+    running: Mutex<HashSet<String>>,
+}
+
+impl AnalyzedTables {
+    pub(crate) fn get(&self, name: &str) -> Option<Arc<TableStatistics>> {
+        self.tables.read().expect("statistics lock poisoned").get(name).cloned()
+    }
+
+    pub(crate) fn insert(&self, name: &str, statistics: Arc<TableStatistics>) {
+        let mut tables = self.tables.write().expect("statistics lock poisoned");
+        tables.insert(name.to_string(), statistics);
+    }
+
+    pub(crate) fn remove(&self, name: &str) -> Option<Arc<TableStatistics>> {
+        self.tables.write().expect("statistics lock poisoned").remove(name)
+    }
+
+    /// Count a modification of `name`, returning its updated statistics if analyzed.
+    pub(crate) fn modified(&self, name: &str) -> Option<Arc<TableStatistics>> {
+        let mut tables = self.tables.write().expect("statistics lock poisoned");
+        let statistics = tables.get_mut(name)?;
+        Arc::make_mut(statistics).modifications += 1;
+        Some(Arc::clone(statistics))
+    }
+
+    /// The tables of `names` that `policy` considers stale and that are not already
+    /// being re-analyzed, which they are from then on until [`Self::finished`].
+    pub(crate) fn start_stale(&self, names: &[String], policy: &AnalyzePolicy) -> Vec<String> {
+        let tables = self.tables.read().expect("statistics lock poisoned");
+        let mut running = self.running.lock().expect("statistics lock poisoned");
+        let stale = names
+            .iter()
+            .filter(|name| tables.get(*name).is_some_and(|statistics| policy.is_stale(statistics)));
+        stale.filter(|name| running.insert(name.to_string())).cloned().collect()
+    }
+
+    pub(crate) fn finished(&self, name: &str) {
+        self.running.lock().expect("statistics lock poisoned").remove(name);
+    }
+
+    /// Apply statistics recorded in the catalog store to `ctx`'s table `name`.
+    pub(crate) async fn apply(
+        &self,
+        ctx: &SessionContext,
+        name: &str,
+        definition: Option<&[u8]>,
+    ) -> DataFusionResult<()> {
+        let Some(definition) = definition else {
+            if self.remove(name).is_some() && ctx.table_exist(name)? {
+                let provider = ctx.table_provider(name).await?;
+                ctx.deregister_table(name)?;
+                ctx.register_table(name, AnalyzedTable::unwrap(provider))?;
+            }
+            return Ok(());
+        };
+        let statistics: TableStatistics = serde_json::from_slice(definition)
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let statistics = Arc::new(statistics);
+        self.insert(name, Arc::clone(&statistics));
+        install(ctx, name, statistics).await
+    }
+}
+
+/// Serve `statistics` with the scans of `ctx`'s table `name`.
+pub(crate) async fn install(
+    ctx: &SessionContext,
+    name: &str,
+    statistics: Arc<TableStatistics>,
+) -> DataFusionResult<()> {
+    let provider = AnalyzedTable::unwrap(ctx.table_provider(name).await?);
+    ctx.deregister_table(name)?;
+    ctx.register_table(name, Arc::new(AnalyzedTable::new(provider, statistics)))?;
+    Ok(())
+}
+
+/// The full names of the tables `plan` scans, including in its subqueries.
+pub(crate) fn scanned_tables(plan: &LogicalPlan, options: &CatalogOptions) -> Vec<String> {
+    let mut tables = Vec::new();
+    let _ = plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            let name = full_name(scan.table_name.clone(), options);
+            if !tables.contains(&name) {
+                tables.push(name);
+            }
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog_store::SqliteCatalogStore;
+    use crate::QueryEngine;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::physical_plan::displayable;
+
+    fn stats_of(engine: &QueryEngine, table: &str) -> TableStatistics {
+        engine.table_statistics(table).expect("analyzed")
+    }
+
+    #[test]
+    fn test_parse_analyze() {
+        let (table, columns) = parse_analyze_sql("ANALYZE TABLE Sales.Orders").unwrap().unwrap();
+        assert_eq!(table, TableReference::partial("sales", "orders"));
+        assert!(columns.is_empty());
+        let (_, columns) =
+            parse_analyze_sql("analyze table orders for columns id, \"Amount\"").unwrap().unwrap();
+        assert_eq!(columns, ["id", "Amount"]);
+        assert_eq!(parse_analyze_sql("SELECT 1").unwrap(), None);
+        assert!(parse_analyze_sql("ANALYZE TABLE orders PARTITION (day = 1)").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_computes_statistics() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine
+            .query(
+                "CREATE TABLE orders (id BIGINT, customer VARCHAR, placed DATE, tags INT[]) AS \
+                 VALUES (1, 'ann', DATE '2024-01-03', [1]), (2, NULL, DATE '2024-02-01', []), \
+                 (3, 'bob', NULL, NULL), (4, 'ann', DATE '2023-12-31', [2, 3])",
+            )
+            .await?;
+        let result = engine.query("ANALYZE TABLE orders").await?;
+        let expected = "\
++------------+-----------+-------------+------------+----------------+------------+------------+
+| table_name | row_count | column_name | null_count | distinct_count | min_value  | max_value  |
++------------+-----------+-------------+------------+----------------+------------+------------+
+| orders     | 4         | id          | 0          | 4              | 1          | 4          |
+| orders     | 4         | customer    | 1          | 2              | ann        | bob        |
+| orders     | 4         | placed      | 1          | 3              | 2023-12-31 | 2024-02-01 |
+| orders     | 4         | tags        | 1          |                |            |            |
++------------+-----------+-------------+------------+----------------+------------+------------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+
+        let schema = engine.session_context().table_provider("orders").await?.schema();
+        let statistics = stats_of(&engine, "orders").to_statistics(&schema);
+        assert_eq!(statistics.num_rows, Precision::Inexact(4));
+        let placed = &statistics.column_statistics[2];
+        assert_eq!(placed.min_value, Precision::Inexact(ScalarValue::Date32(Some(19722))));
+
+        engine.query("ANALYZE TABLE orders FOR COLUMNS id").await?;
+        assert_eq!(stats_of(&engine, "orders").columns.len(), 1);
+        let err = engine.query("ANALYZE TABLE orders FOR COLUMNS nope").await.unwrap_err();
+        assert!(err.to_string().contains("nope"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_statistics_reach_the_planner() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine
+            .query("CREATE TABLE big AS SELECT value AS id FROM generate_series(1, 1000)")
+            .await?;
+        engine.query("CREATE TABLE small AS VALUES (1), (2)").await?;
+        // A source knowing nothing of its size, as a remote table would.
+        let big = engine.session_context().deregister_table("big")?.unwrap();
+        engine.register_table("big", Arc::new(Opaque(big)))?;
+
+        engine.query("ANALYZE TABLE big").await?;
+        let df = engine.sql("SELECT * FROM big JOIN small ON big.id = small.column1").await?;
+        let plan = df.create_physical_plan().await?;
+        let displayed = displayable(plan.as_ref()).indent(false).to_string();
+        assert!(!displayed.contains("StatisticsExec"), "{displayed}");
+        // The small side is collected, the big one streamed.
+        let join = displayed.lines().find(|l| l.contains("HashJoinExec")).unwrap();
+        assert!(join.contains("column1@0, id@0"), "{displayed}");
+
+        // Writes count as modifications; replacing the table discards its statistics.
+        engine.query("INSERT INTO small VALUES (3)").await?;
+        engine.query("ANALYZE TABLE small").await?;
+        engine.query("INSERT INTO small VALUES (4)").await?;
+        assert_eq!(stats_of(&engine, "small").modifications, 1);
+        engine.query("CREATE OR REPLACE TABLE small AS VALUES (1)").await?;
+        assert!(engine.table_statistics("small").is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_statistics_are_persisted_and_refreshed_when_stale() -> DataFusionResult<()> {
+        let dir = std::env::temp_dir().join(format!("igloo-statistics-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let events = dir.join("events");
+        std::fs::create_dir_all(&events)?;
+        std::fs::write(events.join("part-0.csv"), "id,kind\n1,a\n2,b\n3,a\n")?;
+        let store = Arc::new(SqliteCatalogStore::open(dir.join("catalog.db"))?);
+
+        let policy = AnalyzePolicy::new().with_max_modifications(0);
+        let first = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        let first = first.with_analyze_policy(policy);
+        first
+            .query(&format!(
+                "CREATE EXTERNAL TABLE events STORED AS CSV LOCATION '{}/' \
+                 OPTIONS ('format.has_header' 'true')",
+                events.display()
+            ))
+            .await?;
+        first.query("ANALYZE TABLE events").await?;
+
+        let second = QueryEngine::new().with_catalog_store(store.clone()).await?;
+        assert_eq!(stats_of(&second, "events").num_rows, 3);
+        let df = second.sql("SELECT * FROM events").await?;
+        let plan = df.create_physical_plan().await?;
+        assert_eq!(plan.partition_statistics(None)?.num_rows, Precision::Inexact(3));
+
+        first.query("INSERT INTO events VALUES (4, 'c')").await?;
+        assert_eq!(second.refresh_catalog().await?, 1);
+        assert_eq!(stats_of(&second, "events").modifications, 1);
+        // Querying the stale table re-analyzes it in the background.
+        first.query("SELECT count(*) FROM events").await?;
+        for _ in 0..100 {
+            if stats_of(&first, "events").modifications == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(stats_of(&first, "events").num_rows, 4);
+        assert_eq!(stats_of(&first, "events").modifications, 0);
+
+        first.query("DROP TABLE events").await?;
+        let third = QueryEngine::new().with_catalog_store(store).await?;
+        assert!(third.table_statistics("events").is_none());
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    /// A table that hides the statistics of its scans.
+    #[derive(Debug)]
+    struct Opaque(Arc<dyn TableProvider>);
+
+    #[async_trait]
+    impl TableProvider for Opaque {
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn schema(&self) -> SchemaRef {
+            self.0.schema()
+        }
+
+        fn table_type(&self) -> TableType {
+            TableType::Base
+        }
+
+        async fn scan(
+            &self,
+            state: &dyn Session,
+            projection: Option<&Vec<usize>>,
+            filters: &[Expr],
+            limit: Option<usize>,
+        ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+            let plan = self.0.scan(state, projection, filters, limit).await?;
+            Ok(Arc::new(OpaqueExec(plan)))
+        }
+    }
+
+    #[derive(Debug)]
+    struct OpaqueExec(Arc<dyn ExecutionPlan>);
+
+    impl DisplayAs for OpaqueExec {
+        fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "OpaqueExec")
+        }
+    }
+
+    impl ExecutionPlan for OpaqueExec {
+        fn name(&self) -> &str {
+            "OpaqueExec"
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn properties(&self) -> &PlanProperties {
+            self.0.properties()
+        }
+
+        fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+            vec![]
+        }
+
+        fn with_new_children(
+            self: Arc<Self>,
+            _children: Vec<Arc<dyn ExecutionPlan>>,
+        ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+            Ok(self)
+        }
+
+        fn execute(
+            &self,
+            partition: usize,
+            context: Arc<TaskContext>,
+        ) -> DataFusionResult<SendableRecordBatchStream> {
+            self.0.execute(partition, context)
+        }
+    }
+}
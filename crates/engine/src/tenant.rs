@@ -0,0 +1,84 @@
+//! Tenants: isolated catalogs and resources within one engine.
+//!
+//! A tenant added with [`QueryEngine::add_tenant`](crate::QueryEngine::add_tenant) gets
+//! its own engine, sharing only the functions and policies of the engine it was added
+//! to. Everything else is its own:
+//!
+//! - catalogs: it starts with an empty `datafusion.public` schema, sees only the tables
+//!   registered through its engine, and its tables are invisible to other tenants and
+//!   to the base engine;
+//! - caches: file listings and Parquet statistics are cached per tenant;
+//! - memory: queries share the tenant's [`Tenant::with_memory_limit`] pool, spilling
+//!   or failing when it is exhausted rather than taking memory from other tenants;
+//! - time: statements are cancelled after [`Tenant::with_statement_timeout`], which a
+//!   session's own `statement_timeout` can lower but not raise.
+
+use datafusion::error::Result as DataFusionResult;
+use datafusion::execution::runtime_env::RuntimeEnvBuilder;
+use datafusion::execution::session_state::{SessionState, SessionStateBuilder};
+use datafusion::execution::FunctionRegistry;
+use std::time::Duration;
+
+/// A tenant and its resource limits. No limit is set by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tenant {
+    pub(crate) name: String,
+    memory_limit: Option<usize>,
+    pub(crate) statement_timeout: Option<Duration>,
+}
+
+impl Tenant {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), memory_limit: None, statement_timeout: None }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Bytes of memory the tenant's running queries may hold in total.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    pub fn with_statement_timeout(mut self, timeout: Duration) -> Self {
+        self.statement_timeout = Some(timeout);
+        self
+    }
+}
+
+/// A fresh session for `tenant`: `base`'s configuration, rules, functions, table
+/// factories and file formats over a new catalog and a runtime of its own.
+pub(crate) fn tenant_state(base: &SessionState, tenant: &Tenant) -> DataFusionResult<SessionState> {
+    let mut runtime = RuntimeEnvBuilder::new();
+    if let Some(limit) = tenant.memory_limit {
+        runtime = runtime.with_memory_limit(limit, 1.0);
+    }
+    // `base` already has its catalog, so its config says not to create one.
+    let config = base.config().clone().with_create_default_catalog_and_schema(true);
+    // The state only hands its formats out one extension at a time; a builder has them all.
+    let file_formats = SessionStateBuilder::new_from_existing(base.clone()).file_formats().take();
+    let mut state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_config(config)
+        .with_runtime_env(runtime.build_arc()?)
+        .with_analyzer_rules(base.analyzer().rules.clone())
+        .with_optimizer_rules(base.optimizer().rules.clone())
+        .with_physical_optimizer_rules(base.physical_optimizers().to_vec())
+        .with_table_factories(base.table_factories().clone())
+        .with_file_formats(file_formats.unwrap_or_default())
+        .build();
+    for udf in base.scalar_functions().values() {
+        state.register_udf(udf.clone())?;
+    }
+    Ok(state)
+}
+
+/// The tighter of two optional limits.
+pub(crate) fn min_timeout(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,390 @@
+//! Coercing the inputs of `UNION`s to common types.
+//!
+//! The same data read from different sources rarely has exactly the same types: an
+//! `int4` key in Postgres is an `int8` one in MySQL, a Parquet file holds timestamps
+//! in microseconds where a Kafka topic has milliseconds, and list columns name their
+//! elements differently. Every [`QueryEngine`](crate::QueryEngine) runs
+//! [`UnionCoercionRule`] ahead of DataFusion's type coercion, which casts the columns of
+//! each input of a `UNION` to a type all of the inputs' types cast to without losing
+//! values:
+//!
+//! - integers to the narrowest integer holding both (`int` and `bigint` to `bigint`,
+//!   `int unsigned` and `int` to `bigint`), and to floating point or decimals when the
+//!   other side is one and holds them exactly;
+//! - decimals to the precision and scale holding both;
+//! - strings and binaries of any encoding to the widest encoding, dictionary-encoded
+//!   columns to their values;
+//! - timestamps of the same time zone to the finest unit of the two, and dates to
+//!   timestamps or to `Date64`;
+//! - lists and structs element by element, named as in the first input.
+//!
+//! Each cast is reported as a `union_column_cast` notice in the query's diagnostics.
+//! Columns whose types have no such common type (timestamps of different time zones,
+//! `bigint unsigned` and `bigint`, ...) are left to DataFusion's coercion, which may
+//! cast them with loss or fail the query.
+
+use crate::diagnostics::{source_tables, Diagnostic};
+use datafusion::arrow::datatypes::{DataType, Field, Fields, TimeUnit};
+use datafusion::common::tree_node::Transformed;
+use datafusion::common::Column;
+use datafusion::config::ConfigOptions;
+use datafusion::error::Result as DataFusionResult;
+use datafusion::logical_expr::{cast, Expr, LogicalPlan, Projection, Union};
+use datafusion::optimizer::AnalyzerRule;
+use std::sync::Arc;
+
+/// Largest precision of a `Decimal128`.
+const MAX_DECIMAL_PRECISION: u8 = 38;
+
+/// Casts the inputs of unions to common types, see the [module](self) documentation.
+#[derive(Debug, Default)]
+pub struct UnionCoercionRule;
+
+impl UnionCoercionRule {
+    pub const NAME: &'static str = "union_coercion";
+}
+
+impl AnalyzerRule for UnionCoercionRule {
+    fn analyze(&self, plan: LogicalPlan, _config: &ConfigOptions) -> DataFusionResult<LogicalPlan> {
+        Ok(coerce_unions(plan, &mut vec![])?.data)
+    }
+
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+}
+
+/// A column of an input of a union cast to the union's type.
+#[derive(Debug, Clone, PartialEq)]
+struct UnionCast {
+    column: String,
+    /// The tables the input reads.
+    tables: Vec<String>,
+    from: DataType,
+    to: DataType,
+}
+
+/// Notices of the casts [`UnionCoercionRule`] adds to `plan`, not yet analyzed.
+pub(crate) fn union_diagnostics(plan: &LogicalPlan) -> DataFusionResult<Vec<Diagnostic>> {
+    let mut casts = vec![];
+    coerce_unions(plan.clone(), &mut casts)?;
+    let diagnostics = casts.into_iter().map(|cast| {
+        let input = match cast.tables.as_slice() {
+            [] => "an input".to_string(),
+            tables => format!("the input reading `{}`", tables.join("`, `")),
+        };
+        Diagnostic::notice(
+            "union_column_cast",
+            format!(
+                "column `{}` of {input} of a UNION is cast from {} to {} to match the other \
+                 inputs",
+                cast.column, cast.from, cast.to
+            ),
+        )
+    });
+    Ok(diagnostics.collect())
+}
+
+/// `plan` with the inputs of its unions cast to their common types, the casts added
+/// to `casts`.
+fn coerce_unions(
+    plan: LogicalPlan,
+    casts: &mut Vec<UnionCast>,
+) -> DataFusionResult<Transformed<LogicalPlan>> {
+    plan.transform_up_with_subqueries(|node| match node {
+        LogicalPlan::Union(union) => coerce_union(union, casts),
+        node => Ok(Transformed::no(node)),
+    })
+}
+
+fn coerce_union(
+    union: Union,
+    casts: &mut Vec<UnionCast>,
+) -> DataFusionResult<Transformed<LogicalPlan>> {
+    // The common type of each column, if the inputs have one.
+    let columns = union.schema.fields().len();
+    let targets: Vec<Option<DataType>> = (0..columns)
+        .map(|i| {
+            let mut types = union.inputs.iter().map(|input| input.schema().field(i).data_type());
+            let first = types.next()?.clone();
+            types.try_fold(first, |common, data_type| common_type(&common, data_type))
+        })
+        .collect();
+    let mut transformed = false;
+    let mut inputs = Vec::with_capacity(union.inputs.len());
+    for input in union.inputs {
+        let schema = input.schema();
+        let differs = |(i, target): (usize, &Option<DataType>)| {
+            target.as_ref().is_some_and(|target| schema.field(i).data_type() != target)
+        };
+        if !targets.iter().enumerate().any(differs) {
+            inputs.push(input);
+            continue;
+        }
+        let tables = source_tables(&input);
+        let exprs = schema
+            .iter()
+            .zip(&targets)
+            .map(|((qualifier, field), target)| {
+                let column = Expr::Column(Column::from((qualifier, field)));
+                match target {
+                    Some(target) if field.data_type() != target => {
+                        casts.push(UnionCast {
+                            column: field.name().clone(),
+                            tables: tables.clone(),
+                            from: field.data_type().clone(),
+                            to: target.clone(),
+                        });
+                        cast(column, target.clone())
+                            .alias_qualified(qualifier.cloned(), field.name())
+                    }
+                    _ => column,
+                }
+            })
+            .collect();
+        inputs.push(Arc::new(LogicalPlan::Projection(Projection::try_new(exprs, input)?)));
+        transformed = true;
+    }
+    // Its schema is the first input's, which now has the common types.
+    let union = LogicalPlan::Union(Union::try_new_with_loose_types(inputs)?);
+    Ok(if transformed { Transformed::yes(union) } else { Transformed::no(union) })
+}
+
+/// The type both `a` and `b` cast to without losing values, if there is one.
+fn common_type(a: &DataType, b: &DataType) -> Option<DataType> {
+    use DataType::*;
+    if a == b {
+        return Some(a.clone());
+    }
+    match (a, b) {
+        (Dictionary(_, a), b) | (b, Dictionary(_, a)) => common_type(a, b),
+        (a, b) if a.is_integer() && b.is_integer() => common_integer(a, b),
+        (Float32 | Float64, Float32 | Float64) => Some(Float64),
+        (float @ (Float32 | Float64), int) | (int, float @ (Float32 | Float64))
+            if int.is_integer() =>
+        {
+            // Floats hold integers of up to 24 (`Float32`) or 53 (`Float64`) bits.
+            match integer_bits(int) {
+                bits if bits <= 16 => Some(float.clone()),
+                bits if bits <= 32 => Some(Float64),
+                _ => None,
+            }
+        }
+        (Decimal128(p1, s1), Decimal128(p2, s2)) => common_decimal((*p1, *s1), (*p2, *s2)),
+        (Decimal128(p, s), int) | (int, Decimal128(p, s)) if int.is_integer() => {
+            common_decimal((*p, *s), (integer_digits(int), 0))
+        }
+        (Utf8 | LargeUtf8 | Utf8View, Utf8 | LargeUtf8 | Utf8View) => {
+            Some(if [a, b].contains(&&LargeUtf8) { LargeUtf8 } else { Utf8 })
+        }
+        (Binary | LargeBinary | BinaryView, Binary | LargeBinary | BinaryView) => {
+            Some(if [a, b].contains(&&LargeBinary) { LargeBinary } else { Binary })
+        }
+        (Timestamp(u1, tz1), Timestamp(u2, tz2)) if tz1 == tz2 => {
+            Some(Timestamp(finer_unit(*u1, *u2), tz1.clone()))
+        }
+        (Date32 | Date64, Date32 | Date64) => Some(Date64),
+        (Date32 | Date64, Timestamp(unit, None)) | (Timestamp(unit, None), Date32 | Date64) => {
+            Some(Timestamp(*unit, None))
+        }
+        (List(f1), List(f2)) => Some(List(common_field(f1, f2)?)),
+        (List(f1) | LargeList(f1), List(f2) | LargeList(f2)) => {
+            Some(LargeList(common_field(f1, f2)?))
+        }
+        (Struct(f1), Struct(f2)) if f1.len() == f2.len() => {
+            let fields = f1
+                .iter()
+                .zip(f2.iter())
+                .map(|(f1, f2)| (f1.name() == f2.name()).then(|| common_field(f1, f2)).flatten())
+                .collect::<Option<Fields>>()?;
+            Some(Struct(fields))
+        }
+        _ => None,
+    }
+}
+
+/// The field of `a`'s name and the common type of both.
+fn common_field(a: &Arc<Field>, b: &Arc<Field>) -> Option<Arc<Field>> {
+    let data_type = common_type(a.data_type(), b.data_type())?;
+    let nullable = a.is_nullable() || b.is_nullable();
+    Some(Arc::new(Field::new(a.name(), data_type, nullable)))
+}
+
+fn common_integer(a: &DataType, b: &DataType) -> Option<DataType> {
+    let (a_bits, b_bits) = (integer_bits(a), integer_bits(b));
+    let bits = match (a.is_signed_integer(), b.is_signed_integer()) {
+        (true, true) | (false, false) => a_bits.max(b_bits),
+        // A signed integer holds the unsigned ones of half its bits.
+        (true, false) => a_bits.max(2 * b_bits),
+        (false, true) => b_bits.max(2 * a_bits),
+    };
+    let signed = a.is_signed_integer() || b.is_signed_integer();
+    Some(match (signed, bits) {
+        (true, 8) => DataType::Int8,
+        (true, 16) => DataType::Int16,
+        (true, 32) => DataType::Int32,
+        (true, 64) => DataType::Int64,
+        (false, 8) => DataType::UInt8,
+        (false, 16) => DataType::UInt16,
+        (false, 32) => DataType::UInt32,
+        (false, 64) => DataType::UInt64,
+        _ => return None,
+    })
+}
+
+/// The decimal holding both decimals of `(precision, scale)`.
+fn common_decimal(a: (u8, i8), b: (u8, i8)) -> Option<DataType> {
+    let scale = a.1.max(b.1);
+    let digits = (a.0 as i16 - a.1 as i16).max(b.0 as i16 - b.1 as i16);
+    let precision = u8::try_from(digits + scale as i16).ok()?;
+    (precision <= MAX_DECIMAL_PRECISION).then_some(DataType::Decimal128(precision, scale))
+}
+
+fn integer_bits(data_type: &DataType) -> u32 {
+    data_type.primitive_width().map_or(64, |bytes| 8 * bytes as u32)
+}
+
+/// Decimal digits of the largest values of an integer type.
+fn integer_digits(data_type: &DataType) -> u8 {
+    match data_type {
+        DataType::Int8 | DataType::UInt8 => 3,
+        DataType::Int16 | DataType::UInt16 => 5,
+        DataType::Int32 | DataType::UInt32 => 10,
+        DataType::UInt64 => 20,
+        _ => 19,
+    }
+}
+
+fn finer_unit(a: TimeUnit, b: TimeUnit) -> TimeUnit {
+    let rank = |unit| match unit {
+        TimeUnit::Second => 0,
+        TimeUnit::Millisecond => 1,
+        TimeUnit::Microsecond => 2,
+        TimeUnit::Nanosecond => 3,
+    };
+    if rank(a) >= rank(b) {
+        a
+    } else {
+        b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{
+        Decimal128Array, Int32Array, Int64Array, ListArray, StringArray, StringViewArray,
+        TimestampMicrosecondArray, TimestampMillisecondArray,
+    };
+    use datafusion::arrow::datatypes::Int32Type;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::arrow::util::pretty::pretty_format_batches;
+    use datafusion::datasource::MemTable;
+
+    #[test]
+    fn test_common_types() {
+        use DataType::*;
+        let list = |name, data_type| List(Arc::new(Field::new(name, data_type, true)));
+        let ms = Timestamp(TimeUnit::Millisecond, None);
+        let ns = Timestamp(TimeUnit::Nanosecond, None);
+        let utc = Timestamp(TimeUnit::Nanosecond, Some("UTC".into()));
+        let cases = [
+            (Int32, Int64, Some(Int64)),
+            (UInt32, Int32, Some(Int64)),
+            (UInt8, UInt16, Some(UInt16)),
+            (UInt64, Int64, None),
+            (Int16, Float32, Some(Float32)),
+            (Int32, Float32, Some(Float64)),
+            (Int64, Float64, None),
+            (Decimal128(10, 2), Decimal128(12, 4), Some(Decimal128(12, 4))),
+            (Decimal128(10, 2), Int64, Some(Decimal128(21, 2))),
+            (Decimal128(38, 0), Decimal128(38, 10), None),
+            (Utf8View, Utf8, Some(Utf8)),
+            (Utf8, LargeUtf8, Some(LargeUtf8)),
+            (Dictionary(Box::new(Int32), Box::new(Utf8)), Utf8, Some(Utf8)),
+            (ms.clone(), ns.clone(), Some(ns.clone())),
+            (ns, utc, None),
+            (Date32, ms.clone(), Some(ms)),
+            (list("item", Int32), list("element", Int64), Some(list("item", Int64))),
+            (Utf8, Int64, None),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(common_type(&a, &b), expected, "{a} and {b}");
+            assert_eq!(common_type(&b, &a).is_some(), expected.is_some(), "{b} and {a}");
+        }
+    }
+
+    fn table(columns: Vec<(&str, Arc<dyn datafusion::arrow::array::Array>)>) -> Arc<MemTable> {
+        let batch = RecordBatch::try_from_iter(columns).unwrap();
+        Arc::new(MemTable::try_new(batch.schema(), vec![vec![batch]]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unions_of_differing_sources() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        let tags = ListArray::from_iter_primitive::<Int32Type, _, _>([Some([Some(1)])]);
+        let pg = table(vec![
+            ("id", Arc::new(Int32Array::from(vec![1]))),
+            ("amount", Arc::new(Decimal128Array::from(vec![950]).with_precision_and_scale(10, 2)?)),
+            ("at", Arc::new(TimestampMillisecondArray::from(vec![1_000]))),
+            ("name", Arc::new(StringViewArray::from(vec!["a"]))),
+            ("tags", Arc::new(tags)),
+        ]);
+        let tags = ListArray::new(
+            Arc::new(Field::new("element", DataType::Int64, true)),
+            datafusion::arrow::buffer::OffsetBuffer::from_lengths([2]),
+            Arc::new(Int64Array::from(vec![2, 3])),
+            None,
+        );
+        let mysql = table(vec![
+            ("id", Arc::new(Int64Array::from(vec![2]))),
+            (
+                "amount",
+                Arc::new(Decimal128Array::from(vec![200_000]).with_precision_and_scale(12, 4)?),
+            ),
+            ("at", Arc::new(TimestampMicrosecondArray::from(vec![2_000_000]))),
+            ("name", Arc::new(StringArray::from(vec!["b"]))),
+            ("tags", Arc::new(tags)),
+        ]);
+        engine.register_table("pg_orders", pg)?;
+        engine.register_table("mysql_orders", mysql)?;
+
+        let sql = "SELECT * FROM pg_orders UNION ALL SELECT * FROM mysql_orders ORDER BY id";
+        let result = engine.query(sql).await?;
+        let expected = "\
++----+---------+---------------------+------+--------+
+| id | amount  | at                  | name | tags   |
++----+---------+---------------------+------+--------+
+| 1  | 9.5000  | 1970-01-01T00:00:01 | a    | [1]    |
+| 2  | 20.0000 | 1970-01-01T00:00:02 | b    | [2, 3] |
++----+---------+---------------------+------+--------+";
+        assert_eq!(pretty_format_batches(&result.batches)?.to_string(), expected);
+        let types: Vec<_> = result.schema.fields().iter().map(|f| f.data_type().clone()).collect();
+        let expected = [
+            DataType::Int64,
+            DataType::Decimal128(12, 4),
+            DataType::Timestamp(TimeUnit::Microsecond, None),
+            DataType::Utf8,
+            DataType::List(Arc::new(Field::new("item", DataType::Int64, true))),
+        ];
+        assert_eq!(types, expected);
+        let casts: Vec<_> = result
+            .diagnostics
+            .iter()
+            .filter(|d| d.code == "union_column_cast")
+            .map(|d| d.message.as_str())
+            .collect();
+        assert_eq!(casts.len(), 6);
+        assert_eq!(
+            casts[0],
+            "column `id` of the input reading `pg_orders` of a UNION is cast from Int32 to \
+             Int64 to match the other inputs"
+        );
+        assert!(casts.iter().any(|c| c.contains("`tags` of the input reading `mysql_orders`")));
+
+        let plan = engine.sql("SELECT id FROM pg_orders UNION SELECT id FROM pg_orders").await?;
+        assert!(union_diagnostics(plan.logical_plan())?.is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Checking statements without running them.
+//!
+//! [`QueryEngine::validate`](crate::QueryEngine::validate) parses and plans a statement
+//! as running it would, so it fails where running it would fail to plan: on syntax
+//! errors, unknown tables, columns and functions, type errors, and policies (see
+//! [`policy`](crate::policy)) that cannot be applied. Validated through the engine a
+//! caller queries with, e.g. one from
+//! [`QueryEngine::for_roles`](crate::QueryEngine::for_roles) or a
+//! [tenant](crate::tenant)'s, a statement is checked with that caller's permissions:
+//! the tables it may not see are unknown, and its policies are applied to the plan.
+//!
+//! A [`Validation`] reports what running the statement would do: the tables it reads
+//! and, per scan, the columns, filters and limit pushed down to the source, along with
+//! the [`Diagnostic`]s a query reports. Nothing runs, and no backend is asked for data:
+//! sources are only looked up in their catalogs. Statements that change tables or
+//! sessions, including those Igloo runs itself (`ANALYZE`, `MERGE`, ...), are checked
+//! but not applied.
+
+use crate::diagnostics::Diagnostic;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::common::tree_node::TreeNodeRecursion;
+use datafusion::logical_expr::LogicalPlan;
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
+
+/// What running a statement would do, see the [module](self) documentation.
+#[derive(Debug, Clone, Serialize)]
+pub struct Validation {
+    /// Schema of the statement's result, serialized as its columns.
+    #[serde(rename = "columns", serialize_with = "serialize_columns")]
+    pub schema: SchemaRef,
+    /// The tables the statement reads or changes.
+    pub tables: Vec<String>,
+    pub scans: Vec<ScanPushdown>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The optimized logical plan, as `EXPLAIN` shows it. Empty for statements with
+    /// nothing to plan, such as `KILL`.
+    pub plan: String,
+}
+
+/// What one scan asks its source for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanPushdown {
+    pub table: String,
+    /// The columns fetched.
+    pub columns: Vec<String>,
+    /// The filters the source evaluates, exactly or not; the others are evaluated by
+    /// Igloo, see the `filter_not_pushed_down` diagnostic.
+    pub filters: Vec<String>,
+    /// How many rows the source returns at most.
+    pub limit: Option<usize>,
+}
+
+/// The pushdown of each scan of an optimized `plan` (including its subqueries), in plan
+/// order.
+pub fn scan_pushdown(plan: &LogicalPlan) -> Vec<ScanPushdown> {
+    let mut scans = Vec::new();
+    let _ = plan.apply_with_subqueries(|node| {
+        if let LogicalPlan::TableScan(scan) = node {
+            scans.push(ScanPushdown {
+                table: scan.table_name.to_string(),
+                columns: scan.projected_schema.fields().iter().map(|f| f.name().clone()).collect(),
+                filters: scan.filters.iter().map(ToString::to_string).collect(),
+                limit: scan.fetch,
+            });
+        }
+        Ok(TreeNodeRecursion::Continue)
+    });
+    scans
+}
+
+#[derive(Serialize)]
+struct Column<'a> {
+    name: &'a str,
+    data_type: String,
+    nullable: bool,
+}
+
+fn serialize_columns<S: Serializer>(schema: &SchemaRef, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut columns = serializer.serialize_seq(Some(schema.fields().len()))?;
+    for field in schema.fields() {
+        columns.serialize_element(&Column {
+            name: field.name(),
+            data_type: field.data_type().to_string(),
+            nullable: field.is_nullable(),
+        })?;
+    }
+    columns.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::policy::{PolicySet, RowFilter};
+    use crate::QueryEngine;
+    use datafusion::error::Result as DataFusionResult;
+
+    #[tokio::test]
+    async fn test_statements_are_planned_but_not_run() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE orders (id BIGINT, total DOUBLE) AS VALUES (1, 9.5)").await?;
+
+        let validation =
+            engine.validate("SELECT id FROM orders WHERE total > 5 ORDER BY id LIMIT 10").await?;
+        assert_eq!(validation.schema.field(0).name(), "id");
+        assert_eq!(validation.tables, ["orders"]);
+        assert_eq!(validation.scans[0].table, "orders");
+        assert_eq!(validation.scans[0].columns, ["id", "total"]);
+        assert!(validation.plan.contains("TableScan: orders"), "{}", validation.plan);
+
+        // Neither the insert nor the drop is applied.
+        engine.validate("INSERT INTO orders VALUES (2, 1.0)").await?;
+        let validation = engine.validate("DROP TABLE orders").await?;
+        assert!(validation.scans.is_empty());
+        assert!(engine.validate("ANALYZE TABLE orders COMPUTE STATISTICS").await.is_ok());
+        assert!(engine.table_statistics("orders").is_none());
+        let result = engine.query("SELECT id FROM orders").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        let json = serde_json::to_value(engine.validate("SELECT id FROM orders").await?).unwrap();
+        assert_eq!(json["columns"][0]["name"], "id");
+        assert_eq!(json["columns"][0]["data_type"], "Int64");
+
+        for sql in [
+            "SELEC 1",
+            "SELECT id FROM missing",
+            "SELECT missing FROM orders",
+            "ANALYZE TABLE missing COMPUTE STATISTICS",
+        ] {
+            assert!(engine.validate(sql).await.is_err(), "{sql}");
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_validation_applies_the_callers_policies() -> DataFusionResult<()> {
+        let engine = QueryEngine::new();
+        engine.query("CREATE TABLE orders (id BIGINT, region TEXT) AS VALUES (1, 'EU')").await?;
+        engine.set_policies(
+            PolicySet::new().with_row_filter(RowFilter::new("orders", "region = 'EU'")),
+        );
+        let validation = engine.validate("SELECT id FROM orders").await?;
+        assert!(validation.plan.contains("region = Utf8(\"EU\")"), "{}", validation.plan);
+
+        engine.set_policies(
+            PolicySet::new().with_row_filter(RowFilter::new("orders", "missing = 1")),
+        );
+        assert!(engine.validate("SELECT id FROM orders").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,270 @@
+//! Scalar user-defined functions implemented in WebAssembly.
+//!
+//! A UDF module is a plain core WebAssembly module with no imports. It must export:
+//!
+//! * `memory`, its linear memory;
+//! * `alloc(size: i32) -> i32`, returning the address of `size` writable bytes;
+//! * the function itself, taking one `i32` pointer per argument followed by the row
+//!   count and an output pointer, e.g. `add(a: i32, b: i32, len: i32, out: i32)`.
+//!
+//! For every batch the host copies each argument's Arrow values buffer into the
+//! module's memory, calls the function once, and reads `len` values of the return
+//! type back from `out`. Only fixed-width numeric types are supported. A row is NULL
+//! in the result if it is NULL in any argument.
+//!
+//! Each batch runs in a fresh instance with capped memory, so a module cannot keep
+//! state between calls or reach anything outside its own sandbox.
+
+use datafusion::arrow::array::{make_array, Array, ArrayData, ArrayRef};
+use datafusion::arrow::buffer::{Buffer, NullBuffer};
+use datafusion::arrow::datatypes::DataType;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::logical_expr::{
+    ColumnarValue, ScalarFunctionArgs, ScalarUDFImpl, Signature, Volatility,
+};
+use std::any::Any;
+use std::fmt;
+use wasmtime::{Engine, ExternType, Instance, Module, Store, StoreLimits, StoreLimitsBuilder, Val};
+
+/// Upper bound on a UDF instance's linear memory.
+pub const MAX_MEMORY_BYTES: usize = 256 << 20;
+
+pub struct WasmScalarUdf {
+    name: String,
+    signature: Signature,
+    return_type: DataType,
+    engine: Engine,
+    module: Module,
+}
+
+impl fmt::Debug for WasmScalarUdf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmScalarUdf")
+            .field("name", &self.name)
+            .field("signature", &self.signature)
+            .field("return_type", &self.return_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WasmScalarUdf {
+    /// Compile `wasm` (binary or, for convenience, text format) and check that it
+    /// exports `name` with the calling convention described in the module docs.
+    pub fn try_new(
+        name: &str,
+        wasm: &[u8],
+        arg_types: Vec<DataType>,
+        return_type: DataType,
+    ) -> DataFusionResult<Self> {
+        for data_type in arg_types.iter().chain([&return_type]) {
+            if !is_supported(data_type) {
+                return Err(DataFusionError::Plan(format!(
+                    "WASM UDF '{name}': unsupported type {data_type}, expected a fixed-width \
+                     numeric type"
+                )));
+            }
+        }
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm).map_err(|e| {
+            DataFusionError::Plan(format!("WASM UDF '{name}': invalid module: {e}"))
+        })?;
+        let params = arg_types.len() + 2;
+        for (export, expected) in [("alloc", "(i32) -> i32"), (name, "(i32, ...) -> ()")] {
+            let valid = match module.get_export(export) {
+                Some(ExternType::Func(ty)) if export == "alloc" => {
+                    ty.params().len() == 1 && ty.results().len() == 1
+                }
+                Some(ExternType::Func(ty)) => {
+                    ty.params().len() == params
+                        && ty.params().all(|p| p.is_i32())
+                        && ty.results().len() == 0
+                }
+                _ => false,
+            };
+            if !valid {
+                return Err(DataFusionError::Plan(format!(
+                    "WASM UDF '{name}': module must export function '{export}' {expected}"
+                )));
+            }
+        }
+        if !matches!(module.get_export("memory"), Some(ExternType::Memory(_))) {
+            return Err(DataFusionError::Plan(format!(
+                "WASM UDF '{name}': module must export 'memory'"
+            )));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            signature: Signature::exact(arg_types, Volatility::Immutable),
+            return_type,
+            engine,
+            module,
+        })
+    }
+
+    fn error(&self, e: impl fmt::Display) -> DataFusionError {
+        DataFusionError::Execution(format!("WASM UDF '{}' failed: {e}", self.name))
+    }
+
+    fn call(&self, args: &[ArrayRef], len: usize) -> DataFusionResult<ArrayRef> {
+        let limits = StoreLimitsBuilder::new().memory_size(MAX_MEMORY_BYTES).build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        let instance = Instance::new(&mut store, &self.module, &[]).map_err(|e| self.error(e))?;
+        let memory = instance.get_memory(&mut store, "memory").expect("checked in try_new");
+        let alloc =
+            instance.get_typed_func::<i32, i32>(&mut store, "alloc").map_err(|e| self.error(e))?;
+        let alloc = |store: &mut Store<StoreLimits>, size: usize| -> DataFusionResult<i32> {
+            let size = i32::try_from(size).map_err(|e| self.error(e))?;
+            alloc.call(store, size).map_err(|e| self.error(e))
+        };
+
+        let mut params = Vec::with_capacity(args.len() + 2);
+        for arg in args {
+            let data = arg.to_data();
+            let width = primitive_width(arg.data_type());
+            let values = &data.buffers()[0].as_slice()[data.offset() * width..][..len * width];
+            let ptr = alloc(&mut store, values.len())?;
+            memory.write(&mut store, ptr as u32 as usize, values).map_err(|e| self.error(e))?;
+            params.push(Val::I32(ptr));
+        }
+        let out_len = len * primitive_width(&self.return_type);
+        let out = alloc(&mut store, out_len)?;
+        params.push(Val::I32(len as i32));
+        params.push(Val::I32(out));
+
+        let func = instance.get_func(&mut store, &self.name).expect("checked in try_new");
+        func.call(&mut store, &params, &mut []).map_err(|e| self.error(e))?;
+
+        let mut result = vec![0u8; out_len];
+        memory.read(&store, out as u32 as usize, &mut result).map_err(|e| self.error(e))?;
+        let nulls = args.iter().fold(None, |nulls: Option<NullBuffer>, arg| {
+            NullBuffer::union(nulls.as_ref(), arg.logical_nulls().as_ref())
+        });
+        let data = ArrayData::builder(self.return_type.clone())
+            .len(len)
+            .add_buffer(Buffer::from_vec(result))
+            .nulls(nulls)
+            .build()?;
+        Ok(make_array(data))
+    }
+}
+
+impl ScalarUDFImpl for WasmScalarUdf {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn return_type(&self, _arg_types: &[DataType]) -> DataFusionResult<DataType> {
+        Ok(self.return_type.clone())
+    }
+
+    fn invoke_with_args(&self, args: ScalarFunctionArgs) -> DataFusionResult<ColumnarValue> {
+        let arrays = args
+            .args
+            .iter()
+            .map(|arg| arg.to_array(args.number_rows))
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        self.call(&arrays, args.number_rows).map(ColumnarValue::Array)
+    }
+}
+
+fn is_supported(data_type: &DataType) -> bool {
+    data_type.is_integer() || data_type.is_floating()
+}
+
+fn primitive_width(data_type: &DataType) -> usize {
+    data_type.primitive_width().expect("only fixed-width types are accepted")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use datafusion::arrow::array::{AsArray, Int64Array};
+    use datafusion::arrow::datatypes::{Float64Type, Int64Type};
+
+    /// A bump allocator plus `add_one(i64)`, `mul(f64, f64)` and a `crash` that traps.
+    const MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 0))
+          (func (export "alloc") (param $size i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (local.get $ptr) (local.get $size)))
+            (if (i32.gt_u (global.get $next) (i32.mul (memory.size) (i32.const 65536)))
+              (then (drop (memory.grow
+                (i32.add (i32.shr_u (global.get $next) (i32.const 16)) (i32.const 1))))))
+            (local.get $ptr))
+          (func (export "add_one") (param $in i32) (param $len i32) (param $out i32)
+            (local $i i32)
+            (block $done (loop $next
+              (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+              (i64.store (i32.add (local.get $out) (i32.shl (local.get $i) (i32.const 3)))
+                (i64.add (i64.load (i32.add (local.get $in) (i32.shl (local.get $i) (i32.const 3))))
+                  (i64.const 1)))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br $next))))
+          (func (export "mul") (param $a i32) (param $b i32) (param $len i32) (param $out i32)
+            (local $i i32) (local $offset i32)
+            (block $done (loop $next
+              (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+              (local.set $offset (i32.shl (local.get $i) (i32.const 3)))
+              (f64.store (i32.add (local.get $out) (local.get $offset))
+                (f64.mul (f64.load (i32.add (local.get $a) (local.get $offset)))
+                  (f64.load (i32.add (local.get $b) (local.get $offset)))))
+              (local.set $i (i32.add (local.get $i) (i32.const 1)))
+              (br $next))))
+          (func (export "crash") (param i32 i32 i32) unreachable))
+    "#;
+
+    fn engine() -> DataFusionResult<QueryEngine> {
+        let engine = QueryEngine::new();
+        let wasm = MODULE.as_bytes();
+        engine.register_wasm_udf("add_one", wasm, vec![DataType::Int64], DataType::Int64)?;
+        let args = vec![DataType::Float64, DataType::Float64];
+        engine.register_wasm_udf("mul", wasm, args, DataType::Float64)?;
+        engine.register_wasm_udf("crash", wasm, vec![DataType::Int64], DataType::Int64)?;
+        Ok(engine)
+    }
+
+    #[tokio::test]
+    async fn test_wasm_udfs_in_sql() -> DataFusionResult<()> {
+        let engine = engine()?;
+
+        let result =
+            engine.query("SELECT add_one(x) AS y FROM (VALUES (1), (NULL), (41)) AS t(x)").await?;
+        let y = result.batches[0].column(0).as_primitive::<Int64Type>();
+        assert_eq!(y, &Int64Array::from(vec![Some(2), None, Some(42)]));
+
+        let result = engine.query("SELECT mul(1.5, 4.0)").await?;
+        assert_eq!(result.batches[0].column(0).as_primitive::<Float64Type>().value(0), 6.0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_traps_and_bad_modules_are_errors() -> DataFusionResult<()> {
+        let engine = engine()?;
+        let err = engine.query("SELECT crash(1)").await.unwrap_err();
+        assert!(err.to_string().contains("WASM UDF 'crash' failed"), "{err}");
+
+        let wasm = MODULE.as_bytes();
+        let err = engine
+            .register_wasm_udf("missing", wasm, vec![DataType::Int64], DataType::Int64)
+            .unwrap_err();
+        assert!(err.to_string().contains("must export function 'missing'"), "{err}");
+        let err = engine
+            .register_wasm_udf("add_one", wasm, vec![DataType::Utf8], DataType::Int64)
+            .unwrap_err();
+        assert!(err.to_string().contains("unsupported type Utf8"), "{err}");
+        Ok(())
+    }
+}
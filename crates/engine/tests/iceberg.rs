@@ -0,0 +1,154 @@
+use axum::extract::State;
+use axum::routing::get;
+use axum::{Json, Router};
+use datafusion::arrow::util::pretty::pretty_format_batches;
+use igloo_connector_iceberg::{IcebergCatalogProvider, RestCatalog};
+use igloo_engine::QueryEngine;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+/// The metadata of `sales.orders`, the one table of a REST catalog applying the commits
+/// it is sent.
+type Metadata = Arc<Mutex<Value>>;
+
+async fn config() -> Json<Value> {
+    Json(json!({"defaults": {}, "overrides": {}}))
+}
+
+async fn namespaces() -> Json<Value> {
+    Json(json!({"namespaces": [["sales"]]}))
+}
+
+async fn tables() -> Json<Value> {
+    Json(json!({"identifiers": [{"namespace": ["sales"], "name": "orders"}]}))
+}
+
+async fn load_table(State(metadata): State<Metadata>) -> Json<Value> {
+    Json(json!({"metadata": metadata.lock().unwrap().clone()}))
+}
+
+async fn commit_table(State(metadata): State<Metadata>, Json(request): Json<Value>) -> Json<Value> {
+    let mut metadata = metadata.lock().unwrap();
+    for update in request["updates"].as_array().unwrap() {
+        match update["action"].as_str().unwrap() {
+            "add-snapshot" => {
+                let snapshot = update["snapshot"].clone();
+                metadata["last-sequence-number"] = snapshot["sequence-number"].clone();
+                metadata["snapshots"].as_array_mut().unwrap().push(snapshot);
+            }
+            "set-snapshot-ref" => metadata["current-snapshot-id"] = update["snapshot-id"].clone(),
+            other => panic!("unexpected update {other}"),
+        }
+    }
+    Json(json!({"metadata-location": "unused", "metadata": metadata.clone()}))
+}
+
+async fn start(metadata: Metadata) -> String {
+    let app = Router::new()
+        .route("/v1/config", get(config))
+        .route("/v1/namespaces", get(namespaces))
+        .route("/v1/namespaces/:namespace/tables", get(tables))
+        .route("/v1/namespaces/:namespace/tables/:table", get(load_table).post(commit_table))
+        .with_state(metadata);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    format!("http://{addr}")
+}
+
+async fn query(engine: &QueryEngine, sql: &str) -> String {
+    let batches = engine.sql(sql).await.unwrap().collect().await.unwrap();
+    pretty_format_batches(&batches).unwrap().to_string()
+}
+
+/// An engine with an Iceberg catalog, `iceberg`, of one table, `sales.orders`, at
+/// `dir` and holding orders 1 to 3.
+async fn engine(dir: &std::path::Path) -> (QueryEngine, Metadata) {
+    std::fs::create_dir_all(dir).unwrap();
+    let metadata = Arc::new(Mutex::new(json!({
+        "format-version": 2,
+        "table-uuid": "3c7e2f1a-0000-4000-8000-000000000000",
+        "location": format!("file://{}", dir.display()),
+        "last-sequence-number": 0,
+        "current-schema-id": 0,
+        "schemas": [{"schema-id": 0, "fields": [
+            {"id": 1, "name": "id", "required": true, "type": "long"},
+            {"id": 2, "name": "amount", "required": false, "type": "double"}
+        ]}],
+        "default-spec-id": 0,
+        "partition-specs": [{"spec-id": 0, "fields": []}],
+        "snapshots": []
+    })));
+    let catalog = Arc::new(RestCatalog::new(start(metadata.clone()).await));
+    let engine = QueryEngine::new();
+    let provider = IcebergCatalogProvider::try_new(catalog).await.unwrap();
+    engine.session_context().register_catalog("iceberg", Arc::new(provider));
+    query(&engine, "INSERT INTO iceberg.sales.orders VALUES (1, 10.0), (2, 20.0), (3, 30.0)").await;
+    (engine, metadata)
+}
+
+#[tokio::test]
+async fn test_merge_into_an_iceberg_table() {
+    let dir = std::env::temp_dir().join(format!("igloo-engine-merge-{}", std::process::id()));
+    let (engine, metadata) = engine(&dir).await;
+    query(&engine, "CREATE TABLE updates (id BIGINT, amount DOUBLE)").await;
+    query(&engine, "INSERT INTO updates VALUES (1, 5.0), (2, NULL), (4, 40.0)").await;
+
+    let sql = "MERGE INTO iceberg.sales.orders t USING updates s ON t.id = s.id \
+        WHEN MATCHED AND s.amount IS NULL THEN DELETE \
+        WHEN MATCHED THEN UPDATE SET amount = t.amount + s.amount \
+        WHEN NOT MATCHED THEN INSERT (id, amount) VALUES (s.id, s.amount)";
+    let merged = "\
++-------+
+| count |
++-------+
+| 3     |
++-------+";
+    assert_eq!(query(&engine, sql).await, merged);
+    let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 1  | 15.0   |
+| 3  | 30.0   |
+| 4  | 40.0   |
++----+--------+";
+    assert_eq!(query(&engine, "SELECT * FROM iceberg.sales.orders ORDER BY id").await, expected);
+    let snapshots = metadata.lock().unwrap()["snapshots"].clone();
+    assert_eq!(snapshots.as_array().unwrap().len(), 2);
+    assert_eq!(snapshots[1]["summary"]["operation"], "overwrite");
+
+    // Tables other than Iceberg ones are refused.
+    let sql = "MERGE INTO updates t USING updates s ON t.id = s.id WHEN MATCHED THEN DELETE";
+    let error = engine.sql(sql).await.unwrap_err();
+    assert!(error.to_string().contains("only into Iceberg tables"), "{error}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_delete_from_an_iceberg_table() {
+    let dir = std::env::temp_dir().join(format!("igloo-engine-delete-{}", std::process::id()));
+    let (engine, metadata) = engine(&dir).await;
+    let deleted = "\
++-------+
+| count |
++-------+
+| 1     |
++-------+";
+    let sql = "DELETE FROM iceberg.sales.orders WHERE amount > 15 AND id <> 3";
+    assert_eq!(query(&engine, sql).await, deleted);
+    let expected = "\
++----+--------+
+| id | amount |
++----+--------+
+| 1  | 10.0   |
+| 3  | 30.0   |
++----+--------+";
+    assert_eq!(query(&engine, "SELECT * FROM iceberg.sales.orders ORDER BY id").await, expected);
+    let snapshots = metadata.lock().unwrap()["snapshots"].clone();
+    assert_eq!(snapshots[1]["summary"]["operation"], "overwrite");
+
+    let sql = "DELETE FROM iceberg.sales.orders WHERE id IN (SELECT 1)";
+    assert!(engine.sql(sql).await.is_err());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,92 @@
+/*
+ * C API for embedding the Igloo query engine. Link against libigloo_ffi.
+ *
+ * Results use the Arrow C stream interface; the struct definitions below follow
+ * https://arrow.apache.org/docs/format/CStreamInterface.html and are guarded so they
+ * can coexist with other Arrow headers.
+ *
+ * Functions returning int return 0 on success and -1 on failure. After a failure,
+ * igloo_last_error() describes it. A panic inside Igloo is reported as a failure.
+ */
+
+#ifndef IGLOO_H
+#define IGLOO_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#ifndef ARROW_C_DATA_INTERFACE
+#define ARROW_C_DATA_INTERFACE
+
+struct ArrowSchema {
+  const char* format;
+  const char* name;
+  const char* metadata;
+  int64_t flags;
+  int64_t n_children;
+  struct ArrowSchema** children;
+  struct ArrowSchema* dictionary;
+  void (*release)(struct ArrowSchema*);
+  void* private_data;
+};
+
+struct ArrowArray {
+  int64_t length;
+  int64_t null_count;
+  int64_t offset;
+  int64_t n_buffers;
+  int64_t n_children;
+  const void** buffers;
+  struct ArrowArray** children;
+  struct ArrowArray* dictionary;
+  void (*release)(struct ArrowArray*);
+  void* private_data;
+};
+
+#endif /* ARROW_C_DATA_INTERFACE */
+
+#ifndef ARROW_C_STREAM_INTERFACE
+#define ARROW_C_STREAM_INTERFACE
+
+struct ArrowArrayStream {
+  int (*get_schema)(struct ArrowArrayStream*, struct ArrowSchema* out);
+  int (*get_next)(struct ArrowArrayStream*, struct ArrowArray* out);
+  const char* (*get_last_error)(struct ArrowArrayStream*);
+  void (*release)(struct ArrowArrayStream*);
+  void* private_data;
+};
+
+#endif /* ARROW_C_STREAM_INTERFACE */
+
+typedef struct IglooEngineHandle IglooEngineHandle;
+
+/*
+ * Create an engine. config is a JSON object such as
+ * {"tables": {"orders": "/data/orders.parquet"}}, or NULL for an empty engine.
+ * Returns NULL on failure.
+ */
+IglooEngineHandle* igloo_engine_new(const char* config);
+
+/* Free an engine. Streams it returned stay valid. NULL is a no-op. */
+void igloo_engine_free(IglooEngineHandle* engine);
+
+/*
+ * Run sql and write its result stream to out. The caller must call
+ * out->release when done with it.
+ */
+int igloo_execute(const IglooEngineHandle* engine, const char* sql, struct ArrowArrayStream* out);
+
+/*
+ * Message of the last error on this thread, or NULL. Owned by Igloo; valid until the
+ * next failing call on this thread.
+ */
+const char* igloo_last_error(void);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* IGLOO_H */
@@ -0,0 +1,271 @@
+//! C API for embedding Igloo in-process, e.g. from Go, Java or C++ services.
+//!
+//! Results are handed over through the Arrow C stream interface
+//! (`struct ArrowArrayStream`), so any Arrow implementation can consume them without
+//! copying. The declarations are in `include/igloo.h`.
+//!
+//! Functions returning `int` return `0` on success and `-1` on failure; the failure's
+//! message can then be read with [`igloo_last_error`] on the same thread. A panic is a
+//! failure too: it never unwinds into the caller.
+
+use arrow::datatypes::SchemaRef;
+use arrow::error::ArrowError;
+use arrow::ffi_stream::FFI_ArrowArrayStream;
+use arrow::record_batch::{RecordBatch, RecordBatchReader};
+use futures::StreamExt;
+use igloo::datafusion::execution::SendableRecordBatchStream;
+use igloo::IglooEngine;
+use serde::Deserialize;
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Arc;
+use tokio::runtime::Runtime;
+
+/// Engine configuration, passed to [`igloo_engine_new`] as JSON.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Files to register as tables, by table name.
+    #[serde(default)]
+    tables: BTreeMap<String, String>,
+}
+
+/// An engine plus the runtime its queries run on. Opaque to C callers.
+pub struct IglooEngineHandle {
+    runtime: Arc<Runtime>,
+    engine: IglooEngine,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl Display) {
+    // Interior NULs would truncate the message on the C side anyway.
+    let message = message.to_string().replace('\0', " ");
+    let message = CString::new(message).expect("NULs were removed");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run the body of an exported function, returning `failed` and recording the error
+/// if it fails or panics. Unwinding into C is undefined behavior.
+fn ffi_call<T>(failed: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e);
+            failed
+        }
+        Err(panic) => {
+            set_last_error(panic_message(panic.as_ref()));
+            failed
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic.downcast_ref::<String>().map_or("unknown cause", String::as_str),
+    };
+    format!("Igloo panicked: {message}")
+}
+
+/// Read a C string argument, failing if it is NULL or not UTF-8.
+///
+/// # Safety
+/// `s` must be NULL or point to a NUL-terminated string.
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{name} is NULL"));
+    }
+    CStr::from_ptr(s).to_str().map_err(|e| format!("{name} is not valid UTF-8: {e}"))
+}
+
+fn new_engine(config: Option<&str>) -> Result<IglooEngineHandle, String> {
+    let config: Config = match config {
+        Some(json) => serde_json::from_str(json).map_err(|e| format!("invalid config: {e}"))?,
+        None => Config::default(),
+    };
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("failed to start runtime: {e}"))?;
+    let engine = IglooEngine::new();
+    for (name, path) in &config.tables {
+        runtime
+            .block_on(engine.register_file(name, path))
+            .map_err(|e| format!("failed to register table '{name}': {e}"))?;
+    }
+    Ok(IglooEngineHandle { runtime: Arc::new(runtime), engine })
+}
+
+/// Create an engine. `config` is a JSON object such as
+/// `{"tables": {"orders": "/data/orders.parquet"}}`, or NULL for an empty engine.
+/// Returns NULL on failure. Free the engine with [`igloo_engine_free`].
+///
+/// # Safety
+/// `config` must be NULL or point to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn igloo_engine_new(config: *const c_char) -> *mut IglooEngineHandle {
+    ffi_call(ptr::null_mut(), || {
+        let config = match config.is_null() {
+            true => None,
+            false => Some(str_arg(config, "config")?),
+        };
+        Ok(Box::into_raw(Box::new(new_engine(config)?)))
+    })
+}
+
+/// Free an engine created by [`igloo_engine_new`]. Streams already returned by
+/// [`igloo_execute`] stay valid. Passing NULL is a no-op.
+///
+/// # Safety
+/// `engine` must be NULL or a pointer returned by [`igloo_engine_new`] that has not
+/// been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn igloo_engine_free(engine: *mut IglooEngineHandle) {
+    ffi_call((), || {
+        if !engine.is_null() {
+            drop(Box::from_raw(engine));
+        }
+        Ok(())
+    })
+}
+
+/// Run `sql` and write its result stream to `out`. The caller owns the stream and
+/// must call its `release` callback when done. Batches are produced as the caller
+/// pulls them, so large results are never fully materialized.
+///
+/// # Safety
+/// `engine` must be a live engine, `sql` a NUL-terminated string, and `out` must point
+/// to writable memory for an `ArrowArrayStream`.
+#[no_mangle]
+pub unsafe extern "C" fn igloo_execute(
+    engine: *const IglooEngineHandle,
+    sql: *const c_char,
+    out: *mut FFI_ArrowArrayStream,
+) -> c_int {
+    ffi_call(-1, || {
+        if engine.is_null() || out.is_null() {
+            return Err("engine and out must not be NULL".to_string());
+        }
+        let sql = str_arg(sql, "sql")?;
+        let handle = &*engine;
+        let stream = handle.runtime.block_on(async {
+            let df = handle.engine.sql(sql).await?;
+            df.execute_stream().await
+        });
+        let stream = stream.map_err(|e| e.to_string())?;
+        let reader = BlockingReader { runtime: handle.runtime.clone(), stream };
+        ptr::write(out, FFI_ArrowArrayStream::new(Box::new(reader)));
+        Ok(0)
+    })
+}
+
+/// The message of the last error on this thread, or NULL if there was none. The
+/// string is owned by Igloo and valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn igloo_last_error() -> *const c_char {
+    ffi_call(ptr::null(), || {
+        Ok(LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr())))
+    })
+}
+
+/// Drives a query stream from synchronous code, one batch per `next`.
+struct BlockingReader {
+    runtime: Arc<Runtime>,
+    stream: SendableRecordBatchStream,
+}
+
+impl Iterator for BlockingReader {
+    type Item = Result<RecordBatch, ArrowError>;
+
+    /// Called by the stream's C callbacks, so a panic is returned as an error.
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = catch_unwind(AssertUnwindSafe(|| self.runtime.block_on(self.stream.next())));
+        match next {
+            Ok(next) => Some(next?.map_err(|e| ArrowError::ExternalError(Box::new(e)))),
+            Err(panic) => {
+                Some(Err(ArrowError::ExternalError(panic_message(panic.as_ref()).into())))
+            }
+        }
+    }
+}
+
+impl RecordBatchReader for BlockingReader {
+    fn schema(&self) -> SchemaRef {
+        self.stream.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::AsArray;
+    use arrow::datatypes::{Int32Type, Int64Type};
+    use arrow::ffi_stream::ArrowArrayStreamReader;
+
+    fn execute(engine: *const IglooEngineHandle, sql: &str) -> Result<Vec<RecordBatch>, String> {
+        let sql = CString::new(sql).unwrap();
+        let mut stream = FFI_ArrowArrayStream::empty();
+        if unsafe { igloo_execute(engine, sql.as_ptr(), &mut stream) } != 0 {
+            let error = unsafe { CStr::from_ptr(igloo_last_error()) };
+            return Err(error.to_str().unwrap().to_string());
+        }
+        let reader = ArrowArrayStreamReader::try_new(stream).unwrap();
+        Ok(reader.collect::<Result<_, _>>().unwrap())
+    }
+
+    #[test]
+    fn test_execute_returns_arrow_stream() {
+        let path = std::env::temp_dir().join(format!("igloo-ffi-{}.csv", std::process::id()));
+        std::fs::write(&path, "id,name\n1,one\n2,two\n").unwrap();
+        let config = serde_json::json!({ "tables": { "numbers": path } }).to_string();
+        let config = CString::new(config).unwrap();
+        let engine = unsafe { igloo_engine_new(config.as_ptr()) };
+        assert!(!engine.is_null());
+
+        let batches = execute(engine, "SELECT sum(id) AS total FROM numbers").unwrap();
+        assert_eq!(batches[0].column(0).as_primitive::<Int64Type>().value(0), 3);
+
+        let error = execute(engine, "SELECT * FROM missing").unwrap_err();
+        assert!(error.contains("missing"), "{error}");
+        unsafe { igloo_engine_free(engine) };
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dictionary_columns_cross_as_dictionaries() {
+        let engine = unsafe { igloo_engine_new(ptr::null()) };
+        let sql = "SELECT arrow_cast(s, 'Dictionary(Int32, Utf8)') AS s FROM (VALUES ('a'), ('b'), ('a')) t(s)";
+        let batches = execute(engine, sql).unwrap();
+        let statuses = batches[0].column(0).as_dictionary::<Int32Type>();
+        assert_eq!((statuses.len(), statuses.values().len()), (3, 2));
+        unsafe { igloo_engine_free(engine) };
+    }
+
+    #[test]
+    fn test_panics_are_reported_as_errors() {
+        let engine = unsafe { igloo_engine_new(ptr::null()) };
+        // Dropping the engine's runtime from within another runtime panics.
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(async { unsafe { igloo_engine_free(engine) } });
+        let error = unsafe { CStr::from_ptr(igloo_last_error()) }.to_str().unwrap();
+        assert!(error.starts_with("Igloo panicked: "), "{error}");
+    }
+
+    #[test]
+    fn test_invalid_config_is_reported() {
+        let config = CString::new(r#"{"tablez": {}}"#).unwrap();
+        let engine = unsafe { igloo_engine_new(config.as_ptr()) };
+        assert!(engine.is_null());
+        let error = unsafe { CStr::from_ptr(igloo_last_error()) }.to_str().unwrap();
+        assert!(error.starts_with("invalid config"), "{error}");
+    }
+}
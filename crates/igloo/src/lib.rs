@@ -0,0 +1,235 @@
+//! Igloo as a library.
+//!
+//! [`IglooEngine`] is the entry point for Rust services that embed Igloo in-process
+//! instead of talking to a coordinator over the network. The building blocks behind
+//! it (cache, connectors, errors, result formats) are re-exported here so embedders
+//! depend on a single crate.
+//!
+//! # Example
+//! ```rust
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> datafusion::error::Result<()> {
+//! let engine = igloo::IglooEngine::new();
+//! let result = engine.query("SELECT 1 + 1 AS two").await?;
+//! assert_eq!(result.batches[0].num_rows(), 1);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "wasm")]
+use datafusion::arrow::datatypes::DataType;
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::listing::ListingOptions;
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result as DataFusionResult};
+use datafusion::execution::context::SessionContext;
+use datafusion::execution::options::{CsvReadOptions, NdJsonReadOptions, ParquetReadOptions};
+use igloo_engine::avro::{AvroFormat, DEFAULT_AVRO_EXTENSION};
+use igloo_engine::QueryEngine;
+use std::sync::Arc;
+
+pub use datafusion;
+pub use igloo_cache as cache;
+pub use igloo_common::catalog::CatalogSource;
+pub use igloo_common::error::{ApiError, Error, Result};
+pub use igloo_engine::bundle::{ImportReport, StateBundle};
+pub use igloo_engine::diagnostics::{Diagnostic, QueryResult, QueryStream, Severity};
+pub use igloo_engine::external_catalog::SyncReport;
+pub use igloo_engine::formats::OutputFormat;
+pub use igloo_engine::ingest::IngestOptions;
+pub use igloo_engine::load::{BadRecord, LoadFormat, LoadOptions, LoadProgress, LoadReport};
+pub use igloo_engine::schema_drift::{ColumnDrift, ColumnDriftKind, SchemaDiff};
+pub use igloo_engine::validate::{ScanPushdown, Validation};
+
+pub mod connectors {
+    //! Source connectors.
+    pub use igloo_connector_bigquery as bigquery;
+    pub use igloo_connector_delta as delta;
+    pub use igloo_connector_filesystem as filesystem;
+    pub use igloo_connector_flight as flight;
+    pub use igloo_connector_hive as hive;
+    pub use igloo_connector_http as http;
+    pub use igloo_connector_iceberg as iceberg;
+    pub use igloo_connector_kafka as kafka;
+    pub use igloo_connector_mongodb as mongodb;
+    pub use igloo_connector_mysql as mysql;
+    pub use igloo_connector_postgres as postgres;
+    pub use igloo_connector_redis as redis;
+    pub use igloo_connector_sqlite as sqlite;
+}
+
+/// An embedded Igloo query engine. Clones share the same catalog and session.
+#[derive(Clone, Default)]
+pub struct IglooEngine {
+    engine: QueryEngine,
+}
+
+impl IglooEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The engine the network frontends (`igloo-api`) are built on.
+    pub fn query_engine(&self) -> &QueryEngine {
+        &self.engine
+    }
+
+    /// The DataFusion session, for anything not covered by this API.
+    pub fn session_context(&self) -> &SessionContext {
+        self.engine.session_context()
+    }
+
+    /// Register a table under `name`, replacing any table already registered there.
+    pub fn register_table(
+        &self,
+        name: &str,
+        table: Arc<dyn TableProvider>,
+    ) -> DataFusionResult<()> {
+        self.engine.register_table(name, table).map(|_| ())
+    }
+
+    /// Register a file as a table. Parquet, newline-delimited JSON (`.json`, `.ndjson`)
+    /// and Avro are recognised by extension; anything else is read as CSV. ORC files
+    /// are not supported.
+    pub async fn register_file(&self, name: &str, path: &str) -> DataFusionResult<()> {
+        let ctx = self.session_context();
+        if path.ends_with(".parquet") {
+            ctx.register_parquet(name, path, ParquetReadOptions::default()).await
+        } else if path.ends_with(".json") || path.ends_with(".ndjson") {
+            ctx.register_json(name, path, NdJsonReadOptions::default()).await
+        } else if path.ends_with(DEFAULT_AVRO_EXTENSION) {
+            let options = ListingOptions::new(Arc::new(AvroFormat))
+                .with_file_extension(DEFAULT_AVRO_EXTENSION);
+            ctx.register_listing_table(name, path, options, None, None).await
+        } else if path.ends_with(".orc") {
+            Err(DataFusionError::NotImplemented(format!(
+                "{path}: ORC files are not supported yet; see the roadmap"
+            )))
+        } else {
+            ctx.register_csv(name, path, CsvReadOptions::new()).await
+        }
+    }
+
+    /// Register a scalar function implemented in WebAssembly, callable from SQL as
+    /// `name`. See [`igloo_engine::wasm_udf`] for the calling convention.
+    #[cfg(feature = "wasm")]
+    pub fn register_wasm_udf(
+        &self,
+        name: &str,
+        wasm: &[u8],
+        arg_types: Vec<DataType>,
+        return_type: DataType,
+    ) -> DataFusionResult<()> {
+        self.engine.register_wasm_udf(name, wasm, arg_types, return_type)
+    }
+
+    /// Register the catalog `source` loads as `name`, to be re-read later with
+    /// [`sync_catalog`](Self::sync_catalog).
+    pub async fn register_catalog_source(
+        &self,
+        name: &str,
+        source: Arc<dyn CatalogSource>,
+    ) -> DataFusionResult<()> {
+        self.engine.register_catalog_source(name, source).await
+    }
+
+    /// Re-read the external catalog `name` and apply what changed at its source.
+    pub async fn sync_catalog(&self, name: &str) -> DataFusionResult<SyncReport> {
+        self.engine.sync_catalog(name).await
+    }
+
+    /// Report how the external catalog `name` differs from its source, without
+    /// applying anything.
+    pub async fn diff_catalog(&self, name: &str) -> DataFusionResult<SyncReport> {
+        self.engine.diff_catalog(name).await
+    }
+
+    /// How the registered table `table`, a copy of the Postgres table `source` (e.g.
+    /// made by a snapshot and CDC), differs from the live schema of `source` in the
+    /// database at `config`, a libpq-style connection string. See
+    /// [`igloo_engine::schema_drift`].
+    pub async fn diff_postgres_schema(
+        &self,
+        config: &str,
+        source: &str,
+        table: &str,
+    ) -> DataFusionResult<SchemaDiff> {
+        let schema = igloo_connector_postgres::table_schema(config, source).await?;
+        self.engine.diff_schema(source, &schema, table.into()).await
+    }
+
+    /// The tables, views and policies set up at runtime, for setting up another engine
+    /// like this one. See [`igloo_engine::bundle`].
+    pub async fn export_state(&self) -> DataFusionResult<StateBundle> {
+        self.engine.export_state().await
+    }
+
+    /// Set up what `bundle`, exported from another engine, holds.
+    pub async fn import_state(&self, bundle: &StateBundle) -> DataFusionResult<ImportReport> {
+        self.engine.import_state(bundle).await
+    }
+
+    /// Move the table or view `table` to `name`, e.g. to `sales.orders` to place it in
+    /// the `sales` schema, as `ALTER TABLE table RENAME TO name` does.
+    pub async fn rename_table(&self, table: &str, name: &str) -> DataFusionResult<()> {
+        self.engine.rename_table(table.into(), name.into()).await
+    }
+
+    /// Append the records of the CSV or newline-delimited JSON file at `path` to the
+    /// table `table`, calling `progress` as the file is read. Records that do not fit
+    /// the table are skipped up to [`LoadOptions::max_bad_records`], and reported.
+    pub async fn load(
+        &self,
+        table: &str,
+        path: &str,
+        options: LoadOptions,
+        progress: impl FnMut(&LoadProgress) + Send,
+    ) -> DataFusionResult<LoadReport> {
+        self.engine.load(table, path, options, progress).await
+    }
+
+    /// Plan `sql` without executing it.
+    pub async fn sql(&self, sql: &str) -> DataFusionResult<DataFrame> {
+        self.engine.sql(sql).await
+    }
+
+    /// Check `sql` and report what running it would do, without running it. See
+    /// [`igloo_engine::validate`].
+    pub async fn validate(&self, sql: &str) -> DataFusionResult<Validation> {
+        self.engine.validate(sql).await
+    }
+
+    /// Execute `sql` and collect its results and diagnostics.
+    pub async fn query(&self, sql: &str) -> DataFusionResult<QueryResult> {
+        self.engine.query(sql).await
+    }
+
+    /// Execute `sql`, yielding its batches as they are produced instead of collecting
+    /// them.
+    pub async fn query_stream(&self, sql: &str) -> DataFusionResult<QueryStream> {
+        self.engine.query_stream(sql).await
+    }
+}
+
+impl From<QueryEngine> for IglooEngine {
+    fn from(engine: QueryEngine) -> Self {
+        Self { engine }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_clones_share_the_catalog() -> DataFusionResult<()> {
+        let engine = IglooEngine::new();
+        let clone = engine.clone();
+        clone.query("CREATE TABLE t (x INT) AS VALUES (1), (2)").await?;
+
+        let result = engine.query("SELECT sum(x) FROM t").await?;
+        assert_eq!(result.batches[0].num_rows(), 1);
+        assert!(engine.query_engine().session_context().table_exist("t")?);
+        Ok(())
+    }
+}
@@ -1,40 +1,72 @@
+use igloo_api::distributed::WorkerExecutor;
 use igloo_api::igloo::{
     coordinator_service_client::CoordinatorServiceClient,
     worker_service_server::WorkerServiceServer, HeartbeatInfo, WorkerInfo,
 };
+use igloo_common::retry::RetryPolicy;
+use igloo_engine::QueryEngine;
 use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::time::{sleep, Duration};
 use tonic::transport::Server;
+use tracing::{info, warn};
 use uuid::Uuid;
 
-mod service;
-use service::MyWorkerService;
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    igloo_common::logging::init_from_env()?;
     let worker_id = Uuid::new_v4().to_string();
-    let worker_addr: SocketAddr = "127.0.0.1:50052".parse()?;
+    // Several workers on one host each need their own `IGLOO_WORKER_ADDR`
+    let worker_addr: SocketAddr =
+        std::env::var("IGLOO_WORKER_ADDR").as_deref().unwrap_or("127.0.0.1:50052").parse()?;
     let coordinator_addr =
         std::env::args().nth(1).unwrap_or_else(|| "http://127.0.0.1:50051".to_string());
 
-    // Register with coordinator
-    let mut client = CoordinatorServiceClient::connect(coordinator_addr.clone()).await?;
-    let info = WorkerInfo { id: worker_id.clone(), address: format!("{}", worker_addr) };
-    let _ = client.register_worker(info).await?;
-    println!("Worker registered with coordinator at {}", coordinator_addr);
+    // Register with coordinator, tolerating a coordinator that is still starting up
+    let retry = RetryPolicy::default();
+    let client =
+        retry.retry(|| CoordinatorServiceClient::connect(coordinator_addr.clone())).await?;
+    // One task per core unless `IGLOO_WORKER_CAPACITY` says otherwise
+    let capacity = match std::env::var("IGLOO_WORKER_CAPACITY") {
+        Ok(capacity) => capacity.parse()?,
+        Err(_) => std::thread::available_parallelism().map_or(0, |n| n.get() as u32),
+    };
+    let info = WorkerInfo { id: worker_id.clone(), address: format!("{}", worker_addr), capacity };
+    let _ = retry
+        .retry(|| {
+            let (mut client, info) = (client.clone(), info.clone());
+            async move { client.register_worker(info).await }
+        })
+        .await?;
+    info!("Worker registered with coordinator at {}", coordinator_addr);
 
     // Spawn heartbeat task
-    let mut client2 = CoordinatorServiceClient::connect(coordinator_addr).await?;
+    let client2 = client.clone();
     let worker_id2 = worker_id.clone();
+    let info2 = info.clone();
+    let heartbeat_retry = RetryPolicy::new(3);
     tokio::spawn(async move {
         loop {
             let heartbeat = HeartbeatInfo {
                 worker_id: worker_id2.clone(),
                 timestamp: chrono::Utc::now().timestamp(),
             };
-            if let Err(e) = client2.send_heartbeat(heartbeat).await {
-                eprintln!("Failed to send heartbeat: {}", e);
-                // Optionally, implement retry/backoff here
+            let sent = heartbeat_retry
+                .retry(|| {
+                    let (mut client, heartbeat) = (client2.clone(), heartbeat.clone());
+                    async move { client.send_heartbeat(heartbeat).await }
+                })
+                .await;
+            match sent {
+                // The coordinator restarted and no longer knows this worker
+                Ok(response) if !response.get_ref().ok => {
+                    let mut client = client2.clone();
+                    if let Err(e) = client.register_worker(info2.clone()).await {
+                        warn!(error = %e, "Failed to register again");
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!(error = %e, "Failed to send heartbeat"),
             }
             sleep(Duration::from_secs(5)).await;
         }
@@ -42,10 +74,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Start gRPC server with graceful shutdown
     Server::builder()
-        .add_service(WorkerServiceServer::new(MyWorkerService))
+        .add_service(WorkerServiceServer::new(WorkerExecutor::new(Arc::new(QueryEngine::new()))))
         .serve_with_shutdown(worker_addr, async {
-            tokio::signal::ctrl_c().await.expect("failed to listen for event");
-            println!("Shutting down worker gracefully...");
+            igloo_common::shutdown::signal().await;
+            info!("Shutting down worker gracefully...");
         })
         .await?;
     Ok(())